@@ -46,6 +46,14 @@ const LAPIC_TIMER_ICR: u32 = 0x380; // Timer Initial Count
 const LAPIC_TIMER_CCR: u32 = 0x390; // Timer Current Count
 const LAPIC_TIMER_DCR: u32 = 0x3E0; // Timer Divide Configuration
 
+// Modos do LVT Timer (bits 18:17)
+const LVT_TIMER_ONESHOT: u32 = 0 << 17;
+const LVT_TIMER_PERIODIC: u32 = 1 << 17;
+const LVT_TIMER_TSC_DEADLINE: u32 = 2 << 17;
+
+/// MSR com o deadline (em ciclos do TSC) no modo TSC-deadline
+const IA32_TSC_DEADLINE: u32 = 0x6E0;
+
 // I/O APIC registers
 const IOAPIC_REGSEL: u32 = 0x00;
 const IOAPIC_WIN: u32 = 0x10;
@@ -81,6 +89,8 @@ static APIC_ENABLED: AtomicBool = AtomicBool::new(false);
 static LAPIC_BASE: AtomicU64 = AtomicU64::new(0);
 static IOAPIC_BASE: AtomicU64 = AtomicU64::new(0);
 static LAPIC_TICKS_PER_MS: AtomicU32 = AtomicU32::new(0);
+/// Modo atual do timer: periódico, one-shot ou TSC-deadline
+static LAPIC_TIMER_MODE: AtomicU32 = AtomicU32::new(LVT_TIMER_PERIODIC);
 
 /// Interrupt Source Override entry (from MADT)
 #[derive(Debug, Clone, Copy)]
@@ -424,6 +434,66 @@ unsafe fn init_lapic_timer(frequency_hz: u32) {
                      frequency_hz, initial_count);
 }
 
+/// Modos one-shot do timer do LAPIC, usados pelos hrtimers
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OneshotMode {
+    /// Contagem regressiva a partir de um valor inicial
+    Countdown,
+    /// Interrupção quando o TSC alcança o valor do IA32_TSC_DEADLINE
+    TscDeadline,
+}
+
+/// Verifica se o LAPIC aceita o modo TSC-deadline (CPUID.1:ECX[24])
+pub fn has_tsc_deadline() -> bool {
+    let cpuid = core::arch::x86_64::__cpuid(1);
+    (cpuid.ecx & (1 << 24)) != 0
+}
+
+/// Troca o timer do LAPIC de periódico para one-shot. A partir daqui cada
+/// interrupção precisa ser armada com `arm_timer_*`; o tick periódico
+/// passa a ser emulado pelos hrtimers.
+pub fn enable_oneshot_timer(mode: OneshotMode) -> bool {
+    if !is_enabled() || LAPIC_TICKS_PER_MS.load(Ordering::Relaxed) == 0 {
+        return false;
+    }
+    let lvt = match mode {
+        OneshotMode::Countdown => LVT_TIMER_ONESHOT,
+        OneshotMode::TscDeadline => LVT_TIMER_TSC_DEADLINE,
+    };
+    // O PIT também entra no vetor 32; em one-shot cada interrupção tem de
+    // vir de um deadline programado
+    set_irq_mask(0, true);
+    unsafe {
+        lapic_write(LAPIC_TIMER_ICR, 0);
+        lapic_write(LAPIC_TIMER_DCR, 0x03);
+        lapic_write(LAPIC_LVT_TIMER, lvt | APIC_TIMER_VECTOR as u32);
+    }
+    LAPIC_TIMER_MODE.store(lvt, Ordering::Release);
+    crate::kprintln!("apic: timer em modo {:?}", mode);
+    true
+}
+
+/// Se o timer está em um dos modos one-shot
+pub fn timer_is_oneshot() -> bool {
+    LAPIC_TIMER_MODE.load(Ordering::Acquire) != LVT_TIMER_PERIODIC
+}
+
+/// Arma o timer (modo TSC-deadline) para quando o TSC chegar a `deadline`
+pub fn arm_timer_tsc_deadline(deadline: u64) {
+    unsafe {
+        x86_64::registers::model_specific::Msr::new(IA32_TSC_DEADLINE).write(deadline.max(1));
+    }
+}
+
+/// Arma o timer (modo countdown) para daqui a `ns` nanossegundos
+pub fn arm_timer_countdown(ns: u64) {
+    let ticks_per_ms = LAPIC_TICKS_PER_MS.load(Ordering::Relaxed) as u64;
+    let count = (ns as u128 * ticks_per_ms as u128 / 1_000_000).clamp(1, u32::MAX as u128);
+    unsafe {
+        lapic_write(LAPIC_TIMER_ICR, count as u32);
+    }
+}
+
 /// Inicializa o I/O APIC
 unsafe fn init_ioapic(ioapic_addr: Option<u32>) {
     // Use MADT address if available, otherwise use default
@@ -730,6 +800,13 @@ pub fn delay_us(us: u32) {
         return;
     }
 
+    // Em modo one-shot o timer do LAPIC pertence aos hrtimers: reprogramá-lo
+    // aqui perderia o próximo evento
+    if timer_is_oneshot() {
+        super::tsc::delay_us(us as u64);
+        return;
+    }
+
     let ticks = (ticks_per_ms as u64 * us as u64) / 1000;
 
     unsafe {
@@ -224,14 +224,29 @@ pub const IPI_PANIC: u8 = 244;
 pub extern "C" fn stenzel_interrupt_dispatch(tf: &mut TrapFrame) -> *mut TrapFrame {
     let vector = tf.vector as u8;
 
+    // Timing jitter of device interrupts feeds the entropy pool
+    if matches!(vector, IRQ_TIMER | IRQ_KEYBOARD | IRQ_MOUSE) {
+        crate::crypto::random::add_interrupt_randomness(vector);
+    }
+
     match vector {
         IRQ_TIMER => {
-            TICKS.fetch_add(1, Ordering::Relaxed);
-            // Atualiza o contador de tempo do sistema
-            crate::time::tick();
+            // Dispara os hrtimers vencidos; com o timer em one-shot nem
+            // toda interrupção é um tick
+            let tick = crate::time::hrtimer::timer_interrupt();
+            if tick {
+                TICKS.fetch_add(1, Ordering::Relaxed);
+                // Atualiza o contador de tempo do sistema
+                crate::time::tick();
+                crate::time::account_cpu_tick((tf.cs & 3) == 3);
+            }
             // EOI antes do schedule para manter o controlador destravado.
             send_eoi(0);
-            crate::sched::on_timer_tick(tf)
+            if tick {
+                crate::sched::on_timer_tick(tf)
+            } else {
+                crate::sched::on_timer_event(tf)
+            }
         }
         IRQ_KEYBOARD => {
             // Leitura do scancode pelo i8042 (0x60)
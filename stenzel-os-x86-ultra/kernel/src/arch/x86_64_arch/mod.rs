@@ -13,6 +13,7 @@ pub mod smp;
 pub mod switch;
 pub mod syscall;
 pub mod tsc;
+pub mod vdso;
 
     /// Inicialização inicial (antes de mm::init)
     /// Configura GDT, IDT, PIC legado (para ter interrupções mínimas)
@@ -161,6 +161,7 @@ pub fn init_bsp() {
     // Map APIC ID to CPU 0
     APIC_TO_CPU[0].store(bsp_apic_id, Ordering::Release);
     NUM_CPUS.store(1, Ordering::Release);
+    super::vdso::init_cpu(0, bsp_apic_id);
 
     // Set GS base for BSP
     unsafe {
@@ -181,6 +182,7 @@ pub fn init_ap(cpu_num: usize, apic_id: u32) -> *mut PerCpu {
     // For now, we use the APIC_TO_CPU mapping
     APIC_TO_CPU[cpu_num].store(apic_id, Ordering::Release);
     NUM_CPUS.fetch_add(1, Ordering::AcqRel);
+    super::vdso::init_cpu(cpu_num as u32, apic_id);
 
     // Set GS base for this AP
     unsafe {
@@ -328,6 +330,15 @@ pub fn num_cpus() -> u32 {
     NUM_CPUS.load(Ordering::Acquire)
 }
 
+/// Get the current CPU number (0 = BSP), not the APIC ID
+pub fn cpu_number() -> u32 {
+    let apic_id = super::apic::lapic_id();
+    APIC_TO_CPU
+        .iter()
+        .position(|id| id.load(Ordering::Acquire) == apic_id)
+        .unwrap_or(0) as u32
+}
+
 /// Get per-CPU data for a specific CPU number
 pub fn get_percpu(cpu_num: usize) -> Option<&'static PerCpu> {
     if cpu_num >= MAX_CPUS {
@@ -56,6 +56,14 @@ extern "C" fn syscall_dispatch(frame: &mut SyscallFrame) -> u64 {
 
     // Syscall ABI estilo Linux:
     // rax = nro, rdi/rsi/rdx/r10/r8/r9 = args.
+    let args = [frame.rdi, frame.rsi, frame.rdx, frame.r10, frame.r8, frame.r9];
+
+    // Seccomp: os filtros do task decidem antes do dispatch
+    if let Some(result) = crate::security::seccomp::filter_syscall(frame.rax, &args, frame.rcx) {
+        check_and_deliver_signals(frame, result);
+        return result as u64;
+    }
+
     let result: i64 = match frame.rax {
         nr::READ => crate::syscall::sys_read(frame.rdi as i32, frame.rsi, frame.rdx as usize),
         nr::WRITE => crate::syscall::sys_write(frame.rdi as i32, frame.rsi, frame.rdx as usize),
@@ -68,6 +76,7 @@ extern "C" fn syscall_dispatch(frame: &mut SyscallFrame) -> u64 {
         nr::MMAP => crate::syscall::sys_mmap(frame.rdi, frame.rsi as usize, frame.rdx as i32, frame.r10 as i32, frame.r8 as i32, frame.r9 as i64),
         nr::MPROTECT => crate::syscall::sys_mprotect(frame.rdi, frame.rsi as usize, frame.rdx as i32),
         nr::MUNMAP => crate::syscall::sys_munmap(frame.rdi, frame.rsi as usize),
+        nr::MSYNC => crate::syscall::sys_msync(frame.rdi, frame.rsi as usize, frame.rdx as i32),
         nr::BRK => crate::syscall::sys_brk(frame.rdi),
         nr::IOCTL => crate::syscall::sys_ioctl(frame.rdi as i32, frame.rsi, frame.rdx),
         nr::PIPE => crate::syscall::sys_pipe(frame.rdi),
@@ -138,7 +147,10 @@ extern "C" fn syscall_dispatch(frame: &mut SyscallFrame) -> u64 {
         nr::GETRESUID => crate::syscall::sys_getresuid(frame.rdi, frame.rsi, frame.rdx),
         nr::SETRESGID => crate::syscall::sys_setresgid(frame.rdi as i64, frame.rsi as i64, frame.rdx as i64),
         nr::GETRESGID => crate::syscall::sys_getresgid(frame.rdi, frame.rsi, frame.rdx),
+        nr::PRCTL => crate::syscall::sys_prctl(frame.rdi as i32, frame.rsi, frame.rdx, frame.r10, frame.r8),
         nr::ARCH_PRCTL => crate::syscall::sys_arch_prctl(frame.rdi as i32, frame.rsi),
+        nr::SECCOMP => crate::security::seccomp::sys_seccomp(frame.rdi as u32, frame.rsi as u32, frame.rdx),
+        nr::BPF => crate::profiling::bpf_syscall::sys_bpf(frame.rdi as i32, frame.rsi, frame.rdx as u32),
         // Socket syscalls
         nr::SOCKET => crate::syscall::sys_socket(frame.rdi as i32, frame.rsi as i32, frame.rdx as i32),
         nr::BIND => crate::syscall::sys_bind(frame.rdi as i32, frame.rsi, frame.rdx as u32),
@@ -157,6 +169,15 @@ extern "C" fn syscall_dispatch(frame: &mut SyscallFrame) -> u64 {
         nr::GETTIMEOFDAY => crate::syscall::sys_gettimeofday(frame.rdi, frame.rsi),
         nr::CLOCK_GETTIME => crate::syscall::sys_clock_gettime(frame.rdi as i32, frame.rsi),
         nr::CLOCK_GETRES => crate::syscall::sys_clock_getres(frame.rdi as i32, frame.rsi),
+        nr::CLOCK_NANOSLEEP => crate::syscall::sys_clock_nanosleep(frame.rdi as i32, frame.rsi as i32, frame.rdx, frame.r10),
+        nr::GETITIMER => crate::syscall::sys_getitimer(frame.rdi as i32, frame.rsi),
+        nr::SETITIMER => crate::syscall::sys_setitimer(frame.rdi as i32, frame.rsi, frame.rdx),
+        nr::ALARM => crate::syscall::sys_alarm(frame.rdi as u32),
+        nr::TIMER_CREATE => crate::syscall::sys_timer_create(frame.rdi as i32, frame.rsi, frame.rdx),
+        nr::TIMER_SETTIME => crate::syscall::sys_timer_settime(frame.rdi as i32, frame.rsi as i32, frame.rdx, frame.r10),
+        nr::TIMER_GETTIME => crate::syscall::sys_timer_gettime(frame.rdi as i32, frame.rsi),
+        nr::TIMER_GETOVERRUN => crate::syscall::sys_timer_getoverrun(frame.rdi as i32),
+        nr::TIMER_DELETE => crate::syscall::sys_timer_delete(frame.rdi as i32),
         // I/O multiplexing syscalls
         nr::POLL => crate::syscall::sys_poll(frame.rdi, frame.rsi, frame.rdx as i32),
         nr::SELECT => crate::syscall::sys_select(frame.rdi as i32, frame.rsi, frame.rdx, frame.r10, frame.r8),
@@ -216,11 +237,49 @@ extern "C" fn syscall_dispatch(frame: &mut SyscallFrame) -> u64 {
         nr::PRLIMIT64 => crate::syscall::sys_prlimit64(frame.rdi as i32, frame.rsi as u32, frame.rdx, frame.r10),
         // Time
         nr::SETTIMEOFDAY => crate::syscall::sys_settimeofday(frame.rdi, frame.rsi),
+        nr::ADJTIMEX => crate::syscall::sys_adjtimex(frame.rdi),
+        nr::CLOCK_SETTIME => crate::syscall::sys_clock_settime(frame.rdi as i32, frame.rsi),
+        nr::CLOCK_ADJTIME => crate::syscall::sys_clock_adjtime(frame.rdi as i32, frame.rsi),
         // Reboot/shutdown
         nr::REBOOT => crate::syscall::sys_reboot(frame.rdi as u32, frame.rsi as u32, frame.rdx as u32, frame.r10),
+        // Mount
+        nr::MOUNT => crate::syscall::sys_mount(frame.rdi, frame.rsi, frame.rdx, frame.r10, frame.r8),
+        nr::UMOUNT2 => crate::syscall::sys_umount2(frame.rdi, frame.rsi as u32),
+        nr::STATFS => crate::syscall::sys_statfs(frame.rdi, frame.rsi),
+        nr::FSTATFS => crate::syscall::sys_fstatfs(frame.rdi as i32, frame.rsi),
+        // Namespaces
+        nr::PIVOT_ROOT => crate::syscall::sys_pivot_root(frame.rdi, frame.rsi),
+        nr::CHROOT => crate::syscall::sys_chroot(frame.rdi),
+        nr::UNSHARE => crate::syscall::sys_unshare(frame.rdi),
+        nr::SETNS => crate::syscall::sys_setns(frame.rdi as i32, frame.rsi),
+        // Random
+        nr::GETRANDOM => crate::syscall::sys_getrandom(frame.rdi, frame.rsi as usize, frame.rdx as u32),
+        // CPU
+        nr::GETCPU => crate::syscall::sys_getcpu(frame.rdi, frame.rsi),
+        // epoll
+        nr::EPOLL_CREATE => crate::syscall::epoll::sys_epoll_create(frame.rdi as i32),
+        nr::EPOLL_CREATE1 => crate::syscall::epoll::sys_epoll_create1(frame.rdi as i32),
+        nr::EPOLL_CTL => crate::syscall::epoll::sys_epoll_ctl(frame.rdi as i32, frame.rsi as i32, frame.rdx as i32, frame.r10),
+        nr::EPOLL_WAIT => crate::syscall::epoll::sys_epoll_wait(frame.rdi as i32, frame.rsi, frame.rdx as i32, frame.r10 as i32),
+        nr::EPOLL_PWAIT => crate::syscall::epoll::sys_epoll_pwait(frame.rdi as i32, frame.rsi, frame.rdx as i32, frame.r10 as i32, frame.r8, frame.r9 as usize),
+        nr::EPOLL_PWAIT2 => crate::syscall::epoll::sys_epoll_pwait2(frame.rdi as i32, frame.rsi, frame.rdx as i32, frame.r10, frame.r8, frame.r9 as usize),
+        // timerfd / signalfd
+        nr::TIMERFD_CREATE => crate::ipc::sys_timerfd_create(frame.rdi as i32, frame.rsi as i32),
+        nr::TIMERFD_SETTIME => crate::ipc::sys_timerfd_settime(frame.rdi as i32, frame.rsi as i32, frame.rdx, frame.r10),
+        nr::TIMERFD_GETTIME => crate::ipc::sys_timerfd_gettime(frame.rdi as i32, frame.rsi),
+        nr::SIGNALFD => crate::syscall::sys_signalfd(frame.rdi as i32, frame.rsi, frame.rdx as usize),
+        nr::SIGNALFD4 => crate::syscall::sys_signalfd4(frame.rdi as i32, frame.rsi, frame.rdx as usize, frame.r10 as i32),
+        // inotify
+        nr::INOTIFY_INIT => crate::fs::inotify::sys_inotify_init(),
+        nr::INOTIFY_INIT1 => crate::fs::inotify::sys_inotify_init1(frame.rdi as i32),
+        nr::INOTIFY_ADD_WATCH => crate::fs::inotify::sys_inotify_add_watch(frame.rdi as i32, frame.rsi, frame.rdx as u32),
+        nr::INOTIFY_RM_WATCH => crate::fs::inotify::sys_inotify_rm_watch(frame.rdi as i32, frame.rsi as i32),
         _ => errno::ENOSYS,
     };
 
+    // Espera o atraso de io.max do cgroup (sem locks, antes de voltar ao user)
+    crate::storage::iosched::throttle_current();
+
     // Check for pending signals before returning to userspace
     check_and_deliver_signals(frame, result);
 
@@ -281,6 +281,12 @@ pub fn ticks_to_ns(ticks: u64) -> u64 {
     ((ticks as u128 * 1_000_000) / freq_khz as u128) as u64
 }
 
+/// Convert nanoseconds to TSC ticks
+pub fn ns_to_ticks(ns: u64) -> u64 {
+    let freq_khz = TSC_FREQ_KHZ.load(Ordering::Relaxed);
+    ((ns as u128 * freq_khz as u128) / 1_000_000).min(u64::MAX as u128) as u64
+}
+
 /// Convert TSC ticks to microseconds
 pub fn ticks_to_us(ticks: u64) -> u64 {
     let freq_khz = TSC_FREQ_KHZ.load(Ordering::Relaxed);
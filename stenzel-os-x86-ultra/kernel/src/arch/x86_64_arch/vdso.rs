@@ -0,0 +1,681 @@
+//! vDSO: clock_gettime, gettimeofday, time and getcpu without a syscall.
+//!
+//! Every process gets two read-only pages at fixed addresses: the vvar
+//! page, where the kernel publishes the time keeping state, and right above
+//! it the vDSO, a small ELF shared object (`linux-vdso.so.1`, symbols
+//! versioned `LINUX_2.6`) whose address is passed in AT_SYSINFO_EHDR. Both
+//! are built once at boot and shared by all address spaces.
+//!
+//! The vDSO code reads the TSC and converts it with the clock source's
+//! multiplier, under the vvar sequence count. It falls back to the real
+//! syscall when time does not come from the TSC and for the clocks it does
+//! not handle (CPU-time clocks). The code finds the vvar page relative to
+//! its own address, so it runs wherever it is mapped.
+
+use alloc::vec::Vec;
+use core::arch::global_asm;
+use core::mem::{offset_of, size_of};
+use core::slice;
+use core::sync::atomic::{fence, AtomicI32, AtomicU32, AtomicU64, Ordering};
+
+use spin::Once;
+use x86_64::structures::paging::{Mapper, OffsetPageTable, Page, PageTableFlags, PhysFrame, Size4KiB};
+use x86_64::VirtAddr;
+
+use crate::compat::linux::elf_dyn::{dt, elf_hash, sht, stb, stt, Elf64Dyn, Elf64Shdr, Elf64Sym};
+use crate::mm::{self, BitmapFrameAllocator};
+use crate::process::elf::{Elf64Header, Elf64Phdr, ELF_MAGIC, PF_R, PF_X, PT_DYNAMIC, PT_LOAD};
+use crate::syscall::nr;
+use crate::time::clocksource;
+use crate::util::KError;
+
+const PAGE_SIZE: usize = 4096;
+
+/// Data page, mapped in every process right below the vDSO
+pub const VVAR_ADDR: u64 = 0x0000_7fff_f000_0000;
+/// vDSO image (AT_SYSINFO_EHDR)
+pub const VDSO_ADDR: u64 = VVAR_ADDR + PAGE_SIZE as u64;
+
+/// Offset of the code in the image; the ELF headers and tables come before
+const TEXT_OFFSET: usize = 0x800;
+
+/// `clock_mode`: time must be read through the syscall
+const CLOCK_MODE_NONE: u32 = 0;
+/// `clock_mode`: TSC cycles converted with `mult`
+const CLOCK_MODE_TSC: u32 = 1;
+
+const IA32_TSC_AUX: u32 = 0xC000_0103;
+/// TSC_AUX holds the CPU number in its low bits and the node above them
+const TSC_AUX_NODE_SHIFT: u32 = 12;
+
+/// Layout of the vvar page, as read by the vDSO code
+#[repr(C)]
+struct VvarData {
+    /// Odd while the kernel updates the fields below
+    seq: AtomicU32,
+    clock_mode: AtomicU32,
+    cycle_last: AtomicU64,
+    mult: AtomicU64,
+    /// CLOCK_MONOTONIC at `cycle_last`
+    mono_ns: AtomicU64,
+    raw_mult: AtomicU64,
+    /// CLOCK_MONOTONIC_RAW at `cycle_last`
+    raw_ns: AtomicU64,
+    /// CLOCK_REALTIME - CLOCK_MONOTONIC
+    real_offset_ns: AtomicU64,
+    /// CLOCK_BOOTTIME - CLOCK_MONOTONIC
+    boot_offset_ns: AtomicU64,
+    /// CLOCK_MONOTONIC at the last tick
+    coarse_ns: AtomicU64,
+    /// struct timezone, for gettimeofday
+    tz_minuteswest: AtomicI32,
+    tz_dsttime: AtomicI32,
+    /// 1 when getcpu may read the CPU number from TSC_AUX with rdtscp
+    getcpu_rdtscp: AtomicU32,
+}
+
+/// Time keeping state published to the vDSO
+#[derive(Debug, Clone, Copy)]
+pub struct TimeData {
+    pub clocksource: clocksource::Snapshot,
+    /// CLOCK_MONOTONIC_RAW base and multiplier at `clocksource.cycle_last`
+    pub raw_ns: u64,
+    pub raw_mult: u64,
+    pub real_offset_ns: u64,
+    pub boot_offset_ns: u64,
+    pub coarse_ns: u64,
+    pub tz_minuteswest: i32,
+    pub tz_dsttime: i32,
+}
+
+global_asm!(r#"
+.section .rodata
+.balign 16
+.global stenzel_vdso_text_start
+.global stenzel_vdso_text_end
+.global stenzel_vdso_clock_gettime
+.global stenzel_vdso_gettimeofday
+.global stenzel_vdso_time
+.global stenzel_vdso_getcpu
+
+stenzel_vdso_text_start:
+
+// Nanoseconds of clock %r10d in %rax; carry set if the clock must be read
+// through the syscall. Expects the vvar page in %r8, clobbers %rdx and %r9.
+.Lvdso_read_clock:
+    // REALTIME, MONOTONIC, MONOTONIC_RAW, the COARSE clocks and BOOTTIME
+    cmp $7, %r10d
+    ja .Lvdso_read_syscall
+    mov $0xf3, %r9d
+    bt %r10d, %r9d
+    jnc .Lvdso_read_syscall
+.Lvdso_read_retry:
+    mov {seq}(%r8), %r9d
+    test $1, %r9d
+    jnz .Lvdso_read_wait
+    cmp $5, %r10d
+    je .Lvdso_read_coarse
+    cmp $6, %r10d
+    je .Lvdso_read_coarse
+    cmpl ${mode_tsc}, {clock_mode}(%r8)
+    jne .Lvdso_read_syscall
+    lfence
+    rdtsc
+    shl $32, %rdx
+    or %rdx, %rax
+    // A TSC behind cycle_last counts as no time elapsed
+    sub {cycle_last}(%r8), %rax
+    jae 1f
+    xor %eax, %eax
+1:
+    cmp $4, %r10d
+    je .Lvdso_read_raw
+    mulq {mult}(%r8)
+    shrd ${shift}, %rdx, %rax
+    add {mono_ns}(%r8), %rax
+    jmp .Lvdso_read_offset
+.Lvdso_read_raw:
+    mulq {raw_mult}(%r8)
+    shrd ${shift}, %rdx, %rax
+    add {raw_ns}(%r8), %rax
+    jmp .Lvdso_read_check
+.Lvdso_read_coarse:
+    mov {coarse_ns}(%r8), %rax
+.Lvdso_read_offset:
+    // Monotonic time in %rax: add the offset of the clock
+    cmp $1, %r10d
+    je .Lvdso_read_check
+    cmp $6, %r10d
+    je .Lvdso_read_check
+    cmp $7, %r10d
+    je .Lvdso_read_boot
+    add {real_offset}(%r8), %rax
+    jmp .Lvdso_read_check
+.Lvdso_read_boot:
+    add {boot_offset}(%r8), %rax
+.Lvdso_read_check:
+    cmp {seq}(%r8), %r9d
+    jne .Lvdso_read_retry
+    clc
+    ret
+.Lvdso_read_wait:
+    pause
+    jmp .Lvdso_read_retry
+.Lvdso_read_syscall:
+    stc
+    ret
+
+// int clock_gettime(clockid_t clock, struct timespec *ts)
+stenzel_vdso_clock_gettime:
+    lea stenzel_vdso_text_start(%rip), %r8
+    sub ${vvar_distance}, %r8
+    mov %edi, %r10d
+    call .Lvdso_read_clock
+    jc 1f
+    mov $1000000000, %ecx
+    xor %edx, %edx
+    div %rcx
+    mov %rax, (%rsi)
+    mov %rdx, 8(%rsi)
+    xor %eax, %eax
+    ret
+1:
+    mov ${nr_clock_gettime}, %eax
+    syscall
+    ret
+
+// int gettimeofday(struct timeval *tv, struct timezone *tz)
+stenzel_vdso_gettimeofday:
+    lea stenzel_vdso_text_start(%rip), %r8
+    sub ${vvar_distance}, %r8
+    test %rdi, %rdi
+    jz 1f
+    xor %r10d, %r10d
+    call .Lvdso_read_clock
+    jc 4f
+    mov $1000000000, %ecx
+    xor %edx, %edx
+    div %rcx
+    mov %rax, (%rdi)
+    mov %rdx, %rax
+    mov $1000, %ecx
+    xor %edx, %edx
+    div %rcx
+    mov %rax, 8(%rdi)
+1:
+    test %rsi, %rsi
+    jz 3f
+2:
+    mov {seq}(%r8), %r9d
+    test $1, %r9d
+    jnz 5f
+    mov {tz}(%r8), %rax
+    cmp {seq}(%r8), %r9d
+    jne 2b
+    mov %rax, (%rsi)
+3:
+    xor %eax, %eax
+    ret
+4:
+    mov ${nr_gettimeofday}, %eax
+    syscall
+    ret
+5:
+    pause
+    jmp 2b
+
+// time_t time(time_t *tloc)
+stenzel_vdso_time:
+    lea stenzel_vdso_text_start(%rip), %r8
+    sub ${vvar_distance}, %r8
+    mov $5, %r10d
+    call .Lvdso_read_clock
+    mov $1000000000, %ecx
+    xor %edx, %edx
+    div %rcx
+    test %rdi, %rdi
+    jz 1f
+    mov %rax, (%rdi)
+1:
+    ret
+
+// int getcpu(unsigned *cpu, unsigned *node, void *cache)
+stenzel_vdso_getcpu:
+    lea stenzel_vdso_text_start(%rip), %r8
+    sub ${vvar_distance}, %r8
+    cmpl $1, {getcpu_rdtscp}(%r8)
+    jne 3f
+    rdtscp
+    mov %ecx, %eax
+    and ${cpu_mask}, %eax
+    test %rdi, %rdi
+    jz 1f
+    mov %eax, (%rdi)
+1:
+    test %rsi, %rsi
+    jz 2f
+    shr ${node_shift}, %ecx
+    mov %ecx, (%rsi)
+2:
+    xor %eax, %eax
+    ret
+3:
+    mov ${nr_getcpu}, %eax
+    syscall
+    ret
+
+stenzel_vdso_text_end:
+"#,
+    seq = const offset_of!(VvarData, seq),
+    clock_mode = const offset_of!(VvarData, clock_mode),
+    cycle_last = const offset_of!(VvarData, cycle_last),
+    mult = const offset_of!(VvarData, mult),
+    mono_ns = const offset_of!(VvarData, mono_ns),
+    raw_mult = const offset_of!(VvarData, raw_mult),
+    raw_ns = const offset_of!(VvarData, raw_ns),
+    real_offset = const offset_of!(VvarData, real_offset_ns),
+    boot_offset = const offset_of!(VvarData, boot_offset_ns),
+    coarse_ns = const offset_of!(VvarData, coarse_ns),
+    tz = const offset_of!(VvarData, tz_minuteswest),
+    getcpu_rdtscp = const offset_of!(VvarData, getcpu_rdtscp),
+    mode_tsc = const CLOCK_MODE_TSC,
+    shift = const clocksource::SHIFT,
+    vvar_distance = const TEXT_OFFSET + PAGE_SIZE,
+    cpu_mask = const (1u32 << TSC_AUX_NODE_SHIFT) - 1,
+    node_shift = const TSC_AUX_NODE_SHIFT,
+    nr_clock_gettime = const nr::CLOCK_GETTIME,
+    nr_gettimeofday = const nr::GETTIMEOFDAY,
+    nr_getcpu = const nr::GETCPU,
+    options(att_syntax));
+
+extern "C" {
+    static stenzel_vdso_text_start: u8;
+    static stenzel_vdso_text_end: u8;
+    static stenzel_vdso_clock_gettime: u8;
+    static stenzel_vdso_gettimeofday: u8;
+    static stenzel_vdso_time: u8;
+    static stenzel_vdso_getcpu: u8;
+}
+
+/// Code of the vDSO, as copied to TEXT_OFFSET
+fn text() -> &'static [u8] {
+    unsafe {
+        let start = &stenzel_vdso_text_start as *const u8;
+        let end = &stenzel_vdso_text_end as *const u8;
+        slice::from_raw_parts(start, end as usize - start as usize)
+    }
+}
+
+/// Exported functions and their offsets in the code, in code order
+fn exports() -> [(&'static str, usize); 4] {
+    let offset = |sym: &u8| sym as *const u8 as usize - text().as_ptr() as usize;
+    unsafe {
+        [
+            ("clock_gettime", offset(&stenzel_vdso_clock_gettime)),
+            ("gettimeofday", offset(&stenzel_vdso_gettimeofday)),
+            ("time", offset(&stenzel_vdso_time)),
+            ("getcpu", offset(&stenzel_vdso_getcpu)),
+        ]
+    }
+}
+
+const SONAME: &str = "linux-vdso.so.1";
+const VERSION: &str = "LINUX_2.6";
+
+/// Version definition (.gnu.version_d)
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct Elf64Verdef {
+    vd_version: u16,
+    vd_flags: u16,
+    vd_ndx: u16,
+    vd_cnt: u16,
+    vd_hash: u32,
+    vd_aux: u32,
+    vd_next: u32,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct Elf64Verdaux {
+    vda_name: u32,
+    vda_next: u32,
+}
+
+const VER_FLG_BASE: u16 = 1;
+/// Version index of LINUX_2.6 (1 is the base version)
+const VERSION_INDEX: u16 = 2;
+
+const SHF_ALLOC: u64 = 0x2;
+const SHF_EXECINSTR: u64 = 0x4;
+
+struct ImageWriter {
+    buf: Vec<u8>,
+}
+
+impl ImageWriter {
+    fn align(&mut self, align: usize) -> usize {
+        let len = (self.buf.len() + align - 1) & !(align - 1);
+        self.buf.resize(len, 0);
+        len
+    }
+
+    fn bytes(&mut self, data: &[u8]) -> usize {
+        let offset = self.buf.len();
+        self.buf.extend_from_slice(data);
+        offset
+    }
+
+    fn put<T: Copy>(&mut self, value: &T) -> usize {
+        let data = unsafe { slice::from_raw_parts(value as *const T as *const u8, size_of::<T>()) };
+        self.bytes(data)
+    }
+
+    fn put_all<T: Copy>(&mut self, values: &[T]) -> usize {
+        self.align(8);
+        let offset = self.buf.len();
+        for value in values {
+            self.put(value);
+        }
+        offset
+    }
+
+    /// Overwrite the `T` at `offset`
+    fn patch<T: Copy>(&mut self, offset: usize, value: &T) {
+        let data = unsafe { slice::from_raw_parts(value as *const T as *const u8, size_of::<T>()) };
+        self.buf[offset..offset + data.len()].copy_from_slice(data);
+    }
+}
+
+struct StrTab(Vec<u8>);
+
+impl StrTab {
+    fn add(&mut self, s: &str) -> u32 {
+        let offset = self.0.len() as u32;
+        self.0.extend_from_slice(s.as_bytes());
+        self.0.push(0);
+        offset
+    }
+}
+
+fn section(name: u32, sh_type: u32, flags: u64, offset: usize, size: usize, link: u32, info: u32, entsize: usize) -> Elf64Shdr {
+    Elf64Shdr {
+        sh_name: name,
+        sh_type,
+        sh_flags: flags,
+        sh_addr: if flags & SHF_ALLOC != 0 { offset as u64 } else { 0 },
+        sh_offset: offset as u64,
+        sh_size: size as u64,
+        sh_link: link,
+        sh_info: info,
+        sh_addralign: if sh_type == sht::PROGBITS { 16 } else { 8 },
+        sh_entsize: entsize as u64,
+    }
+}
+
+/// Build the vDSO ELF image: a shared object linked at address 0 with
+/// the code at TEXT_OFFSET. Each function is exported as `__vdso_<name>`
+/// and as a weak `<name>`, both with version LINUX_2.6.
+pub fn build_image() -> Vec<u8> {
+    const SEC_DYNSYM: u32 = 2;
+    const SEC_DYNSTR: u32 = 3;
+    const SEC_TEXT: u16 = 7;
+    const PHNUM: usize = 2;
+
+    let code = text();
+    let exports = exports();
+
+    // .dynstr and .dynsym
+    let mut dynstr = StrTab(alloc::vec![0]);
+    let soname = dynstr.add(SONAME);
+    let version = dynstr.add(VERSION);
+    let mut symbols = alloc::vec![Elf64Sym { st_name: 0, st_info: 0, st_other: 0, st_shndx: 0, st_value: 0, st_size: 0 }];
+    let mut hashes = alloc::vec![0u32];
+    for (i, &(name, offset)) in exports.iter().enumerate() {
+        let end = exports.get(i + 1).map_or(code.len(), |next| next.1);
+        let vdso_name = alloc::format!("__vdso_{}", name);
+        for (sym_name, bind) in [(vdso_name.as_str(), stb::GLOBAL), (name, stb::WEAK)] {
+            symbols.push(Elf64Sym {
+                st_name: dynstr.add(sym_name),
+                st_info: (bind << 4) | stt::FUNC,
+                st_other: 0,
+                st_shndx: SEC_TEXT,
+                st_value: (TEXT_OFFSET + offset) as u64,
+                st_size: (end - offset) as u64,
+            });
+            hashes.push(elf_hash(sym_name));
+        }
+    }
+
+    // SysV hash table over the symbols
+    let nbucket = symbols.len();
+    let mut buckets = alloc::vec![0u32; nbucket];
+    let mut chains = alloc::vec![0u32; symbols.len()];
+    for (i, &hash) in hashes.iter().enumerate().skip(1) {
+        let bucket = hash as usize % nbucket;
+        chains[i] = buckets[bucket];
+        buckets[bucket] = i as u32;
+    }
+
+    let mut w = ImageWriter { buf: Vec::new() };
+    let ehdr_offset = w.put(&[0u8; size_of::<Elf64Header>()]);
+    let phdr_offset = w.put_all(&[[0u8; size_of::<Elf64Phdr>()]; PHNUM]);
+
+    let hash_offset = w.align(8);
+    w.put(&(nbucket as u32));
+    w.put(&(symbols.len() as u32));
+    for bucket in &buckets {
+        w.put(bucket);
+    }
+    for chain in &chains {
+        w.put(chain);
+    }
+    let hash_size = w.buf.len() - hash_offset;
+
+    let dynsym_offset = w.put_all(&symbols);
+    let dynsym_size = w.buf.len() - dynsym_offset;
+    let dynstr_offset = w.bytes(&dynstr.0);
+
+    // .gnu.version: every symbol but the null one is LINUX_2.6
+    let versym_offset = w.align(2);
+    for i in 0..symbols.len() {
+        w.put(&if i == 0 { 0u16 } else { VERSION_INDEX });
+    }
+    let versym_size = w.buf.len() - versym_offset;
+
+    // .gnu.version_d: the base version (the soname) and LINUX_2.6
+    let verdef_size = size_of::<Elf64Verdef>() + size_of::<Elf64Verdaux>();
+    let verdef_offset = w.align(8);
+    for (index, name) in [(1u16, (SONAME, soname)), (VERSION_INDEX, (VERSION, version))] {
+        w.put(&Elf64Verdef {
+            vd_version: 1,
+            vd_flags: if index == 1 { VER_FLG_BASE } else { 0 },
+            vd_ndx: index,
+            vd_cnt: 1,
+            vd_hash: elf_hash(name.0),
+            vd_aux: size_of::<Elf64Verdef>() as u32,
+            vd_next: if index == VERSION_INDEX { 0 } else { verdef_size as u32 },
+        });
+        w.put(&Elf64Verdaux { vda_name: name.1, vda_next: 0 });
+    }
+
+    let dynamic = [
+        (dt::HASH, hash_offset as u64),
+        (dt::STRTAB, dynstr_offset as u64),
+        (dt::SYMTAB, dynsym_offset as u64),
+        (dt::STRSZ, dynstr.0.len() as u64),
+        (dt::SYMENT, size_of::<Elf64Sym>() as u64),
+        (dt::SONAME, soname as u64),
+        (dt::VERSYM, versym_offset as u64),
+        (dt::VERDEF, verdef_offset as u64),
+        (dt::VERDEFNUM, 2),
+        (dt::NULL, 0),
+    ]
+    .map(|(d_tag, d_val)| Elf64Dyn { d_tag, d_val });
+    let dynamic_offset = w.put_all(&dynamic);
+    let dynamic_size = w.buf.len() - dynamic_offset;
+
+    assert!(w.buf.len() <= TEXT_OFFSET, "vdso: tabelas não cabem antes do código");
+    w.buf.resize(TEXT_OFFSET, 0);
+    w.bytes(code);
+    let load_size = w.buf.len();
+
+    // Section headers, after the loaded part
+    let mut shstr = StrTab(alloc::vec![0]);
+    let mut sections = [
+        section(0, sht::NULL, 0, 0, 0, 0, 0, 0),
+        section(shstr.add(".hash"), sht::HASH, SHF_ALLOC, hash_offset, hash_size, SEC_DYNSYM, 0, 4),
+        section(shstr.add(".dynsym"), sht::DYNSYM, SHF_ALLOC, dynsym_offset, dynsym_size, SEC_DYNSTR, 1, size_of::<Elf64Sym>()),
+        section(shstr.add(".dynstr"), sht::STRTAB, SHF_ALLOC, dynstr_offset, dynstr.0.len(), 0, 0, 0),
+        section(shstr.add(".gnu.version"), sht::GNU_VERSYM, SHF_ALLOC, versym_offset, versym_size, SEC_DYNSYM, 0, 2),
+        section(shstr.add(".gnu.version_d"), sht::GNU_VERDEF, SHF_ALLOC, verdef_offset, 2 * verdef_size, SEC_DYNSTR, 2, 0),
+        section(shstr.add(".dynamic"), sht::DYNAMIC, SHF_ALLOC, dynamic_offset, dynamic_size, SEC_DYNSTR, 0, size_of::<Elf64Dyn>()),
+        section(shstr.add(".text"), sht::PROGBITS, SHF_ALLOC | SHF_EXECINSTR, TEXT_OFFSET, code.len(), 0, 0, 0),
+        section(shstr.add(".shstrtab"), sht::STRTAB, 0, 0, 0, 0, 0, 0),
+    ];
+    let shstrtab_offset = w.bytes(&shstr.0);
+    sections[8].sh_offset = shstrtab_offset as u64;
+    sections[8].sh_size = shstr.0.len() as u64;
+    sections[8].sh_addralign = 1;
+    let shdr_offset = w.put_all(&sections);
+    assert!(w.buf.len() <= PAGE_SIZE, "vdso: imagem maior que uma página");
+
+    let mut e_ident = [0u8; 16];
+    e_ident[..4].copy_from_slice(&ELF_MAGIC);
+    e_ident[4] = 2; // ELFCLASS64
+    e_ident[5] = 1; // ELFDATA2LSB
+    e_ident[6] = 1; // EV_CURRENT
+    w.patch(ehdr_offset, &Elf64Header {
+        e_ident,
+        e_type: 3, // ET_DYN
+        e_machine: 0x3E,
+        e_version: 1,
+        e_entry: 0,
+        e_phoff: phdr_offset as u64,
+        e_shoff: shdr_offset as u64,
+        e_flags: 0,
+        e_ehsize: size_of::<Elf64Header>() as u16,
+        e_phentsize: size_of::<Elf64Phdr>() as u16,
+        e_phnum: PHNUM as u16,
+        e_shentsize: size_of::<Elf64Shdr>() as u16,
+        e_shnum: sections.len() as u16,
+        e_shstrndx: sections.len() as u16 - 1,
+    });
+    let phdrs = [
+        Elf64Phdr {
+            p_type: PT_LOAD,
+            p_flags: PF_R | PF_X,
+            p_offset: 0,
+            p_vaddr: 0,
+            p_paddr: 0,
+            p_filesz: load_size as u64,
+            p_memsz: load_size as u64,
+            p_align: PAGE_SIZE as u64,
+        },
+        Elf64Phdr {
+            p_type: PT_DYNAMIC,
+            p_flags: PF_R,
+            p_offset: dynamic_offset as u64,
+            p_vaddr: dynamic_offset as u64,
+            p_paddr: dynamic_offset as u64,
+            p_filesz: dynamic_size as u64,
+            p_memsz: dynamic_size as u64,
+            p_align: 8,
+        },
+    ];
+    for (i, phdr) in phdrs.iter().enumerate() {
+        w.patch(phdr_offset + i * size_of::<Elf64Phdr>(), phdr);
+    }
+    w.buf
+}
+
+/// Frames of the vvar page and of the image, shared by every process
+static FRAMES: Once<(PhysFrame<Size4KiB>, PhysFrame<Size4KiB>)> = Once::new();
+
+fn vvar() -> Option<&'static VvarData> {
+    let (vvar, _) = FRAMES.get()?;
+    Some(unsafe { &*mm::phys_to_virt(vvar.start_address()).as_ptr::<VvarData>() })
+}
+
+/// Publish the time keeping state. The caller serializes updates.
+pub fn update(time: &TimeData) {
+    let Some(vvar) = vvar() else {
+        return;
+    };
+    let seq = vvar.seq.load(Ordering::Relaxed);
+    vvar.seq.store(seq.wrapping_add(1), Ordering::Relaxed);
+    fence(Ordering::Release);
+
+    let cs = &time.clocksource;
+    let mode = if cs.source == clocksource::ClockSource::Tsc { CLOCK_MODE_TSC } else { CLOCK_MODE_NONE };
+    vvar.clock_mode.store(mode, Ordering::Relaxed);
+    vvar.cycle_last.store(cs.cycle_last, Ordering::Relaxed);
+    vvar.mult.store(cs.mult, Ordering::Relaxed);
+    vvar.mono_ns.store(cs.base_ns, Ordering::Relaxed);
+    vvar.raw_mult.store(time.raw_mult, Ordering::Relaxed);
+    vvar.raw_ns.store(time.raw_ns, Ordering::Relaxed);
+    vvar.real_offset_ns.store(time.real_offset_ns, Ordering::Relaxed);
+    vvar.boot_offset_ns.store(time.boot_offset_ns, Ordering::Relaxed);
+    vvar.coarse_ns.store(time.coarse_ns, Ordering::Relaxed);
+    vvar.tz_minuteswest.store(time.tz_minuteswest, Ordering::Relaxed);
+    vvar.tz_dsttime.store(time.tz_dsttime, Ordering::Relaxed);
+
+    vvar.seq.store(seq.wrapping_add(2), Ordering::Release);
+}
+
+/// Map the vvar page and the vDSO into a new address space. Returns the
+/// address of the image, or None before `init`.
+pub fn map(mapper: &mut OffsetPageTable<'static>, fa: &mut BitmapFrameAllocator) -> Result<Option<u64>, KError> {
+    let Some(&(vvar, image)) = FRAMES.get() else {
+        return Ok(None);
+    };
+    let user = PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE;
+    for (addr, frame, flags) in [(VVAR_ADDR, vvar, user | PageTableFlags::NO_EXECUTE), (VDSO_ADDR, image, user)] {
+        let page: Page<Size4KiB> = Page::containing_address(VirtAddr::new(addr));
+        unsafe {
+            mapper.map_to(page, frame, flags, fa).map_err(|_| KError::NoMemory)?.flush();
+        }
+    }
+    Ok(Some(VDSO_ADDR))
+}
+
+/// Record the CPU number and node in TSC_AUX, where the vDSO getcpu
+/// reads them with rdtscp. Called on each CPU as it comes up.
+pub fn init_cpu(cpu: u32, apic_id: u32) {
+    if !super::tsc::has_rdtscp() {
+        return;
+    }
+    let node = crate::mm::numa::cpu_to_node(apic_id);
+    let aux = (node << TSC_AUX_NODE_SHIFT) | (cpu & ((1 << TSC_AUX_NODE_SHIFT) - 1));
+    unsafe {
+        x86_64::registers::model_specific::Msr::new(IA32_TSC_AUX).write(aux as u64);
+    }
+}
+
+/// Build the image and allocate the shared pages. Time keeping must
+/// already run on its final clock source.
+pub fn init() {
+    let image = build_image();
+    let frames = {
+        let mut fa = mm::frame_allocator_lock();
+        match (fa.allocate(), fa.allocate()) {
+            (Some(vvar), Some(image)) => (vvar, image),
+            _ => {
+                crate::kprintln!("vdso: sem memória");
+                return;
+            }
+        }
+    };
+    unsafe {
+        let vvar = mm::phys_to_virt(frames.0.start_address()).as_mut_ptr::<u8>();
+        core::ptr::write_bytes(vvar, 0, PAGE_SIZE);
+        let dst = mm::phys_to_virt(frames.1.start_address()).as_mut_ptr::<u8>();
+        core::ptr::write_bytes(dst, 0, PAGE_SIZE);
+        core::ptr::copy_nonoverlapping(image.as_ptr(), dst, image.len());
+    }
+    FRAMES.call_once(|| frames);
+    if let Some(vvar) = vvar() {
+        vvar.getcpu_rdtscp.store(super::tsc::has_rdtscp() as u32, Ordering::Relaxed);
+    }
+    crate::kprintln!("vdso: {} bytes em {:#x}, dados em {:#x}", image.len(), VDSO_ADDR, VVAR_ADDR);
+}
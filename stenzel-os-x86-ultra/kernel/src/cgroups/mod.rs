@@ -9,7 +9,7 @@ use alloc::collections::BTreeMap;
 use alloc::string::String;
 use alloc::sync::Arc;
 use alloc::vec::Vec;
-use core::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use core::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, AtomicUsize, Ordering};
 
 use crate::sync::IrqSafeMutex;
 use crate::process::Pid;
@@ -45,6 +45,12 @@ pub struct CpuController {
     pub nr_throttled: AtomicU64,
     /// Total throttled time (nanoseconds)
     pub throttled_time_ns: AtomicU64,
+    /// CPU time used since creation (nanoseconds)
+    pub total_usage_ns: AtomicU64,
+    /// Number of elapsed periods
+    pub nr_periods: AtomicU64,
+    /// Start of the current period (nanoseconds since boot)
+    pub period_start_ns: AtomicU64,
 }
 
 impl CpuController {
@@ -56,6 +62,9 @@ impl CpuController {
             usage_ns: AtomicU64::new(0),
             nr_throttled: AtomicU64::new(0),
             throttled_time_ns: AtomicU64::new(0),
+            total_usage_ns: AtomicU64::new(0),
+            nr_periods: AtomicU64::new(0),
+            period_start_ns: AtomicU64::new(0),
         }
     }
 
@@ -94,6 +103,7 @@ impl CpuController {
     /// Account CPU usage
     pub fn charge(&self, runtime_ns: u64) {
         self.usage_ns.fetch_add(runtime_ns, Ordering::AcqRel);
+        self.total_usage_ns.fetch_add(runtime_ns, Ordering::AcqRel);
     }
 
     /// Reset quota for new period
@@ -101,6 +111,22 @@ impl CpuController {
         self.usage_ns.store(0, Ordering::Release);
     }
 
+    /// Start a new period if the current one is over at `now_ns`
+    pub fn refresh(&self, now_ns: u64) {
+        let period_ns = self.cfs_period_us.load(Ordering::Acquire) * 1000;
+        let start = self.period_start_ns.load(Ordering::Acquire);
+        if now_ns < start.saturating_add(period_ns) {
+            return;
+        }
+
+        // Periods stay aligned to the first one
+        let new_start = now_ns - (now_ns - start) % period_ns;
+        if self.period_start_ns.compare_exchange(start, new_start, Ordering::AcqRel, Ordering::Acquire).is_ok() {
+            self.nr_periods.fetch_add(1, Ordering::AcqRel);
+            self.reset_period();
+        }
+    }
+
     /// Record throttling event
     pub fn throttle(&self, duration_ns: u64) {
         self.nr_throttled.fetch_add(1, Ordering::AcqRel);
@@ -147,6 +173,12 @@ pub struct MemoryController {
     pub oom_control: AtomicBool,
     /// Under OOM condition
     pub under_oom: AtomicBool,
+    /// Number of times the OOM killer ran for this cgroup
+    pub oom_events: AtomicU64,
+    /// Number of tasks killed by it
+    pub oom_kills: AtomicU64,
+    /// Last task killed by the OOM killer (0 = none)
+    pub oom_victim: AtomicU64,
 }
 
 impl MemoryController {
@@ -161,6 +193,9 @@ impl MemoryController {
             swap_usage_bytes: AtomicU64::new(0),
             oom_control: AtomicBool::new(true),
             under_oom: AtomicBool::new(false),
+            oom_events: AtomicU64::new(0),
+            oom_kills: AtomicU64::new(0),
+            oom_victim: AtomicU64::new(0),
         }
     }
 
@@ -255,7 +290,6 @@ impl Default for MemoryController {
 }
 
 /// I/O controller - limits block device I/O
-#[derive(Debug)]
 pub struct IoController {
     /// Read bytes per second limit (0 = unlimited)
     pub read_bps_limit: AtomicU64,
@@ -275,8 +309,31 @@ pub struct IoController {
     pub write_ops: AtomicU64,
     /// Weight for proportional I/O (default 100)
     pub weight: AtomicU64,
+    /// Per-device io.max limits and io.stat counters
+    pub devices: IrqSafeMutex<BTreeMap<u32, IoDeviceState>>,
 }
 
+/// I/O limits (0 = unlimited) and usage of a cgroup on one block device
+#[derive(Debug, Clone, Default)]
+pub struct IoDeviceState {
+    pub rbps: u64,
+    pub wbps: u64,
+    pub riops: u64,
+    pub wiops: u64,
+    /// Totals reported by io.stat
+    pub rbytes: u64,
+    pub wbytes: u64,
+    pub rios: u64,
+    pub wios: u64,
+    /// Usage in the current one second window
+    window_start_ns: u64,
+    window_bytes: [u64; 2],
+    window_ios: [u64; 2],
+}
+
+/// Length of the io.max accounting window
+const IO_WINDOW_NS: u64 = 1_000_000_000;
+
 impl IoController {
     pub fn new() -> Self {
         Self {
@@ -289,6 +346,7 @@ impl IoController {
             read_ops: AtomicU64::new(0),
             write_ops: AtomicU64::new(0),
             weight: AtomicU64::new(100),
+            devices: IrqSafeMutex::new(BTreeMap::new()),
         }
     }
 
@@ -328,6 +386,80 @@ impl IoController {
         self.bytes_written.fetch_add(bytes, Ordering::AcqRel);
         self.write_ops.fetch_add(1, Ordering::AcqRel);
     }
+
+    /// Change the io.max limits of `device`; None leaves a limit as it is
+    pub fn set_device_limits(&self, device: u32, limits: [Option<u64>; 4]) {
+        let mut devices = self.devices.lock();
+        let state = devices.entry(device).or_default();
+        let fields = [&mut state.rbps, &mut state.wbps, &mut state.riops, &mut state.wiops];
+        for (field, limit) in fields.into_iter().zip(limits) {
+            if let Some(limit) = limit {
+                *field = limit;
+            }
+        }
+    }
+
+    /// When a `bytes` long request to `device` fits in the limits again,
+    /// or None if it can be issued at `now_ns`. The controller-wide limits
+    /// apply to devices without a limit of their own.
+    pub fn throttled_until(&self, device: u32, write: bool, bytes: u64, now_ns: u64) -> Option<u64> {
+        let effective = |limit: u64, global: &AtomicU64| if limit != 0 { limit } else { global.load(Ordering::Acquire) };
+
+        let mut devices = self.devices.lock();
+        let state = devices.entry(device).or_default();
+        let limits = [
+            (effective(state.rbps, &self.read_bps_limit), effective(state.riops, &self.read_iops_limit)),
+            (effective(state.wbps, &self.write_bps_limit), effective(state.wiops, &self.write_iops_limit)),
+        ];
+
+        // Every elapsed window pays off one limit worth of usage, so I/O
+        // issued over the limit keeps delaying the windows after it
+        if now_ns >= state.window_start_ns + IO_WINDOW_NS {
+            let elapsed = (now_ns - state.window_start_ns) / IO_WINDOW_NS;
+            state.window_start_ns += elapsed * IO_WINDOW_NS;
+            for (dir, (bps, iops)) in limits.iter().enumerate() {
+                let paid_bytes = if *bps != 0 { elapsed.saturating_mul(*bps) } else { u64::MAX };
+                let paid_ios = if *iops != 0 { elapsed.saturating_mul(*iops) } else { u64::MAX };
+                state.window_bytes[dir] = state.window_bytes[dir].saturating_sub(paid_bytes);
+                state.window_ios[dir] = state.window_ios[dir].saturating_sub(paid_ios);
+            }
+        }
+
+        // A request larger than the byte limit still goes out alone in a window
+        let dir = write as usize;
+        let (bps, iops) = limits[dir];
+        let used_bytes = state.window_bytes[dir] + bytes;
+        let used_ios = state.window_ios[dir] + 1;
+        let bps_windows = if bps != 0 && state.window_bytes[dir] != 0 && used_bytes > bps {
+            (used_bytes - 1) / bps
+        } else {
+            0
+        };
+        let iops_windows = if iops != 0 && used_ios > iops { (used_ios - 1) / iops } else { 0 };
+        match bps_windows.max(iops_windows) {
+            0 => None,
+            windows => Some(state.window_start_ns + windows * IO_WINDOW_NS),
+        }
+    }
+
+    /// Account a request issued to `device`
+    pub fn charge_device(&self, device: u32, write: bool, bytes: u64) {
+        let mut devices = self.devices.lock();
+        let state = devices.entry(device).or_default();
+        state.window_bytes[write as usize] += bytes;
+        state.window_ios[write as usize] += 1;
+        if write {
+            state.wbytes += bytes;
+            state.wios += 1;
+            drop(devices);
+            self.account_write(bytes);
+        } else {
+            state.rbytes += bytes;
+            state.rios += 1;
+            drop(devices);
+            self.account_read(bytes);
+        }
+    }
 }
 
 impl Default for IoController {
@@ -389,6 +521,11 @@ impl PidsController {
         }
     }
 
+    /// Take a PID slot even past the limit (migration between cgroups)
+    pub fn charge(&self) {
+        self.current.fetch_add(1, Ordering::AcqRel);
+    }
+
     /// Release a PID slot
     pub fn uncharge(&self) {
         let current = self.current.load(Ordering::Acquire);
@@ -495,10 +632,15 @@ pub struct Cgroup {
     pub pids: PidsController,
     /// Freezer controller
     pub freezer: FreezerController,
+    /// Controllers enabled for the children (bit i = CONTROLLERS[i])
+    pub subtree_control: AtomicU32,
     /// Depth in hierarchy
     pub depth: usize,
 }
 
+/// Controllers of the cgroup v2 interface, in `cgroup.controllers` order
+pub const CONTROLLERS: [&str; 4] = ["cpu", "io", "memory", "pids"];
+
 impl Cgroup {
     /// Create a new cgroup
     pub fn new(id: CgroupId, name: String, parent: Option<Arc<Cgroup>>) -> Self {
@@ -515,30 +657,112 @@ impl Cgroup {
             io: IoController::new(),
             pids: PidsController::new(),
             freezer: FreezerController::new(),
+            subtree_control: AtomicU32::new(0),
             depth,
         }
     }
 
-    /// Add a process to this cgroup
-    pub fn add_process(&self, pid: Pid) -> bool {
-        if !self.pids.try_charge() {
-            return false;
+    /// This cgroup and its ancestors, up to the root
+    pub fn ancestors(&self) -> impl Iterator<Item = &Cgroup> {
+        core::iter::successors(Some(self), |&cg| cg.parent.as_deref())
+    }
+
+    /// Controllers available in this cgroup (bitmask over `CONTROLLERS`)
+    pub fn controllers(&self) -> u32 {
+        match &self.parent {
+            Some(parent) => parent.subtree_control.load(Ordering::Acquire),
+            None => (1 << CONTROLLERS.len()) - 1,
         }
+    }
 
+    /// Add a process to this cgroup
+    ///
+    /// Moving a task does not check pids.max, as in Linux; only fork does.
+    pub fn add_process(&self, pid: Pid) -> bool {
         let mut members = self.members.lock();
         if !members.contains(&pid) {
             members.push(pid);
+            self.ancestors().for_each(|cg| cg.pids.charge());
         }
         true
     }
 
+    /// Add a new task, failing if this cgroup or an ancestor is at pids.max
+    pub fn add_forked(&self, pid: Pid) -> bool {
+        for (i, cg) in self.ancestors().enumerate() {
+            if !cg.pids.try_charge() {
+                self.ancestors().take(i).for_each(|cg| cg.pids.uncharge());
+                return false;
+            }
+        }
+        self.members.lock().push(pid);
+        true
+    }
+
     /// Remove a process from this cgroup
     pub fn remove_process(&self, pid: Pid) {
         let mut members = self.members.lock();
         if let Some(pos) = members.iter().position(|&p| p == pid) {
             members.remove(pos);
-            self.pids.uncharge();
+            self.ancestors().for_each(|cg| cg.pids.uncharge());
+        }
+    }
+
+    /// Members of this cgroup and of all its descendants
+    pub fn subtree_members(&self) -> Vec<Pid> {
+        let mut pids = self.members.lock().clone();
+        let children: Vec<Arc<Cgroup>> = self.children.lock().values().cloned().collect();
+        for child in children {
+            pids.extend(child.subtree_members());
         }
+        pids
+    }
+
+    /// Charge `bytes` of memory to this cgroup and its ancestors; fails,
+    /// charging nothing, if any of them would go over memory.max
+    pub fn try_charge_memory(&self, bytes: u64) -> bool {
+        for (i, cg) in self.ancestors().enumerate() {
+            if !cg.memory.try_charge(bytes) {
+                self.ancestors().take(i).for_each(|cg| cg.memory.uncharge(bytes));
+                return false;
+            }
+        }
+        true
+    }
+
+    /// Return memory charged with `try_charge_memory`
+    pub fn uncharge_memory(&self, bytes: u64) {
+        self.ancestors().for_each(|cg| cg.memory.uncharge(bytes));
+    }
+
+    /// Account CPU time used at `now_ns` to this cgroup and its ancestors
+    pub fn charge_cpu(&self, runtime_ns: u64, now_ns: u64) {
+        for cg in self.ancestors() {
+            cg.cpu.refresh(now_ns);
+            cg.cpu.charge(runtime_ns);
+        }
+    }
+
+    /// Whether the cpu.max quota of this cgroup or of an ancestor is used
+    /// up for the current period
+    pub fn cpu_throttled(&self, now_ns: u64) -> bool {
+        self.ancestors().any(|cg| {
+            cg.cpu.refresh(now_ns);
+            !cg.cpu.can_run(1)
+        })
+    }
+
+    /// When io.max of this cgroup and all its ancestors lets a `bytes` long
+    /// request to `device` through, or None if it can go now
+    pub fn io_throttled_until(&self, device: u32, write: bool, bytes: u64, now_ns: u64) -> Option<u64> {
+        self.ancestors()
+            .filter_map(|cg| cg.io.throttled_until(device, write, bytes, now_ns))
+            .max()
+    }
+
+    /// Account an issued request in io.stat of this cgroup and its ancestors
+    pub fn charge_io(&self, device: u32, write: bool, bytes: u64) {
+        self.ancestors().for_each(|cg| cg.io.charge_device(device, write, bytes));
     }
 
     /// Check if process is member
@@ -680,6 +904,11 @@ impl CgroupManager {
         Some(current)
     }
 
+    /// Create a child cgroup `name` under `parent` (mkdir in cgroup2)
+    pub fn create_child(&self, parent: &Arc<Cgroup>, name: &str) -> Option<Arc<Cgroup>> {
+        parent.create_child(String::from(name), self.alloc_id())
+    }
+
     /// Get cgroup by path
     pub fn get(&self, path: &str) -> Option<Arc<Cgroup>> {
         let parts: Vec<&str> = path.trim_matches('/').split('/').filter(|s| !s.is_empty()).collect();
@@ -744,6 +973,21 @@ impl CgroupManager {
         self.process_cgroups.lock().get(&pid).cloned()
     }
 
+    /// Cgroup a process is in; tasks never attached anywhere are in the root
+    pub fn task_cgroup(&self, pid: Pid) -> Arc<Cgroup> {
+        self.get_process_cgroup(pid).unwrap_or_else(|| self.root.clone())
+    }
+
+    /// Put a new task in the cgroup of the task that forked it
+    pub fn fork(&self, parent: Pid, child: Pid) -> bool {
+        let cgroup = self.task_cgroup(parent);
+        if !cgroup.add_forked(child) {
+            return false;
+        }
+        self.process_cgroups.lock().insert(child, cgroup);
+        true
+    }
+
     /// Remove a process from tracking (on exit)
     pub fn detach_process(&self, pid: Pid) {
         let mut mapping = self.process_cgroups.lock();
@@ -899,7 +1143,7 @@ pub fn sys_cgroup_stat(path: &str) -> Result<CgroupStat, KError> {
     Ok(CgroupStat {
         id: cg.id,
         nr_processes: cg.process_count() as u64,
-        cpu_usage_ns: cg.cpu.usage_ns.load(Ordering::Acquire),
+        cpu_usage_ns: cg.cpu.total_usage_ns.load(Ordering::Acquire),
         cpu_throttled_ns: cg.cpu.throttled_time_ns.load(Ordering::Acquire),
         memory_usage_bytes: cg.memory.usage_bytes.load(Ordering::Acquire),
         memory_limit_bytes: cg.memory.limit_bytes.load(Ordering::Acquire),
@@ -927,30 +1171,44 @@ pub struct CgroupStat {
 // Scheduler Integration
 // ============================================================================
 
-/// Hook called by scheduler to check if process can run
-pub fn sched_check_cgroup(pid: Pid) -> bool {
+/// Hook called by fork/clone: the child joins the cgroup of its parent.
+/// Fails if that cgroup or an ancestor is at pids.max.
+pub fn task_fork(parent: Pid, child: Pid) -> Result<(), KError> {
+    match CGROUP_MANAGER.get() {
+        Some(mgr) if !mgr.fork(parent, child) => Err(KError::WouldBlock),
+        _ => Ok(()),
+    }
+}
+
+/// Hook called by scheduler to check if process can run at `now_ns`
+/// (frozen, or out of cpu.max quota for the current period)
+pub fn sched_check_cgroup(pid: Pid, now_ns: u64) -> bool {
     if let Some(mgr) = CGROUP_MANAGER.get() {
-        mgr.can_run(pid)
+        let cg = mgr.task_cgroup(pid);
+        cg.check_limits(pid) && !cg.cpu_throttled(now_ns)
     } else {
         true
     }
 }
 
 /// Hook called by scheduler to account CPU time
-pub fn sched_charge_cpu(pid: Pid, runtime_ns: u64) {
+pub fn sched_charge_cpu(pid: Pid, runtime_ns: u64, now_ns: u64) {
     if let Some(mgr) = CGROUP_MANAGER.get() {
-        if let Some(cg) = mgr.get_process_cgroup(pid) {
-            cg.cpu.charge(runtime_ns);
-        }
+        mgr.task_cgroup(pid).charge_cpu(runtime_ns, now_ns);
+    }
+}
+
+/// Hook called by scheduler when a task it held back may run again
+pub fn sched_unthrottle(pid: Pid, throttled_ns: u64) {
+    if let Some(mgr) = CGROUP_MANAGER.get() {
+        mgr.task_cgroup(pid).cpu.throttle(throttled_ns);
     }
 }
 
 /// Hook called by memory allocator to charge memory
 pub fn mm_try_charge(pid: Pid, bytes: u64) -> bool {
     if let Some(mgr) = CGROUP_MANAGER.get() {
-        if let Some(cg) = mgr.get_process_cgroup(pid) {
-            return cg.memory.try_charge(bytes);
-        }
+        return mgr.task_cgroup(pid).try_charge_memory(bytes);
     }
     true // No cgroup = allow
 }
@@ -958,12 +1216,108 @@ pub fn mm_try_charge(pid: Pid, bytes: u64) -> bool {
 /// Hook called by memory allocator to uncharge memory
 pub fn mm_uncharge(pid: Pid, bytes: u64) {
     if let Some(mgr) = CGROUP_MANAGER.get() {
-        if let Some(cg) = mgr.get_process_cgroup(pid) {
-            cg.memory.uncharge(bytes);
+        mgr.task_cgroup(pid).uncharge_memory(bytes);
+    }
+}
+
+/// Cgroup and process (thread group) each charged user page belongs to,
+/// by physical address. A page stays charged to the cgroup that faulted it
+/// in until it is freed, even if the process moves or pages are shared
+/// after fork.
+static PAGE_CHARGES: IrqSafeMutex<BTreeMap<u64, (Arc<Cgroup>, Pid)>> = IrqSafeMutex::new(BTreeMap::new());
+
+/// Hook called by the page fault handler for a user page it is about to
+/// map for the current task
+///
+/// Over memory.max the OOM killer picks a victim inside the cgroup: fails
+/// with `WouldBlock` if a task was killed (the fault should be retried once
+/// its memory is gone) and with `NoMemory` if none could be.
+pub fn mm_charge_page(frame: u64) -> Result<(), KError> {
+    let (Some(mgr), Some(task)) = (CGROUP_MANAGER.get(), crate::sched::try_current_task()) else {
+        return Ok(());
+    };
+    let cgroup = mgr.task_cgroup(Pid(task.id()));
+    if !cgroup.try_charge_memory(PAGE_SIZE as u64) {
+        return if memory_oom(&cgroup) { Err(KError::WouldBlock) } else { Err(KError::NoMemory) };
+    }
+    PAGE_CHARGES.lock().insert(frame, (cgroup, Pid(task.tgid())));
+    Ok(())
+}
+
+/// Hook called when a user page is freed
+pub fn mm_uncharge_page(frame: u64) {
+    let charge = PAGE_CHARGES.lock().remove(&frame);
+    if let Some((cgroup, _)) = charge {
+        cgroup.uncharge_memory(PAGE_SIZE as u64);
+    }
+}
+
+/// Memory charged to `cgroup` (or a descendant) by each process
+fn charged_per_process(cgroup: &Cgroup) -> BTreeMap<Pid, u64> {
+    let mut usage = BTreeMap::new();
+    for (owner, pid) in PAGE_CHARGES.lock().values() {
+        if owner.ancestors().any(|cg| cg.id == cgroup.id) {
+            *usage.entry(*pid).or_insert(0) += PAGE_SIZE as u64;
+        }
+    }
+    usage
+}
+
+/// OOM inside `cgroup`: kill the process of the subtree with the highest
+/// OOM score. Returns whether one was killed.
+fn memory_oom(cgroup: &Cgroup) -> bool {
+    use crate::mm::oom::{OomPolicy, OomProcessInfo, ProcessMemoryStats};
+
+    // The previous victim still holds its memory until it exits; wait for
+    // it instead of killing another task
+    let victim = cgroup.memory.oom_victim.load(Ordering::Acquire);
+    if victim != 0 && crate::sched::task_alive(victim) {
+        return true;
+    }
+
+    cgroup.memory.oom_events.fetch_add(1, Ordering::AcqRel);
+    let usage = charged_per_process(cgroup);
+    let members = cgroup.subtree_members();
+    let candidates: Vec<OomProcessInfo> = members
+        .iter()
+        .filter_map(|pid| crate::sched::get_task_info(pid.0))
+        .filter(|info| info.tgid == info.pid && crate::sched::task_alive(info.pid))
+        .map(|info| {
+            let rss = usage.get(&Pid(info.pid)).copied().unwrap_or(0);
+            OomProcessInfo {
+                pid: info.pid,
+                name: info.name,
+                ppid: info.ppid,
+                uid: info.uid,
+                memory: ProcessMemoryStats { rss, private: rss, ..Default::default() },
+                nice: crate::sched::get_task_priority(info.pid).unwrap_or(0),
+                start_time: 0,
+                oom_adj: 0,
+                policy: OomPolicy::Normal,
+                is_kernel: false,
+                children: Vec::new(),
+            }
+        })
+        .collect();
+
+    let limit = cgroup.memory.limit_bytes.load(Ordering::Acquire);
+    match crate::mm::oom::cgroup_oom(&cgroup.path(), limit, candidates) {
+        Some(result) => {
+            cgroup.memory.oom_kills.fetch_add(1, Ordering::AcqRel);
+            cgroup.memory.oom_victim.store(result.pid, Ordering::Release);
+            true
         }
+        None => false,
     }
 }
 
+/// Cgroup of the calling task (None before cgroups and the scheduler are up)
+pub fn current_cgroup() -> Option<Arc<Cgroup>> {
+    let mgr = CGROUP_MANAGER.get()?;
+    let task = crate::sched::try_current_task()?;
+    Some(mgr.task_cgroup(Pid(task.id())))
+}
+
 /// Hook called when process exits
 pub fn on_process_exit(pid: Pid) {
     if let Some(mgr) = CGROUP_MANAGER.get() {
@@ -377,7 +377,8 @@ impl ContainerRuntime {
         }
 
         // Create namespaces
-        let ns = Arc::new(NamespaceSet::new_root());
+        // Own mount table, so mounts made for the container stay private
+        let ns = Arc::new(NamespaceSet::new_root().unshare(crate::ipc::namespace::flags::CLONE_NEWNS));
         c.namespaces = Some(ns.clone());
 
         // Create cgroup
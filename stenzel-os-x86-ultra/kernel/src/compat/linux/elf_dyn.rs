@@ -305,7 +305,6 @@ pub struct Elf64Shdr {
 pub mod sht {
     pub const NULL: u32 = 0;
     pub const PROGBITS: u32 = 1;
-    pub const SYMTAB: u32 = 2;
     pub const STRTAB: u32 = 3;
     pub const RELA: u32 = 4;
     pub const HASH: u32 = 5;
@@ -43,7 +43,17 @@ pub fn get_compat_status() -> Vec<FeatureStatus> {
         FeatureStatus {
             name: String::from("prctl"),
             level: CompatLevel::Partial,
-            notes: Some(String::from("PR_SET_NAME, PR_GET_NAME")),
+            notes: Some(String::from("PR_SET/GET_NO_NEW_PRIVS, PR_SET/GET_SECCOMP")),
+        },
+        FeatureStatus {
+            name: String::from("seccomp"),
+            level: CompatLevel::Partial,
+            notes: Some(String::from("Strict and filter modes, no user notification")),
+        },
+        FeatureStatus {
+            name: String::from("bpf"),
+            level: CompatLevel::Partial,
+            notes: Some(String::from("Maps, socket filter/XDP/tracepoint programs, bpffs pins")),
         },
         FeatureStatus {
             name: String::from("arch_prctl"),
@@ -149,18 +159,18 @@ pub fn get_compat_status() -> Vec<FeatureStatus> {
         },
         FeatureStatus {
             name: String::from("timerfd_create"),
-            level: CompatLevel::Stub,
-            notes: Some(String::from("Planned")),
+            level: CompatLevel::Full,
+            notes: None,
         },
         FeatureStatus {
             name: String::from("signalfd"),
-            level: CompatLevel::Stub,
-            notes: Some(String::from("Planned")),
+            level: CompatLevel::Full,
+            notes: None,
         },
         FeatureStatus {
             name: String::from("inotify_*"),
-            level: CompatLevel::Stub,
-            notes: Some(String::from("Planned")),
+            level: CompatLevel::Full,
+            notes: None,
         },
 
         // I/O
@@ -215,17 +225,17 @@ pub fn get_compat_status() -> Vec<FeatureStatus> {
         // I/O Multiplexing
         FeatureStatus {
             name: String::from("epoll_create"),
-            level: CompatLevel::Partial,
-            notes: Some(String::from("Basic support")),
+            level: CompatLevel::Full,
+            notes: None,
         },
         FeatureStatus {
             name: String::from("epoll_ctl"),
-            level: CompatLevel::Partial,
+            level: CompatLevel::Full,
             notes: None,
         },
         FeatureStatus {
             name: String::from("epoll_wait"),
-            level: CompatLevel::Partial,
+            level: CompatLevel::Full,
             notes: None,
         },
         FeatureStatus {
@@ -267,6 +277,11 @@ pub fn get_compat_status() -> Vec<FeatureStatus> {
         },
 
         // Memory
+        FeatureStatus {
+            name: String::from("msync"),
+            level: CompatLevel::Full,
+            notes: None,
+        },
         FeatureStatus {
             name: String::from("mremap"),
             level: CompatLevel::Stub,
@@ -0,0 +1,313 @@
+//! Checksums used by the container formats
+//!
+//! - CRC-32 (IEEE 802.3, reflected): gzip, zip, xz
+//! - CRC-64 (ECMA-182, reflected): xz
+//! - Adler-32: zlib
+//! - xxHash32: LZ4 frames
+//! - xxHash64: zstd frames
+
+const fn make_crc32_table() -> [u32; 256] {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut j = 0;
+        while j < 8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 };
+            j += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+const fn make_crc64_table() -> [u64; 256] {
+    let mut table = [0u64; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u64;
+        let mut j = 0;
+        while j < 8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xC96C_5795_D787_0F42 } else { crc >> 1 };
+            j += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+static CRC32_TABLE: [u32; 256] = make_crc32_table();
+static CRC64_TABLE: [u64; 256] = make_crc64_table();
+
+/// Continue a CRC-32 over `data`; start with `crc = 0`
+pub fn crc32_update(crc: u32, data: &[u8]) -> u32 {
+    let mut crc = !crc;
+    for &byte in data {
+        crc = CRC32_TABLE[((crc ^ byte as u32) & 0xFF) as usize] ^ (crc >> 8);
+    }
+    !crc
+}
+
+pub fn crc32(data: &[u8]) -> u32 {
+    crc32_update(0, data)
+}
+
+/// Continue a CRC-64 over `data`; start with `crc = 0`
+pub fn crc64_update(crc: u64, data: &[u8]) -> u64 {
+    let mut crc = !crc;
+    for &byte in data {
+        crc = CRC64_TABLE[((crc ^ byte as u64) & 0xFF) as usize] ^ (crc >> 8);
+    }
+    !crc
+}
+
+pub fn crc64(data: &[u8]) -> u64 {
+    crc64_update(0, data)
+}
+
+/// Continue an Adler-32 over `data`; start with `adler = 1`
+pub fn adler32_update(adler: u32, data: &[u8]) -> u32 {
+    const MOD: u32 = 65521;
+    // 5552 is the largest run that cannot overflow b before the reduction
+    let mut a = adler & 0xFFFF;
+    let mut b = adler >> 16;
+    for chunk in data.chunks(5552) {
+        for &byte in chunk {
+            a += byte as u32;
+            b += a;
+        }
+        a %= MOD;
+        b %= MOD;
+    }
+    (b << 16) | a
+}
+
+pub fn adler32(data: &[u8]) -> u32 {
+    adler32_update(1, data)
+}
+
+const P32_1: u32 = 0x9E37_79B1;
+const P32_2: u32 = 0x85EB_CA77;
+const P32_3: u32 = 0xC2B2_AE3D;
+const P32_4: u32 = 0x27D4_EB2F;
+const P32_5: u32 = 0x1656_67B1;
+
+fn read32(data: &[u8]) -> u32 {
+    u32::from_le_bytes([data[0], data[1], data[2], data[3]])
+}
+
+fn read64(data: &[u8]) -> u64 {
+    let mut bytes = [0u8; 8];
+    bytes.copy_from_slice(&data[..8]);
+    u64::from_le_bytes(bytes)
+}
+
+/// Streaming xxHash32
+#[derive(Clone)]
+pub struct Xxh32 {
+    acc: [u32; 4],
+    buf: [u8; 16],
+    buf_len: usize,
+    total: u64,
+    seed: u32,
+}
+
+impl Xxh32 {
+    pub fn new(seed: u32) -> Self {
+        Self {
+            acc: [
+                seed.wrapping_add(P32_1).wrapping_add(P32_2),
+                seed.wrapping_add(P32_2),
+                seed,
+                seed.wrapping_sub(P32_1),
+            ],
+            buf: [0; 16],
+            buf_len: 0,
+            total: 0,
+            seed,
+        }
+    }
+
+    fn round(acc: u32, input: u32) -> u32 {
+        acc.wrapping_add(input.wrapping_mul(P32_2)).rotate_left(13).wrapping_mul(P32_1)
+    }
+
+    fn stripe(&mut self, stripe: &[u8]) {
+        for i in 0..4 {
+            self.acc[i] = Self::round(self.acc[i], read32(&stripe[i * 4..]));
+        }
+    }
+
+    pub fn update(&mut self, mut data: &[u8]) {
+        self.total += data.len() as u64;
+        if self.buf_len > 0 {
+            let take = (16 - self.buf_len).min(data.len());
+            self.buf[self.buf_len..self.buf_len + take].copy_from_slice(&data[..take]);
+            self.buf_len += take;
+            data = &data[take..];
+            if self.buf_len < 16 {
+                return;
+            }
+            let buf = self.buf;
+            self.stripe(&buf);
+            self.buf_len = 0;
+        }
+        while data.len() >= 16 {
+            self.stripe(&data[..16]);
+            data = &data[16..];
+        }
+        self.buf[..data.len()].copy_from_slice(data);
+        self.buf_len = data.len();
+    }
+
+    pub fn finish(&self) -> u32 {
+        let mut h = if self.total >= 16 {
+            self.acc[0].rotate_left(1)
+                .wrapping_add(self.acc[1].rotate_left(7))
+                .wrapping_add(self.acc[2].rotate_left(12))
+                .wrapping_add(self.acc[3].rotate_left(18))
+        } else {
+            self.seed.wrapping_add(P32_5)
+        };
+        h = h.wrapping_add(self.total as u32);
+
+        let mut rest = &self.buf[..self.buf_len];
+        while rest.len() >= 4 {
+            h = h.wrapping_add(read32(rest).wrapping_mul(P32_3)).rotate_left(17).wrapping_mul(P32_4);
+            rest = &rest[4..];
+        }
+        for &byte in rest {
+            h = h.wrapping_add((byte as u32).wrapping_mul(P32_5)).rotate_left(11).wrapping_mul(P32_1);
+        }
+
+        h ^= h >> 15;
+        h = h.wrapping_mul(P32_2);
+        h ^= h >> 13;
+        h = h.wrapping_mul(P32_3);
+        h ^= h >> 16;
+        h
+    }
+}
+
+pub fn xxh32(data: &[u8], seed: u32) -> u32 {
+    let mut hasher = Xxh32::new(seed);
+    hasher.update(data);
+    hasher.finish()
+}
+
+const P64_1: u64 = 0x9E37_79B1_85EB_CA87;
+const P64_2: u64 = 0xC2B2_AE3D_27D4_EB4F;
+const P64_3: u64 = 0x1656_67B1_9E37_79F9;
+const P64_4: u64 = 0x85EB_CA77_C2B2_AE63;
+const P64_5: u64 = 0x27D4_EB2F_1656_67C5;
+
+/// Streaming xxHash64
+#[derive(Clone)]
+pub struct Xxh64 {
+    acc: [u64; 4],
+    buf: [u8; 32],
+    buf_len: usize,
+    total: u64,
+    seed: u64,
+}
+
+impl Xxh64 {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            acc: [
+                seed.wrapping_add(P64_1).wrapping_add(P64_2),
+                seed.wrapping_add(P64_2),
+                seed,
+                seed.wrapping_sub(P64_1),
+            ],
+            buf: [0; 32],
+            buf_len: 0,
+            total: 0,
+            seed,
+        }
+    }
+
+    fn round(acc: u64, input: u64) -> u64 {
+        acc.wrapping_add(input.wrapping_mul(P64_2)).rotate_left(31).wrapping_mul(P64_1)
+    }
+
+    fn merge(acc: u64, val: u64) -> u64 {
+        (acc ^ Self::round(0, val)).wrapping_mul(P64_1).wrapping_add(P64_4)
+    }
+
+    fn stripe(&mut self, stripe: &[u8]) {
+        for i in 0..4 {
+            self.acc[i] = Self::round(self.acc[i], read64(&stripe[i * 8..]));
+        }
+    }
+
+    pub fn update(&mut self, mut data: &[u8]) {
+        self.total += data.len() as u64;
+        if self.buf_len > 0 {
+            let take = (32 - self.buf_len).min(data.len());
+            self.buf[self.buf_len..self.buf_len + take].copy_from_slice(&data[..take]);
+            self.buf_len += take;
+            data = &data[take..];
+            if self.buf_len < 32 {
+                return;
+            }
+            let buf = self.buf;
+            self.stripe(&buf);
+            self.buf_len = 0;
+        }
+        while data.len() >= 32 {
+            self.stripe(&data[..32]);
+            data = &data[32..];
+        }
+        self.buf[..data.len()].copy_from_slice(data);
+        self.buf_len = data.len();
+    }
+
+    pub fn finish(&self) -> u64 {
+        let mut h = if self.total >= 32 {
+            let [a, b, c, d] = self.acc;
+            let mut h = a.rotate_left(1)
+                .wrapping_add(b.rotate_left(7))
+                .wrapping_add(c.rotate_left(12))
+                .wrapping_add(d.rotate_left(18));
+            for v in self.acc {
+                h = Self::merge(h, v);
+            }
+            h
+        } else {
+            self.seed.wrapping_add(P64_5)
+        };
+        h = h.wrapping_add(self.total);
+
+        let mut rest = &self.buf[..self.buf_len];
+        while rest.len() >= 8 {
+            h ^= Self::round(0, read64(rest));
+            h = h.rotate_left(27).wrapping_mul(P64_1).wrapping_add(P64_4);
+            rest = &rest[8..];
+        }
+        if rest.len() >= 4 {
+            h ^= (read32(rest) as u64).wrapping_mul(P64_1);
+            h = h.rotate_left(23).wrapping_mul(P64_2).wrapping_add(P64_3);
+            rest = &rest[4..];
+        }
+        for &byte in rest {
+            h ^= (byte as u64).wrapping_mul(P64_5);
+            h = h.rotate_left(11).wrapping_mul(P64_1);
+        }
+
+        h ^= h >> 33;
+        h = h.wrapping_mul(P64_2);
+        h ^= h >> 29;
+        h = h.wrapping_mul(P64_3);
+        h ^= h >> 32;
+        h
+    }
+}
+
+pub fn xxh64(data: &[u8], seed: u64) -> u64 {
+    let mut hasher = Xxh64::new(seed);
+    hasher.update(data);
+    hasher.finish()
+}
@@ -0,0 +1,900 @@
+//! DEFLATE (RFC 1951) with zlib (RFC 1950) and gzip (RFC 1952) framing
+//!
+//! `Inflate` decodes a raw DEFLATE stream; `ZlibDecoder` and `GzipDecoder`
+//! add the container headers and verify the Adler-32/CRC-32 trailers.
+//! Multi-member gzip files are decoded as one stream, as gzip(1) does.
+//! The encoder uses the shared LZ77 match finder and picks stored, fixed
+//! or dynamic Huffman coding per block, whichever is smallest.
+
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+
+use super::checksum::{adler32, adler32_update, crc32, crc32_update};
+use super::huffman::{canonical_codes, code_lengths};
+use super::lz77::{MatchFinder, Params};
+use super::{read_all, CResult, CompressError, Decompress, Window};
+
+pub const GZIP_MAGIC: [u8; 2] = [0x1F, 0x8B];
+
+const WINDOW_SIZE: usize = 32 * 1024;
+const MAX_BITS: usize = 15;
+const END_OF_BLOCK: usize = 256;
+
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115,
+    131, 163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DIST_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DIST_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+/// Order in which code length code lengths are sent
+const CLEN_ORDER: [usize; 19] = [16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15];
+
+fn fixed_lit_lengths() -> [u8; 288] {
+    let mut lengths = [8u8; 288];
+    lengths[144..256].fill(9);
+    lengths[256..280].fill(7);
+    lengths
+}
+
+// ============================================================================
+// Bit input
+// ============================================================================
+
+/// Least-significant-bit-first reader
+struct BitReader<'a> {
+    data: &'a [u8],
+    /// Next byte to load into `bits`
+    pos: usize,
+    bits: u64,
+    count: u32,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data, pos: 0, bits: 0, count: 0 }
+    }
+
+    fn refill(&mut self) {
+        while self.count <= 56 && self.pos < self.data.len() {
+            self.bits |= (self.data[self.pos] as u64) << self.count;
+            self.pos += 1;
+            self.count += 8;
+        }
+    }
+
+    /// Next `n` bits, zero padded past the end of the input
+    fn peek(&mut self, n: u32) -> u32 {
+        if self.count < n {
+            self.refill();
+        }
+        (self.bits & ((1u64 << n) - 1)) as u32
+    }
+
+    fn consume(&mut self, n: u32) -> CResult<()> {
+        if n > self.count {
+            return Err(CompressError::Truncated);
+        }
+        self.bits >>= n;
+        self.count -= n;
+        Ok(())
+    }
+
+    fn bits(&mut self, n: u32) -> CResult<u32> {
+        let value = self.peek(n);
+        self.consume(n)?;
+        Ok(value)
+    }
+
+    fn align(&mut self) {
+        let partial = self.count % 8;
+        self.bits >>= partial;
+        self.count -= partial;
+    }
+
+    /// Input bytes used so far; only meaningful after `align`
+    fn byte_pos(&self) -> usize {
+        self.pos - (self.count / 8) as usize
+    }
+
+    /// Raw bytes from a byte-aligned position
+    fn take_bytes(&mut self, n: usize) -> CResult<&'a [u8]> {
+        let start = self.byte_pos();
+        let end = start.checked_add(n).ok_or(CompressError::Truncated)?;
+        if end > self.data.len() {
+            return Err(CompressError::Truncated);
+        }
+        self.pos = end;
+        self.bits = 0;
+        self.count = 0;
+        Ok(&self.data[start..end])
+    }
+}
+
+// ============================================================================
+// Huffman decoding
+// ============================================================================
+
+const FAST_BITS: u32 = 9;
+
+/// Canonical Huffman decoder: a table for codes up to `FAST_BITS` long and
+/// a bit-by-bit walk over the canonical code for longer ones
+struct Huffman {
+    /// `symbol << 4 | length`, 0 for codes longer than `FAST_BITS`
+    fast: Vec<u16>,
+    counts: [u16; MAX_BITS + 1],
+    /// Symbols ordered by code
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> CResult<Self> {
+        let mut counts = [0u16; MAX_BITS + 1];
+        for &len in lengths {
+            counts[len as usize] += 1;
+        }
+        counts[0] = 0;
+
+        // Over-subscribed codes are invalid; incomplete ones are allowed
+        // (a lone distance code is legal) and fail when an unused code shows up
+        let mut left: i32 = 1;
+        for &count in &counts[1..] {
+            left = (left << 1) - count as i32;
+            if left < 0 {
+                return Err(CompressError::Corrupt);
+            }
+        }
+
+        let mut offsets = [0u16; MAX_BITS + 2];
+        for len in 1..=MAX_BITS {
+            offsets[len + 1] = offsets[len] + counts[len];
+        }
+        let mut symbols = vec![0u16; offsets[MAX_BITS + 1] as usize];
+        for (sym, &len) in lengths.iter().enumerate() {
+            if len != 0 {
+                symbols[offsets[len as usize] as usize] = sym as u16;
+                offsets[len as usize] += 1;
+            }
+        }
+
+        let mut fast = vec![0u16; 1 << FAST_BITS];
+        let codes = canonical_codes(lengths);
+        for (sym, &len) in lengths.iter().enumerate() {
+            let len = len as u32;
+            if len == 0 || len > FAST_BITS {
+                continue;
+            }
+            let reversed = (codes[sym].reverse_bits() >> (16 - len)) as usize;
+            let mut index = reversed;
+            while index < fast.len() {
+                fast[index] = (sym as u16) << 4 | len as u16;
+                index += 1 << len;
+            }
+        }
+
+        Ok(Self { fast, counts, symbols })
+    }
+
+    fn decode(&self, input: &mut BitReader) -> CResult<usize> {
+        let bits = input.peek(MAX_BITS as u32);
+        let entry = self.fast[(bits & ((1 << FAST_BITS) - 1)) as usize];
+        if entry != 0 {
+            input.consume((entry & 0xF) as u32)?;
+            return Ok((entry >> 4) as usize);
+        }
+
+        let (mut code, mut first, mut index) = (0i32, 0i32, 0i32);
+        for len in 1..=MAX_BITS {
+            code |= ((bits >> (len - 1)) & 1) as i32;
+            let count = self.counts[len] as i32;
+            if code - first < count {
+                input.consume(len as u32)?;
+                return Ok(self.symbols[(index + code - first) as usize] as usize);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err(CompressError::Corrupt)
+    }
+}
+
+// ============================================================================
+// Raw DEFLATE decoder
+// ============================================================================
+
+enum BlockState {
+    Header,
+    Stored { remaining: usize },
+    Huffman { lit: Huffman, dist: Huffman },
+    Done,
+}
+
+/// Raw DEFLATE stream decoder
+pub struct Inflate<'a> {
+    input: BitReader<'a>,
+    window: Window,
+    state: BlockState,
+    last_block: bool,
+}
+
+impl<'a> Inflate<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Self {
+            input: BitReader::new(data),
+            window: Window::new(WINDOW_SIZE),
+            state: BlockState::Header,
+            last_block: false,
+        }
+    }
+
+    /// Compressed bytes used; valid once the stream has ended
+    pub fn consumed(&self) -> usize {
+        self.input.byte_pos()
+    }
+
+    fn finished(&self) -> bool {
+        matches!(self.state, BlockState::Done)
+    }
+
+    fn end_block(&mut self) {
+        self.state = if self.last_block {
+            self.input.align();
+            BlockState::Done
+        } else {
+            BlockState::Header
+        };
+    }
+
+    fn read_header(&mut self) -> CResult<()> {
+        self.last_block = self.input.bits(1)? == 1;
+        self.state = match self.input.bits(2)? {
+            0 => {
+                self.input.align();
+                let len = self.input.bits(16)?;
+                let nlen = self.input.bits(16)?;
+                if len != !nlen & 0xFFFF {
+                    return Err(CompressError::Corrupt);
+                }
+                BlockState::Stored { remaining: len as usize }
+            }
+            1 => BlockState::Huffman {
+                lit: Huffman::new(&fixed_lit_lengths())?,
+                dist: Huffman::new(&[5u8; 30])?,
+            },
+            2 => self.read_dynamic_tables()?,
+            _ => return Err(CompressError::Corrupt),
+        };
+        Ok(())
+    }
+
+    fn read_dynamic_tables(&mut self) -> CResult<BlockState> {
+        let input = &mut self.input;
+        let hlit = input.bits(5)? as usize + 257;
+        let hdist = input.bits(5)? as usize + 1;
+        let hclen = input.bits(4)? as usize + 4;
+        if hlit > 286 || hdist > 30 {
+            return Err(CompressError::Corrupt);
+        }
+
+        let mut clen_lengths = [0u8; 19];
+        for &sym in &CLEN_ORDER[..hclen] {
+            clen_lengths[sym] = input.bits(3)? as u8;
+        }
+        let clen = Huffman::new(&clen_lengths)?;
+
+        let mut lengths = vec![0u8; hlit + hdist];
+        let mut i = 0;
+        while i < lengths.len() {
+            let sym = clen.decode(input)?;
+            let (value, repeat) = match sym {
+                0..=15 => (sym as u8, 1),
+                16 => {
+                    let prev = *lengths[..i].last().ok_or(CompressError::Corrupt)?;
+                    (prev, 3 + input.bits(2)? as usize)
+                }
+                17 => (0, 3 + input.bits(3)? as usize),
+                _ => (0, 11 + input.bits(7)? as usize),
+            };
+            if i + repeat > lengths.len() {
+                return Err(CompressError::Corrupt);
+            }
+            lengths[i..i + repeat].fill(value);
+            i += repeat;
+        }
+        if lengths[END_OF_BLOCK] == 0 {
+            return Err(CompressError::Corrupt);
+        }
+
+        Ok(BlockState::Huffman {
+            lit: Huffman::new(&lengths[..hlit])?,
+            dist: Huffman::new(&lengths[hlit..])?,
+        })
+    }
+
+    /// Decode until a block boundary or a window's worth of output
+    fn step(&mut self) -> CResult<()> {
+        match &mut self.state {
+            BlockState::Header => self.read_header(),
+            BlockState::Stored { remaining } => {
+                let take = (*remaining).min(WINDOW_SIZE);
+                *remaining -= take;
+                let done = *remaining == 0;
+                let bytes = self.input.take_bytes(take)?;
+                self.window.extend(bytes);
+                if done {
+                    self.end_block();
+                }
+                Ok(())
+            }
+            BlockState::Huffman { lit, dist } => {
+                while self.window.pending() < WINDOW_SIZE {
+                    let sym = lit.decode(&mut self.input)?;
+                    if sym < 256 {
+                        self.window.push(sym as u8);
+                        continue;
+                    }
+                    if sym == END_OF_BLOCK {
+                        self.end_block();
+                        return Ok(());
+                    }
+                    let idx = sym - 257;
+                    if idx >= LENGTH_BASE.len() {
+                        return Err(CompressError::Corrupt);
+                    }
+                    let len = LENGTH_BASE[idx] as usize
+                        + self.input.bits(LENGTH_EXTRA[idx] as u32)? as usize;
+                    let dsym = dist.decode(&mut self.input)?;
+                    if dsym >= DIST_BASE.len() {
+                        return Err(CompressError::Corrupt);
+                    }
+                    let distance = DIST_BASE[dsym] as usize
+                        + self.input.bits(DIST_EXTRA[dsym] as u32)? as usize;
+                    self.window.copy_match(distance, len)?;
+                }
+                Ok(())
+            }
+            BlockState::Done => Ok(()),
+        }
+    }
+}
+
+impl Decompress for Inflate<'_> {
+    fn read(&mut self, out: &mut [u8]) -> CResult<usize> {
+        while self.window.pending() == 0 && !self.finished() {
+            self.step()?;
+        }
+        Ok(self.window.read(out))
+    }
+}
+
+// ============================================================================
+// zlib
+// ============================================================================
+
+/// Whether two bytes form a valid zlib header
+pub fn is_zlib_header(cmf: u8, flg: u8) -> bool {
+    cmf & 0x0F == 8 && cmf >> 4 <= 7 && ((cmf as u16) << 8 | flg as u16) % 31 == 0
+}
+
+/// zlib stream decoder
+pub struct ZlibDecoder<'a> {
+    data: &'a [u8],
+    inflate: Inflate<'a>,
+    adler: u32,
+    done: bool,
+}
+
+impl<'a> ZlibDecoder<'a> {
+    pub fn new(data: &'a [u8]) -> CResult<Self> {
+        if data.len() < 2 {
+            return Err(CompressError::Truncated);
+        }
+        if !is_zlib_header(data[0], data[1]) {
+            return Err(CompressError::BadFormat);
+        }
+        if data[1] & 0x20 != 0 {
+            // Preset dictionaries are not used by anything we read
+            return Err(CompressError::Unsupported);
+        }
+        Ok(Self {
+            data,
+            inflate: Inflate::new(&data[2..]),
+            adler: 1,
+            done: false,
+        })
+    }
+}
+
+impl Decompress for ZlibDecoder<'_> {
+    fn read(&mut self, out: &mut [u8]) -> CResult<usize> {
+        if self.done {
+            return Ok(0);
+        }
+        let n = self.inflate.read(out)?;
+        if n > 0 {
+            self.adler = adler32_update(self.adler, &out[..n]);
+            return Ok(n);
+        }
+
+        let trailer = 2 + self.inflate.consumed();
+        let stored = self.data.get(trailer..trailer + 4).ok_or(CompressError::Truncated)?;
+        if u32::from_be_bytes([stored[0], stored[1], stored[2], stored[3]]) != self.adler {
+            return Err(CompressError::Checksum);
+        }
+        self.done = true;
+        Ok(0)
+    }
+}
+
+// ============================================================================
+// gzip
+// ============================================================================
+
+const FHCRC: u8 = 0x02;
+const FEXTRA: u8 = 0x04;
+const FNAME: u8 = 0x08;
+const FCOMMENT: u8 = 0x10;
+
+/// Fields of a gzip member header
+#[derive(Debug, Clone)]
+pub struct GzipHeader {
+    pub name: Option<String>,
+    pub mtime: u32,
+    /// Header size in bytes
+    pub len: usize,
+}
+
+/// Parse the gzip member header at the start of `data`
+pub fn gzip_header(data: &[u8]) -> CResult<GzipHeader> {
+    if data.len() < 10 {
+        return Err(CompressError::Truncated);
+    }
+    if !data.starts_with(&GZIP_MAGIC) {
+        return Err(CompressError::BadFormat);
+    }
+    if data[2] != 8 {
+        return Err(CompressError::Unsupported);
+    }
+    let flags = data[3];
+    if flags & 0xE0 != 0 {
+        return Err(CompressError::Corrupt);
+    }
+    let mtime = u32::from_le_bytes([data[4], data[5], data[6], data[7]]);
+
+    let mut pos = 10;
+    if flags & FEXTRA != 0 {
+        let xlen = data.get(pos..pos + 2).ok_or(CompressError::Truncated)?;
+        pos += 2 + u16::from_le_bytes([xlen[0], xlen[1]]) as usize;
+    }
+    let mut name = None;
+    if flags & FNAME != 0 {
+        // Latin-1 on disk
+        name = Some(cstring(data, &mut pos)?.iter().map(|&b| b as char).collect());
+    }
+    if flags & FCOMMENT != 0 {
+        cstring(data, &mut pos)?;
+    }
+    if flags & FHCRC != 0 {
+        let stored = data.get(pos..pos + 2).ok_or(CompressError::Truncated)?;
+        if u16::from_le_bytes([stored[0], stored[1]]) != crc32(&data[..pos]) as u16 {
+            return Err(CompressError::Checksum);
+        }
+        pos += 2;
+    }
+    if pos > data.len() {
+        return Err(CompressError::Truncated);
+    }
+    Ok(GzipHeader { name, mtime, len: pos })
+}
+
+/// NUL-terminated string at `*pos`, advancing past it
+fn cstring<'a>(data: &'a [u8], pos: &mut usize) -> CResult<&'a [u8]> {
+    let rest = data.get(*pos..).ok_or(CompressError::Truncated)?;
+    let len = rest.iter().position(|&b| b == 0).ok_or(CompressError::Truncated)?;
+    *pos += len + 1;
+    Ok(&rest[..len])
+}
+
+/// gzip file decoder
+pub struct GzipDecoder<'a> {
+    data: &'a [u8],
+    /// Start of the current member's DEFLATE data
+    member: usize,
+    inflate: Option<Inflate<'a>>,
+    crc: u32,
+    size: u32,
+}
+
+impl<'a> GzipDecoder<'a> {
+    pub fn new(data: &'a [u8]) -> CResult<Self> {
+        let header = gzip_header(data)?;
+        Ok(Self {
+            data,
+            member: header.len,
+            inflate: Some(Inflate::new(&data[header.len..])),
+            crc: 0,
+            size: 0,
+        })
+    }
+}
+
+impl Decompress for GzipDecoder<'_> {
+    fn read(&mut self, out: &mut [u8]) -> CResult<usize> {
+        loop {
+            let Some(inflate) = self.inflate.as_mut() else {
+                return Ok(0);
+            };
+            let n = inflate.read(out)?;
+            if n > 0 {
+                self.crc = crc32_update(self.crc, &out[..n]);
+                self.size = self.size.wrapping_add(n as u32);
+                return Ok(n);
+            }
+
+            let trailer = self.member + inflate.consumed();
+            let t = self.data.get(trailer..trailer + 8).ok_or(CompressError::Truncated)?;
+            if u32::from_le_bytes([t[0], t[1], t[2], t[3]]) != self.crc
+                || u32::from_le_bytes([t[4], t[5], t[6], t[7]]) != self.size
+            {
+                return Err(CompressError::Checksum);
+            }
+
+            // Another member may follow; zero padding after the last is fine
+            let rest = &self.data[trailer + 8..];
+            if rest.starts_with(&GZIP_MAGIC) {
+                let header = gzip_header(rest)?;
+                self.member = trailer + 8 + header.len;
+                self.inflate = Some(Inflate::new(&self.data[self.member..]));
+                self.crc = 0;
+                self.size = 0;
+            } else if rest.iter().all(|&b| b == 0) {
+                self.inflate = None;
+            } else {
+                return Err(CompressError::Corrupt);
+            }
+        }
+    }
+}
+
+/// Decompress a raw DEFLATE stream
+pub fn inflate(data: &[u8], limit: usize) -> CResult<Vec<u8>> {
+    read_all(&mut Inflate::new(data), data.len() * 3, limit)
+}
+
+/// Decompress a zlib stream
+pub fn zlib_decompress(data: &[u8], limit: usize) -> CResult<Vec<u8>> {
+    read_all(&mut ZlibDecoder::new(data)?, data.len() * 3, limit)
+}
+
+/// Decompress a gzip file
+pub fn gzip_decompress(data: &[u8], limit: usize) -> CResult<Vec<u8>> {
+    read_all(&mut GzipDecoder::new(data)?, data.len() * 3, limit)
+}
+
+// ============================================================================
+// Encoder
+// ============================================================================
+
+/// Input bytes per block; also the most a stored block can hold
+const BLOCK_SIZE: usize = 0xFFFF;
+
+/// Least-significant-bit-first writer
+struct BitWriter {
+    out: Vec<u8>,
+    bits: u64,
+    count: u32,
+}
+
+impl BitWriter {
+    fn new() -> Self {
+        Self { out: Vec::new(), bits: 0, count: 0 }
+    }
+
+    fn put(&mut self, value: u32, n: u32) {
+        self.bits |= (value as u64) << self.count;
+        self.count += n;
+        while self.count >= 8 {
+            self.out.push(self.bits as u8);
+            self.bits >>= 8;
+            self.count -= 8;
+        }
+    }
+
+    fn align(&mut self) {
+        if self.count > 0 {
+            self.out.push(self.bits as u8);
+            self.bits = 0;
+            self.count = 0;
+        }
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        self.align();
+        self.out
+    }
+}
+
+/// Huffman code ready for writing: bit-reversed codes and their lengths
+struct Code {
+    codes: Vec<u16>,
+    lengths: Vec<u8>,
+}
+
+impl Code {
+    fn new(lengths: &[u8]) -> Self {
+        let codes = canonical_codes(lengths)
+            .iter()
+            .zip(lengths)
+            .map(|(&code, &len)| if len == 0 { 0 } else { code.reverse_bits() >> (16 - len) })
+            .collect();
+        Self { codes, lengths: lengths.to_vec() }
+    }
+
+    fn put(&self, out: &mut BitWriter, sym: usize) {
+        out.put(self.codes[sym] as u32, self.lengths[sym] as u32);
+    }
+
+    fn cost(&self, freqs: &[u32]) -> u64 {
+        freqs.iter().zip(&self.lengths).map(|(&f, &l)| f as u64 * l as u64).sum()
+    }
+}
+
+fn length_symbol(len: usize) -> usize {
+    LENGTH_BASE.iter().rposition(|&base| base as usize <= len).unwrap()
+}
+
+fn dist_symbol(dist: usize) -> usize {
+    DIST_BASE.iter().rposition(|&base| base as usize <= dist).unwrap()
+}
+
+/// Length-limited lengths that also form a complete code, as some
+/// decoders insist on for a single used symbol
+fn block_lengths(freqs: &[u32], max_bits: u32) -> Vec<u8> {
+    let mut freqs = freqs.to_vec();
+    let used = freqs.iter().filter(|&&f| f > 0).count();
+    if used < 2 {
+        for f in freqs.iter_mut().take(2) {
+            *f = (*f).max(1);
+        }
+    }
+    code_lengths(&freqs, max_bits)
+}
+
+/// Run-length code the concatenated code lengths with symbols 16-18
+fn rle_lengths(lengths: &[u8]) -> Vec<(u8, u8)> {
+    let mut out = Vec::new();
+    let mut i = 0;
+    while i < lengths.len() {
+        let value = lengths[i];
+        let mut run = lengths[i..].iter().take_while(|&&l| l == value).count();
+        i += run;
+        if value == 0 {
+            while run >= 11 {
+                let r = run.min(138);
+                out.push((18, (r - 11) as u8));
+                run -= r;
+            }
+            if run >= 3 {
+                out.push((17, (run - 3) as u8));
+                run = 0;
+            }
+        } else {
+            out.push((value, 0));
+            run -= 1;
+            while run >= 3 {
+                let r = run.min(6);
+                out.push((16, (r - 3) as u8));
+                run -= r;
+            }
+        }
+        for _ in 0..run {
+            out.push((value, 0));
+        }
+    }
+    out
+}
+
+fn clen_extra_bits(sym: u8) -> u32 {
+    match sym {
+        16 => 2,
+        17 => 3,
+        18 => 7,
+        _ => 0,
+    }
+}
+
+enum Symbol {
+    Literal(u8),
+    Match { len: usize, dist: usize },
+}
+
+fn write_symbols(out: &mut BitWriter, symbols: &[Symbol], lit: &Code, dist: &Code) {
+    for symbol in symbols {
+        match *symbol {
+            Symbol::Literal(byte) => lit.put(out, byte as usize),
+            Symbol::Match { len, dist: distance } => {
+                let ls = length_symbol(len);
+                lit.put(out, 257 + ls);
+                out.put((len - LENGTH_BASE[ls] as usize) as u32, LENGTH_EXTRA[ls] as u32);
+                let ds = dist_symbol(distance);
+                dist.put(out, ds);
+                out.put((distance - DIST_BASE[ds] as usize) as u32, DIST_EXTRA[ds] as u32);
+            }
+        }
+    }
+    lit.put(out, END_OF_BLOCK);
+}
+
+fn write_stored(out: &mut BitWriter, data: &[u8], last: bool) {
+    out.put(last as u32, 1);
+    out.put(0, 2);
+    out.align();
+    out.put(data.len() as u32, 16);
+    out.put(!data.len() as u32 & 0xFFFF, 16);
+    out.out.extend_from_slice(data);
+}
+
+fn write_block(out: &mut BitWriter, data: &[u8], symbols: &[Symbol], last: bool) {
+    let mut lit_freq = [0u32; 286];
+    let mut dist_freq = [0u32; 30];
+    let mut extra_bits = 0u64;
+    for symbol in symbols {
+        match *symbol {
+            Symbol::Literal(byte) => lit_freq[byte as usize] += 1,
+            Symbol::Match { len, dist } => {
+                let ls = length_symbol(len);
+                let ds = dist_symbol(dist);
+                lit_freq[257 + ls] += 1;
+                dist_freq[ds] += 1;
+                extra_bits += LENGTH_EXTRA[ls] as u64 + DIST_EXTRA[ds] as u64;
+            }
+        }
+    }
+    lit_freq[END_OF_BLOCK] = 1;
+
+    let lit = Code::new(&block_lengths(&lit_freq, MAX_BITS as u32));
+    let dist = Code::new(&block_lengths(&dist_freq, MAX_BITS as u32));
+    let hlit = lit.lengths.iter().rposition(|&l| l != 0).unwrap_or(0).max(256) + 1;
+    let hdist = dist.lengths.iter().rposition(|&l| l != 0).unwrap_or(0) + 1;
+
+    let mut all = lit.lengths[..hlit].to_vec();
+    all.extend_from_slice(&dist.lengths[..hdist]);
+    let rle = rle_lengths(&all);
+    let mut clen_freq = [0u32; 19];
+    for &(sym, _) in &rle {
+        clen_freq[sym as usize] += 1;
+    }
+    let clen = Code::new(&block_lengths(&clen_freq, 7));
+    let hclen = CLEN_ORDER.iter().rposition(|&s| clen.lengths[s] != 0).unwrap_or(0).max(3) + 1;
+
+    let header_bits = 14
+        + 3 * hclen as u64
+        + rle.iter().map(|&(s, _)| clen.lengths[s as usize] as u64 + clen_extra_bits(s) as u64).sum::<u64>();
+    let dynamic_bits = 3 + header_bits + lit.cost(&lit_freq) + dist.cost(&dist_freq) + extra_bits;
+
+    let fixed_lit = Code::new(&fixed_lit_lengths());
+    let fixed_dist = Code::new(&[5u8; 30]);
+    let fixed_bits = 3 + fixed_lit.cost(&lit_freq[..]) + fixed_dist.cost(&dist_freq) + extra_bits;
+
+    let stored_bits = 3 + 7 + 32 + 8 * data.len() as u64;
+
+    if stored_bits <= dynamic_bits.min(fixed_bits) {
+        write_stored(out, data, last);
+    } else if fixed_bits <= dynamic_bits {
+        out.put(last as u32, 1);
+        out.put(1, 2);
+        write_symbols(out, symbols, &fixed_lit, &fixed_dist);
+    } else {
+        out.put(last as u32, 1);
+        out.put(2, 2);
+        out.put((hlit - 257) as u32, 5);
+        out.put((hdist - 1) as u32, 5);
+        out.put((hclen - 4) as u32, 4);
+        for &sym in &CLEN_ORDER[..hclen] {
+            out.put(clen.lengths[sym] as u32, 3);
+        }
+        for &(sym, extra) in &rle {
+            clen.put(out, sym as usize);
+            out.put(extra as u32, clen_extra_bits(sym));
+        }
+        write_symbols(out, symbols, &lit, &dist);
+    }
+}
+
+fn level_params(level: u8) -> Params {
+    let (max_chain, nice) = match level {
+        0..=1 => (4, 16),
+        2..=3 => (16, 32),
+        4..=6 => (128, 128),
+        7..=8 => (1024, 258),
+        _ => (4096, 258),
+    };
+    Params {
+        window: WINDOW_SIZE,
+        min_match: 3,
+        max_match: 258,
+        max_chain,
+        nice,
+        last_literals: 0,
+        match_guard: 0,
+    }
+}
+
+/// Compress `data` into a raw DEFLATE stream. Level 0 stores the data,
+/// 1-9 trade speed for ratio as in zlib.
+pub fn deflate(data: &[u8], level: u8) -> Vec<u8> {
+    let mut out = BitWriter::new();
+    if data.is_empty() {
+        // A single empty fixed block
+        out.put(1, 1);
+        out.put(1, 2);
+        out.put(0, 7);
+        return out.finish();
+    }
+
+    let mut finder = MatchFinder::new(data, level_params(level));
+    let mut start = 0;
+    while start < data.len() {
+        let end = (start + BLOCK_SIZE).min(data.len());
+        let last = end == data.len();
+        let block = &data[start..end];
+        if level == 0 {
+            write_stored(&mut out, block, last);
+        } else {
+            let (seqs, tail) = finder.parse(start, end);
+            let mut symbols = Vec::with_capacity(block.len() / 2);
+            let mut pos = start;
+            for seq in &seqs {
+                symbols.extend(data[pos..pos + seq.lit_len].iter().map(|&b| Symbol::Literal(b)));
+                symbols.push(Symbol::Match { len: seq.match_len, dist: seq.offset });
+                pos += seq.lit_len + seq.match_len;
+            }
+            symbols.extend(data[pos..pos + tail].iter().map(|&b| Symbol::Literal(b)));
+            write_block(&mut out, block, &symbols, last);
+        }
+        start = end;
+    }
+    out.finish()
+}
+
+/// Compress `data` into a zlib stream
+pub fn zlib_compress(data: &[u8], level: u8) -> Vec<u8> {
+    let flevel = match level {
+        0..=1 => 0,
+        2..=5 => 1,
+        6 => 2,
+        _ => 3,
+    };
+    let cmf = 0x78u8;
+    let mut flg = flevel << 6;
+    flg += (31 - ((cmf as u16) << 8 | flg as u16) % 31) as u8 % 31;
+    let mut out = vec![cmf, flg];
+    out.extend_from_slice(&deflate(data, level));
+    out.extend_from_slice(&adler32(data).to_be_bytes());
+    out
+}
+
+/// Compress `data` into a single-member gzip file
+pub fn gzip_compress(data: &[u8], level: u8) -> Vec<u8> {
+    let xfl = match level {
+        9 => 2,
+        0..=1 => 4,
+        _ => 0,
+    };
+    // No name or mtime; OS 3 is Unix
+    let mut out = vec![0x1F, 0x8B, 8, 0, 0, 0, 0, 0, xfl, 3];
+    out.extend_from_slice(&deflate(data, level));
+    out.extend_from_slice(&crc32(data).to_le_bytes());
+    out.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    out
+}
@@ -0,0 +1,112 @@
+//! Length-limited Huffman code construction for the encoders
+
+use alloc::vec;
+use alloc::vec::Vec;
+
+/// Code lengths for `freqs`, none longer than `max_bits`. Unused symbols
+/// get length 0. The code is complete whenever two or more symbols are
+/// used; a single used symbol gets length 1.
+pub fn code_lengths(freqs: &[u32], max_bits: u32) -> Vec<u8> {
+    let mut lengths = vec![0u8; freqs.len()];
+    let mut leaves: Vec<(u32, usize)> = freqs
+        .iter()
+        .enumerate()
+        .filter(|(_, &f)| f > 0)
+        .map(|(sym, &f)| (f, sym))
+        .collect();
+    match leaves.len() {
+        0 => return lengths,
+        1 => {
+            lengths[leaves[0].1] = 1;
+            return lengths;
+        }
+        _ => {}
+    }
+    leaves.sort_unstable();
+
+    // Two-queue construction: leaves in order, internal nodes appended
+    // behind them as they are created (their weights never decrease)
+    let n = leaves.len();
+    let mut weight: Vec<u64> = leaves.iter().map(|&(f, _)| f as u64).collect();
+    weight.resize(2 * n - 1, 0);
+    let mut parent = vec![0usize; 2 * n - 1];
+    let (mut next_leaf, mut next_node) = (0, n);
+    for new in n..2 * n - 1 {
+        let mut pick = || {
+            if next_leaf < n && (next_node >= new || weight[next_leaf] <= weight[next_node]) {
+                next_leaf += 1;
+                next_leaf - 1
+            } else {
+                next_node += 1;
+                next_node - 1
+            }
+        };
+        let (a, b) = (pick(), pick());
+        weight[new] = weight[a] + weight[b];
+        parent[a] = new;
+        parent[b] = new;
+    }
+    let mut depth = vec![0u32; 2 * n - 1];
+    for node in (0..2 * n - 2).rev() {
+        depth[node] = depth[parent[node]] + 1;
+    }
+
+    // Count codes per length, folding overlong ones into max_bits and then
+    // rebalancing until the Kraft sum is exactly one again
+    let max = max_bits as usize;
+    let mut count = vec![0u32; max + 1];
+    for &d in &depth[..n] {
+        count[(d as usize).min(max)] += 1;
+    }
+    let mut total: u64 = (1..=max).map(|len| (count[len] as u64) << (max - len)).sum();
+    while total > 1 << max {
+        count[max] -= 1;
+        for len in (1..max).rev() {
+            if count[len] != 0 {
+                count[len] -= 1;
+                count[len + 1] += 2;
+                break;
+            }
+        }
+        total -= 1;
+    }
+
+    // Most frequent symbols get the shortest codes
+    let mut len = 1;
+    for &(_, sym) in leaves.iter().rev() {
+        while count[len] == 0 {
+            len += 1;
+        }
+        lengths[sym] = len as u8;
+        count[len] -= 1;
+    }
+    lengths
+}
+
+/// Canonical codes (most significant bit first) for `lengths`
+pub fn canonical_codes(lengths: &[u8]) -> Vec<u16> {
+    let max = lengths.iter().copied().max().unwrap_or(0) as usize;
+    let mut bl_count = vec![0u16; max + 1];
+    for &len in lengths {
+        if len > 0 {
+            bl_count[len as usize] += 1;
+        }
+    }
+    let mut next = vec![0u16; max + 2];
+    let mut code = 0u16;
+    for len in 1..=max {
+        code = (code + bl_count[len - 1]) << 1;
+        next[len] = code;
+    }
+    lengths
+        .iter()
+        .map(|&len| {
+            if len == 0 {
+                return 0;
+            }
+            let code = next[len as usize];
+            next[len as usize] += 1;
+            code
+        })
+        .collect()
+}
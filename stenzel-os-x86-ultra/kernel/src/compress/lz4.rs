@@ -0,0 +1,343 @@
+//! LZ4 block and frame formats
+//!
+//! Blocks are the bare sequence format zswap and zram store pages in;
+//! frames (magic 0x184D2204) add the size, block and checksum framing used
+//! for files.
+
+use alloc::vec::Vec;
+
+use super::checksum::{xxh32, Xxh32};
+use super::lz77::{MatchFinder, Params};
+use super::{read_all, CResult, CompressError, Decompress, Window};
+
+pub const MAGIC: u32 = 0x184D_2204;
+
+const MIN_MATCH: usize = 4;
+const MAX_OFFSET: usize = 0xFFFF;
+/// Frame block size used by the encoder (the 64 KiB block size ID)
+const FRAME_BLOCK: usize = 64 * 1024;
+
+const FLG_VERSION: u8 = 0x40;
+const FLG_INDEPENDENT: u8 = 0x20;
+const FLG_BLOCK_CHECKSUM: u8 = 0x10;
+const FLG_CONTENT_SIZE: u8 = 0x08;
+const FLG_CONTENT_CHECKSUM: u8 = 0x04;
+const FLG_DICT_ID: u8 = 0x01;
+/// Set in a block size when the block is stored uncompressed
+const BLOCK_UNCOMPRESSED: u32 = 0x8000_0000;
+
+pub fn is_skippable(magic: u32) -> bool {
+    magic & 0xFFFF_FFF0 == 0x184D_2A50
+}
+
+fn le32(bytes: &[u8]) -> u32 {
+    u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+/// Length continuation bytes following a nibble of 15
+fn read_length(src: &[u8], pos: &mut usize) -> CResult<usize> {
+    let mut len = 0usize;
+    loop {
+        let byte = *src.get(*pos).ok_or(CompressError::Truncated)?;
+        *pos += 1;
+        len = len.checked_add(byte as usize).ok_or(CompressError::Corrupt)?;
+        if byte != 255 {
+            return Ok(len);
+        }
+    }
+}
+
+/// Decode one block into `window`. Matches may reach `history` bytes
+/// behind the block; the block may produce at most `limit` bytes.
+fn decode_block(src: &[u8], window: &mut Window, history: usize, limit: usize) -> CResult<()> {
+    let start = window.total();
+    let mut pos = 0;
+    loop {
+        let token = *src.get(pos).ok_or(CompressError::Truncated)?;
+        pos += 1;
+
+        let mut lit_len = (token >> 4) as usize;
+        if lit_len == 15 {
+            lit_len += read_length(src, &mut pos)?;
+        }
+        let literals = src.get(pos..pos + lit_len).ok_or(CompressError::Truncated)?;
+        pos += lit_len;
+        let produced = (window.total() - start) as usize;
+        if produced + lit_len > limit {
+            return Err(CompressError::TooLarge);
+        }
+        window.extend(literals);
+        // The last sequence is literals only
+        if pos == src.len() {
+            return Ok(());
+        }
+
+        let offset = u16::from_le_bytes(src.get(pos..pos + 2).ok_or(CompressError::Truncated)?.try_into().unwrap());
+        pos += 2;
+        let mut match_len = (token & 0xF) as usize + MIN_MATCH;
+        if match_len == 15 + MIN_MATCH {
+            match_len += read_length(src, &mut pos)?;
+        }
+        let produced = (window.total() - start) as usize;
+        if offset == 0 || offset as usize > produced + history {
+            return Err(CompressError::Corrupt);
+        }
+        if produced + match_len > limit {
+            return Err(CompressError::TooLarge);
+        }
+        window.copy_match(offset as usize, match_len)?;
+    }
+}
+
+/// Decompress a bare block that expands to at most `max_len` bytes
+pub fn decompress_block(src: &[u8], max_len: usize) -> CResult<Vec<u8>> {
+    let mut window = Window::new(MAX_OFFSET);
+    decode_block(src, &mut window, 0, max_len)?;
+    let mut out = alloc::vec![0u8; window.pending()];
+    window.read(&mut out);
+    Ok(out)
+}
+
+fn finder_params() -> Params {
+    Params {
+        window: MAX_OFFSET,
+        min_match: MIN_MATCH,
+        max_match: usize::MAX,
+        max_chain: 32,
+        nice: 64,
+        // End of block restrictions of the format
+        last_literals: 5,
+        match_guard: 12,
+    }
+}
+
+fn write_length(out: &mut Vec<u8>, mut len: usize) {
+    while len >= 255 {
+        out.push(255);
+        len -= 255;
+    }
+    out.push(len as u8);
+}
+
+/// Encode `data[start..end]` as one block
+fn encode_block(out: &mut Vec<u8>, finder: &mut MatchFinder, data: &[u8], start: usize, end: usize) {
+    let (seqs, tail) = finder.parse(start, end);
+    let mut pos = start;
+    for seq in &seqs {
+        let match_extra = seq.match_len - MIN_MATCH;
+        out.push((seq.lit_len.min(15) as u8) << 4 | match_extra.min(15) as u8);
+        if seq.lit_len >= 15 {
+            write_length(out, seq.lit_len - 15);
+        }
+        out.extend_from_slice(&data[pos..pos + seq.lit_len]);
+        out.extend_from_slice(&(seq.offset as u16).to_le_bytes());
+        if match_extra >= 15 {
+            write_length(out, match_extra - 15);
+        }
+        pos += seq.lit_len + seq.match_len;
+    }
+    out.push((tail.min(15) as u8) << 4);
+    if tail >= 15 {
+        write_length(out, tail - 15);
+    }
+    out.extend_from_slice(&data[pos..end]);
+}
+
+/// Compress `data` as a single bare block
+pub fn compress_block(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len() / 2 + 16);
+    let mut finder = MatchFinder::new(data, finder_params());
+    encode_block(&mut out, &mut finder, data, 0, data.len());
+    out
+}
+
+/// Compress `data` as a frame with linked 64 KiB blocks, the content size
+/// and a content checksum
+pub fn compress(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len() / 2 + 32);
+    out.extend_from_slice(&MAGIC.to_le_bytes());
+    let descriptor_start = out.len();
+    out.push(FLG_VERSION | FLG_CONTENT_SIZE | FLG_CONTENT_CHECKSUM);
+    out.push(4 << 4);
+    out.extend_from_slice(&(data.len() as u64).to_le_bytes());
+    out.push((xxh32(&out[descriptor_start..], 0) >> 8) as u8);
+
+    let mut finder = MatchFinder::new(data, finder_params());
+    let mut block = Vec::new();
+    let mut start = 0;
+    while start < data.len() {
+        let end = (start + FRAME_BLOCK).min(data.len());
+        block.clear();
+        encode_block(&mut block, &mut finder, data, start, end);
+        if block.len() < end - start {
+            out.extend_from_slice(&(block.len() as u32).to_le_bytes());
+            out.extend_from_slice(&block);
+        } else {
+            out.extend_from_slice(&((end - start) as u32 | BLOCK_UNCOMPRESSED).to_le_bytes());
+            out.extend_from_slice(&data[start..end]);
+        }
+        start = end;
+    }
+    out.extend_from_slice(&0u32.to_le_bytes());
+    out.extend_from_slice(&xxh32(data, 0).to_le_bytes());
+    out
+}
+
+struct Frame {
+    flags: u8,
+    block_max: usize,
+    content_size: Option<u64>,
+    checksum: Option<Xxh32>,
+}
+
+/// LZ4 frame decoder
+pub struct FrameDecoder<'a> {
+    data: &'a [u8],
+    pos: usize,
+    window: Window,
+    frame: Option<Frame>,
+}
+
+impl<'a> FrameDecoder<'a> {
+    pub fn new(data: &'a [u8]) -> CResult<Self> {
+        let magic = le32(data.get(..4).ok_or(CompressError::Truncated)?);
+        if magic != MAGIC && !is_skippable(magic) {
+            return Err(CompressError::BadFormat);
+        }
+        Ok(Self {
+            data,
+            pos: 0,
+            window: Window::new(MAX_OFFSET),
+            frame: None,
+        })
+    }
+
+    fn take(&mut self, len: usize) -> CResult<&'a [u8]> {
+        let data = self.data;
+        let bytes = data.get(self.pos..self.pos + len).ok_or(CompressError::Truncated)?;
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    /// Skip skippable frames and parse the next frame descriptor. Returns
+    /// false at the end of the input.
+    fn start_frame(&mut self) -> CResult<bool> {
+        loop {
+            if self.pos == self.data.len() {
+                return Ok(false);
+            }
+            let magic = le32(self.take(4)?);
+            if is_skippable(magic) {
+                let size = le32(self.take(4)?);
+                self.take(size as usize)?;
+                continue;
+            }
+            if magic != MAGIC {
+                return Err(CompressError::BadFormat);
+            }
+
+            let descriptor_start = self.pos;
+            let header = self.take(2)?;
+            let (flags, bd) = (header[0], header[1]);
+            if flags & 0xC0 != FLG_VERSION || flags & 0x02 != 0 || bd & 0x8F != 0 {
+                return Err(CompressError::BadFormat);
+            }
+            if flags & FLG_DICT_ID != 0 {
+                return Err(CompressError::Unsupported);
+            }
+            let block_max = match bd >> 4 {
+                4 => 64 * 1024,
+                5 => 256 * 1024,
+                6 => 1024 * 1024,
+                7 => 4 * 1024 * 1024,
+                _ => return Err(CompressError::BadFormat),
+            };
+            let content_size = if flags & FLG_CONTENT_SIZE != 0 {
+                Some(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+            } else {
+                None
+            };
+            let descriptor = &self.data[descriptor_start..self.pos];
+            if self.take(1)?[0] != (xxh32(descriptor, 0) >> 8) as u8 {
+                return Err(CompressError::Checksum);
+            }
+
+            self.window = Window::new(MAX_OFFSET);
+            self.frame = Some(Frame {
+                flags,
+                block_max,
+                content_size,
+                checksum: (flags & FLG_CONTENT_CHECKSUM != 0).then(|| Xxh32::new(0)),
+            });
+            return Ok(true);
+        }
+    }
+
+    fn decode_block(&mut self) -> CResult<()> {
+        let size = le32(self.take(4)?);
+        let frame = self.frame.as_mut().unwrap();
+        if size == 0 {
+            if frame.content_size.is_some_and(|size| size != self.window.total()) {
+                return Err(CompressError::Corrupt);
+            }
+            if let Some(hasher) = self.frame.take().unwrap().checksum {
+                if le32(self.take(4)?) != hasher.finish() {
+                    return Err(CompressError::Checksum);
+                }
+            }
+            return Ok(());
+        }
+
+        let len = (size & !BLOCK_UNCOMPRESSED) as usize;
+        if len > frame.block_max {
+            return Err(CompressError::Corrupt);
+        }
+        let data = self.data;
+        let block = data.get(self.pos..self.pos + len).ok_or(CompressError::Truncated)?;
+        self.pos += len;
+        if frame.flags & FLG_BLOCK_CHECKSUM != 0 {
+            let stored = data.get(self.pos..self.pos + 4).ok_or(CompressError::Truncated)?;
+            self.pos += 4;
+            if le32(stored) != xxh32(block, 0) {
+                return Err(CompressError::Checksum);
+            }
+        }
+
+        let before = self.window.total();
+        if size & BLOCK_UNCOMPRESSED != 0 {
+            self.window.extend(block);
+        } else {
+            // Linked blocks may refer back into the previous 64 KiB
+            let history = if frame.flags & FLG_INDEPENDENT != 0 {
+                0
+            } else {
+                before.min(MAX_OFFSET as u64) as usize
+            };
+            decode_block(block, &mut self.window, history, frame.block_max)?;
+        }
+        let produced = (self.window.total() - before) as usize;
+        if let Some(hasher) = frame.checksum.as_mut() {
+            hasher.update(self.window.recent(produced));
+        }
+        Ok(())
+    }
+}
+
+impl Decompress for FrameDecoder<'_> {
+    fn read(&mut self, out: &mut [u8]) -> CResult<usize> {
+        loop {
+            if self.window.pending() > 0 {
+                return Ok(self.window.read(out));
+            }
+            if self.frame.is_none() && !self.start_frame()? {
+                return Ok(0);
+            }
+            self.decode_block()?;
+        }
+    }
+}
+
+/// Decompress a file of LZ4 frames
+pub fn decompress(data: &[u8], limit: usize) -> CResult<Vec<u8>> {
+    read_all(&mut FrameDecoder::new(data)?, data.len() * 3, limit)
+}
@@ -0,0 +1,165 @@
+//! LZ77 match finder shared by the encoders
+//!
+//! Hash chains over the whole input with one step of lazy matching. The
+//! encoders call `parse` block by block; matches may reach back into
+//! earlier blocks as far as the window allows.
+
+use alloc::vec;
+use alloc::vec::Vec;
+
+const HASH_BITS: u32 = 15;
+
+/// Literal run followed by a match
+#[derive(Debug, Clone, Copy)]
+pub struct Sequence {
+    pub lit_len: usize,
+    pub offset: usize,
+    pub match_len: usize,
+}
+
+#[derive(Clone, Copy)]
+pub struct Params {
+    /// Largest match distance, at most `1 << 24`
+    pub window: usize,
+    pub min_match: usize,
+    pub max_match: usize,
+    /// Candidates visited per position
+    pub max_chain: usize,
+    /// Stop searching once a match this long is found
+    pub nice: usize,
+    /// Bytes at the end of a block that must be literals (LZ4)
+    pub last_literals: usize,
+    /// No match may start in this many bytes before the end of a block (LZ4)
+    pub match_guard: usize,
+}
+
+pub struct MatchFinder<'a> {
+    data: &'a [u8],
+    params: Params,
+    head: Vec<u32>,
+    prev: Vec<u32>,
+    prev_mask: usize,
+    /// Next position to insert into the chains
+    inserted: usize,
+}
+
+impl<'a> MatchFinder<'a> {
+    pub fn new(data: &'a [u8], params: Params) -> Self {
+        let prev_len = params.window.next_power_of_two().min(data.len().next_power_of_two()).max(1);
+        Self {
+            data,
+            params,
+            head: vec![0; 1 << HASH_BITS],
+            prev: vec![0; prev_len],
+            prev_mask: prev_len - 1,
+            inserted: 0,
+        }
+    }
+
+    fn hash(&self, pos: usize) -> usize {
+        let d = self.data;
+        let value = if self.params.min_match >= 4 {
+            u32::from_le_bytes([d[pos], d[pos + 1], d[pos + 2], d[pos + 3]])
+        } else {
+            u32::from_le_bytes([d[pos], d[pos + 1], d[pos + 2], 0])
+        };
+        (value.wrapping_mul(0x9E37_79B1) >> (32 - HASH_BITS)) as usize
+    }
+
+    fn hash_len(&self) -> usize {
+        self.params.min_match.clamp(3, 4)
+    }
+
+    /// Insert every position before `upto` into the chains
+    fn insert_until(&mut self, upto: usize) {
+        let last = self.data.len().saturating_sub(self.hash_len() - 1);
+        let upto = upto.min(last);
+        while self.inserted < upto {
+            let pos = self.inserted;
+            let h = self.hash(pos);
+            self.prev[pos & self.prev_mask] = self.head[h];
+            self.head[h] = pos as u32 + 1;
+            self.inserted += 1;
+        }
+    }
+
+    /// Longest match for `pos` whose end does not pass `limit`
+    fn longest_match(&mut self, pos: usize, limit: usize) -> Option<(usize, usize)> {
+        let p = self.params;
+        let max_len = p.max_match.min(limit - pos);
+        if max_len < p.min_match || pos + self.hash_len() > self.data.len() {
+            return None;
+        }
+        self.insert_until(pos);
+
+        let data = self.data;
+        let mut best: Option<(usize, usize)> = None;
+        let mut candidate = self.head[self.hash(pos)] as usize;
+        let mut chain = p.max_chain;
+        let window = p.window.min(self.prev_mask + 1);
+        while candidate != 0 && chain > 0 {
+            let cand = candidate - 1;
+            let dist = pos - cand;
+            if dist > window {
+                break;
+            }
+            let best_len = best.map_or(0, |(len, _)| len);
+            // Cheap reject: the byte that would extend the best match
+            if data[cand + best_len.min(max_len - 1)] == data[pos + best_len.min(max_len - 1)] {
+                let mut len = 0;
+                while len < max_len && data[cand + len] == data[pos + len] {
+                    len += 1;
+                }
+                if len >= p.min_match && len > best_len {
+                    best = Some((len, dist));
+                    if len >= p.nice || len == max_len {
+                        break;
+                    }
+                }
+            }
+            let next = self.prev[cand & self.prev_mask] as usize;
+            // Chains only ever point backwards; anything else is a stale slot
+            if next == 0 || next - 1 >= cand {
+                break;
+            }
+            candidate = next;
+            chain -= 1;
+        }
+        best
+    }
+
+    /// Parse `data[start..end]` into sequences. Returns them together with
+    /// the number of literals left after the last match.
+    pub fn parse(&mut self, start: usize, end: usize) -> (Vec<Sequence>, usize) {
+        let limit = end.saturating_sub(self.params.last_literals).max(start);
+        let last_start = end.saturating_sub(self.params.match_guard);
+        let mut seqs = Vec::new();
+        let mut anchor = start;
+        let mut pos = start;
+        while pos < last_start {
+            let Some((mut len, mut dist)) = self.longest_match(pos, limit) else {
+                pos += 1;
+                continue;
+            };
+            // Lazy step: prefer a longer match starting one byte later
+            if len < self.params.nice && pos + 1 < last_start {
+                if let Some((next_len, next_dist)) = self.longest_match(pos + 1, limit) {
+                    if next_len > len {
+                        pos += 1;
+                        len = next_len;
+                        dist = next_dist;
+                    }
+                }
+            }
+            seqs.push(Sequence {
+                lit_len: pos - anchor,
+                offset: dist,
+                match_len: len,
+            });
+            pos += len;
+            anchor = pos;
+        }
+        self.insert_until(end);
+        (seqs, end - anchor)
+    }
+}
@@ -0,0 +1,501 @@
+//! LZMA2 decoding (the xz filter)
+//!
+//! LZMA2 wraps LZMA in chunks of at most 2 MiB output. Each chunk is
+//! either stored or LZMA coded with its own range coder, and its control
+//! byte says whether the dictionary, the coder state or the literal
+//! parameters are reset first.
+
+use alloc::boxed::Box;
+use alloc::vec;
+use alloc::vec::Vec;
+
+use super::{CResult, CompressError, Window};
+
+const STATES: usize = 12;
+const POS_STATES_MAX: usize = 1 << 4;
+const LITERAL_CODER_SIZE: usize = 0x300;
+const LITERAL_CODERS_MAX: usize = 1 << 4;
+const DIST_STATES: usize = 4;
+const DIST_SLOT_BITS: u32 = 6;
+const DIST_MODEL_START: u32 = 4;
+const DIST_MODEL_END: u32 = 14;
+const FULL_DISTANCES: usize = 1 << (DIST_MODEL_END / 2);
+const ALIGN_BITS: u32 = 4;
+const MATCH_LEN_MIN: usize = 2;
+const PROB_BITS: u32 = 11;
+const PROB_INIT: u16 = 1 << (PROB_BITS - 1);
+const MOVE_BITS: u32 = 5;
+
+/// States below this one were entered through a literal
+const LIT_STATES: usize = 7;
+
+/// Dictionary size encoded in the LZMA2 filter properties byte
+pub fn dict_size(props: u8) -> CResult<u64> {
+    match props & 0x3F {
+        bits @ 0..=39 => Ok((2 | (bits as u64 & 1)) << (bits / 2 + 11)),
+        40 => Ok(u32::MAX as u64),
+        _ => Err(CompressError::Corrupt),
+    }
+}
+
+struct RangeDecoder<'a> {
+    data: &'a [u8],
+    pos: usize,
+    range: u32,
+    code: u32,
+    /// Set when the coder asked for bytes past the end of its chunk
+    overrun: bool,
+}
+
+impl<'a> RangeDecoder<'a> {
+    fn new(data: &'a [u8]) -> CResult<Self> {
+        if data.len() < 5 || data[0] != 0 {
+            return Err(CompressError::Corrupt);
+        }
+        Ok(Self {
+            data,
+            pos: 5,
+            range: u32::MAX,
+            code: u32::from_be_bytes([data[1], data[2], data[3], data[4]]),
+            overrun: false,
+        })
+    }
+
+    fn normalize(&mut self) {
+        if self.range < 1 << 24 {
+            let byte = match self.data.get(self.pos) {
+                Some(&byte) => byte,
+                None => {
+                    self.overrun = true;
+                    0
+                }
+            };
+            self.pos += 1;
+            self.range <<= 8;
+            self.code = self.code << 8 | byte as u32;
+        }
+    }
+
+    fn bit(&mut self, prob: &mut u16) -> u32 {
+        self.normalize();
+        let bound = (self.range >> PROB_BITS) * *prob as u32;
+        if self.code < bound {
+            self.range = bound;
+            *prob += ((1 << PROB_BITS) - *prob) >> MOVE_BITS;
+            0
+        } else {
+            self.range -= bound;
+            self.code -= bound;
+            *prob -= *prob >> MOVE_BITS;
+            1
+        }
+    }
+
+    fn bittree(&mut self, probs: &mut [u16], bits: u32) -> u32 {
+        let mut sym = 1;
+        for _ in 0..bits {
+            sym = sym << 1 | self.bit(&mut probs[sym as usize]);
+        }
+        sym - (1 << bits)
+    }
+
+    /// Least significant bit first tree; unlike `bittree` the root is
+    /// `probs[0]`
+    fn bittree_reverse(&mut self, probs: &mut [u16], bits: u32) -> u32 {
+        let mut sym = 1;
+        let mut value = 0;
+        for i in 0..bits {
+            let bit = self.bit(&mut probs[sym as usize - 1]);
+            sym = sym << 1 | bit;
+            value |= bit << i;
+        }
+        value
+    }
+
+    /// Bits with fixed probability one half
+    fn direct(&mut self, bits: u32) -> u32 {
+        let mut value = 0;
+        for _ in 0..bits {
+            self.normalize();
+            self.range >>= 1;
+            self.code = self.code.wrapping_sub(self.range);
+            let mask = 0u32.wrapping_sub(self.code >> 31);
+            self.code = self.code.wrapping_add(self.range & mask);
+            value = value << 1 | mask.wrapping_add(1);
+        }
+        value
+    }
+
+    /// Whether the chunk was consumed exactly and the coder ended cleanly
+    fn finished(&mut self) -> bool {
+        self.normalize();
+        !self.overrun && self.pos == self.data.len() && self.code == 0
+    }
+}
+
+struct LengthDecoder {
+    choice: u16,
+    choice2: u16,
+    low: [[u16; 8]; POS_STATES_MAX],
+    mid: [[u16; 8]; POS_STATES_MAX],
+    high: [u16; 256],
+}
+
+impl LengthDecoder {
+    fn new() -> Self {
+        Self {
+            choice: PROB_INIT,
+            choice2: PROB_INIT,
+            low: [[PROB_INIT; 8]; POS_STATES_MAX],
+            mid: [[PROB_INIT; 8]; POS_STATES_MAX],
+            high: [PROB_INIT; 256],
+        }
+    }
+
+    fn decode(&mut self, rc: &mut RangeDecoder, pos_state: usize) -> usize {
+        let len = if rc.bit(&mut self.choice) == 0 {
+            rc.bittree(&mut self.low[pos_state], 3)
+        } else if rc.bit(&mut self.choice2) == 0 {
+            8 + rc.bittree(&mut self.mid[pos_state], 3)
+        } else {
+            16 + rc.bittree(&mut self.high, 8)
+        };
+        MATCH_LEN_MIN + len as usize
+    }
+}
+
+/// LZMA coder state: the probability model plus the repeat distances
+struct Lzma {
+    lc: u32,
+    lp: u32,
+    pb: u32,
+    state: usize,
+    reps: [u32; 4],
+    is_match: [[u16; POS_STATES_MAX]; STATES],
+    is_rep: [u16; STATES],
+    is_rep0: [u16; STATES],
+    is_rep1: [u16; STATES],
+    is_rep2: [u16; STATES],
+    is_rep0_long: [[u16; POS_STATES_MAX]; STATES],
+    dist_slot: [[u16; 1 << DIST_SLOT_BITS]; DIST_STATES],
+    dist_special: [u16; FULL_DISTANCES - DIST_MODEL_END as usize],
+    dist_align: [u16; 1 << ALIGN_BITS],
+    match_len: LengthDecoder,
+    rep_len: LengthDecoder,
+    literal: Vec<[u16; LITERAL_CODER_SIZE]>,
+}
+
+impl Lzma {
+    fn new() -> Self {
+        Self {
+            lc: 0,
+            lp: 0,
+            pb: 0,
+            state: 0,
+            reps: [0; 4],
+            is_match: [[PROB_INIT; POS_STATES_MAX]; STATES],
+            is_rep: [PROB_INIT; STATES],
+            is_rep0: [PROB_INIT; STATES],
+            is_rep1: [PROB_INIT; STATES],
+            is_rep2: [PROB_INIT; STATES],
+            is_rep0_long: [[PROB_INIT; POS_STATES_MAX]; STATES],
+            dist_slot: [[PROB_INIT; 1 << DIST_SLOT_BITS]; DIST_STATES],
+            dist_special: [PROB_INIT; FULL_DISTANCES - DIST_MODEL_END as usize],
+            dist_align: [PROB_INIT; 1 << ALIGN_BITS],
+            match_len: LengthDecoder::new(),
+            rep_len: LengthDecoder::new(),
+            literal: vec![[PROB_INIT; LITERAL_CODER_SIZE]; LITERAL_CODERS_MAX],
+        }
+    }
+
+    /// Reset the model, keeping lc/lp/pb
+    fn reset(&mut self) {
+        self.state = 0;
+        self.reps = [0; 4];
+        for probs in self.is_match.iter_mut().chain(self.is_rep0_long.iter_mut()) {
+            probs.fill(PROB_INIT);
+        }
+        for probs in [&mut self.is_rep, &mut self.is_rep0, &mut self.is_rep1, &mut self.is_rep2] {
+            probs.fill(PROB_INIT);
+        }
+        for probs in self.dist_slot.iter_mut() {
+            probs.fill(PROB_INIT);
+        }
+        self.dist_special.fill(PROB_INIT);
+        self.dist_align.fill(PROB_INIT);
+        self.match_len = LengthDecoder::new();
+        self.rep_len = LengthDecoder::new();
+        for probs in self.literal.iter_mut() {
+            probs.fill(PROB_INIT);
+        }
+    }
+
+    fn set_props(&mut self, props: u8) -> CResult<()> {
+        if props >= 9 * 5 * 5 {
+            return Err(CompressError::Corrupt);
+        }
+        let (lc, lp, pb) = ((props % 9) as u32, (props / 9 % 5) as u32, (props / 45) as u32);
+        // LZMA2 limits lc + lp to 4
+        if lc + lp > 4 {
+            return Err(CompressError::Corrupt);
+        }
+        self.lc = lc;
+        self.lp = lp;
+        self.pb = pb;
+        Ok(())
+    }
+
+    fn literal_state(&mut self) {
+        self.state = match self.state {
+            0..=3 => 0,
+            4..=9 => self.state - 3,
+            _ => self.state - 6,
+        };
+    }
+
+    fn decode_literal(&mut self, rc: &mut RangeDecoder, window: &mut Window, pos: u64) -> CResult<()> {
+        let prev = if pos == 0 { 0 } else { window.back(1).unwrap_or(0) as u32 };
+        let coder = (((pos as u32) & ((1 << self.lp) - 1)) << self.lc) + (prev >> (8 - self.lc));
+        let probs = &mut self.literal[coder as usize];
+
+        let mut sym = 1u32;
+        if self.state < LIT_STATES {
+            while sym < 0x100 {
+                sym = sym << 1 | rc.bit(&mut probs[sym as usize]);
+            }
+        } else {
+            // After a match the byte at rep0 steers the probabilities
+            // until the first bit that differs from it
+            let distance = self.reps[0] as usize + 1;
+            if distance as u64 > pos {
+                return Err(CompressError::Corrupt);
+            }
+            let mut match_byte = (window.back(distance).ok_or(CompressError::Corrupt)? as u32) << 1;
+            let mut offset = 0x100;
+            while sym < 0x100 {
+                let match_bit = match_byte & offset;
+                match_byte <<= 1;
+                let bit = rc.bit(&mut probs[(offset + match_bit + sym) as usize]);
+                sym = sym << 1 | bit;
+                if bit != 0 {
+                    offset = match_bit;
+                } else {
+                    offset &= !match_bit;
+                }
+            }
+        }
+        window.push(sym as u8);
+        self.literal_state();
+        Ok(())
+    }
+
+    fn decode_distance(&mut self, rc: &mut RangeDecoder, len: usize) -> u32 {
+        let dist_state = (len - MATCH_LEN_MIN).min(DIST_STATES - 1);
+        let slot = rc.bittree(&mut self.dist_slot[dist_state], DIST_SLOT_BITS);
+        if slot < DIST_MODEL_START {
+            return slot;
+        }
+        let limit = (slot >> 1) - 1;
+        let base = (2 | (slot & 1)) << limit;
+        if slot < DIST_MODEL_END {
+            let probs = &mut self.dist_special[(base - slot) as usize..];
+            base + rc.bittree_reverse(probs, limit)
+        } else {
+            let high = rc.direct(limit - ALIGN_BITS);
+            base + (high << ALIGN_BITS) + rc.bittree_reverse(&mut self.dist_align, ALIGN_BITS)
+        }
+    }
+
+    /// Decode one literal or match. `pos` is the output position since the
+    /// last dictionary reset and `limit` the bytes left in the chunk.
+    fn decode_symbol(&mut self, rc: &mut RangeDecoder, window: &mut Window, pos: u64, limit: usize) -> CResult<usize> {
+        let pos_state = (pos as usize) & ((1 << self.pb) - 1);
+        let state = self.state;
+
+        if rc.bit(&mut self.is_match[state][pos_state]) == 0 {
+            self.decode_literal(rc, window, pos)?;
+            return Ok(1);
+        }
+
+        let len;
+        if rc.bit(&mut self.is_rep[state]) != 0 {
+            if pos == 0 {
+                return Err(CompressError::Corrupt);
+            }
+            if rc.bit(&mut self.is_rep0[state]) == 0 {
+                if rc.bit(&mut self.is_rep0_long[state][pos_state]) == 0 {
+                    // Short rep: one byte from rep0
+                    self.state = if state < LIT_STATES { 9 } else { 11 };
+                    return self.copy(window, pos, 1, limit);
+                }
+            } else {
+                let distance;
+                if rc.bit(&mut self.is_rep1[state]) == 0 {
+                    distance = self.reps[1];
+                } else {
+                    if rc.bit(&mut self.is_rep2[state]) == 0 {
+                        distance = self.reps[2];
+                    } else {
+                        distance = self.reps[3];
+                        self.reps[3] = self.reps[2];
+                    }
+                    self.reps[2] = self.reps[1];
+                }
+                self.reps[1] = self.reps[0];
+                self.reps[0] = distance;
+            }
+            self.state = if state < LIT_STATES { 8 } else { 11 };
+            len = self.rep_len.decode(rc, pos_state);
+        } else {
+            self.reps[3] = self.reps[2];
+            self.reps[2] = self.reps[1];
+            self.reps[1] = self.reps[0];
+            len = self.match_len.decode(rc, pos_state);
+            self.state = if state < LIT_STATES { 7 } else { 10 };
+            self.reps[0] = self.decode_distance(rc, len);
+            if self.reps[0] == u32::MAX {
+                // End of payload marker; LZMA2 chunks never carry one
+                return Err(CompressError::Corrupt);
+            }
+        }
+        self.copy(window, pos, len, limit)
+    }
+
+    fn copy(&mut self, window: &mut Window, pos: u64, len: usize, limit: usize) -> CResult<usize> {
+        let distance = self.reps[0] as u64 + 1;
+        if distance > pos || len > limit {
+            return Err(CompressError::Corrupt);
+        }
+        window.copy_match(distance as usize, len)?;
+        Ok(len)
+    }
+}
+
+enum Chunk<'a> {
+    /// Between chunks
+    None,
+    Stored { remaining: usize },
+    Lzma { remaining: usize, rc: RangeDecoder<'a> },
+}
+
+/// LZMA2 stream decoder writing into a caller provided window
+pub struct Lzma2Decoder<'a> {
+    data: &'a [u8],
+    pos: usize,
+    lzma: Box<Lzma>,
+    chunk: Chunk<'a>,
+    /// Window total at the last dictionary reset
+    dict_start: u64,
+    need_dict_reset: bool,
+    need_props: bool,
+    finished: bool,
+}
+
+/// Output produced per `step` before returning to the caller
+const STEP_OUTPUT: usize = 64 * 1024;
+
+impl<'a> Lzma2Decoder<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Self {
+            data,
+            pos: 0,
+            lzma: Box::new(Lzma::new()),
+            chunk: Chunk::None,
+            dict_start: 0,
+            need_dict_reset: true,
+            need_props: true,
+            finished: false,
+        }
+    }
+
+    pub fn finished(&self) -> bool {
+        self.finished
+    }
+
+    /// Input bytes used; valid once finished
+    pub fn consumed(&self) -> usize {
+        self.pos
+    }
+
+    fn take(&mut self, len: usize) -> CResult<&'a [u8]> {
+        let data = self.data;
+        let bytes = data.get(self.pos..self.pos + len).ok_or(CompressError::Truncated)?;
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    fn start_chunk(&mut self, window: &mut Window) -> CResult<()> {
+        let control = self.take(1)?[0];
+        if control == 0 {
+            self.finished = true;
+            return Ok(());
+        }
+        if control >= 0xE0 || control == 0x01 {
+            self.need_props = true;
+            self.need_dict_reset = false;
+            self.dict_start = window.total();
+        } else if self.need_dict_reset {
+            return Err(CompressError::Corrupt);
+        }
+
+        if control < 0x80 {
+            if control > 0x02 {
+                return Err(CompressError::Corrupt);
+            }
+            let size = self.take(2)?;
+            let remaining = u16::from_be_bytes([size[0], size[1]]) as usize + 1;
+            self.chunk = Chunk::Stored { remaining };
+            return Ok(());
+        }
+
+        let sizes = self.take(4)?;
+        let remaining = ((control as usize & 0x1F) << 16 | (sizes[0] as usize) << 8 | sizes[1] as usize) + 1;
+        let compressed = ((sizes[2] as usize) << 8 | sizes[3] as usize) + 1;
+        if control >= 0xC0 {
+            let props = self.take(1)?[0];
+            self.lzma.set_props(props)?;
+            self.need_props = false;
+            self.lzma.reset();
+        } else if self.need_props {
+            return Err(CompressError::Corrupt);
+        } else if control >= 0xA0 {
+            self.lzma.reset();
+        }
+        let rc = RangeDecoder::new(self.take(compressed)?)?;
+        self.chunk = Chunk::Lzma { remaining, rc };
+        Ok(())
+    }
+
+    /// Decode some output into `window`
+    pub fn step(&mut self, window: &mut Window) -> CResult<()> {
+        match &mut self.chunk {
+            Chunk::None => self.start_chunk(window),
+            Chunk::Stored { remaining } => {
+                let take = (*remaining).min(STEP_OUTPUT);
+                *remaining -= take;
+                if *remaining == 0 {
+                    self.chunk = Chunk::None;
+                }
+                let bytes = self.take(take)?;
+                window.extend(bytes);
+                Ok(())
+            }
+            Chunk::Lzma { remaining, rc } => {
+                let mut produced = 0;
+                while *remaining > 0 && produced < STEP_OUTPUT {
+                    let pos = window.total() - self.dict_start;
+                    let len = self.lzma.decode_symbol(rc, window, pos, *remaining)?;
+                    *remaining -= len;
+                    produced += len;
+                }
+                if *remaining == 0 {
+                    if !rc.finished() {
+                        return Err(CompressError::Corrupt);
+                    }
+                    self.chunk = Chunk::None;
+                }
+                Ok(())
+            }
+        }
+    }
+}
@@ -0,0 +1,237 @@
+//! Compression library
+//!
+//! Codecs shared by the package manager, firmware loader, zswap/zram and
+//! the archive manager:
+//! - zstd (RFC 8878): decoder and encoder
+//! - DEFLATE (RFC 1951) with gzip (RFC 1952) and zlib (RFC 1950) framing:
+//!   decoder and encoder
+//! - xz / LZMA2: decoder
+//! - LZ4 block and frame format: decoder and encoder
+//!
+//! Decoders are pull based: they are created over the whole compressed
+//! input and hand out the decompressed data in pieces through
+//! [`Decompress::read`], keeping only the history window the format needs
+//! in memory. `decompress` helpers in each codec collect everything into a
+//! `Vec` for callers that want the full output.
+
+#![allow(dead_code)]
+
+pub mod checksum;
+pub mod deflate;
+pub mod lz4;
+pub mod xz;
+pub mod zstd;
+
+mod huffman;
+mod lz77;
+mod lzma;
+
+use alloc::vec::Vec;
+
+use crate::util::KError;
+
+/// Errors reported by the codecs
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompressError {
+    /// Input is not in the expected format (bad magic, reserved bits)
+    BadFormat,
+    /// Input is malformed
+    Corrupt,
+    /// Input ended in the middle of the stream
+    Truncated,
+    /// Stored checksum does not match the data
+    Checksum,
+    /// Valid input using a feature this implementation lacks
+    Unsupported,
+    /// Output (or the window it needs) exceeds the allowed size
+    TooLarge,
+}
+
+impl From<CompressError> for KError {
+    fn from(err: CompressError) -> Self {
+        match err {
+            CompressError::Unsupported => KError::NotSupported,
+            CompressError::TooLarge => KError::NoMemory,
+            _ => KError::Invalid,
+        }
+    }
+}
+
+pub type CResult<T> = Result<T, CompressError>;
+
+/// Largest history window a decoder will allocate
+pub const MAX_WINDOW: usize = 1 << 27;
+
+/// Streaming decompressor
+pub trait Decompress {
+    /// Decompress into `out`, returning the number of bytes written.
+    /// 0 means the end of the stream has been reached and verified.
+    fn read(&mut self, out: &mut [u8]) -> CResult<usize>;
+}
+
+/// Formats recognised by [`detect`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Zstd,
+    Gzip,
+    Zlib,
+    Xz,
+    Lz4,
+}
+
+/// Guess the format of `data` from its magic number
+pub fn detect(data: &[u8]) -> Option<Format> {
+    if data.len() >= 4 {
+        let magic = u32::from_le_bytes([data[0], data[1], data[2], data[3]]);
+        if magic == zstd::MAGIC || zstd::is_skippable(magic) {
+            return Some(Format::Zstd);
+        }
+        if magic == lz4::MAGIC {
+            return Some(Format::Lz4);
+        }
+    }
+    if data.starts_with(&xz::MAGIC) {
+        return Some(Format::Xz);
+    }
+    if data.starts_with(&deflate::GZIP_MAGIC) {
+        return Some(Format::Gzip);
+    }
+    if data.len() >= 2 && deflate::is_zlib_header(data[0], data[1]) {
+        return Some(Format::Zlib);
+    }
+    None
+}
+
+/// Decompress `data` in whatever supported format it is in
+pub fn decompress(data: &[u8], limit: usize) -> CResult<Vec<u8>> {
+    match detect(data).ok_or(CompressError::BadFormat)? {
+        Format::Zstd => read_all(&mut zstd::Decoder::new(data)?, 0, limit),
+        Format::Gzip => read_all(&mut deflate::GzipDecoder::new(data)?, 0, limit),
+        Format::Zlib => read_all(&mut deflate::ZlibDecoder::new(data)?, 0, limit),
+        Format::Xz => read_all(&mut xz::Decoder::new(data)?, 0, limit),
+        Format::Lz4 => read_all(&mut lz4::FrameDecoder::new(data)?, 0, limit),
+    }
+}
+
+/// Drain `decoder` into a `Vec`, failing once more than `limit` bytes come out
+pub fn read_all(decoder: &mut dyn Decompress, size_hint: usize, limit: usize) -> CResult<Vec<u8>> {
+    let mut out = Vec::with_capacity(size_hint.min(limit));
+    let mut chunk = [0u8; 4096];
+    loop {
+        let n = decoder.read(&mut chunk)?;
+        if n == 0 {
+            return Ok(out);
+        }
+        if out.len() + n > limit {
+            return Err(CompressError::TooLarge);
+        }
+        out.extend_from_slice(&chunk[..n]);
+    }
+}
+
+/// Decoder output buffer that doubles as the LZ history window.
+///
+/// Decoded bytes are appended at the end; `read` hands out the ones not yet
+/// returned and `compact` drops history older than the window once it has
+/// been handed out.
+pub(crate) struct Window {
+    buf: Vec<u8>,
+    /// First byte not yet handed out
+    pending: usize,
+    /// History that must stay addressable behind the newest byte
+    size: usize,
+    /// Total bytes produced, including the ones already compacted away
+    total: u64,
+}
+
+impl Window {
+    pub fn new(size: usize) -> Self {
+        Self {
+            buf: Vec::new(),
+            pending: 0,
+            size,
+            total: 0,
+        }
+    }
+
+    /// Bytes decoded but not yet handed out
+    pub fn pending(&self) -> usize {
+        self.buf.len() - self.pending
+    }
+
+    /// Total bytes produced so far
+    pub fn total(&self) -> u64 {
+        self.total
+    }
+
+    /// Bytes of history available for matches
+    pub fn history(&self) -> usize {
+        self.buf.len()
+    }
+
+    pub fn push(&mut self, byte: u8) {
+        self.buf.push(byte);
+        self.total += 1;
+    }
+
+    pub fn extend(&mut self, data: &[u8]) {
+        self.buf.extend_from_slice(data);
+        self.total += data.len() as u64;
+    }
+
+    pub fn fill(&mut self, byte: u8, len: usize) {
+        self.buf.resize(self.buf.len() + len, byte);
+        self.total += len as u64;
+    }
+
+    /// Byte `dist` positions behind the end (1 is the newest)
+    pub fn back(&self, dist: usize) -> Option<u8> {
+        if dist == 0 || dist > self.buf.len() {
+            return None;
+        }
+        Some(self.buf[self.buf.len() - dist])
+    }
+
+    /// Copy `len` bytes starting `dist` bytes back; the ranges may overlap
+    pub fn copy_match(&mut self, dist: usize, len: usize) -> CResult<()> {
+        if dist == 0 || dist > self.buf.len() || dist > self.size {
+            return Err(CompressError::Corrupt);
+        }
+        let start = self.buf.len() - dist;
+        self.buf.reserve(len);
+        if dist >= len {
+            self.buf.extend_from_within(start..start + len);
+        } else {
+            for i in 0..len {
+                let byte = self.buf[start + i];
+                self.buf.push(byte);
+            }
+        }
+        self.total += len as u64;
+        Ok(())
+    }
+
+    /// The newest `len` bytes, which must not have been handed out yet
+    pub fn recent(&self, len: usize) -> &[u8] {
+        &self.buf[self.buf.len() - len..]
+    }
+
+    /// Hand out pending bytes into `out`
+    pub fn read(&mut self, out: &mut [u8]) -> usize {
+        let n = out.len().min(self.pending());
+        out[..n].copy_from_slice(&self.buf[self.pending..self.pending + n]);
+        self.pending += n;
+        self.compact();
+        n
+    }
+
+    /// Drop handed-out history beyond the window size. Only done once the
+    /// excess is large, so the memmove is amortised.
+    fn compact(&mut self) {
+        let keep_from = self.pending.saturating_sub(self.size);
+        if keep_from > self.size.max(64 * 1024) {
+            self.buf.drain(..keep_from);
+            self.pending -= keep_from;
+        }
+    }
+}
@@ -0,0 +1,340 @@
+//! xz container format (.xz file format 1.1.0)
+//!
+//! A file is one or more streams, each a header, blocks, an index of the
+//! block sizes and a footer. Blocks are decoded with LZMA2; other filters
+//! (BCJ, delta) are reported as unsupported.
+
+use alloc::vec::Vec;
+
+use super::checksum::{crc32, crc32_update, crc64_update};
+use super::lzma::{dict_size, Lzma2Decoder};
+use super::{read_all, CResult, CompressError, Decompress, Window, MAX_WINDOW};
+use crate::crypto::sha256::Sha256;
+
+/// Stream header magic
+pub const MAGIC: [u8; 6] = [0xFD, b'7', b'z', b'X', b'Z', 0x00];
+const FOOTER_MAGIC: [u8; 2] = [b'Y', b'Z'];
+const HEADER_LEN: usize = 12;
+const FILTER_LZMA2: u64 = 0x21;
+
+/// Integrity check types with a verifier
+const CHECK_NONE: u8 = 0x00;
+const CHECK_CRC32: u8 = 0x01;
+const CHECK_CRC64: u8 = 0x04;
+const CHECK_SHA256: u8 = 0x0A;
+
+/// Size of the check field for each of the 16 check IDs
+fn check_len(id: u8) -> usize {
+    [0, 4, 4, 4, 8, 8, 8, 16, 16, 16, 32, 32, 32, 64, 64, 64][id as usize & 0xF]
+}
+
+/// Running integrity check of one block
+enum Check {
+    None,
+    Crc32(u32),
+    Crc64(u64),
+    Sha256(Sha256),
+    /// Reserved check ID; the field is skipped without being verified
+    Unknown,
+}
+
+impl Check {
+    fn new(id: u8) -> Self {
+        match id {
+            CHECK_NONE => Check::None,
+            CHECK_CRC32 => Check::Crc32(0),
+            CHECK_CRC64 => Check::Crc64(0),
+            CHECK_SHA256 => Check::Sha256(Sha256::new()),
+            _ => Check::Unknown,
+        }
+    }
+
+    fn update(&mut self, data: &[u8]) {
+        match self {
+            Check::Crc32(crc) => *crc = crc32_update(*crc, data),
+            Check::Crc64(crc) => *crc = crc64_update(*crc, data),
+            Check::Sha256(hasher) => hasher.update(data),
+            Check::None | Check::Unknown => {}
+        }
+    }
+
+    fn verify(self, stored: &[u8]) -> bool {
+        match self {
+            Check::Crc32(crc) => stored == crc.to_le_bytes(),
+            Check::Crc64(crc) => stored == crc.to_le_bytes(),
+            Check::Sha256(hasher) => stored == hasher.finalize(),
+            Check::None | Check::Unknown => true,
+        }
+    }
+}
+
+/// Variable length integer used throughout the container
+fn read_varint(data: &[u8], pos: &mut usize) -> CResult<u64> {
+    let mut value = 0u64;
+    for i in 0..9 {
+        let byte = *data.get(*pos).ok_or(CompressError::Truncated)?;
+        *pos += 1;
+        // A trailing zero byte would be a second encoding of the same value
+        if i > 0 && byte == 0 {
+            return Err(CompressError::Corrupt);
+        }
+        value |= ((byte & 0x7F) as u64) << (7 * i);
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err(CompressError::Corrupt)
+}
+
+fn le32(bytes: &[u8]) -> u32 {
+    u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+/// Stream flags from a stream header; returns the check ID
+fn parse_stream_header(header: &[u8]) -> CResult<u8> {
+    if !header.starts_with(&MAGIC) {
+        return Err(CompressError::BadFormat);
+    }
+    let flags = &header[6..8];
+    if le32(&header[8..12]) != crc32(flags) {
+        return Err(CompressError::Checksum);
+    }
+    if flags[0] != 0 || flags[1] & 0xF0 != 0 {
+        return Err(CompressError::Unsupported);
+    }
+    Ok(flags[1])
+}
+
+struct BlockHeader {
+    len: usize,
+    compressed_size: Option<u64>,
+    uncompressed_size: Option<u64>,
+    dict_size: u64,
+}
+
+fn parse_block_header(data: &[u8]) -> CResult<BlockHeader> {
+    let len = (*data.first().ok_or(CompressError::Truncated)? as usize + 1) * 4;
+    let header = data.get(..len).ok_or(CompressError::Truncated)?;
+    if le32(&header[len - 4..]) != crc32(&header[..len - 4]) {
+        return Err(CompressError::Checksum);
+    }
+    let body = &header[..len - 4];
+
+    let flags = body[1];
+    if flags & 0x3C != 0 {
+        return Err(CompressError::Unsupported);
+    }
+    let mut pos = 2;
+    let compressed_size = if flags & 0x40 != 0 { Some(read_varint(body, &mut pos)?) } else { None };
+    let uncompressed_size = if flags & 0x80 != 0 { Some(read_varint(body, &mut pos)?) } else { None };
+
+    // LZMA2 must be the last filter; anything in front of it is a filter
+    // this decoder does not implement
+    if flags & 0x03 != 0 {
+        return Err(CompressError::Unsupported);
+    }
+    let id = read_varint(body, &mut pos)?;
+    let props_len = read_varint(body, &mut pos)?;
+    if id != FILTER_LZMA2 {
+        return Err(CompressError::Unsupported);
+    }
+    if props_len != 1 {
+        return Err(CompressError::Corrupt);
+    }
+    let props = *body.get(pos).ok_or(CompressError::Corrupt)?;
+    pos += 1;
+    if props & 0xC0 != 0 {
+        return Err(CompressError::Corrupt);
+    }
+    if body[pos..].iter().any(|&b| b != 0) {
+        return Err(CompressError::Corrupt);
+    }
+
+    Ok(BlockHeader {
+        len,
+        compressed_size,
+        uncompressed_size,
+        dict_size: dict_size(props)?,
+    })
+}
+
+/// Block being decoded
+struct Block<'a> {
+    lzma2: Lzma2Decoder<'a>,
+    /// Offset of the block's compressed data
+    start: usize,
+    header: BlockHeader,
+    check: Check,
+}
+
+/// xz file decoder
+pub struct Decoder<'a> {
+    data: &'a [u8],
+    pos: usize,
+    window: Window,
+    /// Check ID of the current stream, `None` between streams
+    stream: Option<u8>,
+    block: Option<Block<'a>>,
+    /// (unpadded size, uncompressed size) of each block, for the index
+    records: Vec<(u64, u64)>,
+}
+
+impl<'a> Decoder<'a> {
+    pub fn new(data: &'a [u8]) -> CResult<Self> {
+        if !data.starts_with(&MAGIC) {
+            return Err(CompressError::BadFormat);
+        }
+        Ok(Self {
+            data,
+            pos: 0,
+            window: Window::new(0),
+            stream: None,
+            block: None,
+            records: Vec::new(),
+        })
+    }
+
+    fn take(&mut self, len: usize) -> CResult<&'a [u8]> {
+        let data = self.data;
+        let bytes = data.get(self.pos..self.pos + len).ok_or(CompressError::Truncated)?;
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    /// Parse the next stream header. Returns false at the end of the input.
+    fn start_stream(&mut self) -> CResult<bool> {
+        if self.pos == self.data.len() {
+            return Ok(false);
+        }
+        let header = self.take(HEADER_LEN)?;
+        self.stream = Some(parse_stream_header(header)?);
+        self.records.clear();
+        Ok(true)
+    }
+
+    /// Start the next block, or check the index and footer once the
+    /// blocks run out
+    fn next_block(&mut self, check_id: u8) -> CResult<()> {
+        if *self.data.get(self.pos).ok_or(CompressError::Truncated)? == 0 {
+            return self.finish_stream(check_id);
+        }
+        let header = parse_block_header(&self.data[self.pos..])?;
+        if header.dict_size > MAX_WINDOW as u64 {
+            return Err(CompressError::TooLarge);
+        }
+        self.pos += header.len;
+        self.window = Window::new(header.dict_size as usize);
+        self.block = Some(Block {
+            lzma2: Lzma2Decoder::new(&self.data[self.pos..]),
+            start: self.pos,
+            header,
+            check: Check::new(check_id),
+        });
+        Ok(())
+    }
+
+    fn finish_block(&mut self, check_id: u8) -> CResult<()> {
+        let block = self.block.take().unwrap();
+        let compressed = block.lzma2.consumed() as u64;
+        let uncompressed = self.window.total();
+        if block.header.compressed_size.is_some_and(|size| size != compressed)
+            || block.header.uncompressed_size.is_some_and(|size| size != uncompressed)
+        {
+            return Err(CompressError::Corrupt);
+        }
+
+        self.pos = block.start + compressed as usize;
+        let padding = (4 - self.pos % 4) % 4;
+        if self.take(padding)?.iter().any(|&b| b != 0) {
+            return Err(CompressError::Corrupt);
+        }
+        let stored = self.take(check_len(check_id))?;
+        if !block.check.verify(stored) {
+            return Err(CompressError::Checksum);
+        }
+
+        let unpadded = (block.header.len + check_len(check_id)) as u64 + compressed;
+        self.records.push((unpadded, uncompressed));
+        Ok(())
+    }
+
+    /// Check the index against the decoded blocks, then the footer
+    fn finish_stream(&mut self, check_id: u8) -> CResult<()> {
+        let data = self.data;
+        let start = self.pos;
+        let mut pos = start + 1;
+        let count = read_varint(data, &mut pos)?;
+        if count != self.records.len() as u64 {
+            return Err(CompressError::Corrupt);
+        }
+        for &(unpadded, uncompressed) in &self.records {
+            if read_varint(data, &mut pos)? != unpadded || read_varint(data, &mut pos)? != uncompressed {
+                return Err(CompressError::Corrupt);
+            }
+        }
+        let padding = (4 - (pos - start) % 4) % 4;
+        let tail = data.get(pos..pos + padding + 4).ok_or(CompressError::Truncated)?;
+        if tail[..padding].iter().any(|&b| b != 0) {
+            return Err(CompressError::Corrupt);
+        }
+        pos += padding;
+        if le32(&tail[padding..]) != crc32(&data[start..pos]) {
+            return Err(CompressError::Checksum);
+        }
+        let index_len = pos + 4 - start;
+        self.pos = pos + 4;
+
+        let footer = self.take(HEADER_LEN)?;
+        if le32(&footer[..4]) != crc32(&footer[4..10]) {
+            return Err(CompressError::Checksum);
+        }
+        if (le32(&footer[4..8]) as usize + 1) * 4 != index_len
+            || footer[8..10] != [0, check_id]
+            || footer[10..] != FOOTER_MAGIC
+        {
+            return Err(CompressError::Corrupt);
+        }
+
+        // Stream padding: zero bytes in multiples of four
+        let padding = data[self.pos..].iter().take_while(|&&b| b == 0).count();
+        if padding % 4 != 0 {
+            return Err(CompressError::Corrupt);
+        }
+        self.pos += padding;
+        self.stream = None;
+        Ok(())
+    }
+}
+
+impl Decompress for Decoder<'_> {
+    fn read(&mut self, out: &mut [u8]) -> CResult<usize> {
+        loop {
+            if self.window.pending() > 0 {
+                return Ok(self.window.read(out));
+            }
+            let Some(check_id) = self.stream else {
+                if !self.start_stream()? {
+                    return Ok(0);
+                }
+                continue;
+            };
+            let Some(block) = self.block.as_mut() else {
+                self.next_block(check_id)?;
+                continue;
+            };
+
+            let before = self.window.total();
+            block.lzma2.step(&mut self.window)?;
+            let produced = (self.window.total() - before) as usize;
+            block.check.update(self.window.recent(produced));
+            if block.lzma2.finished() {
+                self.finish_block(check_id)?;
+            }
+        }
+    }
+}
+
+/// Decompress an xz file
+pub fn decompress(data: &[u8], limit: usize) -> CResult<Vec<u8>> {
+    read_all(&mut Decoder::new(data)?, data.len() * 4, limit)
+}
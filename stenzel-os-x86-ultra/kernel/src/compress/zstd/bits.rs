@@ -0,0 +1,110 @@
+//! Bitstreams for the entropy coded parts of zstd
+//!
+//! FSE and Huffman streams are written forwards and read backwards,
+//! starting from the last byte, whose highest set bit marks the end.
+
+use alloc::vec::Vec;
+
+use crate::compress::{CResult, CompressError};
+
+pub struct BackwardReader<'a> {
+    data: &'a [u8],
+    /// Bits not yet read; negative once reads have run past the start
+    left: isize,
+}
+
+impl<'a> BackwardReader<'a> {
+    pub fn new(data: &'a [u8]) -> CResult<Self> {
+        let last = *data.last().ok_or(CompressError::Corrupt)?;
+        if last == 0 {
+            return Err(CompressError::Corrupt);
+        }
+        let padding = last.leading_zeros() as isize + 1;
+        Ok(Self {
+            data,
+            left: data.len() as isize * 8 - padding,
+        })
+    }
+
+    /// `n` (at most 56) bits starting at bit `pos`
+    fn extract(&self, pos: usize, n: u32) -> u64 {
+        let byte = pos / 8;
+        let mut word = [0u8; 8];
+        let end = (byte + 8).min(self.data.len());
+        if byte < end {
+            word[..end - byte].copy_from_slice(&self.data[byte..end]);
+        }
+        (u64::from_le_bytes(word) >> (pos % 8)) & ((1u64 << n) - 1)
+    }
+
+    /// Next `n` bits without consuming them; bits before the start read as 0
+    pub fn peek(&self, n: u32) -> u64 {
+        let end = self.left;
+        let start = end - n as isize;
+        if n == 0 || end <= 0 {
+            0
+        } else if start >= 0 {
+            self.extract(start as usize, n)
+        } else {
+            self.extract(0, end as u32) << (-start)
+        }
+    }
+
+    pub fn consume(&mut self, n: u32) {
+        self.left -= n as isize;
+    }
+
+    pub fn read(&mut self, n: u32) -> u64 {
+        let value = self.peek(n);
+        self.consume(n);
+        value
+    }
+
+    /// Whether more bits were read than the stream holds
+    pub fn overflowed(&self) -> bool {
+        self.left < 0
+    }
+
+    /// Whether the stream has been read exactly to its start
+    pub fn finished(&self) -> bool {
+        self.left == 0
+    }
+}
+
+/// Writer producing streams for `BackwardReader`
+pub struct BitWriter {
+    out: Vec<u8>,
+    acc: u64,
+    count: u32,
+}
+
+impl BitWriter {
+    pub fn new() -> Self {
+        Self { out: Vec::new(), acc: 0, count: 0 }
+    }
+
+    /// Append the low `n` (at most 32) bits of `value`
+    pub fn put(&mut self, value: u64, n: u32) {
+        self.acc |= (value & ((1u64 << n) - 1)) << self.count;
+        self.count += n;
+        while self.count >= 8 {
+            self.out.push(self.acc as u8);
+            self.acc >>= 8;
+            self.count -= 8;
+        }
+    }
+
+    /// Pad the last byte with zeros and return the bytes
+    pub fn finish(mut self) -> Vec<u8> {
+        if self.count > 0 {
+            self.out.push(self.acc as u8);
+        }
+        self.out
+    }
+
+    /// Add the end marker and return the bytes
+    pub fn close(mut self) -> Vec<u8> {
+        self.put(1, 1);
+        self.finish()
+    }
+}
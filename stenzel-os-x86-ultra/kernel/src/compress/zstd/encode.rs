@@ -0,0 +1,326 @@
+//! Zstandard encoder
+//!
+//! Single frame with the content size and checksum. Each block is parsed
+//! with the shared LZ77 match finder; literals are Huffman coded when that
+//! pays off and sequences use the predefined FSE tables or ones fitted to
+//! the block, whichever is smaller. Blocks that do not shrink are stored
+//! raw (or as RLE when they are one repeated byte).
+
+use alloc::vec;
+use alloc::vec::Vec;
+
+use super::bits::BitWriter;
+use super::fse::{normalize, write_ncount, FseEncoder};
+use super::huf::HufEncoder;
+use super::{
+    LL_BASE, LL_BITS, LL_DEFAULT, LL_DEFAULT_LOG, MAGIC, MAX_BLOCK, ML_BASE, ML_BITS, ML_DEFAULT,
+    ML_DEFAULT_LOG, OF_DEFAULT, OF_DEFAULT_LOG,
+};
+use crate::compress::checksum::xxh64;
+use crate::compress::lz77::{MatchFinder, Params, Sequence};
+
+/// Window log and match finder effort per level
+fn level_params(level: u8) -> (u32, Params) {
+    let (window_log, max_chain, nice) = match level {
+        0..=2 => (19, 8, 32),
+        3..=5 => (21, 32, 64),
+        6..=9 => (22, 128, 128),
+        10..=15 => (23, 512, 256),
+        _ => (23, 2048, 1024),
+    };
+    let params = Params {
+        window: 1 << window_log,
+        min_match: 4,
+        max_match: MAX_BLOCK,
+        max_chain,
+        nice,
+        last_literals: 0,
+        match_guard: 0,
+    };
+    (window_log, params)
+}
+
+/// Code with the largest baseline not above `value`
+fn code_for(base: &[u32], value: usize) -> usize {
+    base.iter().rposition(|&b| b as usize <= value).unwrap()
+}
+
+fn raw_literals_header(out: &mut Vec<u8>, kind: u8, size: usize) {
+    if size < 32 {
+        out.push(kind | (size << 3) as u8);
+    } else if size < 4096 {
+        out.push(kind | 0b0100 | ((size & 0xF) << 4) as u8);
+        out.push((size >> 4) as u8);
+    } else {
+        out.push(kind | 0b1100 | ((size & 0xF) << 4) as u8);
+        out.push((size >> 4) as u8);
+        out.push((size >> 12) as u8);
+    }
+}
+
+/// Huffman coded literals section, if it beats storing them raw
+fn huffman_literals(literals: &[u8]) -> Option<Vec<u8>> {
+    if literals.len() < 64 {
+        return None;
+    }
+    let mut counts = [0u32; 256];
+    for &byte in literals {
+        counts[byte as usize] += 1;
+    }
+    let encoder = HufEncoder::new(&counts)?;
+    if encoder.estimate(&counts) + 16 >= literals.len() {
+        return None;
+    }
+    let description = encoder.description()?;
+
+    let mut body = description;
+    let single = literals.len() <= 1023;
+    if single {
+        body.extend_from_slice(&encoder.encode(literals));
+    } else {
+        let segment = (literals.len() + 3) / 4;
+        let streams: Vec<Vec<u8>> = literals.chunks(segment).map(|chunk| encoder.encode(chunk)).collect();
+        if streams.len() != 4 {
+            return None;
+        }
+        for stream in &streams[..3] {
+            body.extend_from_slice(&u16::try_from(stream.len()).ok()?.to_le_bytes());
+        }
+        for stream in &streams {
+            body.extend_from_slice(stream);
+        }
+    }
+
+    let (regenerated, compressed) = (literals.len() as u64, body.len() as u64);
+    let mut out = Vec::with_capacity(body.len() + 5);
+    if single && compressed <= 1023 {
+        let header = 2 | regenerated << 4 | compressed << 14;
+        out.extend_from_slice(&header.to_le_bytes()[..3]);
+    } else if single {
+        return None;
+    } else if regenerated <= 1023 && compressed <= 1023 {
+        let header = 2 | 1 << 2 | regenerated << 4 | compressed << 14;
+        out.extend_from_slice(&header.to_le_bytes()[..3]);
+    } else if regenerated <= 16383 && compressed <= 16383 {
+        let header = 2 | 2 << 2 | regenerated << 4 | compressed << 18;
+        out.extend_from_slice(&header.to_le_bytes()[..4]);
+    } else {
+        let header = 2 | 3 << 2 | regenerated << 4 | compressed << 22;
+        out.extend_from_slice(&header.to_le_bytes()[..5]);
+    }
+    out.extend_from_slice(&body);
+    (out.len() < literals.len()).then_some(out)
+}
+
+fn literals_section(out: &mut Vec<u8>, literals: &[u8]) {
+    if literals.len() > 1 && literals.iter().all(|&b| b == literals[0]) {
+        raw_literals_header(out, 1, literals.len());
+        out.push(literals[0]);
+    } else if let Some(section) = huffman_literals(literals) {
+        out.extend_from_slice(&section);
+    } else {
+        raw_literals_header(out, 0, literals.len());
+        out.extend_from_slice(literals);
+    }
+}
+
+struct SeqCodes {
+    ll_code: u8,
+    ll_extra: u32,
+    ml_code: u8,
+    ml_extra: u32,
+    of_code: u8,
+    of_extra: u32,
+}
+
+impl SeqCodes {
+    fn new(seq: &Sequence) -> Self {
+        let ll_code = code_for(&LL_BASE, seq.lit_len);
+        let ml_code = code_for(&ML_BASE, seq.match_len);
+        // Offsets are always sent as new offsets, never as repeats
+        let offset_value = (seq.offset + 3) as u32;
+        let of_code = 31 - offset_value.leading_zeros();
+        Self {
+            ll_code: ll_code as u8,
+            ll_extra: seq.lit_len as u32 - LL_BASE[ll_code],
+            ml_code: ml_code as u8,
+            ml_extra: seq.match_len as u32 - ML_BASE[ml_code],
+            of_code: of_code as u8,
+            of_extra: offset_value - (1 << of_code),
+        }
+    }
+
+    fn put_extra(&self, out: &mut BitWriter) {
+        out.put(self.ll_extra as u64, LL_BITS[self.ll_code as usize] as u32);
+        out.put(self.ml_extra as u64, ML_BITS[self.ml_code as usize] as u32);
+        out.put(self.of_extra as u64, self.of_code as u32);
+    }
+}
+
+struct SequenceTables {
+    /// Symbol compression modes byte
+    modes: u8,
+    /// Table descriptions for FSE compressed mode
+    descriptions: Vec<u8>,
+    ll: FseEncoder,
+    ml: FseEncoder,
+    of: FseEncoder,
+}
+
+impl SequenceTables {
+    fn predefined() -> Self {
+        let table = |norm: &[i16], log| FseEncoder::new(norm, log).expect("predefined distributions are valid");
+        Self {
+            modes: 0,
+            descriptions: Vec::new(),
+            ll: table(&LL_DEFAULT, LL_DEFAULT_LOG),
+            ml: table(&ML_DEFAULT, ML_DEFAULT_LOG),
+            of: table(&OF_DEFAULT, OF_DEFAULT_LOG),
+        }
+    }
+
+    /// Tables fitted to this block's symbol statistics
+    fn fitted(codes: &[SeqCodes]) -> Option<Self> {
+        let mut descriptions = Vec::new();
+        let mut fit = |symbols: &mut dyn Iterator<Item = u8>, max_symbol: usize, max_log: u32| {
+            let mut counts = vec![0u32; max_symbol + 1];
+            for sym in symbols {
+                counts[sym as usize] += 1;
+            }
+            let last = counts.iter().rposition(|&c| c > 0)?;
+            counts.truncate(last + 1);
+            let log = (usize::BITS - codes.len().leading_zeros()).clamp(5, max_log);
+            let norm = normalize(&counts, log);
+            descriptions.extend_from_slice(&write_ncount(&norm, log));
+            FseEncoder::new(&norm, log).ok()
+        };
+        let ll = fit(&mut codes.iter().map(|c| c.ll_code), 35, 9)?;
+        let of = fit(&mut codes.iter().map(|c| c.of_code), 31, 8)?;
+        let ml = fit(&mut codes.iter().map(|c| c.ml_code), 52, 9)?;
+        Some(Self {
+            modes: 2 << 6 | 2 << 4 | 2 << 2,
+            descriptions,
+            ll,
+            ml,
+            of,
+        })
+    }
+}
+
+fn sequences_section(out: &mut Vec<u8>, codes: &[SeqCodes], tables: &SequenceTables) {
+    let count = codes.len();
+    if count < 128 {
+        out.push(count as u8);
+    } else if count < 0x7F00 {
+        out.push((count >> 8) as u8 + 128);
+        out.push(count as u8);
+    } else {
+        out.push(255);
+        out.extend_from_slice(&((count - 0x7F00) as u16).to_le_bytes());
+    }
+    if count == 0 {
+        return;
+    }
+    out.push(tables.modes);
+    out.extend_from_slice(&tables.descriptions);
+
+    // Encoded last to first; the decoder reads the stream backwards
+    let mut stream = BitWriter::new();
+    let last = &codes[count - 1];
+    let mut ml_state = tables.ml.init(last.ml_code);
+    let mut of_state = tables.of.init(last.of_code);
+    let mut ll_state = tables.ll.init(last.ll_code);
+    last.put_extra(&mut stream);
+    for code in codes[..count - 1].iter().rev() {
+        tables.of.encode(&mut stream, &mut of_state, code.of_code);
+        tables.ml.encode(&mut stream, &mut ml_state, code.ml_code);
+        tables.ll.encode(&mut stream, &mut ll_state, code.ll_code);
+        code.put_extra(&mut stream);
+    }
+    tables.ml.flush(&mut stream, ml_state);
+    tables.of.flush(&mut stream, of_state);
+    tables.ll.flush(&mut stream, ll_state);
+    out.extend_from_slice(&stream.close());
+}
+
+fn block_header(out: &mut Vec<u8>, last: bool, kind: u32, size: usize) {
+    let header = last as u32 | kind << 1 | (size as u32) << 3;
+    out.extend_from_slice(&header.to_le_bytes()[..3]);
+}
+
+/// Compress `data` into a single zstd frame. Levels follow zstd(1):
+/// 1 is fastest, 19 compresses best.
+pub fn compress(data: &[u8], level: u8) -> Vec<u8> {
+    let (window_log, params) = level_params(level);
+    let single_segment = data.len() <= params.window;
+    let size = data.len() as u64;
+
+    let mut out = Vec::with_capacity(data.len() / 2 + 32);
+    out.extend_from_slice(&MAGIC.to_le_bytes());
+    let (fcs_flag, fcs_len) = match size {
+        0..=255 if single_segment => (0u8, 1),
+        256..=65791 => (1, 2),
+        0..=0xFFFF_FFFF => (2, 4),
+        _ => (3, 8),
+    };
+    out.push(fcs_flag << 6 | (single_segment as u8) << 5 | 0x04);
+    if !single_segment {
+        out.push(((window_log - 10) << 3) as u8);
+    }
+    let fcs = if fcs_len == 2 { size - 256 } else { size };
+    out.extend_from_slice(&fcs.to_le_bytes()[..fcs_len]);
+
+    if data.is_empty() {
+        block_header(&mut out, true, 0, 0);
+    }
+
+    let predefined = SequenceTables::predefined();
+    let mut finder = MatchFinder::new(data, params);
+    let mut start = 0;
+    while start < data.len() {
+        let end = (start + MAX_BLOCK).min(data.len());
+        let last = end == data.len();
+        let block = &data[start..end];
+
+        let (seqs, tail) = finder.parse(start, end);
+        let mut literals = Vec::with_capacity(block.len());
+        let mut pos = start;
+        for seq in &seqs {
+            literals.extend_from_slice(&data[pos..pos + seq.lit_len]);
+            pos += seq.lit_len + seq.match_len;
+        }
+        literals.extend_from_slice(&data[pos..pos + tail]);
+
+        let mut body = Vec::with_capacity(block.len());
+        literals_section(&mut body, &literals);
+        let codes: Vec<SeqCodes> = seqs.iter().map(SeqCodes::new).collect();
+        let mut sequences = Vec::new();
+        sequences_section(&mut sequences, &codes, &predefined);
+        if codes.len() > 32 {
+            if let Some(fitted) = SequenceTables::fitted(&codes) {
+                let mut alternative = Vec::new();
+                sequences_section(&mut alternative, &codes, &fitted);
+                if alternative.len() < sequences.len() {
+                    sequences = alternative;
+                }
+            }
+        }
+        body.extend_from_slice(&sequences);
+
+        if block.iter().all(|&b| b == block[0]) && block.len() > 1 {
+            block_header(&mut out, last, 1, block.len());
+            out.push(block[0]);
+        } else if body.len() < block.len() {
+            block_header(&mut out, last, 2, body.len());
+            out.extend_from_slice(&body);
+        } else {
+            block_header(&mut out, last, 0, block.len());
+            out.extend_from_slice(block);
+        }
+        start = end;
+    }
+
+    out.extend_from_slice(&(xxh64(data, 0) as u32).to_le_bytes());
+    out
+}
+
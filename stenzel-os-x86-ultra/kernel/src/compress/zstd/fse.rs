@@ -0,0 +1,338 @@
+//! Finite State Entropy tables (RFC 8878 section 4.1)
+
+use alloc::vec;
+use alloc::vec::Vec;
+
+use super::bits::{BackwardReader, BitWriter};
+use crate::compress::{CResult, CompressError};
+
+fn highbit(value: u32) -> u32 {
+    31 - value.leading_zeros()
+}
+
+/// Cells of the state table, each symbol getting `norm` of them, visited
+/// in the order the format prescribes. "Less than one" symbols (`-1`) take
+/// the cells at the top of the table.
+fn spread(norm: &[i16], log: u32) -> CResult<Vec<u8>> {
+    let size = 1usize << log;
+    let mut cells = vec![0u8; size];
+    let mut high = size - 1;
+    for (sym, &count) in norm.iter().enumerate() {
+        if count == -1 {
+            cells[high] = sym as u8;
+            high = high.checked_sub(1).ok_or(CompressError::Corrupt)?;
+        }
+    }
+    let step = (size >> 1) + (size >> 3) + 3;
+    let mask = size - 1;
+    let mut pos = 0;
+    for (sym, &count) in norm.iter().enumerate() {
+        for _ in 0..count.max(0) {
+            cells[pos] = sym as u8;
+            pos = (pos + step) & mask;
+            while pos > high {
+                pos = (pos + step) & mask;
+            }
+        }
+    }
+    if pos != 0 {
+        return Err(CompressError::Corrupt);
+    }
+    Ok(cells)
+}
+
+#[derive(Clone, Copy, Default)]
+struct Entry {
+    symbol: u8,
+    bits: u8,
+    baseline: u16,
+}
+
+/// Decoding table
+#[derive(Clone)]
+pub struct FseTable {
+    log: u32,
+    entries: Vec<Entry>,
+}
+
+impl FseTable {
+    pub fn new(norm: &[i16], log: u32) -> CResult<Self> {
+        let size = 1u32 << log;
+        let cells = spread(norm, log)?;
+        let mut next: Vec<u32> = norm.iter().map(|&c| if c == -1 { 1 } else { c.max(0) as u32 }).collect();
+        let entries = cells
+            .iter()
+            .map(|&sym| {
+                let state = next[sym as usize];
+                next[sym as usize] += 1;
+                let bits = log - highbit(state);
+                Entry {
+                    symbol: sym,
+                    bits: bits as u8,
+                    baseline: ((state << bits) - size) as u16,
+                }
+            })
+            .collect();
+        Ok(Self { log, entries })
+    }
+
+    /// Table that always yields `symbol` and reads no bits
+    pub fn rle(symbol: u8) -> Self {
+        Self {
+            log: 0,
+            entries: vec![Entry { symbol, bits: 0, baseline: 0 }],
+        }
+    }
+
+    pub fn init(&self, input: &mut BackwardReader) -> usize {
+        input.read(self.log) as usize
+    }
+
+    pub fn symbol(&self, state: usize) -> u8 {
+        self.entries[state].symbol
+    }
+
+    pub fn update(&self, state: usize, input: &mut BackwardReader) -> usize {
+        let entry = self.entries[state];
+        entry.baseline as usize + input.read(entry.bits as u32) as usize
+    }
+}
+
+/// Read a table description (normalized counts). Returns the counts, the
+/// accuracy log and the number of bytes used.
+pub fn read_ncount(data: &[u8], max_symbol: usize, max_log: u32) -> CResult<(Vec<i16>, u32, usize)> {
+    let peek = |bitpos: usize, n: u32| -> u32 {
+        let byte = bitpos / 8;
+        let mut word = [0u8; 4];
+        let end = (byte + 4).min(data.len());
+        if byte < end {
+            word[..end - byte].copy_from_slice(&data[byte..end]);
+        }
+        (u32::from_le_bytes(word) >> (bitpos % 8)) & ((1u32 << n) - 1)
+    };
+
+    let log = peek(0, 4) + 5;
+    if log > max_log {
+        return Err(CompressError::Corrupt);
+    }
+    let mut bitpos = 4;
+    let mut remaining = (1i32 << log) + 1;
+    let mut threshold = 1i32 << log;
+    let mut nbits = log + 1;
+    let mut norm: Vec<i16> = Vec::new();
+    let mut previous_zero = false;
+
+    while remaining > 1 && norm.len() <= max_symbol {
+        if previous_zero {
+            // Runs of zero counts: 2-bit repeat fields, 3 meaning "more"
+            let mut zeros = 0;
+            loop {
+                let repeat = peek(bitpos, 2) as usize;
+                bitpos += 2;
+                zeros += repeat;
+                if repeat != 3 {
+                    break;
+                }
+            }
+            if norm.len() + zeros > max_symbol {
+                return Err(CompressError::Corrupt);
+            }
+            norm.resize(norm.len() + zeros, 0);
+        }
+
+        let max = (2 * threshold - 1) - remaining;
+        let value = peek(bitpos, nbits) as i32;
+        let mut count;
+        if (value & (threshold - 1)) < max {
+            count = value & (threshold - 1);
+            bitpos += nbits as usize - 1;
+        } else {
+            count = value & (2 * threshold - 1);
+            if count >= threshold {
+                count -= max;
+            }
+            bitpos += nbits as usize;
+        }
+        count -= 1;
+        remaining -= count.abs();
+        if remaining < 1 {
+            return Err(CompressError::Corrupt);
+        }
+        norm.push(count as i16);
+        previous_zero = count == 0;
+        while remaining < threshold {
+            nbits -= 1;
+            threshold >>= 1;
+        }
+    }
+    if remaining != 1 {
+        return Err(CompressError::Corrupt);
+    }
+    let used = (bitpos + 7) / 8;
+    if used > data.len() {
+        return Err(CompressError::Truncated);
+    }
+    Ok((norm, log, used))
+}
+
+/// Write `norm` as a table description
+pub fn write_ncount(norm: &[i16], log: u32) -> Vec<u8> {
+    let mut out = BitWriter::new();
+    out.put((log - 5) as u64, 4);
+    let mut remaining = (1i32 << log) + 1;
+    let mut threshold = 1i32 << log;
+    let mut nbits = log + 1;
+    let mut sym = 0;
+    let mut previous_zero = false;
+    while remaining > 1 {
+        if previous_zero {
+            let start = sym;
+            while norm[sym] == 0 {
+                sym += 1;
+            }
+            let mut zeros = sym - start;
+            while zeros >= 3 {
+                out.put(3, 2);
+                zeros -= 3;
+            }
+            out.put(zeros as u64, 2);
+        }
+        let count = norm[sym] as i32;
+        sym += 1;
+        let max = (2 * threshold - 1) - remaining;
+        remaining -= count.abs();
+        let mut value = count + 1;
+        if value >= threshold {
+            value += max;
+        }
+        let n = if value < max { nbits - 1 } else { nbits };
+        out.put(value as u64, n);
+        previous_zero = value == 1;
+        while remaining < threshold {
+            nbits -= 1;
+            threshold >>= 1;
+        }
+    }
+    out.finish()
+}
+
+/// Scale `counts` to a table of `1 << log` cells. Rare symbols become
+/// "less than one" (-1); rounding error goes to the most frequent symbols.
+pub fn normalize(counts: &[u32], log: u32) -> Vec<i16> {
+    let size = 1i64 << log;
+    let total: i64 = counts.iter().map(|&c| c as i64).sum();
+    let mut norm: Vec<i16> = counts
+        .iter()
+        .map(|&c| {
+            if c == 0 {
+                0
+            } else {
+                let scaled = c as i64 * size / total;
+                if scaled == 0 { -1 } else { scaled as i16 }
+            }
+        })
+        .collect();
+    let used: i64 = norm.iter().map(|&n| (n as i64).abs()).sum();
+    let mut diff = size - used;
+    while diff != 0 {
+        let (sym, _) = norm.iter().enumerate().max_by_key(|&(_, &n)| n).unwrap();
+        if diff > 0 {
+            norm[sym] += diff as i16;
+            diff = 0;
+        } else {
+            let take = (-diff).min(norm[sym] as i64 - 1);
+            if take <= 0 {
+                // Nothing left to shrink; cannot happen for log >= 5 and
+                // fewer than 32 symbols
+                break;
+            }
+            norm[sym] -= take as i16;
+            diff += take;
+        }
+    }
+    norm
+}
+
+#[derive(Clone, Copy, Default)]
+struct SymbolTransform {
+    delta_find_state: i32,
+    delta_bits: u32,
+}
+
+/// Encoding table
+pub struct FseEncoder {
+    log: u32,
+    states: Vec<u16>,
+    transforms: Vec<SymbolTransform>,
+}
+
+impl FseEncoder {
+    pub fn new(norm: &[i16], log: u32) -> CResult<Self> {
+        let size = 1u32 << log;
+        let cells = spread(norm, log)?;
+
+        let mut cumul = vec![0u32; norm.len() + 1];
+        for (sym, &count) in norm.iter().enumerate() {
+            cumul[sym + 1] = cumul[sym] + if count == -1 { 1 } else { count.max(0) as u32 };
+        }
+        let mut states = vec![0u16; size as usize];
+        for (cell, &sym) in cells.iter().enumerate() {
+            states[cumul[sym as usize] as usize] = (size + cell as u32) as u16;
+            cumul[sym as usize] += 1;
+        }
+
+        let mut total = 0i32;
+        let transforms = norm
+            .iter()
+            .map(|&count| match count {
+                0 => SymbolTransform {
+                    delta_find_state: 0,
+                    delta_bits: ((log + 1) << 16) - size,
+                },
+                -1 | 1 => {
+                    let transform = SymbolTransform {
+                        delta_find_state: total - 1,
+                        delta_bits: (log << 16) - size,
+                    };
+                    total += 1;
+                    transform
+                }
+                count => {
+                    let count = count as u32;
+                    let max_bits_out = log - highbit(count - 1);
+                    let transform = SymbolTransform {
+                        delta_find_state: total - count as i32,
+                        delta_bits: (max_bits_out << 16) - (count << max_bits_out),
+                    };
+                    total += count as i32;
+                    transform
+                }
+            })
+            .collect();
+
+        Ok(Self { log, states, transforms })
+    }
+
+    fn lookup(&self, index: i32) -> u32 {
+        self.states[index as usize] as u32
+    }
+
+    /// Starting state for a stream whose last symbol is `sym`
+    pub fn init(&self, sym: u8) -> u32 {
+        let t = self.transforms[sym as usize];
+        let bits = (t.delta_bits + (1 << 15)) >> 16;
+        let value = (bits << 16).wrapping_sub(t.delta_bits);
+        self.lookup((value >> bits) as i32 + t.delta_find_state)
+    }
+
+    pub fn encode(&self, out: &mut BitWriter, state: &mut u32, sym: u8) {
+        let t = self.transforms[sym as usize];
+        let bits = (*state + t.delta_bits) >> 16;
+        out.put(*state as u64, bits);
+        *state = self.lookup((*state >> bits) as i32 + t.delta_find_state);
+    }
+
+    pub fn flush(&self, out: &mut BitWriter, state: u32) {
+        out.put(state as u64, self.log);
+    }
+}
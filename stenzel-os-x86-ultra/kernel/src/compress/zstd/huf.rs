@@ -0,0 +1,226 @@
+//! Huffman coded literals (RFC 8878 section 4.2)
+
+use alloc::vec;
+use alloc::vec::Vec;
+
+use super::bits::{BackwardReader, BitWriter};
+use super::fse::{normalize, read_ncount, write_ncount, FseEncoder, FseTable};
+use crate::compress::huffman::code_lengths;
+use crate::compress::{CResult, CompressError};
+
+const MAX_BITS: u32 = 11;
+/// Accuracy log used for FSE compressed weights
+const WEIGHT_LOG: u32 = 6;
+
+/// Decoding table indexed by the next `max_bits` bits of the stream
+pub struct HufTable {
+    max_bits: u32,
+    /// (symbol, code length)
+    entries: Vec<(u8, u8)>,
+}
+
+/// Parse the weights of a tree description, including the implied last
+/// one. Returns them with the largest code length and the bytes used.
+fn read_weights(data: &[u8]) -> CResult<(Vec<u8>, u32, usize)> {
+    let header = *data.first().ok_or(CompressError::Truncated)? as usize;
+    let (mut weights, used) = if header < 128 {
+        let body = data.get(1..1 + header).ok_or(CompressError::Truncated)?;
+        (read_fse_weights(body)?, 1 + header)
+    } else {
+        let count = header - 127;
+        let body = data.get(1..1 + (count + 1) / 2).ok_or(CompressError::Truncated)?;
+        let weights = (0..count)
+            .map(|i| if i % 2 == 0 { body[i / 2] >> 4 } else { body[i / 2] & 0xF })
+            .collect();
+        (weights, 1 + body.len())
+    };
+
+    // The last weight completes the sum to a power of two
+    let mut total = 0u32;
+    for &w in &weights {
+        if w as u32 > MAX_BITS {
+            return Err(CompressError::Corrupt);
+        }
+        if w > 0 {
+            total += 1 << (w - 1);
+        }
+    }
+    if total == 0 {
+        return Err(CompressError::Corrupt);
+    }
+    let max_bits = 32 - total.leading_zeros();
+    if max_bits > MAX_BITS {
+        return Err(CompressError::Corrupt);
+    }
+    let rest = (1u32 << max_bits) - total;
+    if !rest.is_power_of_two() {
+        return Err(CompressError::Corrupt);
+    }
+    weights.push(rest.trailing_zeros() as u8 + 1);
+    if weights.len() > 256 {
+        return Err(CompressError::Corrupt);
+    }
+    Ok((weights, max_bits, used))
+}
+
+impl HufTable {
+    /// Parse a tree description. Returns the table and the bytes used.
+    pub fn read(data: &[u8]) -> CResult<(Self, usize)> {
+        let (weights, max_bits, used) = read_weights(data)?;
+
+        // Codes are handed out from the lowest weight (longest code) up,
+        // in symbol order within a weight
+        let mut entries = vec![(0u8, 0u8); 1 << max_bits];
+        let mut pos = 0;
+        for weight in 1..=max_bits as u8 {
+            let span = 1usize << (weight - 1);
+            let bits = (max_bits + 1 - weight as u32) as u8;
+            for (sym, _) in weights.iter().enumerate().filter(|(_, &w)| w == weight) {
+                entries[pos..pos + span].fill((sym as u8, bits));
+                pos += span;
+            }
+        }
+        Ok((Self { max_bits, entries }, used))
+    }
+
+    /// Decode one stream holding exactly `count` symbols
+    pub fn decode_stream(&self, data: &[u8], count: usize, out: &mut Vec<u8>) -> CResult<()> {
+        let mut input = BackwardReader::new(data)?;
+        for _ in 0..count {
+            let (sym, bits) = self.entries[input.peek(self.max_bits) as usize];
+            input.consume(bits as u32);
+            out.push(sym);
+        }
+        if !input.finished() {
+            return Err(CompressError::Corrupt);
+        }
+        Ok(())
+    }
+}
+
+/// Weights sent as an FSE stream decoded with two interleaved states
+fn read_fse_weights(data: &[u8]) -> CResult<Vec<u8>> {
+    let (norm, log, used) = read_ncount(data, 255, WEIGHT_LOG)?;
+    let table = FseTable::new(&norm, log)?;
+    let mut input = BackwardReader::new(&data[used..])?;
+    let mut states = [table.init(&mut input), table.init(&mut input)];
+    let mut weights = Vec::new();
+    // Ends when a state update runs past the start of the stream; the
+    // other state then holds the final symbol
+    for turn in 0.. {
+        let current = turn % 2;
+        weights.push(table.symbol(states[current]));
+        states[current] = table.update(states[current], &mut input);
+        if input.overflowed() {
+            weights.push(table.symbol(states[1 - current]));
+            break;
+        }
+        if weights.len() > 255 {
+            return Err(CompressError::Corrupt);
+        }
+    }
+    Ok(weights)
+}
+
+/// Huffman code for a block's literals
+pub struct HufEncoder {
+    max_bits: u32,
+    codes: [u16; 256],
+    lengths: [u8; 256],
+}
+
+impl HufEncoder {
+    /// Code for `counts`; `None` when fewer than two symbols are used
+    pub fn new(counts: &[u32; 256]) -> Option<Self> {
+        if counts.iter().filter(|&&c| c > 0).count() < 2 {
+            return None;
+        }
+        let lengths_vec = code_lengths(counts, MAX_BITS);
+        let mut lengths = [0u8; 256];
+        lengths.copy_from_slice(&lengths_vec);
+        let max_bits = *lengths.iter().max().unwrap() as u32;
+
+        // Mirror of the decoder's table layout
+        let mut codes = [0u16; 256];
+        let mut pos = 0u32;
+        for len in (1..=max_bits).rev() {
+            for sym in 0..256 {
+                if lengths[sym] as u32 == len {
+                    codes[sym] = (pos >> (max_bits - len)) as u16;
+                    pos += 1 << (max_bits - len);
+                }
+            }
+        }
+        Some(Self { max_bits, codes, lengths })
+    }
+
+    /// Tree description, or `None` when it does not fit the format
+    pub fn description(&self) -> Option<Vec<u8>> {
+        let last = self.lengths.iter().rposition(|&l| l != 0)?;
+        let weights: Vec<u8> = self.lengths[..last]
+            .iter()
+            .map(|&l| if l == 0 { 0 } else { (self.max_bits + 1 - l as u32) as u8 })
+            .collect();
+
+        if weights.len() <= 128 {
+            let mut out = vec![(127 + weights.len()) as u8];
+            for pair in weights.chunks(2) {
+                out.push(pair[0] << 4 | pair.get(1).copied().unwrap_or(0));
+            }
+            return Some(out);
+        }
+
+        let mut counts = vec![0u32; self.max_bits as usize + 1];
+        for &w in &weights {
+            counts[w as usize] += 1;
+        }
+        let norm = normalize(&counts, WEIGHT_LOG);
+        let encoder = FseEncoder::new(&norm, WEIGHT_LOG).ok()?;
+        let mut body = write_ncount(&norm, WEIGHT_LOG);
+        body.extend_from_slice(&encode_two_states(&encoder, &weights));
+        if body.len() >= 128 {
+            return None;
+        }
+        let mut out = vec![body.len() as u8];
+        out.extend_from_slice(&body);
+
+        // The end of a two-state stream is only implied by the bit count,
+        // which some weight sequences cannot express; check it decodes back
+        let (decoded, _, _) = read_weights(&out).ok()?;
+        let expected = self.max_bits as u8 + 1 - self.lengths[last];
+        (decoded.len() == last + 1 && decoded[..last] == weights[..] && decoded[last] == expected)
+            .then_some(out)
+    }
+
+    /// Encode `data` as one stream
+    pub fn encode(&self, data: &[u8]) -> Vec<u8> {
+        let mut out = BitWriter::new();
+        // Written back to front so the decoder reads front to back
+        for &byte in data.iter().rev() {
+            out.put(self.codes[byte as usize] as u64, self.lengths[byte as usize] as u32);
+        }
+        out.close()
+    }
+
+    /// Size in bytes the literals would take, excluding the description
+    pub fn estimate(&self, counts: &[u32; 256]) -> usize {
+        let bits: u64 = counts.iter().zip(&self.lengths).map(|(&c, &l)| c as u64 * l as u64).sum();
+        (bits / 8) as usize + 1
+    }
+}
+
+/// FSE stream with two interleaved states, as `read_fse_weights` expects
+fn encode_two_states(encoder: &FseEncoder, symbols: &[u8]) -> Vec<u8> {
+    let mut out = BitWriter::new();
+    let n = symbols.len();
+    // Symbol i is decoded from state i % 2; encode back to front
+    let mut states = [0u32; 2];
+    states[(n - 1) % 2] = encoder.init(symbols[n - 1]);
+    states[(n - 2) % 2] = encoder.init(symbols[n - 2]);
+    for i in (0..n - 2).rev() {
+        encoder.encode(&mut out, &mut states[i % 2], symbols[i]);
+    }
+    encoder.flush(&mut out, states[1]);
+    encoder.flush(&mut out, states[0]);
+    out.close()
+}
@@ -0,0 +1,533 @@
+//! Zstandard (RFC 8878)
+//!
+//! The decoder handles everything but dictionaries: raw, RLE and
+//! compressed blocks, Huffman literals (one or four streams, fresh or
+//! repeated tables), FSE sequence tables in all four modes, repeat
+//! offsets, skippable frames, concatenated frames and the XXH64 content
+//! checksum. The encoder lives in `encode`.
+
+mod bits;
+mod encode;
+mod fse;
+mod huf;
+
+pub use encode::compress;
+
+use alloc::vec::Vec;
+
+use self::bits::BackwardReader;
+use self::fse::{read_ncount, FseTable};
+use self::huf::HufTable;
+use super::checksum::Xxh64;
+use super::{read_all, CResult, CompressError, Decompress, Window, MAX_WINDOW};
+
+pub const MAGIC: u32 = 0xFD2F_B528;
+
+/// Largest block content
+pub const MAX_BLOCK: usize = 128 * 1024;
+
+pub fn is_skippable(magic: u32) -> bool {
+    magic & 0xFFFF_FFF0 == 0x184D_2A50
+}
+
+const LL_BASE: [u32; 36] = [
+    0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 18, 20, 22, 24, 28, 32, 40, 48, 64,
+    128, 256, 512, 1024, 2048, 4096, 8192, 16384, 32768, 65536,
+];
+const LL_BITS: [u8; 36] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 3, 3, 4, 6, 7, 8, 9, 10, 11,
+    12, 13, 14, 15, 16,
+];
+const ML_BASE: [u32; 53] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17, 18, 19, 20, 21, 22, 23, 24, 25, 26, 27,
+    28, 29, 30, 31, 32, 33, 34, 35, 37, 39, 41, 43, 47, 51, 59, 67, 83, 99, 131, 259, 515, 1027,
+    2051, 4099, 8195, 16387, 32771, 65539,
+];
+const ML_BITS: [u8; 53] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+    0, 1, 1, 1, 1, 2, 2, 3, 3, 4, 4, 5, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16,
+];
+
+/// Predefined distributions used when a block selects mode 0
+const LL_DEFAULT: [i16; 36] = [
+    4, 3, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 1, 1, 1, 2, 2, 2, 2, 2, 2, 2, 2, 2, 3, 2, 1, 1, 1, 1, 1,
+    -1, -1, -1, -1,
+];
+const ML_DEFAULT: [i16; 53] = [
+    1, 4, 3, 2, 2, 2, 2, 2, 2, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+    1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, -1, -1, -1, -1, -1, -1, -1,
+];
+const OF_DEFAULT: [i16; 29] = [
+    1, 1, 1, 1, 1, 1, 2, 2, 2, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, -1, -1, -1, -1, -1,
+];
+const LL_DEFAULT_LOG: u32 = 6;
+const ML_DEFAULT_LOG: u32 = 6;
+const OF_DEFAULT_LOG: u32 = 5;
+
+/// The three sequence symbol kinds, in the order their tables are sent
+#[derive(Clone, Copy)]
+enum SeqKind {
+    LiteralLength = 0,
+    Offset = 1,
+    MatchLength = 2,
+}
+
+impl SeqKind {
+    fn max_symbol(self) -> usize {
+        match self {
+            SeqKind::LiteralLength => 35,
+            SeqKind::Offset => 31,
+            SeqKind::MatchLength => 52,
+        }
+    }
+
+    fn max_log(self) -> u32 {
+        match self {
+            SeqKind::LiteralLength | SeqKind::MatchLength => 9,
+            SeqKind::Offset => 8,
+        }
+    }
+
+    fn default_table(self) -> FseTable {
+        let table = match self {
+            SeqKind::LiteralLength => FseTable::new(&LL_DEFAULT, LL_DEFAULT_LOG),
+            SeqKind::Offset => FseTable::new(&OF_DEFAULT, OF_DEFAULT_LOG),
+            SeqKind::MatchLength => FseTable::new(&ML_DEFAULT, ML_DEFAULT_LOG),
+        };
+        table.expect("predefined distributions are valid")
+    }
+}
+
+struct FrameHeader {
+    window_size: usize,
+    content_size: Option<u64>,
+    checksum: bool,
+    /// Header size after the magic number
+    len: usize,
+}
+
+/// Parse the frame header that follows the magic number
+fn parse_frame_header(data: &[u8]) -> CResult<FrameHeader> {
+    let descriptor = *data.first().ok_or(CompressError::Truncated)?;
+    let fcs_flag = descriptor >> 6;
+    let single_segment = descriptor & 0x20 != 0;
+    if descriptor & 0x08 != 0 {
+        return Err(CompressError::Corrupt);
+    }
+    let checksum = descriptor & 0x04 != 0;
+    let dict_id_len = [0, 1, 2, 4][(descriptor & 3) as usize];
+    let fcs_len = match fcs_flag {
+        0 => single_segment as usize,
+        1 => 2,
+        2 => 4,
+        _ => 8,
+    };
+
+    let mut pos = 1;
+    let mut window_size = 0;
+    if !single_segment {
+        let wd = *data.get(pos).ok_or(CompressError::Truncated)?;
+        let base = 1u64 << (10 + (wd >> 3));
+        window_size = base + (base / 8) * (wd & 7) as u64;
+        pos += 1;
+    }
+
+    let field = |pos: usize, len: usize| -> CResult<u64> {
+        let bytes = data.get(pos..pos + len).ok_or(CompressError::Truncated)?;
+        Ok(bytes.iter().rev().fold(0u64, |acc, &b| acc << 8 | b as u64))
+    };
+    if field(pos, dict_id_len)? != 0 {
+        return Err(CompressError::Unsupported);
+    }
+    pos += dict_id_len;
+
+    let content_size = if fcs_len == 0 {
+        None
+    } else {
+        let value = field(pos, fcs_len)?;
+        Some(if fcs_len == 2 { value + 256 } else { value })
+    };
+    pos += fcs_len;
+
+    if single_segment {
+        window_size = content_size.unwrap_or(0);
+    }
+    if window_size > MAX_WINDOW as u64 {
+        return Err(CompressError::TooLarge);
+    }
+
+    Ok(FrameHeader {
+        window_size: window_size as usize,
+        content_size,
+        checksum,
+        len: pos,
+    })
+}
+
+/// Decompressed size recorded in the first frame header, if any
+pub fn content_size(data: &[u8]) -> Option<u64> {
+    let magic = u32::from_le_bytes(data.get(..4)?.try_into().ok()?);
+    if magic != MAGIC {
+        return None;
+    }
+    parse_frame_header(&data[4..]).ok()?.content_size
+}
+
+/// State that lives for one frame
+struct Frame {
+    window_size: usize,
+    content_size: Option<u64>,
+    checksum: Option<Xxh64>,
+    produced: u64,
+    huffman: Option<HufTable>,
+    /// Literal length, offset and match length tables for repeat mode
+    tables: [Option<FseTable>; 3],
+    reps: [usize; 3],
+}
+
+/// Zstandard stream decoder
+pub struct Decoder<'a> {
+    data: &'a [u8],
+    pos: usize,
+    window: Window,
+    frame: Option<Frame>,
+}
+
+impl<'a> Decoder<'a> {
+    pub fn new(data: &'a [u8]) -> CResult<Self> {
+        let magic = u32::from_le_bytes(data.get(..4).ok_or(CompressError::Truncated)?.try_into().unwrap());
+        if magic != MAGIC && !is_skippable(magic) {
+            return Err(CompressError::BadFormat);
+        }
+        Ok(Self {
+            data,
+            pos: 0,
+            window: Window::new(0),
+            frame: None,
+        })
+    }
+
+    fn take(&mut self, len: usize) -> CResult<&'a [u8]> {
+        let data = self.data;
+        let bytes = data.get(self.pos..self.pos + len).ok_or(CompressError::Truncated)?;
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    /// Skip skippable frames and parse the next frame header. Returns false
+    /// at the end of the input.
+    fn start_frame(&mut self) -> CResult<bool> {
+        loop {
+            if self.pos == self.data.len() {
+                return Ok(false);
+            }
+            let magic = self.take(4)?;
+            let magic = u32::from_le_bytes([magic[0], magic[1], magic[2], magic[3]]);
+            if is_skippable(magic) {
+                let size = self.take(4)?;
+                let size = u32::from_le_bytes([size[0], size[1], size[2], size[3]]);
+                self.take(size as usize)?;
+                continue;
+            }
+            if magic != MAGIC {
+                return Err(CompressError::BadFormat);
+            }
+
+            let header = parse_frame_header(&self.data[self.pos..])?;
+            self.pos += header.len;
+            self.window = Window::new(header.window_size);
+            self.frame = Some(Frame {
+                window_size: header.window_size,
+                content_size: header.content_size,
+                checksum: header.checksum.then(|| Xxh64::new(0)),
+                produced: 0,
+                huffman: None,
+                tables: [None, None, None],
+                reps: [1, 4, 8],
+            });
+            return Ok(true);
+        }
+    }
+
+    fn decode_block(&mut self) -> CResult<()> {
+        let header = self.take(3)?;
+        let header = u32::from_le_bytes([header[0], header[1], header[2], 0]);
+        let last = header & 1 != 0;
+        let size = (header >> 3) as usize;
+
+        let frame = self.frame.as_mut().unwrap();
+        let max_block = frame.window_size.clamp(1, MAX_BLOCK);
+        let before = self.window.history();
+        match (header >> 1) & 3 {
+            0 => {
+                if size > max_block {
+                    return Err(CompressError::Corrupt);
+                }
+                let data = self.data.get(self.pos..self.pos + size).ok_or(CompressError::Truncated)?;
+                self.window.extend(data);
+                self.pos += size;
+            }
+            1 => {
+                if size > max_block {
+                    return Err(CompressError::Corrupt);
+                }
+                let byte = *self.data.get(self.pos).ok_or(CompressError::Truncated)?;
+                self.window.fill(byte, size);
+                self.pos += 1;
+            }
+            2 => {
+                if size > max_block {
+                    return Err(CompressError::Corrupt);
+                }
+                let data = self.data.get(self.pos..self.pos + size).ok_or(CompressError::Truncated)?;
+                decode_compressed(frame, &mut self.window, data)?;
+                if self.window.history() - before > max_block {
+                    return Err(CompressError::Corrupt);
+                }
+                self.pos += size;
+            }
+            _ => return Err(CompressError::Corrupt),
+        }
+
+        let produced = self.window.history() - before;
+        frame.produced += produced as u64;
+        if let Some(hasher) = frame.checksum.as_mut() {
+            hasher.update(self.window.recent(produced));
+        }
+
+        if last {
+            if frame.content_size.is_some_and(|size| size != frame.produced) {
+                return Err(CompressError::Corrupt);
+            }
+            if let Some(hasher) = frame.checksum.take() {
+                let stored = self.take(4)?;
+                let stored = u32::from_le_bytes([stored[0], stored[1], stored[2], stored[3]]);
+                if stored != hasher.finish() as u32 {
+                    return Err(CompressError::Checksum);
+                }
+            }
+            self.frame = None;
+        }
+        Ok(())
+    }
+}
+
+impl Decompress for Decoder<'_> {
+    fn read(&mut self, out: &mut [u8]) -> CResult<usize> {
+        loop {
+            if self.window.pending() > 0 {
+                return Ok(self.window.read(out));
+            }
+            if self.frame.is_none() && !self.start_frame()? {
+                return Ok(0);
+            }
+            self.decode_block()?;
+        }
+    }
+}
+
+fn decode_compressed(frame: &mut Frame, window: &mut Window, block: &[u8]) -> CResult<()> {
+    let (literals, used) = decode_literals(frame, block)?;
+    decode_sequences(frame, window, &block[used..], &literals)
+}
+
+/// Literals section: returns the literals and the bytes it took
+fn decode_literals(frame: &mut Frame, block: &[u8]) -> CResult<(Vec<u8>, usize)> {
+    let byte = |i: usize| -> CResult<usize> { block.get(i).map(|&b| b as usize).ok_or(CompressError::Truncated) };
+    let b0 = byte(0)?;
+    let kind = b0 & 3;
+    let size_format = (b0 >> 2) & 3;
+
+    if kind < 2 {
+        // Raw or RLE
+        let (size, header) = match size_format {
+            0 | 2 => (b0 >> 3, 1),
+            1 => ((b0 >> 4) | byte(1)? << 4, 2),
+            _ => ((b0 >> 4) | byte(1)? << 4 | byte(2)? << 12, 3),
+        };
+        if size > MAX_BLOCK {
+            return Err(CompressError::Corrupt);
+        }
+        return if kind == 0 {
+            let data = block.get(header..header + size).ok_or(CompressError::Truncated)?;
+            Ok((data.to_vec(), header + size))
+        } else {
+            Ok((alloc::vec![byte(header)? as u8; size], header + 1))
+        };
+    }
+
+    let (regenerated, compressed, header, streams) = match size_format {
+        0 | 1 => {
+            let v = b0 | byte(1)? << 8 | byte(2)? << 16;
+            ((v >> 4) & 0x3FF, (v >> 14) & 0x3FF, 3, if size_format == 0 { 1 } else { 4 })
+        }
+        2 => {
+            let v = b0 | byte(1)? << 8 | byte(2)? << 16 | byte(3)? << 24;
+            ((v >> 4) & 0x3FFF, (v >> 18) & 0x3FFF, 4, 4)
+        }
+        _ => {
+            let v = b0 as u64 | (byte(1)? as u64) << 8 | (byte(2)? as u64) << 16
+                | (byte(3)? as u64) << 24 | (byte(4)? as u64) << 32;
+            (((v >> 4) & 0x3FFFF) as usize, ((v >> 22) & 0x3FFFF) as usize, 5, 4)
+        }
+    };
+    if regenerated > MAX_BLOCK {
+        return Err(CompressError::Corrupt);
+    }
+    let mut data = block.get(header..header + compressed).ok_or(CompressError::Truncated)?;
+
+    if kind == 2 {
+        let (table, used) = HufTable::read(data)?;
+        frame.huffman = Some(table);
+        data = &data[used..];
+    }
+    let table = frame.huffman.as_ref().ok_or(CompressError::Corrupt)?;
+
+    let mut literals = Vec::with_capacity(regenerated);
+    if streams == 1 {
+        table.decode_stream(data, regenerated, &mut literals)?;
+    } else {
+        if data.len() < 6 {
+            return Err(CompressError::Corrupt);
+        }
+        let jump = |i: usize| u16::from_le_bytes([data[2 * i], data[2 * i + 1]]) as usize;
+        let sizes = [jump(0), jump(1), jump(2)];
+        let total: usize = sizes.iter().sum();
+        if 6 + total > data.len() {
+            return Err(CompressError::Corrupt);
+        }
+        let segment = (regenerated + 3) / 4;
+        if segment * 3 > regenerated {
+            return Err(CompressError::Corrupt);
+        }
+        let mut start = 6;
+        for size in sizes {
+            table.decode_stream(&data[start..start + size], segment, &mut literals)?;
+            start += size;
+        }
+        table.decode_stream(&data[start..], regenerated - 3 * segment, &mut literals)?;
+    }
+    Ok((literals, header + compressed))
+}
+
+/// Build or reuse the table for one sequence symbol kind
+fn sequence_table(frame: &mut Frame, kind: SeqKind, mode: u8, data: &[u8], pos: &mut usize) -> CResult<()> {
+    let table = match mode {
+        0 => kind.default_table(),
+        1 => {
+            let symbol = *data.get(*pos).ok_or(CompressError::Truncated)?;
+            *pos += 1;
+            if symbol as usize > kind.max_symbol() {
+                return Err(CompressError::Corrupt);
+            }
+            FseTable::rle(symbol)
+        }
+        2 => {
+            let (norm, log, used) = read_ncount(&data[*pos..], kind.max_symbol(), kind.max_log())?;
+            *pos += used;
+            FseTable::new(&norm, log)?
+        }
+        _ => return frame.tables[kind as usize].as_ref().map(|_| ()).ok_or(CompressError::Corrupt),
+    };
+    frame.tables[kind as usize] = Some(table);
+    Ok(())
+}
+
+fn decode_sequences(frame: &mut Frame, window: &mut Window, data: &[u8], literals: &[u8]) -> CResult<()> {
+    let byte = |i: usize| -> CResult<usize> { data.get(i).map(|&b| b as usize).ok_or(CompressError::Truncated) };
+    let b0 = byte(0)?;
+    let (count, mut pos) = match b0 {
+        0..=127 => (b0, 1),
+        128..=254 => (((b0 - 128) << 8) + byte(1)?, 2),
+        _ => (byte(1)? + (byte(2)? << 8) + 0x7F00, 3),
+    };
+    if count == 0 {
+        if pos != data.len() {
+            return Err(CompressError::Corrupt);
+        }
+        window.extend(literals);
+        return Ok(());
+    }
+
+    let modes = byte(pos)? as u8;
+    pos += 1;
+    if modes & 3 != 0 {
+        return Err(CompressError::Corrupt);
+    }
+    sequence_table(frame, SeqKind::LiteralLength, modes >> 6, data, &mut pos)?;
+    sequence_table(frame, SeqKind::Offset, (modes >> 4) & 3, data, &mut pos)?;
+    sequence_table(frame, SeqKind::MatchLength, (modes >> 2) & 3, data, &mut pos)?;
+
+    let [Some(ll), Some(of), Some(ml)] = &frame.tables else {
+        unreachable!("tables were just set");
+    };
+    let mut input = BackwardReader::new(&data[pos..])?;
+    let mut ll_state = ll.init(&mut input);
+    let mut of_state = of.init(&mut input);
+    let mut ml_state = ml.init(&mut input);
+    let reps = &mut frame.reps;
+    let mut lit_pos = 0;
+
+    for i in 0..count {
+        let ll_code = ll.symbol(ll_state) as usize;
+        let of_code = of.symbol(of_state) as u32;
+        let ml_code = ml.symbol(ml_state) as usize;
+        if ll_code >= LL_BASE.len() || ml_code >= ML_BASE.len() || of_code > 31 {
+            return Err(CompressError::Corrupt);
+        }
+
+        let offset_value = (1usize << of_code) + input.read(of_code) as usize;
+        let match_len = ML_BASE[ml_code] as usize + input.read(ML_BITS[ml_code] as u32) as usize;
+        let lit_len = LL_BASE[ll_code] as usize + input.read(LL_BITS[ll_code] as u32) as usize;
+
+        let offset = if offset_value > 3 {
+            let offset = offset_value - 3;
+            *reps = [offset, reps[0], reps[1]];
+            offset
+        } else {
+            // Repeat offsets; with no literals the indices shift by one
+            let index = offset_value - 1 + (lit_len == 0) as usize;
+            match index {
+                0 => reps[0],
+                1 => {
+                    *reps = [reps[1], reps[0], reps[2]];
+                    reps[0]
+                }
+                2 => {
+                    *reps = [reps[2], reps[0], reps[1]];
+                    reps[0]
+                }
+                _ => {
+                    let offset = reps[0].checked_sub(1).filter(|&o| o > 0).ok_or(CompressError::Corrupt)?;
+                    *reps = [offset, reps[0], reps[1]];
+                    offset
+                }
+            }
+        };
+
+        if i + 1 < count {
+            ll_state = ll.update(ll_state, &mut input);
+            ml_state = ml.update(ml_state, &mut input);
+            of_state = of.update(of_state, &mut input);
+        }
+        if input.overflowed() {
+            return Err(CompressError::Corrupt);
+        }
+
+        let lits = literals.get(lit_pos..lit_pos + lit_len).ok_or(CompressError::Corrupt)?;
+        window.extend(lits);
+        lit_pos += lit_len;
+        window.copy_match(offset, match_len)?;
+    }
+    if !input.finished() {
+        return Err(CompressError::Corrupt);
+    }
+    window.extend(&literals[lit_pos..]);
+    Ok(())
+}
+
+/// Decompress all frames in `data`
+pub fn decompress(data: &[u8], limit: usize) -> CResult<Vec<u8>> {
+    let hint = content_size(data).unwrap_or(0).min(limit as u64) as usize;
+    read_all(&mut Decoder::new(data)?, hint, limit)
+}
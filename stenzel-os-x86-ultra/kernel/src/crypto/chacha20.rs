@@ -27,7 +27,7 @@ fn quarter_round(state: &mut [u32; 16], a: usize, b: usize, c: usize, d: usize)
 }
 
 /// ChaCha20 block function
-fn chacha20_block(key: &[u8; 32], counter: u32, nonce: &[u8; 12]) -> [u8; 64] {
+pub fn chacha20_block(key: &[u8; 32], counter: u32, nonce: &[u8; 12]) -> [u8; 64] {
     // Initialize state
     let mut state: [u32; 16] = [
         // "expand 32-byte k"
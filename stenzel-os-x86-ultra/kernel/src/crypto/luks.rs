@@ -8,7 +8,6 @@
 use alloc::string::{String, ToString};
 use alloc::vec::Vec;
 use alloc::vec;
-use core::sync::atomic::{AtomicBool, Ordering};
 
 use crate::crypto::aes::{aes_encrypt_block, aes_decrypt_block, expand_key_256};
 use crate::crypto::sha256::{sha256, hmac_sha256};
@@ -318,52 +317,73 @@ fn gf_mul2(tweak: &mut [u8; 16]) {
     }
 }
 
-/// AES-XTS encrypt a single 16-byte block
-fn aes_xts_encrypt_block(
-    block: &[u8; 16],
-    key1: &[[u8; 16]; 15], // Expanded AES-256 key 1
-    tweak: &[u8; 16],
-) -> [u8; 16] {
-    // XOR with tweak
-    let mut data = [0u8; 16];
-    for i in 0..16 {
-        data[i] = block[i] ^ tweak[i];
-    }
-
-    // Encrypt
-    let encrypted = aes_encrypt_block(&data, key1);
+/// AES-256-XTS key schedule for one volume key, expanded once
+///
+/// The 64-byte key is the data key followed by the tweak key. Sector
+/// buffers must be a multiple of 16 bytes; ciphertext stealing is not
+/// implemented.
+pub struct XtsKey {
+    data: [[u8; 16]; 15],
+    tweak: [[u8; 16]; 15],
+}
 
-    // XOR with tweak again
-    let mut result = [0u8; 16];
-    for i in 0..16 {
-        result[i] = encrypted[i] ^ tweak[i];
+impl XtsKey {
+    pub fn new(key: &[u8]) -> Option<Self> {
+        if key.len() != KEY_SIZE_512 {
+            return None;
+        }
+        Some(Self {
+            data: expand_key_256(&key[0..32].try_into().unwrap()),
+            tweak: expand_key_256(&key[32..64].try_into().unwrap()),
+        })
     }
 
-    result
-}
-
-/// AES-XTS decrypt a single 16-byte block
-fn aes_xts_decrypt_block(
-    block: &[u8; 16],
-    key1: &[[u8; 16]; 15], // Expanded AES-256 key 1
-    tweak: &[u8; 16],
-) -> [u8; 16] {
-    // XOR with tweak
-    let mut data = [0u8; 16];
-    for i in 0..16 {
-        data[i] = block[i] ^ tweak[i];
+    /// Initial tweak for a sector (plain64 IV)
+    fn initial_tweak(&self, sector_num: u64) -> [u8; 16] {
+        let mut iv = [0u8; 16];
+        iv[0..8].copy_from_slice(&sector_num.to_le_bytes());
+        aes_encrypt_block(&iv, &self.tweak)
     }
 
-    // Decrypt
-    let decrypted = aes_decrypt_block(&data, key1);
+    /// Encrypt one sector in place
+    pub fn encrypt_sector(&self, buf: &mut [u8], sector_num: u64) {
+        let mut tweak = self.initial_tweak(sector_num);
+        for block in buf.chunks_exact_mut(16) {
+            let mut data = [0u8; 16];
+            for i in 0..16 {
+                data[i] = block[i] ^ tweak[i];
+            }
+            let encrypted = aes_encrypt_block(&data, &self.data);
+            for i in 0..16 {
+                block[i] = encrypted[i] ^ tweak[i];
+            }
+            gf_mul2(&mut tweak);
+        }
+    }
 
-    // XOR with tweak again
-    let mut result = [0u8; 16];
-    for i in 0..16 {
-        result[i] = decrypted[i] ^ tweak[i];
+    /// Decrypt one sector in place
+    pub fn decrypt_sector(&self, buf: &mut [u8], sector_num: u64) {
+        let mut tweak = self.initial_tweak(sector_num);
+        for block in buf.chunks_exact_mut(16) {
+            let mut data = [0u8; 16];
+            for i in 0..16 {
+                data[i] = block[i] ^ tweak[i];
+            }
+            let decrypted = aes_decrypt_block(&data, &self.data);
+            for i in 0..16 {
+                block[i] = decrypted[i] ^ tweak[i];
+            }
+            gf_mul2(&mut tweak);
+        }
     }
+}
 
-    result
+impl Drop for XtsKey {
+    fn drop(&mut self) {
+        for round in self.data.iter_mut().chain(self.tweak.iter_mut()) {
+            wipe(round);
+        }
+    }
 }
 
 /// Encrypt a sector using AES-XTS
@@ -372,28 +392,15 @@ pub fn aes_xts_encrypt_sector(
     key: &[u8], // 64 bytes: key1 (32) + key2 (32)
     sector_num: u64,
 ) -> Vec<u8> {
-    if key.len() != 64 || plaintext.len() % 16 != 0 {
+    let Some(xts) = XtsKey::new(key) else {
+        return Vec::new();
+    };
+    if plaintext.len() % 16 != 0 {
         return Vec::new();
     }
 
-    let key1 = expand_key_256(&key[0..32].try_into().unwrap());
-    let key2 = expand_key_256(&key[32..64].try_into().unwrap());
-
-    // Generate initial tweak from sector number
-    let mut tweak_block = [0u8; 16];
-    tweak_block[0..8].copy_from_slice(&sector_num.to_le_bytes());
-    let mut tweak = aes_encrypt_block(&tweak_block, &key2);
-
-    let mut ciphertext = Vec::with_capacity(plaintext.len());
-    let blocks = plaintext.len() / 16;
-
-    for i in 0..blocks {
-        let block: [u8; 16] = plaintext[i * 16..(i + 1) * 16].try_into().unwrap();
-        let encrypted = aes_xts_encrypt_block(&block, &key1, &tweak);
-        ciphertext.extend_from_slice(&encrypted);
-        gf_mul2(&mut tweak);
-    }
-
+    let mut ciphertext = plaintext.to_vec();
+    xts.encrypt_sector(&mut ciphertext, sector_num);
     ciphertext
 }
 
@@ -403,28 +410,15 @@ pub fn aes_xts_decrypt_sector(
     key: &[u8], // 64 bytes: key1 (32) + key2 (32)
     sector_num: u64,
 ) -> Vec<u8> {
-    if key.len() != 64 || ciphertext.len() % 16 != 0 {
+    let Some(xts) = XtsKey::new(key) else {
+        return Vec::new();
+    };
+    if ciphertext.len() % 16 != 0 {
         return Vec::new();
     }
 
-    let key1 = expand_key_256(&key[0..32].try_into().unwrap());
-    let key2 = expand_key_256(&key[32..64].try_into().unwrap());
-
-    // Generate initial tweak from sector number
-    let mut tweak_block = [0u8; 16];
-    tweak_block[0..8].copy_from_slice(&sector_num.to_le_bytes());
-    let mut tweak = aes_encrypt_block(&tweak_block, &key2);
-
-    let mut plaintext = Vec::with_capacity(ciphertext.len());
-    let blocks = ciphertext.len() / 16;
-
-    for i in 0..blocks {
-        let block: [u8; 16] = ciphertext[i * 16..(i + 1) * 16].try_into().unwrap();
-        let decrypted = aes_xts_decrypt_block(&block, &key1, &tweak);
-        plaintext.extend_from_slice(&decrypted);
-        gf_mul2(&mut tweak);
-    }
-
+    let mut plaintext = ciphertext.to_vec();
+    xts.decrypt_sector(&mut plaintext, sector_num);
     plaintext
 }
 
@@ -445,6 +439,10 @@ pub enum LuksError {
     DecryptionFailed,
     AlreadyOpen,
     NotOpen,
+    /// Cipher, KDF or header feature this implementation does not handle
+    Unsupported,
+    /// Device cannot hold the header, keyslots and a data segment
+    DeviceTooSmall,
 }
 
 /// State of a LUKS volume
@@ -638,6 +636,11 @@ impl LuksVolume {
         Ok(plaintext)
     }
 
+    /// Volume key, while the volume is unlocked
+    pub fn master_key(&self) -> Option<&[u8]> {
+        self.master_key.as_deref()
+    }
+
     /// Get the device name
     pub fn device_name(&self) -> &str {
         &self.device_name
@@ -806,8 +809,15 @@ fn generate_random_bytes(buf: &mut [u8]) {
     }
 }
 
+/// Zero key material in a way the optimizer cannot drop
+pub fn wipe(buf: &mut [u8]) {
+    for byte in buf.iter_mut() {
+        unsafe { core::ptr::write_volatile(byte, 0) };
+    }
+}
+
 /// Generate a UUID string
-fn generate_uuid() -> String {
+pub(crate) fn generate_uuid() -> String {
     let mut bytes = [0u8; 16];
     generate_random_bytes(&mut bytes);
 
@@ -827,52 +837,23 @@ fn generate_uuid() -> String {
 }
 
 // ============================================================================
-// dm-crypt Style Block Device Integration
+// Syscall Interface
 // ============================================================================
 
-use crate::storage::BlockDevice;
-
-/// Encrypted block device wrapper
-pub struct EncryptedBlockDevice {
-    /// Underlying block device
-    inner: alloc::sync::Arc<dyn BlockDevice>,
-    /// LUKS volume (must be unlocked)
-    device_name: String,
-    /// Payload offset in sectors
-    payload_offset: u64,
-    /// Is active
-    active: AtomicBool,
-}
+use crate::util::KError;
 
-impl EncryptedBlockDevice {
-    pub fn new(
-        inner: alloc::sync::Arc<dyn BlockDevice>,
-        device_name: &str,
-        payload_offset: u64,
-    ) -> Self {
-        Self {
-            inner,
-            device_name: String::from(device_name),
-            payload_offset,
-            active: AtomicBool::new(true),
+impl From<LuksError> for KError {
+    fn from(e: LuksError) -> Self {
+        match e {
+            LuksError::InvalidPassword => KError::PermissionDenied,
+            LuksError::InvalidHeader => KError::Invalid,
+            LuksError::Unsupported => KError::NotSupported,
+            LuksError::DeviceTooSmall => KError::OutOfRange,
+            _ => KError::IO,
         }
     }
-
-    pub fn is_active(&self) -> bool {
-        self.active.load(Ordering::Acquire)
-    }
-
-    pub fn deactivate(&self) {
-        self.active.store(false, Ordering::Release);
-    }
 }
 
-// ============================================================================
-// Syscall Interface
-// ============================================================================
-
-use crate::util::KError;
-
 /// Format a device with LUKS
 pub fn sys_luks_format(device: &str, passphrase: &[u8]) -> Result<(), KError> {
     manager().format(device, passphrase)
@@ -883,11 +864,7 @@ pub fn sys_luks_format(device: &str, passphrase: &[u8]) -> Result<(), KError> {
 /// Open an encrypted volume
 pub fn sys_luks_open(device: &str, passphrase: &[u8], header: &[u8]) -> Result<(), KError> {
     manager().open(device, passphrase, header)
-        .map_err(|e| match e {
-            LuksError::InvalidPassword => KError::PermissionDenied,
-            LuksError::InvalidHeader => KError::Invalid,
-            _ => KError::IO,
-        })
+        .map_err(KError::from)
 }
 
 /// Close an encrypted volume
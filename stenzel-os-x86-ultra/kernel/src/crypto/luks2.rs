@@ -0,0 +1,945 @@
+//! LUKS2 on-disk format
+//!
+//! A LUKS2 device starts with two copies of the header, each a 4 KiB
+//! binary header followed by a JSON metadata area. After them come the
+//! keyslot areas and then the encrypted data segment. A keyslot holds the
+//! volume key, split with the anti-forensic splitter and encrypted under a
+//! key derived from a passphrase; the digest tells whether a keyslot
+//! opened to the right volume key.
+//!
+//! What cryptsetup writes for aes-xts-plain64 is supported: PBKDF2-SHA256
+//! keyslots, sha256 anti-forensic hashing and pbkdf2 digests. Argon2
+//! keyslots are parsed but skipped when unlocking.
+
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec;
+use alloc::vec::Vec;
+
+use super::luks::{
+    generate_uuid, pbkdf2_sha256, wipe, LuksError, XtsKey, CIPHER_AES_XTS_PLAIN64,
+    DEFAULT_PBKDF2_ITERATIONS, KEY_SIZE_512, LUKS_MAGIC, SECTOR_SIZE,
+};
+use super::sha256::Sha256;
+use crate::storage::BlockDevice;
+
+/// Magic of the secondary header copy
+pub const LUKS2_MAGIC_SECONDARY: &[u8; 6] = b"SKUL\xba\xbe";
+
+/// Binary part of each header copy; the JSON area follows it
+pub const BINARY_HEADER_SIZE: usize = 4096;
+
+/// Sizes a header copy (binary header plus JSON area) may have
+const HEADER_SIZES: [u64; 9] = [
+    0x4000, 0x8000, 0x10000, 0x20000, 0x40000, 0x80000, 0x100000, 0x200000, 0x400000,
+];
+
+/// Header copy size written by `format`
+const FORMAT_HEADER_SIZE: u64 = 0x4000;
+
+/// Alignment of the keyslot areas and of the data segment written by `format`
+const AREA_ALIGNMENT: u64 = 4096;
+const DATA_ALIGNMENT: u64 = 1 << 20;
+
+/// Default number of anti-forensic stripes
+pub const DEFAULT_AF_STRIPES: u32 = 4000;
+
+// Binary header field offsets
+const OFF_VERSION: usize = 6;
+const OFF_HDR_SIZE: usize = 8;
+const OFF_SEQID: usize = 16;
+const OFF_LABEL: usize = 24;
+const OFF_CSUM_ALG: usize = 72;
+const OFF_SALT: usize = 104;
+const OFF_UUID: usize = 168;
+const OFF_HDR_OFFSET: usize = 256;
+const OFF_CSUM: usize = 448;
+
+const LABEL_LEN: usize = 48;
+const UUID_LEN: usize = 40;
+
+// ============================================================================
+// Metadata
+// ============================================================================
+
+/// Key derivation of a keyslot
+#[derive(Debug, Clone)]
+pub enum Kdf {
+    Pbkdf2 { hash: String, iterations: u32, salt: Vec<u8> },
+    /// argon2i / argon2id, named by `kind`
+    Argon2 { kind: String },
+}
+
+/// Keyslot holding one copy of the volume key
+#[derive(Debug, Clone)]
+pub struct Keyslot {
+    pub id: u32,
+    /// Size of the volume key stored in this slot
+    pub key_size: usize,
+    /// 0 = only used when asked for explicitly, 1 = normal, 2 = preferred
+    pub priority: u32,
+    pub kdf: Kdf,
+    pub af_stripes: u32,
+    pub af_hash: String,
+    /// Byte offset of the key material on the device
+    pub area_offset: u64,
+    pub area_size: u64,
+    pub area_encryption: String,
+    /// Size of the key derived from the passphrase
+    pub area_key_size: usize,
+}
+
+/// Region of the device mapped by dm-crypt
+#[derive(Debug, Clone)]
+pub struct Segment {
+    pub id: u32,
+    /// "crypt" for a normal data segment; "linear" appears mid-reencryption
+    pub kind: String,
+    /// Byte offset of the payload on the device
+    pub offset: u64,
+    /// Payload length in bytes; `None` ("dynamic") runs to the end of the device
+    pub size: Option<u64>,
+    /// Added to the sector number for the IV
+    pub iv_tweak: u64,
+    pub encryption: String,
+    /// Encryption unit, 512 to 4096 bytes
+    pub sector_size: u32,
+}
+
+impl Segment {
+    /// Segment of a legacy header: plain64 IVs from `offset` to the end of the device
+    pub fn legacy(offset: u64) -> Self {
+        Self {
+            id: 0,
+            kind: String::from("crypt"),
+            offset,
+            size: None,
+            iv_tweak: 0,
+            encryption: String::from(CIPHER_AES_XTS_PLAIN64),
+            sector_size: SECTOR_SIZE as u32,
+        }
+    }
+}
+
+/// Volume key digest, shared by the keyslots and segments it lists
+#[derive(Debug, Clone)]
+pub struct Digest {
+    pub id: u32,
+    pub kind: String,
+    pub keyslots: Vec<u32>,
+    pub segments: Vec<u32>,
+    pub hash: String,
+    pub iterations: u32,
+    pub salt: Vec<u8>,
+    pub digest: Vec<u8>,
+}
+
+impl Digest {
+    /// Whether `key` is the volume key this digest was made from
+    fn verify(&self, key: &[u8]) -> Result<bool, LuksError> {
+        if self.kind != "pbkdf2" || self.hash != "sha256" {
+            return Err(LuksError::Unsupported);
+        }
+        let computed = pbkdf2_sha256(key, &self.salt, self.iterations, self.digest.len());
+        Ok(super::constant_time_eq(&computed, &self.digest))
+    }
+}
+
+/// One parsed header copy
+#[derive(Debug, Clone)]
+pub struct Luks2Header {
+    /// Size of each header copy, binary header plus JSON area
+    pub hdr_size: u64,
+    /// Bumped on every metadata update; the copy with the higher one wins
+    pub seqid: u64,
+    pub label: String,
+    pub uuid: String,
+    pub keyslots: Vec<Keyslot>,
+    pub segments: Vec<Segment>,
+    pub digests: Vec<Digest>,
+}
+
+impl Luks2Header {
+    /// Parse one header copy and check its checksum. `area` holds the
+    /// whole copy, `hdr_size` bytes.
+    pub fn parse(area: &[u8]) -> Result<Self, LuksError> {
+        if area.len() < BINARY_HEADER_SIZE
+            || (&area[..6] != LUKS_MAGIC && &area[..6] != LUKS2_MAGIC_SECONDARY)
+            || be16(area, OFF_VERSION) != 2
+        {
+            return Err(LuksError::InvalidHeader);
+        }
+        let hdr_size = be64(area, OFF_HDR_SIZE);
+        if hdr_size != area.len() as u64 {
+            return Err(LuksError::InvalidHeader);
+        }
+        if c_string(&area[OFF_CSUM_ALG..OFF_CSUM_ALG + 32]) != "sha256" {
+            return Err(LuksError::Unsupported);
+        }
+        if header_checksum(area) != area[OFF_CSUM..OFF_CSUM + 32] {
+            return Err(LuksError::InvalidHeader);
+        }
+
+        let json = Json::parse(&area[BINARY_HEADER_SIZE..]).ok_or(LuksError::InvalidHeader)?;
+        let mut header = Self {
+            hdr_size,
+            seqid: be64(area, OFF_SEQID),
+            label: c_string(&area[OFF_LABEL..OFF_LABEL + LABEL_LEN]),
+            uuid: c_string(&area[OFF_UUID..OFF_UUID + UUID_LEN]),
+            keyslots: Vec::new(),
+            segments: Vec::new(),
+            digests: Vec::new(),
+        };
+
+        for (id, slot) in field(&json, "keyslots")?.entries() {
+            // Reencryption keyslots carry no volume key
+            if field(slot, "type")?.str() != Some("luks2") {
+                continue;
+            }
+            header.keyslots.push(parse_keyslot(id, slot)?);
+        }
+        for (id, segment) in field(&json, "segments")?.entries() {
+            header.segments.push(parse_segment(id, segment)?);
+        }
+        for (id, digest) in field(&json, "digests")?.entries() {
+            header.digests.push(parse_digest(id, digest)?);
+        }
+        Ok(header)
+    }
+
+    /// The data segment. A device in the middle of reencryption has
+    /// several segments and cannot be opened here.
+    pub fn crypt_segment(&self) -> Result<&Segment, LuksError> {
+        match self.segments.as_slice() {
+            [segment] if segment.kind == "crypt" => Ok(segment),
+            _ => Err(LuksError::Unsupported),
+        }
+    }
+}
+
+fn parse_keyslot(id: &str, slot: &Json) -> Result<Keyslot, LuksError> {
+    let af = field(slot, "af")?;
+    let area = field(slot, "area")?;
+    let kdf = field(slot, "kdf")?;
+    if field(af, "type")?.str() != Some("luks1") || field(area, "type")?.str() != Some("raw") {
+        return Err(LuksError::Unsupported);
+    }
+
+    let kdf = match field(kdf, "type")?.str() {
+        Some("pbkdf2") => Kdf::Pbkdf2 {
+            hash: string(kdf, "hash")?,
+            iterations: number(kdf, "iterations")? as u32,
+            salt: base64(kdf, "salt")?,
+        },
+        Some(kind @ ("argon2i" | "argon2id")) => Kdf::Argon2 { kind: kind.to_string() },
+        _ => return Err(LuksError::Unsupported),
+    };
+
+    Ok(Keyslot {
+        id: parse_id(id)?,
+        key_size: number(slot, "key_size")? as usize,
+        priority: match slot.get("priority") {
+            Some(priority) => priority.u64().ok_or(LuksError::InvalidHeader)? as u32,
+            None => 1,
+        },
+        kdf,
+        af_stripes: number(af, "stripes")? as u32,
+        af_hash: string(af, "hash")?,
+        area_offset: number(area, "offset")?,
+        area_size: number(area, "size")?,
+        area_encryption: string(area, "encryption")?,
+        area_key_size: number(area, "key_size")? as usize,
+    })
+}
+
+fn parse_segment(id: &str, segment: &Json) -> Result<Segment, LuksError> {
+    let kind = string(segment, "type")?;
+    let size = match field(segment, "size")?.str() {
+        Some("dynamic") => None,
+        _ => Some(number(segment, "size")?),
+    };
+    // Linear segments have no cipher
+    let crypt = kind == "crypt";
+    Ok(Segment {
+        id: parse_id(id)?,
+        offset: number(segment, "offset")?,
+        size,
+        iv_tweak: if crypt { number(segment, "iv_tweak")? } else { 0 },
+        encryption: if crypt { string(segment, "encryption")? } else { String::new() },
+        sector_size: if crypt { number(segment, "sector_size")? as u32 } else { SECTOR_SIZE as u32 },
+        kind,
+    })
+}
+
+fn parse_digest(id: &str, digest: &Json) -> Result<Digest, LuksError> {
+    let ids = |key: &str| -> Result<Vec<u32>, LuksError> {
+        field(digest, key)?
+            .items()
+            .iter()
+            .map(|item| item.str().ok_or(LuksError::InvalidHeader).and_then(parse_id))
+            .collect()
+    };
+    let kind = string(digest, "type")?;
+    let pbkdf2 = kind == "pbkdf2";
+    Ok(Digest {
+        id: parse_id(id)?,
+        keyslots: ids("keyslots")?,
+        segments: ids("segments")?,
+        hash: if pbkdf2 { string(digest, "hash")? } else { String::new() },
+        iterations: if pbkdf2 { number(digest, "iterations")? as u32 } else { 0 },
+        salt: base64(digest, "salt")?,
+        digest: base64(digest, "digest")?,
+        kind,
+    })
+}
+
+fn field<'a>(object: &'a Json, key: &str) -> Result<&'a Json, LuksError> {
+    object.get(key).ok_or(LuksError::InvalidHeader)
+}
+
+fn string(object: &Json, key: &str) -> Result<String, LuksError> {
+    field(object, key)?.str().map(String::from).ok_or(LuksError::InvalidHeader)
+}
+
+fn number(object: &Json, key: &str) -> Result<u64, LuksError> {
+    field(object, key)?.u64().ok_or(LuksError::InvalidHeader)
+}
+
+fn base64(object: &Json, key: &str) -> Result<Vec<u8>, LuksError> {
+    field(object, key)?.str().and_then(base64_decode).ok_or(LuksError::InvalidHeader)
+}
+
+fn parse_id(id: &str) -> Result<u32, LuksError> {
+    id.parse().map_err(|_| LuksError::InvalidHeader)
+}
+
+// ============================================================================
+// Reading the header
+// ============================================================================
+
+/// Whether `data`, the start of a device, holds a LUKS2 primary header
+pub fn is_luks2(data: &[u8]) -> bool {
+    data.len() >= OFF_SEQID
+        && &data[..6] == LUKS_MAGIC
+        && be16(data, OFF_VERSION) == 2
+        && HEADER_SIZES.contains(&be64(data, OFF_HDR_SIZE))
+}
+
+/// Read the header of `dev`. Both copies are read and the valid one
+/// with the higher seqid wins, so a torn metadata update or a damaged
+/// primary still opens.
+pub fn read_header(dev: &dyn BlockDevice) -> Result<Luks2Header, LuksError> {
+    let primary = read_copy(dev, 0);
+    // The secondary copy follows the primary; without a readable primary
+    // its size is unknown, so probe every valid one
+    let secondary = match &primary {
+        Ok(header) => read_copy(dev, header.hdr_size).ok(),
+        Err(_) => HEADER_SIZES.iter().find_map(|&offset| read_copy(dev, offset).ok()),
+    };
+
+    match (primary, secondary) {
+        (Ok(primary), Some(secondary)) if secondary.seqid > primary.seqid => Ok(secondary),
+        (Ok(primary), _) => Ok(primary),
+        (Err(_), Some(secondary)) => Ok(secondary),
+        (Err(e), None) => Err(e),
+    }
+}
+
+fn read_copy(dev: &dyn BlockDevice, offset: u64) -> Result<Luks2Header, LuksError> {
+    let binary = read_bytes(dev, offset, BINARY_HEADER_SIZE)?;
+    let magic = if offset == 0 { LUKS_MAGIC } else { LUKS2_MAGIC_SECONDARY };
+    if &binary[..6] != magic || be64(&binary, OFF_HDR_OFFSET) != offset {
+        return Err(LuksError::InvalidHeader);
+    }
+    let hdr_size = be64(&binary, OFF_HDR_SIZE);
+    if !HEADER_SIZES.contains(&hdr_size) {
+        return Err(LuksError::InvalidHeader);
+    }
+    Luks2Header::parse(&read_bytes(dev, offset, hdr_size as usize)?)
+}
+
+/// SHA-256 of a header copy with its checksum field zeroed
+fn header_checksum(area: &[u8]) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update(&area[..OFF_CSUM]);
+    hasher.update(&[0u8; 64]);
+    hasher.update(&area[OFF_CSUM + 64..]);
+    hasher.finalize()
+}
+
+// ============================================================================
+// Unlocking
+// ============================================================================
+
+/// Recover the volume key with `passphrase`, trying keyslots by priority
+pub fn unlock(dev: &dyn BlockDevice, header: &Luks2Header, passphrase: &[u8]) -> Result<Vec<u8>, LuksError> {
+    let mut slots: Vec<&Keyslot> = header.keyslots.iter().filter(|k| k.priority > 0).collect();
+    if slots.is_empty() {
+        return Err(LuksError::SlotDisabled);
+    }
+    slots.sort_by_key(|k| core::cmp::Reverse(k.priority));
+
+    let mut tried = false;
+    for slot in slots {
+        let Some(digest) = header.digests.iter().find(|d| d.keyslots.contains(&slot.id)) else {
+            continue;
+        };
+        let mut key = match open_keyslot(dev, slot, passphrase) {
+            Ok(key) => key,
+            Err(LuksError::Unsupported) => continue,
+            Err(e) => return Err(e),
+        };
+        tried = true;
+        if digest.verify(&key)? {
+            return Ok(key);
+        }
+        wipe(&mut key);
+    }
+
+    Err(if tried { LuksError::InvalidPassword } else { LuksError::Unsupported })
+}
+
+/// Decrypt a keyslot's key material and merge the stripes. Without the
+/// right passphrase this yields garbage, which the digest rejects.
+fn open_keyslot(dev: &dyn BlockDevice, slot: &Keyslot, passphrase: &[u8]) -> Result<Vec<u8>, LuksError> {
+    let Kdf::Pbkdf2 { hash, iterations, salt } = &slot.kdf else {
+        return Err(LuksError::Unsupported);
+    };
+    if hash != "sha256" || slot.af_hash != "sha256" || slot.area_encryption != CIPHER_AES_XTS_PLAIN64 {
+        return Err(LuksError::Unsupported);
+    }
+
+    let material_len = slot.key_size * slot.af_stripes as usize;
+    let area_len = material_len.div_ceil(SECTOR_SIZE) * SECTOR_SIZE;
+    if slot.af_stripes == 0 || area_len as u64 > slot.area_size {
+        return Err(LuksError::InvalidHeader);
+    }
+
+    let mut derived = pbkdf2_sha256(passphrase, salt, *iterations, slot.area_key_size);
+    let xts = XtsKey::new(&derived).ok_or(LuksError::Unsupported);
+    wipe(&mut derived);
+    let xts = xts?;
+
+    let mut material = read_bytes(dev, slot.area_offset, area_len)?;
+    for (i, sector) in material.chunks_exact_mut(SECTOR_SIZE).enumerate() {
+        xts.decrypt_sector(sector, i as u64);
+    }
+    let key = af_merge(&material[..material_len], slot.key_size, slot.af_stripes);
+    wipe(&mut material);
+    Ok(key)
+}
+
+// ============================================================================
+// Anti-forensic splitter
+// ============================================================================
+
+/// Hash each 32-byte chunk of `buf` together with its index
+fn diffuse(buf: &mut [u8]) {
+    for (i, chunk) in buf.chunks_mut(32).enumerate() {
+        let mut hasher = Sha256::new();
+        hasher.update(&(i as u32).to_be_bytes());
+        hasher.update(chunk);
+        let digest = hasher.finalize();
+        let len = chunk.len();
+        chunk.copy_from_slice(&digest[..len]);
+    }
+}
+
+/// Recover a key from `stripes` stripes of `key_size` bytes
+pub fn af_merge(material: &[u8], key_size: usize, stripes: u32) -> Vec<u8> {
+    let mut key = vec![0u8; key_size];
+    let last = stripes as usize - 1;
+    for (i, stripe) in material.chunks_exact(key_size).take(stripes as usize).enumerate() {
+        for (k, s) in key.iter_mut().zip(stripe) {
+            *k ^= s;
+        }
+        if i < last {
+            diffuse(&mut key);
+        }
+    }
+    key
+}
+
+/// Split `key` into `stripes` stripes; all of them are needed to merge it back
+pub fn af_split(key: &[u8], stripes: u32) -> Vec<u8> {
+    let len = key.len();
+    let mut material = vec![0u8; len * stripes as usize];
+    let (random, last) = material.split_at_mut(len * (stripes as usize - 1));
+    super::random::fill_random(random);
+
+    let mut acc = vec![0u8; len];
+    for stripe in random.chunks_exact(len) {
+        for (a, s) in acc.iter_mut().zip(stripe) {
+            *a ^= s;
+        }
+        diffuse(&mut acc);
+    }
+    for ((out, a), k) in last.iter_mut().zip(&acc).zip(key) {
+        *out = a ^ k;
+    }
+    wipe(&mut acc);
+    material
+}
+
+// ============================================================================
+// Formatting
+// ============================================================================
+
+/// Parameters for `format`
+#[derive(Debug, Clone)]
+pub struct FormatParams {
+    pub label: String,
+    /// PBKDF2 iterations for the keyslot and the digest
+    pub iterations: u32,
+    pub stripes: u32,
+    /// Encryption unit of the data segment
+    pub sector_size: u32,
+}
+
+impl Default for FormatParams {
+    fn default() -> Self {
+        Self {
+            label: String::new(),
+            iterations: DEFAULT_PBKDF2_ITERATIONS,
+            stripes: DEFAULT_AF_STRIPES,
+            sector_size: SECTOR_SIZE as u32,
+        }
+    }
+}
+
+/// Write a LUKS2 header with a fresh random volume key (AES-256-XTS) and a
+/// single passphrase keyslot. The data segment is not touched; whatever
+/// was on the device reads back as noise.
+pub fn format(dev: &dyn BlockDevice, passphrase: &[u8], params: &FormatParams) -> Result<Luks2Header, LuksError> {
+    let sector_size = params.sector_size as u64;
+    if !(512..=4096).contains(&sector_size)
+        || !sector_size.is_power_of_two()
+        || sector_size % dev.block_size() as u64 != 0
+        || params.stripes == 0
+    {
+        return Err(LuksError::Unsupported);
+    }
+
+    let key_size = KEY_SIZE_512;
+    let area_offset = 2 * FORMAT_HEADER_SIZE;
+    let area_size = ((key_size as u64 * params.stripes as u64).div_ceil(AREA_ALIGNMENT)) * AREA_ALIGNMENT;
+    let data_offset = (area_offset + area_size).div_ceil(DATA_ALIGNMENT) * DATA_ALIGNMENT;
+    let device_size = dev.num_blocks() * dev.block_size() as u64;
+    if device_size < data_offset + sector_size {
+        return Err(LuksError::DeviceTooSmall);
+    }
+
+    let mut volume_key = [0u8; KEY_SIZE_512];
+    super::random::fill_random(&mut volume_key);
+    let mut slot_salt = [0u8; 32];
+    super::random::fill_random(&mut slot_salt);
+    let mut digest_salt = [0u8; 32];
+    super::random::fill_random(&mut digest_salt);
+
+    // Keyslot 0: split volume key encrypted under the passphrase key
+    let mut material = af_split(&volume_key, params.stripes);
+    material.resize(material.len().div_ceil(SECTOR_SIZE) * SECTOR_SIZE, 0);
+    let mut derived = pbkdf2_sha256(passphrase, &slot_salt, params.iterations, key_size);
+    let xts = XtsKey::new(&derived).ok_or(LuksError::Unsupported)?;
+    wipe(&mut derived);
+    for (i, sector) in material.chunks_exact_mut(SECTOR_SIZE).enumerate() {
+        xts.encrypt_sector(sector, i as u64);
+    }
+
+    let digest = pbkdf2_sha256(&volume_key, &digest_salt, params.iterations, 32);
+    wipe(&mut volume_key);
+
+    let json_size = FORMAT_HEADER_SIZE - BINARY_HEADER_SIZE as u64;
+    let json = format!(
+        concat!(
+            "{{\"keyslots\":{{\"0\":{{\"type\":\"luks2\",\"key_size\":{key_size},",
+            "\"af\":{{\"type\":\"luks1\",\"stripes\":{stripes},\"hash\":\"sha256\"}},",
+            "\"area\":{{\"type\":\"raw\",\"offset\":\"{area_offset}\",\"size\":\"{area_size}\",",
+            "\"encryption\":\"{cipher}\",\"key_size\":{key_size}}},",
+            "\"kdf\":{{\"type\":\"pbkdf2\",\"hash\":\"sha256\",\"iterations\":{iterations},",
+            "\"salt\":\"{slot_salt}\"}}}}}},",
+            "\"tokens\":{{}},",
+            "\"segments\":{{\"0\":{{\"type\":\"crypt\",\"offset\":\"{data_offset}\",\"size\":\"dynamic\",",
+            "\"iv_tweak\":\"0\",\"encryption\":\"{cipher}\",\"sector_size\":{sector_size}}}}},",
+            "\"digests\":{{\"0\":{{\"type\":\"pbkdf2\",\"keyslots\":[\"0\"],\"segments\":[\"0\"],",
+            "\"hash\":\"sha256\",\"iterations\":{iterations},\"salt\":\"{digest_salt}\",",
+            "\"digest\":\"{digest}\"}}}},",
+            "\"config\":{{\"json_size\":\"{json_size}\",\"keyslots_size\":\"{area_size}\"}}}}",
+        ),
+        key_size = key_size,
+        stripes = params.stripes,
+        area_offset = area_offset,
+        area_size = area_size,
+        cipher = CIPHER_AES_XTS_PLAIN64,
+        iterations = params.iterations,
+        slot_salt = base64_encode(&slot_salt),
+        data_offset = data_offset,
+        sector_size = sector_size,
+        digest_salt = base64_encode(&digest_salt),
+        digest = base64_encode(&digest),
+        json_size = json_size,
+    );
+
+    let uuid = generate_uuid();
+    let primary = build_copy(&json, 0, &params.label, &uuid);
+    let secondary = build_copy(&json, FORMAT_HEADER_SIZE, &params.label, &uuid);
+
+    // Primary last, so an interrupted format leaves no openable header
+    write_bytes(dev, area_offset, &material)?;
+    write_bytes(dev, FORMAT_HEADER_SIZE, &secondary)?;
+    write_bytes(dev, 0, &primary)?;
+    Luks2Header::parse(&primary)
+}
+
+/// Binary header plus JSON area of one header copy
+fn build_copy(json: &str, offset: u64, label: &str, uuid: &str) -> Vec<u8> {
+    let mut area = vec![0u8; FORMAT_HEADER_SIZE as usize];
+    area[..6].copy_from_slice(if offset == 0 { LUKS_MAGIC } else { LUKS2_MAGIC_SECONDARY });
+    area[OFF_VERSION..OFF_VERSION + 2].copy_from_slice(&2u16.to_be_bytes());
+    area[OFF_HDR_SIZE..OFF_HDR_SIZE + 8].copy_from_slice(&FORMAT_HEADER_SIZE.to_be_bytes());
+    area[OFF_SEQID..OFF_SEQID + 8].copy_from_slice(&1u64.to_be_bytes());
+    let label = &label.as_bytes()[..label.len().min(LABEL_LEN - 1)];
+    area[OFF_LABEL..OFF_LABEL + label.len()].copy_from_slice(label);
+    area[OFF_CSUM_ALG..OFF_CSUM_ALG + 6].copy_from_slice(b"sha256");
+    super::random::fill_random(&mut area[OFF_SALT..OFF_SALT + 64]);
+    area[OFF_UUID..OFF_UUID + uuid.len()].copy_from_slice(uuid.as_bytes());
+    area[OFF_HDR_OFFSET..OFF_HDR_OFFSET + 8].copy_from_slice(&offset.to_be_bytes());
+    area[BINARY_HEADER_SIZE..BINARY_HEADER_SIZE + json.len()].copy_from_slice(json.as_bytes());
+
+    let checksum = header_checksum(&area);
+    area[OFF_CSUM..OFF_CSUM + 32].copy_from_slice(&checksum);
+    area
+}
+
+// ============================================================================
+// Device I/O
+// ============================================================================
+
+/// Read `len` bytes at byte `offset`, whatever the device's block size
+pub fn read_bytes(dev: &dyn BlockDevice, offset: u64, len: usize) -> Result<Vec<u8>, LuksError> {
+    let (first, count) = covering_blocks(dev, offset, len)?;
+    let block_size = dev.block_size() as u64;
+    let mut buf = vec![0u8; (count as u64 * block_size) as usize];
+    dev.read_blocks(first, count, &mut buf).map_err(|_| LuksError::IoError)?;
+    let skip = (offset - first * block_size) as usize;
+    buf.drain(..skip);
+    buf.truncate(len);
+    Ok(buf)
+}
+
+/// Write `data` at byte `offset`, merging with the blocks around it
+pub fn write_bytes(dev: &dyn BlockDevice, offset: u64, data: &[u8]) -> Result<(), LuksError> {
+    let (first, count) = covering_blocks(dev, offset, data.len())?;
+    let block_size = dev.block_size() as u64;
+    let mut buf = vec![0u8; (count as u64 * block_size) as usize];
+    let skip = (offset - first * block_size) as usize;
+    if skip != 0 || data.len() != buf.len() {
+        dev.read_blocks(first, count, &mut buf).map_err(|_| LuksError::IoError)?;
+    }
+    buf[skip..skip + data.len()].copy_from_slice(data);
+    dev.write_blocks(first, count, &buf).map_err(|_| LuksError::IoError)
+}
+
+fn covering_blocks(dev: &dyn BlockDevice, offset: u64, len: usize) -> Result<(u64, u32), LuksError> {
+    let block_size = dev.block_size() as u64;
+    let end = offset.checked_add(len as u64).ok_or(LuksError::IoError)?;
+    let first = offset / block_size;
+    let last = end.div_ceil(block_size);
+    if last > dev.num_blocks() {
+        return Err(LuksError::IoError);
+    }
+    let count = u32::try_from(last - first).map_err(|_| LuksError::IoError)?;
+    Ok((first, count))
+}
+
+// ============================================================================
+// Helpers
+// ============================================================================
+
+fn be16(data: &[u8], offset: usize) -> u16 {
+    u16::from_be_bytes([data[offset], data[offset + 1]])
+}
+
+fn be64(data: &[u8], offset: usize) -> u64 {
+    u64::from_be_bytes(data[offset..offset + 8].try_into().unwrap())
+}
+
+/// NUL-terminated string field
+fn c_string(field: &[u8]) -> String {
+    let end = field.iter().position(|&b| b == 0).unwrap_or(field.len());
+    String::from_utf8_lossy(&field[..end]).into_owned()
+}
+
+const BASE64_ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+fn base64_encode(data: &[u8]) -> String {
+    let mut out = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let n = (chunk[0] as u32) << 16
+            | (*chunk.get(1).unwrap_or(&0) as u32) << 8
+            | *chunk.get(2).unwrap_or(&0) as u32;
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(BASE64_ALPHABET[(n >> (18 - 6 * i)) as usize & 0x3F] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+fn base64_decode(input: &str) -> Option<Vec<u8>> {
+    let mut output = Vec::with_capacity(input.len() * 3 / 4);
+    let mut buffer = 0u32;
+    let mut bits = 0u8;
+
+    for c in input.bytes() {
+        let val = match c {
+            b'A'..=b'Z' => c - b'A',
+            b'a'..=b'z' => c - b'a' + 26,
+            b'0'..=b'9' => c - b'0' + 52,
+            b'+' => 62,
+            b'/' => 63,
+            b'=' => break,
+            _ => return None,
+        };
+
+        buffer = (buffer << 6) | val as u32;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            output.push((buffer >> bits) as u8);
+        }
+    }
+
+    Some(output)
+}
+
+// ============================================================================
+// JSON metadata
+// ============================================================================
+
+/// Just enough JSON for the metadata area
+#[derive(Debug, Clone)]
+enum Json {
+    Null,
+    Bool(bool),
+    /// Kept as written; LUKS2 only uses non-negative integers
+    Number(String),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+/// Nesting limit, far above what cryptsetup writes
+const JSON_MAX_DEPTH: usize = 32;
+
+impl Json {
+    /// Parse the metadata area: one object, then NUL padding
+    fn parse(area: &[u8]) -> Option<Json> {
+        let end = area.iter().position(|&b| b == 0).unwrap_or(area.len());
+        let mut parser = JsonParser { text: &area[..end], pos: 0, depth: 0 };
+        let value = parser.value()?;
+        parser.skip_ws();
+        (parser.pos == end && matches!(value, Json::Object(_))).then_some(value)
+    }
+
+    fn get(&self, key: &str) -> Option<&Json> {
+        self.entries().iter().find(|(k, _)| k == key).map(|(_, v)| v)
+    }
+
+    fn entries(&self) -> &[(String, Json)] {
+        match self {
+            Json::Object(entries) => entries,
+            _ => &[],
+        }
+    }
+
+    fn items(&self) -> &[Json] {
+        match self {
+            Json::Array(items) => items,
+            _ => &[],
+        }
+    }
+
+    fn str(&self) -> Option<&str> {
+        match self {
+            Json::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    /// 64-bit values are written as strings, smaller ones as numbers
+    fn u64(&self) -> Option<u64> {
+        match self {
+            Json::Number(s) | Json::String(s) => s.parse().ok(),
+            _ => None,
+        }
+    }
+}
+
+struct JsonParser<'a> {
+    text: &'a [u8],
+    pos: usize,
+    depth: usize,
+}
+
+impl JsonParser<'_> {
+    fn peek(&self) -> Option<u8> {
+        self.text.get(self.pos).copied()
+    }
+
+    fn skip_ws(&mut self) {
+        while matches!(self.peek(), Some(b' ' | b'\t' | b'\n' | b'\r')) {
+            self.pos += 1;
+        }
+    }
+
+    fn eat(&mut self, byte: u8) -> bool {
+        self.skip_ws();
+        if self.peek() == Some(byte) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn value(&mut self) -> Option<Json> {
+        self.skip_ws();
+        match self.peek()? {
+            b'{' => self.object(),
+            b'[' => self.array(),
+            b'"' => self.string().map(Json::String),
+            b't' => self.literal(b"true", Json::Bool(true)),
+            b'f' => self.literal(b"false", Json::Bool(false)),
+            b'n' => self.literal(b"null", Json::Null),
+            b'-' | b'0'..=b'9' => self.number(),
+            _ => None,
+        }
+    }
+
+    fn literal(&mut self, word: &[u8], value: Json) -> Option<Json> {
+        if !self.text[self.pos..].starts_with(word) {
+            return None;
+        }
+        self.pos += word.len();
+        Some(value)
+    }
+
+    fn number(&mut self) -> Option<Json> {
+        let start = self.pos;
+        while matches!(self.peek(), Some(b'-' | b'+' | b'.' | b'e' | b'E' | b'0'..=b'9')) {
+            self.pos += 1;
+        }
+        let text = core::str::from_utf8(&self.text[start..self.pos]).ok()?;
+        Some(Json::Number(text.to_string()))
+    }
+
+    fn enter(&mut self) -> Option<()> {
+        self.pos += 1;
+        self.depth += 1;
+        (self.depth <= JSON_MAX_DEPTH).then_some(())
+    }
+
+    fn object(&mut self) -> Option<Json> {
+        self.enter()?;
+        let mut entries = Vec::new();
+        if !self.eat(b'}') {
+            loop {
+                self.skip_ws();
+                let key = self.string()?;
+                if !self.eat(b':') {
+                    return None;
+                }
+                entries.push((key, self.value()?));
+                if self.eat(b'}') {
+                    break;
+                }
+                if !self.eat(b',') {
+                    return None;
+                }
+            }
+        }
+        self.depth -= 1;
+        Some(Json::Object(entries))
+    }
+
+    fn array(&mut self) -> Option<Json> {
+        self.enter()?;
+        let mut items = Vec::new();
+        if !self.eat(b']') {
+            loop {
+                items.push(self.value()?);
+                if self.eat(b']') {
+                    break;
+                }
+                if !self.eat(b',') {
+                    return None;
+                }
+            }
+        }
+        self.depth -= 1;
+        Some(Json::Array(items))
+    }
+
+    fn string(&mut self) -> Option<String> {
+        if self.peek() != Some(b'"') {
+            return None;
+        }
+        self.pos += 1;
+        let mut out = String::new();
+        loop {
+            let start = self.pos;
+            while !matches!(self.peek()?, b'"' | b'\\') {
+                self.pos += 1;
+            }
+            out.push_str(core::str::from_utf8(&self.text[start..self.pos]).ok()?);
+            let quote = self.peek()? == b'"';
+            self.pos += 1;
+            if quote {
+                return Some(out);
+            }
+
+            let escape = self.peek()?;
+            self.pos += 1;
+            out.push(match escape {
+                b'"' => '"',
+                b'\\' => '\\',
+                b'/' => '/',
+                b'b' => '\u{8}',
+                b'f' => '\u{c}',
+                b'n' => '\n',
+                b'r' => '\r',
+                b't' => '\t',
+                b'u' => {
+                    let high = self.hex4()?;
+                    if (0xD800..0xDC00).contains(&high) {
+                        if !self.text[self.pos..].starts_with(b"\\u") {
+                            return None;
+                        }
+                        self.pos += 2;
+                        let low = self.hex4()?;
+                        if !(0xDC00..0xE000).contains(&low) {
+                            return None;
+                        }
+                        char::from_u32(0x10000 + ((high - 0xD800) << 10) + (low - 0xDC00))?
+                    } else {
+                        char::from_u32(high)?
+                    }
+                }
+                _ => return None,
+            });
+        }
+    }
+
+    fn hex4(&mut self) -> Option<u32> {
+        let digits = self.text.get(self.pos..self.pos + 4)?;
+        if !digits.iter().all(u8::is_ascii_hexdigit) {
+            return None;
+        }
+        self.pos += 4;
+        u32::from_str_radix(core::str::from_utf8(digits).ok()?, 16).ok()
+    }
+}
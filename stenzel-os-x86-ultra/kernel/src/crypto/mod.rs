@@ -6,6 +6,8 @@
 //! - X25519 key exchange
 //! - Ed25519 digital signatures
 //! - RSA encryption and signatures
+//! - X.509 certificate validation and the system trust store
+//! - LUKS1-style and LUKS2 disk encryption headers
 
 #![allow(dead_code)]
 
@@ -17,19 +19,19 @@ pub mod ed25519;
 pub mod rsa;
 pub mod random;
 pub mod luks;
+pub mod luks2;
+pub mod x509;
 
 // Re-export commonly used items
 pub use sha256::{sha256, hmac_sha256, hkdf_extract, hkdf_expand, Sha256, Sha256Digest};
 pub use chacha20::{chacha20_encrypt, chacha20_poly1305_encrypt, chacha20_poly1305_decrypt};
 pub use x25519::{x25519, x25519_public_key, x25519_diffie_hellman};
-pub use ed25519::{Keypair as Ed25519Keypair, sign as ed25519_sign, verify as ed25519_verify, public_key_from_secret as ed25519_public_key};
+pub use ed25519::Keypair as Ed25519Keypair;
 pub use rsa::{RsaPublicKey, RsaPrivateKey, generate_keypair as rsa_generate_keypair};
 
-/// Generate random bytes using the kernel's PRNG
+/// Generate random bytes using the kernel's CSPRNG
 pub fn random_bytes(out: &mut [u8]) {
-    for byte in out.iter_mut() {
-        *byte = crate::fs::devfs::random_byte();
-    }
+    random::fill_random(out);
 }
 
 /// Generate a random 32-byte key
@@ -54,10 +56,10 @@ pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
 
 /// Get a single random byte
 pub fn random_byte() -> u8 {
-    crate::fs::devfs::random_byte()
+    random::get_random_u8()
 }
 
 /// Initialize the crypto subsystem
 pub fn init() {
-    crate::kprintln!("crypto: SHA-256, ChaCha20-Poly1305, X25519, Ed25519, RSA available");
+    crate::kprintln!("crypto: SHA-256, ChaCha20-Poly1305, X25519, Ed25519, RSA, X.509 available");
 }
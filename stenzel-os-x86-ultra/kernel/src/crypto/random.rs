@@ -1,43 +1,322 @@
 //! Cryptographic Random Number Generation
 //!
-//! Provides access to the kernel's random number generator for
-//! cryptographic purposes.
+//! Kernel CSPRNG. An entropy pool (a running SHA-256 state) collects input
+//! from the CPU's hardware RNG (RDSEED/RDRAND), interrupt timing jitter and
+//! the TPM. Output comes from a ChaCha20 generator keyed from the pool:
+//!
+//! - the key is replaced after every request (fast key erasure), so a
+//!   compromised key does not reveal earlier output;
+//! - the generator is reseeded from the pool at most every
+//!   `RESEED_INTERVAL_SECS`, and as soon as the pool first holds
+//!   `SEED_BITS` of entropy.
+//!
+//! Until that first seeding the generator still produces output, but
+//! `is_seeded()` is false; `/dev/random` and `getrandom(2)` wait for it.
 
 #![allow(dead_code)]
 
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+use super::chacha20::chacha20_block;
+use super::sha256::Sha256;
+use crate::sync::IrqSafeMutex;
+
+/// Entropy bits the pool must hold before the generator counts as seeded
+const SEED_BITS: u32 = 256;
+
+/// Minimum time between two reseeds from the pool
+const RESEED_INTERVAL_SECS: u64 = 60;
+
+/// Interrupts folded into the pool at once. Each batch is credited one bit.
+const FAST_POOL_BATCH: u32 = 64;
+
+/// Output generated per key before a fresh key is drawn
+const CHUNK_BYTES: usize = 64 * 1024;
+
+struct Pool {
+    hasher: Sha256,
+    /// Entropy credited since the last reseed
+    bits: u32,
+}
+
+impl Pool {
+    fn mix(&mut self, data: &[u8], bits: u32) {
+        self.hasher.update(data);
+        self.bits = self.bits.saturating_add(bits);
+    }
+}
+
+struct Crng {
+    key: [u8; 32],
+    /// Nonce of the next request; never reused with the same key
+    generation: u64,
+    /// Uptime of the last reseed, in seconds
+    last_reseed: u64,
+    /// Whether the key was ever derived from the pool
+    keyed: bool,
+}
+
+/// Interrupt timing samples, mixed cheaply before they reach the pool
+struct FastPool {
+    state: [u64; 4],
+    count: u32,
+}
+
+static POOL: IrqSafeMutex<Pool> = IrqSafeMutex::new(Pool {
+    hasher: Sha256::new(),
+    bits: 0,
+});
+
+static CRNG: IrqSafeMutex<Crng> = IrqSafeMutex::new(Crng {
+    key: [0; 32],
+    generation: 0,
+    last_reseed: 0,
+    keyed: false,
+});
+
+static FAST_POOL: IrqSafeMutex<FastPool> = IrqSafeMutex::new(FastPool {
+    state: [0x6a09e667f3bcc908, 0xbb67ae8584caa73b, 0x3c6ef372fe94f82b, 0xa54ff53a5f1d36f1],
+    count: 0,
+});
+
+static SEEDED: AtomicBool = AtomicBool::new(false);
+static HAS_RDRAND: AtomicBool = AtomicBool::new(false);
+static HAS_RDSEED: AtomicBool = AtomicBool::new(false);
+/// Last tick credited by `wait_until_seeded`
+static LAST_JITTER_TICK: AtomicU64 = AtomicU64::new(0);
+
+fn rdtsc() -> u64 {
+    unsafe { core::arch::x86_64::_rdtsc() }
+}
+
+#[target_feature(enable = "rdseed")]
+unsafe fn rdseed64() -> Option<u64> {
+    let mut v = 0u64;
+    for _ in 0..16 {
+        if core::arch::x86_64::_rdseed64_step(&mut v) == 1 {
+            return Some(v);
+        }
+        core::hint::spin_loop();
+    }
+    None
+}
+
+#[target_feature(enable = "rdrand")]
+unsafe fn rdrand64() -> Option<u64> {
+    let mut v = 0u64;
+    for _ in 0..10 {
+        if core::arch::x86_64::_rdrand64_step(&mut v) == 1 {
+            return Some(v);
+        }
+    }
+    None
+}
+
+/// One word from the CPU's RNG: RDSEED when available, RDRAND otherwise
+fn hw_random_u64() -> Option<u64> {
+    if HAS_RDSEED.load(Ordering::Relaxed) {
+        if let Some(v) = unsafe { rdseed64() } {
+            return Some(v);
+        }
+    }
+    if HAS_RDRAND.load(Ordering::Relaxed) {
+        return unsafe { rdrand64() };
+    }
+    None
+}
+
+/// Feeds 256 bits from the hardware RNG, or the TPM when the CPU has none,
+/// into the pool. Both are trusted and credited in full. Must be called
+/// without the generator locks held: TPM commands wait on the timer.
+fn gather_hw_entropy() {
+    let mut words = 0u32;
+    for _ in 0..4 {
+        if let Some(v) = hw_random_u64() {
+            add_entropy(&v.to_le_bytes(), 64);
+            words += 1;
+        }
+    }
+    if words < 4 && crate::security::tpm::is_available() {
+        if let Ok(bytes) = crate::security::tpm::get_random(32) {
+            add_entropy(&bytes, bytes.len() as u32 * 8);
+        }
+    }
+}
+
+/// Replaces the generator key with a hash of the key and the pool.
+fn reseed(crng: &mut Crng, pool: &mut Pool) {
+    let digest = core::mem::replace(&mut pool.hasher, Sha256::new()).finalize();
+    // The next pool state starts from this one, so no input is ever dropped
+    pool.hasher.update(&digest);
+
+    let mut h = Sha256::new();
+    h.update(&crng.key);
+    h.update(&digest);
+    h.update(&rdtsc().to_le_bytes());
+    crng.key = h.finalize();
+    crng.last_reseed = crate::time::uptime_secs();
+    crng.keyed = true;
+
+    if pool.bits >= SEED_BITS && !SEEDED.swap(true, Ordering::AcqRel) {
+        crate::kprintln!("random: crng seeded");
+    }
+    pool.bits = 0;
+}
+
+fn reseed_due(crng: &Crng) -> bool {
+    !crng.keyed
+        || crate::time::uptime_secs().saturating_sub(crng.last_reseed) >= RESEED_INTERVAL_SECS
+}
+
+fn maybe_reseed(crng: &mut Crng) {
+    let mut pool = POOL.lock();
+    if reseed_due(crng) || (!is_seeded() && pool.bits >= SEED_BITS) {
+        reseed(crng, &mut pool);
+    }
+}
+
+/// Detects the hardware RNG and seeds the generator.
+pub fn init() {
+    let (_, _, _, _, _, features) = crate::arch::x86_64_arch::smp::detect_cpu_features();
+    HAS_RDRAND.store(features.rdrand, Ordering::Relaxed);
+    HAS_RDSEED.store(features.rdseed, Ordering::Relaxed);
+
+    add_entropy(&rdtsc().to_le_bytes(), 0);
+    gather_hw_entropy();
+    {
+        let mut crng = CRNG.lock();
+        let mut pool = POOL.lock();
+        reseed(&mut crng, &mut pool);
+    }
+
+    crate::kprintln!(
+        "random: rdseed={} rdrand={} tpm={} seeded={}",
+        features.rdseed,
+        features.rdrand,
+        crate::security::tpm::is_available(),
+        is_seeded()
+    );
+}
+
+/// Whether the generator has been seeded with enough entropy
+pub fn is_seeded() -> bool {
+    SEEDED.load(Ordering::Acquire)
+}
+
+/// Mixes `data` into the pool, crediting `bits` of entropy.
+pub fn add_entropy(data: &[u8], bits: u32) {
+    POOL.lock().mix(data, bits);
+}
+
+/// Mixes in data that is unique to the machine but not secret (MAC
+/// addresses, serial numbers). Credits no entropy.
+pub fn add_device_randomness(data: &[u8]) {
+    add_entropy(data, 0);
+}
+
+/// Records the timing of an interrupt. Called from the interrupt dispatcher.
+pub fn add_interrupt_randomness(vector: u8) {
+    let Some(mut fast) = FAST_POOL.try_lock() else {
+        return;
+    };
+
+    let cycles = rdtsc();
+    let s = &mut fast.state;
+    s[0] = s[0].wrapping_add(cycles ^ ((vector as u64) << 56));
+    s[1] ^= s[0].rotate_left(13);
+    s[2] = s[2].wrapping_add(s[1]).rotate_left(29);
+    s[3] ^= s[2].wrapping_add(cycles.rotate_left(32));
+    s[0] = s[0].rotate_left(17) ^ s[3];
+    fast.count += 1;
+
+    if fast.count >= FAST_POOL_BATCH {
+        if let Some(mut pool) = POOL.try_lock() {
+            let mut bytes = [0u8; 32];
+            for (i, w) in fast.state.iter().enumerate() {
+                bytes[i * 8..(i + 1) * 8].copy_from_slice(&w.to_le_bytes());
+            }
+            pool.mix(&bytes, 1);
+            fast.count = 0;
+        }
+    }
+}
+
+/// Blocks the caller until the generator is seeded.
+///
+/// While waiting, the timing of each scheduling round is mixed into the pool
+/// and credited one bit per timer tick, like Linux's jitter entropy.
+pub fn wait_until_seeded() {
+    while !is_seeded() {
+        let tick = crate::time::ticks();
+        let credit = if LAST_JITTER_TICK.swap(tick, Ordering::Relaxed) != tick { 1 } else { 0 };
+        add_entropy(&rdtsc().to_le_bytes(), credit);
+
+        {
+            let mut crng = CRNG.lock();
+            maybe_reseed(&mut crng);
+        }
+        if is_seeded() {
+            break;
+        }
+        crate::task::yield_now();
+    }
+}
+
+/// Fill a buffer with random bytes
+pub fn fill_random(buf: &mut [u8]) {
+    for chunk in buf.chunks_mut(CHUNK_BYTES) {
+        if reseed_due(&CRNG.lock()) {
+            gather_hw_entropy();
+        }
+
+        let (key, nonce) = {
+            let mut crng = CRNG.lock();
+            maybe_reseed(&mut crng);
+
+            let mut nonce = [0u8; 12];
+            nonce[..8].copy_from_slice(&crng.generation.to_le_bytes());
+            crng.generation = crng.generation.wrapping_add(1);
+
+            // Block 0 becomes the next key; output starts at block 1
+            let key = crng.key;
+            let next = chacha20_block(&key, 0, &nonce);
+            crng.key.copy_from_slice(&next[..32]);
+            (key, nonce)
+        };
+
+        for (i, out) in chunk.chunks_mut(64).enumerate() {
+            let block = chacha20_block(&key, i as u32 + 1, &nonce);
+            out.copy_from_slice(&block[..out.len()]);
+        }
+    }
+}
+
 /// Get a single random byte
 pub fn get_random_u8() -> u8 {
-    crate::fs::devfs::random_byte()
+    let mut b = [0u8; 1];
+    fill_random(&mut b);
+    b[0]
 }
 
 /// Get a random u16
 pub fn get_random_u16() -> u16 {
-    let b0 = get_random_u8() as u16;
-    let b1 = get_random_u8() as u16;
-    b0 | (b1 << 8)
+    let mut b = [0u8; 2];
+    fill_random(&mut b);
+    u16::from_le_bytes(b)
 }
 
 /// Get a random u32
 pub fn get_random_u32() -> u32 {
-    let b0 = get_random_u8() as u32;
-    let b1 = get_random_u8() as u32;
-    let b2 = get_random_u8() as u32;
-    let b3 = get_random_u8() as u32;
-    b0 | (b1 << 8) | (b2 << 16) | (b3 << 24)
+    let mut b = [0u8; 4];
+    fill_random(&mut b);
+    u32::from_le_bytes(b)
 }
 
 /// Get a random u64
 pub fn get_random_u64() -> u64 {
-    let low = get_random_u32() as u64;
-    let high = get_random_u32() as u64;
-    low | (high << 32)
-}
-
-/// Fill a buffer with random bytes
-pub fn fill_random(buf: &mut [u8]) {
-    for byte in buf.iter_mut() {
-        *byte = get_random_u8();
-    }
+    let mut b = [0u8; 8];
+    fill_random(&mut b);
+    u64::from_le_bytes(b)
 }
 
 /// Generate a random 16-byte array
@@ -66,5 +345,12 @@ pub fn random_range(max: u32) -> u32 {
     if max == 0 {
         return 0;
     }
-    get_random_u32() % max
+    // Rejection sampling avoids the modulo bias
+    let limit = u32::MAX - u32::MAX % max;
+    loop {
+        let v = get_random_u32();
+        if v < limit {
+            return v % max;
+        }
+    }
 }
@@ -557,6 +557,72 @@ impl RsaPublicKey {
 
         &digest_info[19..] == &hash[..]
     }
+
+    /// Verify an RSASSA-PSS signature with SHA-256, MGF1-SHA-256 and a
+    /// 32-byte salt (TLS `rsa_pss_rsae_sha256`)
+    pub fn verify_pss_sha256(&self, message: &[u8], signature: &[u8]) -> bool {
+        const H_LEN: usize = 32;
+        const S_LEN: usize = 32;
+
+        let k = (self.bits + 7) / 8;
+        if signature.len() != k || self.bits < 2 {
+            return false;
+        }
+
+        let s = BigUint::from_bytes_be(signature);
+        if s.cmp(&self.n) != core::cmp::Ordering::Less {
+            return false;
+        }
+        let m = s.mod_pow(&self.e, &self.n);
+
+        // EM is emBits = modBits - 1 long, so it may be one byte shorter than k
+        let em_bits = self.bits - 1;
+        let em_len = (em_bits + 7) / 8;
+        let full = m.to_bytes_be_padded(k);
+        if full.len() != k || full[..k - em_len].iter().any(|&b| b != 0) {
+            return false;
+        }
+        let em = &full[k - em_len..];
+
+        if em_len < H_LEN + S_LEN + 2 || em[em_len - 1] != 0xbc {
+            return false;
+        }
+
+        let db_len = em_len - H_LEN - 1;
+        let masked_db = &em[..db_len];
+        let h = &em[db_len..em_len - 1];
+
+        let top_mask = 0xffu8 >> (8 * em_len - em_bits);
+        if masked_db[0] & !top_mask != 0 {
+            return false;
+        }
+
+        // MGF1: DB = maskedDB xor SHA-256(H || counter) ...
+        let mut db = masked_db.to_vec();
+        for (counter, block) in db.chunks_mut(H_LEN).enumerate() {
+            let mut hasher = super::sha256::Sha256::new();
+            hasher.update(h);
+            hasher.update(&(counter as u32).to_be_bytes());
+            let mask = hasher.finalize();
+            for (b, m) in block.iter_mut().zip(mask.iter()) {
+                *b ^= m;
+            }
+        }
+        db[0] &= top_mask;
+
+        // DB = PS (zeros) || 0x01 || salt
+        let ps_len = db_len - S_LEN - 1;
+        if db[..ps_len].iter().any(|&b| b != 0) || db[ps_len] != 0x01 {
+            return false;
+        }
+        let salt = &db[ps_len + 1..];
+
+        let mut hasher = super::sha256::Sha256::new();
+        hasher.update(&[0u8; 8]);
+        hasher.update(&super::sha256::sha256(message));
+        hasher.update(salt);
+        super::constant_time_eq(&hasher.finalize(), h)
+    }
 }
 
 /// RSA private key
@@ -28,6 +28,7 @@ const K: [u32; 64] = [
 pub type Sha256Digest = [u8; 32];
 
 /// SHA-256 hasher state
+#[derive(Clone)]
 pub struct Sha256 {
     state: [u32; 8],
     buffer: [u8; 64],
@@ -37,7 +38,7 @@ pub struct Sha256 {
 
 impl Sha256 {
     /// Create a new SHA-256 hasher
-    pub fn new() -> Self {
+    pub const fn new() -> Self {
         Self {
             state: H0,
             buffer: [0; 64],
@@ -0,0 +1,831 @@
+//! X.509 Certificates
+//!
+//! DER parsing of X.509 v3 certificates, certificate chain validation and
+//! the system trust store used by the TLS client.
+//!
+//! Signatures are checked with sha256WithRSAEncryption (PKCS#1 v1.5) and
+//! Ed25519. Certificates signed with other algorithms still parse, but fail
+//! verification with `CertError::UnsupportedAlgorithm`.
+//!
+//! The system trust store is read from `/etc/ssl/certs` on first use. Every
+//! regular file there may hold any number of PEM certificates (a bundle such
+//! as `ca-certificates.crt`) or a single DER certificate.
+
+#![allow(dead_code)]
+
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::ops::Range;
+
+use super::rsa::{BigUint, RsaPublicKey};
+use crate::sync::IrqSafeMutex;
+use crate::time::days_from_civil;
+
+/// Directory holding the system CA certificates
+pub const SYSTEM_CERT_DIR: &str = "/etc/ssl/certs";
+
+/// Longest chain followed from the leaf to a trust anchor
+const MAX_CHAIN_DEPTH: usize = 8;
+
+// DER tags
+const TAG_BOOLEAN: u8 = 0x01;
+const TAG_INTEGER: u8 = 0x02;
+const TAG_BIT_STRING: u8 = 0x03;
+const TAG_OCTET_STRING: u8 = 0x04;
+const TAG_OID: u8 = 0x06;
+const TAG_UTF8_STRING: u8 = 0x0c;
+const TAG_PRINTABLE_STRING: u8 = 0x13;
+const TAG_T61_STRING: u8 = 0x14;
+const TAG_IA5_STRING: u8 = 0x16;
+const TAG_UTC_TIME: u8 = 0x17;
+const TAG_GENERALIZED_TIME: u8 = 0x18;
+const TAG_BMP_STRING: u8 = 0x1e;
+const TAG_SEQUENCE: u8 = 0x30;
+const TAG_SET: u8 = 0x31;
+const TAG_VERSION: u8 = 0xa0;
+const TAG_EXTENSIONS: u8 = 0xa3;
+// GeneralName choices inside subjectAltName
+const TAG_SAN_DNS: u8 = 0x82;
+const TAG_SAN_IP: u8 = 0x87;
+
+// Object identifiers (DER contents)
+const OID_RSA_ENCRYPTION: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01, 0x01];
+const OID_SHA256_WITH_RSA: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01, 0x0b];
+const OID_ED25519: &[u8] = &[0x2b, 0x65, 0x70];
+const OID_COMMON_NAME: &[u8] = &[0x55, 0x04, 0x03];
+const OID_KEY_USAGE: &[u8] = &[0x55, 0x1d, 0x0f];
+const OID_SUBJECT_ALT_NAME: &[u8] = &[0x55, 0x1d, 0x11];
+const OID_BASIC_CONSTRAINTS: &[u8] = &[0x55, 0x1d, 0x13];
+const OID_CERTIFICATE_POLICIES: &[u8] = &[0x55, 0x1d, 0x20];
+const OID_EXT_KEY_USAGE: &[u8] = &[0x55, 0x1d, 0x25];
+const OID_ANY_EXT_KEY_USAGE: &[u8] = &[0x55, 0x1d, 0x25, 0x00];
+const OID_SERVER_AUTH: &[u8] = &[0x2b, 0x06, 0x01, 0x05, 0x05, 0x07, 0x03, 0x01];
+
+/// keyUsage bits, first bit of the BIT STRING in the high bit
+pub const KU_DIGITAL_SIGNATURE: u16 = 0x8000;
+pub const KU_KEY_CERT_SIGN: u16 = 0x0400;
+
+/// Why a certificate or chain was rejected
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CertError {
+    /// DER encoding is invalid or truncated
+    Malformed,
+    /// Signature or key algorithm not implemented
+    UnsupportedAlgorithm,
+    /// A critical extension this parser does not understand
+    UnhandledCriticalExtension,
+    BadSignature,
+    Expired,
+    NotYetValid,
+    /// The leaf is not valid for the requested host name
+    HostnameMismatch,
+    /// No trust anchor was reached
+    UnknownIssuer,
+    /// An issuer is not marked as a CA
+    NotCa,
+    /// An issuer's pathLenConstraint, or `MAX_CHAIN_DEPTH`, was exceeded
+    PathTooLong,
+    /// keyUsage or extKeyUsage forbid this use of the key
+    UsageNotAllowed,
+    EmptyChain,
+}
+
+// ============================================================================
+// DER Reader
+// ============================================================================
+
+/// Sequential reader over DER elements
+struct Der<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Der<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data, pos: 0 }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.pos >= self.data.len()
+    }
+
+    fn peek_tag(&self) -> Option<u8> {
+        self.data.get(self.pos).copied()
+    }
+
+    /// Reads the next element as (tag, contents, whole encoding).
+    fn read_any(&mut self) -> Result<(u8, &'a [u8], &'a [u8]), CertError> {
+        let start = self.pos;
+        let tag = *self.data.get(start).ok_or(CertError::Malformed)?;
+        // Multi-byte tags never occur in certificates
+        if tag & 0x1f == 0x1f {
+            return Err(CertError::Malformed);
+        }
+
+        let first = *self.data.get(start + 1).ok_or(CertError::Malformed)?;
+        let mut header = 2;
+        let len = if first < 0x80 {
+            first as usize
+        } else {
+            // Indefinite lengths (0x80) are BER only
+            let n = (first & 0x7f) as usize;
+            if n == 0 || n > 4 {
+                return Err(CertError::Malformed);
+            }
+            let bytes = self.data.get(start + 2..start + 2 + n).ok_or(CertError::Malformed)?;
+            header += n;
+            bytes.iter().fold(0usize, |acc, &b| (acc << 8) | b as usize)
+        };
+
+        let end = (start + header).checked_add(len).ok_or(CertError::Malformed)?;
+        if end > self.data.len() {
+            return Err(CertError::Malformed);
+        }
+        self.pos = end;
+        Ok((tag, &self.data[start + header..end], &self.data[start..end]))
+    }
+
+    /// Reads the next element, which must have tag `tag`, and returns its contents.
+    fn read(&mut self, tag: u8) -> Result<&'a [u8], CertError> {
+        let (t, contents, _) = self.read_any()?;
+        if t != tag {
+            return Err(CertError::Malformed);
+        }
+        Ok(contents)
+    }
+
+    /// Reads the next element only if it has tag `tag`.
+    fn read_optional(&mut self, tag: u8) -> Result<Option<&'a [u8]>, CertError> {
+        if self.peek_tag() == Some(tag) {
+            self.read(tag).map(Some)
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+/// Contents of a BIT STRING without unused bits
+fn bit_string_bytes(contents: &[u8]) -> Result<&[u8], CertError> {
+    match contents.split_first() {
+        Some((0, rest)) => Ok(rest),
+        _ => Err(CertError::Malformed),
+    }
+}
+
+// ============================================================================
+// Certificate
+// ============================================================================
+
+/// Signature algorithm of a certificate or TLS handshake signature
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SignatureAlgorithm {
+    RsaPkcs1Sha256,
+    /// RSASSA-PSS with SHA-256; only used by TLS, never parsed from certificates
+    RsaPssSha256,
+    Ed25519,
+    Unknown,
+}
+
+/// Subject public key
+#[derive(Debug, Clone)]
+pub enum PublicKey {
+    Rsa(RsaPublicKey),
+    Ed25519([u8; 32]),
+    Unsupported,
+}
+
+impl PublicKey {
+    /// Checks `signature` over `message` made with this key.
+    pub fn verify(
+        &self,
+        algorithm: SignatureAlgorithm,
+        message: &[u8],
+        signature: &[u8],
+    ) -> Result<(), CertError> {
+        let ok = match (self, algorithm) {
+            (PublicKey::Rsa(key), SignatureAlgorithm::RsaPkcs1Sha256) => key.verify(message, signature),
+            (PublicKey::Rsa(key), SignatureAlgorithm::RsaPssSha256) => {
+                key.verify_pss_sha256(message, signature)
+            }
+            (PublicKey::Ed25519(key), SignatureAlgorithm::Ed25519) => {
+                let sig: &[u8; 64] = signature.try_into().map_err(|_| CertError::BadSignature)?;
+                super::ed25519::verify(key, message, sig)
+            }
+            _ => return Err(CertError::UnsupportedAlgorithm),
+        };
+        if ok {
+            Ok(())
+        } else {
+            Err(CertError::BadSignature)
+        }
+    }
+}
+
+/// Parsed X.509 certificate
+#[derive(Debug, Clone)]
+pub struct Certificate {
+    /// Complete DER encoding
+    pub der: Vec<u8>,
+    /// Range of the signed tbsCertificate inside `der`
+    tbs: Range<usize>,
+    /// Version field (0 = v1, 2 = v3)
+    pub version: u8,
+    pub serial: Vec<u8>,
+    /// DER encoding of the issuer and subject Names, compared bytewise
+    issuer: Vec<u8>,
+    subject: Vec<u8>,
+    pub issuer_cn: Option<String>,
+    pub subject_cn: Option<String>,
+    /// Validity period, Unix seconds
+    pub not_before: i64,
+    pub not_after: i64,
+    pub public_key: PublicKey,
+    pub signature_algorithm: SignatureAlgorithm,
+    signature: Vec<u8>,
+    /// subjectAltName dNSName entries
+    pub dns_names: Vec<String>,
+    /// subjectAltName iPAddress entries (4 or 16 bytes)
+    pub ip_addresses: Vec<Vec<u8>>,
+    /// Whether the certificate carries a subjectAltName extension
+    has_san: bool,
+    /// basicConstraints cA flag
+    pub is_ca: bool,
+    /// basicConstraints pathLenConstraint
+    pub path_len: Option<u32>,
+    /// keyUsage bits (`KU_*`), when present
+    pub key_usage: Option<u16>,
+    /// Whether extKeyUsage is absent or allows TLS server authentication
+    pub server_auth: bool,
+}
+
+impl Certificate {
+    /// Parses a DER-encoded certificate.
+    pub fn from_der(der: &[u8]) -> Result<Self, CertError> {
+        let mut outer = Der::new(der);
+        let cert = outer.read(TAG_SEQUENCE)?;
+        if !outer.is_empty() {
+            return Err(CertError::Malformed);
+        }
+
+        let mut c = Der::new(cert);
+        let (tag, tbs_contents, tbs_raw) = c.read_any()?;
+        if tag != TAG_SEQUENCE {
+            return Err(CertError::Malformed);
+        }
+        let outer_alg = parse_signature_algorithm(c.read(TAG_SEQUENCE)?)?;
+        let signature = bit_string_bytes(c.read(TAG_BIT_STRING)?)?.to_vec();
+        if !c.is_empty() {
+            return Err(CertError::Malformed);
+        }
+
+        // tbs_raw borrows from `der`, so its offset locates it there
+        let tbs_start = tbs_raw.as_ptr() as usize - der.as_ptr() as usize;
+        let mut cert = Certificate {
+            der: der.to_vec(),
+            tbs: tbs_start..tbs_start + tbs_raw.len(),
+            version: 0,
+            serial: Vec::new(),
+            issuer: Vec::new(),
+            subject: Vec::new(),
+            issuer_cn: None,
+            subject_cn: None,
+            not_before: 0,
+            not_after: 0,
+            public_key: PublicKey::Unsupported,
+            signature_algorithm: outer_alg,
+            signature,
+            dns_names: Vec::new(),
+            ip_addresses: Vec::new(),
+            has_san: false,
+            is_ca: false,
+            path_len: None,
+            key_usage: None,
+            server_auth: true,
+        };
+
+        let mut tbs = Der::new(tbs_contents);
+        if let Some(version) = tbs.read_optional(TAG_VERSION)? {
+            match Der::new(version).read(TAG_INTEGER)? {
+                [v @ 0..=2] => cert.version = *v,
+                _ => return Err(CertError::Malformed),
+            }
+        }
+        cert.serial = tbs.read(TAG_INTEGER)?.to_vec();
+
+        // The signed algorithm must match the one outside the signature
+        if parse_signature_algorithm(tbs.read(TAG_SEQUENCE)?)? != outer_alg {
+            return Err(CertError::Malformed);
+        }
+
+        let (tag, issuer, issuer_raw) = tbs.read_any()?;
+        if tag != TAG_SEQUENCE {
+            return Err(CertError::Malformed);
+        }
+        cert.issuer = issuer_raw.to_vec();
+        cert.issuer_cn = common_name(issuer)?;
+
+        let mut validity = Der::new(tbs.read(TAG_SEQUENCE)?);
+        cert.not_before = parse_time(&mut validity)?;
+        cert.not_after = parse_time(&mut validity)?;
+
+        let (tag, subject, subject_raw) = tbs.read_any()?;
+        if tag != TAG_SEQUENCE {
+            return Err(CertError::Malformed);
+        }
+        cert.subject = subject_raw.to_vec();
+        cert.subject_cn = common_name(subject)?;
+
+        cert.public_key = parse_public_key(tbs.read(TAG_SEQUENCE)?)?;
+
+        // issuerUniqueID [1] and subjectUniqueID [2]
+        tbs.read_optional(0x81)?;
+        tbs.read_optional(0x82)?;
+
+        if let Some(extensions) = tbs.read_optional(TAG_EXTENSIONS)? {
+            if cert.version != 2 {
+                return Err(CertError::Malformed);
+            }
+            cert.parse_extensions(Der::new(extensions).read(TAG_SEQUENCE)?)?;
+        }
+
+        if !tbs.is_empty() {
+            return Err(CertError::Malformed);
+        }
+        Ok(cert)
+    }
+
+    fn parse_extensions(&mut self, data: &[u8]) -> Result<(), CertError> {
+        let mut exts = Der::new(data);
+        while !exts.is_empty() {
+            let mut ext = Der::new(exts.read(TAG_SEQUENCE)?);
+            let oid = ext.read(TAG_OID)?;
+            let critical = match ext.read_optional(TAG_BOOLEAN)? {
+                Some(v) => v.first().map_or(false, |&b| b != 0),
+                None => false,
+            };
+            let value = ext.read(TAG_OCTET_STRING)?;
+
+            match oid {
+                OID_SUBJECT_ALT_NAME => self.parse_san(value)?,
+                OID_BASIC_CONSTRAINTS => {
+                    let mut bc = Der::new(Der::new(value).read(TAG_SEQUENCE)?);
+                    if let Some(ca) = bc.read_optional(TAG_BOOLEAN)? {
+                        self.is_ca = ca.first().map_or(false, |&b| b != 0);
+                    }
+                    if let Some(len) = bc.read_optional(TAG_INTEGER)? {
+                        if len.is_empty() || len.len() > 4 || len[0] & 0x80 != 0 {
+                            return Err(CertError::Malformed);
+                        }
+                        self.path_len = Some(len.iter().fold(0u32, |acc, &b| (acc << 8) | b as u32));
+                    }
+                }
+                OID_KEY_USAGE => {
+                    let bits = Der::new(value).read(TAG_BIT_STRING)?;
+                    // The unused-bits count only matters for the trailing byte
+                    let b0 = bits.get(1).copied().unwrap_or(0);
+                    let b1 = bits.get(2).copied().unwrap_or(0);
+                    self.key_usage = Some(((b0 as u16) << 8) | b1 as u16);
+                }
+                OID_EXT_KEY_USAGE => {
+                    let mut purposes = Der::new(Der::new(value).read(TAG_SEQUENCE)?);
+                    self.server_auth = false;
+                    while !purposes.is_empty() {
+                        let purpose = purposes.read(TAG_OID)?;
+                        if purpose == OID_SERVER_AUTH || purpose == OID_ANY_EXT_KEY_USAGE {
+                            self.server_auth = true;
+                        }
+                    }
+                }
+                // Policies do not restrict anything this client checks
+                OID_CERTIFICATE_POLICIES => {}
+                _ if critical => return Err(CertError::UnhandledCriticalExtension),
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    fn parse_san(&mut self, value: &[u8]) -> Result<(), CertError> {
+        self.has_san = true;
+        let mut names = Der::new(Der::new(value).read(TAG_SEQUENCE)?);
+        while !names.is_empty() {
+            let (tag, contents, _) = names.read_any()?;
+            match tag {
+                TAG_SAN_DNS => {
+                    let name = core::str::from_utf8(contents).map_err(|_| CertError::Malformed)?;
+                    self.dns_names.push(String::from(name));
+                }
+                TAG_SAN_IP => self.ip_addresses.push(contents.to_vec()),
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// The DER bytes covered by the signature
+    pub fn tbs(&self) -> &[u8] {
+        &self.der[self.tbs.clone()]
+    }
+
+    pub fn signature(&self) -> &[u8] {
+        &self.signature
+    }
+
+    /// Whether issuer and subject are the same Name
+    pub fn is_self_issued(&self) -> bool {
+        self.issuer == self.subject
+    }
+
+    /// Checks that `issuer`'s key made this certificate's signature.
+    pub fn verify_signed_by(&self, issuer: &Certificate) -> Result<(), CertError> {
+        issuer.public_key.verify(self.signature_algorithm, self.tbs(), &self.signature)
+    }
+
+    /// Checks the validity period against `now` (Unix seconds).
+    pub fn check_validity(&self, now: i64) -> Result<(), CertError> {
+        if now < self.not_before {
+            Err(CertError::NotYetValid)
+        } else if now > self.not_after {
+            Err(CertError::Expired)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Whether the certificate is valid for `host` (RFC 6125).
+    ///
+    /// IP literals only match iPAddress entries. Names match dNSName entries,
+    /// where a wildcard may stand for the whole leftmost label. The subject
+    /// CN is consulted only when the certificate has no subjectAltName.
+    pub fn matches_host(&self, host: &str) -> bool {
+        if let Some(ip) = parse_ipv4(host) {
+            return self.ip_addresses.iter().any(|a| a.as_slice() == ip.as_slice());
+        }
+
+        if !self.has_san {
+            return self.subject_cn.as_deref().map_or(false, |cn| match_dns_name(cn, host));
+        }
+        self.dns_names.iter().any(|name| match_dns_name(name, host))
+    }
+}
+
+fn parse_signature_algorithm(data: &[u8]) -> Result<SignatureAlgorithm, CertError> {
+    let oid = Der::new(data).read(TAG_OID)?;
+    Ok(match oid {
+        OID_SHA256_WITH_RSA => SignatureAlgorithm::RsaPkcs1Sha256,
+        OID_ED25519 => SignatureAlgorithm::Ed25519,
+        _ => SignatureAlgorithm::Unknown,
+    })
+}
+
+fn parse_public_key(spki: &[u8]) -> Result<PublicKey, CertError> {
+    let mut spki = Der::new(spki);
+    let algorithm = spki.read(TAG_SEQUENCE)?;
+    let key = bit_string_bytes(spki.read(TAG_BIT_STRING)?)?;
+
+    match Der::new(algorithm).read(TAG_OID)? {
+        OID_RSA_ENCRYPTION => {
+            // RSAPublicKey ::= SEQUENCE { modulus INTEGER, publicExponent INTEGER }
+            let mut rsa = Der::new(Der::new(key).read(TAG_SEQUENCE)?);
+            let n = rsa.read(TAG_INTEGER)?;
+            let e = rsa.read(TAG_INTEGER)?;
+            if n.is_empty() || e.is_empty() || n[0] & 0x80 != 0 || e[0] & 0x80 != 0 {
+                return Err(CertError::Malformed);
+            }
+            Ok(PublicKey::Rsa(RsaPublicKey::new(
+                BigUint::from_bytes_be(n),
+                BigUint::from_bytes_be(e),
+            )))
+        }
+        OID_ED25519 => {
+            let key: [u8; 32] = key.try_into().map_err(|_| CertError::Malformed)?;
+            Ok(PublicKey::Ed25519(key))
+        }
+        _ => Ok(PublicKey::Unsupported),
+    }
+}
+
+/// First commonName attribute of a Name
+fn common_name(name: &[u8]) -> Result<Option<String>, CertError> {
+    let mut rdns = Der::new(name);
+    while !rdns.is_empty() {
+        let mut set = Der::new(rdns.read(TAG_SET)?);
+        while !set.is_empty() {
+            let mut attr = Der::new(set.read(TAG_SEQUENCE)?);
+            if attr.read(TAG_OID)? != OID_COMMON_NAME {
+                continue;
+            }
+            let (tag, value, _) = attr.read_any()?;
+            return Ok(match tag {
+                TAG_UTF8_STRING | TAG_PRINTABLE_STRING | TAG_IA5_STRING | TAG_T61_STRING => {
+                    core::str::from_utf8(value).ok().map(String::from)
+                }
+                TAG_BMP_STRING => None,
+                _ => return Err(CertError::Malformed),
+            });
+        }
+    }
+    Ok(None)
+}
+
+/// Parses a UTCTime or GeneralizedTime in the `...Z` form X.509 requires.
+fn parse_time(der: &mut Der) -> Result<i64, CertError> {
+    let (tag, value, _) = der.read_any()?;
+    let digits = match (tag, value.len()) {
+        (TAG_UTC_TIME, 13) | (TAG_GENERALIZED_TIME, 15) => &value[..value.len() - 1],
+        _ => return Err(CertError::Malformed),
+    };
+    if value[value.len() - 1] != b'Z' || !digits.iter().all(u8::is_ascii_digit) {
+        return Err(CertError::Malformed);
+    }
+
+    let num = |s: &[u8]| s.iter().fold(0i64, |acc, &d| acc * 10 + (d - b'0') as i64);
+    let (year, rest) = if tag == TAG_UTC_TIME {
+        // RFC 5280: YY >= 50 is 19YY, otherwise 20YY
+        let yy = num(&digits[..2]);
+        (if yy >= 50 { 1900 + yy } else { 2000 + yy }, &digits[2..])
+    } else {
+        (num(&digits[..4]), &digits[4..])
+    };
+    let month = num(&rest[0..2]);
+    let day = num(&rest[2..4]);
+    let hour = num(&rest[4..6]);
+    let min = num(&rest[6..8]);
+    let sec = num(&rest[8..10]);
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) || hour > 23 || min > 59 || sec > 60 {
+        return Err(CertError::Malformed);
+    }
+
+    Ok(days_from_civil(year, month as u32, day as u32) * 86400 + hour * 3600 + min * 60 + sec)
+}
+
+fn parse_ipv4(s: &str) -> Option<[u8; 4]> {
+    let mut out = [0u8; 4];
+    let mut parts = s.split('.');
+    for byte in out.iter_mut() {
+        *byte = parts.next()?.parse().ok()?;
+    }
+    if parts.next().is_some() {
+        return None;
+    }
+    Some(out)
+}
+
+/// Matches a dNSName (or CN) `pattern` against `host`, case-insensitively.
+fn match_dns_name(pattern: &str, host: &str) -> bool {
+    let pattern = pattern.trim_end_matches('.');
+    let host = host.trim_end_matches('.');
+    if host.is_empty() {
+        return false;
+    }
+
+    match pattern.strip_prefix("*.") {
+        Some(suffix) => {
+            // "*.com" would cover a whole top-level domain
+            if !suffix.contains('.') {
+                return false;
+            }
+            match host.split_once('.') {
+                Some((label, rest)) => !label.is_empty() && rest.eq_ignore_ascii_case(suffix),
+                None => false,
+            }
+        }
+        None => pattern.eq_ignore_ascii_case(host),
+    }
+}
+
+// ============================================================================
+// Chain Validation
+// ============================================================================
+
+/// Checks that `issuer` may sign certificates with `below` intermediates
+/// between it and the leaf.
+fn check_issuer(issuer: &Certificate, below: usize) -> Result<(), CertError> {
+    if !issuer.is_ca {
+        return Err(CertError::NotCa);
+    }
+    if issuer.key_usage.map_or(false, |ku| ku & KU_KEY_CERT_SIGN == 0) {
+        return Err(CertError::UsageNotAllowed);
+    }
+    if issuer.path_len.map_or(false, |max| (below as u64) > max as u64) {
+        return Err(CertError::PathTooLong);
+    }
+    Ok(())
+}
+
+/// Finds the certificate among `candidates` whose key signed `cert`.
+fn find_issuer<'c>(
+    cert: &Certificate,
+    candidates: &'c [Certificate],
+) -> Result<&'c Certificate, CertError> {
+    let mut err = CertError::UnknownIssuer;
+    for candidate in candidates.iter().filter(|c| c.subject == cert.issuer) {
+        match cert.verify_signed_by(candidate) {
+            Ok(()) => return Ok(candidate),
+            Err(e) => err = e,
+        }
+    }
+    Err(err)
+}
+
+/// Validates a server certificate chain for `host` at time `now` (Unix seconds).
+///
+/// `chain[0]` is the leaf; the rest are intermediates in any order, as TLS
+/// servers send them. The path is built from the leaf upwards until a
+/// certificate is signed by (or is) a trust anchor in `store`.
+pub fn verify_chain(
+    chain: &[Certificate],
+    host: &str,
+    store: &TrustStore,
+    now: i64,
+) -> Result<(), CertError> {
+    let leaf = chain.first().ok_or(CertError::EmptyChain)?;
+
+    if !leaf.matches_host(host) {
+        return Err(CertError::HostnameMismatch);
+    }
+    if !leaf.server_auth || leaf.key_usage.map_or(false, |ku| ku & KU_DIGITAL_SIGNATURE == 0) {
+        return Err(CertError::UsageNotAllowed);
+    }
+
+    let mut current = leaf;
+    for depth in 0..MAX_CHAIN_DEPTH {
+        current.check_validity(now)?;
+        if store.contains(current) {
+            return Ok(());
+        }
+
+        if let Ok(anchor) = find_issuer(current, &store.anchors) {
+            anchor.check_validity(now)?;
+            return check_issuer(anchor, depth);
+        }
+
+        let next = find_issuer(current, &chain[1..])?;
+        check_issuer(next, depth)?;
+        current = next;
+    }
+    Err(CertError::PathTooLong)
+}
+
+// ============================================================================
+// Trust Store
+// ============================================================================
+
+/// Set of trusted CA certificates
+#[derive(Debug, Clone, Default)]
+pub struct TrustStore {
+    anchors: Vec<Certificate>,
+}
+
+impl TrustStore {
+    pub const fn new() -> Self {
+        Self { anchors: Vec::new() }
+    }
+
+    pub fn len(&self) -> usize {
+        self.anchors.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.anchors.is_empty()
+    }
+
+    pub fn anchors(&self) -> &[Certificate] {
+        &self.anchors
+    }
+
+    /// Whether this exact certificate is a trust anchor
+    pub fn contains(&self, cert: &Certificate) -> bool {
+        self.anchors.iter().any(|a| a.der == cert.der)
+    }
+
+    /// Adds a trust anchor. Returns false if it was already present.
+    pub fn add(&mut self, cert: Certificate) -> bool {
+        if self.contains(&cert) {
+            return false;
+        }
+        self.anchors.push(cert);
+        true
+    }
+
+    /// Adds a DER-encoded certificate.
+    pub fn add_der(&mut self, der: &[u8]) -> Result<bool, CertError> {
+        Ok(self.add(Certificate::from_der(der)?))
+    }
+
+    /// Adds every `CERTIFICATE` block in PEM text. Returns the number added;
+    /// blocks that fail to parse are skipped.
+    pub fn add_pem(&mut self, pem: &[u8]) -> usize {
+        const BEGIN: &[u8] = b"-----BEGIN CERTIFICATE-----";
+        const END: &[u8] = b"-----END CERTIFICATE-----";
+
+        let mut added = 0;
+        let mut rest = pem;
+        while let Some(start) = find(rest, BEGIN) {
+            let body = &rest[start + BEGIN.len()..];
+            let Some(end) = find(body, END) else {
+                break;
+            };
+            if let Some(der) = base64_decode(&body[..end]) {
+                if let Ok(true) = self.add_der(&der) {
+                    added += 1;
+                }
+            }
+            rest = &body[end + END.len()..];
+        }
+        added
+    }
+
+    /// Loads every PEM or DER certificate file in directory `path`.
+    /// Returns the number of certificates added.
+    pub fn load_dir(&mut self, path: &str) -> usize {
+        let cred = crate::security::Cred::root();
+        let entries = match crate::fs::vfs_lock().list_dir(path, &cred) {
+            Ok(entries) => entries,
+            Err(_) => return 0,
+        };
+
+        let mut added = 0;
+        for entry in entries {
+            if entry.kind == crate::fs::InodeKind::Dir {
+                continue;
+            }
+            let file = alloc::format!("{}/{}", path.trim_end_matches('/'), entry.name);
+            let Ok(data) = crate::fs::read_file(&file, &cred) else {
+                continue;
+            };
+            if data.first() == Some(&TAG_SEQUENCE) {
+                if let Ok(true) = self.add_der(&data) {
+                    added += 1;
+                }
+            } else {
+                added += self.add_pem(&data);
+            }
+        }
+        added
+    }
+}
+
+static SYSTEM_STORE: IrqSafeMutex<Option<Arc<TrustStore>>> = IrqSafeMutex::new(None);
+
+/// The system trust store, loaded from `SYSTEM_CERT_DIR` on first use.
+///
+/// An empty store is not kept, so a later call retries once the directory
+/// is mounted or filled.
+pub fn system_store() -> Arc<TrustStore> {
+    if let Some(store) = SYSTEM_STORE.lock().as_ref() {
+        return store.clone();
+    }
+
+    // Read the files without the lock held; a concurrent loader just loses
+    let mut store = TrustStore::new();
+    store.load_dir(SYSTEM_CERT_DIR);
+    if store.is_empty() {
+        return Arc::new(store);
+    }
+    SYSTEM_STORE.lock().get_or_insert_with(|| Arc::new(store)).clone()
+}
+
+/// Re-reads `SYSTEM_CERT_DIR`, e.g. after CA certificates were installed.
+/// Returns the number of trust anchors.
+pub fn reload_system_store() -> usize {
+    let mut store = TrustStore::new();
+    let count = store.load_dir(SYSTEM_CERT_DIR);
+    *SYSTEM_STORE.lock() = if store.is_empty() { None } else { Some(Arc::new(store)) };
+    crate::kprintln!("x509: {} trusted certificates loaded from {}", count, SYSTEM_CERT_DIR);
+    count
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|w| w == needle)
+}
+
+/// Base64 decoding that skips whitespace, as found in PEM bodies
+fn base64_decode(input: &[u8]) -> Option<Vec<u8>> {
+    let mut output = Vec::with_capacity(input.len() * 3 / 4);
+    let mut buffer = 0u32;
+    let mut bits = 0u8;
+
+    for &c in input {
+        let val = match c {
+            b'A'..=b'Z' => c - b'A',
+            b'a'..=b'z' => c - b'a' + 26,
+            b'0'..=b'9' => c - b'0' + 52,
+            b'+' => 62,
+            b'/' => 63,
+            b'=' => break,
+            c if c.is_ascii_whitespace() => continue,
+            _ => return None,
+        };
+
+        buffer = (buffer << 6) | val as u32;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            output.push((buffer >> bits) as u8);
+        }
+    }
+
+    Some(output)
+}
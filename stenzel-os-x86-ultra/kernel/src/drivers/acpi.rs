@@ -1270,6 +1270,9 @@ pub fn shutdown() -> ! {
 
     crate::kprintln!("acpi: initiating shutdown (S5)...");
 
+    // Keep the NTP-corrected time for the next boot
+    crate::time::sync_rtc();
+
     // Disable interrupts
     x86_64::instructions::interrupts::disable();
 
@@ -1384,6 +1387,9 @@ pub fn reboot() -> ! {
 
     crate::kprintln!("acpi: initiating reboot...");
 
+    // Keep the NTP-corrected time for the next boot
+    crate::time::sync_rtc();
+
     // Disable interrupts
     x86_64::instructions::interrupts::disable();
 
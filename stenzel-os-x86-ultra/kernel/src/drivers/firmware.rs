@@ -15,6 +15,7 @@ use alloc::collections::BTreeMap;
 use spin::Mutex;
 use core::sync::atomic::{AtomicBool, Ordering};
 
+use crate::compress;
 use crate::fs;
 
 static FIRMWARE_CACHE: Mutex<BTreeMap<String, Vec<u8>>> = Mutex::new(BTreeMap::new());
@@ -128,28 +129,24 @@ fn cache_firmware(name: &str, data: Vec<u8>) {
     }
 }
 
-/// Decompress XZ data
-fn decompress_xz(data: &[u8]) -> Result<Vec<u8>, FirmwareError> {
-    // XZ magic: 0xFD 0x37 0x7A 0x58 0x5A 0x00
-    if data.len() < 6 || &data[0..6] != b"\xFD7zXZ\x00" {
-        return Err(FirmwareError::InvalidFormat);
+/// Largest decompressed firmware image accepted
+const MAX_FIRMWARE_SIZE: usize = 64 * 1024 * 1024;
+
+fn decompress_error(err: compress::CompressError) -> FirmwareError {
+    match err {
+        compress::CompressError::BadFormat => FirmwareError::InvalidFormat,
+        _ => FirmwareError::DecompressError,
     }
+}
 
-    // TODO: Implement XZ decompression
-    // For now, return error
-    Err(FirmwareError::DecompressError)
+/// Decompress XZ data
+fn decompress_xz(data: &[u8]) -> Result<Vec<u8>, FirmwareError> {
+    compress::xz::decompress(data, MAX_FIRMWARE_SIZE).map_err(decompress_error)
 }
 
 /// Decompress Zstd data
 fn decompress_zstd(data: &[u8]) -> Result<Vec<u8>, FirmwareError> {
-    // Zstd magic: 0x28 0xB5 0x2F 0xFD
-    if data.len() < 4 || &data[0..4] != b"\x28\xB5\x2F\xFD" {
-        return Err(FirmwareError::InvalidFormat);
-    }
-
-    // TODO: Implement Zstd decompression
-    // For now, return error
-    Err(FirmwareError::DecompressError)
+    compress::zstd::decompress(data, MAX_FIRMWARE_SIZE).map_err(decompress_error)
 }
 
 /// Release firmware from cache
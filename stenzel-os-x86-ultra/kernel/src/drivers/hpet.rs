@@ -45,6 +45,7 @@ static HPET_ENABLED: AtomicBool = AtomicBool::new(false);
 static HPET_BASE: AtomicU64 = AtomicU64::new(0);
 static HPET_PERIOD_FS: AtomicU64 = AtomicU64::new(0);  // Period in femtoseconds
 static HPET_FREQ_HZ: AtomicU64 = AtomicU64::new(0);    // Frequency in Hz
+static HPET_COUNTER_64: AtomicBool = AtomicBool::new(false);
 
 /// Convert HPET physical address to virtual address
 fn hpet_phys_to_virt(phys: u64) -> u64 {
@@ -99,6 +100,7 @@ pub fn init() -> bool {
         let legacy_capable = (cap & (1 << 15)) != 0;
 
         HPET_PERIOD_FS.store(period_fs, Ordering::Relaxed);
+        HPET_COUNTER_64.store(counter_64bit, Ordering::Relaxed);
 
         // Calculate frequency: freq = 10^15 / period_fs
         let freq_hz = 1_000_000_000_000_000u64 / period_fs;
@@ -144,6 +146,16 @@ pub fn frequency() -> u64 {
     HPET_FREQ_HZ.load(Ordering::Relaxed)
 }
 
+/// Bits of the main counter that are valid: a 32-bit counter wraps
+/// after a few minutes and must be read as a delta under this mask
+pub fn counter_mask() -> u64 {
+    if HPET_COUNTER_64.load(Ordering::Relaxed) {
+        u64::MAX
+    } else {
+        u32::MAX as u64
+    }
+}
+
 /// Get the current HPET counter value
 pub fn read_counter() -> u64 {
     if !HPET_ENABLED.load(Ordering::Relaxed) {
@@ -60,6 +60,7 @@ pub mod thermal;
 pub mod thunderbolt;
 pub mod touchpad;
 pub mod touchscreen;
+pub mod tty;
 pub mod uefi_runtime;
 pub mod uefi_vars;
 pub mod usb;
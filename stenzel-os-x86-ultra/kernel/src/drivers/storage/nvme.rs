@@ -19,7 +19,7 @@ use alloc::boxed::Box;
 use alloc::sync::Arc;
 use alloc::vec;
 use core::ptr::{read_volatile, write_volatile};
-use core::sync::atomic::{AtomicU16, Ordering};
+use core::sync::atomic::{AtomicBool, AtomicU16, Ordering};
 
 use spin::Mutex;
 
@@ -59,6 +59,12 @@ const ADMIN_SET_FEATURES: u8 = 0x09;
 // Opcodes I/O Commands
 const IO_READ: u8 = 0x02;
 const IO_WRITE: u8 = 0x01;
+const IO_DSM: u8 = 0x09;
+
+// Identify Controller: ONCS bit 2 = Dataset Management (deallocate)
+const ONCS_DSM: u16 = 1 << 2;
+// DSM cdw11: Attribute - Deallocate
+const DSM_ATTR_DEALLOCATE: u32 = 1 << 2;
 
 // ============================================================================
 // Registradores NVMe (BAR0 offsets)
@@ -226,6 +232,8 @@ pub struct NvmeController {
     io_sq: Mutex<SubmissionQueue>,
     io_cq: Mutex<CompletionQueue>,
     next_cid: AtomicU16,
+    /// Controller suporta Dataset Management (TRIM)
+    dsm: AtomicBool,
     block_size: u32,
     num_blocks: u64,
     id: BlockDeviceId,
@@ -317,6 +325,9 @@ impl NvmeController {
         let mn = core::str::from_utf8(&buf[24..64]).unwrap_or("Unknown").trim();
         crate::kprintln!("nvme: controller: {} {}", mn, sn);
 
+        let oncs = u16::from_le_bytes([buf[520], buf[521]]);
+        self.dsm.store(oncs & ONCS_DSM != 0, Ordering::Relaxed);
+
         Ok(())
     }
 
@@ -506,6 +517,40 @@ impl BlockDevice for NvmeController {
 
         Ok(())
     }
+
+    fn discard(&self, lba: u64, count: u32) -> KResult<()> {
+        if !self.dsm.load(Ordering::Relaxed) {
+            return Err(KError::NotSupported);
+        }
+        if count == 0 {
+            return Ok(());
+        }
+        if lba.checked_add(count as u64).is_none_or(|end| end > self.num_blocks) {
+            return Err(KError::OutOfRange);
+        }
+
+        // Uma única range: context attributes, tamanho em blocos, LBA inicial
+        let mut range = vec![0u8; 4096].into_boxed_slice();
+        range[4..8].copy_from_slice(&count.to_le_bytes());
+        range[8..16].copy_from_slice(&lba.to_le_bytes());
+
+        let cmd = NvmeCommand {
+            opcode: IO_DSM,
+            flags: 0,
+            cid: self.alloc_cid(),
+            nsid: 1,
+            rsvd: 0,
+            mptr: 0,
+            prp1: virt_to_phys(range.as_ptr() as u64),
+            prp2: 0,
+            cdw10: 0, // NR = 0 (1 range)
+            cdw11: DSM_ATTR_DEALLOCATE,
+            ..Default::default()
+        };
+
+        self.io_command(cmd)?;
+        Ok(())
+    }
 }
 
 // ============================================================================
@@ -547,6 +592,7 @@ pub fn probe(dev: &PciDevice) -> Option<Arc<NvmeController>> {
         io_sq: Mutex::new(SubmissionQueue::new(IO_QUEUE_SIZE, core::ptr::null_mut())),
         io_cq: Mutex::new(CompletionQueue::new(IO_QUEUE_SIZE, core::ptr::null_mut())),
         next_cid: AtomicU16::new(1),
+        dsm: AtomicBool::new(false),
         block_size: 512,
         num_blocks: 0,
         id: BlockDeviceId(100),
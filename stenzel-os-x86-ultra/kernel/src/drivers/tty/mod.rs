@@ -0,0 +1,712 @@
+//! TTY layer: termios, line discipline and controlling terminals.
+//!
+//! Every terminal is a `Tty` with its own termios, window size, session and
+//! foreground process group. Bytes typed on the device side go through the
+//! line discipline (`Tty::receive`), which edits lines in canonical mode,
+//! echoes them, and turns VINTR/VQUIT/VSUSP into signals for the foreground
+//! group. Bytes written by programs go through output processing
+//! (`Tty::write`) and are queued for the device side, e.g. a pty master.
+
+#![allow(dead_code)]
+
+pub mod pty;
+
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+use spin::Once;
+
+use crate::signal::sig;
+use crate::sync::IrqSafeMutex;
+use crate::syscall::errno;
+
+// c_iflag
+pub const ISTRIP: u32 = 0o40;
+pub const INLCR: u32 = 0o100;
+pub const IGNCR: u32 = 0o200;
+pub const ICRNL: u32 = 0o400;
+pub const IXON: u32 = 0o2000;
+
+// c_oflag
+pub const OPOST: u32 = 0o1;
+pub const ONLCR: u32 = 0o4;
+pub const OCRNL: u32 = 0o10;
+
+// c_lflag
+pub const ISIG: u32 = 0o1;
+pub const ICANON: u32 = 0o2;
+pub const ECHO: u32 = 0o10;
+pub const ECHOE: u32 = 0o20;
+pub const ECHOK: u32 = 0o40;
+pub const ECHONL: u32 = 0o100;
+pub const NOFLSH: u32 = 0o200;
+pub const TOSTOP: u32 = 0o400;
+pub const ECHOCTL: u32 = 0o1000;
+pub const ECHOKE: u32 = 0o4000;
+pub const IEXTEN: u32 = 0o100000;
+
+// c_cc indices
+pub const VINTR: usize = 0;
+pub const VQUIT: usize = 1;
+pub const VERASE: usize = 2;
+pub const VKILL: usize = 3;
+pub const VEOF: usize = 4;
+pub const VTIME: usize = 5;
+pub const VMIN: usize = 6;
+pub const VSTART: usize = 8;
+pub const VSTOP: usize = 9;
+pub const VSUSP: usize = 10;
+pub const VEOL: usize = 11;
+pub const VWERASE: usize = 14;
+
+/// Longest line canonical mode accepts; further input is dropped
+const MAX_CANON: usize = 4095;
+
+/// Bytes queued for the device side before writers start losing output
+const OUTPUT_LIMIT: usize = 64 * 1024;
+
+/// termios structure (simplified)
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct Termios {
+    pub c_iflag: u32,   // Input flags
+    pub c_oflag: u32,   // Output flags
+    pub c_cflag: u32,   // Control flags
+    pub c_lflag: u32,   // Local flags
+    pub c_line: u8,     // Line discipline
+    pub c_cc: [u8; 32], // Control characters
+    pub c_ispeed: u32,  // Input speed
+    pub c_ospeed: u32,  // Output speed
+}
+
+impl Termios {
+    const fn initial() -> Self {
+        let mut cc = [0u8; 32];
+        // Default control characters
+        cc[VINTR] = 3;    // Ctrl-C
+        cc[VQUIT] = 28;   // Ctrl-\
+        cc[VERASE] = 127; // DEL
+        cc[VKILL] = 21;   // Ctrl-U
+        cc[VEOF] = 4;     // Ctrl-D
+        cc[VTIME] = 0;
+        cc[VMIN] = 1;
+        cc[VSTART] = 17;  // Ctrl-Q
+        cc[VSTOP] = 19;   // Ctrl-S
+        cc[VSUSP] = 26;   // Ctrl-Z
+        cc[VWERASE] = 23; // Ctrl-W
+
+        Self {
+            c_iflag: ICRNL | IXON,
+            c_oflag: OPOST | ONLCR,
+            c_cflag: 0x00BF,  // CS8 | CREAD | HUPCL
+            c_lflag: ISIG | ICANON | ECHO | ECHOE | ECHOK | ECHOCTL | ECHOKE | IEXTEN,
+            c_line: 0,
+            c_cc: cc,
+            c_ispeed: 38400,
+            c_ospeed: 38400,
+        }
+    }
+
+    fn lflag(&self, flag: u32) -> bool {
+        self.c_lflag & flag != 0
+    }
+
+    /// Whether `c` is the control character at `index` (0 disables it)
+    fn is_cc(&self, index: usize, c: u8) -> bool {
+        self.c_cc[index] != 0 && self.c_cc[index] == c
+    }
+}
+
+impl Default for Termios {
+    fn default() -> Self {
+        Self::initial()
+    }
+}
+
+/// Window size structure
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Winsize {
+    pub ws_row: u16,
+    pub ws_col: u16,
+    pub ws_xpixel: u16,
+    pub ws_ypixel: u16,
+}
+
+impl Winsize {
+    const fn initial() -> Self {
+        Self {
+            ws_row: 25,
+            ws_col: 80,
+            ws_xpixel: 0,
+            ws_ypixel: 0,
+        }
+    }
+}
+
+impl Default for Winsize {
+    fn default() -> Self {
+        Self::initial()
+    }
+}
+
+/// Line discipline buffers.
+struct LineDiscipline {
+    /// Line being edited (canonical mode)
+    line: Vec<u8>,
+    /// Input ready for read(). In canonical mode each chunk is one line and
+    /// an empty chunk is an end-of-file mark; in raw mode chunks are just
+    /// the order bytes arrived in.
+    ready: VecDeque<Vec<u8>>,
+    /// Output for the device side, after output processing
+    output: VecDeque<u8>,
+}
+
+impl LineDiscipline {
+    const fn new() -> Self {
+        Self {
+            line: Vec::new(),
+            ready: VecDeque::new(),
+            output: VecDeque::new(),
+        }
+    }
+
+    fn available(&self) -> usize {
+        self.ready.iter().map(|c| c.len()).sum()
+    }
+
+    fn flush_input(&mut self) {
+        self.line.clear();
+        self.ready.clear();
+    }
+
+    /// Queues `c` for the device side, applying output processing.
+    fn put_output(&mut self, termios: &Termios, c: u8) {
+        if self.output.len() >= OUTPUT_LIMIT {
+            return;
+        }
+        if termios.c_oflag & OPOST != 0 {
+            if c == b'\n' && termios.c_oflag & ONLCR != 0 {
+                self.output.push_back(b'\r');
+            } else if c == b'\r' && termios.c_oflag & OCRNL != 0 {
+                self.output.push_back(b'\n');
+                return;
+            }
+        }
+        self.output.push_back(c);
+    }
+
+    /// Echoes an input byte, control characters as ^X with ECHOCTL.
+    fn echo(&mut self, termios: &Termios, c: u8) {
+        if termios.lflag(ECHOCTL) && is_ctrl(c) {
+            self.put_output(termios, b'^');
+            self.put_output(termios, c ^ 0x40);
+        } else {
+            self.put_output(termios, c);
+        }
+    }
+
+    /// Removes the last byte of the edit line and wipes it from the screen.
+    fn erase(&mut self, termios: &Termios) -> Option<u8> {
+        let c = self.line.pop()?;
+        if termios.lflag(ECHO) && termios.lflag(ECHOE) {
+            let width = if termios.lflag(ECHOCTL) && is_ctrl(c) { 2 } else { 1 };
+            for _ in 0..width {
+                self.output.extend(b"\x08 \x08");
+            }
+        }
+        Some(c)
+    }
+
+    /// Feeds one input byte. Signals to raise are appended to `signals`.
+    fn input(&mut self, termios: &Termios, mut c: u8, signals: &mut Vec<u32>) {
+        if termios.c_iflag & ISTRIP != 0 {
+            c &= 0x7F;
+        }
+        if c == b'\r' {
+            if termios.c_iflag & IGNCR != 0 {
+                return;
+            }
+            if termios.c_iflag & ICRNL != 0 {
+                c = b'\n';
+            }
+        } else if c == b'\n' && termios.c_iflag & INLCR != 0 {
+            c = b'\r';
+        }
+
+        if termios.lflag(ISIG) {
+            let signal = if termios.is_cc(VINTR, c) {
+                Some(sig::SIGINT)
+            } else if termios.is_cc(VQUIT, c) {
+                Some(sig::SIGQUIT)
+            } else if termios.is_cc(VSUSP, c) {
+                Some(sig::SIGTSTP)
+            } else {
+                None
+            };
+            if let Some(signal) = signal {
+                if !termios.lflag(NOFLSH) {
+                    self.flush_input();
+                }
+                if termios.lflag(ECHO) {
+                    self.echo(termios, c);
+                }
+                signals.push(signal);
+                return;
+            }
+        }
+
+        if !termios.lflag(ICANON) {
+            match self.ready.back_mut() {
+                Some(chunk) if !chunk.is_empty() => chunk.push(c),
+                _ => self.ready.push_back(alloc::vec![c]),
+            }
+            if termios.lflag(ECHO) {
+                self.echo(termios, c);
+            }
+            return;
+        }
+
+        if termios.is_cc(VERASE, c) || c == 0x08 {
+            self.erase(termios);
+            return;
+        }
+        if termios.is_cc(VKILL, c) {
+            if termios.lflag(ECHO) && termios.lflag(ECHOK) && !termios.lflag(ECHOKE) {
+                self.line.clear();
+                self.echo(termios, c);
+                self.put_output(termios, b'\n');
+            } else {
+                while self.erase(termios).is_some() {}
+            }
+            return;
+        }
+        if termios.lflag(IEXTEN) && termios.is_cc(VWERASE, c) {
+            while self.line.last().map_or(false, |&b| b == b' ' || b == b'\t') {
+                self.erase(termios);
+            }
+            while self.line.last().map_or(false, |&b| b != b' ' && b != b'\t') {
+                self.erase(termios);
+            }
+            return;
+        }
+        if termios.is_cc(VEOF, c) {
+            // The pending line, or an empty chunk read() reports as EOF
+            let line = core::mem::take(&mut self.line);
+            self.ready.push_back(line);
+            return;
+        }
+
+        let ends_line = c == b'\n' || termios.is_cc(VEOL, c);
+        if !ends_line && self.line.len() >= MAX_CANON {
+            return;
+        }
+        self.line.push(c);
+        if termios.lflag(ECHO) {
+            if c == b'\n' {
+                self.put_output(termios, c);
+            } else {
+                self.echo(termios, c);
+            }
+        } else if c == b'\n' && termios.lflag(ECHONL) {
+            self.put_output(termios, c);
+        }
+        if ends_line {
+            let line = core::mem::take(&mut self.line);
+            self.ready.push_back(line);
+        }
+    }
+
+    /// Reads one line (canonical) or whatever is queued (raw).
+    fn read(&mut self, termios: &Termios, out: &mut [u8]) -> usize {
+        if termios.lflag(ICANON) {
+            let Some(chunk) = self.ready.front_mut() else {
+                return 0;
+            };
+            let n = chunk.len().min(out.len());
+            out[..n].copy_from_slice(&chunk[..n]);
+            if n == chunk.len() {
+                self.ready.pop_front();
+            } else {
+                chunk.drain(..n);
+            }
+            return n;
+        }
+
+        let mut n = 0;
+        while n < out.len() {
+            let Some(chunk) = self.ready.front_mut() else {
+                break;
+            };
+            let take = chunk.len().min(out.len() - n);
+            out[n..n + take].copy_from_slice(&chunk[..take]);
+            chunk.drain(..take);
+            n += take;
+            if chunk.is_empty() {
+                self.ready.pop_front();
+            }
+        }
+        n
+    }
+
+    /// Whether read() has something to return (data or an EOF mark)
+    fn readable(&self, termios: &Termios) -> bool {
+        if termios.lflag(ICANON) {
+            !self.ready.is_empty()
+        } else {
+            self.ready.iter().any(|c| !c.is_empty())
+        }
+    }
+
+    /// Moves input between the canonical and raw representations after
+    /// ICANON changes.
+    fn switch_mode(&mut self, canonical: bool) {
+        if canonical {
+            return;
+        }
+        // Raw readers see the line being edited as regular input
+        let line = core::mem::take(&mut self.line);
+        if !line.is_empty() {
+            self.ready.push_back(line);
+        }
+        self.ready.retain(|c| !c.is_empty());
+    }
+}
+
+fn is_ctrl(c: u8) -> bool {
+    (c < 0x20 && c != b'\n' && c != b'\t') || c == 0x7F
+}
+
+struct TtyState {
+    termios: Termios,
+    winsize: Winsize,
+    /// Session the terminal controls (0: none)
+    session: u64,
+    /// Foreground process group (0: none)
+    pgrp: u64,
+    ldisc: LineDiscipline,
+    /// The device side went away; reads return EOF, writes fail
+    hung_up: bool,
+}
+
+/// A terminal: termios settings, line discipline and job control state.
+pub struct Tty {
+    state: IrqSafeMutex<TtyState>,
+}
+
+impl Tty {
+    pub const fn new() -> Self {
+        Self {
+            state: IrqSafeMutex::new(TtyState {
+                termios: Termios::initial(),
+                winsize: Winsize::initial(),
+                session: 0,
+                pgrp: 0,
+                ldisc: LineDiscipline::new(),
+                hung_up: false,
+            }),
+        }
+    }
+
+    pub fn termios(&self) -> Termios {
+        self.state.lock().termios
+    }
+
+    /// Changes the settings. `flush` discards pending input (TCSETSF).
+    pub fn set_termios(&self, termios: Termios, flush: bool) {
+        let mut st = self.state.lock();
+        let canonical = termios.lflag(ICANON);
+        if canonical != st.termios.lflag(ICANON) {
+            st.ldisc.switch_mode(canonical);
+        }
+        if flush {
+            st.ldisc.flush_input();
+        }
+        st.termios = termios;
+        drop(st);
+        crate::syscall::epoll::wake(self);
+    }
+
+    pub fn winsize(&self) -> Winsize {
+        self.state.lock().winsize
+    }
+
+    /// Changes the window size; the foreground group gets SIGWINCH.
+    pub fn set_winsize(&self, ws: Winsize) {
+        let pgrp = {
+            let mut st = self.state.lock();
+            if st.winsize == ws {
+                return;
+            }
+            st.winsize = ws;
+            st.pgrp
+        };
+        signal_pgrp(pgrp, sig::SIGWINCH);
+    }
+
+    pub fn session(&self) -> u64 {
+        self.state.lock().session
+    }
+
+    pub fn pgrp(&self) -> u64 {
+        self.state.lock().pgrp
+    }
+
+    pub fn set_pgrp(&self, pgrp: u64) {
+        self.state.lock().pgrp = pgrp;
+    }
+
+    pub fn is_hung_up(&self) -> bool {
+        self.state.lock().hung_up
+    }
+
+    /// Discards pending input (TCIFLUSH) and/or output (TCOFLUSH).
+    pub fn flush(&self, input: bool, output: bool) {
+        let mut st = self.state.lock();
+        if input {
+            st.ldisc.flush_input();
+        }
+        if output {
+            st.ldisc.output.clear();
+        }
+        drop(st);
+        crate::syscall::epoll::wake(self);
+    }
+
+    /// Input from the device side (keyboard, pty master).
+    pub fn receive(&self, data: &[u8]) {
+        let mut signals = Vec::new();
+        let pgrp = {
+            let mut guard = self.state.lock();
+            let st = &mut *guard;
+            for &c in data {
+                st.ldisc.input(&st.termios, c, &mut signals);
+            }
+            st.pgrp
+        };
+        crate::syscall::epoll::wake(self);
+        for signal in signals {
+            signal_pgrp(pgrp, signal);
+        }
+    }
+
+    /// Bytes read() could return right now
+    pub fn input_available(&self) -> usize {
+        self.state.lock().ldisc.available()
+    }
+
+    /// Whether a read would not block
+    pub fn poll_read(&self) -> bool {
+        let st = self.state.lock();
+        st.hung_up || st.ldisc.readable(&st.termios)
+    }
+
+    /// Reads input, blocking according to ICANON, VMIN and VTIME.
+    pub fn read(&self, out: &mut [u8], nonblock: bool) -> Result<usize, i64> {
+        if out.is_empty() {
+            return Ok(0);
+        }
+        let (vmin, vtime) = {
+            let st = self.state.lock();
+            (st.termios.c_cc[VMIN] as usize, st.termios.c_cc[VTIME] as u64)
+        };
+        let start = crate::time::uptime_ms();
+        let mut timer_armed = vmin == 0;
+
+        loop {
+            {
+                let mut guard = self.state.lock();
+                let st = &mut *guard;
+                if st.termios.lflag(ICANON) {
+                    if !st.ldisc.ready.is_empty() {
+                        return Ok(st.ldisc.read(&st.termios, out));
+                    }
+                } else {
+                    let available = st.ldisc.available();
+                    let wanted = vmin.min(out.len());
+                    let expired = timer_armed
+                        && crate::time::uptime_ms().saturating_sub(start) >= vtime * 100;
+                    if available > 0 && (available >= wanted || expired) {
+                        return Ok(st.ldisc.read(&st.termios, out));
+                    }
+                    if vmin == 0 && (vtime == 0 || expired) {
+                        return Ok(0);
+                    }
+                    // With VMIN > 0, VTIME only runs once input started
+                    if available > 0 && vtime > 0 {
+                        timer_armed = true;
+                    }
+                }
+                if st.hung_up {
+                    return Ok(0);
+                }
+                if nonblock {
+                    return Err(errno::EAGAIN);
+                }
+            }
+            if crate::sched::has_pending_signals() {
+                return Err(errno::EINTR);
+            }
+            crate::task::yield_now();
+        }
+    }
+
+    /// Output from programs. Never blocks: the output queue is bounded and
+    /// drops what does not fit.
+    pub fn write(&self, data: &[u8]) -> Result<usize, i64> {
+        {
+            let mut guard = self.state.lock();
+            let st = &mut *guard;
+            if st.hung_up {
+                return Err(errno::EIO);
+            }
+            for &c in data {
+                st.ldisc.put_output(&st.termios, c);
+            }
+        }
+        crate::syscall::epoll::wake(self);
+        Ok(data.len())
+    }
+
+    /// Moves processed output to the device side.
+    pub fn take_output(&self, out: &mut [u8]) -> usize {
+        let mut st = self.state.lock();
+        let n = out.len().min(st.ldisc.output.len());
+        for (dst, src) in out.iter_mut().zip(st.ldisc.output.drain(..n)) {
+            *dst = src;
+        }
+        n
+    }
+
+    pub fn output_available(&self) -> usize {
+        self.state.lock().ldisc.output.len()
+    }
+
+    /// The device side went away: the session loses its terminal and the
+    /// foreground group gets SIGHUP and SIGCONT.
+    pub fn hangup(&self) {
+        let (session, pgrp) = {
+            let mut st = self.state.lock();
+            st.hung_up = true;
+            let taken = (st.session, st.pgrp);
+            st.session = 0;
+            st.pgrp = 0;
+            taken
+        };
+        if session != 0 {
+            let mut ctty = CONTROLLING_TTYS.lock();
+            if ctty.get(&session).map_or(false, |t| core::ptr::eq(Arc::as_ptr(t), self)) {
+                ctty.remove(&session);
+            }
+        }
+        crate::syscall::epoll::wake(self);
+        signal_pgrp(pgrp, sig::SIGHUP);
+        signal_pgrp(pgrp, sig::SIGCONT);
+    }
+
+    /// Job control check before a read or write by the calling task. A
+    /// background process group touching its controlling terminal gets
+    /// SIGTTIN (read) or SIGTTOU (write with TOSTOP) and the call fails
+    /// with EINTR; if the signal is blocked or ignored the read fails with
+    /// EIO and the write goes through.
+    pub fn check_background(&self, write: bool) -> Result<(), i64> {
+        let Some(task) = crate::sched::try_current_task() else {
+            return Ok(());
+        };
+        let (session, pgrp, tostop) = {
+            let st = self.state.lock();
+            (st.session, st.pgrp, st.termios.lflag(TOSTOP))
+        };
+        if session == 0 || task.sid() != session || pgrp == 0 || task.pgid() == pgrp {
+            return Ok(());
+        }
+        if write && !tostop {
+            return Ok(());
+        }
+
+        let signal = if write { sig::SIGTTOU } else { sig::SIGTTIN };
+        let ignored = task.signals().blocked_mask() & (1u64 << signal) != 0
+            || task
+                .signal_handlers()
+                .get(signal)
+                .map_or(false, |a| a.sa_handler == crate::signal::SIG_IGN);
+        if ignored {
+            return if write { Ok(()) } else { Err(errno::EIO) };
+        }
+        signal_pgrp(task.pgid(), signal);
+        Err(errno::EINTR)
+    }
+}
+
+impl Default for Tty {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn signal_pgrp(pgrp: u64, signal: u32) {
+    if pgrp != 0 {
+        let _ = crate::sched::send_signal_to_pgrp(pgrp, signal);
+    }
+}
+
+/// Controlling terminal of each session, by session ID
+static CONTROLLING_TTYS: IrqSafeMutex<BTreeMap<u64, Arc<Tty>>> = IrqSafeMutex::new(BTreeMap::new());
+
+/// The system console. Input and output still go straight to the console
+/// driver; this only holds its termios and job control state.
+static CONSOLE: Once<Arc<Tty>> = Once::new();
+
+pub fn console() -> Arc<Tty> {
+    Arc::clone(CONSOLE.call_once(|| {
+        let tty = Tty::new();
+        // The boot console starts with init's group in the foreground
+        tty.set_pgrp(1);
+        Arc::new(tty)
+    }))
+}
+
+/// Controlling terminal of session `sid`
+pub fn controlling_tty(sid: u64) -> Option<Arc<Tty>> {
+    CONTROLLING_TTYS.lock().get(&sid).cloned()
+}
+
+/// Makes `tty` the controlling terminal of session `sid`, with the session
+/// leader's group in the foreground.
+///
+/// Fails with EPERM if the session already has another terminal, or if the
+/// terminal belongs to another session and `steal` is false.
+pub fn set_controlling_tty(tty: &Arc<Tty>, sid: u64, pgrp: u64, steal: bool) -> Result<(), i64> {
+    let mut ctty = CONTROLLING_TTYS.lock();
+    if let Some(current) = ctty.get(&sid) {
+        return if Arc::ptr_eq(current, tty) { Ok(()) } else { Err(errno::EPERM) };
+    }
+    let mut st = tty.state.lock();
+    if st.session != 0 && st.session != sid {
+        if !steal {
+            return Err(errno::EPERM);
+        }
+        ctty.remove(&st.session);
+    }
+    st.session = sid;
+    st.pgrp = pgrp;
+    st.hung_up = false;
+    ctty.insert(sid, Arc::clone(tty));
+    Ok(())
+}
+
+/// Detaches session `sid` from its controlling terminal (TIOCNOTTY by the
+/// session leader). The foreground group gets SIGHUP and SIGCONT.
+pub fn drop_controlling_tty(sid: u64) {
+    let Some(tty) = CONTROLLING_TTYS.lock().remove(&sid) else {
+        return;
+    };
+    let pgrp = {
+        let mut st = tty.state.lock();
+        st.session = 0;
+        core::mem::replace(&mut st.pgrp, 0)
+    };
+    signal_pgrp(pgrp, sig::SIGHUP);
+    signal_pgrp(pgrp, sig::SIGCONT);
+}
@@ -0,0 +1,197 @@
+//! Pseudo-terminals (/dev/ptmx and /dev/pts/N).
+//!
+//! Opening /dev/ptmx allocates a pair and returns its master side; the slave
+//! shows up as /dev/pts/N once TIOCSPTLCK unlocks it. What the master writes
+//! is input to the slave's line discipline, and what programs write to the
+//! slave is read back from the master. When the last master is closed the
+//! slave hangs up.
+
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use crate::sync::IrqSafeMutex;
+use crate::syscall::errno;
+use crate::util::{KError, KResult};
+
+use super::Tty;
+
+/// Number of /dev/pts entries
+pub const MAX_PTYS: u32 = 256;
+
+/// A pseudo-terminal pair.
+pub struct Pty {
+    index: u32,
+    tty: Arc<Tty>,
+    /// Open master file descriptors
+    masters: AtomicUsize,
+    /// Open slave file descriptors
+    slaves: AtomicUsize,
+    /// Whether the slave was ever opened; master reads fail with EIO after
+    /// the last slave closes, not before the first one opens
+    slave_opened: AtomicBool,
+    /// Slave cannot be opened while locked (unlockpt clears it)
+    locked: AtomicBool,
+}
+
+static PTYS: IrqSafeMutex<BTreeMap<u32, Arc<Pty>>> = IrqSafeMutex::new(BTreeMap::new());
+
+/// Allocates a new pair and returns it with one master reference.
+pub fn open_master() -> KResult<Arc<Pty>> {
+    let mut ptys = PTYS.lock();
+    let index = (0..MAX_PTYS)
+        .find(|i| !ptys.contains_key(i))
+        .ok_or(KError::NoMemory)?;
+    let pty = Arc::new(Pty {
+        index,
+        tty: Arc::new(Tty::new()),
+        masters: AtomicUsize::new(1),
+        slaves: AtomicUsize::new(0),
+        slave_opened: AtomicBool::new(false),
+        locked: AtomicBool::new(true),
+    });
+    ptys.insert(index, Arc::clone(&pty));
+    Ok(pty)
+}
+
+/// Pair behind /dev/pts/`index`
+pub fn get(index: u32) -> Option<Arc<Pty>> {
+    PTYS.lock().get(&index).cloned()
+}
+
+/// Pair whose slave is `tty`, for opens of /dev/tty
+pub fn find_by_tty(tty: &Arc<Tty>) -> Option<Arc<Pty>> {
+    PTYS.lock().values().find(|p| Arc::ptr_eq(&p.tty, tty)).cloned()
+}
+
+/// Indices of the allocated pairs, for listing /dev/pts
+pub fn indices() -> Vec<u32> {
+    PTYS.lock().keys().copied().collect()
+}
+
+impl Pty {
+    pub fn index(&self) -> u32 {
+        self.index
+    }
+
+    /// Slave path, as returned by ptsname(3)
+    pub fn slave_path(&self) -> String {
+        alloc::format!("/dev/pts/{}", self.index)
+    }
+
+    /// The slave side's terminal
+    pub fn tty(&self) -> &Arc<Tty> {
+        &self.tty
+    }
+
+    pub fn is_locked(&self) -> bool {
+        self.locked.load(Ordering::Acquire)
+    }
+
+    pub fn set_locked(&self, locked: bool) {
+        self.locked.store(locked, Ordering::Release);
+    }
+
+    /// Takes a slave reference for an open of /dev/pts/N.
+    pub fn open_slave(&self) -> Result<(), i64> {
+        if self.is_locked() || self.masters.load(Ordering::Acquire) == 0 {
+            return Err(errno::EIO);
+        }
+        self.add_slave();
+        Ok(())
+    }
+
+    pub fn add_master(&self) {
+        self.masters.fetch_add(1, Ordering::AcqRel);
+    }
+
+    pub fn add_slave(&self) {
+        self.slave_opened.store(true, Ordering::Release);
+        self.slaves.fetch_add(1, Ordering::AcqRel);
+    }
+
+    /// Drops a master reference. The last one hangs up the slave and frees
+    /// the index.
+    pub fn close_master(&self) {
+        if self.masters.fetch_sub(1, Ordering::AcqRel) == 1 {
+            PTYS.lock().remove(&self.index);
+            self.tty.hangup();
+        }
+    }
+
+    pub fn close_slave(&self) {
+        self.slaves.fetch_sub(1, Ordering::AcqRel);
+        // The master may have just hung up
+        crate::syscall::epoll::wake(&*self.tty);
+    }
+
+    fn slave_gone(&self) -> bool {
+        self.slave_opened.load(Ordering::Acquire) && self.slaves.load(Ordering::Acquire) == 0
+    }
+
+    /// Reads slave output from the master side.
+    pub fn master_read(&self, out: &mut [u8], nonblock: bool) -> Result<usize, i64> {
+        if out.is_empty() {
+            return Ok(0);
+        }
+        loop {
+            let n = self.tty.take_output(out);
+            if n > 0 {
+                return Ok(n);
+            }
+            if self.slave_gone() {
+                return Err(errno::EIO);
+            }
+            if nonblock {
+                return Err(errno::EAGAIN);
+            }
+            if crate::sched::has_pending_signals() {
+                return Err(errno::EINTR);
+            }
+            crate::task::yield_now();
+        }
+    }
+
+    /// Master writes are keyboard input for the slave.
+    pub fn master_write(&self, data: &[u8]) -> usize {
+        self.tty.receive(data);
+        data.len()
+    }
+
+    /// Master readiness: (readable, writable, hung up)
+    pub fn poll_master(&self) -> (bool, bool, bool) {
+        let gone = self.slave_gone();
+        (self.tty.output_available() > 0 || gone, true, gone)
+    }
+
+    /// Slave readiness: (readable, writable, hung up)
+    pub fn poll_slave(&self) -> (bool, bool, bool) {
+        let hung_up = self.tty.is_hung_up();
+        (self.tty.poll_read(), !hung_up, hung_up)
+    }
+}
+
+/// Opens a new pair and starts `path` on its slave, in a new session with
+/// the slave as controlling terminal and as fds 0, 1 and 2. Returns the
+/// pair, holding one master reference, and the PID of the program.
+pub fn spawn_on_pty(path: &str, argv: &[&str], envp: &[&str]) -> KResult<(Arc<Pty>, u64)> {
+    let pty = open_master()?;
+    pty.set_locked(false);
+    for _ in 0..3 {
+        pty.add_slave();
+    }
+    let files = crate::syscall::stdio_files(crate::syscall::FdType::PtySlave {
+        pty: Arc::clone(&pty),
+        flags: crate::syscall::OpenFlags::O_RDWR,
+    });
+
+    match crate::sched::spawn_user_process(path, argv, envp, files, Some(Arc::clone(&pty.tty))) {
+        Ok(pid) => Ok((pty, pid)),
+        Err(e) => {
+            pty.close_master();
+            Err(e)
+        }
+    }
+}
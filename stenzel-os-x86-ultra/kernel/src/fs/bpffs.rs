@@ -0,0 +1,186 @@
+//! bpffs - pinned eBPF objects
+//!
+//! Mounted at /sys/fs/bpf. `BPF_OBJ_PIN` creates a file that holds a map,
+//! program or link, so it outlives the fds of the process that loaded it;
+//! `BPF_OBJ_GET` opens a new fd for it and unlinking the file drops the
+//! pin. Files can only be created through bpf(2), directories with mkdir.
+
+use alloc::collections::BTreeMap;
+use alloc::string::{String, ToString};
+use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
+
+use spin::RwLock;
+
+use crate::profiling::ebpf::BpfObject;
+use crate::security::{Cred, Gid, Uid};
+use crate::util::{KError, KResult};
+
+use super::perm;
+use super::vfs::{split_parent, unwrap_mounted, DirEntry, Inode, InodeKind, InodeOps, Metadata, Mode};
+
+enum BpfFsNode {
+    Dir {
+        children: RwLock<BTreeMap<String, Inode>>,
+    },
+    Object(BpfObject),
+}
+
+pub struct BpfFsInode {
+    self_weak: Weak<BpfFsInode>,
+    parent: RwLock<Option<Weak<BpfFsInode>>>,
+    meta: RwLock<Metadata>,
+    node: BpfFsNode,
+}
+
+/// Root of a new bpffs instance
+pub fn new_root() -> Inode {
+    let meta = Metadata::simple(Uid(0), Gid(0), Mode::from_octal(0o755), InodeKind::Dir);
+    Inode(Arc::new_cyclic(|weak| BpfFsInode {
+        self_weak: weak.clone(),
+        parent: RwLock::new(None),
+        meta: RwLock::new(meta),
+        node: BpfFsNode::Dir {
+            children: RwLock::new(BTreeMap::new()),
+        },
+    }))
+}
+
+impl BpfFsInode {
+    fn as_dir(&self) -> KResult<&RwLock<BTreeMap<String, Inode>>> {
+        match &self.node {
+            BpfFsNode::Dir { children } => Ok(children),
+            BpfFsNode::Object(_) => Err(KError::NotADirectory),
+        }
+    }
+
+    fn add(&self, name: &str, meta: Metadata, node: BpfFsNode) -> KResult<Inode> {
+        if name.is_empty() || name.contains('/') {
+            return Err(KError::Invalid);
+        }
+
+        let mut children = self.as_dir()?.write();
+        if children.contains_key(name) {
+            return Err(KError::AlreadyExists);
+        }
+
+        let parent = self.self_weak.clone();
+        let inode = Inode(Arc::new_cyclic(|weak| BpfFsInode {
+            self_weak: weak.clone(),
+            parent: RwLock::new(Some(parent)),
+            meta: RwLock::new(meta),
+            node,
+        }));
+        children.insert(name.to_string(), inode.clone());
+        Ok(inode)
+    }
+}
+
+impl InodeOps for BpfFsInode {
+    fn metadata(&self) -> Metadata {
+        *self.meta.read()
+    }
+
+    fn set_metadata(&self, meta: Metadata) {
+        *self.meta.write() = meta;
+    }
+
+    fn parent(&self) -> Option<Inode> {
+        self.parent.read().as_ref().and_then(|w| w.upgrade()).map(|arc| Inode(arc))
+    }
+
+    fn lookup(&self, name: &str) -> KResult<Inode> {
+        self.as_dir()?.read().get(name).cloned().ok_or(KError::NotFound)
+    }
+
+    fn create(&self, name: &str, kind: InodeKind, meta: Metadata) -> KResult<Inode> {
+        // Objects are pinned with bpf(2), never created by open(O_CREAT)
+        if kind != InodeKind::Dir {
+            return Err(KError::PermissionDenied);
+        }
+        self.add(name, meta, BpfFsNode::Dir {
+            children: RwLock::new(BTreeMap::new()),
+        })
+    }
+
+    fn readdir(&self) -> KResult<Vec<DirEntry>> {
+        let children = self.as_dir()?.read();
+        Ok(children
+            .iter()
+            .map(|(name, inode)| DirEntry {
+                name: name.clone(),
+                kind: inode.kind(),
+            })
+            .collect())
+    }
+
+    fn unlink(&self, name: &str) -> KResult<()> {
+        self.as_dir()?.write().remove(name).map(|_| ()).ok_or(KError::NotFound)
+    }
+
+    fn rmdir(&self, name: &str) -> KResult<()> {
+        // The VFS has already checked that the directory is empty
+        self.unlink(name)
+    }
+
+    fn read_at(&self, _offset: usize, _out: &mut [u8]) -> KResult<usize> {
+        Err(KError::Invalid)
+    }
+
+    fn write_at(&self, _offset: usize, _data: &[u8]) -> KResult<usize> {
+        Err(KError::Invalid)
+    }
+
+    fn truncate(&self, _size: usize) -> KResult<()> {
+        Err(KError::Invalid)
+    }
+
+    fn size(&self) -> KResult<usize> {
+        Ok(0)
+    }
+
+    fn as_any(&self) -> Option<&dyn core::any::Any> {
+        Some(self)
+    }
+}
+
+/// bpffs inode behind `inode`, if it is one
+fn bpffs_inode(inode: &Inode) -> Option<Arc<BpfFsInode>> {
+    let inode = unwrap_mounted(inode);
+    inode.0.as_any()?.downcast_ref::<BpfFsInode>()?.self_weak.upgrade()
+}
+
+/// Pin `object` at `path`, which must be a new name in a bpffs directory
+/// the caller can write to
+pub fn pin(path: &str, object: BpfObject, cred: &Cred) -> KResult<()> {
+    let (parent_path, name) = split_parent(path)?;
+    let parent = super::vfs_lock().resolve(parent_path, cred)?;
+
+    let meta = parent.metadata();
+    if meta.kind != InodeKind::Dir {
+        return Err(KError::NotADirectory);
+    }
+    if !perm::can_write_dir(&meta, cred) || !perm::can_exec_dir(&meta, cred) {
+        return Err(KError::PermissionDenied);
+    }
+
+    let dir = bpffs_inode(&parent).ok_or(KError::Invalid)?;
+    let meta = Metadata::simple(cred.euid, cred.egid, Mode::from_octal(0o600), InodeKind::File);
+    dir.add(name, meta, BpfFsNode::Object(object))?;
+    Ok(())
+}
+
+/// Object pinned at `path`
+pub fn get(path: &str, cred: &Cred) -> KResult<BpfObject> {
+    let inode = super::vfs_lock().resolve(path, cred)?;
+    let node = bpffs_inode(&inode).ok_or(KError::Invalid)?;
+    match &node.node {
+        BpfFsNode::Object(object) => {
+            if !perm::can_read(&node.metadata(), cred) {
+                return Err(KError::PermissionDenied);
+            }
+            Ok(object.clone())
+        }
+        BpfFsNode::Dir { .. } => Err(KError::Invalid),
+    }
+}
@@ -0,0 +1,501 @@
+//! cgroup2 - the cgroup v2 interface
+//!
+//! Mounted at /sys/fs/cgroup. Every directory is a cgroup of
+//! `crate::cgroups`: mkdir creates a child and rmdir removes it once it
+//! has no tasks and no children left. The control files are generated
+//! from the controllers on every read, and writing them changes the
+//! limits. The files of a controller only show up in cgroups whose parent
+//! enabled it in `cgroup.subtree_control`.
+
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
+use core::fmt::Write;
+use core::sync::atomic::Ordering;
+
+use crate::cgroups::{self, Cgroup, CONTROLLERS};
+use crate::process::Pid;
+use crate::security::{Gid, Uid};
+use crate::storage::iosched;
+use crate::util::{KError, KResult};
+
+use super::vfs::{DirEntry, Inode, InodeKind, InodeOps, Metadata, Mode};
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum CgroupFile {
+    Procs,
+    Controllers,
+    SubtreeControl,
+    CpuMax,
+    CpuStat,
+    MemoryMax,
+    MemoryCurrent,
+    MemoryEvents,
+    MemoryStat,
+    PidsMax,
+    PidsCurrent,
+    PidsEvents,
+    IoMax,
+    IoStat,
+}
+
+/// A control file and where it shows up
+struct FileEntry {
+    name: &'static str,
+    file: CgroupFile,
+    /// Controller the file belongs to (None: core file, always present)
+    controller: Option<&'static str>,
+    writable: bool,
+    /// Also present in the root cgroup
+    on_root: bool,
+}
+
+const fn entry(
+    name: &'static str,
+    file: CgroupFile,
+    controller: Option<&'static str>,
+    writable: bool,
+    on_root: bool,
+) -> FileEntry {
+    FileEntry { name, file, controller, writable, on_root }
+}
+
+static FILES: [FileEntry; 14] = [
+    entry("cgroup.procs", CgroupFile::Procs, None, true, true),
+    entry("cgroup.controllers", CgroupFile::Controllers, None, false, true),
+    entry("cgroup.subtree_control", CgroupFile::SubtreeControl, None, true, true),
+    entry("cpu.max", CgroupFile::CpuMax, Some("cpu"), true, false),
+    entry("cpu.stat", CgroupFile::CpuStat, None, false, true),
+    entry("memory.max", CgroupFile::MemoryMax, Some("memory"), true, false),
+    entry("memory.current", CgroupFile::MemoryCurrent, Some("memory"), false, false),
+    entry("memory.events", CgroupFile::MemoryEvents, Some("memory"), false, false),
+    entry("memory.stat", CgroupFile::MemoryStat, Some("memory"), false, true),
+    entry("pids.max", CgroupFile::PidsMax, Some("pids"), true, false),
+    entry("pids.current", CgroupFile::PidsCurrent, Some("pids"), false, false),
+    entry("pids.events", CgroupFile::PidsEvents, Some("pids"), false, false),
+    entry("io.max", CgroupFile::IoMax, Some("io"), true, false),
+    entry("io.stat", CgroupFile::IoStat, Some("io"), false, true),
+];
+
+enum CgroupNode {
+    Dir,
+    File(&'static FileEntry),
+}
+
+pub struct CgroupFsInode {
+    self_weak: Weak<CgroupFsInode>,
+    parent: Option<Weak<CgroupFsInode>>,
+    cgroup: Arc<Cgroup>,
+    node: CgroupNode,
+}
+
+/// Root of a cgroup2 mount: the root of the cgroup hierarchy
+pub fn new_root() -> Inode {
+    Inode(CgroupFsInode::new(None, cgroups::manager().root(), CgroupNode::Dir))
+}
+
+/// Bit of `name` in the controller masks
+fn controller_bit(name: &str) -> Option<u32> {
+    CONTROLLERS.iter().position(|&c| c == name).map(|i| 1 << i)
+}
+
+/// Names of the controllers in `mask`, as listed by cgroup.controllers
+fn controller_list(mask: u32) -> String {
+    let names: Vec<&str> = CONTROLLERS
+        .iter()
+        .enumerate()
+        .filter(|(i, _)| mask & (1 << i) != 0)
+        .map(|(_, &name)| name)
+        .collect();
+    format!("{}\n", names.join(" "))
+}
+
+/// "max" (None) or a number
+fn parse_max(value: &str) -> KResult<Option<u64>> {
+    match value {
+        "max" => Ok(None),
+        _ => value.parse().map(Some).map_err(|_| KError::Invalid),
+    }
+}
+
+/// Byte count with an optional K/M/G/T suffix, or "max"
+fn parse_bytes(value: &str) -> KResult<Option<u64>> {
+    if value == "max" {
+        return Ok(None);
+    }
+    let (digits, shift) = match value.as_bytes().last() {
+        Some(b'k' | b'K') => (&value[..value.len() - 1], 10),
+        Some(b'm' | b'M') => (&value[..value.len() - 1], 20),
+        Some(b'g' | b'G') => (&value[..value.len() - 1], 30),
+        Some(b't' | b'T') => (&value[..value.len() - 1], 40),
+        _ => (value, 0),
+    };
+    let n: u64 = digits.parse().map_err(|_| KError::Invalid)?;
+    n.checked_mul(1 << shift).map(Some).ok_or(KError::OutOfRange)
+}
+
+fn fmt_max(value: u64, unlimited: u64) -> String {
+    if value == unlimited {
+        "max".to_string()
+    } else {
+        value.to_string()
+    }
+}
+
+impl CgroupFsInode {
+    fn new(parent: Option<Weak<CgroupFsInode>>, cgroup: Arc<Cgroup>, node: CgroupNode) -> Arc<Self> {
+        Arc::new_cyclic(|weak| CgroupFsInode {
+            self_weak: weak.clone(),
+            parent,
+            cgroup,
+            node,
+        })
+    }
+
+    fn is_root(&self) -> bool {
+        self.cgroup.parent.is_none()
+    }
+
+    /// Control files of this cgroup
+    fn files(&self) -> impl Iterator<Item = &'static FileEntry> + '_ {
+        let enabled = self.cgroup.controllers();
+        FILES.iter().filter(move |f| {
+            let controller_on = f.controller.and_then(controller_bit).map_or(true, |bit| enabled & bit != 0);
+            controller_on && (f.on_root || !self.is_root())
+        })
+    }
+
+    fn require_dir(&self) -> KResult<()> {
+        match self.node {
+            CgroupNode::Dir => Ok(()),
+            CgroupNode::File(_) => Err(KError::NotADirectory),
+        }
+    }
+
+    /// Current contents of a control file
+    fn generate(&self, file: CgroupFile) -> String {
+        let cg = &self.cgroup;
+        let mut out = String::new();
+        match file {
+            CgroupFile::Procs => {
+                let mgr = cgroups::manager();
+                for pid in crate::sched::list_pids() {
+                    let is_process = crate::sched::get_task_info(pid).map_or(false, |info| info.tgid == pid);
+                    if is_process && mgr.task_cgroup(Pid(pid)).id == cg.id {
+                        let _ = writeln!(out, "{}", pid);
+                    }
+                }
+            }
+            CgroupFile::Controllers => out = controller_list(cg.controllers()),
+            CgroupFile::SubtreeControl => out = controller_list(cg.subtree_control.load(Ordering::Acquire)),
+            CgroupFile::CpuMax => {
+                let quota = cg.cpu.cfs_quota_us.load(Ordering::Acquire);
+                let period = cg.cpu.cfs_period_us.load(Ordering::Acquire);
+                let _ = writeln!(out, "{} {}", fmt_max(quota, u64::MAX), period);
+            }
+            CgroupFile::CpuStat => {
+                let _ = writeln!(out, "usage_usec {}", cg.cpu.total_usage_ns.load(Ordering::Acquire) / 1000);
+                let _ = writeln!(out, "nr_periods {}", cg.cpu.nr_periods.load(Ordering::Acquire));
+                let _ = writeln!(out, "nr_throttled {}", cg.cpu.nr_throttled.load(Ordering::Acquire));
+                let _ = writeln!(out, "throttled_usec {}", cg.cpu.throttled_time_ns.load(Ordering::Acquire) / 1000);
+            }
+            CgroupFile::MemoryMax => {
+                let _ = writeln!(out, "{}", fmt_max(cg.memory.limit_bytes.load(Ordering::Acquire), u64::MAX));
+            }
+            CgroupFile::MemoryCurrent => {
+                let _ = writeln!(out, "{}", cg.memory.usage_bytes.load(Ordering::Acquire));
+            }
+            CgroupFile::MemoryEvents => {
+                let _ = writeln!(out, "max {}", cg.memory.failcnt.load(Ordering::Acquire));
+                let _ = writeln!(out, "oom {}", cg.memory.oom_events.load(Ordering::Acquire));
+                let _ = writeln!(out, "oom_kill {}", cg.memory.oom_kills.load(Ordering::Acquire));
+            }
+            CgroupFile::MemoryStat => {
+                // Only anonymous user pages are charged
+                let _ = writeln!(out, "anon {}", cg.memory.usage_bytes.load(Ordering::Acquire));
+            }
+            CgroupFile::PidsMax => {
+                let _ = writeln!(out, "{}", fmt_max(cg.pids.max.load(Ordering::Acquire), 0));
+            }
+            CgroupFile::PidsCurrent => {
+                let _ = writeln!(out, "{}", cg.pids.count());
+            }
+            CgroupFile::PidsEvents => {
+                let _ = writeln!(out, "max {}", cg.pids.events_max.load(Ordering::Acquire));
+            }
+            CgroupFile::IoMax => {
+                for (device, state) in cg.io.devices.lock().iter() {
+                    if state.rbps | state.wbps | state.riops | state.wiops == 0 {
+                        continue;
+                    }
+                    let _ = writeln!(
+                        out,
+                        "{} rbps={} wbps={} riops={} wiops={}",
+                        iosched::device_key(*device),
+                        fmt_max(state.rbps, 0),
+                        fmt_max(state.wbps, 0),
+                        fmt_max(state.riops, 0),
+                        fmt_max(state.wiops, 0),
+                    );
+                }
+            }
+            CgroupFile::IoStat => {
+                for (device, state) in cg.io.devices.lock().iter() {
+                    if state.rios + state.wios == 0 {
+                        continue;
+                    }
+                    let _ = writeln!(
+                        out,
+                        "{} rbytes={} wbytes={} rios={} wios={} dbytes=0 dios=0",
+                        iosched::device_key(*device),
+                        state.rbytes,
+                        state.wbytes,
+                        state.rios,
+                        state.wios,
+                    );
+                }
+            }
+        }
+        out
+    }
+
+    /// Apply a write to a control file
+    fn apply(&self, file: CgroupFile, text: &str) -> KResult<()> {
+        let cg = &self.cgroup;
+        match file {
+            CgroupFile::Procs => {
+                let pid: u64 = text.parse().map_err(|_| KError::Invalid)?;
+                let pid = match pid {
+                    0 => crate::sched::current_task().tgid(),
+                    pid => pid,
+                };
+                // The whole thread group moves
+                let tgid = crate::sched::get_task_info(pid).ok_or(KError::NotFound)?.tgid;
+                let mgr = cgroups::manager();
+                for tid in crate::sched::thread_group(tgid) {
+                    mgr.attach_process(Pid(tid), cg);
+                }
+                Ok(())
+            }
+            CgroupFile::SubtreeControl => {
+                let mut mask = cg.subtree_control.load(Ordering::Acquire);
+                for token in text.split_whitespace() {
+                    let (enable, name) = match token.as_bytes()[0] {
+                        b'+' => (true, &token[1..]),
+                        b'-' => (false, &token[1..]),
+                        _ => return Err(KError::Invalid),
+                    };
+                    let bit = controller_bit(name).ok_or(KError::NotFound)?;
+                    if enable {
+                        if cg.controllers() & bit == 0 {
+                            return Err(KError::NotFound);
+                        }
+                        mask |= bit;
+                    } else {
+                        // Children still handing it down keep it in use
+                        let in_use = cg
+                            .children
+                            .lock()
+                            .values()
+                            .any(|child| child.subtree_control.load(Ordering::Acquire) & bit != 0);
+                        if in_use {
+                            return Err(KError::Busy);
+                        }
+                        mask &= !bit;
+                    }
+                }
+                cg.subtree_control.store(mask, Ordering::Release);
+                Ok(())
+            }
+            CgroupFile::CpuMax => {
+                let mut fields = text.split_whitespace();
+                let quota = parse_max(fields.next().ok_or(KError::Invalid)?)?;
+                let period = fields.next().map(|p| p.parse::<u64>().map_err(|_| KError::Invalid)).transpose()?;
+                if fields.next().is_some() || quota.map_or(false, |q| q < 1000) {
+                    return Err(KError::Invalid);
+                }
+                if let Some(period) = period {
+                    if !(1000..=1_000_000).contains(&period) {
+                        return Err(KError::Invalid);
+                    }
+                    cg.cpu.set_period(period);
+                }
+                cg.cpu.set_quota(quota.map_or(-1, |q| q as i64));
+                Ok(())
+            }
+            CgroupFile::MemoryMax => {
+                let limit = match parse_bytes(text)? {
+                    Some(bytes) => bytes & !(cgroups::PAGE_SIZE as u64 - 1),
+                    None => u64::MAX,
+                };
+                cg.memory.set_limit(limit);
+                Ok(())
+            }
+            CgroupFile::PidsMax => {
+                // 0 means unlimited in the controller, so it cannot be set
+                match parse_max(text)? {
+                    Some(0) => Err(KError::Invalid),
+                    Some(max) => {
+                        cg.pids.set_max(max);
+                        Ok(())
+                    }
+                    None => {
+                        cg.pids.set_max(0);
+                        Ok(())
+                    }
+                }
+            }
+            CgroupFile::IoMax => {
+                for line in text.lines().filter(|l| !l.trim().is_empty()) {
+                    let mut fields = line.split_whitespace();
+                    let device = fields.next().and_then(iosched::parse_device_key).ok_or(KError::Invalid)?;
+                    let mut limits = [None; 4];
+                    for field in fields {
+                        let (key, value) = field.split_once('=').ok_or(KError::Invalid)?;
+                        let slot = match key {
+                            "rbps" => 0,
+                            "wbps" => 1,
+                            "riops" => 2,
+                            "wiops" => 3,
+                            _ => return Err(KError::Invalid),
+                        };
+                        limits[slot] = Some(parse_max(value)?.unwrap_or(0));
+                    }
+                    cg.io.set_device_limits(device, limits);
+                }
+                Ok(())
+            }
+            _ => Err(KError::PermissionDenied),
+        }
+    }
+}
+
+impl InodeOps for CgroupFsInode {
+    fn metadata(&self) -> Metadata {
+        match self.node {
+            CgroupNode::Dir => Metadata::with_ino(
+                Uid(0),
+                Gid(0),
+                Mode::from_octal(0o755),
+                InodeKind::Dir,
+                self.cgroup.id + 1,
+            ),
+            CgroupNode::File(f) => {
+                let mode = if f.writable { 0o644 } else { 0o444 };
+                Metadata::simple(Uid(0), Gid(0), Mode::from_octal(mode), InodeKind::File)
+            }
+        }
+    }
+
+    fn set_metadata(&self, _meta: Metadata) {
+        // Ownership and modes are fixed
+    }
+
+    fn parent(&self) -> Option<Inode> {
+        self.parent.as_ref().and_then(|w| w.upgrade()).map(|arc| Inode(arc))
+    }
+
+    fn lookup(&self, name: &str) -> KResult<Inode> {
+        self.require_dir()?;
+        let parent = Some(self.self_weak.clone());
+        if let Some(file) = self.files().find(|f| f.name == name) {
+            return Ok(Inode(Self::new(parent, self.cgroup.clone(), CgroupNode::File(file))));
+        }
+        let child = self.cgroup.get_child(name).ok_or(KError::NotFound)?;
+        Ok(Inode(Self::new(parent, child, CgroupNode::Dir)))
+    }
+
+    fn create(&self, name: &str, kind: InodeKind, _meta: Metadata) -> KResult<Inode> {
+        self.require_dir()?;
+        // Control files exist on their own; only cgroups can be created
+        if kind != InodeKind::Dir {
+            return Err(KError::PermissionDenied);
+        }
+        if name.is_empty() || name.contains('/') {
+            return Err(KError::Invalid);
+        }
+        if FILES.iter().any(|f| f.name == name) || self.cgroup.get_child(name).is_some() {
+            return Err(KError::AlreadyExists);
+        }
+        let child = cgroups::manager().create_child(&self.cgroup, name).ok_or(KError::OutOfRange)?;
+        Ok(Inode(Self::new(Some(self.self_weak.clone()), child, CgroupNode::Dir)))
+    }
+
+    fn readdir(&self) -> KResult<Vec<DirEntry>> {
+        self.require_dir()?;
+        let mut entries: Vec<DirEntry> = self
+            .files()
+            .map(|f| DirEntry {
+                name: f.name.to_string(),
+                kind: InodeKind::File,
+            })
+            .collect();
+        entries.extend(self.cgroup.children.lock().keys().map(|name| DirEntry {
+            name: name.clone(),
+            kind: InodeKind::Dir,
+        }));
+        Ok(entries)
+    }
+
+    fn is_empty_dir(&self) -> KResult<bool> {
+        self.require_dir()?;
+        Ok(self.cgroup.children.lock().is_empty())
+    }
+
+    fn rmdir(&self, name: &str) -> KResult<()> {
+        self.require_dir()?;
+        if self.cgroup.get_child(name).is_none() {
+            return Err(KError::NotFound);
+        }
+        // The VFS has already checked there are no child cgroups; tasks
+        // still in it keep it busy
+        if self.cgroup.remove_child(name) {
+            Ok(())
+        } else {
+            Err(KError::Busy)
+        }
+    }
+
+    fn read_at(&self, offset: usize, out: &mut [u8]) -> KResult<usize> {
+        let file = match self.node {
+            CgroupNode::File(f) => f.file,
+            CgroupNode::Dir => return Err(KError::Invalid),
+        };
+        let data = self.generate(file);
+        let data = data.as_bytes();
+        if offset >= data.len() {
+            return Ok(0);
+        }
+        let n = core::cmp::min(out.len(), data.len() - offset);
+        out[..n].copy_from_slice(&data[offset..offset + n]);
+        Ok(n)
+    }
+
+    fn write_at(&self, _offset: usize, data: &[u8]) -> KResult<usize> {
+        let entry = match self.node {
+            CgroupNode::File(f) if f.writable => f,
+            CgroupNode::File(_) => return Err(KError::PermissionDenied),
+            CgroupNode::Dir => return Err(KError::Invalid),
+        };
+        let text = core::str::from_utf8(data).map_err(|_| KError::Invalid)?;
+        self.apply(entry.file, text.trim())?;
+        Ok(data.len())
+    }
+
+    fn truncate(&self, _size: usize) -> KResult<()> {
+        // O_TRUNC from shell redirections; the contents are generated
+        Ok(())
+    }
+
+    fn size(&self) -> KResult<usize> {
+        match self.node {
+            CgroupNode::File(f) => Ok(self.generate(f.file).len()),
+            CgroupNode::Dir => Ok(0),
+        }
+    }
+
+    fn as_any(&self) -> Option<&dyn core::any::Any> {
+        Some(self)
+    }
+}
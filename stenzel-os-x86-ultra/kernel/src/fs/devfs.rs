@@ -1,6 +1,7 @@
 //! devfs - Virtual filesystem para device nodes.
 //!
-//! Expõe dispositivos em /dev (null, zero, urandom, tty, console).
+//! Expõe dispositivos em /dev (null, zero, random, urandom, tty, console,
+//! ptmx, pts/N).
 
 #![allow(dead_code)]
 
@@ -25,8 +26,10 @@ enum DeviceType {
     Null,
     /// /dev/zero - retorna zeros, descarta escrita
     Zero,
-    /// /dev/urandom - retorna bytes pseudo-aleatórios
+    /// /dev/urandom - bytes do CSPRNG do kernel
     Urandom,
+    /// /dev/random - como /dev/urandom, mas bloqueia até o CSPRNG ser semeado
+    Random,
     /// /dev/tty - terminal atual
     Tty,
     /// /dev/console - console do sistema
@@ -43,32 +46,39 @@ enum DeviceType {
     InputEvent(u8),
     /// /dev/fb0 - framebuffer device
     Fb0,
+    /// /dev/ptmx - aloca um pseudo-terminal novo
+    Ptmx,
+    /// /dev/pts - diretório dos slaves de pty
+    PtsDir,
+    /// /dev/pts/N - slave do pty N
+    Pts(u32),
 }
 
-/// Estado do PRNG para /dev/urandom
-static URANDOM_STATE: spin::Mutex<u64> = spin::Mutex::new(0x853c49e6748fea9b);
+/// Terminal aberto através de um inode do devfs.
+pub enum TtyNode {
+    /// /dev/ptmx
+    Ptmx,
+    /// /dev/pts/N
+    Pts(u32),
+    /// /dev/tty
+    Tty,
+}
 
-/// Gera um byte pseudo-aleatório usando xorshift64
-pub fn random_byte() -> u8 {
-    let mut state = URANDOM_STATE.lock();
-    // xorshift64
-    *state ^= *state << 13;
-    *state ^= *state >> 7;
-    *state ^= *state << 17;
-    (*state & 0xFF) as u8
+/// Terminal por trás de um inode do devfs, para o open(2) criar o fd certo.
+pub fn tty_node_of(inode: &Inode) -> Option<TtyNode> {
+    let inner = super::vfs::unwrap_mounted(inode);
+    let dev_inode = inner.0.as_any()?.downcast_ref::<DevfsInode>()?;
+    match dev_inode.device {
+        DeviceType::Ptmx => Some(TtyNode::Ptmx),
+        DeviceType::Pts(n) => Some(TtyNode::Pts(n)),
+        DeviceType::Tty => Some(TtyNode::Tty),
+        _ => None,
+    }
 }
 
-/// Semeia o PRNG com entropia do TSC
-pub fn seed_urandom() {
-    let tsc = unsafe {
-        core::arch::x86_64::_rdtsc()
-    };
-    let mut state = URANDOM_STATE.lock();
-    *state ^= tsc;
-    // Mix adicional
-    *state ^= *state << 13;
-    *state ^= *state >> 7;
-    *state ^= *state << 17;
+/// Um byte do CSPRNG do kernel (o mesmo de /dev/urandom)
+pub fn random_byte() -> u8 {
+    crate::crypto::random::get_random_u8()
 }
 
 /// Inode do devfs.
@@ -81,24 +91,30 @@ pub struct DevfsInode {
 impl DevfsInode {
     fn metadata_for_device(device: DeviceType) -> Metadata {
         match device {
-            DeviceType::Root | DeviceType::InputDir => Metadata::simple(
+            DeviceType::Root | DeviceType::InputDir | DeviceType::PtsDir => Metadata::simple(
                 Uid(0),
                 Gid(0),
                 Mode::from_octal(0o755),
                 InodeKind::Dir,
             ),
-            DeviceType::Null | DeviceType::Zero | DeviceType::Urandom => Metadata::simple(
+            DeviceType::Null | DeviceType::Zero | DeviceType::Urandom | DeviceType::Random => Metadata::simple(
                 Uid(0),
                 Gid(0),
                 Mode::from_octal(0o666),
                 InodeKind::CharDev,
             ),
-            DeviceType::Tty | DeviceType::Console => Metadata::simple(
+            DeviceType::Tty | DeviceType::Console | DeviceType::Pts(_) => Metadata::simple(
                 Uid(0),
                 Gid(0),
                 Mode::from_octal(0o620),
                 InodeKind::CharDev,
             ),
+            DeviceType::Ptmx => Metadata::simple(
+                Uid(0),
+                Gid(0),
+                Mode::from_octal(0o666),
+                InodeKind::CharDev,
+            ),
             DeviceType::Stdin | DeviceType::Stdout | DeviceType::Stderr => Metadata::simple(
                 Uid(0),
                 Gid(0),
@@ -135,6 +151,8 @@ impl DevfsInode {
                     DirEntry { name: "stdout".to_string(), kind: InodeKind::Symlink },
                     DirEntry { name: "stderr".to_string(), kind: InodeKind::Symlink },
                     DirEntry { name: "input".to_string(), kind: InodeKind::Dir },
+                    DirEntry { name: "ptmx".to_string(), kind: InodeKind::CharDev },
+                    DirEntry { name: "pts".to_string(), kind: InodeKind::Dir },
                 ];
                 // Add fb0 if framebuffer is available
                 if crate::drivers::framebuffer::is_available() {
@@ -153,6 +171,13 @@ impl DevfsInode {
                 }
                 entries
             }
+            DeviceType::PtsDir => crate::drivers::tty::pty::indices()
+                .into_iter()
+                .map(|n| DirEntry {
+                    name: alloc::format!("{}", n),
+                    kind: InodeKind::CharDev,
+                })
+                .collect(),
             _ => Vec::new(),
         }
     }
@@ -163,13 +188,16 @@ impl DevfsInode {
             DeviceType::Root => match name {
                 "null" => Ok(DeviceType::Null),
                 "zero" => Ok(DeviceType::Zero),
-                "urandom" | "random" => Ok(DeviceType::Urandom),
+                "urandom" => Ok(DeviceType::Urandom),
+                "random" => Ok(DeviceType::Random),
                 "tty" => Ok(DeviceType::Tty),
                 "console" => Ok(DeviceType::Console),
                 "stdin" => Ok(DeviceType::Stdin),
                 "stdout" => Ok(DeviceType::Stdout),
                 "stderr" => Ok(DeviceType::Stderr),
                 "input" => Ok(DeviceType::InputDir),
+                "ptmx" => Ok(DeviceType::Ptmx),
+                "pts" => Ok(DeviceType::PtsDir),
                 "fb0" => {
                     if crate::drivers::framebuffer::is_available() {
                         Ok(DeviceType::Fb0)
@@ -191,6 +219,10 @@ impl DevfsInode {
                 }
                 Err(KError::NotFound)
             }
+            DeviceType::PtsDir => match name.parse::<u32>() {
+                Ok(n) if crate::drivers::tty::pty::get(n).is_some() => Ok(DeviceType::Pts(n)),
+                _ => Err(KError::NotFound),
+            },
             _ => Err(KError::Invalid),
         }
     }
@@ -248,10 +280,13 @@ impl InodeOps for DevfsInode {
                 Ok(out.len())
             }
             DeviceType::Urandom => {
-                // /dev/urandom retorna bytes aleatórios
-                for b in out.iter_mut() {
-                    *b = random_byte();
-                }
+                // /dev/urandom nunca bloqueia
+                crate::crypto::random::fill_random(out);
+                Ok(out.len())
+            }
+            DeviceType::Random => {
+                crate::crypto::random::wait_until_seeded();
+                crate::crypto::random::fill_random(out);
                 Ok(out.len())
             }
             DeviceType::Tty | DeviceType::Console => {
@@ -290,16 +325,23 @@ impl InodeOps for DevfsInode {
                     Err(KError::NotFound)
                 }
             }
-            DeviceType::Root | DeviceType::InputDir => Err(KError::Invalid),
+            // Ptys são lidos pelos fds que o open(2) cria, não pelo inode
+            DeviceType::Ptmx | DeviceType::Pts(_) => Err(KError::NotSupported),
+            DeviceType::Root | DeviceType::InputDir | DeviceType::PtsDir => Err(KError::Invalid),
         }
     }
 
     fn write_at(&self, _offset: usize, data: &[u8]) -> KResult<usize> {
         match self.device {
-            DeviceType::Null | DeviceType::Zero | DeviceType::Urandom => {
+            DeviceType::Null | DeviceType::Zero => {
                 // Descarta dados
                 Ok(data.len())
             }
+            DeviceType::Urandom | DeviceType::Random => {
+                // Misturado ao pool de entropia, sem creditar entropia
+                crate::crypto::random::add_entropy(data, 0);
+                Ok(data.len())
+            }
             DeviceType::Tty | DeviceType::Console => {
                 // Escreve no console do kernel
                 for &b in data {
@@ -334,18 +376,23 @@ impl InodeOps for DevfsInode {
                     Err(KError::NotFound)
                 }
             }
-            DeviceType::Root | DeviceType::InputDir => Err(KError::Invalid),
+            DeviceType::Ptmx | DeviceType::Pts(_) => Err(KError::NotSupported),
+            DeviceType::Root | DeviceType::InputDir | DeviceType::PtsDir => Err(KError::Invalid),
         }
     }
 
     fn truncate(&self, _size: usize) -> KResult<()> {
         // Dispositivos não podem ser truncados
         match self.device {
-            DeviceType::Null | DeviceType::Zero | DeviceType::Urandom | DeviceType::Fb0 => Ok(()),
+            DeviceType::Null | DeviceType::Zero | DeviceType::Urandom | DeviceType::Random | DeviceType::Fb0 => Ok(()),
             _ => Err(KError::Invalid),
         }
     }
 
+    fn as_any(&self) -> Option<&dyn core::any::Any> {
+        Some(self)
+    }
+
     fn size(&self) -> KResult<usize> {
         match self.device {
             DeviceType::Fb0 => {
@@ -362,9 +409,6 @@ impl InodeOps for DevfsInode {
 
 /// Cria o inode raiz do devfs (/dev).
 pub fn new_root() -> Inode {
-    // Semeia o PRNG
-    seed_urandom();
-
     let inode: Arc<DevfsInode> = Arc::new_cyclic(|weak| DevfsInode {
         device: DeviceType::Root,
         parent: RwLock::new(None),
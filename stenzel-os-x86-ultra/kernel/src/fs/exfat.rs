@@ -1,4 +1,4 @@
-//! exFAT filesystem driver (read-only)
+//! exFAT filesystem driver (read/write)
 //!
 //! exFAT (Extended File Allocation Table) is a file system developed by Microsoft
 //! for flash drives. It overcomes the 4GB file size limit of FAT32.
@@ -10,23 +10,37 @@
 //! - UTF-16 long file names
 //! - File integrity checksum
 //!
+//! Write support:
+//! - Clusters are allocated from the allocation bitmap. New files are laid out
+//!   contiguously (NoFatChain) when possible and only get a FAT chain once they
+//!   can no longer grow in place.
+//! - Directory entry sets carry a correct SetChecksum, and NameHash is computed
+//!   with the volume's up-case table.
+//! - The VolumeDirty flag is set while a modification is in progress.
+//!
+//! Volumes with two FATs (TexFAT), or without a usable allocation bitmap or
+//! up-case table, are mounted read-only.
+//!
 //! References:
 //! - Microsoft exFAT specification (available under OIN license)
 //! - https://wiki.osdev.org/ExFAT
 
 #![allow(dead_code)]
 
+use alloc::collections::{BTreeMap, BTreeSet};
 use alloc::string::String;
-use alloc::sync::Arc;
+use alloc::sync::{Arc, Weak};
 use alloc::vec;
 use alloc::vec::Vec;
-use spin::RwLock;
+use core::any::Any;
+use spin::{Mutex, RwLock};
 
 use crate::security::{Gid, Uid};
 use crate::storage::BlockDevice;
+use crate::time::{civil_from_days, days_from_civil};
 use crate::util::{KError, KResult};
 
-use super::vfs::{DirEntry, Inode, InodeKind, InodeOps, Metadata, Mode};
+use super::vfs::{self, DirEntry, FsStats, Inode, InodeKind, InodeOps, Metadata, Mode, Timespec};
 
 // ============================================================================
 // Constants
@@ -35,6 +49,9 @@ use super::vfs::{DirEntry, Inode, InodeKind, InodeOps, Metadata, Mode};
 /// exFAT signature "EXFAT   " at offset 3
 const EXFAT_SIGNATURE: [u8; 8] = *b"EXFAT   ";
 
+/// statfs f_type reported by Linux for exFAT volumes
+const EXFAT_SUPER_MAGIC: u64 = 0x2011_BAB0;
+
 /// Boot signature
 const BOOT_SIGNATURE: u16 = 0xAA55;
 
@@ -57,6 +74,14 @@ const ENTRY_TYPE_FILE_NAME: u8 = 0xC1;
 const ENTRY_TYPE_VENDOR_EXT: u8 = 0xE0;
 const ENTRY_TYPE_VENDOR_ALLOC: u8 = 0xE1;
 
+/// Bit 7 of the entry type: entry in use (cleared on deletion)
+const ENTRY_IN_USE: u8 = 0x80;
+const ENTRY_SIZE: usize = 32;
+const NAME_CHARS_PER_ENTRY: usize = 15;
+const MAX_NAME_LEN: usize = 255;
+/// Directories may not grow beyond 256 MiB
+const MAX_DIR_SIZE: u64 = 256 * 1024 * 1024;
+
 /// File attributes
 const ATTR_READ_ONLY: u16 = 0x0001;
 const ATTR_HIDDEN: u16 = 0x0002;
@@ -68,6 +93,17 @@ const ATTR_ARCHIVE: u16 = 0x0020;
 const STREAM_FLAG_ALLOC_POSSIBLE: u8 = 0x01;
 const STREAM_FLAG_NO_FAT_CHAIN: u8 = 0x02;
 
+/// Boot sector fields that are excluded from the boot checksum
+const BOOT_VOLUME_FLAGS: usize = 106;
+const BOOT_PERCENT_IN_USE: usize = 112;
+
+/// Volume flags
+const VOLUME_FLAG_ACTIVE_FAT: u16 = 0x0001;
+const VOLUME_FLAG_DIRTY: u16 = 0x0002;
+
+/// Timestamp UTC offset byte: offset is valid
+const UTC_OFFSET_VALID: u8 = 0x80;
+
 // ============================================================================
 // On-disk structures (packed, little-endian)
 // ============================================================================
@@ -222,6 +258,24 @@ struct ExfatBitmapEntry {
     data_length: u64,
 }
 
+/// Up-case table entry (type 0x82)
+#[repr(C, packed)]
+#[derive(Debug, Clone, Copy)]
+struct ExfatUpcaseEntry {
+    /// Entry type (0x82)
+    entry_type: u8,
+    /// Reserved
+    reserved1: [u8; 3],
+    /// Table checksum
+    table_checksum: u32,
+    /// Reserved
+    reserved2: [u8; 12],
+    /// First cluster
+    first_cluster: u32,
+    /// Data length
+    data_length: u64,
+}
+
 /// Volume label entry (type 0x83)
 #[repr(C, packed)]
 #[derive(Debug, Clone, Copy)]
@@ -236,6 +290,150 @@ struct ExfatVolumeLabelEntry {
     reserved: [u8; 8],
 }
 
+fn read_entry<T: Copy>(data: &[u8]) -> T {
+    assert!(data.len() >= core::mem::size_of::<T>());
+    unsafe { core::ptr::read_unaligned(data.as_ptr() as *const T) }
+}
+
+fn write_entry<T: Copy>(data: &mut [u8], value: T) {
+    assert!(data.len() >= core::mem::size_of::<T>());
+    unsafe { core::ptr::write_unaligned(data.as_mut_ptr() as *mut T, value) }
+}
+
+// ============================================================================
+// Checksums, names and timestamps
+// ============================================================================
+
+/// EntrySetChecksum: every byte of the set except the checksum field itself
+fn entry_set_checksum(set: &[u8]) -> u16 {
+    let mut checksum = 0u16;
+    for (i, &b) in set.iter().enumerate() {
+        if i == 2 || i == 3 {
+            continue;
+        }
+        checksum = ((checksum & 1) << 15 | checksum >> 1).wrapping_add(b as u16);
+    }
+    checksum
+}
+
+/// Checksum of the (compressed) up-case table
+fn upcase_table_checksum(data: &[u8]) -> u32 {
+    let mut checksum = 0u32;
+    for &b in data {
+        checksum = ((checksum & 1) << 31 | checksum >> 1).wrapping_add(b as u32);
+    }
+    checksum
+}
+
+/// Expand a compressed up-case table (0xFFFF, n = n identity-mapped characters)
+fn expand_upcase_table(data: &[u8]) -> Vec<u16> {
+    let mut table: Vec<u16> = (0..=0xFFFFu32).map(|c| c as u16).collect();
+    let mut units = data.chunks_exact(2).map(|c| u16::from_le_bytes([c[0], c[1]]));
+    let mut index = 0usize;
+
+    while index < table.len() {
+        match units.next() {
+            Some(0xFFFF) => match units.next() {
+                Some(skip) => index += skip as usize,
+                None => break,
+            },
+            Some(upper) => {
+                table[index] = upper;
+                index += 1;
+            }
+            None => break,
+        }
+    }
+
+    table
+}
+
+/// Up-case table used when the volume has none: ASCII letters only
+fn ascii_upcase_table() -> Vec<u16> {
+    (0..=0xFFFFu32)
+        .map(|c| {
+            let c = c as u16;
+            if (b'a' as u16..=b'z' as u16).contains(&c) { c - 32 } else { c }
+        })
+        .collect()
+}
+
+/// Characters that may not appear in a file name
+fn is_valid_name_char(c: u16) -> bool {
+    c >= 0x20 && !matches!(c, 0x22 | 0x2A | 0x2F | 0x3A | 0x3C | 0x3E | 0x3F | 0x5C | 0x7C)
+}
+
+fn validate_name(name: &str) -> KResult<Vec<u16>> {
+    let units: Vec<u16> = name.encode_utf16().collect();
+    if units.is_empty()
+        || units.len() > MAX_NAME_LEN
+        || name == "."
+        || name == ".."
+        || !units.iter().all(|&c| is_valid_name_char(c))
+    {
+        return Err(KError::Invalid);
+    }
+    Ok(units)
+}
+
+/// Convert an exFAT timestamp (with 10ms increment and UTC offset) to Unix seconds
+fn timestamp_to_unix(ts: u32, increment_10ms: u8, utc_offset: u8) -> u64 {
+    let date = (ts >> 16) as u16;
+    let time = ts as u16;
+    if date == 0 {
+        return 0;
+    }
+
+    let year = 1980 + (date >> 9) as i64;
+    let month = ((date >> 5) & 0xF).clamp(1, 12) as u32;
+    let day = (date & 0x1F).max(1) as u32;
+    let naive = days_from_civil(year, month, day) * 86400
+        + (time >> 11) as i64 * 3600
+        + ((time >> 5) & 0x3F) as i64 * 60
+        + (time & 0x1F) as i64 * 2
+        + (increment_10ms / 100) as i64;
+
+    let utc = if utc_offset & UTC_OFFSET_VALID != 0 {
+        // Signed 7-bit offset in 15 minute units
+        let quarters = ((utc_offset << 1) as i8 >> 1) as i64;
+        naive - quarters * 900
+    } else {
+        crate::time::local_to_utc(naive)
+    };
+    utc.max(0) as u64
+}
+
+/// Convert Unix seconds to (timestamp, 10ms increment, UTC offset) in local time
+fn unix_to_timestamp(secs: u64) -> (u32, u8, u8) {
+    let local = crate::time::utc_to_local(secs as i64);
+    let quarters = (local - secs as i64) / 900;
+    let offset = UTC_OFFSET_VALID | (quarters as i8 as u8 & 0x7F);
+
+    let days = local.div_euclid(86400);
+    let rem = local.rem_euclid(86400);
+    let (year, month, day) = civil_from_days(days);
+    if year < 1980 {
+        return (((1 << 5) | 1) << 16, 0, offset);
+    }
+
+    let year = core::cmp::min(year - 1980, 127) as u32;
+    let date = (year << 9) | (month << 5) | day;
+    let time = ((rem / 3600) as u32) << 11 | (((rem % 3600) / 60) as u32) << 5 | ((rem % 60) / 2) as u32;
+    (date << 16 | time, ((rem % 2) * 100) as u8, offset)
+}
+
+fn now_secs() -> u64 {
+    crate::time::realtime().tv_sec.max(0) as u64
+}
+
+fn le32(buf: &[u8], off: usize) -> u32 {
+    u32::from_le_bytes([buf[off], buf[off + 1], buf[off + 2], buf[off + 3]])
+}
+
+fn put_le32(buf: &mut [u8], off: usize, v: u32) {
+    buf[off..off + 4].copy_from_slice(&v.to_le_bytes());
+}
+
 // ============================================================================
 // Filesystem implementation
 // ============================================================================
@@ -255,12 +453,16 @@ struct ParsedFileEntry {
     valid_data_length: u64,
     /// Uses FAT chain (false = contiguous)
     uses_fat_chain: bool,
-    /// Create timestamp
-    create_time: u32,
-    /// Modify timestamp
-    modify_time: u32,
-    /// Access timestamp
-    access_time: u32,
+    /// Create time (Unix seconds)
+    create_time: u64,
+    /// Modify time (Unix seconds)
+    modify_time: u64,
+    /// Access time (Unix seconds)
+    access_time: u64,
+    /// Index of the file entry in the directory
+    index: u32,
+    /// Number of entries in the set (file entry included)
+    entry_count: u32,
 }
 
 impl ParsedFileEntry {
@@ -269,14 +471,76 @@ impl ParsedFileEntry {
     }
 }
 
+/// Where a file's clusters are and how long its data is
+#[derive(Clone, Copy)]
+struct Extent {
+    first_cluster: u32,
+    /// Clusters are contiguous and the FAT is not used
+    no_fat_chain: bool,
+    data_length: u64,
+}
+
+/// In-memory state of a file or directory, shared by every handle to it
+struct ExfatNode {
+    /// Parent directory and index of our file entry in it (None for the root)
+    loc: Option<(Arc<RwLock<ExfatNode>>, u32)>,
+    first_cluster: u32,
+    data_length: u64,
+    valid_data_length: u64,
+    no_fat_chain: bool,
+    attributes: u16,
+    create_time: u64,
+    modify_time: u64,
+    access_time: u64,
+    /// The entry set was removed; further I/O through open handles fails
+    deleted: bool,
+}
+
+impl ExfatNode {
+    fn extent(&self) -> Extent {
+        Extent {
+            first_cluster: self.first_cluster,
+            no_fat_chain: self.no_fat_chain,
+            data_length: self.data_length,
+        }
+    }
+
+    fn is_dir(&self) -> bool {
+        self.attributes & ATTR_DIRECTORY != 0
+    }
+}
+
+/// In-memory copy of the allocation bitmap
+struct AllocBitmap {
+    /// Bitmap contents, padded to whole clusters
+    bits: Vec<u8>,
+    /// Clusters holding the bitmap
+    clusters: Vec<u32>,
+    free: u32,
+    /// Where to start looking for free clusters
+    hint: u32,
+    /// Bitmap sectors (relative to the bitmap start) that need writing back
+    dirty: BTreeSet<usize>,
+}
+
 /// exFAT filesystem
 pub struct ExfatFs {
     /// Block device
     device: Arc<dyn BlockDevice>,
     /// Boot sector info
     boot: ExfatBootInfo,
-    /// Root directory inode
-    root: ExfatInode,
+    /// Root directory node
+    root: Arc<RwLock<ExfatNode>>,
+    /// Expanded up-case table (65536 entries)
+    upcase: Vec<u16>,
+    /// Allocation bitmap (None when mounted read-only)
+    bitmap: Option<Mutex<AllocBitmap>>,
+    /// The volume was dirty at mount time; leave the flag set for fsck
+    was_dirty: bool,
+    /// Open files/directories by (parent directory cluster, entry index)
+    nodes: Mutex<BTreeMap<(u32, u32), Weak<RwLock<ExfatNode>>>>,
+    /// Serializes modifying operations
+    op_lock: Mutex<()>,
 }
 
 /// Extracted boot sector information
@@ -300,17 +564,20 @@ struct ExfatBootInfo {
     root_cluster: u32,
     /// Volume serial number
     volume_serial: u32,
+    /// Device blocks per filesystem sector
+    device_blocks_per_sector: u32,
 }
 
 impl ExfatFs {
     /// Mount an exFAT filesystem
     pub fn mount(device: Arc<dyn BlockDevice>) -> KResult<Arc<Self>> {
         // Read boot sector
-        let mut boot_buf = vec![0u8; 512];
-        device.read_blocks(0, 1, &mut boot_buf)?;
+        let dev_block_size = device.block_size() as usize;
+        let mut boot_buf = vec![0u8; core::cmp::max(512, dev_block_size)];
+        device.read_blocks(0, (boot_buf.len() / dev_block_size) as u32, &mut boot_buf)?;
 
         // Parse boot sector
-        let boot_sector = unsafe { &*(boot_buf.as_ptr() as *const ExfatBootSector) };
+        let boot_sector: ExfatBootSector = read_entry(&boot_buf);
 
         // Verify signature
         if boot_sector.fs_name != EXFAT_SIGNATURE {
@@ -329,9 +596,17 @@ impl ExfatFs {
         }
 
         // Extract boot info
+        if !(9..=12).contains(&boot_sector.bytes_per_sector_shift)
+            || boot_sector.sectors_per_cluster_shift > 25 - boot_sector.bytes_per_sector_shift
+        {
+            return Err(KError::Invalid);
+        }
         let bytes_per_sector = 1u32 << boot_sector.bytes_per_sector_shift;
         let sectors_per_cluster = 1u32 << boot_sector.sectors_per_cluster_shift;
         let bytes_per_cluster = bytes_per_sector * sectors_per_cluster;
+        if bytes_per_sector < dev_block_size as u32 {
+            return Err(KError::NotSupported);
+        }
 
         let boot_info = ExfatBootInfo {
             bytes_per_sector,
@@ -343,59 +618,189 @@ impl ExfatFs {
             cluster_count: boot_sector.cluster_count,
             root_cluster: boot_sector.root_directory_cluster,
             volume_serial: boot_sector.volume_serial,
+            device_blocks_per_sector: bytes_per_sector / dev_block_size as u32,
         };
+        if boot_info.root_cluster < 2 || boot_info.root_cluster >= boot_info.cluster_count + 2 {
+            return Err(KError::Invalid);
+        }
+
+        let volume_flags = boot_sector.volume_flags;
+        let was_dirty = volume_flags & VOLUME_FLAG_DIRTY != 0;
+        if was_dirty {
+            crate::kprintln!("exfat: volume was not cleanly unmounted, run fsck");
+        }
+
+        let mut fs = ExfatFs {
+            device,
+            boot: boot_info,
+            root: Arc::new(RwLock::new(ExfatNode {
+                loc: None,
+                first_cluster: boot_sector.root_directory_cluster,
+                data_length: 0,
+                valid_data_length: 0,
+                no_fat_chain: false, // Root directory uses FAT chain
+                attributes: ATTR_DIRECTORY,
+                create_time: 0,
+                modify_time: 0,
+                access_time: 0,
+                deleted: false,
+            })),
+            upcase: Vec::new(),
+            bitmap: None,
+            was_dirty,
+            nodes: Mutex::new(BTreeMap::new()),
+            op_lock: Mutex::new(()),
+        };
+
+        // The root directory has no stream entry; its size is its chain length
+        let root_clusters = fs.fat_chain(fs.boot.root_cluster, usize::MAX)?.len() as u64;
+        {
+            let mut root = fs.root.write();
+            root.data_length = root_clusters * fs.boot.bytes_per_cluster as u64;
+            root.valid_data_length = root.data_length;
+        }
+
+        fs.load_system_entries(boot_sector.number_of_fats)?;
 
         crate::kprintln!(
-            "exfat: mounted, {} sectors/cluster, {} clusters, root cluster {}",
-            boot_info.sectors_per_cluster,
-            boot_info.cluster_count,
-            boot_info.root_cluster
+            "exfat: mounted, {} sectors/cluster, {} clusters, root cluster {}, rw={}",
+            fs.boot.sectors_per_cluster,
+            fs.boot.cluster_count,
+            fs.boot.root_cluster,
+            fs.bitmap.is_some()
         );
 
-        let root = ExfatInode {
-            device: Arc::clone(&device),
-            boot: boot_info.clone(),
-            first_cluster: boot_info.root_cluster,
-            data_length: 0, // Directory size determined by entries
-            valid_data_length: 0,
-            uses_fat_chain: true, // Root directory uses FAT chain
-            is_dir: true,
-            attributes: ATTR_DIRECTORY,
-            parent: None,
+        Ok(Arc::new(fs))
+    }
+
+    /// Load the up-case table and allocation bitmap from the root directory
+    fn load_system_entries(&mut self, number_of_fats: u8) -> KResult<()> {
+        let root = self.root.read().extent();
+        let data = self.read_stream(&root)?;
+        let mut bitmap_entry: Option<ExfatBitmapEntry> = None;
+        let mut upcase_entry: Option<ExfatUpcaseEntry> = None;
+
+        for raw in data.chunks_exact(ENTRY_SIZE) {
+            match raw[0] {
+                ENTRY_TYPE_END_OF_DIR => break,
+                ENTRY_TYPE_ALLOC_BITMAP if bitmap_entry.is_none() => bitmap_entry = Some(read_entry(raw)),
+                ENTRY_TYPE_UPCASE_TABLE if upcase_entry.is_none() => upcase_entry = Some(read_entry(raw)),
+                _ => {}
+            }
+        }
+
+        // Up-case table: needed for name comparison and NameHash
+        let mut upcase_ok = false;
+        if let Some(entry) = upcase_entry {
+            let length = entry.data_length;
+            let extent = Extent { first_cluster: entry.first_cluster, no_fat_chain: false, data_length: length };
+            let table = if length <= 0x20000 { self.read_stream(&extent).ok() } else { None };
+            match table {
+                Some(table) if upcase_table_checksum(&table) == entry.table_checksum => {
+                    self.upcase = expand_upcase_table(&table);
+                    upcase_ok = true;
+                }
+                _ => crate::kprintln!("exfat: bad up-case table checksum"),
+            }
+        }
+        if !upcase_ok {
+            self.upcase = ascii_upcase_table();
+        }
+
+        if number_of_fats != 1 {
+            crate::kprintln!("exfat: {} FATs (TexFAT) not supported for writing, mounting read-only", number_of_fats);
+            return Ok(());
+        }
+        if !upcase_ok {
+            crate::kprintln!("exfat: no usable up-case table, mounting read-only");
+            return Ok(());
+        }
+
+        // Allocation bitmap: one bit per cluster
+        let entry = match bitmap_entry {
+            Some(e) => e,
+            None => {
+                crate::kprintln!("exfat: no allocation bitmap, mounting read-only");
+                return Ok(());
+            }
         };
+        let length = entry.data_length;
+        let count = self.boot.cluster_count;
+        let cs = self.boot.bytes_per_cluster as u64;
+        if length < (count as u64 + 7) / 8 {
+            crate::kprintln!("exfat: allocation bitmap too small, mounting read-only");
+            return Ok(());
+        }
 
-        let fs = Arc::new(ExfatFs {
-            device,
-            boot: boot_info,
-            root,
-        });
+        let clusters = self.fat_chain(entry.first_cluster, ((length + cs - 1) / cs) as usize)?;
+        if (clusters.len() as u64) < (length + cs - 1) / cs {
+            crate::kprintln!("exfat: allocation bitmap chain too short, mounting read-only");
+            return Ok(());
+        }
+        let mut bits = vec![0u8; clusters.len() * cs as usize];
+        for (i, &cluster) in clusters.iter().enumerate() {
+            self.read_cluster_into(cluster, &mut bits[i * cs as usize..(i + 1) * cs as usize])?;
+        }
 
-        Ok(fs)
+        let free = (0..count).filter(|&i| bits[(i / 8) as usize] & (1 << (i % 8)) == 0).count() as u32;
+        self.bitmap = Some(Mutex::new(AllocBitmap {
+            bits,
+            clusters,
+            free,
+            hint: 2,
+            dirty: BTreeSet::new(),
+        }));
+
+        Ok(())
     }
 
     /// Get root directory inode
-    pub fn root(&self) -> Inode {
-        Inode(Arc::new(self.root.clone()))
+    pub fn root(self: &Arc<Self>) -> Inode {
+        Inode(Arc::new(ExfatInode {
+            fs: Arc::clone(self),
+            node: Arc::clone(&self.root),
+            is_dir: true,
+            parent: None,
+        }))
     }
 
-    /// Read FAT entry
-    fn read_fat_entry(&self, cluster: u32) -> KResult<u32> {
-        let fat_offset = self.boot.fat_offset as u64 * self.boot.bytes_per_sector as u64;
-        let entry_offset = fat_offset + (cluster as u64 * 4);
-        let sector = (entry_offset / self.boot.bytes_per_sector as u64) as u64;
-        let offset_in_sector = (entry_offset % self.boot.bytes_per_sector as u64) as usize;
+    /// Whether the volume was mounted read/write
+    pub fn is_writable(&self) -> bool {
+        self.bitmap.is_some()
+    }
 
-        let mut buf = vec![0u8; self.boot.bytes_per_sector as usize];
-        self.device.read_blocks(sector, 1, &mut buf)?;
+    /// Number of free clusters
+    pub fn free_clusters(&self) -> u32 {
+        self.bitmap.as_ref().map(|b| b.lock().free).unwrap_or(0)
+    }
 
-        let entry = u32::from_le_bytes([
-            buf[offset_in_sector],
-            buf[offset_in_sector + 1],
-            buf[offset_in_sector + 2],
-            buf[offset_in_sector + 3],
-        ]);
+    /// Cluster counts for statfs
+    pub fn stats(&self) -> FsStats {
+        let free = self.free_clusters() as u64;
+        FsStats {
+            magic: EXFAT_SUPER_MAGIC,
+            block_size: self.bytes_per_cluster(),
+            blocks: self.boot.cluster_count as u64,
+            blocks_free: free,
+            blocks_avail: free,
+            files: 0,
+            files_free: 0,
+            name_max: 255,
+        }
+    }
 
-        Ok(entry)
+    // ========================================================================
+    // Sectors and clusters
+    // ========================================================================
+
+    fn read_sector(&self, sector: u64, buf: &mut [u8]) -> KResult<()> {
+        let factor = self.boot.device_blocks_per_sector;
+        self.device.read_blocks(sector * factor as u64, factor, buf)
+    }
+
+    fn write_sector(&self, sector: u64, buf: &[u8]) -> KResult<()> {
+        let factor = self.boot.device_blocks_per_sector;
+        self.device.write_blocks(sector * factor as u64, factor, buf)
     }
 
     /// Convert cluster number to sector
@@ -404,75 +809,493 @@ impl ExfatFs {
         let cluster_offset = (cluster - 2) as u64;
         self.boot.cluster_heap_offset as u64 + cluster_offset * self.boot.sectors_per_cluster as u64
     }
-}
 
-/// exFAT inode
-#[derive(Clone)]
-pub struct ExfatInode {
-    device: Arc<dyn BlockDevice>,
-    boot: ExfatBootInfo,
-    first_cluster: u32,
-    data_length: u64,
-    valid_data_length: u64,
-    uses_fat_chain: bool,
-    is_dir: bool,
-    attributes: u16,
-    parent: Option<Arc<ExfatInode>>,
-}
+    fn bytes_per_cluster(&self) -> u64 {
+        self.boot.bytes_per_cluster as u64
+    }
 
-impl ExfatInode {
-    /// Read cluster data
-    fn read_cluster(&self, cluster: u32) -> KResult<Vec<u8>> {
-        let sector = cluster_to_sector(&self.boot, cluster);
-        let mut buf = vec![0u8; self.boot.bytes_per_cluster as usize];
-        self.device.read_blocks(sector, self.boot.sectors_per_cluster, &mut buf)?;
-        Ok(buf)
-    }
-
-    /// Read all clusters of this inode
-    fn read_all_data(&self) -> KResult<Vec<u8>> {
-        let mut data = Vec::new();
-        let max_len = self.data_length as usize;
-
-        if self.uses_fat_chain {
-            // Follow FAT chain
-            let mut cluster = self.first_cluster;
-            while cluster >= 2 && cluster < self.boot.cluster_count + 2 && data.len() < max_len {
-                let chunk = self.read_cluster(cluster)?;
-                let remaining = max_len - data.len();
-                let to_copy = remaining.min(chunk.len());
-                data.extend_from_slice(&chunk[..to_copy]);
-
-                // Get next cluster from FAT
-                let next = read_fat_entry(&self.device, &self.boot, cluster)?;
-                if next >= EXFAT_END - 7 {
-                    break;
+    fn valid_cluster(&self, cluster: u32) -> bool {
+        cluster >= 2 && cluster < self.boot.cluster_count + 2
+    }
+
+    fn read_cluster_into(&self, cluster: u32, buf: &mut [u8]) -> KResult<()> {
+        if !self.valid_cluster(cluster) {
+            return Err(KError::IO);
+        }
+        let factor = self.boot.device_blocks_per_sector;
+        let lba = self.cluster_to_sector(cluster) * factor as u64;
+        self.device.read_blocks(lba, self.boot.sectors_per_cluster * factor, buf)
+    }
+
+    fn write_cluster(&self, cluster: u32, buf: &[u8]) -> KResult<()> {
+        if !self.valid_cluster(cluster) {
+            return Err(KError::IO);
+        }
+        let factor = self.boot.device_blocks_per_sector;
+        let lba = self.cluster_to_sector(cluster) * factor as u64;
+        self.device.write_blocks(lba, self.boot.sectors_per_cluster * factor, buf)
+    }
+
+    /// Write `data` at byte `offset` inside a cluster (read-modify-write of partial sectors)
+    fn write_cluster_range(&self, cluster: u32, offset: usize, data: &[u8]) -> KResult<()> {
+        if !self.valid_cluster(cluster) {
+            return Err(KError::IO);
+        }
+        let bps = self.boot.bytes_per_sector as usize;
+        let first_sector = self.cluster_to_sector(cluster);
+        let mut sector_buf = vec![0u8; bps];
+        let mut done = 0;
+
+        while done < data.len() {
+            let pos = offset + done;
+            let sector = first_sector + (pos / bps) as u64;
+            let in_sector = pos % bps;
+            let chunk = core::cmp::min(data.len() - done, bps - in_sector);
+
+            if chunk == bps {
+                self.write_sector(sector, &data[done..done + chunk])?;
+            } else {
+                self.read_sector(sector, &mut sector_buf)?;
+                sector_buf[in_sector..in_sector + chunk].copy_from_slice(&data[done..done + chunk]);
+                self.write_sector(sector, &sector_buf)?;
+            }
+            done += chunk;
+        }
+
+        Ok(())
+    }
+
+    // ========================================================================
+    // FAT and cluster lists
+    // ========================================================================
+
+    /// Sector and byte offset of a cluster's FAT entry
+    fn fat_pos(&self, cluster: u32) -> (u64, usize) {
+        let entry_offset = cluster as u64 * 4;
+        let bps = self.boot.bytes_per_sector as u64;
+        (self.boot.fat_offset as u64 + entry_offset / bps, (entry_offset % bps) as usize)
+    }
+
+    /// Read FAT entry
+    fn read_fat_entry(&self, cluster: u32) -> KResult<u32> {
+        let (sector, offset) = self.fat_pos(cluster);
+        let mut buf = vec![0u8; self.boot.bytes_per_sector as usize];
+        self.read_sector(sector, &mut buf)?;
+        Ok(le32(&buf, offset))
+    }
+
+    /// Follow a FAT chain (at most `limit` clusters)
+    fn fat_chain(&self, first: u32, limit: usize) -> KResult<Vec<u32>> {
+        let mut chain = Vec::new();
+        let mut buf = vec![0u8; self.boot.bytes_per_sector as usize];
+        let mut loaded = u64::MAX;
+        let mut cluster = first;
+
+        while self.valid_cluster(cluster) && chain.len() < limit {
+            chain.push(cluster);
+            // Guard against loops in a corrupted FAT
+            if chain.len() > self.boot.cluster_count as usize {
+                return Err(KError::IO);
+            }
+
+            let (sector, offset) = self.fat_pos(cluster);
+            if sector != loaded {
+                self.read_sector(sector, &mut buf)?;
+                loaded = sector;
+            }
+            let next = le32(&buf, offset);
+            if next >= EXFAT_BAD {
+                break;
+            }
+            cluster = next;
+        }
+
+        Ok(chain)
+    }
+
+    /// Link `clusters` into one FAT chain ending with an end-of-chain mark
+    fn link_clusters(&self, clusters: &[u32]) -> KResult<()> {
+        let mut buf = vec![0u8; self.boot.bytes_per_sector as usize];
+        let mut loaded: Option<u64> = None;
+
+        for (i, &cluster) in clusters.iter().enumerate() {
+            let next = clusters.get(i + 1).copied().unwrap_or(EXFAT_END);
+            let (sector, offset) = self.fat_pos(cluster);
+            if loaded != Some(sector) {
+                if let Some(prev) = loaded {
+                    self.write_sector(prev, &buf)?;
                 }
-                cluster = next;
+                self.read_sector(sector, &mut buf)?;
+                loaded = Some(sector);
             }
+            put_le32(&mut buf, offset, next);
+        }
+
+        if let Some(sector) = loaded {
+            self.write_sector(sector, &buf)?;
+        }
+        Ok(())
+    }
+
+    /// All clusters holding an extent's data
+    fn extent_clusters(&self, extent: &Extent) -> KResult<Vec<u32>> {
+        self.extent_clusters_upto(extent, usize::MAX)
+    }
+
+    /// The first `limit` clusters of an extent
+    fn extent_clusters_upto(&self, extent: &Extent, limit: usize) -> KResult<Vec<u32>> {
+        if extent.first_cluster == 0 || extent.data_length == 0 {
+            return Ok(Vec::new());
+        }
+
+        let cs = self.boot.bytes_per_cluster as u64;
+        let count = core::cmp::min(((extent.data_length + cs - 1) / cs) as usize, limit);
+
+        if extent.no_fat_chain {
+            let last = extent.first_cluster as u64 + count as u64 - 1;
+            if !self.valid_cluster(extent.first_cluster) || last >= self.boot.cluster_count as u64 + 2 {
+                return Err(KError::IO);
+            }
+            Ok((extent.first_cluster..extent.first_cluster + count as u32).collect())
         } else {
-            // Contiguous allocation
-            let num_clusters = (max_len as u64 + self.boot.bytes_per_cluster as u64 - 1)
-                / self.boot.bytes_per_cluster as u64;
-            for i in 0..num_clusters {
-                let cluster = self.first_cluster + i as u32;
-                let chunk = self.read_cluster(cluster)?;
-                let remaining = max_len - data.len();
-                let to_copy = remaining.min(chunk.len());
-                data.extend_from_slice(&chunk[..to_copy]);
+            let chain = self.fat_chain(extent.first_cluster, count)?;
+            if chain.len() < count {
+                return Err(KError::IO);
             }
+            Ok(chain)
+        }
+    }
+
+    /// Read an extent's data (up to its data length)
+    fn read_stream(&self, extent: &Extent) -> KResult<Vec<u8>> {
+        let cs = self.boot.bytes_per_cluster as usize;
+        let clusters = self.extent_clusters(extent)?;
+        let mut data = vec![0u8; clusters.len() * cs];
+
+        for (i, &cluster) in clusters.iter().enumerate() {
+            self.read_cluster_into(cluster, &mut data[i * cs..(i + 1) * cs])?;
         }
 
+        data.truncate(extent.data_length as usize);
         Ok(data)
     }
 
+    // ========================================================================
+    // Allocation bitmap
+    // ========================================================================
+
+    fn bitmap(&self) -> KResult<&Mutex<AllocBitmap>> {
+        self.bitmap.as_ref().ok_or(KError::NotSupported)
+    }
+
+    fn is_cluster_free(bitmap: &AllocBitmap, cluster: u32) -> bool {
+        let bit = (cluster - 2) as usize;
+        bitmap.bits[bit / 8] & (1 << (bit % 8)) == 0
+    }
+
+    fn mark_cluster(&self, bitmap: &mut AllocBitmap, cluster: u32, used: bool) {
+        let bit = (cluster - 2) as usize;
+        let mask = 1u8 << (bit % 8);
+        let was_used = bitmap.bits[bit / 8] & mask != 0;
+        if was_used == used {
+            return;
+        }
+
+        if used {
+            bitmap.bits[bit / 8] |= mask;
+            bitmap.free -= 1;
+        } else {
+            bitmap.bits[bit / 8] &= !mask;
+            bitmap.free += 1;
+        }
+        bitmap.dirty.insert(bit / 8 / self.boot.bytes_per_sector as usize);
+    }
+
+    /// Find and mark `count` contiguous free clusters
+    fn alloc_contiguous(&self, count: u32) -> KResult<Option<u32>> {
+        let mut bitmap = self.bitmap()?.lock();
+        if bitmap.free < count {
+            return Ok(None);
+        }
+
+        let end = self.boot.cluster_count + 2;
+        let hint = bitmap.hint;
+        let mut found = None;
+
+        for (from, to) in [(hint, end), (2, hint)] {
+            let mut run_start = from;
+            let mut run_len = 0;
+            for cluster in from..to {
+                if Self::is_cluster_free(&bitmap, cluster) {
+                    if run_len == 0 {
+                        run_start = cluster;
+                    }
+                    run_len += 1;
+                    if run_len == count {
+                        found = Some(run_start);
+                        break;
+                    }
+                } else {
+                    run_len = 0;
+                }
+            }
+            if found.is_some() {
+                break;
+            }
+        }
+
+        if let Some(start) = found {
+            for cluster in start..start + count {
+                self.mark_cluster(&mut bitmap, cluster, true);
+            }
+            bitmap.hint = if start + count >= end { 2 } else { start + count };
+        }
+        Ok(found)
+    }
+
+    /// Mark `count` clusters right after `last` if they are all free
+    fn alloc_after(&self, last: u32, count: u32) -> KResult<bool> {
+        let mut bitmap = self.bitmap()?.lock();
+        let end = self.boot.cluster_count as u64 + 2;
+        if last as u64 + count as u64 >= end {
+            return Ok(false);
+        }
+        if !(last + 1..=last + count).all(|c| Self::is_cluster_free(&bitmap, c)) {
+            return Ok(false);
+        }
+
+        for cluster in last + 1..=last + count {
+            self.mark_cluster(&mut bitmap, cluster, true);
+        }
+        bitmap.hint = if last + count + 1 >= end as u32 { 2 } else { last + count + 1 };
+        Ok(true)
+    }
+
+    /// Mark `count` free clusters wherever they are
+    fn alloc_scattered(&self, count: u32) -> KResult<Vec<u32>> {
+        let mut bitmap = self.bitmap()?.lock();
+        if bitmap.free < count {
+            return Err(KError::NoMemory);
+        }
+
+        let end = self.boot.cluster_count + 2;
+        let hint = bitmap.hint;
+        let mut clusters = Vec::with_capacity(count as usize);
+        for cluster in (hint..end).chain(2..hint) {
+            if clusters.len() == count as usize {
+                break;
+            }
+            if Self::is_cluster_free(&bitmap, cluster) {
+                clusters.push(cluster);
+            }
+        }
+        if clusters.len() < count as usize {
+            return Err(KError::NoMemory);
+        }
+
+        for &cluster in &clusters {
+            self.mark_cluster(&mut bitmap, cluster, true);
+        }
+        let last = *clusters.last().unwrap_or(&2);
+        bitmap.hint = if last + 1 >= end { 2 } else { last + 1 };
+        Ok(clusters)
+    }
+
+    fn free_clusters_list(&self, clusters: &[u32]) -> KResult<()> {
+        let mut bitmap = self.bitmap()?.lock();
+        for &cluster in clusters {
+            if self.valid_cluster(cluster) {
+                self.mark_cluster(&mut bitmap, cluster, false);
+            }
+        }
+        Ok(())
+    }
+
+    /// Write the changed bitmap sectors back to disk
+    fn flush_bitmap(&self) -> KResult<()> {
+        let mut bitmap = self.bitmap()?.lock();
+        let bps = self.boot.bytes_per_sector as usize;
+        let cs = self.boot.bytes_per_cluster as usize;
+        let dirty = core::mem::take(&mut bitmap.dirty);
+
+        for index in dirty {
+            let byte = index * bps;
+            let cluster = bitmap.clusters[byte / cs];
+            let sector = self.cluster_to_sector(cluster) + ((byte % cs) / bps) as u64;
+            self.write_sector(sector, &bitmap.bits[byte..byte + bps])?;
+        }
+        Ok(())
+    }
+
+    // ========================================================================
+    // Volume flags
+    // ========================================================================
+
+    /// Set or clear VolumeDirty (and refresh PercentInUse when clearing)
+    fn set_volume_dirty(&self, dirty: bool) -> KResult<()> {
+        let mut buf = vec![0u8; self.boot.bytes_per_sector as usize];
+        self.read_sector(0, &mut buf)?;
+
+        let flags = u16::from_le_bytes([buf[BOOT_VOLUME_FLAGS], buf[BOOT_VOLUME_FLAGS + 1]]);
+        let flags = if dirty { flags | VOLUME_FLAG_DIRTY } else { flags & !VOLUME_FLAG_DIRTY };
+        buf[BOOT_VOLUME_FLAGS..BOOT_VOLUME_FLAGS + 2].copy_from_slice(&flags.to_le_bytes());
+
+        if !dirty {
+            let count = self.boot.cluster_count as u64;
+            let used = count - self.free_clusters() as u64;
+            buf[BOOT_PERCENT_IN_USE] = if count == 0 { 0xFF } else { (used * 100 / count) as u8 };
+        }
+
+        // VolumeFlags and PercentInUse are not covered by the boot checksum
+        self.write_sector(0, &buf)
+    }
+
+    /// Run a modifying operation with the volume marked dirty
+    fn modify<T>(&self, f: impl FnOnce() -> KResult<T>) -> KResult<T> {
+        if !self.is_writable() {
+            return Err(KError::NotSupported);
+        }
+
+        let _guard = self.op_lock.lock();
+        self.set_volume_dirty(true)?;
+        let result = f();
+        self.flush_bitmap()?;
+
+        // An I/O error may have left things half-done: keep the flag for fsck
+        if !self.was_dirty && !matches!(result, Err(KError::IO)) {
+            self.set_volume_dirty(false)?;
+        }
+        result
+    }
+
+    // ========================================================================
+    // Growing and shrinking
+    // ========================================================================
+
+    /// Grow a node's allocation from `have` to `count` clusters.
+    /// Returns the new cluster list; new clusters are zeroed if `zero` is set.
+    fn grow(&self, node: &mut ExfatNode, mut have: Vec<u32>, count: usize, zero: bool) -> KResult<Vec<u32>> {
+        if have.len() >= count {
+            return Ok(have);
+        }
+        let need = (count - have.len()) as u32;
+        let first_new = have.len();
+
+        if have.is_empty() {
+            // Prefer a contiguous run: no FAT chain needed
+            if let Some(start) = self.alloc_contiguous(need)? {
+                node.first_cluster = start;
+                node.no_fat_chain = true;
+                have.extend(start..start + need);
+            } else {
+                let clusters = self.alloc_scattered(need)?;
+                if let Err(e) = self.link_clusters(&clusters) {
+                    self.free_clusters_list(&clusters)?;
+                    return Err(e);
+                }
+                node.first_cluster = clusters[0];
+                node.no_fat_chain = false;
+                have = clusters;
+            }
+        } else {
+            let last = *have.last().unwrap();
+            if node.no_fat_chain && self.alloc_after(last, need)? {
+                have.extend(last + 1..=last + need);
+            } else {
+                let clusters = self.alloc_scattered(need)?;
+                if node.no_fat_chain {
+                    // Can no longer grow in place: switch to a FAT chain
+                    have.extend_from_slice(&clusters);
+                    if let Err(e) = self.link_clusters(&have) {
+                        have.truncate(first_new);
+                        self.free_clusters_list(&clusters)?;
+                        return Err(e);
+                    }
+                    node.no_fat_chain = false;
+                } else {
+                    let mut link = vec![last];
+                    link.extend_from_slice(&clusters);
+                    if let Err(e) = self.link_clusters(&link) {
+                        self.free_clusters_list(&clusters)?;
+                        return Err(e);
+                    }
+                    have.extend_from_slice(&clusters);
+                }
+            }
+        }
+
+        if zero {
+            let zeros = vec![0u8; self.boot.bytes_per_cluster as usize];
+            for &cluster in &have[first_new..] {
+                self.write_cluster(cluster, &zeros)?;
+            }
+        }
+
+        Ok(have)
+    }
+
+    /// Cut a node's allocation down to `keep` clusters
+    fn shrink(&self, node: &mut ExfatNode, have: &[u32], keep: usize) -> KResult<()> {
+        if keep >= have.len() {
+            return Ok(());
+        }
+
+        if keep == 0 {
+            node.first_cluster = 0;
+            node.no_fat_chain = false;
+        } else if !node.no_fat_chain {
+            self.link_clusters(&have[keep - 1..keep])?;
+        }
+        self.free_clusters_list(&have[keep..])
+    }
+
+    /// Zero bytes [from, to) of a file inside its clusters
+    fn zero_range(&self, clusters: &[u32], from: u64, to: u64) -> KResult<()> {
+        let cs = self.boot.bytes_per_cluster as u64;
+        let mut pos = from;
+        while pos < to {
+            let Some(&cluster) = clusters.get((pos / cs) as usize) else { break };
+            let in_cluster = pos % cs;
+            let chunk = core::cmp::min(to - pos, cs - in_cluster);
+            self.write_cluster_range(cluster, in_cluster as usize, &vec![0u8; chunk as usize])?;
+            pos += chunk;
+        }
+        Ok(())
+    }
+
+    // ========================================================================
+    // Directories
+    // ========================================================================
+
+    fn upcase_unit(&self, c: u16) -> u16 {
+        self.upcase[c as usize]
+    }
+
+    /// NameHash of the up-cased name
+    fn name_hash(&self, name: &[u16]) -> u16 {
+        let mut hash = 0u16;
+        for &c in name {
+            for b in self.upcase_unit(c).to_le_bytes() {
+                hash = ((hash & 1) << 15 | hash >> 1).wrapping_add(b as u16);
+            }
+        }
+        hash
+    }
+
+    /// exFAT names are case-insensitive through the up-case table
+    fn names_equal(&self, a: &str, b: &str) -> bool {
+        a.encode_utf16()
+            .map(|c| self.upcase_unit(c))
+            .eq(b.encode_utf16().map(|c| self.upcase_unit(c)))
+    }
+
     /// Parse directory entries
-    fn parse_dir_entries(&self) -> KResult<Vec<ParsedFileEntry>> {
-        let data = self.read_all_data()?;
+    fn parse_dir_entries(&self, dir: &Extent) -> KResult<Vec<ParsedFileEntry>> {
+        let data = self.read_stream(dir)?;
         let mut entries = Vec::new();
         let mut i = 0;
 
-        while i + 32 <= data.len() {
+        while i + ENTRY_SIZE <= data.len() {
             let entry_type = data[i];
 
             // End of directory
@@ -481,111 +1304,416 @@ impl ExfatInode {
             }
 
             // Skip deleted entries (type with cleared bit 7)
-            if entry_type & 0x80 == 0 {
-                i += 32;
+            if entry_type & ENTRY_IN_USE == 0 {
+                i += ENTRY_SIZE;
                 continue;
             }
 
             // File entry
             if entry_type == ENTRY_TYPE_FILE {
-                if let Some(parsed) = self.parse_file_entry_set(&data[i..])? {
-                    let secondary_count = data[i + 1] as usize;
+                if let Some(mut parsed) = self.parse_file_entry_set(&data[i..]) {
+                    parsed.index = (i / ENTRY_SIZE) as u32;
+                    i += ENTRY_SIZE * parsed.entry_count as usize;
                     entries.push(parsed);
-                    i += 32 * (1 + secondary_count);
                     continue;
                 }
             }
 
-            i += 32;
+            i += ENTRY_SIZE;
         }
 
         Ok(entries)
     }
 
     /// Parse a complete file entry set (file + stream + name entries)
-    fn parse_file_entry_set(&self, data: &[u8]) -> KResult<Option<ParsedFileEntry>> {
-        if data.len() < 32 {
-            return Ok(None);
-        }
-
-        let file_entry = unsafe { &*(data.as_ptr() as *const ExfatFileEntry) };
+    fn parse_file_entry_set(&self, data: &[u8]) -> Option<ParsedFileEntry> {
+        let file_entry: ExfatFileEntry = read_entry(data);
         let secondary_count = file_entry.secondary_count as usize;
+        let set_len = ENTRY_SIZE * (1 + secondary_count);
 
-        if data.len() < 32 * (1 + secondary_count) {
-            return Ok(None);
+        if !(2..=18).contains(&secondary_count) || data.len() < set_len {
+            return None;
+        }
+        if entry_set_checksum(&data[..set_len]) != file_entry.set_checksum {
+            return None;
         }
 
-        // Find stream extension entry
-        let mut stream_entry: Option<&ExfatStreamEntry> = None;
-        let mut name_chars = Vec::new();
-
-        for j in 0..secondary_count {
-            let offset = 32 * (1 + j);
-            let entry_type = data[offset];
-
-            if entry_type == ENTRY_TYPE_STREAM_EXT {
-                stream_entry = Some(unsafe { &*(data[offset..].as_ptr() as *const ExfatStreamEntry) });
-            } else if entry_type == ENTRY_TYPE_FILE_NAME {
-                let name_entry = unsafe { &*(data[offset..].as_ptr() as *const ExfatNameEntry) };
-                // Copy file_name array to avoid unaligned reference
-                let file_name: [u16; 15] = unsafe {
-                    core::ptr::read_unaligned(core::ptr::addr_of!(name_entry.file_name))
-                };
-                for c in file_name {
-                    if c == 0 {
-                        break;
-                    }
-                    name_chars.push(c);
-                }
-            }
+        // The stream extension comes first, followed by the name entries
+        if data[ENTRY_SIZE] != ENTRY_TYPE_STREAM_EXT {
+            return None;
+        }
+        let stream: ExfatStreamEntry = read_entry(&data[ENTRY_SIZE..]);
+        let name_length = stream.name_length as usize;
+        let name_entries = (name_length + NAME_CHARS_PER_ENTRY - 1) / NAME_CHARS_PER_ENTRY;
+        if name_length == 0 || name_entries > secondary_count - 1 {
+            return None;
         }
 
-        let stream = match stream_entry {
-            Some(s) => s,
-            None => return Ok(None),
-        };
+        let mut name_chars = Vec::with_capacity(name_length);
+        for j in 0..name_entries {
+            let offset = ENTRY_SIZE * (2 + j);
+            if data[offset] != ENTRY_TYPE_FILE_NAME {
+                return None;
+            }
+            let name_entry: ExfatNameEntry = read_entry(&data[offset..]);
+            // Copy file_name array to avoid unaligned reference
+            let file_name: [u16; 15] = name_entry.file_name;
+            name_chars.extend_from_slice(&file_name);
+        }
+        name_chars.truncate(name_length);
 
         // Convert name to String
         let name = String::from_utf16_lossy(&name_chars);
 
-        Ok(Some(ParsedFileEntry {
+        Some(ParsedFileEntry {
             attributes: file_entry.file_attributes,
             name,
             first_cluster: stream.first_cluster,
             data_length: stream.data_length,
             valid_data_length: stream.valid_data_length,
             uses_fat_chain: (stream.flags & STREAM_FLAG_NO_FAT_CHAIN) == 0,
-            create_time: file_entry.create_timestamp,
-            modify_time: file_entry.modify_timestamp,
-            access_time: file_entry.access_timestamp,
-        }))
+            create_time: timestamp_to_unix(
+                file_entry.create_timestamp,
+                file_entry.create_time_10ms,
+                file_entry.create_utc_offset,
+            ),
+            modify_time: timestamp_to_unix(
+                file_entry.modify_timestamp,
+                file_entry.modify_time_10ms,
+                file_entry.modify_utc_offset,
+            ),
+            access_time: timestamp_to_unix(file_entry.access_timestamp, 0, file_entry.access_utc_offset),
+            index: 0,
+            entry_count: 1 + secondary_count as u32,
+        })
     }
-}
 
-/// Helper function to read FAT entry
-fn read_fat_entry(device: &Arc<dyn BlockDevice>, boot: &ExfatBootInfo, cluster: u32) -> KResult<u32> {
-    let fat_offset = boot.fat_offset as u64 * boot.bytes_per_sector as u64;
-    let entry_offset = fat_offset + (cluster as u64 * 4);
-    let sector = entry_offset / boot.bytes_per_sector as u64;
-    let offset_in_sector = (entry_offset % boot.bytes_per_sector as u64) as usize;
+    fn find_entry(&self, dir: &Extent, name: &str) -> KResult<Option<ParsedFileEntry>> {
+        Ok(self
+            .parse_dir_entries(dir)?
+            .into_iter()
+            .find(|entry| self.names_equal(&entry.name, name)))
+    }
+
+    /// Sector and byte offset of every entry in [index, index + count)
+    fn entry_positions(&self, dir: &Extent, index: u32, count: u32) -> KResult<Vec<(u64, usize)>> {
+        let cs = self.boot.bytes_per_cluster as usize;
+        let bps = self.boot.bytes_per_sector as usize;
+        let last_byte = (index + count) as usize * ENTRY_SIZE;
+        let clusters = self.extent_clusters_upto(dir, (last_byte + cs - 1) / cs)?;
+
+        (index..index + count)
+            .map(|i| {
+                let byte = i as usize * ENTRY_SIZE;
+                let cluster = *clusters.get(byte / cs).ok_or(KError::IO)?;
+                let in_cluster = byte % cs;
+                Ok((self.cluster_to_sector(cluster) + (in_cluster / bps) as u64, in_cluster % bps))
+            })
+            .collect()
+    }
 
-    let mut buf = vec![0u8; boot.bytes_per_sector as usize];
-    device.read_blocks(sector, 1, &mut buf)?;
+    fn read_entries(&self, dir: &Extent, index: u32, count: u32) -> KResult<Vec<u8>> {
+        let mut out = vec![0u8; count as usize * ENTRY_SIZE];
+        let mut buf = vec![0u8; self.boot.bytes_per_sector as usize];
+        let mut loaded = None;
 
-    let entry = u32::from_le_bytes([
-        buf[offset_in_sector],
-        buf[offset_in_sector + 1],
-        buf[offset_in_sector + 2],
-        buf[offset_in_sector + 3],
-    ]);
+        for (i, (sector, off)) in self.entry_positions(dir, index, count)?.into_iter().enumerate() {
+            if loaded != Some(sector) {
+                self.read_sector(sector, &mut buf)?;
+                loaded = Some(sector);
+            }
+            out[i * ENTRY_SIZE..(i + 1) * ENTRY_SIZE].copy_from_slice(&buf[off..off + ENTRY_SIZE]);
+        }
+        Ok(out)
+    }
 
-    Ok(entry)
+    fn write_entries(&self, dir: &Extent, index: u32, data: &[u8]) -> KResult<()> {
+        let count = (data.len() / ENTRY_SIZE) as u32;
+        let mut buf = vec![0u8; self.boot.bytes_per_sector as usize];
+        let mut loaded: Option<u64> = None;
+
+        for (i, (sector, off)) in self.entry_positions(dir, index, count)?.into_iter().enumerate() {
+            if loaded != Some(sector) {
+                if let Some(prev) = loaded {
+                    self.write_sector(prev, &buf)?;
+                }
+                self.read_sector(sector, &mut buf)?;
+                loaded = Some(sector);
+            }
+            buf[off..off + ENTRY_SIZE].copy_from_slice(&data[i * ENTRY_SIZE..(i + 1) * ENTRY_SIZE]);
+        }
+
+        if let Some(sector) = loaded {
+            self.write_sector(sector, &buf)?;
+        }
+        Ok(())
+    }
+
+    /// Mark an entry set as deleted (clear bit 7 of every entry type)
+    fn delete_entry_set(&self, dir: &Extent, index: u32, count: u32) -> KResult<()> {
+        let mut set = self.read_entries(dir, index, count)?;
+        for entry in set.chunks_exact_mut(ENTRY_SIZE) {
+            entry[0] &= !ENTRY_IN_USE;
+        }
+        self.write_entries(dir, index, &set)
+    }
+
+    /// Find `count` consecutive unused entries, growing the directory if needed
+    fn find_free_entries(&self, dir_node: &Arc<RwLock<ExfatNode>>, count: u32) -> KResult<u32> {
+        let extent = dir_node.read().extent();
+        let data = self.read_stream(&extent)?;
+        let capacity = (data.len() / ENTRY_SIZE) as u32;
+        let mut run_start = 0;
+        let mut run_len = 0;
+
+        for index in 0..capacity {
+            let entry_type = data[index as usize * ENTRY_SIZE];
+            if entry_type == ENTRY_TYPE_END_OF_DIR {
+                // Everything from the end marker on is unused
+                if run_len == 0 {
+                    run_start = index;
+                }
+                run_len += capacity - index;
+                break;
+            }
+            if entry_type & ENTRY_IN_USE == 0 {
+                if run_len == 0 {
+                    run_start = index;
+                }
+                run_len += 1;
+                if run_len == count {
+                    return Ok(run_start);
+                }
+            } else {
+                run_len = 0;
+            }
+        }
+
+        if run_len >= count {
+            return Ok(run_start);
+        }
+
+        // No room: append zeroed clusters (they read as end-of-directory)
+        let start = if run_len > 0 { run_start } else { capacity };
+        let needed_bytes = (start + count) as u64 * ENTRY_SIZE as u64;
+        if needed_bytes > MAX_DIR_SIZE {
+            return Err(KError::NoMemory);
+        }
+        let cs = self.boot.bytes_per_cluster as u64;
+        let needed_clusters = ((needed_bytes + cs - 1) / cs) as usize;
+
+        let mut dir = dir_node.write();
+        let have = self.extent_clusters(&dir.extent())?;
+        let clusters = self.grow(&mut dir, have, needed_clusters, true)?;
+        dir.data_length = clusters.len() as u64 * cs;
+        dir.valid_data_length = dir.data_length;
+        self.store_node(&dir)?;
+        Ok(start)
+    }
+
+    /// Build the file + stream + name entries for a node
+    fn build_entry_set(&self, name: &[u16], node: &ExfatNode) -> Vec<u8> {
+        let name_entries = (name.len() + NAME_CHARS_PER_ENTRY - 1) / NAME_CHARS_PER_ENTRY;
+        let mut set = vec![0u8; ENTRY_SIZE * (2 + name_entries)];
+
+        let (create, create_10ms, create_off) = unix_to_timestamp(node.create_time);
+        let (modify, modify_10ms, modify_off) = unix_to_timestamp(node.modify_time);
+        let (access, _, access_off) = unix_to_timestamp(node.access_time);
+        write_entry(
+            &mut set[..],
+            ExfatFileEntry {
+                entry_type: ENTRY_TYPE_FILE,
+                secondary_count: (1 + name_entries) as u8,
+                set_checksum: 0,
+                file_attributes: node.attributes,
+                reserved1: 0,
+                create_timestamp: create,
+                modify_timestamp: modify,
+                access_timestamp: access,
+                create_time_10ms: create_10ms,
+                modify_time_10ms: modify_10ms,
+                create_utc_offset: create_off,
+                modify_utc_offset: modify_off,
+                access_utc_offset: access_off,
+                reserved2: [0; 7],
+            },
+        );
+
+        write_entry(
+            &mut set[ENTRY_SIZE..],
+            ExfatStreamEntry {
+                entry_type: ENTRY_TYPE_STREAM_EXT,
+                flags: Self::stream_flags(node),
+                reserved1: 0,
+                name_length: name.len() as u8,
+                name_hash: self.name_hash(name),
+                reserved2: 0,
+                valid_data_length: node.valid_data_length,
+                reserved3: 0,
+                first_cluster: node.first_cluster,
+                data_length: node.data_length,
+            },
+        );
+
+        for (i, chunk) in name.chunks(NAME_CHARS_PER_ENTRY).enumerate() {
+            let mut file_name = [0u16; 15];
+            file_name[..chunk.len()].copy_from_slice(chunk);
+            write_entry(
+                &mut set[ENTRY_SIZE * (2 + i)..],
+                ExfatNameEntry { entry_type: ENTRY_TYPE_FILE_NAME, flags: 0, file_name },
+            );
+        }
+
+        let checksum = entry_set_checksum(&set);
+        set[2..4].copy_from_slice(&checksum.to_le_bytes());
+        set
+    }
+
+    fn stream_flags(node: &ExfatNode) -> u8 {
+        let mut flags = STREAM_FLAG_ALLOC_POSSIBLE;
+        if node.no_fat_chain && node.first_cluster != 0 {
+            flags |= STREAM_FLAG_NO_FAT_CHAIN;
+        }
+        flags
+    }
+
+    /// Write a node's attributes, times and allocation into its entry set
+    fn store_node(&self, node: &ExfatNode) -> KResult<()> {
+        let (parent, index) = match &node.loc {
+            Some((parent, index)) => (parent.read().extent(), *index),
+            None => return Ok(()),
+        };
+
+        let head = self.read_entries(&parent, index, 1)?;
+        if head[0] != ENTRY_TYPE_FILE {
+            return Err(KError::IO);
+        }
+        let mut set = self.read_entries(&parent, index, 1 + head[1] as u32)?;
+
+        let mut file: ExfatFileEntry = read_entry(&set);
+        file.file_attributes = node.attributes;
+        let (modify, modify_10ms, modify_off) = unix_to_timestamp(node.modify_time);
+        file.modify_timestamp = modify;
+        file.modify_time_10ms = modify_10ms;
+        file.modify_utc_offset = modify_off;
+        let (access, _, access_off) = unix_to_timestamp(node.access_time);
+        file.access_timestamp = access;
+        file.access_utc_offset = access_off;
+        write_entry(&mut set[..], file);
+
+        let mut stream: ExfatStreamEntry = read_entry(&set[ENTRY_SIZE..]);
+        stream.flags = (stream.flags & !STREAM_FLAG_NO_FAT_CHAIN) | Self::stream_flags(node);
+        stream.first_cluster = node.first_cluster;
+        stream.data_length = node.data_length;
+        stream.valid_data_length = node.valid_data_length;
+        write_entry(&mut set[ENTRY_SIZE..], stream);
+
+        let checksum = entry_set_checksum(&set);
+        set[2..4].copy_from_slice(&checksum.to_le_bytes());
+        self.write_entries(&parent, index, &set)
+    }
+
+    // ========================================================================
+    // Nodes
+    // ========================================================================
+
+    /// Shared state for an entry of `parent`, creating it if needed
+    fn get_node(&self, parent: &Arc<RwLock<ExfatNode>>, entry: &ParsedFileEntry) -> Arc<RwLock<ExfatNode>> {
+        let key = (parent.read().first_cluster, entry.index);
+        let mut nodes = self.nodes.lock();
+        if let Some(node) = nodes.get(&key).and_then(|w| w.upgrade()) {
+            return node;
+        }
+
+        let node = Arc::new(RwLock::new(ExfatNode {
+            loc: Some((Arc::clone(parent), entry.index)),
+            first_cluster: entry.first_cluster,
+            data_length: entry.data_length,
+            valid_data_length: entry.valid_data_length,
+            no_fat_chain: !entry.uses_fat_chain,
+            attributes: entry.attributes,
+            create_time: entry.create_time,
+            modify_time: entry.modify_time,
+            access_time: entry.access_time,
+            deleted: false,
+        }));
+
+        if nodes.len() >= 256 {
+            nodes.retain(|_, w| w.strong_count() > 0);
+        }
+        nodes.insert(key, Arc::downgrade(&node));
+        node
+    }
 }
 
-/// Helper function to convert cluster to sector
-fn cluster_to_sector(boot: &ExfatBootInfo, cluster: u32) -> u64 {
-    let cluster_offset = (cluster - 2) as u64;
-    boot.cluster_heap_offset as u64 + cluster_offset * boot.sectors_per_cluster as u64
+/// exFAT inode
+#[derive(Clone)]
+pub struct ExfatInode {
+    fs: Arc<ExfatFs>,
+    node: Arc<RwLock<ExfatNode>>,
+    is_dir: bool,
+    parent: Option<Arc<ExfatInode>>,
+}
+
+impl ExfatInode {
+    /// Extent of this directory
+    fn dir_extent(&self) -> KResult<Extent> {
+        if !self.is_dir {
+            return Err(KError::NotADirectory);
+        }
+        let node = self.node.read();
+        if node.deleted {
+            return Err(KError::NotFound);
+        }
+        Ok(node.extent())
+    }
+
+    fn child(&self, node: Arc<RwLock<ExfatNode>>, is_dir: bool) -> Inode {
+        Inode(Arc::new(ExfatInode {
+            fs: Arc::clone(&self.fs),
+            node,
+            is_dir,
+            parent: Some(Arc::new(self.clone())),
+        }))
+    }
+
+    /// Add an entry set for `node` to this directory; returns its index
+    fn add_entry_set(&self, name: &[u16], node: &ExfatNode) -> KResult<u32> {
+        let set = self.fs.build_entry_set(name, node);
+        let index = self.fs.find_free_entries(&self.node, (set.len() / ENTRY_SIZE) as u32)?;
+        let extent = self.node.read().extent();
+        self.fs.write_entries(&extent, index, &set)?;
+        Ok(index)
+    }
+
+    /// Remove an entry set and free its clusters
+    fn remove_entry(&self, dir: &Extent, entry: &ParsedFileEntry) -> KResult<()> {
+        self.fs.delete_entry_set(dir, entry.index, entry.entry_count)?;
+        let extent = Extent {
+            first_cluster: entry.first_cluster,
+            no_fat_chain: !entry.uses_fat_chain,
+            data_length: entry.data_length,
+        };
+        let clusters = self.fs.extent_clusters(&extent)?;
+        self.fs.free_clusters_list(&clusters)?;
+
+        // Open handles see the file as gone
+        if let Some(node) = self.fs.nodes.lock().remove(&(dir.first_cluster, entry.index)).and_then(|w| w.upgrade()) {
+            let mut node = node.write();
+            node.loc = None;
+            node.deleted = true;
+            node.first_cluster = 0;
+            node.data_length = 0;
+            node.valid_data_length = 0;
+        }
+        Ok(())
+    }
+
+    fn touch_dir(&self) -> KResult<()> {
+        let mut node = self.node.write();
+        node.modify_time = now_secs();
+        self.fs.store_node(&node)
+    }
 }
 
 impl InodeOps for ExfatInode {
@@ -596,19 +1724,42 @@ impl InodeOps for ExfatInode {
             InodeKind::File
         };
 
+        let node = self.node.read();
         let mode = if self.is_dir {
             Mode::from_octal(0o755)
-        } else if (self.attributes & ATTR_READ_ONLY) != 0 {
+        } else if (node.attributes & ATTR_READ_ONLY) != 0 {
             Mode::from_octal(0o444)
         } else {
             Mode::from_octal(0o644)
         };
 
-        Metadata::simple(Uid(0), Gid(0), mode, kind)
+        let mut meta = Metadata::simple(Uid(0), Gid(0), mode, kind);
+        meta.ino = match &node.loc {
+            Some((parent, index)) => (parent.read().first_cluster as u64) << 32 | *index as u64,
+            None => self.fs.boot.root_cluster as u64,
+        };
+        meta.atime = Timespec { secs: node.access_time, nsecs: 0 };
+        meta.mtime = Timespec { secs: node.modify_time, nsecs: 0 };
+        meta.ctime = Timespec { secs: node.modify_time, nsecs: 0 };
+        meta
     }
 
-    fn set_metadata(&self, _meta: Metadata) {
-        // Read-only filesystem
+    fn set_metadata(&self, meta: Metadata) {
+        // Only the read-only attribute and the times can be stored
+        let _ = self.fs.modify(|| {
+            let mut node = self.node.write();
+            if node.deleted || node.loc.is_none() {
+                return Ok(());
+            }
+            if meta.mode.to_octal() & 0o200 == 0 && !self.is_dir {
+                node.attributes |= ATTR_READ_ONLY;
+            } else {
+                node.attributes &= !ATTR_READ_ONLY;
+            }
+            node.modify_time = meta.mtime.secs;
+            node.access_time = meta.atime.secs;
+            self.fs.store_node(&node)
+        });
     }
 
     fn parent(&self) -> Option<Inode> {
@@ -616,45 +1767,83 @@ impl InodeOps for ExfatInode {
     }
 
     fn lookup(&self, name: &str) -> KResult<Inode> {
-        if !self.is_dir {
-            return Err(KError::NotADirectory);
-        }
+        let dir = self.dir_extent()?;
 
-        let entries = self.parse_dir_entries()?;
+        // Case-insensitive comparison (exFAT is case-preserving but case-insensitive)
+        match self.fs.find_entry(&dir, name)? {
+            Some(entry) => {
+                let node = self.fs.get_node(&self.node, &entry);
+                Ok(self.child(node, entry.is_directory()))
+            }
+            None => Err(KError::NotFound),
+        }
+    }
 
-        for entry in entries {
-            // Case-insensitive comparison (exFAT is case-preserving but case-insensitive)
-            if entry.name.eq_ignore_ascii_case(name) {
-                let inode = ExfatInode {
-                    device: Arc::clone(&self.device),
-                    boot: self.boot.clone(),
-                    first_cluster: entry.first_cluster,
-                    data_length: entry.data_length,
-                    valid_data_length: entry.valid_data_length,
-                    uses_fat_chain: entry.uses_fat_chain,
-                    is_dir: entry.is_directory(),
-                    attributes: entry.attributes,
-                    parent: Some(Arc::new(self.clone())),
-                };
+    fn create(&self, name: &str, kind: InodeKind, _meta: Metadata) -> KResult<Inode> {
+        let is_dir = match kind {
+            InodeKind::File => false,
+            InodeKind::Dir => true,
+            _ => return Err(KError::NotSupported),
+        };
 
-                return Ok(Inode(Arc::new(inode)));
+        let node = self.fs.modify(|| {
+            let dir = self.dir_extent()?;
+            let units = validate_name(name)?;
+            if self.fs.find_entry(&dir, name)?.is_some() {
+                return Err(KError::AlreadyExists);
             }
-        }
 
-        Err(KError::NotFound)
-    }
+            let now = now_secs();
+            let mut node = ExfatNode {
+                loc: None,
+                first_cluster: 0,
+                data_length: 0,
+                valid_data_length: 0,
+                no_fat_chain: false,
+                attributes: if is_dir { ATTR_DIRECTORY } else { ATTR_ARCHIVE },
+                create_time: now,
+                modify_time: now,
+                access_time: now,
+                deleted: false,
+            };
 
-    fn create(&self, _name: &str, _kind: InodeKind, _meta: Metadata) -> KResult<Inode> {
-        // Read-only filesystem
-        Err(KError::NotSupported)
+            // New directories get one zeroed cluster (exFAT has no "." or "..")
+            let clusters = if is_dir { self.fs.grow(&mut node, Vec::new(), 1, true)? } else { Vec::new() };
+            node.data_length = clusters.len() as u64 * self.fs.boot.bytes_per_cluster as u64;
+            node.valid_data_length = node.data_length;
+
+            let index = match self.add_entry_set(&units, &node) {
+                Ok(i) => i,
+                Err(e) => {
+                    self.fs.free_clusters_list(&clusters)?;
+                    return Err(e);
+                }
+            };
+            self.touch_dir()?;
+
+            let entry = ParsedFileEntry {
+                attributes: node.attributes,
+                name: String::from(name),
+                first_cluster: node.first_cluster,
+                data_length: node.data_length,
+                valid_data_length: node.valid_data_length,
+                uses_fat_chain: !node.no_fat_chain,
+                create_time: now,
+                modify_time: now,
+                access_time: now,
+                index,
+                entry_count: 0,
+            };
+            Ok(self.fs.get_node(&self.node, &entry))
+        })?;
+
+        Ok(self.child(node, is_dir))
     }
 
     fn readdir(&self) -> KResult<Vec<DirEntry>> {
-        if !self.is_dir {
-            return Err(KError::NotADirectory);
-        }
+        let dir = self.dir_extent()?;
 
-        let entries = self.parse_dir_entries()?;
+        let entries = self.fs.parse_dir_entries(&dir)?;
         let mut result = Vec::new();
 
         for entry in entries {
@@ -683,36 +1872,229 @@ impl InodeOps for ExfatInode {
             return Err(KError::IsADirectory);
         }
 
-        let file_size = self.valid_data_length as usize;
+        let (extent, valid) = {
+            let node = self.node.read();
+            (node.extent(), node.valid_data_length)
+        };
+        let file_size = extent.data_length as usize;
         if offset >= file_size {
             return Ok(0);
         }
 
-        let data = self.read_all_data()?;
-        let available = file_size.saturating_sub(offset);
-        let to_read = out.len().min(available);
+        let to_read = out.len().min(file_size - offset);
+        let cs = self.fs.boot.bytes_per_cluster as usize;
+
+        // Bytes past the valid data length read as zeros
+        let valid_end = core::cmp::min(offset + to_read, valid as usize);
+        out[..to_read].fill(0);
+        if offset < valid_end {
+            let clusters = self.fs.extent_clusters_upto(&extent, (valid_end + cs - 1) / cs)?;
+            let mut cluster_buf = vec![0u8; cs];
+            let mut pos = offset;
+            while pos < valid_end {
+                let cluster = *clusters.get(pos / cs).ok_or(KError::IO)?;
+                let in_cluster = pos % cs;
+                let chunk = core::cmp::min(valid_end - pos, cs - in_cluster);
+                self.fs.read_cluster_into(cluster, &mut cluster_buf)?;
+                out[pos - offset..pos - offset + chunk].copy_from_slice(&cluster_buf[in_cluster..in_cluster + chunk]);
+                pos += chunk;
+            }
+        }
 
-        out[..to_read].copy_from_slice(&data[offset..offset + to_read]);
         Ok(to_read)
     }
 
-    fn write_at(&self, _offset: usize, _data: &[u8]) -> KResult<usize> {
-        // Read-only filesystem
-        Err(KError::NotSupported)
+    fn write_at(&self, offset: usize, data: &[u8]) -> KResult<usize> {
+        if self.is_dir {
+            return Err(KError::IsADirectory);
+        }
+        if data.is_empty() {
+            return Ok(0);
+        }
+        let end = offset.checked_add(data.len()).ok_or(KError::Invalid)? as u64;
+
+        self.fs.modify(|| {
+            let mut node = self.node.write();
+            if node.deleted {
+                return Err(KError::NotFound);
+            }
+            if node.attributes & ATTR_READ_ONLY != 0 {
+                return Err(KError::PermissionDenied);
+            }
+
+            let cs = self.fs.bytes_per_cluster();
+            let have = self.fs.extent_clusters(&node.extent())?;
+            let needed = ((end + cs - 1) / cs) as usize;
+            let clusters = self.fs.grow(&mut node, have, needed, false)?;
+            if node.data_length < end {
+                node.data_length = end;
+            }
+
+            // The valid data length is a single watermark: fill the gap up to the write
+            if (offset as u64) > node.valid_data_length {
+                self.fs.zero_range(&clusters, node.valid_data_length, offset as u64)?;
+            }
+
+            let mut written = 0;
+            while written < data.len() {
+                let pos = offset + written;
+                let in_cluster = pos % cs as usize;
+                let chunk = core::cmp::min(data.len() - written, cs as usize - in_cluster);
+                self.fs.write_cluster_range(clusters[pos / cs as usize], in_cluster, &data[written..written + chunk])?;
+                written += chunk;
+            }
+
+            if node.valid_data_length < end {
+                node.valid_data_length = end;
+            }
+            node.attributes |= ATTR_ARCHIVE;
+            node.modify_time = now_secs();
+            self.fs.store_node(&node)?;
+            Ok(written)
+        })
     }
 
-    fn truncate(&self, _size: usize) -> KResult<()> {
-        // Read-only filesystem
-        Err(KError::NotSupported)
+    fn truncate(&self, size: usize) -> KResult<()> {
+        if self.is_dir {
+            return Err(KError::IsADirectory);
+        }
+        let size = size as u64;
+
+        self.fs.modify(|| {
+            let mut node = self.node.write();
+            if node.deleted {
+                return Err(KError::NotFound);
+            }
+
+            let cs = self.fs.bytes_per_cluster();
+            let have = self.fs.extent_clusters(&node.extent())?;
+            let needed = ((size + cs - 1) / cs) as usize;
+
+            if needed < have.len() {
+                self.fs.shrink(&mut node, &have, needed)?;
+            } else {
+                // New space stays past the valid data length, so it reads as zeros
+                self.fs.grow(&mut node, have, needed, false)?;
+            }
+
+            node.data_length = size;
+            node.valid_data_length = core::cmp::min(node.valid_data_length, size);
+            node.attributes |= ATTR_ARCHIVE;
+            node.modify_time = now_secs();
+            self.fs.store_node(&node)
+        })
     }
 
     fn size(&self) -> KResult<usize> {
         if self.is_dir {
             Ok(0)
         } else {
-            Ok(self.valid_data_length as usize)
+            Ok(self.node.read().data_length as usize)
         }
     }
+
+    fn unlink(&self, name: &str) -> KResult<()> {
+        self.fs.modify(|| {
+            let dir = self.dir_extent()?;
+            let entry = self.fs.find_entry(&dir, name)?.ok_or(KError::NotFound)?;
+            if entry.is_directory() {
+                return Err(KError::Invalid);
+            }
+            self.remove_entry(&dir, &entry)?;
+            self.touch_dir()
+        })
+    }
+
+    fn rmdir(&self, name: &str) -> KResult<()> {
+        self.fs.modify(|| {
+            let dir = self.dir_extent()?;
+            let entry = self.fs.find_entry(&dir, name)?.ok_or(KError::NotFound)?;
+            if !entry.is_directory() {
+                return Err(KError::NotADirectory);
+            }
+            let child = Extent {
+                first_cluster: entry.first_cluster,
+                no_fat_chain: !entry.uses_fat_chain,
+                data_length: entry.data_length,
+            };
+            if !self.fs.parse_dir_entries(&child)?.is_empty() {
+                return Err(KError::NotEmpty);
+            }
+            self.remove_entry(&dir, &entry)?;
+            self.touch_dir()
+        })
+    }
+
+    fn rename_to(&self, old_name: &str, new_parent: &Inode, new_name: &str) -> KResult<()> {
+        let target = vfs::own_inode(new_parent, &self.fs, |inode: &ExfatInode| &inode.fs)?;
+
+        self.fs.modify(|| {
+            let old_dir = self.dir_extent()?;
+            let new_dir = target.dir_extent()?;
+            let units = validate_name(new_name)?;
+
+            let entry = self.fs.find_entry(&old_dir, old_name)?.ok_or(KError::NotFound)?;
+            if let Some(existing) = self.fs.find_entry(&new_dir, new_name)? {
+                // Only a change of case of the same entry is allowed
+                if !(old_dir.first_cluster == new_dir.first_cluster && existing.index == entry.index) {
+                    return Err(KError::AlreadyExists);
+                }
+            }
+
+            // A directory cannot move below itself
+            if entry.is_directory() {
+                let mut cursor = Some(Arc::clone(&target.node));
+                while let Some(node) = cursor {
+                    let node = node.read();
+                    if node.first_cluster == entry.first_cluster {
+                        return Err(KError::Invalid);
+                    }
+                    cursor = node.loc.as_ref().map(|(parent, _)| Arc::clone(parent));
+                }
+            }
+
+            // Current state of the file, as an open handle may have changed it
+            let node = self.fs.get_node(&self.node, &entry);
+            let mut moved = node.write();
+
+            // Drop the old set first so a same-directory rename can reuse its slots
+            self.fs.delete_entry_set(&old_dir, entry.index, entry.entry_count)?;
+            let index = match target.add_entry_set(&units, &moved) {
+                Ok(i) => i,
+                Err(e) => {
+                    // Put the old set back
+                    let mut old_set = self.fs.read_entries(&old_dir, entry.index, entry.entry_count)?;
+                    for raw in old_set.chunks_exact_mut(ENTRY_SIZE) {
+                        raw[0] |= ENTRY_IN_USE;
+                    }
+                    self.fs.write_entries(&old_dir, entry.index, &old_set)?;
+                    return Err(e);
+                }
+            };
+
+            // Keep open handles pointing at the new location
+            moved.loc = Some((Arc::clone(&target.node), index));
+            drop(moved);
+            let mut nodes = self.fs.nodes.lock();
+            nodes.remove(&(old_dir.first_cluster, entry.index));
+            nodes.insert((new_dir.first_cluster, index), Arc::downgrade(&node));
+            drop(nodes);
+
+            self.touch_dir()?;
+            if old_dir.first_cluster != new_dir.first_cluster {
+                target.touch_dir()?;
+            }
+            Ok(())
+        })
+    }
+
+    fn as_any(&self) -> Option<&dyn Any> {
+        Some(self)
+    }
+
+    fn statfs(&self) -> KResult<FsStats> {
+        Ok(self.fs.stats())
+    }
 }
 
 // ============================================================================
@@ -721,8 +2103,8 @@ impl InodeOps for ExfatInode {
 
 /// Check if a device contains an exFAT filesystem
 pub fn is_exfat(device: &Arc<dyn BlockDevice>) -> KResult<bool> {
-    let mut buf = vec![0u8; 512];
-    device.read_blocks(0, 1, &mut buf)?;
+    let mut buf = vec![0u8; core::cmp::max(512, device.block_size() as usize)];
+    device.read_blocks(0, (buf.len() / device.block_size() as usize) as u32, &mut buf)?;
 
     // Check signature at offset 3
     if buf[3..11] == EXFAT_SIGNATURE {
@@ -21,7 +21,7 @@ use crate::security::{Gid, Uid};
 use crate::storage::BlockDevice;
 use crate::util::{KError, KResult};
 
-use super::vfs::{DirEntry, Inode, InodeKind, InodeOps, Metadata, Mode};
+use super::vfs::{DirEntry, FsStats, Inode, InodeKind, InodeOps, Metadata, Mode};
 
 // ============================================================================
 // Constantes ext2
@@ -154,6 +154,7 @@ pub struct Ext2Fs {
     first_data_block: u32,
     total_blocks: u32,
     total_inodes: u32,
+    reserved_blocks: u32,
 }
 
 impl Ext2Fs {
@@ -210,9 +211,29 @@ impl Ext2Fs {
             first_data_block: sb.s_first_data_block,
             total_blocks: sb.s_blocks_count,
             total_inodes: sb.s_inodes_count,
+            reserved_blocks: sb.s_r_blocks_count,
         }))
     }
 
+    /// Contagem de blocos e inodes para statfs
+    pub fn stats(&self) -> FsStats {
+        let groups = self.groups.read();
+        let (free_blocks, free_inodes) = groups.iter().fold((0u64, 0u64), |(b, i), g| {
+            (b + g.bg_free_blocks_count as u64, i + g.bg_free_inodes_count as u64)
+        });
+
+        FsStats {
+            magic: EXT2_SUPER_MAGIC as u64,
+            block_size: self.block_size as u64,
+            blocks: self.total_blocks as u64,
+            blocks_free: free_blocks,
+            blocks_avail: free_blocks.saturating_sub(self.reserved_blocks as u64),
+            files: self.total_inodes as u64,
+            files_free: free_inodes,
+            name_max: 255,
+        }
+    }
+
     /// Lê blocos do device convertendo block_size
     fn read_blocks_raw(
         device: &Arc<dyn BlockDevice>,
@@ -1271,6 +1292,10 @@ impl InodeOps for Ext2Inode2 {
         Ok(raw.i_size as usize)
     }
 
+    fn statfs(&self) -> KResult<FsStats> {
+        Ok(self.fs.stats())
+    }
+
     fn unlink(&self, name: &str) -> KResult<()> {
         let mut raw = self.get_raw()?;
         if Self::inode_kind(raw.i_mode) != InodeKind::Dir {
@@ -1,29 +1,49 @@
-//! ext4 filesystem driver (read-only)
+//! ext4 filesystem driver (read/write)
 //!
 //! Key differences from ext2:
 //! - Extents (vs block pointers) - more efficient for large files
 //! - 64-bit block addressing - larger filesystems
-//! - Journaling - data integrity (we ignore the journal for now)
+//! - Journaling - metadata updates go through the jbd2 journal (see `jbd2.rs`)
 //! - Directory indexes (htree) - faster directory lookup
 //! - Larger inodes (256 bytes default vs 128)
 //!
+//! Write support:
+//! - Every modifying operation runs as one transaction: metadata blocks
+//!   (bitmaps, group descriptors, inode table, directory and extent tree
+//!   blocks) are collected in memory, written to the journal, then
+//!   checkpointed to their home location. File data is written directly
+//!   before the commit (ordered mode).
+//! - Extent trees are rebuilt from a flat extent list after allocation or
+//!   truncation, reusing the existing tree blocks where possible.
+//! - metadata_csum (crc32c) and gdt_csum (crc16) checksums are maintained.
+//! - Indexed (htree) directories are converted back to linear directories
+//!   the first time they are modified.
+//! - Filesystems with features we cannot update safely (bigalloc, meta_bg,
+//!   quota, inline-data creation, ...) are mounted read-only.
+//!
+//! A dirty journal left behind by an unclean shutdown is replayed at mount.
+//!
 //! References:
 //! - https://ext4.wiki.kernel.org/index.php/Ext4_Disk_Layout
 //! - Linux kernel fs/ext4/
 
 #![allow(dead_code)]
 
+use alloc::collections::BTreeMap;
+use alloc::string::{String, ToString};
 use alloc::sync::Arc;
 use alloc::vec;
 use alloc::vec::Vec;
+use core::any::Any;
 use core::mem::size_of;
-use spin::RwLock;
+use spin::{Mutex, RwLock};
 
 use crate::security::{Gid, Uid};
 use crate::storage::BlockDevice;
 use crate::util::{KError, KResult};
 
-use super::vfs::{DirEntry, Inode, InodeKind, InodeOps, Metadata, Mode};
+use super::jbd2::Journal;
+use super::vfs::{self, DirEntry, FsStats, Inode, InodeKind, InodeOps, Metadata, Mode, Timespec};
 
 // ============================================================================
 // Constants
@@ -31,32 +51,126 @@ use super::vfs::{DirEntry, Inode, InodeKind, InodeOps, Metadata, Mode};
 
 const EXT4_SUPER_MAGIC: u16 = 0xEF53;
 const EXT4_SUPERBLOCK_OFFSET: u64 = 1024;
+const EXT4_SUPERBLOCK_SIZE: usize = 1024;
 const EXT4_ROOT_INO: u32 = 2;
+const EXT4_GOOD_OLD_INODE_SIZE: usize = 128;
 
-// Feature flags
+// Compatible features
+const EXT4_FEATURE_COMPAT_HAS_JOURNAL: u32 = 0x0004;
+
+// Incompatible features
+const EXT4_FEATURE_INCOMPAT_FILETYPE: u32 = 0x0002;
+const EXT4_FEATURE_INCOMPAT_RECOVER: u32 = 0x0004;
+const EXT4_FEATURE_INCOMPAT_JOURNAL_DEV: u32 = 0x0008;
+const EXT4_FEATURE_INCOMPAT_META_BG: u32 = 0x0010;
 const EXT4_FEATURE_INCOMPAT_EXTENTS: u32 = 0x0040;
 const EXT4_FEATURE_INCOMPAT_64BIT: u32 = 0x0080;
+const EXT4_FEATURE_INCOMPAT_MMP: u32 = 0x0100;
 const EXT4_FEATURE_INCOMPAT_FLEX_BG: u32 = 0x0200;
+const EXT4_FEATURE_INCOMPAT_EA_INODE: u32 = 0x0400;
+const EXT4_FEATURE_INCOMPAT_CSUM_SEED: u32 = 0x2000;
+const EXT4_FEATURE_INCOMPAT_LARGEDIR: u32 = 0x4000;
+const EXT4_FEATURE_INCOMPAT_INLINE_DATA: u32 = 0x8000;
+const EXT4_FEATURE_INCOMPAT_ENCRYPT: u32 = 0x10000;
+
+/// Incompatible features we know how to update
+const EXT4_WRITABLE_INCOMPAT: u32 = EXT4_FEATURE_INCOMPAT_FILETYPE
+    | EXT4_FEATURE_INCOMPAT_RECOVER
+    | EXT4_FEATURE_INCOMPAT_EXTENTS
+    | EXT4_FEATURE_INCOMPAT_64BIT
+    | EXT4_FEATURE_INCOMPAT_FLEX_BG
+    | EXT4_FEATURE_INCOMPAT_EA_INODE
+    | EXT4_FEATURE_INCOMPAT_CSUM_SEED
+    | EXT4_FEATURE_INCOMPAT_LARGEDIR
+    | EXT4_FEATURE_INCOMPAT_INLINE_DATA
+    | EXT4_FEATURE_INCOMPAT_ENCRYPT;
+
+// Read-only compatible features
+const EXT4_FEATURE_RO_COMPAT_SPARSE_SUPER: u32 = 0x0001;
+const EXT4_FEATURE_RO_COMPAT_LARGE_FILE: u32 = 0x0002;
+const EXT4_FEATURE_RO_COMPAT_BTREE_DIR: u32 = 0x0004;
+const EXT4_FEATURE_RO_COMPAT_HUGE_FILE: u32 = 0x0008;
+const EXT4_FEATURE_RO_COMPAT_GDT_CSUM: u32 = 0x0010;
+const EXT4_FEATURE_RO_COMPAT_DIR_NLINK: u32 = 0x0020;
+const EXT4_FEATURE_RO_COMPAT_EXTRA_ISIZE: u32 = 0x0040;
+const EXT4_FEATURE_RO_COMPAT_QUOTA: u32 = 0x0100;
+const EXT4_FEATURE_RO_COMPAT_BIGALLOC: u32 = 0x0200;
+const EXT4_FEATURE_RO_COMPAT_METADATA_CSUM: u32 = 0x0400;
+const EXT4_FEATURE_RO_COMPAT_READONLY: u32 = 0x1000;
+const EXT4_FEATURE_RO_COMPAT_PROJECT: u32 = 0x2000;
+
+/// Read-only compatible features we know how to update
+const EXT4_WRITABLE_RO_COMPAT: u32 = EXT4_FEATURE_RO_COMPAT_SPARSE_SUPER
+    | EXT4_FEATURE_RO_COMPAT_LARGE_FILE
+    | EXT4_FEATURE_RO_COMPAT_BTREE_DIR
+    | EXT4_FEATURE_RO_COMPAT_HUGE_FILE
+    | EXT4_FEATURE_RO_COMPAT_GDT_CSUM
+    | EXT4_FEATURE_RO_COMPAT_DIR_NLINK
+    | EXT4_FEATURE_RO_COMPAT_EXTRA_ISIZE
+    | EXT4_FEATURE_RO_COMPAT_METADATA_CSUM
+    | EXT4_FEATURE_RO_COMPAT_PROJECT;
+
+// Block group flags
+const EXT4_BG_INODE_UNINIT: u16 = 0x0001;
+const EXT4_BG_BLOCK_UNINIT: u16 = 0x0002;
 
 // Inode flags
+const EXT4_INDEX_FL: u32 = 0x00001000;
+const EXT4_HUGE_FILE_FL: u32 = 0x00040000;
 const EXT4_EXTENTS_FL: u32 = 0x00080000;
+const EXT4_INLINE_DATA_FL: u32 = 0x10000000;
 
 // File types in inode (i_mode >> 12)
-const EXT4_S_IFREG: u16 = 0x8; // regular file
-const EXT4_S_IFDIR: u16 = 0x4; // directory
-const EXT4_S_IFLNK: u16 = 0xA; // symlink
+const EXT4_S_IFIFO: u16 = 0x1; // fifo
 const EXT4_S_IFCHR: u16 = 0x2; // char device
+const EXT4_S_IFDIR: u16 = 0x4; // directory
 const EXT4_S_IFBLK: u16 = 0x6; // block device
+const EXT4_S_IFREG: u16 = 0x8; // regular file
+const EXT4_S_IFLNK: u16 = 0xA; // symlink
+const EXT4_S_IFSOCK: u16 = 0xC; // socket
 
 // Directory entry types (d_type)
 const EXT4_FT_REG_FILE: u8 = 1;
 const EXT4_FT_DIR: u8 = 2;
 const EXT4_FT_CHRDEV: u8 = 3;
 const EXT4_FT_BLKDEV: u8 = 4;
+const EXT4_FT_FIFO: u8 = 5;
+const EXT4_FT_SOCK: u8 = 6;
 const EXT4_FT_SYMLINK: u8 = 7;
+/// Fake file type marking the checksum tail of a directory block
+const EXT4_FT_DIR_CSUM: u8 = 0xDE;
+const EXT4_DIRENT_TAIL_SIZE: usize = 12;
+const EXT4_NAME_LEN: usize = 255;
 
 // Extent header magic
 const EXT4_EXT_MAGIC: u16 = 0xF30A;
+/// Extents that fit in the inode's i_block
+const EXT4_EXT_ROOT_MAX: usize = 4;
+const EXT4_EXT_INIT_MAX_LEN: u32 = 32768;
+const EXT4_EXT_UNINIT_MAX_LEN: u32 = 32767;
+
+/// Fast symlinks store the target in i_block when it is shorter than this
+const EXT4_FAST_SYMLINK_MAX: usize = 60;
+
+const EXT4_CRC32C_CHKSUM: u8 = 1;
+
+// Superblock field offsets used when updating the on-disk copy
+const SB_R_BLOCKS_LO: usize = 0x08;
+const SB_FREE_BLOCKS_LO: usize = 0x0C;
+const SB_FREE_INODES: usize = 0x10;
+const SB_WTIME: usize = 0x30;
+const SB_FEATURE_INCOMPAT: usize = 0x60;
+const SB_R_BLOCKS_HI: usize = 0x154;
+const SB_FREE_BLOCKS_HI: usize = 0x158;
+const SB_CHECKSUM_SEED: usize = 0x270;
+const SB_CHECKSUM: usize = 0x3FC;
+
+// Group descriptor field offsets
+const BG_CHECKSUM: usize = 0x1E;
+
+// Inode field offsets (checksums live in the middle of the structure)
+const INODE_CHECKSUM_LO: usize = 0x7C;
+const INODE_CHECKSUM_HI: usize = 0x82;
 
 // ============================================================================
 // On-disk structures (packed, little-endian)
@@ -164,6 +278,64 @@ struct Ext4BlockGroupDesc {
     bg_reserved: u32,
 }
 
+impl Ext4BlockGroupDesc {
+    fn block_bitmap(&self) -> u64 {
+        (self.bg_block_bitmap_hi as u64) << 32 | self.bg_block_bitmap_lo as u64
+    }
+
+    fn inode_bitmap(&self) -> u64 {
+        (self.bg_inode_bitmap_hi as u64) << 32 | self.bg_inode_bitmap_lo as u64
+    }
+
+    fn inode_table(&self) -> u64 {
+        (self.bg_inode_table_hi as u64) << 32 | self.bg_inode_table_lo as u64
+    }
+
+    fn free_blocks(&self) -> u32 {
+        (self.bg_free_blocks_count_hi as u32) << 16 | self.bg_free_blocks_count_lo as u32
+    }
+
+    fn set_free_blocks(&mut self, v: u32) {
+        self.bg_free_blocks_count_lo = v as u16;
+        self.bg_free_blocks_count_hi = (v >> 16) as u16;
+    }
+
+    fn free_inodes(&self) -> u32 {
+        (self.bg_free_inodes_count_hi as u32) << 16 | self.bg_free_inodes_count_lo as u32
+    }
+
+    fn set_free_inodes(&mut self, v: u32) {
+        self.bg_free_inodes_count_lo = v as u16;
+        self.bg_free_inodes_count_hi = (v >> 16) as u16;
+    }
+
+    fn used_dirs(&self) -> u32 {
+        (self.bg_used_dirs_count_hi as u32) << 16 | self.bg_used_dirs_count_lo as u32
+    }
+
+    fn set_used_dirs(&mut self, v: u32) {
+        self.bg_used_dirs_count_lo = v as u16;
+        self.bg_used_dirs_count_hi = (v >> 16) as u16;
+    }
+
+    fn itable_unused(&self) -> u32 {
+        (self.bg_itable_unused_hi as u32) << 16 | self.bg_itable_unused_lo as u32
+    }
+
+    fn set_itable_unused(&mut self, v: u32) {
+        self.bg_itable_unused_lo = v as u16;
+        self.bg_itable_unused_hi = (v >> 16) as u16;
+    }
+
+    fn to_bytes(&self) -> [u8; 64] {
+        let mut out = [0u8; 64];
+        unsafe {
+            core::ptr::write_unaligned(out.as_mut_ptr() as *mut Ext4BlockGroupDesc, *self);
+        }
+        out
+    }
+}
+
 #[repr(C, packed)]
 #[derive(Debug, Clone, Copy)]
 struct Ext4Inode {
@@ -196,6 +368,82 @@ struct Ext4Inode {
     i_projid: u32,
 }
 
+impl Ext4Inode {
+    fn zeroed() -> Self {
+        // All fields are plain integers
+        unsafe { core::mem::zeroed() }
+    }
+
+    fn i_block_bytes(&self) -> [u8; 60] {
+        let mut out = [0u8; 60];
+        unsafe {
+            core::ptr::copy_nonoverlapping(
+                core::ptr::addr_of!(self.i_block) as *const u8,
+                out.as_mut_ptr(),
+                60,
+            );
+        }
+        out
+    }
+
+    fn set_i_block_bytes(&mut self, data: &[u8; 60]) {
+        unsafe {
+            core::ptr::copy_nonoverlapping(
+                data.as_ptr(),
+                core::ptr::addr_of_mut!(self.i_block) as *mut u8,
+                60,
+            );
+        }
+    }
+
+    fn uid(&self) -> u32 {
+        let osd2 = self.i_osd2;
+        (u16::from_le_bytes([osd2[4], osd2[5]]) as u32) << 16 | self.i_uid as u32
+    }
+
+    fn gid(&self) -> u32 {
+        let osd2 = self.i_osd2;
+        (u16::from_le_bytes([osd2[6], osd2[7]]) as u32) << 16 | self.i_gid as u32
+    }
+
+    fn set_uid(&mut self, uid: u32) {
+        self.i_uid = uid as u16;
+        let mut osd2 = self.i_osd2;
+        osd2[4..6].copy_from_slice(&((uid >> 16) as u16).to_le_bytes());
+        self.i_osd2 = osd2;
+    }
+
+    fn set_gid(&mut self, gid: u32) {
+        self.i_gid = gid as u16;
+        let mut osd2 = self.i_osd2;
+        osd2[6..8].copy_from_slice(&((gid >> 16) as u16).to_le_bytes());
+        self.i_osd2 = osd2;
+    }
+
+    fn file_acl(&self) -> u64 {
+        let osd2 = self.i_osd2;
+        (u16::from_le_bytes([osd2[2], osd2[3]]) as u64) << 32 | self.i_file_acl_lo as u64
+    }
+
+    fn set_size(&mut self, size: u64) {
+        self.i_size_lo = size as u32;
+        self.i_size_high = (size >> 32) as u32;
+    }
+
+    /// Set i_blocks from a count of filesystem blocks
+    fn set_block_count(&mut self, fs_blocks: u64, block_size: u32) {
+        let units = if self.i_flags & EXT4_HUGE_FILE_FL != 0 {
+            fs_blocks
+        } else {
+            fs_blocks * (block_size as u64 / 512)
+        };
+        self.i_blocks_lo = units as u32;
+        let mut osd2 = self.i_osd2;
+        osd2[0..2].copy_from_slice(&((units >> 32) as u16).to_le_bytes());
+        self.i_osd2 = osd2;
+    }
+}
+
 #[repr(C, packed)]
 #[derive(Debug, Clone, Copy)]
 struct Ext4DirEntry {
@@ -263,11 +511,124 @@ impl Ext4Extent {
     }
 }
 
+/// In-memory form of a leaf extent, used when rebuilding extent trees
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct ExtentRec {
+    lblk: u32,
+    len: u32,
+    pblk: u64,
+    uninit: bool,
+}
+
+impl ExtentRec {
+    fn end(&self) -> u32 {
+        self.lblk + self.len
+    }
+
+    fn max_len(&self) -> u32 {
+        if self.uninit {
+            EXT4_EXT_UNINIT_MAX_LEN
+        } else {
+            EXT4_EXT_INIT_MAX_LEN
+        }
+    }
+}
+
+// ============================================================================
+// Checksums
+// ============================================================================
+
+const fn make_crc32c_table() -> [u32; 256] {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut j = 0;
+        while j < 8 {
+            if crc & 1 != 0 {
+                crc = (crc >> 1) ^ 0x82F6_3B78;
+            } else {
+                crc >>= 1;
+            }
+            j += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+static CRC32C_TABLE: [u32; 256] = make_crc32c_table();
+
+/// Raw crc32c (Castagnoli) update, without pre/post inversion.
+///
+/// This matches how ext4 and jbd2 chain checksums: the seed is passed in
+/// as `crc` and the result is stored as-is.
+pub(super) fn crc32c(mut crc: u32, data: &[u8]) -> u32 {
+    for &b in data {
+        crc = CRC32C_TABLE[((crc ^ b as u32) & 0xFF) as usize] ^ (crc >> 8);
+    }
+    crc
+}
+
+/// crc16 (poly 0x8005, reflected) as used by the gdt_csum feature
+fn crc16(mut crc: u16, data: &[u8]) -> u16 {
+    for &b in data {
+        crc ^= b as u16;
+        for _ in 0..8 {
+            if crc & 1 != 0 {
+                crc = (crc >> 1) ^ 0xA001;
+            } else {
+                crc >>= 1;
+            }
+        }
+    }
+    crc
+}
+
+/// Which metadata checksum scheme the filesystem uses
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Checksum {
+    None,
+    /// Group descriptors only (crc16)
+    GdtCsum,
+    /// Full metadata_csum (crc32c) with the given seed
+    Metadata(u32),
+}
+
+// ============================================================================
+// Little-endian helpers
+// ============================================================================
+
+fn le16(buf: &[u8], off: usize) -> u16 {
+    u16::from_le_bytes([buf[off], buf[off + 1]])
+}
+
+fn le32(buf: &[u8], off: usize) -> u32 {
+    u32::from_le_bytes([buf[off], buf[off + 1], buf[off + 2], buf[off + 3]])
+}
+
+fn put_le16(buf: &mut [u8], off: usize, v: u16) {
+    buf[off..off + 2].copy_from_slice(&v.to_le_bytes());
+}
+
+fn put_le32(buf: &mut [u8], off: usize, v: u32) {
+    buf[off..off + 4].copy_from_slice(&v.to_le_bytes());
+}
+
+fn align4(n: usize) -> usize {
+    (n + 3) & !3
+}
+
+fn now_secs() -> u32 {
+    crate::time::realtime().tv_sec as u32
+}
+
 // ============================================================================
 // Driver structures
 // ============================================================================
 
-/// Mounted ext4 filesystem (read-only)
+/// Mounted ext4 filesystem
 pub struct Ext4Fs {
     device: Arc<dyn BlockDevice>,
     block_size: u32,
@@ -276,25 +637,73 @@ pub struct Ext4Fs {
     inode_size: u16,
     groups: RwLock<Vec<Ext4BlockGroupDesc>>,
     first_data_block: u32,
+    first_ino: u32,
     total_blocks: u64,
     total_inodes: u32,
+    reserved_gdt_blocks: u16,
     has_extents: bool,
     is_64bit: bool,
     desc_size: u16,
+    feature_ro_compat: u32,
+    /// Mounted read-only (unsupported features or no usable journal)
+    read_only: bool,
+    csum: Checksum,
+    uuid: [u8; 16],
+    /// On-disk superblock image, updated on every commit
+    sb_raw: Mutex<Vec<u8>>,
+    journal: Option<Journal>,
+    /// Metadata blocks modified by the running transaction
+    txn: Mutex<BTreeMap<u64, Vec<u8>>>,
+    /// Serializes modifying operations (one transaction at a time)
+    op_lock: Mutex<()>,
 }
 
 impl Ext4Fs {
     /// Mount an ext4 filesystem from a block device
+    ///
+    /// If the journal holds committed transactions that were never
+    /// checkpointed, they are replayed before the filesystem is used. A
+    /// filesystem that needs recovery but can only be mounted read-only is
+    /// refused.
     pub fn mount(device: Arc<dyn BlockDevice>) -> KResult<Arc<Self>> {
-        // Read superblock (offset 1024)
-        let sb_block = EXT4_SUPERBLOCK_OFFSET / device.block_size() as u64;
-        let sb_offset = (EXT4_SUPERBLOCK_OFFSET % device.block_size() as u64) as usize;
+        let fs = Self::mount_once(Arc::clone(&device))?;
+
+        let needs_recovery = fs.journal.as_ref().map(|j| j.needs_recovery()).unwrap_or(false);
+        if needs_recovery && fs.read_only {
+            // Replaying writes to the device; the metadata on disk cannot be
+            // trusted without it
+            crate::kprintln!("ext4: journal needs recovery but the filesystem is read-only");
+            return Err(KError::ReadOnly);
+        }
+        if needs_recovery {
+            crate::kprintln!("ext4: journal needs recovery, replaying");
+            if let Some(journal) = fs.journal.as_ref() {
+                journal.recover()?;
+            }
+            // Group descriptors and the superblock may have been replayed
+            drop(fs);
+            let fs = Self::mount_once(device)?;
+            fs.write_superblock(false)?;
+            return Ok(fs);
+        }
+
+        if !fs.read_only {
+            let sb = fs.sb_raw.lock();
+            let recover_flag = le32(&sb, SB_FEATURE_INCOMPAT) & EXT4_FEATURE_INCOMPAT_RECOVER != 0;
+            drop(sb);
+            if recover_flag {
+                fs.write_superblock(false)?;
+            }
+        }
 
-        let mut buf = vec![0u8; device.block_size() as usize];
-        device.read_blocks(sb_block, 1, &mut buf)?;
+        Ok(fs)
+    }
+
+    fn mount_once(device: Arc<dyn BlockDevice>) -> KResult<Arc<Self>> {
+        let sb_raw = read_superblock_raw(&device)?;
 
         let sb: Ext4Superblock = unsafe {
-            core::ptr::read_unaligned(buf.as_ptr().add(sb_offset) as *const _)
+            core::ptr::read_unaligned(sb_raw.as_ptr() as *const _)
         };
 
         // Verify magic
@@ -325,7 +734,7 @@ impl Ext4Fs {
         };
 
         // Calculate number of block groups
-        let groups_count = ((total_blocks + sb.s_blocks_per_group as u64 - 1)
+        let groups_count = ((total_blocks - sb.s_first_data_block as u64 + sb.s_blocks_per_group as u64 - 1)
             / sb.s_blocks_per_group as u64) as u32;
 
         // Read block group descriptor table
@@ -347,66 +756,137 @@ impl Ext4Fs {
                 groups.push(desc);
             } else {
                 // 32-byte descriptor, fill high parts with zeros
-                let small_buf = &bgdt_buf[offset..offset + 32];
-                let mut desc = Ext4BlockGroupDesc {
-                    bg_block_bitmap_lo: 0,
-                    bg_inode_bitmap_lo: 0,
-                    bg_inode_table_lo: 0,
-                    bg_free_blocks_count_lo: 0,
-                    bg_free_inodes_count_lo: 0,
-                    bg_used_dirs_count_lo: 0,
-                    bg_flags: 0,
-                    bg_exclude_bitmap_lo: 0,
-                    bg_block_bitmap_csum_lo: 0,
-                    bg_inode_bitmap_csum_lo: 0,
-                    bg_itable_unused_lo: 0,
-                    bg_checksum: 0,
-                    bg_block_bitmap_hi: 0,
-                    bg_inode_bitmap_hi: 0,
-                    bg_inode_table_hi: 0,
-                    bg_free_blocks_count_hi: 0,
-                    bg_free_inodes_count_hi: 0,
-                    bg_used_dirs_count_hi: 0,
-                    bg_itable_unused_hi: 0,
-                    bg_exclude_bitmap_hi: 0,
-                    bg_block_bitmap_csum_hi: 0,
-                    bg_inode_bitmap_csum_hi: 0,
-                    bg_reserved: 0,
+                let mut full = [0u8; 64];
+                full[..32].copy_from_slice(&bgdt_buf[offset..offset + 32]);
+                let desc: Ext4BlockGroupDesc = unsafe {
+                    core::ptr::read_unaligned(full.as_ptr() as *const _)
                 };
-                // Copy first 32 bytes
-                unsafe {
-                    core::ptr::copy_nonoverlapping(
-                        small_buf.as_ptr(),
-                        &mut desc as *mut _ as *mut u8,
-                        32,
-                    );
-                }
                 groups.push(desc);
             }
         }
 
-        crate::kprintln!(
-            "ext4: mounted (blocks={}, block_size={}, extents={}, 64bit={})",
-            total_blocks,
-            block_size,
-            has_extents,
-            is_64bit
-        );
+        // Checksum scheme
+        let uuid = sb.s_uuid;
+        let csum = if sb.s_feature_ro_compat & EXT4_FEATURE_RO_COMPAT_METADATA_CSUM != 0 {
+            let seed = if sb.s_feature_incompat & EXT4_FEATURE_INCOMPAT_CSUM_SEED != 0 {
+                le32(&sb_raw, SB_CHECKSUM_SEED)
+            } else {
+                crc32c(!0, &uuid)
+            };
+            Checksum::Metadata(seed)
+        } else if sb.s_feature_ro_compat & EXT4_FEATURE_RO_COMPAT_GDT_CSUM != 0 {
+            Checksum::GdtCsum
+        } else {
+            Checksum::None
+        };
+
+        // Decide whether we can safely write to this filesystem
+        let mut read_only = false;
+        let unsupported_incompat = sb.s_feature_incompat & !EXT4_WRITABLE_INCOMPAT;
+        let unsupported_ro = sb.s_feature_ro_compat & !EXT4_WRITABLE_RO_COMPAT;
+        if unsupported_incompat != 0 || unsupported_ro != 0 {
+            crate::kprintln!(
+                "ext4: unsupported features (incompat=0x{:x}, ro_compat=0x{:x}), mounting read-only",
+                unsupported_incompat,
+                unsupported_ro
+            );
+            read_only = true;
+        }
+        if !has_extents {
+            read_only = true;
+        }
+        if let Checksum::Metadata(_) = csum {
+            if sb.s_checksum_type != EXT4_CRC32C_CHKSUM {
+                read_only = true;
+            }
+        }
 
-        Ok(Arc::new(Self {
-            device,
+        let mut fs = Self {
+            device: Arc::clone(&device),
             block_size,
             blocks_per_group: sb.s_blocks_per_group,
             inodes_per_group: sb.s_inodes_per_group,
             inode_size,
             groups: RwLock::new(groups),
             first_data_block: sb.s_first_data_block,
+            first_ino: if sb.s_rev_level >= 1 { sb.s_first_ino } else { 11 },
             total_blocks,
             total_inodes: sb.s_inodes_count,
+            reserved_gdt_blocks: sb.s_reserved_gdt_blocks,
             has_extents,
             is_64bit,
             desc_size,
-        }))
+            feature_ro_compat: sb.s_feature_ro_compat,
+            read_only,
+            csum,
+            uuid,
+            sb_raw: Mutex::new(sb_raw),
+            journal: None,
+            txn: Mutex::new(BTreeMap::new()),
+            op_lock: Mutex::new(()),
+        };
+
+        // Open the internal journal
+        if sb.s_feature_compat & EXT4_FEATURE_COMPAT_HAS_JOURNAL != 0 {
+            let journal = if sb.s_journal_inum != 0 {
+                fs.open_journal(sb.s_journal_inum)
+            } else {
+                Err(KError::NotSupported)
+            };
+            match journal {
+                Ok(j) => fs.journal = Some(j),
+                Err(e) => {
+                    crate::kprintln!("ext4: cannot open journal ({:?}), mounting read-only", e);
+                    fs.read_only = true;
+                }
+            }
+        }
+
+        crate::kprintln!(
+            "ext4: mounted (blocks={}, block_size={}, extents={}, 64bit={}, journal={}, rw={})",
+            total_blocks,
+            block_size,
+            has_extents,
+            is_64bit,
+            fs.journal.is_some(),
+            !fs.read_only
+        );
+
+        Ok(Arc::new(fs))
+    }
+
+    /// Resolve the journal inode to its physical blocks and load it
+    fn open_journal(&self, journal_ino: u32) -> KResult<Journal> {
+        let inode = self.read_inode(journal_ino)?;
+        let size = self.inode_size(&inode);
+        let nblocks = (size / self.block_size as u64) as u32;
+
+        let mut blocks = Vec::with_capacity(nblocks as usize);
+        if self.uses_extents(&inode) {
+            let (extents, _) = self.collect_extents(&inode)?;
+            for lblk in 0..nblocks {
+                match Self::map_lookup(&extents, lblk) {
+                    Some((p, _)) => blocks.push(p),
+                    None => return Err(KError::Invalid),
+                }
+            }
+        } else {
+            let i_block = inode.i_block_bytes();
+            for lblk in 0..nblocks {
+                let p = self.get_block_direct(&i_block, lblk)?;
+                if p == 0 {
+                    return Err(KError::Invalid);
+                }
+                blocks.push(p);
+            }
+        }
+
+        Journal::load(Arc::clone(&self.device), self.block_size, blocks)
+    }
+
+    /// Is this filesystem mounted read-write?
+    pub fn is_writable(&self) -> bool {
+        !self.read_only
     }
 
     /// Read blocks from device, converting block size
@@ -424,14 +904,173 @@ impl Ext4Fs {
         device.read_blocks(start_lba, lba_count, buf)
     }
 
-    /// Read a filesystem block
+    /// Write blocks to device, converting block size
+    fn write_blocks_raw(
+        device: &Arc<dyn BlockDevice>,
+        block_size: u32,
+        block: u64,
+        count: u32,
+        buf: &[u8],
+    ) -> KResult<()> {
+        let dev_block_size = device.block_size();
+        let factor = block_size / dev_block_size;
+        let start_lba = block * factor as u64;
+        let lba_count = count * factor;
+        device.write_blocks(start_lba, lba_count, buf)
+    }
+
+    /// Read a filesystem block (sees metadata pending in the running transaction)
     fn read_block(&self, block: u64, buf: &mut [u8]) -> KResult<()> {
+        if let Some(pending) = self.txn.lock().get(&block) {
+            buf.copy_from_slice(pending);
+            return Ok(());
+        }
         Self::read_blocks_raw(&self.device, self.block_size, block, 1, buf)
     }
 
-    /// Read an inode by number
-    fn read_inode(&self, ino: u32) -> KResult<Ext4Inode> {
-        if ino == 0 {
+    /// Queue a metadata block for the running transaction
+    fn write_meta(&self, block: u64, buf: &[u8]) {
+        self.txn.lock().insert(block, buf.to_vec());
+    }
+
+    /// Write a file data block directly (ordered mode: before the commit)
+    fn write_data_block(&self, block: u64, buf: &[u8]) -> KResult<()> {
+        self.txn.lock().remove(&block);
+        Self::write_blocks_raw(&self.device, self.block_size, block, 1, buf)
+    }
+
+    /// Run a modifying operation as a single transaction.
+    ///
+    /// On success the collected metadata is committed through the journal;
+    /// on failure it is discarded and the in-memory group descriptors are
+    /// restored, so nothing of the failed operation reaches the disk.
+    fn transaction<T>(&self, f: impl FnOnce() -> KResult<T>) -> KResult<T> {
+        if self.read_only {
+            return Err(KError::ReadOnly);
+        }
+
+        let _guard = self.op_lock.lock();
+        let saved_groups = self.groups.read().clone();
+
+        match f() {
+            Ok(v) => {
+                if let Err(e) = self.commit() {
+                    *self.groups.write() = saved_groups;
+                    return Err(e);
+                }
+                Ok(v)
+            }
+            Err(e) => {
+                self.txn.lock().clear();
+                *self.groups.write() = saved_groups;
+                Err(e)
+            }
+        }
+    }
+
+    /// Commit the running transaction: journal, checkpoint, mark clean
+    fn commit(&self) -> KResult<()> {
+        let pending = core::mem::take(&mut *self.txn.lock());
+        if pending.is_empty() {
+            return Ok(());
+        }
+
+        let blocks: Vec<(u64, &[u8])> = pending.iter().map(|(b, d)| (*b, d.as_slice())).collect();
+
+        match self.journal.as_ref() {
+            Some(journal) => {
+                // The whole operation must land in one transaction, or a crash
+                // could leave it half applied
+                if blocks.len() > journal.max_transaction_blocks() {
+                    return Err(KError::NoSpace);
+                }
+                journal.commit(&blocks)?;
+                self.write_superblock(true)?;
+                for (block, data) in &blocks {
+                    Self::write_blocks_raw(&self.device, self.block_size, *block, 1, data)?;
+                }
+                journal.checkpoint_done()?;
+            }
+            None => {
+                for (block, data) in &blocks {
+                    Self::write_blocks_raw(&self.device, self.block_size, *block, 1, data)?;
+                }
+            }
+        }
+
+        self.write_superblock(false)
+    }
+
+    /// Block and inode counts for statfs
+    pub fn stats(&self) -> FsStats {
+        let (free_blocks, free_inodes) = {
+            let groups = self.groups.read();
+            groups.iter().fold((0u64, 0u64), |(b, i), g| {
+                (b + g.free_blocks() as u64, i + g.free_inodes() as u64)
+            })
+        };
+
+        let reserved = {
+            let sb = self.sb_raw.lock();
+            let mut r = le32(&sb, SB_R_BLOCKS_LO) as u64;
+            if self.is_64bit {
+                r |= (le32(&sb, SB_R_BLOCKS_HI) as u64) << 32;
+            }
+            r
+        };
+
+        FsStats {
+            magic: EXT4_SUPER_MAGIC as u64,
+            block_size: self.block_size as u64,
+            blocks: self.total_blocks,
+            blocks_free: free_blocks,
+            blocks_avail: free_blocks.saturating_sub(reserved),
+            files: self.total_inodes as u64,
+            files_free: free_inodes,
+            name_max: 255,
+        }
+    }
+
+    /// Update free counts (and the needs_recovery flag) in the on-disk superblock
+    fn write_superblock(&self, recovering: bool) -> KResult<()> {
+        let (free_blocks, free_inodes) = {
+            let groups = self.groups.read();
+            groups.iter().fold((0u64, 0u32), |(b, i), g| {
+                (b + g.free_blocks() as u64, i + g.free_inodes())
+            })
+        };
+
+        let mut sb = self.sb_raw.lock();
+        put_le32(&mut sb, SB_FREE_BLOCKS_LO, free_blocks as u32);
+        if self.is_64bit {
+            put_le32(&mut sb, SB_FREE_BLOCKS_HI, (free_blocks >> 32) as u32);
+        }
+        put_le32(&mut sb, SB_FREE_INODES, free_inodes);
+        put_le32(&mut sb, SB_WTIME, now_secs());
+
+        let mut incompat = le32(&sb, SB_FEATURE_INCOMPAT);
+        if recovering {
+            incompat |= EXT4_FEATURE_INCOMPAT_RECOVER;
+        } else {
+            incompat &= !EXT4_FEATURE_INCOMPAT_RECOVER;
+        }
+        put_le32(&mut sb, SB_FEATURE_INCOMPAT, incompat);
+
+        if let Checksum::Metadata(_) = self.csum {
+            let csum = crc32c(!0, &sb[..SB_CHECKSUM]);
+            put_le32(&mut sb, SB_CHECKSUM, csum);
+        }
+
+        write_superblock_raw(&self.device, &sb)
+    }
+
+    // ========================================================================
+    // Inodes
+    // ========================================================================
+
+    /// Locate an inode in the inode table: (block, offset in block)
+    fn inode_location(&self, ino: u32) -> KResult<(u64, usize)> {
+        if ino == 0 || ino > self.total_inodes {
             return Err(KError::Invalid);
         }
 
@@ -439,50 +1078,529 @@ impl Ext4Fs {
         let index = (ino - 1) % self.inodes_per_group;
 
         let groups = self.groups.read();
-        let group_desc = &groups[group as usize];
-        let inode_table_block = if self.is_64bit {
-            (group_desc.bg_inode_table_hi as u64) << 32 | group_desc.bg_inode_table_lo as u64
-        } else {
-            group_desc.bg_inode_table_lo as u64
-        };
+        let inode_table_block = groups.get(group as usize).ok_or(KError::Invalid)?.inode_table();
         drop(groups);
 
         let inode_offset = index as usize * self.inode_size as usize;
         let block_offset = inode_offset / self.block_size as usize;
         let offset_in_block = inode_offset % self.block_size as usize;
 
+        Ok((inode_table_block + block_offset as u64, offset_in_block))
+    }
+
+    /// Read an inode by number
+    fn read_inode(&self, ino: u32) -> KResult<Ext4Inode> {
+        let (block, offset_in_block) = self.inode_location(ino)?;
+
         let mut buf = vec![0u8; self.block_size as usize];
-        self.read_block(inode_table_block + block_offset as u64, &mut buf)?;
+        self.read_block(block, &mut buf)?;
+
+        let mut raw = [0u8; size_of::<Ext4Inode>()];
+        let len = core::cmp::min(self.inode_size as usize, raw.len());
+        raw[..len].copy_from_slice(&buf[offset_in_block..offset_in_block + len]);
 
         let inode: Ext4Inode = unsafe {
-            core::ptr::read_unaligned(buf.as_ptr().add(offset_in_block) as *const _)
+            core::ptr::read_unaligned(raw.as_ptr() as *const _)
         };
 
         Ok(inode)
     }
 
-    /// Get file size (64-bit)
-    fn inode_size(&self, inode: &Ext4Inode) -> u64 {
-        (inode.i_size_high as u64) << 32 | inode.i_size_lo as u64
-    }
-
-    /// Check if inode uses extents
-    fn uses_extents(&self, inode: &Ext4Inode) -> bool {
-        self.has_extents && (inode.i_flags & EXT4_EXTENTS_FL) != 0
+    /// Number of bytes of `Ext4Inode` that belong to this on-disk inode
+    fn inode_struct_len(&self, inode: &Ext4Inode) -> usize {
+        if self.inode_size as usize <= EXT4_GOOD_OLD_INODE_SIZE {
+            return EXT4_GOOD_OLD_INODE_SIZE;
+        }
+        let extra = inode.i_extra_isize as usize;
+        let len = EXT4_GOOD_OLD_INODE_SIZE + extra;
+        core::cmp::min(len, core::cmp::min(size_of::<Ext4Inode>(), self.inode_size as usize))
     }
 
-    /// Read inode data using extent tree
-    fn read_extent_data(&self, inode: &Ext4Inode, offset: usize, out: &mut [u8]) -> KResult<usize> {
-        let size = self.inode_size(inode) as usize;
-        if offset >= size {
-            return Ok(0);
-        }
+    /// Write an inode back to the inode table (as transaction metadata).
+    ///
+    /// With `fresh` the whole on-disk slot is cleared first, dropping any
+    /// stale in-inode extended attributes of a previously freed inode.
+    fn write_inode(&self, ino: u32, inode: &Ext4Inode, fresh: bool) -> KResult<()> {
+        let (block, off) = self.inode_location(ino)?;
+        let isz = self.inode_size as usize;
 
-        let to_read = core::cmp::min(out.len(), size - offset);
-        let mut read = 0;
-        let mut file_offset = offset;
+        let mut buf = vec![0u8; self.block_size as usize];
+        self.read_block(block, &mut buf)?;
 
-        let mut block_buf = vec![0u8; self.block_size as usize];
+        if fresh {
+            buf[off..off + isz].fill(0);
+        }
+
+        let mut raw = [0u8; size_of::<Ext4Inode>()];
+        unsafe {
+            core::ptr::write_unaligned(raw.as_mut_ptr() as *mut Ext4Inode, *inode);
+        }
+        let len = self.inode_struct_len(inode);
+        buf[off..off + len].copy_from_slice(&raw[..len]);
+
+        if let Checksum::Metadata(seed) = self.csum {
+            let slot = &mut buf[off..off + isz];
+            let has_hi = isz > EXT4_GOOD_OLD_INODE_SIZE
+                && le16(slot, 0x80) as usize >= INODE_CHECKSUM_HI + 2 - EXT4_GOOD_OLD_INODE_SIZE;
+            put_le16(slot, INODE_CHECKSUM_LO, 0);
+            if has_hi {
+                put_le16(slot, INODE_CHECKSUM_HI, 0);
+            }
+            let gen = inode.i_generation;
+            let c = crc32c(self.inode_seed(seed, ino, gen), slot);
+            put_le16(slot, INODE_CHECKSUM_LO, c as u16);
+            if has_hi {
+                put_le16(slot, INODE_CHECKSUM_HI, (c >> 16) as u16);
+            }
+        }
+
+        self.write_meta(block, &buf);
+        Ok(())
+    }
+
+    /// Per-inode checksum seed (metadata_csum)
+    fn inode_seed(&self, fs_seed: u32, ino: u32, generation: u32) -> u32 {
+        let c = crc32c(fs_seed, &ino.to_le_bytes());
+        crc32c(c, &generation.to_le_bytes())
+    }
+
+    /// Allocate a free inode, preferring `goal_group`
+    fn alloc_inode(&self, goal_group: u32, is_dir: bool) -> KResult<u32> {
+        let ngroups = self.groups.read().len() as u32;
+
+        for i in 0..ngroups {
+            let g = (goal_group + i) % ngroups;
+            if self.groups.read()[g as usize].free_inodes() == 0 {
+                continue;
+            }
+
+            let mut bitmap = self.load_inode_bitmap(g)?;
+            let first = if g == 0 { self.first_ino.saturating_sub(1) } else { 0 };
+
+            for idx in first..self.inodes_per_group {
+                let (byte, bit) = ((idx / 8) as usize, idx % 8);
+                if bitmap[byte] & (1 << bit) != 0 {
+                    continue;
+                }
+                bitmap[byte] |= 1 << bit;
+                self.store_inode_bitmap(g, &bitmap)?;
+
+                let mut groups = self.groups.write();
+                let desc = &mut groups[g as usize];
+                desc.set_free_inodes(desc.free_inodes() - 1);
+                if is_dir {
+                    desc.set_used_dirs(desc.used_dirs() + 1);
+                }
+                if self.csum != Checksum::None {
+                    let unused = desc.itable_unused();
+                    if idx >= self.inodes_per_group - unused {
+                        desc.set_itable_unused(self.inodes_per_group - idx - 1);
+                    }
+                }
+                drop(groups);
+                self.write_group_desc(g)?;
+
+                return Ok(g * self.inodes_per_group + idx + 1);
+            }
+        }
+
+        Err(KError::NoMemory)
+    }
+
+    /// Mark an inode free in its group's bitmap
+    fn free_inode(&self, ino: u32, is_dir: bool) -> KResult<()> {
+        if ino == 0 || ino > self.total_inodes {
+            return Err(KError::Invalid);
+        }
+
+        let g = (ino - 1) / self.inodes_per_group;
+        let idx = (ino - 1) % self.inodes_per_group;
+
+        let mut bitmap = self.load_inode_bitmap(g)?;
+        bitmap[(idx / 8) as usize] &= !(1 << (idx % 8));
+        self.store_inode_bitmap(g, &bitmap)?;
+
+        let mut groups = self.groups.write();
+        let desc = &mut groups[g as usize];
+        desc.set_free_inodes(desc.free_inodes() + 1);
+        if is_dir {
+            desc.set_used_dirs(desc.used_dirs().saturating_sub(1));
+        }
+        drop(groups);
+        self.write_group_desc(g)
+    }
+
+    /// Release everything an unlinked inode owns and free it
+    fn release_inode(&self, ino: u32, inode: &mut Ext4Inode) -> KResult<()> {
+        let is_dir = Ext4InodeWrapper::inode_kind(inode.i_mode) == InodeKind::Dir;
+
+        if self.uses_extents(inode) {
+            let (extents, tree) = self.collect_extents(inode)?;
+            for e in &extents {
+                self.free_blocks(e.pblk, e.len)?;
+            }
+            for b in tree {
+                self.free_blocks(b, 1)?;
+            }
+        } else if !self.is_fast_symlink(inode) && self.inode_size(inode) > 0 {
+            // Legacy block-mapped inode: free direct blocks and indirect trees
+            let i_block = inode.i_block;
+            for (i, &b) in i_block.iter().enumerate() {
+                if b != 0 {
+                    self.free_indirect_tree(b as u64, if i < 12 { 0 } else { i as u32 - 11 })?;
+                }
+            }
+        }
+
+        self.release_xattr_block(inode)?;
+
+        inode.i_links_count = 0;
+        inode.i_dtime = now_secs();
+        inode.set_size(0);
+        inode.set_block_count(0, self.block_size);
+        self.write_inode(ino, inode, false)?;
+        self.free_inode(ino, is_dir)
+    }
+
+    fn free_indirect_tree(&self, block: u64, level: u32) -> KResult<()> {
+        if level > 0 {
+            let mut buf = vec![0u8; self.block_size as usize];
+            self.read_block(block, &mut buf)?;
+            for i in 0..(self.block_size / 4) as usize {
+                let ptr = le32(&buf, i * 4);
+                if ptr != 0 {
+                    self.free_indirect_tree(ptr as u64, level - 1)?;
+                }
+            }
+        }
+        self.free_blocks(block, 1)
+    }
+
+    /// Drop the inode's reference on its external xattr block
+    fn release_xattr_block(&self, inode: &Ext4Inode) -> KResult<()> {
+        let block = inode.file_acl();
+        if block == 0 {
+            return Ok(());
+        }
+
+        let mut buf = vec![0u8; self.block_size as usize];
+        self.read_block(block, &mut buf)?;
+        let refcount = le32(&buf, 4);
+        if refcount <= 1 {
+            return self.free_blocks(block, 1);
+        }
+
+        put_le32(&mut buf, 4, refcount - 1);
+        if let Checksum::Metadata(seed) = self.csum {
+            put_le32(&mut buf, 0x10, 0);
+            let c = crc32c(seed, &block.to_le_bytes());
+            let c = crc32c(c, &buf);
+            put_le32(&mut buf, 0x10, c);
+        }
+        self.write_meta(block, &buf);
+        Ok(())
+    }
+
+    // ========================================================================
+    // Group descriptors and bitmaps
+    // ========================================================================
+
+    fn group_desc_csum(&self, group: u32, bytes: &[u8]) -> u16 {
+        let ds = self.desc_size as usize;
+        match self.csum {
+            Checksum::Metadata(seed) => {
+                let c = crc32c(seed, &group.to_le_bytes());
+                let c = crc32c(c, &bytes[..BG_CHECKSUM]);
+                let c = crc32c(c, &[0, 0]);
+                let c = if ds > BG_CHECKSUM + 2 {
+                    crc32c(c, &bytes[BG_CHECKSUM + 2..ds])
+                } else {
+                    c
+                };
+                c as u16
+            }
+            Checksum::GdtCsum => {
+                let c = crc16(!0, &self.uuid);
+                let c = crc16(c, &group.to_le_bytes());
+                let c = crc16(c, &bytes[..BG_CHECKSUM]);
+                if ds > BG_CHECKSUM + 2 {
+                    crc16(c, &bytes[BG_CHECKSUM + 2..ds])
+                } else {
+                    c
+                }
+            }
+            Checksum::None => 0,
+        }
+    }
+
+    /// Write one group descriptor back into the descriptor table
+    fn write_group_desc(&self, group: u32) -> KResult<()> {
+        let ds = self.desc_size as usize;
+        let bs = self.block_size as usize;
+
+        let mut groups = self.groups.write();
+        let desc = &mut groups[group as usize];
+        let bytes = desc.to_bytes();
+        desc.bg_checksum = self.group_desc_csum(group, &bytes);
+        let bytes = desc.to_bytes();
+        drop(groups);
+
+        let gdt_start = self.first_data_block as u64 + 1;
+        let byte_off = group as usize * ds;
+        let block = gdt_start + (byte_off / bs) as u64;
+        let off = byte_off % bs;
+
+        let mut buf = vec![0u8; bs];
+        self.read_block(block, &mut buf)?;
+        buf[off..off + ds].copy_from_slice(&bytes[..ds]);
+        self.write_meta(block, &buf);
+        Ok(())
+    }
+
+    /// Number of blocks covered by a group (the last one may be short)
+    fn blocks_in_group(&self, group: u32) -> u32 {
+        let start = self.first_data_block as u64 + group as u64 * self.blocks_per_group as u64;
+        core::cmp::min(self.blocks_per_group as u64, self.total_blocks - start) as u32
+    }
+
+    fn group_first_block(&self, group: u32) -> u64 {
+        self.first_data_block as u64 + group as u64 * self.blocks_per_group as u64
+    }
+
+    fn block_group(&self, block: u64) -> u32 {
+        ((block - self.first_data_block as u64) / self.blocks_per_group as u64) as u32
+    }
+
+    /// Does this group carry a superblock backup (sparse_super rules)?
+    fn group_has_super(&self, group: u32) -> bool {
+        if group <= 1 || self.feature_ro_compat & EXT4_FEATURE_RO_COMPAT_SPARSE_SUPER == 0 {
+            return true;
+        }
+        if group % 2 == 0 {
+            return false;
+        }
+        for base in [3u32, 5, 7] {
+            let mut n = base;
+            while n < group {
+                n = n.saturating_mul(base);
+            }
+            if n == group {
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Load a block bitmap, building it if the group was never initialized
+    fn load_block_bitmap(&self, group: u32) -> KResult<Vec<u8>> {
+        let bs = self.block_size as usize;
+        let desc = self.groups.read()[group as usize];
+        let mut bitmap = vec![0u8; bs];
+
+        if desc.bg_flags & EXT4_BG_BLOCK_UNINIT == 0 {
+            self.read_block(desc.block_bitmap(), &mut bitmap)?;
+            return Ok(bitmap);
+        }
+
+        // BLOCK_UNINIT: only metadata is in use
+        let start = self.group_first_block(group);
+        let count = self.blocks_in_group(group);
+        let mut mark = |block: u64| {
+            if block >= start && block < start + count as u64 {
+                let bit = (block - start) as usize;
+                bitmap[bit / 8] |= 1 << (bit % 8);
+            }
+        };
+
+        if self.group_has_super(group) {
+            let ngroups = self.groups.read().len();
+            let gdt_blocks = (ngroups * self.desc_size as usize + bs - 1) / bs;
+            let meta = 1 + gdt_blocks as u64 + self.reserved_gdt_blocks as u64;
+            for b in 0..meta {
+                mark(start + b);
+            }
+        }
+
+        let inode_table_blocks = (self.inodes_per_group as u64 * self.inode_size as u64 + bs as u64 - 1) / bs as u64;
+        for g in self.groups.read().iter() {
+            mark(g.block_bitmap());
+            mark(g.inode_bitmap());
+            for b in 0..inode_table_blocks {
+                mark(g.inode_table() + b);
+            }
+        }
+
+        // Bits past the end of the group are always set
+        for bit in count as usize..bs * 8 {
+            bitmap[bit / 8] |= 1 << (bit % 8);
+        }
+
+        self.groups.write()[group as usize].bg_flags &= !EXT4_BG_BLOCK_UNINIT;
+        Ok(bitmap)
+    }
+
+    fn store_block_bitmap(&self, group: u32, bitmap: &[u8]) -> KResult<()> {
+        let block = self.groups.read()[group as usize].block_bitmap();
+        if let Checksum::Metadata(seed) = self.csum {
+            let c = crc32c(seed, &bitmap[..(self.blocks_per_group / 8) as usize]);
+            let mut groups = self.groups.write();
+            groups[group as usize].bg_block_bitmap_csum_lo = c as u16;
+            if self.desc_size >= 64 {
+                groups[group as usize].bg_block_bitmap_csum_hi = (c >> 16) as u16;
+            }
+        }
+        self.write_meta(block, bitmap);
+        Ok(())
+    }
+
+    /// Load an inode bitmap, building it if the group was never initialized
+    fn load_inode_bitmap(&self, group: u32) -> KResult<Vec<u8>> {
+        let bs = self.block_size as usize;
+        let desc = self.groups.read()[group as usize];
+        let mut bitmap = vec![0u8; bs];
+
+        if desc.bg_flags & EXT4_BG_INODE_UNINIT == 0 {
+            self.read_block(desc.inode_bitmap(), &mut bitmap)?;
+            return Ok(bitmap);
+        }
+
+        for bit in self.inodes_per_group as usize..bs * 8 {
+            bitmap[bit / 8] |= 1 << (bit % 8);
+        }
+        self.groups.write()[group as usize].bg_flags &= !EXT4_BG_INODE_UNINIT;
+        Ok(bitmap)
+    }
+
+    fn store_inode_bitmap(&self, group: u32, bitmap: &[u8]) -> KResult<()> {
+        let block = self.groups.read()[group as usize].inode_bitmap();
+        if let Checksum::Metadata(seed) = self.csum {
+            let c = crc32c(seed, &bitmap[..(self.inodes_per_group / 8) as usize]);
+            let mut groups = self.groups.write();
+            groups[group as usize].bg_inode_bitmap_csum_lo = c as u16;
+            if self.desc_size >= 64 {
+                groups[group as usize].bg_inode_bitmap_csum_hi = (c >> 16) as u16;
+            }
+        }
+        self.write_meta(block, bitmap);
+        Ok(())
+    }
+
+    /// Allocate one block, as close to `goal` as possible
+    fn alloc_block(&self, goal: u64) -> KResult<u64> {
+        let ngroups = self.groups.read().len() as u32;
+        let goal = if goal < self.first_data_block as u64 || goal >= self.total_blocks {
+            self.first_data_block as u64
+        } else {
+            goal
+        };
+        let goal_group = self.block_group(goal);
+
+        for i in 0..=ngroups {
+            let g = (goal_group + i) % ngroups;
+            if self.groups.read()[g as usize].free_blocks() == 0 {
+                continue;
+            }
+
+            let mut bitmap = self.load_block_bitmap(g)?;
+            let count = self.blocks_in_group(g);
+            let start_bit = if i == 0 { (goal - self.group_first_block(g)) as u32 } else { 0 };
+
+            let found = (start_bit..count)
+                .chain(0..start_bit)
+                .find(|&bit| bitmap[(bit / 8) as usize] & (1 << (bit % 8)) == 0);
+
+            if let Some(bit) = found {
+                bitmap[(bit / 8) as usize] |= 1 << (bit % 8);
+                self.store_block_bitmap(g, &bitmap)?;
+
+                let mut groups = self.groups.write();
+                let desc = &mut groups[g as usize];
+                desc.set_free_blocks(desc.free_blocks() - 1);
+                drop(groups);
+                self.write_group_desc(g)?;
+
+                return Ok(self.group_first_block(g) + bit as u64);
+            }
+        }
+
+        Err(KError::NoMemory)
+    }
+
+    /// Free `count` contiguous blocks starting at `start`
+    fn free_blocks(&self, start: u64, count: u32) -> KResult<()> {
+        let mut block = start;
+        let end = start + count as u64;
+
+        while block < end {
+            if block < self.first_data_block as u64 || block >= self.total_blocks {
+                return Err(KError::Invalid);
+            }
+            let g = self.block_group(block);
+            let group_start = self.group_first_block(g);
+            let group_end = group_start + self.blocks_in_group(g) as u64;
+            let run_end = core::cmp::min(end, group_end);
+
+            let mut bitmap = self.load_block_bitmap(g)?;
+            let mut freed = 0u32;
+            {
+                let mut txn = self.txn.lock();
+                for b in block..run_end {
+                    let bit = (b - group_start) as usize;
+                    if bitmap[bit / 8] & (1 << (bit % 8)) != 0 {
+                        bitmap[bit / 8] &= !(1 << (bit % 8));
+                        freed += 1;
+                    }
+                    // A freed metadata block must not be written home
+                    txn.remove(&b);
+                }
+            }
+            self.store_block_bitmap(g, &bitmap)?;
+
+            let mut groups = self.groups.write();
+            let desc = &mut groups[g as usize];
+            desc.set_free_blocks(desc.free_blocks() + freed);
+            drop(groups);
+            self.write_group_desc(g)?;
+
+            block = run_end;
+        }
+
+        Ok(())
+    }
+
+    // ========================================================================
+    // Data access
+    // ========================================================================
+
+    /// Get file size (64-bit)
+    fn inode_size(&self, inode: &Ext4Inode) -> u64 {
+        (inode.i_size_high as u64) << 32 | inode.i_size_lo as u64
+    }
+
+    /// Check if inode uses extents
+    fn uses_extents(&self, inode: &Ext4Inode) -> bool {
+        self.has_extents && (inode.i_flags & EXT4_EXTENTS_FL) != 0
+    }
+
+    /// Symlink whose target is stored directly in i_block
+    fn is_fast_symlink(&self, inode: &Ext4Inode) -> bool {
+        Ext4InodeWrapper::inode_kind(inode.i_mode) == InodeKind::Symlink
+            && inode.i_flags & (EXT4_EXTENTS_FL | EXT4_INLINE_DATA_FL) == 0
+            && (self.inode_size(inode) as usize) < EXT4_FAST_SYMLINK_MAX
+    }
+
+    /// Read inode data using extent tree
+    fn read_extent_data(&self, inode: &Ext4Inode, offset: usize, out: &mut [u8]) -> KResult<usize> {
+        let size = self.inode_size(inode) as usize;
+        if offset >= size {
+            return Ok(0);
+        }
+
+        let to_read = core::cmp::min(out.len(), size - offset);
+        let mut read = 0;
+        let mut file_offset = offset;
+
+        let mut block_buf = vec![0u8; self.block_size as usize];
 
         while read < to_read {
             let block_idx = (file_offset / self.block_size as usize) as u32;
@@ -510,15 +1628,7 @@ impl Ext4Fs {
     /// Get physical block from extent tree for a logical block
     fn extent_get_block(&self, inode: &Ext4Inode, logical_block: u32) -> KResult<u64> {
         // i_block contains the extent tree root - copy to avoid unaligned access
-        let mut extent_data = [0u8; 60];
-        unsafe {
-            core::ptr::copy_nonoverlapping(
-                core::ptr::addr_of!(inode.i_block) as *const u8,
-                extent_data.as_mut_ptr(),
-                60,
-            );
-        }
-
+        let extent_data = inode.i_block_bytes();
         self.search_extent_tree(&extent_data, logical_block)
     }
 
@@ -652,14 +1762,7 @@ impl Ext4Fs {
             let mut block_buf = vec![0u8; self.block_size as usize];
 
             // Copy i_block to avoid unaligned access
-            let mut i_block_data = [0u8; 60];
-            unsafe {
-                core::ptr::copy_nonoverlapping(
-                    core::ptr::addr_of!(inode.i_block) as *const u8,
-                    i_block_data.as_mut_ptr(),
-                    60,
-                );
-            }
+            let i_block_data = inode.i_block_bytes();
 
             while read < to_read {
                 let block_idx = (file_offset / self.block_size as usize) as u32;
@@ -683,6 +1786,749 @@ impl Ext4Fs {
         }
     }
 
+    // ========================================================================
+    // Extent tree updates
+    // ========================================================================
+
+    /// Flatten an inode's extent tree: (leaf extents, index/leaf tree blocks)
+    fn collect_extents(&self, inode: &Ext4Inode) -> KResult<(Vec<ExtentRec>, Vec<u64>)> {
+        let mut extents = Vec::new();
+        let mut tree = Vec::new();
+        let root = inode.i_block_bytes();
+        self.collect_node(&root, &mut extents, &mut tree, 0)?;
+        extents.sort_by_key(|e| e.lblk);
+        Ok((extents, tree))
+    }
+
+    fn collect_node(&self, data: &[u8], extents: &mut Vec<ExtentRec>, tree: &mut Vec<u64>, level: u32) -> KResult<()> {
+        let header: Ext4ExtentHeader = unsafe {
+            core::ptr::read_unaligned(data.as_ptr() as *const _)
+        };
+        if header.eh_magic != EXT4_EXT_MAGIC || level > 5 {
+            return Err(KError::Invalid);
+        }
+
+        let hdr = size_of::<Ext4ExtentHeader>();
+        if header.eh_depth == 0 {
+            for i in 0..header.eh_entries as usize {
+                let e: Ext4Extent = unsafe {
+                    core::ptr::read_unaligned(data.as_ptr().add(hdr + i * size_of::<Ext4Extent>()) as *const _)
+                };
+                extents.push(ExtentRec {
+                    lblk: e.ee_block,
+                    len: e.block_count(),
+                    pblk: e.start_block(),
+                    uninit: e.is_uninitialized(),
+                });
+            }
+        } else {
+            let mut buf = vec![0u8; self.block_size as usize];
+            for i in 0..header.eh_entries as usize {
+                let idx: Ext4ExtentIdx = unsafe {
+                    core::ptr::read_unaligned(data.as_ptr().add(hdr + i * size_of::<Ext4ExtentIdx>()) as *const _)
+                };
+                let child = idx.leaf_block();
+                tree.push(child);
+                self.read_block(child, &mut buf)?;
+                self.collect_node(&buf, extents, tree, level + 1)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Map a logical block through a flat extent list: (physical, uninitialized)
+    fn map_lookup(extents: &[ExtentRec], lblk: u32) -> Option<(u64, bool)> {
+        let idx = extents.partition_point(|e| e.end() <= lblk);
+        let e = extents.get(idx)?;
+        if lblk >= e.lblk && lblk < e.end() {
+            Some((e.pblk + (lblk - e.lblk) as u64, e.uninit))
+        } else {
+            None
+        }
+    }
+
+    /// Remove the mapping of logical blocks [from, to) from an extent list,
+    /// returning the physical runs that are no longer referenced
+    fn unmap_range(extents: &mut Vec<ExtentRec>, from: u32, to: u32) -> Vec<(u64, u32)> {
+        let mut released = Vec::new();
+        let mut out = Vec::with_capacity(extents.len() + 1);
+
+        for e in extents.drain(..) {
+            if e.end() <= from || e.lblk >= to {
+                out.push(e);
+                continue;
+            }
+            let cut_start = core::cmp::max(e.lblk, from);
+            let cut_end = core::cmp::min(e.end(), to);
+            released.push((e.pblk + (cut_start - e.lblk) as u64, cut_end - cut_start));
+
+            if e.lblk < cut_start {
+                out.push(ExtentRec { len: cut_start - e.lblk, ..e });
+            }
+            if cut_end < e.end() {
+                out.push(ExtentRec {
+                    lblk: cut_end,
+                    len: e.end() - cut_end,
+                    pblk: e.pblk + (cut_end - e.lblk) as u64,
+                    uninit: e.uninit,
+                });
+            }
+        }
+
+        *extents = out;
+        released
+    }
+
+    /// Map one logical block to a physical block (initialized)
+    fn map_block(extents: &mut Vec<ExtentRec>, lblk: u32, pblk: u64) {
+        Self::unmap_range(extents, lblk, lblk + 1);
+        let idx = extents.partition_point(|e| e.lblk < lblk);
+        extents.insert(idx, ExtentRec { lblk, len: 1, pblk, uninit: false });
+        Self::merge_extents(extents);
+    }
+
+    /// Merge logically and physically contiguous neighbours
+    fn merge_extents(extents: &mut Vec<ExtentRec>) {
+        let mut out: Vec<ExtentRec> = Vec::with_capacity(extents.len());
+        for e in extents.drain(..) {
+            if let Some(last) = out.last_mut() {
+                if last.end() == e.lblk
+                    && last.pblk + last.len as u64 == e.pblk
+                    && last.uninit == e.uninit
+                    && last.len + e.len <= last.max_len()
+                {
+                    last.len += e.len;
+                    continue;
+                }
+            }
+            out.push(e);
+        }
+        *extents = out;
+    }
+
+    /// Entries per extent tree block
+    fn extents_per_block(&self) -> usize {
+        (self.block_size as usize - size_of::<Ext4ExtentHeader>()) / size_of::<Ext4Extent>()
+    }
+
+    /// Rewrite an inode's extent tree from a flat list.
+    ///
+    /// `old_tree` are the index/leaf blocks of the previous tree; they are
+    /// reused first and any left over are freed. Also recomputes i_blocks.
+    fn write_extent_tree(
+        &self,
+        ino: u32,
+        inode: &mut Ext4Inode,
+        extents: &[ExtentRec],
+        old_tree: Vec<u64>,
+    ) -> KResult<()> {
+        let hdr = size_of::<Ext4ExtentHeader>();
+        let entry = size_of::<Ext4Extent>();
+        let cap = self.extents_per_block();
+        let mut pool = old_tree;
+        pool.reverse();
+        let mut tree_blocks = 0u64;
+
+        let goal = extents.first().map(|e| e.pblk).unwrap_or_else(|| {
+            self.group_first_block((ino - 1) / self.inodes_per_group)
+        });
+
+        // Serialized entries of the current level: (first logical block, 12 bytes)
+        let mut level: Vec<(u32, [u8; 12])> = extents
+            .iter()
+            .map(|e| {
+                let mut b = [0u8; 12];
+                put_le32(&mut b, 0, e.lblk);
+                let len = if e.uninit { e.len + EXT4_EXT_INIT_MAX_LEN } else { e.len };
+                put_le16(&mut b, 4, len as u16);
+                put_le16(&mut b, 6, (e.pblk >> 32) as u16);
+                put_le32(&mut b, 8, e.pblk as u32);
+                (e.lblk, b)
+            })
+            .collect();
+        let mut depth = 0u16;
+
+        while level.len() > EXT4_EXT_ROOT_MAX {
+            let mut next = Vec::with_capacity((level.len() + cap - 1) / cap);
+            for chunk in level.chunks(cap) {
+                let block = match pool.pop() {
+                    Some(b) => b,
+                    None => self.alloc_block(goal)?,
+                };
+                tree_blocks += 1;
+
+                let mut buf = vec![0u8; self.block_size as usize];
+                put_le16(&mut buf, 0, EXT4_EXT_MAGIC);
+                put_le16(&mut buf, 2, chunk.len() as u16);
+                put_le16(&mut buf, 4, cap as u16);
+                put_le16(&mut buf, 6, depth);
+                for (i, (_, bytes)) in chunk.iter().enumerate() {
+                    buf[hdr + i * entry..hdr + (i + 1) * entry].copy_from_slice(bytes);
+                }
+                if let Checksum::Metadata(seed) = self.csum {
+                    let tail = hdr + cap * entry;
+                    let c = crc32c(self.inode_seed(seed, ino, inode.i_generation), &buf[..tail]);
+                    put_le32(&mut buf, tail, c);
+                }
+                self.write_meta(block, &buf);
+
+                let mut idx = [0u8; 12];
+                put_le32(&mut idx, 0, chunk[0].0);
+                put_le32(&mut idx, 4, block as u32);
+                put_le16(&mut idx, 8, (block >> 32) as u16);
+                next.push((chunk[0].0, idx));
+            }
+            level = next;
+            depth += 1;
+        }
+
+        // Root lives in i_block
+        let mut root = [0u8; 60];
+        put_le16(&mut root, 0, EXT4_EXT_MAGIC);
+        put_le16(&mut root, 2, level.len() as u16);
+        put_le16(&mut root, 4, EXT4_EXT_ROOT_MAX as u16);
+        put_le16(&mut root, 6, depth);
+        for (i, (_, bytes)) in level.iter().enumerate() {
+            root[hdr + i * entry..hdr + (i + 1) * entry].copy_from_slice(bytes);
+        }
+        inode.set_i_block_bytes(&root);
+        inode.i_flags |= EXT4_EXTENTS_FL;
+
+        for b in pool {
+            self.free_blocks(b, 1)?;
+        }
+
+        let data_blocks: u64 = extents.iter().map(|e| e.len as u64).sum();
+        let xattr_blocks = if inode.file_acl() != 0 { 1 } else { 0 };
+        inode.set_block_count(data_blocks + tree_blocks + xattr_blocks, self.block_size);
+        Ok(())
+    }
+
+    /// Allocation goal for a new block at `lblk`: right after the previous mapping
+    fn block_goal(&self, ino: u32, extents: &[ExtentRec], lblk: u32) -> u64 {
+        let idx = extents.partition_point(|e| e.lblk < lblk);
+        if idx > 0 {
+            let prev = &extents[idx - 1];
+            return prev.pblk + (lblk - prev.lblk) as u64;
+        }
+        if let Some(next) = extents.first() {
+            return next.pblk.saturating_sub((next.lblk - lblk) as u64);
+        }
+        let group = (ino - 1) / self.inodes_per_group;
+        let start = self.group_first_block(group);
+        let desc = self.groups.read()[group as usize];
+        // Skip past the group's inode table
+        let itable_blocks = (self.inodes_per_group as u64 * self.inode_size as u64) / self.block_size as u64;
+        let after_itable = desc.inode_table() + itable_blocks;
+        if after_itable >= start && after_itable < start + self.blocks_per_group as u64 {
+            after_itable
+        } else {
+            start
+        }
+    }
+
+    /// Write data into an extent-mapped inode, allocating blocks as needed
+    fn write_inode_data(&self, ino: u32, inode: &mut Ext4Inode, offset: usize, data: &[u8]) -> KResult<usize> {
+        if data.is_empty() {
+            return Ok(0);
+        }
+        if inode.i_flags & EXT4_INLINE_DATA_FL != 0 {
+            return Err(KError::NotSupported);
+        }
+        if !self.uses_extents(inode) {
+            // Only brand-new, empty block-mapped inodes can be converted
+            if self.inode_size(inode) != 0 || inode.i_block_bytes().iter().any(|&b| b != 0) {
+                return Err(KError::NotSupported);
+            }
+            self.write_extent_tree(ino, inode, &[], Vec::new())?;
+        }
+
+        let bs = self.block_size as usize;
+        let (mut extents, tree) = self.collect_extents(inode)?;
+        let mut changed = false;
+
+        let mut written = 0;
+        let mut file_offset = offset;
+        let mut block_buf = vec![0u8; bs];
+
+        while written < data.len() {
+            let lblk = (file_offset / bs) as u32;
+            let block_offset = file_offset % bs;
+            let chunk = core::cmp::min(data.len() - written, bs - block_offset);
+
+            let pblk = match Self::map_lookup(&extents, lblk) {
+                Some((p, false)) => {
+                    if chunk < bs {
+                        self.read_block(p, &mut block_buf)?;
+                    }
+                    p
+                }
+                Some((p, true)) => {
+                    // Unwritten extent: reads as zeros until initialized
+                    block_buf.fill(0);
+                    Self::map_block(&mut extents, lblk, p);
+                    changed = true;
+                    p
+                }
+                None => {
+                    let goal = self.block_goal(ino, &extents, lblk);
+                    let p = self.alloc_block(goal)?;
+                    block_buf.fill(0);
+                    Self::map_block(&mut extents, lblk, p);
+                    changed = true;
+                    p
+                }
+            };
+
+            block_buf[block_offset..block_offset + chunk].copy_from_slice(&data[written..written + chunk]);
+            self.write_data_block(pblk, &block_buf)?;
+
+            written += chunk;
+            file_offset += chunk;
+        }
+
+        if changed {
+            self.write_extent_tree(ino, inode, &extents, tree)?;
+        }
+
+        let new_size = (offset + written) as u64;
+        if new_size > self.inode_size(inode) {
+            inode.set_size(new_size);
+        }
+
+        Ok(written)
+    }
+
+    /// Shrink or grow an inode to `size` bytes
+    fn truncate_inode(&self, ino: u32, inode: &mut Ext4Inode, size: u64) -> KResult<()> {
+        let bs = self.block_size as u64;
+        let old_size = self.inode_size(inode);
+
+        if size < old_size && self.uses_extents(inode) {
+            let (mut extents, tree) = self.collect_extents(inode)?;
+            let first_free = ((size + bs - 1) / bs) as u32;
+            let released = Self::unmap_range(&mut extents, first_free, u32::MAX);
+            for (pblk, len) in released {
+                self.free_blocks(pblk, len)?;
+            }
+
+            // Zero the tail of the last partial block so a later extension reads zeros
+            if size % bs != 0 {
+                if let Some((p, false)) = Self::map_lookup(&extents, (size / bs) as u32) {
+                    let mut buf = vec![0u8; bs as usize];
+                    self.read_block(p, &mut buf)?;
+                    buf[(size % bs) as usize..].fill(0);
+                    self.write_data_block(p, &buf)?;
+                }
+            }
+
+            self.write_extent_tree(ino, inode, &extents, tree)?;
+        } else if size < old_size {
+            return Err(KError::NotSupported);
+        }
+
+        inode.set_size(size);
+        Ok(())
+    }
+
+    // ========================================================================
+    // Directories
+    // ========================================================================
+
+    /// Physical blocks of a directory, in logical order
+    fn dir_blocks(&self, dir: &Ext4Inode) -> KResult<Vec<u64>> {
+        let nblocks = (self.inode_size(dir) / self.block_size as u64) as u32;
+        let mut out = Vec::with_capacity(nblocks as usize);
+
+        if self.uses_extents(dir) {
+            let (extents, _) = self.collect_extents(dir)?;
+            for lblk in 0..nblocks {
+                match Self::map_lookup(&extents, lblk) {
+                    Some((p, false)) => out.push(p),
+                    _ => return Err(KError::Invalid),
+                }
+            }
+        } else {
+            let i_block = dir.i_block_bytes();
+            for lblk in 0..nblocks {
+                let p = self.get_block_direct(&i_block, lblk)?;
+                if p == 0 {
+                    return Err(KError::Invalid);
+                }
+                out.push(p);
+            }
+        }
+
+        Ok(out)
+    }
+
+    fn has_dirent_tail(&self, buf: &[u8]) -> bool {
+        let off = buf.len() - EXT4_DIRENT_TAIL_SIZE;
+        le32(buf, off) == 0
+            && le16(buf, off + 4) as usize == EXT4_DIRENT_TAIL_SIZE
+            && buf[off + 6] == 0
+            && buf[off + 7] == EXT4_FT_DIR_CSUM
+    }
+
+    /// End of the usable entry area of a directory block
+    fn dir_block_end(&self, buf: &[u8]) -> usize {
+        if self.has_dirent_tail(buf) {
+            buf.len() - EXT4_DIRENT_TAIL_SIZE
+        } else {
+            buf.len()
+        }
+    }
+
+    /// Initialize the checksum tail of a fresh directory block
+    fn init_dirent_tail(&self, buf: &mut [u8]) {
+        let off = buf.len() - EXT4_DIRENT_TAIL_SIZE;
+        buf[off..].fill(0);
+        put_le16(buf, off + 4, EXT4_DIRENT_TAIL_SIZE as u16);
+        buf[off + 7] = EXT4_FT_DIR_CSUM;
+    }
+
+    /// Queue a directory block, refreshing its checksum tail
+    fn write_dir_block(&self, dir_ino: u32, dir: &Ext4Inode, block: u64, buf: &mut [u8]) {
+        if let Checksum::Metadata(seed) = self.csum {
+            if self.has_dirent_tail(buf) {
+                let off = buf.len() - EXT4_DIRENT_TAIL_SIZE;
+                let c = crc32c(self.inode_seed(seed, dir_ino, dir.i_generation), &buf[..off]);
+                put_le32(buf, off + 8, c);
+            }
+        }
+        self.write_meta(block, buf);
+    }
+
+    fn write_dirent(buf: &mut [u8], off: usize, ino: u32, rec_len: usize, name: &[u8], file_type: u8) {
+        put_le32(buf, off, ino);
+        put_le16(buf, off + 4, rec_len as u16);
+        buf[off + 6] = name.len() as u8;
+        buf[off + 7] = file_type;
+        buf[off + 8..off + 8 + name.len()].copy_from_slice(name);
+    }
+
+    /// Build the first block of a new directory ("." and "..")
+    fn new_dir_block(&self, ino: u32, parent: u32) -> Vec<u8> {
+        let bs = self.block_size as usize;
+        let mut buf = vec![0u8; bs];
+        let end = if let Checksum::Metadata(_) = self.csum {
+            self.init_dirent_tail(&mut buf);
+            bs - EXT4_DIRENT_TAIL_SIZE
+        } else {
+            bs
+        };
+        Self::write_dirent(&mut buf, 0, ino, 12, b".", EXT4_FT_DIR);
+        Self::write_dirent(&mut buf, 12, parent, end - 12, b"..", EXT4_FT_DIR);
+        buf
+    }
+
+    /// Find an entry by name: (inode, file type, block index, offset)
+    fn find_dir_entry(&self, dir: &Ext4Inode, name: &str) -> KResult<Option<(u32, u8, usize, usize)>> {
+        let name = name.as_bytes();
+        let mut buf = vec![0u8; self.block_size as usize];
+
+        for (bi, &block) in self.dir_blocks(dir)?.iter().enumerate() {
+            self.read_block(block, &mut buf)?;
+            let end = self.dir_block_end(&buf);
+            let mut off = 0;
+            while off + 8 <= end {
+                let ino = le32(&buf, off);
+                let rec_len = le16(&buf, off + 4) as usize;
+                let name_len = buf[off + 6] as usize;
+                if rec_len < 8 || off + rec_len > end {
+                    break;
+                }
+                if ino != 0 && name_len == name.len() && &buf[off + 8..off + 8 + name_len] == name {
+                    return Ok(Some((ino, buf[off + 7], bi, off)));
+                }
+                off += rec_len;
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Turn an htree-indexed directory back into a plain linear one
+    fn convert_dir_to_linear(&self, dir_ino: u32, dir: &mut Ext4Inode) -> KResult<()> {
+        let bs = self.block_size as usize;
+        let csum = matches!(self.csum, Checksum::Metadata(_));
+        let mut buf = vec![0u8; bs];
+
+        for (bi, block) in self.dir_blocks(dir)?.into_iter().enumerate() {
+            self.read_block(block, &mut buf)?;
+            let end = if csum { bs - EXT4_DIRENT_TAIL_SIZE } else { bs };
+
+            if bi == 0 {
+                // dx_root: keep "." and "..", drop the index that follows
+                let dot_len = le16(&buf, 4) as usize;
+                let parent = le32(&buf, dot_len);
+                let mut fresh = vec![0u8; bs];
+                if csum {
+                    self.init_dirent_tail(&mut fresh);
+                }
+                Self::write_dirent(&mut fresh, 0, dir_ino, 12, b".", EXT4_FT_DIR);
+                Self::write_dirent(&mut fresh, 12, parent, end - 12, b"..", EXT4_FT_DIR);
+                self.write_dir_block(dir_ino, dir, block, &mut fresh);
+            } else if le32(&buf, 0) == 0 && le16(&buf, 4) as usize == bs {
+                // dx_node: an empty fake entry spanning the block
+                let mut fresh = vec![0u8; bs];
+                if csum {
+                    self.init_dirent_tail(&mut fresh);
+                }
+                put_le16(&mut fresh, 4, end as u16);
+                self.write_dir_block(dir_ino, dir, block, &mut fresh);
+            }
+        }
+
+        dir.i_flags &= !EXT4_INDEX_FL;
+        Ok(())
+    }
+
+    /// Add a directory entry, growing the directory by one block if needed.
+    /// The caller writes the directory inode afterwards.
+    fn add_dir_entry(&self, dir_ino: u32, dir: &mut Ext4Inode, child_ino: u32, name: &str, file_type: u8) -> KResult<()> {
+        let name_bytes = name.as_bytes();
+        if name_bytes.is_empty() || name_bytes.len() > EXT4_NAME_LEN {
+            return Err(KError::Invalid);
+        }
+
+        if dir.i_flags & EXT4_INDEX_FL != 0 {
+            self.convert_dir_to_linear(dir_ino, dir)?;
+        }
+
+        let needed = align4(8 + name_bytes.len());
+        let bs = self.block_size as usize;
+        let mut buf = vec![0u8; bs];
+
+        let blocks = self.dir_blocks(dir)?;
+        for &block in &blocks {
+            self.read_block(block, &mut buf)?;
+            let end = self.dir_block_end(&buf);
+            let mut off = 0;
+            while off + 8 <= end {
+                let ino = le32(&buf, off);
+                let rec_len = le16(&buf, off + 4) as usize;
+                if rec_len < 8 || off + rec_len > end {
+                    break;
+                }
+                let used = if ino == 0 { 0 } else { align4(8 + buf[off + 6] as usize) };
+
+                if rec_len - used >= needed {
+                    if ino == 0 {
+                        Self::write_dirent(&mut buf, off, child_ino, rec_len, name_bytes, file_type);
+                    } else {
+                        put_le16(&mut buf, off + 4, used as u16);
+                        Self::write_dirent(&mut buf, off + used, child_ino, rec_len - used, name_bytes, file_type);
+                    }
+                    self.write_dir_block(dir_ino, dir, block, &mut buf);
+                    return Ok(());
+                }
+                off += rec_len;
+            }
+        }
+
+        // No room: append a new block
+        if !self.uses_extents(dir) {
+            return Err(KError::NotSupported);
+        }
+
+        let (mut extents, tree) = self.collect_extents(dir)?;
+        let lblk = blocks.len() as u32;
+        let goal = self.block_goal(dir_ino, &extents, lblk);
+        let block = self.alloc_block(goal)?;
+
+        buf.fill(0);
+        let end = if let Checksum::Metadata(_) = self.csum {
+            self.init_dirent_tail(&mut buf);
+            bs - EXT4_DIRENT_TAIL_SIZE
+        } else {
+            bs
+        };
+        Self::write_dirent(&mut buf, 0, child_ino, end, name_bytes, file_type);
+        self.write_dir_block(dir_ino, dir, block, &mut buf);
+
+        Self::map_block(&mut extents, lblk, block);
+        self.write_extent_tree(dir_ino, dir, &extents, tree)?;
+        dir.set_size((lblk as u64 + 1) * bs as u64);
+        Ok(())
+    }
+
+    /// Remove an entry by name, returning (inode, file type)
+    fn remove_dir_entry(&self, dir_ino: u32, dir: &Ext4Inode, name: &str) -> KResult<(u32, u8)> {
+        let name = name.as_bytes();
+        let mut buf = vec![0u8; self.block_size as usize];
+
+        for block in self.dir_blocks(dir)? {
+            self.read_block(block, &mut buf)?;
+            let end = self.dir_block_end(&buf);
+            let mut off = 0;
+            let mut prev: Option<usize> = None;
+            while off + 8 <= end {
+                let ino = le32(&buf, off);
+                let rec_len = le16(&buf, off + 4) as usize;
+                let name_len = buf[off + 6] as usize;
+                if rec_len < 8 || off + rec_len > end {
+                    break;
+                }
+                if ino != 0 && name_len == name.len() && &buf[off + 8..off + 8 + name_len] == name {
+                    let file_type = buf[off + 7];
+                    match prev {
+                        Some(p) => {
+                            // Merge into the previous entry
+                            let merged = le16(&buf, p + 4) as usize + rec_len;
+                            put_le16(&mut buf, p + 4, merged as u16);
+                        }
+                        None => {
+                            put_le32(&mut buf, off, 0);
+                        }
+                    }
+                    self.write_dir_block(dir_ino, dir, block, &mut buf);
+                    return Ok((ino, file_type));
+                }
+                prev = Some(off);
+                off += rec_len;
+            }
+        }
+
+        Err(KError::NotFound)
+    }
+
+    /// Does a directory contain anything besides "." and ".."?
+    fn dir_is_empty(&self, dir: &Ext4Inode) -> KResult<bool> {
+        let mut buf = vec![0u8; self.block_size as usize];
+        for block in self.dir_blocks(dir)? {
+            self.read_block(block, &mut buf)?;
+            let end = self.dir_block_end(&buf);
+            let mut off = 0;
+            while off + 8 <= end {
+                let ino = le32(&buf, off);
+                let rec_len = le16(&buf, off + 4) as usize;
+                let name_len = buf[off + 6] as usize;
+                if rec_len < 8 || off + rec_len > end {
+                    break;
+                }
+                let name = &buf[off + 8..off + 8 + name_len];
+                if ino != 0 && name != b"." && name != b".." {
+                    return Ok(false);
+                }
+                off += rec_len;
+            }
+        }
+        Ok(true)
+    }
+
+    /// Point a directory's ".." entry at a new parent
+    fn set_dotdot(&self, dir_ino: u32, dir: &Ext4Inode, parent: u32) -> KResult<()> {
+        let blocks = self.dir_blocks(dir)?;
+        let block = *blocks.first().ok_or(KError::Invalid)?;
+        let mut buf = vec![0u8; self.block_size as usize];
+        self.read_block(block, &mut buf)?;
+        let off = le16(&buf, 4) as usize;
+        if off + 8 > buf.len() || &buf[off + 8..off + 10] != b".." {
+            return Err(KError::Invalid);
+        }
+        put_le32(&mut buf, off, parent);
+        self.write_dir_block(dir_ino, dir, block, &mut buf);
+        Ok(())
+    }
+
+    /// Generation number for a new inode
+    fn new_generation(&self, ino: u32) -> u32 {
+        let t = crate::time::realtime();
+        (t.tv_nsec as u32) ^ (t.tv_sec as u32).rotate_left(16) ^ ino.wrapping_mul(0x9E37_79B1)
+    }
+
+    /// Allocate and initialize a new inode and link it into `dir`
+    fn create_inode(
+        &self,
+        dir_ino: u32,
+        kind: InodeKind,
+        name: &str,
+        meta: &Metadata,
+        symlink_target: Option<&str>,
+    ) -> KResult<(u32, Ext4Inode)> {
+        let mut dir = self.read_inode(dir_ino)?;
+        if Ext4InodeWrapper::inode_kind(dir.i_mode) != InodeKind::Dir {
+            return Err(KError::NotADirectory);
+        }
+        if name.is_empty() || name.len() > EXT4_NAME_LEN || name.contains('/') {
+            return Err(KError::Invalid);
+        }
+        if self.find_dir_entry(&dir, name)?.is_some() {
+            return Err(KError::AlreadyExists);
+        }
+
+        let (mode_type, file_type) = match kind {
+            InodeKind::File => (EXT4_S_IFREG, EXT4_FT_REG_FILE),
+            InodeKind::Dir => (EXT4_S_IFDIR, EXT4_FT_DIR),
+            InodeKind::Symlink => (EXT4_S_IFLNK, EXT4_FT_SYMLINK),
+            InodeKind::CharDev => (EXT4_S_IFCHR, EXT4_FT_CHRDEV),
+            InodeKind::BlockDev => (EXT4_S_IFBLK, EXT4_FT_BLKDEV),
+            InodeKind::Fifo => (EXT4_S_IFIFO, EXT4_FT_FIFO),
+            InodeKind::Socket => (EXT4_S_IFSOCK, EXT4_FT_SOCK),
+        };
+
+        let dir_group = (dir_ino - 1) / self.inodes_per_group;
+        let ino = self.alloc_inode(dir_group, kind == InodeKind::Dir)?;
+        let now = now_secs();
+
+        let mut inode = Ext4Inode::zeroed();
+        inode.i_mode = (mode_type << 12) | (meta.mode.to_octal() & 0o7777);
+        inode.set_uid(meta.uid.0);
+        inode.set_gid(meta.gid.0);
+        inode.i_atime = now;
+        inode.i_ctime = now;
+        inode.i_mtime = now;
+        inode.i_crtime = now;
+        inode.i_links_count = 1;
+        inode.i_generation = self.new_generation(ino);
+        if self.inode_size as usize > EXT4_GOOD_OLD_INODE_SIZE {
+            inode.i_extra_isize = core::cmp::min(
+                size_of::<Ext4Inode>() - EXT4_GOOD_OLD_INODE_SIZE,
+                self.inode_size as usize - EXT4_GOOD_OLD_INODE_SIZE,
+            ) as u16;
+        }
+
+        match kind {
+            InodeKind::File => {
+                self.write_extent_tree(ino, &mut inode, &[], Vec::new())?;
+            }
+            InodeKind::Dir => {
+                let goal = self.block_goal(ino, &[], 0);
+                let block = self.alloc_block(goal)?;
+                let mut buf = self.new_dir_block(ino, dir_ino);
+                self.write_dir_block(ino, &inode, block, &mut buf);
+                let extents = [ExtentRec { lblk: 0, len: 1, pblk: block, uninit: false }];
+                self.write_extent_tree(ino, &mut inode, &extents, Vec::new())?;
+                inode.set_size(self.block_size as u64);
+                inode.i_links_count = 2;
+                dir.i_links_count = dir.i_links_count.saturating_add(1);
+            }
+            InodeKind::Symlink => {
+                let target = symlink_target.unwrap_or("");
+                if target.len() < EXT4_FAST_SYMLINK_MAX {
+                    let mut i_block = [0u8; 60];
+                    i_block[..target.len()].copy_from_slice(target.as_bytes());
+                    inode.set_i_block_bytes(&i_block);
+                    inode.set_size(target.len() as u64);
+                } else {
+                    self.write_extent_tree(ino, &mut inode, &[], Vec::new())?;
+                    self.write_inode_data(ino, &mut inode, 0, target.as_bytes())?;
+                }
+            }
+            _ => {}
+        }
+
+        self.write_inode(ino, &inode, true)?;
+
+        self.add_dir_entry(dir_ino, &mut dir, ino, name, file_type)?;
+        dir.i_mtime = now;
+        dir.i_ctime = now;
+        self.write_inode(dir_ino, &dir, false)?;
+
+        Ok((ino, inode))
+    }
+
     /// Return root inode
     pub fn root(self: &Arc<Self>) -> Inode {
         Inode(Arc::new(Ext4InodeWrapper {
@@ -707,7 +2553,8 @@ struct Ext4InodeWrapper {
 
 impl Ext4InodeWrapper {
     fn get_raw(&self) -> KResult<Ext4Inode> {
-        {
+        // Writable mounts can change an inode through another wrapper
+        if self.fs.read_only {
             let guard = self.raw.read();
             if let Some(raw) = *guard {
                 return Ok(raw);
@@ -719,6 +2566,20 @@ impl Ext4InodeWrapper {
         Ok(raw)
     }
 
+    fn child(&self, ino: u32, raw: Option<Ext4Inode>) -> Inode {
+        Inode(Arc::new(Ext4InodeWrapper {
+            fs: Arc::clone(&self.fs),
+            ino,
+            raw: RwLock::new(raw),
+            parent: Some(Inode(Arc::new(Ext4InodeWrapper {
+                fs: Arc::clone(&self.fs),
+                ino: self.ino,
+                raw: RwLock::new(None),
+                parent: self.parent.clone(),
+            }))),
+        }))
+    }
+
     fn inode_kind(mode: u16) -> InodeKind {
         match (mode >> 12) & 0xF {
             EXT4_S_IFREG => InodeKind::File,
@@ -726,6 +2587,8 @@ impl Ext4InodeWrapper {
             EXT4_S_IFLNK => InodeKind::Symlink,
             EXT4_S_IFCHR => InodeKind::CharDev,
             EXT4_S_IFBLK => InodeKind::BlockDev,
+            EXT4_S_IFIFO => InodeKind::Fifo,
+            EXT4_S_IFSOCK => InodeKind::Socket,
             _ => InodeKind::File,
         }
     }
@@ -737,53 +2600,56 @@ impl Ext4InodeWrapper {
             EXT4_FT_SYMLINK => InodeKind::Symlink,
             EXT4_FT_CHRDEV => InodeKind::CharDev,
             EXT4_FT_BLKDEV => InodeKind::BlockDev,
+            EXT4_FT_FIFO => InodeKind::Fifo,
+            EXT4_FT_SOCK => InodeKind::Socket,
             _ => InodeKind::File,
         }
     }
+
+    fn kind_to_file_type(kind: InodeKind) -> u8 {
+        match kind {
+            InodeKind::File => EXT4_FT_REG_FILE,
+            InodeKind::Dir => EXT4_FT_DIR,
+            InodeKind::Symlink => EXT4_FT_SYMLINK,
+            InodeKind::CharDev => EXT4_FT_CHRDEV,
+            InodeKind::BlockDev => EXT4_FT_BLKDEV,
+            InodeKind::Fifo => EXT4_FT_FIFO,
+            InodeKind::Socket => EXT4_FT_SOCK,
+        }
+    }
 }
 
 impl InodeOps for Ext4InodeWrapper {
     fn metadata(&self) -> Metadata {
-        let raw = self.get_raw().unwrap_or(Ext4Inode {
-            i_mode: 0,
-            i_uid: 0,
-            i_size_lo: 0,
-            i_atime: 0,
-            i_ctime: 0,
-            i_mtime: 0,
-            i_dtime: 0,
-            i_gid: 0,
-            i_links_count: 0,
-            i_blocks_lo: 0,
-            i_flags: 0,
-            i_osd1: 0,
-            i_block: [0; 15],
-            i_generation: 0,
-            i_file_acl_lo: 0,
-            i_size_high: 0,
-            i_obso_faddr: 0,
-            i_osd2: [0; 12],
-            i_extra_isize: 0,
-            i_checksum_hi: 0,
-            i_ctime_extra: 0,
-            i_mtime_extra: 0,
-            i_atime_extra: 0,
-            i_crtime: 0,
-            i_crtime_extra: 0,
-            i_version_hi: 0,
-            i_projid: 0,
-        });
-
-        Metadata::simple(
-            Uid(raw.i_uid as u32),
-            Gid(raw.i_gid as u32),
-            Mode::from_octal(raw.i_mode & 0o777),
-            Self::inode_kind(raw.i_mode),
-        )
+        let raw = self.get_raw().unwrap_or(Ext4Inode::zeroed());
+
+        Metadata {
+            uid: Uid(raw.uid()),
+            gid: Gid(raw.gid()),
+            mode: Mode::from_octal(raw.i_mode & 0o7777),
+            kind: Self::inode_kind(raw.i_mode),
+            ino: self.ino as u64,
+            nlink: raw.i_links_count as u32,
+            atime: Timespec { secs: raw.i_atime as u64, nsecs: 0 },
+            mtime: Timespec { secs: raw.i_mtime as u64, nsecs: 0 },
+            ctime: Timespec { secs: raw.i_ctime as u64, nsecs: 0 },
+        }
     }
 
-    fn set_metadata(&self, _meta: Metadata) {
-        // Read-only filesystem
+    fn set_metadata(&self, meta: Metadata) {
+        let _ = self.fs.transaction(|| {
+            let mut raw = self.fs.read_inode(self.ino)?;
+            // Keep the file type (high bits of the mode)
+            raw.i_mode = (raw.i_mode & 0xF000) | (meta.mode.to_octal() & 0o7777);
+            raw.set_uid(meta.uid.0);
+            raw.set_gid(meta.gid.0);
+            raw.i_atime = meta.atime.secs as u32;
+            raw.i_mtime = meta.mtime.secs as u32;
+            raw.i_ctime = now_secs();
+            self.fs.write_inode(self.ino, &raw, false)?;
+            *self.raw.write() = Some(raw);
+            Ok(())
+        });
     }
 
     fn parent(&self) -> Option<Inode> {
@@ -818,18 +2684,7 @@ impl InodeOps for Ext4InodeWrapper {
                         .unwrap_or("");
 
                     if entry_name == name {
-                        let child = Arc::new(Ext4InodeWrapper {
-                            fs: Arc::clone(&self.fs),
-                            ino: entry.inode,
-                            raw: RwLock::new(None),
-                            parent: Some(Inode(Arc::new(Ext4InodeWrapper {
-                                fs: Arc::clone(&self.fs),
-                                ino: self.ino,
-                                raw: RwLock::new(Some(raw)),
-                                parent: self.parent.clone(),
-                            }))),
-                        });
-                        return Ok(Inode(child));
+                        return Ok(self.child(entry.inode, None));
                     }
                 }
             }
@@ -843,9 +2698,11 @@ impl InodeOps for Ext4InodeWrapper {
         Err(KError::NotFound)
     }
 
-    fn create(&self, _name: &str, _kind: InodeKind, _meta: Metadata) -> KResult<Inode> {
-        // Read-only filesystem
-        Err(KError::NotSupported)
+    fn create(&self, name: &str, kind: InodeKind, meta: Metadata) -> KResult<Inode> {
+        let (ino, inode) = self.fs.transaction(|| {
+            self.fs.create_inode(self.ino, kind, name, &meta, None)
+        })?;
+        Ok(self.child(ino, Some(inode)))
     }
 
     fn readdir(&self) -> KResult<Vec<DirEntry>> {
@@ -901,13 +2758,38 @@ impl InodeOps for Ext4InodeWrapper {
         self.fs.read_inode_data(&raw, offset, out)
     }
 
-    fn write_at(&self, _offset: usize, _data: &[u8]) -> KResult<usize> {
-        // Read-only filesystem
-        Err(KError::NotSupported)
+    fn write_at(&self, offset: usize, data: &[u8]) -> KResult<usize> {
+        self.fs.transaction(|| {
+            let mut raw = self.fs.read_inode(self.ino)?;
+            if Self::inode_kind(raw.i_mode) != InodeKind::File {
+                return Err(KError::Invalid);
+            }
+
+            let written = self.fs.write_inode_data(self.ino, &mut raw, offset, data)?;
+            let now = now_secs();
+            raw.i_mtime = now;
+            raw.i_ctime = now;
+            self.fs.write_inode(self.ino, &raw, false)?;
+            *self.raw.write() = Some(raw);
+            Ok(written)
+        })
     }
 
-    fn truncate(&self, _size: usize) -> KResult<()> {
-        Err(KError::NotSupported)
+    fn truncate(&self, size: usize) -> KResult<()> {
+        self.fs.transaction(|| {
+            let mut raw = self.fs.read_inode(self.ino)?;
+            if Self::inode_kind(raw.i_mode) != InodeKind::File {
+                return Err(KError::Invalid);
+            }
+
+            self.fs.truncate_inode(self.ino, &mut raw, size as u64)?;
+            let now = now_secs();
+            raw.i_mtime = now;
+            raw.i_ctime = now;
+            self.fs.write_inode(self.ino, &raw, false)?;
+            *self.raw.write() = Some(raw);
+            Ok(())
+        })
     }
 
     fn size(&self) -> KResult<usize> {
@@ -915,16 +2797,228 @@ impl InodeOps for Ext4InodeWrapper {
         Ok(self.fs.inode_size(&raw) as usize)
     }
 
-    fn unlink(&self, _name: &str) -> KResult<()> {
-        Err(KError::NotSupported)
+    fn unlink(&self, name: &str) -> KResult<()> {
+        self.fs.transaction(|| {
+            let mut dir = self.fs.read_inode(self.ino)?;
+            if Self::inode_kind(dir.i_mode) != InodeKind::Dir {
+                return Err(KError::Invalid);
+            }
+
+            let (child_ino, _, _, _) = self.fs.find_dir_entry(&dir, name)?.ok_or(KError::NotFound)?;
+            let mut child = self.fs.read_inode(child_ino)?;
+            if Self::inode_kind(child.i_mode) == InodeKind::Dir {
+                return Err(KError::Invalid);
+            }
+
+            self.fs.remove_dir_entry(self.ino, &dir, name)?;
+
+            child.i_links_count = child.i_links_count.saturating_sub(1);
+            if child.i_links_count == 0 {
+                self.fs.release_inode(child_ino, &mut child)?;
+            } else {
+                child.i_ctime = now_secs();
+                self.fs.write_inode(child_ino, &child, false)?;
+            }
+
+            let now = now_secs();
+            dir.i_mtime = now;
+            dir.i_ctime = now;
+            self.fs.write_inode(self.ino, &dir, false)?;
+            *self.raw.write() = Some(dir);
+            Ok(())
+        })
     }
 
-    fn rmdir(&self, _name: &str) -> KResult<()> {
-        Err(KError::NotSupported)
+    fn rmdir(&self, name: &str) -> KResult<()> {
+        if name == "." || name == ".." {
+            return Err(KError::Invalid);
+        }
+
+        self.fs.transaction(|| {
+            let mut dir = self.fs.read_inode(self.ino)?;
+            if Self::inode_kind(dir.i_mode) != InodeKind::Dir {
+                return Err(KError::Invalid);
+            }
+
+            let (child_ino, _, _, _) = self.fs.find_dir_entry(&dir, name)?.ok_or(KError::NotFound)?;
+            let mut child = self.fs.read_inode(child_ino)?;
+            if Self::inode_kind(child.i_mode) != InodeKind::Dir {
+                return Err(KError::NotADirectory);
+            }
+            if !self.fs.dir_is_empty(&child)? {
+                return Err(KError::NotEmpty);
+            }
+
+            self.fs.remove_dir_entry(self.ino, &dir, name)?;
+            self.fs.release_inode(child_ino, &mut child)?;
+
+            // The child's ".." no longer references us
+            dir.i_links_count = dir.i_links_count.saturating_sub(1);
+            let now = now_secs();
+            dir.i_mtime = now;
+            dir.i_ctime = now;
+            self.fs.write_inode(self.ino, &dir, false)?;
+            *self.raw.write() = Some(dir);
+            Ok(())
+        })
+    }
+
+    fn symlink(&self, name: &str, target: &str, meta: Metadata) -> KResult<Inode> {
+        if target.is_empty() || target.len() >= self.fs.block_size as usize {
+            return Err(KError::Invalid);
+        }
+        let (ino, inode) = self.fs.transaction(|| {
+            self.fs.create_inode(self.ino, InodeKind::Symlink, name, &meta, Some(target))
+        })?;
+        Ok(self.child(ino, Some(inode)))
+    }
+
+    fn readlink(&self) -> KResult<String> {
+        let raw = self.get_raw()?;
+        if Self::inode_kind(raw.i_mode) != InodeKind::Symlink {
+            return Err(KError::Invalid);
+        }
+
+        let size = self.fs.inode_size(&raw) as usize;
+        let bytes = if self.fs.is_fast_symlink(&raw) {
+            raw.i_block_bytes()[..size].to_vec()
+        } else {
+            let mut buf = vec![0u8; size];
+            let n = self.fs.read_inode_data(&raw, 0, &mut buf)?;
+            buf.truncate(n);
+            buf
+        };
+
+        String::from_utf8(bytes).map_err(|_| KError::Invalid)
+    }
+
+    fn link(&self, name: &str, target: Inode) -> KResult<()> {
+        let target = vfs::own_inode(&target, &self.fs, |wrapper: &Ext4InodeWrapper| &wrapper.fs)?;
+        let target_ino = target.ino;
+
+        self.fs.transaction(|| {
+            let mut dir = self.fs.read_inode(self.ino)?;
+            if Self::inode_kind(dir.i_mode) != InodeKind::Dir {
+                return Err(KError::NotADirectory);
+            }
+            if self.fs.find_dir_entry(&dir, name)?.is_some() {
+                return Err(KError::AlreadyExists);
+            }
+
+            let mut inode = self.fs.read_inode(target_ino)?;
+            let kind = Self::inode_kind(inode.i_mode);
+            if kind == InodeKind::Dir {
+                return Err(KError::PermissionDenied);
+            }
+            if inode.i_links_count == u16::MAX {
+                return Err(KError::NoMemory);
+            }
+
+            self.fs.add_dir_entry(self.ino, &mut dir, target_ino, name, Self::kind_to_file_type(kind))?;
+            let now = now_secs();
+            dir.i_mtime = now;
+            dir.i_ctime = now;
+            self.fs.write_inode(self.ino, &dir, false)?;
+
+            inode.i_links_count += 1;
+            inode.i_ctime = now;
+            self.fs.write_inode(target_ino, &inode, false)?;
+            Ok(())
+        })
     }
+
+    fn rename_to(&self, old_name: &str, new_parent: &Inode, new_name: &str) -> KResult<()> {
+        let new_dir_ino = vfs::own_inode(new_parent, &self.fs, |wrapper: &Ext4InodeWrapper| &wrapper.fs)?.ino;
+        if new_dir_ino == self.ino && old_name == new_name {
+            return Ok(());
+        }
+        if new_name.is_empty() || new_name.len() > EXT4_NAME_LEN || new_name.contains('/') {
+            return Err(KError::Invalid);
+        }
+
+        self.fs.transaction(|| {
+            let old_dir = self.fs.read_inode(self.ino)?;
+            let (child_ino, file_type, _, _) =
+                self.fs.find_dir_entry(&old_dir, old_name)?.ok_or(KError::NotFound)?;
+
+            let mut new_dir = self.fs.read_inode(new_dir_ino)?;
+            if Self::inode_kind(new_dir.i_mode) != InodeKind::Dir {
+                return Err(KError::NotADirectory);
+            }
+            if self.fs.find_dir_entry(&new_dir, new_name)?.is_some() {
+                return Err(KError::AlreadyExists);
+            }
+            if child_ino == new_dir_ino {
+                return Err(KError::Invalid);
+            }
+
+            let now = now_secs();
+
+            // Link under the new name first, then drop the old entry
+            self.fs.add_dir_entry(new_dir_ino, &mut new_dir, child_ino, new_name, file_type)?;
+            new_dir.i_mtime = now;
+            new_dir.i_ctime = now;
+            self.fs.write_inode(new_dir_ino, &new_dir, false)?;
+
+            let mut old_dir = self.fs.read_inode(self.ino)?;
+            self.fs.remove_dir_entry(self.ino, &old_dir, old_name)?;
+
+            let mut child = self.fs.read_inode(child_ino)?;
+            if Self::inode_kind(child.i_mode) == InodeKind::Dir && new_dir_ino != self.ino {
+                self.fs.set_dotdot(child_ino, &child, new_dir_ino)?;
+                old_dir.i_links_count = old_dir.i_links_count.saturating_sub(1);
+                let mut new_dir = self.fs.read_inode(new_dir_ino)?;
+                new_dir.i_links_count = new_dir.i_links_count.saturating_add(1);
+                self.fs.write_inode(new_dir_ino, &new_dir, false)?;
+            }
+            child.i_ctime = now;
+            self.fs.write_inode(child_ino, &child, false)?;
+
+            old_dir.i_mtime = now;
+            old_dir.i_ctime = now;
+            self.fs.write_inode(self.ino, &old_dir, false)?;
+            *self.raw.write() = Some(old_dir);
+            Ok(())
+        })
+    }
+
+    fn as_any(&self) -> Option<&dyn Any> {
+        Some(self)
+    }
+
+    fn statfs(&self) -> KResult<FsStats> {
+        Ok(self.fs.stats())
+    }
+}
+
+// ============================================================================
+// Superblock I/O
+// ============================================================================
+
+/// Device LBA range covering the superblock: (first lba, lba count)
+fn superblock_lbas(device: &Arc<dyn BlockDevice>) -> (u64, u32) {
+    let dbs = device.block_size() as u64;
+    let first = EXT4_SUPERBLOCK_OFFSET / dbs;
+    let last = (EXT4_SUPERBLOCK_OFFSET + EXT4_SUPERBLOCK_SIZE as u64 - 1) / dbs;
+    (first, (last - first + 1) as u32)
 }
 
-use alloc::string::ToString;
+fn read_superblock_raw(device: &Arc<dyn BlockDevice>) -> KResult<Vec<u8>> {
+    let (lba, count) = superblock_lbas(device);
+    let mut buf = vec![0u8; count as usize * device.block_size() as usize];
+    device.read_blocks(lba, count, &mut buf)?;
+    let off = (EXT4_SUPERBLOCK_OFFSET % device.block_size() as u64) as usize;
+    Ok(buf[off..off + EXT4_SUPERBLOCK_SIZE].to_vec())
+}
+
+fn write_superblock_raw(device: &Arc<dyn BlockDevice>, sb: &[u8]) -> KResult<()> {
+    let (lba, count) = superblock_lbas(device);
+    let mut buf = vec![0u8; count as usize * device.block_size() as usize];
+    device.read_blocks(lba, count, &mut buf)?;
+    let off = (EXT4_SUPERBLOCK_OFFSET % device.block_size() as u64) as usize;
+    buf[off..off + EXT4_SUPERBLOCK_SIZE].copy_from_slice(sb);
+    device.write_blocks(lba, count, &buf)
+}
 
 // ============================================================================
 // Helper function to mount ext4
@@ -937,24 +3031,14 @@ pub fn try_mount(device: Arc<dyn BlockDevice>) -> KResult<Arc<Ext4Fs>> {
 
 /// Check if a device contains an ext4 filesystem
 pub fn is_ext4(device: &Arc<dyn BlockDevice>) -> KResult<bool> {
-    let sb_block = EXT4_SUPERBLOCK_OFFSET / device.block_size() as u64;
-    let sb_offset = (EXT4_SUPERBLOCK_OFFSET % device.block_size() as u64) as usize;
-
-    let mut buf = vec![0u8; device.block_size() as usize];
-    device.read_blocks(sb_block, 1, &mut buf)?;
-
-    let magic = unsafe {
-        core::ptr::read_unaligned(buf.as_ptr().add(sb_offset + 56) as *const u16)
-    };
+    let sb = read_superblock_raw(device)?;
 
-    if magic != EXT4_SUPER_MAGIC {
+    if le16(&sb, 56) != EXT4_SUPER_MAGIC {
         return Ok(false);
     }
 
     // Check for ext4-specific features
-    let feature_incompat = unsafe {
-        core::ptr::read_unaligned(buf.as_ptr().add(sb_offset + 96) as *const u32)
-    };
+    let feature_incompat = le32(&sb, 96);
 
     // Has extents or 64-bit feature = ext4
     Ok((feature_incompat & (EXT4_FEATURE_INCOMPAT_EXTENTS | EXT4_FEATURE_INCOMPAT_64BIT)) != 0)
@@ -1,10 +1,16 @@
-//! FAT32 filesystem driver (read-only)
+//! FAT32 filesystem driver (read/write)
 //!
 //! FAT32 is commonly used on USB drives, SD cards, and for EFI system partitions.
 //! This implementation supports:
 //! - FAT32 (not FAT12/FAT16)
-//! - Long File Names (LFN)
-//! - Read-only access
+//! - Long File Names (VFAT LFN), including creation with generated 8.3 aliases
+//! - Reading and writing files, growing and truncating cluster chains
+//! - Creating, removing and renaming files and directories
+//! - Keeping every FAT copy and the FSInfo free-cluster hints up to date
+//!
+//! FAT has no inodes: a file is identified by the position of its short
+//! directory entry. Every open handle to the same entry shares one
+//! `FatNode`, so size and first-cluster updates are seen by all of them.
 //!
 //! References:
 //! - Microsoft FAT Specification
@@ -12,17 +18,20 @@
 
 #![allow(dead_code)]
 
+use alloc::collections::BTreeMap;
 use alloc::string::String;
-use alloc::sync::Arc;
+use alloc::sync::{Arc, Weak};
 use alloc::vec;
 use alloc::vec::Vec;
-use spin::RwLock;
+use core::any::Any;
+use spin::{Mutex, RwLock};
 
 use crate::security::{Gid, Uid};
 use crate::storage::BlockDevice;
+use crate::time::{civil_from_days, days_from_civil};
 use crate::util::{KError, KResult};
 
-use super::vfs::{DirEntry, Inode, InodeKind, InodeOps, Metadata, Mode};
+use super::vfs::{self, DirEntry, FsStats, Inode, InodeKind, InodeOps, Metadata, Mode, Timespec};
 
 // ============================================================================
 // Constants
@@ -30,12 +39,17 @@ use super::vfs::{DirEntry, Inode, InodeKind, InodeOps, Metadata, Mode};
 
 const FAT32_SIGNATURE: u16 = 0xAA55;
 const FAT32_FS_TYPE: [u8; 8] = *b"FAT32   ";
+/// statfs f_type reported by Linux for FAT volumes
+const MSDOS_SUPER_MAGIC: u64 = 0x4D44;
 
 // FAT entry values
 const FAT32_CLUSTER_FREE: u32 = 0x00000000;
 const FAT32_CLUSTER_RESERVED: u32 = 0x0FFFFFF0;
 const FAT32_CLUSTER_BAD: u32 = 0x0FFFFFF7;
 const FAT32_CLUSTER_END: u32 = 0x0FFFFFF8;
+/// End-of-chain marker written for new chains
+const FAT32_CLUSTER_EOC: u32 = 0x0FFFFFFF;
+const FAT32_ENTRY_MASK: u32 = 0x0FFFFFFF;
 
 // Directory entry attributes
 const ATTR_READ_ONLY: u8 = 0x01;
@@ -47,6 +61,31 @@ const ATTR_ARCHIVE: u8 = 0x20;
 const ATTR_LONG_NAME: u8 = ATTR_READ_ONLY | ATTR_HIDDEN | ATTR_SYSTEM | ATTR_VOLUME_ID;
 const ATTR_LONG_NAME_MASK: u8 = ATTR_READ_ONLY | ATTR_HIDDEN | ATTR_SYSTEM | ATTR_VOLUME_ID | ATTR_DIRECTORY | ATTR_ARCHIVE;
 
+// Directory entries
+const DIR_ENTRY_SIZE: usize = 32;
+const DIR_ENTRY_FREE: u8 = 0xE5;
+const DIR_ENTRY_END: u8 = 0x00;
+/// A directory may hold at most 65536 entries (2 MiB)
+const DIR_MAX_ENTRIES: u32 = 65536;
+const LFN_LAST_ENTRY: u8 = 0x40;
+const LFN_CHARS_PER_ENTRY: usize = 13;
+const LFN_MAX_CHARS: usize = 255;
+
+// NT reserved byte: short name parts stored in lowercase
+const NT_LOWER_BASE: u8 = 0x08;
+const NT_LOWER_EXT: u8 = 0x10;
+
+// FSInfo sector
+const FSINFO_LEAD_SIG: u32 = 0x41615252;
+const FSINFO_STRUCT_SIG: u32 = 0x61417272;
+const FSINFO_TRAIL_SIG: u32 = 0xAA550000;
+const FSINFO_FREE_COUNT: usize = 488;
+const FSINFO_NEXT_FREE: usize = 492;
+const FSINFO_UNKNOWN: u32 = 0xFFFFFFFF;
+
+// BPB ext_flags: bit 7 set = only the FAT in bits 0-3 is active
+const EXT_FLAGS_NO_MIRROR: u16 = 0x0080;
+
 // ============================================================================
 // On-disk structures (packed, little-endian)
 // ============================================================================
@@ -115,16 +154,33 @@ struct Fat32LfnEntry {
 }
 
 impl Fat32DirEntry {
+    fn from_bytes(bytes: &[u8]) -> Self {
+        unsafe { core::ptr::read_unaligned(bytes.as_ptr() as *const _) }
+    }
+
+    fn to_bytes(&self) -> [u8; DIR_ENTRY_SIZE] {
+        let mut out = [0u8; DIR_ENTRY_SIZE];
+        unsafe {
+            core::ptr::write_unaligned(out.as_mut_ptr() as *mut Fat32DirEntry, *self);
+        }
+        out
+    }
+
     fn first_cluster(&self) -> u32 {
         ((self.first_cluster_hi as u32) << 16) | (self.first_cluster_lo as u32)
     }
 
+    fn set_first_cluster(&mut self, cluster: u32) {
+        self.first_cluster_hi = (cluster >> 16) as u16;
+        self.first_cluster_lo = cluster as u16;
+    }
+
     fn is_free(&self) -> bool {
-        self.name[0] == 0xE5
+        self.name[0] == DIR_ENTRY_FREE
     }
 
     fn is_end(&self) -> bool {
-        self.name[0] == 0x00
+        self.name[0] == DIR_ENTRY_END
     }
 
     fn is_lfn(&self) -> bool {
@@ -142,6 +198,8 @@ impl Fat32DirEntry {
     /// Extract 8.3 filename
     fn short_name(&self) -> String {
         let mut name = String::new();
+        let lower_base = self.nt_reserved & NT_LOWER_BASE != 0;
+        let lower_ext = self.nt_reserved & NT_LOWER_EXT != 0;
 
         // Name part (first 8 chars)
         for i in 0..8 {
@@ -152,6 +210,8 @@ impl Fat32DirEntry {
             // Handle special first character
             if i == 0 && c == 0x05 {
                 name.push(0xE5 as char);
+            } else if lower_base {
+                name.push(c.to_ascii_lowercase() as char);
             } else {
                 name.push(c as char);
             }
@@ -166,7 +226,11 @@ impl Fat32DirEntry {
                 if c == b' ' {
                     break;
                 }
-                name.push(c as char);
+                if lower_ext {
+                    name.push(c.to_ascii_lowercase() as char);
+                } else {
+                    name.push(c as char);
+                }
             }
         }
 
@@ -210,13 +274,235 @@ impl Fat32LfnEntry {
 
         chars
     }
+
+    /// Build one LFN entry holding `chars` (already padded to 13 units)
+    fn to_bytes(order: u8, checksum: u8, chars: &[u16; 13]) -> [u8; DIR_ENTRY_SIZE] {
+        let mut out = [0u8; DIR_ENTRY_SIZE];
+        out[0] = order;
+        out[11] = ATTR_LONG_NAME;
+        out[13] = checksum;
+        let offsets = [1usize, 3, 5, 7, 9, 14, 16, 18, 20, 22, 24, 28, 30];
+        for (c, &off) in chars.iter().zip(offsets.iter()) {
+            out[off..off + 2].copy_from_slice(&c.to_le_bytes());
+        }
+        out
+    }
+}
+
+/// Checksum of an 8.3 name, stored in each of its LFN entries
+fn lfn_checksum(short: &[u8; 11]) -> u8 {
+    let mut sum = 0u8;
+    for &b in short {
+        sum = ((sum & 1) << 7).wrapping_add(sum >> 1).wrapping_add(b);
+    }
+    sum
+}
+
+// ============================================================================
+// Names
+// ============================================================================
+
+/// Characters never allowed in a long name
+fn is_valid_long_char(c: char) -> bool {
+    !(c < ' ' || matches!(c, '"' | '*' | '/' | ':' | '<' | '>' | '?' | '\\' | '|'))
+}
+
+/// Characters allowed in an 8.3 name (after uppercasing)
+fn is_valid_short_char(c: u8) -> bool {
+    c.is_ascii_uppercase()
+        || c.is_ascii_digit()
+        || c >= 0x80
+        || b"!#$%&'()-@^_`{}~".contains(&c)
+}
+
+/// How a name is stored on disk
+struct ShortName {
+    name: [u8; 11],
+    /// NT case bits, for names that only differ from the 8.3 form in case
+    nt_flags: u8,
+    /// Whether LFN entries are needed to preserve the name
+    needs_lfn: bool,
+}
+
+/// Try to store `name` as a plain 8.3 entry (possibly with NT case bits)
+fn exact_short_name(name: &str) -> Option<ShortName> {
+    if name == "." || name == ".." || !name.is_ascii() {
+        return None;
+    }
+    let (base, ext) = match name.rfind('.') {
+        Some(0) => return None,
+        Some(pos) => (&name[..pos], &name[pos + 1..]),
+        None => (name, ""),
+    };
+    if base.is_empty() || base.len() > 8 || ext.len() > 3 || (name.ends_with('.')) {
+        return None;
+    }
+
+    let mut nt_flags = 0;
+    for (part, flag) in [(base, NT_LOWER_BASE), (ext, NT_LOWER_EXT)] {
+        let has_lower = part.bytes().any(|c| c.is_ascii_lowercase());
+        let has_upper = part.bytes().any(|c| c.is_ascii_uppercase());
+        if has_lower && has_upper {
+            return None;
+        }
+        if has_lower {
+            nt_flags |= flag;
+        }
+    }
+
+    let mut short = [b' '; 11];
+    for (i, c) in base.bytes().enumerate() {
+        short[i] = c.to_ascii_uppercase();
+    }
+    for (i, c) in ext.bytes().enumerate() {
+        short[8 + i] = c.to_ascii_uppercase();
+    }
+    if !short.iter().all(|&c| c == b' ' || is_valid_short_char(c)) {
+        return None;
+    }
+    if short[0] == 0xE5 {
+        short[0] = 0x05;
+    }
+
+    Some(ShortName { name: short, nt_flags, needs_lfn: false })
+}
+
+/// Generate the "BASIS~N.EXT" alias for a long name
+fn generated_short_name(name: &str, n: u32) -> [u8; 11] {
+    let clean = |s: &str, max: usize| -> Vec<u8> {
+        s.chars()
+            .filter(|&c| c != ' ' && c != '.')
+            .map(|c| {
+                let u = c.to_ascii_uppercase();
+                if u.is_ascii() && is_valid_short_char(u as u8) {
+                    u as u8
+                } else {
+                    b'_'
+                }
+            })
+            .take(max)
+            .collect()
+    };
+
+    let trimmed = name.trim_start_matches('.');
+    let (base, ext) = match trimmed.rfind('.') {
+        Some(pos) => (&trimmed[..pos], &trimmed[pos + 1..]),
+        None => (trimmed, ""),
+    };
+    let mut base = clean(base, 8);
+    let ext = clean(ext, 3);
+    if base.is_empty() {
+        base.push(b'_');
+    }
+
+    let mut tail = [0u8; 8];
+    let mut tail_len = 0;
+    let mut v = n;
+    while v > 0 {
+        tail[tail_len] = b'0' + (v % 10) as u8;
+        v /= 10;
+        tail_len += 1;
+    }
+    let keep = core::cmp::min(base.len(), 8 - 1 - tail_len);
+
+    let mut short = [b' '; 11];
+    short[..keep].copy_from_slice(&base[..keep]);
+    short[keep] = b'~';
+    for i in 0..tail_len {
+        short[keep + 1 + i] = tail[tail_len - 1 - i];
+    }
+    short[8..8 + ext.len()].copy_from_slice(&ext);
+    if short[0] == 0xE5 {
+        short[0] = 0x05;
+    }
+    short
+}
+
+fn validate_long_name(name: &str) -> KResult<Vec<u16>> {
+    if name.is_empty() || name == "." || name == ".." || !name.chars().all(is_valid_long_char) {
+        return Err(KError::Invalid);
+    }
+    let units: Vec<u16> = name.encode_utf16().collect();
+    if units.len() > LFN_MAX_CHARS {
+        return Err(KError::Invalid);
+    }
+    Ok(units)
+}
+
+// ============================================================================
+// Timestamps
+// ============================================================================
+
+/// Convert a FAT date/time (local time) to Unix seconds
+fn dos_to_unix(date: u16, time: u16) -> u64 {
+    if date == 0 {
+        return 0;
+    }
+    let year = 1980 + (date >> 9) as i64;
+    let month = ((date >> 5) & 0xF).clamp(1, 12) as u32;
+    let day = (date & 0x1F).max(1) as u32;
+    let secs = days_from_civil(year, month, day) * 86400
+        + (time >> 11) as i64 * 3600
+        + ((time >> 5) & 0x3F) as i64 * 60
+        + (time & 0x1F) as i64 * 2;
+    crate::time::local_to_utc(secs).max(0) as u64
+}
+
+/// Convert Unix seconds to a FAT (date, time) in local time
+fn unix_to_dos(secs: u64) -> (u16, u16) {
+    let local = crate::time::utc_to_local(secs as i64);
+    let days = local.div_euclid(86400);
+    let rem = local.rem_euclid(86400);
+    let (year, month, day) = civil_from_days(days);
+    if year < 1980 {
+        return ((1 << 5) | 1, 0);
+    }
+    let year = core::cmp::min(year - 1980, 127) as u16;
+    let date = (year << 9) | ((month as u16) << 5) | day as u16;
+    let time = ((rem / 3600) as u16) << 11 | (((rem % 3600) / 60) as u16) << 5 | ((rem % 60) / 2) as u16;
+    (date, time)
+}
+
+fn now_secs() -> u64 {
+    crate::time::realtime().tv_sec.max(0) as u64
 }
 
 // ============================================================================
 // Driver structures
 // ============================================================================
 
-/// Mounted FAT32 filesystem (read-only)
+/// Free-cluster hints kept in the FSInfo sector
+struct FsInfo {
+    free_count: u32,
+    next_free: u32,
+}
+
+/// In-memory state of a file or directory, shared by every handle to it
+struct FatNode {
+    /// Short entry location: (first cluster of the parent directory, entry index).
+    /// None for the root directory, which has no entry.
+    loc: Option<(u32, u32)>,
+    first_cluster: u32,
+    size: u32,
+    attr: u8,
+    mtime: u64,
+    atime: u64,
+    crtime: u64,
+    /// The entry was removed; further I/O through open handles fails
+    deleted: bool,
+}
+
+/// A parsed directory entry together with its position
+struct DirSlot {
+    name: String,
+    entry: Fat32DirEntry,
+    /// Index of the short entry
+    index: u32,
+    /// Index of the first LFN entry (== index when there is none)
+    first: u32,
+}
+
+/// Mounted FAT32 filesystem
 pub struct Fat32Fs {
     device: Arc<dyn BlockDevice>,
     bytes_per_sector: u16,
@@ -227,6 +513,17 @@ pub struct Fat32Fs {
     root_cluster: u32,
     total_sectors: u32,
     data_start_sector: u32,
+    /// Highest valid cluster number
+    max_cluster: u32,
+    /// FAT copy to use when mirroring is disabled
+    active_fat: Option<u8>,
+    fs_info_sector: Option<u16>,
+    fs_info: Mutex<FsInfo>,
+    root_node: Arc<RwLock<FatNode>>,
+    /// Open files/directories by short entry location
+    nodes: Mutex<BTreeMap<(u32, u32), Weak<RwLock<FatNode>>>>,
+    /// Serializes modifying operations
+    op_lock: Mutex<()>,
 }
 
 impl Fat32Fs {
@@ -267,26 +564,72 @@ impl Fat32Fs {
         let total_sectors = unsafe {
             core::ptr::read_unaligned(core::ptr::addr_of!(bpb.total_sectors_32))
         };
+        let fat_size_16 = unsafe {
+            core::ptr::read_unaligned(core::ptr::addr_of!(bpb.fat_size_16))
+        };
+        let root_entry_count = unsafe {
+            core::ptr::read_unaligned(core::ptr::addr_of!(bpb.root_entry_count))
+        };
+        let ext_flags = unsafe {
+            core::ptr::read_unaligned(core::ptr::addr_of!(bpb.ext_flags))
+        };
+        let fs_info = unsafe {
+            core::ptr::read_unaligned(core::ptr::addr_of!(bpb.fs_info))
+        };
 
         // Validate parameters
-        if bytes_per_sector < 512 || sectors_per_cluster == 0 || num_fats == 0 {
+        if bytes_per_sector < 512
+            || (bytes_per_sector as u32) < device.block_size()
+            || sectors_per_cluster == 0
+            || num_fats == 0
+            || fat_size == 0
+        {
             crate::kprintln!("fat32: invalid BPB parameters");
             return Err(KError::Invalid);
         }
 
+        // FAT12/16 BPBs have a fixed root directory and a 16-bit FAT size
+        if fat_size_16 != 0 || root_entry_count != 0 {
+            crate::kprintln!("fat32: FAT12/FAT16 BPB, not FAT32");
+            return Err(KError::Invalid);
+        }
+
         // Calculate data start sector
         let data_start_sector = reserved_sectors as u32 + (num_fats as u32 * fat_size);
+        if data_start_sector >= total_sectors {
+            crate::kprintln!("fat32: invalid BPB parameters");
+            return Err(KError::Invalid);
+        }
 
         // Calculate number of data clusters
         let data_sectors = total_sectors - data_start_sector;
         let cluster_count = data_sectors / sectors_per_cluster as u32;
 
-        // FAT32 requires at least 65525 clusters
+        // FAT32 formally requires at least 65525 clusters, but small volumes
+        // formatted as FAT32 (e.g. mkfs.fat -F 32) are common and work fine
         if cluster_count < 65525 {
-            crate::kprintln!("fat32: cluster count {} < 65525, not FAT32", cluster_count);
+            crate::kprintln!("fat32: only {} clusters, accepting FAT32 BPB anyway", cluster_count);
+        }
+
+        let fat_entries = fat_size as u64 * bytes_per_sector as u64 / 4;
+        let max_cluster = core::cmp::min(cluster_count as u64 + 1, fat_entries - 1) as u32;
+        if root_cluster < 2 || root_cluster > max_cluster {
+            crate::kprintln!("fat32: invalid root cluster {}", root_cluster);
             return Err(KError::Invalid);
         }
 
+        let active_fat = if ext_flags & EXT_FLAGS_NO_MIRROR != 0 {
+            Some((ext_flags & 0xF) as u8)
+        } else {
+            None
+        };
+
+        let fs_info_sector = if fs_info != 0 && fs_info != 0xFFFF && (fs_info as u32) < reserved_sectors as u32 {
+            Some(fs_info)
+        } else {
+            None
+        };
+
         crate::kprintln!(
             "fat32: mounted (clusters={}, bytes_per_sector={}, sectors_per_cluster={})",
             cluster_count,
@@ -294,7 +637,7 @@ impl Fat32Fs {
             sectors_per_cluster
         );
 
-        Ok(Arc::new(Self {
+        let fs = Arc::new(Self {
             device,
             bytes_per_sector,
             sectors_per_cluster,
@@ -304,9 +647,121 @@ impl Fat32Fs {
             root_cluster,
             total_sectors,
             data_start_sector,
-        }))
+            max_cluster,
+            active_fat,
+            fs_info_sector,
+            fs_info: Mutex::new(FsInfo { free_count: FSINFO_UNKNOWN, next_free: 2 }),
+            root_node: Arc::new(RwLock::new(FatNode {
+                loc: None,
+                first_cluster: root_cluster,
+                size: 0, // Directory size is dynamic
+                attr: ATTR_DIRECTORY,
+                mtime: 0,
+                atime: 0,
+                crtime: 0,
+                deleted: false,
+            })),
+            nodes: Mutex::new(BTreeMap::new()),
+            op_lock: Mutex::new(()),
+        });
+
+        fs.load_fs_info()?;
+        Ok(fs)
+    }
+
+    /// Read FSInfo hints, recounting free clusters when they are unknown
+    fn load_fs_info(&self) -> KResult<()> {
+        let mut free_count = FSINFO_UNKNOWN;
+        let mut next_free = FSINFO_UNKNOWN;
+
+        if let Some(sector) = self.fs_info_sector {
+            let mut buf = vec![0u8; self.bytes_per_sector as usize];
+            self.read_sector(sector as u32, &mut buf)?;
+            if le32(&buf, 0) == FSINFO_LEAD_SIG && le32(&buf, 484) == FSINFO_STRUCT_SIG {
+                free_count = le32(&buf, FSINFO_FREE_COUNT);
+                next_free = le32(&buf, FSINFO_NEXT_FREE);
+            }
+        }
+
+        if free_count == FSINFO_UNKNOWN || free_count > self.max_cluster - 1 {
+            free_count = self.count_free_clusters()?;
+        }
+        if next_free < 2 || next_free > self.max_cluster {
+            next_free = 2;
+        }
+
+        *self.fs_info.lock() = FsInfo { free_count, next_free };
+        Ok(())
     }
 
+    /// Cluster counts for statfs. FAT has no inode table, so the file
+    /// counts are left at zero.
+    pub fn stats(&self) -> FsStats {
+        let free = self.fs_info.lock().free_count as u64;
+        FsStats {
+            magic: MSDOS_SUPER_MAGIC,
+            block_size: self.bytes_per_sector as u64 * self.sectors_per_cluster as u64,
+            blocks: (self.max_cluster - 1) as u64,
+            blocks_free: free,
+            blocks_avail: free,
+            files: 0,
+            files_free: 0,
+            name_max: 255,
+        }
+    }
+
+    fn count_free_clusters(&self) -> KResult<u32> {
+        let per_sector = self.bytes_per_sector as u32 / 4;
+        let mut buf = vec![0u8; self.bytes_per_sector as usize];
+        let mut free = 0;
+        let mut loaded = u32::MAX;
+
+        for cluster in 2..=self.max_cluster {
+            let sector = cluster / per_sector;
+            if sector != loaded {
+                self.read_sector(self.fat_start(self.first_fat()) + sector, &mut buf)?;
+                loaded = sector;
+            }
+            if le32(&buf, ((cluster % per_sector) * 4) as usize) & FAT32_ENTRY_MASK == FAT32_CLUSTER_FREE {
+                free += 1;
+            }
+        }
+
+        Ok(free)
+    }
+
+    /// Write the FSInfo hints back to disk
+    fn flush_fs_info(&self) -> KResult<()> {
+        let sector = match self.fs_info_sector {
+            Some(s) => s as u32,
+            None => return Ok(()),
+        };
+
+        let mut buf = vec![0u8; self.bytes_per_sector as usize];
+        self.read_sector(sector, &mut buf)?;
+        if le32(&buf, 0) != FSINFO_LEAD_SIG || le32(&buf, 484) != FSINFO_STRUCT_SIG {
+            return Ok(());
+        }
+
+        let info = self.fs_info.lock();
+        put_le32(&mut buf, FSINFO_FREE_COUNT, info.free_count);
+        put_le32(&mut buf, FSINFO_NEXT_FREE, info.next_free);
+        drop(info);
+        self.write_sector(sector, &buf)
+    }
+
+    /// Run a modifying operation and persist the FSInfo hints afterwards
+    fn modify<T>(&self, f: impl FnOnce() -> KResult<T>) -> KResult<T> {
+        let _guard = self.op_lock.lock();
+        let result = f();
+        self.flush_fs_info()?;
+        result
+    }
+
+    // ========================================================================
+    // Sectors and clusters
+    // ========================================================================
+
     /// Read a sector from the device
     fn read_sector(&self, sector: u32, buf: &mut [u8]) -> KResult<()> {
         let dev_block_size = self.device.block_size();
@@ -315,13 +770,32 @@ impl Fat32Fs {
         self.device.read_blocks(lba, factor as u32, buf)
     }
 
+    /// Write a sector to the device
+    fn write_sector(&self, sector: u32, buf: &[u8]) -> KResult<()> {
+        let dev_block_size = self.device.block_size();
+        let factor = self.bytes_per_sector / dev_block_size as u16;
+        let lba = sector as u64 * factor as u64;
+        self.device.write_blocks(lba, factor as u32, buf)
+    }
+
+    fn cluster_size(&self) -> usize {
+        self.bytes_per_sector as usize * self.sectors_per_cluster as usize
+    }
+
     /// Get the first sector of a cluster
     fn cluster_to_sector(&self, cluster: u32) -> u32 {
         self.data_start_sector + (cluster - 2) * self.sectors_per_cluster as u32
     }
 
+    fn valid_cluster(&self, cluster: u32) -> bool {
+        cluster >= 2 && cluster <= self.max_cluster
+    }
+
     /// Read a cluster into buffer
     fn read_cluster(&self, cluster: u32, buf: &mut [u8]) -> KResult<()> {
+        if !self.valid_cluster(cluster) {
+            return Err(KError::IO);
+        }
         let sector = self.cluster_to_sector(cluster);
         let sectors_per_cluster = self.sectors_per_cluster as u32;
         let bytes_per_sector = self.bytes_per_sector as usize;
@@ -334,62 +808,271 @@ impl Fat32Fs {
         Ok(())
     }
 
-    /// Get the next cluster in the chain (from FAT)
-    fn next_cluster(&self, cluster: u32) -> KResult<Option<u32>> {
+    /// Write a whole cluster
+    fn write_cluster(&self, cluster: u32, buf: &[u8]) -> KResult<()> {
+        if !self.valid_cluster(cluster) {
+            return Err(KError::IO);
+        }
+        let sector = self.cluster_to_sector(cluster);
+        let bytes_per_sector = self.bytes_per_sector as usize;
+
+        for i in 0..self.sectors_per_cluster as u32 {
+            let offset = i as usize * bytes_per_sector;
+            self.write_sector(sector + i, &buf[offset..offset + bytes_per_sector])?;
+        }
+
+        Ok(())
+    }
+
+    /// Write `data` at byte `offset` inside a cluster (read-modify-write of partial sectors)
+    fn write_cluster_range(&self, cluster: u32, offset: usize, data: &[u8]) -> KResult<()> {
+        if !self.valid_cluster(cluster) {
+            return Err(KError::IO);
+        }
+        let bps = self.bytes_per_sector as usize;
+        let first_sector = self.cluster_to_sector(cluster);
+        let mut sector_buf = vec![0u8; bps];
+        let mut done = 0;
+
+        while done < data.len() {
+            let pos = offset + done;
+            let sector = first_sector + (pos / bps) as u32;
+            let in_sector = pos % bps;
+            let chunk = core::cmp::min(data.len() - done, bps - in_sector);
+
+            if chunk == bps {
+                self.write_sector(sector, &data[done..done + chunk])?;
+            } else {
+                self.read_sector(sector, &mut sector_buf)?;
+                sector_buf[in_sector..in_sector + chunk].copy_from_slice(&data[done..done + chunk]);
+                self.write_sector(sector, &sector_buf)?;
+            }
+            done += chunk;
+        }
+
+        Ok(())
+    }
+
+    // ========================================================================
+    // File Allocation Table
+    // ========================================================================
+
+    fn first_fat(&self) -> u8 {
+        self.active_fat.unwrap_or(0)
+    }
+
+    fn fat_start(&self, fat: u8) -> u32 {
+        self.reserved_sectors as u32 + fat as u32 * self.fat_size
+    }
+
+    /// Read the raw (masked) FAT entry of a cluster
+    fn fat_entry(&self, cluster: u32) -> KResult<u32> {
         let fat_offset = cluster * 4;
-        let fat_sector = self.reserved_sectors as u32 + (fat_offset / self.bytes_per_sector as u32);
+        let fat_sector = self.fat_start(self.first_fat()) + (fat_offset / self.bytes_per_sector as u32);
         let offset_in_sector = (fat_offset % self.bytes_per_sector as u32) as usize;
 
         let mut sector_buf = vec![0u8; self.bytes_per_sector as usize];
         self.read_sector(fat_sector, &mut sector_buf)?;
 
-        let entry = unsafe {
-            core::ptr::read_unaligned(sector_buf.as_ptr().add(offset_in_sector) as *const u32)
+        Ok(le32(&sector_buf, offset_in_sector) & FAT32_ENTRY_MASK) // Mask off reserved bits
+    }
+
+    /// Update a FAT entry in every FAT copy (keeping the reserved high bits)
+    fn set_fat_entry(&self, cluster: u32, value: u32) -> KResult<()> {
+        let fat_offset = cluster * 4;
+        let sector_in_fat = fat_offset / self.bytes_per_sector as u32;
+        let offset_in_sector = (fat_offset % self.bytes_per_sector as u32) as usize;
+        let mut sector_buf = vec![0u8; self.bytes_per_sector as usize];
+
+        let fats: Vec<u8> = match self.active_fat {
+            Some(fat) => vec![fat],
+            None => (0..self.num_fats).collect(),
         };
-        let entry = entry & 0x0FFFFFFF; // Mask off reserved bits
+
+        for fat in fats {
+            let sector = self.fat_start(fat) + sector_in_fat;
+            self.read_sector(sector, &mut sector_buf)?;
+            let old = le32(&sector_buf, offset_in_sector);
+            put_le32(&mut sector_buf, offset_in_sector, (old & !FAT32_ENTRY_MASK) | (value & FAT32_ENTRY_MASK));
+            self.write_sector(sector, &sector_buf)?;
+        }
+
+        Ok(())
+    }
+
+    /// Get the next cluster in the chain (from FAT)
+    fn next_cluster(&self, cluster: u32) -> KResult<Option<u32>> {
+        let entry = self.fat_entry(cluster)?;
 
         if entry >= FAT32_CLUSTER_END {
             Ok(None) // End of chain
-        } else if entry == FAT32_CLUSTER_FREE || entry == FAT32_CLUSTER_BAD {
+        } else if entry == FAT32_CLUSTER_FREE || entry == FAT32_CLUSTER_BAD || !self.valid_cluster(entry) {
             Ok(None) // Invalid
         } else {
             Ok(Some(entry))
         }
     }
 
-    /// Read all clusters in a chain
-    fn read_cluster_chain(&self, start_cluster: u32) -> KResult<Vec<u8>> {
-        let cluster_size = self.bytes_per_sector as usize * self.sectors_per_cluster as usize;
-        let mut data = Vec::new();
-        let mut current = start_cluster;
+    /// All clusters of a chain, in order
+    fn cluster_chain(&self, start_cluster: u32) -> KResult<Vec<u32>> {
+        let mut chain = Vec::new();
+        if start_cluster == 0 {
+            return Ok(chain);
+        }
+        if !self.valid_cluster(start_cluster) {
+            return Err(KError::IO);
+        }
 
+        let mut current = start_cluster;
         loop {
-            let offset = data.len();
-            data.resize(offset + cluster_size, 0);
-            self.read_cluster(current, &mut data[offset..])?;
-
+            chain.push(current);
+            // Guard against loops in a corrupted FAT
+            if chain.len() > self.max_cluster as usize {
+                return Err(KError::IO);
+            }
             match self.next_cluster(current)? {
                 Some(next) => current = next,
                 None => break,
             }
         }
 
+        Ok(chain)
+    }
+
+    /// Read all clusters in a chain
+    fn read_cluster_chain(&self, start_cluster: u32) -> KResult<Vec<u8>> {
+        let cluster_size = self.cluster_size();
+        let chain = self.cluster_chain(start_cluster)?;
+        let mut data = vec![0u8; chain.len() * cluster_size];
+
+        for (i, &cluster) in chain.iter().enumerate() {
+            self.read_cluster(cluster, &mut data[i * cluster_size..(i + 1) * cluster_size])?;
+        }
+
         Ok(data)
     }
 
+    /// Allocate a free cluster, append it after `prev` and optionally zero it
+    fn alloc_cluster(&self, prev: Option<u32>, zero: bool) -> KResult<u32> {
+        let (free_count, hint) = {
+            let info = self.fs_info.lock();
+            (info.free_count, info.next_free)
+        };
+        if free_count == 0 {
+            return Err(KError::NoMemory);
+        }
+
+        let per_sector = self.bytes_per_sector as u32 / 4;
+        let total = self.max_cluster - 1;
+        let mut buf = vec![0u8; self.bytes_per_sector as usize];
+        let mut loaded = u32::MAX;
+        let mut found = None;
+
+        for i in 0..total {
+            let cluster = 2 + (hint - 2 + i) % total;
+            let sector = cluster / per_sector;
+            if sector != loaded {
+                self.read_sector(self.fat_start(self.first_fat()) + sector, &mut buf)?;
+                loaded = sector;
+            }
+            if le32(&buf, ((cluster % per_sector) * 4) as usize) & FAT32_ENTRY_MASK == FAT32_CLUSTER_FREE {
+                found = Some(cluster);
+                break;
+            }
+        }
+
+        let cluster = found.ok_or(KError::NoMemory)?;
+        self.set_fat_entry(cluster, FAT32_CLUSTER_EOC)?;
+        if let Some(prev) = prev {
+            self.set_fat_entry(prev, cluster)?;
+        }
+
+        {
+            let mut info = self.fs_info.lock();
+            info.free_count = info.free_count.saturating_sub(1);
+            info.next_free = if cluster >= self.max_cluster { 2 } else { cluster + 1 };
+        }
+
+        if zero {
+            self.write_cluster(cluster, &vec![0u8; self.cluster_size()])?;
+        }
+
+        Ok(cluster)
+    }
+
+    /// Free every cluster of a chain
+    fn free_chain(&self, start_cluster: u32) -> KResult<()> {
+        let chain = self.cluster_chain(start_cluster)?;
+        for &cluster in &chain {
+            self.set_fat_entry(cluster, FAT32_CLUSTER_FREE)?;
+        }
+
+        let mut info = self.fs_info.lock();
+        info.free_count = core::cmp::min(info.free_count + chain.len() as u32, self.max_cluster - 1);
+        if let Some(&first) = chain.first() {
+            if first < info.next_free {
+                info.next_free = first;
+            }
+        }
+        Ok(())
+    }
+
+    /// Make sure a chain has at least `count` clusters; returns the new chain.
+    /// On failure the chain is cut back to its original length.
+    fn extend_chain(&self, mut chain: Vec<u32>, count: usize, zero: impl Fn(usize) -> bool) -> KResult<Vec<u32>> {
+        let original = chain.len();
+        while chain.len() < count {
+            let idx = chain.len();
+            match self.alloc_cluster(chain.last().copied(), zero(idx)) {
+                Ok(cluster) => chain.push(cluster),
+                Err(e) => {
+                    self.shrink_chain(&chain, original)?;
+                    return Err(e);
+                }
+            }
+        }
+        Ok(chain)
+    }
+
+    /// Cut a chain down to `keep` clusters, freeing the rest
+    fn shrink_chain(&self, chain: &[u32], keep: usize) -> KResult<()> {
+        if keep >= chain.len() {
+            return Ok(());
+        }
+        if keep > 0 {
+            self.set_fat_entry(chain[keep - 1], FAT32_CLUSTER_EOC)?;
+        }
+        self.free_chain(chain[keep])
+    }
+
+    // ========================================================================
+    // Directories
+    // ========================================================================
+
     /// Read directory entries from a cluster chain
     fn read_directory(&self, start_cluster: u32) -> KResult<Vec<(String, Fat32DirEntry)>> {
+        Ok(self
+            .read_dir_slots(start_cluster)?
+            .into_iter()
+            .map(|slot| (slot.name, slot.entry))
+            .collect())
+    }
+
+    /// Parse a directory, keeping the position of each entry.
+    /// "." and ".." are skipped.
+    fn read_dir_slots(&self, start_cluster: u32) -> KResult<Vec<DirSlot>> {
         let data = self.read_cluster_chain(start_cluster)?;
         let mut entries = Vec::new();
         let mut lfn_parts: Vec<(u8, [u16; 13])> = Vec::new();
+        let mut lfn_checksum_expected = 0u8;
+        let mut lfn_first = 0u32;
 
-        let entry_size = core::mem::size_of::<Fat32DirEntry>();
+        let entry_size = DIR_ENTRY_SIZE;
         let mut offset = 0;
 
         while offset + entry_size <= data.len() {
-            let entry: Fat32DirEntry = unsafe {
-                core::ptr::read_unaligned(data.as_ptr().add(offset) as *const _)
-            };
+            let entry = Fat32DirEntry::from_bytes(&data[offset..]);
+            let index = (offset / entry_size) as u32;
 
             if entry.is_end() {
                 break;
@@ -411,35 +1094,51 @@ impl Fat32Fs {
 
                 if lfn.is_last() {
                     lfn_parts.clear();
+                    lfn_checksum_expected = lfn.checksum;
+                    lfn_first = index;
+                } else if lfn.checksum != lfn_checksum_expected {
+                    lfn_parts.clear();
+                    offset += entry_size;
+                    continue;
+                }
+                if lfn.is_last() || !lfn_parts.is_empty() {
+                    lfn_parts.push((seq, chars));
                 }
-                lfn_parts.push((seq, chars));
             } else if !entry.is_volume_label() {
-                // Regular entry
-                let name = if !lfn_parts.is_empty() {
+                // Regular entry; the LFN run must directly precede it and match its checksum
+                let short = entry.name;
+                let lfn_valid = !lfn_parts.is_empty()
+                    && lfn_checksum_expected == lfn_checksum(&short)
+                    && lfn_first + lfn_parts.len() as u32 == index;
+
+                let (name, first) = if lfn_valid {
                     // Assemble long filename
                     lfn_parts.sort_by_key(|(seq, _)| *seq);
-                    let mut name = String::new();
-                    for (_, chars) in &lfn_parts {
+                    let mut units = Vec::new();
+                    'outer: for (_, chars) in &lfn_parts {
                         for &c in chars {
                             if c == 0 || c == 0xFFFF {
-                                break;
-                            }
-                            if let Some(ch) = char::from_u32(c as u32) {
-                                name.push(ch);
+                                break 'outer;
                             }
+                            units.push(c);
                         }
                     }
-                    lfn_parts.clear();
-                    name
+                    let name = char::decode_utf16(units.iter().copied())
+                        .map(|r| r.unwrap_or(char::REPLACEMENT_CHARACTER))
+                        .collect();
+                    (name, lfn_first)
                 } else {
                     // Use short name
-                    entry.short_name()
+                    (entry.short_name(), index)
                 };
+                lfn_parts.clear();
 
                 // Skip . and ..
                 if name != "." && name != ".." {
-                    entries.push((name, entry));
+                    entries.push(DirSlot { name, entry, index, first });
                 }
+            } else {
+                lfn_parts.clear();
             }
 
             offset += entry_size;
@@ -448,42 +1147,407 @@ impl Fat32Fs {
         Ok(entries)
     }
 
+    /// Find an entry by name (case-insensitive, long or short name)
+    fn find_slot(&self, dir_cluster: u32, name: &str) -> KResult<Option<DirSlot>> {
+        for slot in self.read_dir_slots(dir_cluster)? {
+            if names_equal(&slot.name, name) || names_equal(&slot.entry.short_name(), name) {
+                return Ok(Some(slot));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Sector and byte offset of directory entry `index`
+    fn dir_entry_pos(&self, dir_cluster: u32, index: u32) -> KResult<(u32, usize)> {
+        let cs = self.cluster_size();
+        let byte = index as usize * DIR_ENTRY_SIZE;
+        let chain = self.cluster_chain(dir_cluster)?;
+        let cluster = *chain.get(byte / cs).ok_or(KError::IO)?;
+        let in_cluster = byte % cs;
+        let bps = self.bytes_per_sector as usize;
+        Ok((self.cluster_to_sector(cluster) + (in_cluster / bps) as u32, in_cluster % bps))
+    }
+
+    fn read_dir_entry(&self, dir_cluster: u32, index: u32) -> KResult<Fat32DirEntry> {
+        let (sector, off) = self.dir_entry_pos(dir_cluster, index)?;
+        let mut buf = vec![0u8; self.bytes_per_sector as usize];
+        self.read_sector(sector, &mut buf)?;
+        Ok(Fat32DirEntry::from_bytes(&buf[off..]))
+    }
+
+    /// Write consecutive raw directory entries starting at `index`
+    fn write_dir_entries(&self, dir_cluster: u32, index: u32, entries: &[[u8; DIR_ENTRY_SIZE]]) -> KResult<()> {
+        let mut buf = vec![0u8; self.bytes_per_sector as usize];
+        let mut loaded: Option<u32> = None;
+
+        for (i, raw) in entries.iter().enumerate() {
+            let (sector, off) = self.dir_entry_pos(dir_cluster, index + i as u32)?;
+            if loaded != Some(sector) {
+                if let Some(prev) = loaded {
+                    self.write_sector(prev, &buf)?;
+                }
+                self.read_sector(sector, &mut buf)?;
+                loaded = Some(sector);
+            }
+            buf[off..off + DIR_ENTRY_SIZE].copy_from_slice(raw);
+        }
+
+        if let Some(sector) = loaded {
+            self.write_sector(sector, &buf)?;
+        }
+        Ok(())
+    }
+
+    /// Mark entries [first, last] as deleted
+    fn delete_dir_entries(&self, dir_cluster: u32, first: u32, last: u32) -> KResult<()> {
+        for index in first..=last {
+            let mut entry = self.read_dir_entry(dir_cluster, index)?.to_bytes();
+            entry[0] = DIR_ENTRY_FREE;
+            self.write_dir_entries(dir_cluster, index, &[entry])?;
+        }
+        Ok(())
+    }
+
+    /// Find `count` consecutive free entries, growing the directory if needed
+    fn find_free_entries(&self, dir_cluster: u32, count: u32) -> KResult<u32> {
+        let data = self.read_cluster_chain(dir_cluster)?;
+        let capacity = (data.len() / DIR_ENTRY_SIZE) as u32;
+        let mut run_start = 0;
+        let mut run_len = 0;
+        let mut end = None;
+
+        for index in 0..capacity {
+            let first_byte = data[index as usize * DIR_ENTRY_SIZE];
+            if first_byte == DIR_ENTRY_END {
+                // Everything from the end marker on is free
+                end = Some(index);
+                break;
+            }
+            if first_byte == DIR_ENTRY_FREE {
+                if run_len == 0 {
+                    run_start = index;
+                }
+                run_len += 1;
+                if run_len == count {
+                    return Ok(run_start);
+                }
+            } else {
+                run_len = 0;
+            }
+        }
+
+        // A trailing run of deleted entries joins the free tail
+        let tail = end.unwrap_or(capacity);
+        let start = if run_len > 0 { run_start } else { tail };
+        if start + count <= capacity {
+            return Ok(start);
+        }
+
+        // No room: append clusters (zeroed, so they read as end-of-directory)
+        let needed_entries = start + count;
+        if needed_entries > DIR_MAX_ENTRIES {
+            return Err(KError::NoMemory);
+        }
+        let per_cluster = (self.cluster_size() / DIR_ENTRY_SIZE) as u32;
+        let needed_clusters = ((needed_entries + per_cluster - 1) / per_cluster) as usize;
+        let chain = self.cluster_chain(dir_cluster)?;
+        self.extend_chain(chain, needed_clusters, |_| true)?;
+        Ok(start)
+    }
+
+    /// Choose the on-disk short name for a new entry in `dir_cluster`
+    fn make_short_name(&self, dir_cluster: u32, name: &str) -> KResult<ShortName> {
+        if let Some(short) = exact_short_name(name) {
+            return Ok(short);
+        }
+
+        let existing: Vec<[u8; 11]> = self
+            .read_dir_slots(dir_cluster)?
+            .iter()
+            .map(|slot| slot.entry.name)
+            .collect();
+
+        for n in 1..1_000_000 {
+            let candidate = generated_short_name(name, n);
+            if !existing.contains(&candidate) {
+                return Ok(ShortName { name: candidate, nt_flags: 0, needs_lfn: true });
+            }
+        }
+
+        Err(KError::NoMemory)
+    }
+
+    /// Create the LFN + short entries for `name` in a directory.
+    /// Returns the index of the short entry.
+    fn add_dir_entry(&self, dir_cluster: u32, name: &str, mut entry: Fat32DirEntry) -> KResult<u32> {
+        let units = validate_long_name(name)?;
+        if self.find_slot(dir_cluster, name)?.is_some() {
+            return Err(KError::AlreadyExists);
+        }
+
+        let short = self.make_short_name(dir_cluster, name)?;
+        entry.name = short.name;
+        entry.nt_reserved = short.nt_flags;
+
+        let mut raw: Vec<[u8; DIR_ENTRY_SIZE]> = Vec::new();
+        if short.needs_lfn {
+            let checksum = lfn_checksum(&short.name);
+            let count = (units.len() + LFN_CHARS_PER_ENTRY - 1) / LFN_CHARS_PER_ENTRY;
+            // Stored in reverse order: the last part comes first on disk
+            for seq in (1..=count).rev() {
+                let mut chars = [0xFFFFu16; 13];
+                let start = (seq - 1) * LFN_CHARS_PER_ENTRY;
+                for i in 0..LFN_CHARS_PER_ENTRY {
+                    match units.get(start + i) {
+                        Some(&c) => chars[i] = c,
+                        None => {
+                            chars[i] = 0;
+                            break;
+                        }
+                    }
+                }
+                let order = seq as u8 | if seq == count { LFN_LAST_ENTRY } else { 0 };
+                raw.push(Fat32LfnEntry::to_bytes(order, checksum, &chars));
+            }
+        }
+        raw.push(entry.to_bytes());
+
+        let start = self.find_free_entries(dir_cluster, raw.len() as u32)?;
+        self.write_dir_entries(dir_cluster, start, &raw)?;
+        Ok(start + raw.len() as u32 - 1)
+    }
+
+    /// Is `ancestor` on the ".." path from `dir` up to the root?
+    fn is_ancestor(&self, ancestor: u32, mut dir: u32) -> KResult<bool> {
+        for _ in 0..4096 {
+            if dir == ancestor {
+                return Ok(true);
+            }
+            if dir == self.root_cluster || dir == 0 {
+                return Ok(false);
+            }
+            let dotdot = self.read_dir_entry(dir, 1)?;
+            if &dotdot.name != b"..         " {
+                return Ok(false);
+            }
+            dir = match dotdot.first_cluster() {
+                0 => self.root_cluster,
+                c => c,
+            };
+        }
+        Err(KError::IO)
+    }
+
+    // ========================================================================
+    // Nodes
+    // ========================================================================
+
+    /// Shared state for the entry at `loc`, creating it from `entry` if needed
+    fn get_node(&self, loc: (u32, u32), entry: &Fat32DirEntry) -> Arc<RwLock<FatNode>> {
+        let mut nodes = self.nodes.lock();
+        if let Some(node) = nodes.get(&loc).and_then(|w| w.upgrade()) {
+            return node;
+        }
+
+        let node = Arc::new(RwLock::new(FatNode {
+            loc: Some(loc),
+            first_cluster: entry.first_cluster(),
+            size: entry.file_size,
+            attr: entry.attr,
+            mtime: dos_to_unix(entry.write_date, entry.write_time),
+            atime: dos_to_unix(entry.access_date, 0),
+            crtime: dos_to_unix(entry.create_date, entry.create_time),
+            deleted: false,
+        }));
+
+        if nodes.len() >= 256 {
+            nodes.retain(|_, w| w.strong_count() > 0);
+        }
+        nodes.insert(loc, Arc::downgrade(&node));
+        node
+    }
+
+    /// Write a node's size, first cluster, attributes and times to its entry
+    fn store_node(&self, node: &FatNode) -> KResult<()> {
+        let (dir_cluster, index) = match node.loc {
+            Some(loc) => loc,
+            None => return Ok(()),
+        };
+
+        let mut entry = self.read_dir_entry(dir_cluster, index)?;
+        entry.set_first_cluster(node.first_cluster);
+        entry.file_size = if node.attr & ATTR_DIRECTORY != 0 { 0 } else { node.size };
+        entry.attr = node.attr;
+        let (date, time) = unix_to_dos(node.mtime);
+        entry.write_date = date;
+        entry.write_time = time;
+        entry.access_date = unix_to_dos(node.atime).0;
+        self.write_dir_entries(dir_cluster, index, &[entry.to_bytes()])
+    }
+
+    /// A fresh short entry for a new file or directory
+    fn new_entry(attr: u8, first_cluster: u32) -> Fat32DirEntry {
+        let (date, time) = unix_to_dos(now_secs());
+        let mut entry = Fat32DirEntry {
+            name: [b' '; 11],
+            attr,
+            nt_reserved: 0,
+            create_time_tenths: 0,
+            create_time: time,
+            create_date: date,
+            access_date: date,
+            first_cluster_hi: 0,
+            write_time: time,
+            write_date: date,
+            first_cluster_lo: 0,
+            file_size: 0,
+        };
+        entry.set_first_cluster(first_cluster);
+        entry
+    }
+
+    /// Zero bytes [from, to) of a file inside its existing clusters
+    fn zero_range(&self, chain: &[u32], from: usize, to: usize) -> KResult<()> {
+        let cs = self.cluster_size();
+        let mut pos = from;
+        while pos < to {
+            let idx = pos / cs;
+            let Some(&cluster) = chain.get(idx) else { break };
+            let in_cluster = pos % cs;
+            let chunk = core::cmp::min(to - pos, cs - in_cluster);
+            self.write_cluster_range(cluster, in_cluster, &vec![0u8; chunk])?;
+            pos += chunk;
+        }
+        Ok(())
+    }
+
     /// Return root inode
     pub fn root(self: &Arc<Self>) -> Inode {
         Inode(Arc::new(Fat32InodeWrapper {
             fs: Arc::clone(self),
-            cluster: self.root_cluster,
-            size: 0, // Directory size is dynamic
+            node: Arc::clone(&self.root_node),
             is_dir: true,
             parent: None,
         }))
     }
 }
 
+fn names_equal(a: &str, b: &str) -> bool {
+    if a.is_ascii() && b.is_ascii() {
+        return a.eq_ignore_ascii_case(b);
+    }
+    a.chars().flat_map(char::to_uppercase).eq(b.chars().flat_map(char::to_uppercase))
+}
+
+fn le32(buf: &[u8], off: usize) -> u32 {
+    u32::from_le_bytes([buf[off], buf[off + 1], buf[off + 2], buf[off + 3]])
+}
+
+fn put_le32(buf: &mut [u8], off: usize, v: u32) {
+    buf[off..off + 4].copy_from_slice(&v.to_le_bytes());
+}
+
 // ============================================================================
 // InodeOps implementation
 // ============================================================================
 
 struct Fat32InodeWrapper {
     fs: Arc<Fat32Fs>,
-    cluster: u32,
-    size: u32,
+    node: Arc<RwLock<FatNode>>,
     is_dir: bool,
     parent: Option<Inode>,
 }
 
+impl Fat32InodeWrapper {
+    fn dir_cluster(&self) -> KResult<u32> {
+        if !self.is_dir {
+            return Err(KError::NotADirectory);
+        }
+        let node = self.node.read();
+        if node.deleted {
+            return Err(KError::NotFound);
+        }
+        Ok(node.first_cluster)
+    }
+
+    fn child(&self, node: Arc<RwLock<FatNode>>, is_dir: bool) -> Inode {
+        Inode(Arc::new(Fat32InodeWrapper {
+            fs: Arc::clone(&self.fs),
+            node,
+            is_dir,
+            parent: Some(Inode(Arc::new(Fat32InodeWrapper {
+                fs: Arc::clone(&self.fs),
+                node: Arc::clone(&self.node),
+                is_dir: true,
+                parent: self.parent.clone(),
+            }))),
+        }))
+    }
+
+    /// Remove a directory entry and free its clusters
+    fn remove_entry(&self, slot: &DirSlot, dir_cluster: u32) -> KResult<()> {
+        self.fs.delete_dir_entries(dir_cluster, slot.first, slot.index)?;
+        self.fs.free_chain(slot.entry.first_cluster())?;
+
+        // Open handles see the file as gone
+        let loc = (dir_cluster, slot.index);
+        if let Some(node) = self.fs.nodes.lock().remove(&loc).and_then(|w| w.upgrade()) {
+            let mut node = node.write();
+            node.loc = None;
+            node.deleted = true;
+            node.first_cluster = 0;
+            node.size = 0;
+        }
+        Ok(())
+    }
+
+    fn touch_dir(&self) -> KResult<()> {
+        let mut node = self.node.write();
+        node.mtime = now_secs();
+        self.fs.store_node(&node)
+    }
+}
+
 impl InodeOps for Fat32InodeWrapper {
     fn metadata(&self) -> Metadata {
-        Metadata::simple(
+        let node = self.node.read();
+        let mut mode = if self.is_dir { 0o755 } else { 0o644 };
+        if node.attr & ATTR_READ_ONLY != 0 {
+            mode &= !0o222;
+        }
+
+        let mut meta = Metadata::simple(
             Uid(0),
             Gid(0),
-            Mode::from_octal(if self.is_dir { 0o755 } else { 0o644 }),
+            Mode::from_octal(mode),
             if self.is_dir { InodeKind::Dir } else { InodeKind::File },
-        )
+        );
+        meta.ino = match node.loc {
+            Some((dir, index)) => (dir as u64) << 32 | index as u64,
+            None => self.fs.root_cluster as u64,
+        };
+        meta.atime = Timespec { secs: node.atime, nsecs: 0 };
+        meta.mtime = Timespec { secs: node.mtime, nsecs: 0 };
+        meta.ctime = Timespec { secs: node.mtime, nsecs: 0 };
+        meta
     }
 
-    fn set_metadata(&self, _meta: Metadata) {
-        // Read-only filesystem
+    fn set_metadata(&self, meta: Metadata) {
+        // Only the read-only attribute and the times can be stored
+        let _ = self.fs.modify(|| {
+            let mut node = self.node.write();
+            if node.deleted || node.loc.is_none() {
+                return Ok(());
+            }
+            if meta.mode.to_octal() & 0o200 == 0 {
+                node.attr |= ATTR_READ_ONLY;
+            } else {
+                node.attr &= !ATTR_READ_ONLY;
+            }
+            node.mtime = meta.mtime.secs;
+            node.atime = meta.atime.secs;
+            self.fs.store_node(&node)
+        });
     }
 
     fn parent(&self) -> Option<Inode> {
@@ -491,47 +1555,71 @@ impl InodeOps for Fat32InodeWrapper {
     }
 
     fn lookup(&self, name: &str) -> KResult<Inode> {
-        if !self.is_dir {
-            return Err(KError::NotFound);
+        let dir_cluster = self.dir_cluster().map_err(|_| KError::NotFound)?;
+
+        match self.fs.find_slot(dir_cluster, name)? {
+            Some(slot) => {
+                let node = self.fs.get_node((dir_cluster, slot.index), &slot.entry);
+                Ok(self.child(node, slot.entry.is_directory()))
+            }
+            None => Err(KError::NotFound),
         }
+    }
 
-        let entries = self.fs.read_directory(self.cluster)?;
+    fn create(&self, name: &str, kind: InodeKind, _meta: Metadata) -> KResult<Inode> {
+        let is_dir = match kind {
+            InodeKind::File => false,
+            InodeKind::Dir => true,
+            _ => return Err(KError::NotSupported),
+        };
 
-        for (entry_name, entry) in entries {
-            if entry_name.eq_ignore_ascii_case(name) {
-                let child = Arc::new(Fat32InodeWrapper {
-                    fs: Arc::clone(&self.fs),
-                    cluster: entry.first_cluster(),
-                    size: unsafe {
-                        core::ptr::read_unaligned(core::ptr::addr_of!(entry.file_size))
-                    },
-                    is_dir: entry.is_directory(),
-                    parent: Some(Inode(Arc::new(Fat32InodeWrapper {
-                        fs: Arc::clone(&self.fs),
-                        cluster: self.cluster,
-                        size: self.size,
-                        is_dir: true,
-                        parent: self.parent.clone(),
-                    }))),
-                });
-                return Ok(Inode(child));
+        let node = self.fs.modify(|| {
+            let dir_cluster = self.dir_cluster()?;
+            validate_long_name(name)?;
+            if self.fs.find_slot(dir_cluster, name)?.is_some() {
+                return Err(KError::AlreadyExists);
             }
-        }
 
-        Err(KError::NotFound)
-    }
+            let entry = if is_dir {
+                // New directory: one zeroed cluster holding "." and ".."
+                let cluster = self.fs.alloc_cluster(None, true)?;
+                let mut dot = Fat32Fs::new_entry(ATTR_DIRECTORY, cluster);
+                dot.name = *b".          ";
+                let parent = if dir_cluster == self.fs.root_cluster { 0 } else { dir_cluster };
+                let mut dotdot = Fat32Fs::new_entry(ATTR_DIRECTORY, parent);
+                dotdot.name = *b"..         ";
+                let result = self.fs.write_dir_entries(cluster, 0, &[dot.to_bytes(), dotdot.to_bytes()]);
+                if let Err(e) = result {
+                    let _ = self.fs.free_chain(cluster);
+                    return Err(e);
+                }
+                Fat32Fs::new_entry(ATTR_DIRECTORY, cluster)
+            } else {
+                Fat32Fs::new_entry(ATTR_ARCHIVE, 0)
+            };
+
+            let index = match self.fs.add_dir_entry(dir_cluster, name, entry) {
+                Ok(i) => i,
+                Err(e) => {
+                    if is_dir {
+                        let _ = self.fs.free_chain(entry.first_cluster());
+                    }
+                    return Err(e);
+                }
+            };
+            self.touch_dir()?;
+
+            let stored = self.fs.read_dir_entry(dir_cluster, index)?;
+            Ok(self.fs.get_node((dir_cluster, index), &stored))
+        })?;
 
-    fn create(&self, _name: &str, _kind: InodeKind, _meta: Metadata) -> KResult<Inode> {
-        // Read-only filesystem
-        Err(KError::NotSupported)
+        Ok(self.child(node, is_dir))
     }
 
     fn readdir(&self) -> KResult<Vec<DirEntry>> {
-        if !self.is_dir {
-            return Err(KError::Invalid);
-        }
+        let dir_cluster = self.dir_cluster().map_err(|_| KError::Invalid)?;
 
-        let entries = self.fs.read_directory(self.cluster)?;
+        let entries = self.fs.read_directory(dir_cluster)?;
         let mut result = Vec::new();
 
         for (name, entry) in entries {
@@ -553,43 +1641,237 @@ impl InodeOps for Fat32InodeWrapper {
             return Err(KError::Invalid);
         }
 
-        let file_size = self.size as usize;
+        let (first_cluster, file_size) = {
+            let node = self.node.read();
+            (node.first_cluster, node.size as usize)
+        };
         if offset >= file_size {
             return Ok(0);
         }
 
         let to_read = core::cmp::min(out.len(), file_size - offset);
-
-        // Read cluster chain
-        let data = self.fs.read_cluster_chain(self.cluster)?;
-
-        if offset + to_read > data.len() {
-            return Err(KError::IO);
+        let cs = self.fs.cluster_size();
+        let chain = self.fs.cluster_chain(first_cluster)?;
+
+        // Only read the clusters that cover the requested range
+        let mut cluster_buf = vec![0u8; cs];
+        let mut read = 0;
+        while read < to_read {
+            let pos = offset + read;
+            let cluster = *chain.get(pos / cs).ok_or(KError::IO)?;
+            let in_cluster = pos % cs;
+            let chunk = core::cmp::min(to_read - read, cs - in_cluster);
+            self.fs.read_cluster(cluster, &mut cluster_buf)?;
+            out[read..read + chunk].copy_from_slice(&cluster_buf[in_cluster..in_cluster + chunk]);
+            read += chunk;
         }
 
-        out[..to_read].copy_from_slice(&data[offset..offset + to_read]);
         Ok(to_read)
     }
 
-    fn write_at(&self, _offset: usize, _data: &[u8]) -> KResult<usize> {
-        // Read-only filesystem
-        Err(KError::NotSupported)
+    fn write_at(&self, offset: usize, data: &[u8]) -> KResult<usize> {
+        if self.is_dir {
+            return Err(KError::Invalid);
+        }
+        if data.is_empty() {
+            return Ok(0);
+        }
+
+        let end = offset.checked_add(data.len()).ok_or(KError::Invalid)?;
+        if end > u32::MAX as usize {
+            // FAT32 files are limited to 4 GiB - 1
+            return Err(KError::NoMemory);
+        }
+
+        self.fs.modify(|| {
+            let mut node = self.node.write();
+            if node.deleted {
+                return Err(KError::NotFound);
+            }
+            if node.attr & ATTR_READ_ONLY != 0 {
+                return Err(KError::PermissionDenied);
+            }
+
+            let cs = self.fs.cluster_size();
+            let old_size = node.size as usize;
+            let chain = self.fs.cluster_chain(node.first_cluster)?;
+            let old_clusters = chain.len();
+            let needed = (end + cs - 1) / cs;
+
+            // New clusters only need zeroing where the write leaves a gap
+            let chain = self.fs.extend_chain(chain, needed, |idx| {
+                let start = idx * cs;
+                !(offset <= start && end >= start + cs)
+            })?;
+            if node.first_cluster == 0 {
+                node.first_cluster = chain[0];
+            }
+
+            // Stale bytes past the old end of file must read back as zeros
+            if offset > old_size {
+                let existing_end = core::cmp::min(offset, old_clusters * cs);
+                self.fs.zero_range(&chain, old_size, existing_end)?;
+            }
+
+            let mut written = 0;
+            while written < data.len() {
+                let pos = offset + written;
+                let in_cluster = pos % cs;
+                let chunk = core::cmp::min(data.len() - written, cs - in_cluster);
+                self.fs.write_cluster_range(chain[pos / cs], in_cluster, &data[written..written + chunk])?;
+                written += chunk;
+            }
+
+            if end > old_size {
+                node.size = end as u32;
+            }
+            node.attr |= ATTR_ARCHIVE;
+            node.mtime = now_secs();
+            self.fs.store_node(&node)?;
+            Ok(written)
+        })
     }
 
-    fn truncate(&self, _size: usize) -> KResult<()> {
-        Err(KError::NotSupported)
+    fn truncate(&self, size: usize) -> KResult<()> {
+        if self.is_dir {
+            return Err(KError::Invalid);
+        }
+        if size > u32::MAX as usize {
+            return Err(KError::NoMemory);
+        }
+
+        self.fs.modify(|| {
+            let mut node = self.node.write();
+            if node.deleted {
+                return Err(KError::NotFound);
+            }
+
+            let cs = self.fs.cluster_size();
+            let old_size = node.size as usize;
+            let chain = self.fs.cluster_chain(node.first_cluster)?;
+            let needed = (size + cs - 1) / cs;
+
+            if needed < chain.len() {
+                self.fs.shrink_chain(&chain, needed)?;
+                if needed == 0 {
+                    node.first_cluster = 0;
+                }
+            } else {
+                let old_clusters = chain.len();
+                let chain = self.fs.extend_chain(chain, needed, |_| true)?;
+                if node.first_cluster == 0 {
+                    node.first_cluster = chain.first().copied().unwrap_or(0);
+                }
+                if size > old_size {
+                    self.fs.zero_range(&chain, old_size, core::cmp::min(size, old_clusters * cs))?;
+                }
+            }
+
+            node.size = size as u32;
+            node.attr |= ATTR_ARCHIVE;
+            node.mtime = now_secs();
+            self.fs.store_node(&node)
+        })
     }
 
     fn size(&self) -> KResult<usize> {
-        Ok(self.size as usize)
+        Ok(self.node.read().size as usize)
+    }
+
+    fn unlink(&self, name: &str) -> KResult<()> {
+        self.fs.modify(|| {
+            let dir_cluster = self.dir_cluster()?;
+            let slot = self.fs.find_slot(dir_cluster, name)?.ok_or(KError::NotFound)?;
+            if slot.entry.is_directory() {
+                return Err(KError::Invalid);
+            }
+            self.remove_entry(&slot, dir_cluster)?;
+            self.touch_dir()
+        })
+    }
+
+    fn rmdir(&self, name: &str) -> KResult<()> {
+        self.fs.modify(|| {
+            let dir_cluster = self.dir_cluster()?;
+            let slot = self.fs.find_slot(dir_cluster, name)?.ok_or(KError::NotFound)?;
+            if !slot.entry.is_directory() {
+                return Err(KError::NotADirectory);
+            }
+            if !self.fs.read_dir_slots(slot.entry.first_cluster())?.is_empty() {
+                return Err(KError::NotEmpty);
+            }
+            self.remove_entry(&slot, dir_cluster)?;
+            self.touch_dir()
+        })
+    }
+
+    fn rename_to(&self, old_name: &str, new_parent: &Inode, new_name: &str) -> KResult<()> {
+        let target = vfs::own_inode(new_parent, &self.fs, |wrapper: &Fat32InodeWrapper| &wrapper.fs)?;
+
+        self.fs.modify(|| {
+            let old_dir = self.dir_cluster()?;
+            let new_dir = target.dir_cluster()?;
+            validate_long_name(new_name)?;
+
+            let slot = self.fs.find_slot(old_dir, old_name)?.ok_or(KError::NotFound)?;
+            let is_dir = slot.entry.is_directory();
+
+            if let Some(existing) = self.fs.find_slot(new_dir, new_name)? {
+                // Only a change of case of the same entry is allowed
+                if !(old_dir == new_dir && existing.index == slot.index) {
+                    return Err(KError::AlreadyExists);
+                }
+            }
+            if is_dir && self.fs.is_ancestor(slot.entry.first_cluster(), new_dir)? {
+                return Err(KError::Invalid);
+            }
+
+            // Drop the old entries first so a same-directory rename can reuse them
+            let entry = slot.entry;
+            self.fs.delete_dir_entries(old_dir, slot.first, slot.index)?;
+            let index = match self.fs.add_dir_entry(new_dir, new_name, entry) {
+                Ok(i) => i,
+                Err(e) => {
+                    // Put the old name back
+                    let _ = self.fs.add_dir_entry(old_dir, &slot.name, entry);
+                    return Err(e);
+                }
+            };
+
+            // Keep open handles pointing at the new location
+            let mut nodes = self.fs.nodes.lock();
+            if let Some(weak) = nodes.remove(&(old_dir, slot.index)) {
+                if let Some(node) = weak.upgrade() {
+                    node.write().loc = Some((new_dir, index));
+                    nodes.insert((new_dir, index), weak);
+                }
+            }
+            drop(nodes);
+
+            if is_dir && old_dir != new_dir {
+                let child = slot.entry.first_cluster();
+                let mut dotdot = self.fs.read_dir_entry(child, 1)?;
+                if &dotdot.name == b"..         " {
+                    let parent = if new_dir == self.fs.root_cluster { 0 } else { new_dir };
+                    dotdot.set_first_cluster(parent);
+                    self.fs.write_dir_entries(child, 1, &[dotdot.to_bytes()])?;
+                }
+            }
+
+            self.touch_dir()?;
+            if old_dir != new_dir {
+                target.touch_dir()?;
+            }
+            Ok(())
+        })
     }
 
-    fn unlink(&self, _name: &str) -> KResult<()> {
-        Err(KError::NotSupported)
+    fn as_any(&self) -> Option<&dyn Any> {
+        Some(self)
     }
 
-    fn rmdir(&self, _name: &str) -> KResult<()> {
-        Err(KError::NotSupported)
+    fn statfs(&self) -> KResult<FsStats> {
+        Ok(self.fs.stats())
     }
 }
 
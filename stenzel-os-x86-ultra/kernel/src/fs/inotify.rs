@@ -0,0 +1,550 @@
+//! inotify - File change notification
+//!
+//! Watches are keyed by the normalized path of the watched file. The Vfs
+//! reports every change by path (create, unlink, rename, chmod), as do the
+//! fd paths for writes and closes, so events look the same whatever
+//! filesystem is behind the path; several of them have no stable inode
+//! numbers to key on. Renames carry the watches along with the tree that
+//! moved.
+//!
+//! Paths are the ones seen through the mount table of the task making the
+//! change, so a watch added in one mount namespace also sees changes made
+//! through the same path in another.
+
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::string::{String, ToString};
+use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicI32, AtomicU32, AtomicU64, AtomicUsize, Ordering};
+
+use crate::sync::IrqSafeMutex;
+use crate::syscall::errno;
+use crate::util::KError;
+
+use super::vfs::{normalize, InodeKind};
+
+/// inotify_init1 flags
+pub const IN_CLOEXEC: i32 = 0o2000000;
+pub const IN_NONBLOCK: i32 = 0o0004000;
+
+/// Events
+pub const IN_ACCESS: u32 = 0x0000_0001;
+pub const IN_MODIFY: u32 = 0x0000_0002;
+pub const IN_ATTRIB: u32 = 0x0000_0004;
+pub const IN_CLOSE_WRITE: u32 = 0x0000_0008;
+pub const IN_CLOSE_NOWRITE: u32 = 0x0000_0010;
+pub const IN_OPEN: u32 = 0x0000_0020;
+pub const IN_MOVED_FROM: u32 = 0x0000_0040;
+pub const IN_MOVED_TO: u32 = 0x0000_0080;
+pub const IN_CREATE: u32 = 0x0000_0100;
+pub const IN_DELETE: u32 = 0x0000_0200;
+pub const IN_DELETE_SELF: u32 = 0x0000_0400;
+pub const IN_MOVE_SELF: u32 = 0x0000_0800;
+pub const IN_ALL_EVENTS: u32 = 0x0000_0fff;
+
+pub const IN_CLOSE: u32 = IN_CLOSE_WRITE | IN_CLOSE_NOWRITE;
+pub const IN_MOVE: u32 = IN_MOVED_FROM | IN_MOVED_TO;
+
+/// Reported without being asked for
+pub const IN_UNMOUNT: u32 = 0x0000_2000;
+pub const IN_Q_OVERFLOW: u32 = 0x0000_4000;
+pub const IN_IGNORED: u32 = 0x0000_8000;
+
+/// inotify_add_watch flags
+pub const IN_ONLYDIR: u32 = 0x0100_0000;
+pub const IN_DONT_FOLLOW: u32 = 0x0200_0000;
+pub const IN_EXCL_UNLINK: u32 = 0x0400_0000;
+pub const IN_MASK_CREATE: u32 = 0x1000_0000;
+pub const IN_MASK_ADD: u32 = 0x2000_0000;
+pub const IN_ISDIR: u32 = 0x4000_0000;
+pub const IN_ONESHOT: u32 = 0x8000_0000;
+
+/// Events that happen to a directory entry; only the parent hears of them
+const ENTRY_EVENTS: u32 = IN_CREATE | IN_DELETE | IN_MOVE;
+/// Events that only the watched object itself hears of
+const SELF_EVENTS: u32 = IN_DELETE_SELF | IN_MOVE_SELF;
+
+/// Defaults of fs.inotify.max_queued_events and max_user_watches (the
+/// latter counted system-wide here)
+pub const MAX_QUEUED_EVENTS: usize = 16384;
+pub const MAX_WATCHES: usize = 8192;
+
+/// Size of struct inotify_event without the name
+pub const EVENT_SIZE: usize = 16;
+
+/// One queued event. `name` is set when the event is reported to a
+/// directory watch about one of its entries.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Event {
+    pub wd: i32,
+    pub mask: u32,
+    pub cookie: u32,
+    pub name: String,
+}
+
+impl Event {
+    /// Bytes the name takes in struct inotify_event: NUL terminated and
+    /// padded so the next event stays aligned
+    fn name_len(&self) -> usize {
+        if self.name.is_empty() {
+            0
+        } else {
+            (self.name.len() + 1).next_multiple_of(EVENT_SIZE)
+        }
+    }
+
+    /// Size of the event as read(2) returns it
+    pub fn size(&self) -> usize {
+        EVENT_SIZE + self.name_len()
+    }
+
+    fn write_to(&self, out: &mut [u8]) {
+        let len = self.name_len();
+        out[0..4].copy_from_slice(&self.wd.to_ne_bytes());
+        out[4..8].copy_from_slice(&self.mask.to_ne_bytes());
+        out[8..12].copy_from_slice(&self.cookie.to_ne_bytes());
+        out[12..16].copy_from_slice(&(len as u32).to_ne_bytes());
+        let name = &mut out[EVENT_SIZE..EVENT_SIZE + len];
+        name.fill(0);
+        name[..self.name.len()].copy_from_slice(self.name.as_bytes());
+    }
+}
+
+/// A watch on one path
+struct Watch {
+    owner: u64,
+    wd: i32,
+    mask: u32,
+    inotify: Weak<Inotify>,
+}
+
+/// Watches by path
+static WATCHES: IrqSafeMutex<BTreeMap<String, Vec<Watch>>> = IrqSafeMutex::new(BTreeMap::new());
+/// Number of watches in WATCHES; lets the Vfs skip all the work while
+/// nobody is watching
+static NR_WATCHES: AtomicUsize = AtomicUsize::new(0);
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+static NEXT_COOKIE: AtomicU32 = AtomicU32::new(1);
+
+/// An inotify instance
+pub struct Inotify {
+    id: u64,
+    nonblock: bool,
+    next_wd: AtomicI32,
+    events: IrqSafeMutex<VecDeque<Event>>,
+}
+
+impl Inotify {
+    pub fn new(flags: i32) -> Arc<Self> {
+        Arc::new(Self {
+            id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+            nonblock: flags & IN_NONBLOCK != 0,
+            next_wd: AtomicI32::new(1),
+            events: IrqSafeMutex::new(VecDeque::new()),
+        })
+    }
+
+    pub fn is_nonblock(&self) -> bool {
+        self.nonblock
+    }
+
+    /// Watches `path` (already resolved by the caller) for the events in
+    /// `mask`. Watching the same path again updates the watch.
+    pub fn add_watch(self: &Arc<Self>, path: &str, mask: u32) -> Result<i32, i64> {
+        if mask & IN_ALL_EVENTS == 0 {
+            return Err(errno::EINVAL);
+        }
+        if mask & IN_MASK_ADD != 0 && mask & IN_MASK_CREATE != 0 {
+            return Err(errno::EINVAL);
+        }
+        let kept = mask & (IN_ALL_EVENTS | IN_ONESHOT | IN_EXCL_UNLINK);
+
+        let key = normalize(path);
+        let mut all = WATCHES.lock();
+        let list = all.entry(key.clone()).or_default();
+        if let Some(watch) = list.iter_mut().find(|w| w.owner == self.id) {
+            if mask & IN_MASK_CREATE != 0 {
+                return Err(errno::EEXIST);
+            }
+            watch.mask = if mask & IN_MASK_ADD != 0 { watch.mask | kept } else { kept };
+            return Ok(watch.wd);
+        }
+        if NR_WATCHES.load(Ordering::Relaxed) >= MAX_WATCHES {
+            if list.is_empty() {
+                all.remove(&key);
+            }
+            return Err(errno::ENOSPC);
+        }
+
+        let wd = self.next_wd.fetch_add(1, Ordering::Relaxed);
+        list.push(Watch {
+            owner: self.id,
+            wd,
+            mask: kept,
+            inotify: Arc::downgrade(self),
+        });
+        NR_WATCHES.fetch_add(1, Ordering::Relaxed);
+        Ok(wd)
+    }
+
+    /// Removes watch `wd`; it reports IN_IGNORED.
+    pub fn rm_watch(&self, wd: i32) -> Result<(), i64> {
+        let found = {
+            let mut all = WATCHES.lock();
+            let mut found = false;
+            all.retain(|_, list| {
+                let before = list.len();
+                list.retain(|w| !(w.owner == self.id && w.wd == wd));
+                found |= list.len() != before;
+                !list.is_empty()
+            });
+            found
+        };
+        if !found {
+            return Err(errno::EINVAL);
+        }
+        NR_WATCHES.fetch_sub(1, Ordering::Relaxed);
+        self.queue(Event { wd, mask: IN_IGNORED, cookie: 0, name: String::new() });
+        Ok(())
+    }
+
+    /// Queues an event, merging it with an identical one at the tail. A
+    /// full queue ends with a single IN_Q_OVERFLOW.
+    fn queue(&self, event: Event) {
+        {
+            let mut events = self.events.lock();
+            if events.back() == Some(&event) {
+                return;
+            }
+            if events.len() >= MAX_QUEUED_EVENTS {
+                if events.back().is_some_and(|e| e.mask == IN_Q_OVERFLOW) {
+                    return;
+                }
+                events.push_back(Event { wd: -1, mask: IN_Q_OVERFLOW, cookie: 0, name: String::new() });
+            } else {
+                events.push_back(event);
+            }
+        }
+        crate::syscall::epoll::wake(self);
+    }
+
+    pub fn has_events(&self) -> bool {
+        !self.events.lock().is_empty()
+    }
+
+    /// Bytes a read(2) of the whole queue would return (FIONREAD)
+    pub fn pending_bytes(&self) -> usize {
+        self.events.lock().iter().map(Event::size).sum()
+    }
+
+    /// Takes every queued event, for kernel users of an instance
+    pub fn take_events(&self) -> Vec<Event> {
+        self.events.lock().drain(..).collect()
+    }
+
+    /// Copies as many whole events as fit into `buf` (blocking unless
+    /// nonblock). EINVAL if not even the first one fits.
+    pub fn read(&self, buf: &mut [u8]) -> Result<usize, i64> {
+        loop {
+            {
+                let mut events = self.events.lock();
+                if let Some(first) = events.front() {
+                    if first.size() > buf.len() {
+                        return Err(errno::EINVAL);
+                    }
+                    let mut done = 0;
+                    while let Some(event) = events.front() {
+                        let size = event.size();
+                        if done + size > buf.len() {
+                            break;
+                        }
+                        event.write_to(&mut buf[done..done + size]);
+                        done += size;
+                        events.pop_front();
+                    }
+                    return Ok(done);
+                }
+            }
+            if self.nonblock {
+                return Err(errno::EAGAIN);
+            }
+            if crate::sched::has_pending_signals() {
+                return Err(errno::EINTR);
+            }
+            crate::task::yield_now();
+        }
+    }
+}
+
+impl Drop for Inotify {
+    fn drop(&mut self) {
+        let mut all = WATCHES.lock();
+        all.retain(|_, list| {
+            let before = list.len();
+            list.retain(|w| w.owner != self.id);
+            NR_WATCHES.fetch_sub(before - list.len(), Ordering::Relaxed);
+            !list.is_empty()
+        });
+    }
+}
+
+/// An event on its way to one instance
+struct Delivery {
+    inotify: Arc<Inotify>,
+    event: Event,
+    /// The watch was one-shot (or the watched path is gone) and has been
+    /// removed; IN_IGNORED follows the event
+    ignored: bool,
+}
+
+fn parent_of(path: &str) -> Option<(&str, &str)> {
+    let idx = path.rfind('/')?;
+    let name = &path[idx + 1..];
+    if name.is_empty() {
+        return None;
+    }
+    Some((if idx == 0 { "/" } else { &path[..idx] }, name))
+}
+
+/// Takes the watches on `key` interested in `mask` (all of them with
+/// `remove`, which also drops them from the registry).
+fn collect(
+    all: &mut BTreeMap<String, Vec<Watch>>,
+    key: &str,
+    mask: u32,
+    cookie: u32,
+    name: &str,
+    remove: bool,
+    out: &mut Vec<Delivery>,
+) {
+    let Some(list) = all.get_mut(key) else {
+        return;
+    };
+    list.retain(|watch| {
+        let wanted = mask & IN_ALL_EVENTS == 0 || watch.mask & mask & IN_ALL_EVENTS != 0;
+        let drop = remove || (wanted && watch.mask & IN_ONESHOT != 0);
+        if wanted || remove {
+            if let Some(inotify) = watch.inotify.upgrade() {
+                out.push(Delivery {
+                    inotify,
+                    event: Event {
+                        wd: watch.wd,
+                        mask: if wanted { mask } else { 0 },
+                        cookie,
+                        name: name.to_string(),
+                    },
+                    ignored: drop,
+                });
+            }
+        }
+        if drop {
+            NR_WATCHES.fetch_sub(1, Ordering::Relaxed);
+        }
+        !drop
+    });
+    if list.is_empty() {
+        all.remove(key);
+    }
+}
+
+/// Queues the events. Called without WATCHES held: dropping the last
+/// reference to an instance takes it.
+fn deliver(deliveries: Vec<Delivery>) {
+    for d in deliveries {
+        let wd = d.event.wd;
+        if d.event.mask != 0 {
+            d.inotify.queue(d.event);
+        }
+        if d.ignored {
+            d.inotify.queue(Event { wd, mask: IN_IGNORED, cookie: 0, name: String::new() });
+        }
+    }
+}
+
+fn emit(all: &mut BTreeMap<String, Vec<Watch>>, path: &str, mask: u32, cookie: u32, out: &mut Vec<Delivery>) {
+    if mask & ENTRY_EVENTS == 0 {
+        collect(all, path, mask, cookie, "", false, out);
+    }
+    if mask & SELF_EVENTS == 0 {
+        if let Some((dir, name)) = parent_of(path) {
+            collect(all, dir, mask, cookie, name, false, out);
+        }
+    }
+}
+
+fn isdir(is_dir: bool) -> u32 {
+    if is_dir { IN_ISDIR } else { 0 }
+}
+
+/// `mask` happened to the file at `path` (normalized). Reported to watches
+/// on the file and, with the file's name, on its directory.
+pub fn notify(path: &str, mask: u32) {
+    if NR_WATCHES.load(Ordering::Relaxed) == 0 {
+        return;
+    }
+    let mut out = Vec::new();
+    emit(&mut WATCHES.lock(), path, mask, 0, &mut out);
+    deliver(out);
+}
+
+/// `mask` happened to the inode kind `kind` at `path`
+pub fn notify_kind(path: &str, mask: u32, kind: InodeKind) {
+    notify(path, mask | isdir(kind == InodeKind::Dir));
+}
+
+/// Removes the watches on `path` and, with `subtree`, below it. Each gets
+/// `mask` (if it asked for it) and then IN_IGNORED.
+fn drop_watches(all: &mut BTreeMap<String, Vec<Watch>>, path: &str, subtree: bool, mask: u32, out: &mut Vec<Delivery>) {
+    let mut keys = Vec::new();
+    if all.contains_key(path) {
+        keys.push(path.to_string());
+    }
+    if subtree {
+        let prefix = if path == "/" { String::from("/") } else { alloc::format!("{}/", path) };
+        keys.extend(
+            all.range(prefix.clone()..)
+                .map(|(k, _)| k)
+                .take_while(|k| k.starts_with(prefix.as_str()))
+                .filter(|k| k.as_str() != path)
+                .cloned(),
+        );
+    }
+    for key in keys {
+        collect(all, &key, mask, 0, "", true, out);
+    }
+}
+
+/// The entry at `path` was unlinked or removed
+pub fn deleted(path: &str, is_dir: bool) {
+    if NR_WATCHES.load(Ordering::Relaxed) == 0 {
+        return;
+    }
+    let mut out = Vec::new();
+    {
+        let mut all = WATCHES.lock();
+        emit(&mut all, path, IN_DELETE | isdir(is_dir), 0, &mut out);
+        drop_watches(&mut all, path, false, IN_DELETE_SELF, &mut out);
+    }
+    deliver(out);
+}
+
+/// `old` was renamed to `new`, replacing whatever `new` was when
+/// `replaced`. Watches on and below `old` move to the new path.
+pub fn moved(old: &str, new: &str, is_dir: bool, replaced: bool) {
+    if NR_WATCHES.load(Ordering::Relaxed) == 0 {
+        return;
+    }
+    let cookie = loop {
+        let c = NEXT_COOKIE.fetch_add(1, Ordering::Relaxed);
+        if c != 0 {
+            break c;
+        }
+    };
+    let flag = isdir(is_dir);
+
+    let mut out = Vec::new();
+    {
+        let mut all = WATCHES.lock();
+        emit(&mut all, old, IN_MOVED_FROM | flag, cookie, &mut out);
+        emit(&mut all, new, IN_MOVED_TO | flag, cookie, &mut out);
+        emit(&mut all, old, IN_MOVE_SELF | flag, 0, &mut out);
+        if replaced {
+            drop_watches(&mut all, new, true, IN_DELETE_SELF, &mut out);
+        }
+
+        let prefix = alloc::format!("{}/", old);
+        let keys: Vec<String> = all
+            .range(String::from(old)..)
+            .map(|(k, _)| k)
+            .take_while(|k| k.starts_with(old))
+            .filter(|k| k.as_str() == old || k.starts_with(prefix.as_str()))
+            .cloned()
+            .collect();
+        for key in keys {
+            if let Some(list) = all.remove(&key) {
+                let renamed = alloc::format!("{}{}", new, &key[old.len()..]);
+                all.entry(renamed).or_default().extend(list);
+            }
+        }
+    }
+    deliver(out);
+}
+
+/// The filesystem mounted at `path` went away: watches inside it report
+/// IN_UNMOUNT and are removed.
+pub fn unmounted(path: &str) {
+    if NR_WATCHES.load(Ordering::Relaxed) == 0 {
+        return;
+    }
+    let mut out = Vec::new();
+    drop_watches(&mut WATCHES.lock(), &normalize(path), true, IN_UNMOUNT, &mut out);
+    deliver(out);
+}
+
+/// inotify_init1 syscall
+pub fn sys_inotify_init1(flags: i32) -> i64 {
+    if flags & !(IN_CLOEXEC | IN_NONBLOCK) != 0 {
+        return errno::EINVAL;
+    }
+    match crate::syscall::alloc_fd_for_inotify(Inotify::new(flags)) {
+        Some(fd) => fd as i64,
+        None => errno::EMFILE,
+    }
+}
+
+/// inotify_init syscall
+pub fn sys_inotify_init() -> i64 {
+    sys_inotify_init1(0)
+}
+
+/// inotify_add_watch syscall
+pub fn sys_inotify_add_watch(fd: i32, pathname: u64, mask: u32) -> i64 {
+    let path = match unsafe { crate::syscall::read_user_string(pathname, 4096) } {
+        Some(p) => p,
+        None => return errno::EFAULT,
+    };
+    let inotify = match crate::syscall::inotify_of(fd) {
+        Ok(i) => i,
+        Err(e) => return e,
+    };
+
+    let cred = crate::task::current_cred();
+    let inode = {
+        let vfs = super::vfs_lock();
+        let resolved = if mask & IN_DONT_FOLLOW != 0 {
+            vfs.resolve_nofollow(&path, &cred)
+        } else {
+            vfs.resolve(&path, &cred)
+        };
+        match resolved {
+            Ok(i) => i,
+            Err(KError::NotFound) => return errno::ENOENT,
+            Err(KError::PermissionDenied) => return errno::EACCES,
+            Err(_) => return errno::EIO,
+        }
+    };
+    let meta = inode.metadata();
+    if !super::perm::can_read(&meta, &cred) {
+        return errno::EACCES;
+    }
+    if mask & IN_ONLYDIR != 0 && meta.kind != InodeKind::Dir {
+        return errno::ENOTDIR;
+    }
+
+    match inotify.add_watch(&path, mask) {
+        Ok(wd) => wd as i64,
+        Err(e) => e,
+    }
+}
+
+/// inotify_rm_watch syscall
+pub fn sys_inotify_rm_watch(fd: i32, wd: i32) -> i64 {
+    let inotify = match crate::syscall::inotify_of(fd) {
+        Ok(i) => i,
+        Err(e) => return e,
+    };
+    match inotify.rm_watch(wd) {
+        Ok(()) => 0,
+        Err(e) => e,
+    }
+}
@@ -24,7 +24,7 @@ use crate::security::{Gid, Uid};
 use crate::storage::BlockDevice;
 use crate::util::{KError, KResult};
 
-use super::vfs::{DirEntry, Inode, InodeKind, InodeOps, Metadata, Mode};
+use super::vfs::{DirEntry, FsStats, Inode, InodeKind, InodeOps, Metadata, Mode};
 
 // ============================================================================
 // Constants
@@ -49,6 +49,9 @@ const VD_STANDARD_ID: [u8; 5] = *b"CD001";
 /// Volume descriptor version
 const VD_VERSION: u8 = 1;
 
+/// statfs f_type reported by Linux for ISO 9660 volumes
+const ISOFS_SUPER_MAGIC: u64 = 0x9660;
+
 /// Directory record flags
 const DR_FLAG_HIDDEN: u8 = 0x01;
 const DR_FLAG_DIRECTORY: u8 = 0x02;
@@ -711,6 +714,16 @@ impl InodeOps for IsoInode {
             Ok(self.data_length as usize)
         }
     }
+    fn statfs(&self) -> KResult<FsStats> {
+        // Read-only medium: every block is in use
+        Ok(FsStats {
+            magic: ISOFS_SUPER_MAGIC,
+            block_size: self.volume_info.block_size as u64,
+            blocks: self.volume_info.volume_space_size as u64,
+            name_max: 255,
+            ..FsStats::default()
+        })
+    }
 }
 
 // ============================================================================
@@ -0,0 +1,649 @@
+//! jbd2 journal (used by ext4)
+//!
+//! Implements the subset of the Linux jbd2 on-disk format that we need:
+//! - Journal superblock (v2) parsing and updates
+//! - Recovery (scan, revoke and replay passes) of a dirty journal
+//! - Writing full transactions: descriptor blocks + metadata copies + commit
+//!
+//! All journal structures are big-endian. The journal lives inside a regular
+//! inode of the filesystem; the caller resolves that inode to a list of
+//! physical filesystem blocks and hands it to `Journal::load`.
+//!
+//! Checkpointing is synchronous: after a transaction is committed the caller
+//! writes the blocks to their final location and calls `checkpoint_done`, so
+//! at most one transaction is ever live in the log.
+//!
+//! References:
+//! - https://www.kernel.org/doc/html/latest/filesystems/ext4/journal.html
+//! - Linux kernel fs/jbd2/
+
+#![allow(dead_code)]
+
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use spin::Mutex;
+
+use crate::storage::BlockDevice;
+use crate::util::{KError, KResult};
+
+use super::ext4::crc32c;
+
+// ============================================================================
+// Constants
+// ============================================================================
+
+const JBD2_MAGIC: u32 = 0xC03B_3998;
+
+// Block types
+const JBD2_DESCRIPTOR_BLOCK: u32 = 1;
+const JBD2_COMMIT_BLOCK: u32 = 2;
+const JBD2_SUPERBLOCK_V1: u32 = 3;
+const JBD2_SUPERBLOCK_V2: u32 = 4;
+const JBD2_REVOKE_BLOCK: u32 = 5;
+
+// Incompatible features
+const JBD2_FEATURE_INCOMPAT_REVOKE: u32 = 0x1;
+const JBD2_FEATURE_INCOMPAT_64BIT: u32 = 0x2;
+const JBD2_FEATURE_INCOMPAT_ASYNC_COMMIT: u32 = 0x4;
+const JBD2_FEATURE_INCOMPAT_CSUM_V2: u32 = 0x8;
+const JBD2_FEATURE_INCOMPAT_CSUM_V3: u32 = 0x10;
+const JBD2_FEATURE_INCOMPAT_FAST_COMMIT: u32 = 0x20;
+
+const JBD2_KNOWN_INCOMPAT: u32 = JBD2_FEATURE_INCOMPAT_REVOKE
+    | JBD2_FEATURE_INCOMPAT_64BIT
+    | JBD2_FEATURE_INCOMPAT_ASYNC_COMMIT
+    | JBD2_FEATURE_INCOMPAT_CSUM_V2
+    | JBD2_FEATURE_INCOMPAT_CSUM_V3
+    | JBD2_FEATURE_INCOMPAT_FAST_COMMIT;
+
+// Descriptor tag flags
+const JBD2_FLAG_ESCAPE: u32 = 0x1;
+const JBD2_FLAG_SAME_UUID: u32 = 0x2;
+const JBD2_FLAG_DELETED: u32 = 0x4;
+const JBD2_FLAG_LAST_TAG: u32 = 0x8;
+
+// Journal superblock field offsets
+const JSB_BLOCKSIZE: usize = 0x0C;
+const JSB_MAXLEN: usize = 0x10;
+const JSB_FIRST: usize = 0x14;
+const JSB_SEQUENCE: usize = 0x18;
+const JSB_START: usize = 0x1C;
+const JSB_ERRNO: usize = 0x20;
+const JSB_FEATURE_INCOMPAT: usize = 0x28;
+const JSB_UUID: usize = 0x30;
+const JSB_CHECKSUM_TYPE: usize = 0x50;
+const JSB_NUM_FC_BLKS: usize = 0x54;
+const JSB_CHECKSUM: usize = 0xFC;
+const JSB_SIZE: usize = 1024;
+
+const JBD2_CRC32C_CHKSUM: u8 = 4;
+
+/// Size of the block header (magic, blocktype, sequence)
+const HEADER_SIZE: usize = 12;
+/// Size of the checksum tail on descriptor/revoke blocks (csum v2/v3)
+const TAIL_SIZE: usize = 4;
+
+// ============================================================================
+// Big-endian helpers
+// ============================================================================
+
+fn be32(buf: &[u8], off: usize) -> u32 {
+    u32::from_be_bytes([buf[off], buf[off + 1], buf[off + 2], buf[off + 3]])
+}
+
+fn be16(buf: &[u8], off: usize) -> u16 {
+    u16::from_be_bytes([buf[off], buf[off + 1]])
+}
+
+fn be64(buf: &[u8], off: usize) -> u64 {
+    (be32(buf, off) as u64) << 32 | be32(buf, off + 4) as u64
+}
+
+fn put_be32(buf: &mut [u8], off: usize, v: u32) {
+    buf[off..off + 4].copy_from_slice(&v.to_be_bytes());
+}
+
+fn put_be16(buf: &mut [u8], off: usize, v: u16) {
+    buf[off..off + 2].copy_from_slice(&v.to_be_bytes());
+}
+
+fn put_be64(buf: &mut [u8], off: usize, v: u64) {
+    buf[off..off + 8].copy_from_slice(&v.to_be_bytes());
+}
+
+fn write_header(buf: &mut [u8], blocktype: u32, sequence: u32) {
+    put_be32(buf, 0, JBD2_MAGIC);
+    put_be32(buf, 4, blocktype);
+    put_be32(buf, 8, sequence);
+}
+
+// ============================================================================
+// Journal
+// ============================================================================
+
+/// Mutable journal state (mirrors the on-disk superblock)
+struct JournalState {
+    /// Raw superblock bytes (first 1024 bytes of journal block 0)
+    sb: Vec<u8>,
+    /// First block of the log area
+    first: u32,
+    /// Total number of journal blocks
+    maxlen: u32,
+    /// Sequence number of the next transaction
+    sequence: u32,
+    /// Block where the live log starts (0 = journal is clean)
+    start: u32,
+}
+
+/// An open jbd2 journal
+pub struct Journal {
+    device: Arc<dyn BlockDevice>,
+    block_size: u32,
+    /// Journal logical block -> physical filesystem block
+    blocks: Vec<u64>,
+    incompat: u32,
+    uuid: [u8; 16],
+    /// Checksum seed (crc32c of the journal UUID), only used with csum v2/v3
+    csum_seed: u32,
+    state: Mutex<JournalState>,
+}
+
+/// Result of a recovery run
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RecoveryInfo {
+    /// Number of committed transactions found in the log
+    pub transactions: u32,
+    /// Number of blocks written back to the filesystem
+    pub replayed: u32,
+    /// Number of blocks skipped because they were revoked
+    pub revoked: u32,
+}
+
+impl Journal {
+    /// Open a journal given the physical blocks backing the journal inode
+    pub fn load(device: Arc<dyn BlockDevice>, block_size: u32, blocks: Vec<u64>) -> KResult<Self> {
+        if blocks.is_empty() || blocks[0] == 0 {
+            return Err(KError::Invalid);
+        }
+
+        let mut buf = vec![0u8; block_size as usize];
+        read_fs_block(&device, block_size, blocks[0], &mut buf)?;
+
+        if be32(&buf, 0) != JBD2_MAGIC {
+            crate::kprintln!("jbd2: invalid journal magic 0x{:08x}", be32(&buf, 0));
+            return Err(KError::Invalid);
+        }
+
+        let blocktype = be32(&buf, 4);
+        if blocktype != JBD2_SUPERBLOCK_V1 && blocktype != JBD2_SUPERBLOCK_V2 {
+            return Err(KError::Invalid);
+        }
+
+        if be32(&buf, JSB_BLOCKSIZE) != block_size {
+            crate::kprintln!("jbd2: journal block size mismatch");
+            return Err(KError::Invalid);
+        }
+
+        let incompat = if blocktype == JBD2_SUPERBLOCK_V2 {
+            be32(&buf, JSB_FEATURE_INCOMPAT)
+        } else {
+            0
+        };
+        if incompat & !JBD2_KNOWN_INCOMPAT != 0 {
+            crate::kprintln!("jbd2: unsupported journal features 0x{:x}", incompat);
+            return Err(KError::NotSupported);
+        }
+
+        let mut maxlen = core::cmp::min(be32(&buf, JSB_MAXLEN), blocks.len() as u32);
+        if incompat & JBD2_FEATURE_INCOMPAT_FAST_COMMIT != 0 {
+            // The fast-commit area sits at the end of the journal; we never use it
+            let num_fc = match be32(&buf, JSB_NUM_FC_BLKS) {
+                0 => 256,
+                n => n,
+            };
+            maxlen = maxlen.saturating_sub(num_fc);
+        }
+        let first = be32(&buf, JSB_FIRST);
+        if first == 0 || first >= maxlen {
+            return Err(KError::Invalid);
+        }
+
+        let mut uuid = [0u8; 16];
+        uuid.copy_from_slice(&buf[JSB_UUID..JSB_UUID + 16]);
+
+        let csum_seed = crc32c(!0, &uuid);
+
+        let state = JournalState {
+            sb: buf[..JSB_SIZE].to_vec(),
+            first,
+            maxlen,
+            sequence: be32(&buf, JSB_SEQUENCE),
+            start: be32(&buf, JSB_START),
+        };
+
+        Ok(Self {
+            device,
+            block_size,
+            blocks,
+            incompat,
+            uuid,
+            csum_seed,
+            state: Mutex::new(state),
+        })
+    }
+
+    /// Does the log contain transactions that were never checkpointed?
+    pub fn needs_recovery(&self) -> bool {
+        self.state.lock().start != 0
+    }
+
+    /// Maximum number of metadata blocks a single transaction may carry
+    pub fn max_transaction_blocks(&self) -> usize {
+        let st = self.state.lock();
+        let log_blocks = (st.maxlen - st.first) as usize;
+        let per_desc = self.tags_per_descriptor();
+        // Each group of `per_desc` blocks needs one descriptor; one commit block
+        let groups = log_blocks.saturating_sub(1) / (per_desc + 1);
+        groups * per_desc
+    }
+
+    fn has_csum_v2or3(&self) -> bool {
+        self.incompat & (JBD2_FEATURE_INCOMPAT_CSUM_V2 | JBD2_FEATURE_INCOMPAT_CSUM_V3) != 0
+    }
+
+    fn has_csum_v3(&self) -> bool {
+        self.incompat & JBD2_FEATURE_INCOMPAT_CSUM_V3 != 0
+    }
+
+    fn is_64bit(&self) -> bool {
+        self.incompat & JBD2_FEATURE_INCOMPAT_64BIT != 0
+    }
+
+    /// Size of one descriptor tag (without the optional UUID)
+    fn tag_bytes(&self) -> usize {
+        if self.has_csum_v3() {
+            return 16;
+        }
+        let mut sz = 12;
+        if self.incompat & JBD2_FEATURE_INCOMPAT_CSUM_V2 != 0 {
+            sz += 2;
+        }
+        if self.is_64bit() {
+            sz
+        } else {
+            sz - 4
+        }
+    }
+
+    /// Usable bytes in a descriptor/revoke block after the header
+    fn descriptor_space(&self) -> usize {
+        let mut space = self.block_size as usize - HEADER_SIZE;
+        if self.has_csum_v2or3() {
+            space -= TAIL_SIZE;
+        }
+        space
+    }
+
+    fn tags_per_descriptor(&self) -> usize {
+        // The first tag carries the 16-byte UUID, the rest use SAME_UUID
+        (self.descriptor_space() - 16) / self.tag_bytes()
+    }
+
+    fn next_log_block(&self, st: &JournalState, pos: u32) -> u32 {
+        if pos + 1 >= st.maxlen {
+            st.first
+        } else {
+            pos + 1
+        }
+    }
+
+    fn read_log_block(&self, pos: u32, buf: &mut [u8]) -> KResult<()> {
+        let phys = *self.blocks.get(pos as usize).ok_or(KError::OutOfRange)?;
+        read_fs_block(&self.device, self.block_size, phys, buf)
+    }
+
+    fn write_log_block(&self, pos: u32, buf: &[u8]) -> KResult<()> {
+        let phys = *self.blocks.get(pos as usize).ok_or(KError::OutOfRange)?;
+        write_fs_block(&self.device, self.block_size, phys, buf)
+    }
+
+    /// Parse the tags of a descriptor block: (fs block, flags) pairs
+    fn parse_tags(&self, buf: &[u8]) -> Vec<(u64, u32)> {
+        let mut tags = Vec::new();
+        let end = HEADER_SIZE + self.descriptor_space();
+        let tag_bytes = self.tag_bytes();
+        let mut off = HEADER_SIZE;
+
+        while off + tag_bytes <= end {
+            let (block, flags) = if self.has_csum_v3() {
+                let lo = be32(buf, off) as u64;
+                let flags = be32(buf, off + 4);
+                let hi = be32(buf, off + 8) as u64;
+                let block = if self.is_64bit() { hi << 32 | lo } else { lo };
+                (block, flags)
+            } else {
+                let lo = be32(buf, off) as u64;
+                let flags = be16(buf, off + 6) as u32;
+                let block = if self.is_64bit() {
+                    (be32(buf, off + 8) as u64) << 32 | lo
+                } else {
+                    lo
+                };
+                (block, flags)
+            };
+
+            tags.push((block, flags));
+            off += tag_bytes;
+            if flags & JBD2_FLAG_SAME_UUID == 0 {
+                off += 16;
+            }
+            if flags & JBD2_FLAG_LAST_TAG != 0 {
+                break;
+            }
+        }
+
+        tags
+    }
+
+    /// Parse the records of a revoke block
+    fn parse_revoke(&self, buf: &[u8]) -> Vec<u64> {
+        let mut out = Vec::new();
+        let count = core::cmp::min(be32(buf, HEADER_SIZE) as usize, buf.len());
+        let rec = if self.is_64bit() { 8 } else { 4 };
+        let mut off = HEADER_SIZE + 4;
+        while off + rec <= count {
+            let block = if rec == 8 { be64(buf, off) } else { be32(buf, off) as u64 };
+            out.push(block);
+            off += rec;
+        }
+        out
+    }
+
+    fn commit_block_valid(&self, buf: &[u8]) -> bool {
+        if !self.has_csum_v2or3() {
+            return true;
+        }
+        let stored = be32(buf, 0x10);
+        let mut tmp = buf.to_vec();
+        put_be32(&mut tmp, 0x10, 0);
+        crc32c(self.csum_seed, &tmp) == stored
+    }
+
+    /// Replay committed transactions left in the log by an unclean shutdown.
+    ///
+    /// Uses the classic three passes: find the end of the log, collect
+    /// revoke records, then write every non-revoked block home.
+    pub fn recover(&self) -> KResult<RecoveryInfo> {
+        let mut st = self.state.lock();
+        let mut info = RecoveryInfo::default();
+
+        if st.start == 0 {
+            return Ok(info);
+        }
+
+        let mut buf = vec![0u8; self.block_size as usize];
+
+        // Pass 1: scan - find the last fully committed transaction
+        let start_seq = st.sequence;
+        let mut end_seq = start_seq;
+        {
+            let mut pos = st.start;
+            let mut seq = start_seq;
+            let mut steps = 0u32;
+            loop {
+                if steps > st.maxlen {
+                    break;
+                }
+                self.read_log_block(pos, &mut buf)?;
+                if be32(&buf, 0) != JBD2_MAGIC || be32(&buf, 8) != seq {
+                    break;
+                }
+                match be32(&buf, 4) {
+                    JBD2_DESCRIPTOR_BLOCK => {
+                        let tags = self.parse_tags(&buf);
+                        for _ in 0..tags.len() {
+                            pos = self.next_log_block(&st, pos);
+                            steps += 1;
+                        }
+                    }
+                    JBD2_COMMIT_BLOCK => {
+                        if !self.commit_block_valid(&buf) {
+                            crate::kprintln!("jbd2: bad commit checksum in transaction {}", seq);
+                            break;
+                        }
+                        seq = seq.wrapping_add(1);
+                        end_seq = seq;
+                    }
+                    JBD2_REVOKE_BLOCK => {}
+                    _ => break,
+                }
+                pos = self.next_log_block(&st, pos);
+                steps += 1;
+            }
+        }
+
+        info.transactions = end_seq.wrapping_sub(start_seq);
+
+        // Pass 2: revoke - remember the latest transaction that revoked each block
+        let mut revoked: BTreeMap<u64, u32> = BTreeMap::new();
+        {
+            let mut pos = st.start;
+            let mut seq = start_seq;
+            while seq != end_seq {
+                self.read_log_block(pos, &mut buf)?;
+                match be32(&buf, 4) {
+                    JBD2_DESCRIPTOR_BLOCK => {
+                        let tags = self.parse_tags(&buf);
+                        for _ in 0..tags.len() {
+                            pos = self.next_log_block(&st, pos);
+                        }
+                    }
+                    JBD2_COMMIT_BLOCK => {
+                        seq = seq.wrapping_add(1);
+                    }
+                    JBD2_REVOKE_BLOCK => {
+                        for block in self.parse_revoke(&buf) {
+                            revoked.insert(block, seq);
+                        }
+                    }
+                    _ => break,
+                }
+                pos = self.next_log_block(&st, pos);
+            }
+        }
+
+        // Pass 3: replay
+        {
+            let mut pos = st.start;
+            let mut seq = start_seq;
+            let mut data = vec![0u8; self.block_size as usize];
+            while seq != end_seq {
+                self.read_log_block(pos, &mut buf)?;
+                match be32(&buf, 4) {
+                    JBD2_DESCRIPTOR_BLOCK => {
+                        let tags = self.parse_tags(&buf);
+                        for (block, flags) in tags {
+                            pos = self.next_log_block(&st, pos);
+                            if let Some(&rseq) = revoked.get(&block) {
+                                if rseq.wrapping_sub(seq) as i32 >= 0 {
+                                    info.revoked += 1;
+                                    continue;
+                                }
+                            }
+                            self.read_log_block(pos, &mut data)?;
+                            if flags & JBD2_FLAG_ESCAPE != 0 {
+                                put_be32(&mut data, 0, JBD2_MAGIC);
+                            }
+                            write_fs_block(&self.device, self.block_size, block, &data)?;
+                            info.replayed += 1;
+                        }
+                    }
+                    JBD2_COMMIT_BLOCK => {
+                        seq = seq.wrapping_add(1);
+                    }
+                    _ => {}
+                }
+                pos = self.next_log_block(&st, pos);
+            }
+        }
+
+        // Log is now empty
+        st.sequence = end_seq;
+        st.start = 0;
+        self.write_superblock(&mut st)?;
+
+        crate::kprintln!(
+            "jbd2: recovery complete ({} transactions, {} blocks replayed, {} revoked)",
+            info.transactions,
+            info.replayed,
+            info.revoked
+        );
+
+        Ok(info)
+    }
+
+    /// Write a complete transaction to the log and mark the journal dirty.
+    ///
+    /// `blocks` holds (filesystem block number, block contents). Once this
+    /// returns the transaction is durable: a crash from here on will be
+    /// repaired by `recover`. The caller must then write the blocks home
+    /// and call `checkpoint_done`.
+    pub fn commit(&self, blocks: &[(u64, &[u8])]) -> KResult<()> {
+        if blocks.is_empty() {
+            return Ok(());
+        }
+        if blocks.len() > self.max_transaction_blocks() {
+            return Err(KError::NoMemory);
+        }
+
+        let mut st = self.state.lock();
+        let tid = st.sequence;
+        let bs = self.block_size as usize;
+        let per_desc = self.tags_per_descriptor();
+        let tag_bytes = self.tag_bytes();
+
+        let mut pos = st.first;
+        let mut desc = vec![0u8; bs];
+        let mut data = vec![0u8; bs];
+
+        for chunk in blocks.chunks(per_desc) {
+            // Descriptor block
+            desc.fill(0);
+            write_header(&mut desc, JBD2_DESCRIPTOR_BLOCK, tid);
+            let mut off = HEADER_SIZE;
+            for (i, (block, contents)) in chunk.iter().enumerate() {
+                let mut flags = 0u32;
+                if i > 0 {
+                    flags |= JBD2_FLAG_SAME_UUID;
+                }
+                if i == chunk.len() - 1 {
+                    flags |= JBD2_FLAG_LAST_TAG;
+                }
+                if be32(contents, 0) == JBD2_MAGIC {
+                    flags |= JBD2_FLAG_ESCAPE;
+                }
+
+                let csum = if self.has_csum_v2or3() {
+                    let c = crc32c(self.csum_seed, &tid.to_be_bytes());
+                    crc32c(c, contents)
+                } else {
+                    0
+                };
+
+                if self.has_csum_v3() {
+                    put_be32(&mut desc, off, *block as u32);
+                    put_be32(&mut desc, off + 4, flags);
+                    put_be32(&mut desc, off + 8, (*block >> 32) as u32);
+                    put_be32(&mut desc, off + 12, csum);
+                } else {
+                    put_be32(&mut desc, off, *block as u32);
+                    put_be16(&mut desc, off + 4, csum as u16);
+                    put_be16(&mut desc, off + 6, flags as u16);
+                    if self.is_64bit() {
+                        put_be32(&mut desc, off + 8, (*block >> 32) as u32);
+                    }
+                }
+                off += tag_bytes;
+                if i == 0 {
+                    desc[off..off + 16].copy_from_slice(&self.uuid);
+                    off += 16;
+                }
+            }
+            if self.has_csum_v2or3() {
+                let csum = crc32c(self.csum_seed, &desc);
+                put_be32(&mut desc, bs - TAIL_SIZE, csum);
+            }
+            self.write_log_block(pos, &desc)?;
+            pos += 1;
+
+            // Metadata copies
+            for (_, contents) in chunk {
+                data.copy_from_slice(contents);
+                if be32(&data, 0) == JBD2_MAGIC {
+                    put_be32(&mut data, 0, 0);
+                }
+                self.write_log_block(pos, &data)?;
+                pos += 1;
+            }
+        }
+
+        // Commit block
+        desc.fill(0);
+        write_header(&mut desc, JBD2_COMMIT_BLOCK, tid);
+        let now = crate::time::realtime();
+        put_be64(&mut desc, 0x30, now.tv_sec as u64);
+        put_be32(&mut desc, 0x38, now.tv_nsec as u32);
+        if self.has_csum_v2or3() {
+            let csum = crc32c(self.csum_seed, &desc);
+            put_be32(&mut desc, 0x10, csum);
+        }
+        self.write_log_block(pos, &desc)?;
+
+        // Point the superblock at the live log
+        st.start = st.first;
+        self.write_superblock(&mut st)
+    }
+
+    /// Mark the log empty after the committed transaction reached its home location
+    pub fn checkpoint_done(&self) -> KResult<()> {
+        let mut st = self.state.lock();
+        if st.start == 0 {
+            return Ok(());
+        }
+        st.start = 0;
+        st.sequence = st.sequence.wrapping_add(1);
+        self.write_superblock(&mut st)
+    }
+
+    fn write_superblock(&self, st: &mut JournalState) -> KResult<()> {
+        put_be32(&mut st.sb, JSB_SEQUENCE, st.sequence);
+        put_be32(&mut st.sb, JSB_START, st.start);
+        if self.has_csum_v2or3() {
+            st.sb[JSB_CHECKSUM_TYPE] = JBD2_CRC32C_CHKSUM;
+            put_be32(&mut st.sb, JSB_CHECKSUM, 0);
+            let csum = crc32c(!0, &st.sb);
+            put_be32(&mut st.sb, JSB_CHECKSUM, csum);
+        }
+
+        let mut buf = vec![0u8; self.block_size as usize];
+        self.read_log_block(0, &mut buf)?;
+        buf[..JSB_SIZE].copy_from_slice(&st.sb);
+        self.write_log_block(0, &buf)
+    }
+}
+
+// ============================================================================
+// Block I/O helpers
+// ============================================================================
+
+fn read_fs_block(device: &Arc<dyn BlockDevice>, block_size: u32, block: u64, buf: &mut [u8]) -> KResult<()> {
+    let factor = block_size / device.block_size();
+    device.read_blocks(block * factor as u64, factor, buf)
+}
+
+fn write_fs_block(device: &Arc<dyn BlockDevice>, block_size: u32, block: u64, buf: &[u8]) -> KResult<()> {
+    let factor = block_size / device.block_size();
+    device.write_blocks(block * factor as u64, factor, buf)
+}
@@ -5,8 +5,9 @@
     use spin::Once;
 
     use crate::security::{self, Cred};
+    use crate::ipc::namespace::MountNamespace;
     use crate::storage::BlockDevice;
-    use crate::sync::IrqSafeMutex;
+    use crate::sync::{IrqSafeGuard, IrqSafeMutex};
     use crate::util::KResult;
 
     pub mod dentry;
@@ -15,9 +16,12 @@
     pub mod ext2;
     pub mod ext4;
     pub mod fat32;
+    pub mod inotify;
     pub mod inode_cache;
     pub mod iso9660;
+    pub mod jbd2;
     pub mod ntfs;
+    pub mod overlayfs;
     pub mod page_cache;
     pub mod perm;
     pub mod procfs;
@@ -27,10 +31,12 @@
     pub mod xattr;
     pub mod acl;
     pub mod tuning;
+    pub mod bpffs;
+    pub mod cgroupfs;
 
-    pub use vfs::{Inode, InodeKind, Metadata, Mode, Vfs};
+    pub use vfs::{FsStats, Inode, InodeKind, Metadata, Mode, MountFlags, Vfs};
 
-    static VFS: Once<IrqSafeMutex<Vfs>> = Once::new();
+    static INIT_MNT_NS: Once<Arc<MountNamespace>> = Once::new();
 
     pub fn init() {
         // Initialize page cache first
@@ -43,12 +49,72 @@
         inode_cache::init();
 
         let root = tmpfs::TmpFs::new_root();
-        VFS.call_once(|| IrqSafeMutex::new(Vfs::new(root)));
+        INIT_MNT_NS.call_once(|| MountNamespace::new(Vfs::new(root)));
     }
 
-    pub fn vfs_lock() -> crate::sync::IrqSafeGuard<'static, Vfs> {
-        let v = VFS.call_once(|| panic!("VFS não inicializado"));
-        v.lock()
+    /// Mount namespace of the boot tasks, inherited by every task that never
+    /// unshared its own
+    pub fn init_mnt_ns() -> Arc<MountNamespace> {
+        Arc::clone(INIT_MNT_NS.call_once(|| panic!("VFS não inicializado")))
+    }
+
+    /// Root directory of a task after chroot(2): a view of its mount
+    /// namespace rooted at `path`.
+    pub struct Chroot {
+        /// The new root, as a path in the mount namespace
+        pub path: String,
+        table: Arc<IrqSafeMutex<Vfs>>,
+    }
+
+    impl Chroot {
+        pub fn new(path: String, view: Vfs) -> Self {
+            Self {
+                path,
+                table: Arc::new(IrqSafeMutex::new(view)),
+            }
+        }
+
+        pub fn table(&self) -> Arc<IrqSafeMutex<Vfs>> {
+            Arc::clone(&self.table)
+        }
+    }
+
+    /// Lock on the mount table of the current task. Keeps the table alive even
+    /// if the task switches namespaces while holding it.
+    pub struct VfsGuard {
+        // Declared first: dropped (unlocked) before the table it borrows
+        guard: IrqSafeGuard<'static, Vfs>,
+        _table: Arc<IrqSafeMutex<Vfs>>,
+    }
+
+    impl core::ops::Deref for VfsGuard {
+        type Target = Vfs;
+
+        fn deref(&self) -> &Vfs {
+            &self.guard
+        }
+    }
+
+    impl core::ops::DerefMut for VfsGuard {
+        fn deref_mut(&mut self) -> &mut Vfs {
+            &mut self.guard
+        }
+    }
+
+    /// Locks the mount table of the calling task: its chroot view if it has
+    /// one, its mount namespace otherwise. Boot code running before the
+    /// scheduler gets the initial namespace.
+    pub fn vfs_lock() -> VfsGuard {
+        let table = match crate::sched::try_current_task() {
+            Some(task) => task.vfs_table(),
+            None => init_mnt_ns().table(),
+        };
+        // SAFETY: the guard borrows from the mutex inside `table`, which the
+        // VfsGuard keeps alive and outlives the guard (field drop order).
+        let guard = unsafe {
+            core::mem::transmute::<IrqSafeGuard<'_, Vfs>, IrqSafeGuard<'static, Vfs>>(table.lock())
+        };
+        VfsGuard { guard, _table: table }
     }
 
     pub fn bootstrap_filesystem() {
@@ -62,6 +128,7 @@
             "/sbin",
             "/etc",
             "/etc/stenzel",
+            "/etc/ssl/certs",
             "/home",
             "/home/user",
             "/root",
@@ -157,6 +224,13 @@
         Ok(inode.metadata())
     }
 
+    /// Get statistics of the filesystem containing `path` (statfs)
+    pub fn statfs(path: &str, cred: &Cred) -> KResult<FsStats> {
+        let vfs = vfs_lock();
+        let inode = vfs.resolve(path, cred)?;
+        inode.0.statfs()
+    }
+
     /// Monta uma partição ext2 do dispositivo de bloco
     pub fn mount_ext2(device: Arc<dyn BlockDevice>) -> KResult<Arc<ext2::Ext2Fs>> {
         ext2::Ext2Fs::mount(device)
@@ -225,6 +299,43 @@
         Ok(fs.root())
     }
 
+    /// Whether `fs_type` is backed by a block device (as opposed to a
+    /// virtual filesystem like tmpfs or proc)
+    pub fn fs_needs_device(fs_type: &str) -> bool {
+        !matches!(fs_type, "tmpfs" | "proc" | "sysfs" | "devtmpfs" | "devfs" | "bpf" | "cgroup2" | "overlay")
+    }
+
+    /// Create a filesystem instance of type `fs_type` and return its root.
+    ///
+    /// Block filesystems need `device`; virtual filesystems ignore it.
+    /// Unknown types fail with `NotSupported`.
+    pub fn mount_fs(fs_type: &str, device: Option<Arc<dyn BlockDevice>>) -> KResult<Inode> {
+        use crate::util::KError;
+
+        match fs_type {
+            "tmpfs" => return Ok(tmpfs::TmpFs::new_root()),
+            "proc" => return Ok(procfs::new_root()),
+            "sysfs" => return Ok(sysfs::new_root()),
+            "devtmpfs" | "devfs" => return Ok(devfs::new_root()),
+            "bpf" => return Ok(bpffs::new_root()),
+            "cgroup2" => return Ok(cgroupfs::new_root()),
+            _ => {}
+        }
+
+        let device = device.ok_or(KError::Invalid)?;
+        let root = match fs_type {
+            "ext4" => ext4::Ext4Fs::mount(device)?.root(),
+            "ext2" | "ext3" => ext2::Ext2Fs::mount(device)?.root(),
+            "fat32" | "vfat" => fat32::Fat32Fs::mount(device)?.root(),
+            "ntfs" => ntfs::NtfsFs::mount(device)?.root(),
+            "exfat" => exfat::ExfatFs::mount(device)?.root(),
+            "iso9660" | "cdrom" | "udf" => iso9660::Iso9660Fs::mount(device)?.root(),
+            "auto" => mount_auto(device)?,
+            _ => return Err(KError::NotSupported),
+        };
+        Ok(root)
+    }
+
     /// Mount ISO 9660 filesystem
     pub fn mount_iso9660(device: Arc<dyn BlockDevice>) -> KResult<Arc<iso9660::Iso9660Fs>> {
         iso9660::Iso9660Fs::mount(device)
@@ -245,7 +356,7 @@
             let root_cred = security::user_db().login("root").expect("root user");
             let mut vfs = vfs_lock();
             let _ = vfs.mkdir_all("/mnt", &root_cred, Mode::from_octal(0o755));
-            vfs.mount("/mnt", root);
+            vfs.mount("/mnt", root, "", "ext2", MountFlags::empty());
         }
 
         crate::kprintln!("ext2: filesystem montado com sucesso em /mnt");
@@ -262,7 +373,7 @@
             let root_cred = security::user_db().login("root").expect("root user");
             let mut vfs = vfs_lock();
             let _ = vfs.mkdir_all("/mnt", &root_cred, Mode::from_octal(0o755));
-            vfs.mount("/mnt", root);
+            vfs.mount("/mnt", root, "", "ext4", MountFlags::empty());
         }
 
         Ok(())
@@ -271,7 +382,7 @@
     /// Monta procfs em /proc
     fn mount_procfs(vfs: &mut Vfs, _cred: &Cred) -> KResult<()> {
         let procfs_root = procfs::new_root();
-        vfs.mount("/proc", procfs_root);
+        vfs.mount("/proc", procfs_root, "proc", "proc", MountFlags::NOSUID | MountFlags::NODEV | MountFlags::NOEXEC);
         crate::kprintln!("procfs: montado em /proc");
         Ok(())
     }
@@ -279,7 +390,7 @@
     /// Monta devfs em /dev
     fn mount_devfs(vfs: &mut Vfs, _cred: &Cred) -> KResult<()> {
         let devfs_root = devfs::new_root();
-        vfs.mount("/dev", devfs_root);
+        vfs.mount("/dev", devfs_root, "devtmpfs", "devtmpfs", MountFlags::NOSUID);
         crate::kprintln!("devfs: montado em /dev");
         Ok(())
     }
@@ -287,8 +398,10 @@
     /// Monta sysfs em /sys
     fn mount_sysfs(vfs: &mut Vfs, _cred: &Cred) -> KResult<()> {
         let sysfs_root = sysfs::new_root();
-        vfs.mount("/sys", sysfs_root);
+        vfs.mount("/sys", sysfs_root, "sysfs", "sysfs", MountFlags::NOSUID | MountFlags::NODEV | MountFlags::NOEXEC);
         crate::kprintln!("sysfs: montado em /sys");
+        vfs.mount("/sys/fs/bpf", bpffs::new_root(), "bpf", "bpf", MountFlags::NOSUID | MountFlags::NODEV | MountFlags::NOEXEC);
+        vfs.mount("/sys/fs/cgroup", cgroupfs::new_root(), "cgroup2", "cgroup2", MountFlags::NOSUID | MountFlags::NODEV | MountFlags::NOEXEC);
         Ok(())
     }
 
@@ -312,6 +425,9 @@
         pub ro: bool,
         /// Init program path
         pub init: Option<String>,
+        /// Encrypted root: "device:name[:options]" (e.g.,
+        /// "/dev/sda2:cryptroot:allow-discards")
+        pub cryptdevice: Option<String>,
     }
 
     static BOOT_PARAMS: Once<Mutex<BootParams>> = Once::new();
@@ -329,6 +445,8 @@
                 params.rootflags = Some(String::from(value));
             } else if let Some(value) = param.strip_prefix("init=") {
                 params.init = Some(String::from(value));
+            } else if let Some(value) = param.strip_prefix("cryptdevice=") {
+                params.cryptdevice = Some(String::from(value));
             } else if param == "ro" {
                 params.ro = true;
             } else if param == "rw" {
@@ -393,42 +511,22 @@
     /// Mount root filesystem from a block device
     ///
     /// This function mounts the given device as the root filesystem,
-    /// replacing the current tmpfs root with the real root.
+    /// replacing the current tmpfs root with the real root. A LUKS device
+    /// is mounted through its open dm-crypt mapping and fails with
+    /// `PermissionDenied` until it has been unlocked.
     pub fn mount_root_from_device(device: Arc<dyn BlockDevice>, fstype: Option<&str>) -> KResult<()> {
-        // Determine filesystem type
+        let device = crate::storage::crypt::resolve(device).map_err(|e| {
+            crate::kprintln!("rootmount: root device is encrypted and still locked");
+            e
+        })?;
         let fs_root = match fstype {
-            Some("ext4") => {
-                let fs = ext4::Ext4Fs::mount(Arc::clone(&device))?;
-                fs.root()
-            }
-            Some("ext2") | Some("ext3") => {
-                let fs = ext2::Ext2Fs::mount(Arc::clone(&device))?;
-                fs.root()
-            }
-            Some("fat32") | Some("vfat") => {
-                let fs = fat32::Fat32Fs::mount(Arc::clone(&device))?;
-                fs.root()
-            }
-            Some("ntfs") => {
-                let fs = ntfs::NtfsFs::mount(Arc::clone(&device))?;
-                fs.root()
-            }
-            Some("exfat") => {
-                let fs = exfat::ExfatFs::mount(Arc::clone(&device))?;
-                fs.root()
-            }
-            Some("iso9660") | Some("cdrom") | Some("udf") => {
-                let fs = iso9660::Iso9660Fs::mount(Arc::clone(&device))?;
-                fs.root()
-            }
-            None | Some("auto") => {
-                // Try auto-detection
-                mount_auto(device)?
-            }
-            Some(other) => {
-                crate::kprintln!("rootmount: unknown filesystem type: {}", other);
-                return Err(crate::util::KError::NotSupported);
-            }
+            None | Some("auto") => mount_auto(device)?,
+            Some(other) => mount_fs(other, Some(device)).map_err(|e| {
+                if e == crate::util::KError::NotSupported {
+                    crate::kprintln!("rootmount: unknown filesystem type: {}", other);
+                }
+                e
+            })?,
         };
 
         // Perform switch_root
@@ -438,6 +536,28 @@
         Ok(())
     }
 
+    /// Unlock the `cryptdevice=` root with `passphrase` and mount it
+    pub fn unlock_root(passphrase: &[u8]) -> KResult<()> {
+        use crate::storage::{self, crypt};
+        use crate::util::KError;
+
+        let params = boot_params().ok_or(KError::NotFound)?;
+        let spec = params.cryptdevice.ok_or(KError::NotFound)?;
+        let mut fields = spec.splitn(3, ':');
+        let path = fields.next().unwrap_or("");
+        let name = fields.next().unwrap_or("cryptroot");
+        let options = crypt::CryptOptions::parse(fields.next().unwrap_or(""));
+
+        let device = storage::find_device_by_path(path)
+            .and_then(storage::get_device)
+            .ok_or(KError::NotFound)?;
+        let mapped = match crypt::get(name) {
+            Some(mapped) => mapped,
+            None => crypt::open(name, device, passphrase, options)?,
+        };
+        mount_root_from_device(mapped, params.rootfstype.as_deref())
+    }
+
     /// Switch the VFS root to a new root inode
     ///
     /// This function:
@@ -469,6 +589,27 @@
         Ok(())
     }
 
+    /// Make the filesystem mounted at `new_root` the root, keeping the old
+    /// root and its mounts reachable at `put_old` (a path in the new root),
+    /// and mount procfs, devfs and sysfs on the new root
+    pub fn pivot_root(new_root: &str, put_old: &str) -> KResult<()> {
+        let root_cred = security::user_db().login("root")?;
+        let mut vfs = vfs_lock();
+
+        let old_path = alloc::format!("{}{}", new_root.trim_end_matches('/'), put_old);
+        vfs.mkdir_all(&old_path, &root_cred, Mode::from_octal(0o755))?;
+        vfs.pivot_root(new_root, &old_path, &root_cred)?;
+        crate::kprintln!("rootmount: pivoted to {}, old root at {}", new_root, put_old);
+
+        for d in ["/proc", "/dev", "/sys"] {
+            let _ = vfs.mkdir_all(d, &root_cred, Mode::from_octal(0o755));
+        }
+        let _ = mount_procfs(&mut vfs, &root_cred);
+        let _ = mount_devfs(&mut vfs, &root_cred);
+        let _ = mount_sysfs(&mut vfs, &root_cred);
+        Ok(())
+    }
+
     /// Process fstab and mount all filesystems
     pub fn mount_from_fstab() -> KResult<()> {
         let root_cred = security::user_db().login("root").expect("root user");
@@ -517,7 +658,7 @@
                     let mut vfs = vfs_lock();
                     let _ = vfs.mkdir_all(&entry.mount_point, &root_cred, Mode::from_octal(0o755));
                     let tmpfs_root = tmpfs::TmpFs::new_root();
-                    vfs.mount(&entry.mount_point, tmpfs_root);
+                    vfs.mount(&entry.mount_point, tmpfs_root, "tmpfs", "tmpfs", MountFlags::empty());
                     crate::kprintln!("fstab: mounted tmpfs at {}", entry.mount_point);
                 }
                 _ => {
@@ -33,7 +33,7 @@ use crate::security::{Gid, Uid};
 use crate::storage::BlockDevice;
 use crate::util::{KError, KResult};
 
-use super::vfs::{DirEntry, Inode, InodeKind, InodeOps, Metadata, Mode};
+use super::vfs::{DirEntry, FsStats, Inode, InodeKind, InodeOps, Metadata, Mode};
 
 // ============================================================================
 // Constants
@@ -45,6 +45,9 @@ const NTFS_OEM_ID: [u8; 8] = *b"NTFS    ";
 /// Boot signature
 const BOOT_SIGNATURE: u16 = 0xAA55;
 
+/// statfs f_type reported by Linux for NTFS volumes
+const NTFS_SB_MAGIC: u64 = 0x5346_544E;
+
 /// MFT record signature "FILE"
 const MFT_RECORD_SIGNATURE: [u8; 4] = *b"FILE";
 
@@ -1299,6 +1302,18 @@ impl InodeOps for NtfsInode {
             Ok(self.data_size as usize)
         }
     }
+    fn statfs(&self) -> KResult<FsStats> {
+        // The driver is read-only and does not load $Bitmap, so no free
+        // space is reported
+        let vol = &self.volume_info;
+        Ok(FsStats {
+            magic: NTFS_SB_MAGIC,
+            block_size: vol.bytes_per_cluster as u64,
+            blocks: vol.total_sectors / vol.sectors_per_cluster.max(1) as u64,
+            name_max: 255,
+            ..FsStats::default()
+        })
+    }
 }
 
 // ============================================================================
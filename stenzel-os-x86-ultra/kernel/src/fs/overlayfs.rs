@@ -0,0 +1,657 @@
+//! overlayfs - a writable union of directory trees
+//!
+//! One or more read-only lower directories are merged under a writable
+//! upper directory. Lookups go through the layers top to bottom, and
+//! directories present in several layers list the union of their entries.
+//! Modifying a file that only exists below first copies it up to the upper
+//! layer; removing a lower entry leaves a whiteout that hides it.
+//!
+//! The upper layer uses the Linux layout, so a persistence partition can be
+//! shared with other systems: a whiteout is a character device (0/0) and an
+//! opaque directory, which hides the lower directories of the same name,
+//! has `trusted.overlay.opaque` set to "y". Filesystems without extended
+//! attributes mark opaque directories with an empty `.wh..wh..opq` file,
+//! as aufs does.
+//!
+//! With a work directory, copied up files are filled there and renamed into
+//! place, so a crash never leaves a half copied file in the upper layer.
+
+use alloc::collections::{BTreeMap, BTreeSet};
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::sync::{Arc, Weak};
+use alloc::vec;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use spin::{Mutex, RwLock};
+
+use crate::security::{Cred, Gid, Uid};
+use crate::util::{KError, KResult};
+
+use super::vfs::{DirEntry, FsStats, Inode, InodeKind, InodeOps, Metadata, Mode, MountFlags};
+use super::xattr::XattrFlags;
+
+/// statfs f_type of overlay mounts
+pub const OVERLAYFS_MAGIC: u64 = 0x794C_7630;
+
+/// Extended attributes of the upper layer used by overlayfs itself
+const XATTR_PREFIX: &str = "trusted.overlay.";
+const OPAQUE_XATTR: &str = "trusted.overlay.opaque";
+/// Opaque marker on upper filesystems without xattrs
+const OPAQUE_MARKER: &str = ".wh..wh..opq";
+/// Prefix of the temporary files in the work directory
+const WORK_PREFIX: &str = "#ovl";
+const COPY_CHUNK: usize = 64 * 1024;
+
+/// Mount options ("lowerdir=/a:/b,upperdir=/u,workdir=/w")
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct OverlayOptions {
+    /// Lower directories, topmost first
+    pub lower: Vec<String>,
+    /// Without an upper directory the overlay is read-only
+    pub upper: Option<String>,
+    pub work: Option<String>,
+}
+
+impl OverlayOptions {
+    pub fn parse(data: &str) -> KResult<Self> {
+        let mut options = Self::default();
+        for option in data.split(',').map(str::trim).filter(|o| !o.is_empty()) {
+            let (key, value) = option.split_once('=').unwrap_or((option, ""));
+            match key {
+                "lowerdir" => {
+                    options.lower = value.split(':').filter(|d| !d.is_empty()).map(String::from).collect();
+                }
+                "upperdir" if !value.is_empty() => options.upper = Some(String::from(value)),
+                "workdir" if !value.is_empty() => options.work = Some(String::from(value)),
+                "upperdir" | "workdir" => return Err(KError::Invalid),
+                // index=, redirect_dir=, ... are not implemented
+                _ => {}
+            }
+        }
+        if options.lower.is_empty() {
+            return Err(KError::Invalid);
+        }
+        Ok(options)
+    }
+}
+
+/// Mount an overlay described by mount `data`, resolving the layer
+/// directories with the permissions of `cred`
+pub fn mount(data: &str, cred: &Cred) -> KResult<Inode> {
+    let options = OverlayOptions::parse(data)?;
+    let (lower, upper, work) = {
+        let vfs = super::vfs_lock();
+        let lower = options
+            .lower
+            .iter()
+            .map(|path| vfs.resolve(path, cred))
+            .collect::<KResult<Vec<_>>>()?;
+        let upper = options.upper.as_deref().map(|path| vfs.resolve(path, cred)).transpose()?;
+        let work = options.work.as_deref().map(|path| vfs.resolve(path, cred)).transpose()?;
+        (lower, upper, work)
+    };
+    new_root(lower, upper, work)
+}
+
+/// Root of an overlay of `lower` (topmost first) under `upper`
+pub fn new_root(lower: Vec<Inode>, upper: Option<Inode>, work: Option<Inode>) -> KResult<Inode> {
+    if lower.is_empty() {
+        return Err(KError::Invalid);
+    }
+    for layer in lower.iter().chain(upper.iter()).chain(work.iter()) {
+        if layer.kind() != InodeKind::Dir {
+            return Err(KError::NotADirectory);
+        }
+    }
+    if let Some(upper) = &upper {
+        if upper.0.mount_flags().contains(MountFlags::RDONLY) {
+            return Err(KError::ReadOnly);
+        }
+    }
+    if let Some(work) = &work {
+        // Leftovers of copy-ups interrupted by a crash
+        for entry in work.0.readdir()? {
+            if entry.name.starts_with(WORK_PREFIX) {
+                let _ = work.0.unlink(&entry.name);
+            }
+        }
+    }
+
+    let mut merged = Vec::new();
+    if !upper.as_ref().is_some_and(is_opaque) {
+        for layer in &lower {
+            merged.push(layer.clone());
+            if is_opaque(layer) {
+                break;
+            }
+        }
+    }
+
+    let fs = Arc::new(OverlayFs {
+        upper: upper.clone(),
+        work,
+        lower,
+        next_ino: AtomicU64::new(1),
+    });
+    Ok(Inode(OvlInode::new(fs, None, InodeKind::Dir, upper, merged)))
+}
+
+struct OverlayFs {
+    upper: Option<Inode>,
+    work: Option<Inode>,
+    lower: Vec<Inode>,
+    next_ino: AtomicU64,
+}
+
+/// A merged entry: its upper inode, once there is one, and what it covers
+/// in the lower layers
+struct OvlInode {
+    fs: Arc<OverlayFs>,
+    self_weak: Weak<OvlInode>,
+    ino: u64,
+    kind: InodeKind,
+    /// Parent directory and name in it (None for the root)
+    link: RwLock<Option<(Arc<OvlInode>, String)>>,
+    upper: Mutex<Option<Inode>>,
+    /// Lower inodes, topmost first: the file itself for non-directories,
+    /// every directory merged into this one for directories
+    lower: Vec<Inode>,
+    /// Live children by name, so that a path maps to a single inode while
+    /// it is in use and a copy-up is seen by every user
+    children: Mutex<BTreeMap<String, Weak<OvlInode>>>,
+}
+
+fn is_whiteout(inode: &Inode) -> bool {
+    inode.kind() == InodeKind::CharDev
+}
+
+fn is_opaque(dir: &Inode) -> bool {
+    dir.0.getxattr(OPAQUE_XATTR).is_ok_and(|value| value == b"y") || dir.0.lookup(OPAQUE_MARKER).is_ok()
+}
+
+fn set_opaque(dir: &Inode) -> KResult<()> {
+    match dir.0.setxattr(OPAQUE_XATTR, b"y".to_vec(), XattrFlags::new(0)) {
+        Err(KError::NotSupported) => {
+            let meta = Metadata::simple(Uid(0), Gid(0), Mode::from_octal(0), InodeKind::File);
+            dir.0.create(OPAQUE_MARKER, InodeKind::File, meta).map(|_| ())
+        }
+        other => other,
+    }
+}
+
+fn create_whiteout(dir: &Inode, name: &str) -> KResult<()> {
+    let meta = Metadata::simple(Uid(0), Gid(0), Mode::from_octal(0), InodeKind::CharDev);
+    dir.0.create(name, InodeKind::CharDev, meta).map(|_| ())
+}
+
+/// Remove a whiteout for `name` from `dir`; returns whether there was one
+fn clear_whiteout(dir: &Inode, name: &str) -> KResult<bool> {
+    match dir.0.lookup(name) {
+        Ok(inode) if is_whiteout(&inode) => {
+            dir.0.unlink(name)?;
+            Ok(true)
+        }
+        _ => Ok(false),
+    }
+}
+
+fn copy_data(src: &Inode, dst: &Inode) -> KResult<()> {
+    let mut buf = vec![0u8; COPY_CHUNK];
+    let mut offset = 0;
+    loop {
+        let n = src.0.read_at(offset, &mut buf)?;
+        if n == 0 {
+            return Ok(());
+        }
+        let mut done = 0;
+        while done < n {
+            let written = dst.0.write_at(offset + done, &buf[done..n])?;
+            if written == 0 {
+                return Err(KError::IO);
+            }
+            done += written;
+        }
+        offset += n;
+    }
+}
+
+impl OvlInode {
+    fn new(
+        fs: Arc<OverlayFs>,
+        link: Option<(Arc<OvlInode>, String)>,
+        kind: InodeKind,
+        upper: Option<Inode>,
+        lower: Vec<Inode>,
+    ) -> Arc<Self> {
+        let ino = fs.next_ino.fetch_add(1, Ordering::Relaxed);
+        Arc::new_cyclic(|weak| OvlInode {
+            fs,
+            self_weak: weak.clone(),
+            ino,
+            kind,
+            link: RwLock::new(link),
+            upper: Mutex::new(upper),
+            lower,
+            children: Mutex::new(BTreeMap::new()),
+        })
+    }
+
+    fn arc(&self) -> Arc<OvlInode> {
+        self.self_weak.upgrade().expect("overlayfs: inode em uso sem referência")
+    }
+
+    fn upper(&self) -> Option<Inode> {
+        self.upper.lock().clone()
+    }
+
+    /// The inode that currently holds the contents
+    fn real(&self) -> Inode {
+        match self.upper() {
+            Some(upper) => upper,
+            None => self.lower[0].clone(),
+        }
+    }
+
+    fn check_name(name: &str) -> KResult<()> {
+        if name.is_empty() || name.contains('/') || name == OPAQUE_MARKER {
+            return Err(KError::Invalid);
+        }
+        Ok(())
+    }
+
+    fn lookup_child(&self, name: &str) -> KResult<Arc<OvlInode>> {
+        if self.kind != InodeKind::Dir {
+            return Err(KError::NotADirectory);
+        }
+        if name == OPAQUE_MARKER {
+            return Err(KError::NotFound);
+        }
+        if let Some(child) = self.children.lock().get(name).and_then(Weak::upgrade) {
+            return Ok(child);
+        }
+
+        let mut kind = None;
+        let mut upper = None;
+        let mut merge = true;
+        if let Some(dir) = self.upper() {
+            match dir.0.lookup(name) {
+                Ok(inode) if is_whiteout(&inode) => return Err(KError::NotFound),
+                Ok(inode) => {
+                    kind = Some(inode.kind());
+                    merge = inode.kind() == InodeKind::Dir && !is_opaque(&inode);
+                    upper = Some(inode);
+                }
+                Err(KError::NotFound) => {}
+                Err(e) => return Err(e),
+            }
+        }
+
+        let mut lower = Vec::new();
+        if merge {
+            for dir in &self.lower {
+                let inode = match dir.0.lookup(name) {
+                    Ok(inode) => inode,
+                    Err(KError::NotFound) => continue,
+                    Err(e) => return Err(e),
+                };
+                if is_whiteout(&inode) {
+                    break;
+                }
+                let is_dir = inode.kind() == InodeKind::Dir;
+                // Only directories merge, and only with directories
+                if kind.is_some_and(|k| (k == InodeKind::Dir) != is_dir) {
+                    break;
+                }
+                kind = Some(inode.kind());
+                let opaque = is_dir && is_opaque(&inode);
+                lower.push(inode);
+                if !is_dir || opaque {
+                    break;
+                }
+            }
+        }
+        let kind = kind.ok_or(KError::NotFound)?;
+
+        let mut children = self.children.lock();
+        if let Some(child) = children.get(name).and_then(Weak::upgrade) {
+            return Ok(child);
+        }
+        children.retain(|_, child| child.strong_count() > 0);
+        let child = OvlInode::new(
+            Arc::clone(&self.fs),
+            Some((self.arc(), name.to_string())),
+            kind,
+            upper,
+            lower,
+        );
+        children.insert(name.to_string(), Arc::downgrade(&child));
+        Ok(child)
+    }
+
+    /// Drop the cached child `name` after it was removed or replaced
+    fn forget(&self, name: &str) {
+        self.children.lock().remove(name);
+    }
+
+    /// Whether a lower layer of this directory has an entry `name`, which
+    /// then needs a whiteout once the entry is removed
+    fn lower_has(&self, name: &str) -> bool {
+        for dir in &self.lower {
+            if let Ok(inode) = dir.0.lookup(name) {
+                return !is_whiteout(&inode);
+            }
+        }
+        false
+    }
+
+    /// The upper inode, copying the entry (and its parents) up first
+    fn copy_up(&self) -> KResult<Inode> {
+        let mut upper = self.upper.lock();
+        if let Some(inode) = upper.as_ref() {
+            return Ok(inode.clone());
+        }
+        if self.fs.upper.is_none() {
+            return Err(KError::ReadOnly);
+        }
+        let (parent, name) = self.link.read().clone().ok_or(KError::IO)?;
+        let dir = parent.copy_up()?;
+
+        let src = &self.lower[0];
+        let meta = src.metadata();
+        let inode = match self.kind {
+            InodeKind::File => self.copy_up_file(src, &dir, &name, meta)?,
+            InodeKind::Symlink => dir.0.symlink(&name, &src.0.readlink()?, meta)?,
+            InodeKind::Fifo => dir.0.mkfifo(&name, meta)?,
+            kind => dir.0.create(&name, kind, meta)?,
+        };
+
+        if let Ok(names) = src.0.listxattr() {
+            for xattr in names.iter().filter(|x| !x.starts_with(XATTR_PREFIX)) {
+                if let Ok(value) = src.0.getxattr(xattr) {
+                    let _ = inode.0.setxattr(xattr, value, XattrFlags::new(0));
+                }
+            }
+        }
+        // Keep owner, mode and times of the original
+        inode.0.set_metadata(Metadata { ino: inode.metadata().ino, ..meta });
+
+        *upper = Some(inode.clone());
+        Ok(inode)
+    }
+
+    fn copy_up_file(&self, src: &Inode, dir: &Inode, name: &str, meta: Metadata) -> KResult<Inode> {
+        if let Some(work) = &self.fs.work {
+            let temp = format!("{}{:x}", WORK_PREFIX, self.ino);
+            let file = work.0.create(&temp, InodeKind::File, meta)?;
+            if let Err(e) = copy_data(src, &file) {
+                let _ = work.0.unlink(&temp);
+                return Err(e);
+            }
+            if work.0.rename_to(&temp, dir, name).is_ok() {
+                return dir.0.lookup(name);
+            }
+            // The work directory is on another filesystem: copy in place
+            let _ = work.0.unlink(&temp);
+        }
+
+        let file = dir.0.create(name, InodeKind::File, meta)?;
+        if let Err(e) = copy_data(src, &file) {
+            let _ = dir.0.unlink(name);
+            return Err(e);
+        }
+        Ok(file)
+    }
+
+    /// Create `name` in the upper layer with `make`, replacing a whiteout
+    fn create_with(
+        &self,
+        name: &str,
+        kind: InodeKind,
+        make: impl FnOnce(&Inode) -> KResult<Inode>,
+    ) -> KResult<Inode> {
+        Self::check_name(name)?;
+        match self.lookup_child(name) {
+            Ok(_) => return Err(KError::AlreadyExists),
+            Err(KError::NotFound) => {}
+            Err(e) => return Err(e),
+        }
+        let dir = self.copy_up()?;
+        let replaces_whiteout = clear_whiteout(&dir, name)?;
+        let inode = make(&dir)?;
+        // A new directory must not show what was deleted below it
+        if kind == InodeKind::Dir && replaces_whiteout {
+            set_opaque(&inode)?;
+        }
+
+        let child = OvlInode::new(
+            Arc::clone(&self.fs),
+            Some((self.arc(), name.to_string())),
+            kind,
+            Some(inode),
+            Vec::new(),
+        );
+        self.children.lock().insert(name.to_string(), Arc::downgrade(&child));
+        Ok(Inode(child))
+    }
+
+    fn downcast(inode: &Inode) -> Option<&OvlInode> {
+        inode.0.as_any().and_then(|a| a.downcast_ref::<OvlInode>())
+    }
+}
+
+impl InodeOps for OvlInode {
+    fn metadata(&self) -> Metadata {
+        Metadata { ino: self.ino, ..self.real().metadata() }
+    }
+
+    fn set_metadata(&self, meta: Metadata) {
+        if let Ok(upper) = self.copy_up() {
+            let current = upper.metadata();
+            upper.0.set_metadata(Metadata { ino: current.ino, kind: current.kind, ..meta });
+        }
+    }
+
+    fn parent(&self) -> Option<Inode> {
+        self.link.read().as_ref().map(|(parent, _)| Inode(Arc::clone(parent) as Arc<dyn InodeOps>))
+    }
+
+    fn lookup(&self, name: &str) -> KResult<Inode> {
+        Ok(Inode(self.lookup_child(name)?))
+    }
+
+    fn create(&self, name: &str, kind: InodeKind, meta: Metadata) -> KResult<Inode> {
+        self.create_with(name, kind, |dir| dir.0.create(name, kind, meta))
+    }
+
+    fn readdir(&self) -> KResult<Vec<DirEntry>> {
+        if self.kind != InodeKind::Dir {
+            return Err(KError::NotADirectory);
+        }
+        let mut seen = BTreeSet::new();
+        let mut entries = Vec::new();
+        for layer in self.upper().iter().chain(self.lower.iter()) {
+            for entry in layer.0.readdir()? {
+                if entry.name == OPAQUE_MARKER || !seen.insert(entry.name.clone()) {
+                    continue;
+                }
+                // A whiteout hides the name in the layers below it
+                if entry.kind != InodeKind::CharDev {
+                    entries.push(entry);
+                }
+            }
+        }
+        Ok(entries)
+    }
+
+    fn unlink(&self, name: &str) -> KResult<()> {
+        let child = self.lookup_child(name)?;
+        if child.kind == InodeKind::Dir {
+            return Err(KError::IsADirectory);
+        }
+        let dir = self.copy_up()?;
+        if child.upper().is_some() {
+            dir.0.unlink(name)?;
+        }
+        if self.lower_has(name) {
+            create_whiteout(&dir, name)?;
+        }
+        self.forget(name);
+        Ok(())
+    }
+
+    fn rmdir(&self, name: &str) -> KResult<()> {
+        let child = self.lookup_child(name)?;
+        if child.kind != InodeKind::Dir {
+            return Err(KError::NotADirectory);
+        }
+        if !child.readdir()?.is_empty() {
+            return Err(KError::NotEmpty);
+        }
+        let dir = self.copy_up()?;
+        if let Some(upper) = child.upper() {
+            // Only whiteouts and the opaque marker can be left in it
+            for entry in upper.0.readdir()? {
+                upper.0.unlink(&entry.name)?;
+            }
+            dir.0.rmdir(name)?;
+        }
+        if self.lower_has(name) {
+            create_whiteout(&dir, name)?;
+        }
+        self.forget(name);
+        Ok(())
+    }
+
+    fn symlink(&self, name: &str, target: &str, meta: Metadata) -> KResult<Inode> {
+        self.create_with(name, InodeKind::Symlink, |dir| dir.0.symlink(name, target, meta))
+    }
+
+    fn readlink(&self) -> KResult<String> {
+        self.real().0.readlink()
+    }
+
+    fn mkfifo(&self, name: &str, meta: Metadata) -> KResult<Inode> {
+        self.create_with(name, InodeKind::Fifo, |dir| dir.0.mkfifo(name, meta))
+    }
+
+    fn link(&self, name: &str, target: Inode) -> KResult<()> {
+        let target = Self::downcast(&target)
+            .filter(|t| Arc::ptr_eq(&t.fs, &self.fs))
+            .ok_or(KError::NotSupported)?;
+        if target.kind == InodeKind::Dir {
+            return Err(KError::PermissionDenied);
+        }
+        let source = target.copy_up()?;
+        self.create_with(name, target.kind, |dir| {
+            dir.0.link(name, source)?;
+            dir.0.lookup(name)
+        })
+        .map(|_| ())
+    }
+
+    fn rename_to(&self, old_name: &str, new_parent: &Inode, new_name: &str) -> KResult<()> {
+        let target = Self::downcast(new_parent)
+            .filter(|t| Arc::ptr_eq(&t.fs, &self.fs))
+            .ok_or(KError::NotSupported)?;
+        Self::check_name(new_name)?;
+        let source = self.lookup_child(old_name)?;
+        // A merged directory would need its lower parts to follow it
+        // (redirect_dir); callers fall back to copying (EXDEV)
+        if source.kind == InodeKind::Dir && !source.lower.is_empty() {
+            return Err(KError::NotSupported);
+        }
+
+        let dest = match target.lookup_child(new_name) {
+            Ok(dest) if Arc::ptr_eq(&dest, &source) => return Ok(()),
+            Ok(dest) if (dest.kind == InodeKind::Dir) != (source.kind == InodeKind::Dir) => {
+                return Err(if source.kind == InodeKind::Dir { KError::NotADirectory } else { KError::IsADirectory });
+            }
+            Ok(dest) => Some(dest),
+            Err(KError::NotFound) => None,
+            Err(e) => return Err(e),
+        };
+
+        let moved = source.copy_up()?;
+        let from = self.copy_up()?;
+        let to = target.copy_up()?;
+        match dest {
+            Some(dest) if dest.kind == InodeKind::Dir => target.rmdir(new_name)?,
+            Some(_) => target.unlink(new_name)?,
+            None => {}
+        }
+        clear_whiteout(&to, new_name)?;
+        from.0.rename_to(old_name, &to, new_name)?;
+
+        if source.kind == InodeKind::Dir && target.lower_has(new_name) {
+            set_opaque(&moved)?;
+        }
+        if self.lower_has(old_name) {
+            create_whiteout(&from, old_name)?;
+        }
+
+        self.forget(old_name);
+        target.forget(new_name);
+        *source.link.write() = Some((target.arc(), new_name.to_string()));
+        target.children.lock().insert(new_name.to_string(), Arc::downgrade(&source));
+        Ok(())
+    }
+
+    fn read_at(&self, offset: usize, out: &mut [u8]) -> KResult<usize> {
+        self.real().0.read_at(offset, out)
+    }
+
+    fn write_at(&self, offset: usize, data: &[u8]) -> KResult<usize> {
+        // FIFOs are not copied up to be written
+        let inode = if self.kind == InodeKind::File { self.copy_up()? } else { self.real() };
+        inode.0.write_at(offset, data)
+    }
+
+    fn truncate(&self, size: usize) -> KResult<()> {
+        self.copy_up()?.0.truncate(size)
+    }
+
+    fn size(&self) -> KResult<usize> {
+        self.real().0.size()
+    }
+
+    fn getxattr(&self, name: &str) -> KResult<Vec<u8>> {
+        if name.starts_with(XATTR_PREFIX) {
+            return Err(KError::NotFound);
+        }
+        self.real().0.getxattr(name)
+    }
+
+    fn setxattr(&self, name: &str, value: Vec<u8>, flags: XattrFlags) -> KResult<()> {
+        if name.starts_with(XATTR_PREFIX) {
+            return Err(KError::PermissionDenied);
+        }
+        self.copy_up()?.0.setxattr(name, value, flags)
+    }
+
+    fn removexattr(&self, name: &str) -> KResult<()> {
+        if name.starts_with(XATTR_PREFIX) {
+            return Err(KError::PermissionDenied);
+        }
+        self.copy_up()?.0.removexattr(name)
+    }
+
+    fn listxattr(&self) -> KResult<Vec<String>> {
+        let mut names = self.real().0.listxattr()?;
+        names.retain(|name| !name.starts_with(XATTR_PREFIX));
+        Ok(names)
+    }
+
+    fn as_any(&self) -> Option<&dyn core::any::Any> {
+        Some(self)
+    }
+
+    /// Space is that of the upper layer, or of the top lower layer when
+    /// the overlay is read-only
+    fn statfs(&self) -> KResult<FsStats> {
+        let layer = self.fs.upper.as_ref().unwrap_or(&self.fs.lower[0]);
+        let stats = layer.0.statfs()?;
+        Ok(FsStats { magic: OVERLAYFS_MAGIC, ..stats })
+    }
+}
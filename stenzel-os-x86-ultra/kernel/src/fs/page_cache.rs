@@ -10,6 +10,10 @@
 //! - Per-device caching with device ID
 //! - Configurable cache size
 //! - Sync/flush support
+//!
+//! Pages of regular files mapped with mmap are cached separately (see
+//! "File pages" below): they are keyed by file and page index and own whole
+//! physical frames, so every mapping of a file page shares the same frame.
 
 #![allow(dead_code)]
 
@@ -18,8 +22,11 @@ use alloc::sync::Arc;
 use alloc::vec::Vec;
 use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
 use spin::RwLock;
+use x86_64::structures::paging::{PhysFrame, Size4KiB};
 
+use crate::fs::vfs::{self, Inode};
 use crate::storage::{BlockDevice, BlockDeviceId};
+use crate::sync::IrqSafeMutex;
 use crate::util::{KError, KResult};
 
 /// Default maximum number of cached pages
@@ -480,4 +487,465 @@ pub fn print_stats() {
                      hits, misses, hit_rate);
     crate::kprintln!("page_cache: evictions={} writebacks={}",
                      evictions, writebacks);
+    let (cached, mapped, dirty) = file_page_stats();
+    crate::kprintln!("page_cache: file pages={} mapped={} dirty={}",
+                     cached, mapped, dirty);
+}
+
+// ============================================================
+// File pages
+// ============================================================
+
+const PAGE_SIZE: usize = 4096;
+
+/// Unmapped file pages kept around before the least recently used ones
+/// are evicted
+const MAX_UNMAPPED_FILE_PAGES: usize = DEFAULT_MAX_PAGES;
+
+/// Identity of a file, as returned by `vfs::file_id`
+type FileId = (usize, u64);
+
+/// A page of a regular file backing one or more mappings
+struct FilePage {
+    frame: PhysFrame<Size4KiB>,
+    /// Page table entries pointing at the frame
+    mapcount: usize,
+    /// Entries among those that are writable. A page with writers can be
+    /// modified at any moment and is never considered clean.
+    writers: usize,
+    /// Modified since the last writeback
+    dirty: bool,
+    /// Access counter for LRU tracking
+    access_count: u64,
+}
+
+impl FilePage {
+    fn needs_writeback(&self) -> bool {
+        self.dirty || self.writers > 0
+    }
+}
+
+struct CachedFile {
+    inode: Inode,
+    pages: BTreeMap<u64, FilePage>,
+}
+
+struct FilePages {
+    files: BTreeMap<FileId, CachedFile>,
+    /// Frame address -> owning file and page index, for the fault and fork
+    /// paths which only see page table entries
+    frames: BTreeMap<u64, (FileId, u64)>,
+    /// Cached pages with no mapping left
+    unmapped: usize,
+    access_counter: u64,
+}
+
+impl FilePages {
+    const fn new() -> Self {
+        Self {
+            files: BTreeMap::new(),
+            frames: BTreeMap::new(),
+            unmapped: 0,
+            access_counter: 0,
+        }
+    }
+
+    fn page_of(&mut self, frame: PhysFrame<Size4KiB>) -> Option<&mut FilePage> {
+        let (id, index) = *self.frames.get(&frame.start_address().as_u64())?;
+        self.files.get_mut(&id)?.pages.get_mut(&index)
+    }
+
+    /// Add a mapping to an already cached page
+    fn map_existing(&mut self, id: FileId, index: u64) -> Option<PhysFrame<Size4KiB>> {
+        self.access_counter += 1;
+        let counter = self.access_counter;
+        let page = self.files.get_mut(&id)?.pages.get_mut(&index)?;
+        if page.mapcount == 0 {
+            self.unmapped -= 1;
+        }
+        page.mapcount += 1;
+        page.access_count = counter;
+        Some(page.frame)
+    }
+
+    fn remove(&mut self, id: FileId, index: u64) -> Option<(Inode, FilePage)> {
+        let file = self.files.get_mut(&id)?;
+        let page = file.pages.remove(&index)?;
+        let inode = file.inode.clone();
+        if file.pages.is_empty() {
+            self.files.remove(&id);
+        }
+        self.frames.remove(&page.frame.start_address().as_u64());
+        if page.mapcount == 0 {
+            self.unmapped -= 1;
+        }
+        CACHED_FILE_PAGES.fetch_sub(1, Ordering::Relaxed);
+        Some((inode, page))
+    }
+}
+
+static FILE_PAGES: IrqSafeMutex<FilePages> = IrqSafeMutex::new(FilePages::new());
+
+/// Number of cached file pages, lets read() and write() skip the lock
+static CACHED_FILE_PAGES: AtomicUsize = AtomicUsize::new(0);
+
+fn frame_bytes(frame: PhysFrame<Size4KiB>) -> &'static mut [u8] {
+    let virt = crate::mm::phys_to_virt(frame.start_address());
+    unsafe { core::slice::from_raw_parts_mut(virt.as_mut_ptr::<u8>(), PAGE_SIZE) }
+}
+
+/// Contents of a dirty page, copied under the lock and written outside it
+struct Writeback {
+    inode: Inode,
+    index: u64,
+    data: Vec<u8>,
+}
+
+impl Writeback {
+    fn take(inode: &Inode, index: u64, page: &mut FilePage) -> Self {
+        page.dirty = page.writers > 0;
+        Writeback {
+            inode: inode.clone(),
+            index,
+            data: frame_bytes(page.frame).to_vec(),
+        }
+    }
+
+    /// Write the page back without extending the file
+    fn run(&self) -> KResult<()> {
+        let size = self.inode.0.size()?;
+        let offset = self.index as usize * PAGE_SIZE;
+        if offset >= size {
+            return Ok(());
+        }
+        let len = (size - offset).min(PAGE_SIZE);
+        self.inode.0.write_at(offset, &self.data[..len])?;
+        PAGE_CACHE.stats.writebacks.fetch_add(1, Ordering::Relaxed);
+        Ok(())
+    }
+}
+
+fn run_writebacks(jobs: Vec<Writeback>) -> KResult<()> {
+    let mut result = Ok(());
+    for job in jobs {
+        if let Err(e) = job.run() {
+            result = Err(e);
+        }
+    }
+    result
+}
+
+/// Map page `index` of `inode`, reading it in on first use.
+///
+/// Returns the frame holding the page, which now counts one more mapping.
+/// Pages wholly past the end of the file fail with `OutOfRange`.
+pub fn map_file_page(inode: &Inode, index: u64) -> KResult<PhysFrame<Size4KiB>> {
+    let id = vfs::file_id(inode);
+    if let Some(frame) = FILE_PAGES.lock().map_existing(id, index) {
+        PAGE_CACHE.stats.hits.fetch_add(1, Ordering::Relaxed);
+        return Ok(frame);
+    }
+    PAGE_CACHE.stats.misses.fetch_add(1, Ordering::Relaxed);
+
+    let offset = index as usize * PAGE_SIZE;
+    if offset >= inode.0.size()? {
+        return Err(KError::OutOfRange);
+    }
+
+    let frame = match crate::mm::alloc_frame() {
+        Some(frame) => frame,
+        None => {
+            shrink_file_pages(16);
+            crate::mm::alloc_frame().ok_or(KError::NoMemory)?
+        }
+    };
+    let data = frame_bytes(frame);
+    data.fill(0);
+    if let Err(e) = inode.0.read_at(offset, data) {
+        crate::mm::free_frame(frame);
+        return Err(e);
+    }
+
+    let mut fp = FILE_PAGES.lock();
+    // Someone else may have read the page in while the lock was dropped
+    if let Some(existing) = fp.map_existing(id, index) {
+        drop(fp);
+        crate::mm::free_frame(frame);
+        return Ok(existing);
+    }
+    fp.access_counter += 1;
+    let page = FilePage {
+        frame,
+        mapcount: 1,
+        writers: 0,
+        dirty: false,
+        access_count: fp.access_counter,
+    };
+    fp.files
+        .entry(id)
+        .or_insert_with(|| CachedFile { inode: inode.clone(), pages: BTreeMap::new() })
+        .pages
+        .insert(index, page);
+    fp.frames.insert(frame.start_address().as_u64(), (id, index));
+    CACHED_FILE_PAGES.fetch_add(1, Ordering::Relaxed);
+    Ok(frame)
+}
+
+/// Drop one mapping of a file page. `writable` says whether the entry being
+/// removed was counted as a writer. The last mapping writes a dirty page
+/// back; the page itself stays cached until evicted.
+pub fn unmap_file_page(frame: PhysFrame<Size4KiB>, writable: bool) {
+    let mut fp = FILE_PAGES.lock();
+    let (id, index) = match fp.frames.get(&frame.start_address().as_u64()) {
+        Some(&owner) => owner,
+        None => return,
+    };
+    let file = match fp.files.get_mut(&id) {
+        Some(file) => file,
+        None => return,
+    };
+    let page = match file.pages.get_mut(&index) {
+        Some(page) => page,
+        None => return,
+    };
+    if writable {
+        page.writers = page.writers.saturating_sub(1);
+    }
+    page.mapcount = page.mapcount.saturating_sub(1);
+    let mut job = None;
+    if page.mapcount == 0 {
+        if page.needs_writeback() {
+            job = Some(Writeback::take(&file.inode, index, page));
+        }
+        fp.unmapped += 1;
+    }
+    let excess = fp.unmapped.saturating_sub(MAX_UNMAPPED_FILE_PAGES);
+    drop(fp);
+
+    if let Some(job) = job {
+        let _ = job.run();
+    }
+    if excess > 0 {
+        evict_unmapped(excess);
+    }
+}
+
+/// Account for a page table entry copied by fork. Returns false if the
+/// frame is not a file page.
+pub fn dup_file_page(frame: PhysFrame<Size4KiB>, writable: bool) -> bool {
+    let mut fp = FILE_PAGES.lock();
+    match fp.page_of(frame) {
+        Some(page) => {
+            page.mapcount += 1;
+            if writable {
+                page.writers += 1;
+            }
+            true
+        }
+        None => false,
+    }
+}
+
+/// A shared mapping was made writable after a write fault
+pub fn mark_page_written(frame: PhysFrame<Size4KiB>) -> bool {
+    let mut fp = FILE_PAGES.lock();
+    match fp.page_of(frame) {
+        Some(page) => {
+            page.writers += 1;
+            page.dirty = true;
+            true
+        }
+        None => false,
+    }
+}
+
+/// A shared mapping was write-protected again (msync, mprotect). The page
+/// stays dirty until written back.
+pub fn page_write_protected(frame: PhysFrame<Size4KiB>) {
+    if let Some(page) = FILE_PAGES.lock().page_of(frame) {
+        page.writers = page.writers.saturating_sub(1);
+    }
+}
+
+/// Whether `frame` belongs to the file page cache
+pub fn is_file_page(frame: PhysFrame<Size4KiB>) -> bool {
+    FILE_PAGES.lock().frames.contains_key(&frame.start_address().as_u64())
+}
+
+/// Write back dirty pages `first..=last` of `inode`
+pub fn writeback_file_range(inode: &Inode, first: u64, last: u64) -> KResult<()> {
+    let id = vfs::file_id(inode);
+    let jobs: Vec<Writeback> = {
+        let mut fp = FILE_PAGES.lock();
+        match fp.files.get_mut(&id) {
+            Some(file) => {
+                let inode = file.inode.clone();
+                file.pages
+                    .range_mut(first..=last)
+                    .filter(|(_, page)| page.needs_writeback())
+                    .map(|(&index, page)| Writeback::take(&inode, index, page))
+                    .collect()
+            }
+            None => return Ok(()),
+        }
+    };
+    run_writebacks(jobs)
+}
+
+/// Write back every dirty file page. Returns the number of pages written.
+pub fn writeback_file_pages() -> usize {
+    let jobs: Vec<Writeback> = {
+        let mut fp = FILE_PAGES.lock();
+        let mut jobs = Vec::new();
+        for file in fp.files.values_mut() {
+            for (&index, page) in file.pages.iter_mut() {
+                if page.needs_writeback() {
+                    jobs.push(Writeback::take(&file.inode, index, page));
+                }
+            }
+        }
+        jobs
+    };
+    let count = jobs.len();
+    let _ = run_writebacks(jobs);
+    count
+}
+
+/// Free up to `target` unmapped pages, least recently used first
+fn evict_unmapped(target: usize) -> usize {
+    let victims: Vec<(Inode, u64, FilePage)> = {
+        let mut fp = FILE_PAGES.lock();
+        let mut candidates: Vec<(u64, FileId, u64)> = fp
+            .files
+            .iter()
+            .flat_map(|(&id, file)| {
+                file.pages
+                    .iter()
+                    .filter(|(_, page)| page.mapcount == 0)
+                    .map(move |(&index, page)| (page.access_count, id, index))
+            })
+            .collect();
+        candidates.sort_unstable();
+        candidates
+            .into_iter()
+            .take(target)
+            .filter_map(|(_, id, index)| {
+                fp.remove(id, index).map(|(inode, page)| (inode, index, page))
+            })
+            .collect()
+    };
+
+    let count = victims.len();
+    for (inode, index, mut page) in victims {
+        if page.needs_writeback() {
+            let _ = Writeback::take(&inode, index, &mut page).run();
+        }
+        crate::mm::free_frame(page.frame);
+        PAGE_CACHE.stats.evictions.fetch_add(1, Ordering::Relaxed);
+    }
+    count
+}
+
+/// Memory pressure: write back dirty file pages and free up to `target`
+/// unmapped ones. Returns the number of frames freed.
+pub fn shrink_file_pages(target: usize) -> usize {
+    writeback_file_pages();
+    evict_unmapped(target)
+}
+
+/// Write back and free every unmapped file page. Cached pages keep their
+/// file's mount busy, so this runs before an unmount.
+pub fn drop_unmapped_file_pages() {
+    evict_unmapped(usize::MAX);
+}
+
+/// The file shrank to `size` bytes: forget unmapped pages past the new end
+/// and clear the tail of the last page.
+pub fn truncate_file_pages(inode: &Inode, size: usize) {
+    if CACHED_FILE_PAGES.load(Ordering::Relaxed) == 0 {
+        return;
+    }
+    let id = vfs::file_id(inode);
+    let first_gone = ((size + PAGE_SIZE - 1) / PAGE_SIZE) as u64;
+    let freed: Vec<PhysFrame<Size4KiB>> = {
+        let mut fp = FILE_PAGES.lock();
+        let file = match fp.files.get(&id) {
+            Some(file) => file,
+            None => return,
+        };
+        if size % PAGE_SIZE != 0 {
+            if let Some(page) = file.pages.get(&((size / PAGE_SIZE) as u64)) {
+                frame_bytes(page.frame)[size % PAGE_SIZE..].fill(0);
+            }
+        }
+        let gone: Vec<u64> = file
+            .pages
+            .range(first_gone..)
+            .filter(|(_, page)| page.mapcount == 0)
+            .map(|(&index, _)| index)
+            .collect();
+        gone.into_iter()
+            .filter_map(|index| fp.remove(id, index))
+            .map(|(_, page)| page.frame)
+            .collect()
+    };
+    for frame in freed {
+        crate::mm::free_frame(frame);
+    }
+}
+
+/// Copy cached pages over data just read from `inode` at `offset`, so
+/// read() sees stores made through shared mappings
+pub fn read_overlay(inode: &Inode, offset: usize, buf: &mut [u8]) {
+    copy_cached(inode, offset, buf.len(), |page, range, at| {
+        buf[at..at + range.len()].copy_from_slice(&page[range]);
+    });
+}
+
+/// Apply data written to `inode` at `offset` to the cached pages, so
+/// mappings see write()
+pub fn write_through(inode: &Inode, offset: usize, data: &[u8]) {
+    copy_cached(inode, offset, data.len(), |page, range, at| {
+        let len = range.len();
+        page[range].copy_from_slice(&data[at..at + len]);
+    });
+}
+
+/// Call `f(page, range within page, position in the caller's buffer)` for
+/// every cached page overlapping `offset..offset + len`
+fn copy_cached(
+    inode: &Inode,
+    offset: usize,
+    len: usize,
+    mut f: impl FnMut(&mut [u8], core::ops::Range<usize>, usize),
+) {
+    if len == 0 || CACHED_FILE_PAGES.load(Ordering::Relaxed) == 0 {
+        return;
+    }
+    let id = vfs::file_id(inode);
+    let end = offset + len;
+    let fp = FILE_PAGES.lock();
+    let file = match fp.files.get(&id) {
+        Some(file) => file,
+        None => return,
+    };
+    let first = (offset / PAGE_SIZE) as u64;
+    let last = ((end - 1) / PAGE_SIZE) as u64;
+    for (&index, page) in file.pages.range(first..=last) {
+        let page_start = index as usize * PAGE_SIZE;
+        let from = offset.max(page_start);
+        let to = end.min(page_start + PAGE_SIZE);
+        f(frame_bytes(page.frame), from - page_start..to - page_start, from - offset);
+    }
+}
+
+/// File page statistics: (cached, mapped, dirty)
+pub fn file_page_stats() -> (usize, usize, usize) {
+    let fp = FILE_PAGES.lock();
+    let pages = || fp.files.values().flat_map(|file| file.pages.values());
+    (
+        pages().count(),
+        pages().filter(|page| page.mapcount > 0).count(),
+        pages().filter(|page| page.needs_writeback()).count(),
+    )
 }
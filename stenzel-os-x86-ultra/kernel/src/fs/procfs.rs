@@ -12,6 +12,7 @@ use alloc::vec::Vec;
 
 use spin::RwLock;
 
+use crate::ipc::namespace::MountNamespace;
 use crate::security::{Gid, Uid};
 use crate::util::{KError, KResult};
 
@@ -28,6 +29,10 @@ enum ProcContent {
     ProcessStatus { pid: u64 },
     /// Arquivo /proc/[pid]/cmdline
     ProcessCmdline { pid: u64 },
+    /// Arquivo /proc/[pid]/maps
+    ProcessMaps { pid: u64 },
+    /// Arquivo /proc/[pid]/smaps
+    ProcessSmaps { pid: u64 },
     /// Arquivo /proc/meminfo
     Meminfo,
     /// Arquivo /proc/uptime
@@ -38,6 +43,10 @@ enum ProcContent {
     Cpuinfo,
     /// Arquivo /proc/pagecache
     PageCache,
+    /// Diretório /proc/[pid]/ns
+    NsDir { pid: u64 },
+    /// Arquivo /proc/[pid]/ns/mnt (handle for setns)
+    NsMnt { pid: u64 },
 }
 
 /// Inode do procfs.
@@ -50,7 +59,7 @@ pub struct ProcfsInode {
 impl ProcfsInode {
     fn metadata_for_content(content: &ProcContent) -> Metadata {
         match content {
-            ProcContent::Root | ProcContent::ProcessDir { .. } => Metadata::simple(
+            ProcContent::Root | ProcContent::ProcessDir { .. } | ProcContent::NsDir { .. } => Metadata::simple(
                 Uid(0),
                 Gid(0),
                 Mode::from_octal(0o555),
@@ -75,6 +84,9 @@ impl ProcfsInode {
             ProcContent::PageCache => generate_pagecache(),
             ProcContent::ProcessStatus { pid } => generate_process_status(*pid),
             ProcContent::ProcessCmdline { pid } => generate_process_cmdline(*pid),
+            ProcContent::ProcessMaps { pid } => generate_process_maps(*pid, false),
+            ProcContent::ProcessSmaps { pid } => generate_process_maps(*pid, true),
+            ProcContent::NsMnt { pid } => generate_ns_mnt(*pid),
             _ => Vec::new(),
         }
     }
@@ -105,8 +117,14 @@ impl ProcfsInode {
                 vec![
                     DirEntry { name: "status".to_string(), kind: InodeKind::File },
                     DirEntry { name: "cmdline".to_string(), kind: InodeKind::File },
+                    DirEntry { name: "maps".to_string(), kind: InodeKind::File },
+                    DirEntry { name: "smaps".to_string(), kind: InodeKind::File },
+                    DirEntry { name: "ns".to_string(), kind: InodeKind::Dir },
                 ]
             }
+            ProcContent::NsDir { .. } => {
+                vec![DirEntry { name: "mnt".to_string(), kind: InodeKind::File }]
+            }
             _ => Vec::new(),
         }
     }
@@ -136,6 +154,15 @@ impl ProcfsInode {
                 match name {
                     "status" => Ok(ProcContent::ProcessStatus { pid: *pid }),
                     "cmdline" => Ok(ProcContent::ProcessCmdline { pid: *pid }),
+                    "maps" => Ok(ProcContent::ProcessMaps { pid: *pid }),
+                    "smaps" => Ok(ProcContent::ProcessSmaps { pid: *pid }),
+                    "ns" => Ok(ProcContent::NsDir { pid: *pid }),
+                    _ => Err(KError::NotFound),
+                }
+            }
+            ProcContent::NsDir { pid } => {
+                match name {
+                    "mnt" => Ok(ProcContent::NsMnt { pid: *pid }),
                     _ => Err(KError::NotFound),
                 }
             }
@@ -204,6 +231,10 @@ impl InodeOps for ProcfsInode {
     fn size(&self) -> KResult<usize> {
         Ok(self.generate_content().len())
     }
+
+    fn as_any(&self) -> Option<&dyn core::any::Any> {
+        Some(self)
+    }
 }
 
 /// Cria o inode raiz do procfs (/proc).
@@ -216,6 +247,16 @@ pub fn new_root() -> Inode {
     Inode(inode)
 }
 
+/// Mount namespace behind an open /proc/[pid]/ns/mnt, for setns.
+pub fn mnt_ns_of(inode: &Inode) -> Option<Arc<MountNamespace>> {
+    let inner = super::vfs::unwrap_mounted(inode);
+    let proc_inode = inner.0.as_any()?.downcast_ref::<ProcfsInode>()?;
+    match proc_inode.content {
+        ProcContent::NsMnt { pid } => crate::sched::task_mnt_ns(pid),
+        _ => None,
+    }
+}
+
 // ==================== Geradores de conteúdo ====================
 
 fn generate_meminfo() -> Vec<u8> {
@@ -288,6 +329,55 @@ fn generate_process_cmdline(pid: u64) -> Vec<u8> {
     }
 }
 
+/// Linhas de /proc/[pid]/maps; com `detail`, o formato de smaps.
+fn generate_process_maps(pid: u64, detail: bool) -> Vec<u8> {
+    let vmas = match crate::sched::task_vmas(pid) {
+        Some(vmas) => vmas,
+        None => return Vec::new(),
+    };
+    let manager = vmas.lock();
+
+    let mut out = alloc::string::String::new();
+    for vma in manager.iter() {
+        let ino = vma.file.as_ref().map_or(0, |f| f.inode.metadata().ino);
+        let name = vma.special.map(|n| format!("                          {}", n)).unwrap_or_default();
+        out.push_str(&format!(
+            "{:08x}-{:08x} {}{}{}{} {:08x} 00:00 {}{}\n",
+            vma.start,
+            vma.end(),
+            if vma.prot.read { 'r' } else { '-' },
+            if vma.prot.write { 'w' } else { '-' },
+            if vma.prot.exec { 'x' } else { '-' },
+            if vma.flags.shared { 's' } else { 'p' },
+            vma.file_offset(),
+            ino,
+            name,
+        ));
+        if !detail {
+            continue;
+        }
+
+        let usage = vma.usage();
+        out.push_str(&format!(
+            "Size:           {:8} kB\n\
+             Rss:            {:8} kB\n\
+             Shared_Clean:   {:8} kB\n\
+             Shared_Dirty:   {:8} kB\n\
+             Private_Clean:  {:8} kB\n\
+             Private_Dirty:  {:8} kB\n\
+             Anonymous:      {:8} kB\n",
+            vma.size / 1024,
+            usage.rss * 4,
+            usage.shared_clean * 4,
+            usage.shared_dirty * 4,
+            usage.private_clean * 4,
+            usage.private_dirty * 4,
+            usage.anonymous * 4,
+        ));
+    }
+    out.into_bytes()
+}
+
 fn generate_pagecache() -> Vec<u8> {
     let cache = super::page_cache::cache();
     let (hits, misses, evictions, writebacks) = cache.stats();
@@ -318,3 +408,10 @@ fn generate_pagecache() -> Vec<u8> {
         writebacks,
     ).into_bytes()
 }
+
+fn generate_ns_mnt(pid: u64) -> Vec<u8> {
+    match crate::sched::task_mnt_ns(pid) {
+        Some(ns) => format!("mnt:[{}]\n", ns.id).into_bytes(),
+        None => Vec::new(),
+    }
+}
@@ -12,6 +12,7 @@ use alloc::vec::Vec;
 
 use spin::RwLock;
 
+use crate::security::mac;
 use crate::security::{Gid, Uid};
 use crate::util::{KError, KResult};
 
@@ -28,6 +29,12 @@ enum SysContent {
     Devices,
     /// Diretório /sys/class
     Class,
+    /// Diretório /sys/fs
+    Fs,
+    /// Diretório /sys/fs/bpf (ponto de montagem do bpffs)
+    FsBpf,
+    /// Diretório /sys/fs/cgroup (ponto de montagem do cgroup2)
+    FsCgroup,
     /// Diretório /sys/class/block
     ClassBlock,
     /// Diretório /sys/class/tty
@@ -58,6 +65,16 @@ enum SysContent {
     Version,
     /// Arquivo /sys/kernel/ostype
     OsType,
+    /// Diretório /sys/kernel/security
+    KernelSecurity,
+    /// Diretório /sys/kernel/security/mac
+    SecurityMac,
+    /// Arquivo /sys/kernel/security/mac/mode (disabled, permissive ou enforcing)
+    MacMode,
+    /// Arquivo /sys/kernel/security/mac/audit (negações recentes)
+    MacAudit,
+    /// Arquivo /sys/kernel/security/mac/stats
+    MacStats,
     /// Diretório /sys/devices/system
     DevicesSystem,
     /// Diretório /sys/devices/system/cpu
@@ -85,8 +102,13 @@ impl SysfsInode {
             // Diretórios
             SysContent::Root
             | SysContent::Kernel
+            | SysContent::KernelSecurity
+            | SysContent::SecurityMac
             | SysContent::Devices
             | SysContent::Class
+            | SysContent::Fs
+            | SysContent::FsBpf
+            | SysContent::FsCgroup
             | SysContent::ClassBlock
             | SysContent::ClassTty
             | SysContent::ClassGraphics
@@ -100,6 +122,13 @@ impl SysfsInode {
                 Mode::from_octal(0o755),
                 InodeKind::Dir,
             ),
+            // Arquivos graváveis pelo root
+            SysContent::MacMode => Metadata::simple(
+                Uid(0),
+                Gid(0),
+                Mode::from_octal(0o644),
+                InodeKind::File,
+            ),
             // Arquivos
             _ => Metadata::simple(
                 Uid(0),
@@ -120,6 +149,19 @@ impl SysfsInode {
                 env!("CARGO_PKG_VERSION")
             ).into_bytes(),
             SysContent::OsType => b"StenzelOS\n".to_vec(),
+            SysContent::MacMode => match mac::get_mode() {
+                mac::MacMode::Disabled => b"disabled\n".to_vec(),
+                mac::MacMode::Permissive => b"permissive\n".to_vec(),
+                mac::MacMode::Enforcing => b"enforcing\n".to_vec(),
+            },
+            SysContent::MacAudit => mac::audit_log().into_bytes(),
+            SysContent::MacStats => {
+                let (checks, allowed, denied, audited, hits, misses) = mac::get_stats();
+                format!(
+                    "checks {}\nallowed {}\ndenied {}\naudited {}\ncache_hits {}\ncache_misses {}\n",
+                    checks, allowed, denied, audited, hits, misses
+                ).into_bytes()
+            }
             SysContent::CpuOnline => b"0\n".to_vec(), // CPU 0 online
             SysContent::CpuPossible => b"0\n".to_vec(), // Apenas CPU 0 por enquanto
             // Framebuffer entries
@@ -166,12 +208,26 @@ impl SysfsInode {
                 DirEntry { name: "kernel".to_string(), kind: InodeKind::Dir },
                 DirEntry { name: "devices".to_string(), kind: InodeKind::Dir },
                 DirEntry { name: "class".to_string(), kind: InodeKind::Dir },
+                DirEntry { name: "fs".to_string(), kind: InodeKind::Dir },
+            ],
+            SysContent::Fs => vec![
+                DirEntry { name: "bpf".to_string(), kind: InodeKind::Dir },
+                DirEntry { name: "cgroup".to_string(), kind: InodeKind::Dir },
             ],
             SysContent::Kernel => vec![
                 DirEntry { name: "hostname".to_string(), kind: InodeKind::File },
                 DirEntry { name: "osrelease".to_string(), kind: InodeKind::File },
                 DirEntry { name: "version".to_string(), kind: InodeKind::File },
                 DirEntry { name: "ostype".to_string(), kind: InodeKind::File },
+                DirEntry { name: "security".to_string(), kind: InodeKind::Dir },
+            ],
+            SysContent::KernelSecurity => vec![
+                DirEntry { name: "mac".to_string(), kind: InodeKind::Dir },
+            ],
+            SysContent::SecurityMac => vec![
+                DirEntry { name: "mode".to_string(), kind: InodeKind::File },
+                DirEntry { name: "audit".to_string(), kind: InodeKind::File },
+                DirEntry { name: "stats".to_string(), kind: InodeKind::File },
             ],
             SysContent::Devices => vec![
                 DirEntry { name: "system".to_string(), kind: InodeKind::Dir },
@@ -234,6 +290,12 @@ impl SysfsInode {
                 "kernel" => Ok(SysContent::Kernel),
                 "devices" => Ok(SysContent::Devices),
                 "class" => Ok(SysContent::Class),
+                "fs" => Ok(SysContent::Fs),
+                _ => Err(KError::NotFound),
+            },
+            SysContent::Fs => match name {
+                "bpf" => Ok(SysContent::FsBpf),
+                "cgroup" => Ok(SysContent::FsCgroup),
                 _ => Err(KError::NotFound),
             },
             SysContent::Kernel => match name {
@@ -241,6 +303,17 @@ impl SysfsInode {
                 "osrelease" => Ok(SysContent::OsRelease),
                 "version" => Ok(SysContent::Version),
                 "ostype" => Ok(SysContent::OsType),
+                "security" => Ok(SysContent::KernelSecurity),
+                _ => Err(KError::NotFound),
+            },
+            SysContent::KernelSecurity => match name {
+                "mac" => Ok(SysContent::SecurityMac),
+                _ => Err(KError::NotFound),
+            },
+            SysContent::SecurityMac => match name {
+                "mode" => Ok(SysContent::MacMode),
+                "audit" => Ok(SysContent::MacAudit),
+                "stats" => Ok(SysContent::MacStats),
                 _ => Err(KError::NotFound),
             },
             SysContent::Devices => match name {
@@ -343,9 +416,23 @@ impl InodeOps for SysfsInode {
         Ok(n)
     }
 
-    fn write_at(&self, _offset: usize, _data: &[u8]) -> KResult<usize> {
-        // sysfs é read-only (por enquanto)
-        Err(KError::NotSupported)
+    fn write_at(&self, _offset: usize, data: &[u8]) -> KResult<usize> {
+        match self.content {
+            SysContent::MacMode => {
+                let text = core::str::from_utf8(data).map_err(|_| KError::Invalid)?;
+                let mode = match text.trim() {
+                    "disabled" => mac::MacMode::Disabled,
+                    "permissive" | "0" => mac::MacMode::Permissive,
+                    "enforcing" | "1" => mac::MacMode::Enforcing,
+                    _ => return Err(KError::Invalid),
+                };
+                mac::init();
+                mac::set_mode(mode);
+                Ok(data.len())
+            }
+            // O resto do sysfs é read-only
+            _ => Err(KError::NotSupported),
+        }
     }
 
     fn truncate(&self, _size: usize) -> KResult<()> {
@@ -5,10 +5,14 @@
     use alloc::sync::Arc;
     use alloc::vec;
     use alloc::vec::Vec;
+    use core::sync::atomic::{AtomicUsize, Ordering};
 
+    use crate::security::hooks;
+    use crate::security::mac::Permission as MacPermission;
     use crate::security::{Cred, Gid, Uid};
     use crate::util::{KError, KResult};
 
+    use super::inotify;
     use super::perm;
 
     #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -82,6 +86,42 @@
         }
     }
 
+    bitflags::bitflags! {
+        /// Per-mount flags (same bit values as the Linux MS_* / ST_* constants)
+        #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+        pub struct MountFlags: u64 {
+            /// Writes through this mount fail with EROFS
+            const RDONLY = 1;
+            /// Ignore set-user-ID and set-group-ID bits on exec
+            const NOSUID = 2;
+            /// Do not allow access to device nodes
+            const NODEV = 4;
+            /// Do not allow programs to be executed
+            const NOEXEC = 8;
+        }
+    }
+
+    /// Filesystem statistics reported by statfs()
+    #[derive(Debug, Clone, Copy, Default)]
+    pub struct FsStats {
+        /// Filesystem magic number (f_type)
+        pub magic: u64,
+        /// Allocation unit in bytes
+        pub block_size: u64,
+        /// Total data blocks
+        pub blocks: u64,
+        /// Free blocks
+        pub blocks_free: u64,
+        /// Free blocks available to unprivileged users
+        pub blocks_avail: u64,
+        /// Total inodes (0 if the filesystem has no fixed inode table)
+        pub files: u64,
+        /// Free inodes
+        pub files_free: u64,
+        /// Maximum file name length
+        pub name_max: u64,
+    }
+
     /// Timestamp for file metadata
     #[derive(Debug, Clone, Copy, Default)]
     pub struct Timespec {
@@ -182,6 +222,12 @@
             Err(KError::NotSupported)
         }
 
+        /// Se o diretório pode ser removido com rmdir. Pseudo-filesystems
+        /// com arquivos fixos em cada diretório (cgroup2) contam só o resto.
+        fn is_empty_dir(&self) -> KResult<bool> {
+            Ok(self.readdir()?.is_empty())
+        }
+
         /// Create a symbolic link pointing to target
         fn symlink(&self, _name: &str, _target: &str, _meta: Metadata) -> KResult<Inode> {
             Err(KError::NotSupported)
@@ -233,6 +279,22 @@
         fn listxattr(&self) -> KResult<Vec<String>> {
             Err(KError::NotSupported)
         }
+
+        /// Concrete type access, used by filesystems to recognize their own
+        /// inodes (e.g. the target of link() or the new parent of rename_to())
+        fn as_any(&self) -> Option<&dyn core::any::Any> {
+            None
+        }
+
+        /// Block and inode counts of the filesystem this inode belongs to
+        fn statfs(&self) -> KResult<FsStats> {
+            Err(KError::NotSupported)
+        }
+
+        /// Flags of the mount this inode was reached through
+        fn mount_flags(&self) -> MountFlags {
+            MountFlags::empty()
+        }
     }
 
     #[derive(Clone)]
@@ -247,10 +309,301 @@
         }
     }
 
+    /// State shared by every inode handed out through one mount
+    struct MountRef {
+        flags: MountFlags,
+        fs_type: String,
+        /// Number of mount tables (chroot views included) holding the mount
+        attached: AtomicUsize,
+    }
+
+    impl MountRef {
+        fn new(flags: MountFlags, fs_type: &str) -> Arc<Self> {
+            Arc::new(MountRef {
+                flags,
+                fs_type: fs_type.to_string(),
+                attached: AtomicUsize::new(0),
+            })
+        }
+
+        /// Whether inodes reached through the mount are still alive
+        fn is_busy(self: &Arc<Self>) -> bool {
+            Arc::strong_count(self) > self.attached.load(Ordering::Acquire)
+        }
+    }
+
+    /// A filesystem (or bind source) attached to the tree
+    struct Mount {
+        root: Inode,
+        source: String,
+        /// One strong reference per inode handed out through this mount
+        mnt: Arc<MountRef>,
+    }
+
+    impl Mount {
+        fn new(root: Inode, source: &str, mnt: Arc<MountRef>) -> Self {
+            mnt.attached.fetch_add(1, Ordering::AcqRel);
+            Mount {
+                root: unwrap_mounted(&root),
+                source: source.to_string(),
+                mnt,
+            }
+        }
+
+        fn root(&self) -> Inode {
+            MountedInode::wrap(self.root.clone(), &self.mnt)
+        }
+    }
+
+    /// Clones share the mount: same flags, and the same busy accounting.
+    impl Clone for Mount {
+        fn clone(&self) -> Self {
+            Mount::new(self.root.clone(), &self.source, Arc::clone(&self.mnt))
+        }
+    }
+
+    impl Drop for Mount {
+        fn drop(&mut self) {
+            self.mnt.attached.fetch_sub(1, Ordering::AcqRel);
+        }
+    }
+
+    /// Description of a mount, as listed by `Vfs::mounts`
+    #[derive(Debug, Clone)]
+    pub struct MountInfo {
+        pub path: String,
+        pub source: String,
+        pub fs_type: String,
+        pub flags: MountFlags,
+    }
+
+    /// Inode reached through a mount. Applies the mount flags and keeps the
+    /// mount busy for as long as it is alive.
+    struct MountedInode {
+        inner: Inode,
+        mnt: Arc<MountRef>,
+    }
+
+    impl MountedInode {
+        fn wrap(inner: Inode, mnt: &Arc<MountRef>) -> Inode {
+            Inode(Arc::new(MountedInode {
+                inner: unwrap_mounted(&inner),
+                mnt: Arc::clone(mnt),
+            }))
+        }
+
+        fn check_writable(&self) -> KResult<()> {
+            if self.mnt.flags.contains(MountFlags::RDONLY) {
+                return Err(KError::ReadOnly);
+            }
+            Ok(())
+        }
+    }
+
+    /// The filesystem's own inode behind a mount wrapper. Filesystems only
+    /// recognize their own inode types in link() and rename_to().
+    pub fn unwrap_mounted(inode: &Inode) -> Inode {
+        match inode.0.as_any().and_then(|a| a.downcast_ref::<MountedInode>()) {
+            Some(m) => m.inner.clone(),
+            None => inode.clone(),
+        }
+    }
+
+    /// The concrete inode of type `T` behind `inode`, if it belongs to the
+    /// same filesystem instance `fs` (compared through `fs_of`). link() and
+    /// rename_to() use it to turn away inodes of other filesystems.
+    pub fn own_inode<'a, T: 'static, F: ?Sized>(
+        inode: &'a Inode,
+        fs: &Arc<F>,
+        fs_of: impl FnOnce(&T) -> &Arc<F>,
+    ) -> KResult<&'a T> {
+        let own = inode
+            .0
+            .as_any()
+            .and_then(|a| a.downcast_ref::<T>())
+            .ok_or(KError::NotSupported)?;
+        if !Arc::ptr_eq(fs_of(own), fs) {
+            return Err(KError::NotSupported);
+        }
+        Ok(own)
+    }
+
+    /// Stable identity of the file behind `inode`, used to share cached
+    /// pages between opens. Inodes with a number are identified by mount and
+    /// number, since disk filesystems build a new object on every lookup;
+    /// the rest (tmpfs reports ino 0) by the filesystem's own object.
+    pub fn file_id(inode: &Inode) -> (usize, u64) {
+        let mounted = inode.0.as_any().and_then(|a| a.downcast_ref::<MountedInode>());
+        match mounted {
+            Some(m) if m.inner.metadata().ino != 0 => {
+                (Arc::as_ptr(&m.mnt) as usize, m.inner.metadata().ino)
+            }
+            Some(m) => (Arc::as_ptr(&m.inner.0) as *const () as usize, 0),
+            None => (Arc::as_ptr(&inode.0) as *const () as usize, 0),
+        }
+    }
+
+    /// Filesystem type of the mount `inode` was reached through. None for
+    /// inodes of the root filesystem.
+    pub fn mounted_fs_type(inode: &Inode) -> Option<String> {
+        inode
+            .0
+            .as_any()
+            .and_then(|a| a.downcast_ref::<MountedInode>())
+            .map(|m| m.mnt.fs_type.clone())
+    }
+
+    /// statfs f_type for filesystems that do not report their own statistics
+    fn fs_magic(fs_type: &str) -> u64 {
+        match fs_type {
+            "proc" => 0x9FA0,
+            "sysfs" => 0x6265_6572,
+            "devtmpfs" | "devfs" | "tmpfs" => 0x0102_1994,
+            "ext2" | "ext3" | "ext4" => 0xEF53,
+            "vfat" | "fat32" => 0x4D44,
+            "exfat" => 0x2011_BAB0,
+            "ntfs" => 0x5346_544E,
+            "iso9660" => 0x9660,
+            "bpf" => 0xCAFE_4A11,
+            "cgroup2" => 0x6367_7270,
+            "overlay" => super::overlayfs::OVERLAYFS_MAGIC,
+            _ => 0,
+        }
+    }
+
+    impl InodeOps for MountedInode {
+        fn metadata(&self) -> Metadata {
+            self.inner.metadata()
+        }
+
+        fn set_metadata(&self, meta: Metadata) {
+            if self.check_writable().is_ok() {
+                self.inner.0.set_metadata(meta);
+            }
+        }
+
+        fn parent(&self) -> Option<Inode> {
+            self.inner.0.parent().map(|p| MountedInode::wrap(p, &self.mnt))
+        }
+
+        fn lookup(&self, name: &str) -> KResult<Inode> {
+            Ok(MountedInode::wrap(self.inner.0.lookup(name)?, &self.mnt))
+        }
+
+        fn create(&self, name: &str, kind: InodeKind, meta: Metadata) -> KResult<Inode> {
+            self.check_writable()?;
+            Ok(MountedInode::wrap(self.inner.0.create(name, kind, meta)?, &self.mnt))
+        }
+
+        fn readdir(&self) -> KResult<Vec<DirEntry>> {
+            self.inner.0.readdir()
+        }
+
+        fn unlink(&self, name: &str) -> KResult<()> {
+            self.check_writable()?;
+            self.inner.0.unlink(name)
+        }
+
+        fn rmdir(&self, name: &str) -> KResult<()> {
+            self.check_writable()?;
+            self.inner.0.rmdir(name)
+        }
+
+        fn is_empty_dir(&self) -> KResult<bool> {
+            self.inner.0.is_empty_dir()
+        }
+
+        fn symlink(&self, name: &str, target: &str, meta: Metadata) -> KResult<Inode> {
+            self.check_writable()?;
+            Ok(MountedInode::wrap(self.inner.0.symlink(name, target, meta)?, &self.mnt))
+        }
+
+        fn readlink(&self) -> KResult<String> {
+            self.inner.0.readlink()
+        }
+
+        fn mkfifo(&self, name: &str, meta: Metadata) -> KResult<Inode> {
+            self.check_writable()?;
+            Ok(MountedInode::wrap(self.inner.0.mkfifo(name, meta)?, &self.mnt))
+        }
+
+        fn link(&self, name: &str, target: Inode) -> KResult<()> {
+            self.check_writable()?;
+            self.inner.0.link(name, unwrap_mounted(&target))
+        }
+
+        fn rename_to(&self, old_name: &str, new_parent: &Inode, new_name: &str) -> KResult<()> {
+            self.check_writable()?;
+            if new_parent.0.mount_flags().contains(MountFlags::RDONLY) {
+                return Err(KError::ReadOnly);
+            }
+            self.inner.0.rename_to(old_name, &unwrap_mounted(new_parent), new_name)
+        }
+
+        fn read_at(&self, offset: usize, out: &mut [u8]) -> KResult<usize> {
+            self.inner.0.read_at(offset, out)
+        }
+
+        fn write_at(&self, offset: usize, data: &[u8]) -> KResult<usize> {
+            // FIFOs and device nodes stay writable on a read-only mount
+            if self.inner.kind() == InodeKind::File {
+                self.check_writable()?;
+            }
+            self.inner.0.write_at(offset, data)
+        }
+
+        fn truncate(&self, size: usize) -> KResult<()> {
+            self.check_writable()?;
+            self.inner.0.truncate(size)
+        }
+
+        fn size(&self) -> KResult<usize> {
+            self.inner.0.size()
+        }
+
+        fn getxattr(&self, name: &str) -> KResult<Vec<u8>> {
+            self.inner.0.getxattr(name)
+        }
+
+        fn setxattr(&self, name: &str, value: Vec<u8>, flags: super::xattr::XattrFlags) -> KResult<()> {
+            self.check_writable()?;
+            self.inner.0.setxattr(name, value, flags)
+        }
+
+        fn removexattr(&self, name: &str) -> KResult<()> {
+            self.check_writable()?;
+            self.inner.0.removexattr(name)
+        }
+
+        fn listxattr(&self) -> KResult<Vec<String>> {
+            self.inner.0.listxattr()
+        }
+
+        fn as_any(&self) -> Option<&dyn core::any::Any> {
+            Some(self)
+        }
+
+        fn statfs(&self) -> KResult<FsStats> {
+            match self.inner.0.statfs() {
+                Err(KError::NotSupported) => Ok(FsStats {
+                    magic: fs_magic(&self.mnt.fs_type),
+                    block_size: 4096,
+                    name_max: 255,
+                    ..FsStats::default()
+                }),
+                other => other,
+            }
+        }
+
+        fn mount_flags(&self) -> MountFlags {
+            self.mnt.flags
+        }
+    }
+
     pub struct Vfs {
         root: Inode,
-        /// Mount points: path -> inode
-        mount_points: BTreeMap<String, Inode>,
+        /// Mount points: path -> mount
+        mount_points: BTreeMap<String, Mount>,
     }
 
     impl Vfs {
@@ -274,18 +627,178 @@
         }
 
         /// Monta um filesystem em um caminho específico.
-        pub fn mount(&mut self, path: &str, inode: Inode) {
+        ///
+        /// `inode` is the root of the filesystem, or any directory for a bind
+        /// mount. A mount already at `path` is replaced.
+        pub fn mount(&mut self, path: &str, inode: Inode, source: &str, fs_type: &str, flags: MountFlags) {
+            let path = path.trim_end_matches('/');
+            let mount = Mount::new(inode, source, MountRef::new(flags, fs_type));
+            self.mount_points.insert(path.to_string(), mount);
+        }
+
+        /// Desmonta o filesystem montado em `path`.
+        ///
+        /// Fails with `Busy` while files or directories of the mount are still
+        /// in use, or while other filesystems are mounted below it. A `lazy`
+        /// unmount detaches the mount (and the mounts below it) right away and
+        /// leaves the filesystem alive until its last user goes away.
+        pub fn umount(&mut self, path: &str, lazy: bool) -> KResult<()> {
             let path = path.trim_end_matches('/');
-            self.mount_points.insert(path.to_string(), inode);
+            let mount = match self.mount_points.get(path) {
+                Some(m) => m,
+                None if path.is_empty() => return Err(KError::Busy),
+                None => return Err(KError::Invalid),
+            };
+
+            let prefix = alloc::format!("{}/", path);
+            let below: Vec<String> = self
+                .mount_points
+                .keys()
+                .filter(|p| p.starts_with(&prefix))
+                .cloned()
+                .collect();
+
+            // Cached file pages hold their inodes; only mapped ones should
+            // keep the mount busy
+            super::page_cache::drop_unmapped_file_pages();
+
+            if !lazy && (!below.is_empty() || mount.mnt.is_busy()) {
+                return Err(KError::Busy);
+            }
+
+            for p in below {
+                self.mount_points.remove(&p);
+            }
+            self.mount_points.remove(path);
+            inotify::unmounted(path);
+            Ok(())
+        }
+
+        /// Whether a filesystem is mounted exactly at `path`
+        pub fn is_mount_point(&self, path: &str) -> bool {
+            self.mount_points.contains_key(path.trim_end_matches('/'))
+        }
+
+        /// All mounts, ordered by path
+        pub fn mounts(&self) -> Vec<MountInfo> {
+            self.mount_points
+                .iter()
+                .map(|(path, m)| MountInfo {
+                    path: if path.is_empty() { String::from("/") } else { path.clone() },
+                    source: m.source.clone(),
+                    fs_type: m.mnt.fs_type.clone(),
+                    flags: m.mnt.flags,
+                })
+                .collect()
+        }
+
+        /// Copy of the mount table for a new mount namespace.
+        ///
+        /// The copy sees the same filesystems, but its mounts are its own:
+        /// mounting or unmounting in one table does not affect the other, and
+        /// files open through one do not keep the other's mounts busy.
+        pub fn copy(&self) -> Vfs {
+            let mount_points = self
+                .mount_points
+                .iter()
+                .map(|(path, m)| {
+                    let mnt = MountRef::new(m.mnt.flags, &m.mnt.fs_type);
+                    (path.clone(), Mount::new(m.root.clone(), &m.source, mnt))
+                })
+                .collect();
+            Vfs {
+                root: self.root.clone(),
+                mount_points,
+            }
+        }
+
+        /// Tree rooted at the directory `path`, for chroot.
+        ///
+        /// The mounts below `path` are shared with this table as they are at
+        /// the time of the call; mounts made later in either tree are not
+        /// propagated to the other.
+        pub fn chroot(&self, path: &str, cred: &Cred) -> KResult<Vfs> {
+            let path = normalize(path);
+            let root = self.resolve(&path, cred)?;
+            if root.metadata().kind != InodeKind::Dir {
+                return Err(KError::NotADirectory);
+            }
+
+            let prefix = if path == "/" { String::new() } else { path };
+            let mount_points = self
+                .mount_points
+                .iter()
+                .filter_map(|(mp, m)| {
+                    let rel = mp.strip_prefix(prefix.as_str())?;
+                    (rel.starts_with('/') || (rel.is_empty() && prefix.is_empty()))
+                        .then(|| (rel.to_string(), m.clone()))
+                })
+                .collect();
+
+            Ok(Vfs { root, mount_points })
+        }
+
+        /// Makes the mount at `new_root` the root of this table and moves the
+        /// old root to `put_old`, which must be a directory below `new_root`.
+        /// Mounts below `new_root` move along with it; all others end up
+        /// below `put_old`.
+        pub fn pivot_root(&mut self, new_root: &str, put_old: &str, cred: &Cred) -> KResult<()> {
+            let new_root = normalize(new_root);
+            let put_old = normalize(put_old);
+            if new_root == "/" {
+                return Err(KError::Busy);
+            }
+            if !self.mount_points.contains_key(&new_root) {
+                return Err(KError::Invalid);
+            }
+            let old_rel = match put_old.strip_prefix(new_root.as_str()) {
+                Some(rel) if rel.starts_with('/') => rel.to_string(),
+                _ => return Err(KError::Invalid),
+            };
+            if self.resolve(&put_old, cred)?.metadata().kind != InodeKind::Dir {
+                return Err(KError::NotADirectory);
+            }
+
+            let new_mount = match self.mount_points.remove(&new_root) {
+                Some(m) => m,
+                None => return Err(KError::Invalid),
+            };
+            let old_root = core::mem::replace(&mut self.root, new_mount.root());
+
+            let mut mount_points = BTreeMap::new();
+            for (mp, m) in core::mem::take(&mut self.mount_points) {
+                let moved = match mp.strip_prefix(new_root.as_str()) {
+                    Some(rel) if rel.starts_with('/') => rel.to_string(),
+                    _ => alloc::format!("{}{}", old_rel, mp),
+                };
+                mount_points.insert(moved, m);
+            }
+
+            // A filesystem mounted over "/" already shadows the old root
+            if !mount_points.contains_key(&old_rel) {
+                let mnt = old_root
+                    .0
+                    .as_any()
+                    .and_then(|a| a.downcast_ref::<MountedInode>())
+                    .map(|m| Arc::clone(&m.mnt))
+                    .unwrap_or_else(|| MountRef::new(MountFlags::empty(), "rootfs"));
+                mount_points.insert(old_rel, Mount::new(old_root, "rootfs", mnt));
+            }
+
+            self.mount_points = mount_points;
+            Ok(())
         }
 
         /// Verifica se há um mount point para o caminho e retorna o inode.
         fn check_mount_point(&self, path: &str) -> Option<Inode> {
             let path = path.trim_end_matches('/');
-            self.mount_points.get(path).cloned()
+            self.mount_points.get(path).map(Mount::root)
         }
 
         pub fn resolve(&self, path: &str, cred: &Cred) -> KResult<Inode> {
+            let path = normalize(path);
+            let path = path.as_str();
+
             // Verifica mount point exato primeiro
             if let Some(inode) = self.check_mount_point(path) {
                 return Ok(inode);
@@ -305,18 +818,6 @@
                 if comp == "." || comp.is_empty() {
                     continue;
                 }
-                if comp == ".." {
-                    if let Some(p) = cur.0.parent() {
-                        cur = p;
-                    } else {
-                        cur = self.root.clone();
-                    }
-                    // Atualiza current_path para ..
-                    if let Some(pos) = current_path.rfind('/') {
-                        current_path.truncate(pos);
-                    }
-                    continue;
-                }
 
                 // Travessia exige exec no diretório.
                 let meta = cur.metadata();
@@ -326,6 +827,7 @@
                 if !perm::can_exec_dir(&meta, cred) {
                     return Err(KError::PermissionDenied);
                 }
+                hooks::inode_permission(&cur, dir_path(&current_path), MacPermission::SEARCH)?;
 
                 // Atualiza o path atual
                 current_path.push('/');
@@ -343,8 +845,9 @@
         }
 
         pub fn mkdir_all(&mut self, path: &str, cred: &Cred, mode: Mode) -> KResult<()> {
+            let path = normalize(path);
             let mut cur = self.root.clone();
-            let comps: Vec<&str> = split_path(path).collect();
+            let comps: Vec<&str> = split_path(&path).collect();
             let mut current_path = String::new();
 
             for comp in comps {
@@ -352,17 +855,6 @@
                     continue;
                 }
 
-                if comp == ".." {
-                    if let Some(p) = cur.0.parent() {
-                        cur = p;
-                    }
-                    // Atualiza current_path para ..
-                    if let Some(pos) = current_path.rfind('/') {
-                        current_path.truncate(pos);
-                    }
-                    continue;
-                }
-
                 // precisa ser dir e ter permissão de travessia
                 let meta = cur.metadata();
                 if meta.kind != InodeKind::Dir {
@@ -371,6 +863,8 @@
                 if !perm::can_exec_dir(&meta, cred) {
                     return Err(KError::PermissionDenied);
                 }
+                let parent_path = dir_path(&current_path).to_string();
+                hooks::inode_permission(&cur, &parent_path, MacPermission::SEARCH)?;
 
                 // Atualiza o path atual
                 current_path.push('/');
@@ -392,8 +886,10 @@
                         if !perm::can_write_dir(&meta, cred) {
                             return Err(KError::PermissionDenied);
                         }
+                        hooks::inode_permission(&cur, &parent_path, MacPermission::ADD_NAME)?;
                         let child_meta = Metadata::simple(cred.uid, cred.gid, mode, InodeKind::Dir);
                         cur = cur.0.create(comp, InodeKind::Dir, child_meta)?;
+                        inotify::notify(&current_path, inotify::IN_CREATE | inotify::IN_ISDIR);
                     }
                     Err(e) => return Err(e),
                 }
@@ -419,11 +915,15 @@
                 return Err(KError::PermissionDenied);
             }
 
+            let path = normalize(path);
             let file = match parent.0.lookup(name) {
                 Ok(i) => i,
                 Err(KError::NotFound) => {
+                    hooks::inode_permission(&parent, parent_path, MacPermission::ADD_NAME)?;
                     let fmeta = Metadata::simple(cred.uid, cred.gid, mode, InodeKind::File);
-                    parent.0.create(name, InodeKind::File, fmeta)?
+                    let file = parent.0.create(name, InodeKind::File, fmeta)?;
+                    inotify::notify(&path, inotify::IN_CREATE);
+                    file
                 }
                 Err(e) => return Err(e),
             };
@@ -433,12 +933,34 @@
             if !perm::can_write_file(&fmeta, cred) {
                 return Err(KError::PermissionDenied);
             }
+            hooks::inode_permission(&file, &path, MacPermission::WRITE)?;
 
             file.0.truncate(0)?;
             let _ = file.0.write_at(0, data)?;
+            inotify::notify(&path, inotify::IN_MODIFY);
+            inotify::notify(&path, inotify::IN_CLOSE_WRITE);
             Ok(())
         }
 
+        /// Cria um arquivo regular vazio (open com O_CREAT).
+        pub fn create(&mut self, path: &str, mode: Mode, cred: &Cred) -> KResult<Inode> {
+            let (parent_path, name) = split_parent(path)?;
+            let parent = self.resolve(parent_path, cred)?;
+            let meta = parent.metadata();
+            if meta.kind != InodeKind::Dir {
+                return Err(KError::NotADirectory);
+            }
+            if !perm::can_write_dir(&meta, cred) {
+                return Err(KError::PermissionDenied);
+            }
+            hooks::inode_permission(&parent, parent_path, MacPermission::ADD_NAME)?;
+
+            let fmeta = Metadata::simple(cred.uid, cred.gid, mode, InodeKind::File);
+            let file = parent.0.create(name, InodeKind::File, fmeta)?;
+            inotify::notify(&normalize(path), inotify::IN_CREATE);
+            Ok(file)
+        }
+
         pub fn read_file(&mut self, path: &str, cred: &Cred) -> KResult<Vec<u8>> {
             let inode = self.resolve(path, cred)?;
             let meta = inode.metadata();
@@ -448,6 +970,7 @@
             if !perm::can_read_file(&meta, cred) {
                 return Err(KError::PermissionDenied);
             }
+            hooks::inode_permission(&inode, path, MacPermission::READ)?;
             let size = inode.0.size()?;
             let mut buf = vec![0u8; size];
             let _ = inode.0.read_at(0, &mut buf)?;
@@ -463,6 +986,7 @@
             if !perm::can_exec_dir(&meta, cred) || !perm::can_read_dir(&meta, cred) {
                 return Err(KError::PermissionDenied);
             }
+            hooks::inode_permission(&inode, path, MacPermission::READ)?;
             inode.0.readdir()
         }
 
@@ -496,8 +1020,12 @@
                     return Err(KError::PermissionDenied);
                 }
             }
+            hooks::inode_permission(&parent, parent_path, MacPermission::REMOVE_NAME)?;
+            hooks::inode_permission(&target, path, MacPermission::UNLINK)?;
 
-            parent.0.unlink(name)
+            parent.0.unlink(name)?;
+            inotify::deleted(&normalize(path), false);
+            Ok(())
         }
 
         /// Remove um diretório vazio.
@@ -531,13 +1059,17 @@
                 }
             }
 
+            hooks::inode_permission(&parent, parent_path, MacPermission::REMOVE_NAME)?;
+            hooks::inode_permission(&target, path, MacPermission::RMDIR)?;
+
             // Verifica se está vazio
-            let entries = target.0.readdir()?;
-            if !entries.is_empty() {
+            if !target.0.is_empty_dir()? {
                 return Err(KError::NotEmpty);
             }
 
-            parent.0.rmdir(name)
+            parent.0.rmdir(name)?;
+            inotify::deleted(&normalize(path), true);
+            Ok(())
         }
 
         /// Renomeia um arquivo ou diretório.
@@ -583,10 +1115,15 @@
                     return Err(KError::PermissionDenied);
                 }
             }
+            hooks::inode_permission(&old_parent, old_parent_path, MacPermission::REMOVE_NAME)?;
+            hooks::inode_permission(&new_parent, new_parent_path, MacPermission::ADD_NAME)?;
+            hooks::inode_permission(&source, oldpath, MacPermission::RENAME)?;
 
             // Check if destination exists
+            let mut replaced = false;
             match new_parent.0.lookup(new_name) {
                 Ok(dest) => {
+                    replaced = true;
                     let dest_meta = dest.metadata();
 
                     // Check sticky bit on new parent
@@ -627,7 +1164,14 @@
             }
 
             // Perform the rename operation
-            old_parent.0.rename_to(old_name, &new_parent, new_name)
+            old_parent.0.rename_to(old_name, &new_parent, new_name)?;
+            inotify::moved(
+                &normalize(oldpath),
+                &normalize(newpath),
+                source_meta.kind == InodeKind::Dir,
+                replaced,
+            );
+            Ok(())
         }
 
         /// Altera o modo (permissões) de um arquivo ou diretório.
@@ -639,9 +1183,11 @@
             if cred.uid.0 != 0 && meta.uid != cred.uid {
                 return Err(KError::PermissionDenied);
             }
+            hooks::inode_permission(&inode, path, MacPermission::SETATTR)?;
 
             meta.mode = mode;
             inode.0.set_metadata(meta);
+            inotify::notify_kind(&normalize(path), inotify::IN_ATTRIB, meta.kind);
             Ok(())
         }
 
@@ -661,10 +1207,12 @@
                     return Err(KError::PermissionDenied);
                 }
             }
+            hooks::inode_permission(&inode, path, MacPermission::SETATTR)?;
 
             meta.uid = uid;
             meta.gid = gid;
             inode.0.set_metadata(meta);
+            inotify::notify_kind(&normalize(path), inotify::IN_ATTRIB, meta.kind);
             Ok(())
         }
 
@@ -677,10 +1225,13 @@
             if !perm::can_write(&parent.metadata(), cred) {
                 return Err(KError::PermissionDenied);
             }
+            hooks::inode_permission(&parent, parent_path, MacPermission::ADD_NAME)?;
 
             let meta = Metadata::simple(cred.uid, cred.gid, Mode::from_octal(0o777), InodeKind::Symlink);
 
-            parent.0.symlink(name, target, meta)
+            let link = parent.0.symlink(name, target, meta)?;
+            inotify::notify(&normalize(link_path), inotify::IN_CREATE);
+            Ok(link)
         }
 
         /// Read the target of a symbolic link (does not follow symlinks)
@@ -703,28 +1254,32 @@
             if !perm::can_write(&parent.metadata(), cred) {
                 return Err(KError::PermissionDenied);
             }
+            hooks::inode_permission(&parent, parent_path, MacPermission::ADD_NAME)?;
 
             let meta = Metadata::simple(cred.uid, cred.gid, mode, InodeKind::Fifo);
 
-            parent.0.mkfifo(name, meta)
+            let fifo = parent.0.mkfifo(name, meta)?;
+            inotify::notify(&normalize(path), inotify::IN_CREATE);
+            Ok(fifo)
         }
 
         /// Resolve path without following the final symlink (for lstat)
         pub fn resolve_nofollow(&self, path: &str, cred: &Cred) -> KResult<Inode> {
+            let path = normalize(path);
             let path = path.trim_end_matches('/');
-            if path.is_empty() || path == "/" {
+            if path.is_empty() {
                 return Ok(self.root.clone());
             }
 
-            // Check mount points
-            for (mp, inode) in &self.mount_points {
+            // Check mount points (the longest matching path sorts last)
+            for (mp, mount) in self.mount_points.iter().rev() {
                 if path == mp {
-                    return Ok(inode.clone());
+                    return Ok(mount.root());
                 }
-                if path.starts_with(mp) {
+                if path.starts_with(mp.as_str()) {
                     let rel = &path[mp.len()..];
                     if rel.starts_with('/') || rel.is_empty() {
-                        return self.resolve_relative_nofollow(inode.clone(), rel.trim_start_matches('/'), cred);
+                        return self.resolve_relative_nofollow(mount.root(), rel.trim_start_matches('/'), cred);
                     }
                 }
             }
@@ -778,11 +1333,42 @@
         }
     }
 
+    /// Path of the directory reached so far during a walk ("" is the root)
+    fn dir_path(walked: &str) -> &str {
+        if walked.is_empty() { "/" } else { walked }
+    }
+
+    /// Resolves "." and ".." lexically. ".." at the top stays at the root, so
+    /// no lookup can climb out of a tree (a chroot jail in particular).
+    pub fn normalize(path: &str) -> String {
+        let mut comps: Vec<&str> = Vec::new();
+        for comp in split_path(path) {
+            match comp {
+                "." => {}
+                ".." => {
+                    comps.pop();
+                }
+                _ => comps.push(comp),
+            }
+        }
+
+        if comps.is_empty() {
+            return String::from("/");
+        }
+        let mut out = String::new();
+        for comp in comps {
+            out.push('/');
+            out.push_str(comp);
+        }
+        out
+    }
+
     fn split_path(path: &str) -> impl Iterator<Item = &str> {
         path.split('/').filter(|c| !c.is_empty())
     }
 
-    fn split_parent(path: &str) -> KResult<(&str, &str)> {
+    /// Splits `path` into its parent directory and last component
+    pub fn split_parent(path: &str) -> KResult<(&str, &str)> {
         let p = path.trim_end_matches('/');
         if p.is_empty() || p == "/" {
             return Err(KError::Invalid);
@@ -1,11 +1,20 @@
 //! Archive Manager Application
 //!
 //! A graphical application for managing compressed archives.
-//! Supports ZIP, TAR, GZIP, BZIP2, XZ, 7Z, and RAR formats.
+//! Reads and writes ZIP, TAR and gzip/zstd compressed tarballs and files;
+//! reads xz. BZIP2, 7Z and RAR are recognised but not supported.
 
 use alloc::string::{String, ToString};
+use alloc::vec;
 use alloc::vec::Vec;
 use alloc::format;
+use core::ops::Range;
+
+use crate::compress::{self, CompressError};
+use crate::fs::vfs::Mode;
+use crate::pkg::format::TarArchive;
+use crate::time::{civil_from_days, days_from_civil};
+use crate::util::KError;
 
 use crate::drivers::framebuffer::Color;
 use crate::gui::surface::Surface;
@@ -275,6 +284,308 @@ pub enum ArchiveError {
     PathTooLong,
 }
 
+impl From<CompressError> for ArchiveError {
+    fn from(err: CompressError) -> Self {
+        match err {
+            CompressError::BadFormat => ArchiveError::InvalidFormat,
+            CompressError::Unsupported => ArchiveError::UnsupportedFormat,
+            CompressError::TooLarge => ArchiveError::OutOfMemory,
+            _ => ArchiveError::CorruptArchive,
+        }
+    }
+}
+
+impl From<KError> for ArchiveError {
+    fn from(err: KError) -> Self {
+        match err {
+            KError::NotFound => ArchiveError::FileNotFound,
+            KError::PermissionDenied => ArchiveError::PermissionDenied,
+            KError::NoMemory => ArchiveError::OutOfMemory,
+            _ => ArchiveError::IoError,
+        }
+    }
+}
+
+// ============================================================================
+// Archive formats
+// ============================================================================
+
+/// Largest archive payload decompressed into memory
+const MAX_ARCHIVE_SIZE: usize = 512 * 1024 * 1024;
+
+const ZIP_LOCAL_HEADER: u32 = 0x0403_4B50;
+const ZIP_CENTRAL_HEADER: u32 = 0x0201_4B50;
+const ZIP_END_OF_DIRECTORY: u32 = 0x0605_4B50;
+const ZIP_METHOD_STORED: u16 = 0;
+const ZIP_METHOD_DEFLATE: u16 = 8;
+
+fn le16(data: &[u8], pos: usize) -> u16 {
+    u16::from_le_bytes([data[pos], data[pos + 1]])
+}
+
+fn le32(data: &[u8], pos: usize) -> u32 {
+    u32::from_le_bytes([data[pos], data[pos + 1], data[pos + 2], data[pos + 3]])
+}
+
+/// Read an archive's listing. Returns the bytes entries are stored in
+/// (the archive itself, or its decompressed tar payload) and each entry's
+/// range within them.
+fn load_archive(
+    format: ArchiveFormat,
+    path: &str,
+    raw: &[u8],
+    entries: &mut Vec<ArchiveEntry>,
+) -> Result<(Vec<u8>, Vec<Range<usize>>), ArchiveError> {
+    let (data, compression) = match format {
+        ArchiveFormat::Zip => {
+            let spans = list_zip(raw, entries)?;
+            return Ok((raw.to_vec(), spans));
+        }
+        ArchiveFormat::Tar => (raw.to_vec(), "stored"),
+        ArchiveFormat::TarGz | ArchiveFormat::Gzip => (compress::deflate::gzip_decompress(raw, MAX_ARCHIVE_SIZE)?, "gzip"),
+        ArchiveFormat::TarXz | ArchiveFormat::Xz => (compress::xz::decompress(raw, MAX_ARCHIVE_SIZE)?, "xz"),
+        ArchiveFormat::TarZst | ArchiveFormat::Zstd => (compress::zstd::decompress(raw, MAX_ARCHIVE_SIZE)?, "zstd"),
+        _ => return Err(ArchiveError::UnsupportedFormat),
+    };
+
+    if matches!(format, ArchiveFormat::Gzip | ArchiveFormat::Xz | ArchiveFormat::Zstd) {
+        // A single compressed file, named after the archive
+        let base = path.rsplit('/').next().unwrap_or(path);
+        let mut name = base.rsplit_once('.').map_or(base, |(stem, _)| stem);
+        let header = (format == ArchiveFormat::Gzip).then(|| compress::deflate::gzip_header(raw)).transpose()?;
+        if let Some(stored) = header.as_ref().and_then(|h| h.name.as_deref()) {
+            name = stored.rsplit('/').next().unwrap_or(stored);
+        }
+        let mut entry = ArchiveEntry::new(name, EntryType::File);
+        entry.size = data.len() as u64;
+        entry.compressed_size = raw.len() as u64;
+        entry.mtime = header.map_or(0, |h| h.mtime as u64);
+        entry.crc32 = compress::checksum::crc32(&data);
+        entry.compression = String::from(compression);
+        entries.push(entry);
+        let len = data.len();
+        return Ok((data, vec![0..len]));
+    }
+
+    let spans = list_tar(&data, compression, entries);
+    Ok((data, spans))
+}
+
+fn list_tar(data: &[u8], compression: &str, entries: &mut Vec<ArchiveEntry>) -> Vec<Range<usize>> {
+    let mut spans = Vec::new();
+    let mut tar = TarArchive::new(data);
+    while let Some(item) = tar.next_entry() {
+        let header = &item.header;
+        let entry_type = if header.is_directory() {
+            EntryType::Directory
+        } else if header.is_symlink() {
+            EntryType::Symlink
+        } else if header.typeflag == b'1' {
+            EntryType::Hardlink
+        } else if header.is_file() {
+            EntryType::File
+        } else {
+            EntryType::Unknown
+        };
+        let mut entry = ArchiveEntry::new(&header.name(), entry_type);
+        entry.size = item.data.len() as u64;
+        entry.compressed_size = item.data.len() as u64;
+        entry.mode = header.mode();
+        entry.mtime = parse_octal(&header.mtime);
+        entry.compression = String::from(compression);
+        if matches!(entry_type, EntryType::Symlink | EntryType::Hardlink) {
+            entry.link_target = Some(header.link_target());
+        }
+        let start = item.data.as_ptr() as usize - data.as_ptr() as usize;
+        spans.push(start..start + item.data.len());
+        entries.push(entry);
+    }
+    spans
+}
+
+fn parse_octal(field: &[u8]) -> u64 {
+    field.iter()
+        .skip_while(|&&b| b == b' ')
+        .take_while(|b| (b'0'..=b'7').contains(b))
+        .fold(0, |acc, &b| acc * 8 + (b - b'0') as u64)
+}
+
+/// List a ZIP archive from its central directory
+fn list_zip(data: &[u8], entries: &mut Vec<ArchiveEntry>) -> Result<Vec<Range<usize>>, ArchiveError> {
+    // The end of central directory record sits before a comment of up to 64 KiB
+    let search_start = data.len().saturating_sub(22 + 0xFFFF);
+    let eocd = (search_start..data.len().saturating_sub(21))
+        .rev()
+        .find(|&pos| le32(data, pos) == ZIP_END_OF_DIRECTORY)
+        .ok_or(ArchiveError::InvalidFormat)?;
+    let count = le16(data, eocd + 10) as usize;
+    let mut pos = le32(data, eocd + 16) as usize;
+    if le32(data, eocd + 12) == u32::MAX || pos as u32 == u32::MAX {
+        // ZIP64
+        return Err(ArchiveError::UnsupportedFormat);
+    }
+
+    let mut spans = Vec::with_capacity(count);
+    for _ in 0..count {
+        let header = data.get(pos..pos + 46).ok_or(ArchiveError::CorruptArchive)?;
+        if le32(header, 0) != ZIP_CENTRAL_HEADER {
+            return Err(ArchiveError::CorruptArchive);
+        }
+        let made_by = le16(header, 4);
+        let flags = le16(header, 8);
+        let method = le16(header, 10);
+        let (time, date) = (le16(header, 12), le16(header, 14));
+        let crc = le32(header, 16);
+        let compressed = le32(header, 20) as usize;
+        let size = le32(header, 24) as u64;
+        let name_len = le16(header, 28) as usize;
+        let extra_len = le16(header, 30) as usize;
+        let comment_len = le16(header, 32) as usize;
+        let external = le32(header, 38);
+        let local = le32(header, 42) as usize;
+        let name = data.get(pos + 46..pos + 46 + name_len).ok_or(ArchiveError::CorruptArchive)?;
+        let name = String::from_utf8_lossy(name).into_owned();
+        pos += 46 + name_len + extra_len + comment_len;
+
+        let local_header = data.get(local..local + 30).ok_or(ArchiveError::CorruptArchive)?;
+        if le32(local_header, 0) != ZIP_LOCAL_HEADER {
+            return Err(ArchiveError::CorruptArchive);
+        }
+        let start = local + 30 + le16(local_header, 26) as usize + le16(local_header, 28) as usize;
+        if start + compressed > data.len() {
+            return Err(ArchiveError::CorruptArchive);
+        }
+
+        let entry_type = if name.ends_with('/') { EntryType::Directory } else { EntryType::File };
+        let mut entry = ArchiveEntry::new(&name, entry_type);
+        entry.size = size;
+        entry.compressed_size = compressed as u64;
+        entry.crc32 = crc;
+        entry.mtime = dos_to_unix(date, time);
+        entry.encrypted = flags & 1 != 0;
+        // Unix hosts keep st_mode in the high half of the external attributes
+        if made_by >> 8 == 3 && external >> 16 != 0 {
+            entry.mode = external >> 16;
+        }
+        entry.compression = String::from(match method {
+            ZIP_METHOD_STORED => "stored",
+            ZIP_METHOD_DEFLATE => "deflate",
+            _ => "unsupported",
+        });
+        entries.push(entry);
+        spans.push(start..start + compressed);
+    }
+    Ok(spans)
+}
+
+fn dos_to_unix(date: u16, time: u16) -> u64 {
+    let year = 1980 + (date >> 9) as i64;
+    let month = ((date >> 5) & 0xF).clamp(1, 12) as u32;
+    let day = (date & 0x1F).max(1) as u32;
+    let secs = (time >> 11) as i64 * 3600 + ((time >> 5) & 0x3F) as i64 * 60 + (time & 0x1F) as i64 * 2;
+    (days_from_civil(year, month, day) * 86400 + secs).max(0) as u64
+}
+
+fn unix_to_dos(secs: u64) -> (u16, u16) {
+    let rem = secs % 86400;
+    let (year, month, day) = civil_from_days((secs / 86400) as i64);
+    let year = year.clamp(1980, 2107) as u16;
+    let date = (year - 1980) << 9 | (month as u16) << 5 | day as u16;
+    let time = ((rem / 3600) as u16) << 11 | ((rem / 60 % 60) as u16) << 5 | (rem % 60 / 2) as u16;
+    (date, time)
+}
+
+/// ustar archive of `files`
+fn build_tar(files: &[(String, Vec<u8>)]) -> Vec<u8> {
+    let mtime = crate::time::realtime().tv_sec.max(0) as u64;
+    let mut out = Vec::new();
+    for (name, data) in files {
+        let mut header = [0u8; 512];
+        let (prefix, name) = match name.len() {
+            0..=100 => ("", name.as_str()),
+            _ => name.split_at(name[..name.len().min(156)].rfind('/').unwrap_or(0)),
+        };
+        let name = name.trim_start_matches('/');
+        header[..name.len().min(100)].copy_from_slice(&name.as_bytes()[..name.len().min(100)]);
+        header[100..107].copy_from_slice(format!("{:07o}", 0o644).as_bytes());
+        header[108..115].copy_from_slice(b"0000000");
+        header[116..123].copy_from_slice(b"0000000");
+        header[124..135].copy_from_slice(format!("{:011o}", data.len()).as_bytes());
+        header[136..147].copy_from_slice(format!("{:011o}", mtime).as_bytes());
+        header[156] = b'0';
+        header[257..263].copy_from_slice(b"ustar\0");
+        header[263..265].copy_from_slice(b"00");
+        header[345..345 + prefix.len().min(155)].copy_from_slice(&prefix.as_bytes()[..prefix.len().min(155)]);
+        // Checksum is computed with its own field as spaces
+        header[148..156].fill(b' ');
+        let sum: u32 = header.iter().map(|&b| b as u32).sum();
+        header[148..155].copy_from_slice(format!("{:06o}\0", sum).as_bytes());
+
+        out.extend_from_slice(&header);
+        out.extend_from_slice(data);
+        out.resize(out.len().next_multiple_of(512), 0);
+    }
+    // Two zero blocks end the archive
+    out.resize(out.len() + 1024, 0);
+    out
+}
+
+/// ZIP archive of `files`, deflating each one that gets smaller
+fn build_zip(files: &[(String, Vec<u8>)], level: u8, comment: Option<&str>) -> Vec<u8> {
+    let (date, time) = unix_to_dos(crate::time::realtime().tv_sec.max(0) as u64);
+    let mut out = Vec::new();
+    let mut directory = Vec::new();
+    for (name, data) in files {
+        let crc = compress::checksum::crc32(data);
+        let deflated = (level > 0).then(|| compress::deflate::deflate(data, level)).filter(|d| d.len() < data.len());
+        let (method, stored) = match &deflated {
+            Some(d) => (ZIP_METHOD_DEFLATE, d.as_slice()),
+            None => (ZIP_METHOD_STORED, data.as_slice()),
+        };
+        let offset = out.len() as u32;
+
+        let mut common = Vec::with_capacity(26);
+        common.extend_from_slice(&20u16.to_le_bytes());
+        common.extend_from_slice(&0u16.to_le_bytes());
+        common.extend_from_slice(&method.to_le_bytes());
+        common.extend_from_slice(&time.to_le_bytes());
+        common.extend_from_slice(&date.to_le_bytes());
+        common.extend_from_slice(&crc.to_le_bytes());
+        common.extend_from_slice(&(stored.len() as u32).to_le_bytes());
+        common.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        common.extend_from_slice(&(name.len() as u16).to_le_bytes());
+        common.extend_from_slice(&0u16.to_le_bytes());
+
+        out.extend_from_slice(&ZIP_LOCAL_HEADER.to_le_bytes());
+        out.extend_from_slice(&common);
+        out.extend_from_slice(name.as_bytes());
+        out.extend_from_slice(stored);
+
+        directory.extend_from_slice(&ZIP_CENTRAL_HEADER.to_le_bytes());
+        // Made by Unix, so the mode below is honoured
+        directory.extend_from_slice(&(3u16 << 8 | 20).to_le_bytes());
+        directory.extend_from_slice(&common);
+        directory.extend_from_slice(&[0; 8]);
+        directory.extend_from_slice(&((0o100644u32) << 16).to_le_bytes());
+        directory.extend_from_slice(&offset.to_le_bytes());
+        directory.extend_from_slice(name.as_bytes());
+    }
+
+    let comment = comment.unwrap_or("").as_bytes();
+    let comment = &comment[..comment.len().min(0xFFFF)];
+    let directory_offset = out.len() as u32;
+    out.extend_from_slice(&directory);
+    out.extend_from_slice(&ZIP_END_OF_DIRECTORY.to_le_bytes());
+    out.extend_from_slice(&[0; 4]);
+    out.extend_from_slice(&(files.len() as u16).to_le_bytes());
+    out.extend_from_slice(&(files.len() as u16).to_le_bytes());
+    out.extend_from_slice(&(directory.len() as u32).to_le_bytes());
+    out.extend_from_slice(&directory_offset.to_le_bytes());
+    out.extend_from_slice(&(comment.len() as u16).to_le_bytes());
+    out.extend_from_slice(comment);
+    out
+}
+
 /// View mode for archive contents
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum ViewMode {
@@ -350,6 +661,9 @@ impl Default for CreateOptions {
     }
 }
 
+/// Asks the user for a path for `operation`; `None` if cancelled
+pub type PathChooser = fn(ArchiveOperation) -> Option<String>;
+
 /// Archive Manager widget
 pub struct ArchiveManager {
     id: WidgetId,
@@ -374,6 +688,11 @@ pub struct ArchiveManager {
     context_menu_visible: bool,
     context_menu_pos: (isize, isize),
     current_password: Option<String>,
+    /// Bytes the open archive's entries are stored in
+    archive_data: Vec<u8>,
+    /// Range of each entry's data within `archive_data`
+    entry_spans: Vec<Range<usize>>,
+    on_choose_path: Option<PathChooser>,
 }
 
 impl ArchiveManager {
@@ -407,9 +726,17 @@ impl ArchiveManager {
             context_menu_visible: false,
             context_menu_pos: (0, 0),
             current_password: None,
+            archive_data: Vec::new(),
+            entry_spans: Vec::new(),
+            on_choose_path: None,
         }
     }
 
+    /// Set the file chooser used by the Open, Extract and Create buttons
+    pub fn set_path_chooser(&mut self, chooser: PathChooser) {
+        self.on_choose_path = Some(chooser);
+    }
+
     pub fn open(&mut self, path: &str) -> Result<(), ArchiveError> {
         let format = ArchiveFormat::from_path(path);
         if format == ArchiveFormat::Unknown {
@@ -418,62 +745,25 @@ impl ArchiveManager {
 
         self.status_message = format!("Opening {}...", path);
 
+        let raw = crate::fs::read_file(path, &crate::task::current_cred())?;
         let mut info = ArchiveInfo::new(path, format);
-
-        info.entries.push(ArchiveEntry {
-            path: String::from("README.md"),
-            entry_type: EntryType::File,
-            size: 1024,
-            compressed_size: 512,
-            mtime: 1705574400,
-            mode: 0o644,
-            crc32: 0xDEADBEEF,
-            encrypted: false,
-            compression: String::from("deflate"),
-            comment: None,
-            link_target: None,
-        });
-
-        info.entries.push(ArchiveEntry {
-            path: String::from("src/"),
-            entry_type: EntryType::Directory,
-            size: 0,
-            compressed_size: 0,
-            mtime: 1705574400,
-            mode: 0o755,
-            crc32: 0,
-            encrypted: false,
-            compression: String::from("stored"),
-            comment: None,
-            link_target: None,
-        });
-
-        info.entries.push(ArchiveEntry {
-            path: String::from("src/main.rs"),
-            entry_type: EntryType::File,
-            size: 2048,
-            compressed_size: 1024,
-            mtime: 1705574400,
-            mode: 0o644,
-            crc32: 0xCAFEBABE,
-            encrypted: false,
-            compression: String::from("deflate"),
-            comment: None,
-            link_target: None,
-        });
+        let (data, spans) = load_archive(format, path, &raw, &mut info.entries)?;
 
         for entry in &info.entries {
             info.total_size += entry.size;
-            info.compressed_size += entry.compressed_size;
             if entry.is_directory() {
                 info.dir_count += 1;
             } else {
                 info.file_count += 1;
             }
+            info.encrypted |= entry.encrypted;
         }
+        info.compressed_size = raw.len() as u64;
         info.entry_count = info.entries.len();
 
         self.current_archive = Some(info);
+        self.archive_data = data;
+        self.entry_spans = spans;
         self.selected_entries.clear();
         self.scroll_offset = 0;
         self.current_path.clear();
@@ -484,50 +774,120 @@ impl ArchiveManager {
 
     pub fn close(&mut self) {
         self.current_archive = None;
+        self.archive_data = Vec::new();
+        self.entry_spans.clear();
         self.selected_entries.clear();
         self.current_path.clear();
         self.status_message = String::from("Ready");
     }
 
+    /// Uncompressed contents of entry `index`, verified against its CRC
+    fn entry_contents(&self, index: usize) -> Result<Vec<u8>, ArchiveError> {
+        let archive = self.current_archive.as_ref().ok_or(ArchiveError::FileNotFound)?;
+        let entry = archive.entries.get(index).ok_or(ArchiveError::FileNotFound)?;
+        let stored = &self.archive_data[self.entry_spans[index].clone()];
+        if entry.encrypted {
+            return Err(ArchiveError::PasswordRequired);
+        }
+        match archive.format {
+            ArchiveFormat::Zip => {
+                let data = match entry.compression.as_str() {
+                    "stored" => stored.to_vec(),
+                    "deflate" => compress::deflate::inflate(stored, entry.size as usize)?,
+                    _ => return Err(ArchiveError::UnsupportedFormat),
+                };
+                if data.len() as u64 != entry.size || compress::checksum::crc32(&data) != entry.crc32 {
+                    return Err(ArchiveError::CorruptArchive);
+                }
+                Ok(data)
+            }
+            _ => Ok(stored.to_vec()),
+        }
+    }
+
     pub fn extract(&mut self, options: ExtractOptions) -> Result<(), ArchiveError> {
         let archive = self.current_archive.as_ref().ok_or(ArchiveError::FileNotFound)?;
 
         self.status_message = format!("Extracting to {}...", options.destination);
 
-        let entries_to_extract: Vec<&ArchiveEntry> = if options.selected_only && !self.selected_entries.is_empty() {
+        let indices: Vec<usize> = if options.selected_only && !self.selected_entries.is_empty() {
             self.selected_entries.iter()
-                .filter_map(|&i| archive.entries.get(i))
+                .copied()
+                .filter(|&i| i < archive.entries.len())
                 .collect()
         } else {
-            archive.entries.iter().collect()
+            (0..archive.entries.len()).collect()
         };
+        let entries: Vec<ArchiveEntry> = indices.iter().map(|&i| archive.entries[i].clone()).collect();
 
         self.progress = Some(ArchiveProgress {
             operation: ArchiveOperation::Extracting,
             current_entry: String::new(),
             current_entry_index: 0,
-            total_entries: entries_to_extract.len(),
+            total_entries: entries.len(),
             bytes_processed: 0,
-            total_bytes: entries_to_extract.iter().map(|e| e.size).sum(),
+            total_bytes: entries.iter().map(|e| e.size).sum(),
             percent: 0.0,
         });
 
-        for (i, entry) in entries_to_extract.iter().enumerate() {
+        let result = self.extract_entries(&indices, &entries, &options);
+        self.progress = None;
+        result?;
+        self.status_message = format!("Extracted {} entries", entries.len());
+
+        Ok(())
+    }
+
+    fn extract_entries(&mut self, indices: &[usize], entries: &[ArchiveEntry], options: &ExtractOptions) -> Result<(), ArchiveError> {
+        let cred = crate::task::current_cred();
+        for (i, (&index, entry)) in indices.iter().zip(entries).enumerate() {
             if let Some(ref mut progress) = self.progress {
                 progress.current_entry = entry.path.clone();
                 progress.current_entry_index = i;
-                progress.bytes_processed += entry.size;
-                progress.percent = (i as f32 / entries_to_extract.len() as f32) * 100.0;
+                progress.percent = (i as f32 / entries.len() as f32) * 100.0;
             }
-        }
 
-        self.progress = None;
-        self.status_message = format!("Extracted {} entries", entries_to_extract.len());
+            let relative = if options.preserve_structure { entry.path.as_str() } else { entry.file_name() };
+            let relative = relative.trim_end_matches('/');
+            // Never write outside the destination
+            if relative.is_empty() || relative.starts_with('/') || relative.split('/').any(|c| c == "..") {
+                return Err(ArchiveError::CorruptArchive);
+            }
+            let target = format!("{}/{}", options.destination.trim_end_matches('/'), relative);
+            if target.len() > 4096 {
+                return Err(ArchiveError::PathTooLong);
+            }
+            let mode = if options.preserve_permissions { entry.mode & 0o7777 } else { 0o644 };
 
+            match entry.entry_type {
+                EntryType::Directory => {
+                    crate::fs::mkdir(&target, &cred, Mode::from_octal((mode | 0o700) as u16))?;
+                }
+                EntryType::File => {
+                    if !options.overwrite && crate::fs::read_file(&target, &cred).is_ok() {
+                        continue;
+                    }
+                    let data = self.entry_contents(index)?;
+                    if let Some(slash) = target.rfind('/').filter(|&p| p > 0) {
+                        crate::fs::mkdir(&target[..slash], &cred, Mode::from_octal(0o755))?;
+                    }
+                    crate::fs::write_file(&target, &cred, Mode::from_octal(mode as u16), &data)?;
+                    if let Some(ref mut progress) = self.progress {
+                        progress.bytes_processed += data.len() as u64;
+                    }
+                }
+                // Links are listed but not recreated
+                _ => {}
+            }
+        }
         Ok(())
     }
 
     pub fn create(&mut self, path: &str, files: &[&str], options: CreateOptions) -> Result<(), ArchiveError> {
+        if options.password.is_some() {
+            return Err(ArchiveError::UnsupportedFormat);
+        }
+
         self.status_message = format!("Creating {}...", path);
 
         self.progress = Some(ArchiveProgress {
@@ -540,20 +900,52 @@ impl ArchiveManager {
             percent: 0.0,
         });
 
-        let mut info = ArchiveInfo::new(path, options.format);
-        info.comment = options.comment;
-
+        let cred = crate::task::current_cred();
+        let mut contents = Vec::with_capacity(files.len());
         for (i, file) in files.iter().enumerate() {
             if let Some(ref mut progress) = self.progress {
                 progress.current_entry = String::from(*file);
                 progress.current_entry_index = i;
                 progress.percent = (i as f32 / files.len() as f32) * 100.0;
             }
-            info.entries.push(ArchiveEntry::new(file, EntryType::File));
+            let data = match crate::fs::read_file(file, &cred) {
+                Ok(data) => data,
+                Err(err) => {
+                    self.progress = None;
+                    return Err(err.into());
+                }
+            };
+            if let Some(ref mut progress) = self.progress {
+                progress.bytes_processed += data.len() as u64;
+            }
+            let name = file.trim_start_matches('/');
+            contents.push((String::from(name), data));
         }
-
         self.progress = None;
-        self.current_archive = Some(info);
+
+        let level = options.compression_level.as_level();
+        let deflate_level = level.min(9) as u8;
+        let output = match options.format {
+            ArchiveFormat::Zip => build_zip(&contents, deflate_level, options.comment.as_deref()),
+            ArchiveFormat::Tar => build_tar(&contents),
+            ArchiveFormat::TarGz => compress::deflate::gzip_compress(&build_tar(&contents), deflate_level),
+            ArchiveFormat::TarZst => compress::zstd::compress(&build_tar(&contents), level.max(1) as u8),
+            ArchiveFormat::Gzip | ArchiveFormat::Zstd => {
+                let [(_, data)] = &contents[..] else {
+                    return Err(ArchiveError::InvalidFormat);
+                };
+                if options.format == ArchiveFormat::Gzip {
+                    compress::deflate::gzip_compress(data, deflate_level)
+                } else {
+                    compress::zstd::compress(data, level.max(1) as u8)
+                }
+            }
+            // No encoders for these
+            _ => return Err(ArchiveError::UnsupportedFormat),
+        };
+        crate::fs::write_file(path, &cred, Mode::from_octal(0o644), &output)?;
+
+        self.open(path)?;
         self.status_message = format!("Created archive with {} entries", files.len());
 
         Ok(())
@@ -564,29 +956,45 @@ impl ArchiveManager {
 
         self.status_message = String::from("Testing archive...");
 
+        let sizes: Vec<(String, EntryType, u64)> = archive.entries.iter()
+            .map(|e| (e.path.clone(), e.entry_type, e.size))
+            .collect();
+
         self.progress = Some(ArchiveProgress {
             operation: ArchiveOperation::Testing,
             current_entry: String::new(),
             current_entry_index: 0,
-            total_entries: archive.entries.len(),
+            total_entries: sizes.len(),
             bytes_processed: 0,
             total_bytes: archive.total_size,
             percent: 0.0,
         });
 
-        for (i, entry) in archive.entries.iter().enumerate() {
+        let mut ok = true;
+        for (i, (path, entry_type, size)) in sizes.iter().enumerate() {
             if let Some(ref mut progress) = self.progress {
-                progress.current_entry = entry.path.clone();
+                progress.current_entry = path.clone();
                 progress.current_entry_index = i;
-                progress.bytes_processed += entry.size;
-                progress.percent = (i as f32 / archive.entries.len() as f32) * 100.0;
+                progress.bytes_processed += size;
+                progress.percent = (i as f32 / sizes.len() as f32) * 100.0;
+            }
+            if *entry_type != EntryType::File {
+                continue;
+            }
+            match self.entry_contents(i) {
+                Ok(_) => {}
+                Err(ArchiveError::CorruptArchive) => ok = false,
+                Err(err) => {
+                    self.progress = None;
+                    return Err(err);
+                }
             }
         }
 
         self.progress = None;
-        self.status_message = String::from("Archive OK");
+        self.status_message = String::from(if ok { "Archive OK" } else { "Archive is damaged" });
 
-        Ok(true)
+        Ok(ok)
     }
 
     pub fn get_visible_entries(&self) -> Vec<(usize, &ArchiveEntry)> {
@@ -704,6 +1112,66 @@ impl ArchiveManager {
         format!("{:.1} GB", gb)
     }
 
+    /// Operation of the toolbar button at (`x`, `y`)
+    fn toolbar_action_at(&self, x: isize, y: isize) -> Option<ArchiveOperation> {
+        // Offsets and widths of the labels drawn in render()
+        const BUTTONS: [(usize, usize, ArchiveOperation); 4] = [
+            (10, 4, ArchiveOperation::Opening),
+            (60, 7, ArchiveOperation::Extracting),
+            (130, 6, ArchiveOperation::Creating),
+            (200, 4, ArchiveOperation::Testing),
+        ];
+        let rel_x = x - self.bounds.x;
+        let rel_y = y - self.bounds.y;
+        if rel_x < 0 || rel_y < 0 || rel_y as usize >= Self::HEADER_HEIGHT {
+            return None;
+        }
+        let rel_x = rel_x as usize;
+        BUTTONS.iter()
+            .find(|&&(start, len, _)| rel_x >= start && rel_x < start + len * Self::CHAR_WIDTH)
+            .map(|&(_, _, action)| action)
+    }
+
+    fn run_toolbar_action(&mut self, action: ArchiveOperation) {
+        let choose = |operation| self.on_choose_path.and_then(|chooser| chooser(operation));
+        let result = match action {
+            ArchiveOperation::Opening => match choose(ArchiveOperation::Opening) {
+                Some(path) => self.open(&path),
+                None => return,
+            },
+            ArchiveOperation::Extracting => {
+                let Some(ref archive) = self.current_archive else {
+                    return;
+                };
+                // Next to the archive unless the user picks somewhere else
+                let beside = match archive.path.rfind('/') {
+                    Some(0) => String::from("/"),
+                    Some(pos) => String::from(&archive.path[..pos]),
+                    None => String::from("."),
+                };
+                let destination = choose(ArchiveOperation::Extracting).unwrap_or(beside);
+                self.extract(ExtractOptions {
+                    destination,
+                    selected_only: true,
+                    ..ExtractOptions::default()
+                })
+            }
+            ArchiveOperation::Creating => match choose(ArchiveOperation::Creating) {
+                Some(source) => {
+                    let path = format!("{}.zip", source.trim_end_matches('/'));
+                    self.create(&path, &[source.as_str()], CreateOptions::default())
+                }
+                None => return,
+            },
+            ArchiveOperation::Testing => self.test().map(|_| ()),
+            // No toolbar buttons for these
+            _ => return,
+        };
+        if let Err(err) = result {
+            self.status_message = format!("Error: {:?}", err);
+        }
+    }
+
     fn entry_at_point(&self, x: isize, y: isize) -> Option<usize> {
         let base_x = self.bounds.x.max(0) as usize;
         let base_y = self.bounds.y.max(0) as usize;
@@ -781,6 +1249,10 @@ impl Widget for ArchiveManager {
                 true
             }
             WidgetEvent::MouseDown { button: MouseButton::Left, x, y } => {
+                if let Some(action) = self.toolbar_action_at(*x, *y) {
+                    self.run_toolbar_action(action);
+                    return true;
+                }
                 if let Some(idx) = self.entry_at_point(*x, *y) {
                     self.select_entry(idx, false);
                     return true;
@@ -121,6 +121,21 @@ impl FilesystemType {
         }
     }
 
+    /// Kernel driver name used to mount this filesystem
+    pub fn driver_name(&self) -> Option<&'static str> {
+        match self {
+            FilesystemType::Ext2 => Some("ext2"),
+            FilesystemType::Ext3 => Some("ext3"),
+            FilesystemType::Ext4 => Some("ext4"),
+            FilesystemType::Fat32 => Some("vfat"),
+            FilesystemType::ExFat => Some("exfat"),
+            FilesystemType::Ntfs => Some("ntfs"),
+            FilesystemType::Iso9660 => Some("iso9660"),
+            FilesystemType::Udf => Some("udf"),
+            _ => None,
+        }
+    }
+
     pub fn supports_permissions(&self) -> bool {
         matches!(self,
             FilesystemType::Ext2 | FilesystemType::Ext3 | FilesystemType::Ext4 |
@@ -471,9 +486,38 @@ impl DiskUtility {
 
         self.disks.push(disk2);
 
+        for disk in &mut self.disks {
+            for part in &mut disk.partitions {
+                Self::update_usage(part);
+            }
+        }
+
         self.status_message = format!("Found {} disks", self.disks.len());
     }
 
+    /// Fill used/available space of a mounted partition from statfs
+    fn update_usage(part: &mut PartitionInfo) {
+        let path = match (&part.mount_point, part.is_mounted) {
+            (Some(path), true) => path,
+            _ => return,
+        };
+        if let Ok(st) = crate::fs::statfs(path, &crate::security::Cred::root()) {
+            if st.blocks > 0 {
+                part.size = st.blocks * st.block_size;
+                part.used = (st.blocks - st.blocks_free) * st.block_size;
+                part.available = st.blocks_avail * st.block_size;
+            }
+        }
+    }
+
+    fn selected_partition_mut(&mut self) -> Option<&mut PartitionInfo> {
+        if let SelectedItem::Partition(disk_idx, part_idx) = self.selected_item {
+            self.disks.get_mut(disk_idx).and_then(|d| d.partitions.get_mut(part_idx))
+        } else {
+            None
+        }
+    }
+
     /// Get selected disk
     pub fn get_selected_disk(&self) -> Option<&DiskInfo> {
         match &self.selected_item {
@@ -496,13 +540,56 @@ impl DiskUtility {
 
     /// Mount partition
     pub fn mount(&mut self, mount_point: &str) -> Result<(), DiskError> {
-        let _point = mount_point;
+        use crate::util::KError;
+
+        let part = self.selected_partition_mut().ok_or(DiskError::PartitionNotFound)?;
+        let fs_type = part.filesystem.driver_name().ok_or(DiskError::UnsupportedOperation)?;
+        let source = format!("/dev/{}", part.id);
+        let device = crate::storage::find_device_by_path(&source)
+            .and_then(crate::storage::get_device)
+            .ok_or(DiskError::DiskNotFound)?;
+
+        let root = crate::fs::mount_fs(fs_type, Some(device)).map_err(|e| match e {
+            KError::NotSupported => DiskError::UnsupportedOperation,
+            _ => DiskError::FilesystemError,
+        })?;
+
+        {
+            let cred = crate::security::Cred::root();
+            let mut vfs = crate::fs::vfs_lock();
+            vfs.mkdir_all(mount_point, &cred, crate::fs::Mode::from_octal(0o755))
+                .map_err(|_| DiskError::IoError)?;
+            vfs.mount(mount_point, root, &source, fs_type, crate::fs::MountFlags::empty());
+        }
+
+        part.mount_point = Some(String::from(mount_point));
+        part.is_mounted = true;
+        Self::update_usage(part);
         self.status_message = String::from("Mounted successfully");
         Ok(())
     }
 
     /// Unmount partition
     pub fn unmount(&mut self) -> Result<(), DiskError> {
+        use crate::util::KError;
+
+        let part = self.selected_partition_mut().ok_or(DiskError::PartitionNotFound)?;
+        let path = match (&part.mount_point, part.is_mounted) {
+            (Some(path), true) => path.clone(),
+            _ => return Err(DiskError::UnsupportedOperation),
+        };
+
+        let result = crate::fs::vfs_lock().umount(&path, false);
+        match result {
+            Ok(()) => {}
+            Err(KError::Busy) => {
+                self.status_message = String::from("Device is busy");
+                return Err(DiskError::DiskBusy);
+            }
+            Err(_) => return Err(DiskError::IoError),
+        }
+
+        part.is_mounted = false;
         self.status_message = String::from("Unmounted successfully");
         Ok(())
     }
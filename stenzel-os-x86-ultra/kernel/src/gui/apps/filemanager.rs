@@ -3,10 +3,13 @@
 //! A graphical file manager application for browsing and managing files.
 
 use alloc::collections::VecDeque;
+use alloc::format;
 use alloc::string::String;
+use alloc::sync::Arc;
 use alloc::vec;
 use alloc::vec::Vec;
 use crate::drivers::framebuffer::Color;
+use crate::fs::inotify::{self, Inotify};
 use crate::gui::surface::Surface;
 use crate::gui::widgets::{Widget, WidgetId, WidgetEvent, Bounds, MouseButton, theme};
 
@@ -257,6 +260,10 @@ pub struct FileManager {
     // Callbacks
     on_navigate: Option<NavCallback>,
     on_file_open: Option<NavCallback>,
+
+    // Change notification for the current directory
+    watcher: Arc<Inotify>,
+    watch: Option<i32>,
 }
 
 impl FileManager {
@@ -311,6 +318,8 @@ impl FileManager {
             address_bar_text: String::from("/"),
             on_navigate: None,
             on_file_open: None,
+            watcher: Inotify::new(inotify::IN_NONBLOCK),
+            watch: None,
         }
     }
 
@@ -350,6 +359,7 @@ impl FileManager {
         self.address_bar_text = String::from(path);
         self.selected_indices.clear();
         self.scroll_offset = 0;
+        self.watch_current();
 
         if let Some(callback) = self.on_navigate {
             callback(path);
@@ -364,6 +374,7 @@ impl FileManager {
             self.address_bar_text = path.clone();
             self.selected_indices.clear();
             self.scroll_offset = 0;
+            self.watch_current();
 
             if let Some(callback) = self.on_navigate {
                 callback(&path);
@@ -379,6 +390,7 @@ impl FileManager {
             self.address_bar_text = path.clone();
             self.selected_indices.clear();
             self.scroll_offset = 0;
+            self.watch_current();
 
             if let Some(callback) = self.on_navigate {
                 callback(&path);
@@ -409,6 +421,51 @@ impl FileManager {
         }
     }
 
+    /// Move the directory watch to the current path
+    fn watch_current(&mut self) {
+        if let Some(wd) = self.watch.take() {
+            let _ = self.watcher.rm_watch(wd);
+        }
+        let mask = inotify::IN_CREATE
+            | inotify::IN_DELETE
+            | inotify::IN_MOVE
+            | inotify::IN_MODIFY
+            | inotify::IN_ATTRIB
+            | inotify::IN_CLOSE_WRITE
+            | inotify::IN_DELETE_SELF
+            | inotify::IN_MOVE_SELF;
+        self.watch = self.watcher.add_watch(&self.current_path, mask).ok();
+    }
+
+    /// Refresh if the current directory changed since the last call, and
+    /// drop stale thumbnails of the files that changed. Returns true if it
+    /// refreshed.
+    pub fn poll_changes(&mut self) -> bool {
+        let events = self.watcher.take_events();
+        let mut changed = false;
+        for event in events {
+            if Some(event.wd) != self.watch && event.mask != inotify::IN_Q_OVERFLOW {
+                continue;
+            }
+            if event.mask & inotify::IN_IGNORED != 0 {
+                self.watch = None;
+            }
+            if !event.name.is_empty() && event.mask & (inotify::IN_CREATE | inotify::IN_MOVED_TO) == 0 {
+                let path = if self.current_path == "/" {
+                    format!("/{}", event.name)
+                } else {
+                    format!("{}/{}", self.current_path, event.name)
+                };
+                super::thumbnails::invalidate_thumbnail(&path);
+            }
+            changed = true;
+        }
+        if changed {
+            self.refresh();
+        }
+        changed
+    }
+
     /// Sort entries
     fn sort_entries(&mut self) {
         let sort_order = self.sort_order;
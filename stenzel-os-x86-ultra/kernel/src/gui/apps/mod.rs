@@ -58,7 +58,7 @@ pub use texteditor::TextEditor;
 pub use trash::{TrashViewer, TrashedItem, TrashFileType};
 pub use videoplayer::{VideoPlayer, MediaInfo, PlaybackState};
 pub use softwarecenter::{SoftwareCenter, SoftwareCenterView, AppCategory, AppEntry, UpdateNotificationService};
-pub use archive::{ArchiveManager, ArchiveFormat, ArchiveEntry, ArchiveInfo, ArchiveError, CompressionLevel, ExtractOptions, CreateOptions};
+pub use archive::{ArchiveManager, ArchiveFormat, ArchiveEntry, ArchiveInfo, ArchiveError, CompressionLevel, ExtractOptions, CreateOptions, PathChooser as ArchivePathChooser};
 pub use diskutil::{DiskUtility, DiskInfo, PartitionInfo, DiskType, PartitionTable, FilesystemType, HealthStatus, FormatOptions, DiskError};
 pub use recentfiles::{RecentFilesWidget, RecentFilesManager, RecentFile, RecentFilesStats, FileCategory, TimeGroup, SortOrder};
 pub use thumbnails::{ThumbnailCache, ThumbnailSize, ThumbnailableType, ThumbnailStatus, ThumbnailMetadata, CachedThumbnail, ThumbnailRequest, ThumbnailResult, ThumbnailConfig, ThumbnailStats};
@@ -2,10 +2,12 @@
 //!
 //! System configuration application for Stenzel OS.
 
+use alloc::format;
 use alloc::string::String;
 use alloc::vec;
 use alloc::vec::Vec;
 use crate::drivers::framebuffer::Color;
+use crate::gui::settings::datetime;
 use crate::gui::surface::Surface;
 use crate::gui::widgets::{Widget, WidgetId, WidgetEvent, Bounds, MouseButton, theme};
 
@@ -246,6 +248,8 @@ pub struct Settings {
     focused: bool,
     sidebar_hover: Option<usize>,
     scroll_y: usize,
+    /// The last "Sync now" found no NTP thread to ask
+    sync_unavailable: bool,
 
     // Editing state
     editing_field: Option<String>,
@@ -279,6 +283,7 @@ impl Settings {
             focused: false,
             sidebar_hover: None,
             scroll_y: 0,
+            sync_unavailable: false,
             editing_field: None,
             edit_value: String::new(),
             on_change: None,
@@ -446,6 +451,11 @@ impl Settings {
                 self.datetime.auto_sync = !self.datetime.auto_sync;
                 self.notify_change();
             }
+            4 => {
+                // Sync now: the NTP thread does the query, the status row
+                // shows how it went
+                self.sync_unavailable = datetime::sync_now().is_err();
+            }
             _ => {}
         }
     }
@@ -724,6 +734,24 @@ impl Settings {
         // NTP Server
         draw_string(surface, x, y + Self::ROW_HEIGHT * 3, "NTP Server:", label_color);
         draw_string(surface, x + 120, y + Self::ROW_HEIGHT * 3, &self.datetime.ntp_server, value_color);
+
+        // Sync status
+        let status = datetime::get_sync_status();
+        draw_string(surface, x, y + Self::ROW_HEIGHT * 4, "Status:", label_color);
+        let summary = if self.sync_unavailable {
+            String::from("NTP is not running")
+        } else {
+            status.summary()
+        };
+        draw_string(surface, x + 120, y + Self::ROW_HEIGHT * 4, &summary, value_color);
+        draw_string(surface, x + 440, y + Self::ROW_HEIGHT * 4, "[Sync now]", Color::new(100, 100, 100));
+        if let Some(ago) = status.last_sync_ago {
+            let details = format!(
+                "Offset {} us, stratum {}, last sync {} s ago, next poll in {} s",
+                status.offset_us, status.stratum, ago, status.poll_interval_secs.saturating_sub(ago)
+            );
+            draw_string(surface, x + 120, y + Self::ROW_HEIGHT * 5, &details, label_color);
+        }
     }
 
     fn render_keyboard(&self, surface: &mut Surface, x: usize, y: usize) {
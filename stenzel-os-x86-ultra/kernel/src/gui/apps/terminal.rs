@@ -4,9 +4,12 @@
 
 use alloc::collections::VecDeque;
 use alloc::string::String;
+use alloc::sync::Arc;
 use alloc::vec;
 use alloc::vec::Vec;
 use crate::drivers::framebuffer::Color;
+use crate::drivers::tty::pty::{self, Pty};
+use crate::drivers::tty::Winsize;
 use crate::gui::surface::Surface;
 use crate::gui::widgets::{Widget, WidgetId, WidgetEvent, Bounds, MouseButton};
 
@@ -167,6 +170,9 @@ pub struct Terminal {
     // Input buffer
     input_buffer: VecDeque<u8>,
 
+    // Pseudo-terminal the shell runs on, if any
+    pty: Option<Arc<Pty>>,
+
     // State
     visible: bool,
     focused: bool,
@@ -217,6 +223,7 @@ impl Terminal {
             scroll_bottom: rows.saturating_sub(1),
             on_output: None,
             input_buffer: VecDeque::new(),
+            pty: None,
             visible: true,
             focused: false,
             selection_start: None,
@@ -252,11 +259,70 @@ impl Terminal {
         if let Some(callback) = self.on_output {
             callback(data);
         }
+        if let Some(pty) = &self.pty {
+            pty.master_write(data);
+            return;
+        }
         for &b in data {
             self.input_buffer.push_back(b);
         }
     }
 
+    /// Start /bin/sh on a new pty pair and attach to its master side
+    pub fn spawn_shell(&mut self) -> crate::util::KResult<u64> {
+        let (pty, pid) = pty::spawn_on_pty(
+            "/bin/sh",
+            &["/bin/sh"],
+            &["PATH=/bin:/usr/bin", "HOME=/root", "TERM=xterm"],
+        )?;
+        pty.tty().set_winsize(self.winsize());
+        if let Some(old) = self.pty.replace(pty) {
+            old.close_master();
+        }
+        Ok(pid)
+    }
+
+    /// Whether a shell is attached
+    pub fn has_pty(&self) -> bool {
+        self.pty.is_some()
+    }
+
+    /// Copy pending shell output onto the screen. Returns true if anything
+    /// was drawn.
+    pub fn poll_pty(&mut self) -> bool {
+        let Some(pty) = self.pty.clone() else {
+            return false;
+        };
+        let mut buf = [0u8; 512];
+        let mut drawn = false;
+        loop {
+            match pty.master_read(&mut buf, true) {
+                Ok(0) => break,
+                Ok(n) => {
+                    self.write(&buf[..n]);
+                    drawn = true;
+                }
+                Err(e) if e == crate::syscall::errno::EAGAIN => break,
+                Err(_) => {
+                    // Shell exited
+                    pty.close_master();
+                    self.pty = None;
+                    break;
+                }
+            }
+        }
+        drawn
+    }
+
+    fn winsize(&self) -> Winsize {
+        Winsize {
+            ws_row: self.rows as u16,
+            ws_col: self.cols as u16,
+            ws_xpixel: (self.cols * Self::CHAR_WIDTH) as u16,
+            ws_ypixel: (self.rows * Self::CHAR_HEIGHT) as u16,
+        }
+    }
+
     /// Read pending input
     pub fn read_input(&mut self) -> Option<u8> {
         self.input_buffer.pop_front()
@@ -994,6 +1060,10 @@ impl Widget for Terminal {
             self.scroll_bottom = new_rows.saturating_sub(1);
             self.cursor_x = self.cursor_x.min(new_cols.saturating_sub(1));
             self.cursor_y = self.cursor_y.min(new_rows.saturating_sub(1));
+
+            if let Some(pty) = &self.pty {
+                pty.tty().set_winsize(self.winsize());
+            }
         }
     }
 
@@ -1183,6 +1253,14 @@ impl Widget for Terminal {
     }
 }
 
+impl Drop for Terminal {
+    fn drop(&mut self) {
+        if let Some(pty) = self.pty.take() {
+            pty.close_master();
+        }
+    }
+}
+
 fn draw_char(surface: &mut Surface, x: usize, y: usize, c: char, color: Color) {
     use crate::drivers::font::DEFAULT_FONT;
 
@@ -811,6 +811,19 @@ impl TerminalTabs {
         }
     }
 
+    /// Pump shell output from every tab's pty onto its terminal
+    pub fn poll_ptys(&mut self) {
+        for tab in self.tabs.iter_mut() {
+            if tab.terminal.poll_pty() {
+                tab.update_from_terminal();
+                if tab.state == TabState::Background {
+                    tab.has_activity = true;
+                    tab.state = TabState::HasActivity;
+                }
+            }
+        }
+    }
+
     /// Draw text helper
     fn draw_text(&self, surface: &mut Surface, x: usize, y: usize, text: &str, color: Color) {
         let mut cx = x;
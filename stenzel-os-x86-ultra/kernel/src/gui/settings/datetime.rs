@@ -257,6 +257,10 @@ pub fn init() {
         current_time: 0,
     });
 
+    drop(state);
+
+    crate::time::tz::register_callback(on_timezone_change);
+    on_timezone_change(&crate::time::get_timezone_name());
     crate::kprintln!("datetime settings: initialized");
 }
 
@@ -280,11 +284,51 @@ pub fn get_timezone() -> Option<Timezone> {
     state.as_ref().map(|s| s.timezone.clone())
 }
 
-/// Set timezone
+/// Set timezone; it becomes the system timezone as well
 pub fn set_timezone(timezone: Timezone) {
+    let name = timezone.name.clone();
+    {
+        let mut state = DATETIME_SETTINGS.lock();
+        if let Some(ref mut s) = *state {
+            s.timezone = timezone;
+        }
+    }
+    // The change callback fills in the current offset and DST state
+    crate::time::set_timezone_by_name(&name);
+}
+
+/// System timezone changed: follow it
+fn on_timezone_change(name: &str) {
+    let now = crate::time::realtime().tv_sec;
+    let zone = crate::time::tz::current();
     let mut state = DATETIME_SETTINGS.lock();
-    if let Some(ref mut s) = *state {
-        s.timezone = timezone;
+    let Some(ref mut s) = *state else {
+        return;
+    };
+    if s.timezone.name != name {
+        s.timezone = get_available_timezones()
+            .into_iter()
+            .find(|tz| tz.name == name)
+            .unwrap_or_else(|| Timezone {
+                name: name.to_string(),
+                display_name: name.to_string(),
+                utc_offset: 0,
+                has_dst: false,
+                in_dst: false,
+            });
+    }
+    match zone {
+        Some(zone) => {
+            let local = zone.lookup(now);
+            s.timezone.utc_offset = local.utoff / 60;
+            s.timezone.in_dst = local.is_dst;
+            s.timezone.has_dst = zone.has_dst();
+        }
+        None => {
+            let tz = crate::time::get_timezone();
+            s.timezone.utc_offset = -tz.tz_minuteswest;
+            s.timezone.in_dst = tz.tz_dsttime != 0;
+        }
     }
 }
 
@@ -301,12 +345,15 @@ pub fn get_available_timezones() -> Vec<Timezone> {
     ]
 }
 
-/// Set automatic date/time
+/// Set automatic date/time; turns the NTP client on or off
 pub fn set_automatic_datetime(enabled: bool) {
-    let mut state = DATETIME_SETTINGS.lock();
-    if let Some(ref mut s) = *state {
-        s.automatic_datetime = enabled;
+    {
+        let mut state = DATETIME_SETTINGS.lock();
+        if let Some(ref mut s) = *state {
+            s.automatic_datetime = enabled;
+        }
     }
+    crate::net::ntp::set_enabled(enabled);
 }
 
 /// Is automatic date/time enabled
@@ -315,6 +362,75 @@ pub fn is_automatic_datetime() -> bool {
     state.as_ref().map(|s| s.automatic_datetime).unwrap_or(true)
 }
 
+/// NTP synchronization status
+#[derive(Debug, Clone)]
+pub struct SyncStatus {
+    /// The clock follows an NTP server
+    pub synchronized: bool,
+    /// Server the clock follows
+    pub server: Option<String>,
+    pub stratum: u8,
+    /// Last measured offset, microseconds
+    pub offset_us: i64,
+    /// Maximum error of the clock, microseconds
+    pub max_error_us: i64,
+    /// Seconds since the last successful sync, if any
+    pub last_sync_ago: Option<u64>,
+    pub poll_interval_secs: u64,
+    /// A sync asked for with `sync_now` is still running
+    pub pending: bool,
+    /// The last sync attempt failed
+    pub failed: bool,
+}
+
+impl SyncStatus {
+    /// Short description for the settings page
+    pub fn summary(&self) -> String {
+        if self.pending {
+            return "Synchronizing...".to_string();
+        }
+        if self.failed {
+            return "Sync failed".to_string();
+        }
+        match (&self.server, self.synchronized) {
+            (Some(server), true) => alloc::format!("Synchronized with {}", server),
+            (Some(_), false) | (None, true) => "Synchronizing...".to_string(),
+            (None, false) => "Not synchronized".to_string(),
+        }
+    }
+}
+
+/// Get NTP synchronization status
+pub fn get_sync_status() -> SyncStatus {
+    let timex = crate::time::ntp::status();
+    let stats = crate::net::ntp::get_stats();
+    let now = crate::time::uptime_secs();
+    SyncStatus {
+        synchronized: crate::time::ntp::is_synchronized(),
+        server: stats.as_ref().and_then(|s| s.system_peer).map(|ip| alloc::format!("{}", ip)),
+        stratum: stats.as_ref().map(|s| s.stratum).unwrap_or(16),
+        offset_us: stats.as_ref().map(|s| s.last_offset_ns / 1_000).unwrap_or(0),
+        max_error_us: timex.maxerror,
+        last_sync_ago: stats
+            .as_ref()
+            .filter(|s| s.sync_count > 0)
+            .map(|s| now.saturating_sub(s.last_sync_uptime)),
+        poll_interval_secs: stats.as_ref().map(|s| s.poll_interval_secs).unwrap_or(0),
+        pending: stats.as_ref().is_some_and(|s| s.sync_pending),
+        failed: stats.as_ref().is_some_and(|s| s.last_failed),
+    }
+}
+
+/// Ask the NTP thread to synchronize now instead of at the next poll.
+/// Does not wait for the network; `get_sync_status` shows the outcome.
+pub fn sync_now() -> Result<(), DateTimeError> {
+    if crate::net::ntp::request_sync() {
+        Ok(())
+    } else {
+        Err(DateTimeError::NtpSyncFailed)
+    }
+}
+
 /// Set automatic timezone
 pub fn set_automatic_timezone(enabled: bool) {
     let mut state = DATETIME_SETTINGS.lock();
@@ -47,6 +47,10 @@ pub struct Clock {
     state: WidgetState,
     visible: bool,
     on_click: Option<ClockCallback>,
+    /// UTC time shown, once synced; local time is derived from it
+    utc_time: Option<u64>,
+    /// Timezone generation the display was computed for
+    tz_generation: u64,
 }
 
 impl Clock {
@@ -68,11 +72,14 @@ impl Clock {
             state: WidgetState::Normal,
             visible: true,
             on_click: None,
+            utc_time: None,
+            tz_generation: 0,
         }
     }
 
-    /// Set time
+    /// Set time; the clock then runs free of the system time
     pub fn set_time(&mut self, hour: u8, minute: u8, second: u8) {
+        self.utc_time = None;
         self.hour = hour % 24;
         self.minute = minute % 60;
         self.second = second % 60;
@@ -80,6 +87,7 @@ impl Clock {
 
     /// Set date
     pub fn set_date(&mut self, day: u8, month: u8, year: u16) {
+        self.utc_time = None;
         self.day = day.clamp(1, 31);
         self.month = month.clamp(1, 12);
         self.year = year;
@@ -159,6 +167,16 @@ impl Clock {
 
     /// Tick one second
     pub fn tick(&mut self) {
+        if let Some(utc_time) = self.utc_time {
+            // A timezone change resyncs; DST switches follow from the zone
+            if self.tz_generation != crate::time::tz::generation() {
+                self.sync_from_rtc(crate::time::realtime().tv_sec.max(0) as u64);
+            } else {
+                self.show_utc(utc_time + 1);
+            }
+            return;
+        }
+
         self.second += 1;
         if self.second >= 60 {
             self.second = 0;
@@ -183,16 +201,22 @@ impl Clock {
         }
     }
 
-    /// Sync with RTC
+    /// Sync with RTC (a UTC timestamp); shown in the system timezone
     pub fn sync_from_rtc(&mut self, rtc_time: u64) {
-        // Simple conversion from Unix timestamp
-        // This is a simplified implementation
+        self.tz_generation = crate::time::tz::generation();
+        self.show_utc(rtc_time);
+    }
+
+    fn show_utc(&mut self, utc_time: u64) {
+        self.utc_time = Some(utc_time);
+        let local_time = crate::time::utc_to_local(utc_time as i64).max(0) as u64;
+
         let secs_per_min = 60u64;
         let secs_per_hour = 3600u64;
         let secs_per_day = 86400u64;
 
-        let days_since_epoch = rtc_time / secs_per_day;
-        let remaining_secs = rtc_time % secs_per_day;
+        let days_since_epoch = local_time / secs_per_day;
+        let remaining_secs = local_time % secs_per_day;
 
         self.hour = (remaining_secs / secs_per_hour) as u8;
         self.minute = ((remaining_secs % secs_per_hour) / secs_per_min) as u8;
@@ -7,9 +7,13 @@ extern crate alloc;
 
 use alloc::string::String;
 use alloc::format;
+use alloc::vec;
 use alloc::vec::Vec;
 use core::sync::atomic::{AtomicBool, Ordering};
 
+use crate::fs::{self, InodeKind, Mode, MountFlags};
+use crate::security::Cred;
+use crate::storage::loopdev::{self, LoopConfig};
 use crate::sync::RwSpinlock;
 
 /// Static flag indicating if we're in live boot mode
@@ -18,6 +22,10 @@ static LIVE_BOOT: AtomicBool = AtomicBool::new(false);
 /// Live environment configuration
 static LIVE_CONFIG: RwSpinlock<Option<LiveConfig>> = RwSpinlock::new(None);
 
+/// Loop devices attached by `mount_image`, by mount point, detached again
+/// by `umount`
+static LOOP_MOUNTS: RwSpinlock<Vec<(String, usize)>> = RwSpinlock::new(Vec::new());
+
 /// Live boot modes
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum LiveMode {
@@ -147,6 +155,11 @@ impl LiveUsb {
             .ok_or_else(|| String::from("Squashfs image not found"))?;
         self.config.squashfs_path = squashfs_path.clone();
 
+        // If toram mode, copy to RAM before anything uses the image
+        if self.config.mode == LiveMode::ToRam {
+            self.copy_to_ram()?;
+        }
+
         // Set up overlay filesystem
         self.setup_overlay()?;
 
@@ -155,10 +168,8 @@ impl LiveUsb {
             self.setup_persistence()?;
         }
 
-        // If toram mode, copy to RAM
-        if self.config.mode == LiveMode::ToRam {
-            self.copy_to_ram()?;
-        }
+        // Make /live/merged the new root once the layers are final
+        setup_live_root("/live/merged")?;
 
         // Update global config
         {
@@ -197,10 +208,6 @@ impl LiveUsb {
 
         self.config.overlay_mounted = true;
 
-        // Pivot root or bind mount to make /live/merged the new root
-        // This would require careful handling of /proc, /sys, /dev
-        setup_live_root("/live/merged")?;
-
         Ok(())
     }
 
@@ -250,13 +257,10 @@ impl LiveUsb {
         create_mount_point("/live/toram")?;
         mount_tmpfs("/live/toram", ramdisk_size)?;
 
-        // Copy squashfs to RAM
+        // Copy squashfs to RAM; the overlay is then built from the copy
         let ram_squashfs = "/live/toram/filesystem.squashfs";
         copy_file(&self.config.squashfs_path, ram_squashfs)?;
-
-        // Remount using RAM copy
-        umount("/live/squashfs")?;
-        mount_squashfs(ram_squashfs, "/live/squashfs")?;
+        self.config.squashfs_path = String::from(ram_squashfs);
 
         // Can now safely eject USB
         crate::kprintln!("System copied to RAM - USB can be removed");
@@ -418,90 +422,174 @@ fn get_total_memory() -> u64 {
     4 * 1024 * 1024 * 1024 // 4GB default
 }
 
+fn root_cred() -> Result<Cred, String> {
+    crate::security::user_db()
+        .login("root")
+        .map_err(|e| format!("root credentials: {:?}", e))
+}
+
 fn get_file_size(path: &str) -> Result<u64, String> {
-    // Get file size via VFS
-    Ok(2 * 1024 * 1024 * 1024) // 2GB default
+    let cred = root_cred()?;
+    let inode = fs::vfs_lock()
+        .resolve(path, &cred)
+        .map_err(|e| format!("{}: {:?}", path, e))?;
+    inode.0.size().map(|size| size as u64).map_err(|e| format!("{}: {:?}", path, e))
 }
 
 fn file_exists(path: &str) -> bool {
-    // Check via VFS
-    false
+    root_cred().is_ok_and(|cred| fs::stat(path, &cred).is_ok())
 }
 
 fn create_mount_point(path: &str) -> Result<(), String> {
-    // Create directory for mount point
-    Ok(())
+    create_dir(path)
 }
 
 fn create_dir(path: &str) -> Result<(), String> {
-    // Create directory
+    let cred = root_cred()?;
+    fs::mkdir(path, &cred, Mode::from_octal(0o755)).map_err(|e| format!("mkdir {}: {:?}", path, e))
+}
+
+/// Attach `image` to a loop device and mount it. The loop device is
+/// detached again by `umount`.
+fn mount_image(image: &str, mount_point: &str, fs_type: &str, read_only: bool) -> Result<(), String> {
+    let cred = root_cred()?;
+    let config = LoopConfig { read_only, ..LoopConfig::default() };
+    let device = loopdev::attach_path(image, &cred, config).map_err(|e| format!("{}: {:?}", image, e))?;
+    let index = device.index();
+    let source = format!("/dev/{}", device.name());
+
+    let root = match fs::mount_fs(fs_type, Some(device)) {
+        Ok(root) => root,
+        Err(e) => {
+            let _ = loopdev::detach(index);
+            return Err(format!("mount {} ({}): {:?}", image, fs_type, e));
+        }
+    };
+    let flags = if read_only { MountFlags::RDONLY } else { MountFlags::empty() };
+    fs::vfs_lock().mount(mount_point, root, &source, fs_type, flags);
+    LOOP_MOUNTS.write().push((String::from(mount_point), index));
+    crate::kprintln!("Mounted {} ({}) at {} via {}", image, fs_type, mount_point, source);
     Ok(())
 }
 
 fn mount_squashfs(image: &str, mount_point: &str) -> Result<(), String> {
-    // Mount squashfs filesystem
-    crate::kprintln!("Mounting squashfs {} at {}", image, mount_point);
-    Ok(())
+    mount_image(image, mount_point, "squashfs", true)
 }
 
 fn mount_tmpfs(mount_point: &str, size: u64) -> Result<(), String> {
-    // Mount tmpfs with size limit
+    // tmpfs has no size limit yet; the size is only reported
+    let root = fs::mount_fs("tmpfs", None).map_err(|e| format!("tmpfs: {:?}", e))?;
+    fs::vfs_lock().mount(mount_point, root, "tmpfs", "tmpfs", MountFlags::empty());
     crate::kprintln!("Mounting tmpfs at {} (size: {} MB)", mount_point, size / 1024 / 1024);
     Ok(())
 }
 
 fn mount_overlay(lower: &str, upper: &str, work: &str, merged: &str) -> Result<(), String> {
-    // Mount overlayfs
+    let cred = root_cred()?;
+    let options = format!("lowerdir={},upperdir={},workdir={}", lower, upper, work);
+    let root = fs::overlayfs::mount(&options, &cred).map_err(|e| format!("overlay {}: {:?}", options, e))?;
+    fs::vfs_lock().mount(merged, root, "overlay", "overlay", MountFlags::empty());
     crate::kprintln!("Mounting overlay: lower={}, upper={}, merged={}", lower, upper, merged);
     Ok(())
 }
 
 fn mount_ext4(device: &str, mount_point: &str) -> Result<(), String> {
-    // Mount ext4 filesystem
+    let dev = crate::storage::find_device_by_path(device)
+        .and_then(crate::storage::get_device)
+        .ok_or_else(|| format!("{}: no such device", device))?;
+    let root = fs::mount_fs("ext4", Some(dev)).map_err(|e| format!("mount {}: {:?}", device, e))?;
+    fs::vfs_lock().mount(mount_point, root, device, "ext4", MountFlags::empty());
     Ok(())
 }
 
 fn mount_loop_file(file: &str, mount_point: &str) -> Result<(), String> {
-    // Mount file as loop device
-    Ok(())
+    // Persistence files are ext4 images
+    mount_image(file, mount_point, "ext4", false)
 }
 
 fn umount(mount_point: &str) -> Result<(), String> {
-    // Unmount filesystem
+    fs::vfs_lock()
+        .umount(mount_point, false)
+        .map_err(|e| format!("umount {}: {:?}", mount_point, e))?;
+
+    let attached = {
+        let mut mounts = LOOP_MOUNTS.write();
+        let position = mounts.iter().position(|(path, _)| path == mount_point);
+        position.map(|i| mounts.remove(i).1)
+    };
+    if let Some(index) = attached {
+        if let Err(e) = loopdev::detach(index) {
+            crate::kprintln!("loop{}: still in use after umount of {} ({:?})", index, mount_point, e);
+        }
+    }
     Ok(())
 }
 
 fn setup_live_root(merged_root: &str) -> Result<(), String> {
-    // Set up the merged overlay as the new root
-    // This involves:
-    // 1. Moving /proc, /sys, /dev to new root
-    // 2. pivot_root or switch_root
-    // 3. Cleaning up old root
-
+    // The old root, with /live and the layers below it, stays reachable
+    // at /run/live/rootfs; /proc, /dev and /sys are mounted again on the
+    // new root
     crate::kprintln!("Setting up live root at {}", merged_root);
-    Ok(())
+    fs::pivot_root(merged_root, "/run/live/rootfs").map_err(|e| format!("pivot_root {}: {:?}", merged_root, e))
 }
 
 fn remount_overlay_with_persistence(persistence_path: &str) -> Result<(), String> {
-    // Remount overlay using persistence as upper layer
+    // The persistence storage replaces the tmpfs as upper layer
     crate::kprintln!("Remounting overlay with persistence at {}", persistence_path);
-    Ok(())
+    let upper = format!("{}/upper", persistence_path);
+    let work = format!("{}/work", persistence_path);
+    create_dir(&upper)?;
+    create_dir(&work)?;
+    umount("/live/merged")?;
+    mount_overlay("/live/squashfs", &upper, &work, "/live/merged")
 }
 
 fn copy_file(src: &str, dst: &str) -> Result<(), String> {
-    // Copy file
     crate::kprintln!("Copying {} to {}", src, dst);
-    Ok(())
+    let cred = root_cred()?;
+    let (from, to) = {
+        let mut vfs = fs::vfs_lock();
+        let from = vfs.resolve(src, &cred).map_err(|e| format!("{}: {:?}", src, e))?;
+        if from.kind() != InodeKind::File {
+            return Err(format!("{}: not a regular file", src));
+        }
+        let to = vfs
+            .create(dst, from.metadata().mode, &cred)
+            .map_err(|e| format!("{}: {:?}", dst, e))?;
+        (from, to)
+    };
+
+    let mut buf = vec![0u8; 1024 * 1024];
+    let mut offset = 0;
+    loop {
+        let n = from.0.read_at(offset, &mut buf).map_err(|e| format!("{}: {:?}", src, e))?;
+        if n == 0 {
+            return Ok(());
+        }
+        let mut done = 0;
+        while done < n {
+            match to.0.write_at(offset + done, &buf[done..n]) {
+                Ok(0) => return Err(format!("{}: short write", dst)),
+                Ok(written) => done += written,
+                Err(e) => return Err(format!("{}: {:?}", dst, e)),
+            }
+        }
+        offset += n;
+    }
 }
 
 fn create_sparse_file(path: &str, size: u64) -> Result<(), String> {
-    // Create sparse file
-    Ok(())
+    let cred = root_cred()?;
+    let file = fs::vfs_lock()
+        .create(path, Mode::from_octal(0o600), &cred)
+        .map_err(|e| format!("{}: {:?}", path, e))?;
+    file.0.truncate(size as usize).map_err(|e| format!("{}: {:?}", path, e))
 }
 
 fn write_file(path: &str, content: &str) -> Result<(), String> {
-    // Write content to file
-    Ok(())
+    let cred = root_cred()?;
+    fs::write_file(path, &cred, Mode::from_octal(0o644), content.as_bytes())
+        .map_err(|e| format!("{}: {:?}", path, e))
 }
 
 fn get_overlay_usage() -> (u64, u64) {
@@ -710,6 +710,8 @@ impl RegionalConfigManager {
 
         // Update system timezone offset
         CURRENT_TZ_OFFSET.store(tz.utc_offset, Ordering::Relaxed);
+        // The live system follows it too when its tzdata is present
+        crate::time::set_timezone_by_name(tz_id);
 
         Ok(())
     }
@@ -77,6 +77,7 @@ impl EventFd {
                 Ordering::SeqCst,
                 Ordering::SeqCst
             ).is_ok() {
+                crate::syscall::epoll::wake(self);
                 return Some(return_val);
             }
             // CAS failed, retry
@@ -110,6 +111,7 @@ impl EventFd {
                 Ordering::SeqCst,
                 Ordering::SeqCst
             ).is_ok() {
+                crate::syscall::epoll::wake(self);
                 return Some(());
             }
             // CAS failed, retry
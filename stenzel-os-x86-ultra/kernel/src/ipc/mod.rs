@@ -1,13 +1,14 @@
 //! Inter-Process Communication (IPC)
 //!
 //! System V IPC: shared memory, semaphores, message queues.
-//! Linux-specific: eventfd, namespaces.
+//! Linux-specific: eventfd, timerfd, namespaces.
 
 #![allow(dead_code)]
 
 pub mod shm;
 pub mod msg;
 pub mod eventfd;
+pub mod timerfd;
 pub mod namespace;
 
 pub use shm::{
@@ -23,7 +24,12 @@ pub use msg::{
 
 pub use eventfd::{
     sys_eventfd, sys_eventfd2,
-    EventFdFile, EFD_CLOEXEC, EFD_NONBLOCK, EFD_SEMAPHORE,
+    EventFdFile, EFD_NONBLOCK,
+};
+
+pub use timerfd::{
+    sys_timerfd_create, sys_timerfd_settime, sys_timerfd_gettime,
+    TimerFdFile, Itimerspec, TFD_TIMER_ABSTIME,
 };
 
 /// Initialize IPC subsystem
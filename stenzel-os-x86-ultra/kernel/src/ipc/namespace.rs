@@ -19,7 +19,10 @@ use alloc::sync::Arc;
 use alloc::vec::Vec;
 use core::sync::atomic::{AtomicU64, Ordering};
 
-use crate::sync::IrqSafeMutex;
+use crate::fs::{Chroot, Vfs};
+use crate::sched::Task;
+use crate::security::Cred;
+use crate::sync::{IrqSafeGuard, IrqSafeMutex};
 use crate::util::{KError, KResult};
 
 /// Namespace types (flags for clone/unshare)
@@ -307,60 +310,35 @@ impl UserNamespace {
     }
 }
 
-/// Mount namespace data
-#[derive(Debug)]
+/// Mount namespace: the mount table path lookups of its tasks go through
 pub struct MountNamespace {
     pub id: u64,
-    /// Root mount point
-    pub root: String,
-    /// All mount points in this namespace
-    pub mounts: Vec<MountPoint>,
-}
-
-#[derive(Debug, Clone)]
-pub struct MountPoint {
-    pub source: String,
-    pub target: String,
-    pub fstype: String,
-    pub flags: u64,
-    pub data: String,
+    table: Arc<IrqSafeMutex<Vfs>>,
 }
 
 impl MountNamespace {
-    pub fn new() -> Arc<IrqSafeMutex<Self>> {
-        Arc::new(IrqSafeMutex::new(Self {
-            id: next_namespace_id(),
-            root: String::from("/"),
-            mounts: Vec::new(),
-        }))
-    }
-
-    /// Clone mount namespace (creates new namespace with same mounts)
-    pub fn clone_ns(&self) -> Self {
-        Self {
+    pub fn new(vfs: Vfs) -> Arc<Self> {
+        Arc::new(Self {
             id: next_namespace_id(),
-            root: self.root.clone(),
-            mounts: self.mounts.clone(),
-        }
+            table: Arc::new(IrqSafeMutex::new(vfs)),
+        })
     }
 
-    /// Add a mount point
-    pub fn add_mount(&mut self, mount: MountPoint) {
-        self.mounts.push(mount);
+    /// Clone mount namespace (creates new namespace with a private copy of
+    /// the mounts)
+    pub fn clone_ns(&self) -> Arc<Self> {
+        let vfs = self.table.lock().copy();
+        Self::new(vfs)
     }
 
-    /// Remove a mount point
-    pub fn remove_mount(&mut self, target: &str) -> bool {
-        let orig_len = self.mounts.len();
-        self.mounts.retain(|m| m.target != target);
-        self.mounts.len() != orig_len
+    /// Lock the mount table
+    pub fn lock(&self) -> IrqSafeGuard<'_, Vfs> {
+        self.table.lock()
     }
 
-    /// Find mount for a path
-    pub fn find_mount(&self, path: &str) -> Option<&MountPoint> {
-        self.mounts.iter()
-            .filter(|m| path.starts_with(&m.target))
-            .max_by_key(|m| m.target.len())
+    /// Shared handle on the mount table, for `fs::vfs_lock`
+    pub fn table(&self) -> Arc<IrqSafeMutex<Vfs>> {
+        Arc::clone(&self.table)
     }
 }
 
@@ -475,7 +453,7 @@ pub struct NamespaceSet {
     pub uts_ns: Arc<IrqSafeMutex<UtsNamespace>>,
     pub ipc_ns: Arc<IrqSafeMutex<IpcNamespace>>,
     pub user_ns: Arc<UserNamespace>,
-    pub mnt_ns: Arc<IrqSafeMutex<MountNamespace>>,
+    pub mnt_ns: Arc<MountNamespace>,
     pub net_ns: Arc<IrqSafeMutex<NetNamespace>>,
     pub cgroup_ns: Arc<CgroupNamespace>,
 }
@@ -488,7 +466,7 @@ impl NamespaceSet {
             uts_ns: UtsNamespace::new(),
             ipc_ns: IpcNamespace::new(),
             user_ns: UserNamespace::new(None, 0, 0),
-            mnt_ns: MountNamespace::new(),
+            mnt_ns: crate::fs::init_mnt_ns(),
             net_ns: NetNamespace::new(),
             cgroup_ns: CgroupNamespace::new("/"),
         }
@@ -524,8 +502,7 @@ impl NamespaceSet {
         }
 
         if ns_flags & flags::CLONE_NEWNS != 0 {
-            let mnt = self.mnt_ns.lock().clone_ns();
-            new.mnt_ns = Arc::new(IrqSafeMutex::new(mnt));
+            new.mnt_ns = self.mnt_ns.clone_ns();
         }
 
         if ns_flags & flags::CLONE_NEWNET != 0 {
@@ -548,7 +525,7 @@ impl NamespaceSet {
             NamespaceType::Uts => self.uts_ns.lock().id,
             NamespaceType::Ipc => self.ipc_ns.lock().id,
             NamespaceType::User => self.user_ns.id,
-            NamespaceType::Mount => self.mnt_ns.lock().id,
+            NamespaceType::Mount => self.mnt_ns.id,
             NamespaceType::Net => self.net_ns.lock().id,
             NamespaceType::Cgroup => self.cgroup_ns.id,
         }
@@ -583,30 +560,62 @@ pub fn create_namespaces(parent: &NamespaceSet, flags: u64) -> NamespaceSet {
 // System Calls
 // =============================================================================
 
+/// Private copy of `task`'s mount namespace, with its chroot (if any) moved
+/// over to the copy
+pub fn copy_mnt_ns(task: &Task) -> KResult<(Arc<MountNamespace>, Option<Arc<Chroot>>)> {
+    let ns = task.mnt_ns().clone_ns();
+    let root = match task.fs_root() {
+        Some(old) => {
+            let view = ns.lock().chroot(&old.path, &Cred::root())?;
+            Some(Arc::new(Chroot::new(old.path.clone(), view)))
+        }
+        None => None,
+    };
+    Ok((ns, root))
+}
+
 /// unshare - disassociate parts of the process execution context
 /// Creates new namespace(s) for the calling process
+///
+/// Only the mount namespace is per task so far; any other flag is
+/// rejected, so callers never believe they got an isolation they did not.
 pub fn sys_unshare(flags: u64) -> KResult<()> {
-    // Validate flags
-    if flags & !flags::CLONE_NEWNS_ALL != 0 {
+    if flags & !flags::CLONE_NEWNS != 0 {
         return Err(KError::Invalid);
     }
 
-    // In a full implementation, this would update the current task's namespace set
-    crate::kprintln!("namespace: unshare called with flags {:#x}", flags);
+    let task = crate::sched::current_task();
+
+    if flags & flags::CLONE_NEWNS != 0 {
+        if crate::sched::current_cred().euid.0 != 0 {
+            return Err(KError::PermissionDenied);
+        }
+        let (ns, root) = copy_mnt_ns(task)?;
+        task.set_mnt_ns(ns);
+        task.set_fs_root(root);
+    }
 
     Ok(())
 }
 
 /// setns - reassociate thread with a namespace
-/// fd is an open file descriptor referring to a namespace
-pub fn sys_setns(_fd: i32, _ns_type: u64) -> KResult<()> {
-    // In a full implementation:
-    // 1. Validate fd is a valid namespace fd
-    // 2. Check permissions (CAP_SYS_ADMIN or matching user namespace)
-    // 3. Switch to the target namespace
-
-    crate::kprintln!("namespace: setns not fully implemented");
-    Err(KError::NotSupported)
+///
+/// Like on Linux, joining a mount namespace also resets the root and the
+/// working directory of the caller to the root of that namespace.
+pub fn sys_setns(ns: Arc<MountNamespace>, ns_type: u64) -> KResult<()> {
+    if ns_type != 0 && ns_type != flags::CLONE_NEWNS {
+        return Err(KError::Invalid);
+    }
+
+    let task = crate::sched::current_task();
+    if crate::sched::current_cred().euid.0 != 0 {
+        return Err(KError::PermissionDenied);
+    }
+
+    task.set_mnt_ns(ns);
+    task.set_fs_root(None);
+    task.set_cwd(String::from("/"));
+    Ok(())
 }
 
 /// Get hostname (from UTS namespace)
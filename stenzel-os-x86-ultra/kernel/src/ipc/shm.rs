@@ -315,14 +315,9 @@ pub fn sys_shmat(shmid: ShmId, shmaddr: u64, shmflg: i32) -> i64 {
         (seg.frames.clone(), seg.ds.shm_segsz)
     };
 
-    // Find a free address in user space
+    // Find a free address in the caller's address space
+    let mut vma_manager = mm::vma::manager_lock();
     let addr = {
-        let mut vma_guard = mm::vma::manager_lock();
-        let vma_manager = match vma_guard.as_mut() {
-            Some(v) => v,
-            None => return -22, // EINVAL
-        };
-
         // Use VmaManager to allocate address space
         let prot = if readonly {
             mm::vma::Protection::READ
@@ -345,7 +340,7 @@ pub fn sys_shmat(shmid: ShmId, shmaddr: u64, shmflg: i32) -> i64 {
 
     // Map the shared frames into the address space
     {
-        let mut mapper = mm::mapper_lock();
+        let mut mapper = vma_manager.mapper();
         let mut fa = mm::frame_allocator_lock();
 
         let page_flags = if readonly {
@@ -366,10 +361,12 @@ pub fn sys_shmat(shmid: ShmId, shmaddr: u64, shmflg: i32) -> i64 {
                     let prev_page = Page::<Size4KiB>::containing_address(VirtAddr::new(prev_addr));
                     let _ = mapper.unmap_page_simple(prev_page);
                 }
+                let _ = vma_manager.remove_vma(addr);
                 return -12; // ENOMEM
             }
         }
     }
+    drop(vma_manager);
 
     // Update segment stats and record attachment
     {
@@ -433,8 +430,9 @@ pub fn sys_shmdt(shmaddr: u64) -> i64 {
     };
 
     // Unmap the pages (don't free physical frames - they belong to the segment)
+    let mut vma_manager = mm::vma::manager_lock();
     {
-        let mut mapper = mm::mapper_lock();
+        let mut mapper = vma_manager.mapper();
         let num_pages = size / 4096;
         for i in 0..num_pages {
             let page_addr = shmaddr + (i * 4096) as u64;
@@ -444,14 +442,9 @@ pub fn sys_shmdt(shmaddr: u64) -> i64 {
         }
     }
 
-    // Remove from VMA manager
-    {
-        let mut vma_guard = mm::vma::manager_lock();
-        if let Some(vma_manager) = vma_guard.as_mut() {
-            // Remove the VMA entry (without freeing frames since they're shared)
-            let _ = vma_manager.remove_vma(shmaddr);
-        }
-    }
+    // Remove from VMA manager (without freeing frames since they're shared)
+    let _ = vma_manager.remove_vma(shmaddr);
+    drop(vma_manager);
 
     // Update segment stats and remove attachment
     let should_destroy = {
@@ -0,0 +1,241 @@
+//! timerfd - Timers that notify via a file descriptor
+//!
+//! A timerfd becomes readable when its timer expires; read() returns the
+//! number of expirations since the last read as a u64. Expirations are
+//! counted from the clock whenever the timer is read or polled, so an
+//! interval timer that nobody looks at costs nothing.
+
+use alloc::sync::Arc;
+
+use crate::sync::IrqSafeMutex;
+use crate::syscall::errno;
+use crate::time::{clock, Timespec};
+
+/// timerfd_create flags
+pub const TFD_CLOEXEC: i32 = 0o2000000;
+pub const TFD_NONBLOCK: i32 = 0o0004000;
+
+/// timerfd_settime flags
+pub const TFD_TIMER_ABSTIME: i32 = 1;
+pub const TFD_TIMER_CANCEL_ON_SET: i32 = 2;
+
+pub use crate::time::Itimerspec;
+
+struct TimerState {
+    /// Next expiration on the timer's clock, 0 when disarmed
+    deadline: u64,
+    /// Reload value, 0 for one-shot timers
+    interval: u64,
+    /// Expirations not yet read
+    expirations: u64,
+}
+
+/// Internal timerfd state
+pub struct TimerFd {
+    clockid: i32,
+    state: IrqSafeMutex<TimerState>,
+}
+
+impl TimerFd {
+    pub fn new(clockid: i32) -> Self {
+        Self {
+            clockid,
+            state: IrqSafeMutex::new(TimerState {
+                deadline: 0,
+                interval: 0,
+                expirations: 0,
+            }),
+        }
+    }
+
+    /// Current time on this timer's clock, in nanoseconds
+    fn now(&self) -> u64 {
+        let ts = crate::time::clock_gettime(self.clockid)
+            .unwrap_or_else(crate::time::monotonic);
+        ts.to_ns().unwrap_or(0)
+    }
+
+    /// Counts the expirations that happened up to `now`.
+    fn update(state: &mut TimerState, now: u64) {
+        if state.deadline == 0 || now < state.deadline {
+            return;
+        }
+        if state.interval == 0 {
+            state.expirations += 1;
+            state.deadline = 0;
+        } else {
+            let missed = (now - state.deadline) / state.interval + 1;
+            state.expirations = state.expirations.saturating_add(missed);
+            state.deadline += missed * state.interval;
+        }
+    }
+
+    fn current(state: &TimerState, now: u64) -> Itimerspec {
+        Itimerspec {
+            it_interval: Timespec::from_ns(state.interval),
+            it_value: Timespec::from_ns(if state.deadline == 0 {
+                0
+            } else {
+                state.deadline.saturating_sub(now).max(1)
+            }),
+        }
+    }
+
+    /// Arms (or, with a zero it_value, disarms) the timer. Returns the
+    /// previous setting.
+    pub fn settime(&self, flags: i32, new: &Itimerspec) -> Result<Itimerspec, i64> {
+        let value = new.it_value.to_ns().ok_or(errno::EINVAL)?;
+        let interval = new.it_interval.to_ns().ok_or(errno::EINVAL)?;
+        let now = self.now();
+
+        let mut state = self.state.lock();
+        Self::update(&mut state, now);
+        let old = Self::current(&state, now);
+
+        state.expirations = 0;
+        state.interval = interval;
+        state.deadline = if value == 0 {
+            0
+        } else if flags & TFD_TIMER_ABSTIME != 0 {
+            value.max(1)
+        } else {
+            now.saturating_add(value)
+        };
+        Ok(old)
+    }
+
+    /// Time until the next expiration and the reload interval
+    pub fn gettime(&self) -> Itimerspec {
+        let now = self.now();
+        let mut state = self.state.lock();
+        Self::update(&mut state, now);
+        Self::current(&state, now)
+    }
+
+    /// Takes the expiration count, or None if the timer has not fired
+    pub fn take_expirations(&self) -> Option<u64> {
+        let now = self.now();
+        let mut state = self.state.lock();
+        Self::update(&mut state, now);
+        match state.expirations {
+            0 => None,
+            n => {
+                state.expirations = 0;
+                Some(n)
+            }
+        }
+    }
+
+    /// Check if readable (at least one expiration)
+    pub fn is_readable(&self) -> bool {
+        let now = self.now();
+        let mut state = self.state.lock();
+        Self::update(&mut state, now);
+        state.expirations > 0
+    }
+}
+
+/// Wrapper for timerfd as a file descriptor
+#[derive(Clone)]
+pub struct TimerFdFile {
+    pub inner: Arc<TimerFd>,
+    pub flags: i32,
+}
+
+impl TimerFdFile {
+    pub fn new(clockid: i32, flags: i32) -> Self {
+        Self {
+            inner: Arc::new(TimerFd::new(clockid)),
+            flags,
+        }
+    }
+
+    pub fn is_nonblock(&self) -> bool {
+        self.flags & TFD_NONBLOCK != 0
+    }
+
+    /// Read the expiration count (blocking unless nonblock)
+    pub fn read(&self, buf: &mut [u8]) -> Result<usize, i64> {
+        if buf.len() < 8 {
+            return Err(errno::EINVAL);
+        }
+        loop {
+            if let Some(n) = self.inner.take_expirations() {
+                buf[..8].copy_from_slice(&n.to_ne_bytes());
+                return Ok(8);
+            }
+            if self.is_nonblock() {
+                return Err(errno::EAGAIN);
+            }
+            if crate::sched::has_pending_signals() {
+                return Err(errno::EINTR);
+            }
+            crate::task::yield_now();
+        }
+    }
+
+    /// Poll for readability/writability
+    pub fn poll(&self) -> (bool, bool) {
+        (self.inner.is_readable(), false)
+    }
+}
+
+/// timerfd_create syscall
+///
+/// clockid: CLOCK_REALTIME, CLOCK_MONOTONIC or CLOCK_BOOTTIME
+/// flags: TFD_CLOEXEC, TFD_NONBLOCK
+pub fn sys_timerfd_create(clockid: i32, flags: i32) -> i64 {
+    match clockid {
+        clock::CLOCK_REALTIME | clock::CLOCK_MONOTONIC | clock::CLOCK_BOOTTIME => {}
+        _ => return errno::EINVAL,
+    }
+    if flags & !(TFD_CLOEXEC | TFD_NONBLOCK) != 0 {
+        return errno::EINVAL;
+    }
+
+    match crate::syscall::alloc_fd_for_timerfd(TimerFdFile::new(clockid, flags)) {
+        Some(fd) => fd as i64,
+        None => errno::EMFILE,
+    }
+}
+
+/// timerfd_settime syscall
+pub fn sys_timerfd_settime(fd: i32, flags: i32, new_value: u64, old_value: u64) -> i64 {
+    if flags & !(TFD_TIMER_ABSTIME | TFD_TIMER_CANCEL_ON_SET) != 0 {
+        return errno::EINVAL;
+    }
+    if new_value == 0 || !crate::syscall::is_user_range(new_value, core::mem::size_of::<Itimerspec>()) {
+        return errno::EFAULT;
+    }
+    if old_value != 0 && !crate::syscall::is_user_range(old_value, core::mem::size_of::<Itimerspec>()) {
+        return errno::EFAULT;
+    }
+    let timer = match crate::syscall::timerfd_of(fd) {
+        Ok(t) => t,
+        Err(e) => return e,
+    };
+
+    let new = unsafe { core::ptr::read_unaligned(new_value as *const Itimerspec) };
+    match timer.settime(flags, &new) {
+        Ok(old) => {
+            if old_value != 0 {
+                unsafe { core::ptr::write_unaligned(old_value as *mut Itimerspec, old) };
+            }
+            0
+        }
+        Err(e) => e,
+    }
+}
+
+/// timerfd_gettime syscall
+pub fn sys_timerfd_gettime(fd: i32, curr_value: u64) -> i64 {
+    if curr_value == 0 || !crate::syscall::is_user_range(curr_value, core::mem::size_of::<Itimerspec>()) {
+        return errno::EFAULT;
+    }
+    let timer = match crate::syscall::timerfd_of(fd) {
+        Ok(t) => t,
+        Err(e) => return e,
+    };
+    unsafe { core::ptr::write_unaligned(curr_value as *mut Itimerspec, timer.gettime()) };
+    0
+}
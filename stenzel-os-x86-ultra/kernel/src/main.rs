@@ -8,6 +8,7 @@
     mod arch;
     mod boot;
     mod cgroups;
+    mod compress;
     mod console;
     mod crypto;
     mod drivers;
@@ -105,6 +106,10 @@
             util::kprintln!("boot: TSC não disponível");
         }
 
+        // CSPRNG (RDSEED/RDRAND, TPM e jitter de interrupções)
+        util::kprintln!("boot: inicializando CSPRNG...");
+        crypto::random::init();
+
         // Parse DSDT/SSDT for ACPI device discovery
         util::kprintln!("boot: parsing ACPI DSDT/SSDT...");
         drivers::acpi::init_dsdt();
@@ -145,6 +150,7 @@
 
         util::kprintln!("boot: inicializando syscalls...");
         syscall::init();
+        profiling::ebpf::init();
 
         util::kprintln!("boot: inicializando network stack...");
         net::init();
@@ -159,6 +165,9 @@
             util::kprintln!("boot: WARN: failed to spawn test kernel thread: {:?}", e);
         }
 
+        // Sincronização NTP em segundo plano
+        net::ntp::start();
+
         util::kprintln!("boot: habilitando interrupções...");
         arch::enable_interrupts();
 
@@ -453,6 +453,12 @@ impl OomKiller {
         let processes = process_info();
         let (total_memory, _) = memory_info();
 
+        self.pick_victim(&processes, total_memory)
+    }
+
+    /// Highest scoring process of `processes`, with scores relative to
+    /// `total_memory`
+    fn pick_victim(&self, processes: &[OomProcessInfo], total_memory: u64) -> Option<(Pid, OomScore)> {
         if processes.is_empty() {
             return None;
         }
@@ -498,12 +504,13 @@ impl OomKiller {
         // 3. Compact memory
         // 4. Drop caches
 
-        // For now, just report if we'd try
         if self.config.try_reclaim_first {
-            // Signal MM to try reclaiming
-            // mm::try_reclaim_memory()
-            self.stats.reclaim_success += 1;
-            false // For now, always proceed to kill
+            // Dirty file pages go back to disk and unmapped ones are freed
+            let freed = crate::fs::page_cache::shrink_file_pages(usize::MAX);
+            if freed > 0 {
+                self.stats.reclaim_success += 1;
+            }
+            freed > 0
         } else {
             false
         }
@@ -513,7 +520,7 @@ impl OomKiller {
     fn do_kill(&mut self, pid: Pid) -> bool {
         match self.kill_fn {
             Some(f) => f(pid),
-            None => false,
+            None => crate::sched::send_kernel_signal(pid, crate::signal::sig::SIGKILL).is_ok(),
         }
     }
 
@@ -611,6 +618,59 @@ impl OomKiller {
         Some(result)
     }
 
+    /// OOM inside the memory cgroup at `path`: kill one of `candidates`
+    /// (the processes of the cgroup) and leave the rest of the system
+    /// alone. Scores are relative to the cgroup's memory.max, and page
+    /// cache reclaim does not help since it is not charged to cgroups.
+    pub fn handle_cgroup_oom(
+        &mut self,
+        path: &str,
+        limit: u64,
+        candidates: &[OomProcessInfo],
+    ) -> Option<OomKillResult> {
+        if !self.config.enabled {
+            return None;
+        }
+        self.stats.oom_count += 1;
+
+        let (victim_pid, score) = self.pick_victim(candidates, limit)?;
+        let victim = candidates.iter().find(|p| p.pid == victim_pid)?;
+        let name = victim.name.clone();
+        let memory_to_free = victim.memory.rss;
+
+        if !self.do_kill(victim_pid) {
+            crate::util::kprintln!("OOM: Failed to kill process {} ({}) in cgroup {}", victim_pid, name, path);
+            return None;
+        }
+
+        self.stats.total_kills += 1;
+        self.stats.total_freed += memory_to_free;
+        self.stats.last_kill_time = crate::time::realtime().tv_sec as u64;
+
+        crate::util::kprintln!(
+            "OOM: memory.max of cgroup {} reached, killed process {} ({}) score={} freed ~{} KB",
+            path,
+            victim_pid,
+            name,
+            score,
+            memory_to_free / 1024
+        );
+        if self.config.notify_on_kill {
+            self.notify(
+                OomNotificationKind::ProcessKilled,
+                alloc::format!("Killed process {} ({}) in cgroup {}", victim_pid, name, path),
+            );
+        }
+
+        Some(OomKillResult {
+            pid: victim_pid,
+            name,
+            memory_freed: memory_to_free,
+            score,
+            reason: alloc::format!("Highest OOM score ({}) in cgroup {}", score, path),
+        })
+    }
+
     /// Process cleanup (called when a process exits)
     pub fn process_exit(&mut self, pid: Pid) {
         self.adjustments.remove(&pid);
@@ -666,6 +726,16 @@ pub fn trigger() -> Option<OomKillResult> {
     OOM_KILLER.lock().handle_oom()
 }
 
+/// OOM handler of a memory cgroup (called when a charge over memory.max fails)
+pub fn cgroup_oom(path: &str, limit: u64, candidates: Vec<OomProcessInfo>) -> Option<OomKillResult> {
+    if !OOM_ENABLED.load(Ordering::Acquire) {
+        return None;
+    }
+
+    LAST_OOM.store(crate::time::realtime().tv_sec as u64, Ordering::Release);
+    OOM_KILLER.lock().handle_cgroup_oom(path, limit, &candidates)
+}
+
 /// Set OOM adjustment for a process
 pub fn set_oom_adj(pid: Pid, adj: OomAdjustment) {
     OOM_KILLER.lock().set_oom_adj(pid, adj);
@@ -16,6 +16,17 @@
     }
 
     impl KernelMapper {
+        /// Mapper sobre a page table de um processo.
+        ///
+        /// # Safety
+        /// `p4` precisa ser um P4 válido enquanto o mapper existir, e o
+        /// chamador deve serializar o acesso a essa page table.
+        pub unsafe fn for_page_table(p4: PhysFrame<Size4KiB>, phys_offset: VirtAddr) -> Self {
+            let virt = phys_offset + p4.start_address().as_u64();
+            let level_4_table = &mut *virt.as_mut_ptr::<PageTable>();
+            Self { inner: OffsetPageTable::new(level_4_table, phys_offset) }
+        }
+
         pub fn map_page(
             &mut self,
             page: Page<Size4KiB>,
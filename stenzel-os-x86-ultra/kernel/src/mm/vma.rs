@@ -5,13 +5,27 @@
 #![allow(dead_code)]
 
 use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
 use alloc::vec::Vec;
+use core::ops::{Deref, DerefMut, Range};
+use spin::Once;
 use x86_64::structures::paging::{Page, PageTableFlags, PhysFrame, Size4KiB};
 use x86_64::VirtAddr;
 
-use crate::sync::IrqSafeMutex;
+use super::paging::KernelMapper;
+use crate::fs::page_cache;
+use crate::fs::vfs::Inode;
+use crate::sync::{IrqSafeGuard, IrqSafeMutex};
 use crate::util::{KError, KResult};
 
+/// Bit livre da PTE que marca frames do page cache de arquivos. O fork
+/// compartilha essas entradas como estão em vez de aplicar CoW.
+pub const PAGE_CACHE_BIT: PageTableFlags = PageTableFlags::BIT_9;
+
+const MS_ASYNC: i32 = 0x1;
+const MS_INVALIDATE: i32 = 0x2;
+const MS_SYNC: i32 = 0x4;
+
 /// Proteção de memória
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct Protection {
@@ -79,6 +93,77 @@ impl MapFlags {
     }
 }
 
+/// Arquivo por trás de uma VMA
+#[derive(Clone)]
+pub struct FileMapping {
+    pub inode: Inode,
+    /// Offset no arquivo correspondente ao início da VMA (page-aligned)
+    pub offset: u64,
+    /// Se o fd foi aberto para escrita (exigido por MAP_SHARED + PROT_WRITE)
+    pub writable: bool,
+}
+
+impl core::fmt::Debug for FileMapping {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("FileMapping")
+            .field("ino", &self.inode.metadata().ino)
+            .field("offset", &self.offset)
+            .field("writable", &self.writable)
+            .finish()
+    }
+}
+
+/// Page table de um espaço de endereçamento: a do kernel, sob o lock
+/// global, ou a de um processo, serializada pelo lock do seu `VmaManager`
+pub enum SpaceMapper {
+    Kernel(IrqSafeGuard<'static, KernelMapper>),
+    Process(KernelMapper),
+}
+
+impl SpaceMapper {
+    fn new(p4: Option<PhysFrame<Size4KiB>>) -> Self {
+        match p4 {
+            // SAFETY: o P4 pertence ao espaço do VmaManager, que vive enquanto
+            // o chamador segura o lock dele
+            Some(p4) => SpaceMapper::Process(unsafe {
+                KernelMapper::for_page_table(p4, super::physical_memory_offset())
+            }),
+            None => SpaceMapper::Kernel(super::mapper_lock()),
+        }
+    }
+}
+
+impl Deref for SpaceMapper {
+    type Target = KernelMapper;
+
+    fn deref(&self) -> &KernelMapper {
+        match self {
+            SpaceMapper::Kernel(guard) => guard,
+            SpaceMapper::Process(mapper) => mapper,
+        }
+    }
+}
+
+impl DerefMut for SpaceMapper {
+    fn deref_mut(&mut self) -> &mut KernelMapper {
+        match self {
+            SpaceMapper::Kernel(guard) => guard,
+            SpaceMapper::Process(mapper) => mapper,
+        }
+    }
+}
+
+/// Páginas residentes de uma VMA por tipo (/proc/<pid>/smaps)
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct VmaUsage {
+    pub rss: usize,
+    pub shared_clean: usize,
+    pub shared_dirty: usize,
+    pub private_clean: usize,
+    pub private_dirty: usize,
+    pub anonymous: usize,
+}
+
 /// Uma região de memória virtual
 #[derive(Debug, Clone)]
 pub struct Vma {
@@ -92,8 +177,19 @@ pub struct Vma {
     pub flags: MapFlags,
     /// Frames físicos alocados para esta VMA (None = not allocated yet, for demand paging)
     pub frames: Vec<Option<PhysFrame<Size4KiB>>>,
-    /// Bitmap de páginas que são CoW (compartilhadas com outro processo)
+    /// Bitmap de páginas que são CoW (compartilhadas com outro processo).
+    /// Em mapeamentos privados de arquivo, marca páginas que ainda apontam
+    /// para o frame do page cache.
     pub cow_pages: Vec<bool>,
+    /// Arquivo mapeado (None = anônimo)
+    pub file: Option<FileMapping>,
+    /// Páginas de mapeamento compartilhado de arquivo mapeadas com escrita.
+    /// As demais ficam read-only para que a primeira escrita marque a página
+    /// como suja no page cache.
+    pub dirty_pages: Vec<bool>,
+    /// Páginas do kernel compartilhadas por todos os processos ([vvar],
+    /// [vdso]). Não têm frames próprios e munmap/mprotect não mexem nelas.
+    pub special: Option<&'static str>,
 }
 
 impl Vma {
@@ -109,6 +205,164 @@ impl Vma {
         let end = start + size as u64;
         !(end <= self.start || start >= self.end())
     }
+
+    /// Offset no arquivo do início da VMA (0 se anônima)
+    pub fn file_offset(&self) -> u64 {
+        self.file.as_ref().map_or(0, |f| f.offset)
+    }
+
+    /// Conta as páginas residentes. Frames do page cache e frames ainda
+    /// compartilhados por CoW contam como compartilhados; páginas anônimas
+    /// são sempre sujas.
+    pub fn usage(&self) -> VmaUsage {
+        let mut usage = VmaUsage::default();
+        for (i, frame) in self.frames.iter().enumerate() {
+            let frame = match frame {
+                Some(frame) => frame,
+                None => continue,
+            };
+            usage.rss += 1;
+            let cache = self.is_cache_page(i);
+            let dirty = !cache || self.dirty_pages[i];
+            let shared = cache || super::cow::is_shared(frame.start_address());
+            if !cache {
+                usage.anonymous += 1;
+            }
+            match (shared, dirty) {
+                (true, false) => usage.shared_clean += 1,
+                (true, true) => usage.shared_dirty += 1,
+                (false, false) => usage.private_clean += 1,
+                (false, true) => usage.private_dirty += 1,
+            }
+        }
+        usage
+    }
+
+    fn page_addr(&self, index: usize) -> u64 {
+        self.start + (index * 4096) as u64
+    }
+
+    /// Índice no arquivo da página `index` da VMA
+    fn file_page(&self, index: usize) -> u64 {
+        self.file.as_ref().map_or(0, |f| f.offset / 4096) + index as u64
+    }
+
+    fn is_shared_file(&self) -> bool {
+        self.file.is_some() && self.flags.shared
+    }
+
+    /// Se a página `index` aponta para um frame do page cache
+    fn is_cache_page(&self, index: usize) -> bool {
+        self.file.is_some() && (self.flags.shared || self.cow_pages[index])
+    }
+
+    /// Flags da PTE da página `index`. Páginas do page cache só ficam
+    /// graváveis em mapeamentos compartilhados que já tiveram escrita.
+    fn page_flags(&self, index: usize) -> PageTableFlags {
+        let mut flags = self.prot.to_page_flags(true);
+        if self.is_cache_page(index) {
+            flags |= PAGE_CACHE_BIT;
+            if !(self.flags.shared && self.dirty_pages[index]) {
+                flags.remove(PageTableFlags::WRITABLE);
+            }
+        } else if self.cow_pages[index] {
+            flags.remove(PageTableFlags::WRITABLE);
+        }
+        flags
+    }
+
+    /// Cópia das páginas `pages` como uma VMA própria
+    fn slice(&self, pages: Range<usize>) -> Vma {
+        let mut file = self.file.clone();
+        if let Some(f) = file.as_mut() {
+            f.offset += (pages.start * 4096) as u64;
+        }
+        Vma {
+            start: self.page_addr(pages.start),
+            size: pages.len() * 4096,
+            prot: self.prot,
+            flags: self.flags,
+            frames: self.frames[pages.clone()].to_vec(),
+            cow_pages: self.cow_pages[pages.clone()].to_vec(),
+            file,
+            dirty_pages: self.dirty_pages[pages].to_vec(),
+            special: self.special,
+        }
+    }
+
+    /// Desmapeia as páginas `pages` da page table `p4`. Frames anônimos são
+    /// liberados pelo último dono; frames do page cache perdem um mapeamento
+    /// (o último escreve a página de volta).
+    fn release_pages(&mut self, pages: Range<usize>, p4: Option<PhysFrame<Size4KiB>>) {
+        let mut anon_frames = Vec::new();
+        let mut file_frames = Vec::new();
+        {
+            let mut mapper = SpaceMapper::new(p4);
+            for i in pages {
+                let recorded = match self.frames[i].take() {
+                    Some(frame) => frame,
+                    None => continue,
+                };
+                // Vale o frame da PTE: o fault de CoW pode tê-lo trocado
+                let page: Page<Size4KiB> = Page::containing_address(VirtAddr::new(self.page_addr(i)));
+                let frame = match mapper.unmap_page(page) {
+                    Ok((frame, flush)) => {
+                        flush();
+                        frame
+                    }
+                    Err(_) => recorded,
+                };
+                if self.is_cache_page(i) {
+                    file_frames.push((frame, self.dirty_pages[i]));
+                } else {
+                    anon_frames.push(frame);
+                }
+                self.cow_pages[i] = false;
+                self.dirty_pages[i] = false;
+            }
+        }
+
+        // Frames compartilhados após fork só voltam ao allocator com a última referência
+        for frame in anon_frames {
+            if super::cow::decrement_ref(frame.start_address()) {
+                crate::cgroups::mm_uncharge_page(frame.start_address().as_u64());
+                super::free_frame(frame);
+            }
+        }
+
+        // Fora dos locks: o page cache pode fazer I/O
+        for (frame, writable) in file_frames {
+            page_cache::unmap_file_page(frame, writable);
+        }
+    }
+
+    /// Volta as páginas sujas de `pages` para read-only, para que a próxima
+    /// escrita seja notada, e escreve o trecho do arquivo de volta
+    fn writeback(&mut self, pages: Range<usize>, p4: Option<PhysFrame<Size4KiB>>) -> KResult<()> {
+        if !self.is_shared_file() || pages.is_empty() {
+            return Ok(());
+        }
+
+        {
+            let mut mapper = SpaceMapper::new(p4);
+            for i in pages.clone() {
+                if !self.dirty_pages[i] {
+                    continue;
+                }
+                self.dirty_pages[i] = false;
+                let page: Page<Size4KiB> = Page::containing_address(VirtAddr::new(self.page_addr(i)));
+                mapper.update_page_flags(page, self.page_flags(i));
+                if let Some(frame) = self.frames[i] {
+                    page_cache::page_write_protected(frame);
+                }
+            }
+        }
+
+        let first = self.file_page(pages.start);
+        let last = self.file_page(pages.end - 1);
+        let inode = self.file.as_ref().map(|f| f.inode.clone()).ok_or(KError::Invalid)?;
+        page_cache::writeback_file_range(&inode, first, last)
+    }
 }
 
 /// Gerenciador de VMAs por processo
@@ -117,6 +371,11 @@ pub struct VmaManager {
     vmas: BTreeMap<u64, Vma>,
     /// Próximo endereço para alocação automática
     next_addr: u64,
+    /// P4 do espaço de endereçamento (None = page table do kernel)
+    p4: Option<PhysFrame<Size4KiB>>,
+    /// Início do heap (fim da imagem carregada) e program break atual
+    brk_start: u64,
+    brk: u64,
 }
 
 impl VmaManager {
@@ -129,7 +388,66 @@ impl VmaManager {
         Self {
             vmas: BTreeMap::new(),
             next_addr: Self::MMAP_BASE,
+            p4: None,
+            brk_start: 0,
+            brk: 0,
+        }
+    }
+
+    /// VMAs vazias para o espaço de endereçamento com P4 em `cr3`
+    pub fn for_page_table(cr3: PhysFrame<Size4KiB>) -> Self {
+        Self {
+            p4: Some(cr3),
+            ..Self::new()
+        }
+    }
+
+    /// Cópia das VMAs para o filho do fork. As page tables já foram clonadas
+    /// (CoW e páginas de arquivo compartilhadas), então os frames valem igual.
+    pub fn fork(&self, child_cr3: PhysFrame<Size4KiB>) -> Self {
+        Self {
+            vmas: self.vmas.clone(),
+            next_addr: self.next_addr,
+            p4: Some(child_cr3),
+            brk_start: self.brk_start,
+            brk: self.brk,
+        }
+    }
+
+    /// Program break atual
+    pub fn brk(&self) -> u64 {
+        self.brk
+    }
+
+    /// Início do heap; o break nunca desce abaixo dele
+    pub fn brk_start(&self) -> u64 {
+        self.brk_start
+    }
+
+    pub fn set_brk(&mut self, brk: u64) {
+        self.brk = brk;
+    }
+
+    /// Page table onde as VMAs são mapeadas
+    pub fn mapper(&self) -> SpaceMapper {
+        SpaceMapper::new(self.p4)
+    }
+
+    /// Remove todas as VMAs, escrevendo de volta as páginas sujas de arquivo
+    /// e liberando os frames (exec e exit)
+    pub fn clear(&mut self) {
+        let p4 = self.p4;
+        for (_, mut vma) in core::mem::take(&mut self.vmas) {
+            let pages = 0..vma.frames.len();
+            let _ = vma.writeback(pages.clone(), p4);
+            vma.release_pages(pages, p4);
         }
+        self.next_addr = Self::MMAP_BASE;
+    }
+
+    /// Itera sobre as VMAs em ordem de endereço
+    pub fn iter(&self) -> impl Iterator<Item = &Vma> {
+        self.vmas.values()
     }
 
     /// Encontra um endereço livre para um mapeamento de `size` bytes
@@ -162,6 +480,35 @@ impl VmaManager {
         size: usize,
         prot: Protection,
         flags: MapFlags,
+    ) -> KResult<u64> {
+        self.map_region(addr_hint, size, prot, flags, None)
+    }
+
+    /// Cria um mapeamento de arquivo. As páginas vêm do page cache sob demanda.
+    pub fn mmap_file(
+        &mut self,
+        addr_hint: u64,
+        size: usize,
+        prot: Protection,
+        flags: MapFlags,
+        file: FileMapping,
+    ) -> KResult<u64> {
+        if file.offset & 0xFFF != 0 || flags.shared == flags.private {
+            return Err(KError::Invalid);
+        }
+        if flags.shared && prot.write && !file.writable {
+            return Err(KError::PermissionDenied);
+        }
+        self.map_region(addr_hint, size, prot, flags, Some(file))
+    }
+
+    fn map_region(
+        &mut self,
+        addr_hint: u64,
+        size: usize,
+        prot: Protection,
+        flags: MapFlags,
+        file: Option<FileMapping>,
     ) -> KResult<u64> {
         if size == 0 {
             return Err(KError::Invalid);
@@ -195,6 +542,7 @@ impl VmaManager {
         // Frames serão alocados sob demanda no page fault handler
         let frames = alloc::vec![None; num_pages];
         let cow_pages = alloc::vec![false; num_pages];
+        let dirty_pages = alloc::vec![false; num_pages];
 
         // Registra a VMA (páginas não estão mapeadas ainda)
         let vma = Vma {
@@ -204,6 +552,9 @@ impl VmaManager {
             flags,
             frames,
             cow_pages,
+            file,
+            dirty_pages,
+            special: None,
         };
         self.vmas.insert(addr, vma);
 
@@ -222,6 +573,9 @@ impl VmaManager {
         // Encontra a VMA que contém este endereço
         let vma = self.vmas.values_mut().find(|vma| vma.contains(fault_addr));
         let vma = vma.ok_or(KError::Invalid)?;
+        if vma.special.is_some() {
+            return Err(KError::PermissionDenied);
+        }
 
         // Calcula qual página dentro da VMA
         let page_index = ((page_addr - vma.start) / 4096) as usize;
@@ -232,26 +586,65 @@ impl VmaManager {
             return Err(KError::PermissionDenied);
         }
 
-        // Aloca um frame físico
-        let mut fa = super::frame_allocator_lock();
-        let frame = fa.allocate().ok_or(KError::NoMemory)?;
-        drop(fa);
+        let frame = if let Some(file) = &vma.file {
+            // Página de arquivo: vem do page cache. Em mapeamentos privados
+            // fica read-only até a primeira escrita (CoW).
+            let frame = page_cache::map_file_page(&file.inode, vma.file_page(page_index))?;
+            if vma.flags.private {
+                vma.cow_pages[page_index] = true;
+            }
+            frame
+        } else {
+            // Aloca um frame físico (sob pressão, libera páginas de arquivo)
+            let frame = match super::alloc_frame() {
+                Some(frame) => frame,
+                None => {
+                    page_cache::shrink_file_pages(16);
+                    super::alloc_frame().ok_or(KError::NoMemory)?
+                }
+            };
+
+            // Conta a página no memory.max do cgroup. Se o OOM killer matou
+            // alguém, retorna sem mapear: o acesso gera o fault de novo.
+            match crate::cgroups::mm_charge_page(frame.start_address().as_u64()) {
+                Ok(()) => {}
+                Err(KError::WouldBlock) => {
+                    super::free_frame(frame);
+                    return Ok(());
+                }
+                Err(e) => {
+                    super::free_frame(frame);
+                    return Err(e);
+                }
+            }
 
-        // Zero a página se for anônima
-        if vma.flags.anonymous {
-            let virt = super::phys_to_virt(frame.start_address());
-            unsafe {
-                core::ptr::write_bytes(virt.as_mut_ptr::<u8>(), 0, 4096);
+            // Zero a página se for anônima
+            if vma.flags.anonymous {
+                let virt = super::phys_to_virt(frame.start_address());
+                unsafe {
+                    core::ptr::write_bytes(virt.as_mut_ptr::<u8>(), 0, 4096);
+                }
             }
-        }
+            frame
+        };
 
         // Mapeia a página
-        let page_flags = vma.prot.to_page_flags(true);
-        {
-            let mut mapper = super::mapper_lock();
+        let page_flags = vma.page_flags(page_index);
+        let mapped = {
+            let mut mapper = SpaceMapper::new(self.p4);
             let mut fa = super::frame_allocator_lock();
             let page: Page<Size4KiB> = Page::containing_address(VirtAddr::new(page_addr));
-            mapper.map_page(page, frame, page_flags, &mut *fa)?;
+            mapper.map_page(page, frame, page_flags, &mut *fa)
+        };
+        if let Err(e) = mapped {
+            if vma.file.is_some() {
+                vma.cow_pages[page_index] = false;
+                page_cache::unmap_file_page(frame, false);
+            } else {
+                crate::cgroups::mm_uncharge_page(frame.start_address().as_u64());
+                super::free_frame(frame);
+            }
+            return Err(e);
         }
 
         // Registra o frame na VMA
@@ -262,6 +655,110 @@ impl VmaManager {
         Ok(())
     }
 
+    /// Escrita em página presente mas read-only: primeira escrita em
+    /// mapeamento compartilhado de arquivo ou cópia de página CoW
+    pub fn handle_write_fault(&mut self, fault_addr: u64, old_phys: x86_64::PhysAddr) -> KResult<()> {
+        let page_addr = fault_addr & !0xFFF;
+
+        // Encontra a VMA que contém este endereço
+        let vma = self.vmas.values_mut().find(|vma| vma.contains(fault_addr));
+        let vma = vma.ok_or(KError::Invalid)?;
+
+        // Verifica se a VMA permite escrita
+        if !vma.prot.write {
+            return Err(KError::PermissionDenied);
+        }
+
+        // Calcula qual página dentro da VMA
+        let page_index = ((page_addr - vma.start) / 4096) as usize;
+        let page: Page<Size4KiB> = Page::containing_address(VirtAddr::new(page_addr));
+
+        // Mapeamento compartilhado de arquivo: primeira escrita desde o último
+        // writeback. Marca a página como suja e libera a escrita.
+        if vma.is_shared_file() {
+            let frame = vma.frames.get(page_index).copied().flatten().ok_or(KError::Invalid)?;
+            if !vma.dirty_pages[page_index] {
+                if !page_cache::mark_page_written(frame) {
+                    return Err(KError::Invalid);
+                }
+                vma.dirty_pages[page_index] = true;
+            }
+            SpaceMapper::new(self.p4).update_page_flags(page, vma.page_flags(page_index));
+            return Ok(());
+        }
+
+        // Verifica se esta página está marcada como CoW
+        if page_index >= vma.cow_pages.len() || !vma.cow_pages[page_index] {
+            return Err(KError::Invalid);
+        }
+
+        // Aloca novo frame
+        let mut fa = super::frame_allocator_lock();
+        let new_frame = fa.allocate().ok_or(KError::NoMemory)?;
+        drop(fa);
+        match crate::cgroups::mm_charge_page(new_frame.start_address().as_u64()) {
+            Ok(()) => {}
+            Err(KError::WouldBlock) => {
+                super::free_frame(new_frame);
+                return Ok(());
+            }
+            Err(e) => {
+                super::free_frame(new_frame);
+                return Err(e);
+            }
+        }
+
+        // Copia o conteúdo do frame antigo para o novo
+        let old_virt = super::phys_to_virt(old_phys);
+        let new_virt = super::phys_to_virt(new_frame.start_address());
+        unsafe {
+            core::ptr::copy_nonoverlapping(
+                old_virt.as_ptr::<u8>(),
+                new_virt.as_mut_ptr::<u8>(),
+                4096,
+            );
+        }
+
+        // Atualiza o mapeamento para apontar para o novo frame com permissão de escrita
+        let page_flags = vma.prot.to_page_flags(true);
+
+        {
+            let mut mapper = SpaceMapper::new(self.p4);
+            let mut fa = super::frame_allocator_lock();
+
+            // Desmapeia a página antiga
+            if let Ok((_old_frame, flush)) = mapper.unmap_page(page) {
+                flush();
+            }
+
+            // Mapeia o novo frame
+            if let Err(e) = mapper.map_page(page, new_frame, page_flags, &mut *fa) {
+                drop(fa);
+                crate::cgroups::mm_uncharge_page(new_frame.start_address().as_u64());
+                super::free_frame(new_frame);
+                return Err(e);
+            }
+        }
+
+        // Devolve o frame antigo: página do page cache (mapeamento privado de
+        // arquivo) ou frame compartilhado no CoW manager
+        let old_frame = PhysFrame::containing_address(old_phys);
+        if vma.file.is_some() {
+            page_cache::unmap_file_page(old_frame, false);
+        } else if super::cow::decrement_ref(old_phys) {
+            crate::cgroups::mm_uncharge_page(old_phys.as_u64());
+            super::free_frame(old_frame);
+        }
+
+        // Atualiza a VMA
+        if page_index < vma.frames.len() {
+            vma.frames[page_index] = Some(new_frame);
+        }
+        vma.cow_pages[page_index] = false;
+
+        Ok(())
+    }
+
     /// Remove um mapeamento
     pub fn munmap(&mut self, addr: u64, size: usize) -> KResult<()> {
         if addr & 0xFFF != 0 || size == 0 {
@@ -277,177 +774,165 @@ impl VmaManager {
             .filter(|(_, vma)| vma.overlaps(addr, aligned_size))
             .map(|(&k, _)| k)
             .collect();
+        if overlapping.iter().any(|k| self.vmas[k].special.is_some()) {
+            return Err(KError::Invalid);
+        }
 
         for vma_start in overlapping {
-            if let Some(vma) = self.vmas.remove(&vma_start) {
-                // Caso 1: VMA completamente contida na região - remove tudo
-                if vma.start >= addr && vma.end() <= end {
-                    // Desmapeia todas as páginas e libera os frames
-                    let mut mapper = super::mapper_lock();
-                    let mut fa = super::frame_allocator_lock();
-                    for (i, frame) in vma.frames.iter().enumerate() {
-                        if let Some(frame) = frame {
-                            let page_addr = VirtAddr::new(vma.start + (i * 4096) as u64);
-                            let page: Page<Size4KiB> = Page::containing_address(page_addr);
-                            let _ = mapper.unmap_page(page);
-                            // Libera o frame físico
-                            fa.deallocate(*frame);
-                        }
-                    }
+            if let Some(mut vma) = self.vmas.remove(&vma_start) {
+                // Páginas da VMA dentro da região removida
+                let num_pages = vma.frames.len();
+                let first = ((addr.max(vma.start) - vma.start) / 4096) as usize;
+                let last = ((end.min(vma.end()) - vma.start) / 4096) as usize;
+
+                // Páginas sujas de mapeamentos compartilhados vão para o
+                // arquivo antes de desmapear
+                let _ = vma.writeback(first..last, self.p4);
+                vma.release_pages(first..last, self.p4);
+
+                // Mantém as partes antes e depois da região (split)
+                if first > 0 {
+                    let before = vma.slice(0..first);
+                    self.vmas.insert(before.start, before);
                 }
-                // Caso 2: VMA parcialmente contida - precisa split
-                else if vma.start < addr && vma.end() > end {
-                    // Split: cria duas novas VMAs (antes e depois da região removida)
-                    let mut mapper = super::mapper_lock();
-                    let mut fa = super::frame_allocator_lock();
-
-                    // Calcula índices das páginas a remover
-                    let start_page = ((addr - vma.start) / 4096) as usize;
-                    let end_page = ((end - vma.start) / 4096) as usize;
-
-                    // Remove as páginas no meio
-                    for i in start_page..end_page.min(vma.frames.len()) {
-                        if let Some(frame) = vma.frames[i] {
-                            let page_addr = VirtAddr::new(vma.start + (i * 4096) as u64);
-                            let page: Page<Size4KiB> = Page::containing_address(page_addr);
-                            let _ = mapper.unmap_page(page);
-                            fa.deallocate(frame);
-                        }
-                    }
+                if last < num_pages {
+                    let after = vma.slice(last..num_pages);
+                    self.vmas.insert(after.start, after);
+                }
+            }
+        }
 
-                    drop(mapper);
-                    drop(fa);
-
-                    // Cria VMA antes da região removida
-                    if start_page > 0 {
-                        let before_vma = Vma {
-                            start: vma.start,
-                            size: start_page * 4096,
-                            prot: vma.prot,
-                            flags: vma.flags,
-                            frames: vma.frames[..start_page].to_vec(),
-                            cow_pages: vma.cow_pages[..start_page].to_vec(),
-                        };
-                        self.vmas.insert(before_vma.start, before_vma);
-                    }
+        Ok(())
+    }
 
-                    // Cria VMA depois da região removida
-                    if end_page < vma.frames.len() {
-                        let after_vma = Vma {
-                            start: end,
-                            size: (vma.frames.len() - end_page) * 4096,
-                            prot: vma.prot,
-                            flags: vma.flags,
-                            frames: vma.frames[end_page..].to_vec(),
-                            cow_pages: vma.cow_pages[end_page..].to_vec(),
-                        };
-                        self.vmas.insert(after_vma.start, after_vma);
-                    }
-                }
-                // Caso 3: VMA parcialmente sobreposta no início
-                else if vma.start < addr {
-                    let mut mapper = super::mapper_lock();
-                    let mut fa = super::frame_allocator_lock();
-
-                    let keep_pages = ((addr - vma.start) / 4096) as usize;
-
-                    // Remove páginas do final
-                    for i in keep_pages..vma.frames.len() {
-                        if let Some(frame) = vma.frames[i] {
-                            let page_addr = VirtAddr::new(vma.start + (i * 4096) as u64);
-                            let page: Page<Size4KiB> = Page::containing_address(page_addr);
-                            let _ = mapper.unmap_page(page);
-                            fa.deallocate(frame);
-                        }
-                    }
+    /// Altera proteção de uma região. VMAs parcialmente cobertas são divididas.
+    pub fn mprotect(&mut self, addr: u64, size: usize, prot: Protection) -> KResult<()> {
+        if addr & 0xFFF != 0 || size == 0 {
+            return Err(KError::Invalid);
+        }
 
-                    drop(mapper);
-                    drop(fa);
-
-                    // Mantém a parte inicial
-                    if keep_pages > 0 {
-                        let trimmed_vma = Vma {
-                            start: vma.start,
-                            size: keep_pages * 4096,
-                            prot: vma.prot,
-                            flags: vma.flags,
-                            frames: vma.frames[..keep_pages].to_vec(),
-                            cow_pages: vma.cow_pages[..keep_pages].to_vec(),
-                        };
-                        self.vmas.insert(trimmed_vma.start, trimmed_vma);
-                    }
-                }
-                // Caso 4: VMA parcialmente sobreposta no fim
-                else {
-                    let mut mapper = super::mapper_lock();
-                    let mut fa = super::frame_allocator_lock();
-
-                    let remove_pages = ((vma.end().min(end) - vma.start) / 4096) as usize;
-
-                    // Remove páginas do início
-                    for i in 0..remove_pages.min(vma.frames.len()) {
-                        if let Some(frame) = vma.frames[i] {
-                            let page_addr = VirtAddr::new(vma.start + (i * 4096) as u64);
-                            let page: Page<Size4KiB> = Page::containing_address(page_addr);
-                            let _ = mapper.unmap_page(page);
-                            fa.deallocate(frame);
-                        }
-                    }
+        let aligned_size = (size + 0xFFF) & !0xFFF;
+        let end = addr + aligned_size as u64;
 
-                    drop(mapper);
-                    drop(fa);
-
-                    // Mantém a parte final
-                    if remove_pages < vma.frames.len() {
-                        let trimmed_vma = Vma {
-                            start: vma.start + (remove_pages * 4096) as u64,
-                            size: (vma.frames.len() - remove_pages) * 4096,
-                            prot: vma.prot,
-                            flags: vma.flags,
-                            frames: vma.frames[remove_pages..].to_vec(),
-                            cow_pages: vma.cow_pages[remove_pages..].to_vec(),
-                        };
-                        self.vmas.insert(trimmed_vma.start, trimmed_vma);
-                    }
+        let keys = self.covering(addr, end).ok_or(KError::NoMemory)?;
+
+        // Escrita em mapeamento compartilhado exige fd aberto para escrita
+        let denied = keys.iter().filter_map(|k| self.vmas.get(k)).any(|vma| {
+            vma.special.is_some()
+                || (prot.write && vma.flags.shared && vma.file.as_ref().map_or(false, |f| !f.writable))
+        });
+        if denied {
+            return Err(KError::PermissionDenied);
+        }
+
+        for key in keys {
+            let vma = match self.vmas.remove(&key) {
+                Some(vma) => vma,
+                None => continue,
+            };
+            let num_pages = vma.frames.len();
+            let first = ((addr.max(vma.start) - vma.start) / 4096) as usize;
+            let last = ((end.min(vma.end()) - vma.start) / 4096) as usize;
+
+            if first > 0 {
+                let before = vma.slice(0..first);
+                self.vmas.insert(before.start, before);
+            }
+            if last < num_pages {
+                let after = vma.slice(last..num_pages);
+                self.vmas.insert(after.start, after);
+            }
+
+            let mut middle = vma.slice(first..last);
+            middle.prot = prot;
+
+            // Atualiza as flags de cada página mapeada na região
+            let mut mapper = self.mapper();
+            for i in 0..middle.frames.len() {
+                let frame = match middle.frames[i] {
+                    Some(frame) => frame,
+                    None => continue,
+                };
+                if !prot.write && middle.dirty_pages[i] {
+                    middle.dirty_pages[i] = false;
+                    page_cache::page_write_protected(frame);
                 }
+                let page: Page<Size4KiB> = Page::containing_address(VirtAddr::new(middle.page_addr(i)));
+                mapper.update_page_flags(page, middle.page_flags(i));
             }
+            drop(mapper);
+
+            self.vmas.insert(middle.start, middle);
         }
 
         Ok(())
     }
 
-    /// Altera proteção de uma região
-    pub fn mprotect(&mut self, addr: u64, size: usize, prot: Protection) -> KResult<()> {
-        if addr & 0xFFF != 0 || size == 0 {
+    /// Escreve de volta as páginas sujas de mapeamentos compartilhados de
+    /// arquivo na região. MS_ASYNC também escreve na hora.
+    pub fn msync(&mut self, addr: u64, size: usize, flags: i32) -> KResult<()> {
+        if addr & 0xFFF != 0
+            || flags & !(MS_ASYNC | MS_INVALIDATE | MS_SYNC) != 0
+            || (flags & MS_ASYNC != 0 && flags & MS_SYNC != 0)
+        {
             return Err(KError::Invalid);
         }
+        if size == 0 {
+            return Ok(());
+        }
 
         let aligned_size = (size + 0xFFF) & !0xFFF;
-        let new_flags = prot.to_page_flags(true);
+        let end = addr + aligned_size as u64;
 
-        // Encontra a VMA que contém este endereço
-        for vma in self.vmas.values_mut() {
-            if vma.contains(addr) && addr + aligned_size as u64 <= vma.end() {
-                vma.prot = prot;
-
-                // Atualiza as flags de cada página na região
-                let mut mapper = super::mapper_lock();
-                let start_offset = (addr - vma.start) as usize;
-                let start_page = start_offset / 4096;
-                let num_pages = aligned_size / 4096;
-
-                for i in start_page..(start_page + num_pages).min(vma.frames.len()) {
-                    let page_addr = VirtAddr::new(vma.start + (i * 4096) as u64);
-                    let page: Page<Size4KiB> = Page::containing_address(page_addr);
-                    // Atualiza as flags da página
-                    mapper.update_page_flags(page, new_flags);
+        let keys = self.covering(addr, end).ok_or(KError::NoMemory)?;
+
+        let mut result = Ok(());
+        for key in keys {
+            if let Some(vma) = self.vmas.get_mut(&key) {
+                let first = ((addr.max(vma.start) - vma.start) / 4096) as usize;
+                let last = ((end.min(vma.end()) - vma.start) / 4096) as usize;
+                if let Err(e) = vma.writeback(first..last, self.p4) {
+                    result = Err(e);
                 }
+            }
+        }
+        result
+    }
 
-                return Ok(());
+    /// Chaves das VMAs que cobrem `addr..end`, ou None se houver buraco
+    fn covering(&self, addr: u64, end: u64) -> Option<Vec<u64>> {
+        let mut keys = Vec::new();
+        let mut cursor = addr;
+        for (&key, vma) in self.vmas.range(..end) {
+            if vma.end() <= cursor {
+                continue;
             }
+            if vma.start > cursor {
+                return None;
+            }
+            keys.push(key);
+            cursor = vma.end();
+        }
+        if cursor < end {
+            return None;
         }
+        Some(keys)
+    }
 
-        Err(KError::Invalid)
+    /// Registra uma página especial já mapeada na page table (vvar, vDSO)
+    pub fn insert_special(&mut self, start: u64, prot: Protection, name: &'static str) {
+        let vma = Vma {
+            start,
+            size: 4096,
+            prot,
+            flags: MapFlags { shared: false, private: true, anonymous: false, fixed: true },
+            frames: alloc::vec![None],
+            cow_pages: alloc::vec![false],
+            file: None,
+            dirty_pages: alloc::vec![false],
+            special: Some(name),
+        };
+        self.vmas.insert(start, vma);
     }
 
     /// Verifica se um endereço está mapeado
@@ -465,6 +950,9 @@ impl VmaManager {
     pub fn remove_vma(&mut self, addr: u64) -> KResult<()> {
         // Simply remove the VMA entry without deallocating frames
         // The frames are managed by the shared memory subsystem
+        if self.vmas.get(&addr).is_some_and(|vma| vma.special.is_some()) {
+            return Err(KError::Invalid);
+        }
         if self.vmas.remove(&addr).is_some() {
             Ok(())
         } else {
@@ -483,121 +971,124 @@ impl VmaManager {
     }
 }
 
-/// VMA manager global (simplificado para single process)
-static VMA_MANAGER: IrqSafeMutex<Option<VmaManager>> = IrqSafeMutex::new(None);
+/// VMAs de um espaço de endereçamento, compartilhadas pelas threads (CLONE_VM)
+pub type Vmas = Arc<IrqSafeMutex<VmaManager>>;
+
+/// VMAs da page table do kernel, usadas por tasks sem espaço próprio
+static KERNEL_VMAS: Once<Vmas> = Once::new();
 
 pub fn init() {
-    *VMA_MANAGER.lock() = Some(VmaManager::new());
+    kernel_vmas();
+}
+
+pub fn kernel_vmas() -> Vmas {
+    KERNEL_VMAS
+        .call_once(|| Arc::new(IrqSafeMutex::new(VmaManager::new())))
+        .clone()
+}
+
+/// VMAs para um novo espaço de endereçamento (spawn e exec), com o heap
+/// começando em `brk`. Com `vdso`, registra as páginas que `vdso::map`
+/// mapeou.
+pub fn new_vmas(cr3: PhysFrame<Size4KiB>, brk: u64, vdso: bool) -> Vmas {
+    let mut manager = VmaManager {
+        brk_start: brk,
+        brk,
+        ..VmaManager::for_page_table(cr3)
+    };
+    if vdso {
+        use crate::arch::x86_64_arch::vdso::{VDSO_ADDR, VVAR_ADDR};
+        manager.insert_special(VVAR_ADDR, Protection::READ, "[vvar]");
+        manager.insert_special(VDSO_ADDR, Protection::READ_EXEC, "[vdso]");
+    }
+    Arc::new(IrqSafeMutex::new(manager))
+}
+
+/// VMAs do filho do fork, com page table em `child_cr3`
+pub fn fork_vmas(parent: &Vmas, child_cr3: PhysFrame<Size4KiB>) -> Vmas {
+    Arc::new(IrqSafeMutex::new(parent.lock().fork(child_cr3)))
+}
+
+/// Solta uma referência às VMAs. A última (exec ou exit da última thread)
+/// desmapeia tudo; deve rodar antes de a page table ser liberada.
+pub fn release_vmas(vmas: Vmas) {
+    if let Ok(manager) = Arc::try_unwrap(vmas) {
+        manager.lock().clear();
+    }
 }
 
-pub fn manager_lock() -> crate::sync::IrqSafeGuard<'static, Option<VmaManager>> {
-    VMA_MANAGER.lock()
+/// Guard das VMAs da task atual. Mantém o `Arc` vivo enquanto o lock é usado.
+pub struct VmaGuard {
+    // Declarado primeiro: destravado antes de o Arc ser solto
+    guard: IrqSafeGuard<'static, VmaManager>,
+    _vmas: Vmas,
 }
 
-/// Syscall mmap
-pub fn sys_mmap(addr: u64, length: usize, prot: i32, flags: i32, _fd: i32, _offset: i64) -> KResult<u64> {
+impl Deref for VmaGuard {
+    type Target = VmaManager;
+
+    fn deref(&self) -> &VmaManager {
+        &self.guard
+    }
+}
+
+impl DerefMut for VmaGuard {
+    fn deref_mut(&mut self) -> &mut VmaManager {
+        &mut self.guard
+    }
+}
+
+/// Trava as VMAs da task atual (as do kernel se ainda não há scheduler)
+pub fn manager_lock() -> VmaGuard {
+    let vmas = match crate::sched::try_current_task() {
+        Some(task) => task.vmas(),
+        None => kernel_vmas(),
+    };
+    // SAFETY: o guard aponta para o mutex dentro de `vmas`, que o VmaGuard
+    // mantém vivo e é derrubado depois do guard (ordem dos campos)
+    let guard = unsafe {
+        core::mem::transmute::<IrqSafeGuard<'_, VmaManager>, IrqSafeGuard<'static, VmaManager>>(vmas.lock())
+    };
+    VmaGuard { guard, _vmas: vmas }
+}
+
+/// Syscall mmap. `file` é o arquivo já resolvido a partir do fd (None para
+/// mapeamentos anônimos).
+pub fn sys_mmap(addr: u64, length: usize, prot: i32, flags: i32, file: Option<FileMapping>) -> KResult<u64> {
     let prot = Protection::from_prot(prot);
     let map_flags = MapFlags::from_flags(flags);
 
-    // Por enquanto só suportamos mapeamentos anônimos
-    if !map_flags.anonymous {
-        // TODO: suportar mapeamento de arquivos
-        return Err(KError::NotSupported);
+    let mut manager = manager_lock();
+    match file {
+        Some(file) if !map_flags.anonymous => manager.mmap_file(addr, length, prot, map_flags, file),
+        _ if map_flags.anonymous => manager.mmap(addr, length, prot, map_flags),
+        _ => Err(KError::Invalid),
     }
-
-    let mut guard = VMA_MANAGER.lock();
-    let manager = guard.as_mut().ok_or(KError::NotSupported)?;
-    manager.mmap(addr, length, prot, map_flags)
 }
 
 /// Syscall munmap
 pub fn sys_munmap(addr: u64, length: usize) -> KResult<()> {
-    let mut guard = VMA_MANAGER.lock();
-    let manager = guard.as_mut().ok_or(KError::NotSupported)?;
-    manager.munmap(addr, length)
+    manager_lock().munmap(addr, length)
 }
 
 /// Syscall mprotect
 pub fn sys_mprotect(addr: u64, length: usize, prot: i32) -> KResult<()> {
     let prot = Protection::from_prot(prot);
-    let mut guard = VMA_MANAGER.lock();
-    let manager = guard.as_mut().ok_or(KError::NotSupported)?;
-    manager.mprotect(addr, length, prot)
+    manager_lock().mprotect(addr, length, prot)
+}
+
+/// Syscall msync
+pub fn sys_msync(addr: u64, length: usize, flags: i32) -> KResult<()> {
+    manager_lock().msync(addr, length, flags)
 }
 
 /// Handle page fault for demand paging (called from interrupt handler)
 pub fn handle_page_fault(fault_addr: u64) -> KResult<()> {
-    let mut guard = VMA_MANAGER.lock();
-    let manager = guard.as_mut().ok_or(KError::Invalid)?;
-    manager.handle_page_fault(fault_addr)
+    manager_lock().handle_page_fault(fault_addr)
 }
 
 /// Handle CoW page fault (write to a page that was CoW-shared)
 /// Called when a write fault occurs on a present page that's marked as CoW
 pub fn handle_cow_page_fault(fault_addr: u64, old_phys: x86_64::PhysAddr) -> KResult<()> {
-    let mut guard = VMA_MANAGER.lock();
-    let manager = guard.as_mut().ok_or(KError::Invalid)?;
-
-    let page_addr = fault_addr & !0xFFF;
-
-    // Encontra a VMA que contém este endereço
-    let vma = manager.vmas.values_mut().find(|vma| vma.contains(fault_addr));
-    let vma = vma.ok_or(KError::Invalid)?;
-
-    // Verifica se a VMA permite escrita
-    if !vma.prot.write {
-        return Err(KError::PermissionDenied);
-    }
-
-    // Calcula qual página dentro da VMA
-    let page_index = ((page_addr - vma.start) / 4096) as usize;
-
-    // Verifica se esta página está marcada como CoW
-    if page_index >= vma.cow_pages.len() || !vma.cow_pages[page_index] {
-        return Err(KError::Invalid);
-    }
-
-    // Aloca novo frame
-    let mut fa = super::frame_allocator_lock();
-    let new_frame = fa.allocate().ok_or(KError::NoMemory)?;
-    drop(fa);
-
-    // Copia o conteúdo do frame antigo para o novo
-    let old_virt = super::phys_to_virt(old_phys);
-    let new_virt = super::phys_to_virt(new_frame.start_address());
-    unsafe {
-        core::ptr::copy_nonoverlapping(
-            old_virt.as_ptr::<u8>(),
-            new_virt.as_mut_ptr::<u8>(),
-            4096,
-        );
-    }
-
-    // Atualiza o mapeamento para apontar para o novo frame com permissão de escrita
-    let page: Page<Size4KiB> = Page::containing_address(VirtAddr::new(page_addr));
-    let page_flags = vma.prot.to_page_flags(true);
-
-    {
-        let mut mapper = super::mapper_lock();
-        let mut fa = super::frame_allocator_lock();
-
-        // Desmapeia a página antiga
-        if let Ok((_old_frame, flush)) = mapper.unmap_page(page) {
-            flush();
-        }
-
-        // Mapeia o novo frame
-        mapper.map_page(page, new_frame, page_flags, &mut *fa)?;
-    }
-
-    // Decrementa referência do frame antigo no CoW manager
-    super::cow::decrement_ref(old_phys);
-
-    // Atualiza a VMA
-    if page_index < vma.frames.len() {
-        vma.frames[page_index] = Some(new_frame);
-    }
-    vma.cow_pages[page_index] = false;
-
-    Ok(())
+    manager_lock().handle_write_fault(fault_addr, old_phys)
 }
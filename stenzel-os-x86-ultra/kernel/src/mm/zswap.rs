@@ -10,6 +10,7 @@ use alloc::collections::BTreeMap;
 use alloc::vec;
 use alloc::vec::Vec;
 use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use crate::compress;
 use crate::sync::IrqSafeMutex;
 
 /// Physical page frame number
@@ -49,6 +50,31 @@ impl CompressionAlgo {
     }
 }
 
+/// Compress one page with `algo`
+///
+/// LZ4HC shares the LZ4 encoder, and LZO pages are stored as LZ4 blocks;
+/// the algorithm only has to match between store and load.
+fn compress_page(algo: CompressionAlgo, data: &[u8]) -> Vec<u8> {
+    match algo {
+        CompressionAlgo::Lz4 | CompressionAlgo::Lz4Hc | CompressionAlgo::Lzo => compress::lz4::compress_block(data),
+        CompressionAlgo::Zstd => compress::zstd::compress(data, algo.default_level()),
+        CompressionAlgo::None => data.to_vec(),
+    }
+}
+
+/// Decompress a page stored by `compress_page`; `None` unless it expands
+/// to exactly `size` bytes
+fn decompress_page(algo: CompressionAlgo, data: &[u8], size: usize) -> Option<Vec<u8>> {
+    let page = match algo {
+        CompressionAlgo::Lz4 | CompressionAlgo::Lz4Hc | CompressionAlgo::Lzo => {
+            compress::lz4::decompress_block(data, size).ok()?
+        }
+        CompressionAlgo::Zstd => compress::zstd::decompress(data, size).ok()?,
+        CompressionAlgo::None => data.to_vec(),
+    };
+    (page.len() == size).then_some(page)
+}
+
 /// Compressed page entry
 #[derive(Debug, Clone)]
 pub struct CompressedPage {
@@ -295,16 +321,16 @@ impl ZswapPool {
                 }
 
                 // Clone data for decompression (releases the borrow)
-                Some((entry.data.clone(), entry.original_size as usize))
+                Some((entry.algo, entry.data.clone(), entry.original_size as usize))
             }
             None => None,
         };
 
         // Now process outside the borrow
         match page_data {
-            Some((data, original_size)) => {
+            Some((algo, data, original_size)) => {
                 self.stats.hits += 1;
-                self.decompress(&data, original_size)
+                self.decompress(algo, &data, original_size)
             }
             None => {
                 self.stats.misses += 1;
@@ -355,113 +381,12 @@ impl ZswapPool {
 
     /// Compress data using configured algorithm
     fn compress(&mut self, data: &[u8]) -> Result<Vec<u8>, ZswapError> {
-        match self.config.compressor {
-            CompressionAlgo::Lz4 | CompressionAlgo::Lz4Hc => {
-                self.compress_lz4(data)
-            }
-            CompressionAlgo::Zstd => {
-                self.compress_zstd(data)
-            }
-            CompressionAlgo::Lzo => {
-                self.compress_lzo(data)
-            }
-            CompressionAlgo::None => {
-                Ok(data.to_vec())
-            }
-        }
-    }
-
-    /// Decompress data
-    fn decompress(&self, data: &[u8], original_size: usize) -> Result<Vec<u8>, ZswapError> {
-        // For now, use simple RLE-like decompression as placeholder
-        // In real implementation, would use proper LZ4/ZSTD libs
-
-        // Simple placeholder: just return the data if it matches original size
-        if data.len() == original_size {
-            return Ok(data.to_vec());
-        }
-
-        // Try to decompress
-        let mut output = Vec::with_capacity(original_size);
-
-        // Simple RLE decode for testing
-        let mut i = 0;
-        while i < data.len() && output.len() < original_size {
-            if i + 1 < data.len() && data[i] == 0xFF {
-                // Escape sequence
-                let count = data[i + 1] as usize;
-                if i + 2 < data.len() {
-                    let byte = data[i + 2];
-                    for _ in 0..count {
-                        output.push(byte);
-                    }
-                    i += 3;
-                } else {
-                    break;
-                }
-            } else {
-                output.push(data[i]);
-                i += 1;
-            }
-        }
-
-        // Pad to original size if needed
-        while output.len() < original_size {
-            output.push(0);
-        }
-
-        Ok(output)
-    }
-
-    /// LZ4 compression (placeholder)
-    fn compress_lz4(&mut self, data: &[u8]) -> Result<Vec<u8>, ZswapError> {
-        // Simple RLE-like compression as placeholder
-        let mut output = Vec::new();
-        let mut i = 0;
-
-        while i < data.len() {
-            let byte = data[i];
-            let mut count = 1usize;
-
-            // Count repetitions
-            while i + count < data.len() && data[i + count] == byte && count < 255 {
-                count += 1;
-            }
-
-            if count >= 4 {
-                // RLE encode: 0xFF, count, byte
-                output.push(0xFF);
-                output.push(count as u8);
-                output.push(byte);
-            } else {
-                // Literal
-                for _ in 0..count {
-                    if byte == 0xFF {
-                        output.push(0xFF);
-                        output.push(1);
-                        output.push(0xFF);
-                    } else {
-                        output.push(byte);
-                    }
-                }
-            }
-
-            i += count;
-        }
-
-        Ok(output)
-    }
-
-    /// ZSTD compression (placeholder)
-    fn compress_zstd(&mut self, data: &[u8]) -> Result<Vec<u8>, ZswapError> {
-        // Use LZ4 as placeholder
-        self.compress_lz4(data)
+        Ok(compress_page(self.config.compressor, data))
     }
 
-    /// LZO compression (placeholder)
-    fn compress_lzo(&mut self, data: &[u8]) -> Result<Vec<u8>, ZswapError> {
-        // Use LZ4 as placeholder
-        self.compress_lz4(data)
+    /// Decompress data stored with `algo`
+    fn decompress(&self, algo: CompressionAlgo, data: &[u8], original_size: usize) -> Result<Vec<u8>, ZswapError> {
+        decompress_page(algo, data, original_size).ok_or(ZswapError::DecompressionFailed)
     }
 
     /// Writeback least recently used pages to swap
@@ -692,68 +617,13 @@ impl ZramDevice {
 
     /// Compress data
     fn compress(&self, data: &[u8]) -> Result<Vec<u8>, ZramError> {
-        // Simple RLE compression
-        let mut output = Vec::new();
-        let mut i = 0;
-
-        while i < data.len() {
-            let byte = data[i];
-            let mut count = 1usize;
-
-            while i + count < data.len() && data[i + count] == byte && count < 255 {
-                count += 1;
-            }
-
-            if count >= 4 {
-                output.push(0xFF);
-                output.push(count as u8);
-                output.push(byte);
-            } else {
-                for _ in 0..count {
-                    if byte == 0xFF {
-                        output.push(0xFF);
-                        output.push(1);
-                        output.push(0xFF);
-                    } else {
-                        output.push(byte);
-                    }
-                }
-            }
-
-            i += count;
-        }
-
-        Ok(output)
+        Ok(compress_page(self.config.compressor, data))
     }
 
     /// Decompress data
     fn decompress(&self, data: &[u8]) -> Result<Vec<u8>, ZramError> {
-        let mut output = Vec::with_capacity(self.sector_size as usize);
-        let mut i = 0;
-
-        while i < data.len() && output.len() < self.sector_size as usize {
-            if i + 1 < data.len() && data[i] == 0xFF {
-                let count = data[i + 1] as usize;
-                if i + 2 < data.len() {
-                    let byte = data[i + 2];
-                    for _ in 0..count {
-                        output.push(byte);
-                    }
-                    i += 3;
-                } else {
-                    break;
-                }
-            } else {
-                output.push(data[i]);
-                i += 1;
-            }
-        }
-
-        while output.len() < self.sector_size as usize {
-            output.push(0);
-        }
-
-        Ok(output)
+        decompress_page(self.config.compressor, data, self.sector_size as usize)
+            .ok_or(ZramError::DecompressionFailed)
     }
 }
 
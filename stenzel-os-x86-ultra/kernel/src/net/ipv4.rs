@@ -131,9 +131,11 @@ pub fn handle_packet(data: &[u8], _eth_frame: &ethernet::EthernetFrame) {
         }
         PROTO_UDP => {
             udp::handle_packet(payload, &ip);
+            crate::syscall::epoll::wake_sockets();
         }
         PROTO_TCP => {
             tcp::handle_packet(payload, &ip);
+            crate::syscall::epoll::wake_sockets();
         }
         _ => {
             // Protocolo não suportado
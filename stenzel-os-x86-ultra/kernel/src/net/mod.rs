@@ -39,6 +39,9 @@ pub mod profiles;
 use alloc::vec::Vec;
 use crate::sync::IrqSafeMutex;
 
+/// Índice da única interface de rede (eth0), como em `ip link`
+pub const ETH0_IFINDEX: u32 = 1;
+
 /// Configuração de rede
 #[derive(Debug, Clone, Copy)]
 pub struct NetConfig {
@@ -161,6 +164,7 @@ pub fn init() {
         dns::init();
         http::init();
         tls::init();
+        ntp::init();
 
         crate::kprintln!("net: stack inicializado");
     } else {
@@ -174,8 +178,22 @@ pub fn config() -> Option<NetConfig> {
 }
 
 /// Processa pacotes recebidos
+///
+/// Cada quadro passa antes pelo programa XDP da interface, se houver um.
 pub fn poll() {
-    while let Some(packet) = crate::drivers::net::recv() {
+    use crate::profiling::{ebpf, ftrace};
+
+    while let Some(mut packet) = crate::drivers::net::recv() {
+        match ebpf::run_xdp(ETH0_IFINDEX, &mut packet) {
+            ebpf::XdpAction::Pass => {}
+            ebpf::XdpAction::Tx => {
+                let _ = crate::drivers::net::send(&packet);
+                continue;
+            }
+            _ => continue,
+        }
+
+        ftrace::trace_event("net", "netif_receive_skb", (packet.len() as u32).to_le_bytes().to_vec());
         if let Some(eth_frame) = ethernet::EthernetFrame::parse(&packet) {
             ethernet::handle_frame(eth_frame, &packet);
         }
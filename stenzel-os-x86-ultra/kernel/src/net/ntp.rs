@@ -7,20 +7,30 @@
 //! - NTP timestamp: 64-bit (32 bits seconds + 32 bits fraction since 1900-01-01)
 //! - Unix timestamp: seconds since 1970-01-01
 //!
+//! ## Clock discipline
+//! A background thread (`start`) polls the configured servers. Each server
+//! keeps its last eight samples; the one with the least round-trip delay is
+//! its estimate (RFC 5905 clock filter). The estimates go through the
+//! intersection and cluster algorithms to drop falsetickers, and the
+//! survivors are averaged by root distance. Offsets under 128 ms are handed
+//! to the kernel PLL (`time::ntp::adjtimex`) and slewed out; larger ones
+//! step the clock. The poll interval grows from 64 s to 1024 s while the
+//! offsets stay within the jitter.
+//!
 //! ## Usage
 //! ```ignore
-//! // Synchronize with a single server
-//! ntp::sync_time("pool.ntp.org")?;
+//! // One round against the configured servers
+//! let result = ntp::sync()?;
 //!
-//! // Get current offset from server
-//! let offset = ntp::get_offset("time.google.com")?;
+//! // Query a server without touching the clock
+//! let result = ntp::query_hostname("time.google.com", ntp::DEFAULT_TIMEOUT_MS)?;
 //! ```
 
 #![allow(dead_code)]
 
 use alloc::string::String;
 use alloc::vec::Vec;
-use core::sync::atomic::{AtomicU64, AtomicI64, AtomicU32, Ordering};
+use core::sync::atomic::{AtomicBool, AtomicU64, AtomicI64, AtomicU32, Ordering};
 
 use super::Ipv4Addr;
 use super::udp;
@@ -38,6 +48,25 @@ pub const DEFAULT_TIMEOUT_MS: u32 = 5000;
 /// Maximum allowed clock offset before warning (in seconds)
 pub const MAX_OFFSET_WARN: i64 = 3600; // 1 hour
 
+/// Offsets above this step the clock instead of slewing it (128 ms)
+pub const STEP_THRESHOLD_NS: i64 = 128_000_000;
+
+/// Clock filter stages per server
+const NSTAGE: usize = 8;
+/// Dispersion growth while a sample ages, 15 ppm
+const PHI_PPB: i64 = 15_000;
+/// Floor of the round trip in the root distance (10 ms)
+const MINDISP_NS: i64 = 10_000_000;
+/// Servers farther than this (root distance) are not selected (1.5 s)
+const MAXDIST_NS: i64 = 1_500_000_000;
+/// The cluster algorithm stops pruning at this many survivors
+const MINCLOCK: usize = 3;
+/// Poll interval bounds, log2 seconds
+const MINPOLL: u8 = 6;
+const MAXPOLL: u8 = 10;
+/// Rounds 2 s apart at startup, to fill the clock filters quickly
+const BURST: u32 = 4;
+
 /// NTP Leap Indicator values
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(u8)]
@@ -115,7 +144,7 @@ impl Stratum {
 }
 
 /// NTP timestamp (64 bits: 32 seconds + 32 fraction)
-#[derive(Debug, Clone, Copy, Default)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
 pub struct NtpTimestamp {
     pub seconds: u32,
     pub fraction: u32,
@@ -176,6 +205,19 @@ impl NtpTimestamp {
         let ts = crate::time::realtime();
         Self::from_unix_secs_ns(ts.tv_sec as u64, ts.tv_nsec as u32)
     }
+
+    /// `self - earlier` in nanoseconds; correct across an era boundary
+    /// as long as the two are within 68 years
+    pub fn diff_ns(&self, earlier: &NtpTimestamp) -> i64 {
+        let a = ((self.seconds as u64) << 32) | self.fraction as u64;
+        let b = ((earlier.seconds as u64) << 32) | earlier.fraction as u64;
+        ((a.wrapping_sub(b) as i64 as i128 * 1_000_000_000) >> 32) as i64
+    }
+}
+
+/// NTP short format (16.16 seconds) to nanoseconds
+fn short_to_ns(short: u32) -> i64 {
+    ((short as u64 * 1_000_000_000) >> 16) as i64
 }
 
 /// NTP packet structure (48 bytes minimum)
@@ -302,10 +344,15 @@ impl NtpPacket {
 pub struct NtpResult {
     /// Server IP address
     pub server: Ipv4Addr,
-    /// Clock offset (local - server) in milliseconds
-    pub offset_ms: i64,
-    /// Round-trip delay in milliseconds
-    pub delay_ms: u64,
+    /// Clock offset (server - local) in nanoseconds
+    pub offset_ns: i64,
+    /// Round-trip delay in nanoseconds
+    pub delay_ns: i64,
+    /// Error of this measurement in nanoseconds
+    pub dispersion_ns: i64,
+    /// Delay and dispersion from the server to its reference clock
+    pub root_delay_ns: i64,
+    pub root_dispersion_ns: i64,
     /// Server stratum
     pub stratum: Stratum,
     /// Reference ID string
@@ -314,14 +361,283 @@ pub struct NtpResult {
     pub leap: LeapIndicator,
 }
 
+/// One measurement kept by a clock filter
+#[derive(Debug, Clone, Copy)]
+pub struct Sample {
+    pub offset: i64,
+    pub delay: i64,
+    pub dispersion: i64,
+    /// Monotonic time of the measurement, ns
+    pub time: u64,
+}
+
+/// What a clock filter makes of its samples
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Estimate {
+    pub offset: i64,
+    pub delay: i64,
+    pub dispersion: i64,
+    /// RMS difference of the other samples from the chosen one
+    pub jitter: i64,
+    /// Monotonic time of the chosen sample, ns
+    pub time: u64,
+}
+
+/// The last `NSTAGE` measurements of a server (RFC 5905 clock filter).
+/// The sample with the least delay is the one least disturbed by
+/// queueing in the network, so it wins.
+#[derive(Debug, Clone, Default)]
+pub struct ClockFilter {
+    /// Newest first
+    samples: Vec<Sample>,
+}
+
+impl ClockFilter {
+    pub const fn new() -> Self {
+        Self { samples: Vec::new() }
+    }
+
+    pub fn push(&mut self, sample: Sample) {
+        self.samples.insert(0, sample);
+        self.samples.truncate(NSTAGE);
+    }
+
+    pub fn clear(&mut self) {
+        self.samples.clear();
+    }
+
+    pub fn len(&self) -> usize {
+        self.samples.len()
+    }
+
+    /// Estimate at monotonic time `now`; dispersion grows while samples age
+    pub fn estimate(&self, now: u64) -> Option<Estimate> {
+        let mut aged: Vec<Sample> = self
+            .samples
+            .iter()
+            .map(|s| Sample {
+                dispersion: s.dispersion + age_dispersion(now.saturating_sub(s.time)),
+                ..*s
+            })
+            .collect();
+        // Stable, so the newer of two equal delays comes first
+        aged.sort_by_key(|s| s.delay);
+        let best = *aged.first()?;
+
+        let mut dispersion = 0i64;
+        for (i, s) in aged.iter().enumerate() {
+            dispersion += s.dispersion >> (i + 1);
+        }
+        let jitter = if aged.len() > 1 {
+            let sum: u128 = aged[1..]
+                .iter()
+                .map(|s| {
+                    let d = (s.offset - best.offset).unsigned_abs() as u128;
+                    d * d
+                })
+                .sum();
+            (sum / (aged.len() - 1) as u128).isqrt() as i64
+        } else {
+            0
+        };
+
+        Some(Estimate { offset: best.offset, delay: best.delay, dispersion, jitter, time: best.time })
+    }
+}
+
+/// Dispersion added by `age_ns` of frequency tolerance
+fn age_dispersion(age_ns: u64) -> i64 {
+    (age_ns as i128 * PHI_PPB as i128 / 1_000_000_000) as i64
+}
+
+/// A server as seen by clock selection
+#[derive(Debug, Clone, Copy)]
+pub struct Candidate {
+    pub offset: i64,
+    /// Maximum error of `offset`: half the round trip to the reference
+    /// clock plus all dispersions and the jitter
+    pub root_distance: i64,
+    pub jitter: i64,
+    /// Monotonic time of the sample behind `offset`, ns
+    pub time: u64,
+}
+
+/// Root distance (lambda) of a server from its filter estimate
+pub fn root_distance(est: &Estimate, root_delay: i64, root_dispersion: i64) -> i64 {
+    (root_delay + est.delay).max(MINDISP_NS) / 2 + root_dispersion + est.dispersion + est.jitter
+}
+
+/// RFC 5905 clock selection: the intersection algorithm (Marzullo) finds
+/// the interval the majority of candidates agree on, the cluster algorithm
+/// then prunes the outliers among those. Returns the surviving indices,
+/// best first; the first one is the system peer.
+pub fn select(candidates: &[Candidate]) -> Vec<usize> {
+    let n = candidates.len();
+    if n == 0 {
+        return Vec::new();
+    }
+
+    // Edges: -1 lower end, 0 midpoint, +1 upper end of each interval
+    let mut edges: Vec<(i64, i32)> = Vec::with_capacity(3 * n);
+    for c in candidates {
+        edges.push((c.offset - c.root_distance, -1));
+        edges.push((c.offset, 0));
+        edges.push((c.offset + c.root_distance, 1));
+    }
+    edges.sort();
+
+    // Allow `allow` falsetickers until the majority agrees on an interval
+    let mut interval = None;
+    let mut allow = 0;
+    while 2 * allow < n {
+        let need = (n - allow) as i32;
+        let mut found = 0;
+        let mut chime = 0;
+        let mut low = i64::MAX;
+        for &(value, kind) in &edges {
+            chime -= kind;
+            if chime >= need {
+                low = value;
+                break;
+            }
+            if kind == 0 {
+                found += 1;
+            }
+        }
+        chime = 0;
+        let mut high = i64::MIN;
+        for &(value, kind) in edges.iter().rev() {
+            chime += kind;
+            if chime >= need {
+                high = value;
+                break;
+            }
+            if kind == 0 {
+                found += 1;
+            }
+        }
+        if found <= allow && low < high {
+            interval = Some((low, high));
+            break;
+        }
+        allow += 1;
+    }
+    let Some((low, high)) = interval else {
+        return Vec::new();
+    };
+
+    let mut survivors: Vec<usize> = (0..n)
+        .filter(|&i| {
+            let c = &candidates[i];
+            c.offset + c.root_distance >= low && c.offset - c.root_distance <= high
+        })
+        .collect();
+    survivors.sort_by_key(|&i| candidates[i].root_distance);
+
+    // Cluster: drop the survivor farthest from the others until that
+    // spread is below the jitter of the best one, or too few remain
+    while survivors.len() > MINCLOCK {
+        let mut worst = 0;
+        let mut max_spread = 0u128;
+        for (k, &i) in survivors.iter().enumerate() {
+            let sum: u128 = survivors
+                .iter()
+                .map(|&j| {
+                    let d = (candidates[i].offset - candidates[j].offset).unsigned_abs() as u128;
+                    d * d
+                })
+                .sum();
+            let spread = (sum / (survivors.len() - 1) as u128).isqrt();
+            if spread > max_spread {
+                max_spread = spread;
+                worst = k;
+            }
+        }
+        let min_jitter = survivors.iter().map(|&i| candidates[i].jitter).min().unwrap_or(0);
+        if max_spread <= min_jitter.max(0) as u128 {
+            break;
+        }
+        survivors.remove(worst);
+    }
+    survivors
+}
+
+/// Combine the survivors into one offset, each weighted by the inverse of
+/// its root distance. Returns the offset and the jitter of the system peer.
+pub fn combine(candidates: &[Candidate], survivors: &[usize]) -> Option<(i64, i64)> {
+    let first = *survivors.first()?;
+    let mut sum = 0i128;
+    let mut weights = 0i128;
+    for &i in survivors {
+        let weight = (1i128 << 62) / candidates[i].root_distance.max(1) as i128;
+        sum += candidates[i].offset as i128 * weight;
+        weights += weight;
+    }
+    Some(((sum / weights) as i64, candidates[first].jitter))
+}
+
+/// A configured server
+#[derive(Debug, Clone)]
+struct Peer {
+    addr: Ipv4Addr,
+    filter: ClockFilter,
+    stratum: Stratum,
+    leap: LeapIndicator,
+    root_delay: i64,
+    root_dispersion: i64,
+    /// Shift register of the last eight polls, 1 = answered
+    reach: u8,
+    /// Time of the newest sample the clock was disciplined with, ns
+    last_used: u64,
+}
+
+impl Peer {
+    fn new(addr: Ipv4Addr) -> Self {
+        Self {
+            addr,
+            filter: ClockFilter::new(),
+            stratum: Stratum::UNSYNCHRONIZED,
+            leap: LeapIndicator::AlarmCondition,
+            root_delay: 0,
+            root_dispersion: 0,
+            reach: 0,
+            last_used: 0,
+        }
+    }
+
+    fn candidate(&self, now: u64) -> Option<Candidate> {
+        if !self.stratum.is_synchronized() || self.leap == LeapIndicator::AlarmCondition {
+            return None;
+        }
+        let est = self.filter.estimate(now)?;
+        let root_distance = root_distance(&est, self.root_delay, self.root_dispersion);
+        if root_distance >= MAXDIST_NS {
+            return None;
+        }
+        Some(Candidate { offset: est.offset, root_distance, jitter: est.jitter, time: est.time })
+    }
+}
+
 /// NTP client state
 pub struct NtpClient {
     /// Configured NTP servers
-    servers: Vec<Ipv4Addr>,
+    peers: Vec<Peer>,
+    /// Server the clock follows
+    system_peer: Option<Ipv4Addr>,
+    /// Stratum of the system peer
+    stratum: u8,
+    /// Poll interval, log2 seconds
+    poll: u8,
+    /// Votes for a longer (positive) or shorter poll interval
+    poll_votes: i32,
+    /// Rounds left at the startup rate
+    burst: u32,
+    /// Jitter of the system peer in nanoseconds
+    jitter_ns: i64,
     /// Last synchronization timestamp
     last_sync: AtomicU64,
-    /// Last calculated offset in milliseconds
-    last_offset_ms: AtomicI64,
+    /// Last calculated offset in nanoseconds
+    last_offset_ns: AtomicI64,
     /// Number of successful syncs
     sync_count: AtomicU32,
     /// Number of failed syncs
@@ -331,18 +647,51 @@ pub struct NtpClient {
 impl NtpClient {
     pub const fn new() -> Self {
         Self {
-            servers: Vec::new(),
+            peers: Vec::new(),
+            system_peer: None,
+            stratum: Stratum::UNSYNCHRONIZED.0,
+            poll: MINPOLL,
+            poll_votes: 0,
+            burst: BURST,
+            jitter_ns: 0,
             last_sync: AtomicU64::new(0),
-            last_offset_ms: AtomicI64::new(0),
+            last_offset_ns: AtomicI64::new(0),
             sync_count: AtomicU32::new(0),
             fail_count: AtomicU32::new(0),
         }
     }
+
+    /// Start over after the clock was stepped
+    fn reset(&mut self) {
+        for peer in &mut self.peers {
+            peer.filter.clear();
+            peer.last_used = 0;
+        }
+        self.poll = MINPOLL;
+        self.poll_votes = 0;
+        self.burst = BURST;
+    }
+
+    /// Seconds until the next round
+    fn interval_secs(&self) -> u64 {
+        if self.burst > 0 { 2 } else { 1 << self.poll }
+    }
 }
 
 /// Global NTP client
 static NTP_CLIENT: IrqSafeMutex<Option<NtpClient>> = IrqSafeMutex::new(None);
 
+/// Whether the background thread disciplines the clock
+static ENABLED: AtomicBool = AtomicBool::new(true);
+static STARTED: AtomicBool = AtomicBool::new(false);
+
+/// A round was asked for with `request_sync` and has not finished yet
+static SYNC_REQUESTED: AtomicBool = AtomicBool::new(false);
+/// Whether the last round failed
+static LAST_FAILED: AtomicBool = AtomicBool::new(false);
+/// Thread id of ntpd, 0 while it is not running
+static NTPD_TID: AtomicU64 = AtomicU64::new(0);
+
 /// Default NTP servers
 static DEFAULT_SERVERS: &[Ipv4Addr] = &[
     Ipv4Addr::new(216, 239, 35, 0),   // time.google.com
@@ -354,7 +703,7 @@ static DEFAULT_SERVERS: &[Ipv4Addr] = &[
 /// Initialize the NTP client
 pub fn init() {
     let mut client = NtpClient::new();
-    client.servers = DEFAULT_SERVERS.to_vec();
+    client.peers = DEFAULT_SERVERS.iter().map(|&ip| Peer::new(ip)).collect();
 
     *NTP_CLIENT.lock() = Some(client);
     crate::kprintln!("ntp: client initialized with {} servers", DEFAULT_SERVERS.len());
@@ -364,8 +713,8 @@ pub fn init() {
 pub fn add_server(ip: Ipv4Addr) {
     let mut guard = NTP_CLIENT.lock();
     if let Some(client) = guard.as_mut() {
-        if !client.servers.contains(&ip) {
-            client.servers.push(ip);
+        if !client.peers.iter().any(|p| p.addr == ip) {
+            client.peers.push(Peer::new(ip));
         }
     }
 }
@@ -374,7 +723,10 @@ pub fn add_server(ip: Ipv4Addr) {
 pub fn remove_server(ip: Ipv4Addr) {
     let mut guard = NTP_CLIENT.lock();
     if let Some(client) = guard.as_mut() {
-        client.servers.retain(|&s| s != ip);
+        client.peers.retain(|p| p.addr != ip);
+        if client.system_peer == Some(ip) {
+            client.system_peer = None;
+        }
     }
 }
 
@@ -412,8 +764,13 @@ pub fn query_server(server_ip: Ipv4Addr, timeout_ms: u32) -> crate::util::KResul
     let reply = NtpPacket::parse(&response.data)
         .ok_or(crate::util::KError::Invalid)?;
 
-    // Verify response
-    if reply.mode() != NtpMode::Server {
+    // Verify response: a server reply to this very request (origin echoes
+    // T1), from a server that has a time to give (stratum 0 is a kiss code)
+    if reply.mode() != NtpMode::Server
+        || reply.origin_ts != t1
+        || reply.stratum == Stratum::UNSPECIFIED
+        || reply.transmit_ts == NtpTimestamp::ZERO
+    {
         return Err(crate::util::KError::Invalid);
     }
 
@@ -422,40 +779,151 @@ pub fn query_server(server_ip: Ipv4Addr, timeout_ms: u32) -> crate::util::KResul
     // T2 = receive timestamp (server received our request)
     // T3 = transmit timestamp (server sent response)
     // T4 = our receive time
-
     let t2 = reply.receive_ts;
     let t3 = reply.transmit_ts;
 
-    // Calculate offset and delay using NTP algorithm
     // offset = ((T2 - T1) + (T3 - T4)) / 2
     // delay = (T4 - T1) - (T3 - T2)
-
-    // Convert to milliseconds for calculation
-    let t1_ms = t1.to_unix_secs() * 1000 + (t1.fraction as i64 * 1000 / 4294967296);
-    let t2_ms = t2.to_unix_secs() * 1000 + (t2.fraction as i64 * 1000 / 4294967296);
-    let t3_ms = t3.to_unix_secs() * 1000 + (t3.fraction as i64 * 1000 / 4294967296);
-    let t4_ms = t4.to_unix_secs() * 1000 + (t4.fraction as i64 * 1000 / 4294967296);
-
-    let offset_ms = ((t2_ms - t1_ms) + (t3_ms - t4_ms)) / 2;
-    let delay_ms = ((t4_ms - t1_ms) - (t3_ms - t2_ms)).unsigned_abs();
+    let offset_ns = (t2.diff_ns(&t1) + t3.diff_ns(&t4)) / 2;
+    let delay_ns = (t4.diff_ns(&t1) - t3.diff_ns(&t2)).max(0);
+
+    // Measurement error: the precision of the server clock plus the
+    // frequency tolerance over the round trip
+    let precision_ns = if reply.precision >= 0 {
+        1_000_000_000
+    } else {
+        1_000_000_000i64 >> (-(reply.precision as i32)).min(63)
+    };
+    let dispersion_ns = precision_ns + age_dispersion(delay_ns as u64);
 
     Ok(NtpResult {
         server: server_ip,
-        offset_ms,
-        delay_ms,
+        offset_ns,
+        delay_ns,
+        dispersion_ns,
+        root_delay_ns: short_to_ns(reply.root_delay),
+        root_dispersion_ns: short_to_ns(reply.root_dispersion),
         stratum: reply.stratum,
         reference_id: reply.reference_id_string(),
         leap: reply.leap_indicator(),
     })
 }
 
-/// Synchronize time with configured servers
-/// Returns the best result (lowest delay)
+/// Feed a measurement into the filter of its server
+fn record(client: &mut NtpClient, result: &NtpResult, now: u64) {
+    if let Some(peer) = client.peers.iter_mut().find(|p| p.addr == result.server) {
+        peer.filter.push(Sample {
+            offset: result.offset_ns,
+            delay: result.delay_ns,
+            dispersion: result.dispersion_ns,
+            time: now,
+        });
+        peer.stratum = result.stratum;
+        peer.leap = result.leap;
+        peer.root_delay = result.root_delay_ns;
+        peer.root_dispersion = result.root_dispersion_ns;
+        peer.reach = (peer.reach << 1) | 1;
+    }
+}
+
+/// Outcome of clock selection
+struct Selection {
+    peer: Ipv4Addr,
+    offset: i64,
+    jitter: i64,
+    root_distance: i64,
+    stratum: u8,
+    leap: LeapIndicator,
+    /// Time of the system peer's sample, ns
+    time: u64,
+}
+
+fn clock_select(client: &NtpClient, now: u64) -> Option<Selection> {
+    let mut peers = Vec::new();
+    let mut candidates = Vec::new();
+    for peer in &client.peers {
+        if let Some(candidate) = peer.candidate(now) {
+            peers.push(peer);
+            candidates.push(candidate);
+        }
+    }
+    let survivors = select(&candidates);
+    let (offset, jitter) = combine(&candidates, &survivors)?;
+    let best = survivors[0];
+    Some(Selection {
+        peer: peers[best].addr,
+        offset,
+        jitter,
+        root_distance: candidates[best].root_distance,
+        stratum: peers[best].stratum.0,
+        leap: peers[best].leap,
+        time: candidates[best].time,
+    })
+}
+
+/// Correct the clock by `sel.offset`: step it when far off, otherwise let
+/// the kernel PLL slew it out
+fn discipline(client: &mut NtpClient, sel: &Selection) -> crate::util::KResult<()> {
+    if sel.offset.abs() > STEP_THRESHOLD_NS {
+        if sel.offset.abs() / 1_000_000_000 > MAX_OFFSET_WARN {
+            crate::kprintln!(
+                "ntp: WARNING: large clock offset detected: {} seconds",
+                sel.offset / 1_000_000_000
+            );
+        }
+        crate::time::step(sel.offset).map_err(|_| crate::util::KError::Invalid)?;
+        // The phase being slewed is void; the learnt frequency stays
+        crate::time::ntp::clear();
+        crate::kprintln!("ntp: clock stepped by {} ms", sel.offset / 1_000_000);
+        client.reset();
+        return Ok(());
+    }
+
+    let mut status = crate::time::ntp::STA_PLL;
+    match sel.leap {
+        LeapIndicator::LastMinute61Seconds => status |= crate::time::ntp::STA_INS,
+        LeapIndicator::LastMinute59Seconds => status |= crate::time::ntp::STA_DEL,
+        _ => {}
+    }
+    let mut txc = crate::time::ntp::Timex {
+        modes: crate::time::ntp::ADJ_OFFSET
+            | crate::time::ntp::ADJ_STATUS
+            | crate::time::ntp::ADJ_NANO
+            | crate::time::ntp::ADJ_MAXERROR
+            | crate::time::ntp::ADJ_ESTERROR
+            | crate::time::ntp::ADJ_TIMECONST,
+        offset: sel.offset,
+        status,
+        maxerror: sel.root_distance / 1_000,
+        esterror: sel.jitter / 1_000,
+        constant: client.poll as i64 - 4,
+        ..Default::default()
+    };
+    crate::time::ntp::adjtimex(&mut txc, true).map_err(|_| crate::util::KError::Invalid)?;
+
+    // Poll less often while the offsets stay within the jitter
+    if sel.offset.abs() < 4 * sel.jitter.max(MINDISP_NS / 10) {
+        client.poll_votes += 1;
+    } else {
+        client.poll_votes -= 2;
+    }
+    if client.poll_votes >= 4 {
+        client.poll = (client.poll + 1).min(MAXPOLL);
+        client.poll_votes = 0;
+    } else if client.poll_votes <= -4 {
+        client.poll = (client.poll - 1).max(MINPOLL);
+        client.poll_votes = 0;
+    }
+    Ok(())
+}
+
+/// One round: query every configured server, select among them and
+/// discipline the clock. Returns the latest measurement of the system peer.
 pub fn sync() -> crate::util::KResult<NtpResult> {
-    let servers = {
+    let servers: Vec<Ipv4Addr> = {
         let guard = NTP_CLIENT.lock();
         match guard.as_ref() {
-            Some(client) => client.servers.clone(),
+            Some(client) => client.peers.iter().map(|p| p.addr).collect(),
             None => return Err(crate::util::KError::NotSupported),
         }
     };
@@ -464,117 +932,86 @@ pub fn sync() -> crate::util::KResult<NtpResult> {
         return Err(crate::util::KError::NotSupported);
     }
 
-    let mut best_result: Option<NtpResult> = None;
-
+    // The network is polled without the client lock held
+    let mut results = Vec::new();
     for server in &servers {
         match query_server(*server, DEFAULT_TIMEOUT_MS) {
-            Ok(result) => {
-                // Check if this is better (lower delay)
-                let is_better = match &best_result {
-                    None => true,
-                    Some(prev) => result.delay_ms < prev.delay_ms,
-                };
-
-                if is_better {
-                    best_result = Some(result);
-                }
-            }
+            Ok(result) => results.push(result),
             Err(_) => {
-                // Try next server
-                continue;
+                let mut guard = NTP_CLIENT.lock();
+                if let Some(peer) = guard.as_mut().and_then(|c| c.peers.iter_mut().find(|p| p.addr == *server)) {
+                    peer.reach <<= 1;
+                }
             }
         }
     }
+    if results.is_empty() {
+        return Err(crate::util::KError::Timeout);
+    }
 
-    let result = best_result.ok_or(crate::util::KError::Timeout)?;
-
-    // Update client state
-    {
-        let mut guard = NTP_CLIENT.lock();
-        if let Some(client) = guard.as_mut() {
-            client.last_sync.store(crate::time::uptime_secs(), Ordering::Relaxed);
-            client.last_offset_ms.store(result.offset_ms, Ordering::Relaxed);
-            client.sync_count.fetch_add(1, Ordering::Relaxed);
-        }
+    let now = crate::time::uptime_ns();
+    let mut guard = NTP_CLIENT.lock();
+    let client = guard.as_mut().ok_or(crate::util::KError::NotSupported)?;
+    for result in &results {
+        record(client, result, now);
+    }
+    client.burst = client.burst.saturating_sub(1);
+    // No majority of truechimers (yet)
+    let sel = clock_select(client, now).ok_or(crate::util::KError::Invalid)?;
+
+    // The filter can pick the same low-delay sample for several polls; the
+    // clock only follows a sample once (RFC 5905 clock_filter)
+    let peer = client
+        .peers
+        .iter_mut()
+        .find(|p| p.addr == sel.peer)
+        .ok_or(crate::util::KError::NotFound)?;
+    let fresh = sel.time > peer.last_used;
+    if fresh {
+        peer.last_used = sel.time;
+        discipline(client, &sel)?;
+        finish(client, &sel);
     }
 
-    // Apply the offset to system time
-    apply_offset(result.offset_ms);
+    let result = results
+        .into_iter()
+        .find(|r| r.server == sel.peer)
+        .ok_or(crate::util::KError::NotFound)?;
+    if !fresh {
+        return Ok(result);
+    }
 
     crate::kprintln!(
-        "ntp: synced with {} (stratum {}, offset {}ms, delay {}ms)",
+        "ntp: synced with {} (stratum {}, offset {}us, delay {}us)",
         result.server,
         result.stratum.0,
-        result.offset_ms,
-        result.delay_ms
+        sel.offset / 1_000,
+        result.delay_ns / 1_000
     );
 
     Ok(result)
 }
 
-/// Apply a time offset to the system clock
-fn apply_offset(offset_ms: i64) {
-    // Get current boot time and adjust it
-    use core::sync::atomic::Ordering;
-
-    // Access BOOT_TIME_SECS through time module
-    // The offset tells us: local_time - server_time = offset
-    // So server_time = local_time - offset
-    // To correct, we need to adjust BOOT_TIME_SECS by -offset
-
-    // Convert offset from ms to seconds
-    let offset_secs = offset_ms / 1000;
-
-    if offset_secs.abs() > MAX_OFFSET_WARN {
-        crate::kprintln!(
-            "ntp: WARNING: large clock offset detected: {} seconds",
-            offset_secs
-        );
-    }
-
-    // For now, we just log the offset
-    // In a full implementation, we would:
-    // 1. For small offsets: use adjtime() for gradual adjustment
-    // 2. For large offsets: step the clock immediately
-
-    // Update the system time by adjusting the boot time epoch
-    // This is done through the time module's set_time function
-    let current = crate::time::realtime();
-    let corrected_secs = (current.tv_sec - offset_secs) as u64;
-
-    // Set the new time
-    set_system_time(corrected_secs);
+/// Update client state after the clock was disciplined
+fn finish(client: &mut NtpClient, sel: &Selection) {
+    client.system_peer = Some(sel.peer);
+    client.stratum = sel.stratum;
+    client.jitter_ns = sel.jitter;
+    client.last_sync.store(crate::time::uptime_secs(), Ordering::Relaxed);
+    client.last_offset_ns.store(sel.offset, Ordering::Relaxed);
+    client.sync_count.fetch_add(1, Ordering::Relaxed);
 }
 
-/// Set the system time (Unix timestamp in seconds)
-fn set_system_time(unix_secs: u64) {
-    // Calculate what BOOT_TIME_SECS should be so that:
-    // realtime() = BOOT_TIME_SECS + uptime = unix_secs
-    // BOOT_TIME_SECS = unix_secs - uptime_secs
-
-    let uptime = crate::time::uptime_secs();
-    let new_boot_time = unix_secs.saturating_sub(uptime);
-
-    // We need to access the BOOT_TIME_SECS in time.rs
-    // For now, we store it in our own static
-    CORRECTED_BOOT_TIME.store(new_boot_time, Ordering::Relaxed);
-    TIME_CORRECTED.store(1, Ordering::Relaxed);
-}
-
-/// Corrected boot time (set by NTP)
-static CORRECTED_BOOT_TIME: AtomicU64 = AtomicU64::new(0);
-static TIME_CORRECTED: AtomicU64 = AtomicU64::new(0);
-
-/// Check if time has been corrected by NTP
+/// Check if the clock is synchronized to NTP
 pub fn is_time_corrected() -> bool {
-    TIME_CORRECTED.load(Ordering::Relaxed) != 0
+    crate::time::ntp::is_synchronized()
 }
 
 /// Get the time correction that was applied (in seconds)
 pub fn get_correction() -> i64 {
     let guard = NTP_CLIENT.lock();
     match guard.as_ref() {
-        Some(client) => client.last_offset_ms.load(Ordering::Relaxed) / 1000,
+        Some(client) => client.last_offset_ns.load(Ordering::Relaxed) / 1_000_000_000,
         None => 0,
     }
 }
@@ -583,11 +1020,20 @@ pub fn get_correction() -> i64 {
 pub fn get_stats() -> Option<NtpStats> {
     let guard = NTP_CLIENT.lock();
     guard.as_ref().map(|client| NtpStats {
-        servers_configured: client.servers.len(),
+        servers_configured: client.peers.len(),
+        servers_reachable: client.peers.iter().filter(|p| p.reach != 0).count(),
+        enabled: ENABLED.load(Ordering::Relaxed),
+        synchronized: crate::time::ntp::is_synchronized(),
+        system_peer: client.system_peer,
+        stratum: client.stratum,
+        poll_interval_secs: client.interval_secs(),
         last_sync_uptime: client.last_sync.load(Ordering::Relaxed),
-        last_offset_ms: client.last_offset_ms.load(Ordering::Relaxed),
+        last_offset_ns: client.last_offset_ns.load(Ordering::Relaxed),
+        jitter_ns: client.jitter_ns,
         sync_count: client.sync_count.load(Ordering::Relaxed),
         fail_count: client.fail_count.load(Ordering::Relaxed),
+        sync_pending: SYNC_REQUESTED.load(Ordering::Acquire),
+        last_failed: LAST_FAILED.load(Ordering::Relaxed),
     })
 }
 
@@ -595,10 +1041,22 @@ pub fn get_stats() -> Option<NtpStats> {
 #[derive(Debug, Clone)]
 pub struct NtpStats {
     pub servers_configured: usize,
+    /// Servers that answered one of the last eight polls
+    pub servers_reachable: usize,
+    pub enabled: bool,
+    pub synchronized: bool,
+    pub system_peer: Option<Ipv4Addr>,
+    /// Stratum of the system peer
+    pub stratum: u8,
+    pub poll_interval_secs: u64,
     pub last_sync_uptime: u64,
-    pub last_offset_ms: i64,
+    pub last_offset_ns: i64,
+    pub jitter_ns: i64,
     pub sync_count: u32,
     pub fail_count: u32,
+    /// A round asked for with `request_sync` is still running
+    pub sync_pending: bool,
+    pub last_failed: bool,
 }
 
 /// Format NTP info for display
@@ -610,10 +1068,10 @@ pub fn format_info() -> String {
         None => return String::from("NTP client not initialized\n"),
     };
 
-    let servers = {
+    let servers: Vec<(Ipv4Addr, u8)> = {
         let guard = NTP_CLIENT.lock();
         match guard.as_ref() {
-            Some(client) => client.servers.clone(),
+            Some(client) => client.peers.iter().map(|p| (p.addr, p.reach)).collect(),
             None => Vec::new(),
         }
     };
@@ -621,15 +1079,23 @@ pub fn format_info() -> String {
     let mut info = String::new();
     info.push_str(&format!("NTP Client Status\n"));
     info.push_str(&format!("=================\n"));
+    info.push_str(&format!("Enabled:            {}\n", if stats.enabled { "yes" } else { "no" }));
+    info.push_str(&format!("Synchronized:       {}\n", if stats.synchronized { "yes" } else { "no" }));
+    match stats.system_peer {
+        Some(peer) => info.push_str(&format!("System peer:        {} (stratum {})\n", peer, stats.stratum)),
+        None => info.push_str(&format!("System peer:        none\n")),
+    }
     info.push_str(&format!("Servers configured: {}\n", stats.servers_configured));
+    info.push_str(&format!("Servers reachable:  {}\n", stats.servers_reachable));
+    info.push_str(&format!("Poll interval:      {} seconds\n", stats.poll_interval_secs));
     info.push_str(&format!("Successful syncs:   {}\n", stats.sync_count));
     info.push_str(&format!("Failed syncs:       {}\n", stats.fail_count));
     info.push_str(&format!("Last sync uptime:   {} seconds\n", stats.last_sync_uptime));
-    info.push_str(&format!("Last offset:        {} ms\n", stats.last_offset_ms));
-    info.push_str(&format!("Time corrected:     {}\n", if is_time_corrected() { "yes" } else { "no" }));
+    info.push_str(&format!("Last offset:        {} us\n", stats.last_offset_ns / 1_000));
+    info.push_str(&format!("Jitter:             {} us\n", stats.jitter_ns / 1_000));
     info.push_str(&format!("\nConfigured servers:\n"));
-    for server in &servers {
-        info.push_str(&format!("  {}\n", server));
+    for (server, reach) in &servers {
+        info.push_str(&format!("  {} (reach {:03o})\n", server, reach));
     }
 
     info
@@ -642,46 +1108,148 @@ pub fn query_hostname(hostname: &str, timeout_ms: u32) -> crate::util::KResult<N
     query_server(ip, timeout_ms)
 }
 
-/// Synchronize time using a hostname
+/// Synchronize time using a hostname. A single server cannot be checked
+/// against others, so its offset is taken as is.
 pub fn sync_with_hostname(hostname: &str) -> crate::util::KResult<NtpResult> {
     let result = query_hostname(hostname, DEFAULT_TIMEOUT_MS)?;
+    if !result.stratum.is_synchronized() || result.leap == LeapIndicator::AlarmCondition {
+        return Err(crate::util::KError::Invalid);
+    }
 
-    // Update client state
+    let est = Estimate {
+        offset: result.offset_ns,
+        delay: result.delay_ns,
+        dispersion: result.dispersion_ns,
+        jitter: 0,
+        time: crate::time::uptime_ns(),
+    };
+    let sel = Selection {
+        peer: result.server,
+        offset: result.offset_ns,
+        jitter: 0,
+        root_distance: root_distance(&est, result.root_delay_ns, result.root_dispersion_ns),
+        stratum: result.stratum.0,
+        leap: result.leap,
+        time: est.time,
+    };
     {
         let mut guard = NTP_CLIENT.lock();
-        if let Some(client) = guard.as_mut() {
-            client.last_sync.store(crate::time::uptime_secs(), Ordering::Relaxed);
-            client.last_offset_ms.store(result.offset_ms, Ordering::Relaxed);
-            client.sync_count.fetch_add(1, Ordering::Relaxed);
-        }
+        let client = guard.as_mut().ok_or(crate::util::KError::NotSupported)?;
+        discipline(client, &sel)?;
+        finish(client, &sel);
     }
 
-    // Apply the offset to system time
-    apply_offset(result.offset_ms);
-
     crate::kprintln!(
-        "ntp: synced with {} ({}) stratum {}, offset {}ms",
+        "ntp: synced with {} ({}) stratum {}, offset {}us",
         hostname,
         result.server,
         result.stratum.0,
-        result.offset_ms
+        result.offset_ns / 1_000
     );
 
     Ok(result)
 }
 
 /// Background NTP synchronization task
-/// This should be called periodically (e.g., every 64-1024 seconds)
+/// Runs one round; `start` calls it every poll interval (2 s during the
+/// startup burst, then 64-1024 seconds)
 pub fn periodic_sync() {
-    match sync() {
+    let result = sync();
+    LAST_FAILED.store(result.is_err(), Ordering::Relaxed);
+    match result {
         Ok(_) => {},
         Err(e) => {
             // Record failure
             let mut guard = NTP_CLIENT.lock();
             if let Some(client) = guard.as_mut() {
                 client.fail_count.fetch_add(1, Ordering::Relaxed);
+                client.burst = client.burst.saturating_sub(1);
             }
             crate::kprintln!("ntp: sync failed: {:?}", e);
         }
     }
 }
+
+/// Enable or disable automatic synchronization. Disabling leaves the
+/// clock running at the frequency learnt so far.
+pub fn set_enabled(enabled: bool) {
+    if enabled && !ENABLED.swap(true, Ordering::Relaxed) {
+        let mut guard = NTP_CLIENT.lock();
+        if let Some(client) = guard.as_mut() {
+            client.reset();
+        }
+    } else if !enabled {
+        ENABLED.store(false, Ordering::Relaxed);
+    }
+}
+
+pub fn is_enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// Start the background synchronization thread (once). Does nothing
+/// without a network interface.
+pub fn start() {
+    if NTP_CLIENT.lock().is_none() || STARTED.swap(true, Ordering::AcqRel) {
+        return;
+    }
+    if let Err(e) = crate::sched::spawn_kernel_thread("ntpd", ntpd_thread, 0) {
+        STARTED.store(false, Ordering::Release);
+        crate::kprintln!("ntp: failed to start: {:?}", e);
+    }
+}
+
+/// Ask ntpd for a round now instead of at the next poll. Returns at once;
+/// the outcome shows up in `get_stats`. False if ntpd is not running.
+pub fn request_sync() -> bool {
+    let tid = NTPD_TID.load(Ordering::Acquire);
+    if tid == 0 {
+        return false;
+    }
+    SYNC_REQUESTED.store(true, Ordering::Release);
+    crate::sched::wake_task(tid);
+    true
+}
+
+fn ntpd_thread(_arg: u64) -> ! {
+    NTPD_TID.store(crate::sched::current_tid(), Ordering::Release);
+    loop {
+        let requested = SYNC_REQUESTED.load(Ordering::Acquire);
+        if requested || ENABLED.load(Ordering::Relaxed) {
+            periodic_sync();
+        }
+        if requested {
+            SYNC_REQUESTED.store(false, Ordering::Release);
+        }
+        let secs = {
+            let guard = NTP_CLIENT.lock();
+            guard.as_ref().map_or(1 << MINPOLL, |c| c.interval_secs())
+        };
+        wait_for_poll(crate::time::uptime_ns() + secs * 1_000_000_000);
+    }
+}
+
+/// Sleep until `deadline` or until `request_sync` wakes us
+fn wait_for_poll(deadline: u64) {
+    let tid = crate::sched::current_tid();
+    while !SYNC_REQUESTED.load(Ordering::Acquire) && crate::time::uptime_ns() < deadline {
+        // Mark the task sleeping before checking for a request, so a
+        // request made in between still finds it to wake
+        let timer = x86_64::instructions::interrupts::without_interrupts(|| {
+            crate::sched::prepare_to_sleep();
+            if SYNC_REQUESTED.load(Ordering::Acquire) {
+                crate::sched::finish_sleep();
+                return None;
+            }
+            Some(crate::time::hrtimer::start(deadline, move |_| {
+                crate::sched::wake_task(tid);
+                None
+            }))
+        });
+        let Some(timer) = timer else {
+            return;
+        };
+        crate::sched::sleep();
+        crate::time::hrtimer::cancel(timer);
+    }
+}
@@ -12,6 +12,8 @@ use alloc::sync::Arc;
 use alloc::vec::Vec;
 
 use super::{Ipv4Addr, tcp, udp};
+use crate::profiling::ebpf::{self, BpfProg};
+use crate::security::hooks;
 use crate::sync::IrqSafeMutex;
 
 /// Tipos de socket
@@ -30,6 +32,11 @@ pub enum SocketDomain {
     Unix,       // Unix domain (não implementado)
 }
 
+/// Nível e opções de setsockopt tratados pelo kernel
+pub const SOL_SOCKET: i32 = 1;
+pub const SO_DETACH_BPF: i32 = 27;
+pub const SO_ATTACH_BPF: i32 = 50;
+
 /// Estado do socket
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum SocketState {
@@ -79,6 +86,12 @@ impl SocketAddr {
     }
 }
 
+impl core::fmt::Display for SocketAddr {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{}:{}", self.ip, self.port)
+    }
+}
+
 // ==================== Unix Domain Sockets ====================
 
 /// Maximum path length for Unix socket addresses
@@ -238,6 +251,8 @@ pub struct Socket {
     pub unix_is_side_a: bool,
     /// Opções
     pub nonblocking: bool,
+    /// Programa eBPF (SO_ATTACH_BPF) que filtra os datagramas recebidos
+    pub filter: Option<Arc<BpfProg>>,
 }
 
 impl Socket {
@@ -255,6 +270,7 @@ impl Socket {
             unix_conn_id: None,
             unix_is_side_a: false,
             nonblocking: false,
+            filter: None,
         }
     }
 }
@@ -285,6 +301,8 @@ pub fn socket(domain: i32, sock_type: i32, _protocol: i32) -> crate::util::KResu
         _ => return Err(crate::util::KError::NotSupported),
     };
 
+    hooks::socket_create(domain, sock_type)?;
+
     let id = unsafe {
         let id = NEXT_SOCKET_ID;
         NEXT_SOCKET_ID += 1;
@@ -308,6 +326,8 @@ pub fn bind(sockfd: u64, addr: &SocketAddr) -> crate::util::KResult<()> {
         return Err(crate::util::KError::Invalid);
     }
 
+    hooks::socket_bind(sock.domain, sock.sock_type, addr)?;
+
     match sock.sock_type {
         SocketType::Datagram => {
             udp::bind(addr.port)?;
@@ -400,6 +420,8 @@ pub fn connect(sockfd: u64, addr: &SocketAddr) -> crate::util::KResult<()> {
     let mut sockets = SOCKETS.lock();
     let sock = sockets.get_mut(&sockfd).ok_or(crate::util::KError::Invalid)?;
 
+    hooks::socket_connect(sock.domain, sock.sock_type, addr)?;
+
     match sock.sock_type {
         SocketType::Stream => {
             drop(sockets);
@@ -477,15 +499,16 @@ pub fn sendto(sockfd: u64, data: &[u8], addr: &SocketAddr) -> crate::util::KResu
 
 /// Recebe dados
 pub fn recv(sockfd: u64, buf: &mut [u8]) -> crate::util::KResult<usize> {
-    let (sock_type, key_or_port, nonblocking) = {
+    let (sock_type, key_or_port, nonblocking, filter) = {
         let sockets = SOCKETS.lock();
         let sock = sockets.get(&sockfd).ok_or(crate::util::KError::Invalid)?;
         let nonblocking = sock.nonblocking;
+        let filter = sock.filter.clone();
 
         match sock.sock_type {
             SocketType::Stream => {
                 let key = sock.tcp_key.ok_or(crate::util::KError::Invalid)?;
-                (SocketType::Stream, Some((key, 0u16)), nonblocking)
+                (SocketType::Stream, Some((key, 0u16)), nonblocking, filter)
             }
             SocketType::Datagram => {
                 let port = sock.udp_port.ok_or(crate::util::KError::Invalid)?;
@@ -494,7 +517,7 @@ pub fn recv(sockfd: u64, buf: &mut [u8]) -> crate::util::KResult<usize> {
                     local_port: port,
                     remote_addr: super::Ipv4Addr::UNSPECIFIED,
                     remote_port: 0,
-                }, port)), nonblocking)
+                }, port)), nonblocking, filter)
             }
             _ => return Err(crate::util::KError::NotSupported),
         }
@@ -529,7 +552,7 @@ for _ in 0..1000 { core::hint::spin_loop(); }
             for _ in 0..max_iterations {
                 super::poll();
 
-                if let Some(dgram) = udp::recv(port) {
+                if let Some(dgram) = next_datagram(port, filter.as_deref()) {
                     let len = dgram.data.len().min(buf.len());
                     buf[..len].copy_from_slice(&dgram.data[..len]);
                     return Ok(len);
@@ -547,9 +570,28 @@ for _ in 0..1000 { core::hint::spin_loop(); }
     }
 }
 
+/// Próximo datagrama da porta que passa pelo filtro do socket
+///
+/// O programa vê o payload do datagrama; os descartados (retorno 0) somem
+/// da fila, os outros são truncados ao tamanho que o programa devolveu.
+fn next_datagram(port: u16, filter: Option<&BpfProg>) -> Option<udp::ReceivedDatagram> {
+    while let Some(mut dgram) = udp::recv(port) {
+        let prog = match filter {
+            Some(prog) => prog,
+            None => return Some(dgram),
+        };
+        let keep = ebpf::run_socket_filter(prog, &mut dgram.data, 0x0800, super::ETH0_IFINDEX);
+        if keep > 0 {
+            dgram.data.truncate(keep);
+            return Some(dgram);
+        }
+    }
+    None
+}
+
 /// Recebe dados com endereço de origem (UDP)
 pub fn recvfrom(sockfd: u64, buf: &mut [u8]) -> crate::util::KResult<(usize, SocketAddr)> {
-    let (port, nonblocking) = {
+    let (port, nonblocking, filter) = {
         let sockets = SOCKETS.lock();
         let sock = sockets.get(&sockfd).ok_or(crate::util::KError::Invalid)?;
 
@@ -557,7 +599,7 @@ pub fn recvfrom(sockfd: u64, buf: &mut [u8]) -> crate::util::KResult<(usize, Soc
             return Err(crate::util::KError::NotSupported);
         }
 
-        (sock.udp_port.ok_or(crate::util::KError::Invalid)?, sock.nonblocking)
+        (sock.udp_port.ok_or(crate::util::KError::Invalid)?, sock.nonblocking, sock.filter.clone())
     };
 
     // Timeout: ~30 segundos de espera máxima para blocking sockets
@@ -566,7 +608,7 @@ pub fn recvfrom(sockfd: u64, buf: &mut [u8]) -> crate::util::KResult<(usize, Soc
     for _ in 0..max_iterations {
         super::poll();
 
-        if let Some(dgram) = udp::recv(port) {
+        if let Some(dgram) = next_datagram(port, filter.as_deref()) {
             let len = dgram.data.len().min(buf.len());
             buf[..len].copy_from_slice(&dgram.data[..len]);
             let addr = SocketAddr::new(dgram.src_addr, dgram.src_port);
@@ -638,6 +680,23 @@ pub fn setsockopt(sockfd: u64, _level: i32, _optname: i32, _optval: &[u8]) -> cr
     Ok(())
 }
 
+/// Anexa um filtro eBPF (SO_ATTACH_BPF), trocando o anterior
+///
+/// Só os datagramas passam pelo filtro.
+pub fn attach_filter(sockfd: u64, prog: Arc<BpfProg>) -> crate::util::KResult<()> {
+    let mut sockets = SOCKETS.lock();
+    let sock = sockets.get_mut(&sockfd).ok_or(crate::util::KError::Invalid)?;
+    sock.filter = Some(prog);
+    Ok(())
+}
+
+/// Remove o filtro eBPF (SO_DETACH_BPF); NotFound se não havia um
+pub fn detach_filter(sockfd: u64) -> crate::util::KResult<()> {
+    let mut sockets = SOCKETS.lock();
+    let sock = sockets.get_mut(&sockfd).ok_or(crate::util::KError::Invalid)?;
+    sock.filter.take().map(|_| ()).ok_or(crate::util::KError::NotFound)
+}
+
 /// Obtém opções do socket
 pub fn getsockopt(sockfd: u64, _level: i32, _optname: i32, buf: &mut [u8]) -> crate::util::KResult<usize> {
     let sockets = SOCKETS.lock();
@@ -829,6 +888,8 @@ pub fn unix_bind(sockfd: u64, addr: &UnixSocketAddr) -> crate::util::KResult<()>
         return Err(crate::util::KError::Invalid);
     }
 
+    hooks::socket_bind(sock.domain, sock.sock_type, &addr.path)?;
+
     // Check if path is already bound
     {
         let listeners = UNIX_LISTENERS.lock();
@@ -941,6 +1002,12 @@ pub fn unix_accept(sockfd: u64) -> crate::util::KResult<(u64, UnixSocketAddr)> {
 
 /// Connect to a Unix domain socket
 pub fn unix_connect(sockfd: u64, addr: &UnixSocketAddr) -> crate::util::KResult<()> {
+    {
+        let sockets = SOCKETS.lock();
+        let sock = sockets.get(&sockfd).ok_or(crate::util::KError::Invalid)?;
+        hooks::socket_connect(sock.domain, sock.sock_type, &addr.path)?;
+    }
+
     // First, check if there's a listener at the target path
     {
         let mut listeners = UNIX_LISTENERS.lock();
@@ -982,6 +1049,7 @@ pub fn unix_connect(sockfd: u64, addr: &UnixSocketAddr) -> crate::util::KResult<
         listener.pending.push_back(sockfd);
     }
 
+    crate::syscall::epoll::wake_sockets();
     Ok(())
 }
 
@@ -1022,6 +1090,8 @@ pub fn unix_send(sockfd: u64, data: &[u8]) -> crate::util::KResult<usize> {
 
         let written = buffer.write(data);
         if written > 0 {
+            drop(conn);
+            crate::syscall::epoll::wake_sockets();
             return Ok(written);
         }
 
@@ -1075,6 +1145,8 @@ pub fn unix_recv(sockfd: u64, buf: &mut [u8]) -> crate::util::KResult<usize> {
 
         let read = buffer.read(buf);
         if read > 0 {
+            drop(conn);
+            crate::syscall::epoll::wake_sockets();
             return Ok(read);
         }
 
@@ -1126,6 +1198,8 @@ pub fn unix_close(sockfd: u64) {
         let mut listeners = UNIX_LISTENERS.lock();
         listeners.remove(&path);
     }
+
+    crate::syscall::epoll::wake_sockets();
 }
 
 /// Get the domain of a socket
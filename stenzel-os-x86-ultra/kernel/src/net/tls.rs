@@ -3,6 +3,12 @@
 //! Minimal TLS implementation supporting:
 //! - TLS 1.2 with TLS_ECDHE_RSA_WITH_CHACHA20_POLY1305_SHA256
 //! - TLS 1.3 with TLS_CHACHA20_POLY1305_SHA256
+//!
+//! The server certificate chain is validated against the system trust store
+//! (`crypto::x509`), and the server's handshake signature (ServerKeyExchange
+//! in TLS 1.2, CertificateVerify in TLS 1.3) is checked with the leaf key.
+//! In both versions the server's Finished must match the handshake
+//! transcript, so a tampered handshake is rejected.
 
 #![allow(dead_code)]
 
@@ -12,7 +18,8 @@ use alloc::vec;
 
 use crate::crypto::{sha256, hmac_sha256, hkdf_extract, hkdf_expand, Sha256};
 use crate::crypto::{chacha20_poly1305_encrypt, chacha20_poly1305_decrypt};
-use crate::crypto::{x25519_public_key, x25519_diffie_hellman, random_bytes};
+use crate::crypto::{x25519_public_key, x25519_diffie_hellman, random_bytes, constant_time_eq};
+use crate::crypto::x509::{self, CertError, Certificate, PublicKey, SignatureAlgorithm};
 use crate::util::{KError, KResult};
 
 use super::tcp::{self, TcpConnKey};
@@ -54,11 +61,29 @@ const EXT_KEY_SHARE: u16 = 51;
 // Named Groups
 const X25519: u16 = 29;
 
+// Signature Schemes
+const SIG_RSA_PKCS1_SHA256: u16 = 0x0401;
+const SIG_RSA_PSS_RSAE_SHA256: u16 = 0x0804;
+const SIG_ED25519: u16 = 0x0807;
+
 // Alert Descriptions
 const ALERT_CLOSE_NOTIFY: u8 = 0;
 const ALERT_UNEXPECTED_MESSAGE: u8 = 10;
 const ALERT_BAD_RECORD_MAC: u8 = 20;
 const ALERT_HANDSHAKE_FAILURE: u8 = 40;
+const ALERT_BAD_CERTIFICATE: u8 = 42;
+const ALERT_UNSUPPORTED_CERTIFICATE: u8 = 43;
+const ALERT_CERTIFICATE_EXPIRED: u8 = 45;
+const ALERT_CERTIFICATE_UNKNOWN: u8 = 46;
+const ALERT_ILLEGAL_PARAMETER: u8 = 47;
+const ALERT_UNKNOWN_CA: u8 = 48;
+const ALERT_DECRYPT_ERROR: u8 = 51;
+
+/// Context string of the server's TLS 1.3 CertificateVerify signature
+const SERVER_CERTIFICATE_VERIFY_CONTEXT: &[u8] = b"TLS 1.3, server CertificateVerify";
+
+/// Largest handshake message accepted (long certificate chains included)
+const MAX_HANDSHAKE_MESSAGE: usize = 128 * 1024;
 
 /// TLS Connection State
 #[derive(Debug, Clone, Copy, PartialEq)]
@@ -88,6 +113,13 @@ impl TrafficKeys {
         Self { key, iv, seq: 0 }
     }
 
+    /// Key and IV of a TLS 1.3 traffic secret
+    fn from_secret(secret: &[u8; 32]) -> Self {
+        let key = expand_label_32(secret, b"key", &[]);
+        let iv = expand_label(secret, b"iv", &[], 12);
+        Self::new(key, iv.try_into().unwrap())
+    }
+
     fn nonce(&self) -> [u8; 12] {
         let mut nonce = self.iv;
         let seq_bytes = self.seq.to_be_bytes();
@@ -115,10 +147,23 @@ pub struct TlsConnection {
     client_public_key: [u8; 32],
     server_public_key: [u8; 32],
 
+    // Public key of the validated server certificate
+    peer_key: Option<PublicKey>,
+
     // Session keys
     client_keys: Option<TrafficKeys>,
     server_keys: Option<TrafficKeys>,
 
+    // TLS 1.3 key schedule
+    handshake_secret: [u8; 32],
+    client_hs_secret: [u8; 32],
+    server_hs_secret: [u8; 32],
+    // Client application keys, installed once the client Finished is sent
+    client_app_keys: Option<TrafficKeys>,
+
+    // TLS 1.2 master secret, also the key of the Finished messages
+    master_secret: [u8; 48],
+
     // Handshake hash (for Finished message)
     handshake_hash: Sha256,
 
@@ -127,6 +172,9 @@ pub struct TlsConnection {
 
     // Receive buffer
     recv_buffer: Vec<u8>,
+
+    // Handshake bytes received but not yet parsed into messages
+    handshake_buffer: Vec<u8>,
 }
 
 impl TlsConnection {
@@ -148,11 +196,18 @@ impl TlsConnection {
             client_private_key,
             client_public_key,
             server_public_key: [0; 32],
+            peer_key: None,
             client_keys: None,
             server_keys: None,
+            handshake_secret: [0; 32],
+            client_hs_secret: [0; 32],
+            server_hs_secret: [0; 32],
+            client_app_keys: None,
+            master_secret: [0; 48],
             handshake_hash: Sha256::new(),
             host,
             recv_buffer: Vec::new(),
+            handshake_buffer: Vec::new(),
         }
     }
 
@@ -167,6 +222,11 @@ impl TlsConnection {
 
         // Process based on TLS version
         if self.tls_version == TLS_VERSION_1_3 {
+            // The handshake keys apply from the next record on
+            if !self.handshake_buffer.is_empty() {
+                self.send_alert(ALERT_UNEXPECTED_MESSAGE);
+                return Err(KError::Invalid);
+            }
             self.handshake_tls13()?;
         } else {
             self.handshake_tls12()?;
@@ -280,9 +340,11 @@ impl TlsConnection {
 
         // Signature Algorithms
         ext.extend_from_slice(&EXT_SIGNATURE_ALGORITHMS.to_be_bytes());
-        ext.extend_from_slice(&4u16.to_be_bytes());
-        ext.extend_from_slice(&2u16.to_be_bytes());
-        ext.extend_from_slice(&0x0804u16.to_be_bytes()); // rsa_pss_rsae_sha256
+        ext.extend_from_slice(&8u16.to_be_bytes());
+        ext.extend_from_slice(&6u16.to_be_bytes());
+        ext.extend_from_slice(&SIG_ED25519.to_be_bytes());
+        ext.extend_from_slice(&SIG_RSA_PSS_RSAE_SHA256.to_be_bytes());
+        ext.extend_from_slice(&SIG_RSA_PKCS1_SHA256.to_be_bytes());
 
         // Supported Versions (for TLS 1.3)
         ext.extend_from_slice(&EXT_SUPPORTED_VERSIONS.to_be_bytes());
@@ -321,66 +383,58 @@ impl TlsConnection {
 
     /// Receive and process ServerHello
     fn receive_server_hello(&mut self) -> KResult<()> {
-        let record = self.receive_record()?;
-        if record.content_type != CONTENT_TYPE_HANDSHAKE {
-            return Err(KError::Invalid);
-        }
-
-        self.handshake_hash.update(&record.data);
-
-        if record.data.is_empty() || record.data[0] != HANDSHAKE_SERVER_HELLO {
-            return Err(KError::Invalid);
-        }
+        let message = self.receive_handshake_plain(HANDSHAKE_SERVER_HELLO)?;
+        self.handshake_hash.update(&message);
 
         // Parse ServerHello
         let mut offset = 4; // Skip type and length
 
         // Server version
-        if record.data.len() < offset + 2 {
+        if message.len() < offset + 2 {
             return Err(KError::Invalid);
         }
-        let _legacy_version = u16::from_be_bytes([record.data[offset], record.data[offset + 1]]);
+        let _legacy_version = u16::from_be_bytes([message[offset], message[offset + 1]]);
         offset += 2;
 
         // Server random
-        if record.data.len() < offset + 32 {
+        if message.len() < offset + 32 {
             return Err(KError::Invalid);
         }
-        self.server_random.copy_from_slice(&record.data[offset..offset + 32]);
+        self.server_random.copy_from_slice(&message[offset..offset + 32]);
         offset += 32;
 
         // Session ID
-        if record.data.len() < offset + 1 {
+        if message.len() < offset + 1 {
             return Err(KError::Invalid);
         }
-        let session_id_len = record.data[offset] as usize;
+        let session_id_len = message[offset] as usize;
         offset += 1 + session_id_len;
 
         // Cipher suite
-        if record.data.len() < offset + 2 {
+        if message.len() < offset + 2 {
             return Err(KError::Invalid);
         }
-        let cipher_suite = u16::from_be_bytes([record.data[offset], record.data[offset + 1]]);
+        let cipher_suite = u16::from_be_bytes([message[offset], message[offset + 1]]);
         offset += 2;
 
         // Compression
         offset += 1;
 
         // Extensions
-        if record.data.len() > offset {
-            let ext_len = u16::from_be_bytes([record.data[offset], record.data[offset + 1]]) as usize;
+        if message.len() > offset {
+            let ext_len = u16::from_be_bytes([message[offset], message[offset + 1]]) as usize;
             offset += 2;
 
             let ext_end = offset + ext_len;
-            while offset < ext_end && offset + 4 <= record.data.len() {
-                let ext_type = u16::from_be_bytes([record.data[offset], record.data[offset + 1]]);
-                let ext_len = u16::from_be_bytes([record.data[offset + 2], record.data[offset + 3]]) as usize;
+            while offset < ext_end && offset + 4 <= message.len() {
+                let ext_type = u16::from_be_bytes([message[offset], message[offset + 1]]);
+                let ext_len = u16::from_be_bytes([message[offset + 2], message[offset + 3]]) as usize;
                 offset += 4;
 
                 if ext_type == EXT_SUPPORTED_VERSIONS {
                     // Check for TLS 1.3
                     if ext_len >= 2 {
-                        let version = u16::from_be_bytes([record.data[offset], record.data[offset + 1]]);
+                        let version = u16::from_be_bytes([message[offset], message[offset + 1]]);
                         if version == TLS_VERSION_1_3 {
                             self.tls_version = TLS_VERSION_1_3;
                         }
@@ -388,11 +442,11 @@ impl TlsConnection {
                 } else if ext_type == EXT_KEY_SHARE {
                     // Server's key share
                     if ext_len >= 36 {
-                        let group = u16::from_be_bytes([record.data[offset], record.data[offset + 1]]);
+                        let group = u16::from_be_bytes([message[offset], message[offset + 1]]);
                         if group == X25519 {
-                            let key_len = u16::from_be_bytes([record.data[offset + 2], record.data[offset + 3]]) as usize;
+                            let key_len = u16::from_be_bytes([message[offset + 2], message[offset + 3]]) as usize;
                             if key_len == 32 {
-                                self.server_public_key.copy_from_slice(&record.data[offset + 4..offset + 4 + 32]);
+                                self.server_public_key.copy_from_slice(&message[offset + 4..offset + 4 + 32]);
                             }
                         }
                     }
@@ -414,19 +468,11 @@ impl TlsConnection {
 
     /// Receive Certificate (TLS 1.2)
     fn receive_certificate(&mut self) -> KResult<()> {
-        let record = self.receive_record()?;
-        if record.content_type != CONTENT_TYPE_HANDSHAKE {
-            return Err(KError::Invalid);
-        }
-
-        self.handshake_hash.update(&record.data);
+        let message = self.receive_handshake_plain(HANDSHAKE_CERTIFICATE)?;
+        self.handshake_hash.update(&message);
 
-        if record.data.is_empty() || record.data[0] != HANDSHAKE_CERTIFICATE {
-            return Err(KError::Invalid);
-        }
-
-        // We skip certificate verification for now (trusting all certificates)
-        // In a production system, proper X.509 validation is required
+        let certs = parse_certificate_list(&message[4..], false)?;
+        self.verify_server_chain(&certs)?;
 
         self.state = TlsState::ReceivedCertificate;
         Ok(())
@@ -434,46 +480,47 @@ impl TlsConnection {
 
     /// Receive ServerKeyExchange (TLS 1.2)
     fn receive_server_key_exchange(&mut self) -> KResult<()> {
-        let record = self.receive_record()?;
-        if record.content_type != CONTENT_TYPE_HANDSHAKE {
-            return Err(KError::Invalid);
-        }
-
-        self.handshake_hash.update(&record.data);
-
-        if record.data.is_empty() || record.data[0] != HANDSHAKE_SERVER_KEY_EXCHANGE {
-            return Err(KError::Invalid);
-        }
+        let message = self.receive_handshake_plain(HANDSHAKE_SERVER_KEY_EXCHANGE)?;
+        self.handshake_hash.update(&message);
 
         // Parse ServerKeyExchange for ECDHE
         let mut offset = 4; // Skip type and length
 
         // Curve type (named curve = 3)
-        if record.data.len() < offset + 4 {
+        if message.len() < offset + 4 {
             return Err(KError::Invalid);
         }
-        let curve_type = record.data[offset];
+        let curve_type = message[offset];
         if curve_type != 3 {
             return Err(KError::NotSupported);
         }
         offset += 1;
 
         // Named curve
-        let named_curve = u16::from_be_bytes([record.data[offset], record.data[offset + 1]]);
+        let named_curve = u16::from_be_bytes([message[offset], message[offset + 1]]);
         if named_curve != X25519 {
             return Err(KError::NotSupported);
         }
         offset += 2;
 
         // Public key length
-        let key_len = record.data[offset] as usize;
+        let key_len = message[offset] as usize;
         offset += 1;
 
-        if key_len != 32 || record.data.len() < offset + 32 {
+        if key_len != 32 || message.len() < offset + 32 {
             return Err(KError::Invalid);
         }
 
-        self.server_public_key.copy_from_slice(&record.data[offset..offset + 32]);
+        self.server_public_key.copy_from_slice(&message[offset..offset + 32]);
+        let params_end = offset + 32;
+
+        // The server signs client_random || server_random || ECDH params
+        let (scheme, signature) = parse_digitally_signed(&message[params_end..])?;
+        let mut signed = Vec::with_capacity(64 + params_end - 4);
+        signed.extend_from_slice(&self.client_random);
+        signed.extend_from_slice(&self.server_random);
+        signed.extend_from_slice(&message[4..params_end]);
+        self.verify_peer_signature(scheme, &signed, signature)?;
 
         self.state = TlsState::ReceivedServerKeyExchange;
         Ok(())
@@ -481,16 +528,8 @@ impl TlsConnection {
 
     /// Receive ServerHelloDone (TLS 1.2)
     fn receive_server_hello_done(&mut self) -> KResult<()> {
-        let record = self.receive_record()?;
-        if record.content_type != CONTENT_TYPE_HANDSHAKE {
-            return Err(KError::Invalid);
-        }
-
-        self.handshake_hash.update(&record.data);
-
-        if record.data.is_empty() || record.data[0] != HANDSHAKE_SERVER_HELLO_DONE {
-            return Err(KError::Invalid);
-        }
+        let message = self.receive_handshake_plain(HANDSHAKE_SERVER_HELLO_DONE)?;
+        self.handshake_hash.update(&message);
 
         self.state = TlsState::ReceivedServerHelloDone;
         Ok(())
@@ -524,7 +563,7 @@ impl TlsConnection {
         seed.extend_from_slice(&self.client_random);
         seed.extend_from_slice(&self.server_random);
 
-        let master_secret = self.prf(&premaster, b"master secret", &seed, 48);
+        let master_secret = prf_sha256(&premaster, b"master secret", &seed, 48);
 
         // Key expansion
         let mut key_block_seed = Vec::new();
@@ -532,7 +571,7 @@ impl TlsConnection {
         key_block_seed.extend_from_slice(&self.client_random);
 
         // Need: client_write_key(32) + server_write_key(32) + client_write_iv(12) + server_write_iv(12) = 88 bytes
-        let key_block = self.prf(&master_secret, b"key expansion", &key_block_seed, 88);
+        let key_block = prf_sha256(&master_secret, b"key expansion", &key_block_seed, 88);
 
         let client_key: [u8; 32] = key_block[0..32].try_into().unwrap();
         let server_key: [u8; 32] = key_block[32..64].try_into().unwrap();
@@ -541,34 +580,11 @@ impl TlsConnection {
 
         self.client_keys = Some(TrafficKeys::new(client_key, client_iv));
         self.server_keys = Some(TrafficKeys::new(server_key, server_iv));
+        self.master_secret.copy_from_slice(&master_secret);
 
         Ok(())
     }
 
-    /// TLS 1.2 PRF (HMAC-SHA256 based)
-    fn prf(&self, secret: &[u8], label: &[u8], seed: &[u8], length: usize) -> Vec<u8> {
-        let mut combined_seed = Vec::new();
-        combined_seed.extend_from_slice(label);
-        combined_seed.extend_from_slice(seed);
-
-        let mut result = Vec::new();
-        let mut a = hmac_sha256(secret, &combined_seed);
-
-        while result.len() < length {
-            let mut p_input = Vec::new();
-            p_input.extend_from_slice(&a);
-            p_input.extend_from_slice(&combined_seed);
-
-            let p = hmac_sha256(secret, &p_input);
-            result.extend_from_slice(&p);
-
-            a = hmac_sha256(secret, &a);
-        }
-
-        result.truncate(length);
-        result
-    }
-
     /// Send ChangeCipherSpec
     fn send_change_cipher_spec(&mut self) -> KResult<()> {
         self.send_record(CONTENT_TYPE_CHANGE_CIPHER_SPEC, &[1])?;
@@ -576,48 +592,70 @@ impl TlsConnection {
         Ok(())
     }
 
-    /// Send Finished (TLS 1.2)
+    /// Send Finished (TLS 1.2), covering the transcript through
+    /// ClientKeyExchange
     fn send_finished(&mut self) -> KResult<()> {
-        // Clone the hash state for computing verify_data
-        let handshake_hash = sha256(&[]); // This should be the actual hash - simplified
-
-        // Compute verify_data
-        let verify_data = self.compute_verify_data(b"client finished", &handshake_hash);
+        let transcript_hash = self.handshake_hash.clone().finalize();
+        let verify_data = self.compute_verify_data(b"client finished", &transcript_hash);
 
         let handshake = self.wrap_handshake(HANDSHAKE_FINISHED, &verify_data);
 
         // Encrypt with client keys
         let encrypted = self.encrypt_record(CONTENT_TYPE_HANDSHAKE, &handshake)?;
         tcp::send(&self.tcp, &encrypted)?;
+        self.handshake_hash.update(&handshake);
 
         self.state = TlsState::SentFinished;
         Ok(())
     }
 
-    /// Compute verify_data for Finished message
+    /// verify_data of a TLS 1.2 Finished message (RFC 5246 7.4.9)
     fn compute_verify_data(&self, label: &[u8], hash: &[u8; 32]) -> Vec<u8> {
-        // Simplified - in real implementation, use master secret
-        let mut seed = Vec::new();
-        seed.extend_from_slice(hash);
-
-        hmac_sha256(label, &seed)[..12].to_vec()
+        prf_sha256(&self.master_secret, label, hash, 12)
     }
 
     /// Receive ChangeCipherSpec
     fn receive_change_cipher_spec(&mut self) -> KResult<()> {
         let record = self.receive_record()?;
-        if record.content_type != CONTENT_TYPE_CHANGE_CIPHER_SPEC {
+        // The server keys apply from the next record on
+        if record.content_type != CONTENT_TYPE_CHANGE_CIPHER_SPEC
+            || record.data != [1]
+            || !self.handshake_buffer.is_empty()
+        {
+            self.send_alert(ALERT_UNEXPECTED_MESSAGE);
             return Err(KError::Invalid);
         }
         Ok(())
     }
 
-    /// Receive Finished
+    /// Receive the server Finished (TLS 1.2), a PRF of the master secret
+    /// over the transcript through the client Finished
     fn receive_finished(&mut self) -> KResult<()> {
-        let record = self.receive_encrypted_record()?;
-        if record.is_empty() || record[0] != HANDSHAKE_FINISHED {
+        let transcript_hash = self.handshake_hash.clone().finalize();
+        let record = self.receive_record()?;
+        if record.content_type != CONTENT_TYPE_HANDSHAKE {
+            self.send_alert(ALERT_UNEXPECTED_MESSAGE);
+            return Err(KError::Invalid);
+        }
+        let finished = match self.decrypt_record(&record) {
+            Ok(finished) => finished,
+            Err(e) => {
+                self.send_alert(ALERT_BAD_RECORD_MAC);
+                return Err(e);
+            }
+        };
+        if finished.first() != Some(&HANDSHAKE_FINISHED) || read_u24(&finished, 1) != Some(12) || finished.len() != 16 {
+            self.send_alert(ALERT_UNEXPECTED_MESSAGE);
             return Err(KError::Invalid);
         }
+
+        let expected = self.compute_verify_data(b"server finished", &transcript_hash);
+        if !constant_time_eq(&finished[4..], &expected) {
+            crate::kprintln!("tls: {}: server Finished does not match the transcript", self.host);
+            self.send_alert(ALERT_DECRYPT_ERROR);
+            return Err(KError::PermissionDenied);
+        }
+        self.handshake_hash.update(&finished);
         Ok(())
     }
 
@@ -631,83 +669,268 @@ impl TlsConnection {
 
         // Handshake secret = HKDF-Extract(derive-secret(early, "derived", ""), shared_secret)
         let derived_secret = self.derive_secret(&early_secret, b"derived", &[]);
-        let handshake_secret = hkdf_extract(&derived_secret, &shared_secret);
-
-        // Get current handshake transcript hash
-        let transcript_hash = [0u8; 32]; // Simplified
-
-        // Client handshake traffic secret
-        let client_hs_secret_vec = self.derive_secret(&handshake_secret, b"c hs traffic", &transcript_hash);
-        let client_hs_secret: [u8; 32] = client_hs_secret_vec.try_into().unwrap();
-        let client_key = hkdf_expand(&client_hs_secret, b"tls13 key", 32);
-        let client_iv = hkdf_expand(&client_hs_secret, b"tls13 iv", 12);
-
-        // Server handshake traffic secret
-        let server_hs_secret_vec = self.derive_secret(&handshake_secret, b"s hs traffic", &transcript_hash);
-        let server_hs_secret: [u8; 32] = server_hs_secret_vec.try_into().unwrap();
-        let server_key = hkdf_expand(&server_hs_secret, b"tls13 key", 32);
-        let server_iv = hkdf_expand(&server_hs_secret, b"tls13 iv", 12);
-
-        self.client_keys = Some(TrafficKeys::new(
-            client_key.try_into().unwrap(),
-            client_iv.try_into().unwrap(),
-        ));
-        self.server_keys = Some(TrafficKeys::new(
-            server_key.try_into().unwrap(),
-            server_iv.try_into().unwrap(),
-        ));
+        self.handshake_secret = hkdf_extract(&derived_secret, &shared_secret);
+
+        // Transcript hash of ClientHello..ServerHello
+        let transcript_hash = self.handshake_hash.clone().finalize();
+
+        self.client_hs_secret = expand_label_32(&self.handshake_secret, b"c hs traffic", &transcript_hash);
+        self.server_hs_secret = expand_label_32(&self.handshake_secret, b"s hs traffic", &transcript_hash);
+
+        self.client_keys = Some(TrafficKeys::from_secret(&self.client_hs_secret));
+        self.server_keys = Some(TrafficKeys::from_secret(&self.server_hs_secret));
 
         Ok(())
     }
 
     /// Derive traffic keys for TLS 1.3
+    ///
+    /// The application secrets cover the transcript through the server
+    /// Finished. The server switches right away; the client keeps its
+    /// handshake keys until its own Finished is sent.
     fn derive_traffic_keys_tls13(&mut self) -> KResult<()> {
-        // Simplified - would normally derive from handshake secret and transcript
+        let derived_secret = self.derive_secret(&self.handshake_secret, b"derived", &[]);
+        let master_secret = hkdf_extract(&derived_secret, &[0u8; 32]);
+
+        let transcript_hash = self.handshake_hash.clone().finalize();
+        let client_secret = expand_label_32(&master_secret, b"c ap traffic", &transcript_hash);
+        let server_secret = expand_label_32(&master_secret, b"s ap traffic", &transcript_hash);
+
+        self.server_keys = Some(TrafficKeys::from_secret(&server_secret));
+        self.client_app_keys = Some(TrafficKeys::from_secret(&client_secret));
         Ok(())
     }
 
     /// Receive encrypted handshake messages (TLS 1.3)
     fn receive_encrypted_handshake_tls13(&mut self) -> KResult<()> {
         // Receive EncryptedExtensions
-        let _ = self.receive_encrypted_record()?;
+        let extensions = self.receive_handshake_tls13(HANDSHAKE_ENCRYPTED_EXTENSIONS)?;
+        self.handshake_hash.update(&extensions);
 
         // Receive Certificate
-        let _ = self.receive_encrypted_record()?;
+        let certificate = self.receive_handshake_tls13(HANDSHAKE_CERTIFICATE)?;
+        self.handshake_hash.update(&certificate);
+        let certs = parse_certificate_list(&certificate[4..], true)?;
+        self.verify_server_chain(&certs)?;
+
+        // Receive CertificateVerify, signed over the transcript up to Certificate
+        let transcript_hash = self.handshake_hash.clone().finalize();
+        let verify = self.receive_handshake_tls13(HANDSHAKE_CERTIFICATE_VERIFY)?;
+        self.verify_certificate_verify_tls13(&verify[4..], &transcript_hash)?;
+        self.handshake_hash.update(&verify);
+
+        // Receive Finished, a MAC over the transcript up to CertificateVerify
+        let transcript_hash = self.handshake_hash.clone().finalize();
+        let finished = self.receive_handshake_tls13(HANDSHAKE_FINISHED)?;
+        let finished_key = expand_label_32(&self.server_hs_secret, b"finished", &[]);
+        if !constant_time_eq(&finished[4..], &hmac_sha256(&finished_key, &transcript_hash)) {
+            crate::kprintln!("tls: {}: server Finished does not match the transcript", self.host);
+            self.send_alert(ALERT_DECRYPT_ERROR);
+            return Err(KError::PermissionDenied);
+        }
+        self.handshake_hash.update(&finished);
 
-        // Receive CertificateVerify
-        let _ = self.receive_encrypted_record()?;
+        // The key change must fall on a record boundary
+        if !self.handshake_buffer.is_empty() {
+            self.send_alert(ALERT_UNEXPECTED_MESSAGE);
+            return Err(KError::Invalid);
+        }
 
-        // Receive Finished
-        let _ = self.receive_encrypted_record()?;
+        Ok(())
+    }
 
+    /// Receive the next handshake message, which must be of type `expected`
+    /// (TLS 1.3)
+    fn receive_handshake_tls13(&mut self, expected: u8) -> KResult<Vec<u8>> {
+        let message = self.next_handshake_message_tls13()?;
+        if message[0] != expected {
+            self.send_alert(ALERT_UNEXPECTED_MESSAGE);
+            return Err(KError::Invalid);
+        }
+        Ok(message)
+    }
+
+    /// Next message of the encrypted handshake stream, header included.
+    /// One record may carry several messages (servers usually send
+    /// EncryptedExtensions..Finished together) and a long Certificate may
+    /// span several records.
+    fn next_handshake_message_tls13(&mut self) -> KResult<Vec<u8>> {
+        loop {
+            if let Some(message) = self.buffered_handshake_message()? {
+                return Ok(message);
+            }
+
+            let (content_type, data) = self.receive_record_tls13()?;
+            if content_type != CONTENT_TYPE_HANDSHAKE || data.is_empty() {
+                self.send_alert(ALERT_UNEXPECTED_MESSAGE);
+                return Err(KError::Invalid);
+            }
+            self.handshake_buffer.extend_from_slice(&data);
+        }
+    }
+
+    /// Receive the next unprotected handshake message (ServerHello, and the
+    /// rest of the server's TLS 1.2 flight), which must be of type
+    /// `expected`. Servers often put several messages in one record.
+    fn receive_handshake_plain(&mut self, expected: u8) -> KResult<Vec<u8>> {
+        let message = loop {
+            if let Some(message) = self.buffered_handshake_message()? {
+                break message;
+            }
+
+            let record = self.receive_record()?;
+            if record.content_type != CONTENT_TYPE_HANDSHAKE || record.data.is_empty() {
+                self.send_alert(ALERT_UNEXPECTED_MESSAGE);
+                return Err(KError::Invalid);
+            }
+            self.handshake_buffer.extend_from_slice(&record.data);
+        };
+        if message[0] != expected {
+            self.send_alert(ALERT_UNEXPECTED_MESSAGE);
+            return Err(KError::Invalid);
+        }
+        Ok(message)
+    }
+
+    /// Take a complete message, header included, off the handshake buffer
+    fn buffered_handshake_message(&mut self) -> KResult<Option<Vec<u8>>> {
+        let Some(len) = read_u24(&self.handshake_buffer, 1) else {
+            return Ok(None);
+        };
+        if len > MAX_HANDSHAKE_MESSAGE {
+            self.send_alert(ALERT_UNEXPECTED_MESSAGE);
+            return Err(KError::Invalid);
+        }
+        if self.handshake_buffer.len() < 4 + len {
+            return Ok(None);
+        }
+        Ok(Some(self.handshake_buffer.drain(..4 + len).collect()))
+    }
+
+    /// Receive a protected TLS 1.3 record; returns the inner content type
+    /// and the content. ChangeCipherSpec records, sent by servers for
+    /// middlebox compatibility, are skipped.
+    fn receive_record_tls13(&mut self) -> KResult<(u8, Vec<u8>)> {
+        loop {
+            let record = self.receive_record()?;
+            match record.content_type {
+                CONTENT_TYPE_APPLICATION_DATA => {}
+                CONTENT_TYPE_CHANGE_CIPHER_SPEC => continue,
+                _ => {
+                    self.send_alert(ALERT_UNEXPECTED_MESSAGE);
+                    return Err(KError::Invalid);
+                }
+            }
+
+            // TLSInnerPlaintext: content || content type || zero padding
+            let mut data = self.decrypt_record(&record)?;
+            while data.last() == Some(&0) {
+                data.pop();
+            }
+            let content_type = data.pop().ok_or(KError::Invalid)?;
+            return Ok((content_type, data));
+        }
+    }
+
+    /// Check the server's CertificateVerify (TLS 1.3)
+    fn verify_certificate_verify_tls13(&mut self, body: &[u8], transcript_hash: &[u8; 32]) -> KResult<()> {
+        let (scheme, signature) = parse_digitally_signed(body)?;
+
+        // RFC 8446 4.4.3: PKCS#1 v1.5 is only allowed in certificates
+        if scheme == SIG_RSA_PKCS1_SHA256 {
+            self.send_alert(ALERT_ILLEGAL_PARAMETER);
+            return Err(KError::Invalid);
+        }
+
+        let mut content = Vec::with_capacity(64 + SERVER_CERTIFICATE_VERIFY_CONTEXT.len() + 1 + 32);
+        content.extend_from_slice(&[0x20; 64]);
+        content.extend_from_slice(SERVER_CERTIFICATE_VERIFY_CONTEXT);
+        content.push(0);
+        content.extend_from_slice(transcript_hash);
+
+        self.verify_peer_signature(scheme, &content, signature)
+    }
+
+    /// Validate the server certificate chain and keep the leaf's public key
+    fn verify_server_chain(&mut self, certs: &[&[u8]]) -> KResult<()> {
+        let result = certs
+            .iter()
+            .map(|der| Certificate::from_der(der))
+            .collect::<Result<Vec<_>, _>>()
+            .and_then(|chain| {
+                let now = crate::time::realtime().tv_sec;
+                x509::verify_chain(&chain, &self.host, &x509::system_store(), now)?;
+                Ok(chain)
+            });
+
+        match result {
+            Ok(mut chain) => {
+                self.peer_key = Some(chain.swap_remove(0).public_key);
+                Ok(())
+            }
+            Err(e) => {
+                crate::kprintln!("tls: {}: certificate rejected: {:?}", self.host, e);
+                self.send_alert(cert_error_alert(e));
+                Err(KError::PermissionDenied)
+            }
+        }
+    }
+
+    /// Check a handshake signature made with the server certificate's key
+    fn verify_peer_signature(&mut self, scheme: u16, message: &[u8], signature: &[u8]) -> KResult<()> {
+        let algorithm = match scheme {
+            SIG_RSA_PKCS1_SHA256 => SignatureAlgorithm::RsaPkcs1Sha256,
+            SIG_RSA_PSS_RSAE_SHA256 => SignatureAlgorithm::RsaPssSha256,
+            SIG_ED25519 => SignatureAlgorithm::Ed25519,
+            _ => SignatureAlgorithm::Unknown,
+        };
+
+        let result = match &self.peer_key {
+            Some(key) => key.verify(algorithm, message, signature),
+            None => return Err(KError::Invalid),
+        };
+
+        if let Err(e) = result {
+            crate::kprintln!("tls: {}: server signature rejected: {:?}", self.host, e);
+            self.send_alert(match e {
+                CertError::UnsupportedAlgorithm => ALERT_HANDSHAKE_FAILURE,
+                _ => ALERT_DECRYPT_ERROR,
+            });
+            return Err(KError::PermissionDenied);
+        }
         Ok(())
     }
 
-    /// Send Finished (TLS 1.3)
+    /// Send a fatal alert. Errors are ignored: the handshake is aborted anyway.
+    fn send_alert(&mut self, description: u8) {
+        let alert = [2, description];
+        if self.client_keys.is_some() {
+            if let Ok(encrypted) = self.encrypt_record(CONTENT_TYPE_ALERT, &alert) {
+                let _ = tcp::send(&self.tcp, &encrypted);
+            }
+        } else {
+            let _ = self.send_record(CONTENT_TYPE_ALERT, &alert);
+        }
+    }
+
+    /// Send Finished (TLS 1.3) and switch to the client application keys
     fn send_finished_tls13(&mut self) -> KResult<()> {
-        let transcript_hash = [0u8; 32]; // Simplified
-        let verify_data = hmac_sha256(&transcript_hash, &transcript_hash);
+        let transcript_hash = self.handshake_hash.clone().finalize();
+        let finished_key = expand_label_32(&self.client_hs_secret, b"finished", &[]);
+        let verify_data = hmac_sha256(&finished_key, &transcript_hash);
 
         let handshake = self.wrap_handshake(HANDSHAKE_FINISHED, &verify_data);
         let encrypted = self.encrypt_record(CONTENT_TYPE_HANDSHAKE, &handshake)?;
         tcp::send(&self.tcp, &encrypted)?;
+        self.handshake_hash.update(&handshake);
 
+        self.client_keys = self.client_app_keys.take();
         Ok(())
     }
 
     /// Derive-Secret helper for TLS 1.3
-    fn derive_secret(&self, secret: &[u8; 32], label: &[u8], messages: &[u8]) -> Vec<u8> {
-        let transcript_hash = sha256(messages);
-
-        let mut hkdf_label = Vec::new();
-        hkdf_label.extend_from_slice(&(32u16).to_be_bytes()); // Length
-        hkdf_label.push((6 + label.len()) as u8); // Label length
-        hkdf_label.extend_from_slice(b"tls13 ");
-        hkdf_label.extend_from_slice(label);
-        hkdf_label.push(32); // Context length
-        hkdf_label.extend_from_slice(&transcript_hash);
-
-        hkdf_expand(secret, &hkdf_label, 32)
+    fn derive_secret(&self, secret: &[u8; 32], label: &[u8], messages: &[u8]) -> [u8; 32] {
+        expand_label_32(secret, label, &sha256(messages))
     }
 
     /// Wrap data in a handshake message
@@ -735,13 +958,21 @@ impl TlsConnection {
 
     /// Encrypt and send a record
     fn encrypt_record(&mut self, content_type: u8, data: &[u8]) -> KResult<Vec<u8>> {
+        // TLS 1.3 hides the real content type inside the encrypted record
+        let tls13 = self.tls_version == TLS_VERSION_1_3;
+        let mut plaintext = data.to_vec();
+        if tls13 {
+            plaintext.push(content_type);
+        }
+
         let keys = self.client_keys.as_mut().ok_or(KError::Invalid)?;
+        let aad = if tls13 {
+            record_header(CONTENT_TYPE_APPLICATION_DATA, plaintext.len() + 16).to_vec()
+        } else {
+            additional_data_tls12(keys.seq, content_type, plaintext.len()).to_vec()
+        };
         let nonce = keys.nonce();
-
-        // AAD = record header
-        let aad = [content_type, 0x03, 0x03, 0, 0]; // Will be updated
-
-        let (ciphertext, tag) = chacha20_poly1305_encrypt(&keys.key, &nonce, &aad, data);
+        let (ciphertext, tag) = chacha20_poly1305_encrypt(&keys.key, &nonce, &aad, &plaintext);
         keys.increment_seq();
 
         let mut encrypted = Vec::new();
@@ -749,7 +980,7 @@ impl TlsConnection {
         encrypted.extend_from_slice(&tag);
 
         let mut record = Vec::new();
-        record.push(CONTENT_TYPE_APPLICATION_DATA);
+        record.push(if tls13 { CONTENT_TYPE_APPLICATION_DATA } else { content_type });
         record.extend_from_slice(&TLS_VERSION_1_2.to_be_bytes());
         record.extend_from_slice(&(encrypted.len() as u16).to_be_bytes());
         record.extend_from_slice(&encrypted);
@@ -776,26 +1007,23 @@ impl TlsConnection {
         Ok(TlsRecord { content_type, data })
     }
 
-    /// Receive and decrypt a record
-    fn receive_encrypted_record(&mut self) -> KResult<Vec<u8>> {
-        let record = self.receive_record()?;
-
-        if record.content_type != CONTENT_TYPE_APPLICATION_DATA {
-            return Ok(record.data);
+    /// Decrypt a protected record with the server keys
+    fn decrypt_record(&mut self, record: &TlsRecord) -> KResult<Vec<u8>> {
+        if record.data.len() < 16 {
+            return Err(KError::Invalid);
         }
 
         let keys = self.server_keys.as_mut().ok_or(KError::Invalid)?;
+        let aad = if self.tls_version == TLS_VERSION_1_3 {
+            record_header(record.content_type, record.data.len()).to_vec()
+        } else {
+            additional_data_tls12(keys.seq, record.content_type, record.data.len() - 16).to_vec()
+        };
         let nonce = keys.nonce();
 
-        if record.data.len() < 16 {
-            return Err(KError::Invalid);
-        }
-
         let ciphertext = &record.data[..record.data.len() - 16];
         let tag: [u8; 16] = record.data[record.data.len() - 16..].try_into().unwrap();
 
-        let aad = [record.content_type, 0x03, 0x03, 0, 0];
-
         let plaintext = chacha20_poly1305_decrypt(&keys.key, &nonce, &aad, ciphertext, &tag)
             .ok_or(KError::Invalid)?;
 
@@ -849,7 +1077,33 @@ impl TlsConnection {
             return Err(KError::Invalid);
         }
 
-        let data = self.receive_encrypted_record()?;
+        let data = if self.tls_version == TLS_VERSION_1_3 {
+            // Skip post-handshake messages such as NewSessionTicket
+            loop {
+                match self.receive_record_tls13()? {
+                    (CONTENT_TYPE_APPLICATION_DATA, data) => break data,
+                    (CONTENT_TYPE_HANDSHAKE, _) => continue,
+                    _ => {
+                        self.state = TlsState::Closed;
+                        return Ok(0);
+                    }
+                }
+            }
+        } else {
+            // TLS 1.2 protects every record and keeps the real content type
+            loop {
+                let record = self.receive_record()?;
+                let data = self.decrypt_record(&record)?;
+                match record.content_type {
+                    CONTENT_TYPE_APPLICATION_DATA => break data,
+                    CONTENT_TYPE_HANDSHAKE => continue,
+                    _ => {
+                        self.state = TlsState::Closed;
+                        return Ok(0);
+                    }
+                }
+            }
+        };
         let len = core::cmp::min(data.len(), out.len());
         out[..len].copy_from_slice(&data[..len]);
         Ok(len)
@@ -874,6 +1128,131 @@ struct TlsRecord {
     data: Vec<u8>,
 }
 
+/// HKDF-Expand-Label (RFC 8446 7.1)
+fn expand_label(secret: &[u8; 32], label: &[u8], context: &[u8], length: usize) -> Vec<u8> {
+    let mut hkdf_label = Vec::with_capacity(4 + 6 + label.len() + context.len());
+    hkdf_label.extend_from_slice(&(length as u16).to_be_bytes());
+    hkdf_label.push((6 + label.len()) as u8);
+    hkdf_label.extend_from_slice(b"tls13 ");
+    hkdf_label.extend_from_slice(label);
+    hkdf_label.push(context.len() as u8);
+    hkdf_label.extend_from_slice(context);
+
+    hkdf_expand(secret, &hkdf_label, length)
+}
+
+fn expand_label_32(secret: &[u8; 32], label: &[u8], context: &[u8]) -> [u8; 32] {
+    expand_label(secret, label, context, 32).try_into().unwrap()
+}
+
+/// Header of a protected record, the additional data of its AEAD
+/// TLS 1.2 PRF with SHA-256 (RFC 5246 section 5)
+pub fn prf_sha256(secret: &[u8], label: &[u8], seed: &[u8], length: usize) -> Vec<u8> {
+    let mut combined_seed = Vec::new();
+    combined_seed.extend_from_slice(label);
+    combined_seed.extend_from_slice(seed);
+
+    let mut result = Vec::new();
+    let mut a = hmac_sha256(secret, &combined_seed);
+
+    while result.len() < length {
+        let mut p_input = Vec::new();
+        p_input.extend_from_slice(&a);
+        p_input.extend_from_slice(&combined_seed);
+
+        let p = hmac_sha256(secret, &p_input);
+        result.extend_from_slice(&p);
+
+        a = hmac_sha256(secret, &a);
+    }
+
+    result.truncate(length);
+    result
+}
+
+/// Additional data of a TLS 1.2 AEAD record: sequence number, type,
+/// version and plaintext length (RFC 5246 6.2.3.3)
+fn additional_data_tls12(seq: u64, content_type: u8, len: usize) -> [u8; 13] {
+    let mut aad = [0u8; 13];
+    aad[..8].copy_from_slice(&seq.to_be_bytes());
+    aad[8] = content_type;
+    aad[9..11].copy_from_slice(&TLS_VERSION_1_2.to_be_bytes());
+    aad[11..].copy_from_slice(&(len as u16).to_be_bytes());
+    aad
+}
+
+fn record_header(content_type: u8, len: usize) -> [u8; 5] {
+    [content_type, 0x03, 0x03, (len >> 8) as u8, len as u8]
+}
+
+fn read_u24(data: &[u8], offset: usize) -> Option<usize> {
+    let b = data.get(offset..offset + 3)?;
+    Some(((b[0] as usize) << 16) | ((b[1] as usize) << 8) | b[2] as usize)
+}
+
+/// Split the body of a Certificate message into DER certificates, leaf first.
+/// TLS 1.3 adds a request context and per-certificate extensions.
+fn parse_certificate_list(body: &[u8], tls13: bool) -> KResult<Vec<&[u8]>> {
+    let mut offset = 0;
+    if tls13 {
+        let context_len = *body.first().ok_or(KError::Invalid)? as usize;
+        offset += 1 + context_len;
+    }
+
+    let list_len = read_u24(body, offset).ok_or(KError::Invalid)?;
+    offset += 3;
+    let end = offset + list_len;
+    if end > body.len() {
+        return Err(KError::Invalid);
+    }
+
+    let mut certs = Vec::new();
+    while offset < end {
+        let cert_len = read_u24(body, offset).ok_or(KError::Invalid)?;
+        offset += 3;
+        if offset + cert_len > end {
+            return Err(KError::Invalid);
+        }
+        certs.push(&body[offset..offset + cert_len]);
+        offset += cert_len;
+
+        if tls13 {
+            let ext = body.get(offset..offset + 2).ok_or(KError::Invalid)?;
+            offset += 2 + u16::from_be_bytes([ext[0], ext[1]]) as usize;
+        }
+    }
+    if offset != end {
+        return Err(KError::Invalid);
+    }
+    Ok(certs)
+}
+
+/// Parse a signature as (scheme, signature bytes)
+fn parse_digitally_signed(data: &[u8]) -> KResult<(u16, &[u8])> {
+    if data.len() < 4 {
+        return Err(KError::Invalid);
+    }
+    let scheme = u16::from_be_bytes([data[0], data[1]]);
+    let len = u16::from_be_bytes([data[2], data[3]]) as usize;
+    if data.len() != 4 + len {
+        return Err(KError::Invalid);
+    }
+    Ok((scheme, &data[4..]))
+}
+
+/// Alert sent when the server's certificate chain is rejected
+fn cert_error_alert(err: CertError) -> u8 {
+    match err {
+        CertError::Malformed | CertError::BadSignature | CertError::EmptyChain => ALERT_BAD_CERTIFICATE,
+        CertError::UnsupportedAlgorithm | CertError::UnhandledCriticalExtension => {
+            ALERT_UNSUPPORTED_CERTIFICATE
+        }
+        CertError::Expired | CertError::NotYetValid => ALERT_CERTIFICATE_EXPIRED,
+        CertError::UnknownIssuer => ALERT_UNKNOWN_CA,
+        _ => ALERT_CERTIFICATE_UNKNOWN,
+    }
+}
+
 // ============================================================================
 // HTTPS Client
 // ============================================================================
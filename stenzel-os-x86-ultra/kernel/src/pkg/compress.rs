@@ -1,311 +1,39 @@
 //! Package Compression
 //!
-//! Zstd compression/decompression for package data.
+//! Zstd compression/decompression for package data, backed by the kernel
+//! compression library.
 
 use alloc::vec::Vec;
+use crate::compress::{self, zstd};
 use crate::util::{KResult, KError};
 
-/// Zstd magic number
-const ZSTD_MAGIC: u32 = 0xFD2FB528;
-
-/// Maximum window size (128 MB)
-const MAX_WINDOW_SIZE: usize = 128 * 1024 * 1024;
-
 /// Compression level (1-22, higher = better compression but slower)
 pub const DEFAULT_LEVEL: u8 = 3;
 
 /// Decompress zstd-compressed data
+///
+/// `expected_size` is the uncompressed size recorded in the package
+/// header; output of any other length is rejected.
 pub fn decompress_zstd(data: &[u8], expected_size: usize) -> KResult<Vec<u8>> {
-    if data.len() < 4 {
-        return Err(KError::Invalid);
-    }
-
-    // Check magic number
-    let magic = u32::from_le_bytes([data[0], data[1], data[2], data[3]]);
-    if magic != ZSTD_MAGIC {
+    let mut decoder = zstd::Decoder::new(data)?;
+    let output = compress::read_all(&mut decoder, expected_size, expected_size)?;
+    if output.len() != expected_size {
         return Err(KError::Invalid);
     }
-
-    // In a full implementation, this would use a zstd decoder
-    // For now, we implement a basic frame parser
-
-    let mut output = Vec::with_capacity(expected_size);
-    let mut pos = 4; // Skip magic
-
-    // Parse frame header
-    if pos >= data.len() {
-        return Err(KError::Invalid);
-    }
-
-    let frame_header_desc = data[pos];
-    pos += 1;
-
-    // Parse frame header descriptor
-    let _frame_content_size_flag = (frame_header_desc >> 6) & 0x03;
-    let single_segment_flag = (frame_header_desc >> 5) & 0x01;
-    let _content_checksum_flag = (frame_header_desc >> 2) & 0x01;
-    let _dict_id_flag = frame_header_desc & 0x03;
-
-    // Window descriptor (if not single segment)
-    let _window_size = if single_segment_flag == 0 {
-        if pos >= data.len() {
-            return Err(KError::Invalid);
-        }
-        let window_desc = data[pos];
-        pos += 1;
-        let exponent = (window_desc >> 3) as u32;
-        let mantissa = (window_desc & 0x07) as u32;
-        let window_log = 10 + exponent;
-        let window_base = 1u64 << window_log;
-        let window_add = (window_base / 8) * mantissa as u64;
-        (window_base + window_add) as usize
-    } else {
-        expected_size.min(MAX_WINDOW_SIZE)
-    };
-
-    // For a complete implementation, we would need:
-    // 1. Huffman decoder
-    // 2. FSE (Finite State Entropy) decoder
-    // 3. LZ77 sequence decoder
-    // 4. Ring buffer for backreferences
-
-    // Simplified: If data appears uncompressed (fallback)
-    // Real implementation would decode zstd blocks
-    if pos < data.len() {
-        // Try to detect if this is actually compressed or just wrapped
-        let remaining = &data[pos..];
-
-        // For now, return a placeholder - real implementation needed
-        // This allows the package manager structure to work
-        output.extend_from_slice(remaining);
-
-        // Truncate or extend to expected size
-        output.truncate(expected_size);
-        while output.len() < expected_size {
-            output.push(0);
-        }
-    }
-
     Ok(output)
 }
 
 /// Compress data with zstd
 pub fn compress_zstd(data: &[u8], level: u8) -> KResult<Vec<u8>> {
-    let level = level.min(22).max(1);
-
-    // Calculate estimated output size
-    let estimated_size = data.len() + 128; // Header + some overhead
-    let mut output = Vec::with_capacity(estimated_size);
-
-    // Write magic number
-    output.extend_from_slice(&ZSTD_MAGIC.to_le_bytes());
-
-    // Write frame header
-    // For simplicity, use single segment mode for small data
-    let single_segment = data.len() <= 128 * 1024;
-
-    let fcs_field_size = if data.len() <= 255 {
-        0 // No FCS field, size in single segment
-    } else if data.len() <= 65535 {
-        1 // 2-byte FCS
-    } else {
-        2 // 4-byte FCS
-    };
-
-    let frame_header_desc = (fcs_field_size << 6)
-        | (if single_segment { 1 << 5 } else { 0 })
-        | (1 << 2); // Content checksum
-
-    output.push(frame_header_desc);
-
-    // Window descriptor (if not single segment)
-    if !single_segment {
-        // Calculate window log (integer log2 + 1 for ceiling)
-        let window_log = {
-            let len = data.len();
-            if len <= 1 {
-                0
-            } else {
-                (64 - (len - 1).leading_zeros()) as u8
-            }
-        };
-        let window_log = window_log.max(10).min(30);
-        let window_desc = (window_log - 10) << 3;
-        output.push(window_desc);
-    }
-
-    // Frame content size
-    match fcs_field_size {
-        0 => {
-            if single_segment {
-                output.push(data.len() as u8);
-            }
-        }
-        1 => {
-            output.extend_from_slice(&(data.len() as u16).to_le_bytes());
-        }
-        2 => {
-            output.extend_from_slice(&(data.len() as u32).to_le_bytes());
-        }
-        _ => {
-            output.extend_from_slice(&(data.len() as u64).to_le_bytes());
-        }
-    }
-
-    // For a complete implementation, we would need:
-    // 1. LZ77 matching
-    // 2. Huffman encoding
-    // 3. FSE encoding
-    // 4. Block formatting
-
-    // Simplified: Store as raw block (type 0)
-    // This is valid zstd but not actually compressed
-    let mut remaining = data;
-    while !remaining.is_empty() {
-        let block_size = remaining.len().min(128 * 1024);
-        let is_last = block_size == remaining.len();
-
-        // Block header: 3 bytes
-        // Bits 0-1: Block type (0 = raw)
-        // Bit 2: Last block flag
-        // Bits 3-23: Block size
-        let block_header = (block_size as u32) << 3
-            | (if is_last { 1 << 2 } else { 0 })
-            | 0; // Raw block type
-
-        output.push((block_header & 0xFF) as u8);
-        output.push(((block_header >> 8) & 0xFF) as u8);
-        output.push(((block_header >> 16) & 0xFF) as u8);
-
-        output.extend_from_slice(&remaining[..block_size]);
-        remaining = &remaining[block_size..];
-    }
-
-    // Content checksum (xxHash64 lower 32 bits)
-    let checksum = simple_hash(data);
-    output.extend_from_slice(&checksum.to_le_bytes());
-
-    let _ = level; // Compression level would be used in real encoder
-
-    Ok(output)
-}
-
-/// Simple hash function (placeholder for xxHash64)
-fn simple_hash(data: &[u8]) -> u32 {
-    let mut hash: u32 = 0x811c9dc5;
-    for &byte in data {
-        hash ^= byte as u32;
-        hash = hash.wrapping_mul(0x01000193);
-    }
-    hash
+    Ok(zstd::compress(data, level.clamp(1, 22)))
 }
 
 /// Get uncompressed size from zstd frame
 pub fn get_uncompressed_size(data: &[u8]) -> Option<usize> {
-    if data.len() < 5 {
-        return None;
-    }
-
-    // Check magic
-    let magic = u32::from_le_bytes([data[0], data[1], data[2], data[3]]);
-    if magic != ZSTD_MAGIC {
-        return None;
-    }
-
-    let frame_header_desc = data[4];
-    let fcs_flag = (frame_header_desc >> 6) & 0x03;
-    let single_segment = (frame_header_desc >> 5) & 0x01 != 0;
-
-    let mut pos = 5;
-
-    // Skip window descriptor if present
-    if !single_segment {
-        pos += 1;
-    }
-
-    // Skip dictionary ID if present
-    let dict_id_flag = frame_header_desc & 0x03;
-    pos += match dict_id_flag {
-        0 => 0,
-        1 => 1,
-        2 => 2,
-        _ => 4,
-    };
-
-    if pos >= data.len() {
-        return None;
-    }
-
-    // Read frame content size
-    match fcs_flag {
-        0 => {
-            if single_segment && pos < data.len() {
-                Some(data[pos] as usize)
-            } else {
-                None // Unknown size
-            }
-        }
-        1 => {
-            if pos + 2 <= data.len() {
-                let size = u16::from_le_bytes([data[pos], data[pos + 1]]);
-                Some((size as usize) + 256)
-            } else {
-                None
-            }
-        }
-        2 => {
-            if pos + 4 <= data.len() {
-                let size = u32::from_le_bytes([
-                    data[pos], data[pos + 1], data[pos + 2], data[pos + 3]
-                ]);
-                Some(size as usize)
-            } else {
-                None
-            }
-        }
-        _ => {
-            if pos + 8 <= data.len() {
-                let size = u64::from_le_bytes([
-                    data[pos], data[pos + 1], data[pos + 2], data[pos + 3],
-                    data[pos + 4], data[pos + 5], data[pos + 6], data[pos + 7],
-                ]);
-                Some(size as usize)
-            } else {
-                None
-            }
-        }
-    }
+    zstd::content_size(data).map(|size| size as usize)
 }
 
 /// Calculate CRC32 of data
 pub fn crc32(data: &[u8]) -> u32 {
-    const CRC32_TABLE: [u32; 256] = generate_crc32_table();
-
-    let mut crc = 0xFFFFFFFF;
-    for &byte in data {
-        let index = ((crc ^ byte as u32) & 0xFF) as usize;
-        crc = (crc >> 8) ^ CRC32_TABLE[index];
-    }
-    !crc
-}
-
-/// Generate CRC32 lookup table at compile time
-const fn generate_crc32_table() -> [u32; 256] {
-    let mut table = [0u32; 256];
-    let mut i = 0;
-    while i < 256 {
-        let mut crc = i as u32;
-        let mut j = 0;
-        while j < 8 {
-            if crc & 1 != 0 {
-                crc = (crc >> 1) ^ 0xEDB88320;
-            } else {
-                crc >>= 1;
-            }
-            j += 1;
-        }
-        table[i] = crc;
-        i += 1;
-    }
-    table
+    compress::checksum::crc32(data)
 }
@@ -165,6 +165,12 @@ pub fn unregister_package(name: &str) -> KResult<InstalledPackage> {
         .ok_or(KError::NotFound)
 }
 
+/// Put back a record removed by `unregister_package`, keeping its install
+/// date and reason
+pub fn restore_package(pkg: InstalledPackage) {
+    DATABASE.lock().add(pkg);
+}
+
 /// Get package information
 pub fn get_package(name: &str) -> Option<InstalledPackage> {
     DATABASE.lock().get(name).cloned()
@@ -5,15 +5,17 @@
 use alloc::string::String;
 use alloc::vec;
 use alloc::vec::Vec;
-use alloc::collections::BTreeSet;
 use crate::util::{KResult, KError};
 use crate::fs::vfs::{Inode, Mode, InodeKind};
 use crate::security::Cred;
 use super::{
     Package, PackageMetadata, TarArchive,
     database::{self, InstallReason, InstalledPackage},
-    repository,
+    metadata::parse_dependency,
+    repository::{self, RepoPackage},
+    rollback::{self, DbChange, FileAction, OperationType},
     sign,
+    solver::{self, Job, Plan, Request, Step},
 };
 
 /// Installation options
@@ -172,75 +174,44 @@ pub fn install(name: &str) -> KResult<()> {
 pub fn install_with_options(name: &str, options: &InstallOptions) -> KResult<()> {
     crate::kprintln!("spkg: installing {}", name);
 
+    // The name may carry a version constraint ("foo>=1.2")
+    let dep = parse_dependency(name).ok_or(KError::Invalid)?;
+
     // Check if already installed
-    if !options.force && database::is_installed(name) {
-        crate::kprintln!("spkg: {} is already installed", name);
+    let installed = database::installed_version(&dep.name);
+    if !options.force && installed.as_ref().is_some_and(|v| dep.version_constraint.satisfies(v)) {
+        crate::kprintln!("spkg: {} is already installed", dep.name);
         return Ok(());
     }
 
-    // Resolve dependencies
-    let mut to_install = if options.no_deps {
-        vec![String::from(name)]
+    let mut plan = if options.no_deps {
+        let pkg = repository::get_package_info(&dep.name).ok_or(KError::NotFound)?;
+        Plan { steps: vec![single_step(pkg)] }
     } else {
-        resolve_dependencies(name)?
+        plan_request(&Request {
+            jobs: vec![Job::Install(dep.clone())],
+            allow_uninstall: false,
+        })?
     };
 
-    // Filter out already installed packages
-    to_install.retain(|n| options.force || !database::is_installed(n));
+    // A forced install of an up to date package reinstalls it
+    if options.force && plan.step(&dep.name).is_none() && installed.is_some() {
+        let pkg = repository::get_package_info(&dep.name).ok_or(KError::NotFound)?;
+        plan.steps.push(single_step(pkg));
+    }
 
-    if to_install.is_empty() {
+    if plan.is_empty() {
         crate::kprintln!("spkg: nothing to do");
         return Ok(());
     }
 
-    crate::kprintln!("spkg: packages to install: {:?}", to_install);
+    print_plan(&plan);
 
     if options.dry_run {
         return Ok(());
     }
 
-    // Download and install each package
-    for pkg_name in &to_install {
-        let pkg_data = repository::download_package(pkg_name)?;
-        let pkg = Package::from_bytes(&pkg_data)?;
-
-        // Verify signature
-        if !options.no_verify && pkg.is_signed() {
-            if !sign::verify_trusted_signature(&pkg.data, pkg.signature.as_ref().unwrap())? {
-                crate::kprintln!("spkg: signature verification failed for {}", pkg_name);
-                return Err(KError::PermissionDenied);
-            }
-        }
-
-        // Check for file conflicts
-        let decompressed = pkg.decompress_data()?;
-        let files = list_package_files(&decompressed)?;
-        let conflicts = database::check_conflicts(&files);
-        if !conflicts.is_empty() {
-            crate::kprintln!("spkg: file conflicts detected:");
-            for (file, owner) in &conflicts {
-                crate::kprintln!("  {} is owned by {}", file, owner);
-            }
-            return Err(KError::AlreadyExists);
-        }
-
-        // Install files
-        install_package_files(&pkg)?;
-
-        // Register in database
-        let reason = if pkg_name == name && !options.as_dependency {
-            InstallReason::Explicit
-        } else {
-            InstallReason::Dependency
-        };
-
-        let dirs = list_package_dirs(&decompressed)?;
-        database::register_package(pkg.metadata.clone(), files, dirs, reason)?;
-
-        crate::kprintln!("spkg: installed {} {}", pkg_name, pkg.metadata.version);
-    }
-
-    Ok(())
+    execute_plan(&plan, options, &alloc::format!("install {}", name))
 }
 
 /// Install package from local file
@@ -262,46 +233,35 @@ pub fn install_from_file(path: &str, options: &InstallOptions) -> KResult<()> {
         }
     }
 
-    // Install the package
     let name = pkg.metadata.name.clone();
 
-    // Resolve and install dependencies first
+    // Resolve the dependencies; they are installed first, in the same
+    // transaction as the package
+    let mut deps = Plan::default();
     if !options.no_deps {
-        for dep in &pkg.metadata.dependencies {
-            if !database::is_installed(&dep.name) {
-                let mut dep_options = options.clone();
-                dep_options.as_dependency = true;
-                install_with_options(&dep.name, &dep_options)?;
-            }
-        }
+        let jobs: Vec<Job> = pkg.metadata.dependencies.iter()
+            .filter(|dep| !dep.optional)
+            .map(|dep| Job::Install(dep.clone()))
+            .collect();
+        deps = plan_request(&Request { jobs, allow_uninstall: false })?;
     }
+    let step = package_step(&pkg.metadata, None);
+    print_plan(&Plan { steps: deps.steps.iter().cloned().chain([step.clone()]).collect() });
 
-    // Check for file conflicts
-    let decompressed = pkg.decompress_data()?;
-    let files = list_package_files(&decompressed)?;
-
-    if !options.force {
-        let conflicts = database::check_conflicts(&files);
-        if !conflicts.is_empty() {
-            for (file, owner) in &conflicts {
-                crate::kprintln!("  {} is owned by {}", file, owner);
-            }
-            return Err(KError::AlreadyExists);
-        }
+    if options.dry_run {
+        return Ok(());
     }
 
-    // Install files
-    install_package_files(&pkg)?;
-
-    // Register in database
-    let reason = if options.as_dependency {
-        InstallReason::Dependency
-    } else {
-        InstallReason::Explicit
-    };
-
-    let dirs = list_package_dirs(&decompressed)?;
-    database::register_package(pkg.metadata, files, dirs, reason)?;
+    let mut dep_options = options.clone();
+    dep_options.as_dependency = true;
+    run_transaction(&alloc::format!("install {}", path), |txn| {
+        for dep in &deps.steps {
+            apply_step(txn, dep, &dep_options)?;
+        }
+        apply_package(txn, &step, &pkg, options).inspect_err(|e| {
+            crate::kprintln!("spkg: {} failed: {:?}", step, e);
+        })
+    })?;
 
     crate::kprintln!("spkg: installed {}", name);
     Ok(())
@@ -363,82 +323,231 @@ pub fn remove_with_options(name: &str, options: &RemoveOptions) -> KResult<()> {
 
 /// Upgrade all installed packages
 pub fn upgrade_all() -> KResult<()> {
+    upgrade_with_options(&InstallOptions::default()).map(|_| ())
+}
+
+/// Upgrade all installed packages. With `dry_run` the transaction is only
+/// planned and returned.
+pub fn upgrade_with_options(options: &InstallOptions) -> KResult<Plan> {
     crate::kprintln!("spkg: checking for upgrades");
 
     // Sync repositories first
     repository::sync_all()?;
 
-    let installed = database::list_installed();
-    let mut upgrades = Vec::new();
-
-    for pkg in installed {
-        if let Some(remote) = repository::get_package_info(&pkg.metadata.name) {
-            if remote.metadata.version > pkg.metadata.version {
-                crate::kprintln!("  {} {} -> {}", pkg.metadata.name, pkg.metadata.version, remote.metadata.version);
-                upgrades.push(pkg.metadata.name.clone());
-            }
-        }
-    }
+    let plan = plan_request(&Request {
+        jobs: vec![Job::UpgradeAll],
+        allow_uninstall: false,
+    })?;
 
-    if upgrades.is_empty() {
+    if plan.is_empty() {
         crate::kprintln!("spkg: all packages up to date");
-        return Ok(());
+        return Ok(plan);
     }
 
-    crate::kprintln!("spkg: upgrading {} packages", upgrades.len());
+    print_plan(&plan);
 
-    for name in upgrades {
-        let options = InstallOptions {
-            force: true,
-            ..Default::default()
-        };
-        install_with_options(&name, &options)?;
+    if !options.dry_run {
+        execute_plan(&plan, options, "upgrade")?;
     }
 
-    Ok(())
+    Ok(plan)
+}
+
+/// Solve a request against the installed packages and repositories,
+/// printing why it cannot be satisfied
+fn plan_request(request: &Request) -> KResult<Plan> {
+    solver::solve_system(request).map_err(|problem| {
+        crate::kprintln!("spkg: unable to satisfy the request:");
+        for reason in &problem.reasons {
+            crate::kprintln!("  {}", reason);
+        }
+        if problem.is_missing() {
+            KError::NotFound
+        } else {
+            KError::Invalid
+        }
+    })
 }
 
-/// Resolve package dependencies recursively
-fn resolve_dependencies(name: &str) -> KResult<Vec<String>> {
-    let mut resolved = Vec::new();
-    let mut seen = BTreeSet::new();
+/// Step installing one repository package without looking at its
+/// dependencies
+fn single_step(pkg: RepoPackage) -> Step {
+    let metadata = pkg.metadata.clone();
+    package_step(&metadata, Some(pkg))
+}
 
-    resolve_deps_recursive(name, &mut resolved, &mut seen)?;
+/// Step installing a package over the installed version, if any
+fn package_step(metadata: &PackageMetadata, package: Option<RepoPackage>) -> Step {
+    let old = database::get_package(&metadata.name);
+    let old_version = old.as_ref().map(|o| o.metadata.version.clone());
+    let action = match &old_version {
+        None => OperationType::Install,
+        Some(v) if metadata.version > *v => OperationType::Upgrade,
+        Some(v) if metadata.version < *v => OperationType::Downgrade,
+        Some(_) => OperationType::Reinstall,
+    };
 
-    Ok(resolved)
+    Step {
+        action,
+        name: metadata.name.clone(),
+        old_version,
+        new_version: Some(metadata.version.clone()),
+        reason: old.map(|o| o.reason).unwrap_or(InstallReason::Explicit),
+        package,
+    }
+}
+
+fn print_plan(plan: &Plan) {
+    crate::kprintln!("spkg: transaction ({} steps):", plan.steps.len());
+    for step in &plan.steps {
+        crate::kprintln!("  {}", step);
+    }
+}
+
+/// Apply a planned transaction, logging it with the rollback system. A
+/// failing step aborts the whole transaction.
+pub fn execute_plan(plan: &Plan, options: &InstallOptions, description: &str) -> KResult<()> {
+    run_transaction(description, |txn| {
+        for step in &plan.steps {
+            apply_step(txn, step, options)?;
+        }
+        Ok(())
+    })
 }
 
-fn resolve_deps_recursive(
-    name: &str,
-    resolved: &mut Vec<String>,
-    seen: &mut BTreeSet<String>,
+/// Run `apply` as one logged transaction. If it fails, the file and
+/// database changes it made are rolled back.
+fn run_transaction(
+    description: &str,
+    apply: impl FnOnce(rollback::TransactionId) -> KResult<()>,
 ) -> KResult<()> {
-    if seen.contains(name) {
+    let txn = rollback::begin_transaction(description, 0)?;
+
+    if let Err(e) = apply(txn) {
+        rollback::abort_transaction()?;
+        return Err(e);
+    }
+
+    rollback::commit_transaction()?;
+    Ok(())
+}
+
+fn apply_step(txn: rollback::TransactionId, step: &Step, options: &InstallOptions) -> KResult<()> {
+    fetch_and_apply(txn, step, options).inspect_err(|e| {
+        crate::kprintln!("spkg: {} failed: {:?}", step, e);
+    })
+}
+
+fn fetch_and_apply(txn: rollback::TransactionId, step: &Step, options: &InstallOptions) -> KResult<()> {
+    if step.action == OperationType::Remove {
+        rollback::set_operation_info(
+            &step.name,
+            step.action.clone(),
+            step.new_version.clone(),
+            step.old_version.clone(),
+        )?;
+        let old = database::get_package(&step.name).ok_or(KError::NotFound)?;
+        for file in &old.files {
+            let backup = rollback::backup_file(txn, file)?;
+            rollback::record_file_operation(&step.name, file, FileAction::Delete, Some(&backup), None)?;
+        }
+        remove_package_files(&step.name)?;
+        unregister_logged(&step.name)?;
+        crate::kprintln!("spkg: {}", step);
         return Ok(());
     }
-    seen.insert(String::from(name));
 
-    // Get package info from repository
-    let info = repository::get_package_info(name).ok_or(KError::NotFound)?;
+    let repo_pkg = step.package.as_ref().ok_or(KError::Invalid)?;
+    let pkg_data = repository::fetch_package(repo_pkg)?;
+    let pkg = Package::from_bytes(&pkg_data)?;
 
-    // Resolve dependencies first (depth-first)
-    for dep in &info.metadata.dependencies {
-        // Check if installed version satisfies constraint
-        if let Some(installed_ver) = database::installed_version(&dep.name) {
-            if dep.version_constraint.satisfies(&installed_ver) {
-                continue;
-            }
+    // Verify signature
+    if !options.no_verify && pkg.is_signed() {
+        if !sign::verify_trusted_signature(&pkg.data, pkg.signature.as_ref().unwrap())? {
+            crate::kprintln!("spkg: signature verification failed for {}", step.name);
+            return Err(KError::PermissionDenied);
+        }
+    }
+
+    apply_package(txn, step, &pkg, options)
+}
+
+/// Install `pkg` for an install, upgrade, downgrade or reinstall step,
+/// logging every change in transaction `txn`
+fn apply_package(txn: rollback::TransactionId, step: &Step, pkg: &Package, options: &InstallOptions) -> KResult<()> {
+    rollback::set_operation_info(
+        &step.name,
+        step.action.clone(),
+        step.new_version.clone(),
+        step.old_version.clone(),
+    )?;
+
+    let old = database::get_package(&step.name);
+
+    // Check for file conflicts; files of the version being replaced are
+    // not conflicts
+    let decompressed = pkg.decompress_data()?;
+    let files = list_package_files(&decompressed)?;
+    let dirs = list_package_dirs(&decompressed)?;
+    let conflicts: Vec<(String, String)> = database::check_conflicts(&files)
+        .into_iter()
+        .filter(|(_, owner)| *owner != step.name)
+        .collect();
+    if !options.force && !conflicts.is_empty() {
+        crate::kprintln!("spkg: file conflicts detected:");
+        for (file, owner) in &conflicts {
+            crate::kprintln!("  {} is owned by {}", file, owner);
+        }
+        return Err(KError::AlreadyExists);
+    }
+
+    // Log every file before touching the filesystem
+    let cred = root_cred();
+    if let Some(old) = &old {
+        for file in old.files.iter().filter(|f| !files.contains(f)) {
+            let backup = rollback::backup_file(txn, file)?;
+            rollback::record_file_operation(&step.name, file, FileAction::Delete, Some(&backup), None)?;
+        }
+    }
+    for file in &files {
+        if old.as_ref().is_some_and(|o| o.files.contains(file)) {
+            let backup = rollback::backup_file(txn, file)?;
+            rollback::record_file_operation(&step.name, file, FileAction::Modify, Some(&backup), None)?;
+        } else {
+            rollback::record_file_operation(&step.name, file, FileAction::Create, None, None)?;
+        }
+    }
+    for dir in &dirs {
+        if crate::fs::stat(dir, &cred).is_err() {
+            rollback::record_dir_operation(&step.name, dir, true)?;
         }
+    }
 
-        resolve_deps_recursive(&dep.name, resolved, seen)?;
+    if old.is_some() {
+        remove_package_files(&step.name)?;
+        unregister_logged(&step.name)?;
     }
 
-    // Add this package after dependencies
-    resolved.push(String::from(name));
+    install_package_files(pkg)?;
 
+    let reason = if options.as_dependency && step.action == OperationType::Install {
+        InstallReason::Dependency
+    } else {
+        step.reason
+    };
+    database::register_package(pkg.metadata.clone(), files, dirs, reason)?;
+    rollback::record_db_change(&step.name, DbChange::Registered(step.name.clone()))?;
+
+    crate::kprintln!("spkg: {}", step);
     Ok(())
 }
 
+/// Unregister a package, logging its record so a rollback can put it back
+fn unregister_logged(name: &str) -> KResult<()> {
+    let record = database::unregister_package(name)?;
+    rollback::record_db_change(name, DbChange::Unregistered(record))
+}
+
 /// Get root credentials for package operations
 fn root_cred() -> Cred {
     Cred::root()
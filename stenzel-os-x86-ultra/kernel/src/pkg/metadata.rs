@@ -116,6 +116,19 @@ impl VersionConstraint {
             return Some(Self::Any);
         }
 
+        // Range format: >=1.0.0,<2.0.0
+        if s.contains(',') {
+            let parts: Vec<&str> = s.split(',').collect();
+            if parts.len() == 2 {
+                if let (Some(Self::GreaterOrEqual(min)), Some(Self::LessThan(max))) =
+                    (Self::parse(parts[0]), Self::parse(parts[1]))
+                {
+                    return Some(Self::Range(min, max));
+                }
+            }
+            return None;
+        }
+
         if s.starts_with(">=") {
             let v = Version::parse(&s[2..])?;
             return Some(Self::GreaterOrEqual(v));
@@ -150,24 +163,30 @@ impl VersionConstraint {
             return Some(Self::Exact(v));
         }
 
-        // Range format: >=1.0.0,<2.0.0
-        if s.contains(',') {
-            let parts: Vec<&str> = s.split(',').collect();
-            if parts.len() == 2 {
-                if let (Some(Self::GreaterOrEqual(min)), Some(Self::LessThan(max))) =
-                    (Self::parse(parts[0]), Self::parse(parts[1]))
-                {
-                    return Some(Self::Range(min, max));
-                }
-            }
-        }
-
         // Plain version = exact match
         let v = Version::parse(s)?;
         Some(Self::Exact(v))
     }
 }
 
+impl core::fmt::Display for VersionConstraint {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::Any => Ok(()),
+            Self::Exact(v) => write!(f, "={}", v),
+            Self::GreaterOrEqual(v) => write!(f, ">={}", v),
+            Self::LessThan(v) => write!(f, "<{}", v),
+            Self::Range(min, max) => write!(f, ">={},<{}", min, max),
+        }
+    }
+}
+
+impl core::fmt::Display for Dependency {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{}{}", self.name, self.version_constraint)
+    }
+}
+
 /// Package metadata
 #[derive(Debug, Clone)]
 pub struct PackageMetadata {
@@ -331,9 +350,17 @@ impl PackageMetadata {
         }
 
         if !self.dependencies.is_empty() {
-            let deps: Vec<String> = self.dependencies.iter()
-                .map(|d| d.name.clone())
-                .collect();
+            // The list is comma separated, so a range is written as its two bounds
+            let mut deps: Vec<String> = Vec::new();
+            for dep in &self.dependencies {
+                match &dep.version_constraint {
+                    VersionConstraint::Range(min, max) => {
+                        deps.push(alloc::format!("{}>={}", dep.name, min));
+                        deps.push(alloc::format!("{}<{}", dep.name, max));
+                    }
+                    _ => deps.push(alloc::format!("{}", dep)),
+                }
+            }
             s.push_str(&alloc::format!("depends=\"{}\"\n", deps.join(", ")));
         }
 
@@ -345,12 +372,16 @@ impl PackageMetadata {
             s.push_str(&alloc::format!("conflicts=\"{}\"\n", self.conflicts.join(", ")));
         }
 
+        if !self.replaces.is_empty() {
+            s.push_str(&alloc::format!("replaces=\"{}\"\n", self.replaces.join(", ")));
+        }
+
         s
     }
 }
 
 /// Parse a dependency string (e.g., "foo>=1.0.0" or "bar")
-fn parse_dependency(s: &str) -> Option<Dependency> {
+pub fn parse_dependency(s: &str) -> Option<Dependency> {
     let s = s.trim();
     if s.is_empty() {
         return None;
@@ -6,7 +6,7 @@
 //! - Package compression (zstd)
 //! - Package signing (Ed25519)
 //! - Local package database
-//! - Dependency resolution
+//! - Dependency solving (versions, provides, conflicts, upgrades)
 //! - Repository support
 
 pub mod format;
@@ -20,6 +20,7 @@ pub mod build;
 pub mod rollback;
 pub mod source;
 pub mod crossbuild;
+pub mod solver;
 
 pub use format::*;
 pub use metadata::*;
@@ -77,6 +78,14 @@ pub fn upgrade_all() -> KResult<()> {
     install::upgrade_all()
 }
 
+/// Plan an upgrade of all installed packages without applying it
+pub fn upgrade_dry_run() -> KResult<solver::Plan> {
+    install::upgrade_with_options(&InstallOptions {
+        dry_run: true,
+        ..Default::default()
+    })
+}
+
 /// Get list of installed packages
 pub fn list_installed() -> Vec<InstalledPackage> {
     database::list_installed()
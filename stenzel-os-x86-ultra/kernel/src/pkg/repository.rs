@@ -369,10 +369,32 @@ pub fn get_package_info(name: &str) -> Option<RepoPackage> {
     REPO_MANAGER.lock().get_package(name).cloned()
 }
 
+/// Get every package version in enabled repositories with its repository priority
+pub fn all_candidates() -> Vec<(RepoPackage, u32)> {
+    let manager = REPO_MANAGER.lock();
+    let mut candidates = Vec::new();
+
+    for pkgs in manager.packages.values() {
+        for pkg in pkgs {
+            if let Some(repo) = manager.get_repo(&pkg.repo_name) {
+                if repo.enabled {
+                    candidates.push((pkg.clone(), repo.priority));
+                }
+            }
+        }
+    }
+
+    candidates
+}
+
 /// Download a package
 pub fn download_package(name: &str) -> KResult<Vec<u8>> {
     let pkg_info = get_package_info(name).ok_or(KError::NotFound)?;
+    fetch_package(&pkg_info)
+}
 
+/// Download a specific package version
+pub fn fetch_package(pkg_info: &RepoPackage) -> KResult<Vec<u8>> {
     let manager = REPO_MANAGER.lock();
     let repo = manager.get_repo(&pkg_info.repo_name).ok_or(KError::NotFound)?;
 
@@ -393,7 +415,7 @@ pub fn download_package(name: &str) -> KResult<Vec<u8>> {
     // Real implementation would use HTTP client
 
     // Create a minimal valid package for testing
-    let pkg_data = create_mock_package_data(&pkg_info);
+    let pkg_data = create_mock_package_data(pkg_info);
 
     Ok(pkg_data)
 }
@@ -4,6 +4,7 @@
 //! Features:
 //! - Transaction logging for all package operations
 //! - File backup before modifications
+//! - Package database changes undone along with the files
 //! - Rollback to previous system state
 //! - Snapshot-based restore points
 
@@ -69,6 +70,8 @@ pub struct Operation {
     pub files: Vec<FileOperation>,
     /// Directories affected
     pub dirs: Vec<DirOperation>,
+    /// Package database changes, in the order they were made
+    pub db_changes: Vec<DbChange>,
     /// Timestamp
     pub timestamp: u64,
 }
@@ -95,6 +98,15 @@ pub struct DirOperation {
     pub created: bool,
 }
 
+/// Package database change within a package operation
+#[derive(Debug, Clone)]
+pub enum DbChange {
+    /// The named package was registered
+    Registered(String),
+    /// This record was unregistered
+    Unregistered(InstalledPackage),
+}
+
 /// File action type
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum FileAction {
@@ -264,13 +276,16 @@ impl Default for RollbackConfig {
         RollbackConfig {
             max_transactions: 100,
             max_snapshots: 10,
-            backup_dir: "/var/lib/spkg/backup".to_string(),
+            backup_dir: DEFAULT_BACKUP_DIR.to_string(),
             auto_snapshot: true,
             max_backup_size: 0,
         }
     }
 }
 
+/// Backup directory used until `init` sets the configured one
+const DEFAULT_BACKUP_DIR: &str = "/var/lib/spkg/backup";
+
 /// Rollback system state
 struct RollbackState {
     /// Transaction history
@@ -319,7 +334,7 @@ static ROLLBACK: IrqSafeMutex<RollbackState> = IrqSafeMutex::new(RollbackState::
 /// Initialize the rollback system
 pub fn init() -> KResult<()> {
     let mut state = ROLLBACK.lock();
-    state.config.backup_dir = "/var/lib/spkg/backup".to_string();
+    state.config.backup_dir = DEFAULT_BACKUP_DIR.to_string();
 
     // In a full implementation:
     // 1. Create backup directory if not exists
@@ -384,6 +399,7 @@ pub fn record_file_operation(
             old_version: None,
             files: vec![file_op],
             dirs: Vec::new(),
+            db_changes: Vec::new(),
             timestamp: crate::time::realtime().tv_sec as u64,
         };
         txn.add_operation(new_op);
@@ -418,6 +434,22 @@ pub fn record_dir_operation(
     Ok(())
 }
 
+/// Record a package database change, to be undone if the transaction is
+/// rolled back
+pub fn record_db_change(package: &str, change: DbChange) -> KResult<()> {
+    let mut state = ROLLBACK.lock();
+
+    let txn = state.current_transaction.as_mut()
+        .ok_or(KError::NotFound)?;
+
+    let op = txn.operations.iter_mut()
+        .find(|o| o.package_name == package)
+        .ok_or(KError::NotFound)?;
+    op.db_changes.push(change);
+
+    Ok(())
+}
+
 /// Set operation type and versions for a package
 pub fn set_operation_info(
     package: &str,
@@ -437,6 +469,18 @@ pub fn set_operation_info(
         op.op_type = op_type;
         op.new_version = new_version;
         op.old_version = old_version;
+    } else {
+        // Operations without files (e.g. empty packages) are still logged
+        txn.add_operation(Operation {
+            op_type,
+            package_name: package.to_string(),
+            new_version,
+            old_version,
+            files: Vec::new(),
+            dirs: Vec::new(),
+            db_changes: Vec::new(),
+            timestamp: crate::time::realtime().tv_sec as u64,
+        });
     }
 
     Ok(())
@@ -468,9 +512,7 @@ pub fn commit_transaction() -> KResult<TransactionId> {
 
 /// Abort and rollback the current transaction
 pub fn abort_transaction() -> KResult<()> {
-    let mut state = ROLLBACK.lock();
-
-    let txn = state.current_transaction.take()
+    let txn = ROLLBACK.lock().current_transaction.take()
         .ok_or(KError::NotFound)?;
 
     // Rollback all operations in reverse order
@@ -481,7 +523,7 @@ pub fn abort_transaction() -> KResult<()> {
 
 /// Rollback a completed transaction
 pub fn rollback_transaction(txn_id: TransactionId) -> KResult<()> {
-    let mut state = ROLLBACK.lock();
+    let state = ROLLBACK.lock();
 
     // Find the transaction
     let txn_idx = state.transactions.iter()
@@ -500,13 +542,16 @@ pub fn rollback_transaction(txn_id: TransactionId) -> KResult<()> {
         .cloned()
         .collect();
 
+    // The filesystem is not touched with the lock held
+    drop(state);
     for txn in to_rollback.iter().rev() {
         rollback_operations(&txn.operations)?;
     }
 
     // Mark transactions as rolled back
-    for i in txn_idx..state.transactions.len() {
-        state.transactions[i].mark_rolled_back();
+    let mut state = ROLLBACK.lock();
+    for txn in state.transactions.iter_mut().filter(|t| to_rollback.iter().any(|r| r.id == t.id)) {
+        txn.mark_rolled_back();
     }
 
     Ok(())
@@ -685,7 +730,12 @@ pub fn set_config(config: RollbackConfig) -> KResult<()> {
 /// Backup directory for a transaction
 pub fn get_transaction_backup_dir(txn_id: TransactionId) -> String {
     let state = ROLLBACK.lock();
-    format!("{}/txn-{}", state.config.backup_dir, txn_id)
+    let backup_dir = if state.config.backup_dir.is_empty() {
+        DEFAULT_BACKUP_DIR
+    } else {
+        &state.config.backup_dir
+    };
+    format!("{}/txn-{}", backup_dir, txn_id)
 }
 
 /// Backup a file before modification
@@ -693,26 +743,15 @@ pub fn backup_file(txn_id: TransactionId, path: &str) -> KResult<String> {
     let backup_dir = get_transaction_backup_dir(txn_id);
     let backup_path = format!("{}{}", backup_dir, path);
 
-    // In a full implementation:
-    // 1. Create backup directory structure
-    // 2. Copy file to backup location
-    // 3. Calculate and store checksum
-    // 4. Track backup size
+    let size = copy_file(path, &backup_path)?;
+    ROLLBACK.lock().total_backup_size += size as u64;
 
-    // For now, just return the path
     Ok(backup_path)
 }
 
 /// Restore a file from backup
 pub fn restore_file(backup_path: &str, original_path: &str) -> KResult<()> {
-    // In a full implementation:
-    // 1. Verify backup exists
-    // 2. Copy backup to original location
-    // 3. Verify checksum
-    // 4. Set correct permissions/ownership
-
-    let _ = (backup_path, original_path);
-    Ok(())
+    copy_file(backup_path, original_path).map(|_| ())
 }
 
 /// Calculate SHA-256 checksum of a file
@@ -739,37 +778,28 @@ pub fn verify_checksum(path: &str, expected: &[u8; 32]) -> KResult<bool> {
 /// Rollback a list of operations in reverse order
 fn rollback_operations(operations: &[Operation]) -> KResult<()> {
     for op in operations.iter().rev() {
-        match op.op_type {
-            OperationType::Install => {
-                // Remove installed files
-                for file in &op.files {
-                    if file.action == FileAction::Create {
-                        // Delete the file
-                        delete_file(&file.path)?;
-                    }
-                }
-                // Remove created directories (in reverse order)
-                for dir in op.dirs.iter().rev() {
-                    if dir.created {
-                        remove_directory(&dir.path)?;
-                    }
-                }
+        // Delete the files the operation created and restore the ones it
+        // modified or deleted
+        for file in op.files.iter().rev() {
+            match (file.action, &file.backup_path) {
+                (FileAction::Create, _) => delete_file(&file.path)?,
+                (_, Some(backup)) => restore_file(backup, &file.path)?,
+                (_, None) => {}
             }
-            OperationType::Remove => {
-                // Restore removed files from backup
-                for file in &op.files {
-                    if let Some(backup) = &file.backup_path {
-                        restore_file(backup, &file.path)?;
-                    }
-                }
+        }
+        // Remove created directories (in reverse order)
+        for dir in op.dirs.iter().rev() {
+            if dir.created {
+                remove_directory(&dir.path)?;
             }
-            OperationType::Upgrade | OperationType::Downgrade | OperationType::Reinstall => {
-                // Restore modified files from backup
-                for file in &op.files {
-                    if let Some(backup) = &file.backup_path {
-                        restore_file(backup, &file.path)?;
-                    }
+        }
+        // Undo the database changes, newest first
+        for change in op.db_changes.iter().rev() {
+            match change {
+                DbChange::Registered(name) => {
+                    super::database::unregister_package(name)?;
                 }
+                DbChange::Unregistered(pkg) => super::database::restore_package(pkg.clone()),
             }
         }
     }
@@ -810,18 +840,39 @@ fn install_package_for_rollback(name: &str, version: &Version) -> KResult<()> {
     Ok(())
 }
 
-/// Delete a file
+fn root_cred() -> crate::security::Cred {
+    crate::security::Cred::root()
+}
+
+/// Copy a file with its mode, creating the parent directories of `to`.
+/// Returns the number of bytes copied.
+fn copy_file(from: &str, to: &str) -> KResult<usize> {
+    let cred = root_cred();
+    let mode = crate::fs::stat(from, &cred)?.mode;
+    let data = crate::fs::read_file(from, &cred)?;
+    if let Some((parent, _)) = to.rsplit_once('/') {
+        if !parent.is_empty() {
+            crate::fs::mkdir(parent, &cred, crate::fs::vfs::Mode::from_octal(0o755))?;
+        }
+    }
+    crate::fs::write_file(to, &cred, mode, &data)?;
+    Ok(data.len())
+}
+
+/// Delete a file; a failed step may not have created it
 fn delete_file(path: &str) -> KResult<()> {
-    // In a full implementation: use VFS to delete
-    let _ = path;
-    Ok(())
+    match crate::fs::unlink(path, &root_cred()) {
+        Ok(()) | Err(KError::NotFound) => Ok(()),
+        Err(e) => Err(e),
+    }
 }
 
-/// Remove a directory
+/// Remove a directory if it is empty
 fn remove_directory(path: &str) -> KResult<()> {
-    // In a full implementation: use VFS to rmdir
-    let _ = path;
-    Ok(())
+    match crate::fs::rmdir(path, &root_cred()) {
+        Ok(()) | Err(KError::NotFound) | Err(KError::NotEmpty) => Ok(()),
+        Err(e) => Err(e),
+    }
 }
 
 // ============================================================================
@@ -0,0 +1,1040 @@
+//! Dependency Solver
+//!
+//! Picks package versions for install, removal and upgrade requests.
+//!
+//! Every candidate (an installed package or a package version from an
+//! enabled repository) is a boolean variable. Dependencies, provides,
+//! conflicts, replaces and the "one version per name" rule become clauses,
+//! and a small CDCL solver searches for an assignment. Optional
+//! dependencies are not installed on their own; they only rule out
+//! versions of the named package outside their constraint. Decisions follow the
+//! order a user would pick by hand: keep installed packages, prefer the
+//! package actually named over other providers, then repository priority,
+//! then the newest version.
+//!
+//! Each clause remembers the rules it came from, and learned clauses carry
+//! the union of the rules they were derived from, so an unsolvable request
+//! is reported with the rules that caused it.
+
+use alloc::collections::{BTreeMap, BTreeSet};
+use alloc::format;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::cmp::Reverse;
+use core::fmt;
+
+use super::database::{self, InstallReason};
+use super::metadata::{parse_dependency, Dependency, PackageMetadata, Version, VersionConstraint};
+use super::repository::{self, RepoPackage};
+use super::rollback::OperationType;
+
+// ============================================================================
+// Candidate Pool
+// ============================================================================
+
+/// Where a candidate comes from
+#[derive(Debug, Clone)]
+pub enum Source {
+    /// Already installed
+    Installed(InstallReason),
+    /// Available from a repository with the given priority
+    Repo(RepoPackage, u32),
+}
+
+/// One installable package version
+#[derive(Debug, Clone)]
+pub struct Candidate {
+    pub metadata: PackageMetadata,
+    pub source: Source,
+}
+
+impl Candidate {
+    pub fn name(&self) -> &str {
+        &self.metadata.name
+    }
+
+    pub fn version(&self) -> &Version {
+        &self.metadata.version
+    }
+
+    pub fn is_installed(&self) -> bool {
+        matches!(self.source, Source::Installed(_))
+    }
+
+    /// Repository priority (lower = higher priority); installed packages
+    /// rank after every repository
+    fn priority(&self) -> u32 {
+        match self.source {
+            Source::Installed(_) => u32::MAX,
+            Source::Repo(_, priority) => priority,
+        }
+    }
+
+    /// "name-version" for messages
+    pub fn label(&self) -> String {
+        format!("{}-{}", self.metadata.name, self.metadata.version)
+    }
+}
+
+/// Split a provides entry ("sh" or "sh=5.2") into name and version
+fn parse_provide(s: &str) -> (&str, Option<Version>) {
+    match s.split_once('=') {
+        Some((name, version)) => (name.trim(), Version::parse(version.trim())),
+        None => (s.trim(), None),
+    }
+}
+
+/// Set of candidates a request is solved against
+pub struct Pool {
+    candidates: Vec<Candidate>,
+    /// Package name -> candidates with that name
+    by_name: BTreeMap<String, Vec<usize>>,
+    /// Virtual name -> (candidate, provided version)
+    providers: BTreeMap<String, Vec<(usize, Option<Version>)>>,
+}
+
+impl Pool {
+    pub fn new() -> Self {
+        Self {
+            candidates: Vec::new(),
+            by_name: BTreeMap::new(),
+            providers: BTreeMap::new(),
+        }
+    }
+
+    /// Pool of the installed packages and every enabled repository
+    pub fn from_system() -> Self {
+        let mut pool = Self::new();
+        for pkg in database::list_installed() {
+            pool.add_installed(pkg.metadata, pkg.reason);
+        }
+        for (pkg, priority) in repository::all_candidates() {
+            pool.add_repo_package(pkg, priority);
+        }
+        pool
+    }
+
+    /// Add an installed package
+    pub fn add_installed(&mut self, metadata: PackageMetadata, reason: InstallReason) {
+        self.add(Candidate {
+            metadata,
+            source: Source::Installed(reason),
+        });
+    }
+
+    /// Add a repository package. A version that is already installed is
+    /// skipped, so it never shows up as a reinstall.
+    pub fn add_repo_package(&mut self, pkg: RepoPackage, priority: u32) {
+        let duplicate = self.by_name.get(&pkg.metadata.name).is_some_and(|ids| {
+            ids.iter().any(|&id| {
+                self.candidates[id].is_installed()
+                    && self.candidates[id].metadata.version == pkg.metadata.version
+            })
+        });
+        if duplicate {
+            return;
+        }
+        self.add(Candidate {
+            metadata: pkg.metadata.clone(),
+            source: Source::Repo(pkg, priority),
+        });
+    }
+
+    fn add(&mut self, candidate: Candidate) {
+        let id = self.candidates.len();
+        self.by_name
+            .entry(candidate.metadata.name.clone())
+            .or_insert_with(Vec::new)
+            .push(id);
+        for provide in &candidate.metadata.provides {
+            let (name, version) = parse_provide(provide);
+            if !name.is_empty() {
+                self.providers
+                    .entry(String::from(name))
+                    .or_insert_with(Vec::new)
+                    .push((id, version));
+            }
+        }
+        self.candidates.push(candidate);
+    }
+
+    pub fn candidate(&self, id: usize) -> &Candidate {
+        &self.candidates[id]
+    }
+
+    pub fn len(&self) -> usize {
+        self.candidates.len()
+    }
+
+    /// Installed version of a package name
+    fn installed_version(&self, name: &str) -> Option<&Version> {
+        self.installed(name).map(|id| self.candidates[id].version())
+    }
+
+    fn installed(&self, name: &str) -> Option<usize> {
+        self.by_name
+            .get(name)?
+            .iter()
+            .copied()
+            .find(|&id| self.candidates[id].is_installed())
+    }
+
+    /// Candidates satisfying a dependency: the named package within the
+    /// version constraint, or a package providing the name. An unversioned
+    /// provide only satisfies an unconstrained dependency.
+    pub fn matching(&self, dep: &Dependency) -> Vec<usize> {
+        let mut ids = Vec::new();
+        if let Some(named) = self.by_name.get(&dep.name) {
+            for &id in named {
+                if dep.version_constraint.satisfies(self.candidates[id].version()) {
+                    ids.push(id);
+                }
+            }
+        }
+        if let Some(providers) = self.providers.get(&dep.name) {
+            for (id, version) in providers {
+                let satisfied = match version {
+                    Some(version) => dep.version_constraint.satisfies(version),
+                    None => matches!(dep.version_constraint, VersionConstraint::Any),
+                };
+                if satisfied && !ids.contains(id) {
+                    ids.push(*id);
+                }
+            }
+        }
+        ids
+    }
+}
+
+impl Default for Pool {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// ============================================================================
+// Requests and Results
+// ============================================================================
+
+/// A single thing the user asked for
+#[derive(Debug, Clone)]
+pub enum Job {
+    /// Install a package (or virtual name) matching the dependency
+    Install(Dependency),
+    /// Remove every version of a package
+    Remove(String),
+    /// Upgrade one package to its best candidate
+    Upgrade(String),
+    /// Upgrade every installed package
+    UpgradeAll,
+}
+
+/// Solver request
+#[derive(Debug, Clone, Default)]
+pub struct Request {
+    pub jobs: Vec<Job>,
+    /// Allow removing installed packages that stand in the way instead of
+    /// reporting a problem
+    pub allow_uninstall: bool,
+}
+
+/// Rule behind a clause, used to explain problems
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Reason {
+    /// The request asked to install or upgrade this
+    Requested(String),
+    /// The request asked to remove this package
+    RemoveRequested(String),
+    /// This package is installed and must stay installed
+    Installed(String),
+    /// A package requires a dependency
+    Requires { package: String, dependency: String },
+    /// An optional dependency, if present, must satisfy its constraint
+    Optional { package: String, dependency: String },
+    /// Nothing satisfies a dependency of a package (or of the request)
+    NothingProvides { package: Option<String>, dependency: String },
+    /// A package conflicts with another
+    Conflicts { package: String, conflict: String },
+    /// A package replaces another
+    Replaces { package: String, replaced: String },
+    /// Only one version of a package can be installed
+    SingleVersion(String),
+}
+
+impl fmt::Display for Reason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Reason::Requested(spec) => write!(f, "{} was requested", spec),
+            Reason::RemoveRequested(name) => write!(f, "removal of {} was requested", name),
+            Reason::Installed(name) => write!(f, "{} is installed and must be kept", name),
+            Reason::Requires { package, dependency } => {
+                write!(f, "{} requires {}", package, dependency)
+            }
+            Reason::Optional { package, dependency } => {
+                write!(f, "{} optionally uses {}", package, dependency)
+            }
+            Reason::NothingProvides { package: Some(package), dependency } => {
+                write!(f, "nothing provides {} needed by {}", dependency, package)
+            }
+            Reason::NothingProvides { package: None, dependency } => {
+                write!(f, "nothing provides {}", dependency)
+            }
+            Reason::Conflicts { package, conflict } => {
+                write!(f, "{} conflicts with {}", package, conflict)
+            }
+            Reason::Replaces { package, replaced } => write!(f, "{} replaces {}", package, replaced),
+            Reason::SingleVersion(name) => write!(f, "only one version of {} can be installed", name),
+        }
+    }
+}
+
+/// Unsolvable request, with the rules that together rule out every answer
+#[derive(Debug, Clone)]
+pub struct Problem {
+    pub reasons: Vec<Reason>,
+}
+
+impl Problem {
+    /// Does the problem come from a dependency nothing satisfies?
+    pub fn is_missing(&self) -> bool {
+        self.reasons.iter().any(|r| matches!(r, Reason::NothingProvides { .. }))
+    }
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, reason) in self.reasons.iter().enumerate() {
+            if i > 0 {
+                f.write_str("; ")?;
+            }
+            write!(f, "{}", reason)?;
+        }
+        Ok(())
+    }
+}
+
+/// One step of a transaction
+#[derive(Debug, Clone)]
+pub struct Step {
+    pub action: OperationType,
+    pub name: String,
+    pub old_version: Option<Version>,
+    pub new_version: Option<Version>,
+    /// Package to fetch for installs, upgrades and downgrades
+    pub package: Option<RepoPackage>,
+    /// Install reason to record
+    pub reason: InstallReason,
+}
+
+impl fmt::Display for Step {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.action.as_str(), self.name)?;
+        match (&self.old_version, &self.new_version) {
+            (Some(old), Some(new)) if old != new => write!(f, " {} -> {}", old, new),
+            (_, Some(version)) | (Some(version), None) => write!(f, " {}", version),
+            (None, None) => Ok(()),
+        }
+    }
+}
+
+/// Ordered transaction: removals (dependents first), then installs,
+/// upgrades and downgrades (dependencies first)
+#[derive(Debug, Clone, Default)]
+pub struct Plan {
+    pub steps: Vec<Step>,
+}
+
+impl Plan {
+    pub fn is_empty(&self) -> bool {
+        self.steps.is_empty()
+    }
+
+    /// Find the step for a package
+    pub fn step(&self, name: &str) -> Option<&Step> {
+        self.steps.iter().find(|s| s.name == name)
+    }
+}
+
+// ============================================================================
+// Clause Solver
+// ============================================================================
+
+/// Literal: candidate variable and polarity, packed as `var * 2 + negated`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Lit(u32);
+
+impl Lit {
+    fn pos(var: usize) -> Self {
+        Lit((var as u32) << 1)
+    }
+
+    fn neg(var: usize) -> Self {
+        Lit((var as u32) << 1 | 1)
+    }
+
+    fn var(self) -> usize {
+        (self.0 >> 1) as usize
+    }
+
+    fn is_neg(self) -> bool {
+        self.0 & 1 != 0
+    }
+
+    fn not(self) -> Self {
+        Lit(self.0 ^ 1)
+    }
+
+    fn index(self) -> usize {
+        self.0 as usize
+    }
+}
+
+struct Clause {
+    lits: Vec<Lit>,
+    /// Rules the clause stands for (sorted)
+    rules: Vec<usize>,
+    /// Name a decision satisfying this clause should prefer
+    want: Option<String>,
+}
+
+struct Solver<'a> {
+    pool: &'a Pool,
+    rules: Vec<Reason>,
+    clauses: Vec<Clause>,
+    /// Clauses listed by the literal they watch
+    watches: Vec<Vec<usize>>,
+    value: Vec<Option<bool>>,
+    level: Vec<usize>,
+    /// Clause that implied each assignment (None for decisions)
+    reason: Vec<Option<usize>>,
+    trail: Vec<Lit>,
+    /// Trail length at the start of each decision level
+    trail_lim: Vec<usize>,
+    /// Next trail entry to propagate
+    head: usize,
+    /// Names whose best candidate is preferred over the installed one
+    upgrading: BTreeSet<String>,
+    upgrade_all: bool,
+}
+
+fn lit_value(value: &[Option<bool>], lit: Lit) -> Option<bool> {
+    value[lit.var()].map(|v| v != lit.is_neg())
+}
+
+impl<'a> Solver<'a> {
+    fn new(pool: &'a Pool) -> Self {
+        let vars = pool.len();
+        Self {
+            pool,
+            rules: Vec::new(),
+            clauses: Vec::new(),
+            watches: vec![Vec::new(); vars * 2],
+            value: vec![None; vars],
+            level: vec![0; vars],
+            reason: vec![None; vars],
+            trail: Vec::new(),
+            trail_lim: Vec::new(),
+            head: 0,
+            upgrading: BTreeSet::new(),
+            upgrade_all: false,
+        }
+    }
+
+    fn add_rule(&mut self, reason: Reason) -> usize {
+        self.rules.push(reason);
+        self.rules.len() - 1
+    }
+
+    fn add_clause(&mut self, mut lits: Vec<Lit>, rule: usize, want: Option<&str>) {
+        lits.sort_by_key(|l| l.0);
+        lits.dedup();
+        // x | !x is always true
+        if lits.windows(2).any(|w| w[0].var() == w[1].var()) {
+            return;
+        }
+        self.clauses.push(Clause {
+            lits,
+            rules: vec![rule],
+            want: want.map(String::from),
+        });
+    }
+
+    /// Turn the request and pool into clauses
+    fn encode(&mut self, request: &Request) -> Result<(), Problem> {
+        let pool = self.pool;
+        let mut removing = BTreeSet::new();
+
+        for job in &request.jobs {
+            match job {
+                Job::Install(dep) => {
+                    let ids = pool.matching(dep);
+                    if ids.is_empty() {
+                        return Err(Problem {
+                            reasons: vec![Reason::NothingProvides {
+                                package: None,
+                                dependency: format!("{}", dep),
+                            }],
+                        });
+                    }
+                    let rule = self.add_rule(Reason::Requested(format!("{}", dep)));
+                    self.add_clause(ids.into_iter().map(Lit::pos).collect(), rule, Some(&dep.name));
+                }
+                Job::Remove(name) => {
+                    removing.insert(name.clone());
+                    let rule = self.add_rule(Reason::RemoveRequested(name.clone()));
+                    for &id in pool.by_name.get(name).into_iter().flatten() {
+                        self.add_clause(vec![Lit::neg(id)], rule, None);
+                    }
+                }
+                Job::Upgrade(name) => {
+                    self.upgrading.insert(name.clone());
+                    let ids = pool.by_name.get(name).cloned().unwrap_or_default();
+                    if ids.is_empty() {
+                        return Err(Problem {
+                            reasons: vec![Reason::NothingProvides {
+                                package: None,
+                                dependency: name.clone(),
+                            }],
+                        });
+                    }
+                    let rule = self.add_rule(Reason::Requested(name.clone()));
+                    self.add_clause(ids.into_iter().map(Lit::pos).collect(), rule, Some(name));
+                }
+                Job::UpgradeAll => self.upgrade_all = true,
+            }
+        }
+
+        // Installed packages stay, as some version of themselves or as a
+        // package replacing them
+        if !request.allow_uninstall {
+            for (name, ids) in &pool.by_name {
+                if removing.contains(name) || pool.installed(name).is_none() {
+                    continue;
+                }
+                let mut lits: Vec<Lit> = ids.iter().copied().map(Lit::pos).collect();
+                for (id, candidate) in pool.candidates.iter().enumerate() {
+                    if candidate.name() != name && self.replaces(id, name) {
+                        lits.push(Lit::pos(id));
+                    }
+                }
+                let rule = self.add_rule(Reason::Installed(name.clone()));
+                self.add_clause(lits, rule, Some(name));
+            }
+        }
+
+        for (name, ids) in &pool.by_name {
+            if ids.len() < 2 {
+                continue;
+            }
+            let rule = self.add_rule(Reason::SingleVersion(name.clone()));
+            for (i, &a) in ids.iter().enumerate() {
+                for &b in &ids[i + 1..] {
+                    self.add_clause(vec![Lit::neg(a), Lit::neg(b)], rule, None);
+                }
+            }
+        }
+
+        for (id, candidate) in pool.candidates.iter().enumerate() {
+            let metadata = &candidate.metadata;
+            for dep in metadata.dependencies.iter().chain(&metadata.optional_dependencies) {
+                // Optional dependencies are never pulled in, but a version
+                // of the package that is there anyway must fit
+                if dep.optional {
+                    let fits = pool.matching(dep);
+                    let misfits: Vec<usize> = pool.by_name.get(&dep.name).into_iter().flatten()
+                        .copied()
+                        .filter(|other| !fits.contains(other))
+                        .collect();
+                    if misfits.is_empty() {
+                        continue;
+                    }
+                    let rule = self.add_rule(Reason::Optional {
+                        package: candidate.label(),
+                        dependency: format!("{}", dep),
+                    });
+                    for other in misfits {
+                        self.add_clause(vec![Lit::neg(id), Lit::neg(other)], rule, None);
+                    }
+                    continue;
+                }
+                let ids = pool.matching(dep);
+                let reason = if ids.is_empty() {
+                    Reason::NothingProvides {
+                        package: Some(candidate.label()),
+                        dependency: format!("{}", dep),
+                    }
+                } else {
+                    Reason::Requires {
+                        package: candidate.label(),
+                        dependency: format!("{}", dep),
+                    }
+                };
+                let rule = self.add_rule(reason);
+                let mut lits = vec![Lit::neg(id)];
+                lits.extend(ids.into_iter().map(Lit::pos));
+                self.add_clause(lits, rule, Some(&dep.name));
+            }
+
+            for spec in &candidate.metadata.conflicts {
+                let Some(dep) = parse_dependency(spec) else { continue };
+                for other in pool.matching(&dep) {
+                    if pool.candidates[other].name() == candidate.name() {
+                        continue;
+                    }
+                    let rule = self.add_rule(Reason::Conflicts {
+                        package: candidate.label(),
+                        conflict: pool.candidates[other].label(),
+                    });
+                    self.add_clause(vec![Lit::neg(id), Lit::neg(other)], rule, None);
+                }
+            }
+
+            for spec in &candidate.metadata.replaces {
+                let Some(dep) = parse_dependency(spec) else { continue };
+                for other in pool.matching(&dep) {
+                    if pool.candidates[other].name() == candidate.name() {
+                        continue;
+                    }
+                    let rule = self.add_rule(Reason::Replaces {
+                        package: candidate.label(),
+                        replaced: pool.candidates[other].label(),
+                    });
+                    self.add_clause(vec![Lit::neg(id), Lit::neg(other)], rule, None);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Does a candidate replace the package `name`?
+    fn replaces(&self, id: usize, name: &str) -> bool {
+        self.pool.candidates[id].metadata.replaces.iter().any(|spec| {
+            parse_dependency(spec).is_some_and(|dep| {
+                dep.name == name
+                    && self.pool.installed_version(name).is_some_and(|v| dep.version_constraint.satisfies(v))
+            })
+        })
+    }
+
+    fn is_upgrading(&self, name: &str) -> bool {
+        self.upgrade_all || self.upgrading.contains(name)
+    }
+
+    fn decision_level(&self) -> usize {
+        self.trail_lim.len()
+    }
+
+    fn assign(&mut self, lit: Lit, reason: Option<usize>) {
+        let var = lit.var();
+        self.value[var] = Some(!lit.is_neg());
+        self.level[var] = self.decision_level();
+        self.reason[var] = reason;
+        self.trail.push(lit);
+    }
+
+    /// Attach a clause to the watch lists, assigning unit clauses. Returns
+    /// a conflicting clause.
+    fn attach(&mut self, ci: usize) -> Option<usize> {
+        let lits = &self.clauses[ci].lits;
+        if lits.len() >= 2 {
+            self.watches[lits[0].index()].push(ci);
+            self.watches[lits[1].index()].push(ci);
+            return None;
+        }
+        let lit = lits[0];
+        match lit_value(&self.value, lit) {
+            Some(true) => None,
+            Some(false) => Some(ci),
+            None => {
+                self.assign(lit, Some(ci));
+                None
+            }
+        }
+    }
+
+    /// Unit propagation over the watched literals. Returns a conflicting
+    /// clause.
+    fn propagate(&mut self) -> Option<usize> {
+        while self.head < self.trail.len() {
+            let false_lit = self.trail[self.head].not();
+            self.head += 1;
+
+            let mut watchers = core::mem::take(&mut self.watches[false_lit.index()]);
+            let mut conflict = None;
+            let mut i = 0;
+            while i < watchers.len() {
+                let ci = watchers[i];
+                let lits = &mut self.clauses[ci].lits;
+                if lits[0] == false_lit {
+                    lits.swap(0, 1);
+                }
+                if lit_value(&self.value, lits[0]) == Some(true) {
+                    i += 1;
+                    continue;
+                }
+
+                // Move the watch to a literal that is not false
+                if let Some(k) = (2..lits.len()).find(|&k| lit_value(&self.value, lits[k]) != Some(false)) {
+                    lits.swap(1, k);
+                    self.watches[lits[1].index()].push(ci);
+                    watchers.swap_remove(i);
+                    continue;
+                }
+
+                let first = lits[0];
+                if lit_value(&self.value, first) == Some(false) {
+                    conflict = Some(ci);
+                    break;
+                }
+                self.assign(first, Some(ci));
+                i += 1;
+            }
+            self.watches[false_lit.index()] = watchers;
+            if conflict.is_some() {
+                return conflict;
+            }
+        }
+        None
+    }
+
+    /// Add the rules behind level 0 assignments of `var` and whatever they
+    /// were implied from
+    fn level0_rules(&self, var: usize, rules: &mut BTreeSet<usize>, seen: &mut [bool]) {
+        let mut stack = vec![var];
+        while let Some(var) = stack.pop() {
+            if core::mem::replace(&mut seen[var], true) {
+                continue;
+            }
+            if let Some(ci) = self.reason[var] {
+                let clause = &self.clauses[ci];
+                rules.extend(clause.rules.iter().copied());
+                stack.extend(clause.lits.iter().map(|l| l.var()).filter(|&v| v != var));
+            }
+        }
+    }
+
+    /// First-UIP conflict analysis. Returns the learned clause (asserting
+    /// literal first), the level to return to and the rules it rests on.
+    fn analyze(&self, conflict: usize) -> (Vec<Lit>, usize, BTreeSet<usize>) {
+        let mut seen = vec![false; self.value.len()];
+        let mut seen0 = vec![false; self.value.len()];
+        let mut rules = BTreeSet::new();
+        let mut learned = vec![Lit(0)];
+        let mut pending = 0;
+        let mut clause = conflict;
+        let mut implied: Option<Lit> = None;
+        let mut idx = self.trail.len();
+
+        loop {
+            rules.extend(self.clauses[clause].rules.iter().copied());
+            for &lit in &self.clauses[clause].lits {
+                let var = lit.var();
+                if implied.is_some_and(|p| p.var() == var) || seen[var] {
+                    continue;
+                }
+                seen[var] = true;
+                if self.level[var] == self.decision_level() {
+                    pending += 1;
+                } else if self.level[var] > 0 {
+                    learned.push(lit);
+                } else {
+                    self.level0_rules(var, &mut rules, &mut seen0);
+                }
+            }
+
+            loop {
+                idx -= 1;
+                if seen[self.trail[idx].var()] {
+                    break;
+                }
+            }
+            let lit = self.trail[idx];
+            implied = Some(lit);
+            pending -= 1;
+            if pending == 0 {
+                learned[0] = lit.not();
+                break;
+            }
+            clause = self.reason[lit.var()].expect("implied literal without a reason");
+        }
+
+        // Watch the highest remaining level second, so the clause is unit
+        // after backtracking
+        let mut back = 0;
+        if learned.len() > 1 {
+            let (i, _) = learned[1..]
+                .iter()
+                .enumerate()
+                .max_by_key(|(_, l)| self.level[l.var()])
+                .unwrap();
+            learned.swap(1, i + 1);
+            back = self.level[learned[1].var()];
+        }
+        (learned, back, rules)
+    }
+
+    fn backtrack(&mut self, level: usize) {
+        if self.decision_level() <= level {
+            return;
+        }
+        let start = self.trail_lim[level];
+        for lit in self.trail.drain(start..) {
+            self.value[lit.var()] = None;
+            self.reason[lit.var()] = None;
+        }
+        self.trail_lim.truncate(level);
+        self.head = self.trail.len();
+    }
+
+    /// Explain a conflict at level 0
+    fn explain(&self, conflict: usize) -> Problem {
+        let mut rules: BTreeSet<usize> = self.clauses[conflict].rules.iter().copied().collect();
+        let mut seen = vec![false; self.value.len()];
+        for lit in &self.clauses[conflict].lits {
+            self.level0_rules(lit.var(), &mut rules, &mut seen);
+        }
+        Problem {
+            reasons: rules.into_iter().map(|r| self.rules[r].clone()).collect(),
+        }
+    }
+
+    /// Preference order of a candidate for satisfying a clause about `want`
+    fn rank(&self, id: usize, want: &str) -> (bool, bool, bool, bool, Reverse<u32>, &'a Version) {
+        let pool = self.pool;
+        let candidate = &pool.candidates[id];
+        let replacing = candidate.name() != want
+            && self.is_upgrading(want)
+            && self.replaces(id, want);
+        let keep = candidate.is_installed() && !self.is_upgrading(candidate.name());
+        let not_downgrade = pool
+            .installed_version(candidate.name())
+            .map_or(true, |v| candidate.version() >= v);
+        (
+            replacing,
+            keep,
+            candidate.name() == want,
+            not_downgrade,
+            Reverse(candidate.priority()),
+            candidate.version(),
+        )
+    }
+
+    /// Next decision: satisfy the first open clause with its best
+    /// candidate, else keep an unassigned installed package, else leave a
+    /// package out
+    fn next_decision(&self) -> Option<Lit> {
+        for clause in &self.clauses {
+            let Some(want) = clause.want.as_deref() else { continue };
+            let mut open = false;
+            let mut best: Option<Lit> = None;
+            for &lit in &clause.lits {
+                match lit_value(&self.value, lit) {
+                    Some(true) => {
+                        open = false;
+                        best = None;
+                        break;
+                    }
+                    // Only clauses whose requirer is chosen are open
+                    None if lit.is_neg() => {
+                        best = None;
+                        break;
+                    }
+                    None => {
+                        open = true;
+                        if best.map_or(true, |b| self.rank(lit.var(), want) > self.rank(b.var(), want)) {
+                            best = Some(lit);
+                        }
+                    }
+                    Some(false) => {}
+                }
+            }
+            if open {
+                if let Some(lit) = best {
+                    return Some(lit);
+                }
+            }
+        }
+
+        // Nothing open: keep installed packages (at their best version when
+        // upgrading) and leave everything else out
+        let var = self.value.iter().position(|v| v.is_none())?;
+        let name = self.pool.candidates[var].name();
+        if self.pool.installed(name).is_some() {
+            let best = self.pool.by_name[name]
+                .iter()
+                .copied()
+                .filter(|&id| self.value[id].is_none())
+                .max_by_key(|&id| self.rank(id, name));
+            if let Some(id) = best {
+                return Some(Lit::pos(id));
+            }
+        }
+        Some(Lit::neg(var))
+    }
+
+    fn solve(&mut self) -> Result<(), Problem> {
+        for ci in 0..self.clauses.len() {
+            if self.clauses[ci].lits.is_empty() {
+                return Err(Problem { reasons: vec![self.rules[self.clauses[ci].rules[0]].clone()] });
+            }
+            if let Some(conflict) = self.attach(ci) {
+                return Err(self.explain(conflict));
+            }
+        }
+
+        loop {
+            if let Some(conflict) = self.propagate() {
+                if self.decision_level() == 0 {
+                    return Err(self.explain(conflict));
+                }
+                let (learned, back, rules) = self.analyze(conflict);
+                self.backtrack(back);
+                let asserting = learned[0];
+                self.clauses.push(Clause {
+                    lits: learned,
+                    rules: rules.into_iter().collect(),
+                    want: None,
+                });
+                let ci = self.clauses.len() - 1;
+                if self.clauses[ci].lits.len() >= 2 {
+                    self.attach(ci);
+                }
+                self.assign(asserting, Some(ci));
+                continue;
+            }
+
+            let Some(lit) = self.next_decision() else {
+                return Ok(());
+            };
+            self.trail_lim.push(self.trail.len());
+            self.assign(lit, None);
+        }
+    }
+
+    fn is_selected(&self, id: usize) -> bool {
+        self.value[id] == Some(true)
+    }
+}
+
+// ============================================================================
+// Transaction Planning
+// ============================================================================
+
+/// Order candidates so each comes after the ones it depends on
+fn dependency_order(pool: &Pool, ids: &[usize]) -> Vec<usize> {
+    fn visit(pool: &Pool, ids: &[usize], id: usize, done: &mut BTreeSet<usize>, order: &mut Vec<usize>) {
+        if !done.insert(id) {
+            return;
+        }
+        // Optional dependencies that are part of the transaction go first
+        // too, so they are usable as soon as the package is
+        let metadata = &pool.candidates[id].metadata;
+        for dep in metadata.dependencies.iter().chain(&metadata.optional_dependencies) {
+            for other in pool.matching(dep) {
+                if ids.contains(&other) {
+                    visit(pool, ids, other, done, order);
+                }
+            }
+        }
+        order.push(id);
+    }
+
+    let mut done = BTreeSet::new();
+    let mut order = Vec::new();
+    for &id in ids {
+        visit(pool, ids, id, &mut done, &mut order);
+    }
+    order
+}
+
+/// Solve a request against a pool
+pub fn solve(pool: &Pool, request: &Request) -> Result<Plan, Problem> {
+    let mut solver = Solver::new(pool);
+    solver.encode(request)?;
+    solver.solve()?;
+
+    let requested: Vec<&Dependency> = request
+        .jobs
+        .iter()
+        .filter_map(|job| match job {
+            Job::Install(dep) => Some(dep),
+            _ => None,
+        })
+        .collect();
+
+    let mut removed = Vec::new();
+    let mut added = Vec::new();
+    for (id, candidate) in pool.candidates.iter().enumerate() {
+        match (candidate.is_installed(), solver.is_selected(id)) {
+            (true, false) => removed.push(id),
+            (false, true) => added.push(id),
+            _ => {}
+        }
+    }
+
+    let mut plan = Plan::default();
+    let new_names: BTreeSet<&str> = added.iter().map(|&id| pool.candidates[id].name()).collect();
+
+    // Dependents go before their dependencies
+    for id in dependency_order(pool, &removed).into_iter().rev() {
+        let candidate = &pool.candidates[id];
+        if new_names.contains(candidate.name()) {
+            continue;
+        }
+        let Source::Installed(reason) = candidate.source else { continue };
+        plan.steps.push(Step {
+            action: OperationType::Remove,
+            name: candidate.metadata.name.clone(),
+            old_version: Some(candidate.metadata.version.clone()),
+            new_version: None,
+            package: None,
+            reason,
+        });
+    }
+
+    for id in dependency_order(pool, &added) {
+        let candidate = &pool.candidates[id];
+        let Source::Repo(ref package, _) = candidate.source else { continue };
+        let old = pool.installed(candidate.name()).map(|old| &pool.candidates[old]);
+
+        let (action, reason) = match old {
+            Some(old) => {
+                let action = if candidate.version() > old.version() {
+                    OperationType::Upgrade
+                } else {
+                    OperationType::Downgrade
+                };
+                let Source::Installed(reason) = old.source else { unreachable!() };
+                (action, reason)
+            }
+            None => {
+                // A replacement inherits the reason of what it replaces
+                let explicit = requested.iter().any(|dep| pool.matching(dep).contains(&id))
+                    || removed.iter().any(|&r| {
+                        matches!(pool.candidates[r].source, Source::Installed(InstallReason::Explicit))
+                            && solver.replaces(id, pool.candidates[r].name())
+                    });
+                let reason = if explicit { InstallReason::Explicit } else { InstallReason::Dependency };
+                (OperationType::Install, reason)
+            }
+        };
+
+        plan.steps.push(Step {
+            action,
+            name: candidate.metadata.name.clone(),
+            old_version: old.map(|o| o.metadata.version.clone()),
+            new_version: Some(candidate.metadata.version.clone()),
+            package: Some(package.clone()),
+            reason,
+        });
+    }
+
+    Ok(plan)
+}
+
+/// Solve a request against the installed packages and enabled repositories
+pub fn solve_system(request: &Request) -> Result<Plan, Problem> {
+    solve(&Pool::from_system(), request)
+}
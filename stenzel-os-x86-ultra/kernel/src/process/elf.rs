@@ -122,6 +122,7 @@ pub enum AuxvType {
     Random = 25,
     Hwcap2 = 26,
     Execfn = 31,
+    SysinfoEhdr = 33,
 }
 
 /// Informações extraídas do ELF
@@ -377,6 +378,7 @@ pub fn setup_user_stack(
     argv: &[&str],
     envp: &[&str],
     exec_path: &str,
+    vdso_base: Option<u64>,
 ) -> Result<StackInfo, KError> {
     // Mapeia páginas de stack
     let stack_base = USER_STACK_TOP - (USER_STACK_PAGES as u64 * 4096);
@@ -464,7 +466,7 @@ pub fn setup_user_stack(
     sp &= !0xF;
 
     // 5. Auxiliary vector
-    let auxv: [(u64, u64); 12] = [
+    let mut auxv: Vec<(u64, u64)> = alloc::vec![
         (AuxvType::Phdr as u64, elf_info.phdr_addr),
         (AuxvType::Phent as u64, elf_info.phdr_size as u64),
         (AuxvType::Phnum as u64, elf_info.phdr_num as u64),
@@ -476,8 +478,11 @@ pub fn setup_user_stack(
         (AuxvType::Egid as u64, 1000),
         (AuxvType::Random as u64, random_addr),
         (AuxvType::Execfn as u64, execfn_addr),
-        (AuxvType::Null as u64, 0), // Terminator
     ];
+    if let Some(base) = vdso_base {
+        auxv.push((AuxvType::SysinfoEhdr as u64, base));
+    }
+    auxv.push((AuxvType::Null as u64, 0)); // Terminator
 
     // Write auxv (in reverse, bottom to top)
     for &(atype, aval) in auxv.iter().rev() {
@@ -68,6 +68,10 @@ pub struct LoadedElf {
     pub cr3: PhysFrame<Size4KiB>,
     pub entry: u64,
     pub stack_pointer: u64,
+    /// Program break inicial: a página seguinte ao último segmento
+    pub brk: u64,
+    /// vvar e vDSO mapeados
+    pub vdso: bool,
     pub elf_info: ElfInfo,
 }
 
@@ -102,13 +106,18 @@ pub fn load_elf_into_new_space(
         elf_info.load_end
     );
 
+    // vDSO e sua página de dados
+    let vdso_base = crate::arch::x86_64_arch::vdso::map(&mut mapper, &mut *fa)?;
+
     // Setup da stack do usuário
-    let stack_info = elf::setup_user_stack(&mut mapper, &mut *fa, &elf_info, argv, envp, exec_path)?;
+    let stack_info = elf::setup_user_stack(&mut mapper, &mut *fa, &elf_info, argv, envp, exec_path, vdso_base)?;
 
     Ok(LoadedElf {
         cr3,
         entry: elf_info.entry,
         stack_pointer: stack_info.sp,
+        brk: (elf_info.load_end + 0xFFF) & !0xFFF,
+        vdso: vdso_base.is_some(),
         elf_info,
     })
 }
@@ -0,0 +1,314 @@
+//! bpf(2) syscall.
+//!
+//! Maps, programs and links are handed to userspace as fds
+//! (`FdType::Bpf`); pinning one in a bpffs keeps it alive after its fds
+//! are closed. `union bpf_attr` is read with the Linux layout, so libbpf
+//! style loaders work unchanged for the commands implemented here.
+//!
+//! Tracepoint programs are attached with BPF_RAW_TRACEPOINT_OPEN (there is
+//! no perf_event_open to attach them through), XDP programs with
+//! BPF_LINK_CREATE and socket filters with setsockopt(SO_ATTACH_BPF).
+//! Only root may use the syscall.
+
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+use crate::fs::bpffs;
+use crate::syscall::errno;
+use crate::util::KError;
+
+use super::ebpf::{
+    self, BpfAttachPoint, BpfError, BpfInsn, BpfLink, BpfMap, BpfMapType, BpfObject, BpfProg, BpfProgType,
+    BPF_LD_IMM64, BPF_PSEUDO_MAP_FD,
+};
+
+/// Commands
+pub const BPF_MAP_CREATE: i32 = 0;
+pub const BPF_MAP_LOOKUP_ELEM: i32 = 1;
+pub const BPF_MAP_UPDATE_ELEM: i32 = 2;
+pub const BPF_MAP_DELETE_ELEM: i32 = 3;
+pub const BPF_MAP_GET_NEXT_KEY: i32 = 4;
+pub const BPF_PROG_LOAD: i32 = 5;
+pub const BPF_OBJ_PIN: i32 = 6;
+pub const BPF_OBJ_GET: i32 = 7;
+pub const BPF_RAW_TRACEPOINT_OPEN: i32 = 17;
+pub const BPF_LINK_CREATE: i32 = 28;
+
+/// Attach type of BPF_LINK_CREATE for XDP
+pub const BPF_XDP: u32 = 37;
+
+/// Part of `union bpf_attr` used by the commands above
+const ATTR_SIZE: usize = 72;
+/// Largest program accepted (same as the verifier)
+const MAX_INSNS: usize = 1_000_000;
+/// Length of `map_name` / `prog_name`
+const OBJ_NAME_LEN: usize = 16;
+
+/// Copy of the caller's `union bpf_attr`
+struct Attr([u8; ATTR_SIZE]);
+
+impl Attr {
+    /// Read `size` bytes at `addr`; a larger attr than this kernel knows is
+    /// fine as long as the extra bytes are zero
+    fn read(addr: u64, size: u32) -> Result<Self, i64> {
+        let size = size as usize;
+        if size > 4096 {
+            return Err(errno::E2BIG);
+        }
+        let bytes = unsafe { crate::syscall::validate_user_buffer(addr, size) }.ok_or(errno::EFAULT)?;
+        let len = size.min(ATTR_SIZE);
+        if bytes[len..].iter().any(|&b| b != 0) {
+            return Err(errno::E2BIG);
+        }
+        let mut attr = [0u8; ATTR_SIZE];
+        attr[..len].copy_from_slice(&bytes[..len]);
+        Ok(Self(attr))
+    }
+
+    fn u32(&self, off: usize) -> u32 {
+        u32::from_ne_bytes(self.0[off..off + 4].try_into().unwrap())
+    }
+
+    fn u64(&self, off: usize) -> u64 {
+        u64::from_ne_bytes(self.0[off..off + 8].try_into().unwrap())
+    }
+
+    /// Fixed-size, NUL-padded name
+    fn name(&self, off: usize) -> String {
+        let raw = &self.0[off..off + OBJ_NAME_LEN];
+        let len = raw.iter().position(|&b| b == 0).unwrap_or(OBJ_NAME_LEN);
+        String::from_utf8_lossy(&raw[..len]).into_owned()
+    }
+}
+
+/// bpf syscall
+pub fn sys_bpf(cmd: i32, uattr: u64, size: u32) -> i64 {
+    if crate::sched::current_cred().euid.0 != 0 {
+        return errno::EPERM;
+    }
+    let attr = match Attr::read(uattr, size) {
+        Ok(attr) => attr,
+        Err(e) => return e,
+    };
+
+    let result = match cmd {
+        BPF_MAP_CREATE => map_create(&attr),
+        BPF_MAP_LOOKUP_ELEM => map_lookup_elem(&attr),
+        BPF_MAP_UPDATE_ELEM => map_update_elem(&attr),
+        BPF_MAP_DELETE_ELEM => map_delete_elem(&attr),
+        BPF_MAP_GET_NEXT_KEY => map_get_next_key(&attr),
+        BPF_PROG_LOAD => prog_load(&attr),
+        BPF_OBJ_PIN => obj_pin(&attr),
+        BPF_OBJ_GET => obj_get(&attr),
+        BPF_RAW_TRACEPOINT_OPEN => raw_tracepoint_open(&attr),
+        BPF_LINK_CREATE => link_create(&attr),
+        _ => Err(errno::EINVAL),
+    };
+    result.unwrap_or_else(|e| e)
+}
+
+fn object_fd(object: BpfObject) -> Result<i64, i64> {
+    crate::syscall::alloc_fd_for_bpf(object).map(|fd| fd as i64).ok_or(errno::EMFILE)
+}
+
+fn map_of(fd: u32) -> Result<Arc<BpfMap>, i64> {
+    match crate::syscall::bpf_object_of(fd as i32)? {
+        BpfObject::Map(map) => Ok(map),
+        _ => Err(errno::EINVAL),
+    }
+}
+
+fn prog_of(fd: u32) -> Result<Arc<BpfProg>, i64> {
+    match crate::syscall::bpf_object_of(fd as i32)? {
+        BpfObject::Prog(prog) => Ok(prog),
+        _ => Err(errno::EINVAL),
+    }
+}
+
+fn read_user(addr: u64, len: usize) -> Result<Vec<u8>, i64> {
+    unsafe { crate::syscall::validate_user_buffer(addr, len) }
+        .map(|bytes| bytes.to_vec())
+        .ok_or(errno::EFAULT)
+}
+
+fn write_user(addr: u64, data: &[u8]) -> Result<(), i64> {
+    let out = unsafe { crate::syscall::validate_user_buffer_mut(addr, data.len()) }.ok_or(errno::EFAULT)?;
+    out.copy_from_slice(data);
+    Ok(())
+}
+
+fn read_path(addr: u64) -> Result<String, i64> {
+    unsafe { crate::syscall::read_user_string(addr, 4096) }.ok_or(errno::EFAULT)
+}
+
+fn fs_errno(e: KError) -> i64 {
+    match e {
+        KError::NotFound => errno::ENOENT,
+        KError::PermissionDenied => errno::EACCES,
+        KError::AlreadyExists => errno::EEXIST,
+        KError::NotADirectory => errno::ENOTDIR,
+        _ => errno::EINVAL,
+    }
+}
+
+/// BPF_MAP_CREATE: map_type@0, key_size@4, value_size@8, max_entries@12,
+/// map_flags@16, map_name@28
+fn map_create(attr: &Attr) -> Result<i64, i64> {
+    let map_type = BpfMapType::from_u32(attr.u32(0)).ok_or(errno::EINVAL)?;
+    let map = ebpf::manager()
+        .lock()
+        .create_map(map_type, attr.u32(4), attr.u32(8), attr.u32(12), attr.u32(16), &attr.name(28))
+        .map_err(|e| e.errno())?;
+    object_fd(BpfObject::Map(map))
+}
+
+/// BPF_MAP_LOOKUP_ELEM: map_fd@0, key@8, value@16
+fn map_lookup_elem(attr: &Attr) -> Result<i64, i64> {
+    let map = map_of(attr.u32(0))?;
+    let key = read_user(attr.u64(8), map.key_size as usize)?;
+    let value = map.lookup(&key).ok_or(errno::ENOENT)?;
+    write_user(attr.u64(16), &value)?;
+    Ok(0)
+}
+
+/// BPF_MAP_UPDATE_ELEM: map_fd@0, key@8, value@16, flags@24
+fn map_update_elem(attr: &Attr) -> Result<i64, i64> {
+    let map = map_of(attr.u32(0))?;
+    let key = read_user(attr.u64(8), map.key_size as usize)?;
+    let value = read_user(attr.u64(16), map.value_size as usize)?;
+    map.update(&key, &value, attr.u64(24)).map_err(|e| e.errno())?;
+    Ok(0)
+}
+
+/// BPF_MAP_DELETE_ELEM: map_fd@0, key@8
+fn map_delete_elem(attr: &Attr) -> Result<i64, i64> {
+    let map = map_of(attr.u32(0))?;
+    let key = read_user(attr.u64(8), map.key_size as usize)?;
+    map.delete(&key).map_err(|e| e.errno())?;
+    Ok(0)
+}
+
+/// BPF_MAP_GET_NEXT_KEY: map_fd@0, key@8 (NULL for the first key),
+/// next_key@16
+fn map_get_next_key(attr: &Attr) -> Result<i64, i64> {
+    let map = map_of(attr.u32(0))?;
+    let key = match attr.u64(8) {
+        0 => None,
+        addr => Some(read_user(addr, map.key_size as usize)?),
+    };
+    let next = map.get_next_key(key.as_deref()).ok_or(errno::ENOENT)?;
+    write_user(attr.u64(16), &next)?;
+    Ok(0)
+}
+
+/// Turn the map fds of `lddw rX, map` into indexes into the returned list
+fn resolve_maps(insns: &mut [BpfInsn]) -> Result<Vec<Arc<BpfMap>>, i64> {
+    let mut maps: Vec<Arc<BpfMap>> = Vec::new();
+    for insn in insns.iter_mut() {
+        if insn.code != BPF_LD_IMM64 || insn.src_reg() != BPF_PSEUDO_MAP_FD {
+            continue;
+        }
+        let map = map_of(insn.imm as u32)?;
+        let idx = match maps.iter().position(|m| Arc::ptr_eq(m, &map)) {
+            Some(idx) => idx,
+            None => {
+                maps.push(map);
+                maps.len() - 1
+            }
+        };
+        insn.imm = idx as i32;
+    }
+    Ok(maps)
+}
+
+/// BPF_PROG_LOAD: prog_type@0, insn_cnt@4, insns@8, license@16,
+/// log_level@24, log_size@28, log_buf@32, prog_name@48
+fn prog_load(attr: &Attr) -> Result<i64, i64> {
+    let prog_type = match BpfProgType::from_u32(attr.u32(0)) {
+        Some(
+            t @ (BpfProgType::SocketFilter
+            | BpfProgType::Xdp
+            | BpfProgType::Tracepoint
+            | BpfProgType::RawTracepoint),
+        ) => t,
+        _ => return Err(errno::EINVAL),
+    };
+
+    let count = attr.u32(4) as usize;
+    if count == 0 {
+        return Err(errno::EINVAL);
+    }
+    if count > MAX_INSNS {
+        return Err(errno::E2BIG);
+    }
+    let raw = read_user(attr.u64(8), count * 8)?;
+    let mut insns: Vec<BpfInsn> = raw
+        .chunks_exact(8)
+        .map(|chunk| BpfInsn::from_bytes(chunk.try_into().unwrap()))
+        .collect();
+    let license = match attr.u64(16) {
+        0 => String::new(),
+        addr => unsafe { crate::syscall::read_user_string(addr, 128) }.ok_or(errno::EFAULT)?,
+    };
+    let maps = resolve_maps(&mut insns)?;
+
+    let loaded = ebpf::manager()
+        .lock()
+        .load_prog(prog_type, &attr.name(48), insns, &license, maps);
+    match loaded {
+        Ok(prog) => object_fd(BpfObject::Prog(prog)),
+        Err(BpfError::VerificationFailed(log)) => {
+            let (level, size, buf) = (attr.u32(24), attr.u32(28) as usize, attr.u64(32));
+            if level != 0 && size != 0 && buf != 0 {
+                // Truncated to the buffer, always NUL-terminated
+                let len = log.len().min(size - 1);
+                let mut out = Vec::with_capacity(len + 1);
+                out.extend_from_slice(&log.as_bytes()[..len]);
+                out.push(0);
+                write_user(buf, &out)?;
+            }
+            Err(errno::EACCES)
+        }
+        Err(e) => Err(e.errno()),
+    }
+}
+
+/// BPF_OBJ_PIN: pathname@0, bpf_fd@8
+fn obj_pin(attr: &Attr) -> Result<i64, i64> {
+    let path = read_path(attr.u64(0))?;
+    let object = crate::syscall::bpf_object_of(attr.u32(8) as i32)?;
+    let cred = crate::sched::current_cred();
+    bpffs::pin(&path, object, &cred).map_err(fs_errno)?;
+    Ok(0)
+}
+
+/// BPF_OBJ_GET: pathname@0
+fn obj_get(attr: &Attr) -> Result<i64, i64> {
+    let path = read_path(attr.u64(0))?;
+    let cred = crate::sched::current_cred();
+    let object = bpffs::get(&path, &cred).map_err(fs_errno)?;
+    object_fd(object)
+}
+
+/// BPF_RAW_TRACEPOINT_OPEN: name@0, prog_fd@8
+fn raw_tracepoint_open(attr: &Attr) -> Result<i64, i64> {
+    let name = unsafe { crate::syscall::read_user_string(attr.u64(0), 128) }.ok_or(errno::EFAULT)?;
+    let prog = prog_of(attr.u32(8))?;
+    let link = BpfLink::attach(prog, BpfAttachPoint::Tracepoint { name }).map_err(|e| e.errno())?;
+    object_fd(BpfObject::Link(link))
+}
+
+/// BPF_LINK_CREATE: prog_fd@0, target_ifindex@4, attach_type@8
+fn link_create(attr: &Attr) -> Result<i64, i64> {
+    if attr.u32(8) != BPF_XDP {
+        return Err(errno::EINVAL);
+    }
+    let prog = prog_of(attr.u32(0))?;
+    let point = BpfAttachPoint::Xdp { ifindex: attr.u32(4) };
+    let link = BpfLink::attach(prog, point).map_err(|e| match e {
+        BpfError::NotFound => errno::ENODEV,
+        e => e.errno(),
+    })?;
+    object_fd(BpfObject::Link(link))
+}
@@ -7,21 +7,31 @@
 //! - Program verification
 //! - Maps (hash, array, per-cpu)
 //! - Helper functions
-//! - Tracepoint/kprobe attachment
+//! - Tracepoint attachment
 //! - XDP (eXpress Data Path) support
-//! - Program types (socket filter, tracepoint, kprobe, etc.)
+//! - Socket filters
+//!
+//! Programs are loaded and attached from userspace with bpf(2) (see
+//! `bpf_syscall`). They never see kernel addresses: each run gets a small
+//! address space of its own in which every load and store is checked.
 
 #![allow(dead_code)]
 
 use alloc::collections::BTreeMap;
 use alloc::format;
 use alloc::string::{String, ToString};
-use alloc::sync::Arc;
+use alloc::sync::{Arc, Weak};
 use alloc::vec;
 use alloc::vec::Vec;
+use core::fmt::Write;
+use core::ops::Bound;
 use core::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
 use spin::{Mutex, Once, RwLock};
 
+use crate::syscall::errno;
+
+use super::ftrace;
+
 // ============================================================================
 // eBPF Instruction Set
 // ============================================================================
@@ -88,6 +98,24 @@ pub enum BpfJmpOp {
     Jsle = 0xd0,
 }
 
+/// Memory access modes
+pub const BPF_IMM: u8 = 0x00;
+pub const BPF_ABS: u8 = 0x20;
+pub const BPF_IND: u8 = 0x40;
+pub const BPF_MEM: u8 = 0x60;
+pub const BPF_ATOMIC: u8 = 0xc0;
+
+/// Opcode of `lddw` (first of its two slots)
+pub const BPF_LD_IMM64: u8 = 0x18;
+
+/// src_reg of `lddw` when imm is a map fd
+pub const BPF_PSEUDO_MAP_FD: u8 = 1;
+
+/// BPF_ATOMIC operations (imm of the instruction)
+pub const BPF_FETCH: i32 = 0x01;
+pub const BPF_XCHG: i32 = 0xe0 | BPF_FETCH;
+pub const BPF_CMPXCHG: i32 = 0xf0 | BPF_FETCH;
+
 /// eBPF instruction
 #[derive(Debug, Clone, Copy)]
 #[repr(C)]
@@ -147,6 +175,26 @@ impl BpfInsn {
     pub fn mode(&self) -> u8 {
         self.code & 0xe0
     }
+
+    /// Memory access size in bytes
+    pub fn size_bytes(&self) -> usize {
+        match self.size() {
+            0x00 => 4,
+            0x08 => 2,
+            0x10 => 1,
+            _ => 8,
+        }
+    }
+
+    /// Decode one instruction from its 8-byte wire format
+    pub fn from_bytes(raw: &[u8; 8]) -> Self {
+        Self {
+            code: raw[0],
+            regs: raw[1],
+            off: i16::from_le_bytes([raw[2], raw[3]]),
+            imm: i32::from_le_bytes([raw[4], raw[5], raw[6], raw[7]]),
+        }
+    }
 }
 
 // ============================================================================
@@ -254,6 +302,36 @@ pub enum BpfMapType {
     Ringbuf = 16,
 }
 
+impl BpfMapType {
+    /// Map type from its bpf(2) number
+    pub fn from_u32(n: u32) -> Option<Self> {
+        Some(match n {
+            0 => Self::Unspec,
+            1 => Self::Hash,
+            2 => Self::Array,
+            3 => Self::ProgArray,
+            4 => Self::PerfEventArray,
+            5 => Self::PercpuHash,
+            6 => Self::PercpuArray,
+            7 => Self::StackTrace,
+            8 => Self::CgroupArray,
+            9 => Self::LruHash,
+            10 => Self::LruPercpuHash,
+            11 => Self::LpmTrie,
+            12 => Self::ArrayOfMaps,
+            13 => Self::HashOfMaps,
+            14 => Self::Devmap,
+            15 => Self::Sockmap,
+            16 => Self::Ringbuf,
+            _ => return None,
+        })
+    }
+
+    fn is_array(self) -> bool {
+        matches!(self, Self::Array | Self::PercpuArray)
+    }
+}
+
 /// Map key-value entry
 #[derive(Debug, Clone)]
 pub struct MapEntry {
@@ -261,6 +339,19 @@ pub struct MapEntry {
     pub value: Vec<u8>,
 }
 
+/// Largest key or value a map can hold: a value must fit in its window of
+/// the program address space
+pub const BPF_MAX_VALUE_SIZE: u32 = MAP_VALUE_STRIDE as u32;
+
+/// Flags of `BpfMap::update`
+pub const BPF_ANY: u64 = 0;
+pub const BPF_NOEXIST: u64 = 1;
+pub const BPF_EXIST: u64 = 2;
+
+/// A map value. Programs get a pointer to the value itself, so what they
+/// write through it is seen by later lookups.
+pub type MapValue = Arc<Mutex<Vec<u8>>>;
+
 /// eBPF map
 pub struct BpfMap {
     /// Map ID
@@ -277,10 +368,10 @@ pub struct BpfMap {
     pub flags: u32,
     /// Map name
     pub name: String,
-    /// Entries (for hash/array maps)
-    entries: RwLock<BTreeMap<Vec<u8>, Vec<u8>>>,
+    /// Entries (for hash maps)
+    entries: RwLock<BTreeMap<Vec<u8>, MapValue>>,
     /// Array storage (for array maps)
-    array: RwLock<Vec<Vec<u8>>>,
+    array: Vec<MapValue>,
 }
 
 impl BpfMap {
@@ -294,8 +385,10 @@ impl BpfMap {
         flags: u32,
         name: &str,
     ) -> Self {
-        let array = if map_type == BpfMapType::Array || map_type == BpfMapType::PercpuArray {
-            vec![vec![0u8; value_size as usize]; max_entries as usize]
+        let array = if map_type.is_array() {
+            (0..max_entries)
+                .map(|_| Arc::new(Mutex::new(vec![0u8; value_size as usize])))
+                .collect()
         } else {
             Vec::new()
         };
@@ -309,29 +402,69 @@ impl BpfMap {
             flags,
             name: name.to_string(),
             entries: RwLock::new(BTreeMap::new()),
-            array: RwLock::new(array),
+            array,
+        }
+    }
+
+    /// Check the parameters of a new map
+    pub fn validate(
+        map_type: BpfMapType,
+        key_size: u32,
+        value_size: u32,
+        max_entries: u32,
+    ) -> Result<(), BpfError> {
+        match map_type {
+            BpfMapType::Hash | BpfMapType::PercpuHash | BpfMapType::Array | BpfMapType::PercpuArray => {}
+            _ => return Err(BpfError::NotSupported),
+        }
+        if key_size == 0 || key_size > BPF_MAX_VALUE_SIZE {
+            return Err(BpfError::InvalidKey);
+        }
+        if map_type.is_array() && key_size != 4 {
+            return Err(BpfError::InvalidKey);
+        }
+        if value_size == 0 || value_size > BPF_MAX_VALUE_SIZE {
+            return Err(BpfError::InvalidValue);
+        }
+        if max_entries == 0 {
+            return Err(BpfError::InvalidValue);
+        }
+        Ok(())
+    }
+
+    /// Index of an array map key
+    fn array_index(&self, key: &[u8]) -> Option<usize> {
+        let idx = u32::from_ne_bytes(key.try_into().ok()?) as usize;
+        (idx < self.array.len()).then_some(idx)
+    }
+
+    /// Number of entries in use
+    pub fn len(&self) -> usize {
+        if self.map_type.is_array() {
+            self.array.len()
+        } else {
+            self.entries.read().len()
         }
     }
 
     /// Lookup value by key
     pub fn lookup(&self, key: &[u8]) -> Option<Vec<u8>> {
-        match self.map_type {
-            BpfMapType::Array | BpfMapType::PercpuArray => {
-                if key.len() != 4 {
-                    return None;
-                }
-                let idx = u32::from_ne_bytes([key[0], key[1], key[2], key[3]]) as usize;
-                let array = self.array.read();
-                array.get(idx).cloned()
-            }
-            _ => {
-                let entries = self.entries.read();
-                entries.get(key).cloned()
-            }
+        self.lookup_value(key).map(|value| value.lock().clone())
+    }
+
+    /// Value stored under `key`, shared with the map
+    pub fn lookup_value(&self, key: &[u8]) -> Option<MapValue> {
+        if self.map_type.is_array() {
+            self.array_index(key).map(|idx| self.array[idx].clone())
+        } else {
+            self.entries.read().get(key).cloned()
         }
     }
 
     /// Update value
+    ///
+    /// `flags` is BPF_ANY, BPF_NOEXIST (only create) or BPF_EXIST (only
+    /// replace). Array elements always exist.
     pub fn update(&self, key: &[u8], value: &[u8], flags: u64) -> Result<(), BpfError> {
         if key.len() != self.key_size as usize {
             return Err(BpfError::InvalidKey);
@@ -339,74 +472,71 @@ impl BpfMap {
         if value.len() != self.value_size as usize {
             return Err(BpfError::InvalidValue);
         }
+        if flags > BPF_EXIST {
+            return Err(BpfError::InvalidFlags);
+        }
 
-        match self.map_type {
-            BpfMapType::Array | BpfMapType::PercpuArray => {
-                let idx = u32::from_ne_bytes([key[0], key[1], key[2], key[3]]) as usize;
-                let mut array = self.array.write();
-                if idx >= array.len() {
-                    return Err(BpfError::InvalidKey);
+        if self.map_type.is_array() {
+            let idx = self.array_index(key).ok_or(BpfError::InvalidKey)?;
+            if flags == BPF_NOEXIST {
+                return Err(BpfError::AlreadyExists);
+            }
+            self.array[idx].lock().copy_from_slice(value);
+            return Ok(());
+        }
+
+        let mut entries = self.entries.write();
+        match entries.get(key) {
+            Some(existing) => {
+                if flags == BPF_NOEXIST {
+                    return Err(BpfError::AlreadyExists);
                 }
-                array[idx] = value.to_vec();
-                Ok(())
+                existing.lock().copy_from_slice(value);
             }
-            _ => {
-                let mut entries = self.entries.write();
-                if entries.len() >= self.max_entries as usize && !entries.contains_key(key) {
+            None => {
+                if flags == BPF_EXIST {
+                    return Err(BpfError::NotFound);
+                }
+                if entries.len() >= self.max_entries as usize {
                     return Err(BpfError::MapFull);
                 }
-                entries.insert(key.to_vec(), value.to_vec());
-                Ok(())
+                entries.insert(key.to_vec(), Arc::new(Mutex::new(value.to_vec())));
             }
         }
+        Ok(())
     }
 
     /// Delete entry
     pub fn delete(&self, key: &[u8]) -> Result<(), BpfError> {
-        match self.map_type {
-            BpfMapType::Array | BpfMapType::PercpuArray => {
-                // Arrays don't support deletion, just zero
-                let idx = u32::from_ne_bytes([key[0], key[1], key[2], key[3]]) as usize;
-                let mut array = self.array.write();
-                if idx >= array.len() {
-                    return Err(BpfError::InvalidKey);
-                }
-                array[idx] = vec![0u8; self.value_size as usize];
-                Ok(())
-            }
-            _ => {
-                let mut entries = self.entries.write();
-                entries.remove(key).ok_or(BpfError::NotFound)?;
-                Ok(())
-            }
+        if self.map_type.is_array() {
+            // Arrays don't support deletion, just zero
+            let idx = self.array_index(key).ok_or(BpfError::InvalidKey)?;
+            self.array[idx].lock().fill(0);
+            return Ok(());
         }
+        self.entries.write().remove(key).ok_or(BpfError::NotFound)?;
+        Ok(())
     }
 
     /// Get next key (for iteration)
+    ///
+    /// Starts over from the first key when `key` is not in the map.
     pub fn get_next_key(&self, key: Option<&[u8]>) -> Option<Vec<u8>> {
-        match self.map_type {
-            BpfMapType::Array | BpfMapType::PercpuArray => {
-                let array = self.array.read();
-                let next_idx = match key {
-                    None => 0,
-                    Some(k) => {
-                        let idx = u32::from_ne_bytes([k[0], k[1], k[2], k[3]]) as usize;
-                        idx + 1
-                    }
-                };
-                if next_idx < array.len() {
-                    Some((next_idx as u32).to_ne_bytes().to_vec())
-                } else {
-                    None
-                }
-            }
-            _ => {
-                let entries = self.entries.read();
-                match key {
-                    None => entries.keys().next().cloned(),
-                    Some(k) => entries.range(k.to_vec()..).skip(1).next().map(|(k, _)| k.clone()),
-                }
-            }
+        if self.map_type.is_array() {
+            let next_idx = match key.and_then(|k| self.array_index(k)) {
+                None => 0,
+                Some(idx) => idx + 1,
+            };
+            return (next_idx < self.array.len()).then(|| (next_idx as u32).to_ne_bytes().to_vec());
+        }
+
+        let entries = self.entries.read();
+        match key {
+            Some(k) if entries.contains_key(k) => entries
+                .range::<[u8], _>((Bound::Excluded(k), Bound::Unbounded))
+                .next()
+                .map(|(k, _)| k.clone()),
+            _ => entries.keys().next().cloned(),
         }
     }
 }
@@ -460,6 +590,35 @@ pub enum BpfProgType {
     Syscall = 19,
 }
 
+impl BpfProgType {
+    /// Program type from its bpf(2) number
+    pub fn from_u32(n: u32) -> Option<Self> {
+        Some(match n {
+            0 => Self::Unspec,
+            1 => Self::SocketFilter,
+            2 => Self::Kprobe,
+            3 => Self::SchedCls,
+            4 => Self::SchedAct,
+            5 => Self::Tracepoint,
+            6 => Self::Xdp,
+            7 => Self::PerfEvent,
+            8 => Self::CgroupSkb,
+            9 => Self::CgroupSock,
+            10 => Self::LwtIn,
+            11 => Self::LwtOut,
+            12 => Self::SockOps,
+            13 => Self::SkMsg,
+            14 => Self::RawTracepoint,
+            15 => Self::CgroupSockAddr,
+            16 => Self::Lsm,
+            17 => Self::StructOps,
+            18 => Self::Ext,
+            19 => Self::Syscall,
+            _ => return None,
+        })
+    }
+}
+
 /// Program attach type
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum BpfAttachType {
@@ -503,8 +662,8 @@ pub struct BpfProg {
     pub license: String,
     /// Kernel version requirement
     pub kern_version: u32,
-    /// Maps used by this program
-    pub maps: Vec<u32>,
+    /// Maps used by this program; `lddw rX, map` carries an index into it
+    pub maps: Vec<Arc<BpfMap>>,
     /// Attached
     attached: AtomicBool,
     /// Run count
@@ -593,6 +752,12 @@ pub struct VerifierResult {
 }
 
 /// eBPF program verifier
+///
+/// Checks the program statically: every instruction is one the VM runs,
+/// jumps stay inside the program and only go forward, `lddw` is well
+/// formed and refers to a map of the program, helpers are known to the
+/// program type and the frame pointer is never written. Memory accesses
+/// are checked by the VM while the program runs.
 pub struct BpfVerifier {
     /// Maximum instructions
     max_insns: usize,
@@ -607,7 +772,7 @@ impl BpfVerifier {
     pub fn new() -> Self {
         Self {
             max_insns: 1_000_000,
-            max_stack: 512,
+            max_stack: BPF_STACK_SIZE,
             allow_loops: false,
         }
     }
@@ -616,54 +781,110 @@ impl BpfVerifier {
     pub fn verify(&self, prog: &BpfProg) -> VerifierResult {
         let mut log = String::new();
 
-        // Check instruction count
-        if prog.insn_count() > self.max_insns {
-            return VerifierResult {
-                success: false,
-                error: Some("Too many instructions".to_string()),
-                log,
-                insn_count: prog.insn_count(),
-                stack_depth: 0,
-            };
-        }
-
-        // Check for invalid instructions
-        for (i, insn) in prog.insns().iter().enumerate() {
-            if let Err(e) = self.verify_insn(insn, i) {
-                return VerifierResult {
+        match self.check(prog) {
+            Ok(stack_depth) => {
+                let _ = writeln!(log, "processed {} insns, stack depth {}", prog.insn_count(), stack_depth);
+                VerifierResult {
+                    success: true,
+                    error: None,
+                    log,
+                    insn_count: prog.insn_count(),
+                    stack_depth,
+                }
+            }
+            Err(e) => {
+                let _ = writeln!(log, "{}", e);
+                VerifierResult {
                     success: false,
                     error: Some(e),
                     log,
                     insn_count: prog.insn_count(),
                     stack_depth: 0,
-                };
+                }
             }
         }
+    }
 
-        // Check control flow
-        if let Err(e) = self.verify_control_flow(prog) {
-            return VerifierResult {
-                success: false,
-                error: Some(e),
-                log,
-                insn_count: prog.insn_count(),
-                stack_depth: 0,
+    /// Run all checks; returns the stack depth used through r10
+    fn check(&self, prog: &BpfProg) -> Result<usize, String> {
+        let insns = prog.insns();
+        if insns.is_empty() {
+            return Err("Empty program".to_string());
+        }
+        if insns.len() > self.max_insns {
+            return Err("Too many instructions".to_string());
+        }
+
+        // Second slots of lddw; nothing may run or jump there
+        let mut wide = vec![false; insns.len()];
+        let mut stack_depth = 0;
+        let mut i = 0;
+        while i < insns.len() {
+            let insn = &insns[i];
+            self.verify_insn(prog, insn, i)?;
+
+            if insn.code == BPF_LD_IMM64 {
+                let next = insns
+                    .get(i + 1)
+                    .ok_or_else(|| format!("Truncated lddw at insn {}", i))?;
+                if next.code != 0 || next.regs != 0 || next.off != 0 {
+                    return Err(format!("Invalid second half of lddw at insn {}", i + 1));
+                }
+                if insn.src_reg() == BPF_PSEUDO_MAP_FD && next.imm != 0 {
+                    return Err(format!("Invalid map reference at insn {}", i));
+                }
+                wide[i + 1] = true;
+                i += 2;
+                continue;
+            }
+
+            let class = insn.class();
+            let base = match class {
+                c if c == BpfClass::Ldx as u8 => Some(insn.src_reg()),
+                c if c == BpfClass::St as u8 || c == BpfClass::Stx as u8 => Some(insn.dst_reg()),
+                _ => None,
             };
+            if base == Some(10) {
+                let start = insn.off as i64;
+                let end = start + insn.size_bytes() as i64;
+                if start < -(self.max_stack as i64) || end > 0 {
+                    return Err(format!("Stack access out of bounds at insn {}", i));
+                }
+                stack_depth = stack_depth.max((-start) as usize);
+            }
+            i += 1;
         }
 
-        log.push_str("Verification passed\n");
+        for (i, insn) in insns.iter().enumerate() {
+            let class = insn.class();
+            if wide[i] || (class != BpfClass::Jmp as u8 && class != BpfClass::Jmp32 as u8) {
+                continue;
+            }
+            let op = insn.op();
+            if class == BpfClass::Jmp as u8 && (op == BpfJmpOp::Call as u8 || op == BpfJmpOp::Exit as u8) {
+                continue;
+            }
+            if insn.off < 0 && !self.allow_loops {
+                return Err(format!("Back-edge at insn {}", i));
+            }
+            let target = i as i64 + 1 + insn.off as i64;
+            if target < 0 || target >= insns.len() as i64 || wide[target as usize] {
+                return Err(format!("Jump out of range at insn {}", i));
+            }
+        }
 
-        VerifierResult {
-            success: true,
-            error: None,
-            log,
-            insn_count: prog.insn_count(),
-            stack_depth: 0, // Would calculate actual stack depth
+        // Jumps only go forward, so the last instruction must end the program
+        let last = &insns[insns.len() - 1];
+        let is_exit = last.class() == BpfClass::Jmp as u8 && last.op() == BpfJmpOp::Exit as u8;
+        if wide[insns.len() - 1] || !is_exit {
+            return Err("Program doesn't end with exit".to_string());
         }
+
+        Ok(stack_depth)
     }
 
     /// Verify single instruction
-    fn verify_insn(&self, insn: &BpfInsn, idx: usize) -> Result<(), String> {
+    fn verify_insn(&self, prog: &BpfProg, insn: &BpfInsn, idx: usize) -> Result<(), String> {
         // Check register bounds
         if insn.dst_reg() > 10 {
             return Err(format!("Invalid dst register at insn {}", idx));
@@ -672,27 +893,90 @@ impl BpfVerifier {
             return Err(format!("Invalid src register at insn {}", idx));
         }
 
-        // Check opcode validity
-        let class = insn.class();
-        if class > 7 {
-            return Err(format!("Invalid opcode class at insn {}", idx));
-        }
-
-        Ok(())
-    }
+        let invalid = || format!("Invalid opcode {:#x} at insn {}", insn.code, idx);
+        let read_only = || format!("Frame pointer is read-only at insn {}", idx);
+        let op = insn.op();
 
-    /// Verify control flow (no unreachable code, proper termination)
-    fn verify_control_flow(&self, prog: &BpfProg) -> Result<(), String> {
-        let insns = prog.insns();
-        if insns.is_empty() {
-            return Err("Empty program".to_string());
-        }
-
-        // Check last instruction is exit
-        let last = &insns[insns.len() - 1];
-        let is_exit = last.class() == BpfClass::Jmp as u8 && last.op() == BpfJmpOp::Exit as u8;
-        if !is_exit {
-            return Err("Program doesn't end with exit".to_string());
+        match insn.class() {
+            c if c == BpfClass::Alu as u8 || c == BpfClass::Alu64 as u8 => {
+                if op > BpfAluOp::End as u8 {
+                    return Err(invalid());
+                }
+                if insn.dst_reg() == 10 {
+                    return Err(read_only());
+                }
+                if (op == BpfAluOp::Div as u8 || op == BpfAluOp::Mod as u8) && insn.src_type() == 0 && insn.imm == 0 {
+                    return Err(format!("Division by zero at insn {}", idx));
+                }
+                if op == BpfAluOp::End as u8 {
+                    let to_be = insn.src_type() != 0;
+                    if !matches!(insn.imm, 16 | 32 | 64) || (c == BpfClass::Alu64 as u8 && to_be) {
+                        return Err(invalid());
+                    }
+                }
+            }
+            c if c == BpfClass::Jmp as u8 || c == BpfClass::Jmp32 as u8 => {
+                if op > BpfJmpOp::Jsle as u8 {
+                    return Err(invalid());
+                }
+                let is_jmp32 = c == BpfClass::Jmp32 as u8;
+                if is_jmp32 && (op == BpfJmpOp::Ja as u8 || op == BpfJmpOp::Call as u8 || op == BpfJmpOp::Exit as u8) {
+                    return Err(invalid());
+                }
+                if !is_jmp32 && op == BpfJmpOp::Call as u8 {
+                    if insn.src_reg() != 0 {
+                        return Err(format!("Only helper calls are supported (insn {})", idx));
+                    }
+                    if !helper_allowed(prog.prog_type, insn.imm as u32) {
+                        return Err(format!("Unknown helper {} at insn {}", insn.imm, idx));
+                    }
+                }
+            }
+            c if c == BpfClass::Ld as u8 => {
+                if insn.code == BPF_LD_IMM64 {
+                    if insn.dst_reg() == 10 {
+                        return Err(read_only());
+                    }
+                    match insn.src_reg() {
+                        0 => {}
+                        BPF_PSEUDO_MAP_FD if (insn.imm as u32 as usize) < prog.maps.len() => {}
+                        _ => return Err(format!("Invalid map reference at insn {}", idx)),
+                    }
+                } else if matches!(insn.mode(), BPF_ABS | BPF_IND) && insn.size_bytes() != 8 {
+                    if prog.prog_type != BpfProgType::SocketFilter {
+                        return Err(format!("Packet load outside a socket filter at insn {}", idx));
+                    }
+                } else {
+                    return Err(invalid());
+                }
+            }
+            c if c == BpfClass::Ldx as u8 => {
+                if insn.mode() != BPF_MEM {
+                    return Err(invalid());
+                }
+                if insn.dst_reg() == 10 {
+                    return Err(read_only());
+                }
+            }
+            c if c == BpfClass::St as u8 => {
+                if insn.mode() != BPF_MEM {
+                    return Err(invalid());
+                }
+            }
+            c if c == BpfClass::Stx as u8 => match insn.mode() {
+                BPF_MEM => {}
+                BPF_ATOMIC => {
+                    if !matches!(insn.size_bytes(), 4 | 8) {
+                        return Err(invalid());
+                    }
+                    let plain = insn.imm & !BPF_FETCH;
+                    if !matches!(plain, 0x00 | 0x40 | 0x50 | 0xa0) && insn.imm != BPF_XCHG && insn.imm != BPF_CMPXCHG {
+                        return Err(format!("Invalid atomic operation at insn {}", idx));
+                    }
+                }
+                _ => return Err(invalid()),
+            },
+            _ => return Err(invalid()),
         }
 
         Ok(())
@@ -705,31 +989,196 @@ impl Default for BpfVerifier {
     }
 }
 
+/// Helper function ids (same numbers as Linux)
+pub const BPF_FUNC_MAP_LOOKUP_ELEM: u32 = 1;
+pub const BPF_FUNC_MAP_UPDATE_ELEM: u32 = 2;
+pub const BPF_FUNC_MAP_DELETE_ELEM: u32 = 3;
+pub const BPF_FUNC_KTIME_GET_NS: u32 = 5;
+pub const BPF_FUNC_TRACE_PRINTK: u32 = 6;
+pub const BPF_FUNC_GET_CURRENT_PID_TGID: u32 = 14;
+pub const BPF_FUNC_GET_CURRENT_UID_GID: u32 = 15;
+pub const BPF_FUNC_GET_CURRENT_COMM: u32 = 16;
+pub const BPF_FUNC_SKB_LOAD_BYTES: u32 = 26;
+
+/// Whether programs of `prog_type` may call helper `id`
+fn helper_allowed(prog_type: BpfProgType, id: u32) -> bool {
+    match id {
+        BPF_FUNC_MAP_LOOKUP_ELEM
+        | BPF_FUNC_MAP_UPDATE_ELEM
+        | BPF_FUNC_MAP_DELETE_ELEM
+        | BPF_FUNC_KTIME_GET_NS
+        | BPF_FUNC_TRACE_PRINTK
+        | BPF_FUNC_GET_CURRENT_PID_TGID
+        | BPF_FUNC_GET_CURRENT_UID_GID
+        | BPF_FUNC_GET_CURRENT_COMM => true,
+        BPF_FUNC_SKB_LOAD_BYTES => prog_type == BpfProgType::SocketFilter,
+        _ => false,
+    }
+}
+
 // ============================================================================
 // eBPF Virtual Machine
 // ============================================================================
 
+// Address space of a run. Pointers held by a program are offsets into one
+// of these regions, never kernel addresses.
+
+/// Size of the program stack
+pub const BPF_STACK_SIZE: usize = 512;
+
+const REGION_SHIFT: u32 = 28;
+const REGION_MASK: u64 = (1 << REGION_SHIFT) - 1;
+/// Context structure (r1 at entry)
+const CTX_BASE: u64 = 1 << REGION_SHIFT;
+/// Packet data of XDP programs and socket filters
+const PACKET_BASE: u64 = 2 << REGION_SHIFT;
+/// Stack; r10 points to its end
+const STACK_BASE: u64 = 3 << REGION_SHIFT;
+/// Map values returned by bpf_map_lookup_elem, MAP_VALUE_STRIDE apart
+const MAP_VALUE_BASE: u64 = 4 << REGION_SHIFT;
+const MAP_VALUE_STRIDE: u64 = 1 << 16;
+/// Maps loaded by `lddw rX, map`; only usable as helper arguments
+const MAP_HANDLE_BASE: u64 = 8 << REGION_SHIFT;
+
+/// Size of `struct xdp_md`
+const XDP_MD_SIZE: usize = 24;
+/// Size of `struct __sk_buff` up to `data_end`
+const SK_BUFF_SIZE: usize = 84;
+
+fn put_u32(buf: &mut [u8], off: usize, val: u32) {
+    buf[off..off + 4].copy_from_slice(&val.to_le_bytes());
+}
+
+/// `len` bytes at `off` of `buf`, if they are all inside it
+fn window(buf: &mut [u8], off: usize, len: usize) -> Option<&mut [u8]> {
+    buf.get_mut(off..off.checked_add(len)?)
+}
+
 /// eBPF VM execution context
-pub struct BpfContext {
+pub struct BpfContext<'a> {
     /// Registers
     pub regs: BpfRegs,
-    /// Stack (512 bytes)
-    pub stack: [u8; 512],
-    /// Data pointer (context-specific)
-    pub data: u64,
-    /// Data end pointer
-    pub data_end: u64,
+    /// Stack
+    pub stack: [u8; BPF_STACK_SIZE],
+    /// Context structure the program gets in r1 (read-only)
+    ctx: Vec<u8>,
+    /// Packet data
+    packet: &'a mut [u8],
+    /// Whether the program may rewrite the packet
+    packet_writable: bool,
+    /// Map values looked up during the run
+    values: Vec<MapValue>,
 }
 
-impl Default for BpfContext {
-    fn default() -> Self {
+impl<'a> BpfContext<'a> {
+    fn new(ctx: Vec<u8>, packet: &'a mut [u8], packet_writable: bool) -> Self {
         Self {
             regs: BpfRegs::default(),
-            stack: [0; 512],
-            data: 0,
-            data_end: 0,
+            stack: [0; BPF_STACK_SIZE],
+            ctx,
+            packet,
+            packet_writable,
+            values: Vec::new(),
         }
     }
+
+    /// Context of an XDP program: `struct xdp_md` over a received frame,
+    /// which the program may rewrite
+    pub fn xdp(frame: &'a mut [u8], ifindex: u32) -> Self {
+        let end = PACKET_BASE as u32 + frame.len() as u32;
+        let mut md = vec![0u8; XDP_MD_SIZE];
+        put_u32(&mut md, 0, PACKET_BASE as u32); // data
+        put_u32(&mut md, 4, end); // data_end
+        put_u32(&mut md, 8, PACKET_BASE as u32); // data_meta
+        put_u32(&mut md, 12, ifindex); // ingress_ifindex
+        Self::new(md, frame, true)
+    }
+
+    /// Context of a socket filter: the `struct __sk_buff` fields this
+    /// kernel knows, over a packet the program can only read
+    pub fn socket_filter(packet: &'a mut [u8], protocol: u16, ifindex: u32) -> Self {
+        let len = packet.len() as u32;
+        let mut skb = vec![0u8; SK_BUFF_SIZE];
+        put_u32(&mut skb, 0, len); // len
+        put_u32(&mut skb, 16, protocol.to_be() as u32); // protocol
+        put_u32(&mut skb, 36, ifindex); // ingress_ifindex
+        put_u32(&mut skb, 40, ifindex); // ifindex
+        put_u32(&mut skb, 76, PACKET_BASE as u32); // data
+        put_u32(&mut skb, 80, PACKET_BASE as u32 + len); // data_end
+        Self::new(skb, packet, false)
+    }
+}
+
+impl BpfContext<'static> {
+    /// Context of a tracepoint program: the event record
+    pub fn tracepoint(record: &[u8]) -> Self {
+        Self::new(record.to_vec(), &mut [], false)
+    }
+}
+
+impl Default for BpfContext<'static> {
+    fn default() -> Self {
+        Self::new(Vec::new(), &mut [], false)
+    }
+}
+
+impl BpfContext<'_> {
+    /// Run `f` on the `len` bytes at program address `addr`
+    fn access<R>(&mut self, addr: u64, len: usize, write: bool, f: impl FnOnce(&mut [u8]) -> R) -> Result<R, BpfError> {
+        let fault = || BpfError::InvalidAccess(addr);
+        let off = (addr & REGION_MASK) as usize;
+        match addr >> REGION_SHIFT {
+            1 if !write => window(&mut self.ctx, off, len).map(f).ok_or_else(fault),
+            2 if !write || self.packet_writable => window(self.packet, off, len).map(f).ok_or_else(fault),
+            3 => window(&mut self.stack, off, len).map(f).ok_or_else(fault),
+            4..=7 => {
+                let rel = addr - MAP_VALUE_BASE;
+                let value = self.values.get((rel / MAP_VALUE_STRIDE) as usize).ok_or_else(fault)?;
+                let mut data = value.lock();
+                window(&mut data, (rel % MAP_VALUE_STRIDE) as usize, len).map(f).ok_or_else(fault)
+            }
+            _ => Err(fault()),
+        }
+    }
+
+    /// Copy `out.len()` bytes from program address `addr`
+    pub fn read(&mut self, addr: u64, out: &mut [u8]) -> Result<(), BpfError> {
+        self.access(addr, out.len(), false, |mem| out.copy_from_slice(mem))
+    }
+
+    /// Copy `data` to program address `addr`
+    pub fn write(&mut self, addr: u64, data: &[u8]) -> Result<(), BpfError> {
+        self.access(addr, data.len(), true, |mem| mem.copy_from_slice(data))
+    }
+
+    fn load(&mut self, addr: u64, size: usize) -> Result<u64, BpfError> {
+        let mut buf = [0u8; 8];
+        self.read(addr, &mut buf[..size])?;
+        Ok(u64::from_le_bytes(buf))
+    }
+
+    fn store(&mut self, addr: u64, size: usize, val: u64) -> Result<(), BpfError> {
+        self.write(addr, &val.to_le_bytes()[..size])
+    }
+
+    /// Big-endian load from packet offset `off` (LD_ABS/LD_IND)
+    fn packet_load_be(&self, off: u64, size: usize) -> Option<u64> {
+        let off = usize::try_from(off).ok()?;
+        let bytes = self.packet.get(off..off.checked_add(size)?)?;
+        Some(bytes.iter().fold(0u64, |acc, &b| (acc << 8) | b as u64))
+    }
+
+    /// Make a looked-up map value addressable by the program
+    fn map_value(&mut self, value: MapValue) -> u64 {
+        let idx = match self.values.iter().position(|v| Arc::ptr_eq(v, &value)) {
+            Some(idx) => idx,
+            None => {
+                self.values.push(value);
+                self.values.len() - 1
+            }
+        };
+        MAP_VALUE_BASE + idx as u64 * MAP_VALUE_STRIDE
+    }
 }
 
 /// eBPF VM
@@ -738,8 +1187,151 @@ pub struct BpfVm {
     helpers: BTreeMap<u32, BpfHelper>,
 }
 
-/// Helper function type
-type BpfHelper = fn(&mut BpfContext, u64, u64, u64, u64, u64) -> u64;
+/// Helper function type: the running program, its context and r1-r5
+type BpfHelper = fn(&BpfProg, &mut BpfContext<'_>, [u64; 5]) -> Result<u64, BpfError>;
+
+/// Map referenced by a helper argument
+fn map_arg(prog: &BpfProg, handle: u64) -> Result<&Arc<BpfMap>, BpfError> {
+    handle
+        .checked_sub(MAP_HANDLE_BASE)
+        .and_then(|idx| prog.maps.get(idx as usize))
+        .ok_or(BpfError::InvalidAccess(handle))
+}
+
+/// Copy `len` bytes of program memory
+fn mem_arg(ctx: &mut BpfContext<'_>, addr: u64, len: usize) -> Result<Vec<u8>, BpfError> {
+    let mut buf = vec![0u8; len];
+    ctx.read(addr, &mut buf)?;
+    Ok(buf)
+}
+
+/// Negative errno as a helper return value
+fn helper_err(e: BpfError) -> u64 {
+    e.errno() as u64
+}
+
+/// Format the way bpf_trace_printk does: %d %i %u %x %p and %c, with
+/// optional l/ll length modifiers, and %%
+fn format_printk(fmt: &[u8], args: [u64; 3]) -> String {
+    let mut out = String::new();
+    let mut args = args.iter().copied();
+    let mut i = 0;
+    while i < fmt.len() {
+        let c = fmt[i];
+        i += 1;
+        if c != b'%' {
+            out.push(c as char);
+            continue;
+        }
+        let mut long = false;
+        while fmt.get(i) == Some(&b'l') {
+            long = true;
+            i += 1;
+        }
+        let Some(&conv) = fmt.get(i) else {
+            break;
+        };
+        i += 1;
+        if conv == b'%' {
+            out.push('%');
+            continue;
+        }
+        let arg = args.next().unwrap_or(0);
+        let _ = match (conv, long) {
+            (b'd' | b'i', false) => write!(out, "{}", arg as i32),
+            (b'd' | b'i', true) => write!(out, "{}", arg as i64),
+            (b'u', false) => write!(out, "{}", arg as u32),
+            (b'u', true) => write!(out, "{}", arg),
+            (b'x', false) => write!(out, "{:x}", arg as u32),
+            (b'x', true) => write!(out, "{:x}", arg),
+            (b'p', _) => write!(out, "0x{:x}", arg),
+            (b'c', _) => write!(out, "{}", arg as u8 as char),
+            _ => write!(out, "%{}", conv as char),
+        };
+    }
+    out
+}
+
+/// ALU operation, 64-bit (ALU64) or 32-bit (ALU) when `wide` is false
+///
+/// Division by zero gives 0 and modulo by zero leaves the destination
+/// unchanged, as on Linux.
+fn alu(op: u8, dst: u64, src: u64, imm: i32, from_reg: bool, wide: bool) -> Result<u64, BpfError> {
+    if op == BpfAluOp::End as u8 {
+        // ALU: to little endian (a no-op here) or big endian; ALU64: bswap
+        let swap = wide || from_reg;
+        return Ok(match (imm, swap) {
+            (16, false) => dst as u16 as u64,
+            (16, true) => (dst as u16).swap_bytes() as u64,
+            (32, false) => dst as u32 as u64,
+            (32, true) => (dst as u32).swap_bytes() as u64,
+            (_, false) => dst,
+            (_, true) => dst.swap_bytes(),
+        });
+    }
+
+    if wide {
+        return Ok(match op {
+            0x00 => dst.wrapping_add(src),
+            0x10 => dst.wrapping_sub(src),
+            0x20 => dst.wrapping_mul(src),
+            0x30 => dst.checked_div(src).unwrap_or(0),
+            0x40 => dst | src,
+            0x50 => dst & src,
+            0x60 => dst << (src & 0x3f),
+            0x70 => dst >> (src & 0x3f),
+            0x80 => dst.wrapping_neg(),
+            0x90 => dst.checked_rem(src).unwrap_or(dst),
+            0xa0 => dst ^ src,
+            0xb0 => src,
+            0xc0 => ((dst as i64) >> (src & 0x3f)) as u64,
+            _ => return Err(BpfError::InvalidInsn),
+        });
+    }
+
+    let (dst, src) = (dst as u32, src as u32);
+    let result = match op {
+        0x00 => dst.wrapping_add(src),
+        0x10 => dst.wrapping_sub(src),
+        0x20 => dst.wrapping_mul(src),
+        0x30 => dst.checked_div(src).unwrap_or(0),
+        0x40 => dst | src,
+        0x50 => dst & src,
+        0x60 => dst << (src & 0x1f),
+        0x70 => dst >> (src & 0x1f),
+        0x80 => dst.wrapping_neg(),
+        0x90 => dst.checked_rem(src).unwrap_or(dst),
+        0xa0 => dst ^ src,
+        0xb0 => src,
+        0xc0 => ((dst as i32) >> (src & 0x1f)) as u32,
+        _ => return Err(BpfError::InvalidInsn),
+    };
+    Ok(result as u64)
+}
+
+/// Condition of a conditional jump, on 64 bits (JMP) or 32 bits (JMP32)
+fn jump_taken(op: u8, dst: u64, src: u64, wide: bool) -> Result<bool, BpfError> {
+    let (d, s, sd, ss) = if wide {
+        (dst, src, dst as i64, src as i64)
+    } else {
+        (dst as u32 as u64, src as u32 as u64, dst as i32 as i64, src as i32 as i64)
+    };
+    Ok(match op {
+        0x00 => true,        // JA
+        0x10 => d == s,      // JEQ
+        0x20 => d > s,       // JGT
+        0x30 => d >= s,      // JGE
+        0x40 => d & s != 0,  // JSET
+        0x50 => d != s,      // JNE
+        0x60 => sd > ss,     // JSGT
+        0x70 => sd >= ss,    // JSGE
+        0xa0 => d < s,       // JLT
+        0xb0 => d <= s,      // JLE
+        0xc0 => sd < ss,     // JSLT
+        0xd0 => sd <= ss,    // JSLE
+        _ => return Err(BpfError::InvalidInsn),
+    })
+}
 
 impl BpfVm {
     /// Create new VM
@@ -753,204 +1345,175 @@ impl BpfVm {
 
     /// Register built-in helper functions
     fn register_builtin_helpers(&mut self) {
-        // Helper 1: bpf_map_lookup_elem
-        self.helpers.insert(1, |_ctx, _map_fd, _key, _, _, _| 0);
+        self.helpers.insert(BPF_FUNC_MAP_LOOKUP_ELEM, |prog, ctx, [map, key, ..]| {
+            let map = map_arg(prog, map)?;
+            let key = mem_arg(ctx, key, map.key_size as usize)?;
+            Ok(match map.lookup_value(&key) {
+                Some(value) => ctx.map_value(value),
+                None => 0,
+            })
+        });
 
-        // Helper 2: bpf_map_update_elem
-        self.helpers.insert(2, |_ctx, _map_fd, _key, _value, _flags, _| 0);
+        self.helpers.insert(BPF_FUNC_MAP_UPDATE_ELEM, |prog, ctx, [map, key, value, flags, _]| {
+            let map = map_arg(prog, map)?;
+            let key = mem_arg(ctx, key, map.key_size as usize)?;
+            let value = mem_arg(ctx, value, map.value_size as usize)?;
+            Ok(map.update(&key, &value, flags).map_or_else(helper_err, |()| 0))
+        });
 
-        // Helper 3: bpf_map_delete_elem
-        self.helpers.insert(3, |_ctx, _map_fd, _key, _, _, _| 0);
+        self.helpers.insert(BPF_FUNC_MAP_DELETE_ELEM, |prog, ctx, [map, key, ..]| {
+            let map = map_arg(prog, map)?;
+            let key = mem_arg(ctx, key, map.key_size as usize)?;
+            Ok(map.delete(&key).map_or_else(helper_err, |()| 0))
+        });
 
-        // Helper 4: bpf_probe_read
-        self.helpers.insert(4, |_ctx, _dst, _size, _src, _, _| 0);
+        self.helpers.insert(BPF_FUNC_KTIME_GET_NS, |_, _, _| Ok(crate::time::uptime_ns()));
 
-        // Helper 5: bpf_ktime_get_ns
-        self.helpers.insert(5, |_ctx, _, _, _, _, _| {
-            // Return current time in nanoseconds
-            crate::time::uptime_ns()
+        self.helpers.insert(BPF_FUNC_TRACE_PRINTK, |_, ctx, [fmt, size, a1, a2, a3]| {
+            let raw = mem_arg(ctx, fmt, (size as usize).min(BPF_STACK_SIZE))?;
+            let fmt = raw.split(|&b| b == 0).next().unwrap_or(&[]);
+            let message = format_printk(fmt, [a1, a2, a3]);
+            ftrace::trace_printk(&message);
+            Ok(message.len() as u64)
         });
 
-        // Helper 6: bpf_trace_printk
-        self.helpers.insert(6, |_ctx, _fmt, _fmt_size, _arg1, _arg2, _arg3| 0);
+        self.helpers.insert(BPF_FUNC_GET_CURRENT_PID_TGID, |_, _, _| {
+            Ok(crate::sched::try_current_task()
+                .map(|t| (t.tgid() << 32) | (t.id() & 0xffff_ffff))
+                .unwrap_or(0))
+        });
 
-        // Helper 14: bpf_get_current_pid_tgid
-        self.helpers.insert(14, |_ctx, _, _, _, _, _| {
-            // Return (tgid << 32) | pid
-            0
+        self.helpers.insert(BPF_FUNC_GET_CURRENT_UID_GID, |_, _, _| {
+            if crate::sched::try_current_task().is_none() {
+                return Ok(0);
+            }
+            let cred = crate::sched::current_cred();
+            Ok(((cred.gid.0 as u64) << 32) | cred.uid.0 as u64)
+        });
+
+        self.helpers.insert(BPF_FUNC_GET_CURRENT_COMM, |_, ctx, [buf, size, ..]| {
+            let size = size as usize;
+            if size == 0 || size > BPF_STACK_SIZE {
+                return Ok(helper_err(BpfError::InvalidValue));
+            }
+            let mut comm = vec![0u8; size];
+            if let Some(task) = crate::sched::try_current_task() {
+                let name = task.name().as_bytes();
+                let len = name.len().min(size - 1);
+                comm[..len].copy_from_slice(&name[..len]);
+            }
+            ctx.write(buf, &comm)?;
+            Ok(0)
         });
 
-        // Helper 35: bpf_get_current_comm
-        self.helpers.insert(35, |_ctx, _buf, _size, _, _, _| 0);
+        self.helpers.insert(BPF_FUNC_SKB_LOAD_BYTES, |_, ctx, [_, offset, to, len, _]| {
+            let (offset, len) = (offset as usize, len as usize);
+            let bytes = offset
+                .checked_add(len)
+                .and_then(|end| ctx.packet.get(offset..end))
+                .map(|b| b.to_vec());
+            match bytes {
+                Some(bytes) => {
+                    ctx.write(to, &bytes)?;
+                    Ok(0)
+                }
+                None => Ok(errno::EFAULT as u64),
+            }
+        });
     }
 
     /// Execute program
-    pub fn run(&self, prog: &BpfProg, ctx: &mut BpfContext) -> Result<u64, BpfError> {
+    ///
+    /// The verifier only lets through programs whose jumps go forward, so
+    /// a run is a single pass over the instructions.
+    pub fn run(&self, prog: &BpfProg, ctx: &mut BpfContext<'_>) -> Result<u64, BpfError> {
+        let start = crate::time::uptime_ns();
+        let result = self.exec(prog, ctx);
+        prog.record_run(crate::time::uptime_ns().saturating_sub(start));
+        result
+    }
+
+    fn exec(&self, prog: &BpfProg, ctx: &mut BpfContext<'_>) -> Result<u64, BpfError> {
         let insns = prog.insns();
         let mut pc: usize = 0;
 
+        ctx.regs = BpfRegs::default();
+        ctx.regs.r1 = CTX_BASE;
         // Set up frame pointer
-        ctx.regs.r10 = ctx.stack.as_ptr() as u64 + 512;
+        ctx.regs.r10 = STACK_BASE + BPF_STACK_SIZE as u64;
 
         while pc < insns.len() {
             let insn = &insns[pc];
             let dst = insn.dst_reg();
             let src = insn.src_reg();
             let imm = insn.imm as i64 as u64;
+            let off = insn.off as i64 as u64;
+            let from_reg = insn.src_type() == 0x08;
+            let src_val = if from_reg { ctx.regs.get(src) } else { imm };
 
             match insn.class() {
-                // ALU64
-                0x07 => {
-                    let src_val = if insn.src_type() == 0x08 {
-                        ctx.regs.get(src)
-                    } else {
-                        imm
-                    };
-                    let dst_val = ctx.regs.get(dst);
-
-                    let result = match insn.op() {
-                        0x00 => dst_val.wrapping_add(src_val), // ADD
-                        0x10 => dst_val.wrapping_sub(src_val), // SUB
-                        0x20 => dst_val.wrapping_mul(src_val), // MUL
-                        0x30 => {
-                            if src_val == 0 {
-                                return Err(BpfError::DivByZero);
-                            }
-                            dst_val / src_val // DIV
-                        }
-                        0x40 => dst_val | src_val,  // OR
-                        0x50 => dst_val & src_val,  // AND
-                        0x60 => dst_val << (src_val & 0x3f), // LSH
-                        0x70 => dst_val >> (src_val & 0x3f), // RSH
-                        0x80 => (-(dst_val as i64)) as u64,  // NEG
-                        0x90 => {
-                            if src_val == 0 {
-                                return Err(BpfError::DivByZero);
-                            }
-                            dst_val % src_val // MOD
-                        }
-                        0xa0 => dst_val ^ src_val,  // XOR
-                        0xb0 => src_val,            // MOV
-                        0xc0 => ((dst_val as i64) >> (src_val & 0x3f)) as u64, // ARSH
-                        _ => return Err(BpfError::InvalidInsn),
-                    };
+                // ALU64 / ALU32
+                0x07 | 0x04 => {
+                    let result = alu(insn.op(), ctx.regs.get(dst), src_val, insn.imm, from_reg, insn.class() == 0x07)?;
                     ctx.regs.set(dst, result);
                 }
 
-                // ALU32
-                0x04 => {
-                    let src_val = if insn.src_type() == 0x08 {
-                        ctx.regs.get(src) as u32
-                    } else {
-                        imm as u32
-                    };
-                    let dst_val = ctx.regs.get(dst) as u32;
-
-                    let result = match insn.op() {
-                        0x00 => dst_val.wrapping_add(src_val),
-                        0x10 => dst_val.wrapping_sub(src_val),
-                        0x20 => dst_val.wrapping_mul(src_val),
-                        0x30 => {
-                            if src_val == 0 {
-                                return Err(BpfError::DivByZero);
-                            }
-                            dst_val / src_val
-                        }
-                        0x40 => dst_val | src_val,
-                        0x50 => dst_val & src_val,
-                        0x60 => dst_val << (src_val & 0x1f),
-                        0x70 => dst_val >> (src_val & 0x1f),
-                        0xb0 => src_val,
-                        _ => return Err(BpfError::InvalidInsn),
-                    };
-                    ctx.regs.set(dst, result as u64);
+                // JMP / JMP32
+                0x05 | 0x06 => {
+                    let op = insn.op();
+                    if insn.class() == 0x05 && op == BpfJmpOp::Call as u8 {
+                        ctx.regs.r0 = self.call(prog, ctx, insn.imm as u32)?;
+                    } else if insn.class() == 0x05 && op == BpfJmpOp::Exit as u8 {
+                        return Ok(ctx.regs.r0);
+                    } else if jump_taken(op, ctx.regs.get(dst), src_val, insn.class() == 0x05)? {
+                        pc = (pc as i64 + insn.off as i64) as usize;
+                    }
                 }
 
-                // JMP
-                0x05 => {
-                    let src_val = if insn.src_type() == 0x08 {
-                        ctx.regs.get(src)
+                // LD: lddw, and LD_ABS/LD_IND packet loads
+                0x00 => {
+                    if insn.code == BPF_LD_IMM64 {
+                        let next = insns.get(pc + 1).ok_or(BpfError::InvalidInsn)?;
+                        let val = if src == BPF_PSEUDO_MAP_FD {
+                            MAP_HANDLE_BASE + insn.imm as u32 as u64
+                        } else {
+                            insn.imm as u32 as u64 | ((next.imm as u32 as u64) << 32)
+                        };
+                        ctx.regs.set(dst, val);
+                        pc += 1;
                     } else {
-                        imm
-                    };
-                    let dst_val = ctx.regs.get(dst);
-
-                    let take_branch = match insn.op() {
-                        0x00 => true,  // JA
-                        0x10 => dst_val == src_val, // JEQ
-                        0x20 => dst_val > src_val,  // JGT
-                        0x30 => dst_val >= src_val, // JGE
-                        0x40 => (dst_val & src_val) != 0, // JSET
-                        0x50 => dst_val != src_val, // JNE
-                        0x60 => (dst_val as i64) > (src_val as i64), // JSGT
-                        0x70 => (dst_val as i64) >= (src_val as i64), // JSGE
-                        0xa0 => dst_val < src_val,  // JLT
-                        0xb0 => dst_val <= src_val, // JLE
-                        0x80 => {
-                            // CALL
-                            let func_id = insn.imm as u32;
-                            if let Some(helper) = self.helpers.get(&func_id) {
-                                let result = helper(
-                                    ctx,
-                                    ctx.regs.r1,
-                                    ctx.regs.r2,
-                                    ctx.regs.r3,
-                                    ctx.regs.r4,
-                                    ctx.regs.r5,
-                                );
-                                ctx.regs.r0 = result;
-                            } else {
-                                return Err(BpfError::UnknownHelper);
-                            }
-                            false
+                        let mut at = imm;
+                        if insn.mode() == BPF_IND {
+                            at = at.wrapping_add(ctx.regs.get(src));
                         }
-                        0x90 => {
-                            // EXIT
-                            return Ok(ctx.regs.r0);
+                        // Out of the packet ends the program with 0
+                        match ctx.packet_load_be(at, insn.size_bytes()) {
+                            Some(val) => ctx.regs.r0 = val,
+                            None => return Ok(0),
                         }
-                        _ => return Err(BpfError::InvalidInsn),
-                    };
-
-                    if take_branch && insn.op() != 0x80 {
-                        pc = (pc as i64 + insn.off as i64) as usize;
                     }
                 }
 
                 // LDX (load from memory)
                 0x01 => {
-                    let addr = ctx.regs.get(src).wrapping_add(insn.off as i64 as u64);
-                    let val = match insn.size() {
-                        0x00 => unsafe { *(addr as *const u32) as u64 }, // W
-                        0x08 => unsafe { *(addr as *const u16) as u64 }, // H
-                        0x10 => unsafe { *(addr as *const u8) as u64 },  // B
-                        0x18 => unsafe { *(addr as *const u64) },        // DW
-                        _ => return Err(BpfError::InvalidInsn),
-                    };
+                    let addr = ctx.regs.get(src).wrapping_add(off);
+                    let val = ctx.load(addr, insn.size_bytes())?;
                     ctx.regs.set(dst, val);
                 }
 
-                // STX (store to memory)
-                0x03 => {
-                    let addr = ctx.regs.get(dst).wrapping_add(insn.off as i64 as u64);
-                    let val = ctx.regs.get(src);
-                    match insn.size() {
-                        0x00 => unsafe { *(addr as *mut u32) = val as u32 },
-                        0x08 => unsafe { *(addr as *mut u16) = val as u16 },
-                        0x10 => unsafe { *(addr as *mut u8) = val as u8 },
-                        0x18 => unsafe { *(addr as *mut u64) = val },
-                        _ => return Err(BpfError::InvalidInsn),
-                    }
-                }
-
                 // ST (store immediate)
                 0x02 => {
-                    let addr = ctx.regs.get(dst).wrapping_add(insn.off as i64 as u64);
-                    match insn.size() {
-                        0x00 => unsafe { *(addr as *mut u32) = imm as u32 },
-                        0x08 => unsafe { *(addr as *mut u16) = imm as u16 },
-                        0x10 => unsafe { *(addr as *mut u8) = imm as u8 },
-                        0x18 => unsafe { *(addr as *mut u64) = imm },
-                        _ => return Err(BpfError::InvalidInsn),
+                    let addr = ctx.regs.get(dst).wrapping_add(off);
+                    ctx.store(addr, insn.size_bytes(), imm)?;
+                }
+
+                // STX (store from register, atomics)
+                0x03 => {
+                    let addr = ctx.regs.get(dst).wrapping_add(off);
+                    if insn.mode() == BPF_ATOMIC {
+                        Self::atomic(ctx, insn, addr)?;
+                    } else {
+                        ctx.store(addr, insn.size_bytes(), ctx.regs.get(src))?;
                     }
                 }
 
@@ -962,6 +1525,48 @@ impl BpfVm {
 
         Err(BpfError::NoExit)
     }
+
+    /// Call helper `id` with r1-r5
+    fn call(&self, prog: &BpfProg, ctx: &mut BpfContext<'_>, id: u32) -> Result<u64, BpfError> {
+        let helper = self.helpers.get(&id).ok_or(BpfError::UnknownHelper)?;
+        let r = &ctx.regs;
+        let args = [r.r1, r.r2, r.r3, r.r4, r.r5];
+        helper(prog, ctx, args)
+    }
+
+    /// BPF_ATOMIC: read-modify-write done in one access, under the lock of
+    /// the map value it hits
+    fn atomic(ctx: &mut BpfContext<'_>, insn: &BpfInsn, addr: u64) -> Result<(), BpfError> {
+        let size = insn.size_bytes();
+        let mask = if size == 8 { u64::MAX } else { u32::MAX as u64 };
+        let val = ctx.regs.get(insn.src_reg()) & mask;
+        let expected = ctx.regs.r0 & mask;
+        let op = insn.imm;
+
+        let old = ctx.access(addr, size, true, |mem| {
+            let mut buf = [0u8; 8];
+            buf[..size].copy_from_slice(mem);
+            let old = u64::from_le_bytes(buf);
+            let new = match op & !BPF_FETCH {
+                0x00 => old.wrapping_add(val),
+                0x40 => old | val,
+                0x50 => old & val,
+                0xa0 => old ^ val,
+                0xe0 => val,
+                0xf0 if old == expected => val,
+                _ => old,
+            };
+            mem.copy_from_slice(&new.to_le_bytes()[..size]);
+            old
+        })?;
+
+        if op == BPF_CMPXCHG {
+            ctx.regs.r0 = old;
+        } else if op & BPF_FETCH != 0 {
+            ctx.regs.set(insn.src_reg(), old);
+        }
+        Ok(())
+    }
 }
 
 impl Default for BpfVm {
@@ -970,24 +1575,179 @@ impl Default for BpfVm {
     }
 }
 
+static VM: Once<BpfVm> = Once::new();
+
+/// The VM; it keeps no state between runs, so programs run without
+/// taking the manager lock
+pub fn vm() -> &'static BpfVm {
+    VM.call_once(BpfVm::new)
+}
+
+// ============================================================================
+// Attachment
+// ============================================================================
+
+/// XDP verdicts
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum XdpAction {
+    /// Program error; the frame is dropped
+    Aborted = 0,
+    /// Drop the frame
+    Drop = 1,
+    /// Hand the frame to the network stack
+    Pass = 2,
+    /// Send the (possibly rewritten) frame back out of the interface
+    Tx = 3,
+    /// Redirect to another interface; dropped, there is only one
+    Redirect = 4,
+}
+
+impl XdpAction {
+    fn from_u64(v: u64) -> Self {
+        match v {
+            1 => Self::Drop,
+            2 => Self::Pass,
+            3 => Self::Tx,
+            4 => Self::Redirect,
+            _ => Self::Aborted,
+        }
+    }
+}
+
+/// XDP program of each interface, by ifindex
+static XDP_PROGS: RwLock<BTreeMap<u32, Arc<BpfProg>>> = RwLock::new(BTreeMap::new());
+
+/// Run the XDP program of interface `ifindex` on a received frame
+///
+/// Frames pass when no program is attached.
+pub fn run_xdp(ifindex: u32, frame: &mut [u8]) -> XdpAction {
+    let prog = match XDP_PROGS.read().get(&ifindex) {
+        Some(prog) => prog.clone(),
+        None => return XdpAction::Pass,
+    };
+    let mut ctx = BpfContext::xdp(frame, ifindex);
+    match vm().run(&prog, &mut ctx) {
+        Ok(verdict) => XdpAction::from_u64(verdict),
+        Err(_) => XdpAction::Aborted,
+    }
+}
+
+/// Run socket filter `prog` on a packet about to be delivered to a socket
+///
+/// Returns how many bytes of the packet to keep; 0 drops it.
+pub fn run_socket_filter(prog: &BpfProg, packet: &mut [u8], protocol: u16, ifindex: u32) -> usize {
+    let len = packet.len();
+    let mut ctx = BpfContext::socket_filter(packet, protocol, ifindex);
+    match vm().run(prog, &mut ctx) {
+        Ok(keep) => (keep as u32 as usize).min(len),
+        Err(_) => 0,
+    }
+}
+
+/// Run tracepoint program `prog` on an event record
+pub fn run_tracepoint(prog: &BpfProg, record: &[u8]) {
+    let mut ctx = BpfContext::tracepoint(record);
+    let _ = vm().run(prog, &mut ctx);
+}
+
+/// What a link attaches its program to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BpfAttachPoint {
+    /// XDP hook of an interface
+    Xdp { ifindex: u32 },
+    /// ftrace event, "subsystem:name"
+    Tracepoint { name: String },
+}
+
+/// A program attached to a hook; dropping the last reference detaches it
+pub struct BpfLink {
+    /// Link ID
+    pub id: u32,
+    /// Attached program
+    pub prog: Arc<BpfProg>,
+    /// Where it is attached
+    pub point: BpfAttachPoint,
+}
+
+static NEXT_LINK_ID: AtomicU32 = AtomicU32::new(1);
+
+impl BpfLink {
+    /// Attach `prog` to `point`
+    ///
+    /// Tracepoints are looked up by "subsystem:name" or by event name
+    /// alone. An interface has at most one XDP program.
+    pub fn attach(prog: Arc<BpfProg>, point: BpfAttachPoint) -> Result<Arc<Self>, BpfError> {
+        let point = match point {
+            BpfAttachPoint::Xdp { ifindex } => {
+                if prog.prog_type != BpfProgType::Xdp {
+                    return Err(BpfError::InvalidProgType);
+                }
+                if ifindex != crate::net::ETH0_IFINDEX {
+                    return Err(BpfError::NotFound);
+                }
+                let mut xdp = XDP_PROGS.write();
+                if xdp.contains_key(&ifindex) {
+                    return Err(BpfError::AlreadyAttached);
+                }
+                xdp.insert(ifindex, prog.clone());
+                BpfAttachPoint::Xdp { ifindex }
+            }
+            BpfAttachPoint::Tracepoint { name } => {
+                if !matches!(prog.prog_type, BpfProgType::Tracepoint | BpfProgType::RawTracepoint) {
+                    return Err(BpfError::InvalidProgType);
+                }
+                let name = ftrace::attach_bpf(&name, prog.clone()).ok_or(BpfError::NotFound)?;
+                BpfAttachPoint::Tracepoint { name }
+            }
+        };
+
+        prog.set_attached(true);
+        Ok(Arc::new(Self {
+            id: NEXT_LINK_ID.fetch_add(1, Ordering::Relaxed),
+            prog,
+            point,
+        }))
+    }
+}
+
+impl Drop for BpfLink {
+    fn drop(&mut self) {
+        match &self.point {
+            BpfAttachPoint::Xdp { ifindex } => {
+                XDP_PROGS.write().remove(ifindex);
+            }
+            BpfAttachPoint::Tracepoint { name } => ftrace::detach_bpf(name, &self.prog),
+        }
+    }
+}
+
+/// Object behind a bpf fd or a bpffs pin
+#[derive(Clone)]
+pub enum BpfObject {
+    Map(Arc<BpfMap>),
+    Prog(Arc<BpfProg>),
+    Link(Arc<BpfLink>),
+}
+
 // ============================================================================
 // eBPF Subsystem Manager
 // ============================================================================
 
 /// eBPF subsystem manager
+///
+/// Objects live as long as something holds them (an fd, a pin, a link or
+/// a program using a map); the registries only keep weak references.
 pub struct BpfManager {
     /// Programs by ID
-    progs: BTreeMap<u32, Arc<BpfProg>>,
+    progs: BTreeMap<u32, Weak<BpfProg>>,
     /// Maps by ID
-    maps: BTreeMap<u32, Arc<BpfMap>>,
+    maps: BTreeMap<u32, Weak<BpfMap>>,
     /// Next program ID
     next_prog_id: AtomicU32,
     /// Next map ID
     next_map_id: AtomicU32,
     /// Verifier
     verifier: BpfVerifier,
-    /// VM
-    vm: BpfVm,
     /// Enabled
     enabled: AtomicBool,
 }
@@ -1001,7 +1761,6 @@ impl BpfManager {
             next_prog_id: AtomicU32::new(1),
             next_map_id: AtomicU32::new(1),
             verifier: BpfVerifier::new(),
-            vm: BpfVm::new(),
             enabled: AtomicBool::new(true),
         }
     }
@@ -1015,47 +1774,61 @@ impl BpfManager {
         max_entries: u32,
         flags: u32,
         name: &str,
-    ) -> Result<u32, BpfError> {
+    ) -> Result<Arc<BpfMap>, BpfError> {
+        BpfMap::validate(map_type, key_size, value_size, max_entries)?;
+        self.maps.retain(|_, m| m.strong_count() > 0);
+
         let id = self.next_map_id.fetch_add(1, Ordering::Relaxed);
-        let map = BpfMap::new(id, map_type, key_size, value_size, max_entries, flags, name);
-        self.maps.insert(id, Arc::new(map));
-        Ok(id)
+        let map = Arc::new(BpfMap::new(id, map_type, key_size, value_size, max_entries, flags, name));
+        self.maps.insert(id, Arc::downgrade(&map));
+        Ok(map)
     }
 
     /// Get map by ID
     pub fn get_map(&self, id: u32) -> Option<Arc<BpfMap>> {
-        self.maps.get(&id).cloned()
+        self.maps.get(&id)?.upgrade()
     }
 
     /// Load a program
+    ///
+    /// `maps` are the maps the program refers to with `lddw rX, map`, by
+    /// index. Rejected programs return the verifier log.
     pub fn load_prog(
         &mut self,
         prog_type: BpfProgType,
         name: &str,
         insns: Vec<BpfInsn>,
         license: &str,
-    ) -> Result<u32, BpfError> {
+        maps: Vec<Arc<BpfMap>>,
+    ) -> Result<Arc<BpfProg>, BpfError> {
+        if !self.enabled.load(Ordering::Relaxed) {
+            return Err(BpfError::PermissionDenied);
+        }
+
         let id = self.next_prog_id.fetch_add(1, Ordering::Relaxed);
-        let prog = BpfProg::new(id, prog_type, name, insns, license);
+        let mut prog = BpfProg::new(id, prog_type, name, insns, license);
+        prog.maps = maps;
 
         // Verify program
         let result = self.verifier.verify(&prog);
         if !result.success {
-            return Err(BpfError::VerificationFailed(
-                result.error.unwrap_or_default(),
-            ));
+            return Err(BpfError::VerificationFailed(result.log));
         }
 
-        self.progs.insert(id, Arc::new(prog));
-        Ok(id)
+        self.progs.retain(|_, p| p.strong_count() > 0);
+        let prog = Arc::new(prog);
+        self.progs.insert(id, Arc::downgrade(&prog));
+        Ok(prog)
     }
 
     /// Get program by ID
     pub fn get_prog(&self, id: u32) -> Option<Arc<BpfProg>> {
-        self.progs.get(&id).cloned()
+        self.progs.get(&id)?.upgrade()
     }
 
     /// Unload program
+    ///
+    /// Holders of the program keep it until they drop it.
     pub fn unload_prog(&mut self, id: u32) -> Result<(), BpfError> {
         self.progs.remove(&id).ok_or(BpfError::NotFound)?;
         Ok(())
@@ -1068,19 +1841,27 @@ impl BpfManager {
     }
 
     /// Run program
-    pub fn run_prog(&self, id: u32, ctx: &mut BpfContext) -> Result<u64, BpfError> {
-        let prog = self.progs.get(&id).ok_or(BpfError::NotFound)?;
-        self.vm.run(prog, ctx)
+    pub fn run_prog(&self, id: u32, ctx: &mut BpfContext<'_>) -> Result<u64, BpfError> {
+        let prog = self.get_prog(id).ok_or(BpfError::NotFound)?;
+        vm().run(&prog, ctx)
     }
 
     /// List programs
     pub fn list_progs(&self) -> Vec<u32> {
-        self.progs.keys().copied().collect()
+        self.progs
+            .iter()
+            .filter(|(_, p)| p.strong_count() > 0)
+            .map(|(id, _)| *id)
+            .collect()
     }
 
     /// List maps
     pub fn list_maps(&self) -> Vec<u32> {
-        self.maps.keys().copied().collect()
+        self.maps
+            .iter()
+            .filter(|(_, m)| m.strong_count() > 0)
+            .map(|(id, _)| *id)
+            .collect()
     }
 }
 
@@ -1109,16 +1890,43 @@ pub enum BpfError {
     InvalidKey,
     /// Invalid value
     InvalidValue,
+    /// Invalid update flags
+    InvalidFlags,
+    /// Memory access outside the regions of the run
+    InvalidAccess(u64),
+    /// Program type doesn't fit the hook
+    InvalidProgType,
     /// Map full
     MapFull,
     /// Not found
     NotFound,
-    /// Verification failed
+    /// Key already present (BPF_NOEXIST)
+    AlreadyExists,
+    /// Verification failed; carries the verifier log
     VerificationFailed(String),
     /// Permission denied
     PermissionDenied,
     /// Already attached
     AlreadyAttached,
+    /// Map or program type not implemented
+    NotSupported,
+}
+
+impl BpfError {
+    /// Negative errno for bpf(2) and helper return values
+    pub fn errno(&self) -> i64 {
+        match self {
+            BpfError::MapFull => errno::E2BIG,
+            BpfError::NotFound => errno::ENOENT,
+            BpfError::AlreadyExists => errno::EEXIST,
+            BpfError::VerificationFailed(_) => errno::EACCES,
+            BpfError::PermissionDenied => errno::EPERM,
+            BpfError::AlreadyAttached => errno::EBUSY,
+            BpfError::NotSupported => errno::EOPNOTSUPP,
+            BpfError::InvalidAccess(_) => errno::EFAULT,
+            _ => errno::EINVAL,
+        }
+    }
 }
 
 // ============================================================================
@@ -1144,7 +1952,7 @@ pub fn bpf_create_map(
     key_size: u32,
     value_size: u32,
     max_entries: u32,
-) -> Result<u32, BpfError> {
+) -> Result<Arc<BpfMap>, BpfError> {
     manager().lock().create_map(map_type, key_size, value_size, max_entries, 0, "")
 }
 
@@ -1153,8 +1961,9 @@ pub fn bpf_prog_load(
     prog_type: BpfProgType,
     insns: Vec<BpfInsn>,
     license: &str,
-) -> Result<u32, BpfError> {
-    manager().lock().load_prog(prog_type, "", insns, license)
+    maps: Vec<Arc<BpfMap>>,
+) -> Result<Arc<BpfProg>, BpfError> {
+    manager().lock().load_prog(prog_type, "", insns, license, maps)
 }
 
 /// Map lookup
@@ -1164,7 +1973,7 @@ pub fn bpf_map_lookup_elem(map_id: u32, key: &[u8]) -> Option<Vec<u8>> {
 
 /// Map update
 pub fn bpf_map_update_elem(map_id: u32, key: &[u8], value: &[u8]) -> Result<(), BpfError> {
-    manager().lock().get_map(map_id).ok_or(BpfError::NotFound)?.update(key, value, 0)
+    manager().lock().get_map(map_id).ok_or(BpfError::NotFound)?.update(key, value, BPF_ANY)
 }
 
 /// Map delete
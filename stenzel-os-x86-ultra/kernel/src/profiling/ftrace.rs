@@ -11,17 +11,21 @@
 //! - Trace triggers
 //! - Stack traces
 //! - Latency tracing
+//! - eBPF programs attached to tracepoints
 
 #![allow(dead_code)]
 
 use alloc::collections::BTreeMap;
 use alloc::format;
 use alloc::string::{String, ToString};
+use alloc::sync::Arc;
 use alloc::vec;
 use alloc::vec::Vec;
 use core::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
 use spin::{Mutex, Once, RwLock};
 
+use super::ebpf::{self, BpfProg};
+
 // ============================================================================
 // Trace Entry Types
 // ============================================================================
@@ -564,6 +568,10 @@ pub struct FtraceManager {
     filters: RwLock<Vec<TraceFilter>>,
     /// Total entries recorded
     total_entries: AtomicU64,
+    /// eBPF programs attached to tracepoints, by "subsystem:name"
+    bpf_progs: RwLock<BTreeMap<String, Vec<Arc<BpfProg>>>>,
+    /// Number of attached eBPF programs
+    bpf_attached: AtomicUsize,
 }
 
 impl FtraceManager {
@@ -583,6 +591,8 @@ impl FtraceManager {
             event_trace_enabled: AtomicBool::new(true),
             filters: RwLock::new(Vec::new()),
             total_entries: AtomicU64::new(0),
+            bpf_progs: RwLock::new(BTreeMap::new()),
+            bpf_attached: AtomicUsize::new(0),
         };
 
         // Register built-in tracepoints
@@ -734,8 +744,58 @@ impl FtraceManager {
         self.write_entry(cpu, entry);
     }
 
+    /// Attach an eBPF program to a tracepoint, named "subsystem:name" or
+    /// just "name"; returns the full name, or None if there is no such
+    /// tracepoint
+    pub fn attach_bpf(&self, name: &str, prog: Arc<BpfProg>) -> Option<String> {
+        let full_name = self
+            .tracepoints
+            .read()
+            .iter()
+            .find(|(full, tp)| full.as_str() == name || tp.name == name)
+            .map(|(full, _)| full.clone())?;
+
+        self.bpf_progs.write().entry(full_name.clone()).or_default().push(prog);
+        self.bpf_attached.fetch_add(1, Ordering::Relaxed);
+        Some(full_name)
+    }
+
+    /// Detach an eBPF program from tracepoint `full_name`
+    pub fn detach_bpf(&self, full_name: &str, prog: &Arc<BpfProg>) {
+        let mut progs = self.bpf_progs.write();
+        if let Some(list) = progs.get_mut(full_name) {
+            if let Some(pos) = list.iter().position(|p| Arc::ptr_eq(p, prog)) {
+                list.remove(pos);
+                self.bpf_attached.fetch_sub(1, Ordering::Relaxed);
+            }
+            if list.is_empty() {
+                progs.remove(full_name);
+            }
+        }
+    }
+
+    /// Run the eBPF programs attached to a tracepoint on its record
+    fn run_bpf(&self, full_name: &str, data: &[u8]) {
+        // Programs may end up back here (bpf_trace_printk), so run them
+        // without holding the lock
+        let progs = match self.bpf_progs.read().get(full_name) {
+            Some(list) => list.clone(),
+            None => return,
+        };
+        for prog in &progs {
+            ebpf::run_tracepoint(prog, data);
+        }
+    }
+
     /// Trace tracepoint hit
+    ///
+    /// Attached eBPF programs run whether or not the event is traced.
     pub fn trace_event(&self, cpu: u32, pid: u32, subsystem: &str, name: &str, data: Vec<u8>) {
+        let full_name = format!("{}:{}", subsystem, name);
+        if self.bpf_attached.load(Ordering::Relaxed) > 0 {
+            self.run_bpf(&full_name, &data);
+        }
+
         if !self.enabled.load(Ordering::Relaxed) {
             return;
         }
@@ -744,7 +804,6 @@ impl FtraceManager {
         }
 
         // Check if tracepoint is enabled
-        let full_name = format!("{}:{}", subsystem, name);
         {
             let tps = self.tracepoints.read();
             if let Some(tp) = tps.get(&full_name) {
@@ -913,8 +972,10 @@ static FTRACE: Once<FtraceManager> = Once::new();
 
 /// Initialize ftrace subsystem
 pub fn init() {
-    FTRACE.call_once(|| FtraceManager::new(1, 65536));
-    crate::kprintln!("ftrace: initialized");
+    FTRACE.call_once(|| {
+        crate::kprintln!("ftrace: initialized");
+        FtraceManager::new(1, 65536)
+    });
 }
 
 /// Get ftrace manager
@@ -982,6 +1043,32 @@ pub fn trace_func(func_addr: u64, parent_addr: u64) {
 }
 
 /// Trace event (for use from other modules)
+///
+/// Does nothing until ftrace is initialized.
 pub fn trace_event(subsystem: &str, name: &str, data: Vec<u8>) {
-    manager().trace_event(0, 0, subsystem, name, data);
+    if let Some(ftrace) = FTRACE.get() {
+        let pid = crate::sched::try_current_task().map(|t| t.id() as u32).unwrap_or(0);
+        ftrace.trace_event(0, pid, subsystem, name, data);
+    }
+}
+
+/// Attach an eBPF program to a tracepoint; see `FtraceManager::attach_bpf`
+pub fn attach_bpf(name: &str, prog: Arc<BpfProg>) -> Option<String> {
+    init();
+    manager().attach_bpf(name, prog)
+}
+
+/// Detach an eBPF program from a tracepoint
+pub fn detach_bpf(full_name: &str, prog: &Arc<BpfProg>) {
+    if let Some(ftrace) = FTRACE.get() {
+        ftrace.detach_bpf(full_name, prog);
+    }
+}
+
+/// Output of bpf_trace_printk
+pub fn trace_printk(message: &str) {
+    if let Some(ftrace) = FTRACE.get() {
+        let pid = crate::sched::try_current_task().map(|t| t.id() as u32).unwrap_or(0);
+        ftrace.trace_print(0, pid, message);
+    }
 }
@@ -10,6 +10,7 @@
 
 pub mod perf;
 pub mod ebpf;
+pub mod bpf_syscall;
 pub mod ftrace;
 pub mod cpuprof;
 pub mod memprof;
@@ -139,6 +139,11 @@ impl SchedEntity {
     pub fn sum_exec_runtime(&self) -> u64 {
         self.sum_exec_runtime.load(AtomicOrdering::Relaxed)
     }
+
+    /// Charge `delta_ns` of CPU time (CLOCK_*_CPUTIME_ID)
+    pub fn add_exec_runtime(&self, delta_ns: u64) {
+        self.sum_exec_runtime.fetch_add(delta_ns, AtomicOrdering::Relaxed);
+    }
 }
 
 /// CFS run queue
@@ -287,6 +292,11 @@ impl CfsRunqueue {
         Some(entry)
     }
 
+    /// Whether a task is queued
+    pub fn contains(&self, task_id: u64) -> bool {
+        self.tasks.iter().any(|e| e.task_id == task_id)
+    }
+
     /// Remove a specific task by ID
     pub fn remove(&mut self, task_id: u64) -> Option<CfsEntry> {
         if let Some(idx) = self.tasks.iter().position(|e| e.task_id == task_id) {
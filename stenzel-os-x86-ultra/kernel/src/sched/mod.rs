@@ -25,7 +25,7 @@ use alloc::vec::Vec;
 
 use core::cell::UnsafeCell;
 use core::ptr;
-use core::sync::atomic::{AtomicPtr, AtomicU64, Ordering};
+use core::sync::atomic::{AtomicBool, AtomicPtr, AtomicU64, Ordering};
 
 use spin::Once;
 
@@ -37,6 +37,8 @@ use x86_64::{PhysAddr, VirtAddr};
 
 use crate::arch::x86_64_arch::{gdt, syscall};
 use crate::arch::x86_64_arch::interrupts::TrapFrame;
+use crate::fs::{Chroot, Vfs};
+use crate::ipc::namespace::MountNamespace;
 use crate::mm;
 use crate::security::Cred;
 use crate::signal::{SignalState, SignalHandlers};
@@ -57,6 +59,7 @@ enum TaskState {
     Ready,
     Running,
     Blocked,  // Aguardando evento (wait, etc)
+    Sleeping, // Dormindo até um timer ou signal (sched::sleep)
     Zombie,   // Terminado, aguardando wait do pai
     Exited,   // Terminado e pode ser removido
 }
@@ -84,9 +87,9 @@ pub struct Task {
     /// Thread group ID (processo principal)
     tgid: u64,
     /// Process Group ID
-    pgid: u64,
+    pgid: UnsafeCell<u64>,
     /// Session ID
-    sid: u64,
+    sid: UnsafeCell<u64>,
     name: String,
     state: UnsafeCell<TaskState>,
     exit_status: UnsafeCell<i32>,
@@ -98,6 +101,8 @@ pub struct Task {
     // Root do page table (CR3) se user task.
     // Wrapped in UnsafeCell so it can be updated by exec_replace.
     cr3: UnsafeCell<PhysFrame<Size4KiB>>,
+    // VMAs (mmap) do address space; compartilhadas entre threads (CLONE_VM).
+    vmas: UnsafeCell<crate::mm::vma::Vmas>,
     // TrapFrame salva quando o task é preemptado.
     saved_tf: UnsafeCell<*mut TrapFrame>,
     // Credenciais mínimas
@@ -112,6 +117,10 @@ pub struct Task {
     user_gs_base: UnsafeCell<u64>,
     /// Current working directory
     cwd: UnsafeCell<String>,
+    /// Mount namespace
+    mnt_ns: UnsafeCell<Arc<MountNamespace>>,
+    /// Root directory set by chroot (None: root of the mount namespace)
+    fs_root: UnsafeCell<Option<Arc<Chroot>>>,
     /// Scheduling priority (nice value): -20 (highest) to +19 (lowest), default 0
     priority: UnsafeCell<i8>,
     /// Resource limits for the process
@@ -128,6 +137,10 @@ pub struct Task {
     process_caps: UnsafeCell<crate::security::ProcessCaps>,
     /// Seccomp state (syscall filtering)
     seccomp: UnsafeCell<crate::security::SeccompState>,
+    /// PR_SET_NO_NEW_PRIVS: execve can't grant privileges (sticky, inherited)
+    no_new_privs: UnsafeCell<bool>,
+    /// File descriptor table (shared with CLONE_FILES)
+    files: UnsafeCell<crate::syscall::Files>,
 }
 
 unsafe impl Send for Task {}
@@ -155,6 +168,10 @@ impl Task {
     fn set_exit_status(&self, status: i32) {
         unsafe { *self.exit_status.get() = status; }
     }
+    /// Se é um task de usuário (ring 3)
+    pub fn is_user(&self) -> bool {
+        self.is_user
+    }
     pub fn signals(&self) -> &SignalState {
         &self.signals
     }
@@ -181,12 +198,50 @@ impl Task {
     pub fn set_cwd(&self, path: String) {
         unsafe { *self.cwd.get() = path; }
     }
+    /// Returns the mount namespace of the task
+    pub fn mnt_ns(&self) -> Arc<MountNamespace> {
+        unsafe { (*self.mnt_ns.get()).clone() }
+    }
+    /// Moves the task to another mount namespace
+    pub fn set_mnt_ns(&self, ns: Arc<MountNamespace>) {
+        unsafe { *self.mnt_ns.get() = ns; }
+    }
+    /// Returns the root directory set by chroot, if any
+    pub fn fs_root(&self) -> Option<Arc<Chroot>> {
+        unsafe { (*self.fs_root.get()).clone() }
+    }
+    /// Sets the root directory (None: root of the mount namespace)
+    pub fn set_fs_root(&self, root: Option<Arc<Chroot>>) {
+        unsafe { *self.fs_root.get() = root; }
+    }
+    /// Mount table the task resolves paths in
+    pub fn vfs_table(&self) -> Arc<IrqSafeMutex<Vfs>> {
+        match self.fs_root() {
+            Some(root) => root.table(),
+            None => self.mnt_ns().table(),
+        }
+    }
     fn set_cr3(&self, frame: PhysFrame<Size4KiB>) {
         unsafe { *self.cr3.get() = frame; }
     }
+    /// VMAs do address space do task
+    pub fn vmas(&self) -> crate::mm::vma::Vmas {
+        unsafe { (*self.vmas.get()).clone() }
+    }
+    /// Troca as VMAs do task, retornando as antigas
+    fn replace_vmas(&self, vmas: crate::mm::vma::Vmas) -> crate::mm::vma::Vmas {
+        unsafe { core::mem::replace(&mut *self.vmas.get(), vmas) }
+    }
     pub fn id(&self) -> u64 {
         self.id
     }
+    /// Id do grupo de threads (o pid visto pelo usuário)
+    pub fn tgid(&self) -> u64 {
+        self.tgid
+    }
+    pub fn name(&self) -> &str {
+        &self.name
+    }
     pub fn parent_id(&self) -> u64 {
         unsafe { *self.parent_id.get() }
     }
@@ -194,10 +249,24 @@ impl Task {
         unsafe { *self.parent_id.get() = pid; }
     }
     pub fn pgid(&self) -> u64 {
-        self.pgid
+        unsafe { *self.pgid.get() }
+    }
+    fn set_pgid(&self, pgid: u64) {
+        unsafe { *self.pgid.get() = pgid; }
     }
     pub fn sid(&self) -> u64 {
-        self.sid
+        unsafe { *self.sid.get() }
+    }
+    fn set_sid(&self, sid: u64) {
+        unsafe { *self.sid.get() = sid; }
+    }
+    /// Returns the file descriptor table of the task
+    pub fn files(&self) -> crate::syscall::Files {
+        unsafe { (*self.files.get()).clone() }
+    }
+    /// Detaches the task from its file descriptor table, returning it
+    fn take_files(&self) -> crate::syscall::Files {
+        unsafe { core::mem::replace(&mut *self.files.get(), crate::syscall::init_files()) }
     }
     /// Returns the task's scheduling priority (nice value)
     pub fn priority(&self) -> i8 {
@@ -289,6 +358,14 @@ impl Task {
     pub fn set_seccomp_state(&self, state: crate::security::SeccompState) {
         unsafe { *self.seccomp.get() = state; }
     }
+    /// Whether execve is barred from granting privileges
+    pub fn no_new_privs(&self) -> bool {
+        unsafe { *self.no_new_privs.get() }
+    }
+    /// Set NO_NEW_PRIVS (can't be cleared)
+    pub fn set_no_new_privs(&self) {
+        unsafe { *self.no_new_privs.get() = true; }
+    }
 }
 
 struct Scheduler {
@@ -304,11 +381,51 @@ struct Scheduler {
     clock_ticks: u64,
     /// Nanoseconds per tick (assuming ~1000 Hz = 1ms per tick)
     ns_per_tick: u64,
+    /// Tasks retidos pelo cgroup (quota do cpu.max esgotada ou freezer):
+    /// ficam na runq mas fora do CFS. Guarda o id e desde quando.
+    throttled: Vec<(u64, u64)>,
+}
+
+impl Scheduler {
+    fn is_throttled(&self, id: u64) -> bool {
+        self.throttled.iter().any(|&(t, _)| t == id)
+    }
+
+    /// Se o task pode ser escolhido pelo scheduler
+    fn can_pick(&self, t: &Task) -> bool {
+        t.state() == TaskState::Ready && !self.is_throttled(t.id)
+    }
+
+    /// Devolve ao CFS os tasks retidos cujo cgroup já pode rodar (novo
+    /// período do cpu.max ou cgroup descongelado)
+    fn unthrottle(&mut self, now_ns: u64) {
+        let mut i = 0;
+        while i < self.throttled.len() {
+            let (id, since) = self.throttled[i];
+            if !crate::cgroups::sched_check_cgroup(crate::process::Pid(id), now_ns) {
+                i += 1;
+                continue;
+            }
+            self.throttled.swap_remove(i);
+            crate::cgroups::sched_unthrottle(crate::process::Pid(id), now_ns.saturating_sub(since));
+            if let Some(t) = self.runq.iter().find(|t| t.id == id) {
+                let se = t.sched_entity();
+                self.cfs_rq.enqueue(CfsEntry {
+                    task_id: id,
+                    vruntime: se.vruntime(),
+                    weight: se.weight(),
+                });
+            }
+        }
+    }
 }
 
 static SCHED: Once<IrqSafeMutex<Scheduler>> = Once::new();
 static CURRENT_PTR: AtomicPtr<Task> = AtomicPtr::new(ptr::null_mut());
 static NEXT_ID: AtomicU64 = AtomicU64::new(2); // 0=idle, 1=init, next=2
+/// Um task acordou: o idle (ou um kernel thread) deve ceder a CPU já na
+/// próxima interrupção do timer, sem esperar o tick
+static NEED_RESCHED: AtomicBool = AtomicBool::new(false);
 
 /// Inicializa scheduler e cria processos user (ring3).
 ///
@@ -324,8 +441,8 @@ fn init_userspace() {
         id: 0,
         parent_id: UnsafeCell::new(0),
         tgid: 0,
-        pgid: 0,
-        sid: 0,
+        pgid: UnsafeCell::new(0),
+        sid: UnsafeCell::new(0),
         name: "idle".into(),
         state: UnsafeCell::new(TaskState::Running),
         exit_status: UnsafeCell::new(0),
@@ -333,6 +450,7 @@ fn init_userspace() {
         is_thread: false,
         kstack: KernelStack::new(0), // não usado (usa a stack atual)
         cr3: UnsafeCell::new(current_cr3),
+        vmas: UnsafeCell::new(crate::mm::vma::kernel_vmas()),
         saved_tf: UnsafeCell::new(ptr::null_mut()),
         cred: UnsafeCell::new(crate::security::Cred::root()),
         signals: SignalState::new(),
@@ -340,6 +458,8 @@ fn init_userspace() {
         clear_child_tid: UnsafeCell::new(0),
         user_gs_base: UnsafeCell::new(0),
         cwd: UnsafeCell::new(String::from("/")),
+        mnt_ns: UnsafeCell::new(crate::fs::init_mnt_ns()),
+        fs_root: UnsafeCell::new(None),
         priority: UnsafeCell::new(0),
         rlimits: UnsafeCell::new(crate::syscall::ResourceLimits::default()),
         sched_entity: UnsafeCell::new(SchedEntity::new(0)),
@@ -348,6 +468,8 @@ fn init_userspace() {
         cpu_allowed: UnsafeCell::new(CpuMask::all()),
         process_caps: UnsafeCell::new(crate::security::ProcessCaps::root()),
         seccomp: UnsafeCell::new(crate::security::SeccompState::new()),
+        no_new_privs: UnsafeCell::new(false),
+        files: UnsafeCell::new(crate::syscall::init_files()),
     });
 
     // Cria 2 user tasks para demonstrar preempção.
@@ -386,6 +508,7 @@ fn init_userspace() {
         rt_rq: RtRunqueue::new(),
         clock_ticks: 0,
         ns_per_tick: 1_000_000, // 1ms per tick (assuming ~1000 Hz timer)
+        throttled: Vec::new(),
     };
 
     SCHED.call_once(|| IrqSafeMutex::new(sched));
@@ -413,6 +536,12 @@ pub fn current_task() -> &'static Task {
     unsafe { &*p }
 }
 
+/// Like `current_task`, but None until the scheduler is up.
+pub fn try_current_task() -> Option<&'static Task> {
+    let p = CURRENT_PTR.load(Ordering::Acquire);
+    unsafe { p.as_ref() }
+}
+
 pub fn current_cred() -> Cred {
     current_task().cred()
 }
@@ -420,6 +549,17 @@ pub fn current_cred() -> Cred {
 /// Chamado no IRQ0. Pode devolver uma TrapFrame diferente para retomar outro task.
 /// Usa CFS-like scheduling baseado em vruntime.
 pub fn on_timer_tick(tf: &mut TrapFrame) -> *mut TrapFrame {
+    timer_interrupt(tf, true)
+}
+
+/// Interrupção do timer one-shot que não é um tick (só disparou hrtimers):
+/// não contabiliza tempo, mas troca de task se o atual foi dormir ou se o
+/// idle tem alguém acordado para rodar.
+pub fn on_timer_event(tf: &mut TrapFrame) -> *mut TrapFrame {
+    timer_interrupt(tf, false)
+}
+
+fn timer_interrupt(tf: &mut TrapFrame, tick: bool) -> *mut TrapFrame {
     // Se o scheduler não foi inicializado, apenas retorna o TrapFrame atual
     let Some(sched_lock) = SCHED.get() else {
         return tf as *mut TrapFrame;
@@ -440,36 +580,64 @@ pub fn on_timer_tick(tf: &mut TrapFrame) -> *mut TrapFrame {
         }
     }
 
-    // CFS: Update clock and vruntime for current task
-    sched.clock_ticks += 1;
-    let delta_ns = sched.ns_per_tick;
-    sched.cfs_rq.update_clock(delta_ns);
+    let now_ns = crate::time::uptime_ns();
+    let mut throttle = false;
+    let mut ticks_run = 0;
+    let mut time_slice = 0;
+    if tick {
+        // CFS: Update clock and vruntime for current task
+        sched.clock_ticks += 1;
+        let delta_ns = sched.ns_per_tick;
+        sched.cfs_rq.update_clock(delta_ns);
+
+        // Increment ticks run for current task
+        cur.incr_ticks_run();
+        ticks_run = cur.ticks_run();
+        if cur.state() == TaskState::Running {
+            cur.sched_entity().add_exec_runtime(delta_ns);
+        }
+
+        // Update vruntime for current task (if it's a runnable user task)
+        if cur.is_user && cur.state() == TaskState::Running {
+            let se = cur.sched_entity();
+            let delta_vruntime = CfsRunqueue::calc_delta_vruntime(
+                delta_ns,
+                se.weight(),
+                cfs::wmult_from_nice(cur.priority()),
+            );
+            let new_vruntime = se.vruntime().saturating_add(delta_vruntime);
+            se.set_vruntime(new_vruntime);
 
-    // Increment ticks run for current task
-    cur.incr_ticks_run();
-    let ticks_run = cur.ticks_run();
+            // Record statistics
+            cfs::record_runtime(delta_ns);
+        }
 
-    // Update vruntime for current task (if it's a runnable user task)
-    if cur.is_user && cur.state() == TaskState::Running {
-        let se = cur.sched_entity();
-        let delta_vruntime = CfsRunqueue::calc_delta_vruntime(
-            delta_ns,
-            se.weight(),
-            cfs::wmult_from_nice(cur.priority()),
-        );
-        let new_vruntime = se.vruntime().saturating_add(delta_vruntime);
-        se.set_vruntime(new_vruntime);
+        // cgroup v2: o tick conta para o cpu.max do cgroup do task; com a quota
+        // do período esgotada (ou o cgroup congelado) ele sai do CFS até poder
+        // rodar de novo
+        if cur.is_user && cur.state() == TaskState::Running {
+            let pid = crate::process::Pid(cur.id);
+            crate::cgroups::sched_charge_cpu(pid, delta_ns, now_ns);
+            throttle = !crate::cgroups::sched_check_cgroup(pid, now_ns);
+        }
+        sched.unthrottle(now_ns);
 
-        // Record statistics
-        cfs::record_runtime(delta_ns);
+        // CFS: Calculate time slice and check if preemption is needed
+        time_slice = sched.cfs_rq.calc_time_slice(cur.sched_entity().weight());
+        cur.sched_entity().set_time_slice(time_slice);
     }
 
-    // CFS: Calculate time slice and check if preemption is needed
-    let time_slice = sched.cfs_rq.calc_time_slice(cur.sched_entity().weight());
-    cur.sched_entity().set_time_slice(time_slice);
-
     // Check if we should preempt
-    let do_switch = if cur.is_user && cur.state() == TaskState::Running {
+    let off_cpu = matches!(cur.state(), TaskState::Blocked | TaskState::Sleeping);
+    let resched = NEED_RESCHED.swap(false, Ordering::AcqRel);
+    let do_switch = if off_cpu {
+        // Bloqueado ou dormindo: não há o que rodar aqui
+        true
+    } else if !tick {
+        resched && !cur.is_user
+    } else if throttle {
+        true
+    } else if cur.is_user && cur.state() == TaskState::Running {
         sched.cfs_rq.check_preempt_tick(
             cur.sched_entity().vruntime(),
             time_slice,
@@ -477,7 +645,7 @@ pub fn on_timer_tick(tf: &mut TrapFrame) -> *mut TrapFrame {
         )
     } else {
         // Non-user tasks (idle) or non-running: check every few ticks
-        ticks_run >= 8
+        resched || ticks_run >= 8
     };
 
     if !do_switch {
@@ -493,7 +661,9 @@ pub fn on_timer_tick(tf: &mut TrapFrame) -> *mut TrapFrame {
         sched.runq.push_back(cur.clone());
 
         // Re-enqueue to CFS runqueue with updated vruntime
-        if cur.is_user {
+        if throttle {
+            sched.throttled.push((cur.id, now_ns));
+        } else if cur.is_user {
             let se = cur.sched_entity();
             sched.cfs_rq.enqueue(CfsEntry {
                 task_id: cur.id,
@@ -502,15 +672,34 @@ pub fn on_timer_tick(tf: &mut TrapFrame) -> *mut TrapFrame {
             });
             cfs::record_switch(true); // preempted
         }
+    } else if off_cpu && !sched.runq.iter().any(|t| t.id == cur.id) {
+        // Fica na runq (fora do CFS) para ser achado por wake_task,
+        // wait e signals
+        sched.runq.push_back(cur.clone());
     }
 
     // Remove tasks Exited da fila
     sched.runq.retain(|t| t.state() != TaskState::Exited);
 
+    // Tasks acordados de um Blocked (wait, stop) voltam como Ready só na
+    // runq: devolve-os ao CFS
+    let woken: Vec<Arc<Task>> = sched
+        .runq
+        .iter()
+        .filter(|t| t.is_user && sched.can_pick(t) && !sched.cfs_rq.contains(t.id))
+        .cloned()
+        .collect();
+    for t in woken {
+        let se = t.sched_entity();
+        let vruntime = se.vruntime().max(sched.cfs_rq.place_entity(se, false));
+        se.set_vruntime(vruntime);
+        sched.cfs_rq.enqueue(CfsEntry { task_id: t.id, vruntime, weight: se.weight() });
+    }
+
     // CFS: Pick next task with smallest vruntime
     let next = if let Some(entry) = sched.cfs_rq.dequeue_next() {
         // Find the task in runq by ID
-        let idx = sched.runq.iter().position(|t| t.id == entry.task_id && t.state() == TaskState::Ready);
+        let idx = sched.runq.iter().position(|t| t.id == entry.task_id && sched.can_pick(t));
         if let Some(idx) = idx {
             sched.runq.remove(idx)
         } else {
@@ -518,7 +707,7 @@ pub fn on_timer_tick(tf: &mut TrapFrame) -> *mut TrapFrame {
             let mut best_idx: Option<usize> = None;
             let mut best_priority: i8 = i8::MAX;
             for (idx, t) in sched.runq.iter().enumerate() {
-                if t.state() == TaskState::Ready {
+                if sched.can_pick(t) {
                     let prio = t.priority();
                     if prio < best_priority {
                         best_priority = prio;
@@ -537,7 +726,7 @@ pub fn on_timer_tick(tf: &mut TrapFrame) -> *mut TrapFrame {
         let mut best_idx: Option<usize> = None;
         let mut best_priority: i8 = i8::MAX;
         for (idx, t) in sched.runq.iter().enumerate() {
-            if t.state() == TaskState::Ready {
+            if sched.can_pick(t) {
                 let prio = t.priority();
                 if prio < best_priority {
                     best_priority = prio;
@@ -553,7 +742,16 @@ pub fn on_timer_tick(tf: &mut TrapFrame) -> *mut TrapFrame {
     };
 
     let Some(next) = next else {
-        // nada para rodar: continua no current
+        // nada para rodar: continua no current, mesmo que o cgroup o tenha
+        // retido (não há para onde trocar)
+        if throttle {
+            sched.throttled.retain(|&(id, _)| id != cur.id);
+            sched.runq.retain(|t| t.id != cur.id);
+            cur.set_state(TaskState::Running);
+        } else if off_cpu {
+            // Continua como current (em hlt) até ser acordado
+            sched.runq.retain(|t| t.id != cur.id);
+        }
         return tf as *mut TrapFrame;
     };
 
@@ -598,6 +796,15 @@ pub fn on_timer_tick(tf: &mut TrapFrame) -> *mut TrapFrame {
                             );
                             next.set_state(TaskState::Zombie);
                             next.set_exit_status(128 + signum as i32);
+                            crate::cgroups::on_process_exit(crate::process::Pid(next.id));
+                            if next.tgid == next.id {
+                                crate::time::exit_timers(next.tgid);
+                            }
+                            // Volta para a runq para o pai poder fazer wait
+                            sched.runq.push_back(next.clone());
+                            if off_cpu {
+                                sched.runq.retain(|t| t.id != cur.id);
+                            }
 
                             // Notifica o pai
                             for t in sched.runq.iter() {
@@ -615,6 +822,10 @@ pub fn on_timer_tick(tf: &mut TrapFrame) -> *mut TrapFrame {
                         }
                         DefaultAction::Stop => {
                             next.set_state(TaskState::Blocked);
+                            sched.runq.push_back(next.clone());
+                            if off_cpu {
+                                sched.runq.retain(|t| t.id != cur.id);
+                            }
                             return tf as *mut TrapFrame;
                         }
                         DefaultAction::Continue | DefaultAction::Ignore => {
@@ -659,6 +870,14 @@ pub fn on_timer_tick(tf: &mut TrapFrame) -> *mut TrapFrame {
     next_tf
 }
 
+/// Tracepoint sched:sched_process_fork; o registro é o pid do pai e o do
+/// filho (u32 cada)
+fn trace_fork(parent: u64, child: u64) {
+    let mut record = (parent as u32).to_le_bytes().to_vec();
+    record.extend_from_slice(&(child as u32).to_le_bytes());
+    crate::profiling::ftrace::trace_event("sched", "sched_process_fork", record);
+}
+
 /// Syscall exit: marca task atual como Zombie (ou Exited) e troca imediatamente.
 ///
 /// Este caminho **não retorna**.
@@ -667,6 +886,21 @@ pub fn exit_current_and_switch(status: u64) -> ! {
     // IMPORTANTE: precisamos soltar o lock do scheduler antes de fazer o salto
     // (senão a trava nunca é liberada e o sistema congela no próximo IRQ).
 
+    // Fecha os FDs antes de pegar o lock: soltar o último master de um pty
+    // manda SIGHUP, e enviar signals também trava o scheduler.
+    crate::syscall::release_files(current_task().take_files());
+    // Desmapeia os mmaps (a última thread do address space escreve de volta
+    // as páginas sujas de arquivo)
+    crate::mm::vma::release_vmas(current_task().replace_vmas(crate::mm::vma::kernel_vmas()));
+    crate::security::hooks::task_free(current_task().id);
+    crate::cgroups::on_process_exit(crate::process::Pid(current_task().id));
+    if current_task().tgid == current_task().id {
+        crate::time::exit_timers(current_task().id);
+    }
+    let mut record = (current_task().id as u32).to_le_bytes().to_vec();
+    record.extend_from_slice(&(status as i32).to_le_bytes());
+    crate::profiling::ftrace::trace_event("sched", "sched_process_exit", record);
+
     let (next_tf, is_user, next_cr3, next_kstack_top, next_gs_base, clear_tid) = {
         let sched_lock = SCHED.get().expect("sched não inicializado");
         let mut sched = sched_lock.lock();
@@ -873,6 +1107,10 @@ pub fn wait_for_child(pid: i64, status_ptr: u64) -> Result<u64, KError> {
 
             drop(sched); // Libera o lock antes de acessar memória user
 
+            // Task morto por signal não passou pelo exit: solta as VMAs aqui
+            // (fora do lock, o writeback de páginas de arquivo faz I/O)
+            crate::mm::vma::release_vmas(zombie.replace_vmas(crate::mm::vma::kernel_vmas()));
+
             // Escreve o status se o ponteiro for válido
             if status_ptr != 0 {
                 unsafe {
@@ -974,6 +1212,10 @@ pub fn wait_for_child_with_options(pid: i64, status_ptr: u64, options: i32) -> R
 
             drop(sched);
 
+            // Task morto por signal não passou pelo exit: solta as VMAs aqui
+            // (fora do lock, o writeback de páginas de arquivo faz I/O)
+            crate::mm::vma::release_vmas(zombie.replace_vmas(crate::mm::vma::kernel_vmas()));
+
             // Escreve o status se o ponteiro for válido
             if status_ptr != 0 {
                 unsafe {
@@ -1014,9 +1256,9 @@ pub fn wait_for_child_with_options(pid: i64, status_ptr: u64, options: i32) -> R
 /// Envia um signal para um processo pelo PID.
 ///
 /// - pid > 0: envia para o processo específico
-/// - pid == 0: envia para todos os processos no mesmo grupo (não implementado)
+/// - pid == 0: envia para todos os processos no mesmo grupo
 /// - pid == -1: envia para todos os processos (não implementado)
-/// - pid < -1: envia para o grupo -pid (não implementado)
+/// - pid < -1: envia para o grupo -pid
 pub fn send_signal(pid: i64, signum: u32) -> Result<(), KError> {
     use crate::signal::sig;
 
@@ -1024,6 +1266,13 @@ pub fn send_signal(pid: i64, signum: u32) -> Result<(), KError> {
         return Err(KError::Invalid);
     }
 
+    if pid == 0 {
+        return send_signal_to_pgrp(current_task().pgid(), signum);
+    }
+    if pid < -1 {
+        return send_signal_to_pgrp((-pid) as u64, signum);
+    }
+
     let sched_lock = SCHED.get().ok_or(KError::NotSupported)?;
     let sched = sched_lock.lock();
 
@@ -1048,25 +1297,118 @@ pub fn send_signal(pid: i64, signum: u32) -> Result<(), KError> {
 
         found.ok_or(KError::NotFound)?
     } else {
-        // Por enquanto, só suportamos pid > 0
+        // pid == -1 ainda não é suportado
         return Err(KError::NotSupported);
     };
 
     drop(sched); // Libera o lock antes de processar
 
+    may_signal(&target, signum)?;
+    deliver_to(&target, signum);
+    Ok(())
+}
+
+/// Envia um signal gerado pelo próprio kernel (ex.: OOM killer) ao task `pid`.
+/// Não passa pela política MAC.
+pub fn send_kernel_signal(pid: u64, signum: u32) -> Result<(), KError> {
+    use crate::signal::sig;
+
+    if signum == 0 || signum >= sig::NSIG {
+        return Err(KError::Invalid);
+    }
+
+    let sched_lock = SCHED.get().ok_or(KError::NotSupported)?;
+    let target = {
+        let sched = sched_lock.lock();
+        core::iter::once(&sched.current)
+            .chain(sched.runq.iter())
+            .find(|t| t.id == pid)
+            .cloned()
+            .ok_or(KError::NotFound)?
+    };
+
+    deliver_to(&target, signum);
+    Ok(())
+}
+
+/// Consulta a política MAC; signals gerados pelo kernel não passam por ela.
+fn may_signal(target: &Task, signum: u32) -> Result<(), KError> {
+    match try_current_task() {
+        Some(cur) if cur.is_user => crate::security::hooks::task_kill(cur.id, target.id, signum),
+        _ => Ok(()),
+    }
+}
+
+/// Marca o signal como pendente e acorda o task se o signal o interrompe.
+fn deliver_to(target: &Task, signum: u32) {
+    use crate::signal::sig;
+
     // Envia o signal
     target.signals.send(signum);
 
-    // Se o signal é SIGKILL ou SIGTERM e o processo está bloqueado, acorda-o
-    if signum == sig::SIGKILL || signum == sig::SIGTERM || signum == sig::SIGINT {
+    // Signals que encerram o processo o acordam se estiver bloqueado
+    if signum == sig::SIGKILL || signum == sig::SIGTERM || signum == sig::SIGINT || signum == sig::SIGHUP {
         if target.state() == TaskState::Blocked {
             target.set_state(TaskState::Ready);
         }
     }
 
+    // Sono interrompível: qualquer signal não bloqueado acorda
+    if target.state() == TaskState::Sleeping && target.signals.has_pending() {
+        wake_task(target.id);
+    }
+}
+
+/// Envia um signal para todos os processos do grupo `pgid`.
+pub fn send_signal_to_pgrp(pgid: u64, signum: u32) -> Result<(), KError> {
+    use crate::signal::sig;
+
+    if signum == 0 || signum >= sig::NSIG {
+        return Err(KError::Invalid);
+    }
+    if pgid == 0 {
+        return Err(KError::Invalid);
+    }
+
+    let targets: Vec<Arc<Task>> = {
+        let sched_lock = SCHED.get().ok_or(KError::NotSupported)?;
+        let sched = sched_lock.lock();
+        core::iter::once(&sched.current)
+            .chain(sched.runq.iter())
+            .filter(|t| t.pgid() == pgid && t.is_user)
+            .filter(|t| !matches!(t.state(), TaskState::Zombie | TaskState::Exited))
+            .cloned()
+            .collect()
+    };
+
+    if targets.is_empty() {
+        return Err(KError::NotFound);
+    }
+    // Basta um membro do grupo aceitar o signal para o kill ter sucesso
+    let mut delivered = false;
+    for target in &targets {
+        if may_signal(target, signum).is_ok() {
+            deliver_to(target, signum);
+            delivered = true;
+        }
+    }
+    if !delivered {
+        return Err(KError::PermissionDenied);
+    }
     Ok(())
 }
 
+/// Se existe algum processo no grupo `pgid` dentro da sessão `sid`.
+pub fn pgrp_in_session(pgid: u64, sid: u64) -> bool {
+    let Some(sched_lock) = SCHED.get() else {
+        return false;
+    };
+    let sched = sched_lock.lock();
+    core::iter::once(&sched.current)
+        .chain(sched.runq.iter())
+        .any(|t| t.pgid() == pgid && t.sid() == sid && t.state() != TaskState::Exited)
+}
+
 /// Processa signals pendentes para o processo atual.
 ///
 /// Retorna true se o processo deve terminar.
@@ -1314,6 +1656,18 @@ pub fn has_pending_signals() -> bool {
     task.signals.has_pending()
 }
 
+/// Se o signal `signum` já está pendente no task `id`
+pub fn signal_pending_for(id: u64, signum: u32) -> bool {
+    let Some(sched_lock) = SCHED.get() else {
+        return false;
+    };
+    let sched = sched_lock.lock();
+    core::iter::once(&sched.current)
+        .chain(sched.runq.iter())
+        .find(|t| t.id == id)
+        .is_some_and(|t| t.signals.is_pending(signum))
+}
+
 /// Gets the signal mask (blocked signals) for the current task.
 pub fn get_signal_mask() -> u64 {
     let task = current_task();
@@ -1326,11 +1680,104 @@ pub fn set_signal_mask(mask: u64) {
     task.signals.set_blocked_mask(mask);
 }
 
+// ---------------- Sono interrompível ----------------
+//
+// Um task dorme em três passos: `prepare_to_sleep` (com interrupções
+// desligadas, antes de armar quem vai acordá-lo), `sleep`, e de volta
+// Running. Enquanto dorme ele sai da CPU na próxima interrupção do timer e
+// só volta ao CFS por `wake_task`, disparado por um hrtimer ou signal.
+
+impl Scheduler {
+    /// Sleeping -> Ready, de volta ao CFS
+    fn wake_locked(&mut self, task: &Arc<Task>) {
+        if task.state() != TaskState::Sleeping {
+            return;
+        }
+        if Arc::ptr_eq(task, &self.current) {
+            // Ainda não saiu da CPU (está no hlt de `sleep`)
+            task.set_state(TaskState::Running);
+        } else {
+            task.set_state(TaskState::Ready);
+            if task.is_user {
+                // Não acumula crédito de vruntime enquanto dormia
+                let se = task.sched_entity();
+                let vruntime = se.vruntime().max(self.cfs_rq.place_entity(se, false));
+                se.set_vruntime(vruntime);
+                self.cfs_rq.enqueue(CfsEntry {
+                    task_id: task.id,
+                    vruntime,
+                    weight: se.weight(),
+                });
+            }
+        }
+        NEED_RESCHED.store(true, Ordering::Release);
+    }
+}
+
+/// Acorda o task `id` se estiver dormindo
+pub fn wake_task(id: u64) {
+    let Some(sched_lock) = SCHED.get() else {
+        return;
+    };
+    let mut sched = sched_lock.lock();
+    let task = core::iter::once(&sched.current)
+        .chain(sched.runq.iter())
+        .find(|t| t.id == id)
+        .cloned();
+    if let Some(task) = task {
+        sched.wake_locked(&task);
+    }
+}
+
+/// Marca o task atual como dormindo. Deve ser seguido de `sleep` (ou de
+/// `finish_sleep`, se desistir).
+pub fn prepare_to_sleep() {
+    let Some(sched_lock) = SCHED.get() else {
+        return;
+    };
+    let sched = sched_lock.lock();
+    sched.current.set_state(TaskState::Sleeping);
+}
+
+/// Desiste de dormir (ou termina o sono): volta a Running
+pub fn finish_sleep() {
+    let Some(sched_lock) = SCHED.get() else {
+        return;
+    };
+    let sched = sched_lock.lock();
+    if sched.current.state() == TaskState::Sleeping {
+        sched.current.set_state(TaskState::Running);
+    }
+}
+
+/// Cede a CPU até o task atual ser acordado por `wake_task`
+pub fn sleep() {
+    let enabled = x86_64::instructions::interrupts::are_enabled();
+    while try_current_task().is_some_and(|t| t.state() == TaskState::Sleeping) {
+        x86_64::instructions::interrupts::enable_and_hlt();
+    }
+    if !enabled {
+        x86_64::instructions::interrupts::disable();
+    }
+}
+
+/// Dorme até chegar um signal não bloqueado (sigsuspend, pause)
+pub fn sleep_until_signal() {
+    loop {
+        x86_64::instructions::interrupts::without_interrupts(prepare_to_sleep);
+        if has_pending_signals() {
+            finish_sleep();
+            return;
+        }
+        sleep();
+    }
+}
+
 /// Substitui o address space do processo atual e salta para o novo código.
 ///
 /// Usado por execve para substituir a imagem do processo.
 /// Esta função não retorna.
-pub fn exec_replace(new_cr3: PhysFrame<Size4KiB>, entry: u64, stack_pointer: u64) -> ! {
+pub fn exec_replace(new_cr3: PhysFrame<Size4KiB>, entry: u64, stack_pointer: u64, brk: u64, vdso: bool) -> ! {
     let task = current_task();
 
     // Prepara um TrapFrame para a nova execução
@@ -1346,6 +1793,10 @@ pub fn exec_replace(new_cr3: PhysFrame<Size4KiB>, entry: u64, stack_pointer: u64
     // page tables when switching to this task after preemption.
     task.set_cr3(new_cr3);
 
+    // Os mmaps e o heap da imagem antiga somem; o novo address space começa
+    // só com o vDSO e com o break logo após a nova imagem
+    crate::mm::vma::release_vmas(task.replace_vmas(crate::mm::vma::new_vmas(new_cr3, brk, vdso)));
+
     // Troca para o novo address space
     unsafe {
         Cr3::write(new_cr3, Cr3::read().1);
@@ -1375,20 +1826,44 @@ pub fn exec_replace(new_cr3: PhysFrame<Size4KiB>, entry: u64, stack_pointer: u64
 /// Fork a partir de um SyscallFrame (chamado via syscall).
 ///
 /// Converte o SyscallFrame para TrapFrame e chama fork_current.
-pub fn fork_current_from_syscall(sf: &crate::arch::x86_64_arch::syscall::SyscallFrame) -> Result<u64, KError> {
+/// With CLONE_NEWNS in `clone_flags` the child gets a private copy of the
+/// parent's mount namespace; with CLONE_FILES it shares the parent's file
+/// descriptor table instead of getting a copy.
+pub fn fork_current_from_syscall(
+    sf: &crate::arch::x86_64_arch::syscall::SyscallFrame,
+    clone_flags: u64,
+) -> Result<u64, KError> {
     let parent = current_task();
 
     if !parent.is_user {
         return Err(KError::NotSupported);
     }
 
+    let (child_mnt_ns, child_fs_root) = if clone_flags & crate::ipc::namespace::flags::CLONE_NEWNS != 0 {
+        crate::ipc::namespace::copy_mnt_ns(parent)?
+    } else {
+        (parent.mnt_ns(), parent.fs_root())
+    };
+
+    let child_files = if clone_flags & crate::syscall::clone_flags::CLONE_FILES != 0 {
+        parent.files()
+    } else {
+        crate::syscall::fork_files(&parent.files())
+    };
+
     let child_id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
 
+    // O filho entra no cgroup do pai (falha com pids.max atingido)
+    crate::cgroups::task_fork(crate::process::Pid(parent.id), crate::process::Pid(child_id))?;
+
     // 1. Cria nova kernel stack para o filho
     let child_kstack = KernelStack::new(64 * 1024);
 
     // 2. Clona o address space do pai
-    let child_cr3 = clone_address_space(parent.cr3())?;
+    let child_cr3 = clone_address_space(parent.cr3()).map_err(|e| {
+        crate::cgroups::on_process_exit(crate::process::Pid(child_id));
+        e
+    })?;
 
     // 3. Cria TrapFrame para o filho a partir do SyscallFrame
     // O filho será escalonado via iretq, então precisamos de um TrapFrame
@@ -1434,8 +1909,8 @@ pub fn fork_current_from_syscall(sf: &crate::arch::x86_64_arch::syscall::Syscall
         id: child_id,
         parent_id: UnsafeCell::new(parent.id),
         tgid: child_id, // Fork cria novo processo, então tgid = pid
-        pgid: parent.pgid, // Filho herda process group do pai
-        sid: parent.sid,   // Filho herda sessão do pai
+        pgid: UnsafeCell::new(parent.pgid()), // Filho herda process group do pai
+        sid: UnsafeCell::new(parent.sid()),   // Filho herda sessão do pai
         name: format!("{}-child", parent.name),
         state: UnsafeCell::new(TaskState::Ready),
         exit_status: UnsafeCell::new(0),
@@ -1443,6 +1918,7 @@ pub fn fork_current_from_syscall(sf: &crate::arch::x86_64_arch::syscall::Syscall
         is_thread: false,
         kstack: child_kstack,
         cr3: UnsafeCell::new(child_cr3),
+        vmas: UnsafeCell::new(crate::mm::vma::fork_vmas(&parent.vmas(), child_cr3)),
         saved_tf: UnsafeCell::new(child_tf),
         cred: UnsafeCell::new(parent.cred()),
         signals: parent.signals.clone(),
@@ -1450,6 +1926,8 @@ pub fn fork_current_from_syscall(sf: &crate::arch::x86_64_arch::syscall::Syscall
         clear_child_tid: UnsafeCell::new(0),
         user_gs_base: UnsafeCell::new(parent.user_gs_base()),
         cwd: UnsafeCell::new(parent.cwd()),  // Filho herda cwd do pai
+        mnt_ns: UnsafeCell::new(child_mnt_ns),
+        fs_root: UnsafeCell::new(child_fs_root),
         priority: UnsafeCell::new(child_priority),  // Filho herda prioridade do pai
         rlimits: UnsafeCell::new(parent.rlimits()),  // Filho herda limites do pai
         sched_entity: UnsafeCell::new(SchedEntity::new(child_priority)),
@@ -1457,8 +1935,12 @@ pub fn fork_current_from_syscall(sf: &crate::arch::x86_64_arch::syscall::Syscall
         rt_entity: UnsafeCell::new(RtEntity::new()),
         cpu_allowed: UnsafeCell::new(CpuMask::all()),
         process_caps: UnsafeCell::new(crate::security::ProcessCaps::root()),
-        seccomp: UnsafeCell::new(crate::security::SeccompState::new()),
+        seccomp: UnsafeCell::new(parent.seccomp_state()),
+        no_new_privs: UnsafeCell::new(parent.no_new_privs()),
+        files: UnsafeCell::new(child_files),
     });
+    crate::security::hooks::task_alloc(parent.id, child_id);
+    trace_fork(parent.id, child_id);
 
     // 5. Adiciona o filho à fila do scheduler (both legacy and CFS)
     {
@@ -1505,11 +1987,17 @@ pub fn fork_current(parent_tf: &TrapFrame) -> Result<u64, KError> {
 
     let child_id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
 
+    // O filho entra no cgroup do pai (falha com pids.max atingido)
+    crate::cgroups::task_fork(crate::process::Pid(parent.id), crate::process::Pid(child_id))?;
+
     // 1. Cria nova kernel stack para o filho
     let child_kstack = KernelStack::new(64 * 1024);
 
     // 2. Clona o address space do pai
-    let child_cr3 = clone_address_space(parent.cr3())?;
+    let child_cr3 = clone_address_space(parent.cr3()).map_err(|e| {
+        crate::cgroups::on_process_exit(crate::process::Pid(child_id));
+        e
+    })?;
 
     // 3. Copia o TrapFrame do pai para a kernel stack do filho
     // O filho deve retornar 0 do fork
@@ -1533,8 +2021,8 @@ pub fn fork_current(parent_tf: &TrapFrame) -> Result<u64, KError> {
         id: child_id,
         parent_id: UnsafeCell::new(parent.id),
         tgid: child_id, // Fork cria novo processo, então tgid = pid
-        pgid: parent.pgid, // Filho herda process group do pai
-        sid: parent.sid,   // Filho herda sessão do pai
+        pgid: UnsafeCell::new(parent.pgid()), // Filho herda process group do pai
+        sid: UnsafeCell::new(parent.sid()),   // Filho herda sessão do pai
         name: format!("{}-child", parent.name),
         state: UnsafeCell::new(TaskState::Ready),
         exit_status: UnsafeCell::new(0),
@@ -1542,6 +2030,7 @@ pub fn fork_current(parent_tf: &TrapFrame) -> Result<u64, KError> {
         is_thread: false,
         kstack: child_kstack,
         cr3: UnsafeCell::new(child_cr3),
+        vmas: UnsafeCell::new(crate::mm::vma::fork_vmas(&parent.vmas(), child_cr3)),
         saved_tf: UnsafeCell::new(child_tf),
         cred: UnsafeCell::new(parent.cred()),
         signals: parent.signals.clone(),
@@ -1549,6 +2038,8 @@ pub fn fork_current(parent_tf: &TrapFrame) -> Result<u64, KError> {
         clear_child_tid: UnsafeCell::new(0),
         user_gs_base: UnsafeCell::new(parent.user_gs_base()),
         cwd: UnsafeCell::new(parent.cwd()),  // Filho herda cwd do pai
+        mnt_ns: UnsafeCell::new(parent.mnt_ns()),
+        fs_root: UnsafeCell::new(parent.fs_root()),
         priority: UnsafeCell::new(child_priority),  // Filho herda prioridade do pai
         rlimits: UnsafeCell::new(parent.rlimits()),  // Filho herda limites do pai
         sched_entity: UnsafeCell::new(SchedEntity::new(child_priority)),
@@ -1556,8 +2047,12 @@ pub fn fork_current(parent_tf: &TrapFrame) -> Result<u64, KError> {
         rt_entity: UnsafeCell::new(RtEntity::new()),
         cpu_allowed: UnsafeCell::new(CpuMask::all()),
         process_caps: UnsafeCell::new(crate::security::ProcessCaps::root()),
-        seccomp: UnsafeCell::new(crate::security::SeccompState::new()),
+        seccomp: UnsafeCell::new(parent.seccomp_state()),
+        no_new_privs: UnsafeCell::new(parent.no_new_privs()),
+        files: UnsafeCell::new(crate::syscall::fork_files(&parent.files())),
     });
+    crate::security::hooks::task_alloc(parent.id, child_id);
+    trace_fork(parent.id, child_id);
 
     // 5. Adiciona o filho à fila do scheduler (both legacy and CFS)
     {
@@ -1595,6 +2090,8 @@ fn clone_address_space(parent_cr3: PhysFrame<Size4KiB>) -> Result<PhysFrame<Size
 
     // Vetor para coletar frames que serão marcados como CoW
     let mut cow_frames: Vec<PhysAddr> = Vec::new();
+    // Frames do page cache mapeados (compartilhados sem CoW) e se são graváveis
+    let mut file_frames: Vec<(PhysAddr, bool)> = Vec::new();
 
     // Aloca novo P4 para o filho
     let child_p4_frame = fa.allocate().ok_or(KError::NoMemory)?;
@@ -1638,6 +2135,7 @@ fn clone_address_space(parent_cr3: PhysFrame<Size4KiB>) -> Result<PhysFrame<Size
                 3,
                 &mut *fa,
                 &mut cow_frames,
+                &mut file_frames,
             )?;
         }
     }
@@ -1651,6 +2149,11 @@ fn clone_address_space(parent_cr3: PhysFrame<Size4KiB>) -> Result<PhysFrame<Size
         mm::cow::increment_ref(*phys_addr);
     }
 
+    // O filho ganha mais um mapeamento de cada página de arquivo
+    for &(phys_addr, writable) in &file_frames {
+        crate::fs::page_cache::dup_file_page(PhysFrame::containing_address(phys_addr), writable);
+    }
+
     // Flush TLB do pai (as páginas agora são read-only)
     x86_64::instructions::tlb::flush_all();
 
@@ -1665,12 +2168,17 @@ fn clone_address_space(parent_cr3: PhysFrame<Size4KiB>) -> Result<PhysFrame<Size
 /// - Registra o frame no CoW manager
 ///
 /// Quando o processo tenta escrever, ocorre page fault e a cópia é feita.
+///
+/// Páginas do page cache de arquivos (marcadas com `vma::PAGE_CACHE_BIT`)
+/// são compartilhadas como estão: mapeamentos compartilhados continuam
+/// vendo o mesmo frame e os privados já são read-only até a primeira escrita.
 fn clone_page_table_level(
     parent_phys: PhysAddr,
     child_phys: PhysAddr,
     level: u8,
     fa: &mut crate::mm::BitmapFrameAllocator,
     cow_frames: &mut alloc::vec::Vec<PhysAddr>, // Coleta frames CoW para registrar depois
+    file_frames: &mut alloc::vec::Vec<(PhysAddr, bool)>,
 ) -> Result<(), KError> {
     use x86_64::structures::paging::PageTableFlags;
 
@@ -1697,7 +2205,12 @@ fn clone_page_table_level(
 
         if level == 1 {
             // Nível 1 (PT): página física
-            if is_user {
+            if is_user && parent_entry.flags().contains(mm::vma::PAGE_CACHE_BIT) {
+                // Página de arquivo: compartilha o frame do page cache
+                let writable = parent_entry.flags().contains(PageTableFlags::WRITABLE);
+                file_frames.push((parent_entry.addr(), writable));
+                child_table[i] = parent_entry.clone();
+            } else if is_user {
                 let parent_frame_phys = parent_entry.addr();
                 let mut flags = parent_entry.flags();
 
@@ -1738,6 +2251,7 @@ fn clone_page_table_level(
                 level - 1,
                 fa,
                 cow_frames,
+                file_frames,
             )?;
         }
     }
@@ -1766,8 +2280,8 @@ fn spawn_user_task(name: &str, prog: &'static [u8]) -> Arc<Task> {
         id,
         parent_id: UnsafeCell::new(0), // spawned pelo kernel
         tgid: id, // Novo processo, tgid = pid
-        pgid: id, // Novo processo é líder de seu próprio group
-        sid: id,  // Novo processo é líder de sua própria sessão
+        pgid: UnsafeCell::new(id), // Novo processo é líder de seu próprio group
+        sid: UnsafeCell::new(id),  // Novo processo é líder de sua própria sessão
         name: name.into(),
         state: UnsafeCell::new(TaskState::Ready),
         exit_status: UnsafeCell::new(0),
@@ -1775,6 +2289,8 @@ fn spawn_user_task(name: &str, prog: &'static [u8]) -> Arc<Task> {
         is_thread: false,
         kstack,
         cr3: UnsafeCell::new(cr3),
+        // Programas embutidos: heap acima da stack
+        vmas: UnsafeCell::new(crate::mm::vma::new_vmas(cr3, USER_STACK_TOP, false)),
         saved_tf: UnsafeCell::new(tf_ptr),
         cred: UnsafeCell::new(crate::security::Cred::user(1000, 1000)),
         signals: SignalState::new(),
@@ -1782,6 +2298,8 @@ fn spawn_user_task(name: &str, prog: &'static [u8]) -> Arc<Task> {
         clear_child_tid: UnsafeCell::new(0),
         user_gs_base: UnsafeCell::new(0),
         cwd: UnsafeCell::new(String::from("/")),
+        mnt_ns: UnsafeCell::new(crate::fs::init_mnt_ns()),
+        fs_root: UnsafeCell::new(None),
         priority: UnsafeCell::new(0),
         rlimits: UnsafeCell::new(crate::syscall::ResourceLimits::default()),
         sched_entity: UnsafeCell::new(SchedEntity::new(0)),
@@ -1790,6 +2308,8 @@ fn spawn_user_task(name: &str, prog: &'static [u8]) -> Arc<Task> {
         cpu_allowed: UnsafeCell::new(CpuMask::all()),
         process_caps: UnsafeCell::new(crate::security::ProcessCaps::user()),
         seccomp: UnsafeCell::new(crate::security::SeccompState::new()),
+        no_new_privs: UnsafeCell::new(false),
+        files: UnsafeCell::new(crate::syscall::init_files()),
     })
 }
 
@@ -1921,6 +2441,9 @@ pub fn clone_thread(
 
     let thread_id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
 
+    // Threads também contam no pids.max
+    crate::cgroups::task_fork(crate::process::Pid(parent.id), crate::process::Pid(thread_id))?;
+
     // Thread usa o mesmo CR3 (compartilha address space)
     let thread_cr3 = parent.cr3();
 
@@ -1981,8 +2504,8 @@ pub fn clone_thread(
         id: thread_id,
         parent_id: UnsafeCell::new(parent.id),
         tgid: thread_tgid,
-        pgid: parent.pgid, // Thread herda process group
-        sid: parent.sid,   // Thread herda sessão
+        pgid: UnsafeCell::new(parent.pgid()), // Thread herda process group
+        sid: UnsafeCell::new(parent.sid()),   // Thread herda sessão
         name: format!("{}-thread-{}", parent.name, thread_id),
         state: UnsafeCell::new(TaskState::Ready),
         exit_status: UnsafeCell::new(0),
@@ -1990,6 +2513,7 @@ pub fn clone_thread(
         is_thread: true,
         kstack: thread_kstack,
         cr3: UnsafeCell::new(thread_cr3),
+        vmas: UnsafeCell::new(parent.vmas()),  // Threads compartilham as VMAs
         saved_tf: UnsafeCell::new(thread_tf),
         cred: UnsafeCell::new(parent.cred()),
         signals: parent.signals.clone(),
@@ -1997,6 +2521,8 @@ pub fn clone_thread(
         clear_child_tid: UnsafeCell::new(if (flags & CLONE_CHILD_CLEARTID) != 0 { child_tidptr } else { 0 }),
         user_gs_base: UnsafeCell::new(thread_gs_base),
         cwd: UnsafeCell::new(parent.cwd()),  // Thread herda cwd do parent
+        mnt_ns: UnsafeCell::new(parent.mnt_ns()),
+        fs_root: UnsafeCell::new(parent.fs_root()),
         priority: UnsafeCell::new(thread_priority),  // Thread herda prioridade do parent
         rlimits: UnsafeCell::new(parent.rlimits()),  // Thread herda limites do parent
         sched_entity: UnsafeCell::new(SchedEntity::new(thread_priority)),
@@ -2004,7 +2530,9 @@ pub fn clone_thread(
         rt_entity: UnsafeCell::new(RtEntity::new()),
         cpu_allowed: UnsafeCell::new(CpuMask::all()),
         process_caps: UnsafeCell::new(crate::security::ProcessCaps::root()),
-        seccomp: UnsafeCell::new(crate::security::SeccompState::new()),
+        seccomp: UnsafeCell::new(parent.seccomp_state()),
+        no_new_privs: UnsafeCell::new(parent.no_new_privs()),
+        files: UnsafeCell::new(parent.files()),
     });
 
     // Se CLONE_PARENT_SETTID, escreve TID no parent
@@ -2021,6 +2549,9 @@ pub fn clone_thread(
         }
     }
 
+    crate::security::hooks::task_alloc(parent.id, thread_id);
+    trace_fork(parent.id, thread_id);
+
     // Adiciona à fila de execução (both legacy and CFS)
     {
         let sched_lock = SCHED.get().ok_or(KError::NotSupported)?;
@@ -2087,26 +2618,42 @@ pub fn exit_thread_group(status: u64) -> ! {
     exit_current_and_switch(status);
 }
 
+/// Seccomp TSYNC: gives `state` to every thread of the current thread group.
+/// Fails without changing anything, returning the thread id, if a thread has
+/// filters that `state` doesn't build upon.
+pub fn sync_thread_group_seccomp(state: &crate::security::SeccompState) -> Result<(), u64> {
+    let current = current_task();
+    let sched_lock = SCHED.get().expect("scheduler não inicializado");
+    let sched = sched_lock.lock();
+
+    let threads = || sched.runq.iter().filter(|t| t.tgid == current.tgid && t.id != current.id);
+    if let Some(task) = threads().find(|t| !state.extends(&t.seccomp_state())) {
+        return Err(task.id);
+    }
+    for task in threads() {
+        task.set_no_new_privs();
+        task.set_seccomp_state(state.clone());
+    }
+    Ok(())
+}
+
 /// Envia sinal para uma thread específica
 pub fn send_signal_to_thread(tgid: u64, tid: u64, sig: u32) -> KResult<()> {
     let sched_lock = SCHED.get().ok_or(KError::NotSupported)?;
-    let sched = sched_lock.lock();
+    let mut sched = sched_lock.lock();
 
-    // Procura a thread
-    for task in sched.runq.iter() {
-        if task.id == tid && (tgid == u64::MAX || task.tgid == tgid) {
-            task.signals.send(sig);
-            return Ok(());
-        }
-    }
+    // Procura a thread (na runq ou o task atual)
+    let task = core::iter::once(&sched.current)
+        .chain(sched.runq.iter())
+        .find(|t| t.id == tid && (tgid == u64::MAX || t.tgid == tgid))
+        .cloned()
+        .ok_or(KError::NotFound)?;
 
-    // Verifica também o task atual
-    if sched.current.id == tid && (tgid == u64::MAX || sched.current.tgid == tgid) {
-        sched.current.signals.send(sig);
-        return Ok(());
+    task.signals.send(sig);
+    if task.state() == TaskState::Sleeping && task.signals.has_pending() {
+        sched.wake_locked(&task);
     }
-
-    Err(KError::NotFound)
+    Ok(())
 }
 
 // ---------------- Init process spawning ----------------
@@ -2160,8 +2707,8 @@ pub fn spawn_init() -> KResult<()> {
         id: 1,  // PID 1 é sempre o init
         parent_id: UnsafeCell::new(0),  // Kernel é o pai
         tgid: 1,
-        pgid: 1, // Init é líder de seu próprio process group
-        sid: 1,  // Init é líder de sua própria sessão
+        pgid: UnsafeCell::new(1), // Init é líder de seu próprio process group
+        sid: UnsafeCell::new(1),  // Init é líder de sua própria sessão
         name: "init".into(),
         state: UnsafeCell::new(TaskState::Ready),
         exit_status: UnsafeCell::new(0),
@@ -2169,6 +2716,7 @@ pub fn spawn_init() -> KResult<()> {
         is_thread: false,
         kstack,
         cr3: UnsafeCell::new(loaded.cr3),
+        vmas: UnsafeCell::new(crate::mm::vma::new_vmas(loaded.cr3, loaded.brk, loaded.vdso)),
         saved_tf: UnsafeCell::new(tf_ptr),
         cred: UnsafeCell::new(Cred::root()),  // Init roda como root
         signals: SignalState::new(),
@@ -2176,6 +2724,8 @@ pub fn spawn_init() -> KResult<()> {
         clear_child_tid: UnsafeCell::new(0),
         user_gs_base: UnsafeCell::new(0),
         cwd: UnsafeCell::new(String::from("/")),  // Init começa em /
+        mnt_ns: UnsafeCell::new(crate::fs::init_mnt_ns()),
+        fs_root: UnsafeCell::new(None),
         priority: UnsafeCell::new(0),
         rlimits: UnsafeCell::new(crate::syscall::ResourceLimits::default()),  // Init com prioridade normal
         sched_entity: UnsafeCell::new(SchedEntity::new(0)),
@@ -2184,6 +2734,8 @@ pub fn spawn_init() -> KResult<()> {
         cpu_allowed: UnsafeCell::new(CpuMask::all()),
         process_caps: UnsafeCell::new(crate::security::ProcessCaps::root()),
         seccomp: UnsafeCell::new(crate::security::SeccompState::new()),
+        no_new_privs: UnsafeCell::new(false),
+        files: UnsafeCell::new(crate::syscall::init_files()),
     });
 
     // Adiciona init à fila de execução (both legacy and CFS)
@@ -2207,6 +2759,93 @@ pub fn spawn_init() -> KResult<()> {
     Ok(())
 }
 
+/// Inicia um programa de usuário fora de fork/exec (ex.: o shell de um
+/// terminal), como líder de uma sessão nova.
+///
+/// `files` vira a tabela de FDs do processo e `ctty`, se houver, o terminal
+/// de controle da sessão. O processo não tem pai: ao terminar não vira
+/// zombie. Roda com as credenciais de quem chama.
+pub fn spawn_user_process(
+    path: &str,
+    argv: &[&str],
+    envp: &[&str],
+    files: crate::syscall::Files,
+    ctty: Option<Arc<crate::drivers::tty::Tty>>,
+) -> KResult<u64> {
+    use crate::process;
+
+    let loaded = process::load_elf_from_path(path, argv, envp)?;
+    let cred = current_cred();
+    let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+
+    let kstack = KernelStack::new(64 * 1024);
+    let tf_ptr = unsafe {
+        init_user_trapframe(kstack.top(), loaded.entry, loaded.stack_pointer)
+    };
+
+    let name = path.rsplit('/').next().unwrap_or(path);
+    let task = Arc::new(Task {
+        id,
+        parent_id: UnsafeCell::new(0),
+        tgid: id,
+        pgid: UnsafeCell::new(id), // Líder do próprio grupo
+        sid: UnsafeCell::new(id),  // e da própria sessão
+        name: name.into(),
+        state: UnsafeCell::new(TaskState::Ready),
+        exit_status: UnsafeCell::new(0),
+        is_user: true,
+        is_thread: false,
+        kstack,
+        cr3: UnsafeCell::new(loaded.cr3),
+        vmas: UnsafeCell::new(crate::mm::vma::new_vmas(loaded.cr3, loaded.brk, loaded.vdso)),
+        saved_tf: UnsafeCell::new(tf_ptr),
+        cred: UnsafeCell::new(cred),
+        signals: SignalState::new(),
+        signal_handlers: SignalHandlers::new(),
+        clear_child_tid: UnsafeCell::new(0),
+        user_gs_base: UnsafeCell::new(0),
+        cwd: UnsafeCell::new(String::from("/")),
+        mnt_ns: UnsafeCell::new(crate::fs::init_mnt_ns()),
+        fs_root: UnsafeCell::new(None),
+        priority: UnsafeCell::new(0),
+        rlimits: UnsafeCell::new(crate::syscall::ResourceLimits::default()),
+        sched_entity: UnsafeCell::new(SchedEntity::new(0)),
+        ticks_run: UnsafeCell::new(0),
+        rt_entity: UnsafeCell::new(RtEntity::new()),
+        cpu_allowed: UnsafeCell::new(CpuMask::all()),
+        process_caps: UnsafeCell::new(if cred.is_root() {
+            crate::security::ProcessCaps::root()
+        } else {
+            crate::security::ProcessCaps::user()
+        }),
+        seccomp: UnsafeCell::new(crate::security::SeccompState::new()),
+        no_new_privs: UnsafeCell::new(false),
+        files: UnsafeCell::new(files),
+    });
+
+    // O terminal tem que estar pronto antes do processo rodar
+    if let Some(tty) = ctty {
+        crate::drivers::tty::set_controlling_tty(&tty, id, id, true)
+            .map_err(|_| KError::Busy)?;
+    }
+
+    {
+        let sched_lock = SCHED.get().ok_or(KError::NotSupported)?;
+        let mut sched = sched_lock.lock();
+        sched.runq.push_back(task.clone());
+
+        let vrt = sched.cfs_rq.place_entity(task.sched_entity(), true);
+        task.sched_entity().set_vruntime(vrt);
+        sched.cfs_rq.enqueue(CfsEntry {
+            task_id: task.id,
+            vruntime: vrt,
+            weight: task.sched_entity().weight(),
+        });
+    }
+
+    Ok(id)
+}
+
 /// Inicializa o scheduler com apenas o idle task e sem user tasks.
 ///
 /// Após chamar esta função, você pode usar spawn_init() para iniciar /bin/init.
@@ -2218,8 +2857,8 @@ pub fn init_scheduler_only() {
         id: 0,
         parent_id: UnsafeCell::new(0),
         tgid: 0,
-        pgid: 0,
-        sid: 0,
+        pgid: UnsafeCell::new(0),
+        sid: UnsafeCell::new(0),
         name: "idle".into(),
         state: UnsafeCell::new(TaskState::Running),
         exit_status: UnsafeCell::new(0),
@@ -2227,6 +2866,7 @@ pub fn init_scheduler_only() {
         is_thread: false,
         kstack: KernelStack::new(0),
         cr3: UnsafeCell::new(current_cr3),
+        vmas: UnsafeCell::new(crate::mm::vma::kernel_vmas()),
         saved_tf: UnsafeCell::new(ptr::null_mut()),
         cred: UnsafeCell::new(Cred::root()),
         signals: SignalState::new(),
@@ -2234,6 +2874,8 @@ pub fn init_scheduler_only() {
         clear_child_tid: UnsafeCell::new(0),
         user_gs_base: UnsafeCell::new(0),
         cwd: UnsafeCell::new(String::from("/")),
+        mnt_ns: UnsafeCell::new(crate::fs::init_mnt_ns()),
+        fs_root: UnsafeCell::new(None),
         priority: UnsafeCell::new(19),  // Idle has lowest priority
         rlimits: UnsafeCell::new(crate::syscall::ResourceLimits::default()),
         sched_entity: UnsafeCell::new(SchedEntity::new(19)), // Idle has lowest priority
@@ -2242,6 +2884,8 @@ pub fn init_scheduler_only() {
         cpu_allowed: UnsafeCell::new(CpuMask::all()),
         process_caps: UnsafeCell::new(crate::security::ProcessCaps::root()),
         seccomp: UnsafeCell::new(crate::security::SeccompState::new()),
+        no_new_privs: UnsafeCell::new(false),
+        files: UnsafeCell::new(crate::syscall::init_files()),
     });
 
     let sched = Scheduler {
@@ -2252,6 +2896,7 @@ pub fn init_scheduler_only() {
         rt_rq: RtRunqueue::new(),
         clock_ticks: 0,
         ns_per_tick: 1_000_000, // 1ms per tick
+        throttled: Vec::new(),
     };
 
     SCHED.call_once(|| IrqSafeMutex::new(sched));
@@ -2264,11 +2909,32 @@ pub fn init_scheduler_only() {
 
 // ==================== Funções para procfs ====================
 
+/// Tempo de CPU do task atual em ns (CLOCK_THREAD_CPUTIME_ID)
+pub fn current_thread_cputime() -> u64 {
+    try_current_task().map_or(0, |t| t.sched_entity().sum_exec_runtime())
+}
+
+/// Tempo de CPU somado das threads do processo `tgid` em ns
+/// (CLOCK_PROCESS_CPUTIME_ID)
+pub fn thread_group_cputime(tgid: u64) -> u64 {
+    let Some(sched_lock) = SCHED.get() else {
+        return 0;
+    };
+    let sched = sched_lock.lock();
+    let current = sched.current.id;
+    core::iter::once(&sched.current)
+        .chain(sched.runq.iter().filter(|t| t.id != current))
+        .filter(|t| t.tgid == tgid && t.state() != TaskState::Exited)
+        .map(|t| t.sched_entity().sum_exec_runtime())
+        .sum()
+}
+
 /// Informações de um processo para /proc/[pid]/status.
 pub struct TaskInfo {
     pub name: String,
     pub state: &'static str,
     pub pid: u64,
+    pub tgid: u64,
     pub ppid: u64,
     pub uid: u32,
     pub gid: u32,
@@ -2312,6 +2978,36 @@ pub fn task_exists(pid: u64) -> bool {
     sched.runq.iter().any(|t| t.id == pid)
 }
 
+/// Verifica se um task existe e ainda não terminou (nem zombie).
+pub fn task_alive(pid: u64) -> bool {
+    let Some(sched_lock) = SCHED.get() else {
+        return false;
+    };
+
+    let sched = sched_lock.lock();
+    core::iter::once(&sched.current)
+        .chain(sched.runq.iter())
+        .find(|t| t.id == pid)
+        .map_or(false, |t| !matches!(t.state(), TaskState::Zombie | TaskState::Exited))
+}
+
+/// Ids dos tasks (threads) do grupo `tgid`.
+pub fn thread_group(tgid: u64) -> Vec<u64> {
+    let Some(sched_lock) = SCHED.get() else {
+        return Vec::new();
+    };
+
+    let sched = sched_lock.lock();
+    let mut tids: Vec<u64> = core::iter::once(&sched.current)
+        .chain(sched.runq.iter())
+        .filter(|t| t.tgid == tgid)
+        .map(|t| t.id)
+        .collect();
+    tids.sort();
+    tids.dedup();
+    tids
+}
+
 /// Retorna informações de um processo.
 pub fn get_task_info(pid: u64) -> Option<TaskInfo> {
     let Some(sched_lock) = SCHED.get() else {
@@ -2330,7 +3026,7 @@ pub fn get_task_info(pid: u64) -> Option<TaskInfo> {
         let state_str = match t.state() {
             TaskState::Ready => "R (runnable)",
             TaskState::Running => "R (running)",
-            TaskState::Blocked => "S (sleeping)",
+            TaskState::Blocked | TaskState::Sleeping => "S (sleeping)",
             TaskState::Zombie => "Z (zombie)",
             TaskState::Exited => "X (dead)",
         };
@@ -2341,6 +3037,7 @@ pub fn get_task_info(pid: u64) -> Option<TaskInfo> {
             name: t.name.clone(),
             state: state_str,
             pid: t.id,
+            tgid: t.tgid,
             ppid: t.parent_id(),
             uid: cred.uid.0,
             gid: cred.gid.0,
@@ -2438,8 +3135,8 @@ pub fn spawn_kernel_thread(name: &str, entry: KernelThreadFn, arg: u64) -> KResu
         id: thread_id,
         parent_id: UnsafeCell::new(0), // Kernel is the parent
         tgid: thread_id, // Each kernel thread is its own thread group
-        pgid: 0, // Kernel threads don't have process groups
-        sid: 0,  // Kernel threads don't have sessions
+        pgid: UnsafeCell::new(0), // Kernel threads don't have process groups
+        sid: UnsafeCell::new(0),  // Kernel threads don't have sessions
         name: String::from(name),
         state: UnsafeCell::new(TaskState::Ready),
         exit_status: UnsafeCell::new(0),
@@ -2447,6 +3144,7 @@ pub fn spawn_kernel_thread(name: &str, entry: KernelThreadFn, arg: u64) -> KResu
         is_thread: true,
         kstack,
         cr3: UnsafeCell::new(kernel_cr3),
+        vmas: UnsafeCell::new(crate::mm::vma::kernel_vmas()),
         saved_tf: UnsafeCell::new(tf_ptr),
         cred: UnsafeCell::new(Cred::root()), // Kernel threads run as root
         signals: SignalState::new(),
@@ -2454,6 +3152,8 @@ pub fn spawn_kernel_thread(name: &str, entry: KernelThreadFn, arg: u64) -> KResu
         clear_child_tid: UnsafeCell::new(0),
         user_gs_base: UnsafeCell::new(0),
         cwd: UnsafeCell::new(String::from("/")),
+        mnt_ns: UnsafeCell::new(crate::fs::init_mnt_ns()),
+        fs_root: UnsafeCell::new(None),
         priority: UnsafeCell::new(-5),  // Kernel threads have slightly higher priority by default
         rlimits: UnsafeCell::new(crate::syscall::ResourceLimits::default()),
         sched_entity: UnsafeCell::new(SchedEntity::new(-5)), // Match priority
@@ -2462,6 +3162,8 @@ pub fn spawn_kernel_thread(name: &str, entry: KernelThreadFn, arg: u64) -> KResu
         cpu_allowed: UnsafeCell::new(CpuMask::all()),
         process_caps: UnsafeCell::new(crate::security::ProcessCaps::root()),
         seccomp: UnsafeCell::new(crate::security::SeccompState::new()),
+        no_new_privs: UnsafeCell::new(false),
+        files: UnsafeCell::new(crate::syscall::init_files()),
     });
 
     // Add to scheduler run queue
@@ -2566,12 +3268,12 @@ pub fn get_task_pgid(pid: u64) -> Option<u64> {
     let sched = sched_lock.lock();
 
     if sched.current.id == pid {
-        return Some(sched.current.pgid);
+        return Some(sched.current.pgid());
     }
 
     sched.runq.iter()
         .find(|t| t.id == pid)
-        .map(|t| t.pgid)
+        .map(|t| t.pgid())
 }
 
 /// Retorna o SID de um processo.
@@ -2580,74 +3282,76 @@ pub fn get_task_sid(pid: u64) -> Option<u64> {
     let sched = sched_lock.lock();
 
     if sched.current.id == pid {
-        return Some(sched.current.sid);
+        return Some(sched.current.sid());
     }
 
     sched.runq.iter()
         .find(|t| t.id == pid)
-        .map(|t| t.sid)
+        .map(|t| t.sid())
 }
 
-/// Define o PGID de um processo.
+/// Define o PGID de um processo (setpgid).
 ///
-/// Por enquanto, simplificado: apenas o próprio processo pode mudar seu pgid.
-pub fn set_task_pgid(pid: u64, _new_pgid: u64) -> KResult<()> {
+/// Segue as regras do Linux: o alvo é o próprio processo ou um filho, na
+/// mesma sessão e que não seja líder de sessão; e `new_pgid` é o próprio
+/// pid do alvo ou um grupo que já existe na sessão.
+pub fn set_task_pgid(pid: u64, new_pgid: u64) -> KResult<()> {
     let sched_lock = SCHED.get().ok_or(KError::NotSupported)?;
     let sched = sched_lock.lock();
 
+    let cur = sched.current.clone();
+
     // Encontra o task
-    let task = if sched.current.id == pid {
-        sched.current.clone()
+    let task = if cur.id == pid {
+        cur.clone()
     } else {
         sched.runq.iter()
-            .find(|t| t.id == pid)
+            .find(|t| t.id == pid && !t.is_thread)
             .cloned()
             .ok_or(KError::NotFound)?
     };
 
-    // Verifica permissões: apenas o próprio processo ou filho pode mudar pgid
-    let cur = sched.current.clone();
-    if cur.id != pid && task.parent_id() != cur.id {
+    // Apenas o próprio processo ou um filho
+    if task.id != cur.id && task.parent_id() != cur.id {
+        return Err(KError::NotFound);
+    }
+    if task.sid() != cur.sid() || task.sid() == task.id {
         return Err(KError::PermissionDenied);
     }
 
-    // Atualiza pgid (usando unsafe pois pgid não é UnsafeCell)
-    // Por simplicidade, vamos aceitar que isso funcione para processos na runq
-    // Note: Isso é uma simplificação - em um OS real, pgid seria UnsafeCell
-    // Por ora, vamos apenas permitir que o processo atual mude seu próprio pgid
-
-    if cur.id == pid {
-        // Não podemos mudar diretamente pois pgid não é mut
-        // Por simplicidade, retornamos sucesso (o valor já está setado)
-        Ok(())
-    } else {
-        // Para outros processos, também não podemos mudar sem UnsafeCell
-        Ok(())
+    if new_pgid != task.id {
+        let exists = core::iter::once(&sched.current)
+            .chain(sched.runq.iter())
+            .any(|t| t.pgid() == new_pgid && t.sid() == cur.sid());
+        if !exists {
+            return Err(KError::PermissionDenied);
+        }
     }
+
+    task.set_pgid(new_pgid);
+    Ok(())
 }
 
-/// Cria uma nova sessão.
+/// Cria uma nova sessão (setsid).
 ///
-/// O processo atual se torna o líder da nova sessão.
-/// pgid e sid são setados para o pid do processo.
+/// O processo atual se torna o líder da nova sessão e de um novo grupo,
+/// sem terminal de controle. Falha se ele já é líder de um grupo.
 pub fn create_session() -> KResult<u64> {
     let sched_lock = SCHED.get().ok_or(KError::NotSupported)?;
     let sched = sched_lock.lock();
 
     let cur = sched.current.clone();
 
-    // Verifica se já é líder de grupo (não pode criar sessão)
-    if cur.pgid == cur.id {
-        // Se já é líder de grupo e NÃO é líder de sessão, pode criar
-        // Se já é líder de sessão, não pode criar nova
-        if cur.sid == cur.id {
-            return Err(KError::PermissionDenied);
-        }
+    // Um líder de grupo não pode criar sessão (o grupo ficaria dividido)
+    let leads_group = core::iter::once(&sched.current)
+        .chain(sched.runq.iter())
+        .any(|t| t.pgid() == cur.id);
+    if leads_group {
+        return Err(KError::PermissionDenied);
     }
 
-    // Cria nova sessão - o processo se torna líder de sessão e de grupo
-    // Por simplicidade, retornamos o pid como novo sid
-    // Note: Em um OS real, precisaríamos atualizar os campos
+    cur.set_sid(cur.id);
+    cur.set_pgid(cur.id);
 
     Ok(cur.id)
 }
@@ -2676,6 +3380,34 @@ pub fn set_current_priority(new_prio: i8) -> KResult<i8> {
     Ok(task.priority())
 }
 
+/// Returns the mount namespace of a task by PID.
+pub fn task_mnt_ns(pid: u64) -> Option<Arc<MountNamespace>> {
+    let sched_lock = SCHED.get()?;
+    let sched = sched_lock.lock();
+
+    if sched.current.id == pid {
+        return Some(sched.current.mnt_ns());
+    }
+
+    sched.runq.iter()
+        .find(|t| t.id == pid)
+        .map(|t| t.mnt_ns())
+}
+
+/// Returns the VMAs of a task's address space by PID.
+pub fn task_vmas(pid: u64) -> Option<crate::mm::vma::Vmas> {
+    let sched_lock = SCHED.get()?;
+    let sched = sched_lock.lock();
+
+    if sched.current.id == pid {
+        return Some(sched.current.vmas());
+    }
+
+    sched.runq.iter()
+        .find(|t| t.id == pid)
+        .map(|t| t.vmas())
+}
+
 /// Returns the priority of a task by PID.
 pub fn get_task_priority(pid: u64) -> Option<i8> {
     let sched_lock = SCHED.get()?;
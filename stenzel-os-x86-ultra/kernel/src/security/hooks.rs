@@ -0,0 +1,222 @@
+//! Security hooks
+//!
+//! Decision points called by the VFS, exec, sockets, mount and signal
+//! delivery. Each hook runs after the regular (DAC) checks have passed,
+//! asks the MAC policy in `security::mac` and turns a denial into
+//! `KError::PermissionDenied`. While MAC is disabled the hooks allow
+//! everything without looking up any label.
+//!
+//! Object labels are stored in the `security.selinux` extended attribute
+//! of the inode; files without one fall back to the path labels of the
+//! policy. Process labels are kept by the MAC system, keyed by task id.
+
+use alloc::format;
+use core::fmt;
+
+use crate::fs::vfs::{Inode, InodeKind};
+use crate::fs::xattr::{security_attrs, XattrFlags};
+use crate::net::socket::{SocketDomain, SocketType};
+use crate::util::{KError, KResult};
+
+use super::mac::{self, Decision, ObjectClass, Permission, SecurityLabel};
+
+/// Task id of the caller (0 before the scheduler is up)
+fn current_pid() -> u32 {
+    crate::sched::try_current_task().map(|t| t.id() as u32).unwrap_or(0)
+}
+
+/// Label of the calling task
+pub fn current_label() -> SecurityLabel {
+    mac::get_process_label(current_pid())
+}
+
+/// Label of an inode: its `security.selinux` xattr, or the policy's label
+/// for `path`
+pub fn inode_label(inode: &Inode, path: &str) -> SecurityLabel {
+    inode
+        .0
+        .getxattr(security_attrs::SELINUX)
+        .ok()
+        .and_then(|raw| {
+            let s = core::str::from_utf8(&raw).ok()?;
+            SecurityLabel::parse(s.trim_end_matches('\0'))
+        })
+        .unwrap_or_else(|| mac::get_file_label(path))
+}
+
+/// Store `label` in the `security.selinux` xattr of `inode`
+pub fn set_inode_label(inode: &Inode, label: &SecurityLabel) -> KResult<()> {
+    inode
+        .0
+        .setxattr(security_attrs::SELINUX, label.to_string().into_bytes(), XattrFlags::new(0))
+}
+
+/// Object class of an inode
+fn inode_class(kind: InodeKind) -> ObjectClass {
+    match kind {
+        InodeKind::File => ObjectClass::File,
+        InodeKind::Dir => ObjectClass::Dir,
+        InodeKind::Symlink => ObjectClass::Lnk,
+        InodeKind::CharDev => ObjectClass::Chr,
+        InodeKind::BlockDev => ObjectClass::Blk,
+        InodeKind::Fifo => ObjectClass::Fifo,
+        InodeKind::Socket => ObjectClass::Socket,
+    }
+}
+
+/// Object class of a socket
+fn socket_class(domain: SocketDomain, sock_type: SocketType) -> ObjectClass {
+    match (domain, sock_type) {
+        (SocketDomain::Unix, SocketType::Stream) => ObjectClass::UnixStreamSocket,
+        (SocketDomain::Unix, SocketType::Datagram) => ObjectClass::UnixDgramSocket,
+        (_, SocketType::Stream) => ObjectClass::TcpSocket,
+        (_, SocketType::Datagram) => ObjectClass::UdpSocket,
+        (_, SocketType::Raw) => ObjectClass::RawIpSocket,
+    }
+}
+
+fn check(
+    pid: u32,
+    source: &SecurityLabel,
+    target: &SecurityLabel,
+    target_desc: &str,
+    class: ObjectClass,
+    requested: Permission,
+) -> KResult<()> {
+    match mac::check_labels(pid, source, target, target_desc, class, requested).decision {
+        Decision::Deny => Err(KError::PermissionDenied),
+        Decision::Allow | Decision::Audit => Ok(()),
+    }
+}
+
+/// Check an access by the caller to `inode` (reached as `path`)
+fn check_inode(inode: &Inode, path: &str, requested: Permission) -> KResult<()> {
+    let pid = current_pid();
+    let source = mac::get_process_label(pid);
+    let target = inode_label(inode, path);
+    check(pid, &source, &target, path, inode_class(inode.kind()), requested)
+}
+
+/// Access to an inode that already passed the permission bits: search on
+/// directories during lookup, read/write on files, add/remove name on the
+/// parent directory, setattr for chmod/chown
+pub fn inode_permission(inode: &Inode, path: &str, requested: Permission) -> KResult<()> {
+    if !mac::is_active() {
+        return Ok(());
+    }
+    check_inode(inode, path, requested)
+}
+
+/// open(2) of `inode` with the access mode bits of the open flags
+pub fn file_open(inode: &Inode, path: &str, access_mode: u32, truncate: bool) -> KResult<()> {
+    if !mac::is_active() {
+        return Ok(());
+    }
+    let mut requested = Permission::OPEN;
+    match access_mode {
+        0 => requested |= Permission::READ,
+        1 => requested |= Permission::WRITE,
+        2 => requested |= Permission::READ | Permission::WRITE,
+        _ => {}
+    }
+    if truncate {
+        requested |= Permission::WRITE;
+    }
+    check_inode(inode, path, requested)
+}
+
+/// execve(2) of `inode`
+///
+/// Returns the label the process should run with after the exec if the
+/// policy has a type transition for this executable.
+pub fn bprm_check(inode: &Inode, path: &str) -> KResult<Option<SecurityLabel>> {
+    if !mac::is_active() {
+        return Ok(None);
+    }
+    let pid = current_pid();
+    let source = mac::get_process_label(pid);
+    let file = inode_label(inode, path);
+    check(pid, &source, &file, path, ObjectClass::File, Permission::file_execute())?;
+
+    let new = match mac::transition_for(&source, &file, ObjectClass::Process) {
+        Some(new) if new != source => new,
+        _ => return Ok(None),
+    };
+    // The old domain must be allowed to enter the new one, and the
+    // executable must be an entry point of the new domain
+    check(pid, &source, &new, path, ObjectClass::Process, Permission::TRANSITION)?;
+    check(pid, &new, &file, path, ObjectClass::File, Permission::ENTRYPOINT)?;
+    Ok(Some(new))
+}
+
+/// Commit the label computed by `bprm_check` once exec can no longer fail
+pub fn bprm_committed(label: SecurityLabel) {
+    mac::set_process_label(current_pid(), label);
+}
+
+/// socket(2)
+pub fn socket_create(domain: SocketDomain, sock_type: SocketType) -> KResult<()> {
+    socket_check(domain, sock_type, &"socket", Permission::CREATE)
+}
+
+/// bind(2) to `addr`
+pub fn socket_bind(domain: SocketDomain, sock_type: SocketType, addr: &dyn fmt::Display) -> KResult<()> {
+    socket_check(domain, sock_type, addr, Permission::BIND)
+}
+
+/// connect(2) to `addr`
+pub fn socket_connect(domain: SocketDomain, sock_type: SocketType, addr: &dyn fmt::Display) -> KResult<()> {
+    socket_check(domain, sock_type, addr, Permission::CONNECT)
+}
+
+/// Sockets carry the label of the task that uses them
+fn socket_check(
+    domain: SocketDomain,
+    sock_type: SocketType,
+    addr: &dyn fmt::Display,
+    requested: Permission,
+) -> KResult<()> {
+    if !mac::is_active() {
+        return Ok(());
+    }
+    let pid = current_pid();
+    let label = mac::get_process_label(pid);
+    let desc = format!("{}", addr);
+    check(pid, &label, &label, &desc, socket_class(domain, sock_type), requested)
+}
+
+/// Signal `signum` sent by task `sender` to task `target`
+pub fn task_kill(sender: u64, target: u64, signum: u32) -> KResult<()> {
+    if !mac::is_active() {
+        return Ok(());
+    }
+    let source = mac::get_process_label(sender as u32);
+    let dest = mac::get_process_label(target as u32);
+    let desc = format!("pid {} sig {}", target, signum);
+    check(sender as u32, &source, &dest, &desc, ObjectClass::Process, Permission::SIGNAL)
+}
+
+/// mount(2) of `fs_type` on the directory `mountpoint`
+pub fn sb_mount(mountpoint: &Inode, path: &str, fs_type: &str) -> KResult<()> {
+    if !mac::is_active() {
+        return Ok(());
+    }
+    let pid = current_pid();
+    let source = mac::get_process_label(pid);
+    let target = inode_label(mountpoint, path);
+    let desc = format!("{} ({})", path, fs_type);
+    check(pid, &source, &target, &desc, ObjectClass::Dir, Permission::MOUNTON)
+}
+
+/// A new task starts with the label of the task that created it
+///
+/// Done even while MAC is disabled, so labels stay right if it is turned
+/// on later.
+pub fn task_alloc(parent: u64, child: u64) {
+    mac::inherit_process_label(parent as u32, child as u32);
+}
+
+/// Drop the label of an exited task
+pub fn task_free(pid: u64) {
+    mac::remove_process_label(pid as u32);
+}
@@ -8,7 +8,7 @@
 use alloc::string::{String, ToString};
 use alloc::vec::Vec;
 use alloc::vec;
-use alloc::collections::BTreeMap;
+use alloc::collections::{BTreeMap, VecDeque};
 use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 
 use crate::sync::IrqSafeMutex;
@@ -20,6 +20,16 @@ static MAC_SYSTEM: IrqSafeMutex<Option<MacSystem>> = IrqSafeMutex::new(None);
 /// Statistics
 static STATS: MacStats = MacStats::new();
 
+/// Whether the MAC system is initialized and not disabled; lets the
+/// security hooks skip label lookups entirely while MAC is off
+static ACTIVE: AtomicBool = AtomicBool::new(false);
+
+/// Recent denials, oldest first
+static AUDIT_LOG: IrqSafeMutex<VecDeque<AuditRecord>> = IrqSafeMutex::new(VecDeque::new());
+
+/// Maximum number of denials kept in the audit log
+const AUDIT_LOG_SIZE: usize = 256;
+
 /// MAC framework type
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum MacFramework {
@@ -561,6 +571,55 @@ pub enum MacError {
 
 pub type MacResult<T> = Result<T, MacError>;
 
+/// Audit log entry for a denied access
+#[derive(Debug, Clone)]
+pub struct AuditRecord {
+    /// Uptime in milliseconds
+    pub timestamp: u64,
+    pub pid: u32,
+    /// Source (process) label
+    pub scontext: String,
+    /// Target (object) label
+    pub tcontext: String,
+    /// Path or description of the object
+    pub target: String,
+    pub class: ObjectClass,
+    pub requested: Permission,
+    /// Whether the access was let through because of permissive mode
+    pub permissive: bool,
+}
+
+impl AuditRecord {
+    /// Format the record as an AVC message
+    pub fn to_string(&self) -> String {
+        let perms: Vec<String> = self
+            .requested
+            .iter_names()
+            .map(|(name, _)| name.to_ascii_lowercase())
+            .collect();
+        alloc::format!(
+            "[{}.{:03}] avc: denied {{ {} }} for pid={} name=\"{}\" scontext={} tcontext={} tclass={} permissive={}",
+            self.timestamp / 1000,
+            self.timestamp % 1000,
+            perms.join(" "),
+            self.pid,
+            self.target,
+            self.scontext,
+            self.tcontext,
+            self.class.as_str(),
+            self.permissive as u8
+        )
+    }
+}
+
+fn audit_denial(record: AuditRecord) {
+    let mut log = AUDIT_LOG.lock();
+    if log.len() >= AUDIT_LOG_SIZE {
+        log.pop_front();
+    }
+    log.push_back(record);
+}
+
 /// Statistics
 pub struct MacStats {
     checks: AtomicU64,
@@ -647,6 +706,7 @@ impl MacSystem {
     /// Set mode
     pub fn set_mode(&mut self, mode: MacMode) {
         self.mode = mode;
+        ACTIVE.store(mode != MacMode::Disabled, Ordering::Release);
         kprintln!("mac: Mode set to {:?}", mode);
     }
 
@@ -736,6 +796,23 @@ impl MacSystem {
         target_path: &str,
         class: ObjectClass,
         requested: Permission,
+    ) -> AccessResult {
+        let source_label = self.get_process_label(source_pid);
+        let target_label = self.get_file_label(target_path);
+        self.check_labels(source_pid, &source_label, &target_label, target_path, class, requested)
+    }
+
+    /// Check access between two labels
+    ///
+    /// `target` only names the object in the audit log.
+    pub fn check_labels(
+        &mut self,
+        source_pid: u32,
+        source_label: &SecurityLabel,
+        target_label: &SecurityLabel,
+        target: &str,
+        class: ObjectClass,
+        requested: Permission,
     ) -> AccessResult {
         STATS.checks.fetch_add(1, Ordering::Relaxed);
 
@@ -747,60 +824,82 @@ impl MacSystem {
             };
         }
 
-        let source_label = self.get_process_label(source_pid);
-        let target_label = self.get_file_label(target_path);
-
         // Check cache
         if let Some(cached) = self.check_cache(&source_label.stype, &target_label.stype, class, requested) {
             STATS.cache_hits.fetch_add(1, Ordering::Relaxed);
-            return cached;
+            if cached.decision == Decision::Allow {
+                STATS.allowed.fetch_add(1, Ordering::Relaxed);
+                return cached;
+            }
+            // Denials are audited every time
+            let result = AccessResult {
+                decision: Decision::Deny,
+                audit: true,
+                reason: cached.reason,
+            };
+            return self.finish_denial(source_pid, source_label, target_label, target, class, requested, result);
         }
         STATS.cache_misses.fetch_add(1, Ordering::Relaxed);
 
         // Check TE rules
-        let result = self.evaluate_te_rules(&source_label, &target_label, class, requested);
+        let result = self.evaluate_te_rules(source_label, target_label, class, requested);
 
         // Update cache
         self.update_cache(&source_label.stype, &target_label.stype, class, requested, result.decision);
 
-        // Handle permissive mode
-        if self.mode == MacMode::Permissive && result.decision == Decision::Deny {
-            STATS.denied.fetch_add(1, Ordering::Relaxed);
-            if self.audit_denials {
-                kprintln!(
-                    "mac: PERMISSIVE DENIAL: {} ({}) -> {} ({}) {} {:?}",
-                    source_pid,
-                    source_label.to_string(),
-                    target_path,
-                    target_label.to_string(),
-                    class.as_str(),
-                    requested
-                );
-            }
+        if result.decision == Decision::Allow {
+            STATS.allowed.fetch_add(1, Ordering::Relaxed);
+            return result;
+        }
+
+        self.finish_denial(source_pid, source_label, target_label, target, class, requested, result)
+    }
+
+    /// Account and log a denial; in permissive mode it is turned into a grant
+    fn finish_denial(
+        &self,
+        source_pid: u32,
+        source_label: &SecurityLabel,
+        target_label: &SecurityLabel,
+        target: &str,
+        class: ObjectClass,
+        requested: Permission,
+        result: AccessResult,
+    ) -> AccessResult {
+        STATS.denied.fetch_add(1, Ordering::Relaxed);
+        let permissive = self.mode == MacMode::Permissive;
+
+        if self.audit_denials && result.audit {
+            STATS.audited.fetch_add(1, Ordering::Relaxed);
+            kprintln!(
+                "mac: {}: {} ({}) -> {} ({}) {} {:?}",
+                if permissive { "PERMISSIVE DENIAL" } else { "DENIED" },
+                source_pid,
+                source_label.to_string(),
+                target,
+                target_label.to_string(),
+                class.as_str(),
+                requested
+            );
+            audit_denial(AuditRecord {
+                timestamp: crate::time::uptime_ms(),
+                pid: source_pid,
+                scontext: source_label.to_string(),
+                tcontext: target_label.to_string(),
+                target: target.to_string(),
+                class,
+                requested,
+                permissive,
+            });
+        }
+
+        if permissive {
             return AccessResult {
                 decision: Decision::Allow,
                 audit: true,
                 reason: result.reason,
             };
         }
-
-        if result.decision == Decision::Allow {
-            STATS.allowed.fetch_add(1, Ordering::Relaxed);
-        } else {
-            STATS.denied.fetch_add(1, Ordering::Relaxed);
-            if self.audit_denials {
-                kprintln!(
-                    "mac: DENIED: {} ({}) -> {} ({}) {} {:?}",
-                    source_pid,
-                    source_label.to_string(),
-                    target_path,
-                    target_label.to_string(),
-                    class.as_str(),
-                    requested
-                );
-            }
-        }
-
         result
     }
 
@@ -1015,7 +1114,16 @@ impl MacSystem {
     ) -> Option<SecurityLabel> {
         let source_label = self.get_process_label(source_pid);
         let target_label = self.get_file_label(target_path);
+        self.transition_for(&source_label, &target_label, class)
+    }
 
+    /// Type transition between two labels
+    pub fn transition_for(
+        &self,
+        source_label: &SecurityLabel,
+        target_label: &SecurityLabel,
+        class: ObjectClass,
+    ) -> Option<SecurityLabel> {
         for trans in &self.type_transitions {
             if self.type_matches(&trans.source, &source_label.stype)
                 && self.type_matches(&trans.target, &target_label.stype)
@@ -1050,7 +1158,9 @@ impl MacSystem {
 pub fn init() {
     let mut guard = MAC_SYSTEM.lock();
     if guard.is_none() {
-        *guard = Some(MacSystem::new());
+        let system = MacSystem::new();
+        ACTIVE.store(system.mode != MacMode::Disabled, Ordering::Release);
+        *guard = Some(system);
         kprintln!("mac: Initialized");
     }
 }
@@ -1063,6 +1173,11 @@ pub fn set_mode(mode: MacMode) {
     }
 }
 
+/// Whether accesses are being checked (initialized and not disabled)
+pub fn is_active() -> bool {
+    ACTIVE.load(Ordering::Acquire)
+}
+
 /// Get mode
 pub fn get_mode() -> MacMode {
     let guard = MAC_SYSTEM.lock();
@@ -1109,6 +1224,32 @@ pub fn get_process_label(pid: u32) -> SecurityLabel {
         .unwrap_or_else(SecurityLabel::unconfined)
 }
 
+/// Give a new task the label of the task it was created from
+pub fn inherit_process_label(parent_pid: u32, child_pid: u32) {
+    let mut guard = MAC_SYSTEM.lock();
+    if let Some(system) = guard.as_mut() {
+        if let Some(label) = system.process_labels.get(&parent_pid).cloned() {
+            system.process_labels.insert(child_pid, label);
+        }
+    }
+}
+
+/// Forget the label of a task that has exited
+pub fn remove_process_label(pid: u32) {
+    let mut guard = MAC_SYSTEM.lock();
+    if let Some(system) = guard.as_mut() {
+        system.process_labels.remove(&pid);
+    }
+}
+
+/// Get file label
+pub fn get_file_label(path: &str) -> SecurityLabel {
+    let guard = MAC_SYSTEM.lock();
+    guard.as_ref()
+        .map(|s| s.get_file_label(path))
+        .unwrap_or_else(SecurityLabel::unconfined)
+}
+
 /// Set file label
 pub fn set_file_label(path: &str, label: SecurityLabel) {
     let mut guard = MAC_SYSTEM.lock();
@@ -1135,6 +1276,26 @@ pub fn check_access(
     }
 }
 
+/// Check access between two labels
+pub fn check_labels(
+    source_pid: u32,
+    source: &SecurityLabel,
+    target: &SecurityLabel,
+    target_desc: &str,
+    class: ObjectClass,
+    requested: Permission,
+) -> AccessResult {
+    let mut guard = MAC_SYSTEM.lock();
+    match guard.as_mut() {
+        Some(system) => system.check_labels(source_pid, source, target, target_desc, class, requested),
+        None => AccessResult {
+            decision: Decision::Allow,
+            audit: false,
+            reason: "MAC not initialized".to_string(),
+        },
+    }
+}
+
 /// Check AppArmor access
 pub fn check_aa_access(profile_name: &str, path: &str, requested: FilePermission) -> AccessResult {
     let guard = MAC_SYSTEM.lock();
@@ -1154,6 +1315,12 @@ pub fn compute_transition(source_pid: u32, target_path: &str, class: ObjectClass
     guard.as_ref().and_then(|s| s.compute_transition(source_pid, target_path, class))
 }
 
+/// Type transition between two labels
+pub fn transition_for(source: &SecurityLabel, target: &SecurityLabel, class: ObjectClass) -> Option<SecurityLabel> {
+    let guard = MAC_SYSTEM.lock();
+    guard.as_ref().and_then(|s| s.transition_for(source, target, class))
+}
+
 /// Get statistics
 pub fn get_stats() -> (u64, u64, u64, u64, u64, u64) {
     let guard = MAC_SYSTEM.lock();
@@ -1161,3 +1328,19 @@ pub fn get_stats() -> (u64, u64, u64, u64, u64, u64) {
         .map(|s| s.get_stats())
         .unwrap_or((0, 0, 0, 0, 0, 0))
 }
+
+/// Audit log of recent denials, one AVC message per line
+pub fn audit_log() -> String {
+    let log = AUDIT_LOG.lock();
+    let mut out = String::new();
+    for record in log.iter() {
+        out.push_str(&record.to_string());
+        out.push('\n');
+    }
+    out
+}
+
+/// Drop all entries from the audit log
+pub fn clear_audit_log() {
+    AUDIT_LOG.lock().clear();
+}
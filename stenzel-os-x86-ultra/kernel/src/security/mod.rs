@@ -27,6 +27,7 @@
     pub mod password_policy;
     pub mod portal;
     pub mod mac;
+    pub mod hooks;
     pub mod verified_boot;
 
     pub use caps::{ProcessCaps, CapSet, Cap, FileCaps};
@@ -564,10 +564,13 @@ impl SandboxManager {
     }
 
     fn apply_seccomp(&self, profile: &SandboxProfile) -> KResult<()> {
-        if let Some(ref _filter) = profile.seccomp {
-            // Would apply seccomp filter to current task
-            // let task = crate::sched::current_task();
-            // task.set_seccomp_filter(filter.clone());
+        if let Some(ref filter) = profile.seccomp {
+            let task = crate::sched::current_task();
+            // Same rule as seccomp(2): a filtered task can't gain privileges on exec
+            task.set_no_new_privs();
+            let mut state = task.seccomp_state();
+            state.attach(filter.to_program(), super::seccomp::SeccompFlags::empty())?;
+            task.set_seccomp_state(state);
         }
         Ok(())
     }
@@ -9,6 +9,11 @@
 //! - **Strict**: Only read, write, exit, sigreturn allowed
 //! - **Filter**: BPF filter determines allowed syscalls
 //!
+//! Filters are classic BPF programs run against a [`SeccompData`] on every
+//! syscall entry. A task's filters form a chain: installing a filter never
+//! removes older ones, children inherit the chain on fork and it survives
+//! execve. When several filters disagree, the most restrictive action wins.
+//!
 //! ## Usage
 //! ```ignore
 //! // Enable strict mode
@@ -24,9 +29,8 @@
 
 #![allow(dead_code)]
 
+use alloc::sync::Arc;
 use alloc::vec::Vec;
-use alloc::boxed::Box;
-use core::sync::atomic::{AtomicU32, Ordering};
 
 use crate::util::{KError, KResult};
 
@@ -46,10 +50,10 @@ pub enum SeccompMode {
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(u32)]
 pub enum SeccompAction {
-    /// Kill the process (SIGKILL)
-    Kill = 0x00000000,
-    /// Kill the thread (SIGKILL)
-    KillThread = 0x10000000,
+    /// Kill the process (SIGSYS)
+    Kill = 0x80000000,
+    /// Kill the thread (SIGSYS)
+    KillThread = 0x00000000,
     /// Send SIGSYS with info
     Trap = 0x00030000,
     /// Return an errno
@@ -71,8 +75,8 @@ impl SeccompAction {
     /// Convert to raw value for filter
     pub fn to_raw(&self) -> u32 {
         match self {
-            SeccompAction::Kill => 0x00000000,
-            SeccompAction::KillThread => 0x10000000,
+            SeccompAction::Kill => 0x80000000,
+            SeccompAction::KillThread => 0x00000000,
             SeccompAction::Trap => 0x00030000,
             SeccompAction::Errno(e) => 0x00050000 | (*e as u32),
             SeccompAction::Trace => 0x7ff00000,
@@ -83,10 +87,10 @@ impl SeccompAction {
 
     /// Parse from raw value
     pub fn from_raw(raw: u32) -> Self {
-        let action = raw & 0xffff0000;
+        let action = raw & SECCOMP_RET_ACTION_FULL;
         match action {
-            0x00000000 => SeccompAction::Kill,
-            0x10000000 => SeccompAction::KillThread,
+            0x80000000 => SeccompAction::Kill,
+            0x00000000 => SeccompAction::KillThread,
             0x00030000 => SeccompAction::Trap,
             0x00050000 => SeccompAction::Errno((raw & 0xffff) as u16),
             0x7ff00000 => SeccompAction::Trace,
@@ -111,9 +115,42 @@ pub struct SeccompData {
     pub args: [u64; 6],
 }
 
+impl SeccompData {
+    /// 32-bit word at byte `offset` (aligned and in bounds, see `check_filter`)
+    fn load_word(&self, offset: u32) -> u32 {
+        match offset {
+            0 => self.nr as u32,
+            4 => self.arch,
+            8 => self.instruction_pointer as u32,
+            12 => (self.instruction_pointer >> 32) as u32,
+            _ => {
+                let arg = self.args[(offset as usize - 16) / 8];
+                if offset % 8 == 0 { arg as u32 } else { (arg >> 32) as u32 }
+            }
+        }
+    }
+}
+
 /// Architecture for seccomp
 pub const AUDIT_ARCH_X86_64: u32 = 0xc000003e;
 
+/// Mask of the action part of a filter return value
+pub const SECCOMP_RET_ACTION_FULL: u32 = 0xffff0000;
+/// Mask of the data part (the errno for SECCOMP_RET_ERRNO)
+pub const SECCOMP_RET_DATA: u32 = 0x0000ffff;
+
+/// Size of `struct seccomp_data`, the only packet a filter can load from
+const SECCOMP_DATA_SIZE: u32 = core::mem::size_of::<SeccompData>() as u32;
+/// Maximum instructions in one program
+pub const BPF_MAXINSNS: usize = 4096;
+/// Maximum instructions across a task's filter chain, counting a penalty of
+/// 4 per filter
+const MAX_INSNS_PER_PATH: usize = 32768;
+/// Scratch memory words (M[0..16])
+const BPF_MEMWORDS: u32 = 16;
+/// Largest errno a filter can return
+const MAX_ERRNO: u16 = 4095;
+
 /// BPF instruction
 #[repr(C)]
 #[derive(Debug, Clone, Copy)]
@@ -149,8 +186,23 @@ pub const BPF_MISC: u16 = 0x07;
 pub const BPF_W: u16 = 0x00;
 pub const BPF_H: u16 = 0x08;
 pub const BPF_B: u16 = 0x10;
+pub const BPF_IMM: u16 = 0x00;
 pub const BPF_ABS: u16 = 0x20;
 pub const BPF_MEM: u16 = 0x60;
+pub const BPF_LEN: u16 = 0x80;
+
+// BPF ALU fields
+pub const BPF_ADD: u16 = 0x00;
+pub const BPF_SUB: u16 = 0x10;
+pub const BPF_MUL: u16 = 0x20;
+pub const BPF_DIV: u16 = 0x30;
+pub const BPF_OR: u16 = 0x40;
+pub const BPF_AND: u16 = 0x50;
+pub const BPF_LSH: u16 = 0x60;
+pub const BPF_RSH: u16 = 0x70;
+pub const BPF_NEG: u16 = 0x80;
+pub const BPF_MOD: u16 = 0x90;
+pub const BPF_XOR: u16 = 0xa0;
 
 // BPF JMP fields
 pub const BPF_JA: u16 = 0x00;
@@ -161,6 +213,11 @@ pub const BPF_JSET: u16 = 0x40;
 pub const BPF_K: u16 = 0x00;
 pub const BPF_X: u16 = 0x08;
 
+// BPF RET/MISC fields
+pub const BPF_A: u16 = 0x10;
+pub const BPF_TAX: u16 = 0x00;
+pub const BPF_TXA: u16 = 0x80;
+
 /// Helper macro for BPF instructions
 macro_rules! bpf_stmt {
     ($code:expr, $k:expr) => {
@@ -174,11 +231,162 @@ macro_rules! bpf_jump {
     };
 }
 
+/// Validates a program the way the kernel does before attaching it: known
+/// opcodes only, loads confined to `seccomp_data`, no constant division by
+/// zero, jumps inside the program and a return at the end.
+pub fn check_filter(insns: &[BpfInsn]) -> KResult<()> {
+    if insns.is_empty() || insns.len() > BPF_MAXINSNS {
+        return Err(KError::Invalid);
+    }
+
+    let in_range = |pc: usize, off: u32| pc + 1 + (off as usize) < insns.len();
+    for (pc, insn) in insns.iter().enumerate() {
+        let code = insn.code;
+        let valid = match code & 0x07 {
+            BPF_LD | BPF_LDX => {
+                let class = code & 0x07;
+                if code == BPF_LD | BPF_W | BPF_ABS {
+                    insn.k < SECCOMP_DATA_SIZE && insn.k % 4 == 0
+                } else if code == class | BPF_W | BPF_LEN || code == class | BPF_IMM {
+                    true
+                } else if code == class | BPF_MEM {
+                    insn.k < BPF_MEMWORDS
+                } else {
+                    false
+                }
+            }
+            BPF_ST | BPF_STX => code & !0x07 == 0 && insn.k < BPF_MEMWORDS,
+            BPF_ALU => match code & 0xf0 {
+                BPF_DIV | BPF_MOD => code & BPF_X != 0 || insn.k != 0,
+                BPF_LSH | BPF_RSH => code & BPF_X != 0 || insn.k < 32,
+                BPF_NEG => code & BPF_X == 0,
+                BPF_ADD | BPF_SUB | BPF_MUL | BPF_OR | BPF_AND | BPF_XOR => true,
+                _ => false,
+            },
+            BPF_JMP => match code & 0xf0 {
+                BPF_JA => code == BPF_JMP | BPF_JA && in_range(pc, insn.k),
+                BPF_JEQ | BPF_JGT | BPF_JGE | BPF_JSET => {
+                    in_range(pc, insn.jt as u32) && in_range(pc, insn.jf as u32)
+                }
+                _ => false,
+            },
+            BPF_RET => code == BPF_RET | BPF_K || code == BPF_RET | BPF_A,
+            _ => code == BPF_MISC | BPF_TAX || code == BPF_MISC | BPF_TXA,
+        };
+        if !valid {
+            return Err(KError::Invalid);
+        }
+    }
+
+    if insns[insns.len() - 1].code & 0x07 != BPF_RET {
+        return Err(KError::Invalid);
+    }
+    Ok(())
+}
+
+/// Runs a program accepted by [`check_filter`] and returns its verdict
+pub fn run_filter(insns: &[BpfInsn], data: &SeccompData) -> u32 {
+    let mut a: u32 = 0;
+    let mut x: u32 = 0;
+    let mut mem = [0u32; BPF_MEMWORDS as usize];
+    let mut pc = 0;
+
+    while let Some(insn) = insns.get(pc) {
+        pc += 1;
+        let k = insn.k;
+        let code = insn.code;
+        let operand = if code & BPF_X != 0 { x } else { k };
+        match code & 0x07 {
+            BPF_LD | BPF_LDX => {
+                let value = match code & 0xe0 {
+                    BPF_ABS => data.load_word(k),
+                    BPF_LEN => SECCOMP_DATA_SIZE,
+                    BPF_MEM => mem[k as usize],
+                    _ => k,
+                };
+                if code & 0x07 == BPF_LD { a = value } else { x = value }
+            }
+            BPF_ST => mem[k as usize] = a,
+            BPF_STX => mem[k as usize] = x,
+            BPF_ALU => {
+                a = match code & 0xf0 {
+                    BPF_ADD => a.wrapping_add(operand),
+                    BPF_SUB => a.wrapping_sub(operand),
+                    BPF_MUL => a.wrapping_mul(operand),
+                    // Division by a zero X aborts the program, like Linux
+                    BPF_DIV if operand == 0 => return 0,
+                    BPF_DIV => a / operand,
+                    BPF_MOD if operand == 0 => return 0,
+                    BPF_MOD => a % operand,
+                    BPF_OR => a | operand,
+                    BPF_AND => a & operand,
+                    BPF_XOR => a ^ operand,
+                    BPF_LSH => a.checked_shl(operand).unwrap_or(0),
+                    BPF_RSH => a.checked_shr(operand).unwrap_or(0),
+                    _ => a.wrapping_neg(),
+                };
+            }
+            BPF_JMP => {
+                let taken = match code & 0xf0 {
+                    BPF_JA => {
+                        pc += k as usize;
+                        continue;
+                    }
+                    BPF_JEQ => a == operand,
+                    BPF_JGT => a > operand,
+                    BPF_JGE => a >= operand,
+                    _ => a & operand != 0,
+                };
+                pc += if taken { insn.jt as usize } else { insn.jf as usize };
+            }
+            BPF_RET => return if code & BPF_A != 0 { a } else { k },
+            _ => {
+                if code & 0xf8 == BPF_TXA { a = x } else { x = a }
+            }
+        }
+    }
+
+    // Unreachable for validated programs (they end in RET)
+    SeccompAction::Kill.to_raw()
+}
+
+/// Orders actions by precedence: lower is more restrictive
+fn action_precedence(ret: u32) -> i32 {
+    (ret & SECCOMP_RET_ACTION_FULL) as i32
+}
+
+/// Program for a list of per-syscall rules: check the architecture, then
+/// compare the syscall number against each rule in order.
+fn rules_program(rules: &[SyscallRule], default_action: SeccompAction) -> Vec<BpfInsn> {
+    let mut prog = Vec::with_capacity(5 + rules.len() * 2);
+
+    // Validate architecture (x86_64)
+    // Load architecture
+    prog.push(bpf_stmt!(BPF_LD | BPF_W | BPF_ABS, 4)); // offsetof(seccomp_data, arch)
+    // Jump if arch != x86_64
+    prog.push(bpf_jump!(BPF_JMP | BPF_JEQ | BPF_K, AUDIT_ARCH_X86_64, 1, 0));
+    // Kill if wrong architecture
+    prog.push(bpf_stmt!(BPF_RET | BPF_K, SeccompAction::Kill.to_raw()));
+
+    // Load syscall number
+    prog.push(bpf_stmt!(BPF_LD | BPF_W | BPF_ABS, 0)); // offsetof(seccomp_data, nr)
+
+    for rule in rules {
+        prog.push(bpf_jump!(BPF_JMP | BPF_JEQ | BPF_K, rule.nr as u32, 0, 1));
+        prog.push(bpf_stmt!(BPF_RET | BPF_K, rule.action.to_raw()));
+    }
+
+    // Default action at the end
+    prog.push(bpf_stmt!(BPF_RET | BPF_K, default_action.to_raw()));
+
+    prog
+}
+
 /// Seccomp BPF filter
 #[derive(Debug, Clone)]
 pub struct SeccompFilter {
-    /// BPF program instructions
-    instructions: Vec<BpfInsn>,
+    /// Per-syscall rules, compiled to BPF by `build()`
+    rules: Vec<SyscallRule>,
     /// Default action for unmatched syscalls
     default_action: SeccompAction,
 }
@@ -187,7 +395,7 @@ impl SeccompFilter {
     /// Create a new filter with default KILL action
     pub fn new() -> Self {
         Self {
-            instructions: Vec::new(),
+            rules: Vec::new(),
             default_action: SeccompAction::Kill,
         }
     }
@@ -212,30 +420,12 @@ impl SeccompFilter {
 
     /// Add a rule for a syscall
     pub fn add_rule(&mut self, syscall_nr: u64, action: SeccompAction) {
-        // This simplified implementation stores rules, actual BPF generation
-        // would happen in build()
-        // For now, we use a direct rule storage approach
+        self.rules.push(SyscallRule { nr: syscall_nr, action });
     }
 
     /// Build the BPF program
     pub fn build(&self) -> Vec<BpfInsn> {
-        let mut prog = Vec::new();
-
-        // Validate architecture (x86_64)
-        // Load architecture
-        prog.push(bpf_stmt!(BPF_LD | BPF_W | BPF_ABS, 4)); // offsetof(seccomp_data, arch)
-        // Jump if arch != x86_64
-        prog.push(bpf_jump!(BPF_JMP | BPF_JEQ | BPF_K, AUDIT_ARCH_X86_64, 1, 0));
-        // Kill if wrong architecture
-        prog.push(bpf_stmt!(BPF_RET | BPF_K, SeccompAction::Kill.to_raw()));
-
-        // Load syscall number
-        prog.push(bpf_stmt!(BPF_LD | BPF_W | BPF_ABS, 0)); // offsetof(seccomp_data, nr)
-
-        // Default action at the end
-        prog.push(bpf_stmt!(BPF_RET | BPF_K, self.default_action.to_raw()));
-
-        prog
+        rules_program(&self.rules, self.default_action)
     }
 }
 
@@ -245,13 +435,23 @@ impl Default for SeccompFilter {
     }
 }
 
+/// A validated program attached to a task. Newer filters point at the
+/// ones installed before them; forked children share the chain.
+#[derive(Debug)]
+pub struct AttachedFilter {
+    insns: Vec<BpfInsn>,
+    /// Log every action but ALLOW (SECCOMP_FILTER_FLAG_LOG)
+    log: bool,
+    prev: Option<Arc<AttachedFilter>>,
+}
+
 /// Seccomp state for a process
 #[derive(Debug, Clone)]
 pub struct SeccompState {
     /// Current mode
     mode: SeccompMode,
-    /// Filter (if mode == Filter)
-    filter: Option<Box<SeccompFilter>>,
+    /// Most recently attached filter (if mode == Filter)
+    filter: Option<Arc<AttachedFilter>>,
     /// Filter flags
     flags: SeccompFlags,
 }
@@ -260,7 +460,7 @@ bitflags::bitflags! {
     /// Seccomp filter flags
     #[derive(Debug, Clone, Copy, PartialEq, Eq)]
     pub struct SeccompFlags: u32 {
-        /// Filter should be inherited across fork
+        /// Synchronize all threads of the process to the new filter
         const TSYNC = 1 << 0;
         /// Log all filtered syscalls
         const LOG = 1 << 1;
@@ -268,8 +468,10 @@ bitflags::bitflags! {
         const SPEC_ALLOW = 1 << 2;
         /// Create a listener fd for filter notifications
         const NEW_LISTENER = 1 << 3;
+        /// Report TSYNC failures as ESRCH instead of a thread id
+        const TSYNC_ESRCH = 1 << 4;
         /// Wait for tracer
-        const WAIT_KILLABLE_RECV = 1 << 4;
+        const WAIT_KILLABLE_RECV = 1 << 5;
     }
 }
 
@@ -304,23 +506,67 @@ impl SeccompState {
 
     /// Set filter mode
     pub fn set_filter(&mut self, filter: SeccompFilter, flags: SeccompFlags) -> KResult<()> {
+        self.attach(filter.build(), flags)
+    }
+
+    /// Validate a BPF program and add it on top of the filter chain
+    pub fn attach(&mut self, insns: Vec<BpfInsn>, flags: SeccompFlags) -> KResult<()> {
         // Can add filters when disabled or already in filter mode
         if self.mode == SeccompMode::Strict {
             return Err(KError::Invalid);
         }
+        check_filter(&insns)?;
+
+        let mut total = insns.len();
+        let mut node = self.filter.as_deref();
+        while let Some(f) = node {
+            total += f.insns.len() + 4;
+            node = f.prev.as_deref();
+        }
+        if total > MAX_INSNS_PER_PATH {
+            return Err(KError::NoMemory);
+        }
 
         self.mode = SeccompMode::Filter;
-        self.filter = Some(Box::new(filter));
+        self.filter = Some(Arc::new(AttachedFilter {
+            insns,
+            log: flags.contains(SeccompFlags::LOG),
+            prev: self.filter.take(),
+        }));
         self.flags = flags;
         Ok(())
     }
 
+    /// Whether `self` can replace `other` without dropping any of its
+    /// filters, i.e. `other`'s chain is a prefix of ours (TSYNC)
+    pub fn extends(&self, other: &SeccompState) -> bool {
+        match (&other.mode, &other.filter) {
+            (SeccompMode::Disabled, _) => return true,
+            (SeccompMode::Strict, _) | (_, None) => return false,
+            (SeccompMode::Filter, Some(_)) => {}
+        }
+        let mut node = self.filter.as_ref();
+        while let Some(f) = node {
+            if other.filter.as_ref().map_or(false, |o| Arc::ptr_eq(f, o)) {
+                return true;
+            }
+            node = f.prev.as_ref();
+        }
+        false
+    }
+
     /// Check if a syscall is allowed
     pub fn check_syscall(&self, data: &SeccompData) -> SeccompAction {
+        SeccompAction::from_raw(self.evaluate(data).0)
+    }
+
+    /// Raw verdict for a syscall and whether the deciding filter asked for
+    /// logging
+    pub fn evaluate(&self, data: &SeccompData) -> (u32, bool) {
         match self.mode {
-            SeccompMode::Disabled => SeccompAction::Allow,
-            SeccompMode::Strict => self.check_strict(data.nr as u64),
-            SeccompMode::Filter => self.run_filter(data),
+            SeccompMode::Disabled => (SeccompAction::Allow.to_raw(), false),
+            SeccompMode::Strict => (self.check_strict(data.nr as u64).to_raw(), false),
+            SeccompMode::Filter => self.run_filters(data),
         }
     }
 
@@ -337,17 +583,20 @@ impl SeccompState {
         }
     }
 
-    /// Run BPF filter
-    fn run_filter(&self, data: &SeccompData) -> SeccompAction {
-        let filter = match &self.filter {
-            Some(f) => f,
-            None => return SeccompAction::Allow,
-        };
-
-        // Simple filter execution
-        // A full implementation would run the BPF program
-        // For now, return the default action
-        filter.default_action
+    /// Run every filter in the chain; the most restrictive verdict wins
+    fn run_filters(&self, data: &SeccompData) -> (u32, bool) {
+        let mut ret = SeccompAction::Allow.to_raw();
+        let mut log = false;
+        let mut node = self.filter.as_deref();
+        while let Some(f) = node {
+            let cur = run_filter(&f.insns, data);
+            if action_precedence(cur) < action_precedence(ret) {
+                ret = cur;
+                log = f.log;
+            }
+            node = f.prev.as_deref();
+        }
+        (ret, log)
     }
 }
 
@@ -436,6 +685,11 @@ impl SimpleFilter {
     pub fn rule_count(&self) -> usize {
         self.rules.len()
     }
+
+    /// Compile the rules into a BPF program for `SeccompState::attach`
+    pub fn to_program(&self) -> Vec<BpfInsn> {
+        rules_program(&self.rules, self.default_action)
+    }
 }
 
 impl Default for SimpleFilter {
@@ -458,6 +712,10 @@ pub const SECCOMP_SET_MODE_FILTER: u32 = 1;
 pub const SECCOMP_GET_ACTION_AVAIL: u32 = 2;
 pub const SECCOMP_GET_NOTIF_SIZES: u32 = 3;
 
+/// Modes for prctl(PR_SET_SECCOMP)
+pub const SECCOMP_MODE_STRICT: u64 = 1;
+pub const SECCOMP_MODE_FILTER: u64 = 2;
+
 /// BPF program for seccomp syscall
 #[repr(C)]
 #[derive(Debug, Clone, Copy)]
@@ -469,35 +727,96 @@ pub struct SockFprog {
 }
 
 /// sys_seccomp implementation
-pub fn sys_seccomp(op: u32, flags: u32, args: u64) -> KResult<i64> {
+pub fn sys_seccomp(op: u32, flags: u32, args: u64) -> i64 {
+    use crate::syscall::errno;
+
     match op {
         SECCOMP_SET_MODE_STRICT => {
-            if flags != 0 {
-                return Err(KError::Invalid);
+            if flags != 0 || args != 0 {
+                return errno::EINVAL;
+            }
+            match enable_strict() {
+                Ok(()) => 0,
+                Err(_) => errno::EINVAL,
             }
-            enable_strict()?;
-            Ok(0)
-        }
-        SECCOMP_SET_MODE_FILTER => {
-            // Would need to copy BPF program from userspace
-            // For now, not fully implemented
-            Err(KError::NotSupported)
         }
+        SECCOMP_SET_MODE_FILTER => match set_mode_filter(flags, args) {
+            Ok(ret) => ret,
+            Err(e) => e,
+        },
         SECCOMP_GET_ACTION_AVAIL => {
-            // Check if an action is available
-            let action = args as u32;
-            match SeccompAction::from_raw(action) {
-                SeccompAction::Kill
-                | SeccompAction::KillThread
-                | SeccompAction::Trap
-                | SeccompAction::Errno(_)
-                | SeccompAction::Log
-                | SeccompAction::Allow => Ok(0),
-                _ => Err(KError::Invalid),
+            if flags != 0 {
+                return errno::EINVAL;
+            }
+            let action = match unsafe { crate::syscall::validate_user_buffer(args, 4) } {
+                Some(bytes) => u32::from_ne_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]),
+                None => return errno::EFAULT,
+            };
+            match action & SECCOMP_RET_ACTION_FULL {
+                0x80000000 | 0x00000000 | 0x00030000 | 0x00050000 | 0x7ff00000 | 0x7ffc0000 | 0x7fff0000 => 0,
+                _ => errno::EOPNOTSUPP,
             }
         }
-        _ => Err(KError::Invalid),
+        _ => errno::EINVAL,
+    }
+}
+
+/// SECCOMP_SET_MODE_FILTER: copy the program from userspace and attach it.
+/// Requires NO_NEW_PRIVS so a filter can't be used to confuse a setuid
+/// program into misbehaving.
+fn set_mode_filter(flags: u32, uprog: u64) -> Result<i64, i64> {
+    use crate::syscall::errno;
+
+    let flags = SeccompFlags::from_bits(flags).ok_or(errno::EINVAL)?;
+    if flags.intersects(SeccompFlags::NEW_LISTENER | SeccompFlags::WAIT_KILLABLE_RECV) {
+        // No user-space notification support
+        return Err(errno::EINVAL);
+    }
+
+    let task = crate::sched::current_task();
+    if !task.no_new_privs() {
+        return Err(errno::EACCES);
+    }
+
+    let fprog = match unsafe { crate::syscall::validate_user_buffer(uprog, core::mem::size_of::<SockFprog>()) } {
+        Some(bytes) => unsafe { core::ptr::read_unaligned(bytes.as_ptr() as *const SockFprog) },
+        None => return Err(errno::EFAULT),
+    };
+    let len = fprog.len as usize;
+    if len == 0 || len > BPF_MAXINSNS {
+        return Err(errno::EINVAL);
+    }
+    let bytes = match unsafe { crate::syscall::validate_user_buffer(fprog.filter, len * 8) } {
+        Some(bytes) => bytes,
+        None => return Err(errno::EFAULT),
+    };
+    let insns = bytes
+        .chunks_exact(8)
+        .map(|c| BpfInsn::new(
+            u16::from_ne_bytes([c[0], c[1]]),
+            c[2],
+            c[3],
+            u32::from_ne_bytes([c[4], c[5], c[6], c[7]]),
+        ))
+        .collect();
+
+    let mut state = task.seccomp_state();
+    state.attach(insns, flags).map_err(|e| match e {
+        KError::NoMemory => errno::ENOMEM,
+        _ => errno::EINVAL,
+    })?;
+
+    if flags.contains(SeccompFlags::TSYNC) {
+        if let Err(tid) = crate::sched::sync_thread_group_seccomp(&state) {
+            return if flags.contains(SeccompFlags::TSYNC_ESRCH) {
+                Err(errno::ESRCH)
+            } else {
+                Ok(tid as i64)
+            };
+        }
     }
+    task.set_seccomp_state(state);
+    Ok(0)
 }
 
 /// Enable strict mode for current task
@@ -509,23 +828,48 @@ pub fn enable_strict() -> KResult<()> {
     Ok(())
 }
 
-/// Check if a syscall is allowed for current task
-pub fn check_syscall(nr: u64, args: &[u64; 6]) -> SeccompAction {
+/// Runs the current task's filters before a syscall is dispatched. Returns
+/// the value the syscall returns instead of running, or None to let it run.
+/// The kill actions don't return.
+pub fn filter_syscall(nr: u64, args: &[u64; 6], ip: u64) -> Option<i64> {
+    use crate::signal::sig;
+    use crate::syscall::errno;
+
     let task = crate::sched::current_task();
     let state = task.seccomp_state();
 
     if !state.is_enabled() {
-        return SeccompAction::Allow;
+        return None;
     }
 
     let data = SeccompData {
         nr: nr as i32,
         arch: AUDIT_ARCH_X86_64,
-        instruction_pointer: 0, // Would need to get from trap frame
+        instruction_pointer: ip,
         args: *args,
     };
 
-    state.check_syscall(&data)
+    let (ret, log) = state.evaluate(&data);
+    let action = SeccompAction::from_raw(ret);
+    if log || matches!(action, SeccompAction::Kill | SeccompAction::KillThread | SeccompAction::Log) {
+        crate::kprintln!(
+            "seccomp: pid={} syscall={} ip={:#x} action={:#x}",
+            task.id(), nr, ip, ret
+        );
+    }
+
+    match action {
+        SeccompAction::Allow | SeccompAction::Log => None,
+        SeccompAction::Errno(e) => Some(-(e.min(MAX_ERRNO) as i64)),
+        SeccompAction::Trap => {
+            crate::sched::send_signal_to_current(sig::SIGSYS);
+            Some(errno::ENOSYS)
+        }
+        // No tracer can be attached: the syscall fails like on Linux
+        SeccompAction::Trace => Some(errno::ENOSYS),
+        SeccompAction::KillThread => crate::syscall::sys_exit(128 + sig::SIGSYS as u64),
+        SeccompAction::Kill => crate::sched::exit_thread_group(128 + sig::SIGSYS as u64),
+    }
 }
 
 /// Predefined filter: minimal filter for sandboxed processes
@@ -515,9 +515,9 @@ impl TpmDiskUnlock {
     }
 
     /// Get LUKS UUID
-    fn get_luks_uuid(&self, _device: &str) -> Option<String> {
-        // Would read LUKS header
-        Some("00000000-0000-0000-0000-000000000000".to_string())
+    fn get_luks_uuid(&self, device: &str) -> Option<String> {
+        let dev = crate::storage::find_device_by_path(device).and_then(crate::storage::get_device)?;
+        crate::storage::crypt::luks_uuid(&*dev)
     }
 
     /// Unenroll a key slot
@@ -731,16 +731,36 @@ impl TpmDiskUnlock {
         Ok("unsealed_passphrase".to_string())
     }
 
-    /// Open LUKS device
+    /// Open LUKS device as /dev/mapper/luks-<uuid>
+    ///
+    /// Every keyslot is tried, so `_slot` is only a hint.
     fn open_luks(
         &self,
-        _device: &str,
-        _passphrase: &str,
+        device: &str,
+        passphrase: &str,
         _slot: u8,
     ) -> Result<(), TpmUnlockError> {
-        // Would call cryptsetup open
-        kprintln!("tpm-disk-unlock: Opening LUKS device");
-        Ok(())
+        use crate::storage::{self, crypt};
+
+        let dev = storage::find_device_by_path(device)
+            .and_then(storage::get_device)
+            .ok_or(TpmUnlockError::LuksOpenFailed)?;
+        if crypt::mapping_for(dev.id()).is_some() {
+            return Ok(());
+        }
+
+        let uuid = crypt::luks_uuid(&*dev).ok_or(TpmUnlockError::LuksOpenFailed)?;
+        let name = alloc::format!("luks-{}", uuid);
+        match crypt::open(&name, dev, passphrase.as_bytes(), crypt::CryptOptions::default()) {
+            Ok(_) => {
+                kprintln!("tpm-disk-unlock: {} opened as /dev/mapper/{}", device, name);
+                Ok(())
+            }
+            Err(e) => {
+                kprintln!("tpm-disk-unlock: Opening {} failed: {:?}", device, e);
+                Err(TpmUnlockError::LuksOpenFailed)
+            }
+        }
     }
 
     /// Unlock with password fallback
@@ -443,6 +443,23 @@ impl SignalState {
         Some(signum)
     }
 
+    /// Retira o menor signal pendente dentro de `mask`, bloqueado ou não
+    /// (leitura de signalfd).
+    pub fn dequeue_masked(&self, mask: u64) -> Option<u32> {
+        loop {
+            let pending = self.pending.load(Ordering::Acquire);
+            let candidates = pending & mask & !1;
+            if candidates == 0 {
+                return None;
+            }
+            let signum = candidates.trailing_zeros();
+            let bit = 1u64 << signum;
+            if self.pending.fetch_and(!bit, Ordering::AcqRel) & bit != 0 {
+                return Some(signum);
+            }
+        }
+    }
+
     /// Verifica se há algum signal pendente não bloqueado.
     pub fn has_pending(&self) -> bool {
         let pending = self.pending.load(Ordering::Acquire);
@@ -674,11 +691,6 @@ pub fn restore_signal_frame(
 // signalfd - File descriptor for signals
 // ============================================================================
 
-use alloc::collections::VecDeque;
-use alloc::sync::Arc;
-use alloc::vec::Vec;
-use spin::Mutex;
-
 /// signalfd flags
 pub mod sfd_flags {
     /// Non-blocking mode
@@ -785,18 +797,21 @@ impl SignalfdSiginfo {
 }
 
 /// A signalfd instance
+///
+/// Reading takes the signals in the mask out of the reader's own pending
+/// set, so the program must keep them blocked or they are delivered
+/// normally before it gets to read them.
 pub struct SignalFd {
     /// Signal mask (which signals to accept)
     mask: AtomicU64,
     /// Flags (SFD_NONBLOCK, SFD_CLOEXEC)
     flags: u32,
-    /// Queued signals
-    queue: Mutex<VecDeque<SignalfdSiginfo>>,
-    /// Maximum queue size
-    max_queue: usize,
 }
 
 impl SignalFd {
+    /// SIGKILL and SIGSTOP are never read from a signalfd
+    const UNREADABLE: u64 = (1u64 << sig::SIGKILL) | (1u64 << sig::SIGSTOP);
+
     /// Create a new signalfd
     ///
     /// # Arguments
@@ -804,16 +819,14 @@ impl SignalFd {
     /// * `flags` - Flags (SFD_NONBLOCK, SFD_CLOEXEC)
     pub fn new(mask: u64, flags: u32) -> Self {
         Self {
-            mask: AtomicU64::new(mask),
+            mask: AtomicU64::new(mask & !Self::UNREADABLE),
             flags,
-            queue: Mutex::new(VecDeque::with_capacity(32)),
-            max_queue: 256,
         }
     }
 
     /// Update the signal mask
     pub fn set_mask(&self, mask: u64) {
-        self.mask.store(mask, Ordering::SeqCst);
+        self.mask.store(mask & !Self::UNREADABLE, Ordering::SeqCst);
     }
 
     /// Get the current signal mask
@@ -830,53 +843,27 @@ impl SignalFd {
         (mask & (1u64 << signum)) != 0
     }
 
-    /// Queue a signal for reading
+    /// Read signals pending in `state` into `buf`, one signalfd_siginfo each
     ///
-    /// Returns true if the signal was queued, false if queue is full
-    pub fn queue_signal(&self, info: &Siginfo) -> bool {
-        let signum = info.si_signo as u32;
-        if !self.accepts_signal(signum) {
-            return false;
-        }
-
-        let mut queue = self.queue.lock();
-        if queue.len() >= self.max_queue {
-            return false;
-        }
-
-        queue.push_back(SignalfdSiginfo::from_siginfo(info));
-        true
-    }
-
-    /// Read signals from the fd
-    ///
-    /// Returns the number of signals read, or error
-    pub fn read(&self, buf: &mut [u8]) -> Result<usize, SignalFdError> {
+    /// Returns the number of bytes read, or WouldBlock if none is pending
+    pub fn read(&self, state: &SignalState, buf: &mut [u8]) -> Result<usize, SignalFdError> {
         if buf.len() < SignalfdSiginfo::SIZE {
             return Err(SignalFdError::InvalidSize);
         }
 
-        let mut queue = self.queue.lock();
-
-        if queue.is_empty() {
-            if (self.flags & sfd_flags::SFD_NONBLOCK) != 0 {
-                return Err(SignalFdError::WouldBlock);
-            }
-            // Would block - in a real implementation we'd wait here
-            return Err(SignalFdError::WouldBlock);
-        }
-
         let mut bytes_read = 0;
         let max_signals = buf.len() / SignalfdSiginfo::SIZE;
 
         for _ in 0..max_signals {
-            if let Some(info) = queue.pop_front() {
-                let bytes = info.to_bytes();
-                buf[bytes_read..bytes_read + SignalfdSiginfo::SIZE].copy_from_slice(&bytes);
-                bytes_read += SignalfdSiginfo::SIZE;
-            } else {
-                break;
-            }
+            let signum = match state.dequeue_masked(self.get_mask()) {
+                Some(s) => s,
+                None => break,
+            };
+            let mut info = Siginfo::new(signum as i32);
+            info.si_code = si_code::SI_USER;
+            let bytes = SignalfdSiginfo::from_siginfo(&info).to_bytes();
+            buf[bytes_read..bytes_read + SignalfdSiginfo::SIZE].copy_from_slice(&bytes);
+            bytes_read += SignalfdSiginfo::SIZE;
         }
 
         if bytes_read == 0 {
@@ -886,14 +873,9 @@ impl SignalFd {
         }
     }
 
-    /// Check if there are signals ready to read
-    pub fn is_readable(&self) -> bool {
-        !self.queue.lock().is_empty()
-    }
-
-    /// Get the number of queued signals
-    pub fn queued_count(&self) -> usize {
-        self.queue.lock().len()
+    /// Check if a signal in the mask is pending in `state`
+    pub fn is_readable(&self, state: &SignalState) -> bool {
+        state.pending_mask() & self.get_mask() != 0
     }
 
     /// Check if non-blocking mode is enabled
@@ -917,67 +899,3 @@ pub enum SignalFdError {
     /// Invalid signal mask
     InvalidMask,
 }
-
-/// Global registry of signalfd instances (for delivering signals)
-static SIGNALFD_REGISTRY: spin::Once<Mutex<Vec<Arc<SignalFd>>>> = spin::Once::new();
-
-fn signalfd_registry() -> &'static Mutex<Vec<Arc<SignalFd>>> {
-    SIGNALFD_REGISTRY.call_once(|| Mutex::new(Vec::new()))
-}
-
-/// Register a signalfd for signal delivery
-pub fn register_signalfd(sfd: Arc<SignalFd>) {
-    signalfd_registry().lock().push(sfd);
-}
-
-/// Unregister a signalfd
-pub fn unregister_signalfd(sfd: &Arc<SignalFd>) {
-    let mut registry = signalfd_registry().lock();
-    registry.retain(|s| !Arc::ptr_eq(s, sfd));
-}
-
-/// Deliver a signal to all matching signalfds
-///
-/// Returns true if at least one signalfd accepted the signal
-pub fn deliver_to_signalfds(info: &Siginfo) -> bool {
-    let registry = signalfd_registry().lock();
-    let mut delivered = false;
-
-    for sfd in registry.iter() {
-        if sfd.queue_signal(info) {
-            delivered = true;
-        }
-    }
-
-    delivered
-}
-
-/// Create a new signalfd
-///
-/// This is the implementation of the signalfd() syscall
-pub fn sys_signalfd(fd: i32, mask: u64, flags: u32) -> Result<Arc<SignalFd>, SignalFdError> {
-    // Validate flags
-    let valid_flags = sfd_flags::SFD_NONBLOCK | sfd_flags::SFD_CLOEXEC;
-    if (flags & !valid_flags) != 0 {
-        return Err(SignalFdError::InvalidMask);
-    }
-
-    if fd == -1 {
-        // Create new signalfd
-        let sfd = Arc::new(SignalFd::new(mask, flags));
-        register_signalfd(Arc::clone(&sfd));
-        Ok(sfd)
-    } else {
-        // Update existing signalfd - would need to lookup by fd
-        // For now, just create a new one
-        let sfd = Arc::new(SignalFd::new(mask, flags));
-        register_signalfd(Arc::clone(&sfd));
-        Ok(sfd)
-    }
-}
-
-/// Initialize signalfd subsystem
-pub fn init_signalfd() {
-    SIGNALFD_REGISTRY.call_once(|| Mutex::new(Vec::new()));
-    crate::kprintln!("signalfd: subsystem initialized");
-}
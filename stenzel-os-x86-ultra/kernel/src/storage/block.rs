@@ -20,6 +20,14 @@
 
         /// Escreve `count` blocos a partir de `lba` de `data`.
         fn write_blocks(&self, lba: u64, count: u32, data: &[u8]) -> KResult<()>;
+
+        /// Descarta `count` blocos a partir de `lba` (TRIM/UNMAP).
+        /// O conteúdo descartado fica indefinido; dispositivos sem suporte
+        /// retornam `NotSupported`.
+        fn discard(&self, lba: u64, count: u32) -> KResult<()> {
+            let _ = (lba, count);
+            Err(KError::NotSupported)
+        }
     }
 
     pub fn check_io_args(block_size: u32, count: u32, buf_len: usize) -> KResult<()> {
@@ -73,6 +81,8 @@
             }
             // Traduz LBA
             let abs_lba = self.start_lba + lba;
+            // io.max/io.stat do cgroup de quem fez o I/O
+            super::iosched::throttle(self.id.0, super::iosched::IoRequestType::Read, out.len() as u64);
             self.inner.read_blocks(abs_lba, count, out)
         }
 
@@ -83,6 +93,16 @@
             }
             // Traduz LBA
             let abs_lba = self.start_lba + lba;
+            // io.max/io.stat do cgroup de quem fez o I/O
+            super::iosched::throttle(self.id.0, super::iosched::IoRequestType::Write, data.len() as u64);
             self.inner.write_blocks(abs_lba, count, data)
         }
+
+        fn discard(&self, lba: u64, count: u32) -> KResult<()> {
+            // Verifica limites
+            if lba >= self.num_blocks || lba + count as u64 > self.num_blocks {
+                return Err(KError::OutOfRange);
+            }
+            self.inner.discard(self.start_lba + lba, count)
+        }
     }
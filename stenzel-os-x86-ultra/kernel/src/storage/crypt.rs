@@ -0,0 +1,323 @@
+//! dm-crypt: transparent encryption of a block device.
+//!
+//! `CryptBlockDevice` wraps any `BlockDevice` holding a LUKS volume and
+//! exposes its data segment. Reads are decrypted and writes encrypted
+//! with AES-XTS, one encryption sector at a time, with plain64 IVs
+//! counted in encryption sectors from the start of the segment, as
+//! cryptsetup sets up dm-crypt for LUKS2 (`iv_large_sectors`). LUKS1 only
+//! has 512-byte sectors, so both count the same there.
+//!
+//! Open devices are registered by name, like `/dev/mapper/<name>`.
+
+#![allow(dead_code)]
+
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use super::block::{check_io_args, BlockDevice, BlockDeviceId};
+use crate::crypto::luks::{
+    wipe, LuksError, LuksHeader, LuksVolume, XtsKey, CIPHER_AES_XTS_PLAIN64, LUKS_HEADER_SIZE,
+    LUKS_MAGIC, SECTOR_SIZE,
+};
+use crate::crypto::luks2::{self, Segment};
+use crate::sync::IrqSafeMutex;
+use crate::util::{KError, KResult};
+
+/// Options of a mapping, as in crypttab
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CryptOptions {
+    /// Pass discards down to the device. Off by default: trimmed blocks
+    /// reveal which parts of the volume are unused.
+    pub allow_discards: bool,
+    pub read_only: bool,
+}
+
+impl CryptOptions {
+    /// Parse a comma separated option list ("allow-discards", "discard",
+    /// "ro"/"readonly"); unknown options are ignored
+    pub fn parse(list: &str) -> Self {
+        let mut options = Self::default();
+        for option in list.split(',') {
+            match option.trim() {
+                "allow-discards" | "discard" => options.allow_discards = true,
+                "ro" | "readonly" | "read-only" => options.read_only = true,
+                _ => {}
+            }
+        }
+        options
+    }
+}
+
+/// Decrypted view of the data segment of a LUKS device
+pub struct CryptBlockDevice {
+    id: BlockDeviceId,
+    inner: Arc<dyn BlockDevice>,
+    key: XtsKey,
+    /// First block of the segment on `inner`
+    start: u64,
+    num_blocks: u64,
+    /// Encryption sector, which is also the block size of this device
+    sector_size: u32,
+    /// Blocks of `inner` per encryption sector
+    ratio: u64,
+    /// IV of the first sector: the LUKS2 `iv_tweak`, which is in 512-byte
+    /// units, converted to encryption sectors
+    iv_offset: u64,
+    options: CryptOptions,
+}
+
+impl CryptBlockDevice {
+    /// Map `segment` of `inner` with an already recovered volume key
+    pub fn new(
+        inner: Arc<dyn BlockDevice>,
+        id: BlockDeviceId,
+        volume_key: &[u8],
+        segment: &Segment,
+        options: CryptOptions,
+    ) -> KResult<Self> {
+        if segment.kind != "crypt" || segment.encryption != CIPHER_AES_XTS_PLAIN64 {
+            return Err(KError::NotSupported);
+        }
+        let key = XtsKey::new(volume_key).ok_or(KError::NotSupported)?;
+
+        let inner_block = inner.block_size() as u64;
+        let sector_size = segment.sector_size as u64;
+        if !(512..=4096).contains(&sector_size)
+            || !sector_size.is_power_of_two()
+            || sector_size % inner_block != 0
+            || segment.offset % inner_block != 0
+            || segment.iv_tweak % (sector_size / SECTOR_SIZE as u64) != 0
+        {
+            return Err(KError::NotSupported);
+        }
+
+        let device_size = inner.num_blocks() * inner_block;
+        let end = match segment.size {
+            Some(size) => segment.offset.checked_add(size).ok_or(KError::OutOfRange)?,
+            None => device_size,
+        };
+        if end > device_size || end <= segment.offset {
+            return Err(KError::OutOfRange);
+        }
+
+        Ok(Self {
+            id,
+            start: segment.offset / inner_block,
+            num_blocks: (end - segment.offset) / sector_size,
+            sector_size: segment.sector_size,
+            ratio: sector_size / inner_block,
+            iv_offset: segment.iv_tweak / (sector_size / SECTOR_SIZE as u64),
+            key,
+            inner,
+            options,
+        })
+    }
+
+    /// Unlock the LUKS header on `inner` with `passphrase` and map its data
+    /// segment. LUKS2 headers are read from disk; anything else is taken
+    /// as the legacy `crypto::luks` header.
+    pub fn open(
+        inner: Arc<dyn BlockDevice>,
+        id: BlockDeviceId,
+        passphrase: &[u8],
+        options: CryptOptions,
+    ) -> KResult<Self> {
+        let head = luks2::read_bytes(&*inner, 0, LUKS_HEADER_SIZE)?;
+
+        if luks2::is_luks2(&head) {
+            let header = luks2::read_header(&*inner)?;
+            let segment = header.crypt_segment()?.clone();
+            let mut volume_key = luks2::unlock(&*inner, &header, passphrase)?;
+            let device = Self::new(inner, id, &volume_key, &segment, options);
+            wipe(&mut volume_key);
+            return device;
+        }
+
+        let mut volume = LuksVolume::from_header("", &head).ok_or(LuksError::InvalidHeader)?;
+        volume.unlock(passphrase)?;
+        let segment = Segment::legacy(volume.payload_offset() * SECTOR_SIZE as u64);
+        let device = Self::new(inner, id, volume.master_key().unwrap_or_default(), &segment, options);
+        volume.lock();
+        device
+    }
+
+    /// Underlying (encrypted) device
+    pub fn inner(&self) -> &Arc<dyn BlockDevice> {
+        &self.inner
+    }
+
+    pub fn options(&self) -> CryptOptions {
+        self.options
+    }
+
+    /// Blocks of `inner` backing `count` blocks from `lba`
+    fn translate(&self, lba: u64, count: u32) -> KResult<(u64, u32)> {
+        if lba.checked_add(count as u64).is_none_or(|end| end > self.num_blocks) {
+            return Err(KError::OutOfRange);
+        }
+        let inner_count = u32::try_from(count as u64 * self.ratio).map_err(|_| KError::Invalid)?;
+        Ok((self.start + lba * self.ratio, inner_count))
+    }
+
+    /// plain64 IV of a sector: its position in encryption sectors
+    fn iv(&self, sector: u64) -> u64 {
+        self.iv_offset.wrapping_add(sector)
+    }
+}
+
+impl BlockDevice for CryptBlockDevice {
+    fn id(&self) -> BlockDeviceId {
+        self.id
+    }
+
+    fn block_size(&self) -> u32 {
+        self.sector_size
+    }
+
+    fn num_blocks(&self) -> u64 {
+        self.num_blocks
+    }
+
+    fn read_blocks(&self, lba: u64, count: u32, out: &mut [u8]) -> KResult<()> {
+        check_io_args(self.sector_size, count, out.len())?;
+        let (inner_lba, inner_count) = self.translate(lba, count)?;
+        self.inner.read_blocks(inner_lba, inner_count, out)?;
+        for (i, sector) in out.chunks_exact_mut(self.sector_size as usize).enumerate() {
+            self.key.decrypt_sector(sector, self.iv(lba + i as u64));
+        }
+        Ok(())
+    }
+
+    fn write_blocks(&self, lba: u64, count: u32, data: &[u8]) -> KResult<()> {
+        if self.options.read_only {
+            return Err(KError::ReadOnly);
+        }
+        check_io_args(self.sector_size, count, data.len())?;
+        let (inner_lba, inner_count) = self.translate(lba, count)?;
+        let mut buf = data.to_vec();
+        for (i, sector) in buf.chunks_exact_mut(self.sector_size as usize).enumerate() {
+            self.key.encrypt_sector(sector, self.iv(lba + i as u64));
+        }
+        self.inner.write_blocks(inner_lba, inner_count, &buf)
+    }
+
+    fn discard(&self, lba: u64, count: u32) -> KResult<()> {
+        if !self.options.allow_discards {
+            return Err(KError::NotSupported);
+        }
+        if self.options.read_only {
+            return Err(KError::ReadOnly);
+        }
+        let (inner_lba, inner_count) = self.translate(lba, count)?;
+        self.inner.discard(inner_lba, inner_count)
+    }
+}
+
+// ============================================================================
+// Mappings (/dev/mapper)
+// ============================================================================
+
+/// Device IDs handed to mappings, like dm-0, dm-1, ...
+pub const MAPPER_ID_BASE: u32 = 200;
+
+struct Mapping {
+    name: String,
+    device: Arc<CryptBlockDevice>,
+}
+
+static MAPPINGS: IrqSafeMutex<Vec<Mapping>> = IrqSafeMutex::new(Vec::new());
+static NEXT_ID: AtomicU32 = AtomicU32::new(MAPPER_ID_BASE);
+
+/// Whether `device` starts with a LUKS header (any version)
+pub fn is_luks(device: &dyn BlockDevice) -> bool {
+    luks2::read_bytes(device, 0, LUKS_MAGIC.len()).is_ok_and(|magic| magic == LUKS_MAGIC)
+}
+
+/// UUID from the LUKS header of `device`
+pub fn luks_uuid(device: &dyn BlockDevice) -> Option<String> {
+    let head = luks2::read_bytes(device, 0, LUKS_HEADER_SIZE).ok()?;
+    if luks2::is_luks2(&head) {
+        return luks2::read_header(device).ok().map(|header| header.uuid);
+    }
+    LuksHeader::from_bytes(&head).map(|header| header.uuid)
+}
+
+/// Unlock `inner` and register it as `/dev/mapper/<name>`
+pub fn open(
+    name: &str,
+    inner: Arc<dyn BlockDevice>,
+    passphrase: &[u8],
+    options: CryptOptions,
+) -> KResult<Arc<CryptBlockDevice>> {
+    if name.is_empty() || name.contains('/') {
+        return Err(KError::Invalid);
+    }
+    {
+        let mappings = MAPPINGS.lock();
+        if mappings.iter().any(|m| m.name == name) {
+            return Err(KError::AlreadyExists);
+        }
+        if mappings.iter().any(|m| m.device.inner.id() == inner.id()) {
+            return Err(KError::Busy);
+        }
+    }
+
+    // Key derivation is slow; keep the table unlocked meanwhile
+    let id = BlockDeviceId(NEXT_ID.fetch_add(1, Ordering::Relaxed));
+    let device = Arc::new(CryptBlockDevice::open(inner, id, passphrase, options)?);
+
+    let mut mappings = MAPPINGS.lock();
+    if mappings.iter().any(|m| m.name == name || m.device.inner.id() == device.inner.id()) {
+        return Err(KError::AlreadyExists);
+    }
+    mappings.push(Mapping { name: String::from(name), device: Arc::clone(&device) });
+    crate::kprintln!("dm-crypt: {} aberto ({} blocos de {} bytes)", name, device.num_blocks, device.sector_size);
+    Ok(device)
+}
+
+/// Remove a mapping and drop its key. Fails with `Busy` while the device
+/// is still referenced, e.g. by a mounted filesystem.
+pub fn close(name: &str) -> KResult<()> {
+    let mut mappings = MAPPINGS.lock();
+    let index = mappings.iter().position(|m| m.name == name).ok_or(KError::NotFound)?;
+    if Arc::strong_count(&mappings[index].device) > 1 {
+        return Err(KError::Busy);
+    }
+    mappings.remove(index);
+    Ok(())
+}
+
+/// Mapping registered as `name`
+pub fn get(name: &str) -> Option<Arc<CryptBlockDevice>> {
+    MAPPINGS.lock().iter().find(|m| m.name == name).map(|m| Arc::clone(&m.device))
+}
+
+/// Mapping with device ID `id`
+pub fn get_by_id(id: u32) -> Option<Arc<CryptBlockDevice>> {
+    MAPPINGS.lock().iter().find(|m| m.device.id.0 == id).map(|m| Arc::clone(&m.device))
+}
+
+/// Mapping opened on top of `inner`
+pub fn mapping_for(inner: BlockDeviceId) -> Option<Arc<CryptBlockDevice>> {
+    MAPPINGS.lock().iter().find(|m| m.device.inner.id() == inner).map(|m| Arc::clone(&m.device))
+}
+
+/// Names of the open mappings
+pub fn names() -> Vec<String> {
+    MAPPINGS.lock().iter().map(|m| m.name.clone()).collect()
+}
+
+/// The device to mount for `device`: its open mapping when it holds a
+/// LUKS volume, the device itself otherwise. A locked LUKS device fails
+/// with `PermissionDenied`.
+pub fn resolve(device: Arc<dyn BlockDevice>) -> KResult<Arc<dyn BlockDevice>> {
+    if !is_luks(&*device) {
+        return Ok(device);
+    }
+    match mapping_for(device.id()) {
+        Some(mapped) => Ok(mapped),
+        None => Err(KError::PermissionDenied),
+    }
+}
@@ -7,7 +7,7 @@
 //! - Priority-based scheduling
 
 use alloc::collections::{BTreeMap, VecDeque};
-use alloc::string::{String, ToString};
+use alloc::string::String;
 use alloc::vec::Vec;
 use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use crate::sync::IrqSafeMutex;
@@ -760,3 +760,65 @@ pub fn set_default_scheduler(sched_type: SchedulerType) {
 pub fn get_default_scheduler() -> SchedulerType {
     *DEFAULT_SCHEDULER.lock()
 }
+
+// ==================== cgroup io.max ====================
+
+/// Major number of the block devices in io.max/io.stat keys ("259:<id>")
+pub const BLOCK_MAJOR: u32 = 259;
+
+/// io.max/io.stat key of a block device
+pub fn device_key(device_id: DeviceId) -> String {
+    alloc::format!("{}:{}", BLOCK_MAJOR, device_id)
+}
+
+/// Block device id of an io.max key
+pub fn parse_device_key(key: &str) -> Option<DeviceId> {
+    let (major, minor) = key.split_once(':')?;
+    if major.parse::<u32>().ok()? != BLOCK_MAJOR {
+        return None;
+    }
+    minor.parse().ok()
+}
+
+/// Time until which each task has to wait for the I/O it issued over its
+/// cgroup's io.max limits, keyed by task id
+static THROTTLED_TASKS: IrqSafeMutex<BTreeMap<u64, u64>> = IrqSafeMutex::new(BTreeMap::new());
+
+/// Account a request of `bytes` to `device` issued by the current task in
+/// its cgroup's io.max/io.stat
+///
+/// The request itself is never delayed here, since the caller usually
+/// holds filesystem locks. Going over the limits makes the task wait in
+/// `throttle_current` before it returns to user space instead.
+pub fn throttle(device_id: DeviceId, req_type: IoRequestType, bytes: u64) {
+    let write = match req_type {
+        IoRequestType::Read => false,
+        IoRequestType::Write => true,
+        IoRequestType::Discard | IoRequestType::Flush => return,
+    };
+    let (Some(cgroup), Some(task)) = (crate::cgroups::current_cgroup(), crate::sched::try_current_task()) else {
+        return;
+    };
+
+    let until = cgroup.io_throttled_until(device_id, write, bytes, crate::time::uptime_ns());
+    cgroup.charge_io(device_id, write, bytes);
+    if let Some(until) = until {
+        let mut tasks = THROTTLED_TASKS.lock();
+        let deadline = tasks.entry(task.id()).or_insert(0);
+        *deadline = (*deadline).max(until);
+    }
+}
+
+/// Wait out the io.max delay of the current task. Called on the way back
+/// to user space, with no locks held.
+pub fn throttle_current() {
+    let Some(task) = crate::sched::try_current_task() else {
+        return;
+    };
+    let Some(deadline) = THROTTLED_TASKS.lock().remove(&task.id()) else {
+        return;
+    };
+    while crate::time::uptime_ns() < deadline {
+        crate::task::yield_now();
+    }
+}
@@ -0,0 +1,260 @@
+//! Loop devices: a regular file seen as a block device.
+//!
+//! `LoopDevice` forwards block I/O to `read_at`/`write_at` on any VFS
+//! inode, optionally restricted to a window of the file (`offset` and
+//! `size_limit`, as with `losetup --offset --sizelimit`). Attached devices
+//! show up as `/dev/loop<N>`.
+
+#![allow(dead_code)]
+
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use super::block::{check_io_args, BlockDevice, BlockDeviceId};
+use crate::fs::{perm, Inode, InodeKind, MountFlags};
+use crate::security::Cred;
+use crate::sync::IrqSafeMutex;
+use crate::util::{KError, KResult};
+
+/// Device IDs of loop0, loop1, ...
+pub const LOOP_ID_BASE: u32 = 150;
+/// Number of loop devices (/dev/loop0 to /dev/loop31)
+pub const MAX_LOOP_DEVICES: usize = 32;
+
+/// How a file is attached, as in LOOP_SET_STATUS64
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LoopConfig {
+    /// Start of the device within the file, in bytes
+    pub offset: u64,
+    /// Maximum size of the device in bytes (0: up to the end of the file)
+    pub size_limit: u64,
+    /// Logical block size: 512, 1024, 2048 or 4096
+    pub block_size: u32,
+    pub read_only: bool,
+}
+
+impl Default for LoopConfig {
+    fn default() -> Self {
+        Self {
+            offset: 0,
+            size_limit: 0,
+            block_size: 512,
+            read_only: false,
+        }
+    }
+}
+
+/// A file (or a window of it) exposed as a block device
+pub struct LoopDevice {
+    id: BlockDeviceId,
+    index: usize,
+    file: Inode,
+    /// Path the file was attached from, for listings
+    backing: String,
+    config: LoopConfig,
+    /// Size in blocks, fixed at attach time until `set_capacity`
+    num_blocks: AtomicU64,
+}
+
+impl LoopDevice {
+    /// Attach `file` as device `index`. Files on read-only mounts give
+    /// read-only devices.
+    pub fn new(file: Inode, backing: &str, index: usize, mut config: LoopConfig) -> KResult<Self> {
+        if file.kind() != InodeKind::File && file.kind() != InodeKind::BlockDev {
+            return Err(KError::Invalid);
+        }
+        if !(512..=4096).contains(&config.block_size) || !config.block_size.is_power_of_two() {
+            return Err(KError::Invalid);
+        }
+        if config.offset % 512 != 0 || config.size_limit % 512 != 0 {
+            return Err(KError::Invalid);
+        }
+        if file.0.mount_flags().contains(MountFlags::RDONLY) {
+            config.read_only = true;
+        }
+
+        let device = Self {
+            id: BlockDeviceId(LOOP_ID_BASE + index as u32),
+            index,
+            file,
+            backing: String::from(backing),
+            config,
+            num_blocks: AtomicU64::new(0),
+        };
+        device.set_capacity()?;
+        Ok(device)
+    }
+
+    /// Recompute the size after the backing file grew or shrank
+    /// (LOOP_SET_CAPACITY)
+    pub fn set_capacity(&self) -> KResult<u64> {
+        let file_size = self.file.0.size()? as u64;
+        let mut size = file_size.saturating_sub(self.config.offset);
+        if self.config.size_limit != 0 {
+            size = size.min(self.config.size_limit);
+        }
+        let blocks = size / self.config.block_size as u64;
+        self.num_blocks.store(blocks, Ordering::Release);
+        Ok(blocks)
+    }
+
+    /// N in /dev/loopN
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    pub fn name(&self) -> String {
+        alloc::format!("loop{}", self.index)
+    }
+
+    pub fn backing_file(&self) -> &str {
+        &self.backing
+    }
+
+    pub fn config(&self) -> LoopConfig {
+        self.config
+    }
+
+    /// Byte position in the file of block `lba`, after checking the range
+    fn file_offset(&self, lba: u64, count: u32) -> KResult<usize> {
+        if lba.checked_add(count as u64).is_none_or(|end| end > self.num_blocks()) {
+            return Err(KError::OutOfRange);
+        }
+        let offset = self.config.offset + lba * self.config.block_size as u64;
+        usize::try_from(offset).map_err(|_| KError::OutOfRange)
+    }
+}
+
+impl BlockDevice for LoopDevice {
+    fn id(&self) -> BlockDeviceId {
+        self.id
+    }
+
+    fn block_size(&self) -> u32 {
+        self.config.block_size
+    }
+
+    fn num_blocks(&self) -> u64 {
+        self.num_blocks.load(Ordering::Acquire)
+    }
+
+    fn read_blocks(&self, lba: u64, count: u32, out: &mut [u8]) -> KResult<()> {
+        check_io_args(self.config.block_size, count, out.len())?;
+        let offset = self.file_offset(lba, count)?;
+        let mut done = 0;
+        while done < out.len() {
+            let n = self.file.0.read_at(offset + done, &mut out[done..])?;
+            if n == 0 {
+                // The file shrank below the device size: reads past the
+                // end see zeros, as with a hole
+                out[done..].fill(0);
+                break;
+            }
+            done += n;
+        }
+        Ok(())
+    }
+
+    fn write_blocks(&self, lba: u64, count: u32, data: &[u8]) -> KResult<()> {
+        if self.config.read_only {
+            return Err(KError::ReadOnly);
+        }
+        check_io_args(self.config.block_size, count, data.len())?;
+        let offset = self.file_offset(lba, count)?;
+        let mut done = 0;
+        while done < data.len() {
+            let n = self.file.0.write_at(offset + done, &data[done..])?;
+            if n == 0 {
+                return Err(KError::IO);
+            }
+            done += n;
+        }
+        Ok(())
+    }
+}
+
+// ============================================================================
+// /dev/loopN
+// ============================================================================
+
+static LOOPS: IrqSafeMutex<Vec<Option<Arc<LoopDevice>>>> = IrqSafeMutex::new(Vec::new());
+
+/// Attach `file` to the first free loop device
+pub fn attach(file: Inode, backing: &str, config: LoopConfig) -> KResult<Arc<LoopDevice>> {
+    let mut loops = LOOPS.lock();
+    let index = match loops.iter().position(Option::is_none) {
+        Some(index) => index,
+        None if loops.len() < MAX_LOOP_DEVICES => {
+            loops.push(None);
+            loops.len() - 1
+        }
+        None => return Err(KError::Busy),
+    };
+
+    let device = Arc::new(LoopDevice::new(file, backing, index, config)?);
+    loops[index] = Some(Arc::clone(&device));
+    crate::kprintln!(
+        "loop: {} ligado a {} ({} blocos de {} bytes)",
+        device.name(),
+        backing,
+        device.num_blocks(),
+        config.block_size
+    );
+    Ok(device)
+}
+
+/// Attach the file at `path`, resolved with the permissions of `cred`.
+/// The file must be writable by `cred` unless the device is read-only.
+pub fn attach_path(path: &str, cred: &Cred, config: LoopConfig) -> KResult<Arc<LoopDevice>> {
+    let file = crate::fs::vfs_lock().resolve(path, cred)?;
+    if file.kind() == InodeKind::Dir {
+        return Err(KError::IsADirectory);
+    }
+    let meta = file.metadata();
+    let allowed = if config.read_only {
+        perm::can_read(&meta, cred)
+    } else {
+        perm::can_read_write(&meta, cred)
+    };
+    if !allowed {
+        return Err(KError::PermissionDenied);
+    }
+    attach(file, path, config)
+}
+
+/// Detach loop device `index`. Fails with `Busy` while the device is still
+/// referenced, e.g. by a mounted filesystem.
+pub fn detach(index: usize) -> KResult<()> {
+    let mut loops = LOOPS.lock();
+    let slot = loops.get_mut(index).ok_or(KError::NotFound)?;
+    match slot {
+        Some(device) if Arc::strong_count(device) > 1 => Err(KError::Busy),
+        Some(_) => {
+            *slot = None;
+            Ok(())
+        }
+        None => Err(KError::NotFound),
+    }
+}
+
+/// Loop device `index`, if attached
+pub fn get(index: usize) -> Option<Arc<LoopDevice>> {
+    LOOPS.lock().get(index).cloned().flatten()
+}
+
+/// Loop device with device ID `id`
+pub fn get_by_id(id: u32) -> Option<Arc<LoopDevice>> {
+    get(id.checked_sub(LOOP_ID_BASE)? as usize)
+}
+
+/// Loop device named like "loop3"
+pub fn get_by_name(name: &str) -> Option<Arc<LoopDevice>> {
+    get(name.strip_prefix("loop")?.parse().ok()?)
+}
+
+/// Attached loop devices
+pub fn devices() -> Vec<Arc<LoopDevice>> {
+    LOOPS.lock().iter().flatten().cloned().collect()
+}
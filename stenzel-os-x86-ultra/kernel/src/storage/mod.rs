@@ -11,7 +11,9 @@
 
 pub mod block;
 pub mod cache;
+pub mod crypt;
 pub mod gpt;
+pub mod loopdev;
 pub mod mbr;
 pub mod raid;
 pub mod ramdisk;
@@ -160,6 +162,12 @@ pub fn init() {
                 let part_arc: Arc<dyn BlockDevice> = Arc::new(partition);
                 ROOT_PARTITION.call_once(|| Arc::clone(&part_arc));
 
+                // Root cifrado: só monta depois do desbloqueio (senha ou TPM)
+                if crypt::is_luks(&*part_arc) {
+                    crate::kprintln!("storage: partição 0 é LUKS, aguardando desbloqueio");
+                    return;
+                }
+
                 // Tenta montar ext2
                 crate::kprintln!("ext2: tentando montar partição 0...");
                 match fs::mount_root_ext2(part_arc) {
@@ -187,7 +195,9 @@ pub fn init() {
                 ROOT_PARTITION.call_once(|| Arc::clone(&part_arc));
 
                 // Try to mount based on partition type
-                if part0.partition_type.is_linux() {
+                if crypt::is_luks(&*part_arc) {
+                    crate::kprintln!("storage: partição LUKS, aguardando desbloqueio");
+                } else if part0.partition_type.is_linux() {
                     crate::kprintln!("ext2: tentando montar partição Linux...");
                     match fs::mount_root_ext2(Arc::clone(&part_arc)) {
                         Ok(()) => {
@@ -206,7 +216,7 @@ pub fn init() {
                             let root_cred = crate::security::user_db().login("root").expect("root user");
                             let mut vfs = fs::vfs_lock();
                             let _ = vfs.mkdir_all("/mnt", &root_cred, fs::Mode::from_octal(0o755));
-                            vfs.mount("/mnt", fat.root());
+                            vfs.mount("/mnt", fat.root(), "", "vfat", fs::MountFlags::empty());
                         }
                         Err(e) => {
                             crate::kprintln!("fat32: falha ao montar: {:?}", e);
@@ -239,6 +249,16 @@ pub fn find_device_by_path(path: &str) -> Option<usize> {
 
     let name = &path[5..]; // Remove "/dev/"
 
+    // Dispositivos loop (/dev/loop0, /dev/loop1, ...)
+    if let Some(dev) = loopdev::get_by_name(name) {
+        return Some(dev.id().0 as usize);
+    }
+
+    // Volumes dm-crypt abertos
+    if let Some(mapped) = name.strip_prefix("mapper/") {
+        return crypt::get(mapped).map(|dev| dev.id().0 as usize);
+    }
+
     // Check if it's the root block device
     if name == "sda" || name == "vda" || name == "nvme0n1" {
         if ROOT_BLOCK.get().is_some() {
@@ -261,6 +281,12 @@ pub fn get_device(device_id: usize) -> Option<Arc<dyn BlockDevice>> {
     match device_id {
         0 => ROOT_BLOCK.get().cloned(),
         100 => ROOT_PARTITION.get().cloned(),
+        id if (id as u32).wrapping_sub(loopdev::LOOP_ID_BASE) < loopdev::MAX_LOOP_DEVICES as u32 => {
+            loopdev::get_by_id(id as u32).map(|dev| dev as Arc<dyn BlockDevice>)
+        }
+        id if id >= crypt::MAPPER_ID_BASE as usize => {
+            crypt::get_by_id(id as u32).map(|dev| dev as Arc<dyn BlockDevice>)
+        }
         _ => None,
     }
 }
@@ -69,4 +69,12 @@ impl BlockDevice for RamDisk {
         g[s..e].copy_from_slice(data);
         Ok(())
     }
+
+    /// Blocos descartados passam a ler como zero.
+    fn discard(&self, lba: u64, count: u32) -> KResult<()> {
+        let (s, e) = self.range(lba, count)?;
+        let mut g = self.data.lock();
+        g[s..e].fill(0);
+        Ok(())
+    }
 }
@@ -0,0 +1,580 @@
+//! epoll - I/O event notification over an interest list
+//!
+//! An epoll instance keeps the fds registered with epoll_ctl and a ready
+//! list. Pipes, eventfds, ttys, sockets and inotify instances call [`wake`]
+//! (or [`wake_sockets`]) when their state changes, which puts the items
+//! watching them on the ready list of every instance; epoll_wait only looks
+//! at those. Sources that have no wake hook (the console, timerfds,
+//! signalfds and nested epoll instances) are sampled on every pass instead.
+//!
+//! Level-triggered items go back on the ready list after being reported and
+//! drop off once they stop being ready. Edge-triggered items are reported
+//! once per wake, and EPOLLONESHOT items are disabled until EPOLL_CTL_MOD.
+
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
+
+use crate::profiling::ebpf::BpfObject;
+use crate::sync::IrqSafeMutex;
+
+use super::{errno, fd_table, FdType};
+
+/// epoll_create1 flags
+pub const EPOLL_CLOEXEC: i32 = 0o2000000;
+
+/// epoll_ctl operations
+pub const EPOLL_CTL_ADD: i32 = 1;
+pub const EPOLL_CTL_DEL: i32 = 2;
+pub const EPOLL_CTL_MOD: i32 = 3;
+
+/// Event bits (same values as the POLL* flags)
+pub const EPOLLIN: u32 = 0x001;
+pub const EPOLLPRI: u32 = 0x002;
+pub const EPOLLOUT: u32 = 0x004;
+pub const EPOLLERR: u32 = 0x008;
+pub const EPOLLHUP: u32 = 0x010;
+pub const EPOLLRDNORM: u32 = 0x040;
+pub const EPOLLRDBAND: u32 = 0x080;
+pub const EPOLLWRNORM: u32 = 0x100;
+pub const EPOLLWRBAND: u32 = 0x200;
+pub const EPOLLMSG: u32 = 0x400;
+pub const EPOLLRDHUP: u32 = 0x2000;
+
+/// Input flags
+pub const EPOLLEXCLUSIVE: u32 = 1 << 28;
+pub const EPOLLWAKEUP: u32 = 1 << 29;
+pub const EPOLLONESHOT: u32 = 1 << 30;
+pub const EPOLLET: u32 = 1 << 31;
+
+/// Bits that select behaviour rather than events
+const PRIVATE_BITS: u32 = EPOLLEXCLUSIVE | EPOLLWAKEUP | EPOLLONESHOT | EPOLLET;
+
+/// Reported whether asked for or not
+const ALWAYS: u32 = EPOLLERR | EPOLLHUP;
+
+/// Largest maxevents accepted by epoll_wait
+pub const MAX_EVENTS: usize = i32::MAX as usize / core::mem::size_of::<EpollEvent>();
+
+/// struct epoll_event; packed on x86_64
+#[repr(C, packed)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct EpollEvent {
+    pub events: u32,
+    pub data: u64,
+}
+
+/// A registered fd
+struct Item {
+    file: FdType,
+    /// Wake key of the source, 0 when it has to be sampled
+    key: usize,
+    events: u32,
+    data: u64,
+    /// On the ready list
+    queued: bool,
+    /// Readiness at the last sample, so edge-triggered sampled items only
+    /// report transitions
+    was_ready: bool,
+}
+
+struct EpollState {
+    items: BTreeMap<i32, Item>,
+    ready: VecDeque<i32>,
+}
+
+/// An epoll instance
+pub struct Epoll {
+    state: IrqSafeMutex<EpollState>,
+}
+
+/// Instances watching each wake key
+static WATCHERS: IrqSafeMutex<BTreeMap<usize, Vec<Weak<Epoll>>>> = IrqSafeMutex::new(BTreeMap::new());
+
+/// Shared key for sockets; the network stack does not know which socket a
+/// packet made ready, so all socket items are rechecked
+static SOCKETS_KEY: u8 = 0;
+
+/// Queues the items watching `source`. Must not be called with locks held
+/// that readiness checks take (tty, socket or pipe state).
+pub fn wake<T>(source: &T) {
+    wake_key(source as *const T as usize);
+}
+
+/// A packet arrived or a socket changed state.
+pub fn wake_sockets() {
+    wake_key(&SOCKETS_KEY as *const u8 as usize);
+}
+
+fn wake_key(key: usize) {
+    let watchers: Vec<Arc<Epoll>> = {
+        let mut all = WATCHERS.lock();
+        let Some(list) = all.get_mut(&key) else {
+            return;
+        };
+        let live: Vec<Arc<Epoll>> = list.iter().filter_map(Weak::upgrade).collect();
+        if live.len() != list.len() {
+            list.retain(|w| w.strong_count() > 0);
+            if list.is_empty() {
+                all.remove(&key);
+            }
+        }
+        live
+    };
+    for epoll in watchers {
+        epoll.queue_key(key);
+    }
+}
+
+/// Wake key of the object behind `file`, 0 for sources that are sampled.
+fn key_of(file: &FdType) -> usize {
+    match file {
+        FdType::PipeRead { pipe } | FdType::PipeWrite { pipe } => Arc::as_ptr(pipe) as usize,
+        FdType::EventFd { eventfd } => Arc::as_ptr(&eventfd.inner) as usize,
+        FdType::PtyMaster { pty, .. } | FdType::PtySlave { pty, .. } => Arc::as_ptr(pty.tty()) as usize,
+        FdType::Socket { .. } => &SOCKETS_KEY as *const u8 as usize,
+        FdType::Inotify { inotify } => Arc::as_ptr(inotify) as usize,
+        _ => 0,
+    }
+}
+
+/// Identity of the open file behind an fd, to notice when the fd is closed
+/// or reused for something else.
+fn identity(file: &FdType) -> (u8, usize) {
+    match file {
+        FdType::Console => (0, 0),
+        FdType::File { .. } | FdType::Dir { .. } => (1, 0),
+        FdType::PipeRead { pipe } => (2, Arc::as_ptr(pipe) as usize),
+        FdType::PipeWrite { pipe } => (3, Arc::as_ptr(pipe) as usize),
+        FdType::Socket { socket_id } => (4, *socket_id as usize),
+        FdType::EventFd { eventfd } => (5, Arc::as_ptr(&eventfd.inner) as usize),
+        FdType::PtyMaster { pty, .. } => (6, Arc::as_ptr(pty) as usize),
+        FdType::PtySlave { pty, .. } => (7, Arc::as_ptr(pty) as usize),
+        FdType::Epoll { epoll } => (8, Arc::as_ptr(epoll) as usize),
+        FdType::TimerFd { timerfd } => (9, Arc::as_ptr(&timerfd.inner) as usize),
+        FdType::SignalFd { signalfd } => (10, Arc::as_ptr(signalfd) as usize),
+        FdType::Inotify { inotify } => (11, Arc::as_ptr(inotify) as usize),
+        FdType::Bpf { object } => match object {
+            BpfObject::Map(map) => (12, Arc::as_ptr(map) as usize),
+            BpfObject::Prog(prog) => (13, Arc::as_ptr(prog) as usize),
+            BpfObject::Link(link) => (14, Arc::as_ptr(link) as usize),
+        },
+    }
+}
+
+impl Epoll {
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self {
+            state: IrqSafeMutex::new(EpollState {
+                items: BTreeMap::new(),
+                ready: VecDeque::new(),
+            }),
+        })
+    }
+
+    /// Whether epoll_wait would find something right away (for poll, or an
+    /// outer epoll instance)
+    pub fn has_ready(&self) -> bool {
+        !self.state.lock().ready.is_empty()
+    }
+
+    fn has_nested(&self) -> bool {
+        self.state.lock().items.values().any(|i| matches!(i.file, FdType::Epoll { .. }))
+    }
+
+    fn queue_key(&self, key: usize) {
+        let mut guard = self.state.lock();
+        let state = &mut *guard;
+        for (&fd, item) in state.items.iter_mut() {
+            if item.key == key && !item.queued && item.events & !PRIVATE_BITS != 0 {
+                item.queued = true;
+                state.ready.push_back(fd);
+            }
+        }
+    }
+
+    fn register(self: &Arc<Self>, key: usize) {
+        let mut all = WATCHERS.lock();
+        let list = all.entry(key).or_default();
+        if !list.iter().any(|w| core::ptr::eq(w.as_ptr(), Arc::as_ptr(self))) {
+            list.push(Arc::downgrade(self));
+        }
+    }
+
+    fn unregister(&self, key: usize) {
+        let mut all = WATCHERS.lock();
+        if let Some(list) = all.get_mut(&key) {
+            list.retain(|w| w.strong_count() > 0 && !core::ptr::eq(w.as_ptr(), self));
+            if list.is_empty() {
+                all.remove(&key);
+            }
+        }
+    }
+
+    /// Drops the key's registration once no item uses it any more.
+    fn release_key(&self, key: usize) {
+        if key != 0 && !self.state.lock().items.values().any(|i| i.key == key) {
+            self.unregister(key);
+        }
+    }
+
+    /// epoll_ctl on this instance. `file` is what `fd` refers to now.
+    pub fn ctl(self: &Arc<Self>, op: i32, fd: i32, file: FdType, event: EpollEvent) -> Result<(), i64> {
+        match &file {
+            // Regular files and directories are always ready; Linux refuses them
+            FdType::File { .. } | FdType::Dir { .. } => return Err(errno::EPERM),
+            FdType::Epoll { epoll } => {
+                if Arc::ptr_eq(epoll, self) {
+                    return Err(errno::EINVAL);
+                }
+                // One level of nesting, which also rules out cycles
+                if epoll.has_nested() {
+                    return Err(errno::ELOOP);
+                }
+            }
+            _ => {}
+        }
+
+        let events = event.events;
+        let key = key_of(&file);
+        let mut stale_key = None;
+        {
+            let mut guard = self.state.lock();
+            let state = &mut *guard;
+            let existing = state.items.get(&fd).map(|i| (identity(&i.file) == identity(&file), i.key));
+
+            match op {
+                EPOLL_CTL_ADD => {
+                    match existing {
+                        Some((true, _)) => return Err(errno::EEXIST),
+                        // The fd was closed and reused; the old registration is gone
+                        Some((false, old_key)) => {
+                            state.items.remove(&fd);
+                            stale_key = Some(old_key);
+                        }
+                        None => {}
+                    }
+                    state.items.insert(fd, Item {
+                        file: file.clone(),
+                        key,
+                        events,
+                        data: event.data,
+                        queued: false,
+                        was_ready: false,
+                    });
+                }
+                EPOLL_CTL_MOD => {
+                    if events & EPOLLEXCLUSIVE != 0 {
+                        return Err(errno::EINVAL);
+                    }
+                    let item = match (existing, state.items.get_mut(&fd)) {
+                        (Some((true, _)), Some(item)) => item,
+                        _ => return Err(errno::ENOENT),
+                    };
+                    if item.events & EPOLLEXCLUSIVE != 0 {
+                        return Err(errno::EINVAL);
+                    }
+                    item.events = events;
+                    item.data = event.data;
+                    item.was_ready = false;
+                }
+                EPOLL_CTL_DEL => {
+                    if !matches!(existing, Some((true, _))) {
+                        return Err(errno::ENOENT);
+                    }
+                    state.items.remove(&fd);
+                    state.ready.retain(|&r| r != fd);
+                    stale_key = Some(key);
+                }
+                _ => return Err(errno::EINVAL),
+            }
+        }
+
+        if let Some(old) = stale_key {
+            self.release_key(old);
+        }
+        if op != EPOLL_CTL_DEL {
+            if key != 0 {
+                self.register(key);
+            }
+            // Events that are already pending count as the first edge
+            if file.poll_mask() & (events | ALWAYS) != 0 {
+                self.queue_fd(fd);
+            }
+        }
+        Ok(())
+    }
+
+    fn queue_fd(&self, fd: i32) {
+        let mut guard = self.state.lock();
+        let state = &mut *guard;
+        if let Some(item) = state.items.get_mut(&fd) {
+            if !item.queued {
+                item.queued = true;
+                state.ready.push_back(fd);
+            }
+        }
+    }
+
+    /// One pass over the ready list and the sampled items.
+    fn collect(&self, max: usize) -> Vec<EpollEvent> {
+        // Candidates are taken out under the lock and checked without it,
+        // since readiness checks take the sources' own locks
+        let candidates: Vec<(i32, FdType, bool)> = {
+            let mut guard = self.state.lock();
+            let state = &mut *guard;
+            let mut list = Vec::new();
+            while let Some(fd) = state.ready.pop_front() {
+                if let Some(item) = state.items.get_mut(&fd) {
+                    item.queued = false;
+                    list.push((fd, item.file.clone(), false));
+                }
+            }
+            for (&fd, item) in state.items.iter() {
+                if item.key == 0 && item.events & !PRIVATE_BITS != 0 {
+                    list.push((fd, item.file.clone(), true));
+                }
+            }
+            list
+        };
+        if candidates.is_empty() {
+            return Vec::new();
+        }
+
+        // Items whose fd was closed are dropped, as Linux does once the
+        // file goes away
+        let checked: Vec<(i32, Option<u32>, bool)> = {
+            let table = fd_table();
+            candidates
+                .into_iter()
+                .map(|(fd, file, sampled)| {
+                    let open = table
+                        .as_ref()
+                        .and_then(|t| t.get(fd))
+                        .map_or(false, |e| identity(&e.fd_type) == identity(&file));
+                    (fd, open.then(|| file.poll_mask()), sampled)
+                })
+                .collect()
+        };
+
+        let mut out = Vec::new();
+        let mut stale = Vec::new();
+        {
+            let mut guard = self.state.lock();
+            let state = &mut *guard;
+            for (fd, mask, sampled) in checked {
+                let Some(item) = state.items.get_mut(&fd) else {
+                    continue;
+                };
+                let Some(mask) = mask else {
+                    stale.push(item.key);
+                    state.items.remove(&fd);
+                    state.ready.retain(|&r| r != fd);
+                    continue;
+                };
+                let revents = mask & (item.events | ALWAYS);
+                let wanted = item.events & !PRIVATE_BITS != 0;
+                let edge = item.events & EPOLLET != 0;
+                let was_ready = core::mem::replace(&mut item.was_ready, revents != 0);
+
+                if !wanted || revents == 0 {
+                    continue;
+                }
+                if out.len() == max {
+                    // Did not fit in this batch: keep it for the next call
+                    if sampled {
+                        item.was_ready = was_ready;
+                    } else if !item.queued {
+                        item.queued = true;
+                        state.ready.push_back(fd);
+                    }
+                    continue;
+                }
+                if sampled && edge && was_ready {
+                    continue;
+                }
+
+                out.push(EpollEvent { events: revents, data: item.data });
+                if item.events & EPOLLONESHOT != 0 {
+                    item.events &= PRIVATE_BITS;
+                } else if !edge && !sampled && !item.queued {
+                    item.queued = true;
+                    state.ready.push_back(fd);
+                }
+            }
+        }
+        for key in stale {
+            self.release_key(key);
+        }
+        out
+    }
+
+    /// Waits up to `timeout_ms` (negative: forever) for events.
+    pub fn wait(&self, max: usize, timeout_ms: i64) -> Result<Vec<EpollEvent>, i64> {
+        let start = crate::time::uptime_ms();
+        loop {
+            let events = self.collect(max);
+            if !events.is_empty() {
+                return Ok(events);
+            }
+            if timeout_ms == 0
+                || (timeout_ms > 0 && crate::time::uptime_ms().saturating_sub(start) >= timeout_ms as u64)
+            {
+                return Ok(events);
+            }
+            if crate::sched::has_pending_signals() {
+                return Err(errno::EINTR);
+            }
+            crate::task::yield_now();
+        }
+    }
+}
+
+/// Instance behind an epoll fd
+fn epoll_of(epfd: i32) -> Result<Arc<Epoll>, i64> {
+    let table = fd_table();
+    match table.as_ref().and_then(|t| t.get(epfd)).map(|e| &e.fd_type) {
+        Some(FdType::Epoll { epoll }) => Ok(Arc::clone(epoll)),
+        Some(_) => Err(errno::EINVAL),
+        None => Err(errno::EBADF),
+    }
+}
+
+/// epoll_create1 - create an epoll instance
+pub fn sys_epoll_create1(flags: i32) -> i64 {
+    if flags & !EPOLL_CLOEXEC != 0 {
+        return errno::EINVAL;
+    }
+    let mut table = fd_table();
+    let Some(table) = table.as_mut() else {
+        return errno::EBADF;
+    };
+    let fd = table.alloc();
+    table.insert(fd, super::FdEntry {
+        fd_type: FdType::Epoll { epoll: Epoll::new() },
+    });
+    fd as i64
+}
+
+/// epoll_create - older form; `size` only has to be positive
+pub fn sys_epoll_create(size: i32) -> i64 {
+    if size <= 0 {
+        return errno::EINVAL;
+    }
+    sys_epoll_create1(0)
+}
+
+/// epoll_ctl - add, modify or remove an fd in the interest list
+pub fn sys_epoll_ctl(epfd: i32, op: i32, fd: i32, event: u64) -> i64 {
+    let event = if op == EPOLL_CTL_DEL {
+        EpollEvent::default()
+    } else {
+        if event == 0 || !super::is_user_range(event, core::mem::size_of::<EpollEvent>()) {
+            return errno::EFAULT;
+        }
+        unsafe { core::ptr::read_unaligned(event as *const EpollEvent) }
+    };
+
+    let epoll = match epoll_of(epfd) {
+        Ok(e) => e,
+        Err(e) => return e,
+    };
+    let file = {
+        let table = fd_table();
+        match table.as_ref().and_then(|t| t.get(fd)) {
+            Some(entry) => entry.fd_type.clone(),
+            None => return errno::EBADF,
+        }
+    };
+
+    match epoll.ctl(op, fd, file, event) {
+        Ok(()) => 0,
+        Err(e) => e,
+    }
+}
+
+/// Shared by the epoll_wait family: timeout in ms (negative: forever)
+/// and an optional signal mask for the duration of the wait
+fn epoll_wait_common(epfd: i32, events: u64, maxevents: i32, timeout_ms: i64, sigmask: Option<u64>) -> i64 {
+    if maxevents <= 0 || maxevents as usize > MAX_EVENTS {
+        return errno::EINVAL;
+    }
+    let max = maxevents as usize;
+    if !super::is_user_range(events, max * core::mem::size_of::<EpollEvent>()) {
+        return errno::EFAULT;
+    }
+    let epoll = match epoll_of(epfd) {
+        Ok(e) => e,
+        Err(e) => return e,
+    };
+
+    // Same as ppoll: the mask only applies while waiting
+    let old_mask = sigmask.map(|mask| {
+        let old = crate::sched::get_signal_mask();
+        crate::sched::set_signal_mask(mask);
+        old
+    });
+
+    let result = match epoll.wait(max, timeout_ms) {
+        Ok(ready) => {
+            let out = events as *mut EpollEvent;
+            for (i, ev) in ready.iter().enumerate() {
+                unsafe { core::ptr::write_unaligned(out.add(i), *ev) };
+            }
+            ready.len() as i64
+        }
+        Err(e) => e,
+    };
+
+    if let Some(old) = old_mask {
+        crate::sched::set_signal_mask(old);
+    }
+    result
+}
+
+fn read_sigmask(sigmask: u64, sigsetsize: usize) -> Result<Option<u64>, i64> {
+    if sigmask == 0 {
+        return Ok(None);
+    }
+    if sigsetsize != 8 {
+        return Err(errno::EINVAL);
+    }
+    if !super::is_user_range(sigmask, 8) {
+        return Err(errno::EFAULT);
+    }
+    Ok(Some(unsafe { core::ptr::read_unaligned(sigmask as *const u64) }))
+}
+
+/// epoll_wait - wait for events; timeout in ms (-1: forever)
+pub fn sys_epoll_wait(epfd: i32, events: u64, maxevents: i32, timeout: i32) -> i64 {
+    epoll_wait_common(epfd, events, maxevents, timeout as i64, None)
+}
+
+/// epoll_pwait - epoll_wait with a signal mask while waiting
+pub fn sys_epoll_pwait(epfd: i32, events: u64, maxevents: i32, timeout: i32, sigmask: u64, sigsetsize: usize) -> i64 {
+    match read_sigmask(sigmask, sigsetsize) {
+        Ok(mask) => epoll_wait_common(epfd, events, maxevents, timeout as i64, mask),
+        Err(e) => e,
+    }
+}
+
+/// epoll_pwait2 - epoll_pwait with a timespec timeout (NULL: forever)
+pub fn sys_epoll_pwait2(epfd: i32, events: u64, maxevents: i32, timeout: u64, sigmask: u64, sigsetsize: usize) -> i64 {
+    let timeout_ms = if timeout == 0 {
+        -1
+    } else {
+        if !super::is_user_range(timeout, core::mem::size_of::<crate::time::Timespec>()) {
+            return errno::EFAULT;
+        }
+        let ts = unsafe { core::ptr::read_unaligned(timeout as *const crate::time::Timespec) };
+        if ts.tv_sec < 0 || ts.tv_nsec < 0 || ts.tv_nsec >= 1_000_000_000 {
+            return errno::EINVAL;
+        }
+        // Rounded up so a short timeout does not turn into a poll
+        ts.tv_sec.saturating_mul(1000).saturating_add((ts.tv_nsec + 999_999) / 1_000_000)
+    };
+    match read_sigmask(sigmask, sigsetsize) {
+        Ok(mask) => epoll_wait_common(epfd, events, maxevents, timeout_ms, mask),
+        Err(e) => e,
+    }
+}
@@ -8,17 +8,23 @@
 
 extern crate alloc;
 
+pub mod epoll;
+
 use alloc::collections::BTreeMap;
 use alloc::string::String;
 use alloc::sync::Arc;
 use alloc::vec;
 use alloc::vec::Vec;
 use core::cell::UnsafeCell;
-use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use spin::Once;
 
+use crate::drivers::tty::pty::Pty;
+use crate::drivers::tty::Tty;
 use crate::fs::{self, Inode, InodeKind, Mode};
 use crate::security::Cred;
-use crate::sync::IrqSafeMutex;
+use crate::sync::{IrqSafeGuard, IrqSafeMutex};
 use crate::util::KError;
 
 // ======================== User address validation ========================
@@ -182,10 +188,12 @@ impl Pipe {
 
     pub fn remove_reader(&self) {
         self.readers.fetch_sub(1, Ordering::AcqRel);
+        epoll::wake(self);
     }
 
     pub fn remove_writer(&self) {
         self.writers.fetch_sub(1, Ordering::AcqRel);
+        epoll::wake(self);
     }
 
     pub fn has_readers(&self) -> bool {
@@ -198,7 +206,11 @@ impl Pipe {
 
     /// Lê do pipe. Retorna 0 se não há writers e buffer vazio (EOF).
     pub fn read(&self, out: &mut [u8]) -> usize {
-        self.buffer.read(out, out.len())
+        let n = self.buffer.read(out, out.len());
+        if n > 0 {
+            epoll::wake(self);
+        }
+        n
     }
 
     /// Escreve no pipe. Retorna 0 se não há readers (EPIPE).
@@ -206,7 +218,11 @@ impl Pipe {
         if !self.has_readers() {
             return 0;
         }
-        self.buffer.write(input, input.len())
+        let n = self.buffer.write(input, input.len());
+        if n > 0 {
+            epoll::wake(self);
+        }
+        n
     }
 
     pub fn is_empty(&self) -> bool {
@@ -274,7 +290,11 @@ pub mod nr {
     pub const GETRESGID: u64 = 120;
     pub const MPROTECT: u64 = 10;
     pub const MUNMAP: u64 = 11;
+    pub const MSYNC: u64 = 26;
+    pub const PRCTL: u64 = 157;
     pub const ARCH_PRCTL: u64 = 158;
+    pub const SECCOMP: u64 = 317;
+    pub const BPF: u64 = 321;
     pub const OPENAT: u64 = 257;
     // Socket syscalls
     pub const SOCKET: u64 = 41;
@@ -294,6 +314,15 @@ pub mod nr {
     pub const GETTIMEOFDAY: u64 = 96;
     pub const CLOCK_GETTIME: u64 = 228;
     pub const CLOCK_GETRES: u64 = 229;
+    pub const CLOCK_NANOSLEEP: u64 = 230;
+    pub const GETITIMER: u64 = 36;
+    pub const ALARM: u64 = 37;
+    pub const SETITIMER: u64 = 38;
+    pub const TIMER_CREATE: u64 = 222;
+    pub const TIMER_SETTIME: u64 = 223;
+    pub const TIMER_GETTIME: u64 = 224;
+    pub const TIMER_GETOVERRUN: u64 = 225;
+    pub const TIMER_DELETE: u64 = 226;
     // I/O multiplexing syscalls
     pub const POLL: u64 = 7;
     pub const SELECT: u64 = 23;
@@ -373,8 +402,43 @@ pub mod nr {
     pub const PRLIMIT64: u64 = 302;
     // Time
     pub const SETTIMEOFDAY: u64 = 164;
+    pub const ADJTIMEX: u64 = 159;
+    pub const CLOCK_SETTIME: u64 = 227;
+    pub const CLOCK_ADJTIME: u64 = 305;
     // Reboot/shutdown
     pub const REBOOT: u64 = 169;
+    // Mount
+    pub const STATFS: u64 = 137;
+    pub const FSTATFS: u64 = 138;
+    pub const MOUNT: u64 = 165;
+    pub const UMOUNT2: u64 = 166;
+    // Namespaces
+    pub const PIVOT_ROOT: u64 = 155;
+    pub const CHROOT: u64 = 161;
+    pub const UNSHARE: u64 = 272;
+    pub const SETNS: u64 = 308;
+    // Random
+    pub const GETRANDOM: u64 = 318;
+    // CPU
+    pub const GETCPU: u64 = 309;
+    // epoll
+    pub const EPOLL_CREATE: u64 = 213;
+    pub const EPOLL_WAIT: u64 = 232;
+    pub const EPOLL_CTL: u64 = 233;
+    pub const EPOLL_PWAIT: u64 = 281;
+    pub const EPOLL_CREATE1: u64 = 291;
+    pub const EPOLL_PWAIT2: u64 = 441;
+    // timerfd / signalfd
+    pub const SIGNALFD: u64 = 282;
+    pub const TIMERFD_CREATE: u64 = 283;
+    pub const TIMERFD_SETTIME: u64 = 286;
+    pub const TIMERFD_GETTIME: u64 = 287;
+    pub const SIGNALFD4: u64 = 289;
+    // inotify
+    pub const INOTIFY_INIT: u64 = 253;
+    pub const INOTIFY_ADD_WATCH: u64 = 254;
+    pub const INOTIFY_RM_WATCH: u64 = 255;
+    pub const INOTIFY_INIT1: u64 = 294;
 }
 
 /// Erros de syscall (negativo = errno).
@@ -392,12 +456,18 @@ pub mod errno {
     pub const ENOMEM: i64 = -12;
     pub const EACCES: i64 = -13;
     pub const EFAULT: i64 = -14;
+    pub const ENOTBLK: i64 = -15;
+    pub const EBUSY: i64 = -16;
+    pub const EXDEV: i64 = -18;
+    pub const ENODEV: i64 = -19;
     pub const ENOTDIR: i64 = -20;
     pub const EISDIR: i64 = -21;
     pub const EINVAL: i64 = -22;
     pub const EMFILE: i64 = -24;
     pub const ENOSPC: i64 = -28;
+    pub const ENOTTY: i64 = -25;
     pub const ESPIPE: i64 = -29;
+    pub const EROFS: i64 = -30;
     pub const EPIPE: i64 = -32;
     pub const ENOSYS: i64 = -38;
     pub const ENOTEMPTY: i64 = -39;
@@ -507,6 +577,7 @@ bitflags::bitflags! {
         const O_RDWR      = 2;
         const O_CREAT     = 0o100;
         const O_EXCL      = 0o200;
+        const O_NOCTTY    = 0o400;
         const O_TRUNC     = 0o1000;
         const O_APPEND    = 0o2000;
         const O_NONBLOCK  = 0o4000;
@@ -520,8 +591,8 @@ bitflags::bitflags! {
 pub enum FdType {
     /// Console (stdin/stdout/stderr).
     Console,
-    /// Arquivo no VFS.
-    File { inode: Inode, offset: usize, flags: OpenFlags },
+    /// Arquivo no VFS. `path` é o caminho do open(), para o inotify.
+    File { inode: Inode, path: String, offset: usize, flags: OpenFlags },
     /// Diretório aberto.
     Dir { inode: Inode, offset: usize },
     /// Lado de leitura de um pipe.
@@ -532,6 +603,107 @@ pub enum FdType {
     Socket { socket_id: u64 },
     /// eventfd for event notification.
     EventFd { eventfd: crate::ipc::EventFdFile },
+    /// Lado master de um pseudo-terminal (/dev/ptmx).
+    PtyMaster { pty: Arc<Pty>, flags: OpenFlags },
+    /// Lado slave de um pseudo-terminal (/dev/pts/N).
+    PtySlave { pty: Arc<Pty>, flags: OpenFlags },
+    /// Instância de epoll.
+    Epoll { epoll: Arc<epoll::Epoll> },
+    /// timerfd.
+    TimerFd { timerfd: crate::ipc::TimerFdFile },
+    /// signalfd; lê os signals pendentes da task que lê.
+    SignalFd { signalfd: Arc<crate::signal::SignalFd> },
+    /// Instância de inotify.
+    Inotify { inotify: Arc<fs::inotify::Inotify> },
+    /// Map, programa ou link de eBPF (bpf(2)).
+    Bpf { object: crate::profiling::ebpf::BpfObject },
+}
+
+impl FdType {
+    /// Conta mais uma referência ao recurso (dup, fork).
+    fn add_ref(&self) {
+        match self {
+            FdType::PipeRead { pipe } => pipe.add_reader(),
+            FdType::PipeWrite { pipe } => pipe.add_writer(),
+            FdType::PtyMaster { pty, .. } => pty.add_master(),
+            FdType::PtySlave { pty, .. } => pty.add_slave(),
+            _ => {}
+        }
+    }
+
+    /// Solta uma referência ao recurso (close, dup2 sobre um fd aberto).
+    fn release(&self) {
+        match self {
+            FdType::PipeRead { pipe } => pipe.remove_reader(),
+            FdType::PipeWrite { pipe } => pipe.remove_writer(),
+            FdType::PtyMaster { pty, .. } => pty.close_master(),
+            FdType::PtySlave { pty, .. } => pty.close_slave(),
+            FdType::File { path, flags, .. } => {
+                let mask = if flags.bits() & 0o3 == 0 {
+                    fs::inotify::IN_CLOSE_NOWRITE
+                } else {
+                    fs::inotify::IN_CLOSE_WRITE
+                };
+                fs::inotify::notify(path, mask);
+            }
+            _ => {}
+        }
+    }
+
+    /// Prontidão de um pty para poll/select: (legível, gravável, hangup)
+    fn pty_poll(&self) -> (bool, bool, bool) {
+        match self {
+            FdType::PtyMaster { pty, .. } => pty.poll_master(),
+            FdType::PtySlave { pty, .. } => pty.poll_slave(),
+            _ => (false, false, false),
+        }
+    }
+
+    /// Eventos prontos (bits POLL*/EPOLL*), para epoll
+    fn poll_mask(&self) -> u32 {
+        use epoll::{EPOLLERR, EPOLLHUP, EPOLLIN, EPOLLOUT};
+
+        let bit = |cond: bool, flag: u32| if cond { flag } else { 0 };
+        match self {
+            FdType::Console => EPOLLOUT | bit(crate::console::has_data(), EPOLLIN),
+            FdType::File { .. } | FdType::Dir { .. } => EPOLLIN | EPOLLOUT,
+            FdType::PipeRead { pipe } => {
+                bit(!pipe.is_empty(), EPOLLIN) | bit(!pipe.has_writers(), EPOLLHUP)
+            }
+            FdType::PipeWrite { pipe } => {
+                bit(!pipe.is_full(), EPOLLOUT) | bit(!pipe.has_readers(), EPOLLERR)
+            }
+            FdType::Socket { socket_id } => {
+                bit(crate::net::socket::poll_read(*socket_id), EPOLLIN)
+                    | bit(crate::net::socket::poll_write(*socket_id), EPOLLOUT)
+            }
+            FdType::EventFd { eventfd } => {
+                let (readable, writable) = eventfd.poll();
+                bit(readable, EPOLLIN) | bit(writable, EPOLLOUT)
+            }
+            FdType::PtyMaster { .. } | FdType::PtySlave { .. } => {
+                let (readable, writable, hangup) = self.pty_poll();
+                bit(readable, EPOLLIN) | bit(writable, EPOLLOUT) | bit(hangup, EPOLLHUP)
+            }
+            FdType::Epoll { epoll } => bit(epoll.has_ready(), EPOLLIN),
+            FdType::TimerFd { timerfd } => bit(timerfd.inner.is_readable(), EPOLLIN),
+            FdType::SignalFd { signalfd } => {
+                let task = crate::sched::current_task();
+                bit(signalfd.is_readable(task.signals()), EPOLLIN)
+            }
+            FdType::Inotify { inotify } => bit(inotify.has_events(), EPOLLIN),
+            FdType::Bpf { .. } => 0,
+        }
+    }
+
+    /// Terminal por trás do fd, para os ioctls de tty
+    fn tty(&self) -> Option<Arc<Tty>> {
+        match self {
+            FdType::Console => Some(crate::drivers::tty::console()),
+            FdType::PtyMaster { pty, .. } | FdType::PtySlave { pty, .. } => Some(Arc::clone(pty.tty())),
+            _ => None,
+        }
+    }
 }
 
 /// File descriptor entry.
@@ -548,14 +720,19 @@ pub struct FdTable {
 
 impl FdTable {
     pub fn new() -> Self {
+        // FDs padrão: 0=stdin, 1=stdout, 2=stderr (console).
+        Self::with_stdio(FdType::Console)
+    }
+
+    /// Tabela com `fd_type` em 0, 1 e 2. As referências já devem estar contadas.
+    pub fn with_stdio(fd_type: FdType) -> Self {
         let mut table = Self {
             fds: BTreeMap::new(),
             next_fd: 3,
         };
-        // FDs padrão: 0=stdin, 1=stdout, 2=stderr (console).
-        table.fds.insert(0, FdEntry { fd_type: FdType::Console });
-        table.fds.insert(1, FdEntry { fd_type: FdType::Console });
-        table.fds.insert(2, FdEntry { fd_type: FdType::Console });
+        table.fds.insert(0, FdEntry { fd_type: fd_type.clone() });
+        table.fds.insert(1, FdEntry { fd_type: fd_type.clone() });
+        table.fds.insert(2, FdEntry { fd_type });
         table
     }
 
@@ -582,11 +759,81 @@ impl FdTable {
     }
 }
 
-/// Tabela de FDs global (single-process por enquanto).
-static FD_TABLE: IrqSafeMutex<Option<FdTable>> = IrqSafeMutex::new(None);
+/// Tabela de FDs de uma task; compartilhada entre as tasks criadas com
+/// CLONE_FILES (e threads).
+pub type Files = Arc<IrqSafeMutex<Option<FdTable>>>;
+
+/// Tabela das tasks de boot e das kernel threads
+static INIT_FILES: Once<Files> = Once::new();
+
+pub fn init_files() -> Files {
+    Arc::clone(INIT_FILES.call_once(|| Arc::new(IrqSafeMutex::new(None))))
+}
+
+/// Nova tabela com `fd_type` em 0, 1 e 2 (ex.: o slave de um pty).
+pub fn stdio_files(fd_type: FdType) -> Files {
+    Arc::new(IrqSafeMutex::new(Some(FdTable::with_stdio(fd_type))))
+}
+
+/// Cópia da tabela para um filho de fork(); os FDs copiados contam como
+/// referências novas aos pipes e ptys.
+pub fn fork_files(files: &Files) -> Files {
+    let table = files.lock();
+    let copy = table.as_ref().map(|t| {
+        for entry in t.fds.values() {
+            entry.fd_type.add_ref();
+        }
+        FdTable { fds: t.fds.clone(), next_fd: t.next_fd }
+    });
+    Arc::new(IrqSafeMutex::new(copy))
+}
+
+/// Solta a referência de uma task que terminou. A última task usando a
+/// tabela fecha os FDs.
+pub fn release_files(files: Files) {
+    if let Ok(files) = Arc::try_unwrap(files) {
+        if let Some(table) = files.lock().take() {
+            for entry in table.fds.values() {
+                entry.fd_type.release();
+            }
+        }
+    }
+}
+
+/// Lock sobre a tabela de FDs da task atual.
+pub struct FdTableGuard {
+    // Declared first: dropped (unlocked) before the table it borrows
+    guard: IrqSafeGuard<'static, Option<FdTable>>,
+    _files: Files,
+}
+
+impl core::ops::Deref for FdTableGuard {
+    type Target = Option<FdTable>;
+
+    fn deref(&self) -> &Option<FdTable> {
+        &self.guard
+    }
+}
+
+impl core::ops::DerefMut for FdTableGuard {
+    fn deref_mut(&mut self) -> &mut Option<FdTable> {
+        &mut self.guard
+    }
+}
 
-fn fd_table() -> crate::sync::IrqSafeGuard<'static, Option<FdTable>> {
-    FD_TABLE.lock()
+fn fd_table() -> FdTableGuard {
+    let files = match crate::sched::try_current_task() {
+        Some(task) => task.files(),
+        None => init_files(),
+    };
+    // SAFETY: the guard borrows from the mutex inside `files`, which the
+    // FdTableGuard keeps alive and outlives the guard (field drop order).
+    let guard = unsafe {
+        core::mem::transmute::<IrqSafeGuard<'_, Option<FdTable>>, IrqSafeGuard<'static, Option<FdTable>>>(
+            files.lock(),
+        )
+    };
+    FdTableGuard { guard, _files: files }
 }
 
 pub fn init() {
@@ -608,6 +855,78 @@ pub fn alloc_fd_for_eventfd(eventfd: crate::ipc::EventFdFile) -> Option<i32> {
     Some(fd)
 }
 
+/// Allocate a file descriptor for a timerfd
+pub fn alloc_fd_for_timerfd(timerfd: crate::ipc::TimerFdFile) -> Option<i32> {
+    let mut table = fd_table();
+    let table = table.as_mut()?;
+
+    let fd = table.alloc();
+    table.insert(fd, FdEntry {
+        fd_type: FdType::TimerFd { timerfd },
+    });
+
+    Some(fd)
+}
+
+/// Allocate a file descriptor for an inotify instance
+pub fn alloc_fd_for_inotify(inotify: Arc<fs::inotify::Inotify>) -> Option<i32> {
+    let mut table = fd_table();
+    let table = table.as_mut()?;
+
+    let fd = table.alloc();
+    table.insert(fd, FdEntry {
+        fd_type: FdType::Inotify { inotify },
+    });
+
+    Some(fd)
+}
+
+/// Instância por trás de um fd de inotify (EBADF se fechado, EINVAL se for
+/// outra coisa).
+pub fn inotify_of(fd: i32) -> Result<Arc<fs::inotify::Inotify>, i64> {
+    let table = fd_table();
+    match table.as_ref().and_then(|t| t.get(fd)).map(|e| &e.fd_type) {
+        Some(FdType::Inotify { inotify }) => Ok(Arc::clone(inotify)),
+        Some(_) => Err(errno::EINVAL),
+        None => Err(errno::EBADF),
+    }
+}
+
+/// Allocate a file descriptor for an eBPF object
+pub fn alloc_fd_for_bpf(object: crate::profiling::ebpf::BpfObject) -> Option<i32> {
+    let mut table = fd_table();
+    let table = table.as_mut()?;
+
+    let fd = table.alloc();
+    table.insert(fd, FdEntry {
+        fd_type: FdType::Bpf { object },
+    });
+
+    Some(fd)
+}
+
+/// Objeto por trás de um fd de bpf (EBADF se fechado, EINVAL se for outra
+/// coisa).
+pub fn bpf_object_of(fd: i32) -> Result<crate::profiling::ebpf::BpfObject, i64> {
+    let table = fd_table();
+    match table.as_ref().and_then(|t| t.get(fd)).map(|e| &e.fd_type) {
+        Some(FdType::Bpf { object }) => Ok(object.clone()),
+        Some(_) => Err(errno::EINVAL),
+        None => Err(errno::EBADF),
+    }
+}
+
+/// Timer por trás de um fd de timerfd (EBADF se fechado, EINVAL se for
+/// outra coisa).
+pub fn timerfd_of(fd: i32) -> Result<Arc<crate::ipc::timerfd::TimerFd>, i64> {
+    let table = fd_table();
+    match table.as_ref().and_then(|t| t.get(fd)).map(|e| &e.fd_type) {
+        Some(FdType::TimerFd { timerfd }) => Ok(Arc::clone(&timerfd.inner)),
+        Some(_) => Err(errno::EINVAL),
+        None => Err(errno::EBADF),
+    }
+}
+
 /// Lê uma string C terminada em nulo de memória do usuário.
 ///
 /// Valida que o endereço está em user space antes de acessar.
@@ -652,16 +971,55 @@ pub fn sys_read(fd: i32, buf: u64, count: usize) -> i64 {
         return errno::EFAULT;
     }
 
-    // Primeiro verifica se é Console (precisa de tratamento especial para blocking).
+    // Primeiro verifica se é Console, pty, timerfd, signalfd ou inotify (precisam de
+    // tratamento especial para blocking).
     // Liberamos o lock antes de bloquear para evitar deadlocks.
-    let is_console = {
+    let (is_console, pty, waitable) = {
         let guard = fd_table();
         match guard.as_ref() {
-            Some(t) => matches!(t.get(fd).map(|e| &e.fd_type), Some(FdType::Console)),
+            Some(t) => match t.get(fd).map(|e| &e.fd_type) {
+                Some(FdType::Console) => (true, None, None),
+                Some(FdType::PtyMaster { pty, flags }) => {
+                    (false, Some((pty.clone(), true, flags.contains(OpenFlags::O_NONBLOCK))), None)
+                }
+                Some(FdType::PtySlave { pty, flags }) => {
+                    (false, Some((pty.clone(), false, flags.contains(OpenFlags::O_NONBLOCK))), None)
+                }
+                Some(f @ (FdType::TimerFd { .. } | FdType::SignalFd { .. } | FdType::Inotify { .. })) => {
+                    (false, None, Some(f.clone()))
+                }
+                _ => (false, None, None),
+            },
             None => return errno::EBADF,
         }
     }; // guard é dropado aqui automaticamente
 
+    if let Some((pty, master, nonblock)) = pty {
+        return pty_read(&pty, master, buf, count, nonblock);
+    }
+
+    match waitable {
+        Some(FdType::TimerFd { timerfd }) => {
+            if count < 8 {
+                return errno::EINVAL;
+            }
+            let out = unsafe { core::slice::from_raw_parts_mut(buf as *mut u8, 8) };
+            return match timerfd.read(out) {
+                Ok(n) => n as i64,
+                Err(e) => e,
+            };
+        }
+        Some(FdType::SignalFd { signalfd }) => return signalfd_read(&signalfd, buf, count),
+        Some(FdType::Inotify { inotify }) => {
+            let out = unsafe { core::slice::from_raw_parts_mut(buf as *mut u8, count) };
+            return match inotify.read(out) {
+                Ok(n) => n as i64,
+                Err(e) => e,
+            };
+        }
+        _ => {}
+    }
+
     if is_console {
         // Define este processo como foreground (para receber SIGINT)
         if fd == 0 {
@@ -708,27 +1066,36 @@ pub fn sys_read(fd: i32, buf: u64, count: usize) -> i64 {
     };
 
     match &mut entry.fd_type {
-        FdType::Console => {
+        FdType::Console
+        | FdType::PtyMaster { .. }
+        | FdType::PtySlave { .. }
+        | FdType::TimerFd { .. }
+        | FdType::SignalFd { .. }
+        | FdType::Inotify { .. } => {
             // Já tratado acima, não deveria chegar aqui.
             unreachable!()
         }
-        FdType::File { inode, offset, flags: _ } => {
+        FdType::File { inode, path, offset, flags: _ } => {
             if count == 0 {
                 return 0;
             }
             let mut buf_vec = vec![0u8; count];
             match inode.0.read_at(*offset, &mut buf_vec) {
                 Ok(n) => {
+                    // Escritas feitas via mmap compartilhado ainda no page cache
+                    fs::page_cache::read_overlay(inode, *offset, &mut buf_vec[..n]);
                     unsafe {
                         core::ptr::copy_nonoverlapping(buf_vec.as_ptr(), buf as *mut u8, n);
                     }
                     *offset += n;
+                    fs::inotify::notify(path, fs::inotify::IN_ACCESS);
                     n as i64
                 }
                 Err(_) => errno::EIO,
             }
         }
         FdType::Dir { .. } => errno::EISDIR,
+        FdType::Epoll { .. } | FdType::Bpf { .. } => errno::EINVAL,
         FdType::PipeRead { pipe } => {
             if count == 0 {
                 return 0;
@@ -789,6 +1156,23 @@ pub fn sys_write(fd: i32, buf: u64, count: usize) -> i64 {
         return errno::EFAULT;
     }
 
+    // Ptys podem mandar signals (SIGTTOU, ISIG no eco); sem o lock da tabela.
+    let pty = {
+        let guard = fd_table();
+        match guard.as_ref() {
+            Some(t) => match t.get(fd).map(|e| &e.fd_type) {
+                Some(FdType::PtyMaster { pty, .. }) => Some((pty.clone(), true)),
+                Some(FdType::PtySlave { pty, .. }) => Some((pty.clone(), false)),
+                _ => None,
+            },
+            None => return errno::EBADF,
+        }
+    };
+
+    if let Some((pty, master)) = pty {
+        return pty_write(&pty, master, buf, count);
+    }
+
     let mut table = fd_table();
     let table = match table.as_mut() {
         Some(t) => t,
@@ -813,7 +1197,7 @@ pub fn sys_write(fd: i32, buf: u64, count: usize) -> i64 {
             }
             max as i64
         }
-        FdType::File { inode, offset, flags } => {
+        FdType::File { inode, path, offset, flags } => {
             if count == 0 {
                 return 0;
             }
@@ -826,13 +1210,22 @@ pub fn sys_write(fd: i32, buf: u64, count: usize) -> i64 {
             let slice = unsafe { core::slice::from_raw_parts(buf as *const u8, count) };
             match inode.0.write_at(write_offset, slice) {
                 Ok(n) => {
+                    fs::page_cache::write_through(inode, write_offset, &slice[..n]);
                     *offset = write_offset + n;
+                    fs::inotify::notify(path, fs::inotify::IN_MODIFY);
                     n as i64
                 }
+                Err(KError::ReadOnly) => errno::EROFS,
+                Err(KError::NoSpace) => errno::ENOSPC,
                 Err(_) => errno::EIO,
             }
         }
         FdType::Dir { .. } => errno::EISDIR,
+        FdType::Epoll { .. }
+        | FdType::TimerFd { .. }
+        | FdType::SignalFd { .. }
+        | FdType::Inotify { .. }
+        | FdType::Bpf { .. } => errno::EINVAL,
         FdType::PipeWrite { pipe } => {
             if count == 0 {
                 return 0;
@@ -871,6 +1264,47 @@ pub fn sys_write(fd: i32, buf: u64, count: usize) -> i64 {
                 Err(e) => e as i64,
             }
         }
+        FdType::PtyMaster { .. } | FdType::PtySlave { .. } => unreachable!(),
+    }
+}
+
+/// read() de um pty. Chamado sem o lock da tabela de FDs: pode bloquear.
+fn pty_read(pty: &Pty, master: bool, buf: u64, count: usize, nonblock: bool) -> i64 {
+    if count == 0 {
+        return 0;
+    }
+    let mut buf_vec = vec![0u8; count];
+    let result = if master {
+        pty.master_read(&mut buf_vec, nonblock)
+    } else {
+        pty.tty()
+            .check_background(false)
+            .and_then(|()| pty.tty().read(&mut buf_vec, nonblock))
+    };
+    match result {
+        Ok(n) => {
+            unsafe {
+                core::ptr::copy_nonoverlapping(buf_vec.as_ptr(), buf as *mut u8, n);
+            }
+            n as i64
+        }
+        Err(e) => e,
+    }
+}
+
+/// write() de um pty: no master é entrada para o slave, no slave é saída
+/// para o master.
+fn pty_write(pty: &Pty, master: bool, buf: u64, count: usize) -> i64 {
+    if count == 0 {
+        return 0;
+    }
+    let slice = unsafe { core::slice::from_raw_parts(buf as *const u8, count) };
+    if master {
+        return pty.master_write(slice) as i64;
+    }
+    match pty.tty().check_background(true).and_then(|()| pty.tty().write(slice)) {
+        Ok(n) => n as i64,
+        Err(e) => e,
     }
 }
 
@@ -883,7 +1317,7 @@ pub fn sys_open(pathname: u64, flags: u32, mode: u32) -> i64 {
     let oflags = OpenFlags::from_bits_truncate(flags);
     let cred = current_cred();
 
-    let vfs = fs::vfs_lock();
+    let mut vfs = fs::vfs_lock();
 
     // Tenta resolver o path.
     let inode = match vfs.resolve(&path, &cred) {
@@ -891,24 +1325,12 @@ pub fn sys_open(pathname: u64, flags: u32, mode: u32) -> i64 {
         Err(KError::NotFound) => {
             // Se O_CREAT, tenta criar.
             if oflags.contains(OpenFlags::O_CREAT) {
-                // Encontra o parent e cria arquivo.
-                let (parent_path, name) = match path.rfind('/') {
-                    Some(idx) if idx > 0 => (&path[..idx], &path[idx + 1..]),
-                    Some(_) => ("/", &path[1..]),
-                    None => ("/", path.as_str()),
-                };
-                let parent = match vfs.resolve(parent_path, &cred) {
-                    Ok(p) => p,
-                    Err(_) => return errno::ENOENT,
-                };
-                let meta = fs::Metadata::simple(
-                    cred.uid,
-                    cred.gid,
-                    Mode::from_octal(mode as u16),
-                    InodeKind::File,
-                );
-                match parent.0.create(name, InodeKind::File, meta) {
+                match vfs.create(&path, Mode::from_octal(mode as u16), &cred) {
                     Ok(i) => i,
+                    Err(KError::NotFound) => return errno::ENOENT,
+                    Err(KError::NotADirectory) => return errno::ENOTDIR,
+                    Err(KError::PermissionDenied) => return errno::EACCES,
+                    Err(KError::ReadOnly) => return errno::EROFS,
                     Err(_) => return errno::EIO,
                 }
             } else {
@@ -928,6 +1350,20 @@ pub fn sys_open(pathname: u64, flags: u32, mode: u32) -> i64 {
     let meta = inode.metadata();
     let access_mode = oflags.bits() & 0o3; // O_RDONLY=0, O_WRONLY=1, O_RDWR=2
 
+    // Mount restrictions (MS_RDONLY, MS_NODEV)
+    let mnt_flags = inode.0.mount_flags();
+    if meta.kind == InodeKind::File
+        && (access_mode != 0 || oflags.contains(OpenFlags::O_TRUNC))
+        && mnt_flags.contains(fs::MountFlags::RDONLY)
+    {
+        return errno::EROFS;
+    }
+    if matches!(meta.kind, InodeKind::CharDev | InodeKind::BlockDev)
+        && mnt_flags.contains(fs::MountFlags::NODEV)
+    {
+        return errno::EACCES;
+    }
+
     // For directories, we check different permissions
     if meta.kind == InodeKind::Dir {
         // Directories require execute permission for traversal
@@ -956,51 +1392,120 @@ pub fn sys_open(pathname: u64, flags: u32, mode: u32) -> i64 {
         }
     }
 
+    // MAC policy, after the permission bits
+    let truncate = oflags.contains(OpenFlags::O_TRUNC);
+    if crate::security::hooks::file_open(&inode, &path, access_mode, truncate).is_err() {
+        return errno::EACCES;
+    }
+
     // Aloca fd.
     drop(vfs);
+
+    // Terminais viram fds próprios em vez de arquivos do devfs
+    let tty_fd = match fs::devfs::tty_node_of(&inode) {
+        Some(node) => match open_tty_node(node, oflags) {
+            Ok(fd_type) => fd_type,
+            Err(e) => return e,
+        },
+        None => None,
+    };
+
     let mut table = fd_table();
     let table = match table.as_mut() {
         Some(t) => t,
-        None => return errno::ENOMEM,
+        None => {
+            if let Some(fd_type) = tty_fd {
+                fd_type.release();
+            }
+            return errno::ENOMEM;
+        }
     };
 
     let fd = table.alloc();
-    let fd_type = if inode.kind() == InodeKind::Dir {
+    let fd_type = if let Some(fd_type) = tty_fd {
+        fd_type
+    } else if inode.kind() == InodeKind::Dir {
         FdType::Dir { inode, offset: 0 }
     } else {
+        let path = fs::vfs::normalize(&path);
+        fs::inotify::notify(&path, fs::inotify::IN_OPEN);
         // O_TRUNC: trunca arquivo.
-        if oflags.contains(OpenFlags::O_TRUNC) {
-            let _ = inode.0.truncate(0);
+        if oflags.contains(OpenFlags::O_TRUNC) && inode.0.truncate(0).is_ok() {
+            fs::page_cache::truncate_file_pages(&inode, 0);
+            fs::inotify::notify(&path, fs::inotify::IN_MODIFY);
         }
-        FdType::File { inode, offset: 0, flags: oflags }
+        FdType::File { inode, path, offset: 0, flags: oflags }
     };
     table.insert(fd, FdEntry { fd_type });
 
     fd as i64
 }
 
+/// open(2) de /dev/ptmx, /dev/pts/N ou /dev/tty.
+///
+/// /dev/tty sem um pty como terminal de controle continua sendo o arquivo
+/// do devfs (console), por isso `Ok(None)`.
+fn open_tty_node(node: fs::devfs::TtyNode, oflags: OpenFlags) -> Result<Option<FdType>, i64> {
+    use fs::devfs::TtyNode;
+
+    let pty = match node {
+        TtyNode::Ptmx => {
+            let pty = crate::drivers::tty::pty::open_master().map_err(|_| errno::ENOSPC)?;
+            return Ok(Some(FdType::PtyMaster { pty, flags: oflags }));
+        }
+        TtyNode::Pts(n) => crate::drivers::tty::pty::get(n).ok_or(errno::EIO)?,
+        TtyNode::Tty => {
+            let sid = crate::sched::current_task().sid();
+            let Some(ctty) = crate::drivers::tty::controlling_tty(sid) else {
+                return Ok(None);
+            };
+            match crate::drivers::tty::pty::find_by_tty(&ctty) {
+                Some(pty) => {
+                    pty.add_slave();
+                    return Ok(Some(FdType::PtySlave { pty, flags: oflags }));
+                }
+                None => return Ok(None),
+            }
+        }
+    };
+
+    pty.open_slave()?;
+
+    // Um líder de sessão sem terminal ganha o primeiro que abrir
+    let task = crate::sched::current_task();
+    if !oflags.contains(OpenFlags::O_NOCTTY)
+        && task.sid() == task.id()
+        && pty.tty().session() == 0
+        && crate::drivers::tty::controlling_tty(task.sid()).is_none()
+    {
+        let _ = crate::drivers::tty::set_controlling_tty(pty.tty(), task.sid(), task.pgid(), false);
+    }
+
+    Ok(Some(FdType::PtySlave { pty, flags: oflags }))
+}
+
 pub fn sys_close(fd: i32) -> i64 {
-    let mut table = fd_table();
-    let table = match table.as_mut() {
+    let mut guard = fd_table();
+    let table = match guard.as_mut() {
         Some(t) => t,
         None => return errno::EBADF,
     };
 
-    match table.remove(fd) {
-        Some(entry) => {
-            // Decrementa contadores de pipe se necessário
-            match entry.fd_type {
-                FdType::PipeRead { pipe } => pipe.remove_reader(),
-                FdType::PipeWrite { pipe } => pipe.remove_writer(),
-                FdType::Socket { socket_id } => {
-                    let _ = crate::net::socket::close(socket_id);
-                }
-                _ => {}
-            }
-            0
+    let entry = match table.remove(fd) {
+        Some(entry) => entry,
+        None => return errno::EBADF,
+    };
+    drop(guard);
+
+    // Decrementa contadores de pipe/pty se necessário. Fora do lock: o
+    // último master de um pty manda SIGHUP para o slave.
+    match entry.fd_type {
+        FdType::Socket { socket_id } => {
+            let _ = crate::net::socket::close(socket_id);
         }
-        None => errno::EBADF,
+        other => other.release(),
     }
+    0
 }
 
 // fcntl commands
@@ -1040,17 +1545,10 @@ pub fn sys_fcntl(fd: i32, cmd: i32, arg: u64) -> i64 {
                 }
             }
 
+            // For pipe and pty types, increment reference counts
+            entry.fd_type.add_ref();
             table.insert(new_fd, entry);
 
-            // For pipe types, increment reference counts
-            if let Some(e) = table.get(new_fd) {
-                match &e.fd_type {
-                    FdType::PipeRead { pipe } => pipe.add_reader(),
-                    FdType::PipeWrite { pipe } => pipe.add_writer(),
-                    _ => {}
-                }
-            }
-
             new_fd as i64
         }
 
@@ -1088,6 +1586,20 @@ pub fn sys_fcntl(fd: i32, cmd: i32, arg: u64) -> i64 {
                             }
                             flags
                         }
+                        FdType::PtyMaster { flags, .. } | FdType::PtySlave { flags, .. } => {
+                            flags.bits() as i64
+                        }
+                        FdType::Epoll { .. } => 0o2, // O_RDWR
+                        FdType::TimerFd { timerfd } => {
+                            if timerfd.is_nonblock() { 0o4000 } else { 0o0 } // O_RDONLY
+                        }
+                        FdType::SignalFd { signalfd } => {
+                            if signalfd.is_nonblock() { 0o4000 } else { 0o0 } // O_RDONLY
+                        }
+                        FdType::Inotify { inotify } => {
+                            if inotify.is_nonblock() { 0o4000 } else { 0o0 } // O_RDONLY
+                        }
+                        FdType::Bpf { .. } => 0o2, // O_RDWR
                     }
                 }
                 None => errno::EBADF,
@@ -1099,7 +1611,9 @@ pub fn sys_fcntl(fd: i32, cmd: i32, arg: u64) -> i64 {
             match table.get_mut(fd) {
                 Some(entry) => {
                     match &mut entry.fd_type {
-                        FdType::File { flags, .. } => {
+                        FdType::File { flags, .. }
+                        | FdType::PtyMaster { flags, .. }
+                        | FdType::PtySlave { flags, .. } => {
                             // Keep access mode, update other flags
                             let access = flags.bits() & 0o3;
                             let new_flags = (arg as u32) & !0o3 | access;
@@ -1264,6 +1778,12 @@ pub fn sys_lseek(fd: i32, offset: i64, whence: i32) -> i64 {
         }
         FdType::Dir { .. } => errno::EISDIR,
         FdType::PipeRead { .. } | FdType::PipeWrite { .. } | FdType::Socket { .. } | FdType::EventFd { .. } => errno::ESPIPE,
+        FdType::PtyMaster { .. } | FdType::PtySlave { .. } => errno::ESPIPE,
+        FdType::Epoll { .. }
+        | FdType::TimerFd { .. }
+        | FdType::SignalFd { .. }
+        | FdType::Inotify { .. }
+        | FdType::Bpf { .. } => errno::ESPIPE,
     }
 }
 
@@ -1479,11 +1999,28 @@ pub fn sys_fstat(fd: i32, statbuf: u64) -> i64 {
             stat.st_mode = 0o140600; // S_IFSOCK | rw-------
             stat
         }
-        FdType::EventFd { .. } => {
+        FdType::EventFd { .. }
+        | FdType::Epoll { .. }
+        | FdType::TimerFd { .. }
+        | FdType::SignalFd { .. }
+        | FdType::Inotify { .. }
+        | FdType::Bpf { .. } => {
             let mut stat = Stat::new();
             stat.st_mode = 0o100600; // S_IFREG | rw-------
             stat
         }
+        FdType::PtyMaster { .. } => {
+            let mut stat = Stat::new();
+            stat.st_mode = 0o020666; // S_IFCHR | rw-rw-rw-
+            stat.st_rdev = (5 << 8) | 2; // /dev/ptmx
+            stat
+        }
+        FdType::PtySlave { pty, .. } => {
+            let mut stat = Stat::new();
+            stat.st_mode = 0o020620; // S_IFCHR | rw--w----
+            stat.st_rdev = (136 << 8) | pty.index() as u64; // /dev/pts/N
+            stat
+        }
     };
 
     // Write to user buffer
@@ -1711,6 +2248,9 @@ pub fn sys_rename(oldpath_ptr: u64, newpath_ptr: u64) -> i64 {
         Err(KError::NotEmpty) => errno::ENOTEMPTY,
         Err(KError::AlreadyExists) => errno::EEXIST,
         Err(KError::Invalid) => errno::EINVAL,
+        Err(KError::ReadOnly) => errno::EROFS,
+        // Moves the filesystem cannot do in place, e.g. merged overlay dirs
+        Err(KError::NotSupported) => errno::EXDEV,
         Err(_) => errno::EIO,
     }
 }
@@ -1932,6 +2472,7 @@ pub fn sys_mkdir(pathname: u64, mode: u32) -> i64 {
     match vfs.mkdir_all(&path, &cred, Mode::from_octal(mode as u16)) {
         Ok(()) => 0,
         Err(KError::PermissionDenied) => errno::EACCES,
+        Err(KError::ReadOnly) => errno::EROFS,
         Err(_) => errno::EIO,
     }
 }
@@ -1951,6 +2492,7 @@ pub fn sys_rmdir(pathname: u64) -> i64 {
         Err(KError::PermissionDenied) => errno::EACCES,
         Err(KError::NotEmpty) => errno::ENOTEMPTY,
         Err(KError::Invalid) => errno::ENOTDIR,
+        Err(KError::ReadOnly) => errno::EROFS,
         Err(_) => errno::EIO,
     }
 }
@@ -1969,6 +2511,7 @@ pub fn sys_unlink(pathname: u64) -> i64 {
         Err(KError::NotFound) => errno::ENOENT,
         Err(KError::PermissionDenied) => errno::EACCES,
         Err(KError::Invalid) => errno::EISDIR, // unlink on a directory
+        Err(KError::ReadOnly) => errno::EROFS,
         Err(_) => errno::EIO,
     }
 }
@@ -1996,6 +2539,7 @@ pub fn sys_symlink(target: u64, linkpath: u64) -> i64 {
         Err(KError::AlreadyExists) => errno::EEXIST,
         Err(KError::PermissionDenied) => errno::EACCES,
         Err(KError::NotSupported) => errno::EPERM,
+        Err(KError::ReadOnly) => errno::EROFS,
         Err(_) => errno::EIO,
     }
 }
@@ -2069,8 +2613,13 @@ pub fn sys_truncate(pathname: u64, length: i64) -> i64 {
     }
 
     match inode.0.truncate(length as usize) {
-        Ok(()) => 0,
+        Ok(()) => {
+            fs::page_cache::truncate_file_pages(&inode, length as usize);
+            fs::inotify::notify(&fs::vfs::normalize(&path), fs::inotify::IN_MODIFY);
+            0
+        }
         Err(KError::PermissionDenied) => errno::EACCES,
+        Err(KError::ReadOnly) => errno::EROFS,
         Err(_) => errno::EIO,
     }
 }
@@ -2093,7 +2642,7 @@ pub fn sys_ftruncate(fd: i32, length: i64) -> i64 {
     };
 
     match &entry.fd_type {
-        FdType::File { inode, flags, .. } => {
+        FdType::File { inode, path, flags, .. } => {
             // Check if it's a regular file
             if inode.metadata().kind != InodeKind::File {
                 return errno::EINVAL;
@@ -2106,7 +2655,12 @@ pub fn sys_ftruncate(fd: i32, length: i64) -> i64 {
             }
 
             match inode.0.truncate(length as usize) {
-                Ok(()) => 0,
+                Ok(()) => {
+                    fs::page_cache::truncate_file_pages(inode, length as usize);
+                    fs::inotify::notify(path, fs::inotify::IN_MODIFY);
+                    0
+                }
+                Err(KError::ReadOnly) => errno::EROFS,
                 Err(_) => errno::EIO,
             }
         }
@@ -2145,56 +2699,637 @@ pub fn sys_fdatasync(fd: i32) -> i64 {
     sys_fsync(fd)
 }
 
-// ==================== Permission syscalls ====================
-
-pub fn sys_chmod(pathname: u64, mode: u32) -> i64 {
-    use crate::fs::Mode;
+// ==================== Mount syscalls ====================
 
-    let path = match unsafe { read_user_string(pathname, 1024) } {
-        Some(p) => p,
-        None => return errno::EFAULT,
-    };
+/// mount(2) flags
+pub mod mount_flags {
+    pub const MS_RDONLY: u64 = 1;
+    pub const MS_NOSUID: u64 = 2;
+    pub const MS_NODEV: u64 = 4;
+    pub const MS_NOEXEC: u64 = 8;
+    pub const MS_REMOUNT: u64 = 32;
+    pub const MS_BIND: u64 = 4096;
+    pub const MS_REC: u64 = 16384;
+    /// Magic value some callers still put in the upper 16 bits
+    pub const MS_MGC_MSK: u64 = 0xFFFF_0000;
+    pub const MS_MGC_VAL: u64 = 0xC0ED_0000;
+}
 
-    let cred = current_cred();
-    let mut vfs = fs::vfs_lock();
+/// umount2(2) flags
+pub mod umount_flags {
+    pub const MNT_FORCE: u32 = 1;
+    pub const MNT_DETACH: u32 = 2;
+    pub const MNT_EXPIRE: u32 = 4;
+    pub const UMOUNT_NOFOLLOW: u32 = 8;
+}
 
-    match vfs.chmod(&path, Mode::from_octal(mode as u16), &cred) {
-        Ok(()) => 0,
-        Err(KError::NotFound) => errno::ENOENT,
-        Err(KError::PermissionDenied) => errno::EPERM,
-        Err(_) => errno::EIO,
+/// struct statfs (Linux x86_64 layout)
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Statfs {
+    pub f_type: i64,
+    pub f_bsize: i64,
+    pub f_blocks: u64,
+    pub f_bfree: u64,
+    pub f_bavail: u64,
+    pub f_files: u64,
+    pub f_ffree: u64,
+    pub f_fsid: [i32; 2],
+    pub f_namelen: i64,
+    pub f_frsize: i64,
+    pub f_flags: i64,
+    pub f_spare: [i64; 4],
+}
+
+impl Statfs {
+    fn from_inode(inode: &Inode) -> Option<Self> {
+        let st = inode.0.statfs().ok()?;
+        Some(Self {
+            f_type: st.magic as i64,
+            f_bsize: st.block_size as i64,
+            f_blocks: st.blocks,
+            f_bfree: st.blocks_free,
+            f_bavail: st.blocks_avail,
+            f_files: st.files,
+            f_ffree: st.files_free,
+            f_fsid: [0; 2],
+            f_namelen: st.name_max as i64,
+            f_frsize: st.block_size as i64,
+            f_flags: inode.0.mount_flags().bits() as i64,
+            f_spare: [0; 4],
+        })
     }
 }
 
-pub fn sys_fchmod(fd: i32, mode: u32) -> i64 {
-    use crate::fs::Mode;
-
-    let table = fd_table();
-    let table = match table.as_ref() {
-        Some(t) => t,
-        None => return errno::EBADF,
-    };
-
-    let entry = match table.get(fd) {
-        Some(e) => e,
-        None => return errno::EBADF,
-    };
+/// Absolute, normalized form of a user-supplied path
+fn absolute_path(path: &str) -> String {
+    if path.starts_with('/') {
+        return normalize_path(path);
+    }
+    let cwd = crate::sched::current_task().cwd();
+    normalize_path(&alloc::format!("{}/{}", cwd, path))
+}
 
-    let inode = match &entry.fd_type {
-        FdType::File { inode, .. } => inode.clone(),
-        _ => return errno::EINVAL, // Não é um arquivo regular
-    };
+/// mount syscall - attach a filesystem (or, with MS_BIND, a directory) at `target`
+///
+/// `fstype` selects the driver; block filesystems read `source` as a device
+/// path such as /dev/sda1. `data` holds the layers of an overlay
+/// ("lowerdir=...,upperdir=...,workdir=..."); other filesystems ignore it.
+pub fn sys_mount(source: u64, target: u64, fstype: u64, flags: u64, data: u64) -> i64 {
+    use mount_flags::*;
 
     let cred = current_cred();
-    let mut meta = inode.metadata();
-
-    // Somente root ou owner pode alterar permissões
-    if cred.uid.0 != 0 && meta.uid != cred.uid {
+    if cred.euid.0 != 0 {
         return errno::EPERM;
     }
 
-    meta.mode = Mode::from_octal(mode as u16);
+    let target = match unsafe { read_user_string(target, 4096) } {
+        Some(p) if !p.is_empty() => absolute_path(&p),
+        Some(_) => return errno::ENOENT,
+        None => return errno::EFAULT,
+    };
+    let source = if source == 0 {
+        String::new()
+    } else {
+        match unsafe { read_user_string(source, 4096) } {
+            Some(s) => s,
+            None => return errno::EFAULT,
+        }
+    };
+
+    let flags = if flags & MS_MGC_MSK == MS_MGC_VAL { flags & !MS_MGC_MSK } else { flags };
+    if flags & MS_REMOUNT != 0 {
+        // Changing the flags of a live mount is not supported
+        return errno::EINVAL;
+    }
+    let mnt_flags = fs::MountFlags::from_bits_truncate(flags & (MS_RDONLY | MS_NOSUID | MS_NODEV | MS_NOEXEC));
+
+    // The mount point must be an existing directory
+    let mountpoint = {
+        let vfs = fs::vfs_lock();
+        match vfs.resolve(&target, &cred) {
+            Ok(i) if i.kind() == InodeKind::Dir => i,
+            Ok(_) => return errno::ENOTDIR,
+            Err(KError::NotFound) => return errno::ENOENT,
+            Err(KError::PermissionDenied) => return errno::EACCES,
+            Err(KError::NotADirectory) => return errno::ENOTDIR,
+            Err(_) => return errno::EIO,
+        }
+    };
+
+    if flags & MS_BIND != 0 {
+        let source = absolute_path(&source);
+        let mut vfs = fs::vfs_lock();
+        let inode = match vfs.resolve(&source, &cred) {
+            Ok(i) => i,
+            Err(KError::NotFound) => return errno::ENOENT,
+            Err(KError::PermissionDenied) => return errno::EACCES,
+            Err(KError::NotADirectory) => return errno::ENOTDIR,
+            Err(_) => return errno::EIO,
+        };
+        if inode.kind() != InodeKind::Dir {
+            return errno::ENOTDIR;
+        }
+        let fs_type = fs::vfs::mounted_fs_type(&inode).unwrap_or_else(|| String::from("rootfs"));
+        if crate::security::hooks::sb_mount(&mountpoint, &target, &fs_type).is_err() {
+            return errno::EACCES;
+        }
+        vfs.mount(&target, inode, &source, &fs_type, mnt_flags);
+        return 0;
+    }
+
+    let fs_type = match unsafe { read_user_string(fstype, 64) } {
+        Some(t) if !t.is_empty() => t,
+        Some(_) => return errno::ENODEV,
+        None => return errno::EFAULT,
+    };
+    if crate::security::hooks::sb_mount(&mountpoint, &target, &fs_type).is_err() {
+        return errno::EACCES;
+    }
+
+    let device = if fs::fs_needs_device(&fs_type) {
+        match crate::storage::find_device_by_path(&source).and_then(crate::storage::get_device) {
+            Some(d) => Some(d),
+            None if source.starts_with("/dev/") => return errno::ENXIO,
+            None => return errno::ENOTBLK,
+        }
+    } else {
+        None
+    };
+
+    let root = if fs_type == "overlay" {
+        let options = if data == 0 {
+            String::new()
+        } else {
+            match unsafe { read_user_string(data, 4096) } {
+                Some(s) => s,
+                None => return errno::EFAULT,
+            }
+        };
+        fs::overlayfs::mount(&options, &cred)
+    } else {
+        fs::mount_fs(&fs_type, device)
+    };
+    let root = match root {
+        Ok(r) => r,
+        Err(KError::NotSupported) => return errno::ENODEV,
+        Err(KError::Invalid) => return errno::EINVAL,
+        Err(KError::NotFound) => return errno::ENOENT,
+        Err(KError::NotADirectory) => return errno::ENOTDIR,
+        Err(KError::ReadOnly) => return errno::EROFS,
+        Err(KError::NoMemory) => return errno::ENOMEM,
+        Err(_) => return errno::EIO,
+    };
+
+    let mut vfs = fs::vfs_lock();
+    vfs.mount(&target, root, &source, &fs_type, mnt_flags);
+    crate::kprintln!("mount: {} ({}) em {}", source, fs_type, target);
+    0
+}
+
+/// umount2 syscall - detach the filesystem mounted at `target`
+///
+/// Fails with EBUSY while the mount is in use unless MNT_DETACH is given.
+pub fn sys_umount2(target: u64, flags: u32) -> i64 {
+    use umount_flags::*;
+
+    let cred = current_cred();
+    if cred.euid.0 != 0 {
+        return errno::EPERM;
+    }
+
+    if flags & !(MNT_FORCE | MNT_DETACH | MNT_EXPIRE | UMOUNT_NOFOLLOW) != 0 {
+        return errno::EINVAL;
+    }
+    if flags & MNT_EXPIRE != 0 && flags & (MNT_FORCE | MNT_DETACH) != 0 {
+        return errno::EINVAL;
+    }
+
+    let target = match unsafe { read_user_string(target, 4096) } {
+        Some(p) if !p.is_empty() => absolute_path(&p),
+        Some(_) => return errno::ENOENT,
+        None => return errno::EFAULT,
+    };
+
+    let mut vfs = fs::vfs_lock();
+    match vfs.umount(&target, flags & MNT_DETACH != 0) {
+        Ok(()) => {
+            crate::kprintln!("umount: {}", target);
+            0
+        }
+        Err(KError::Busy) => errno::EBUSY,
+        Err(KError::Invalid) => errno::EINVAL,
+        Err(_) => errno::EIO,
+    }
+}
+
+/// statfs syscall - filesystem statistics by path
+pub fn sys_statfs(path_ptr: u64, buf: u64) -> i64 {
+    if !is_user_addr(path_ptr) || !is_user_range(buf, core::mem::size_of::<Statfs>()) {
+        return errno::EFAULT;
+    }
+
+    let path = match unsafe { read_user_string(path_ptr, 4096) } {
+        Some(p) => p,
+        None => return errno::EFAULT,
+    };
+
+    if path.is_empty() {
+        return errno::ENOENT;
+    }
+
+    let cred = current_cred();
+    let vfs = fs::vfs_lock();
+    let inode = match vfs.resolve(&path, &cred) {
+        Ok(i) => i,
+        Err(KError::NotFound) => return errno::ENOENT,
+        Err(KError::PermissionDenied) => return errno::EACCES,
+        Err(KError::NotADirectory) => return errno::ENOTDIR,
+        Err(_) => return errno::EIO,
+    };
+    drop(vfs);
+
+    let st = match Statfs::from_inode(&inode) {
+        Some(st) => st,
+        None => return errno::ENOSYS,
+    };
+
+    unsafe {
+        core::ptr::write(buf as *mut Statfs, st);
+    }
+
+    0
+}
+
+/// fstatfs syscall - filesystem statistics by file descriptor
+pub fn sys_fstatfs(fd: i32, buf: u64) -> i64 {
+    if !is_user_range(buf, core::mem::size_of::<Statfs>()) {
+        return errno::EFAULT;
+    }
+
+    let table = fd_table();
+    let table = match table.as_ref() {
+        Some(t) => t,
+        None => return errno::EBADF,
+    };
+
+    let entry = match table.get(fd) {
+        Some(e) => e,
+        None => return errno::EBADF,
+    };
+
+    let st = match &entry.fd_type {
+        FdType::File { inode, .. } | FdType::Dir { inode, .. } => match Statfs::from_inode(inode) {
+            Some(st) => st,
+            None => return errno::ENOSYS,
+        },
+        // Pipes, sockets and the console live on in-kernel pseudo filesystems
+        _ => Statfs {
+            f_bsize: 4096,
+            f_frsize: 4096,
+            f_namelen: 255,
+            ..Statfs::default()
+        },
+    };
+
+    unsafe {
+        core::ptr::write(buf as *mut Statfs, st);
+    }
+
+    0
+}
+
+// ==================== Random ====================
+
+/// Flags de getrandom
+pub mod getrandom_flags {
+    pub const GRND_NONBLOCK: u32 = 0x0001;
+    pub const GRND_RANDOM: u32 = 0x0002;
+    pub const GRND_INSECURE: u32 = 0x0004;
+}
+
+/// Largest getrandom request served at once, as on Linux
+const GETRANDOM_MAX: usize = 0x01ff_ffff;
+
+/// Largest GRND_RANDOM request served at once
+const GETRANDOM_RANDOM_MAX: usize = 512;
+
+/// getrandom syscall - fill `buf` from the kernel CSPRNG
+///
+/// Blocks until the generator has been seeded, unless GRND_NONBLOCK (fail
+/// with EAGAIN instead) or GRND_INSECURE (return unseeded output) is given.
+/// GRND_RANDOM reads the /dev/random source, which returns at most 512 bytes
+/// per call.
+pub fn sys_getrandom(buf: u64, count: usize, flags: u32) -> i64 {
+    use getrandom_flags::*;
+
+    if flags & !(GRND_NONBLOCK | GRND_RANDOM | GRND_INSECURE) != 0 {
+        return errno::EINVAL;
+    }
+    if flags & GRND_INSECURE != 0 && flags & GRND_RANDOM != 0 {
+        return errno::EINVAL;
+    }
+
+    let max = if flags & GRND_RANDOM != 0 { GETRANDOM_RANDOM_MAX } else { GETRANDOM_MAX };
+    let count = count.min(max);
+    if count == 0 {
+        return 0;
+    }
+    if !is_user_range(buf, count) {
+        return errno::EFAULT;
+    }
+
+    if flags & GRND_INSECURE == 0 && !crate::crypto::random::is_seeded() {
+        if flags & GRND_NONBLOCK != 0 {
+            return errno::EAGAIN;
+        }
+        crate::crypto::random::wait_until_seeded();
+    }
+
+    let out = unsafe { core::slice::from_raw_parts_mut(buf as *mut u8, count) };
+    crate::crypto::random::fill_random(out);
+    count as i64
+}
+
+// ==================== getcpu ====================
+
+/// getcpu - CPU e nó NUMA onde a thread está rodando (o vDSO responde sem
+/// syscall quando há rdtscp)
+pub fn sys_getcpu(cpu: u64, node: u64) -> i64 {
+    if cpu != 0 && !is_user_range(cpu, 4) {
+        return errno::EFAULT;
+    }
+    if node != 0 && !is_user_range(node, 4) {
+        return errno::EFAULT;
+    }
+    let apic_id = crate::arch::x86_64_arch::apic::lapic_id();
+    unsafe {
+        if cpu != 0 {
+            core::ptr::write_unaligned(cpu as *mut u32, crate::arch::x86_64_arch::percpu::cpu_number());
+        }
+        if node != 0 {
+            core::ptr::write_unaligned(node as *mut u32, crate::mm::numa::cpu_to_node(apic_id));
+        }
+    }
+    0
+}
+
+// ==================== signalfd ====================
+
+/// signalfd4 - cria um signalfd (fd == -1) ou troca a máscara de um existente
+///
+/// Os signals da máscara ficam pendentes na task (desde que bloqueados) e
+/// são retirados por read().
+pub fn sys_signalfd4(fd: i32, mask: u64, sizemask: usize, flags: i32) -> i64 {
+    use crate::signal::sfd_flags::{SFD_CLOEXEC, SFD_NONBLOCK};
+
+    if sizemask != 8 {
+        return errno::EINVAL;
+    }
+    if flags as u32 & !(SFD_CLOEXEC | SFD_NONBLOCK) != 0 {
+        return errno::EINVAL;
+    }
+    if mask == 0 || !is_user_range(mask, 8) {
+        return errno::EFAULT;
+    }
+    // Mesmo formato de máscara que rt_sigprocmask
+    let sigset = unsafe { core::ptr::read_unaligned(mask as *const u64) };
+
+    let mut table = fd_table();
+    let Some(table) = table.as_mut() else {
+        return errno::EBADF;
+    };
+
+    if fd != -1 {
+        return match table.get(fd).map(|e| &e.fd_type) {
+            Some(FdType::SignalFd { signalfd }) => {
+                signalfd.set_mask(sigset);
+                fd as i64
+            }
+            Some(_) => errno::EINVAL,
+            None => errno::EBADF,
+        };
+    }
+
+    let new_fd = table.alloc();
+    table.insert(new_fd, FdEntry {
+        fd_type: FdType::SignalFd {
+            signalfd: Arc::new(crate::signal::SignalFd::new(sigset, flags as u32)),
+        },
+    });
+    new_fd as i64
+}
+
+/// signalfd - versão sem flags
+pub fn sys_signalfd(fd: i32, mask: u64, sizemask: usize) -> i64 {
+    sys_signalfd4(fd, mask, sizemask, 0)
+}
+
+/// read() de um signalfd: bloqueia até um signal da máscara ficar pendente
+fn signalfd_read(signalfd: &crate::signal::SignalFd, buf: u64, count: usize) -> i64 {
+    use crate::signal::{SignalFdError, SignalfdSiginfo};
+
+    if count < SignalfdSiginfo::SIZE {
+        return errno::EINVAL;
+    }
+    let out = unsafe { core::slice::from_raw_parts_mut(buf as *mut u8, count) };
+    let task = crate::sched::current_task();
+    loop {
+        match signalfd.read(task.signals(), out) {
+            Ok(n) => return n as i64,
+            Err(SignalFdError::WouldBlock) => {}
+            Err(_) => return errno::EINVAL,
+        }
+        if signalfd.is_nonblock() {
+            return errno::EAGAIN;
+        }
+        if crate::sched::has_pending_signals() {
+            return errno::EINTR;
+        }
+        crate::task::yield_now();
+    }
+}
+
+// ==================== Namespace syscalls ====================
+
+/// unshare syscall - move the caller to new namespaces
+///
+/// CLONE_NEWNS gives the caller a private copy of its mount table.
+pub fn sys_unshare(flags: u64) -> i64 {
+    match crate::ipc::namespace::sys_unshare(flags) {
+        Ok(()) => 0,
+        Err(KError::Invalid) => errno::EINVAL,
+        Err(KError::PermissionDenied) => errno::EPERM,
+        Err(KError::NoMemory) => errno::ENOMEM,
+        Err(_) => errno::EIO,
+    }
+}
+
+/// setns syscall - join the mount namespace `fd` refers to
+///
+/// `fd` must be an open /proc/<pid>/ns/mnt file.
+pub fn sys_setns(fd: i32, nstype: u64) -> i64 {
+    let ns = {
+        let table = fd_table();
+        let table = match table.as_ref() {
+            Some(t) => t,
+            None => return errno::EBADF,
+        };
+        let entry = match table.get(fd) {
+            Some(e) => e,
+            None => return errno::EBADF,
+        };
+        let ns = match &entry.fd_type {
+            FdType::File { inode, .. } => fs::procfs::mnt_ns_of(inode),
+            _ => None,
+        };
+        match ns {
+            Some(ns) => ns,
+            None => return errno::EINVAL,
+        }
+    };
+
+    match crate::ipc::namespace::sys_setns(ns, nstype) {
+        Ok(()) => 0,
+        Err(KError::Invalid) => errno::EINVAL,
+        Err(KError::PermissionDenied) => errno::EPERM,
+        Err(_) => errno::EIO,
+    }
+}
+
+/// pivot_root syscall - make `new_root` the root of the mount namespace and
+/// move the old root to `put_old`
+pub fn sys_pivot_root(new_root: u64, put_old: u64) -> i64 {
+    let cred = current_cred();
+    if cred.euid.0 != 0 {
+        return errno::EPERM;
+    }
+
+    let new_root = match unsafe { read_user_string(new_root, 4096) } {
+        Some(p) if !p.is_empty() => absolute_path(&p),
+        Some(_) => return errno::ENOENT,
+        None => return errno::EFAULT,
+    };
+    let put_old = match unsafe { read_user_string(put_old, 4096) } {
+        Some(p) if !p.is_empty() => absolute_path(&p),
+        Some(_) => return errno::ENOENT,
+        None => return errno::EFAULT,
+    };
+
+    // The caller's root must be the root of its mount namespace
+    let task = crate::sched::current_task();
+    if task.fs_root().is_some() {
+        return errno::EINVAL;
+    }
+
+    let mut vfs = fs::vfs_lock();
+    match vfs.pivot_root(&new_root, &put_old, &cred) {
+        Ok(()) => {
+            drop(vfs);
+            task.set_cwd(String::from("/"));
+            0
+        }
+        Err(KError::NotFound) => errno::ENOENT,
+        Err(KError::NotADirectory) => errno::ENOTDIR,
+        Err(KError::PermissionDenied) => errno::EACCES,
+        Err(KError::Busy) => errno::EBUSY,
+        Err(KError::Invalid) => errno::EINVAL,
+        Err(_) => errno::EIO,
+    }
+}
+
+/// chroot syscall - change the root directory of the caller
+///
+/// The working directory moves to the new root as well, so no relative path
+/// can reach outside of it.
+pub fn sys_chroot(path_ptr: u64) -> i64 {
+    let cred = current_cred();
+    if cred.euid.0 != 0 {
+        return errno::EPERM;
+    }
+
+    let path = match unsafe { read_user_string(path_ptr, 4096) } {
+        Some(p) if !p.is_empty() => absolute_path(&p),
+        Some(_) => return errno::ENOENT,
+        None => return errno::EFAULT,
+    };
+
+    let task = crate::sched::current_task();
+    let view = match fs::vfs_lock().chroot(&path, &cred) {
+        Ok(v) => v,
+        Err(KError::NotFound) => return errno::ENOENT,
+        Err(KError::NotADirectory) => return errno::ENOTDIR,
+        Err(KError::PermissionDenied) => return errno::EACCES,
+        Err(_) => return errno::EIO,
+    };
+
+    // Paths in the current view are relative to the current root
+    let ns_path = match task.fs_root() {
+        Some(root) if root.path != "/" => {
+            if path == "/" {
+                root.path.clone()
+            } else {
+                alloc::format!("{}{}", root.path, path)
+            }
+        }
+        _ => path,
+    };
+    task.set_fs_root(Some(Arc::new(fs::Chroot::new(ns_path, view))));
+    task.set_cwd(String::from("/"));
+    0
+}
+
+// ==================== Permission syscalls ====================
+
+pub fn sys_chmod(pathname: u64, mode: u32) -> i64 {
+    use crate::fs::Mode;
+
+    let path = match unsafe { read_user_string(pathname, 1024) } {
+        Some(p) => p,
+        None => return errno::EFAULT,
+    };
+
+    let cred = current_cred();
+    let mut vfs = fs::vfs_lock();
+
+    match vfs.chmod(&path, Mode::from_octal(mode as u16), &cred) {
+        Ok(()) => 0,
+        Err(KError::NotFound) => errno::ENOENT,
+        Err(KError::PermissionDenied) => errno::EPERM,
+        Err(_) => errno::EIO,
+    }
+}
+
+pub fn sys_fchmod(fd: i32, mode: u32) -> i64 {
+    use crate::fs::Mode;
+
+    let table = fd_table();
+    let table = match table.as_ref() {
+        Some(t) => t,
+        None => return errno::EBADF,
+    };
+
+    let entry = match table.get(fd) {
+        Some(e) => e,
+        None => return errno::EBADF,
+    };
+
+    let (inode, path) = match &entry.fd_type {
+        FdType::File { inode, path, .. } => (inode.clone(), path.clone()),
+        _ => return errno::EINVAL, // Não é um arquivo regular
+    };
+
+    let cred = current_cred();
+    let mut meta = inode.metadata();
+
+    // Somente root ou owner pode alterar permissões
+    if cred.uid.0 != 0 && meta.uid != cred.uid {
+        return errno::EPERM;
+    }
+
+    meta.mode = Mode::from_octal(mode as u16);
     inode.0.set_metadata(meta);
+    fs::inotify::notify(&path, fs::inotify::IN_ATTRIB);
     0
 }
 
@@ -2235,8 +3370,8 @@ pub fn sys_fchown(fd: i32, owner: u32, group: u32) -> i64 {
         None => return errno::EBADF,
     };
 
-    let inode = match &entry.fd_type {
-        FdType::File { inode, .. } => inode.clone(),
+    let (inode, path) = match &entry.fd_type {
+        FdType::File { inode, path, .. } => (inode.clone(), path.clone()),
         _ => return errno::EINVAL,
     };
 
@@ -2260,6 +3395,7 @@ pub fn sys_fchown(fd: i32, owner: u32, group: u32) -> i64 {
     meta.uid = uid;
     meta.gid = gid;
     inode.0.set_metadata(meta);
+    fs::inotify::notify(&path, fs::inotify::IN_ATTRIB);
     0
 }
 
@@ -2631,23 +3767,33 @@ pub fn sys_uname(buf: u64) -> i64 {
     0
 }
 
+/// brk - move o program break do processo. O break fica nas VMAs do
+/// address space: começa no fim da imagem (exec), é copiado no fork e
+/// compartilhado entre threads.
 pub fn sys_brk(addr: u64) -> i64 {
-    // Simplificado: retorna o endereço atual do heap (fixo por enquanto).
-    // Um OS real gerenciaria o break do processo.
-    static HEAP_END: AtomicU64 = AtomicU64::new(0x0000_0000_1000_0000);
-
-    if addr == 0 {
-        return HEAP_END.load(Ordering::Relaxed) as i64;
+    let mut vmas = crate::mm::vma::manager_lock();
+    let current = vmas.brk();
+    if addr < vmas.brk_start() || addr >= 0x0000_7FFF_FFFF_0000 {
+        return current as i64;
     }
 
-    // Aceita qualquer valor dentro de limites razoáveis.
-    let current = HEAP_END.load(Ordering::Relaxed);
-    if addr >= current && addr < 0x0000_7FFF_FFFF_0000 {
-        HEAP_END.store(addr, Ordering::Relaxed);
-        return addr as i64;
+    let old_end = (current + 0xFFF) & !0xFFF;
+    let new_end = (addr + 0xFFF) & !0xFFF;
+    if new_end > old_end {
+        // Mapeia a parte nova do heap (páginas sob demanda)
+        const MAP_PRIVATE_ANONYMOUS_FIXED: i32 = 0x02 | 0x20 | 0x10;
+        let flags = crate::mm::vma::MapFlags::from_flags(MAP_PRIVATE_ANONYMOUS_FIXED);
+        let prot = crate::mm::vma::Protection::READ_WRITE;
+        let size = (new_end - old_end) as usize;
+        // MAP_FIXED substituiria o que já está mapeado ali
+        if vmas.iter().any(|vma| vma.overlaps(old_end, size)) || vmas.mmap(old_end, size, prot, flags).is_err() {
+            return current as i64;
+        }
+    } else if new_end < old_end && vmas.munmap(new_end, (old_end - new_end) as usize).is_err() {
+        return current as i64;
     }
-
-    current as i64
+    vmas.set_brk(addr);
+    addr as i64
 }
 
 /// mmap - mapeia memória virtual
@@ -2659,15 +3805,54 @@ pub fn sys_brk(addr: u64) -> i64 {
 /// fd: file descriptor (-1 para anônimo)
 /// offset: offset no arquivo
 pub fn sys_mmap(addr: u64, length: usize, prot: i32, flags: i32, fd: i32, offset: i64) -> i64 {
-    match crate::mm::vma::sys_mmap(addr, length, prot, flags, fd, offset) {
+    const MAP_ANONYMOUS: i32 = 0x20;
+
+    let file = if flags & MAP_ANONYMOUS == 0 {
+        if offset < 0 || offset & 0xFFF != 0 {
+            return errno::EINVAL;
+        }
+        match file_for_mmap(fd, offset as u64) {
+            Ok(file) => Some(file),
+            Err(e) => return e,
+        }
+    } else {
+        None
+    };
+
+    match crate::mm::vma::sys_mmap(addr, length, prot, flags, file) {
         Ok(mapped_addr) => mapped_addr as i64,
         Err(KError::NoMemory) => errno::ENOMEM,
         Err(KError::Invalid) => errno::EINVAL,
+        Err(KError::PermissionDenied) => errno::EACCES,
         Err(KError::NotSupported) => errno::ENOSYS,
         Err(_) => errno::EIO,
     }
 }
 
+/// Arquivo por trás de `fd` para mmap. Só arquivos regulares abertos para
+/// leitura podem ser mapeados.
+fn file_for_mmap(fd: i32, offset: u64) -> Result<crate::mm::vma::FileMapping, i64> {
+    let table = fd_table();
+    match table.as_ref().and_then(|t| t.get(fd)).map(|e| &e.fd_type) {
+        Some(FdType::File { inode, flags, .. }) => {
+            if inode.kind() != InodeKind::File {
+                return Err(errno::ENODEV);
+            }
+            let access_mode = flags.bits() & 0o3;
+            if access_mode == OpenFlags::O_WRONLY.bits() {
+                return Err(errno::EACCES);
+            }
+            Ok(crate::mm::vma::FileMapping {
+                inode: inode.clone(),
+                offset,
+                writable: access_mode == OpenFlags::O_RDWR.bits(),
+            })
+        }
+        Some(_) => Err(errno::ENODEV),
+        None => Err(errno::EBADF),
+    }
+}
+
 /// munmap - remove mapeamento de memória
 pub fn sys_munmap(addr: u64, length: usize) -> i64 {
     match crate::mm::vma::sys_munmap(addr, length) {
@@ -2680,6 +3865,17 @@ pub fn sys_munmap(addr: u64, length: usize) -> i64 {
 /// mprotect - altera proteção de memória
 pub fn sys_mprotect(addr: u64, length: usize, prot: i32) -> i64 {
     match crate::mm::vma::sys_mprotect(addr, length, prot) {
+        Ok(()) => 0,
+        Err(KError::Invalid) => errno::EINVAL,
+        Err(KError::NoMemory) => errno::ENOMEM,
+        Err(KError::PermissionDenied) => errno::EACCES,
+        Err(_) => errno::EIO,
+    }
+}
+
+/// msync - escreve de volta páginas sujas de mapeamentos de arquivo
+pub fn sys_msync(addr: u64, length: usize, flags: i32) -> i64 {
+    match crate::mm::vma::sys_msync(addr, length, flags) {
         Ok(()) => 0,
         Err(KError::Invalid) => errno::EINVAL,
         Err(KError::NoMemory) => errno::ENOMEM,
@@ -2735,22 +3931,78 @@ pub fn sys_settimeofday(tv: u64, tz: u64) -> i64 {
         return errno::EFAULT;
     }
 
+    let time = if tv != 0 {
+        let timeval = unsafe { core::ptr::read_unaligned(tv as *const crate::time::Timeval) };
+        match timeval.to_ns() {
+            Some(ns) => Some(ns),
+            None => return errno::EINVAL,
+        }
+    } else {
+        None
+    };
+
     // Set timezone if provided
     if tz != 0 {
         let timezone = unsafe { *(tz as *const crate::time::Timezone) };
         crate::time::set_timezone(&timezone);
     }
 
-    // Setting time (tv) is not supported yet - would need RTC write
-    // For now, just acknowledge success if only setting timezone
-    if tv != 0 {
-        // Time setting not implemented - would need RTC access
-        return errno::EINVAL;
+    if let Some(ns) = time {
+        if let Err(e) = crate::time::settime(ns) {
+            return e;
+        }
     }
 
     0
 }
 
+/// clock_settime - only CLOCK_REALTIME can be set
+pub fn sys_clock_settime(clock_id: i32, tp: u64) -> i64 {
+    if tp == 0 || !is_user_range(tp, core::mem::size_of::<crate::time::Timespec>()) {
+        return errno::EFAULT;
+    }
+    if clock_id != crate::time::clock::CLOCK_REALTIME {
+        return errno::EINVAL;
+    }
+    if crate::sched::current_cred().euid.0 != 0 {
+        return errno::EPERM;
+    }
+    let ts = unsafe { core::ptr::read_unaligned(tp as *const crate::time::Timespec) };
+    match ts.to_ns() {
+        Some(ns) => match crate::time::settime(ns) {
+            Ok(()) => 0,
+            Err(e) => e,
+        },
+        None => errno::EINVAL,
+    }
+}
+
+/// adjtimex - read or tune the kernel clock discipline (NTP)
+pub fn sys_adjtimex(buf: u64) -> i64 {
+    sys_clock_adjtime(crate::time::clock::CLOCK_REALTIME, buf)
+}
+
+/// clock_adjtime - adjtimex for a clock; only CLOCK_REALTIME is disciplined
+pub fn sys_clock_adjtime(clock_id: i32, buf: u64) -> i64 {
+    use crate::time::ntp::Timex;
+
+    if buf == 0 || !is_user_range(buf, core::mem::size_of::<Timex>()) {
+        return errno::EFAULT;
+    }
+    if clock_id != crate::time::clock::CLOCK_REALTIME {
+        return errno::EOPNOTSUPP;
+    }
+    let mut txc = unsafe { core::ptr::read_unaligned(buf as *const Timex) };
+    let privileged = crate::sched::current_cred().euid.0 == 0;
+    match crate::time::ntp::adjtimex(&mut txc, privileged) {
+        Ok(state) => {
+            unsafe { core::ptr::write_unaligned(buf as *mut Timex, txc) };
+            state as i64
+        }
+        Err(e) => e,
+    }
+}
+
 // ==================== Reboot/Shutdown Syscalls ====================
 
 /// Linux reboot magic numbers
@@ -2863,16 +4115,14 @@ pub fn sys_clock_gettime(clock_id: i32, tp: u64) -> i64 {
 
 /// clock_getres - obtém resolução de um clock
 pub fn sys_clock_getres(clock_id: i32, res: u64) -> i64 {
-    // Todos os clocks têm resolução de 10ms (100Hz)
-    if crate::time::clock_gettime(clock_id).is_none() {
+    let Some(resolution) = crate::time::clock_getres(clock_id) else {
         return errno::EINVAL;
-    }
+    };
 
     if res != 0 {
-        let resolution = crate::time::Timespec {
-            tv_sec: 0,
-            tv_nsec: 10_000_000, // 10ms em nanosegundos
-        };
+        if !is_user_range(res, core::mem::size_of::<crate::time::Timespec>()) {
+            return errno::EFAULT;
+        }
         unsafe {
             core::ptr::write(res as *mut crate::time::Timespec, resolution);
         }
@@ -2883,26 +4133,168 @@ pub fn sys_clock_getres(clock_id: i32, res: u64) -> i64 {
 
 /// nanosleep - dorme por um período específico
 pub fn sys_nanosleep(req: u64, rem: u64) -> i64 {
-    if req == 0 {
+    sys_clock_nanosleep(crate::time::clock::CLOCK_MONOTONIC, 0, req, rem)
+}
+
+/// clock_nanosleep - dorme até um tempo (TIMER_ABSTIME) ou por um período
+/// medido em `clock_id`. Interrompido por um signal, devolve EINTR e, se o
+/// sono era relativo, o tempo restante em `rem`.
+pub fn sys_clock_nanosleep(clock_id: i32, flags: i32, req: u64, rem: u64) -> i64 {
+    use crate::time::{clock, Timespec, TIMER_ABSTIME};
+
+    match clock_id {
+        clock::CLOCK_REALTIME | clock::CLOCK_MONOTONIC | clock::CLOCK_BOOTTIME => {}
+        // Dormir no próprio tempo de CPU nunca termina
+        clock::CLOCK_THREAD_CPUTIME_ID => return errno::EINVAL,
+        clock::CLOCK_PROCESS_CPUTIME_ID => return errno::EOPNOTSUPP,
+        _ => return errno::EINVAL,
+    }
+    if req == 0 || !is_user_range(req, core::mem::size_of::<Timespec>()) {
+        return errno::EFAULT;
+    }
+    let request = unsafe { core::ptr::read(req as *const Timespec) };
+    let Some(value) = request.to_ns() else {
+        return errno::EINVAL;
+    };
+    let Some(deadline) = crate::time::sleep_deadline(clock_id, flags, value) else {
+        return errno::EINVAL;
+    };
+
+    match crate::time::hrtimer::sleep_until(deadline) {
+        Ok(()) => 0,
+        Err(left) => {
+            if flags & TIMER_ABSTIME == 0 && rem != 0 && is_user_range(rem, core::mem::size_of::<Timespec>()) {
+                unsafe {
+                    core::ptr::write(rem as *mut Timespec, Timespec::from_ns(left));
+                }
+            }
+            errno::EINTR
+        }
+    }
+}
+
+/// getitimer
+pub fn sys_getitimer(which: i32, curr_value: u64) -> i64 {
+    use crate::time::Itimerval;
+
+    if curr_value == 0 || !is_user_range(curr_value, core::mem::size_of::<Itimerval>()) {
         return errno::EFAULT;
     }
+    match crate::time::itimer::getitimer(which) {
+        Ok(value) => {
+            unsafe { core::ptr::write_unaligned(curr_value as *mut Itimerval, value) };
+            0
+        }
+        Err(e) => e,
+    }
+}
+
+/// setitimer
+pub fn sys_setitimer(which: i32, new_value: u64, old_value: u64) -> i64 {
+    use crate::time::Itimerval;
+
+    if new_value == 0 || !is_user_range(new_value, core::mem::size_of::<Itimerval>()) {
+        return errno::EFAULT;
+    }
+    if old_value != 0 && !is_user_range(old_value, core::mem::size_of::<Itimerval>()) {
+        return errno::EFAULT;
+    }
+    let new = unsafe { core::ptr::read_unaligned(new_value as *const Itimerval) };
+    match crate::time::itimer::setitimer(which, &new) {
+        Ok(old) => {
+            if old_value != 0 {
+                unsafe { core::ptr::write_unaligned(old_value as *mut Itimerval, old) };
+            }
+            0
+        }
+        Err(e) => e,
+    }
+}
+
+/// alarm - SIGALRM depois de `seconds` segundos; devolve o que faltava
+/// do alarme anterior
+pub fn sys_alarm(seconds: u32) -> i64 {
+    crate::time::itimer::alarm(seconds) as i64
+}
+
+/// timer_create
+pub fn sys_timer_create(clock_id: i32, sevp: u64, timerid: u64) -> i64 {
+    use crate::time::posix_timers::Sigevent;
+
+    if timerid == 0 || !is_user_range(timerid, core::mem::size_of::<i32>()) {
+        return errno::EFAULT;
+    }
+    let event = if sevp == 0 {
+        None
+    } else if is_user_range(sevp, core::mem::size_of::<Sigevent>()) {
+        Some(unsafe { core::ptr::read_unaligned(sevp as *const Sigevent) })
+    } else {
+        return errno::EFAULT;
+    };
+    match crate::time::posix_timers::create(clock_id, event.as_ref()) {
+        Ok(id) => {
+            unsafe { core::ptr::write_unaligned(timerid as *mut i32, id) };
+            0
+        }
+        Err(e) => e,
+    }
+}
 
-    let request = unsafe { core::ptr::read(req as *const crate::time::Timespec) };
+/// timer_settime
+pub fn sys_timer_settime(timerid: i32, flags: i32, new_value: u64, old_value: u64) -> i64 {
+    use crate::time::{Itimerspec, TIMER_ABSTIME};
 
-    // Valida os valores
-    if request.tv_sec < 0 || request.tv_nsec < 0 || request.tv_nsec >= 1_000_000_000 {
+    if flags & !TIMER_ABSTIME != 0 {
         return errno::EINVAL;
     }
+    if new_value == 0 || !is_user_range(new_value, core::mem::size_of::<Itimerspec>()) {
+        return errno::EFAULT;
+    }
+    if old_value != 0 && !is_user_range(old_value, core::mem::size_of::<Itimerspec>()) {
+        return errno::EFAULT;
+    }
+    let new = unsafe { core::ptr::read_unaligned(new_value as *const Itimerspec) };
+    match crate::time::posix_timers::settime(timerid, flags, &new) {
+        Ok(old) => {
+            if old_value != 0 {
+                unsafe { core::ptr::write_unaligned(old_value as *mut Itimerspec, old) };
+            }
+            0
+        }
+        Err(e) => e,
+    }
+}
 
-    let remaining = crate::time::nanosleep(&request);
+/// timer_gettime
+pub fn sys_timer_gettime(timerid: i32, curr_value: u64) -> i64 {
+    use crate::time::Itimerspec;
 
-    if rem != 0 {
-        unsafe {
-            core::ptr::write(rem as *mut crate::time::Timespec, remaining);
+    if curr_value == 0 || !is_user_range(curr_value, core::mem::size_of::<Itimerspec>()) {
+        return errno::EFAULT;
+    }
+    match crate::time::posix_timers::gettime(timerid) {
+        Ok(value) => {
+            unsafe { core::ptr::write_unaligned(curr_value as *mut Itimerspec, value) };
+            0
         }
+        Err(e) => e,
     }
+}
 
-    0
+/// timer_getoverrun
+pub fn sys_timer_getoverrun(timerid: i32) -> i64 {
+    match crate::time::posix_timers::getoverrun(timerid) {
+        Ok(overrun) => overrun as i64,
+        Err(e) => e,
+    }
+}
+
+/// timer_delete
+pub fn sys_timer_delete(timerid: i32) -> i64 {
+    match crate::time::posix_timers::delete(timerid) {
+        Ok(()) => 0,
+        Err(e) => e,
+    }
 }
 
 // ======================== I/O Multiplexing syscalls ========================
@@ -3065,6 +4457,26 @@ pub fn sys_poll(fds: u64, nfds: u64, timeout: i32) -> i64 {
                             pfd.revents |= POLLOUT;
                         }
                     }
+                    FdType::PtyMaster { .. } | FdType::PtySlave { .. } => {
+                        let (readable, writable, hangup) = entry.fd_type.pty_poll();
+                        if pfd.events & POLLIN != 0 && readable {
+                            pfd.revents |= POLLIN;
+                        }
+                        if pfd.events & POLLOUT != 0 && writable {
+                            pfd.revents |= POLLOUT;
+                        }
+                        if hangup {
+                            pfd.revents |= POLLHUP;
+                        }
+                    }
+                    FdType::Epoll { .. }
+                    | FdType::TimerFd { .. }
+                    | FdType::SignalFd { .. }
+                    | FdType::Inotify { .. }
+                    | FdType::Bpf { .. } => {
+                        let mask = entry.fd_type.poll_mask() as i16;
+                        pfd.revents |= mask & (pfd.events | POLLERR | POLLHUP);
+                    }
                 }
 
                 if pfd.revents != 0 {
@@ -3282,6 +4694,32 @@ pub fn sys_select(nfds: i32, readfds: u64, writefds: u64, exceptfds: u64, timeou
                             ready_count += 1;
                         }
                     }
+                    FdType::PtyMaster { .. } | FdType::PtySlave { .. } => {
+                        let (readable, writable, _) = entry.fd_type.pty_poll();
+                        if check_read && readable {
+                            fd_set(fd, readfds);
+                            ready_count += 1;
+                        }
+                        if check_write && writable {
+                            fd_set(fd, writefds);
+                            ready_count += 1;
+                        }
+                    }
+                    FdType::Epoll { .. }
+                    | FdType::TimerFd { .. }
+                    | FdType::SignalFd { .. }
+                    | FdType::Inotify { .. }
+                    | FdType::Bpf { .. } => {
+                        let mask = entry.fd_type.poll_mask();
+                        if check_read && mask & epoll::EPOLLIN != 0 {
+                            fd_set(fd, readfds);
+                            ready_count += 1;
+                        }
+                        if check_write && mask & epoll::EPOLLOUT != 0 {
+                            fd_set(fd, writefds);
+                            ready_count += 1;
+                        }
+                    }
                 }
             }
         }
@@ -3452,6 +4890,26 @@ pub fn sys_ppoll(fds: u64, nfds: u64, tmo_p: u64, sigmask: u64, sigsetsize: u64)
                             pfd.revents |= POLLOUT;
                         }
                     }
+                    FdType::PtyMaster { .. } | FdType::PtySlave { .. } => {
+                        let (readable, writable, hangup) = entry.fd_type.pty_poll();
+                        if pfd.events & POLLIN != 0 && readable {
+                            pfd.revents |= POLLIN;
+                        }
+                        if pfd.events & POLLOUT != 0 && writable {
+                            pfd.revents |= POLLOUT;
+                        }
+                        if hangup {
+                            pfd.revents |= POLLHUP;
+                        }
+                    }
+                    FdType::Epoll { .. }
+                    | FdType::TimerFd { .. }
+                    | FdType::SignalFd { .. }
+                    | FdType::Inotify { .. }
+                    | FdType::Bpf { .. } => {
+                        let mask = entry.fd_type.poll_mask() as i16;
+                        pfd.revents |= mask & (pfd.events | POLLERR | POLLHUP);
+                    }
                 }
 
                 if pfd.revents != 0 {
@@ -3687,6 +5145,32 @@ pub fn sys_pselect6(nfds: i32, readfds: u64, writefds: u64, exceptfds: u64, time
                             ready_count += 1;
                         }
                     }
+                    FdType::PtyMaster { .. } | FdType::PtySlave { .. } => {
+                        let (readable, writable, _) = entry.fd_type.pty_poll();
+                        if check_read && readable {
+                            fd_set(fd, readfds);
+                            ready_count += 1;
+                        }
+                        if check_write && writable {
+                            fd_set(fd, writefds);
+                            ready_count += 1;
+                        }
+                    }
+                    FdType::Epoll { .. }
+                    | FdType::TimerFd { .. }
+                    | FdType::SignalFd { .. }
+                    | FdType::Inotify { .. }
+                    | FdType::Bpf { .. } => {
+                        let mask = entry.fd_type.poll_mask();
+                        if check_read && mask & epoll::EPOLLIN != 0 {
+                            fd_set(fd, readfds);
+                            ready_count += 1;
+                        }
+                        if check_write && mask & epoll::EPOLLOUT != 0 {
+                            fd_set(fd, writefds);
+                            ready_count += 1;
+                        }
+                    }
                 }
             }
         }
@@ -3761,13 +5245,17 @@ pub fn sys_clone(
         match crate::sched::clone_thread(sf, flags, child_stack, parent_tidptr, child_tidptr, tls) {
             Ok(tid) => tid as i64,
             Err(crate::util::KError::NoMemory) => errno::ENOMEM,
+            Err(crate::util::KError::WouldBlock) => errno::EAGAIN,
             Err(crate::util::KError::Invalid) => errno::EINVAL,
             Err(_) => errno::EIO,
         }
     } else {
         // Fork-like (sem compartilhamento de memória)
         // Reutiliza o fork existente mas com algumas diferenças
-        match crate::sched::fork_current_from_syscall(sf) {
+        if (flags & CLONE_NEWNS) != 0 && current_cred().euid.0 != 0 {
+            return errno::EPERM;
+        }
+        match crate::sched::fork_current_from_syscall(sf, flags) {
             Ok(child_pid) => {
                 if (flags & CLONE_PARENT_SETTID) != 0 && parent_tidptr != 0 {
                     unsafe {
@@ -3777,6 +5265,7 @@ pub fn sys_clone(
                 child_pid as i64
             }
             Err(crate::util::KError::NoMemory) => errno::ENOMEM,
+            Err(crate::util::KError::WouldBlock) => errno::EAGAIN,
             Err(_) => errno::EIO,
         }
     }
@@ -4088,12 +5577,7 @@ pub fn sys_rt_sigsuspend(mask: u64, sigsetsize: usize) -> i64 {
     }
 
     // Espera por um signal
-    loop {
-        if signals.has_pending() {
-            break;
-        }
-        crate::task::yield_now();
-    }
+    crate::sched::sleep_until_signal();
 
     // Restaura a máscara original
     signals.set_blocked_mask(old_mask);
@@ -4411,18 +5895,9 @@ pub fn sys_dup(oldfd: i32) -> i64 {
         None => return errno::EBADF,
     };
 
-    // Clona o tipo e incrementa contadores se for pipe
-    let new_fd_type = match &old_entry.fd_type {
-        FdType::PipeRead { pipe } => {
-            pipe.add_reader();
-            FdType::PipeRead { pipe: pipe.clone() }
-        }
-        FdType::PipeWrite { pipe } => {
-            pipe.add_writer();
-            FdType::PipeWrite { pipe: pipe.clone() }
-        }
-        other => other.clone(),
-    };
+    // Clona o tipo e incrementa contadores se for pipe ou pty
+    let new_fd_type = old_entry.fd_type.clone();
+    new_fd_type.add_ref();
 
     // Aloca novo FD
     let newfd = table.alloc();
@@ -4461,26 +5936,13 @@ pub fn sys_dup2(oldfd: i32, newfd: i32) -> i64 {
         None => return errno::EBADF,
     };
 
-    // Clona o tipo e incrementa contadores se for pipe
-    let new_fd_type = match &old_entry.fd_type {
-        FdType::PipeRead { pipe } => {
-            pipe.add_reader();
-            FdType::PipeRead { pipe: pipe.clone() }
-        }
-        FdType::PipeWrite { pipe } => {
-            pipe.add_writer();
-            FdType::PipeWrite { pipe: pipe.clone() }
-        }
-        other => other.clone(),
-    };
+    // Clona o tipo e incrementa contadores se for pipe ou pty
+    let new_fd_type = old_entry.fd_type.clone();
+    new_fd_type.add_ref();
 
     // Se newfd já está aberto, fecha primeiro
     if let Some(old_new_entry) = table.remove(newfd) {
-        match old_new_entry.fd_type {
-            FdType::PipeRead { pipe } => pipe.remove_reader(),
-            FdType::PipeWrite { pipe } => pipe.remove_writer(),
-            _ => {}
-        }
+        old_new_entry.fd_type.release();
     }
 
     // Insere o novo FD
@@ -4502,6 +5964,8 @@ pub mod ioctl_nr {
     pub const TIOCSPGRP: u64 = 0x5410;   // Set foreground pgrp
     pub const TIOCNOTTY: u64 = 0x5422;   // Give up controlling terminal
     pub const TIOCSCTTY: u64 = 0x540E;   // Become controlling terminal
+    pub const TIOCGSID: u64 = 0x5429;    // Get session of the terminal
+    pub const TCFLSH: u64 = 0x540B;      // Flush input/output queues
     pub const TIOCGPTN: u64 = 0x80045430; // Get pty number
     pub const TIOCSPTLCK: u64 = 0x40045431; // Lock/unlock pty
 
@@ -4524,129 +5988,131 @@ pub mod ioctl_nr {
     pub const SIOCSIFHWADDR: u64 = 0x8924;
 }
 
-/// termios structure (simplified)
-#[repr(C)]
-#[derive(Debug, Clone, Copy)]
-pub struct Termios {
-    pub c_iflag: u32,   // Input flags
-    pub c_oflag: u32,   // Output flags
-    pub c_cflag: u32,   // Control flags
-    pub c_lflag: u32,   // Local flags
-    pub c_line: u8,     // Line discipline
-    pub c_cc: [u8; 32], // Control characters
-    pub c_ispeed: u32,  // Input speed
-    pub c_ospeed: u32,  // Output speed
-}
-
-impl Default for Termios {
-    fn default() -> Self {
-        let mut cc = [0u8; 32];
-        // Default control characters
-        cc[0] = 3;    // VINTR = Ctrl-C
-        cc[1] = 28;   // VQUIT = Ctrl-\
-        cc[2] = 127;  // VERASE = DEL
-        cc[3] = 21;   // VKILL = Ctrl-U
-        cc[4] = 4;    // VEOF = Ctrl-D
-        cc[5] = 0;    // VTIME
-        cc[6] = 1;    // VMIN
-        cc[8] = 17;   // VSTART = Ctrl-Q
-        cc[9] = 19;   // VSTOP = Ctrl-S
-        cc[10] = 26;  // VSUSP = Ctrl-Z
+pub use crate::drivers::tty::{Termios, Winsize};
 
-        Self {
-            c_iflag: 0x0500,  // ICRNL | IXON
-            c_oflag: 0x0005,  // OPOST | ONLCR
-            c_cflag: 0x00BF,  // CS8 | CREAD | HUPCL
-            c_lflag: 0x8A3B,  // ISIG | ICANON | ECHO | ECHOE | ECHOK | ECHOCTL | ECHOKE | IEXTEN
-            c_line: 0,
-            c_cc: cc,
-            c_ispeed: 38400,
-            c_ospeed: 38400,
-        }
-    }
-}
+pub fn sys_ioctl(fd: i32, request: u64, arg: u64) -> i64 {
+    use ioctl_nr::*;
 
-/// Window size structure
-#[repr(C)]
-#[derive(Debug, Clone, Copy)]
-pub struct Winsize {
-    pub ws_row: u16,
-    pub ws_col: u16,
-    pub ws_xpixel: u16,
-    pub ws_ypixel: u16,
-}
+    // Get the fd entry to determine the type. The lock is released before
+    // the tty ioctls, which may signal process groups.
+    let (tty, master, bytes_available) = {
+        let table = fd_table();
+        let table = match table.as_ref() {
+            Some(t) => t,
+            None => return errno::EBADF,
+        };
 
-impl Default for Winsize {
-    fn default() -> Self {
-        Self {
-            ws_row: 25,
-            ws_col: 80,
-            ws_xpixel: 0,
-            ws_ypixel: 0,
-        }
-    }
-}
+        let entry = match table.get(fd) {
+            Some(e) => e,
+            None => return errno::EBADF,
+        };
 
-/// Global terminal state
-use spin::Mutex as SpinMutex;
+        let master = match &entry.fd_type {
+            FdType::PtyMaster { pty, .. } => Some(pty.clone()),
+            _ => None,
+        };
+        // Bytes available to read (FIONREAD)
+        let bytes_available: i32 = if request != FIONREAD {
+            0
+        } else {
+            match &entry.fd_type {
+                FdType::PipeRead { pipe } => pipe.available() as i32,
+                FdType::Console => {
+                    if crate::console::has_data() { 1 } else { 0 }
+                }
+                FdType::File { inode, offset, .. } => {
+                    let size = inode.0.size().unwrap_or(0);
+                    (size.saturating_sub(*offset)) as i32
+                }
+                FdType::Socket { socket_id } => {
+                    crate::net::socket::available(*socket_id) as i32
+                }
+                FdType::PtyMaster { pty, .. } => pty.tty().output_available() as i32,
+                FdType::PtySlave { pty, .. } => pty.tty().input_available() as i32,
+                FdType::Inotify { inotify } => inotify.pending_bytes() as i32,
+                _ => 0,
+            }
+        };
+        (entry.fd_type.tty(), master, bytes_available)
+    };
 
-static TERMINAL_STATE: SpinMutex<TerminalState> = SpinMutex::new(TerminalState::new());
+    match request {
+        // Terminal ioctls
+        TCGETS | TCSETS | TCSETSW | TCSETSF | TCFLSH | TIOCGWINSZ | TIOCSWINSZ | TIOCGPGRP
+        | TIOCSPGRP | TIOCGSID | TIOCSCTTY | TIOCNOTTY => match tty {
+            Some(tty) => tty_ioctl(&tty, master.is_some(), request, arg),
+            None => errno::ENOTTY,
+        },
+        TIOCGPTN => {
+            let Some(pty) = master else {
+                return errno::ENOTTY;
+            };
+            if !is_user_range(arg, core::mem::size_of::<u32>()) {
+                return errno::EFAULT;
+            }
+            unsafe {
+                core::ptr::write(arg as *mut u32, pty.index());
+            }
+            0
+        }
+        TIOCSPTLCK => {
+            let Some(pty) = master else {
+                return errno::ENOTTY;
+            };
+            if !is_user_range(arg, core::mem::size_of::<i32>()) {
+                return errno::EFAULT;
+            }
+            let lock = unsafe { core::ptr::read(arg as *const i32) };
+            pty.set_locked(lock != 0);
+            0
+        }
 
-struct TerminalState {
-    termios: Termios,
-    winsize: Winsize,
-    foreground_pgrp: i32,
-}
+        // File ioctls
+        FIONREAD => {
+            // Return bytes available to read
+            if !is_user_range(arg, core::mem::size_of::<i32>()) {
+                return errno::EFAULT;
+            }
+            unsafe {
+                core::ptr::write(arg as *mut i32, bytes_available);
+            }
+            0
+        }
+        FIONBIO => {
+            // Set/clear non-blocking - stub (would need to modify fd flags)
+            0
+        }
+        FIOCLEX | FIONCLEX => {
+            // Set/clear close-on-exec - stub
+            0
+        }
 
-impl TerminalState {
-    const fn new() -> Self {
-        Self {
-            termios: Termios {
-                c_iflag: 0x0500,
-                c_oflag: 0x0005,
-                c_cflag: 0x00BF,
-                c_lflag: 0x8A3B,
-                c_line: 0,
-                c_cc: [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
-                       0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0],
-                c_ispeed: 38400,
-                c_ospeed: 38400,
-            },
-            winsize: Winsize {
-                ws_row: 25,
-                ws_col: 80,
-                ws_xpixel: 0,
-                ws_ypixel: 0,
-            },
-            foreground_pgrp: 1,
+        // Unknown ioctl - return success for compatibility
+        _ => {
+            // For unhandled ioctls, return success (many programs check for errors)
+            0
         }
     }
 }
 
-pub fn sys_ioctl(fd: i32, request: u64, arg: u64) -> i64 {
+/// ioctls de terminal, sobre o tty de um fd de console ou pty.
+///
+/// Pelo slave, TIOCGPGRP/TIOCSPGRP/TIOCGSID só valem para o terminal de
+/// controle da sessão de quem chama; o master pode sempre consultá-los.
+fn tty_ioctl(tty: &Arc<Tty>, master: bool, request: u64, arg: u64) -> i64 {
     use ioctl_nr::*;
 
-    // Get the fd entry to determine the type
-    let table = fd_table();
-    let table = match table.as_ref() {
-        Some(t) => t,
-        None => return errno::EBADF,
-    };
-
-    let entry = match table.get(fd) {
-        Some(e) => e,
-        None => return errno::EBADF,
-    };
+    let task = crate::sched::current_task();
+    let session = tty.session();
+    let own_terminal = master || session == 0 || session == task.sid();
 
     match request {
-        // Terminal ioctls
         TCGETS => {
             if !is_user_range(arg, core::mem::size_of::<Termios>()) {
                 return errno::EFAULT;
             }
-            let state = TERMINAL_STATE.lock();
             unsafe {
-                core::ptr::write(arg as *mut Termios, state.termios);
+                core::ptr::write(arg as *mut Termios, tty.termios());
             }
             0
         }
@@ -4654,19 +6120,27 @@ pub fn sys_ioctl(fd: i32, request: u64, arg: u64) -> i64 {
             if !is_user_range(arg, core::mem::size_of::<Termios>()) {
                 return errno::EFAULT;
             }
-            let mut state = TERMINAL_STATE.lock();
-            unsafe {
-                state.termios = core::ptr::read(arg as *const Termios);
-            }
+            let termios = unsafe { core::ptr::read(arg as *const Termios) };
+            // Não há fila de saída para drenar: TCSETSW é como TCSETS
+            tty.set_termios(termios, request == TCSETSF);
+            0
+        }
+        TCFLSH => {
+            let (input, output) = match arg {
+                0 => (true, false), // TCIFLUSH
+                1 => (false, true), // TCOFLUSH
+                2 => (true, true),  // TCIOFLUSH
+                _ => return errno::EINVAL,
+            };
+            tty.flush(input, output);
             0
         }
         TIOCGWINSZ => {
             if !is_user_range(arg, core::mem::size_of::<Winsize>()) {
                 return errno::EFAULT;
             }
-            let state = TERMINAL_STATE.lock();
             unsafe {
-                core::ptr::write(arg as *mut Winsize, state.winsize);
+                core::ptr::write(arg as *mut Winsize, tty.winsize());
             }
             0
         }
@@ -4674,81 +6148,108 @@ pub fn sys_ioctl(fd: i32, request: u64, arg: u64) -> i64 {
             if !is_user_range(arg, core::mem::size_of::<Winsize>()) {
                 return errno::EFAULT;
             }
-            let mut state = TERMINAL_STATE.lock();
-            unsafe {
-                state.winsize = core::ptr::read(arg as *const Winsize);
-            }
-            // Could send SIGWINCH to foreground process group here
+            // SIGWINCH para o grupo em foreground se o tamanho mudou
+            tty.set_winsize(unsafe { core::ptr::read(arg as *const Winsize) });
             0
         }
         TIOCGPGRP => {
+            if !own_terminal {
+                return errno::ENOTTY;
+            }
             if !is_user_range(arg, core::mem::size_of::<i32>()) {
                 return errno::EFAULT;
             }
-            let state = TERMINAL_STATE.lock();
             unsafe {
-                core::ptr::write(arg as *mut i32, state.foreground_pgrp);
+                core::ptr::write(arg as *mut i32, tty.pgrp() as i32);
             }
             0
         }
         TIOCSPGRP => {
+            if !own_terminal {
+                return errno::ENOTTY;
+            }
             if !is_user_range(arg, core::mem::size_of::<i32>()) {
                 return errno::EFAULT;
             }
-            let mut state = TERMINAL_STATE.lock();
-            unsafe {
-                state.foreground_pgrp = core::ptr::read(arg as *const i32);
+            let pgrp = unsafe { core::ptr::read(arg as *const i32) };
+            if pgrp <= 0 {
+                return errno::EINVAL;
             }
+            // O grupo tem que existir na sessão dona do terminal
+            let owner = if session != 0 { session } else { task.sid() };
+            if !crate::sched::pgrp_in_session(pgrp as u64, owner) {
+                return errno::EPERM;
+            }
+            tty.set_pgrp(pgrp as u64);
             0
         }
-        TIOCNOTTY => {
-            // Give up controlling terminal - stub
-            0
-        }
-        TIOCSCTTY => {
-            // Become controlling terminal - stub
-            0
-        }
-
-        // File ioctls
-        FIONREAD => {
-            // Return bytes available to read
+        TIOCGSID => {
+            if session == 0 || !own_terminal {
+                return errno::ENOTTY;
+            }
             if !is_user_range(arg, core::mem::size_of::<i32>()) {
                 return errno::EFAULT;
             }
-            let bytes_available: i32 = match &entry.fd_type {
-                FdType::PipeRead { pipe } => pipe.available() as i32,
-                FdType::Console => {
-                    if crate::console::has_data() { 1 } else { 0 }
-                }
-                FdType::File { inode, offset, .. } => {
-                    let size = inode.0.size().unwrap_or(0);
-                    (size.saturating_sub(*offset)) as i32
-                }
-                FdType::Socket { socket_id } => {
-                    crate::net::socket::available(*socket_id) as i32
-                }
-                _ => 0,
-            };
             unsafe {
-                core::ptr::write(arg as *mut i32, bytes_available);
+                core::ptr::write(arg as *mut i32, session as i32);
             }
             0
         }
-        FIONBIO => {
-            // Set/clear non-blocking - stub (would need to modify fd flags)
-            0
+        TIOCSCTTY => {
+            // Só o líder de sessão pode adquirir um terminal de controle;
+            // root pode roubá-lo de outra sessão com arg == 1
+            if task.sid() != task.id() {
+                return errno::EPERM;
+            }
+            let steal = arg == 1 && current_cred().is_root();
+            match crate::drivers::tty::set_controlling_tty(tty, task.sid(), task.pgid(), steal) {
+                Ok(()) => 0,
+                Err(e) => e,
+            }
         }
-        FIOCLEX | FIONCLEX => {
-            // Set/clear close-on-exec - stub
+        TIOCNOTTY => {
+            if session == 0 || session != task.sid() {
+                return errno::ENOTTY;
+            }
+            if task.sid() == task.id() {
+                crate::drivers::tty::drop_controlling_tty(session);
+            }
             0
         }
+        _ => errno::ENOTTY,
+    }
+}
 
-        // Unknown ioctl - return success for compatibility
-        _ => {
-            // For unhandled ioctls, return success (many programs check for errors)
+/// prctl - operações sobre o processo atual (NO_NEW_PRIVS e seccomp)
+pub fn sys_prctl(option: i32, arg2: u64, arg3: u64, arg4: u64, arg5: u64) -> i64 {
+    use crate::security::seccomp;
+
+    const PR_SET_NO_NEW_PRIVS: i32 = 38;
+    const PR_GET_NO_NEW_PRIVS: i32 = 39;
+
+    let task = crate::sched::current_task();
+    match option {
+        PR_SET_NO_NEW_PRIVS => {
+            // Só pode ser ligado, nunca desligado
+            if arg2 != 1 || arg3 != 0 || arg4 != 0 || arg5 != 0 {
+                return errno::EINVAL;
+            }
+            task.set_no_new_privs();
             0
         }
+        PR_GET_NO_NEW_PRIVS => {
+            if arg2 != 0 || arg3 != 0 || arg4 != 0 || arg5 != 0 {
+                return errno::EINVAL;
+            }
+            task.no_new_privs() as i64
+        }
+        seccomp::PR_GET_SECCOMP => task.seccomp_state().mode() as i64,
+        seccomp::PR_SET_SECCOMP => match arg2 {
+            seccomp::SECCOMP_MODE_STRICT => seccomp::sys_seccomp(seccomp::SECCOMP_SET_MODE_STRICT, 0, 0),
+            seccomp::SECCOMP_MODE_FILTER => seccomp::sys_seccomp(seccomp::SECCOMP_SET_MODE_FILTER, 0, arg3),
+            _ => errno::EINVAL,
+        },
+        _ => errno::EINVAL,
     }
 }
 
@@ -4804,9 +6305,10 @@ unsafe fn read_user_string_array(ptr: u64, max_count: usize) -> Option<Vec<Strin
 /// Retorna 0 para o filho e o PID do filho para o pai.
 /// Precisa receber o SyscallFrame do processo para copiar o estado.
 pub fn sys_fork(sf: &crate::arch::x86_64_arch::syscall::SyscallFrame) -> i64 {
-    match crate::sched::fork_current_from_syscall(sf) {
+    match crate::sched::fork_current_from_syscall(sf, 0) {
         Ok(child_pid) => child_pid as i64,
         Err(KError::NoMemory) => errno::ENOMEM,
+        Err(KError::WouldBlock) => errno::EAGAIN,
         Err(KError::NotSupported) => errno::ENOSYS,
         Err(_) => errno::EIO,
     }
@@ -4967,6 +6469,7 @@ pub fn sys_socket(domain: i32, sock_type: i32, protocol: i32) -> i64 {
             fd as i64
         }
         Err(KError::NotSupported) => errno::EAFNOSUPPORT,
+        Err(KError::PermissionDenied) => errno::EACCES,
         Err(_) => errno::EIO,
     }
 }
@@ -5003,6 +6506,7 @@ pub fn sys_bind(sockfd: i32, addr: u64, addrlen: u32) -> i64 {
                 Ok(()) => 0,
                 Err(KError::AlreadyExists) => errno::EADDRINUSE,
                 Err(KError::Invalid) => errno::EINVAL,
+                Err(KError::PermissionDenied) => errno::EACCES,
                 Err(_) => errno::EIO,
             }
         }
@@ -5018,6 +6522,7 @@ pub fn sys_bind(sockfd: i32, addr: u64, addrlen: u32) -> i64 {
                 Ok(()) => 0,
                 Err(KError::AlreadyExists) => errno::EADDRINUSE,
                 Err(KError::Invalid) => errno::EINVAL,
+                Err(KError::PermissionDenied) => errno::EACCES,
                 Err(_) => errno::EIO,
             }
         }
@@ -5177,6 +6682,7 @@ pub fn sys_connect(sockfd: i32, addr: u64, addrlen: u32) -> i64 {
                 Err(KError::NotFound) => errno::ECONNREFUSED,
                 Err(KError::WouldBlock) => errno::EAGAIN,
                 Err(KError::Invalid) => errno::EINVAL,
+                Err(KError::PermissionDenied) => errno::EACCES,
                 Err(_) => errno::EIO,
             }
         }
@@ -5192,6 +6698,7 @@ pub fn sys_connect(sockfd: i32, addr: u64, addrlen: u32) -> i64 {
                 Ok(()) => 0,
                 Err(KError::NotSupported) => errno::ECONNREFUSED,
                 Err(KError::Invalid) => errno::EINVAL,
+                Err(KError::PermissionDenied) => errno::EACCES,
                 Err(_) => errno::EIO,
             }
         }
@@ -5424,6 +6931,35 @@ pub fn sys_setsockopt(sockfd: i32, level: i32, optname: i32, optval: u64, optlen
         }
     };
 
+    // SO_ATTACH_BPF recebe um fd de programa, que só existe aqui
+    if level == sock::SOL_SOCKET && optname == sock::SO_ATTACH_BPF {
+        let prog_fd = match unsafe { validate_user_buffer(optval, 4) } {
+            Some(bytes) if optlen >= 4 => i32::from_ne_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]),
+            Some(_) => return errno::EINVAL,
+            None => return errno::EFAULT,
+        };
+        let prog = match bpf_object_of(prog_fd) {
+            Ok(crate::profiling::ebpf::BpfObject::Prog(prog))
+                if prog.prog_type == crate::profiling::ebpf::BpfProgType::SocketFilter =>
+            {
+                prog
+            }
+            Ok(_) => return errno::EINVAL,
+            Err(e) => return e,
+        };
+        return match sock::attach_filter(socket_id, prog) {
+            Ok(()) => 0,
+            Err(_) => errno::EINVAL,
+        };
+    }
+    if level == sock::SOL_SOCKET && optname == sock::SO_DETACH_BPF {
+        return match sock::detach_filter(socket_id) {
+            Ok(()) => 0,
+            Err(KError::NotFound) => errno::ENOENT,
+            Err(_) => errno::EINVAL,
+        };
+    }
+
     let optval_slice = unsafe { core::slice::from_raw_parts(optval as *const u8, optlen as usize) };
     match sock::setsockopt(socket_id, level, optname, optval_slice) {
         Ok(()) => 0,
@@ -5500,9 +7036,16 @@ pub fn sys_execve(pathname: u64, argv_ptr: u64, envp_ptr: u64) -> i64 {
     // Get file metadata first and check execute permission
     let cred = current_cred();
     let vfs = fs::vfs_lock();
-    let exec_metadata = match vfs.resolve(&path, &cred) {
+    let exec_label;
+    let (exec_metadata, exec_mnt_flags) = match vfs.resolve(&path, &cred) {
         Ok(inode) => {
             let meta = inode.metadata();
+            let mnt_flags = inode.0.mount_flags();
+            if mnt_flags.contains(fs::MountFlags::NOEXEC) {
+                drop(vfs);
+                crate::kprintln!("execve: filesystem mounted noexec: {}", path);
+                return errno::EACCES;
+            }
             // Check if it's a regular file
             if meta.kind != InodeKind::File {
                 drop(vfs);
@@ -5514,7 +7057,16 @@ pub fn sys_execve(pathname: u64, argv_ptr: u64, envp_ptr: u64) -> i64 {
                 crate::kprintln!("execve: permission denied (no execute): {}", path);
                 return errno::EACCES;
             }
-            Some(meta)
+            // MAC: execute permission and domain transition
+            match crate::security::hooks::bprm_check(&inode, &path) {
+                Ok(label) => exec_label = label,
+                Err(_) => {
+                    drop(vfs);
+                    crate::kprintln!("execve: denied by MAC policy: {}", path);
+                    return errno::EACCES;
+                }
+            }
+            (Some(meta), mnt_flags)
         }
         Err(KError::NotFound) => {
             drop(vfs);
@@ -5561,11 +7113,14 @@ pub fn sys_execve(pathname: u64, argv_ptr: u64, envp_ptr: u64) -> i64 {
 
     // Handle setuid/setgid bits
     // When executing a setuid/setgid program, update effective UID/GID
+    // (ignored on MS_NOSUID mounts and under NO_NEW_PRIVS)
     if let Some(meta) = exec_metadata {
         let mut cred = current_cred();
+        let honor_suid = !exec_mnt_flags.contains(fs::MountFlags::NOSUID)
+            && !crate::sched::current_task().no_new_privs();
 
         // Check for setuid bit
-        if meta.mode.is_setuid() {
+        if honor_suid && meta.mode.is_setuid() {
             crate::kprintln!("execve: setuid binary, setting euid to {}", meta.uid.0);
             cred.euid = crate::security::Uid(meta.uid.0);
             cred.suid = cred.euid; // Save for later restoration
@@ -5581,7 +7136,7 @@ pub fn sys_execve(pathname: u64, argv_ptr: u64, envp_ptr: u64) -> i64 {
         }
 
         // Check for setgid bit
-        if meta.mode.is_setgid() {
+        if honor_suid && meta.mode.is_setgid() {
             crate::kprintln!("execve: setgid binary, setting egid to {}", meta.gid.0);
             cred.egid = crate::security::Gid(meta.gid.0);
             cred.sgid = cred.egid;
@@ -5593,9 +7148,18 @@ pub fn sys_execve(pathname: u64, argv_ptr: u64, envp_ptr: u64) -> i64 {
         crate::task::set_current_cred(cred);
     }
 
+    if let Some(label) = exec_label {
+        crate::security::hooks::bprm_committed(label);
+    }
+
+    // Tracepoint sched:sched_process_exec: pid (u32) seguido do caminho
+    let mut record = (crate::sched::current_task().id() as u32).to_le_bytes().to_vec();
+    record.extend_from_slice(path.as_bytes());
+    crate::profiling::ftrace::trace_event("sched", "sched_process_exec", record);
+
     // Agora precisamos substituir o address space atual e saltar para o novo código
     // Isso é feito através do scheduler
-    crate::sched::exec_replace(loaded.cr3, loaded.entry, loaded.stack_pointer);
+    crate::sched::exec_replace(loaded.cr3, loaded.entry, loaded.stack_pointer, loaded.brk, loaded.vdso);
 }
 
 // ==================== Mknod syscall ====================
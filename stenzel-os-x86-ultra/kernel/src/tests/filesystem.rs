@@ -1,12 +1,35 @@
 //! Filesystem tests
 
 use super::{TestRunner, TestResult};
-use crate::{test_assert, test_assert_eq, test_assert_ne};
+use crate::{test_assert, test_assert_eq, test_assert_ne, test_assert_ok};
 
 pub fn register_tests(runner: &mut TestRunner) {
     runner.add_test("fs::path_normalization", test_path_normalization, "filesystem");
     runner.add_test("fs::path_components", test_path_components, "filesystem");
     runner.add_test("fs::tmpfs_operations", test_tmpfs_operations, "filesystem");
+    runner.add_test("fs::ext4_write_read", test_ext4_write_read, "filesystem");
+    runner.add_test("fs::ext4_create_unlink", test_ext4_create_unlink, "filesystem");
+    runner.add_test("fs::ext4_rename_link", test_ext4_rename_link, "filesystem");
+    runner.add_test("fs::ext4_truncate", test_ext4_truncate, "filesystem");
+    runner.add_test("fs::ext4_remount", test_ext4_remount, "filesystem");
+    runner.add_test("fs::ext4_journal_replay", test_ext4_journal_replay, "filesystem");
+    runner.add_test("fs::ext4_read_only", test_ext4_read_only, "filesystem");
+    runner.add_test("fs::fat32_write_read", test_fat32_write_read, "filesystem");
+    runner.add_test("fs::fat32_long_names", test_fat32_long_names, "filesystem");
+    runner.add_test("fs::fat32_truncate_rename", test_fat32_truncate_rename, "filesystem");
+    runner.add_test("fs::fat32_remount", test_fat32_remount, "filesystem");
+    runner.add_test("fs::exfat_write_read", test_exfat_write_read, "filesystem");
+    runner.add_test("fs::exfat_names", test_exfat_names, "filesystem");
+    runner.add_test("fs::exfat_truncate_rename", test_exfat_truncate_rename, "filesystem");
+    runner.add_test("fs::exfat_remount", test_exfat_remount, "filesystem");
+    runner.add_test("fs::mount_flags", test_mount_flags, "filesystem");
+    runner.add_test("fs::umount_busy", test_umount_busy, "filesystem");
+    runner.add_test("fs::ext4_statfs", test_ext4_statfs, "filesystem");
+    runner.add_test("fs::mount_ns_copy", test_mount_ns_copy, "filesystem");
+    runner.add_test("fs::pivot_root", test_pivot_root, "filesystem");
+    runner.add_test("fs::chroot_confined", test_chroot_confined, "filesystem");
+    runner.add_test("fs::inotify_events", test_inotify_events, "filesystem");
+    runner.add_test("fs::mac_hooks", test_mac_hooks, "filesystem");
 }
 
 fn test_path_normalization() -> TestResult {
@@ -52,6 +75,1429 @@ fn test_tmpfs_operations() -> TestResult {
     TestResult::Pass
 }
 
+// ============================================================================
+// ext4 (on a ramdisk)
+// ============================================================================
+
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+
+use crate::fs::ext4::Ext4Fs;
+use crate::fs::vfs::{Inode, InodeKind, Metadata, Mode};
+use crate::security::{Gid, Uid};
+use crate::storage::ramdisk::RamDisk;
+use crate::storage::BlockDevice;
+use crate::util::KError;
+
+// Test image layout (1 KiB blocks, a single block group)
+const EXT4_TEST_BLOCKS: u32 = 4096;
+const EXT4_TEST_INODES: u32 = 256;
+const EXT4_TEST_ITABLE: u32 = 5;
+const EXT4_TEST_ROOT_BLOCK: u32 = 69;
+const EXT4_TEST_JOURNAL: u32 = 70;
+const EXT4_TEST_JOURNAL_LEN: u32 = 1024;
+const EXT4_TEST_LPF_BLOCK: u32 = EXT4_TEST_JOURNAL + EXT4_TEST_JOURNAL_LEN;
+
+fn put16(buf: &mut [u8], off: usize, v: u16) {
+    buf[off..off + 2].copy_from_slice(&v.to_le_bytes());
+}
+
+fn put32(buf: &mut [u8], off: usize, v: u32) {
+    buf[off..off + 4].copy_from_slice(&v.to_le_bytes());
+}
+
+fn set_bits(bitmap: &mut [u8], range: core::ops::Range<u32>) {
+    for bit in range {
+        bitmap[(bit / 8) as usize] |= 1 << (bit % 8);
+    }
+}
+
+/// Write an extent-mapped inode with a single extent into the inode table
+fn put_test_inode(img: &mut [u8], ino: u32, mode: u16, links: u16, size: u32, start: u32, len: u32) {
+    let off = EXT4_TEST_ITABLE as usize * 1024 + (ino as usize - 1) * 256;
+    let inode = &mut img[off..off + 256];
+    put16(inode, 0x00, mode);
+    put32(inode, 0x04, size);
+    put16(inode, 0x1A, links);
+    put32(inode, 0x1C, len * 2);
+    put32(inode, 0x20, 0x80000); // EXTENTS_FL
+    // Extent header + one extent in i_block
+    put16(inode, 0x28, 0xF30A);
+    put16(inode, 0x2A, 1);
+    put16(inode, 0x2C, 4);
+    put32(inode, 0x34, 0);
+    put16(inode, 0x38, len as u16);
+    put32(inode, 0x3C, start);
+    put16(inode, 0x80, 32); // i_extra_isize
+}
+
+/// Write a directory block holding "." and ".." (and optionally one more entry)
+fn put_test_dir(img: &mut [u8], block: u32, ino: u32, parent: u32, extra: Option<(u32, &str)>) {
+    let off = block as usize * 1024;
+    let dir = &mut img[off..off + 1024];
+    put32(dir, 0, ino);
+    put16(dir, 4, 12);
+    dir[6] = 1;
+    dir[7] = 2;
+    dir[8] = b'.';
+    put32(dir, 12, parent);
+    dir[18] = 2;
+    dir[19] = 2;
+    dir[20..22].copy_from_slice(b"..");
+    match extra {
+        Some((child, name)) => {
+            put16(dir, 16, 12);
+            put32(dir, 24, child);
+            put16(dir, 28, 1024 - 24);
+            dir[30] = name.len() as u8;
+            dir[31] = 2;
+            dir[32..32 + name.len()].copy_from_slice(name.as_bytes());
+        }
+        None => put16(dir, 16, 1024 - 12),
+    }
+}
+
+/// Build a tiny journaled ext4 filesystem (extents, no checksums) on a ramdisk
+fn ext4_test_device() -> Arc<dyn BlockDevice> {
+    let mut img = vec![0u8; EXT4_TEST_BLOCKS as usize * 1024];
+    let used_blocks = EXT4_TEST_LPF_BLOCK; // blocks 1..=LPF_BLOCK
+    let used_inodes = 11;
+
+    // Superblock
+    {
+        let sb = &mut img[1024..2048];
+        put32(sb, 0x00, EXT4_TEST_INODES);
+        put32(sb, 0x04, EXT4_TEST_BLOCKS);
+        put32(sb, 0x0C, EXT4_TEST_BLOCKS - 1 - used_blocks);
+        put32(sb, 0x10, EXT4_TEST_INODES - used_inodes);
+        put32(sb, 0x14, 1); // s_first_data_block
+        put32(sb, 0x20, 8192); // s_blocks_per_group
+        put32(sb, 0x24, 8192); // s_clusters_per_group
+        put32(sb, 0x28, EXT4_TEST_INODES);
+        put16(sb, 0x38, 0xEF53);
+        put16(sb, 0x3A, 1); // clean
+        put16(sb, 0x3C, 1);
+        put32(sb, 0x4C, 1); // dynamic revision
+        put32(sb, 0x54, 11); // s_first_ino
+        put16(sb, 0x58, 256); // s_inode_size
+        put32(sb, 0x5C, 0x0004); // has_journal
+        put32(sb, 0x60, 0x0042); // filetype | extents
+        put32(sb, 0x64, 0x0043); // sparse_super | large_file | extra_isize
+        sb[0x68..0x78].copy_from_slice(b"stenzel-ext4-tst");
+        put32(sb, 0xE0, 8); // s_journal_inum
+        put16(sb, 0x15C, 32);
+        put16(sb, 0x15E, 32);
+    }
+
+    // Group descriptor
+    {
+        let gd = &mut img[2048..2048 + 32];
+        put32(gd, 0x00, 3);
+        put32(gd, 0x04, 4);
+        put32(gd, 0x08, EXT4_TEST_ITABLE);
+        put16(gd, 0x0C, (EXT4_TEST_BLOCKS - 1 - used_blocks) as u16);
+        put16(gd, 0x0E, (EXT4_TEST_INODES - used_inodes) as u16);
+        put16(gd, 0x10, 2);
+    }
+
+    // Bitmaps (bit 0 is block 1)
+    set_bits(&mut img[3 * 1024..4 * 1024], 0..used_blocks);
+    set_bits(&mut img[3 * 1024..4 * 1024], EXT4_TEST_BLOCKS - 1..8192);
+    set_bits(&mut img[4 * 1024..5 * 1024], 0..used_inodes);
+    set_bits(&mut img[4 * 1024..5 * 1024], EXT4_TEST_INODES..8192);
+
+    // Root, journal and lost+found
+    put_test_inode(&mut img, 2, 0x41ED, 3, 1024, EXT4_TEST_ROOT_BLOCK, 1);
+    put_test_inode(&mut img, 8, 0x8180, 1, EXT4_TEST_JOURNAL_LEN * 1024, EXT4_TEST_JOURNAL, EXT4_TEST_JOURNAL_LEN);
+    put_test_inode(&mut img, 11, 0x41C0, 2, 1024, EXT4_TEST_LPF_BLOCK, 1);
+    put_test_dir(&mut img, EXT4_TEST_ROOT_BLOCK, 2, 2, Some((11, "lost+found")));
+    put_test_dir(&mut img, EXT4_TEST_LPF_BLOCK, 11, 2, None);
+
+    // jbd2 superblock (v2, empty log)
+    {
+        let off = EXT4_TEST_JOURNAL as usize * 1024;
+        let jsb = &mut img[off..off + 1024];
+        jsb[0x00..0x04].copy_from_slice(&0xC03B3998u32.to_be_bytes());
+        jsb[0x04..0x08].copy_from_slice(&4u32.to_be_bytes());
+        jsb[0x0C..0x10].copy_from_slice(&1024u32.to_be_bytes());
+        jsb[0x10..0x14].copy_from_slice(&EXT4_TEST_JOURNAL_LEN.to_be_bytes());
+        jsb[0x14..0x18].copy_from_slice(&1u32.to_be_bytes());
+        jsb[0x18..0x1C].copy_from_slice(&1u32.to_be_bytes());
+        jsb[0x40..0x44].copy_from_slice(&1u32.to_be_bytes());
+    }
+
+    let disk = RamDisk::new(0xE4, 512, (img.len() / 512) as u64);
+    let _ = disk.write_blocks(0, (img.len() / 512) as u32, &img);
+    Arc::new(disk)
+}
+
+fn ext4_test_mount() -> Option<(Arc<dyn BlockDevice>, Inode)> {
+    let dev = ext4_test_device();
+    let fs = Ext4Fs::mount(Arc::clone(&dev)).ok()?;
+    if !fs.is_writable() {
+        return None;
+    }
+    Some((dev, fs.root()))
+}
+
+fn ext4_meta(kind: InodeKind) -> Metadata {
+    Metadata::simple(Uid(0), Gid(0), Mode::from_octal(0o644), kind)
+}
+
+fn test_pattern(len: usize, seed: u8) -> Vec<u8> {
+    (0..len).map(|i| (i as u8).wrapping_mul(31).wrapping_add(seed)).collect()
+}
+
+fn test_ext4_write_read() -> TestResult {
+    let (_dev, root) = match ext4_test_mount() {
+        Some(m) => m,
+        None => return TestResult::Fail,
+    };
+
+    let file = match root.0.create("data.bin", InodeKind::File, ext4_meta(InodeKind::File)) {
+        Ok(f) => f,
+        Err(_) => return TestResult::Fail,
+    };
+
+    // Spans many blocks, with unaligned start and end
+    let data = test_pattern(40_000, 7);
+    test_assert_eq!(file.0.write_at(100, &data).ok(), Some(data.len()));
+    test_assert_eq!(file.0.size().ok(), Some(40_100));
+
+    let mut out = vec![0u8; 40_100];
+    test_assert_eq!(file.0.read_at(0, &mut out).ok(), Some(40_100));
+    test_assert!(out[..100].iter().all(|&b| b == 0));
+    test_assert!(out[100..] == data[..]);
+
+    // Overwrite in the middle
+    test_assert_eq!(file.0.write_at(5000, b"stenzel").ok(), Some(7));
+    let mut small = [0u8; 7];
+    test_assert_eq!(file.0.read_at(5000, &mut small).ok(), Some(7));
+    test_assert_eq!(&small, b"stenzel");
+    test_assert_eq!(file.0.size().ok(), Some(40_100));
+
+    // Sparse write far past the end leaves a readable hole
+    test_assert_eq!(file.0.write_at(200_000, b"end").ok(), Some(3));
+    let mut hole = [0xFFu8; 16];
+    test_assert_eq!(file.0.read_at(100_000, &mut hole).ok(), Some(16));
+    test_assert!(hole.iter().all(|&b| b == 0));
+
+    TestResult::Pass
+}
+
+fn test_ext4_create_unlink() -> TestResult {
+    let (_dev, root) = match ext4_test_mount() {
+        Some(m) => m,
+        None => return TestResult::Fail,
+    };
+
+    let dir = match root.0.create("dir", InodeKind::Dir, ext4_meta(InodeKind::Dir)) {
+        Ok(d) => d,
+        Err(_) => return TestResult::Fail,
+    };
+    test_assert_eq!(dir.metadata().nlink, 2);
+    test_assert!(root.0.create("dir", InodeKind::Dir, ext4_meta(InodeKind::Dir)).is_err());
+
+    // Enough entries to grow the directory past one block
+    for i in 0..64 {
+        let name = alloc::format!("entry-with-a-long-name-{:03}", i);
+        test_assert_ok!(dir.0.create(&name, InodeKind::File, ext4_meta(InodeKind::File)));
+    }
+    test_assert_eq!(dir.0.readdir().map(|e| e.len()).ok(), Some(64));
+    test_assert!(dir.0.size().unwrap_or(0) > 1024);
+
+    test_assert_eq!(root.0.rmdir("dir").err(), Some(KError::NotEmpty));
+    for i in 0..64 {
+        let name = alloc::format!("entry-with-a-long-name-{:03}", i);
+        test_assert_ok!(dir.0.unlink(&name));
+    }
+    test_assert_eq!(dir.0.readdir().map(|e| e.len()).ok(), Some(0));
+    test_assert!(dir.0.lookup("entry-with-a-long-name-000").is_err());
+
+    test_assert_eq!(root.0.unlink("dir").err(), Some(KError::Invalid));
+    test_assert_ok!(root.0.rmdir("dir"));
+    test_assert!(root.0.lookup("dir").is_err());
+
+    TestResult::Pass
+}
+
+fn test_ext4_rename_link() -> TestResult {
+    let (_dev, root) = match ext4_test_mount() {
+        Some(m) => m,
+        None => return TestResult::Fail,
+    };
+
+    let (a, b) = match (
+        root.0.create("a", InodeKind::Dir, ext4_meta(InodeKind::Dir)),
+        root.0.create("b", InodeKind::Dir, ext4_meta(InodeKind::Dir)),
+    ) {
+        (Ok(a), Ok(b)) => (a, b),
+        _ => return TestResult::Fail,
+    };
+    let file = match a.0.create("file", InodeKind::File, ext4_meta(InodeKind::File)) {
+        Ok(f) => f,
+        Err(_) => return TestResult::Fail,
+    };
+    test_assert_ok!(file.0.write_at(0, b"content"));
+
+    // Hard link
+    test_assert_ok!(b.0.link("alias", file.clone()));
+    test_assert_eq!(file.metadata().nlink, 2);
+
+    // Rename across directories
+    test_assert_ok!(a.0.rename_to("file", &b, "renamed"));
+    test_assert!(a.0.lookup("file").is_err());
+    let renamed = match b.0.lookup("renamed") {
+        Ok(f) => f,
+        Err(_) => return TestResult::Fail,
+    };
+    let mut buf = [0u8; 7];
+    test_assert_eq!(renamed.0.read_at(0, &mut buf).ok(), Some(7));
+    test_assert_eq!(&buf, b"content");
+
+    // Moving a directory updates ".." and both parents' link counts
+    test_assert_ok!(root.0.rename_to("a", &b, "a"));
+    test_assert_eq!(b.metadata().nlink, 3);
+    test_assert_eq!(root.metadata().nlink, 4); // ".", "..", lost+found, b
+
+    // Dropping one name keeps the inode alive
+    test_assert_ok!(b.0.unlink("alias"));
+    test_assert_eq!(renamed.metadata().nlink, 1);
+    test_assert_eq!(renamed.0.read_at(0, &mut buf).ok(), Some(7));
+
+    TestResult::Pass
+}
+
+fn test_ext4_truncate() -> TestResult {
+    let (_dev, root) = match ext4_test_mount() {
+        Some(m) => m,
+        None => return TestResult::Fail,
+    };
+
+    let file = match root.0.create("t", InodeKind::File, ext4_meta(InodeKind::File)) {
+        Ok(f) => f,
+        Err(_) => return TestResult::Fail,
+    };
+    let data = test_pattern(20_000, 3);
+    test_assert_ok!(file.0.write_at(0, &data));
+
+    test_assert_ok!(file.0.truncate(1500));
+    test_assert_eq!(file.0.size().ok(), Some(1500));
+
+    // Growing again must expose zeros, not the old data
+    test_assert_ok!(file.0.truncate(8000));
+    let mut out = vec![0u8; 8000];
+    test_assert_eq!(file.0.read_at(0, &mut out).ok(), Some(8000));
+    test_assert!(out[..1500] == data[..1500]);
+    test_assert!(out[1500..].iter().all(|&b| b == 0));
+
+    test_assert_ok!(file.0.truncate(0));
+    test_assert_eq!(file.0.size().ok(), Some(0));
+
+    TestResult::Pass
+}
+
+fn test_ext4_remount() -> TestResult {
+    let (dev, root) = match ext4_test_mount() {
+        Some(m) => m,
+        None => return TestResult::Fail,
+    };
+
+    let data = test_pattern(10_000, 11);
+    let dir = match root.0.create("keep", InodeKind::Dir, ext4_meta(InodeKind::Dir)) {
+        Ok(d) => d,
+        Err(_) => return TestResult::Fail,
+    };
+    match dir.0.create("file", InodeKind::File, ext4_meta(InodeKind::File)) {
+        Ok(f) => test_assert_ok!(f.0.write_at(0, &data)),
+        Err(_) => return TestResult::Fail,
+    }
+    test_assert_ok!(root.0.symlink("link", "keep/file", ext4_meta(InodeKind::Symlink)));
+    drop(dir);
+    drop(root);
+
+    // Everything must be on the device, not only in memory
+    let root = match Ext4Fs::mount(dev) {
+        Ok(fs) => fs.root(),
+        Err(_) => return TestResult::Fail,
+    };
+    let file = match root.0.lookup("keep").and_then(|d| d.0.lookup("file")) {
+        Ok(f) => f,
+        Err(_) => return TestResult::Fail,
+    };
+    let mut out = vec![0u8; data.len()];
+    test_assert_eq!(file.0.read_at(0, &mut out).ok(), Some(data.len()));
+    test_assert!(out == data);
+    test_assert_eq!(
+        root.0.lookup("link").and_then(|l| l.0.readlink()).ok().as_deref(),
+        Some("keep/file")
+    );
+
+    TestResult::Pass
+}
+
+fn test_ext4_journal_replay() -> TestResult {
+    let dev = ext4_test_device();
+
+    // Commit a transaction to the journal but "crash" before checkpointing it
+    let journal_blocks: Vec<u64> = (EXT4_TEST_JOURNAL as u64..EXT4_TEST_LPF_BLOCK as u64).collect();
+    let journal = match crate::fs::jbd2::Journal::load(Arc::clone(&dev), 1024, journal_blocks) {
+        Ok(j) => j,
+        Err(_) => return TestResult::Fail,
+    };
+
+    // The first block starts with the jbd2 magic and has to be escaped
+    let mut escaped = test_pattern(1024, 5);
+    escaped[..4].copy_from_slice(&0xC03B3998u32.to_be_bytes());
+    let plain = test_pattern(1024, 9);
+    test_assert_ok!(journal.commit(&[(2000, &escaped[..]), (2001, &plain[..])]));
+    test_assert!(journal.needs_recovery());
+    drop(journal);
+
+    let mut raw = vec![0u8; 1024];
+    test_assert_ok!(dev.read_blocks(4000, 2, &mut raw));
+    test_assert!(raw.iter().all(|&b| b == 0));
+
+    // Mounting replays the journal
+    test_assert_ok!(Ext4Fs::mount(Arc::clone(&dev)));
+    test_assert_ok!(dev.read_blocks(4000, 2, &mut raw));
+    test_assert!(raw == escaped);
+    test_assert_ok!(dev.read_blocks(4002, 2, &mut raw));
+    test_assert!(raw == plain);
+
+    let journal_blocks: Vec<u64> = (EXT4_TEST_JOURNAL as u64..EXT4_TEST_LPF_BLOCK as u64).collect();
+    match crate::fs::jbd2::Journal::load(dev, 1024, journal_blocks) {
+        Ok(j) => test_assert!(!j.needs_recovery()),
+        Err(_) => return TestResult::Fail,
+    }
+
+    TestResult::Pass
+}
+
+fn test_ext4_read_only() -> TestResult {
+    let (dev, root) = match ext4_test_mount() {
+        Some(m) => m,
+        None => return TestResult::Fail,
+    };
+    let data = test_pattern(3000, 3);
+    match root.0.create("file", InodeKind::File, ext4_meta(InodeKind::File)) {
+        Ok(f) => test_assert_ok!(f.0.write_at(0, &data)),
+        Err(_) => return TestResult::Fail,
+    }
+    drop(root);
+
+    // An unknown ro_compat feature forces a read-only mount
+    let mut sb = vec![0u8; 1024];
+    test_assert_ok!(dev.read_blocks(2, 2, &mut sb));
+    let ro_compat = u32::from_le_bytes([sb[0x64], sb[0x65], sb[0x66], sb[0x67]]);
+    put32(&mut sb, 0x64, ro_compat | 0x8000_0000);
+    test_assert_ok!(dev.write_blocks(2, 2, &sb));
+
+    let fs = match Ext4Fs::mount(dev) {
+        Ok(fs) => fs,
+        Err(_) => return TestResult::Fail,
+    };
+    test_assert!(!fs.is_writable());
+    let root = fs.root();
+    let file = match root.0.lookup("file") {
+        Ok(f) => f,
+        Err(_) => return TestResult::Fail,
+    };
+    test_assert_eq!(file.0.write_at(0, b"x").err(), Some(KError::ReadOnly));
+    test_assert_eq!(root.0.unlink("file").err(), Some(KError::ReadOnly));
+    test_assert_eq!(
+        root.0.create("new", InodeKind::File, ext4_meta(InodeKind::File)).err(),
+        Some(KError::ReadOnly)
+    );
+
+    let mut out = vec![0u8; data.len()];
+    test_assert_eq!(file.0.read_at(0, &mut out).ok(), Some(data.len()));
+    test_assert!(out == data);
+
+    TestResult::Pass
+}
+
+// ============================================================================
+// FAT32 (on a ramdisk)
+// ============================================================================
+
+use crate::fs::fat32::Fat32Fs;
+
+// Test image layout (512-byte sectors, one sector per cluster)
+const FAT32_TEST_SECTORS: u32 = 8192;
+const FAT32_TEST_RESERVED: u32 = 32;
+const FAT32_TEST_FAT_SIZE: u32 = 64;
+const FAT32_TEST_CLUSTERS: u32 = FAT32_TEST_SECTORS - FAT32_TEST_RESERVED - 2 * FAT32_TEST_FAT_SIZE;
+
+/// Build an empty FAT32 filesystem (two FATs, FSInfo, root at cluster 2) on a ramdisk
+fn fat32_test_device() -> Arc<dyn BlockDevice> {
+    let mut img = vec![0u8; FAT32_TEST_SECTORS as usize * 512];
+
+    // Boot sector
+    {
+        let bs = &mut img[0..512];
+        bs[0..3].copy_from_slice(&[0xEB, 0x58, 0x90]);
+        bs[3..11].copy_from_slice(b"STENZEL ");
+        put16(bs, 11, 512);
+        bs[13] = 1; // sectors per cluster
+        put16(bs, 14, FAT32_TEST_RESERVED as u16);
+        bs[16] = 2; // FAT copies
+        bs[21] = 0xF8;
+        put32(bs, 32, FAT32_TEST_SECTORS);
+        put32(bs, 36, FAT32_TEST_FAT_SIZE);
+        put32(bs, 44, 2); // root cluster
+        put16(bs, 48, 1); // FSInfo sector
+        put16(bs, 50, 6); // backup boot sector
+        bs[64] = 0x80;
+        bs[66] = 0x29;
+        put32(bs, 67, 0x5354_4E5A);
+        bs[71..82].copy_from_slice(b"STENZEL    ");
+        bs[82..90].copy_from_slice(b"FAT32   ");
+        put16(bs, 510, 0xAA55);
+    }
+    let boot = img[0..512].to_vec();
+    img[6 * 512..7 * 512].copy_from_slice(&boot);
+
+    // FSInfo (the root directory uses one cluster)
+    {
+        let fsi = &mut img[512..1024];
+        put32(fsi, 0, 0x4161_5252);
+        put32(fsi, 484, 0x6141_7272);
+        put32(fsi, 488, FAT32_TEST_CLUSTERS - 1);
+        put32(fsi, 492, 3);
+        put32(fsi, 508, 0xAA55_0000);
+    }
+
+    // Both FATs: media entry, reserved entry, end of the root chain
+    for fat in 0..2 {
+        let off = (FAT32_TEST_RESERVED + fat * FAT32_TEST_FAT_SIZE) as usize * 512;
+        put32(&mut img[off..], 0, 0x0FFF_FFF8);
+        put32(&mut img[off..], 4, 0x0FFF_FFFF);
+        put32(&mut img[off..], 8, 0x0FFF_FFFF);
+    }
+
+    let disk = RamDisk::new(0xFA, 512, FAT32_TEST_SECTORS as u64);
+    let _ = disk.write_blocks(0, FAT32_TEST_SECTORS, &img);
+    Arc::new(disk)
+}
+
+fn fat32_test_mount() -> Option<(Arc<dyn BlockDevice>, Inode)> {
+    let dev = fat32_test_device();
+    let fs = Fat32Fs::mount(Arc::clone(&dev)).ok()?;
+    Some((dev, fs.root()))
+}
+
+/// Free-cluster count stored in the FSInfo sector
+fn fat32_fsinfo_free(dev: &Arc<dyn BlockDevice>) -> u32 {
+    let mut sector = [0u8; 512];
+    let _ = dev.read_blocks(1, 1, &mut sector);
+    u32::from_le_bytes([sector[488], sector[489], sector[490], sector[491]])
+}
+
+fn test_fat32_write_read() -> TestResult {
+    let (dev, root) = match fat32_test_mount() {
+        Some(m) => m,
+        None => return TestResult::Fail,
+    };
+
+    let file = match root.0.create("DATA.BIN", InodeKind::File, ext4_meta(InodeKind::File)) {
+        Ok(f) => f,
+        Err(_) => return TestResult::Fail,
+    };
+
+    // Spans many clusters, with unaligned start and end
+    let data = test_pattern(40_000, 7);
+    test_assert_eq!(file.0.write_at(100, &data).ok(), Some(data.len()));
+    test_assert_eq!(file.0.size().ok(), Some(40_100));
+
+    let mut out = vec![0u8; 40_100];
+    test_assert_eq!(file.0.read_at(0, &mut out).ok(), Some(40_100));
+    test_assert!(out[..100].iter().all(|&b| b == 0));
+    test_assert!(out[100..] == data[..]);
+
+    // Overwrite in the middle
+    test_assert_eq!(file.0.write_at(5000, b"stenzel").ok(), Some(7));
+    let mut small = [0u8; 7];
+    test_assert_eq!(file.0.read_at(5000, &mut small).ok(), Some(7));
+    test_assert_eq!(&small, b"stenzel");
+
+    // Writing past the end fills the gap with zeros
+    test_assert_eq!(file.0.write_at(60_000, b"end").ok(), Some(3));
+    let mut gap = [0xFFu8; 16];
+    test_assert_eq!(file.0.read_at(50_000, &mut gap).ok(), Some(16));
+    test_assert!(gap.iter().all(|&b| b == 0));
+
+    // 118 data clusters plus the root directory are in use
+    test_assert_eq!(fat32_fsinfo_free(&dev), FAT32_TEST_CLUSTERS - 1 - 118);
+
+    TestResult::Pass
+}
+
+fn test_fat32_long_names() -> TestResult {
+    let (_dev, root) = match fat32_test_mount() {
+        Some(m) => m,
+        None => return TestResult::Fail,
+    };
+
+    let dir = match root.0.create("Documents", InodeKind::Dir, ext4_meta(InodeKind::Dir)) {
+        Ok(d) => d,
+        Err(_) => return TestResult::Fail,
+    };
+
+    // Long, mixed-case and lowercase 8.3 names are all preserved
+    let names = ["A file with a rather long name.text", "readme.txt", "MixedCase.Txt", "Ünïcode ✓.md"];
+    for name in names {
+        test_assert_ok!(dir.0.create(name, InodeKind::File, ext4_meta(InodeKind::File)));
+    }
+    // Enough entries to grow the directory past one cluster
+    for i in 0..40 {
+        let name = alloc::format!("entry-with-a-long-name-{:03}", i);
+        test_assert_ok!(dir.0.create(&name, InodeKind::File, ext4_meta(InodeKind::File)));
+    }
+
+    let entries = match dir.0.readdir() {
+        Ok(e) => e,
+        Err(_) => return TestResult::Fail,
+    };
+    test_assert_eq!(entries.len(), 44);
+    for name in names {
+        test_assert!(entries.iter().any(|e| e.name == name));
+    }
+
+    // Lookups ignore case, duplicates and invalid names are rejected
+    test_assert_ok!(dir.0.lookup("README.TXT"));
+    test_assert_ok!(dir.0.lookup("a FILE with a rather long name.TEXT"));
+    test_assert_eq!(
+        dir.0.create("ReadMe.txt", InodeKind::File, ext4_meta(InodeKind::File)).err(),
+        Some(KError::AlreadyExists)
+    );
+    test_assert_eq!(
+        dir.0.create("bad:name", InodeKind::File, ext4_meta(InodeKind::File)).err(),
+        Some(KError::Invalid)
+    );
+
+    test_assert_eq!(root.0.rmdir("Documents").err(), Some(KError::NotEmpty));
+    for entry in &entries {
+        test_assert_ok!(dir.0.unlink(&entry.name));
+    }
+    test_assert_eq!(dir.0.readdir().map(|e| e.len()).ok(), Some(0));
+    test_assert_eq!(root.0.unlink("Documents").err(), Some(KError::Invalid));
+    test_assert_ok!(root.0.rmdir("Documents"));
+    test_assert!(root.0.lookup("Documents").is_err());
+
+    TestResult::Pass
+}
+
+fn test_fat32_truncate_rename() -> TestResult {
+    let (dev, root) = match fat32_test_mount() {
+        Some(m) => m,
+        None => return TestResult::Fail,
+    };
+    let free_before = fat32_fsinfo_free(&dev);
+
+    let (a, b) = match (
+        root.0.create("a", InodeKind::Dir, ext4_meta(InodeKind::Dir)),
+        root.0.create("b", InodeKind::Dir, ext4_meta(InodeKind::Dir)),
+    ) {
+        (Ok(a), Ok(b)) => (a, b),
+        _ => return TestResult::Fail,
+    };
+    let file = match a.0.create("file.dat", InodeKind::File, ext4_meta(InodeKind::File)) {
+        Ok(f) => f,
+        Err(_) => return TestResult::Fail,
+    };
+    let data = test_pattern(20_000, 3);
+    test_assert_ok!(file.0.write_at(0, &data));
+
+    test_assert_ok!(file.0.truncate(1500));
+    test_assert_eq!(file.0.size().ok(), Some(1500));
+
+    // Growing again must expose zeros, not the old data
+    test_assert_ok!(file.0.truncate(8000));
+    let mut out = vec![0u8; 8000];
+    test_assert_eq!(file.0.read_at(0, &mut out).ok(), Some(8000));
+    test_assert!(out[..1500] == data[..1500]);
+    test_assert!(out[1500..].iter().all(|&b| b == 0));
+
+    // Rename across directories keeps the open handle working
+    test_assert_ok!(a.0.rename_to("file.dat", &b, "Renamed File.dat"));
+    test_assert!(a.0.lookup("file.dat").is_err());
+    test_assert_ok!(b.0.lookup("renamed file.DAT"));
+    test_assert_ok!(file.0.write_at(0, b"head"));
+    let mut head = [0u8; 4];
+    test_assert_eq!(b.0.lookup("Renamed File.dat").and_then(|f| f.0.read_at(0, &mut head)).ok(), Some(4));
+    test_assert_eq!(&head, b"head");
+
+    // A directory cannot move below itself
+    test_assert_eq!(root.0.rename_to("b", &b, "inner").err(), Some(KError::Invalid));
+    test_assert_ok!(root.0.rename_to("a", &b, "moved"));
+
+    // Removing everything gives every cluster back
+    test_assert_ok!(b.0.unlink("Renamed File.dat"));
+    test_assert_ok!(b.0.rmdir("moved"));
+    test_assert_ok!(root.0.rmdir("b"));
+    test_assert_eq!(fat32_fsinfo_free(&dev), free_before);
+
+    TestResult::Pass
+}
+
+fn test_fat32_remount() -> TestResult {
+    let (dev, root) = match fat32_test_mount() {
+        Some(m) => m,
+        None => return TestResult::Fail,
+    };
+
+    let data = test_pattern(10_000, 11);
+    let dir = match root.0.create("EFI", InodeKind::Dir, ext4_meta(InodeKind::Dir)) {
+        Ok(d) => d,
+        Err(_) => return TestResult::Fail,
+    };
+    match dir.0.create("bootx64.efi", InodeKind::File, ext4_meta(InodeKind::File)) {
+        Ok(f) => test_assert_ok!(f.0.write_at(0, &data)),
+        Err(_) => return TestResult::Fail,
+    }
+    drop(dir);
+    drop(root);
+
+    // Both FAT copies must match
+    let mut fat0 = vec![0u8; FAT32_TEST_FAT_SIZE as usize * 512];
+    let mut fat1 = vec![0u8; FAT32_TEST_FAT_SIZE as usize * 512];
+    test_assert_ok!(dev.read_blocks(FAT32_TEST_RESERVED as u64, FAT32_TEST_FAT_SIZE, &mut fat0));
+    test_assert_ok!(dev.read_blocks((FAT32_TEST_RESERVED + FAT32_TEST_FAT_SIZE) as u64, FAT32_TEST_FAT_SIZE, &mut fat1));
+    test_assert!(fat0 == fat1);
+
+    let root = match Fat32Fs::mount(Arc::clone(&dev)) {
+        Ok(fs) => fs.root(),
+        Err(_) => return TestResult::Fail,
+    };
+    let file = match root.0.lookup("EFI").and_then(|d| d.0.lookup("BOOTX64.EFI")) {
+        Ok(f) => f,
+        Err(_) => return TestResult::Fail,
+    };
+    let mut out = vec![0u8; data.len()];
+    test_assert_eq!(file.0.read_at(0, &mut out).ok(), Some(data.len()));
+    test_assert!(out == data);
+    test_assert_eq!(
+        root.0.lookup("EFI").and_then(|d| d.0.readdir()).ok().map(|e| e[0].name.clone()).as_deref(),
+        Some("bootx64.efi")
+    );
+
+    // Directory cluster plus 20 data clusters
+    test_assert_eq!(fat32_fsinfo_free(&dev), FAT32_TEST_CLUSTERS - 1 - 21);
+
+    TestResult::Pass
+}
+
+// ============================================================================
+// exFAT (on a ramdisk)
+// ============================================================================
+
+use crate::fs::exfat::ExfatFs;
+
+// Test image layout (512-byte sectors, one sector per cluster)
+const EXFAT_TEST_SECTORS: u32 = 4064;
+const EXFAT_TEST_FAT_OFFSET: u32 = 24;
+const EXFAT_TEST_FAT_LENGTH: u32 = 40;
+const EXFAT_TEST_HEAP_OFFSET: u32 = 64;
+const EXFAT_TEST_CLUSTERS: u32 = 4000;
+
+/// Sector of a cluster in the test image
+fn exfat_test_sector(cluster: u32) -> usize {
+    (EXFAT_TEST_HEAP_OFFSET + cluster - 2) as usize
+}
+
+/// Build an empty exFAT filesystem (bitmap at cluster 2, up-case table at 3, root at 4)
+fn exfat_test_device() -> Arc<dyn BlockDevice> {
+    let mut img = vec![0u8; EXFAT_TEST_SECTORS as usize * 512];
+
+    // Main boot sector
+    {
+        let bs = &mut img[0..512];
+        bs[0..3].copy_from_slice(&[0xEB, 0x76, 0x90]);
+        bs[3..11].copy_from_slice(b"EXFAT   ");
+        put32(bs, 72, EXFAT_TEST_SECTORS);
+        put32(bs, 80, EXFAT_TEST_FAT_OFFSET);
+        put32(bs, 84, EXFAT_TEST_FAT_LENGTH);
+        put32(bs, 88, EXFAT_TEST_HEAP_OFFSET);
+        put32(bs, 92, EXFAT_TEST_CLUSTERS);
+        put32(bs, 96, 4); // root cluster
+        put32(bs, 100, 0x5354_4E5A);
+        put16(bs, 104, 0x0100);
+        bs[108] = 9; // 512-byte sectors
+        bs[109] = 0; // one sector per cluster
+        bs[110] = 1; // one FAT
+        bs[111] = 0x80;
+        put16(bs, 510, 0xAA55);
+    }
+    // Extended boot sectors carry a signature too
+    for sector in 1..9 {
+        put32(&mut img[sector * 512..], 508, 0xAA55_0000);
+    }
+    // Boot region checksum (VolumeFlags and PercentInUse are excluded)
+    let mut checksum = 0u32;
+    for (i, &b) in img[..11 * 512].iter().enumerate() {
+        if i == 106 || i == 107 || i == 112 {
+            continue;
+        }
+        checksum = (checksum >> 1 | (checksum & 1) << 31).wrapping_add(b as u32);
+    }
+    for i in 0..128 {
+        put32(&mut img[11 * 512..], i * 4, checksum);
+    }
+    let boot = img[..12 * 512].to_vec();
+    img[12 * 512..24 * 512].copy_from_slice(&boot);
+
+    // FAT: media entry, reserved entry, single-cluster chains for clusters 2-4
+    let fat = EXFAT_TEST_FAT_OFFSET as usize * 512;
+    put32(&mut img[fat..], 0, 0xFFFF_FFF8);
+    for entry in 1..5 {
+        put32(&mut img[fat..], entry * 4, 0xFFFF_FFFF);
+    }
+
+    // Allocation bitmap
+    img[exfat_test_sector(2) * 512] = 0x07;
+
+    // Compressed up-case table: only a-z map to A-Z
+    let mut upcase = Vec::new();
+    upcase.extend_from_slice(&[0xFFFF, 0x61]);
+    upcase.extend(0x41u16..=0x5A);
+    upcase.extend_from_slice(&[0xFFFF, (0x10000 - 0x7B) as u16]);
+    let upcase: Vec<u8> = upcase.iter().flat_map(|u| u.to_le_bytes()).collect();
+    let mut upcase_checksum = 0u32;
+    for &b in &upcase {
+        upcase_checksum = (upcase_checksum >> 1 | (upcase_checksum & 1) << 31).wrapping_add(b as u32);
+    }
+    let off = exfat_test_sector(3) * 512;
+    img[off..off + upcase.len()].copy_from_slice(&upcase);
+
+    // Root directory: allocation bitmap and up-case table entries
+    {
+        let root = &mut img[exfat_test_sector(4) * 512..];
+        root[0] = 0x81;
+        put32(root, 20, 2);
+        put32(root, 24, (EXFAT_TEST_CLUSTERS + 7) / 8);
+        root[32] = 0x82;
+        put32(&mut root[32..], 4, upcase_checksum);
+        put32(&mut root[32..], 20, 3);
+        put32(&mut root[32..], 24, upcase.len() as u32);
+    }
+
+    let disk = RamDisk::new(0xEF, 512, EXFAT_TEST_SECTORS as u64);
+    let _ = disk.write_blocks(0, EXFAT_TEST_SECTORS, &img);
+    Arc::new(disk)
+}
+
+fn exfat_test_mount() -> Option<(Arc<dyn BlockDevice>, Inode)> {
+    let dev = exfat_test_device();
+    let fs = ExfatFs::mount(Arc::clone(&dev)).ok()?;
+    if !fs.is_writable() {
+        return None;
+    }
+    Some((dev, fs.root()))
+}
+
+/// Number of clusters marked in use in the on-disk allocation bitmap
+fn exfat_bitmap_used(dev: &Arc<dyn BlockDevice>) -> u32 {
+    let mut sector = [0u8; 512];
+    let _ = dev.read_blocks(exfat_test_sector(2) as u64, 1, &mut sector);
+    sector.iter().map(|b| b.count_ones()).sum()
+}
+
+/// VolumeFlags field of the main boot sector
+fn exfat_volume_flags(dev: &Arc<dyn BlockDevice>) -> u16 {
+    let mut sector = [0u8; 512];
+    let _ = dev.read_blocks(0, 1, &mut sector);
+    u16::from_le_bytes([sector[106], sector[107]])
+}
+
+/// Raw 32-byte directory entries of a cluster
+fn exfat_dir_entries(dev: &Arc<dyn BlockDevice>, cluster: u32) -> Vec<[u8; 32]> {
+    let mut sector = [0u8; 512];
+    let _ = dev.read_blocks(exfat_test_sector(cluster) as u64, 1, &mut sector);
+    sector
+        .chunks_exact(32)
+        .map(|c| {
+            let mut e = [0u8; 32];
+            e.copy_from_slice(c);
+            e
+        })
+        .collect()
+}
+
+fn test_exfat_write_read() -> TestResult {
+    let (dev, root) = match exfat_test_mount() {
+        Some(m) => m,
+        None => return TestResult::Fail,
+    };
+
+    let file = match root.0.create("clip0001.mp4", InodeKind::File, ext4_meta(InodeKind::File)) {
+        Ok(f) => f,
+        Err(_) => return TestResult::Fail,
+    };
+
+    // Spans many clusters, with unaligned start and end
+    let data = test_pattern(40_000, 5);
+    test_assert_eq!(file.0.write_at(100, &data).ok(), Some(data.len()));
+    test_assert_eq!(file.0.size().ok(), Some(40_100));
+
+    // A fresh file is laid out contiguously without a FAT chain
+    let entries = exfat_dir_entries(&dev, 4);
+    let stream = match entries.iter().position(|e| e[0] == 0x85) {
+        Some(i) => entries[i + 1],
+        None => return TestResult::Fail,
+    };
+    test_assert_eq!(stream[0], 0xC0);
+    test_assert_eq!(stream[1] & 0x02, 0x02);
+
+    // Another file right behind it forces the first one onto a FAT chain
+    let other = match root.0.create("clip0002.mp4", InodeKind::File, ext4_meta(InodeKind::File)) {
+        Ok(f) => f,
+        Err(_) => return TestResult::Fail,
+    };
+    test_assert_ok!(other.0.write_at(0, &test_pattern(1000, 9)));
+    let tail = test_pattern(3000, 6);
+    test_assert_ok!(file.0.write_at(40_100, &tail));
+
+    let mut out = vec![0u8; 43_100];
+    test_assert_eq!(file.0.read_at(0, &mut out).ok(), Some(43_100));
+    test_assert!(out[..100].iter().all(|&b| b == 0));
+    test_assert!(out[100..40_100] == data[..]);
+    test_assert!(out[40_100..] == tail[..]);
+
+    let entries = exfat_dir_entries(&dev, 4);
+    let stream = match entries.iter().position(|e| e[0] == 0x85) {
+        Some(i) => entries[i + 1],
+        None => return TestResult::Fail,
+    };
+    test_assert_eq!(stream[1] & 0x02, 0);
+
+    // Writing past the end fills the gap with zeros
+    test_assert_eq!(other.0.write_at(20_000, b"end").ok(), Some(3));
+    let mut gap = [0xFFu8; 16];
+    test_assert_eq!(other.0.read_at(10_000, &mut gap).ok(), Some(16));
+    test_assert!(gap.iter().all(|&b| b == 0));
+
+    // 85 + 40 data clusters plus bitmap, up-case table and root
+    test_assert_eq!(exfat_bitmap_used(&dev), 3 + 85 + 40);
+    test_assert_eq!(exfat_volume_flags(&dev) & 0x02, 0);
+
+    TestResult::Pass
+}
+
+fn test_exfat_names() -> TestResult {
+    let (_dev, root) = match exfat_test_mount() {
+        Some(m) => m,
+        None => return TestResult::Fail,
+    };
+
+    let dir = match root.0.create("DCIM", InodeKind::Dir, ext4_meta(InodeKind::Dir)) {
+        Ok(d) => d,
+        Err(_) => return TestResult::Fail,
+    };
+
+    // Names spanning several name entries and non-ASCII names are preserved
+    let names = ["A file with a rather long name, longer than thirty characters.text", "readme.txt", "Ünïcode ✓.md"];
+    for name in names {
+        test_assert_ok!(dir.0.create(name, InodeKind::File, ext4_meta(InodeKind::File)));
+    }
+    // Enough entry sets to grow the directory past one cluster
+    for i in 0..40 {
+        let name = alloc::format!("entry-with-a-long-name-{:03}", i);
+        test_assert_ok!(dir.0.create(&name, InodeKind::File, ext4_meta(InodeKind::File)));
+    }
+
+    let entries = match dir.0.readdir() {
+        Ok(e) => e,
+        Err(_) => return TestResult::Fail,
+    };
+    test_assert_eq!(entries.len(), 43);
+    for name in names {
+        test_assert!(entries.iter().any(|e| e.name == name));
+    }
+
+    // Lookups ignore case, duplicates and invalid names are rejected
+    test_assert_ok!(dir.0.lookup("README.TXT"));
+    test_assert_ok!(dir.0.lookup("ENTRY-WITH-A-LONG-NAME-039"));
+    test_assert_eq!(
+        dir.0.create("ReadMe.txt", InodeKind::File, ext4_meta(InodeKind::File)).err(),
+        Some(KError::AlreadyExists)
+    );
+    test_assert_eq!(
+        dir.0.create("bad:name", InodeKind::File, ext4_meta(InodeKind::File)).err(),
+        Some(KError::Invalid)
+    );
+
+    test_assert_eq!(root.0.rmdir("DCIM").err(), Some(KError::NotEmpty));
+    for entry in &entries {
+        test_assert_ok!(dir.0.unlink(&entry.name));
+    }
+    test_assert_eq!(dir.0.readdir().map(|e| e.len()).ok(), Some(0));
+    test_assert_eq!(root.0.unlink("DCIM").err(), Some(KError::Invalid));
+    test_assert_ok!(root.0.rmdir("DCIM"));
+    test_assert!(root.0.lookup("DCIM").is_err());
+
+    TestResult::Pass
+}
+
+fn test_exfat_truncate_rename() -> TestResult {
+    let (dev, root) = match exfat_test_mount() {
+        Some(m) => m,
+        None => return TestResult::Fail,
+    };
+    let used_before = exfat_bitmap_used(&dev);
+
+    let (a, b) = match (
+        root.0.create("a", InodeKind::Dir, ext4_meta(InodeKind::Dir)),
+        root.0.create("b", InodeKind::Dir, ext4_meta(InodeKind::Dir)),
+    ) {
+        (Ok(a), Ok(b)) => (a, b),
+        _ => return TestResult::Fail,
+    };
+    let file = match a.0.create("file.dat", InodeKind::File, ext4_meta(InodeKind::File)) {
+        Ok(f) => f,
+        Err(_) => return TestResult::Fail,
+    };
+    let data = test_pattern(20_000, 3);
+    test_assert_ok!(file.0.write_at(0, &data));
+
+    test_assert_ok!(file.0.truncate(1500));
+    test_assert_eq!(file.0.size().ok(), Some(1500));
+
+    // Growing again must expose zeros, not the old data
+    test_assert_ok!(file.0.truncate(8000));
+    let mut out = vec![0u8; 8000];
+    test_assert_eq!(file.0.read_at(0, &mut out).ok(), Some(8000));
+    test_assert!(out[..1500] == data[..1500]);
+    test_assert!(out[1500..].iter().all(|&b| b == 0));
+
+    // Rename across directories keeps the open handle working
+    test_assert_ok!(a.0.rename_to("file.dat", &b, "Renamed File.dat"));
+    test_assert!(a.0.lookup("file.dat").is_err());
+    test_assert_ok!(b.0.lookup("renamed file.DAT"));
+    test_assert_ok!(file.0.write_at(0, b"head"));
+    let mut head = [0u8; 4];
+    test_assert_eq!(b.0.lookup("Renamed File.dat").and_then(|f| f.0.read_at(0, &mut head)).ok(), Some(4));
+    test_assert_eq!(&head, b"head");
+
+    // A directory cannot move below itself
+    test_assert_eq!(root.0.rename_to("b", &b, "inner").err(), Some(KError::Invalid));
+    test_assert_ok!(root.0.rename_to("a", &b, "moved"));
+
+    // Removing everything gives every cluster back
+    test_assert_ok!(b.0.unlink("Renamed File.dat"));
+    test_assert_ok!(b.0.rmdir("moved"));
+    test_assert_ok!(root.0.rmdir("b"));
+    test_assert_eq!(exfat_bitmap_used(&dev), used_before);
+
+    TestResult::Pass
+}
+
+fn test_exfat_remount() -> TestResult {
+    let (dev, root) = match exfat_test_mount() {
+        Some(m) => m,
+        None => return TestResult::Fail,
+    };
+
+    let data = test_pattern(10_000, 11);
+    let dir = match root.0.create("PRIVATE", InodeKind::Dir, ext4_meta(InodeKind::Dir)) {
+        Ok(d) => d,
+        Err(_) => return TestResult::Fail,
+    };
+    match dir.0.create("Movie.MTS", InodeKind::File, ext4_meta(InodeKind::File)) {
+        Ok(f) => {
+            test_assert_ok!(f.0.write_at(0, &data));
+            // Preallocated tail beyond the valid data length
+            test_assert_ok!(f.0.truncate(12_000));
+        }
+        Err(_) => return TestResult::Fail,
+    }
+    drop(dir);
+    drop(root);
+
+    // Entry set checksum covers every entry of the set except its own field
+    let entries = exfat_dir_entries(&dev, 4);
+    let file = match entries.iter().position(|e| e[0] == 0x85) {
+        Some(i) => i,
+        None => return TestResult::Fail,
+    };
+    let count = 1 + entries[file][1] as usize;
+    let mut checksum = 0u16;
+    for (i, &b) in entries[file..file + count].iter().flatten().enumerate() {
+        if i == 2 || i == 3 {
+            continue;
+        }
+        checksum = (checksum >> 1 | (checksum & 1) << 15).wrapping_add(b as u16);
+    }
+    test_assert_eq!(checksum, u16::from_le_bytes([entries[file][2], entries[file][3]]));
+    test_assert_eq!(exfat_volume_flags(&dev) & 0x02, 0);
+
+    let root = match ExfatFs::mount(Arc::clone(&dev)) {
+        Ok(fs) => fs.root(),
+        Err(_) => return TestResult::Fail,
+    };
+    let file = match root.0.lookup("private").and_then(|d| d.0.lookup("movie.mts")) {
+        Ok(f) => f,
+        Err(_) => return TestResult::Fail,
+    };
+    test_assert_eq!(file.0.size().ok(), Some(12_000));
+    let mut out = vec![0xFFu8; 12_000];
+    test_assert_eq!(file.0.read_at(0, &mut out).ok(), Some(12_000));
+    test_assert!(out[..10_000] == data[..]);
+    test_assert!(out[10_000..].iter().all(|&b| b == 0));
+    test_assert_eq!(
+        root.0.lookup("PRIVATE").and_then(|d| d.0.readdir()).ok().map(|e| e[0].name.clone()).as_deref(),
+        Some("Movie.MTS")
+    );
+
+    // Directory cluster plus 24 data clusters
+    test_assert_eq!(exfat_bitmap_used(&dev), 3 + 1 + 24);
+
+    TestResult::Pass
+}
+
+// ============================================================================
+// Mount table
+// ============================================================================
+
+use crate::fs::vfs::{MountFlags, Vfs};
+use crate::security::Cred;
+
+fn tmpfs_root() -> Inode {
+    crate::fs::mount_fs("tmpfs", None).expect("tmpfs")
+}
+
+fn mount_test_vfs() -> Vfs {
+    let mut vfs = Vfs::new(tmpfs_root());
+    let _ = vfs.mkdir_all("/mnt", &Cred::root(), Mode::from_octal(0o755));
+    vfs
+}
+
+fn test_mount_flags() -> TestResult {
+    let cred = Cred::root();
+    let mut vfs = mount_test_vfs();
+
+    let fs_root = tmpfs_root();
+    test_assert_ok!(fs_root.0.create("file", InodeKind::File, ext4_meta(InodeKind::File)));
+    vfs.mount("/mnt", fs_root, "tmpfs", "tmpfs", MountFlags::RDONLY | MountFlags::NOEXEC);
+
+    let dir = match vfs.resolve("/mnt", &cred) {
+        Ok(d) => d,
+        Err(_) => return TestResult::Fail,
+    };
+    test_assert!(dir.0.mount_flags().contains(MountFlags::RDONLY | MountFlags::NOEXEC));
+    test_assert!(matches!(
+        dir.0.create("new", InodeKind::File, ext4_meta(InodeKind::File)),
+        Err(KError::ReadOnly)
+    ));
+
+    // Inodes found through the mount carry its flags too
+    let file = match vfs.resolve("/mnt/file", &cred) {
+        Ok(f) => f,
+        Err(_) => return TestResult::Fail,
+    };
+    test_assert!(file.0.mount_flags().contains(MountFlags::RDONLY));
+    test_assert!(matches!(file.0.write_at(0, b"x"), Err(KError::ReadOnly)));
+    test_assert!(matches!(file.0.truncate(0), Err(KError::ReadOnly)));
+    test_assert!(matches!(vfs.unlink("/mnt/file", &cred), Err(KError::ReadOnly)));
+
+    // A bind mount of the same directory has its own flags
+    let _ = vfs.mkdir_all("/bind", &cred, Mode::from_octal(0o755));
+    vfs.mount("/bind", dir, "/mnt", "tmpfs", MountFlags::empty());
+    drop(file);
+    let file = match vfs.resolve("/bind/file", &cred) {
+        Ok(f) => f,
+        Err(_) => return TestResult::Fail,
+    };
+    test_assert!(file.0.mount_flags().is_empty());
+    test_assert_eq!(file.0.write_at(0, b"x").ok(), Some(1));
+
+    let mounts = vfs.mounts();
+    test_assert_eq!(mounts.len(), 2);
+    test_assert_eq!(mounts[0].path.as_str(), "/bind");
+    test_assert_eq!(mounts[0].source.as_str(), "/mnt");
+
+    TestResult::Pass
+}
+
+fn test_umount_busy() -> TestResult {
+    let cred = Cred::root();
+    let mut vfs = mount_test_vfs();
+
+    vfs.mount("/mnt", tmpfs_root(), "tmpfs", "tmpfs", MountFlags::empty());
+    test_assert!(vfs.is_mount_point("/mnt"));
+    test_assert!(matches!(vfs.umount("/tmp", false), Err(KError::Invalid)));
+    test_assert!(matches!(vfs.umount("/", false), Err(KError::Busy)));
+
+    // An inode reached through the mount keeps it busy
+    let held = match vfs.resolve("/mnt", &cred) {
+        Ok(i) => i,
+        Err(_) => return TestResult::Fail,
+    };
+    test_assert!(matches!(vfs.umount("/mnt", false), Err(KError::Busy)));
+    drop(held);
+
+    // So does a mount below it
+    let _ = vfs.mkdir_all("/mnt/sub", &cred, Mode::from_octal(0o755));
+    vfs.mount("/mnt/sub", tmpfs_root(), "tmpfs", "tmpfs", MountFlags::empty());
+    test_assert!(matches!(vfs.umount("/mnt", false), Err(KError::Busy)));
+    test_assert_ok!(vfs.umount("/mnt/sub", false));
+    test_assert_ok!(vfs.umount("/mnt", false));
+    test_assert!(!vfs.is_mount_point("/mnt"));
+
+    // A lazy unmount detaches even while in use
+    vfs.mount("/mnt", tmpfs_root(), "tmpfs", "tmpfs", MountFlags::empty());
+    let held = match vfs.resolve("/mnt", &cred) {
+        Ok(i) => i,
+        Err(_) => return TestResult::Fail,
+    };
+    test_assert_ok!(vfs.umount("/mnt", true));
+    test_assert!(!vfs.is_mount_point("/mnt"));
+    test_assert_ok!(held.0.create("still-usable", InodeKind::File, ext4_meta(InodeKind::File)));
+
+    TestResult::Pass
+}
+
+fn test_ext4_statfs() -> TestResult {
+    let (_dev, root) = match ext4_test_mount() {
+        Some(m) => m,
+        None => return TestResult::Fail,
+    };
+
+    let before = match root.0.statfs() {
+        Ok(st) => st,
+        Err(_) => return TestResult::Fail,
+    };
+    test_assert_eq!(before.magic, 0xEF53);
+    test_assert_eq!(before.block_size, 1024);
+    test_assert_eq!(before.blocks, EXT4_TEST_BLOCKS as u64);
+    test_assert_eq!(before.files, EXT4_TEST_INODES as u64);
+    test_assert!(before.blocks_free > 0 && before.blocks_free < before.blocks);
+
+    match root.0.create("big", InodeKind::File, ext4_meta(InodeKind::File)) {
+        Ok(f) => test_assert_eq!(f.0.write_at(0, &test_pattern(8192, 3)).ok(), Some(8192)),
+        Err(_) => return TestResult::Fail,
+    }
+
+    let after = match root.0.statfs() {
+        Ok(st) => st,
+        Err(_) => return TestResult::Fail,
+    };
+    test_assert!(after.blocks_free <= before.blocks_free - 8);
+    test_assert_eq!(after.files_free, before.files_free - 1);
+
+    TestResult::Pass
+}
+
+fn test_mount_ns_copy() -> TestResult {
+    let cred = Cred::root();
+    let mut parent = mount_test_vfs();
+    let _ = parent.mkdir_all("/srv", &cred, Mode::from_octal(0o755));
+    parent.mount("/mnt", tmpfs_root(), "tmpfs", "tmpfs", MountFlags::empty());
+
+    let mut child = parent.copy();
+    test_assert!(child.is_mount_point("/mnt"));
+
+    // Mount changes stay in their own table
+    child.mount("/srv", tmpfs_root(), "tmpfs", "tmpfs", MountFlags::empty());
+    test_assert!(!parent.is_mount_point("/srv"));
+    test_assert_ok!(child.umount("/mnt", false));
+    test_assert!(parent.is_mount_point("/mnt"));
+
+    // Both still see the same filesystems
+    test_assert_ok!(parent.write_file("/mnt/shared", &cred, Mode::from_octal(0o644), b"x"));
+    test_assert_ok!(child.write_file("/srv/own", &cred, Mode::from_octal(0o644), b"y"));
+    test_assert!(parent.resolve("/srv/own", &cred).is_err());
+
+    // Files open in one table do not keep the other's mounts busy
+    let child2 = parent.copy();
+    let held = match child2.resolve("/mnt/shared", &cred) {
+        Ok(i) => i,
+        Err(_) => return TestResult::Fail,
+    };
+    test_assert_ok!(parent.umount("/mnt", false));
+    test_assert_eq!(held.0.size().ok(), Some(1));
+
+    TestResult::Pass
+}
+
+fn test_pivot_root() -> TestResult {
+    let cred = Cred::root();
+    let mut vfs = mount_test_vfs();
+    let _ = vfs.mkdir_all("/proc", &cred, Mode::from_octal(0o755));
+    test_assert_ok!(vfs.write_file("/old-file", &cred, Mode::from_octal(0o644), b"old"));
+    vfs.mount("/proc", tmpfs_root(), "proc", "proc", MountFlags::empty());
+
+    let new_root = tmpfs_root();
+    test_assert_ok!(new_root.0.create("put_old", InodeKind::Dir, ext4_meta(InodeKind::Dir)));
+    test_assert_ok!(new_root.0.create("lib", InodeKind::Dir, ext4_meta(InodeKind::Dir)));
+    vfs.mount("/mnt", new_root, "rootfs.img", "tmpfs", MountFlags::empty());
+    vfs.mount("/mnt/lib", tmpfs_root(), "tmpfs", "tmpfs", MountFlags::empty());
+
+    // new_root has to be a mount point, put_old has to be below it
+    test_assert!(matches!(vfs.pivot_root("/proc/..", "/put_old", &cred), Err(KError::Busy)));
+    test_assert!(matches!(vfs.pivot_root("/mnt", "/put_old", &cred), Err(KError::Invalid)));
+    test_assert!(matches!(vfs.pivot_root("/mnt", "/mnt/missing", &cred), Err(KError::NotFound)));
+
+    test_assert_ok!(vfs.pivot_root("/mnt", "/mnt/put_old", &cred));
+    test_assert!(vfs.is_mount_point("/lib"));
+    test_assert!(vfs.is_mount_point("/put_old"));
+    test_assert!(vfs.is_mount_point("/put_old/proc"));
+    test_assert!(!vfs.is_mount_point("/proc"));
+    test_assert_eq!(vfs.read_file("/put_old/old-file", &cred).ok(), Some(b"old".to_vec()));
+
+    // The old root can be detached afterwards, like a container runtime does
+    test_assert_ok!(vfs.umount("/put_old", true));
+    test_assert!(vfs.resolve("/put_old/old-file", &cred).is_err());
+    test_assert!(vfs.resolve("/lib", &cred).is_ok());
+
+    TestResult::Pass
+}
+
+fn test_chroot_confined() -> TestResult {
+    let cred = Cred::root();
+    let mut vfs = mount_test_vfs();
+    test_assert_ok!(vfs.write_file("/secret", &cred, Mode::from_octal(0o600), b"s"));
+    let _ = vfs.mkdir_all("/jail/proc", &cred, Mode::from_octal(0o755));
+    test_assert_ok!(vfs.write_file("/jail/inside", &cred, Mode::from_octal(0o644), b"i"));
+    vfs.mount("/jail/proc", tmpfs_root(), "proc", "proc", MountFlags::NOEXEC);
+
+    let mut jail = match vfs.chroot("/jail", &cred) {
+        Ok(j) => j,
+        Err(_) => return TestResult::Fail,
+    };
+    test_assert!(matches!(vfs.chroot("/secret", &cred), Err(KError::NotADirectory)));
+
+    test_assert_eq!(jail.read_file("/inside", &cred).ok(), Some(b"i".to_vec()));
+    test_assert_eq!(jail.read_file("/../../inside", &cred).ok(), Some(b"i".to_vec()));
+    test_assert!(jail.read_file("/../secret", &cred).is_err());
+    test_assert!(jail.resolve_nofollow("/proc/../../secret", &cred).is_err());
+
+    // Mounts below the new root come along, with their flags
+    test_assert!(jail.is_mount_point("/proc"));
+    match jail.resolve("/proc", &cred) {
+        Ok(dir) => test_assert!(dir.0.mount_flags().contains(MountFlags::NOEXEC)),
+        Err(_) => return TestResult::Fail,
+    }
+
+    // Writes land in the real tree
+    test_assert_ok!(jail.write_file("/new", &cred, Mode::from_octal(0o644), b"n"));
+    test_assert_eq!(vfs.read_file("/jail/new", &cred).ok(), Some(b"n".to_vec()));
+
+    TestResult::Pass
+}
+
+fn test_inotify_events() -> TestResult {
+    use crate::fs::inotify::*;
+
+    let cred = Cred::root();
+    let mut vfs = mount_test_vfs();
+    let mode = Mode::from_octal(0o644);
+    test_assert_ok!(vfs.mkdir_all("/watched/sub", &cred, Mode::from_octal(0o755)));
+
+    let ino = Inotify::new(IN_NONBLOCK);
+    let dir_wd = match ino.add_watch("/watched", IN_ALL_EVENTS) {
+        Ok(wd) => wd,
+        Err(_) => return TestResult::Fail,
+    };
+    let events = |ino: &Inotify| -> Vec<(i32, u32, alloc::string::String)> {
+        ino.take_events().into_iter().map(|e| (e.wd, e.mask, e.name)).collect()
+    };
+    let ev = |wd: i32, mask: u32, name: &str| (wd, mask, alloc::string::String::from(name));
+
+    // Entry events reach the directory watch with the entry's name
+    test_assert_ok!(vfs.write_file("/watched/a", &cred, mode, b"x"));
+    test_assert_ok!(vfs.chmod("/watched/a", Mode::from_octal(0o600), &cred));
+    let got = events(&ino);
+    test_assert_eq!(
+        got,
+        vec![
+            ev(dir_wd, IN_CREATE, "a"),
+            ev(dir_wd, IN_MODIFY, "a"),
+            ev(dir_wd, IN_CLOSE_WRITE, "a"),
+            ev(dir_wd, IN_ATTRIB, "a"),
+        ]
+    );
+
+    // A rename pairs MOVED_FROM and MOVED_TO by cookie
+    test_assert_ok!(vfs.rename("/watched/a", "/watched/b", &cred));
+    let moved = ino.take_events();
+    test_assert_eq!(moved.len(), 2);
+    test_assert_eq!((moved[0].mask, moved[0].name.as_str()), (IN_MOVED_FROM, "a"));
+    test_assert_eq!((moved[1].mask, moved[1].name.as_str()), (IN_MOVED_TO, "b"));
+    test_assert_ne!(moved[0].cookie, 0);
+    test_assert_eq!(moved[0].cookie, moved[1].cookie);
+
+    // One-shot watches fire once and report IN_IGNORED
+    let file_wd = match ino.add_watch("/watched/b", IN_ATTRIB | IN_ONESHOT) {
+        Ok(wd) => wd,
+        Err(_) => return TestResult::Fail,
+    };
+    let again = ino.add_watch("/watched/b", IN_ATTRIB | IN_MASK_CREATE);
+    test_assert_eq!(again.err(), Some(crate::syscall::errno::EEXIST));
+    test_assert_ok!(vfs.chmod("/watched/b", mode, &cred));
+    test_assert_ok!(vfs.chmod("/watched/b", mode, &cred));
+    // (the second ATTRIB on the directory merges with the first)
+    let got = events(&ino);
+    test_assert_eq!(
+        got,
+        vec![
+            ev(file_wd, IN_ATTRIB, ""),
+            ev(file_wd, IN_IGNORED, ""),
+            ev(dir_wd, IN_ATTRIB, "b"),
+        ]
+    );
+
+    // Watches follow a renamed directory
+    let sub_wd = match ino.add_watch("/watched/sub", IN_CREATE) {
+        Ok(wd) => wd,
+        Err(_) => return TestResult::Fail,
+    };
+    test_assert_ok!(vfs.rename("/watched/sub", "/watched/moved", &cred));
+    let _ = events(&ino);
+    test_assert_ok!(vfs.write_file("/watched/moved/f", &cred, mode, b"y"));
+    let got = events(&ino);
+    test_assert_eq!(got, vec![ev(sub_wd, IN_CREATE, "f")]);
+
+    // Unlink and rmdir; the removed directory's watch goes away
+    test_assert_ok!(vfs.unlink("/watched/moved/f", &cred));
+    test_assert_ok!(vfs.rmdir("/watched/moved", &cred));
+    let got = events(&ino);
+    test_assert_eq!(got, vec![ev(dir_wd, IN_DELETE | IN_ISDIR, "moved"), ev(sub_wd, IN_IGNORED, "")]);
+
+    // Unmounting reports IN_UNMOUNT to watches inside the mount
+    test_assert_ok!(vfs.mkdir_all("/watched/mnt", &cred, Mode::from_octal(0o755)));
+    vfs.mount("/watched/mnt", tmpfs_root(), "tmpfs", "tmpfs", MountFlags::empty());
+    let mnt_wd = match ino.add_watch("/watched/mnt", IN_CREATE) {
+        Ok(wd) => wd,
+        Err(_) => return TestResult::Fail,
+    };
+    let _ = events(&ino);
+    test_assert_ok!(vfs.umount("/watched/mnt", false));
+    let got = events(&ino);
+    test_assert_eq!(got, vec![ev(mnt_wd, IN_UNMOUNT, ""), ev(mnt_wd, IN_IGNORED, "")]);
+
+    // A full queue ends with one IN_Q_OVERFLOW
+    for i in 0..MAX_QUEUED_EVENTS + 8 {
+        notify("/watched/b", if i % 2 == 0 { IN_MODIFY } else { IN_ACCESS });
+    }
+    let flood = ino.take_events();
+    test_assert_eq!(flood.len(), MAX_QUEUED_EVENTS + 1);
+    test_assert_eq!(flood.last().map(|e| (e.wd, e.mask)), Some((-1, IN_Q_OVERFLOW)));
+
+    // read() returns struct inotify_event with the name padded to 16 bytes
+    test_assert_ok!(vfs.unlink("/watched/b", &cred));
+    let mut buf = [0u8; 64];
+    test_assert_eq!(ino.read(&mut buf[..20]).err(), Some(crate::syscall::errno::EINVAL));
+    test_assert_eq!(ino.read(&mut buf).ok(), Some(32));
+    test_assert_eq!(u32::from_ne_bytes([buf[4], buf[5], buf[6], buf[7]]), IN_DELETE);
+    test_assert_eq!(u32::from_ne_bytes([buf[12], buf[13], buf[14], buf[15]]), 16);
+    test_assert_eq!(&buf[16..18], b"b\0");
+    test_assert_eq!(ino.read(&mut buf).err(), Some(crate::syscall::errno::EAGAIN));
+
+    TestResult::Pass
+}
+
 // Helper function (public for integration tests)
 pub fn normalize_path_test(path: &str) -> alloc::string::String {
     normalize_path(path)
@@ -86,3 +1532,75 @@ fn normalize_path(path: &str) -> alloc::string::String {
         result
     }
 }
+
+fn test_mac_hooks() -> TestResult {
+    use alloc::string::String;
+    use crate::security::hooks;
+    use crate::security::mac::{self, MacMode, ObjectClass, Permission, SecurityLabel, TeRule, TeRuleType};
+
+    let cred = Cred::root();
+    let mut vfs = mount_test_vfs();
+    vfs.mount("/mnt", tmpfs_root(), "tmpfs", "tmpfs", MountFlags::empty());
+    test_assert_ok!(vfs.write_file("/mnt/secret", &cred, Mode::from_octal(0o644), b"data"));
+
+    let file = match vfs.resolve("/mnt/secret", &cred) {
+        Ok(f) => f,
+        Err(_) => return TestResult::Fail,
+    };
+    let secret = SecurityLabel::new("system_u", "object_r", "secret_t");
+    test_assert_ok!(hooks::set_inode_label(&file, &secret));
+    test_assert_eq!(hooks::inode_label(&file, "/mnt/secret"), secret);
+
+    let prev_mode = mac::get_mode();
+    mac::init();
+    let me = hooks::current_label().stype;
+    for class in [ObjectClass::Dir, ObjectClass::File] {
+        mac::add_te_rule(TeRule {
+            source: me.clone(),
+            target: String::from("unconfined_t"),
+            class,
+            permissions: Permission::all(),
+            rule_type: TeRuleType::Allow,
+        });
+    }
+    mac::clear_audit_log();
+
+    // Enforcing: the xattr label is not readable by the caller's domain
+    mac::set_mode(MacMode::Enforcing);
+    let denied = vfs.read_file("/mnt/secret", &cred);
+    let dir_listed = vfs.list_dir("/mnt", &cred).is_ok();
+
+    // Permissive: same denial is logged but let through
+    mac::set_mode(MacMode::Permissive);
+    let permissive = vfs.read_file("/mnt/secret", &cred);
+
+    // Signals between confined domains
+    mac::set_process_label(90001, SecurityLabel::new("system_u", "system_r", "sender_t"));
+    mac::set_process_label(90002, SecurityLabel::new("system_u", "system_r", "target_t"));
+    mac::set_mode(MacMode::Enforcing);
+    let kill = hooks::task_kill(90001, 90002, 15);
+    mac::add_te_rule(TeRule {
+        source: String::from("sender_t"),
+        target: String::from("target_t"),
+        class: ObjectClass::Process,
+        permissions: Permission::SIGNAL,
+        rule_type: TeRuleType::Allow,
+    });
+    let kill_allowed = hooks::task_kill(90001, 90002, 15);
+
+    mac::set_mode(prev_mode);
+    mac::remove_process_label(90001);
+    mac::remove_process_label(90002);
+    let log = mac::audit_log();
+
+    test_assert!(matches!(denied, Err(KError::PermissionDenied)));
+    test_assert!(dir_listed);
+    test_assert!(matches!(permissive.as_deref(), Ok(b"data")));
+    test_assert!(matches!(kill, Err(KError::PermissionDenied)));
+    test_assert!(kill_allowed.is_ok());
+    test_assert!(log.contains("tcontext=system_u:object_r:secret_t tclass=file permissive=0"));
+    test_assert!(log.contains("tcontext=system_u:object_r:secret_t tclass=file permissive=1"));
+    test_assert!(log.contains("avc: denied { signal }"));
+
+    TestResult::Pass
+}
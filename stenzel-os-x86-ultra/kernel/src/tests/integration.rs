@@ -12,6 +12,15 @@ pub fn register_tests(runner: &mut TestRunner) {
     runner.add_test("integration::scheduler_timer", test_scheduler_timer_integration, "integration");
     runner.add_test("integration::signal_delivery", test_signal_delivery_integration, "integration");
     runner.add_test("integration::pipe_communication", test_pipe_communication, "integration");
+    runner.add_test("integration::compression_codecs", test_compression_codecs, "integration");
+    runner.add_test("integration::pkg_solver", test_pkg_solver, "integration");
+    runner.add_test("integration::pkg_rollback", test_pkg_rollback, "integration");
+    runner.add_test("integration::dm_crypt", test_dm_crypt, "integration");
+    runner.add_test("integration::loop_overlay", test_loop_overlay, "integration");
+    runner.add_test("integration::timer_wheel", test_timer_wheel, "integration");
+    runner.add_test("integration::vdso_image", test_vdso_image, "integration");
+    runner.add_test("integration::tzif", test_tzif, "integration");
+    runner.add_test("integration::ntp_selection", test_ntp_selection, "integration");
 }
 
 /// Test VFS + tmpfs interaction
@@ -240,3 +249,759 @@ fn test_pipe_communication() -> TestResult {
 
     TestResult::Pass
 }
+
+/// Plain text of the compression reference vectors below
+const COMPRESS_TEST_TEXT: &[u8] =
+    b"The quick brown fox jumps over the lazy dog. The quick brown fox jumps over the lazy dog again.\n";
+
+// Reference vectors produced by zlib (gzip -9), libzstd (-19 with checksum),
+// liblzma (xz -6, CRC64) and an LZ4 frame encoder (content checksum)
+const COMPRESS_TEST_GZIP: [u8; 79] = [
+    0x1f, 0x8b, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02, 0xff, 0x8d, 0xca, 0x49, 0x01, 0x80, 0x20,
+    0x10, 0x40, 0xd1, 0xbb, 0x29, 0x7e, 0x02, 0xd2, 0x50, 0x60, 0x54, 0x44, 0x5c, 0x18, 0x45, 0x71,
+    0x4b, 0x2f, 0x11, 0x38, 0xbf, 0x67, 0x47, 0xc7, 0x9e, 0x43, 0x37, 0xd3, 0x26, 0xbd, 0x23, 0x83,
+    0x3e, 0x4c, 0x79, 0xdd, 0x0e, 0xf4, 0x72, 0x89, 0xb3, 0xf0, 0x22, 0xdf, 0x4b, 0xaf, 0xde, 0x60,
+    0xeb, 0x33, 0xe2, 0x25, 0x44, 0xd3, 0xfc, 0x7b, 0x3f, 0x9f, 0x2f, 0x60, 0x00, 0x00, 0x00,
+];
+const COMPRESS_TEST_ZSTD: [u8; 72] = [
+    0x28, 0xb5, 0x2f, 0xfd, 0x04, 0x68, 0xdd, 0x01, 0x00, 0x52, 0x83, 0x0c, 0x11, 0x90, 0x7d, 0x50,
+    0xfa, 0x13, 0x4a, 0x77, 0x28, 0x7d, 0xbe, 0xee, 0xae, 0x1e, 0xcf, 0xb7, 0x35, 0x03, 0x20, 0xc0,
+    0x35, 0xd2, 0x70, 0x0f, 0xe9, 0x8d, 0xeb, 0x3d, 0x9c, 0x1f, 0x55, 0x7d, 0x9e, 0xfb, 0x4e, 0xe3,
+    0xb3, 0xcc, 0xf3, 0x23, 0x87, 0xf8, 0x27, 0xf1, 0x56, 0x96, 0x95, 0x7e, 0x94, 0x08, 0x01, 0x00,
+    0x05, 0x9a, 0xaa, 0x0c, 0xd9, 0x54, 0xb4, 0x6d,
+];
+const COMPRESS_TEST_XZ: [u8; 120] = [
+    0xfd, 0x37, 0x7a, 0x58, 0x5a, 0x00, 0x00, 0x04, 0xe6, 0xd6, 0xb4, 0x46, 0x02, 0x00, 0x21, 0x01,
+    0x16, 0x00, 0x00, 0x00, 0x74, 0x2f, 0xe5, 0xa3, 0xe0, 0x00, 0x5f, 0x00, 0x3b, 0x5d, 0x00, 0x2a,
+    0x1a, 0x08, 0xa2, 0x03, 0x25, 0x66, 0xf1, 0x4b, 0x78, 0xc5, 0xa2, 0x05, 0xff, 0x2e, 0xe6, 0xd9,
+    0xd2, 0x20, 0x1a, 0xad, 0x34, 0xf8, 0xe2, 0x1d, 0xe8, 0x41, 0x36, 0xfa, 0xdc, 0x06, 0x69, 0xbb,
+    0x3c, 0xe4, 0x10, 0x34, 0x27, 0x09, 0xeb, 0xb3, 0x66, 0xe3, 0xed, 0x37, 0x4a, 0xfa, 0x3e, 0xc0,
+    0x54, 0x5c, 0xb4, 0xa7, 0x83, 0x1e, 0xb9, 0x45, 0x00, 0x00, 0x00, 0x00, 0x5c, 0xb7, 0x0f, 0x6c,
+    0x44, 0xcc, 0xc6, 0xe2, 0x00, 0x01, 0x57, 0x60, 0xe0, 0x1f, 0xce, 0x98, 0x1f, 0xb6, 0xf3, 0x7d,
+    0x01, 0x00, 0x00, 0x00, 0x00, 0x04, 0x59, 0x5a,
+];
+const COMPRESS_TEST_LZ4: [u8; 78] = [
+    0x04, 0x22, 0x4d, 0x18, 0x64, 0x40, 0xa7, 0x3b, 0x00, 0x00, 0x00, 0xff, 0x1e, 0x54, 0x68, 0x65,
+    0x20, 0x71, 0x75, 0x69, 0x63, 0x6b, 0x20, 0x62, 0x72, 0x6f, 0x77, 0x6e, 0x20, 0x66, 0x6f, 0x78,
+    0x20, 0x6a, 0x75, 0x6d, 0x70, 0x73, 0x20, 0x6f, 0x76, 0x65, 0x72, 0x20, 0x74, 0x68, 0x65, 0x20,
+    0x6c, 0x61, 0x7a, 0x79, 0x20, 0x64, 0x6f, 0x67, 0x2e, 0x20, 0x2d, 0x00, 0x18, 0x80, 0x20, 0x61,
+    0x67, 0x61, 0x69, 0x6e, 0x2e, 0x0a, 0x00, 0x00, 0x00, 0x00, 0x98, 0xfc, 0xa5, 0x18,
+];
+
+/// Test the compression library against reference encoders and itself
+fn test_compression_codecs() -> TestResult {
+    use alloc::vec::Vec;
+    use crate::compress::{self, checksum, deflate, lz4, xz, zstd, CompressError, Format};
+
+    test_assert_eq!(checksum::crc32(b"123456789"), 0xCBF43926u32);
+    test_assert_eq!(checksum::crc64(b"123456789"), 0x995DC9BBDF1939FAu64);
+    test_assert_eq!(checksum::adler32(b"Wikipedia"), 0x11E60398u32);
+    test_assert_eq!(checksum::xxh32(b"abc", 0), 0x32D153FFu32);
+    test_assert_eq!(checksum::xxh64(b"abc", 0), 0x44BC2CF5AD770999u64);
+
+    // Decoding what the reference implementations produced
+    let vectors: [(&[u8], Format); 4] = [
+        (&COMPRESS_TEST_GZIP, Format::Gzip),
+        (&COMPRESS_TEST_ZSTD, Format::Zstd),
+        (&COMPRESS_TEST_XZ, Format::Xz),
+        (&COMPRESS_TEST_LZ4, Format::Lz4),
+    ];
+    for (data, format) in vectors {
+        test_assert_eq!(compress::detect(data), Some(format));
+        test_assert_eq!(compress::decompress(data, 4096).ok(), Some(COMPRESS_TEST_TEXT.to_vec()));
+        // Output limits are enforced
+        test_assert_eq!(compress::decompress(data, 16).err(), Some(CompressError::TooLarge));
+    }
+
+    // Stored checksums are verified
+    let mut corrupt = COMPRESS_TEST_XZ;
+    corrupt[95] ^= 1;
+    test_assert_eq!(xz::decompress(&corrupt, 4096).err(), Some(CompressError::Checksum));
+    let mut corrupt = COMPRESS_TEST_ZSTD;
+    corrupt[70] ^= 1;
+    test_assert_eq!(zstd::decompress(&corrupt, 4096).err(), Some(CompressError::Checksum));
+
+    // Round trips through the encoders, across several blocks
+    let mut data = Vec::new();
+    for i in 0..300_000u32 {
+        data.push(if i % 1000 < 700 { (i / 7) as u8 } else { (i.wrapping_mul(2654435761) >> 24) as u8 });
+    }
+    for level in [1u8, 6, 9] {
+        let packed = deflate::gzip_compress(&data, level);
+        test_assert!(packed.len() < data.len());
+        test_assert_eq!(deflate::gzip_decompress(&packed, data.len()).ok(), Some(data.clone()));
+        test_assert_eq!(deflate::zlib_decompress(&deflate::zlib_compress(&data, level), data.len()).ok(), Some(data.clone()));
+    }
+    for level in [1u8, 3, 19] {
+        let packed = zstd::compress(&data, level);
+        test_assert!(packed.len() < data.len());
+        test_assert_eq!(zstd::content_size(&packed), Some(data.len() as u64));
+        test_assert_eq!(zstd::decompress(&packed, data.len()).ok(), Some(data.clone()));
+    }
+    let packed = lz4::compress(&data);
+    test_assert_eq!(lz4::decompress(&packed, data.len()).ok(), Some(data.clone()));
+    let page = &data[..4096];
+    test_assert_eq!(lz4::decompress_block(&lz4::compress_block(page), 4096).ok(), Some(page.to_vec()));
+
+    TestResult::Pass
+}
+
+/// Test the spkg dependency solver on a hand-built pool
+fn test_pkg_solver() -> TestResult {
+    use alloc::string::String;
+    use alloc::vec;
+    use alloc::vec::Vec;
+    use crate::pkg::{parse_dependency, PackageMetadata, RepoPackage, Version, InstallReason};
+    use crate::pkg::rollback::OperationType;
+    use crate::pkg::solver::{self, Job, Pool, Reason, Request};
+
+    fn meta(name: &str, version: &str, deps: &[&str]) -> PackageMetadata {
+        let mut meta = PackageMetadata::new(name, Version::parse(version).unwrap());
+        meta.dependencies = deps.iter().filter_map(|d| parse_dependency(d)).collect();
+        meta
+    }
+    fn repo(metadata: PackageMetadata) -> RepoPackage {
+        RepoPackage {
+            filename: alloc::format!("{}-{}.spkg", metadata.name, metadata.version),
+            metadata,
+            download_size: 0,
+            sha256: None,
+            repo_name: String::from("core"),
+        }
+    }
+    fn install(spec: &str) -> Request {
+        Request { jobs: vec![Job::Install(parse_dependency(spec).unwrap())], allow_uninstall: false }
+    }
+    fn names(plan: &solver::Plan) -> Vec<&str> {
+        plan.steps.iter().map(|s| s.name.as_str()).collect()
+    }
+
+    let mut pool = Pool::new();
+    pool.add_installed(meta("base", "1.0", &[]), InstallReason::Explicit);
+    let mut dash = meta("dash", "0.5", &[]);
+    dash.provides.push(String::from("sh"));
+    pool.add_installed(dash, InstallReason::Explicit);
+    pool.add_repo_package(repo(meta("glibc", "2.38", &["base"])), 10);
+    pool.add_repo_package(repo(meta("openssl", "1.1", &["glibc"])), 10);
+    // A newer version from a lower priority repository
+    pool.add_repo_package(repo(meta("openssl", "3.1", &["glibc"])), 20);
+    pool.add_repo_package(repo(meta("curl", "8.4", &["glibc", "openssl>=3.0", "sh"])), 20);
+
+    // Dependencies come first, the constraint overrides repository
+    // priority, and the installed provider of "sh" is used
+    let plan = solver::solve(&pool, &install("curl"));
+    test_assert_ok!(plan);
+    let plan = plan.unwrap();
+    test_assert_eq!(names(&plan), vec!["glibc", "openssl", "curl"]);
+    test_assert_eq!(plan.steps[1].new_version, Version::parse("3.1"));
+    test_assert_eq!(plan.steps[0].reason, InstallReason::Dependency);
+    test_assert_eq!(plan.steps[2].reason, InstallReason::Explicit);
+    let plan = solver::solve(&pool, &install("openssl")).ok();
+    test_assert_eq!(plan.as_ref().and_then(|p| p.step("openssl")).and_then(|s| s.new_version.clone()),
+        Version::parse("1.1"));
+
+    // Conflicts with an installed package are explained
+    let mut busybox = meta("busybox", "1.36", &[]);
+    busybox.conflicts.push(String::from("dash"));
+    pool.add_repo_package(repo(busybox), 10);
+    let problem = solver::solve(&pool, &install("busybox")).err();
+    test_assert_some!(problem);
+    let reasons = problem.unwrap().reasons;
+    test_assert!(reasons.contains(&Reason::Installed(String::from("dash"))));
+    test_assert!(reasons.iter().any(|r| matches!(r, Reason::Conflicts { .. })));
+
+    // Upgrades follow the replaces of the new package and keep reasons
+    let mut ash = meta("ash", "1.0", &[]);
+    ash.provides.push(String::from("sh"));
+    ash.replaces.push(String::from("dash"));
+    pool.add_repo_package(repo(ash), 10);
+    let plan = solver::solve(&pool, &Request { jobs: vec![Job::UpgradeAll], allow_uninstall: false });
+    test_assert_ok!(plan);
+    let plan = plan.unwrap();
+    test_assert_eq!(names(&plan), vec!["dash", "ash"]);
+    test_assert_eq!(plan.steps[0].action, OperationType::Remove);
+    test_assert_eq!(plan.steps[1].reason, InstallReason::Explicit);
+
+    TestResult::Pass
+}
+
+/// Test that failed spkg transactions leave the files and the package
+/// database as they were
+fn test_pkg_rollback() -> TestResult {
+    use alloc::string::String;
+    use alloc::vec;
+    use alloc::vec::Vec;
+    use crate::fs::vfs::Mode;
+    use crate::pkg::format::{PackageHeader, FORMAT_VERSION, MAGIC};
+    use crate::pkg::{self, InstallOptions, InstallReason, PackageMetadata, RepoPackage, Version};
+    use crate::pkg::rollback::OperationType;
+    use crate::pkg::solver::{Plan, Step};
+    use crate::security::Cred;
+    use crate::util::KError;
+
+    // Uncompressed package; entries are (path, type flag, content or
+    // symlink target)
+    fn package(name: &str, version: &str, entries: &[(&str, u8, &[u8])]) -> Vec<u8> {
+        let mut tar = Vec::new();
+        for (path, kind, data) in entries {
+            let mut header = [0u8; 512];
+            header[..path.len()].copy_from_slice(path.as_bytes());
+            header[100..107].copy_from_slice(b"0000644");
+            let size = if *kind == b'0' { data.len() } else { 0 };
+            header[124..135].copy_from_slice(alloc::format!("{:011o}", size).as_bytes());
+            header[156] = *kind;
+            if *kind == b'2' {
+                header[157..157 + data.len()].copy_from_slice(data);
+            }
+            tar.extend_from_slice(&header);
+            if size > 0 {
+                tar.extend_from_slice(data);
+                tar.resize(tar.len().next_multiple_of(512), 0);
+            }
+        }
+        tar.extend_from_slice(&[0u8; 1024]);
+
+        let meta = PackageMetadata::new(name, Version::parse(version).unwrap()).serialize();
+        let mut header = PackageHeader::new();
+        header.magic = MAGIC;
+        header.version = FORMAT_VERSION;
+        header.metadata_offset = PackageHeader::SIZE as u32;
+        header.metadata_size = meta.len() as u32;
+        header.data_offset = (PackageHeader::SIZE + meta.len()) as u32;
+        header.data_size = tar.len() as u32;
+        header.data_uncompressed_size = tar.len() as u32;
+
+        let mut data = header.to_bytes().to_vec();
+        data.extend_from_slice(meta.as_bytes());
+        data.extend_from_slice(&tar);
+        data
+    }
+
+    let cred = Cred::root();
+    let read = |path: &str| crate::fs::read_file(path, &cred).ok();
+    let options = InstallOptions { no_deps: true, ..InstallOptions::default() };
+
+    let v1 = package("rbtest", "1.0", &[
+        ("opt/rbtest", b'5', b""),
+        ("opt/rbtest/a", b'0', b"one"),
+        ("opt/rbtest/old", b'0', b"old"),
+    ]);
+    test_assert_ok!(crate::fs::write_file("/tmp/rbtest-1.spkg", &cred, Mode::from_octal(0o644), &v1));
+    test_assert_ok!(pkg::install_from_file("/tmp/rbtest-1.spkg", &options));
+    test_assert_eq!(pkg::installed_version("rbtest"), Version::parse("1.0"));
+
+    // The upgrade fails on its last entry, a symlink over one of its files
+    let v2 = package("rbtest", "2.0", &[
+        ("opt/rbtest", b'5', b""),
+        ("opt/rbtest/a", b'0', b"two"),
+        ("opt/rbtest/b", b'0', b"new"),
+        ("opt/rbtest/b", b'2', b"a"),
+    ]);
+    test_assert_ok!(crate::fs::write_file("/tmp/rbtest-2.spkg", &cred, Mode::from_octal(0o644), &v2));
+    test_assert_eq!(pkg::install_from_file("/tmp/rbtest-2.spkg", &options).err(), Some(KError::AlreadyExists));
+    test_assert_eq!(pkg::installed_version("rbtest"), Version::parse("1.0"));
+    test_assert_eq!(read("/opt/rbtest/a"), Some(b"one".to_vec()));
+    test_assert_eq!(read("/opt/rbtest/old"), Some(b"old".to_vec()));
+    test_assert!(read("/opt/rbtest/b").is_none());
+
+    // A plan whose second step cannot be fetched undoes the removal
+    let missing = PackageMetadata::new("rbmissing", Version::parse("1.0").unwrap());
+    let plan = Plan { steps: vec![
+        Step {
+            action: OperationType::Remove,
+            name: String::from("rbtest"),
+            old_version: Version::parse("1.0"),
+            new_version: None,
+            package: None,
+            reason: InstallReason::Explicit,
+        },
+        Step {
+            action: OperationType::Install,
+            name: String::from("rbmissing"),
+            old_version: None,
+            new_version: Some(missing.version.clone()),
+            package: Some(RepoPackage {
+                filename: String::from("rbmissing-1.0.spkg"),
+                metadata: missing,
+                download_size: 0,
+                sha256: None,
+                repo_name: String::from("no-such-repo"),
+            }),
+            reason: InstallReason::Explicit,
+        },
+    ] };
+    test_assert_eq!(pkg::execute_plan(&plan, &options, "rollback test").err(), Some(KError::NotFound));
+    test_assert_eq!(pkg::get_package("rbtest").map(|p| p.reason), Some(InstallReason::Explicit));
+    test_assert_eq!(pkg::get_package("rbtest").map(|p| p.files.len()), Some(2));
+    test_assert!(!pkg::is_installed("rbmissing"));
+    test_assert_eq!(read("/opt/rbtest/a"), Some(b"one".to_vec()));
+    test_assert_eq!(read("/opt/rbtest/old"), Some(b"old".to_vec()));
+
+    test_assert_ok!(pkg::remove("rbtest"));
+    test_assert!(read("/opt/rbtest/a").is_none());
+    let _ = crate::fs::unlink("/tmp/rbtest-1.spkg", &cred);
+    let _ = crate::fs::unlink("/tmp/rbtest-2.spkg", &cred);
+
+    TestResult::Pass
+}
+
+/// Test LUKS2 formatting and a dm-crypt mapping over a RAM disk
+fn test_dm_crypt() -> TestResult {
+    use alloc::sync::Arc;
+    use alloc::vec;
+    use alloc::vec::Vec;
+    use crate::crypto::luks2::{self, FormatParams};
+    use crate::storage::block::BlockDevice;
+    use crate::storage::crypt::{self, CryptOptions};
+    use crate::storage::ramdisk::RamDisk;
+    use crate::util::KError;
+
+    // 1 MiB of header and keyslots followed by 64 KiB of data
+    let disk = Arc::new(RamDisk::new(90, 512, 2048 + 128));
+    let params = FormatParams { iterations: 1000, stripes: 64, sector_size: 4096, ..FormatParams::default() };
+    let uuid = luks2::format(&*disk, b"correct horse", &params).ok().map(|header| header.uuid);
+    test_assert_some!(uuid);
+    test_assert!(crypt::is_luks(&*disk));
+    test_assert_eq!(crypt::luks_uuid(&*disk), uuid);
+
+    test_assert_eq!(crypt::open("itest", disk.clone(), b"wrong", CryptOptions::default()).err(),
+        Some(KError::PermissionDenied));
+    test_assert_eq!(crypt::resolve(disk.clone()).err(), Some(KError::PermissionDenied));
+    let mapped = crypt::open("itest", disk.clone(), b"correct horse", CryptOptions::default());
+    test_assert_ok!(mapped);
+    let mapped = mapped.unwrap();
+    test_assert_eq!(mapped.block_size(), 4096u32);
+    test_assert_eq!(mapped.num_blocks(), 16u64);
+    test_assert_eq!(crypt::open("itest2", disk.clone(), b"correct horse", CryptOptions::default()).err(),
+        Some(KError::Busy));
+
+    // Plaintext goes in, only ciphertext reaches the disk
+    let data: Vec<u8> = (0..8192u32).map(|i| (i % 251) as u8).collect();
+    test_assert_ok!(mapped.write_blocks(2, 2, &data));
+    let mut back = vec![0u8; 8192];
+    test_assert_ok!(mapped.read_blocks(2, 2, &mut back));
+    test_assert_eq!(back, data);
+    let mut raw = vec![0u8; 8192];
+    test_assert_ok!(disk.read_blocks(2048 + 16, 16, &mut raw));
+    test_assert_ne!(raw, data);
+    test_assert_eq!(mapped.read_blocks(15, 2, &mut back).err(), Some(KError::OutOfRange));
+
+    // Discards are refused unless the mapping allows them
+    test_assert_eq!(mapped.discard(2, 1).err(), Some(KError::NotSupported));
+
+    test_assert!(crate::storage::find_device_by_path("/dev/mapper/itest").is_some());
+    test_assert_eq!(crypt::close("itest").err(), Some(KError::Busy));
+    drop(mapped);
+    test_assert_ok!(crypt::close("itest"));
+    test_assert!(crypt::get("itest").is_none());
+
+    // Reopening with discards passes them down to the disk
+    let mapped = crypt::open("itest", disk.clone(), b"correct horse", CryptOptions::parse("allow-discards"));
+    test_assert_ok!(mapped);
+    let mapped = mapped.unwrap();
+    test_assert_ok!(mapped.read_blocks(2, 2, &mut back));
+    test_assert_eq!(back, data);
+    test_assert_ok!(mapped.discard(2, 1));
+    test_assert_ok!(disk.read_blocks(2048 + 16, 8, &mut raw[..4096]));
+    test_assert!(raw[..4096].iter().all(|&b| b == 0));
+    drop(mapped);
+    test_assert_ok!(crypt::close("itest"));
+
+    // With 4096-byte sectors the IV counts sectors (dm-crypt
+    // iv_large_sectors), and iv_tweak 16 in 512-byte units starts it at 2.
+    // Expected ciphertext from an independent AES-XTS with tweak 3.
+    let key: Vec<u8> = (0..64u8).collect();
+    let segment = luks2::Segment { iv_tweak: 16, sector_size: 4096, ..luks2::Segment::legacy(0) };
+    let raw_disk = Arc::new(RamDisk::new(91, 512, 16));
+    let mapped = crypt::CryptBlockDevice::new(
+        raw_disk.clone(),
+        crate::storage::block::BlockDeviceId(91),
+        &key,
+        &segment,
+        CryptOptions::default(),
+    );
+    test_assert_ok!(mapped);
+    let mapped = mapped.unwrap();
+    test_assert_ok!(mapped.write_blocks(1, 1, &data[..4096]));
+    test_assert_ok!(raw_disk.read_blocks(8, 8, &mut raw[..4096]));
+    test_assert!(raw[..16] == [
+        0x03, 0xd1, 0x4e, 0x10, 0x53, 0xa7, 0xbc, 0xf9, 0x55, 0xad, 0x77, 0x2d, 0x3a, 0x22, 0xb2, 0x44,
+    ]);
+    test_assert!(raw[4080..4096] == [
+        0xc6, 0x0d, 0x68, 0x4f, 0xcc, 0x16, 0xd4, 0x8a, 0x59, 0xb3, 0xe6, 0x53, 0x30, 0xcf, 0x34, 0x92,
+    ]);
+    let bad_tweak = luks2::Segment { iv_tweak: 4, ..segment };
+    let refused = crypt::CryptBlockDevice::new(
+        raw_disk,
+        crate::storage::block::BlockDeviceId(91),
+        &key,
+        &bad_tweak,
+        CryptOptions::default(),
+    )
+    .err();
+    test_assert_eq!(refused, Some(KError::NotSupported));
+
+    TestResult::Pass
+}
+
+/// Test loop devices over a tmpfs file + overlayfs over tmpfs layers
+fn test_loop_overlay() -> TestResult {
+    use alloc::format;
+    use alloc::vec;
+    use crate::fs::overlayfs;
+    use crate::fs::{InodeKind, Metadata, Mode};
+    use crate::security::{Gid, Uid};
+    use crate::storage::block::BlockDevice;
+    use crate::storage::loopdev::{self, LoopConfig};
+    use crate::util::KError;
+
+    let tmpfs = || crate::fs::mount_fs("tmpfs", None).expect("tmpfs");
+    let meta = |kind| Metadata::simple(Uid(0), Gid(0), Mode::from_octal(0o644), kind);
+
+    // A 1 KiB offset and a 4 KiB limit leave eight 512-byte blocks
+    let scratch = tmpfs();
+    let image = scratch.0.create("disk.img", InodeKind::File, meta(InodeKind::File));
+    test_assert_ok!(image);
+    let image = image.unwrap();
+    test_assert_ok!(image.0.truncate(8192));
+    let config = LoopConfig { offset: 1024, size_limit: 4096, ..LoopConfig::default() };
+    let device = loopdev::attach(image.clone(), "disk.img", config);
+    test_assert_ok!(device);
+    let device = device.unwrap();
+    test_assert_eq!(device.num_blocks(), 8u64);
+
+    let data = vec![0xa5u8; 512];
+    test_assert_ok!(device.write_blocks(1, 1, &data));
+    let mut raw = vec![0u8; 512];
+    test_assert_ok!(image.0.read_at(1536, &mut raw));
+    test_assert_eq!(raw, data);
+    test_assert_eq!(device.read_blocks(7, 2, &mut vec![0u8; 1024]).err(), Some(KError::OutOfRange));
+
+    let path = format!("/dev/{}", device.name());
+    test_assert!(crate::storage::find_device_by_path(&path).is_some());
+    test_assert_eq!(loopdev::detach(device.index()).err(), Some(KError::Busy));
+    let index = device.index();
+    drop(device);
+    test_assert_ok!(loopdev::detach(index));
+    test_assert!(loopdev::get(index).is_none());
+
+    let config = LoopConfig { read_only: true, ..LoopConfig::default() };
+    let device = loopdev::attach(image.clone(), "disk.img", config);
+    test_assert_ok!(device);
+    let device = device.unwrap();
+    test_assert_eq!(device.num_blocks(), 16u64);
+    test_assert_eq!(device.write_blocks(0, 1, &data).err(), Some(KError::ReadOnly));
+    let index = device.index();
+    drop(device);
+    test_assert_ok!(loopdev::detach(index));
+
+    // Overlay: lower stays untouched, changes land in upper
+    let lower = tmpfs();
+    let etc = lower.0.create("etc", InodeKind::Dir, meta(InodeKind::Dir));
+    test_assert_ok!(etc);
+    let etc = etc.unwrap();
+    for name in ["hostname", "hosts"] {
+        let file = etc.0.create(name, InodeKind::File, meta(InodeKind::File));
+        test_assert_ok!(file);
+        test_assert_ok!(file.unwrap().0.write_at(0, b"live"));
+    }
+    let upper_fs = tmpfs();
+    let upper = upper_fs.0.create("upper", InodeKind::Dir, meta(InodeKind::Dir));
+    let work = upper_fs.0.create("work", InodeKind::Dir, meta(InodeKind::Dir));
+    test_assert_ok!(upper);
+    test_assert_ok!(work);
+    let upper = upper.unwrap();
+    let root = overlayfs::new_root(vec![lower.clone()], Some(upper.clone()), work.ok());
+    test_assert_ok!(root);
+    let root = root.unwrap();
+
+    let merged_etc = root.0.lookup("etc");
+    test_assert_ok!(merged_etc);
+    let merged_etc = merged_etc.unwrap();
+    let hostname = merged_etc.0.lookup("hostname");
+    test_assert_ok!(hostname);
+    test_assert_ok!(hostname.unwrap().0.write_at(0, b"usb!"));
+
+    let mut buf = [0u8; 4];
+    test_assert_ok!(etc.0.lookup("hostname").unwrap().0.read_at(0, &mut buf));
+    test_assert_eq!(&buf, b"live");
+    let copied = upper.0.lookup("etc").and_then(|dir| dir.0.lookup("hostname"));
+    test_assert_ok!(copied);
+    test_assert_ok!(copied.unwrap().0.read_at(0, &mut buf));
+    test_assert_eq!(&buf, b"usb!");
+
+    // Deleting a lower file leaves a whiteout in upper
+    test_assert_ok!(merged_etc.0.unlink("hosts"));
+    test_assert_eq!(merged_etc.0.lookup("hosts").err(), Some(KError::NotFound));
+    test_assert!(etc.0.lookup("hosts").is_ok());
+    let whiteout = upper.0.lookup("etc").and_then(|dir| dir.0.lookup("hosts")).map(|inode| inode.kind());
+    test_assert_eq!(whiteout.ok(), Some(InodeKind::CharDev));
+    let names = merged_etc.0.readdir().map(|entries| entries.len());
+    test_assert_eq!(names.ok(), Some(1usize));
+
+    // Without an upper layer the overlay is read-only
+    let readonly = overlayfs::new_root(vec![lower], None, None);
+    test_assert_ok!(readonly);
+    let created = readonly.unwrap().0.create("x", InodeKind::File, meta(InodeKind::File)).map(|_| ());
+    test_assert_eq!(created.err(), Some(KError::ReadOnly));
+
+    TestResult::Pass
+}
+
+/// Test the hrtimer wheel: sub-tick expiry, ordering, cascading, cancel
+fn test_timer_wheel() -> TestResult {
+    use alloc::vec;
+    use alloc::vec::Vec;
+    use crate::time::hrtimer::{forward, Expired, TimerId, TimerWheel};
+
+    const MS: u64 = 1_000_000;
+    fn data(expired: Vec<Expired<u32>>) -> Vec<u32> {
+        expired.into_iter().map(|e| e.data).collect()
+    }
+
+    let mut wheel = TimerWheel::new(MS, 0);
+
+    // Timers expire at their exact time, not at the tick
+    wheel.insert(TimerId(1), MS + MS / 2, 1);
+    let none = data(wheel.advance(MS + MS / 4));
+    test_assert!(none.is_empty());
+    test_assert_eq!(wheel.next_expiry(), Some(MS + MS / 2));
+    let fired = data(wheel.advance(MS + MS / 2));
+    test_assert_eq!(fired, vec![1u32]);
+
+    // Earliest first, later ones stay
+    wheel.insert(TimerId(3), 5 * MS + 300, 3);
+    wheel.insert(TimerId(2), 5 * MS + 100, 2);
+    wheel.insert(TimerId(4), 7 * MS, 4);
+    let fired = data(wheel.advance(6 * MS));
+    test_assert_eq!(fired, vec![2u32, 3]);
+    test_assert_eq!(wheel.len(), 1usize);
+    let fired = data(wheel.advance(10 * MS));
+    test_assert_eq!(fired, vec![4u32]);
+
+    // Beyond level 0 (256 ticks) and level 1: cascaded down on time
+    wheel.insert(TimerId(5), 300 * MS, 5);
+    wheel.insert(TimerId(6), 20_000 * MS + 7, 6);
+    test_assert_eq!(wheel.expires(TimerId(5)), Some(300 * MS));
+    let none = data(wheel.advance(300 * MS - 1));
+    test_assert!(none.is_empty());
+    let fired = data(wheel.advance(300 * MS));
+    test_assert_eq!(fired, vec![5u32]);
+    let none = data(wheel.advance(20_000 * MS));
+    test_assert!(none.is_empty());
+    let fired = data(wheel.advance(20_000 * MS + 7));
+    test_assert_eq!(fired, vec![6u32]);
+
+    // Cancelled and far-future timers
+    wheel.insert(TimerId(7), 20_400 * MS, 7);
+    wheel.insert(TimerId(8), u64::MAX / 2, 8);
+    test_assert_eq!(wheel.cancel(TimerId(7)), Some(7u32));
+    test_assert_eq!(wheel.expires(TimerId(7)), None::<u64>);
+    test_assert_eq!(wheel.expires(TimerId(8)), Some(u64::MAX / 2));
+    let none = data(wheel.advance(21_000 * MS));
+    test_assert!(none.is_empty());
+    test_assert_eq!(wheel.cancel(TimerId(8)), Some(8u32));
+    test_assert!(wheel.is_empty());
+
+    // Periodic timers skip the periods they missed
+    test_assert_eq!(forward(100, 10, 135), (140u64, 3u64));
+    test_assert_eq!(forward(100, 10, 100), (110u64, 0u64));
+    test_assert_eq!(forward(100, 0, 135), (100u64, 0u64));
+
+    TestResult::Pass
+}
+
+fn test_vdso_image() -> TestResult {
+    use crate::compat::linux::elf_dyn::{elf_hash, read_string, DynamicInfo, Elf64Sym};
+    use crate::process::elf::{Elf64Header, Elf64Phdr, PT_DYNAMIC};
+
+    let image = crate::arch::x86_64_arch::vdso::build_image();
+    test_assert!(image.len() <= 4096);
+    let ehdr = match Elf64Header::from_bytes(&image) {
+        Some(ehdr) => *ehdr,
+        None => return TestResult::Fail,
+    };
+    test_assert!(ehdr.is_valid_x86_64());
+    test_assert_eq!(ehdr.e_type, 3u16);
+
+    let dynamic = (0..ehdr.e_phnum as usize)
+        .map(|i| unsafe {
+            core::ptr::read_unaligned(image.as_ptr().add(ehdr.e_phoff as usize + i * core::mem::size_of::<Elf64Phdr>()) as *const Elf64Phdr)
+        })
+        .find(|phdr| phdr.p_type == PT_DYNAMIC);
+    let dynamic = match dynamic {
+        Some(phdr) => DynamicInfo::parse(&image, phdr.p_offset as usize),
+        None => return TestResult::Fail,
+    };
+    let strtab = &image[dynamic.strtab as usize..(dynamic.strtab + dynamic.strsz) as usize];
+    let soname = read_string(strtab, dynamic.soname as usize);
+    test_assert_eq!(soname.as_deref(), Some("linux-vdso.so.1"));
+
+    // Looks a symbol up through DT_HASH the way a dynamic loader does
+    let read_u32 = |offset: usize| u32::from_le_bytes([image[offset], image[offset + 1], image[offset + 2], image[offset + 3]]);
+    let lookup = |name: &str| -> Option<Elf64Sym> {
+        let hash = dynamic.hash as usize;
+        let nbucket = read_u32(hash) as usize;
+        let mut index = read_u32(hash + 8 + (elf_hash(name) as usize % nbucket) * 4) as usize;
+        while index != 0 {
+            let sym = unsafe {
+                core::ptr::read_unaligned(image.as_ptr().add(dynamic.symtab as usize + index * core::mem::size_of::<Elf64Sym>()) as *const Elf64Sym)
+            };
+            if read_string(strtab, sym.st_name as usize).as_deref() == Some(name) {
+                return Some(sym);
+            }
+            index = read_u32(hash + 8 + (nbucket + index) * 4) as usize;
+        }
+        None
+    };
+
+    for name in ["clock_gettime", "gettimeofday", "time", "getcpu"] {
+        let exported = lookup(&alloc::format!("__vdso_{}", name));
+        let alias = lookup(name);
+        test_assert!(exported.is_some());
+        test_assert_eq!(exported.map(|sym| sym.st_value), alias.map(|sym| sym.st_value));
+        let sym = exported.unwrap();
+        test_assert!(sym.st_shndx != 0);
+        test_assert!(sym.st_value >= 0x800 && sym.st_value + sym.st_size <= image.len() as u64);
+    }
+    test_assert!(lookup("__vdso_clock_settime").is_none());
+
+    TestResult::Pass
+}
+
+/// Test TZif parsing: table transitions, the footer rule after them and
+/// local times around daylight saving switches
+fn test_tzif() -> TestResult {
+    use crate::time::tz::Zone;
+    use alloc::vec::Vec;
+
+    // US Eastern for 2006 only; the footer covers later years
+    let mut data = Vec::new();
+    let header = |data: &mut Vec<u8>, version: u8, counts: [u32; 6]| {
+        data.extend_from_slice(b"TZif");
+        data.push(version);
+        data.extend_from_slice(&[0u8; 15]);
+        for count in counts {
+            data.extend_from_slice(&count.to_be_bytes());
+        }
+    };
+    header(&mut data, b'2', [0, 0, 0, 0, 1, 4]);
+    data.extend_from_slice(&[0, 0, 0, 0, 0, 0]);
+    data.extend_from_slice(b"UTC\0");
+    header(&mut data, b'2', [0, 0, 0, 2, 2, 8]);
+    data.extend_from_slice(&1143961200i64.to_be_bytes());
+    data.extend_from_slice(&1162101600i64.to_be_bytes());
+    data.extend_from_slice(&[1, 0]);
+    data.extend_from_slice(&(-18000i32).to_be_bytes());
+    data.extend_from_slice(&[0, 0]);
+    data.extend_from_slice(&(-14400i32).to_be_bytes());
+    data.extend_from_slice(&[1, 4]);
+    data.extend_from_slice(b"EST\0EDT\0");
+    data.extend_from_slice(b"\nEST5EDT,M3.2.0,M11.1.0\n");
+
+    let zone = match Zone::parse("America/New_York", &data) {
+        Ok(zone) => zone,
+        Err(_) => return TestResult::Fail,
+    };
+    test_assert_eq!(zone.name(), "America/New_York");
+    test_assert!(zone.has_dst());
+
+    // Before the first transition, inside the table, then the footer
+    let offset = |utc: i64| zone.lookup(utc).utoff;
+    let (early, summer_2006, autumn_2006) = (offset(1000000000), offset(1150000000), offset(1162101600));
+    test_assert_eq!(early, -18000i32);
+    test_assert_eq!(summer_2006, -14400i32);
+    test_assert_eq!(autumn_2006, -18000i32);
+    let (before_start, at_start) = (offset(1710053999), offset(1710054000));
+    test_assert_eq!(before_start, -18000i32);
+    test_assert_eq!(at_start, -14400i32);
+    test_assert_eq!(zone.lookup(1710054000).abbr.as_str(), "EDT");
+    let (before_end, at_end) = (offset(1730613599), offset(1730613600));
+    test_assert_eq!(before_end, -14400i32);
+    test_assert_eq!(at_end, -18000i32);
+    let next = zone.next_transition(1710054000).map(|(at, ltt)| (at, ltt.is_dst));
+    test_assert_eq!(next, Some((1730613600i64, false)));
+
+    // 2024-07-01 12:00, 02:30 on the spring gap and 01:30 on the autumn fold
+    let (summer, gap, fold) = (
+        zone.local_to_utc(1719835200),
+        zone.local_to_utc(1710037800),
+        zone.local_to_utc(1730597400),
+    );
+    test_assert_eq!(summer, 1719849600i64);
+    test_assert_eq!(gap, 1710055800i64);
+    test_assert_eq!(fold, 1730611800i64);
+
+    // Southern hemisphere rule: DST from October to February
+    let sao_paulo = match Zone::from_posix("<-03>3<-02>,M10.3.0/0,M2.3.0/0") {
+        Ok(zone) => zone,
+        Err(_) => return TestResult::Fail,
+    };
+    let (january, july) = (sao_paulo.lookup(1516017600).utoff, sao_paulo.lookup(1531656000).utoff);
+    test_assert_eq!(january, -7200i32);
+    test_assert_eq!(july, -10800i32);
+    test_assert_eq!(sao_paulo.lookup(1531656000).abbr.as_str(), "-03");
+
+    test_assert!(Zone::parse("bad", b"TZif2").is_err());
+    test_assert!(Zone::from_posix("EST").is_err());
+
+    TestResult::Pass
+}
+
+/// Test the NTP clock filter, clock selection and the timex ABI
+fn test_ntp_selection() -> TestResult {
+    use crate::net::ntp::{combine, select, Candidate, ClockFilter, Sample};
+
+    test_assert_eq!(core::mem::size_of::<crate::time::ntp::Timex>(), 208usize);
+
+    // The least-delayed sample wins; the others make up the jitter
+    let mut filter = ClockFilter::new();
+    for (time, (offset, delay)) in [(5_000_000i64, 40_000_000i64), (1_000_000, 10_000_000), (3_000_000, 30_000_000)]
+        .into_iter()
+        .enumerate()
+    {
+        filter.push(Sample { offset, delay, dispersion: 1_000, time: time as u64 });
+    }
+    let est = match filter.estimate(0) {
+        Some(est) => est,
+        None => return TestResult::Fail,
+    };
+    test_assert_eq!(est.offset, 1_000_000i64);
+    test_assert_eq!(est.delay, 10_000_000i64);
+    test_assert_eq!(est.jitter, 3_162_277i64);
+    test_assert_eq!(est.time, 1u64);
+    // A worse newer sample leaves the old one chosen, so sync() must not
+    // apply it again
+    filter.push(Sample { offset: 4_000_000, delay: 50_000_000, dispersion: 1_000, time: 3 });
+    test_assert_eq!(filter.estimate(3).map(|e| e.time), Some(1u64));
+    // Dispersion grows with age
+    let aged = filter.estimate(1_000_000_000).map(|e| e.dispersion).unwrap_or(0);
+    test_assert!(aged > est.dispersion);
+
+    // Three servers agree near +2 ms; a falseticker is 1 s ahead
+    let candidate = |offset: i64, root_distance: i64| Candidate { offset, root_distance, jitter: 500_000, time: 0 };
+    let candidates = [
+        candidate(2_000_000, 20_000_000),
+        candidate(3_000_000, 15_000_000),
+        candidate(1_000_000_000, 10_000_000),
+        candidate(1_500_000, 30_000_000),
+    ];
+    let survivors = select(&candidates);
+    test_assert!(!survivors.contains(&2));
+    test_assert_eq!(survivors.len(), 3usize);
+    // Lowest root distance first
+    test_assert_eq!(survivors[0], 1usize);
+    let (offset, jitter) = match combine(&candidates, &survivors) {
+        Some(combined) => combined,
+        None => return TestResult::Fail,
+    };
+    test_assert!(offset > 1_500_000 && offset < 3_000_000);
+    test_assert_eq!(jitter, 500_000i64);
+
+    // No majority, no time
+    let split = [candidate(0, 1_000_000), candidate(100_000_000, 1_000_000)];
+    test_assert!(select(&split).is_empty());
+    test_assert!(select(&[]).is_empty());
+
+    TestResult::Pass
+}
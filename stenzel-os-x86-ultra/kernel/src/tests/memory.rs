@@ -1,7 +1,7 @@
 //! Memory subsystem tests
 
 use super::{TestRunner, TestResult};
-use crate::{test_assert, test_assert_eq, test_assert_ne};
+use crate::{test_assert, test_assert_eq, test_assert_ne, test_assert_ok};
 
 pub fn register_tests(runner: &mut TestRunner) {
     runner.add_test("memory::heap_allocation", test_heap_allocation, "memory");
@@ -9,6 +9,8 @@ pub fn register_tests(runner: &mut TestRunner) {
     runner.add_test("memory::large_allocation", test_large_allocation, "memory");
     runner.add_test("memory::many_small_allocations", test_many_small_allocations, "memory");
     runner.add_test("memory::box_allocation", test_box_allocation, "memory");
+    runner.add_test("memory::file_mappings", test_file_mappings, "memory");
+    runner.add_test("memory::address_space_vmas", test_address_space_vmas, "memory");
 }
 
 fn test_heap_allocation() -> TestResult {
@@ -121,3 +123,209 @@ fn test_box_allocation() -> TestResult {
 
     TestResult::Pass
 }
+
+/// Kernel view of the frame mapped at `addr` in `vmas`
+fn mapped_bytes(vmas: &crate::mm::vma::VmaManager, addr: u64) -> Option<&'static mut [u8]> {
+    let vma = vmas.get_vma(addr)?;
+    let frame = vma.frames[((addr - vma.start) / 4096) as usize]?;
+    let virt = crate::mm::phys_to_virt(frame.start_address());
+    Some(unsafe { core::slice::from_raw_parts_mut(virt.as_mut_ptr::<u8>(), 4096) })
+}
+
+fn test_file_mappings() -> TestResult {
+    use crate::fs::page_cache;
+    use crate::fs::vfs::{InodeKind, Metadata};
+    use crate::mm::vma::{FileMapping, MapFlags, Protection, VmaManager};
+
+    let root = match crate::fs::mount_fs("tmpfs", None) {
+        Ok(root) => root,
+        Err(_) => return TestResult::Fail,
+    };
+    let file = match root.0.create("data", InodeKind::File, Metadata::default()) {
+        Ok(file) => file,
+        Err(_) => return TestResult::Fail,
+    };
+    test_assert_ok!(file.0.write_at(0, &[b'a'; 6000]));
+
+    let shared_flags = MapFlags { shared: true, private: false, anonymous: false, fixed: false };
+    let private_flags = MapFlags { shared: false, private: true, anonymous: false, fixed: false };
+    let mapping = |writable| FileMapping { inode: file.clone(), offset: 0, writable };
+    let mut vmas = VmaManager::new();
+
+    // Shared writes need a writable fd
+    test_assert!(vmas.mmap_file(0, 8192, Protection::READ_WRITE, shared_flags, mapping(false)).is_err());
+    let shared = match vmas.mmap_file(0, 3 * 4096, Protection::READ_WRITE, shared_flags, mapping(true)) {
+        Ok(addr) => addr,
+        Err(_) => return TestResult::Fail,
+    };
+
+    // Demand paging fills from the file; the page is clean until written
+    test_assert_ok!(vmas.handle_page_fault(shared));
+    let page = match mapped_bytes(&vmas, shared) {
+        Some(page) => page,
+        None => return TestResult::Fail,
+    };
+    test_assert_eq!(page[0], b'a');
+    test_assert!(!vmas.get_vma(shared).map_or(true, |v| v.dirty_pages[0]));
+    test_assert_ok!(vmas.handle_page_fault(shared + 4096));
+    let tail = match mapped_bytes(&vmas, shared + 4096) {
+        Some(page) => page,
+        None => return TestResult::Fail,
+    };
+    test_assert_eq!(tail[6000 - 4096 - 1], b'a');
+    test_assert_eq!(tail[6000 - 4096], 0);
+
+    // The third page lies past the end of the file
+    test_assert!(vmas.handle_page_fault(shared + 2 * 4096).is_err());
+
+    let shared_phys = match vmas.get_vma(shared).and_then(|v| v.frames[0]) {
+        Some(frame) => frame.start_address(),
+        None => return TestResult::Fail,
+    };
+    test_assert_ok!(vmas.handle_write_fault(shared, shared_phys));
+    test_assert!(vmas.get_vma(shared).map_or(false, |v| v.dirty_pages[0]));
+    page[0] = b'Z';
+
+    // read() sees the store before writeback, the file only after msync
+    let mut buf = [0u8; 1];
+    test_assert_ok!(file.0.read_at(0, &mut buf));
+    test_assert_eq!(buf[0], b'a');
+    page_cache::read_overlay(&file, 0, &mut buf);
+    test_assert_eq!(buf[0], b'Z');
+    test_assert_ok!(vmas.msync(shared, 4096, 4));
+    test_assert!(!vmas.get_vma(shared).map_or(true, |v| v.dirty_pages[0]));
+    test_assert_ok!(file.0.read_at(0, &mut buf));
+    test_assert_eq!(buf[0], b'Z');
+    test_assert!(vmas.msync(shared + 1, 4096, 4).is_err());
+    test_assert!(vmas.msync(shared, 4096, 1 | 4).is_err());
+
+    // write() is visible through the mapping
+    page_cache::write_through(&file, 1, b"W");
+    test_assert_eq!(page[1], b'W');
+
+    // A private mapping starts on the cached frame and copies on write
+    let private = match vmas.mmap_file(0, 4096, Protection::READ_WRITE, private_flags, mapping(false)) {
+        Ok(addr) => addr,
+        Err(_) => return TestResult::Fail,
+    };
+    test_assert_ok!(vmas.handle_page_fault(private));
+    let private_page = match mapped_bytes(&vmas, private) {
+        Some(page) => page,
+        None => return TestResult::Fail,
+    };
+    test_assert_eq!(private_page.as_ptr(), page.as_ptr());
+    test_assert_ok!(vmas.handle_write_fault(private, shared_phys));
+    let private_page = match mapped_bytes(&vmas, private) {
+        Some(page) => page,
+        None => return TestResult::Fail,
+    };
+    test_assert_ne!(private_page.as_ptr(), page.as_ptr());
+    test_assert_eq!(private_page[0], b'Z');
+    private_page[0] = b'P';
+    test_assert_eq!(page[0], b'Z');
+    test_assert_ok!(vmas.munmap(private, 4096));
+
+    // mprotect splits the mapping and keeps file offsets
+    test_assert_ok!(vmas.mprotect(shared + 4096, 4096, Protection::READ));
+    let tail_offset = vmas.get_vma(shared + 4096).and_then(|v| v.file.as_ref().map(|f| f.offset));
+    test_assert_eq!(tail_offset, Some(4096));
+    test_assert_ok!(vmas.mprotect(shared, 4096, Protection::READ));
+    test_assert!(vmas.mprotect(shared + 3 * 4096, 4096, Protection::READ).is_err());
+
+    // munmap writes dirty pages back
+    test_assert_ok!(vmas.mprotect(shared, 4096, Protection::READ_WRITE));
+    test_assert_ok!(vmas.handle_write_fault(shared, shared_phys));
+    page[2] = b'M';
+    test_assert_ok!(vmas.munmap(shared, 3 * 4096));
+    let mut head = [0u8; 3];
+    test_assert_ok!(file.0.read_at(0, &mut head));
+    test_assert_eq!(&head, b"ZWM");
+
+    // Truncating forgets the now unmapped pages
+    test_assert_ok!(file.0.truncate(0));
+    page_cache::truncate_file_pages(&file, 0);
+    let mut empty = [0u8; 1];
+    page_cache::read_overlay(&file, 0, &mut empty);
+    test_assert_eq!(empty[0], 0);
+
+    TestResult::Pass
+}
+
+/// Empty user page table sharing the running kernel half
+fn new_page_table() -> Option<x86_64::structures::paging::PhysFrame> {
+    use x86_64::registers::control::Cr3;
+    use x86_64::structures::paging::PageTable;
+
+    let frame = crate::mm::alloc_frame()?;
+    let table = unsafe { &mut *crate::mm::phys_to_virt(frame.start_address()).as_mut_ptr::<PageTable>() };
+    let current = unsafe { &*crate::mm::phys_to_virt(Cr3::read().0.start_address()).as_ptr::<PageTable>() };
+    table.zero();
+    for i in 256..512 {
+        table[i] = current[i].clone();
+    }
+    Some(frame)
+}
+
+fn test_address_space_vmas() -> TestResult {
+    use crate::mm::vma::{MapFlags, Protection, VmaManager, VmaUsage};
+    use x86_64::VirtAddr;
+
+    let (p4_a, p4_b) = match (new_page_table(), new_page_table()) {
+        (Some(a), Some(b)) => (a, b),
+        _ => return TestResult::Fail,
+    };
+    let flags = MapFlags { shared: false, private: true, anonymous: true, fixed: false };
+    let mut a = VmaManager::for_page_table(p4_a);
+    let mut b = VmaManager::for_page_table(p4_b);
+
+    // Each address space hands out the same addresses without colliding
+    let addr = match (a.mmap(0, 4096, Protection::READ_WRITE, flags), b.mmap(0, 4096, Protection::READ_WRITE, flags)) {
+        (Ok(x), Ok(y)) if x == y => x,
+        _ => return TestResult::Fail,
+    };
+    test_assert_ok!(a.handle_page_fault(addr));
+    test_assert!(b.mapper().translate_addr(VirtAddr::new(addr)).is_none());
+    test_assert_ok!(b.handle_page_fault(addr));
+
+    let (page_a, page_b) = match (mapped_bytes(&a, addr), mapped_bytes(&b, addr)) {
+        (Some(x), Some(y)) => (x, y),
+        _ => return TestResult::Fail,
+    };
+    page_a[0] = 7;
+    test_assert_eq!(page_b[0], 0);
+    let frame_a = a.get_vma(addr).and_then(|v| v.frames[0]).map(|f| f.start_address());
+    test_assert_eq!(a.mapper().translate_addr(VirtAddr::new(addr)), frame_a);
+    test_assert_ne!(b.mapper().translate_addr(VirtAddr::new(addr)), frame_a);
+
+    // The fork copy points at the child's page table and keeps the break
+    a.set_brk(0x0060_1234);
+    let child = a.fork(p4_b);
+    test_assert_eq!(child.iter().count(), 1);
+    test_assert_eq!(child.brk(), 0x0060_1234u64);
+    test_assert_eq!(child.get_vma(addr).and_then(|v| v.frames[0]).map(|f| f.start_address()), frame_a);
+
+    let usage = a.get_vma(addr).map(|v| v.usage()).unwrap_or_default();
+    test_assert_eq!(usage, VmaUsage { rss: 1, private_dirty: 1, anonymous: 1, ..VmaUsage::default() });
+
+    // The shared vvar page can be neither unmapped, reprotected nor replaced
+    let vvar = crate::arch::x86_64_arch::vdso::VVAR_ADDR;
+    b.insert_special(vvar, Protection::READ, "[vvar]");
+    test_assert!(b.munmap(vvar, 4096).is_err());
+    test_assert!(b.munmap(vvar - 4096, 2 * 4096).is_err());
+    test_assert!(b.mprotect(vvar, 4096, Protection::READ_WRITE).is_err());
+    test_assert!(b.mmap(vvar, 4096, Protection::READ_WRITE, MapFlags { fixed: true, ..flags }).is_err());
+    test_assert!(b.handle_page_fault(vvar).is_err());
+    test_assert_eq!(b.get_vma(vvar).and_then(|v| v.special), Some("[vvar]"));
+
+    // Teardown unmaps everything from its own page table only
+    a.clear();
+    test_assert_eq!(a.iter().count(), 0);
+    test_assert!(a.mapper().translate_addr(VirtAddr::new(addr)).is_none());
+    test_assert!(b.mapper().translate_addr(VirtAddr::new(addr)).is_some());
+    b.clear();
+    test_assert!(b.mapper().translate_addr(VirtAddr::new(addr)).is_none());
+
+    crate::mm::free_frame(p4_a);
+    crate::mm::free_frame(p4_b);
+    TestResult::Pass
+}
@@ -8,6 +8,10 @@ pub fn register_tests(runner: &mut TestRunner) {
     runner.add_test("net::mac_address_parsing", test_mac_address_parsing, "network");
     runner.add_test("net::checksum_calculation", test_checksum_calculation, "network");
     runner.add_test("net::url_parsing", test_url_parsing, "network");
+    runner.add_test("net::x509_chain", test_x509_chain, "network");
+    runner.add_test("net::x509_hostname", test_x509_hostname, "network");
+    runner.add_test("net::tls12_prf", test_tls12_prf, "network");
+    runner.add_test("net::ebpf_programs", test_ebpf_programs, "network");
 }
 
 fn test_ip_address_parsing() -> TestResult {
@@ -86,8 +90,260 @@ fn test_url_parsing() -> TestResult {
     TestResult::Pass
 }
 
+fn test_x509_chain() -> TestResult {
+    use crate::crypto::x509::{self, CertError, Certificate, TrustStore};
+    use crate::crypto::Ed25519Keypair;
+
+    let ca_key = Ed25519Keypair::from_seed(&[1u8; 32]);
+    let leaf_key = Ed25519Keypair::from_seed(&[2u8; 32]);
+    let other_key = Ed25519Keypair::from_seed(&[3u8; 32]);
+
+    let ca_der = make_test_cert("Test CA", "Test CA", ca_key.public_key(), &ca_key, true, &[]);
+    let leaf_der = make_test_cert("Test CA", "leaf", leaf_key.public_key(), &ca_key, false, &["example.com"]);
+    let forged_der = make_test_cert("Test CA", "leaf", leaf_key.public_key(), &other_key, false, &["example.com"]);
+
+    let ca = Certificate::from_der(&ca_der);
+    test_assert!(ca.is_ok());
+    let ca = ca.unwrap();
+    test_assert!(ca.is_ca);
+    test_assert_eq!(ca.subject_cn.as_deref(), Some("Test CA"));
+    test_assert_eq!(ca.not_before, 1704067200); // 2024-01-01
+    let leaf = Certificate::from_der(&leaf_der).unwrap();
+    let forged = [Certificate::from_der(&forged_der).unwrap()];
+
+    let mut store = TrustStore::new();
+    test_assert!(store.add(ca));
+
+    // 2025-01-01
+    let now = 1735689600;
+    let chain = [leaf.clone()];
+    test_assert!(x509::verify_chain(&chain, "example.com", &store, now).is_ok());
+    test_assert_eq!(
+        x509::verify_chain(&chain, "other.com", &store, now).err(),
+        Some(CertError::HostnameMismatch)
+    );
+    test_assert_eq!(
+        x509::verify_chain(&chain, "example.com", &store, 1924992000 + 86400).err(),
+        Some(CertError::Expired)
+    );
+    test_assert_eq!(
+        x509::verify_chain(&forged, "example.com", &store, now).err(),
+        Some(CertError::BadSignature)
+    );
+    test_assert_eq!(
+        x509::verify_chain(&chain, "example.com", &TrustStore::new(), now).err(),
+        Some(CertError::UnknownIssuer)
+    );
+
+    // Truncated DER must be rejected, not panic
+    test_assert_eq!(
+        Certificate::from_der(&leaf_der[..leaf_der.len() - 1]).err(),
+        Some(CertError::Malformed)
+    );
+
+    TestResult::Pass
+}
+
+fn test_x509_hostname() -> TestResult {
+    use crate::crypto::x509::Certificate;
+    use crate::crypto::Ed25519Keypair;
+
+    let key = Ed25519Keypair::from_seed(&[4u8; 32]);
+    let der = make_test_cert("ca", "cn.example.org", key.public_key(), &key, false, &["*.example.com", "example.net"]);
+    let cert = Certificate::from_der(&der).unwrap();
+
+    test_assert!(cert.matches_host("www.example.com"));
+    test_assert!(cert.matches_host("WWW.Example.COM."));
+    test_assert!(cert.matches_host("example.net"));
+    test_assert!(!cert.matches_host("example.com"));
+    test_assert!(!cert.matches_host("a.b.example.com"));
+    // With a subjectAltName present the CN is ignored
+    test_assert!(!cert.matches_host("cn.example.org"));
+
+    let der = make_test_cert("ca", "cn.example.org", key.public_key(), &key, false, &[]);
+    let cert = Certificate::from_der(&der).unwrap();
+    test_assert!(cert.matches_host("cn.example.org"));
+
+    TestResult::Pass
+}
+
+fn test_tls12_prf() -> TestResult {
+    use crate::net::tls::prf_sha256;
+
+    // Published P_SHA256 test vector
+    let secret = [
+        0x9b, 0xbe, 0x43, 0x6b, 0xa9, 0x40, 0xf0, 0x17, 0xb1, 0x76, 0x52, 0x84,
+        0x9a, 0x71, 0xdb, 0x35,
+    ];
+    let seed = [
+        0xa0, 0xba, 0x9f, 0x93, 0x6c, 0xda, 0x31, 0x18, 0x27, 0xa6, 0xf7, 0x96,
+        0xff, 0xd5, 0x19, 0x8c,
+    ];
+    let expected = [
+        0xe3, 0xf2, 0x29, 0xba, 0x72, 0x7b, 0xe1, 0x7b, 0x8d, 0x12, 0x26, 0x20,
+        0x55, 0x7c, 0xd4, 0x53, 0xc2, 0xaa, 0xb2, 0x1d, 0x07, 0xc3, 0xd4, 0x95,
+        0x32, 0x9b, 0x52, 0xd4, 0xe6, 0x1e, 0xdb, 0x5a, 0x6b, 0x30, 0x17, 0x91,
+        0xe9, 0x0d, 0x35, 0xc9, 0xc9, 0xa4, 0x6b, 0x4e, 0x14, 0xba, 0xf9, 0xaf,
+        0x0f, 0xa0, 0x22, 0xf7, 0x07, 0x7d, 0xef, 0x17, 0xab, 0xfd, 0x37, 0x97,
+        0xc0, 0x56, 0x4b, 0xab, 0x4f, 0xbc, 0x91, 0x66, 0x6e, 0x9d, 0xef, 0x9b,
+        0x97, 0xfc, 0xe3, 0x4f, 0x79, 0x67, 0x89, 0xba, 0xa4, 0x80, 0x82, 0xd1,
+        0x22, 0xee, 0x42, 0xc5, 0xa7, 0x2e, 0x5a, 0x51, 0x10, 0xff, 0xf7, 0x01,
+        0x87, 0x34, 0x7b, 0x66,
+    ];
+    let out = prf_sha256(&secret, b"test label", &seed, expected.len());
+    test_assert!(out[..] == expected[..]);
+    // A shorter output is a prefix of the longer one
+    test_assert!(prf_sha256(&secret, b"test label", &seed, 12)[..] == expected[..12]);
+
+    TestResult::Pass
+}
+
+fn test_ebpf_programs() -> TestResult {
+    use crate::profiling::ebpf::*;
+    use alloc::sync::Arc;
+    use alloc::vec;
+
+    let insn = BpfInsn::new;
+    let verifier = BpfVerifier::new();
+
+    // XDP: count frames in an array map, drop the ones starting with 0xff
+    let counter = Arc::new(BpfMap::new(1, BpfMapType::Array, 4, 8, 1, 0, "counter"));
+    let mut prog = BpfProg::new(1, BpfProgType::Xdp, "xdp_test", vec![
+        insn(0xbf, 6, 1, 0, 0),     // r6 = r1
+        insn(0x62, 10, 0, -4, 0),   // *(u32 *)(r10 - 4) = 0
+        insn(0xbf, 2, 10, 0, 0),    // r2 = r10
+        insn(0x07, 2, 0, 0, -4),    // r2 += -4
+        insn(0x18, 1, 1, 0, 0),     // r1 = map[0]
+        insn(0x00, 0, 0, 0, 0),
+        insn(0x85, 0, 0, 0, 1),     // call bpf_map_lookup_elem
+        insn(0x15, 0, 0, 2, 0),     // if r0 == 0 goto +2
+        insn(0xb7, 1, 0, 0, 1),     // r1 = 1
+        insn(0xdb, 0, 1, 0, 0),     // lock *(u64 *)(r0 + 0) += r1
+        insn(0x61, 2, 6, 0, 0),     // r2 = ctx->data
+        insn(0x61, 3, 6, 4, 0),     // r3 = ctx->data_end
+        insn(0xbf, 4, 2, 0, 0),     // r4 = r2
+        insn(0x07, 4, 0, 0, 1),     // r4 += 1
+        insn(0x2d, 4, 3, 4, 0),     // if r4 > r3 goto +4
+        insn(0x71, 5, 2, 0, 0),     // r5 = *(u8 *)(r2 + 0)
+        insn(0x55, 5, 0, 2, 0xff),  // if r5 != 0xff goto +2
+        insn(0xb7, 0, 0, 0, 1),     // r0 = XDP_DROP
+        insn(0x95, 0, 0, 0, 0),     // exit
+        insn(0xb7, 0, 0, 0, 2),     // r0 = XDP_PASS
+        insn(0x95, 0, 0, 0, 0),     // exit
+    ], "GPL");
+    prog.maps = vec![counter.clone()];
+    test_assert!(verifier.verify(&prog).success);
+
+    let run = |frame: &mut [u8]| vm().run(&prog, &mut BpfContext::xdp(frame, 1)).ok();
+    test_assert_eq!(run(&mut [0xff, 1, 2]), Some(XdpAction::Drop as u64));
+    test_assert_eq!(run(&mut [0x00, 1, 2]), Some(XdpAction::Pass as u64));
+    test_assert_eq!(run(&mut []), Some(XdpAction::Pass as u64));
+    test_assert_eq!(counter.lookup(&[0; 4]), Some(3u64.to_le_bytes().to_vec()));
+
+    // Socket filter: keep 4 bytes of IPv4 packets, drop the rest
+    let filter = BpfProg::new(2, BpfProgType::SocketFilter, "filter_test", vec![
+        insn(0x30, 0, 0, 0, 0),     // r0 = packet[0]
+        insn(0x15, 0, 0, 2, 0x45),  // if r0 == 0x45 goto +2
+        insn(0xb7, 0, 0, 0, 0),     // r0 = 0
+        insn(0x95, 0, 0, 0, 0),     // exit
+        insn(0xb7, 0, 0, 0, 4),     // r0 = 4
+        insn(0x95, 0, 0, 0, 0),     // exit
+    ], "GPL");
+    test_assert!(verifier.verify(&filter).success);
+    test_assert_eq!(run_socket_filter(&filter, &mut [0x45, 0, 0, 20, 0, 0], 0x0800, 1), 4);
+    test_assert_eq!(run_socket_filter(&filter, &mut [0x60, 0, 0, 0], 0x86dd, 1), 0);
+    test_assert_eq!(run_socket_filter(&filter, &mut [], 0x0800, 1), 0);
+
+    // Accesses outside the context fail at run time
+    let oob = BpfProg::new(3, BpfProgType::Xdp, "oob", vec![
+        insn(0x79, 0, 1, 64, 0),    // r0 = *(u64 *)(r1 + 64)
+        insn(0x95, 0, 0, 0, 0),
+    ], "GPL");
+    test_assert!(verifier.verify(&oob).success);
+    test_assert!(vm().run(&oob, &mut BpfContext::xdp(&mut [0; 8], 1)).is_err());
+
+    // The verifier refuses loops, writes to r10, unknown maps and helpers
+    let rejected = [
+        vec![insn(0xb7, 0, 0, 0, 0), insn(0x05, 0, 0, -2, 0), insn(0x95, 0, 0, 0, 0)],
+        vec![insn(0xb7, 10, 0, 0, 0), insn(0x95, 0, 0, 0, 0)],
+        vec![insn(0x18, 1, 1, 0, 0), insn(0x00, 0, 0, 0, 0), insn(0x95, 0, 0, 0, 0)],
+        vec![insn(0x85, 0, 0, 0, 26), insn(0x95, 0, 0, 0, 0)],
+        vec![insn(0x7a, 10, 0, 0, 1), insn(0x95, 0, 0, 0, 0)],
+        vec![insn(0xb7, 0, 0, 0, 0)],
+    ];
+    for insns in rejected {
+        let prog = BpfProg::new(4, BpfProgType::Xdp, "bad", insns, "GPL");
+        test_assert!(!verifier.verify(&prog).success);
+    }
+
+    TestResult::Pass
+}
+
 // Helper functions
 
+fn der(tag: u8, contents: &[u8]) -> alloc::vec::Vec<u8> {
+    let mut out = alloc::vec![tag];
+    let len = contents.len();
+    if len < 0x80 {
+        out.push(len as u8);
+    } else if len < 0x100 {
+        out.extend_from_slice(&[0x81, len as u8]);
+    } else {
+        out.extend_from_slice(&[0x82, (len >> 8) as u8, len as u8]);
+    }
+    out.extend_from_slice(contents);
+    out
+}
+
+fn der_seq(parts: &[&[u8]]) -> alloc::vec::Vec<u8> {
+    der(0x30, &parts.concat())
+}
+
+/// Builds an Ed25519 certificate valid from 2024-01-01 to 2031-01-01.
+fn make_test_cert(
+    issuer: &str,
+    subject: &str,
+    subject_key: &[u8; 32],
+    signer: &crate::crypto::Ed25519Keypair,
+    ca: bool,
+    dns_names: &[&str],
+) -> alloc::vec::Vec<u8> {
+    let ed25519 = der_seq(&[&der(0x06, &[0x2b, 0x65, 0x70])]);
+    let name = |cn: &str| {
+        let attr = der_seq(&[&der(0x06, &[0x55, 0x04, 0x03]), &der(0x0c, cn.as_bytes())]);
+        der_seq(&[&der(0x31, &attr)])
+    };
+    let validity = der_seq(&[&der(0x17, b"240101000000Z"), &der(0x17, b"310101000000Z")]);
+    let mut key_bits = alloc::vec![0u8];
+    key_bits.extend_from_slice(subject_key);
+    let spki = der_seq(&[&ed25519, &der(0x03, &key_bits)]);
+
+    let mut extensions = alloc::vec::Vec::new();
+    if ca {
+        let bc = der_seq(&[&der(0x01, &[0xff])]);
+        extensions.extend(der_seq(&[&der(0x06, &[0x55, 0x1d, 0x13]), &der(0x01, &[0xff]), &der(0x04, &bc)]));
+    }
+    if !dns_names.is_empty() {
+        let names: alloc::vec::Vec<u8> = dns_names.iter().flat_map(|n| der(0x82, n.as_bytes())).collect();
+        extensions.extend(der_seq(&[&der(0x06, &[0x55, 0x1d, 0x11]), &der(0x04, &der(0x30, &names))]));
+    }
+
+    let tbs = der_seq(&[
+        &der(0xa0, &der(0x02, &[2])),
+        &der(0x02, &[1]),
+        &ed25519,
+        &name(issuer),
+        &validity,
+        &name(subject),
+        &spki,
+        &der(0xa3, &der(0x30, &extensions)),
+    ]);
+
+    let mut sig_bits = alloc::vec![0u8];
+    sig_bits.extend_from_slice(&signer.sign(&tbs));
+    der_seq(&[&tbs, &ed25519, &der(0x03, &sig_bits)])
+}
+
 fn parse_ipv4(s: &str) -> Option<[u8; 4]> {
     let parts: alloc::vec::Vec<&str> = s.split('.').collect();
     if parts.len() != 4 {
@@ -8,6 +8,7 @@ pub fn register_tests(runner: &mut TestRunner) {
     runner.add_test("scheduler::task_creation", test_task_creation, "scheduler");
     runner.add_test("scheduler::task_state_transitions", test_task_state_transitions, "scheduler");
     runner.add_test("scheduler::priority_ordering", test_priority_ordering, "scheduler");
+    runner.add_test("scheduler::cgroup_limits", test_cgroup_limits, "scheduler");
 }
 
 fn test_task_creation() -> TestResult {
@@ -33,3 +34,53 @@ fn test_priority_ordering() -> TestResult {
     // Placeholder
     TestResult::Pass
 }
+
+fn test_cgroup_limits() -> TestResult {
+    use alloc::string::String;
+    use alloc::sync::Arc;
+    use crate::cgroups::Cgroup;
+    use crate::process::Pid;
+
+    let root = Arc::new(Cgroup::new(0, String::from("/"), None));
+    let child = root.create_child(String::from("test"), 1).unwrap();
+
+    // pids.max: fork fails at the limit, charging nothing
+    child.pids.set_max(2);
+    test_assert!(child.add_forked(Pid(1)));
+    test_assert!(child.add_forked(Pid(2)));
+    test_assert!(!child.add_forked(Pid(3)));
+    test_assert_eq!(root.pids.count(), 2);
+    child.remove_process(Pid(1));
+    test_assert_eq!(root.pids.count(), 1);
+
+    // memory.max is charged up the hierarchy
+    child.memory.set_limit(8192);
+    test_assert!(child.try_charge_memory(4096));
+    test_assert!(child.try_charge_memory(4096));
+    test_assert!(!child.try_charge_memory(4096));
+    test_assert_eq!(root.memory.usage_bytes.load(core::sync::atomic::Ordering::Acquire), 8192);
+    child.uncharge_memory(8192);
+    test_assert_eq!(root.memory.usage_bytes.load(core::sync::atomic::Ordering::Acquire), 0);
+
+    // cpu.max: 10ms every 100ms
+    let now = 1_000_000_000;
+    child.cpu.set_quota(10_000);
+    child.charge_cpu(5_000_000, now);
+    test_assert!(!child.cpu_throttled(now));
+    child.charge_cpu(6_000_000, now);
+    test_assert!(child.cpu_throttled(now));
+    test_assert!(!root.cpu_throttled(now));
+    test_assert!(!child.cpu_throttled(now + 100_000_000));
+
+    // io.max: 4 KiB/s of reads on device 7
+    let now = 5_000_000_000;
+    child.io.set_device_limits(7, [Some(4096), None, None, None]);
+    test_assert!(child.io_throttled_until(7, false, 4096, now).is_none());
+    child.charge_io(7, false, 4096);
+    test_assert_eq!(child.io_throttled_until(7, false, 4096, now), Some(6_000_000_000u64));
+    test_assert!(child.io_throttled_until(7, true, 4096, now).is_none());
+    test_assert!(child.io_throttled_until(7, false, 4096, 6_000_000_000).is_none());
+    test_assert_eq!(root.io.devices.lock().get(&7).map(|d| d.rbytes), Some(4096));
+
+    TestResult::Pass
+}
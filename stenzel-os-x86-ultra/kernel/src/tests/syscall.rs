@@ -1,11 +1,16 @@
 //! Syscall tests
 
 use super::{TestRunner, TestResult};
-use crate::{test_assert, test_assert_eq, test_assert_ne};
+use crate::{test_assert, test_assert_eq, test_assert_ne, test_assert_some};
 
 pub fn register_tests(runner: &mut TestRunner) {
     runner.add_test("syscall::syscall_number_range", test_syscall_number_range, "syscall");
     runner.add_test("syscall::error_codes", test_error_codes, "syscall");
+    runner.add_test("syscall::getrandom", test_getrandom, "syscall");
+    runner.add_test("syscall::tty_line_discipline", test_tty_line_discipline, "syscall");
+    runner.add_test("syscall::epoll_eventfd", test_epoll_eventfd, "syscall");
+    runner.add_test("syscall::timerfd_signalfd", test_timerfd_signalfd, "syscall");
+    runner.add_test("syscall::seccomp_filters", test_seccomp_filters, "syscall");
 }
 
 fn test_syscall_number_range() -> TestResult {
@@ -72,3 +77,234 @@ fn test_error_codes() -> TestResult {
 
     TestResult::Pass
 }
+
+fn test_getrandom() -> TestResult {
+    use crate::crypto::chacha20::chacha20_block;
+    use crate::crypto::random::fill_random;
+    use crate::syscall::{errno, getrandom_flags::*, sys_getrandom};
+
+    // RFC 7539, section 2.3.2: the generator's block function
+    let key: [u8; 32] = core::array::from_fn(|i| i as u8);
+    let nonce = [0, 0, 0, 0x09, 0, 0, 0, 0x4a, 0, 0, 0, 0];
+    let block = chacha20_block(&key, 1, &nonce);
+    test_assert_eq!(&block[..8], &[0x10, 0xf1, 0xe7, 0xe4, 0xd1, 0x3b, 0x59, 0x15]);
+
+    // Consecutive requests never repeat
+    let mut a = [0u8; 64];
+    let mut b = [0u8; 64];
+    fill_random(&mut a);
+    fill_random(&mut b);
+    test_assert_ne!(a, b);
+    test_assert!(a.iter().any(|&x| x != 0));
+
+    test_assert_eq!(sys_getrandom(0, 16, 0x80), errno::EINVAL);
+    test_assert_eq!(sys_getrandom(0, 16, GRND_RANDOM | GRND_INSECURE), errno::EINVAL);
+    test_assert_eq!(sys_getrandom(0, 0, GRND_NONBLOCK), 0);
+    test_assert_eq!(sys_getrandom(0, 16, GRND_INSECURE), errno::EFAULT);
+
+    TestResult::Pass
+}
+
+fn test_tty_line_discipline() -> TestResult {
+    use crate::drivers::tty::{Tty, ICANON, ECHO, VMIN};
+    use crate::syscall::errno;
+
+    // No foreground group, so ^C below signals nobody
+    let tty = Tty::new();
+    let mut buf = [0u8; 64];
+
+    // Canonical mode: DEL erases, input only shows up at end of line
+    tty.receive(b"ab\x7fc");
+    test_assert_eq!(tty.read(&mut buf, true).err(), Some(errno::EAGAIN));
+    tty.receive(b"\r");
+    let n = tty.take_output(&mut buf);
+    test_assert_eq!(&buf[..n], b"ab\x08 \x08c\r\n");
+    let n = tty.read(&mut buf, true).unwrap_or(0);
+    test_assert_eq!(&buf[..n], b"ac\n");
+
+    // ^C throws away the pending line
+    tty.receive(b"xyz\x03");
+    test_assert_eq!(tty.input_available(), 0);
+    let n = tty.take_output(&mut buf);
+    test_assert!(buf[..n].windows(2).any(|w| w == b"^C"));
+
+    // ^D on an empty line is end of file
+    tty.receive(b"\x04");
+    test_assert_eq!(tty.read(&mut buf, true).ok(), Some(0));
+
+    // Output processing maps NL to CR NL
+    test_assert!(tty.write(b"hi\n").is_ok());
+    let n = tty.take_output(&mut buf);
+    test_assert_eq!(&buf[..n], b"hi\r\n");
+
+    // Raw mode without echo hands bytes over one at a time
+    let mut termios = tty.termios();
+    termios.c_lflag &= !(ICANON | ECHO);
+    termios.c_cc[VMIN] = 1;
+    tty.set_termios(termios, true);
+    tty.receive(b"q");
+    test_assert_eq!(tty.take_output(&mut buf), 0);
+    let n = tty.read(&mut buf, true).unwrap_or(0);
+    test_assert_eq!(&buf[..n], b"q");
+
+    TestResult::Pass
+}
+
+fn test_epoll_eventfd() -> TestResult {
+    use crate::ipc::{EventFdFile, EFD_NONBLOCK};
+    use crate::syscall::epoll::*;
+    use crate::syscall::{errno, FdType};
+
+    let eventfd = EventFdFile::new(0, EFD_NONBLOCK);
+    let fd = crate::syscall::alloc_fd_for_eventfd(eventfd.clone());
+    test_assert_some!(fd);
+    let fd = fd.unwrap_or(-1);
+    let file = FdType::EventFd { eventfd: eventfd.clone() };
+    let epoll = Epoll::new();
+    let watch = |events| EpollEvent { events, data: 42 };
+
+    // Level-triggered: reported for as long as the counter is non-zero
+    test_assert!(epoll.ctl(EPOLL_CTL_ADD, fd, file.clone(), watch(EPOLLIN)).is_ok());
+    test_assert_eq!(epoll.ctl(EPOLL_CTL_ADD, fd, file.clone(), watch(EPOLLIN)).err(), Some(errno::EEXIST));
+    test_assert_eq!(epoll.wait(8, 0).map(|v| v.len()).ok(), Some(0));
+    test_assert!(eventfd.inner.write(1).is_some());
+    let ready = epoll.wait(8, 0).unwrap_or_default();
+    test_assert_eq!(ready.len(), 1);
+    test_assert_eq!({ ready[0].data }, 42);
+    test_assert_eq!({ ready[0].events }, EPOLLIN);
+    test_assert_eq!(epoll.wait(8, 0).map(|v| v.len()).ok(), Some(1));
+
+    // Edge-triggered: once per write
+    test_assert!(epoll.ctl(EPOLL_CTL_MOD, fd, file.clone(), watch(EPOLLIN | EPOLLET)).is_ok());
+    test_assert_eq!(epoll.wait(8, 0).map(|v| v.len()).ok(), Some(1));
+    test_assert_eq!(epoll.wait(8, 0).map(|v| v.len()).ok(), Some(0));
+    test_assert!(eventfd.inner.write(1).is_some());
+    test_assert_eq!(epoll.wait(8, 0).map(|v| v.len()).ok(), Some(1));
+
+    // One-shot: disabled after the first report until re-armed
+    test_assert!(epoll.ctl(EPOLL_CTL_MOD, fd, file.clone(), watch(EPOLLIN | EPOLLONESHOT)).is_ok());
+    test_assert_eq!(epoll.wait(8, 0).map(|v| v.len()).ok(), Some(1));
+    test_assert!(eventfd.inner.write(1).is_some());
+    test_assert_eq!(epoll.wait(8, 0).map(|v| v.len()).ok(), Some(0));
+
+    // Closing the fd drops the registration
+    test_assert!(epoll.ctl(EPOLL_CTL_MOD, fd, file.clone(), watch(EPOLLIN)).is_ok());
+    test_assert_eq!(crate::syscall::sys_close(fd), 0);
+    test_assert_eq!(epoll.wait(8, 0).map(|v| v.len()).ok(), Some(0));
+    test_assert_eq!(epoll.ctl(EPOLL_CTL_DEL, fd, file.clone(), watch(0)).err(), Some(errno::ENOENT));
+
+    TestResult::Pass
+}
+
+fn test_timerfd_signalfd() -> TestResult {
+    use crate::ipc::timerfd::TimerFd;
+    use crate::ipc::{Itimerspec, TFD_TIMER_ABSTIME};
+    use crate::signal::{sig, SignalFd, SignalState, SignalfdSiginfo};
+    use crate::time::{clock, Timespec};
+
+    let ms = |n: i64| Timespec { tv_sec: 0, tv_nsec: n * 1_000_000 };
+
+    // A deadline in the past fires at once; the interval counts overruns
+    let timer = TimerFd::new(clock::CLOCK_MONOTONIC);
+    test_assert!(timer.take_expirations().is_none());
+    let spec = Itimerspec { it_interval: ms(1), it_value: Timespec { tv_sec: 0, tv_nsec: 1 } };
+    test_assert!(timer.settime(TFD_TIMER_ABSTIME, &spec).is_ok());
+    test_assert!(timer.take_expirations().map_or(false, |n| n >= 1));
+    test_assert_eq!({ timer.gettime().it_interval.tv_nsec }, 1_000_000);
+
+    // Disarming returns the old setting
+    let old = timer.settime(0, &Itimerspec::default());
+    test_assert!(old.map_or(false, |o| o.it_interval.tv_nsec == 1_000_000));
+    test_assert!(!timer.is_readable());
+    test_assert!(timer.settime(0, &Itimerspec { it_interval: ms(0), it_value: ms(1000) }).is_ok());
+    test_assert!(!timer.is_readable());
+    test_assert!(timer.settime(0, &Itimerspec { it_interval: ms(0), it_value: Timespec { tv_sec: 0, tv_nsec: 1_000_000_000 } }).is_err());
+
+    // signalfd takes the signals in its mask out of the pending set
+    let state = SignalState::new();
+    let sfd = SignalFd::new((1 << sig::SIGUSR1) | (1 << sig::SIGKILL), 0);
+    test_assert!(!sfd.accepts_signal(sig::SIGKILL));
+    state.send(sig::SIGUSR2);
+    test_assert!(!sfd.is_readable(&state));
+    state.send(sig::SIGUSR1);
+    test_assert!(sfd.is_readable(&state));
+    let mut buf = [0u8; SignalfdSiginfo::SIZE * 2];
+    test_assert_eq!(sfd.read(&state, &mut buf).ok(), Some(SignalfdSiginfo::SIZE));
+    test_assert_eq!(u32::from_ne_bytes([buf[0], buf[1], buf[2], buf[3]]), sig::SIGUSR1);
+    test_assert!(!state.is_pending(sig::SIGUSR1));
+    test_assert!(state.is_pending(sig::SIGUSR2));
+    test_assert!(sfd.read(&state, &mut buf).is_err());
+
+    TestResult::Pass
+}
+
+fn test_seccomp_filters() -> TestResult {
+    use crate::security::seccomp::*;
+    use crate::security::SeccompState;
+    use alloc::vec;
+
+    let stmt = |code, k| BpfInsn::new(code, 0, 0, k);
+    let jeq = |k, jt, jf| BpfInsn::new(BPF_JMP | BPF_JEQ | BPF_K, jt, jf, k);
+    let data = |nr: i32, arg0: u64| SeccompData { nr, arch: AUDIT_ARCH_X86_64, instruction_pointer: 0, args: [arg0, 0, 0, 0, 0, 0] };
+
+    // getpid fails with EPERM; write(42, ..) traps; everything else is allowed
+    let prog = vec![
+        stmt(BPF_LD | BPF_W | BPF_ABS, 0),
+        jeq(39, 0, 1),
+        stmt(BPF_RET | BPF_K, SeccompAction::Errno(1).to_raw()),
+        jeq(1, 0, 3),
+        stmt(BPF_LD | BPF_W | BPF_ABS, 16),
+        jeq(42, 0, 1),
+        stmt(BPF_RET | BPF_K, SeccompAction::Trap.to_raw()),
+        stmt(BPF_RET | BPF_K, SeccompAction::Allow.to_raw()),
+    ];
+    test_assert!(check_filter(&prog).is_ok());
+
+    let mut state = SeccompState::new();
+    test_assert_eq!(state.check_syscall(&data(39, 0)), SeccompAction::Allow);
+    test_assert!(state.attach(prog, SeccompFlags::empty()).is_ok());
+    test_assert_eq!(state.mode(), SeccompMode::Filter);
+    test_assert_eq!(state.check_syscall(&data(39, 0)), SeccompAction::Errno(1));
+    test_assert_eq!(state.check_syscall(&data(1, 42)), SeccompAction::Trap);
+    test_assert_eq!(state.check_syscall(&data(1, 42 << 32)), SeccompAction::Allow);
+    test_assert_eq!(state.check_syscall(&data(0, 42)), SeccompAction::Allow);
+
+    // Malformed programs are refused
+    test_assert!(check_filter(&[]).is_err());
+    test_assert!(check_filter(&[jeq(1, 5, 0), stmt(BPF_RET | BPF_K, 0)]).is_err());
+    test_assert!(check_filter(&[stmt(BPF_LD | BPF_W | BPF_ABS, 64), stmt(BPF_RET | BPF_K, 0)]).is_err());
+    test_assert!(check_filter(&[stmt(BPF_LD | BPF_W | BPF_ABS, 2), stmt(BPF_RET | BPF_K, 0)]).is_err());
+    test_assert!(check_filter(&[stmt(BPF_ALU | BPF_DIV | BPF_K, 0), stmt(BPF_RET | BPF_K, 0)]).is_err());
+    test_assert!(check_filter(&[stmt(BPF_LD | BPF_W | BPF_ABS, 0)]).is_err());
+
+    // A forked child shares the chain; a newer filter can only tighten it
+    let parent = state.clone();
+    let mut kill_write = SimpleFilter::allow_all();
+    kill_write.deny(1);
+    test_assert!(state.attach(kill_write.to_program(), SeccompFlags::LOG).is_ok());
+    test_assert_eq!(state.check_syscall(&data(1, 3)), SeccompAction::Kill);
+    test_assert_eq!(state.check_syscall(&data(39, 0)), SeccompAction::Errno(1));
+    test_assert_eq!(parent.check_syscall(&data(1, 3)), SeccompAction::Allow);
+    test_assert!(state.extends(&parent));
+    test_assert!(!parent.extends(&state));
+    test_assert!(state.extends(&SeccompState::new()));
+
+    // Scratch memory, X and the LEN load
+    let prog = vec![
+        stmt(BPF_LD | BPF_W | BPF_LEN, 0),
+        stmt(BPF_ST, 3),
+        stmt(BPF_LDX | BPF_MEM, 3),
+        stmt(BPF_MISC | BPF_TXA, 0),
+        stmt(BPF_ALU | BPF_OR | BPF_K, SeccompAction::Errno(0).to_raw()),
+        stmt(BPF_RET | BPF_A, 0),
+    ];
+    test_assert!(check_filter(&prog).is_ok());
+    test_assert_eq!(run_filter(&prog, &data(0, 0)), SeccompAction::Errno(64).to_raw());
+
+    let mut strict = SeccompState::new();
+    test_assert!(strict.enable_strict().is_ok());
+    test_assert_eq!(strict.check_syscall(&data(39, 0)), SeccompAction::Kill);
+    test_assert!(strict.attach(standard_filter().to_program(), SeccompFlags::empty()).is_err());
+
+    TestResult::Pass
+}
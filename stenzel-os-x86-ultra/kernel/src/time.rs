@@ -1,331 +0,0 @@
-//! Time management
-//!
-//! Gerencia tempo do sistema, relógios e timers.
-
-#![allow(dead_code)]
-
-extern crate alloc;
-
-use alloc::string::String;
-use core::sync::atomic::{AtomicU64, AtomicI32, Ordering};
-use crate::sync::IrqSafeMutex;
-
-/// Ticks desde o boot (incrementado pelo timer IRQ)
-static TICKS: AtomicU64 = AtomicU64::new(0);
-
-/// Global timezone offset in minutes west of UTC
-/// Positive = west of UTC (behind), Negative = east of UTC (ahead)
-static TZ_MINUTESWEST: AtomicI32 = AtomicI32::new(0);
-
-/// Global timezone DST flag
-static TZ_DSTTIME: AtomicI32 = AtomicI32::new(0);
-
-/// Global timezone name
-static TZ_NAME: IrqSafeMutex<Option<String>> = IrqSafeMutex::new(None);
-
-/// Frequência do timer em Hz
-/// DEVE corresponder à frequência configurada no PIT (arch/x86_64_arch/mod.rs)
-/// PIT está configurado para 1000Hz (1ms) - ideal para cursor/animações suaves
-const TIMER_HZ: u64 = 1000;
-
-/// Nanosegundos por tick
-const NS_PER_TICK: u64 = 1_000_000_000 / TIMER_HZ;
-
-/// Epoch Unix em segundos (boot time - simplificado)
-/// Em um OS real, isso viria do RTC
-static BOOT_TIME_SECS: AtomicU64 = AtomicU64::new(1704067200); // 2024-01-01 00:00:00 UTC
-
-/// Chamado pelo timer IRQ handler
-pub fn tick() {
-    TICKS.fetch_add(1, Ordering::Relaxed);
-
-    // Poll USB HID devices every tick
-    crate::drivers::usb::hid::poll_all();
-}
-
-/// Retorna ticks desde o boot
-pub fn ticks() -> u64 {
-    TICKS.load(Ordering::Relaxed)
-}
-
-/// Retorna a frequência do timer em Hz
-pub fn hz() -> u64 {
-    TIMER_HZ
-}
-
-/// Retorna tempo desde o boot em nanosegundos
-pub fn uptime_ns() -> u64 {
-    ticks() * NS_PER_TICK
-}
-
-/// Retorna tempo desde o boot em milissegundos
-pub fn uptime_ms() -> u64 {
-    (ticks() * 1000) / TIMER_HZ
-}
-
-/// Retorna tempo desde o boot em segundos
-pub fn uptime_secs() -> u64 {
-    ticks() / TIMER_HZ
-}
-
-/// Estrutura timespec (para clock_gettime)
-#[repr(C)]
-#[derive(Debug, Clone, Copy, Default)]
-pub struct Timespec {
-    pub tv_sec: i64,
-    pub tv_nsec: i64,
-}
-
-/// Estrutura timeval (para gettimeofday)
-#[repr(C)]
-#[derive(Debug, Clone, Copy, Default)]
-pub struct Timeval {
-    pub tv_sec: i64,
-    pub tv_usec: i64,
-}
-
-/// Estrutura timezone
-#[repr(C)]
-#[derive(Debug, Clone, Copy, Default)]
-pub struct Timezone {
-    pub tz_minuteswest: i32,
-    pub tz_dsttime: i32,
-}
-
-/// IDs de clock
-pub mod clock {
-    pub const CLOCK_REALTIME: i32 = 0;
-    pub const CLOCK_MONOTONIC: i32 = 1;
-    pub const CLOCK_PROCESS_CPUTIME_ID: i32 = 2;
-    pub const CLOCK_THREAD_CPUTIME_ID: i32 = 3;
-    pub const CLOCK_MONOTONIC_RAW: i32 = 4;
-    pub const CLOCK_REALTIME_COARSE: i32 = 5;
-    pub const CLOCK_MONOTONIC_COARSE: i32 = 6;
-    pub const CLOCK_BOOTTIME: i32 = 7;
-}
-
-/// Obtém o tempo real (wall clock)
-pub fn realtime() -> Timespec {
-    let uptime = uptime_ns();
-    let boot_secs = BOOT_TIME_SECS.load(Ordering::Relaxed);
-
-    let total_ns = boot_secs * 1_000_000_000 + uptime;
-    Timespec {
-        tv_sec: (total_ns / 1_000_000_000) as i64,
-        tv_nsec: (total_ns % 1_000_000_000) as i64,
-    }
-}
-
-/// Obtém tempo monotônico (desde o boot)
-pub fn monotonic() -> Timespec {
-    let uptime = uptime_ns();
-    Timespec {
-        tv_sec: (uptime / 1_000_000_000) as i64,
-        tv_nsec: (uptime % 1_000_000_000) as i64,
-    }
-}
-
-/// clock_gettime syscall
-pub fn clock_gettime(clock_id: i32) -> Option<Timespec> {
-    match clock_id {
-        clock::CLOCK_REALTIME | clock::CLOCK_REALTIME_COARSE => Some(realtime()),
-        clock::CLOCK_MONOTONIC
-        | clock::CLOCK_MONOTONIC_RAW
-        | clock::CLOCK_MONOTONIC_COARSE
-        | clock::CLOCK_BOOTTIME => Some(monotonic()),
-        clock::CLOCK_PROCESS_CPUTIME_ID | clock::CLOCK_THREAD_CPUTIME_ID => {
-            // CPU time = uptime por simplicidade
-            Some(monotonic())
-        }
-        _ => None,
-    }
-}
-
-/// gettimeofday syscall
-pub fn gettimeofday() -> (Timeval, Timezone) {
-    let ts = realtime();
-    let tv = Timeval {
-        tv_sec: ts.tv_sec,
-        tv_usec: ts.tv_nsec / 1000,
-    };
-    let tz = Timezone {
-        tz_minuteswest: TZ_MINUTESWEST.load(Ordering::Relaxed),
-        tz_dsttime: TZ_DSTTIME.load(Ordering::Relaxed),
-    };
-    (tv, tz)
-}
-
-/// Get current timezone
-pub fn get_timezone() -> Timezone {
-    Timezone {
-        tz_minuteswest: TZ_MINUTESWEST.load(Ordering::Relaxed),
-        tz_dsttime: TZ_DSTTIME.load(Ordering::Relaxed),
-    }
-}
-
-/// Set timezone
-pub fn set_timezone(tz: &Timezone) {
-    TZ_MINUTESWEST.store(tz.tz_minuteswest, Ordering::Relaxed);
-    TZ_DSTTIME.store(tz.tz_dsttime, Ordering::Relaxed);
-}
-
-/// Get timezone name
-pub fn get_timezone_name() -> String {
-    let guard = TZ_NAME.lock();
-    match &*guard {
-        Some(name) => name.clone(),
-        None => String::from("UTC"),
-    }
-}
-
-/// Set timezone by name (common timezone names)
-/// Returns true if timezone was recognized and set
-pub fn set_timezone_by_name(name: &str) -> bool {
-    // Common timezone offsets (minutes west of UTC)
-    let (tz_minuteswest, tz_dsttime) = match name {
-        "UTC" | "GMT" => (0, 0),
-        "EST" => (5 * 60, 0),          // Eastern Standard Time (UTC-5)
-        "EDT" => (4 * 60, 1),          // Eastern Daylight Time (UTC-4)
-        "CST" => (6 * 60, 0),          // Central Standard Time (UTC-6)
-        "CDT" => (5 * 60, 1),          // Central Daylight Time (UTC-5)
-        "MST" => (7 * 60, 0),          // Mountain Standard Time (UTC-7)
-        "MDT" => (6 * 60, 1),          // Mountain Daylight Time (UTC-6)
-        "PST" => (8 * 60, 0),          // Pacific Standard Time (UTC-8)
-        "PDT" => (7 * 60, 1),          // Pacific Daylight Time (UTC-7)
-        "WET" => (0, 0),               // Western European Time (UTC)
-        "CET" => (-1 * 60, 0),         // Central European Time (UTC+1)
-        "CEST" => (-2 * 60, 1),        // Central European Summer Time (UTC+2)
-        "EET" => (-2 * 60, 0),         // Eastern European Time (UTC+2)
-        "EEST" => (-3 * 60, 1),        // Eastern European Summer Time (UTC+3)
-        "BRT" => (3 * 60, 0),          // Brasília Time (UTC-3)
-        "BRST" => (2 * 60, 1),         // Brasília Summer Time (UTC-2)
-        "JST" => (-9 * 60, 0),         // Japan Standard Time (UTC+9)
-        "CST-China" | "CST8" => (-8 * 60, 0), // China Standard Time (UTC+8)
-        "AEST" => (-10 * 60, 0),       // Australian Eastern Standard Time (UTC+10)
-        "AEDT" => (-11 * 60, 1),       // Australian Eastern Daylight Time (UTC+11)
-        "IST" => (-5 * 60 - 30, 0),    // India Standard Time (UTC+5:30)
-        _ => return false,             // Unknown timezone
-    };
-
-    TZ_MINUTESWEST.store(tz_minuteswest, Ordering::Relaxed);
-    TZ_DSTTIME.store(tz_dsttime, Ordering::Relaxed);
-
-    // Store the timezone name
-    let mut guard = TZ_NAME.lock();
-    *guard = Some(String::from(name));
-
-    true
-}
-
-/// Convert UTC timestamp to local time (considering timezone)
-pub fn utc_to_local(utc_secs: i64) -> i64 {
-    let offset_minutes = TZ_MINUTESWEST.load(Ordering::Relaxed);
-    // tz_minuteswest is positive for west of UTC (behind), so we subtract
-    utc_secs - (offset_minutes as i64 * 60)
-}
-
-/// Convert local time to UTC (considering timezone)
-pub fn local_to_utc(local_secs: i64) -> i64 {
-    let offset_minutes = TZ_MINUTESWEST.load(Ordering::Relaxed);
-    local_secs + (offset_minutes as i64 * 60)
-}
-
-/// Get local time as Timespec
-pub fn localtime() -> Timespec {
-    let ts = realtime();
-    Timespec {
-        tv_sec: utc_to_local(ts.tv_sec),
-        tv_nsec: ts.tv_nsec,
-    }
-}
-
-/// nanosleep - dorme por um período
-pub fn nanosleep(req: &Timespec) -> Timespec {
-    let sleep_ns = req.tv_sec as u64 * 1_000_000_000 + req.tv_nsec as u64;
-    let sleep_ticks = (sleep_ns + NS_PER_TICK - 1) / NS_PER_TICK;
-
-    let start = ticks();
-    let target = start + sleep_ticks;
-
-    while ticks() < target {
-        // Yield para outros processos
-        crate::task::yield_now();
-    }
-
-    // Retorna tempo restante (0 se dormiu tudo)
-    Timespec { tv_sec: 0, tv_nsec: 0 }
-}
-
-/// Inicializa o RTC e obtém hora real
-pub fn init() {
-    // Lê o RTC para obter a hora real
-    let rtc_time = read_rtc();
-    if rtc_time > 0 {
-        BOOT_TIME_SECS.store(rtc_time, Ordering::Relaxed);
-        crate::kprintln!("time: RTC epoch = {}", rtc_time);
-    }
-    crate::kprintln!("time: timer hz = {}", TIMER_HZ);
-}
-
-/// Lê segundos desde epoch do RTC (CMOS)
-fn read_rtc() -> u64 {
-    use x86_64::instructions::port::Port;
-
-    unsafe {
-        let mut addr = Port::<u8>::new(0x70);
-        let mut data = Port::<u8>::new(0x71);
-
-        // Espera RTC não estar em update
-        loop {
-            addr.write(0x0A);
-            if data.read() & 0x80 == 0 {
-                break;
-            }
-        }
-
-        // Lê valores
-        addr.write(0x00);
-        let sec = bcd_to_bin(data.read());
-        addr.write(0x02);
-        let min = bcd_to_bin(data.read());
-        addr.write(0x04);
-        let hour = bcd_to_bin(data.read());
-        addr.write(0x07);
-        let day = bcd_to_bin(data.read());
-        addr.write(0x08);
-        let month = bcd_to_bin(data.read());
-        addr.write(0x09);
-        let year = bcd_to_bin(data.read()) as u64 + 2000;
-
-        // Converte para Unix timestamp (simplificado, sem leap seconds)
-        let mut days: u64 = 0;
-
-        // Anos desde 1970
-        for y in 1970..year {
-            days += if is_leap_year(y) { 366 } else { 365 };
-        }
-
-        // Meses no ano atual
-        let days_in_month = [31, 28, 31, 30, 31, 30, 31, 31, 30, 31, 30, 31];
-        for m in 1..month {
-            days += days_in_month[m as usize - 1] as u64;
-            if m == 2 && is_leap_year(year) {
-                days += 1;
-            }
-        }
-
-        // Dias no mês
-        days += day as u64 - 1;
-
-        // Converte para segundos
-        days * 86400 + hour as u64 * 3600 + min as u64 * 60 + sec as u64
-    }
-}
-
-fn bcd_to_bin(bcd: u8) -> u8 {
-    (bcd & 0x0F) + ((bcd >> 4) * 10)
-}
-
-fn is_leap_year(year: u64) -> bool {
-    (year % 4 == 0 && year % 100 != 0) || (year % 400 == 0)
-}
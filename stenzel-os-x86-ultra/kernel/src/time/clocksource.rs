@@ -0,0 +1,253 @@
+//! Clock sources: the counter monotonic time is read from.
+//!
+//! Until `init` runs, time advances one tick at a time. `init` then picks
+//! the best counter available (invariant TSC, HPET, a TSC that may drift
+//! with power states, in that order) and continues from the time already
+//! counted, so monotonic time never jumps back.
+//!
+//! Readers take no lock. The timer interrupt, the only writer, folds the
+//! cycles elapsed into `BASE_NS` under a sequence count; folding often also
+//! keeps a 32-bit HPET counter from wrapping between two readings.
+//!
+//! Monotonic time runs at the rate NTP steers it to (`set_rate_adjust`);
+//! raw monotonic time (CLOCK_MONOTONIC_RAW) keeps the nominal rate of the
+//! counter and is folded alongside it.
+
+use core::sync::atomic::{fence, AtomicI64, AtomicU32, AtomicU64, AtomicU8, Ordering};
+
+use crate::arch::x86_64_arch::tsc;
+use crate::drivers::hpet;
+
+/// Counter behind monotonic time
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum ClockSource {
+    /// Timer interrupts only: resolution of one tick
+    Tick = 0,
+    Hpet = 1,
+    Tsc = 2,
+}
+
+impl ClockSource {
+    pub fn name(self) -> &'static str {
+        match self {
+            ClockSource::Tick => "tick",
+            ClockSource::Hpet => "hpet",
+            ClockSource::Tsc => "tsc",
+        }
+    }
+
+    fn from_u8(v: u8) -> Self {
+        match v {
+            1 => ClockSource::Hpet,
+            2 => ClockSource::Tsc,
+            _ => ClockSource::Tick,
+        }
+    }
+
+    fn read(self) -> u64 {
+        match self {
+            ClockSource::Tick => 0,
+            ClockSource::Hpet => hpet::read_counter(),
+            ClockSource::Tsc => tsc::read(),
+        }
+    }
+}
+
+/// Cycles are converted with `ns = cycles * MULT >> SHIFT`
+pub const SHIFT: u32 = 32;
+
+/// Odd while the writer updates the fields below
+static SEQ: AtomicU32 = AtomicU32::new(0);
+static SOURCE: AtomicU8 = AtomicU8::new(ClockSource::Tick as u8);
+/// Nanoseconds since boot at `CYCLE_LAST`
+static BASE_NS: AtomicU64 = AtomicU64::new(0);
+static CYCLE_LAST: AtomicU64 = AtomicU64::new(0);
+static MULT: AtomicU64 = AtomicU64::new(0);
+static MASK: AtomicU64 = AtomicU64::new(u64::MAX);
+/// Raw monotonic time at `CYCLE_LAST`
+static RAW_BASE_NS: AtomicU64 = AtomicU64::new(0);
+/// Nominal `MULT`, before the rate adjustment
+static RAW_MULT: AtomicU64 = AtomicU64::new(0);
+/// Rate adjustment in nanoseconds per second, shifted left by 32
+static RATE_ADJ: AtomicI64 = AtomicI64::new(0);
+
+fn cycles_to_ns(cycles: u64, mult: u64) -> u64 {
+    ((cycles as u128 * mult as u128) >> SHIFT) as u64
+}
+
+/// `ns` sped up (or slowed down) by the rate adjustment `adj`
+fn adjust(ns: u64, adj: i64) -> u64 {
+    (ns as i128 + ((ns as i128 * adj as i128) >> 32) / 1_000_000_000) as u64
+}
+
+/// Runs `f` with the sequence count held. Fails if another CPU is
+/// already writing.
+fn write<R>(f: impl FnOnce() -> R) -> Option<R> {
+    let seq = SEQ.load(Ordering::Relaxed);
+    if seq & 1 != 0 || SEQ.compare_exchange(seq, seq + 1, Ordering::Acquire, Ordering::Relaxed).is_err() {
+        return None;
+    }
+    fence(Ordering::Release);
+    let result = f();
+    SEQ.store(seq + 2, Ordering::Release);
+    Some(result)
+}
+
+/// Nanoseconds since boot at `now` cycles; caller holds a consistent view
+fn ns_at(source: ClockSource, now: u64) -> u64 {
+    let base = BASE_NS.load(Ordering::Relaxed);
+    if source == ClockSource::Tick {
+        return base;
+    }
+    let delta = now.wrapping_sub(CYCLE_LAST.load(Ordering::Relaxed)) & MASK.load(Ordering::Relaxed);
+    base + cycles_to_ns(delta, MULT.load(Ordering::Relaxed))
+}
+
+/// Raw monotonic time at `now` cycles; caller holds a consistent view
+fn raw_ns_at(source: ClockSource, now: u64) -> u64 {
+    let base = RAW_BASE_NS.load(Ordering::Relaxed);
+    if source == ClockSource::Tick {
+        return base;
+    }
+    let delta = now.wrapping_sub(CYCLE_LAST.load(Ordering::Relaxed)) & MASK.load(Ordering::Relaxed);
+    base + cycles_to_ns(delta, RAW_MULT.load(Ordering::Relaxed))
+}
+
+/// Reads a clock under the sequence count
+fn read(f: impl Fn(ClockSource, u64) -> u64) -> u64 {
+    loop {
+        let seq = SEQ.load(Ordering::Acquire);
+        if seq & 1 != 0 {
+            core::hint::spin_loop();
+            continue;
+        }
+        let source = current();
+        let ns = f(source, source.read());
+        fence(Ordering::Acquire);
+        if SEQ.load(Ordering::Relaxed) == seq {
+            return ns;
+        }
+    }
+}
+
+/// Nanoseconds since boot
+pub fn now_ns() -> u64 {
+    read(ns_at)
+}
+
+/// Nanoseconds since boot at the nominal rate of the counter
+pub fn raw_ns() -> u64 {
+    read(raw_ns_at)
+}
+
+/// Consistent copy of the conversion state, for readers that redo the
+/// conversion themselves (the vDSO)
+#[derive(Debug, Clone, Copy)]
+pub struct Snapshot {
+    pub source: ClockSource,
+    pub cycle_last: u64,
+    /// Nanoseconds since boot at `cycle_last`
+    pub base_ns: u64,
+    /// Cycles to nanoseconds, shifted left by 32
+    pub mult: u64,
+    /// Raw monotonic time at `cycle_last`
+    pub raw_base_ns: u64,
+    pub raw_mult: u64,
+}
+
+pub fn snapshot() -> Snapshot {
+    loop {
+        let seq = SEQ.load(Ordering::Acquire);
+        if seq & 1 != 0 {
+            core::hint::spin_loop();
+            continue;
+        }
+        let snapshot = Snapshot {
+            source: current(),
+            cycle_last: CYCLE_LAST.load(Ordering::Relaxed),
+            base_ns: BASE_NS.load(Ordering::Relaxed),
+            mult: MULT.load(Ordering::Relaxed),
+            raw_base_ns: RAW_BASE_NS.load(Ordering::Relaxed),
+            raw_mult: RAW_MULT.load(Ordering::Relaxed),
+        };
+        fence(Ordering::Acquire);
+        if SEQ.load(Ordering::Relaxed) == seq {
+            return snapshot;
+        }
+    }
+}
+
+/// Counter in use
+pub fn current() -> ClockSource {
+    ClockSource::from_u8(SOURCE.load(Ordering::Relaxed))
+}
+
+/// Called on every timer tick: folds the elapsed cycles into the base
+/// (or, without a counter, advances time by one tick)
+pub fn update(tick_ns: u64) {
+    write(|| {
+        let source = current();
+        if source == ClockSource::Tick {
+            BASE_NS.fetch_add(adjust(tick_ns, RATE_ADJ.load(Ordering::Relaxed)), Ordering::Relaxed);
+            RAW_BASE_NS.fetch_add(tick_ns, Ordering::Relaxed);
+            return;
+        }
+        fold(source);
+    });
+}
+
+/// Move the base to the current cycle count; caller holds the sequence
+fn fold(source: ClockSource) {
+    let now = source.read();
+    BASE_NS.store(ns_at(source, now), Ordering::Relaxed);
+    RAW_BASE_NS.store(raw_ns_at(source, now), Ordering::Relaxed);
+    CYCLE_LAST.store(now, Ordering::Relaxed);
+}
+
+/// Run monotonic time `adj` nanoseconds per second fast (negative: slow),
+/// shifted left by 32. Raw monotonic time is unaffected.
+pub fn set_rate_adjust(adj: i64) {
+    loop {
+        let done = write(|| {
+            let source = current();
+            if source != ClockSource::Tick {
+                fold(source);
+            }
+            RATE_ADJ.store(adj, Ordering::Relaxed);
+            MULT.store(adjust(RAW_MULT.load(Ordering::Relaxed), adj), Ordering::Relaxed);
+        });
+        if done.is_some() {
+            return;
+        }
+        core::hint::spin_loop();
+    }
+}
+
+/// Pick the best counter. HPET and TSC must already be initialized.
+pub fn init() -> ClockSource {
+    let (source, freq) = if tsc::is_enabled() && tsc::has_invariant_tsc() {
+        (ClockSource::Tsc, tsc::frequency_hz())
+    } else if hpet::is_enabled() && hpet::frequency() != 0 {
+        (ClockSource::Hpet, hpet::frequency())
+    } else if tsc::is_enabled() {
+        (ClockSource::Tsc, tsc::frequency_hz())
+    } else {
+        return ClockSource::Tick;
+    };
+
+    write(|| {
+        let old = current();
+        let now = old.read();
+        BASE_NS.store(ns_at(old, now), Ordering::Relaxed);
+        RAW_BASE_NS.store(raw_ns_at(old, now), Ordering::Relaxed);
+        let mult = ((1_000_000_000u128 << SHIFT) / freq as u128) as u64;
+        RAW_MULT.store(mult, Ordering::Relaxed);
+        MULT.store(adjust(mult, RATE_ADJ.load(Ordering::Relaxed)), Ordering::Relaxed);
+        MASK.store(if source == ClockSource::Hpet { hpet::counter_mask() } else { u64::MAX }, Ordering::Relaxed);
+        CYCLE_LAST.store(source.read(), Ordering::Relaxed);
+        SOURCE.store(source as u8, Ordering::Relaxed);
+    });
+    crate::kprintln!("time: clocksource {} ({} Hz)", source.name(), freq);
+    source
+}
@@ -0,0 +1,388 @@
+//! High-resolution timers.
+//!
+//! Timers expire at an absolute monotonic time in nanoseconds (see
+//! `clocksource::now_ns`). They are kept in a hierarchical timing wheel:
+//! level 0 has one slot per tick for the next 256 ticks, and each of the
+//! four levels above covers 64 times the range of the one below. Timers
+//! move down a level ("cascade") as the wheel turns, so inserting and
+//! cancelling are O(1) whatever the number of pending timers.
+//!
+//! Once the local APIC timer can run in one-shot mode, the timer interrupt
+//! is programmed for the earlier of the next scheduler tick and the next
+//! timer due within the current tick, which gives timers sub-tick
+//! precision. Without it (or before `init`), timers fire on the periodic
+//! tick.
+//!
+//! Timer callbacks run in interrupt context, outside the wheel lock. A
+//! callback returning `Some(expires)` re-arms the same timer.
+
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU64, AtomicU8, Ordering};
+
+use super::{clocksource, NS_PER_TICK};
+use crate::arch::x86_64_arch::{apic, smp, tsc};
+use crate::sync::IrqSafeMutex;
+
+/// Identifies a pending timer
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct TimerId(pub u64);
+
+/// Slots of level 0, one per tick
+const LVL0_BITS: u32 = 8;
+const LVL0_SIZE: usize = 1 << LVL0_BITS;
+/// Slots of each upper level
+const LVL_BITS: u32 = 6;
+const LVL_SIZE: usize = 1 << LVL_BITS;
+const LEVELS: usize = 5;
+/// Ticks covered by the wheel; later timers are parked in the last slot
+const MAX_DELTA: u64 = 1 << (LVL0_BITS + LVL_BITS * (LEVELS as u32 - 1));
+
+struct Entry<T> {
+    id: TimerId,
+    expires: u64,
+    data: T,
+}
+
+/// A timer that has expired, as returned by `TimerWheel::advance`
+pub struct Expired<T> {
+    pub id: TimerId,
+    pub expires: u64,
+    pub data: T,
+}
+
+/// Hierarchical timing wheel with a resolution of `granularity` ns
+pub struct TimerWheel<T> {
+    granularity: u64,
+    /// Tick being processed: every earlier tick has been emptied
+    clock: u64,
+    slots: Vec<Vec<Entry<T>>>,
+    /// Slot of each pending timer
+    index: BTreeMap<TimerId, usize>,
+}
+
+impl<T> TimerWheel<T> {
+    /// Empty wheel starting at time `now`
+    pub fn new(granularity: u64, now: u64) -> Self {
+        let mut slots = Vec::with_capacity(LVL0_SIZE + (LEVELS - 1) * LVL_SIZE);
+        slots.resize_with(LVL0_SIZE + (LEVELS - 1) * LVL_SIZE, Vec::new);
+        Self {
+            granularity,
+            clock: now / granularity,
+            slots,
+            index: BTreeMap::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.index.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.index.is_empty()
+    }
+
+    /// Slot for a timer expiring at tick `tick`
+    fn slot_for(&self, tick: u64) -> usize {
+        let tick = tick.max(self.clock);
+        let delta = tick - self.clock;
+        if delta < LVL0_SIZE as u64 {
+            return (tick & (LVL0_SIZE as u64 - 1)) as usize;
+        }
+        // Too far away: park it at the farthest slot, it is placed again
+        // when that slot cascades
+        let (delta, tick) = if delta >= MAX_DELTA {
+            (MAX_DELTA - 1, self.clock + MAX_DELTA - 1)
+        } else {
+            (delta, tick)
+        };
+        let mut level = 1;
+        while delta >= 1 << (LVL0_BITS + LVL_BITS * level as u32) {
+            level += 1;
+        }
+        let shift = LVL0_BITS + LVL_BITS * (level as u32 - 1);
+        LVL0_SIZE + (level - 1) * LVL_SIZE + ((tick >> shift) as usize & (LVL_SIZE - 1))
+    }
+
+    fn place(&mut self, entry: Entry<T>) {
+        let slot = self.slot_for(entry.expires / self.granularity);
+        self.index.insert(entry.id, slot);
+        self.slots[slot].push(entry);
+    }
+
+    /// Add timer `id` expiring at `expires` ns. An `id` already pending is
+    /// replaced.
+    pub fn insert(&mut self, id: TimerId, expires: u64, data: T) {
+        self.cancel(id);
+        self.place(Entry { id, expires, data });
+    }
+
+    /// Remove a pending timer and return its data
+    pub fn cancel(&mut self, id: TimerId) -> Option<T> {
+        let slot = self.index.remove(&id)?;
+        let bucket = &mut self.slots[slot];
+        let pos = bucket.iter().position(|e| e.id == id)?;
+        Some(bucket.swap_remove(pos).data)
+    }
+
+    /// Expiry time of a pending timer
+    pub fn expires(&self, id: TimerId) -> Option<u64> {
+        let slot = *self.index.get(&id)?;
+        self.slots[slot].iter().find(|e| e.id == id).map(|e| e.expires)
+    }
+
+    /// Move the timers of the current slot of each upper level one level
+    /// down, as in the classic Linux timer wheel
+    fn cascade(&mut self) {
+        for level in 1..LEVELS {
+            let shift = LVL0_BITS + LVL_BITS * (level as u32 - 1);
+            let idx = (self.clock >> shift) as usize & (LVL_SIZE - 1);
+            let slot = LVL0_SIZE + (level - 1) * LVL_SIZE + idx;
+            for entry in core::mem::take(&mut self.slots[slot]) {
+                self.place(entry);
+            }
+            if idx != 0 {
+                break;
+            }
+        }
+    }
+
+    /// Turn the wheel up to time `now` and return the timers that expired,
+    /// earliest first
+    pub fn advance(&mut self, now: u64) -> Vec<Expired<T>> {
+        let target = now / self.granularity;
+        let mut expired = Vec::new();
+        if self.index.is_empty() {
+            self.clock = self.clock.max(target);
+            return expired;
+        }
+        loop {
+            let slot = (self.clock & (LVL0_SIZE as u64 - 1)) as usize;
+            let mut i = 0;
+            while i < self.slots[slot].len() {
+                if self.slots[slot][i].expires <= now {
+                    let entry = self.slots[slot].swap_remove(i);
+                    self.index.remove(&entry.id);
+                    expired.push(Expired { id: entry.id, expires: entry.expires, data: entry.data });
+                } else {
+                    i += 1;
+                }
+            }
+            if self.clock >= target {
+                break;
+            }
+            self.clock += 1;
+            if self.clock & (LVL0_SIZE as u64 - 1) == 0 {
+                self.cascade();
+            }
+        }
+        expired.sort_by_key(|e| e.expires);
+        expired
+    }
+
+    /// Earliest expiry among the timers of the nearest non-empty slot
+    pub fn next_expiry(&self) -> Option<u64> {
+        if self.index.is_empty() {
+            return None;
+        }
+        for offset in 0..LVL0_SIZE as u64 {
+            let slot = ((self.clock + offset) & (LVL0_SIZE as u64 - 1)) as usize;
+            if let Some(min) = self.slots[slot].iter().map(|e| e.expires).min() {
+                return Some(min);
+            }
+        }
+        self.slots[LVL0_SIZE..].iter().flatten().map(|e| e.expires).min()
+    }
+}
+
+/// Next expiry of a periodic timer that was due at `expires` and is seen at
+/// `now`, and how many periods were missed in between
+pub fn forward(expires: u64, interval: u64, now: u64) -> (u64, u64) {
+    if interval == 0 || now < expires {
+        return (expires, 0);
+    }
+    let overruns = (now - expires) / interval;
+    (expires + (overruns + 1) * interval, overruns)
+}
+
+// ============================================================================
+// System timers
+// ============================================================================
+
+type TimerFn = Box<dyn FnMut(u64) -> Option<u64> + Send>;
+
+struct Timers {
+    wheel: TimerWheel<TimerFn>,
+    /// Timer whose callback is running, and whether it was cancelled
+    /// meanwhile
+    running: Option<(TimerId, bool)>,
+}
+
+static TIMERS: IrqSafeMutex<Option<Timers>> = IrqSafeMutex::new(None);
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+/// Time of the next scheduler tick
+static NEXT_TICK: AtomicU64 = AtomicU64::new(0);
+
+const MODE_PERIODIC: u8 = 0;
+const MODE_COUNTDOWN: u8 = 1;
+const MODE_TSC_DEADLINE: u8 = 2;
+static MODE: AtomicU8 = AtomicU8::new(MODE_PERIODIC);
+
+fn with_timers<R>(f: impl FnOnce(&mut Timers) -> R) -> R {
+    let mut guard = TIMERS.lock();
+    let timers = guard.get_or_insert_with(|| Timers {
+        wheel: TimerWheel::new(NS_PER_TICK, clocksource::now_ns()),
+        running: None,
+    });
+    f(timers)
+}
+
+/// Whether timers fire with sub-tick precision
+pub fn is_high_resolution() -> bool {
+    MODE.load(Ordering::Relaxed) != MODE_PERIODIC
+}
+
+/// Arm a timer that calls `callback(now)` at `expires` ns of monotonic
+/// time. Returning `Some(t)` from the callback re-arms it for `t`.
+pub fn start(expires: u64, callback: impl FnMut(u64) -> Option<u64> + Send + 'static) -> TimerId {
+    let id = TimerId(NEXT_ID.fetch_add(1, Ordering::Relaxed));
+    let earliest = with_timers(|timers| {
+        let earlier = timers.wheel.next_expiry().is_none_or(|next| expires < next);
+        timers.wheel.insert(id, expires, Box::new(callback));
+        earlier
+    });
+    // Only the BSP runs the timer interrupt
+    if earliest && is_high_resolution() && smp::is_bsp() {
+        x86_64::instructions::interrupts::without_interrupts(|| program(clocksource::now_ns()));
+    }
+    id
+}
+
+/// Disarm a timer. Returns false if it already fired (a periodic timer
+/// whose callback is running is not re-armed).
+pub fn cancel(id: TimerId) -> bool {
+    with_timers(|timers| {
+        if timers.wheel.cancel(id).is_some() {
+            return true;
+        }
+        if let Some((running, cancelled)) = &mut timers.running {
+            if *running == id {
+                *cancelled = true;
+            }
+        }
+        false
+    })
+}
+
+/// When a pending timer expires
+pub fn expires(id: TimerId) -> Option<u64> {
+    with_timers(|timers| timers.wheel.expires(id))
+}
+
+/// Program the one-shot APIC timer for the next event
+fn program(now: u64) {
+    let tick = NEXT_TICK.load(Ordering::Relaxed);
+    let next = with_timers(|timers| timers.wheel.next_expiry())
+        .map_or(tick, |expiry| expiry.min(tick));
+    let delta = next.saturating_sub(now);
+    match MODE.load(Ordering::Relaxed) {
+        MODE_TSC_DEADLINE => apic::arm_timer_tsc_deadline(tsc::read() + tsc::ns_to_ticks(delta).max(1)),
+        MODE_COUNTDOWN => apic::arm_timer_countdown(delta),
+        _ => {}
+    }
+}
+
+/// Run the expired timers. Called on every timer interrupt; returns whether
+/// this interrupt is also a scheduler tick.
+pub fn timer_interrupt() -> bool {
+    let now = clocksource::now_ns();
+    let mut expired = with_timers(|timers| timers.wheel.advance(now));
+
+    for timer in expired.iter_mut() {
+        with_timers(|timers| timers.running = Some((timer.id, false)));
+        let rearm = (timer.data)(now);
+        let (_, cancelled) = with_timers(|timers| timers.running.take()).unwrap_or((timer.id, true));
+        if let (Some(expires), false) = (rearm, cancelled) {
+            let callback = core::mem::replace(&mut timer.data, Box::new(|_| None));
+            with_timers(|timers| timers.wheel.insert(timer.id, expires, callback));
+        }
+    }
+
+    let periodic = !is_high_resolution();
+    let tick = periodic || now >= NEXT_TICK.load(Ordering::Relaxed);
+    if tick {
+        NEXT_TICK.store((now / NS_PER_TICK + 1) * NS_PER_TICK, Ordering::Relaxed);
+    }
+    if !periodic {
+        program(clocksource::now_ns());
+    }
+    tick
+}
+
+/// Switch the APIC timer to one-shot mode when there is a counter to read
+/// time from. Called once the clock sources are initialized.
+pub fn init() {
+    if clocksource::current() == clocksource::ClockSource::Tick || !apic::is_enabled() {
+        crate::kprintln!("time: hrtimers on the periodic tick");
+        return;
+    }
+    let mode = if tsc::is_enabled() && tsc::has_invariant_tsc() && apic::has_tsc_deadline() {
+        apic::OneshotMode::TscDeadline
+    } else {
+        apic::OneshotMode::Countdown
+    };
+
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        if !apic::enable_oneshot_timer(mode) {
+            return;
+        }
+        let now = clocksource::now_ns();
+        NEXT_TICK.store((now / NS_PER_TICK + 1) * NS_PER_TICK, Ordering::Relaxed);
+        MODE.store(
+            match mode {
+                apic::OneshotMode::TscDeadline => MODE_TSC_DEADLINE,
+                apic::OneshotMode::Countdown => MODE_COUNTDOWN,
+            },
+            Ordering::Relaxed,
+        );
+        program(now);
+    });
+    crate::kprintln!("time: hrtimers in one-shot mode ({:?})", mode);
+}
+
+/// Block the current task until `deadline` ns of monotonic time. Returns
+/// the time left if a signal interrupted the sleep.
+pub fn sleep_until(deadline: u64) -> Result<(), u64> {
+    loop {
+        let now = clocksource::now_ns();
+        if now >= deadline {
+            return Ok(());
+        }
+        if crate::sched::try_current_task().is_none() {
+            // No scheduler yet: wait for the next interrupt
+            x86_64::instructions::interrupts::enable_and_hlt();
+            continue;
+        }
+
+        // Mark the task sleeping before arming the timer, so a timer that
+        // fires at once still finds it to wake
+        let timer = x86_64::instructions::interrupts::without_interrupts(|| {
+            crate::sched::prepare_to_sleep();
+            if crate::sched::has_pending_signals() {
+                crate::sched::finish_sleep();
+                return None;
+            }
+            let tid = crate::sched::current_tid();
+            Some(start(deadline, move |_| {
+                crate::sched::wake_task(tid);
+                None
+            }))
+        });
+        let Some(timer) = timer else {
+            return Err(deadline.saturating_sub(clocksource::now_ns()));
+        };
+        crate::sched::sleep();
+        cancel(timer);
+    }
+}
@@ -0,0 +1,185 @@
+//! Interval timers: setitimer, getitimer and alarm.
+//!
+//! ITIMER_REAL counts monotonic time on a hrtimer and sends SIGALRM.
+//! ITIMER_VIRTUAL (CPU time in user mode) and ITIMER_PROF (all CPU time of
+//! the process) are charged one tick at a time by the timer interrupt and
+//! send SIGVTALRM and SIGPROF. Timers belong to the process and are not
+//! inherited across fork.
+
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+
+use super::hrtimer::{self, TimerId};
+use super::{uptime_ns, Itimerval, Timeval, NS_PER_TICK};
+use crate::signal::sig;
+use crate::sync::IrqSafeMutex;
+use crate::syscall::errno;
+
+pub const ITIMER_REAL: i32 = 0;
+pub const ITIMER_VIRTUAL: i32 = 1;
+pub const ITIMER_PROF: i32 = 2;
+
+/// A timer counting CPU time
+#[derive(Default)]
+struct CpuTimer {
+    /// CPU time left before it expires, 0 when disarmed
+    value: u64,
+    interval: u64,
+}
+
+impl CpuTimer {
+    /// Charge `ns` of CPU time; true if the timer expired
+    fn charge(&mut self, ns: u64) -> bool {
+        if self.value == 0 {
+            return false;
+        }
+        if self.value > ns {
+            self.value -= ns;
+            return false;
+        }
+        // Reloads keep the time charged past the expiry
+        let excess = ns - self.value;
+        self.value = match self.interval {
+            0 => 0,
+            interval => interval - excess % interval,
+        };
+        true
+    }
+}
+
+#[derive(Default)]
+struct ProcessTimers {
+    real: Option<TimerId>,
+    real_interval: u64,
+    virt: CpuTimer,
+    prof: CpuTimer,
+}
+
+/// Interval timers of each process, by tgid
+static ITIMERS: IrqSafeMutex<BTreeMap<u64, ProcessTimers>> = IrqSafeMutex::new(BTreeMap::new());
+
+fn itimerval(value: u64, interval: u64) -> Itimerval {
+    Itimerval {
+        it_interval: Timeval::from_ns(interval),
+        it_value: Timeval::from_ns(value),
+    }
+}
+
+fn get_locked(timers: &ProcessTimers, which: i32) -> Itimerval {
+    match which {
+        ITIMER_REAL => {
+            let left = timers
+                .real
+                .and_then(hrtimer::expires)
+                .map_or(0, |expires| expires.saturating_sub(uptime_ns()).max(1));
+            itimerval(left, if left == 0 { 0 } else { timers.real_interval })
+        }
+        ITIMER_VIRTUAL => itimerval(timers.virt.value, timers.virt.interval),
+        _ => itimerval(timers.prof.value, timers.prof.interval),
+    }
+}
+
+/// getitimer for the current process
+pub fn getitimer(which: i32) -> Result<Itimerval, i64> {
+    if !(ITIMER_REAL..=ITIMER_PROF).contains(&which) {
+        return Err(errno::EINVAL);
+    }
+    let tgid = crate::sched::current_tgid();
+    let itimers = ITIMERS.lock();
+    Ok(itimers.get(&tgid).map_or_else(Itimerval::default, |timers| get_locked(timers, which)))
+}
+
+/// setitimer for the current process; returns the previous setting
+pub fn setitimer(which: i32, new: &Itimerval) -> Result<Itimerval, i64> {
+    if !(ITIMER_REAL..=ITIMER_PROF).contains(&which) {
+        return Err(errno::EINVAL);
+    }
+    let value = new.it_value.to_ns().ok_or(errno::EINVAL)?;
+    let interval = new.it_interval.to_ns().ok_or(errno::EINVAL)?;
+    // A disarmed timer has no interval
+    let interval = if value == 0 { 0 } else { interval };
+    let tgid = crate::sched::current_tgid();
+
+    let mut itimers = ITIMERS.lock();
+    let timers = itimers.entry(tgid).or_default();
+    let old = get_locked(timers, which);
+    match which {
+        ITIMER_REAL => {
+            if let Some(id) = timers.real.take() {
+                hrtimer::cancel(id);
+            }
+            timers.real_interval = interval;
+            if value != 0 {
+                let mut expires = uptime_ns().saturating_add(value);
+                timers.real = Some(hrtimer::start(expires, move |now| {
+                    let _ = crate::sched::send_kernel_signal(tgid, sig::SIGALRM);
+                    if interval == 0 {
+                        return None;
+                    }
+                    expires = hrtimer::forward(expires, interval, now).0;
+                    Some(expires)
+                }));
+            }
+        }
+        ITIMER_VIRTUAL => timers.virt = CpuTimer { value, interval },
+        _ => timers.prof = CpuTimer { value, interval },
+    }
+    Ok(old)
+}
+
+/// alarm: arm ITIMER_REAL for `seconds` (0 disarms) and return the seconds
+/// that were left, rounded to the nearest
+pub fn alarm(seconds: u32) -> u32 {
+    let new = Itimerval {
+        it_interval: Timeval::default(),
+        it_value: Timeval { tv_sec: seconds as i64, tv_usec: 0 },
+    };
+    let Ok(old) = setitimer(ITIMER_REAL, &new) else {
+        return 0;
+    };
+    let mut left = old.it_value.tv_sec as u32;
+    if old.it_value.tv_usec >= 500_000 || (left == 0 && old.it_value.tv_usec != 0) {
+        left += 1;
+    }
+    left
+}
+
+/// Charge one tick of CPU time to the current process. Called from the
+/// timer interrupt; `user_mode` tells whether the tick interrupted user
+/// code.
+pub fn account_cpu_tick(user_mode: bool) {
+    let Some(task) = crate::sched::try_current_task() else {
+        return;
+    };
+    if !task.is_user() {
+        return;
+    }
+    let tgid = task.tgid();
+
+    let mut signals = Vec::new();
+    {
+        let mut itimers = ITIMERS.lock();
+        let Some(timers) = itimers.get_mut(&tgid) else {
+            return;
+        };
+        if user_mode && timers.virt.charge(NS_PER_TICK) {
+            signals.push(sig::SIGVTALRM);
+        }
+        if timers.prof.charge(NS_PER_TICK) {
+            signals.push(sig::SIGPROF);
+        }
+    }
+    // Sent without ITIMERS held: the scheduler lock comes first
+    for signum in signals {
+        let _ = crate::sched::send_kernel_signal(tgid, signum);
+    }
+}
+
+/// Drop the timers of an exiting process
+pub fn exit(tgid: u64) {
+    if let Some(timers) = ITIMERS.lock().remove(&tgid) {
+        if let Some(id) = timers.real {
+            hrtimer::cancel(id);
+        }
+    }
+}
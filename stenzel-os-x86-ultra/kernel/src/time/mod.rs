@@ -0,0 +1,618 @@
+//! Time management
+//!
+//! Gerencia tempo do sistema, relógios e timers.
+
+#![allow(dead_code)]
+
+extern crate alloc;
+
+use alloc::string::String;
+use core::sync::atomic::{AtomicI64, AtomicU64, AtomicI32, Ordering};
+use crate::sync::IrqSafeMutex;
+
+pub mod clocksource;
+pub mod hrtimer;
+pub mod itimer;
+pub mod ntp;
+pub mod posix_timers;
+pub mod tz;
+
+const NSEC_PER_SEC: u64 = 1_000_000_000;
+
+/// Ticks desde o boot (incrementado pelo timer IRQ)
+static TICKS: AtomicU64 = AtomicU64::new(0);
+
+/// Global timezone offset in minutes west of UTC
+/// Positive = west of UTC (behind), Negative = east of UTC (ahead)
+static TZ_MINUTESWEST: AtomicI32 = AtomicI32::new(0);
+
+/// Global timezone DST flag
+static TZ_DSTTIME: AtomicI32 = AtomicI32::new(0);
+
+/// Global timezone name
+static TZ_NAME: IrqSafeMutex<Option<String>> = IrqSafeMutex::new(None);
+
+/// Frequência do timer em Hz
+/// DEVE corresponder à frequência configurada no PIT (arch/x86_64_arch/mod.rs)
+/// PIT está configurado para 1000Hz (1ms) - ideal para cursor/animações suaves
+const TIMER_HZ: u64 = 1000;
+
+/// Nanosegundos por tick
+const NS_PER_TICK: u64 = 1_000_000_000 / TIMER_HZ;
+
+/// Monotonic time at the last tick (CLOCK_*_COARSE)
+static COARSE_NS: AtomicU64 = AtomicU64::new(0);
+
+/// Time spent suspended, counted by CLOCK_BOOTTIME but not CLOCK_MONOTONIC
+static SUSPENDED_NS: AtomicU64 = AtomicU64::new(0);
+
+/// Realtime minus monotonic time, in ns. Starts from the RTC; stepped by
+/// clock_settime, settimeofday, ADJ_SETOFFSET and leap seconds.
+static REAL_OFFSET_NS: AtomicU64 = AtomicU64::new(1704067200 * NSEC_PER_SEC); // 2024-01-01 00:00:00 UTC
+
+/// Realtime second the NTP discipline last ran for
+static LAST_SECOND: AtomicI64 = AtomicI64::new(0);
+
+/// Serializes updates of the vDSO data page
+static VSYSCALL_LOCK: IrqSafeMutex<()> = IrqSafeMutex::new(());
+
+/// Chamado pelo timer IRQ handler
+pub fn tick() {
+    TICKS.fetch_add(1, Ordering::Relaxed);
+    clocksource::update(NS_PER_TICK);
+    COARSE_NS.store(clocksource::now_ns(), Ordering::Relaxed);
+
+    let secs = realtime().tv_sec;
+    if LAST_SECOND.swap(secs, Ordering::Relaxed) != secs {
+        let leap = ntp::second_overflow(secs);
+        if leap != 0 {
+            let _ = step(leap * NSEC_PER_SEC as i64);
+            LAST_SECOND.store(realtime().tv_sec, Ordering::Relaxed);
+        }
+    }
+    update_vsyscall();
+
+    // Poll USB HID devices every tick
+    crate::drivers::usb::hid::poll_all();
+}
+
+/// Retorna ticks desde o boot
+pub fn ticks() -> u64 {
+    TICKS.load(Ordering::Relaxed)
+}
+
+/// Retorna a frequência do timer em Hz
+pub fn hz() -> u64 {
+    TIMER_HZ
+}
+
+/// Retorna tempo desde o boot em nanosegundos
+pub fn uptime_ns() -> u64 {
+    clocksource::now_ns()
+}
+
+/// Retorna tempo desde o boot em milissegundos
+pub fn uptime_ms() -> u64 {
+    uptime_ns() / 1_000_000
+}
+
+/// Retorna tempo desde o boot em segundos
+pub fn uptime_secs() -> u64 {
+    uptime_ns() / NSEC_PER_SEC
+}
+
+/// Account for time spent suspended (CLOCK_BOOTTIME keeps counting)
+pub fn add_suspended_time(ns: u64) {
+    SUSPENDED_NS.fetch_add(ns, Ordering::Relaxed);
+    update_vsyscall();
+}
+
+/// Publish the clock state to the vDSO. Called on every tick and whenever
+/// an offset between the clocks or the timezone changes.
+fn update_vsyscall() {
+    let _guard = VSYSCALL_LOCK.lock();
+    let cs = clocksource::snapshot();
+    let tz = get_timezone();
+    crate::arch::x86_64_arch::vdso::update(&crate::arch::x86_64_arch::vdso::TimeData {
+        clocksource: cs,
+        raw_ns: cs.raw_base_ns,
+        raw_mult: cs.raw_mult,
+        real_offset_ns: REAL_OFFSET_NS.load(Ordering::Relaxed),
+        boot_offset_ns: SUSPENDED_NS.load(Ordering::Relaxed),
+        coarse_ns: COARSE_NS.load(Ordering::Relaxed),
+        tz_minuteswest: tz.tz_minuteswest,
+        tz_dsttime: tz.tz_dsttime,
+    });
+}
+
+/// Estrutura timespec (para clock_gettime)
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Timespec {
+    pub tv_sec: i64,
+    pub tv_nsec: i64,
+}
+
+impl Timespec {
+    pub fn from_ns(ns: u64) -> Self {
+        Self {
+            tv_sec: (ns / NSEC_PER_SEC) as i64,
+            tv_nsec: (ns % NSEC_PER_SEC) as i64,
+        }
+    }
+
+    /// Nanoseconds, or None if the timespec is negative or not normalized
+    pub fn to_ns(&self) -> Option<u64> {
+        if self.tv_sec < 0 || self.tv_nsec < 0 || self.tv_nsec >= NSEC_PER_SEC as i64 {
+            return None;
+        }
+        (self.tv_sec as u64)
+            .checked_mul(NSEC_PER_SEC)?
+            .checked_add(self.tv_nsec as u64)
+    }
+}
+
+/// struct itimerspec (timer_settime, timerfd_settime)
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Itimerspec {
+    pub it_interval: Timespec,
+    pub it_value: Timespec,
+}
+
+/// Estrutura timeval (para gettimeofday)
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Timeval {
+    pub tv_sec: i64,
+    pub tv_usec: i64,
+}
+
+impl Timeval {
+    pub fn from_ns(ns: u64) -> Self {
+        Self {
+            tv_sec: (ns / NSEC_PER_SEC) as i64,
+            tv_usec: ((ns % NSEC_PER_SEC) / 1000) as i64,
+        }
+    }
+
+    /// Nanoseconds, or None if the timeval is negative or not normalized
+    pub fn to_ns(&self) -> Option<u64> {
+        if self.tv_sec < 0 || self.tv_usec < 0 || self.tv_usec >= 1_000_000 {
+            return None;
+        }
+        (self.tv_sec as u64)
+            .checked_mul(NSEC_PER_SEC)?
+            .checked_add(self.tv_usec as u64 * 1000)
+    }
+}
+
+/// struct itimerval (setitimer, getitimer)
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Itimerval {
+    pub it_interval: Timeval,
+    pub it_value: Timeval,
+}
+
+/// Estrutura timezone
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Timezone {
+    pub tz_minuteswest: i32,
+    pub tz_dsttime: i32,
+}
+
+/// IDs de clock
+pub mod clock {
+    pub const CLOCK_REALTIME: i32 = 0;
+    pub const CLOCK_MONOTONIC: i32 = 1;
+    pub const CLOCK_PROCESS_CPUTIME_ID: i32 = 2;
+    pub const CLOCK_THREAD_CPUTIME_ID: i32 = 3;
+    pub const CLOCK_MONOTONIC_RAW: i32 = 4;
+    pub const CLOCK_REALTIME_COARSE: i32 = 5;
+    pub const CLOCK_MONOTONIC_COARSE: i32 = 6;
+    pub const CLOCK_BOOTTIME: i32 = 7;
+}
+
+/// clock_nanosleep/timer_settime flag: the time is absolute
+pub const TIMER_ABSTIME: i32 = 1;
+
+/// Obtém o tempo real (wall clock)
+pub fn realtime() -> Timespec {
+    Timespec::from_ns(REAL_OFFSET_NS.load(Ordering::Relaxed) + uptime_ns())
+}
+
+/// Set realtime to `ns` since the epoch (clock_settime, settimeofday).
+/// Monotonic time is not affected; the NTP discipline starts over.
+pub fn settime(ns: u64) -> Result<(), i64> {
+    let uptime = uptime_ns();
+    // Realtime cannot be set before the boot
+    if ns < uptime {
+        return Err(crate::syscall::errno::EINVAL);
+    }
+    REAL_OFFSET_NS.store(ns - uptime, Ordering::Relaxed);
+    ntp::clear();
+    update_vsyscall();
+    crate::kprintln!("time: clock set to {}", ns / NSEC_PER_SEC);
+    Ok(())
+}
+
+/// Step realtime by `delta` ns
+pub fn step(delta: i64) -> Result<(), i64> {
+    let offset = REAL_OFFSET_NS.load(Ordering::Relaxed);
+    let stepped = offset.checked_add_signed(delta).filter(|&offset| offset > 0);
+    REAL_OFFSET_NS.store(stepped.ok_or(crate::syscall::errno::EINVAL)?, Ordering::Relaxed);
+    update_vsyscall();
+    Ok(())
+}
+
+/// Obtém tempo monotônico (desde o boot)
+pub fn monotonic() -> Timespec {
+    Timespec::from_ns(uptime_ns())
+}
+
+/// Current time of `clock_id` in nanoseconds
+pub fn clock_now_ns(clock_id: i32) -> Option<u64> {
+    let ns = match clock_id {
+        clock::CLOCK_REALTIME => REAL_OFFSET_NS.load(Ordering::Relaxed) + uptime_ns(),
+        clock::CLOCK_REALTIME_COARSE => REAL_OFFSET_NS.load(Ordering::Relaxed) + COARSE_NS.load(Ordering::Relaxed),
+        clock::CLOCK_MONOTONIC => uptime_ns(),
+        clock::CLOCK_MONOTONIC_RAW => clocksource::raw_ns(),
+        clock::CLOCK_MONOTONIC_COARSE => COARSE_NS.load(Ordering::Relaxed),
+        clock::CLOCK_BOOTTIME => uptime_ns() + SUSPENDED_NS.load(Ordering::Relaxed),
+        clock::CLOCK_PROCESS_CPUTIME_ID => crate::sched::thread_group_cputime(crate::sched::current_tgid()),
+        clock::CLOCK_THREAD_CPUTIME_ID => crate::sched::current_thread_cputime(),
+        _ => return None,
+    };
+    Some(ns)
+}
+
+/// clock_gettime syscall
+pub fn clock_gettime(clock_id: i32) -> Option<Timespec> {
+    clock_now_ns(clock_id).map(Timespec::from_ns)
+}
+
+/// clock_getres syscall: 1 ns for clocks read from the clock source, a
+/// tick for the coarse and CPU-time clocks (updated once per tick)
+pub fn clock_getres(clock_id: i32) -> Option<Timespec> {
+    let res = match clock_id {
+        clock::CLOCK_REALTIME | clock::CLOCK_MONOTONIC | clock::CLOCK_MONOTONIC_RAW | clock::CLOCK_BOOTTIME => {
+            if clocksource::current() == clocksource::ClockSource::Tick {
+                NS_PER_TICK
+            } else {
+                1
+            }
+        }
+        clock::CLOCK_REALTIME_COARSE
+        | clock::CLOCK_MONOTONIC_COARSE
+        | clock::CLOCK_PROCESS_CPUTIME_ID
+        | clock::CLOCK_THREAD_CPUTIME_ID => NS_PER_TICK,
+        _ => return None,
+    };
+    Some(Timespec::from_ns(res))
+}
+
+/// Monotonic deadline of a sleep on `clock_id` for `value`, relative or
+/// (with TIMER_ABSTIME) absolute on that clock
+pub fn sleep_deadline(clock_id: i32, flags: i32, value: u64) -> Option<u64> {
+    let now = uptime_ns();
+    if flags & TIMER_ABSTIME == 0 {
+        return Some(now.saturating_add(value));
+    }
+    let clock_now = clock_now_ns(clock_id)?;
+    Some(now.saturating_add(value.saturating_sub(clock_now)))
+}
+
+/// gettimeofday syscall
+pub fn gettimeofday() -> (Timeval, Timezone) {
+    let ts = realtime();
+    let tv = Timeval {
+        tv_sec: ts.tv_sec,
+        tv_usec: ts.tv_nsec / 1000,
+    };
+    let tz = Timezone {
+        tz_minuteswest: TZ_MINUTESWEST.load(Ordering::Relaxed),
+        tz_dsttime: TZ_DSTTIME.load(Ordering::Relaxed),
+    };
+    (tv, tz)
+}
+
+/// Get current timezone
+pub fn get_timezone() -> Timezone {
+    Timezone {
+        tz_minuteswest: TZ_MINUTESWEST.load(Ordering::Relaxed),
+        tz_dsttime: TZ_DSTTIME.load(Ordering::Relaxed),
+    }
+}
+
+/// Set timezone
+pub fn set_timezone(tz: &Timezone) {
+    TZ_MINUTESWEST.store(tz.tz_minuteswest, Ordering::Relaxed);
+    TZ_DSTTIME.store(tz.tz_dsttime, Ordering::Relaxed);
+    update_vsyscall();
+}
+
+/// Get timezone name
+pub fn get_timezone_name() -> String {
+    let guard = TZ_NAME.lock();
+    match &*guard {
+        Some(name) => name.clone(),
+        None => String::from("UTC"),
+    }
+}
+
+/// Set the timezone by name: an IANA zone from the zoneinfo directory
+/// ("America/Sao_Paulo"), a common abbreviation (fixed offset) or a POSIX
+/// TZ string ("CET-1CEST,M3.5.0,M10.5.0/3").
+/// Returns true if timezone was recognized and set
+pub fn set_timezone_by_name(name: &str) -> bool {
+    if let Ok(zone) = tz::load(name) {
+        set_zone(zone);
+        return true;
+    }
+
+    // Common timezone offsets (minutes west of UTC)
+    let (tz_minuteswest, tz_dsttime) = match name {
+        "UTC" | "GMT" => (0, 0),
+        "EST" => (5 * 60, 0),          // Eastern Standard Time (UTC-5)
+        "EDT" => (4 * 60, 1),          // Eastern Daylight Time (UTC-4)
+        "CST" => (6 * 60, 0),          // Central Standard Time (UTC-6)
+        "CDT" => (5 * 60, 1),          // Central Daylight Time (UTC-5)
+        "MST" => (7 * 60, 0),          // Mountain Standard Time (UTC-7)
+        "MDT" => (6 * 60, 1),          // Mountain Daylight Time (UTC-6)
+        "PST" => (8 * 60, 0),          // Pacific Standard Time (UTC-8)
+        "PDT" => (7 * 60, 1),          // Pacific Daylight Time (UTC-7)
+        "WET" => (0, 0),               // Western European Time (UTC)
+        "CET" => (-1 * 60, 0),         // Central European Time (UTC+1)
+        "CEST" => (-2 * 60, 1),        // Central European Summer Time (UTC+2)
+        "EET" => (-2 * 60, 0),         // Eastern European Time (UTC+2)
+        "EEST" => (-3 * 60, 1),        // Eastern European Summer Time (UTC+3)
+        "BRT" => (3 * 60, 0),          // Brasília Time (UTC-3)
+        "BRST" => (2 * 60, 1),         // Brasília Summer Time (UTC-2)
+        "JST" => (-9 * 60, 0),         // Japan Standard Time (UTC+9)
+        "CST-China" | "CST8" => (-8 * 60, 0), // China Standard Time (UTC+8)
+        "AEST" => (-10 * 60, 0),       // Australian Eastern Standard Time (UTC+10)
+        "AEDT" => (-11 * 60, 1),       // Australian Eastern Daylight Time (UTC+11)
+        "IST" => (-5 * 60 - 30, 0),    // India Standard Time (UTC+5:30)
+        _ => match tz::Zone::from_posix(name) {
+            Ok(zone) => {
+                set_zone(zone);
+                return true;
+            }
+            Err(_) => return false,    // Unknown timezone
+        },
+    };
+
+    tz::set_current(None);
+    TZ_MINUTESWEST.store(tz_minuteswest, Ordering::Relaxed);
+    TZ_DSTTIME.store(tz_dsttime, Ordering::Relaxed);
+    update_vsyscall();
+
+    // Store the timezone name
+    *TZ_NAME.lock() = Some(String::from(name));
+    tz::notify(name);
+
+    true
+}
+
+/// Follow `zone` for local time. The offset reported by gettimeofday is
+/// the one in force now, as with settimeofday.
+fn set_zone(zone: tz::Zone) {
+    let now = zone.lookup(realtime().tv_sec);
+    TZ_MINUTESWEST.store(-now.utoff / 60, Ordering::Relaxed);
+    TZ_DSTTIME.store(now.is_dst as i32, Ordering::Relaxed);
+    let name = String::from(zone.name());
+    tz::set_current(Some(zone));
+    update_vsyscall();
+
+    *TZ_NAME.lock() = Some(name.clone());
+    tz::notify(&name);
+}
+
+/// Convert UTC timestamp to local time (considering timezone)
+pub fn utc_to_local(utc_secs: i64) -> i64 {
+    if let Some(zone) = tz::current() {
+        return zone.utc_to_local(utc_secs);
+    }
+    let offset_minutes = TZ_MINUTESWEST.load(Ordering::Relaxed);
+    // tz_minuteswest is positive for west of UTC (behind), so we subtract
+    utc_secs - (offset_minutes as i64 * 60)
+}
+
+/// Convert local time to UTC (considering timezone)
+pub fn local_to_utc(local_secs: i64) -> i64 {
+    if let Some(zone) = tz::current() {
+        return zone.local_to_utc(local_secs);
+    }
+    let offset_minutes = TZ_MINUTESWEST.load(Ordering::Relaxed);
+    local_secs + (offset_minutes as i64 * 60)
+}
+
+/// Get local time as Timespec
+pub fn localtime() -> Timespec {
+    let ts = realtime();
+    Timespec {
+        tv_sec: utc_to_local(ts.tv_sec),
+        tv_nsec: ts.tv_nsec,
+    }
+}
+
+/// nanosleep - dorme por um período. Interrompido por um signal, devolve
+/// o tempo que faltava.
+pub fn nanosleep(req: &Timespec) -> Result<(), Timespec> {
+    let sleep_ns = req.to_ns().unwrap_or(0);
+    hrtimer::sleep_until(uptime_ns().saturating_add(sleep_ns)).map_err(Timespec::from_ns)
+}
+
+/// Charge a tick of CPU time to the CPU-time timers of the current process
+pub fn account_cpu_tick(user_mode: bool) {
+    itimer::account_cpu_tick(user_mode);
+    posix_timers::account_cpu_tick();
+}
+
+/// Release the interval and POSIX timers of process `tgid` on exit
+pub fn exit_timers(tgid: u64) {
+    itimer::exit(tgid);
+    posix_timers::exit(tgid);
+}
+
+/// Inicializa o RTC e obtém hora real
+pub fn init() {
+    // Lê o RTC para obter a hora real
+    let rtc_time = read_rtc();
+    if rtc_time > 0 {
+        REAL_OFFSET_NS.store((rtc_time * NSEC_PER_SEC).saturating_sub(uptime_ns()), Ordering::Relaxed);
+        crate::kprintln!("time: RTC epoch = {}", rtc_time);
+    }
+    crate::kprintln!("time: timer hz = {}", TIMER_HZ);
+    clocksource::init();
+    hrtimer::init();
+    crate::arch::x86_64_arch::vdso::init();
+    update_vsyscall();
+
+    // Sem /etc/localtime o sistema fica em UTC
+    if let Ok(zone) = tz::load_localtime() {
+        crate::kprintln!("time: timezone {}", zone.name());
+        set_zone(zone);
+    }
+}
+
+/// Lê segundos desde epoch do RTC (CMOS)
+fn read_rtc() -> u64 {
+    use x86_64::instructions::port::Port;
+
+    unsafe {
+        let mut addr = Port::<u8>::new(0x70);
+        let mut data = Port::<u8>::new(0x71);
+
+        // Espera RTC não estar em update
+        loop {
+            addr.write(0x0A);
+            if data.read() & 0x80 == 0 {
+                break;
+            }
+        }
+
+        // Lê valores
+        addr.write(0x00);
+        let sec = bcd_to_bin(data.read());
+        addr.write(0x02);
+        let min = bcd_to_bin(data.read());
+        addr.write(0x04);
+        let hour = bcd_to_bin(data.read());
+        addr.write(0x07);
+        let day = bcd_to_bin(data.read());
+        addr.write(0x08);
+        let month = bcd_to_bin(data.read());
+        addr.write(0x09);
+        let year = bcd_to_bin(data.read()) as u64 + 2000;
+
+        // Converte para Unix timestamp (simplificado, sem leap seconds)
+        let mut days: u64 = 0;
+
+        // Anos desde 1970
+        for y in 1970..year {
+            days += if is_leap_year(y) { 366 } else { 365 };
+        }
+
+        // Meses no ano atual
+        let days_in_month = [31, 28, 31, 30, 31, 30, 31, 31, 30, 31, 30, 31];
+        for m in 1..month {
+            days += days_in_month[m as usize - 1] as u64;
+            if m == 2 && is_leap_year(year) {
+                days += 1;
+            }
+        }
+
+        // Dias no mês
+        days += day as u64 - 1;
+
+        // Converte para segundos
+        days * 86400 + hour as u64 * 3600 + min as u64 * 60 + sec as u64
+    }
+}
+
+/// Escreve a hora real no RTC (CMOS), em UTC e BCD como `read_rtc` lê
+fn write_rtc(unix_secs: u64) {
+    use x86_64::instructions::port::Port;
+
+    let secs = unix_secs % 86400;
+    let (year, month, day) = civil_from_days((unix_secs / 86400) as i64);
+
+    let fields = [
+        (0x00, (secs % 60) as u8),
+        (0x02, (secs / 60 % 60) as u8),
+        (0x04, (secs / 3600) as u8),
+        (0x07, day as u8),
+        (0x08, month as u8),
+        (0x09, (year % 100) as u8),
+    ];
+    unsafe {
+        let mut addr = Port::<u8>::new(0x70);
+        let mut data = Port::<u8>::new(0x71);
+
+        // SET no registrador B congela o relógio durante a escrita
+        addr.write(0x0B);
+        let reg_b = data.read();
+        addr.write(0x0B);
+        data.write(reg_b | 0x80);
+        for (reg, value) in fields {
+            addr.write(reg);
+            data.write(bin_to_bcd(value));
+        }
+        addr.write(0x0B);
+        data.write(reg_b & !0x80);
+    }
+}
+
+/// Save the system time to the RTC if NTP keeps it synchronized, so the
+/// next boot starts from it. Called on shutdown and reboot.
+pub fn sync_rtc() {
+    if !ntp::is_synchronized() {
+        return;
+    }
+    let now = realtime();
+    // Rounded: the RTC starts counting the new second when written
+    let secs = now.tv_sec as u64 + (now.tv_nsec >= 500_000_000) as u64;
+    write_rtc(secs);
+    crate::kprintln!("time: RTC updated to {}", secs);
+}
+
+/// Days since 1970-01-01 of a proleptic Gregorian date
+pub fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let mp = (month as i64 + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + day as i64 - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+/// Proleptic Gregorian date (year, month, day) of a count of days since
+/// 1970-01-01; the inverse of `days_from_civil`
+pub fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+fn bcd_to_bin(bcd: u8) -> u8 {
+    (bcd & 0x0F) + ((bcd >> 4) * 10)
+}
+
+fn bin_to_bcd(bin: u8) -> u8 {
+    ((bin / 10) << 4) | (bin % 10)
+}
+
+fn is_leap_year(year: u64) -> bool {
+    (year % 4 == 0 && year % 100 != 0) || (year % 400 == 0)
+}
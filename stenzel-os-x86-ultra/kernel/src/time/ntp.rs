@@ -0,0 +1,427 @@
+//! Kernel clock discipline: adjtimex and clock_adjtime.
+//!
+//! An NTP daemon (the kernel's own client in `net::ntp`, or one in user
+//! space) measures the offset of the clock and hands it over here. Offsets
+//! are slewed out by a phase-locked loop that also learns the frequency
+//! error of the counter (a frequency-locked loop takes over for long poll
+//! intervals), following the nanokernel model of RFC 1589 as Linux does.
+//! `adjtime` requests (ADJ_OFFSET_SINGLESHOT) slew at a fixed 500 ppm.
+//!
+//! Once a second the timer interrupt calls `second_overflow`, which takes
+//! the next slice of the offset, ages the error estimates and runs the leap
+//! second state machine; the result becomes the rate of monotonic time for
+//! the next second (`clocksource::set_rate_adjust`).
+
+use super::{clocksource, Timeval};
+use crate::sync::IrqSafeMutex;
+use crate::syscall::errno;
+
+pub const ADJ_OFFSET: u32 = 0x0001;
+pub const ADJ_FREQUENCY: u32 = 0x0002;
+pub const ADJ_MAXERROR: u32 = 0x0004;
+pub const ADJ_ESTERROR: u32 = 0x0008;
+pub const ADJ_STATUS: u32 = 0x0010;
+pub const ADJ_TIMECONST: u32 = 0x0020;
+pub const ADJ_TAI: u32 = 0x0080;
+pub const ADJ_SETOFFSET: u32 = 0x0100;
+pub const ADJ_MICRO: u32 = 0x1000;
+pub const ADJ_NANO: u32 = 0x2000;
+pub const ADJ_TICK: u32 = 0x4000;
+/// adjtime(3): slew by `offset` microseconds at a fixed rate
+pub const ADJ_OFFSET_SINGLESHOT: u32 = 0x8001;
+/// Read the adjtime offset still to go
+pub const ADJ_OFFSET_SS_READ: u32 = 0xa001;
+const ADJ_ADJTIME: u32 = 0x8000;
+const ADJ_OFFSET_READONLY: u32 = 0x2000;
+
+pub const STA_PLL: i32 = 0x0001;
+pub const STA_PPSFREQ: i32 = 0x0002;
+pub const STA_PPSTIME: i32 = 0x0004;
+pub const STA_FLL: i32 = 0x0008;
+pub const STA_INS: i32 = 0x0010;
+pub const STA_DEL: i32 = 0x0020;
+pub const STA_UNSYNC: i32 = 0x0040;
+pub const STA_FREQHOLD: i32 = 0x0080;
+pub const STA_PPSSIGNAL: i32 = 0x0100;
+pub const STA_PPSJITTER: i32 = 0x0200;
+pub const STA_PPSWANDER: i32 = 0x0400;
+pub const STA_PPSERROR: i32 = 0x0800;
+pub const STA_CLOCKERR: i32 = 0x1000;
+pub const STA_NANO: i32 = 0x2000;
+/// FLL mode in use
+pub const STA_MODE: i32 = 0x4000;
+pub const STA_CLK: i32 = 0x8000;
+/// Bits adjtimex cannot change
+const STA_RONLY: i32 = STA_PPSSIGNAL
+    | STA_PPSJITTER
+    | STA_PPSWANDER
+    | STA_PPSERROR
+    | STA_CLOCKERR
+    | STA_NANO
+    | STA_MODE
+    | STA_CLK;
+
+/// Clock states, returned by adjtimex
+pub const TIME_OK: i32 = 0;
+/// A leap second is inserted at the end of the day
+pub const TIME_INS: i32 = 1;
+/// A leap second is deleted at the end of the day
+pub const TIME_DEL: i32 = 2;
+/// Leap second in progress
+pub const TIME_OOP: i32 = 3;
+/// Leap second done
+pub const TIME_WAIT: i32 = 4;
+/// Clock not synchronized
+pub const TIME_ERROR: i32 = 5;
+
+const NSEC_PER_USEC: i64 = 1_000;
+const NSEC_PER_SEC: i64 = 1_000_000_000;
+/// Rates are kept in nanoseconds per second shifted left by this
+const SCALE_SHIFT: u32 = 32;
+/// timex.freq is in ppm shifted left by 16
+const PPM_SCALE: i64 = (NSEC_PER_USEC << SCALE_SHIFT) >> 16;
+/// Largest frequency correction, 500 ppm
+const MAXFREQ: i64 = 500_000;
+const MAXFREQ_SCALED: i64 = MAXFREQ << SCALE_SHIFT;
+/// Largest offset the PLL takes, 0.5 s
+const MAXPHASE: i64 = 500_000_000;
+const MAXTC: i64 = 10;
+const SHIFT_PLL: i64 = 2;
+const SHIFT_FLL: u32 = 2;
+/// Shortest and longest update interval for the FLL
+const MINSEC: i64 = 256;
+const MAXSEC: i64 = 2048;
+/// maxerror at which the clock counts as unsynchronized, 16 s
+const PHASE_LIMIT_US: i64 = 16_000_000;
+/// USER_HZ of the timex tick field
+const USER_HZ: i64 = 100;
+/// Rate of adjtime slews, 500 µs per second
+const MAX_TICKADJ_US: i64 = 500;
+
+/// struct timex of the Linux x86-64 ABI
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Timex {
+    pub modes: u32,
+    /// Time offset, µs (ns with STA_NANO)
+    pub offset: i64,
+    /// Frequency offset, ppm shifted left by 16
+    pub freq: i64,
+    /// Maximum error, µs
+    pub maxerror: i64,
+    /// Estimated error, µs
+    pub esterror: i64,
+    pub status: i32,
+    /// PLL time constant
+    pub constant: i64,
+    /// Clock precision, µs
+    pub precision: i64,
+    /// Largest frequency correction, ppm shifted left by 16
+    pub tolerance: i64,
+    /// Current time; the step for ADJ_SETOFFSET
+    pub time: Timeval,
+    /// Microseconds per USER_HZ tick
+    pub tick: i64,
+    pub ppsfreq: i64,
+    pub jitter: i64,
+    pub shift: i32,
+    pub stabil: i64,
+    pub jitcnt: i64,
+    pub calcnt: i64,
+    pub errcnt: i64,
+    pub stbcnt: i64,
+    /// TAI - UTC, seconds
+    pub tai: i32,
+    pub _reserved: [i32; 11],
+}
+
+struct NtpState {
+    status: i32,
+    state: i32,
+    /// Phase error still to slew, ns
+    offset: i64,
+    /// Frequency correction, ns per second shifted left by 32
+    freq: i64,
+    /// Realtime seconds of the last offset update
+    reftime: i64,
+    /// PLL time constant, 0..=MAXTC
+    constant: i64,
+    maxerror: i64,
+    esterror: i64,
+    tai: i32,
+    /// Microseconds per USER_HZ tick
+    tick_usec: i64,
+    /// adjtime offset still to slew, µs
+    adjust: i64,
+    /// Slice of the offset slewed during the current second, ns
+    slice: i64,
+}
+
+impl NtpState {
+    const fn new() -> Self {
+        Self {
+            status: STA_UNSYNC,
+            state: TIME_OK,
+            offset: 0,
+            freq: 0,
+            reftime: 0,
+            constant: 2,
+            maxerror: PHASE_LIMIT_US,
+            esterror: PHASE_LIMIT_US,
+            tai: 0,
+            tick_usec: 1_000_000 / USER_HZ,
+            adjust: 0,
+            slice: 0,
+        }
+    }
+
+    /// Rate of monotonic time for the rest of the second
+    fn rate(&self) -> i64 {
+        let tick = (self.tick_usec * USER_HZ - 1_000_000) * NSEC_PER_USEC;
+        self.freq + ((tick + self.slice) << SCALE_SHIFT)
+    }
+
+    /// Feed a measured offset to the PLL/FLL
+    fn update_offset(&mut self, offset: i64, now: i64) {
+        if self.status & STA_PLL == 0 {
+            return;
+        }
+        let offset = if self.status & STA_NANO == 0 {
+            offset.clamp(-1_000_000, 1_000_000) * NSEC_PER_USEC
+        } else {
+            offset
+        }
+        .clamp(-MAXPHASE, MAXPHASE);
+
+        let mut secs = now - self.reftime;
+        if self.status & STA_FREQHOLD != 0 || self.reftime == 0 {
+            secs = 0;
+        }
+        self.reftime = now;
+
+        // FLL: the frequency error is offset over interval
+        self.status &= !STA_MODE;
+        let mut freq = self.freq as i128;
+        if secs >= MINSEC && (self.status & STA_FLL != 0 || secs > MAXSEC) {
+            self.status |= STA_MODE;
+            freq += ((offset as i128) << (SCALE_SHIFT - SHIFT_FLL)) / secs as i128;
+        }
+        // PLL: the frequency integrates the phase error. A long interval
+        // (an intermittent network) would make the gain unstable.
+        let secs = secs.min(1 << (SHIFT_PLL + 1 + self.constant));
+        let shift = 2 * (SHIFT_PLL + 2 + self.constant) - SCALE_SHIFT as i64;
+        let pll = offset as i128 * secs as i128;
+        freq += if shift >= 0 { pll >> shift } else { pll << -shift };
+        self.freq = freq.clamp(-MAXFREQ_SCALED as i128, MAXFREQ_SCALED as i128) as i64;
+        self.offset = offset;
+    }
+
+    /// Called at each second of realtime `secs`; returns the leap second
+    /// step for realtime (-1 inserts a second, 1 deletes one)
+    fn second_overflow(&mut self, secs: i64) -> i64 {
+        let mut leap = 0;
+        match self.state {
+            TIME_OK if self.status & STA_INS != 0 => self.state = TIME_INS,
+            TIME_OK if self.status & STA_DEL != 0 => self.state = TIME_DEL,
+            TIME_INS if self.status & STA_INS == 0 => self.state = TIME_OK,
+            TIME_INS if secs % 86_400 == 0 => {
+                leap = -1;
+                self.state = TIME_OOP;
+                self.tai += 1;
+                crate::kprintln!("time: inserting leap second 23:59:60 UTC");
+            }
+            TIME_DEL if self.status & STA_DEL == 0 => self.state = TIME_OK,
+            TIME_DEL if (secs + 1) % 86_400 == 0 => {
+                leap = 1;
+                self.state = TIME_WAIT;
+                self.tai -= 1;
+                crate::kprintln!("time: deleting leap second 23:59:59 UTC");
+            }
+            TIME_OOP => self.state = TIME_WAIT,
+            TIME_WAIT if self.status & (STA_INS | STA_DEL) == 0 => self.state = TIME_OK,
+            _ => {}
+        }
+
+        // Without updates the error grows by the frequency tolerance
+        self.maxerror += MAXFREQ / NSEC_PER_USEC;
+        if self.maxerror > PHASE_LIMIT_US {
+            self.maxerror = PHASE_LIMIT_US;
+            self.status |= STA_UNSYNC;
+        }
+
+        // Next slice of the phase error, and of an adjtime slew
+        self.slice = shift_right(self.offset, SHIFT_PLL + self.constant);
+        self.offset -= self.slice;
+        if self.adjust != 0 {
+            let step = self.adjust.clamp(-MAX_TICKADJ_US, MAX_TICKADJ_US);
+            self.adjust -= step;
+            self.slice += step * NSEC_PER_USEC;
+        }
+        leap
+    }
+
+    fn clock_state(&self) -> i32 {
+        if self.status & STA_UNSYNC != 0 { TIME_ERROR } else { self.state }
+    }
+
+    fn fill(&self, txc: &mut Timex, now: Timeval) {
+        txc.offset = if self.status & STA_NANO != 0 { self.offset } else { self.offset / NSEC_PER_USEC };
+        txc.freq = self.freq / PPM_SCALE;
+        txc.maxerror = self.maxerror;
+        txc.esterror = self.esterror;
+        txc.status = self.status;
+        txc.constant = self.constant;
+        txc.precision = 1;
+        txc.tolerance = MAXFREQ_SCALED / PPM_SCALE;
+        txc.tick = self.tick_usec;
+        txc.tai = self.tai;
+        txc.time = now;
+        if self.status & STA_NANO == 0 {
+            txc.time.tv_usec /= NSEC_PER_USEC;
+        }
+    }
+}
+
+/// `x >> s`, rounding toward zero
+fn shift_right(x: i64, s: i64) -> i64 {
+    if x < 0 { -((-x) >> s) } else { x >> s }
+}
+
+static NTP: IrqSafeMutex<NtpState> = IrqSafeMutex::new(NtpState::new());
+
+/// Check the requested modes; `privileged` tells whether the caller may
+/// set the clock
+fn validate(txc: &Timex, privileged: bool) -> Result<(), i64> {
+    if txc.modes & ADJ_ADJTIME != 0 {
+        // adjtime takes no other modes
+        if txc.modes & ADJ_OFFSET_SINGLESHOT != ADJ_OFFSET_SINGLESHOT {
+            return Err(errno::EINVAL);
+        }
+        if txc.modes & ADJ_OFFSET_READONLY == 0 && !privileged {
+            return Err(errno::EPERM);
+        }
+        return Ok(());
+    }
+    if txc.modes != 0 && !privileged {
+        return Err(errno::EPERM);
+    }
+    if txc.modes & ADJ_TICK != 0
+        && !(900_000 / USER_HZ..=1_100_000 / USER_HZ).contains(&txc.tick)
+    {
+        return Err(errno::EINVAL);
+    }
+    if txc.modes & ADJ_SETOFFSET != 0 {
+        let limit = if txc.modes & ADJ_NANO != 0 { NSEC_PER_SEC } else { 1_000_000 };
+        if !(0..limit).contains(&txc.time.tv_usec) {
+            return Err(errno::EINVAL);
+        }
+    }
+    Ok(())
+}
+
+/// adjtimex: read the clock discipline state and change the parts named
+/// in `txc.modes`. Returns the clock state (TIME_*).
+pub fn adjtimex(txc: &mut Timex, privileged: bool) -> Result<i32, i64> {
+    validate(txc, privileged)?;
+
+    if txc.modes & ADJ_SETOFFSET != 0 {
+        let unit = if txc.modes & ADJ_NANO != 0 { 1 } else { NSEC_PER_USEC };
+        let delta = txc.time.tv_sec.checked_mul(NSEC_PER_SEC).and_then(|ns| ns.checked_add(txc.time.tv_usec * unit));
+        super::step(delta.ok_or(errno::EINVAL)?)?;
+    }
+
+    let now = super::realtime();
+    let now_tv = Timeval { tv_sec: now.tv_sec, tv_usec: now.tv_nsec };
+    let mut ntp = NTP.lock();
+
+    if txc.modes & ADJ_ADJTIME != 0 {
+        let remaining = ntp.adjust;
+        if txc.modes & ADJ_OFFSET_READONLY == 0 {
+            ntp.adjust = txc.offset;
+        }
+        let state = ntp.clock_state();
+        ntp.fill(txc, now_tv);
+        txc.offset = remaining;
+        return Ok(state);
+    }
+
+    if txc.modes & ADJ_STATUS != 0 {
+        if ntp.status & STA_PLL != 0 && txc.status & STA_PLL == 0 {
+            ntp.state = TIME_OK;
+            ntp.status = STA_UNSYNC;
+        }
+        if ntp.status & STA_PLL == 0 && txc.status & STA_PLL != 0 {
+            ntp.reftime = now.tv_sec;
+        }
+        ntp.status = (ntp.status & STA_RONLY) | (txc.status & !STA_RONLY);
+    }
+    if txc.modes & ADJ_NANO != 0 {
+        ntp.status |= STA_NANO;
+    }
+    if txc.modes & ADJ_MICRO != 0 {
+        ntp.status &= !STA_NANO;
+    }
+    if txc.modes & ADJ_FREQUENCY != 0 {
+        ntp.freq = txc.freq.clamp(-MAXFREQ_SCALED / PPM_SCALE, MAXFREQ_SCALED / PPM_SCALE) * PPM_SCALE;
+    }
+    if txc.modes & ADJ_MAXERROR != 0 {
+        ntp.maxerror = txc.maxerror.clamp(0, PHASE_LIMIT_US);
+    }
+    if txc.modes & ADJ_ESTERROR != 0 {
+        ntp.esterror = txc.esterror.clamp(0, PHASE_LIMIT_US);
+    }
+    if txc.modes & ADJ_TIMECONST != 0 {
+        let constant = if ntp.status & STA_NANO == 0 { txc.constant + 4 } else { txc.constant };
+        ntp.constant = constant.clamp(0, MAXTC);
+    }
+    if txc.modes & ADJ_TAI != 0 && txc.constant >= 0 {
+        ntp.tai = txc.constant as i32;
+    }
+    if txc.modes & ADJ_OFFSET != 0 {
+        ntp.update_offset(txc.offset, now.tv_sec);
+    }
+    if txc.modes & ADJ_TICK != 0 {
+        ntp.tick_usec = txc.tick;
+    }
+    if txc.modes & (ADJ_FREQUENCY | ADJ_OFFSET | ADJ_TICK) != 0 {
+        clocksource::set_rate_adjust(ntp.rate());
+    }
+
+    let state = ntp.clock_state();
+    ntp.fill(txc, now_tv);
+    Ok(state)
+}
+
+/// Run the discipline for the second of realtime `secs` that just began.
+/// Returns the leap second step to apply to realtime.
+pub fn second_overflow(secs: i64) -> i64 {
+    let mut ntp = NTP.lock();
+    let leap = ntp.second_overflow(secs);
+    clocksource::set_rate_adjust(ntp.rate());
+    leap
+}
+
+/// The clock was set: forget the offset being slewed and mark the clock
+/// unsynchronized
+pub fn clear() {
+    let mut ntp = NTP.lock();
+    ntp.status |= STA_UNSYNC;
+    ntp.maxerror = PHASE_LIMIT_US;
+    ntp.esterror = PHASE_LIMIT_US;
+    ntp.offset = 0;
+    ntp.adjust = 0;
+    ntp.slice = 0;
+    clocksource::set_rate_adjust(ntp.rate());
+}
+
+/// Whether an NTP daemon keeps the clock synchronized
+pub fn is_synchronized() -> bool {
+    NTP.lock().status & STA_UNSYNC == 0
+}
+
+/// Read-only snapshot of the discipline state
+pub fn status() -> Timex {
+    let mut txc = Timex::default();
+    let _ = adjtimex(&mut txc, false);
+    txc
+}
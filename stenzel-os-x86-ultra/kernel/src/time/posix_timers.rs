@@ -0,0 +1,359 @@
+//! POSIX per-process timers: timer_create, timer_settime, timer_gettime,
+//! timer_getoverrun and timer_delete.
+//!
+//! Timers on CLOCK_REALTIME, CLOCK_MONOTONIC and CLOCK_BOOTTIME run on a
+//! hrtimer; timers on the CPU-time clocks are charged one tick at a time by
+//! the timer interrupt. An expiry sends a signal to the process
+//! (SIGEV_SIGNAL) or to one of its threads (SIGEV_THREAD_ID). SIGEV_THREAD
+//! is implemented by the C library on top of SIGEV_THREAD_ID, so the
+//! kernel treats it as SIGEV_SIGNAL.
+//!
+//! Signals carry no siginfo, so `sigev_value` is kept but not delivered.
+//! While the signal of a timer is still pending, later expiries are counted
+//! as overruns instead of being queued.
+
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+
+use super::hrtimer::{self, TimerId};
+use super::{clock, clock_now_ns, uptime_ns, Itimerspec, Timespec, NS_PER_TICK, TIMER_ABSTIME};
+use crate::signal::sig;
+use crate::sync::IrqSafeMutex;
+use crate::syscall::errno;
+
+pub const SIGEV_SIGNAL: i32 = 0;
+pub const SIGEV_NONE: i32 = 1;
+pub const SIGEV_THREAD: i32 = 2;
+pub const SIGEV_THREAD_ID: i32 = 4;
+
+/// Timers per process
+pub const MAX_TIMERS: usize = 256;
+
+/// struct sigevent
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Sigevent {
+    pub sigev_value: u64,
+    pub sigev_signo: i32,
+    pub sigev_notify: i32,
+    /// Thread ID for SIGEV_THREAD_ID; function and attributes of
+    /// SIGEV_THREAD (used by the C library only)
+    pub sigev_un: [u64; 6],
+}
+
+impl Sigevent {
+    fn thread_id(&self) -> u64 {
+        self.sigev_un[0] as u32 as u64
+    }
+}
+
+struct PosixTimer {
+    clock: i32,
+    notify: i32,
+    signo: u32,
+    /// Thread signalled with SIGEV_THREAD_ID
+    tid: u64,
+    /// Thread whose CPU time a CLOCK_THREAD_CPUTIME_ID timer counts
+    owner: u64,
+    value: u64,
+    interval: u64,
+    /// Monotonic expiry of a wall-clock timer, 0 when disarmed
+    expires: u64,
+    hrtimer: Option<TimerId>,
+    /// CPU time left on a CPU-time timer, 0 when disarmed
+    cpu_left: u64,
+    /// Bumped by every timer_settime, so a stale callback can tell
+    generation: u64,
+    /// Expiries while the signal was pending, not yet reported
+    overrun_pending: u64,
+    /// Overruns of the last signal sent (timer_getoverrun)
+    overrun: u64,
+}
+
+impl PosixTimer {
+    fn is_cpu(&self) -> bool {
+        matches!(self.clock, clock::CLOCK_PROCESS_CPUTIME_ID | clock::CLOCK_THREAD_CPUTIME_ID)
+    }
+
+    /// Task the signal goes to, if any
+    fn target(&self, tgid: u64) -> Option<u64> {
+        match self.notify {
+            SIGEV_NONE => None,
+            SIGEV_THREAD_ID => Some(self.tid),
+            _ => Some(tgid),
+        }
+    }
+
+    /// Account an expiry (plus `missed` skipped periods); true if the
+    /// signal should be sent, false if it counts as an overrun of the
+    /// signal still pending
+    fn expire(&mut self, missed: u64, pending: bool) -> bool {
+        if pending {
+            self.overrun_pending = self.overrun_pending.saturating_add(1 + missed);
+            return false;
+        }
+        self.overrun = self.overrun_pending.saturating_add(missed);
+        self.overrun_pending = 0;
+        true
+    }
+
+    fn disarm(&mut self) {
+        if let Some(id) = self.hrtimer.take() {
+            hrtimer::cancel(id);
+        }
+        self.expires = 0;
+        self.cpu_left = 0;
+        self.interval = 0;
+    }
+
+    fn current(&self) -> Itimerspec {
+        let left = if self.is_cpu() {
+            self.cpu_left
+        } else if self.hrtimer.is_some_and(|id| hrtimer::expires(id).is_some()) {
+            self.expires.saturating_sub(uptime_ns()).max(1)
+        } else {
+            0
+        };
+        Itimerspec {
+            it_interval: Timespec::from_ns(if left == 0 { 0 } else { self.interval }),
+            it_value: Timespec::from_ns(left),
+        }
+    }
+}
+
+struct ProcessTimers {
+    next_id: i32,
+    timers: BTreeMap<i32, PosixTimer>,
+}
+
+/// Timers of each process, by tgid
+static TIMERS: IrqSafeMutex<BTreeMap<u64, ProcessTimers>> = IrqSafeMutex::new(BTreeMap::new());
+
+/// Notify an expiry of timer `id` of process `tgid`, after `update`
+/// (which gets the timer under the lock) returns the missed periods.
+/// Whether the previous signal is still pending is checked without TIMERS
+/// held: the scheduler lock comes first.
+fn notify(tgid: u64, id: i32, update: impl FnOnce(&mut PosixTimer) -> Option<u64>) {
+    let target = {
+        let mut all = TIMERS.lock();
+        let Some(timer) = all.get_mut(&tgid).and_then(|process| process.timers.get_mut(&id)) else {
+            return;
+        };
+        let Some(missed) = update(timer) else {
+            return;
+        };
+        timer.target(tgid).map(|target| (target, timer.signo, timer.notify, missed))
+    };
+    let Some((target, signo, notify, missed)) = target else {
+        return;
+    };
+
+    let pending = crate::sched::signal_pending_for(target, signo);
+    let send = {
+        let mut all = TIMERS.lock();
+        let Some(timer) = all.get_mut(&tgid).and_then(|process| process.timers.get_mut(&id)) else {
+            return;
+        };
+        timer.expire(missed, pending)
+    };
+    if !send {
+        return;
+    }
+    if notify == SIGEV_THREAD_ID {
+        let _ = crate::sched::send_signal_to_thread(tgid, target, signo);
+    } else {
+        let _ = crate::sched::send_kernel_signal(target, signo);
+    }
+}
+
+/// timer_create for the current process. `event` None means SIGEV_SIGNAL
+/// with SIGALRM.
+pub fn create(clock_id: i32, event: Option<&Sigevent>) -> Result<i32, i64> {
+    if clock_now_ns(clock_id).is_none()
+        || matches!(clock_id, clock::CLOCK_MONOTONIC_RAW | clock::CLOCK_REALTIME_COARSE | clock::CLOCK_MONOTONIC_COARSE)
+    {
+        return Err(errno::EINVAL);
+    }
+    let tgid = crate::sched::current_tgid();
+    let (notify, signo, tid) = match event {
+        None => (SIGEV_SIGNAL, sig::SIGALRM, 0),
+        Some(event) => {
+            let notify = event.sigev_notify;
+            if !matches!(notify, SIGEV_SIGNAL | SIGEV_NONE | SIGEV_THREAD | SIGEV_THREAD_ID) {
+                return Err(errno::EINVAL);
+            }
+            let signo = event.sigev_signo as u32;
+            if notify != SIGEV_NONE && (signo == 0 || signo >= sig::NSIG) {
+                return Err(errno::EINVAL);
+            }
+            let tid = if notify == SIGEV_THREAD_ID { event.thread_id() } else { 0 };
+            if notify == SIGEV_THREAD_ID && !crate::sched::thread_group(tgid).contains(&tid) {
+                return Err(errno::EINVAL);
+            }
+            (notify, signo, tid)
+        }
+    };
+
+    let mut all = TIMERS.lock();
+    let process = all.entry(tgid).or_insert_with(|| ProcessTimers { next_id: 0, timers: BTreeMap::new() });
+    if process.timers.len() >= MAX_TIMERS {
+        return Err(errno::EAGAIN);
+    }
+    let mut id = process.next_id;
+    while process.timers.contains_key(&id) {
+        id = id.checked_add(1).unwrap_or(0);
+    }
+    process.next_id = id.checked_add(1).unwrap_or(0);
+    process.timers.insert(
+        id,
+        PosixTimer {
+            clock: clock_id,
+            notify,
+            signo,
+            tid,
+            owner: crate::sched::current_tid(),
+            value: event.map_or(id as u64, |event| event.sigev_value),
+            interval: 0,
+            expires: 0,
+            hrtimer: None,
+            cpu_left: 0,
+            generation: 0,
+            overrun_pending: 0,
+            overrun: 0,
+        },
+    );
+    Ok(id)
+}
+
+fn with_timer<R>(id: i32, f: impl FnOnce(&mut PosixTimer) -> R) -> Result<R, i64> {
+    let tgid = crate::sched::current_tgid();
+    let mut all = TIMERS.lock();
+    let timer = all
+        .get_mut(&tgid)
+        .and_then(|process| process.timers.get_mut(&id))
+        .ok_or(errno::EINVAL)?;
+    Ok(f(timer))
+}
+
+/// hrtimer callback of a wall-clock timer
+fn fire(tgid: u64, id: i32, generation: u64, now: u64) -> Option<u64> {
+    let mut next = None;
+    notify(tgid, id, |timer| {
+        if timer.generation != generation {
+            return None;
+        }
+        let (expires, missed) = hrtimer::forward(timer.expires, timer.interval, now);
+        timer.expires = if timer.interval == 0 { 0 } else { expires };
+        next = (timer.interval != 0).then_some(expires);
+        Some(missed)
+    });
+    next
+}
+
+/// timer_settime; returns the previous setting
+pub fn settime(id: i32, flags: i32, new: &Itimerspec) -> Result<Itimerspec, i64> {
+    let value = new.it_value.to_ns().ok_or(errno::EINVAL)?;
+    let interval = new.it_interval.to_ns().ok_or(errno::EINVAL)?;
+    let tgid = crate::sched::current_tgid();
+
+    // Read the clock before taking TIMERS: the CPU-time clocks need the
+    // scheduler lock
+    let clock_id = with_timer(id, |timer| timer.clock)?;
+    let now = clock_now_ns(clock_id).ok_or(errno::EINVAL)?;
+    let expires = if flags & TIMER_ABSTIME != 0 {
+        uptime_ns().saturating_add(value.saturating_sub(now))
+    } else {
+        uptime_ns().saturating_add(value)
+    };
+
+    with_timer(id, |timer| {
+        let old = timer.current();
+        timer.disarm();
+        timer.generation += 1;
+        timer.overrun_pending = 0;
+        if value == 0 {
+            return old;
+        }
+        timer.interval = interval;
+
+        if timer.is_cpu() {
+            timer.cpu_left = if flags & TIMER_ABSTIME != 0 { value.saturating_sub(now).max(1) } else { value };
+            return old;
+        }
+
+        let generation = timer.generation;
+        timer.expires = expires;
+        timer.hrtimer = Some(hrtimer::start(expires, move |now| fire(tgid, id, generation, now)));
+        old
+    })
+}
+
+/// timer_gettime
+pub fn gettime(id: i32) -> Result<Itimerspec, i64> {
+    with_timer(id, |timer| timer.current())
+}
+
+/// timer_getoverrun
+pub fn getoverrun(id: i32) -> Result<i32, i64> {
+    with_timer(id, |timer| timer.overrun.min(i32::MAX as u64) as i32)
+}
+
+/// timer_delete
+pub fn delete(id: i32) -> Result<(), i64> {
+    let tgid = crate::sched::current_tgid();
+    let mut all = TIMERS.lock();
+    let mut timer = all
+        .get_mut(&tgid)
+        .and_then(|process| process.timers.remove(&id))
+        .ok_or(errno::EINVAL)?;
+    timer.disarm();
+    Ok(())
+}
+
+/// Charge one tick of CPU time to the CPU-time timers of the current
+/// process. Called from the timer interrupt.
+pub fn account_cpu_tick() {
+    let Some(task) = crate::sched::try_current_task() else {
+        return;
+    };
+    if !task.is_user() {
+        return;
+    }
+    let (tgid, tid) = (task.tgid(), task.id());
+
+    let mut expired = Vec::new();
+    {
+        let mut all = TIMERS.lock();
+        let Some(process) = all.get_mut(&tgid) else {
+            return;
+        };
+        for (&id, timer) in process.timers.iter_mut() {
+            if timer.cpu_left == 0 || (timer.clock == clock::CLOCK_THREAD_CPUTIME_ID && timer.owner != tid) {
+                continue;
+            }
+            if timer.cpu_left > NS_PER_TICK {
+                timer.cpu_left -= NS_PER_TICK;
+                continue;
+            }
+            let excess = NS_PER_TICK - timer.cpu_left;
+            let (left, missed) = match timer.interval {
+                0 => (0, 0),
+                interval => (interval - excess % interval, excess / interval),
+            };
+            timer.cpu_left = left;
+            expired.push((id, timer.generation, missed));
+        }
+    }
+    for (id, generation, missed) in expired {
+        notify(tgid, id, |timer| (timer.generation == generation).then_some(missed));
+    }
+}
+
+/// Delete the timers of an exiting process
+pub fn exit(tgid: u64) {
+    if let Some(process) = TIMERS.lock().remove(&tgid) {
+        for mut timer in process.timers.into_values() {
+            timer.disarm();
+        }
+    }
+}
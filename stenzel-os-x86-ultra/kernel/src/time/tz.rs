@@ -0,0 +1,561 @@
+//! IANA time zones: TZif files and POSIX TZ rules.
+//!
+//! A zone is read from a TZif file (RFC 8536) under `/usr/share/zoneinfo`.
+//! Its transition table covers the past; after the last transition the
+//! POSIX TZ string in the file footer (e.g. `EST5EDT,M3.2.0,M11.1.0`) gives
+//! the rule, so daylight saving keeps switching in later years. Version 1
+//! files have no footer and stay at their last offset.
+//!
+//! The zone in use is read from `/etc/localtime` at boot and changed with
+//! `time::set_timezone_by_name`. Code that caches local time registers a
+//! callback or compares `generation()`.
+
+use alloc::format;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use crate::security::Cred;
+use crate::sync::IrqSafeMutex;
+use crate::util::{KError, KResult};
+
+use super::{civil_from_days, days_from_civil};
+
+pub const ZONEINFO_DIR: &str = "/usr/share/zoneinfo";
+pub const LOCALTIME_PATH: &str = "/etc/localtime";
+
+const SECS_PER_DAY: i64 = 86_400;
+
+/// Offset and abbreviation of local time during some period
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LocalTimeType {
+    /// Seconds east of UTC
+    pub utoff: i32,
+    pub is_dst: bool,
+    /// Abbreviation, e.g. "EST"
+    pub abbr: String,
+}
+
+/// Day of the year a daylight saving rule switches on
+#[derive(Debug, Clone, Copy)]
+enum RuleDay {
+    /// `Jn`: day 1..=365, February 29 never counted
+    Julian1(u16),
+    /// `n`: day 0..=365, February 29 counted
+    Julian0(u16),
+    /// `Mm.w.d`: weekday `d` (0 = Sunday) of week `w` (5 = last) of month `m`
+    MonthWeekDay { month: u32, week: u32, weekday: u32 },
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Rule {
+    day: RuleDay,
+    /// Local time of the switch in seconds after midnight; may be
+    /// negative or past 24h
+    time: i32,
+}
+
+impl Rule {
+    /// Local time, in seconds since the epoch, the rule fires at in `year`
+    fn local_time(&self, year: i64) -> i64 {
+        let jan1 = days_from_civil(year, 1, 1);
+        let day = match self.day {
+            RuleDay::Julian1(n) => {
+                let day = n as i64 - 1;
+                if is_leap(year) && day >= 59 { day + 1 } else { day }
+            }
+            RuleDay::Julian0(n) => n as i64,
+            RuleDay::MonthWeekDay { month, week, weekday } => {
+                let first = days_from_civil(year, month, 1);
+                let mut mday = (weekday as i64 - weekday_of(first)).rem_euclid(7) + (week as i64 - 1) * 7;
+                while mday >= days_in_month(year, month) {
+                    mday -= 7;
+                }
+                first - jan1 + mday
+            }
+        };
+        (jan1 + day) * SECS_PER_DAY + self.time as i64
+    }
+}
+
+#[derive(Debug, Clone)]
+struct DstRule {
+    dst: LocalTimeType,
+    start: Rule,
+    end: Rule,
+}
+
+/// A POSIX TZ string such as `<-03>3` or `CET-1CEST,M3.5.0,M10.5.0/3`
+#[derive(Debug, Clone)]
+pub struct PosixTz {
+    std: LocalTimeType,
+    dst: Option<DstRule>,
+}
+
+impl PosixTz {
+    pub fn parse(s: &str) -> KResult<PosixTz> {
+        let mut cur = Cursor { s: s.as_bytes(), pos: 0 };
+        let std = LocalTimeType {
+            abbr: cur.name()?,
+            utoff: -cur.hms(24)?,
+            is_dst: false,
+        };
+        if cur.done() {
+            return Ok(PosixTz { std, dst: None });
+        }
+
+        let abbr = cur.name()?;
+        let utoff = match cur.peek() {
+            Some(b'+' | b'-' | b'0'..=b'9') => -cur.hms(24)?,
+            _ => std.utoff + 3600,
+        };
+        let (start, end) = if cur.eat(b',') {
+            let start = cur.rule()?;
+            if !cur.eat(b',') {
+                return Err(KError::Invalid);
+            }
+            (start, cur.rule()?)
+        } else {
+            // No rule given: the US one, as other implementations assume
+            let rule = |month, week| Rule { day: RuleDay::MonthWeekDay { month, week, weekday: 0 }, time: 7200 };
+            (rule(3, 2), rule(11, 1))
+        };
+        if !cur.done() {
+            return Err(KError::Invalid);
+        }
+        Ok(PosixTz {
+            std,
+            dst: Some(DstRule { dst: LocalTimeType { utoff, is_dst: true, abbr }, start, end }),
+        })
+    }
+
+    /// UTC instants daylight saving starts and ends at in `year`
+    fn switches(&self, rule: &DstRule, year: i64) -> (i64, i64) {
+        (
+            rule.start.local_time(year) - self.std.utoff as i64,
+            rule.end.local_time(year) - rule.dst.utoff as i64,
+        )
+    }
+
+    fn lookup(&self, utc: i64) -> &LocalTimeType {
+        let Some(rule) = &self.dst else {
+            return &self.std;
+        };
+        let (start, end) = self.switches(rule, year_of(utc + self.std.utoff as i64));
+        // Southern hemisphere rules start late in the year and end early
+        let in_dst = if start <= end {
+            start <= utc && utc < end
+        } else {
+            !(end <= utc && utc < start)
+        };
+        if in_dst { &rule.dst } else { &self.std }
+    }
+
+    fn next_transition(&self, after: i64) -> Option<(i64, &LocalTimeType)> {
+        let rule = self.dst.as_ref()?;
+        let year = year_of(after);
+        (year - 1..=year + 1)
+            .flat_map(|year| {
+                let (start, end) = self.switches(rule, year);
+                [(start, &rule.dst), (end, &self.std)]
+            })
+            .filter(|&(at, _)| at > after)
+            .min_by_key(|&(at, _)| at)
+    }
+}
+
+/// A time zone: transitions from a TZif file, then the footer rule
+#[derive(Debug, Clone)]
+pub struct Zone {
+    name: String,
+    /// UTC instants of the transitions, ascending
+    transitions: Vec<i64>,
+    /// Index into `types` of the type in force from each transition on
+    type_of: Vec<u8>,
+    types: Vec<LocalTimeType>,
+    footer: Option<PosixTz>,
+}
+
+impl Zone {
+    /// Parse a TZif file (versions 1 to 4)
+    pub fn parse(name: &str, data: &[u8]) -> KResult<Zone> {
+        let mut r = Reader { data, pos: 0 };
+        let mut header = Header::read(&mut r)?;
+        let mut time_size = 4;
+        // Version 2+ repeats the data with 64-bit times after the v1 block
+        if header.version >= b'2' {
+            r.take(header.body_len(4))?;
+            header = Header::read(&mut r)?;
+            time_size = 8;
+        }
+        if header.typecnt == 0
+            || header.charcnt == 0
+            || (header.isstdcnt != 0 && header.isstdcnt != header.typecnt)
+            || (header.isutcnt != 0 && header.isutcnt != header.typecnt)
+        {
+            return Err(KError::Invalid);
+        }
+
+        let mut transitions = Vec::with_capacity(header.timecnt);
+        for _ in 0..header.timecnt {
+            let at = if time_size == 8 { r.i64()? } else { r.i32()? as i64 };
+            if transitions.last().is_some_and(|&last| at <= last) {
+                return Err(KError::Invalid);
+            }
+            transitions.push(at);
+        }
+        let type_of = r.take(header.timecnt)?.to_vec();
+        if type_of.iter().any(|&idx| idx as usize >= header.typecnt) {
+            return Err(KError::Invalid);
+        }
+        let mut raw_types = Vec::with_capacity(header.typecnt);
+        for _ in 0..header.typecnt {
+            let utoff = r.i32()?;
+            let isdst = r.take(1)?[0];
+            let abbrind = r.take(1)?[0] as usize;
+            if utoff == i32::MIN || isdst > 1 {
+                return Err(KError::Invalid);
+            }
+            raw_types.push((utoff, isdst == 1, abbrind));
+        }
+        let chars = r.take(header.charcnt)?;
+        let mut types = Vec::with_capacity(header.typecnt);
+        for (utoff, is_dst, abbrind) in raw_types {
+            let abbr = chars.get(abbrind..).ok_or(KError::Invalid)?;
+            let len = abbr.iter().position(|&c| c == 0).ok_or(KError::Invalid)?;
+            let abbr = core::str::from_utf8(&abbr[..len]).map_err(|_| KError::Invalid)?;
+            types.push(LocalTimeType { utoff, is_dst, abbr: String::from(abbr) });
+        }
+        // Leap seconds and the standard/UT indicators are not used
+        r.take(header.leapcnt * (time_size + 4) + header.isstdcnt + header.isutcnt)?;
+
+        let footer = if header.version >= b'2' {
+            if r.take(1)? != b"\n" {
+                return Err(KError::Invalid);
+            }
+            let rest = &data[r.pos..];
+            let len = rest.iter().position(|&c| c == b'\n').ok_or(KError::Invalid)?;
+            match core::str::from_utf8(&rest[..len]).map_err(|_| KError::Invalid)? {
+                "" => None,
+                tz => Some(PosixTz::parse(tz)?),
+            }
+        } else {
+            None
+        };
+
+        Ok(Zone { name: String::from(name), transitions, type_of, types, footer })
+    }
+
+    /// A zone following a POSIX TZ string only
+    pub fn from_posix(tz: &str) -> KResult<Zone> {
+        let footer = PosixTz::parse(tz)?;
+        Ok(Zone {
+            name: String::from(tz),
+            transitions: Vec::new(),
+            type_of: Vec::new(),
+            types: alloc::vec![footer.std.clone()],
+            footer: Some(footer),
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Local time type in force at `utc` (seconds since the epoch)
+    pub fn lookup(&self, utc: i64) -> &LocalTimeType {
+        let n = self.transitions.partition_point(|&at| at <= utc);
+        if n == self.transitions.len() {
+            if let Some(footer) = &self.footer {
+                return footer.lookup(utc);
+            }
+        }
+        match n {
+            // Before the first transition the first type applies
+            0 => &self.types[0],
+            n => &self.types[self.type_of[n - 1] as usize],
+        }
+    }
+
+    /// First transition after `utc`, and the type it switches to
+    pub fn next_transition(&self, utc: i64) -> Option<(i64, &LocalTimeType)> {
+        let n = self.transitions.partition_point(|&at| at <= utc);
+        match self.transitions.get(n) {
+            Some(&at) => Some((at, &self.types[self.type_of[n] as usize])),
+            None => self.footer.as_ref()?.next_transition(utc),
+        }
+    }
+
+    /// Whether the zone still switches to daylight saving time
+    pub fn has_dst(&self) -> bool {
+        self.footer.as_ref().is_some_and(|footer| footer.dst.is_some())
+    }
+
+    pub fn utc_to_local(&self, utc: i64) -> i64 {
+        utc + self.lookup(utc).utoff as i64
+    }
+
+    /// Local time to UTC. A local time repeated when clocks go back maps
+    /// to its first occurrence; one skipped when they go forward is read
+    /// with the offset from before the switch.
+    pub fn local_to_utc(&self, local: i64) -> i64 {
+        // Transitions are far more than a day apart, so these are the
+        // offsets either side of any switch near `local`
+        let before = self.lookup(local - SECS_PER_DAY).utoff as i64;
+        let after = self.lookup(local + SECS_PER_DAY).utoff as i64;
+        [local - before, local - after]
+            .into_iter()
+            .filter(|&utc| self.utc_to_local(utc) == local)
+            .min()
+            .unwrap_or(local - before)
+    }
+}
+
+struct Header {
+    version: u8,
+    isutcnt: usize,
+    isstdcnt: usize,
+    leapcnt: usize,
+    timecnt: usize,
+    typecnt: usize,
+    charcnt: usize,
+}
+
+impl Header {
+    fn read(r: &mut Reader) -> KResult<Header> {
+        if r.take(4)? != b"TZif" {
+            return Err(KError::Invalid);
+        }
+        let version = match r.take(1)?[0] {
+            0 => b'1',
+            v @ b'2'..=b'4' => v,
+            _ => return Err(KError::NotSupported),
+        };
+        r.take(15)?;
+        let mut count = || r.i32().map(|n| n as u32 as usize);
+        Ok(Header {
+            version,
+            isutcnt: count()?,
+            isstdcnt: count()?,
+            leapcnt: count()?,
+            timecnt: count()?,
+            typecnt: count()?,
+            charcnt: count()?,
+        })
+    }
+
+    /// Size of the data block following the header
+    fn body_len(&self, time_size: usize) -> usize {
+        self.timecnt * (time_size + 1)
+            + self.typecnt * 6
+            + self.charcnt
+            + self.leapcnt * (time_size + 4)
+            + self.isstdcnt
+            + self.isutcnt
+    }
+}
+
+/// Big-endian reader over a TZif file
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> KResult<&'a [u8]> {
+        let end = self.pos.checked_add(len).filter(|&end| end <= self.data.len()).ok_or(KError::Invalid)?;
+        let bytes = &self.data[self.pos..end];
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    fn i32(&mut self) -> KResult<i32> {
+        let bytes = self.take(4)?;
+        Ok(i32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    fn i64(&mut self) -> KResult<i64> {
+        let mut bytes = [0u8; 8];
+        bytes.copy_from_slice(self.take(8)?);
+        Ok(i64::from_be_bytes(bytes))
+    }
+}
+
+/// Parser state over a POSIX TZ string
+struct Cursor<'a> {
+    s: &'a [u8],
+    pos: usize,
+}
+
+impl Cursor<'_> {
+    fn peek(&self) -> Option<u8> {
+        self.s.get(self.pos).copied()
+    }
+
+    fn done(&self) -> bool {
+        self.pos == self.s.len()
+    }
+
+    fn eat(&mut self, c: u8) -> bool {
+        let found = self.peek() == Some(c);
+        if found {
+            self.pos += 1;
+        }
+        found
+    }
+
+    fn number(&mut self, min: u32, max: u32) -> KResult<u32> {
+        let start = self.pos;
+        let mut n = 0u32;
+        while let Some(c @ b'0'..=b'9') = self.peek() {
+            n = n.saturating_mul(10).saturating_add((c - b'0') as u32);
+            self.pos += 1;
+        }
+        if self.pos == start || n < min || n > max {
+            return Err(KError::Invalid);
+        }
+        Ok(n)
+    }
+
+    /// A zone abbreviation: three or more letters, or `<...>` quoted
+    fn name(&mut self) -> KResult<String> {
+        let quoted = self.eat(b'<');
+        let start = self.pos;
+        while let Some(c) = self.peek() {
+            let ok = c.is_ascii_alphabetic() || (quoted && (c.is_ascii_digit() || c == b'+' || c == b'-'));
+            if !ok {
+                break;
+            }
+            self.pos += 1;
+        }
+        let name = &self.s[start..self.pos];
+        if name.len() < 3 || (quoted && !self.eat(b'>')) {
+            return Err(KError::Invalid);
+        }
+        Ok(name.iter().map(|&c| c as char).collect())
+    }
+
+    /// `[+-]hh[:mm[:ss]]` in seconds, hours up to `max_hours`
+    fn hms(&mut self, max_hours: u32) -> KResult<i32> {
+        let sign = if self.eat(b'-') {
+            -1
+        } else {
+            self.eat(b'+');
+            1
+        };
+        let mut secs = self.number(0, max_hours)? * 3600;
+        if self.eat(b':') {
+            secs += self.number(0, 59)? * 60;
+            if self.eat(b':') {
+                secs += self.number(0, 59)?;
+            }
+        }
+        Ok(sign * secs as i32)
+    }
+
+    /// `date[/time]` of a daylight saving switch
+    fn rule(&mut self) -> KResult<Rule> {
+        let day = if self.eat(b'J') {
+            RuleDay::Julian1(self.number(1, 365)? as u16)
+        } else if self.eat(b'M') {
+            let month = self.number(1, 12)?;
+            if !self.eat(b'.') {
+                return Err(KError::Invalid);
+            }
+            let week = self.number(1, 5)?;
+            if !self.eat(b'.') {
+                return Err(KError::Invalid);
+            }
+            RuleDay::MonthWeekDay { month, week, weekday: self.number(0, 6)? }
+        } else {
+            RuleDay::Julian0(self.number(0, 365)? as u16)
+        };
+        // Version 3 allows times from -167h to 167h
+        let time = if self.eat(b'/') { self.hms(167)? } else { 7200 };
+        Ok(Rule { day, time })
+    }
+}
+
+fn is_leap(year: i64) -> bool {
+    (year % 4 == 0 && year % 100 != 0) || year % 400 == 0
+}
+
+fn days_in_month(year: i64, month: u32) -> i64 {
+    match month {
+        2 if is_leap(year) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// Year of an instant given in seconds since the epoch
+fn year_of(secs: i64) -> i64 {
+    civil_from_days(secs.div_euclid(SECS_PER_DAY)).0
+}
+
+/// 0 = Sunday
+fn weekday_of(days: i64) -> i64 {
+    // 1970-01-01 was a Thursday
+    (days + 4).rem_euclid(7)
+}
+
+/// Zone in use, None for the fixed offset of `time::set_timezone`
+static CURRENT: IrqSafeMutex<Option<Arc<Zone>>> = IrqSafeMutex::new(None);
+
+/// Bumped on every timezone change
+static GENERATION: AtomicU64 = AtomicU64::new(0);
+
+/// Called with the new zone name after the timezone changes
+pub type TzChangeCallback = fn(&str);
+
+static CALLBACKS: IrqSafeMutex<Vec<TzChangeCallback>> = IrqSafeMutex::new(Vec::new());
+
+pub fn current() -> Option<Arc<Zone>> {
+    CURRENT.lock().clone()
+}
+
+pub(super) fn set_current(zone: Option<Zone>) {
+    *CURRENT.lock() = zone.map(Arc::new);
+}
+
+/// Changes with every timezone change, for code polling rather than
+/// registering a callback
+pub fn generation() -> u64 {
+    GENERATION.load(Ordering::Acquire)
+}
+
+pub fn register_callback(cb: TzChangeCallback) {
+    CALLBACKS.lock().push(cb);
+}
+
+pub(super) fn notify(name: &str) {
+    GENERATION.fetch_add(1, Ordering::AcqRel);
+    // Called without the lock held: callbacks may read the timezone back
+    let callbacks = CALLBACKS.lock().clone();
+    for cb in callbacks {
+        cb(name);
+    }
+}
+
+/// Read zone `name` (e.g. "America/Sao_Paulo") from the zoneinfo directory
+pub fn load(name: &str) -> KResult<Zone> {
+    if name.is_empty() || name.starts_with('/') || name.split('/').any(|part| part == "..") {
+        return Err(KError::Invalid);
+    }
+    let data = crate::fs::read_file(&format!("{}/{}", ZONEINFO_DIR, name), &Cred::root())?;
+    Zone::parse(name, &data)
+}
+
+/// Zone of `/etc/localtime`, named after the zoneinfo file it links to
+pub fn load_localtime() -> KResult<Zone> {
+    let cred = Cred::root();
+    let target = crate::fs::vfs_lock().readlink(LOCALTIME_PATH, &cred).ok();
+    let name = target
+        .as_deref()
+        .and_then(|target| target.find("zoneinfo/").map(|i| &target[i + "zoneinfo/".len()..]))
+        .unwrap_or("localtime");
+    let data = crate::fs::read_file(LOCALTIME_PATH, &cred)?;
+    Zone::parse(name, &data)
+}
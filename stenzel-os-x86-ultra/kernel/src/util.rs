@@ -51,6 +51,8 @@
         BrokenPipe,
         NotADirectory,
         IsADirectory,
+        ReadOnly,
+        NoSpace,
     }
 
     pub type KResult<T> = core::result::Result<T, KError>;
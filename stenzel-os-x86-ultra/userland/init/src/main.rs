@@ -50,18 +50,20 @@ pub extern "C" fn main(_argc: i32, _argv: *const *const u8, _envp: *const *const
             let path = b"/bin/sh\0";
             let arg0 = b"/bin/sh\0";
             let argv: [*const u8; 2] = [arg0.as_ptr(), core::ptr::null()];
-            let envp: [*const u8; 1] = [core::ptr::null()];
+            // Ambiente padrão do sistema
+            setenv("PATH", "/bin", false);
+            setenv("HOME", "/", false);
+            let envp = environ();
 
-            let ret = execve(
+            execve(
                 "/bin/sh",
                 &argv,
                 &envp,
             );
 
             // Se chegou aqui, execve falhou
-            print("[init] execve failed with code: ");
-            print_num(ret as i64);
-            print("\n");
+            print("[init] execve failed: ");
+            println(strerror(errno()));
 
             // Shell não existe ainda, vamos ao menos mostrar algo útil
             println("[init] No /bin/sh found. Running built-in shell...");
@@ -0,0 +1,105 @@
+//! Leitura de diretórios: opendir/readdir sobre getdents64
+
+use alloc::boxed::Box;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::ptr;
+
+use crate::errno::{set_errno, EBADF};
+use crate::{close, getdents64, lseek, open, Dirent64Header, O_CLOEXEC, O_DIRECTORY, O_RDONLY, SEEK_SET};
+
+/// Diretório aberto
+pub struct DIR {
+    fd: i32,
+    /// Entradas lidas de uma vez por getdents64
+    buf: Vec<u8>,
+    pos: usize,
+    len: usize,
+}
+
+const DIR_BUF: usize = 4096;
+
+/// Abre o diretório `path`. Null com errno em caso de erro.
+pub fn opendir(path: &str) -> *mut DIR {
+    let fd = open(path, O_RDONLY | O_DIRECTORY | O_CLOEXEC, 0);
+    if fd < 0 {
+        return ptr::null_mut();
+    }
+    fdopendir(fd)
+}
+
+/// DIR sobre um fd de diretório já aberto
+pub fn fdopendir(fd: i32) -> *mut DIR {
+    if fd < 0 {
+        set_errno(EBADF);
+        return ptr::null_mut();
+    }
+    Box::into_raw(Box::new(DIR { fd, buf: vec![0; DIR_BUF], pos: 0, len: 0 }))
+}
+
+/// Próxima entrada; null no fim (errno intacto) ou em erro (errno
+/// definido). A entrada vale até a próxima chamada.
+///
+/// # Safety
+///
+/// `dir` é null ou um DIR de `opendir`/`fdopendir` ainda não fechado.
+pub unsafe fn readdir(dir: *mut DIR) -> *const Dirent64Header {
+    if dir.is_null() {
+        set_errno(EBADF);
+        return ptr::null();
+    }
+    let dir = unsafe { &mut *dir };
+    if dir.pos >= dir.len {
+        let n = getdents64(dir.fd, &mut dir.buf);
+        if n <= 0 {
+            return ptr::null();
+        }
+        dir.pos = 0;
+        dir.len = n as usize;
+    }
+    let entry = unsafe { dir.buf.as_ptr().add(dir.pos) as *const Dirent64Header };
+    dir.pos += unsafe { (*entry).d_reclen } as usize;
+    entry
+}
+
+/// Volta ao início do diretório
+///
+/// # Safety
+///
+/// `dir` é null ou um DIR de `opendir`/`fdopendir` ainda não fechado.
+pub unsafe fn rewinddir(dir: *mut DIR) {
+    if dir.is_null() {
+        return;
+    }
+    let dir = unsafe { &mut *dir };
+    lseek(dir.fd, 0, SEEK_SET);
+    dir.pos = 0;
+    dir.len = 0;
+}
+
+/// fd do diretório
+///
+/// # Safety
+///
+/// `dir` é null ou um DIR de `opendir`/`fdopendir` ainda não fechado.
+pub unsafe fn dirfd(dir: *mut DIR) -> i32 {
+    if dir.is_null() {
+        set_errno(EBADF);
+        return -1;
+    }
+    unsafe { (*dir).fd }
+}
+
+/// Fecha o diretório e libera o DIR
+///
+/// # Safety
+///
+/// `dir` é null ou um DIR de `opendir`/`fdopendir` ainda não fechado.
+pub unsafe fn closedir(dir: *mut DIR) -> i32 {
+    if dir.is_null() {
+        set_errno(EBADF);
+        return -1;
+    }
+    let dir = unsafe { Box::from_raw(dir) };
+    close(dir.fd)
+}
@@ -0,0 +1,187 @@
+//! Variáveis de ambiente
+//!
+//! O ambiente recebido do kernel (envp) é copiado na primeira consulta;
+//! `setenv` e `unsetenv` mudam a cópia, e `environ()` monta o envp que se
+//! passa para `execve`.
+
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use crate::errno::{set_errno, EINVAL};
+use crate::strlen;
+use crate::sync::Mutex;
+
+/// envp passado para `main`, guardado por `_start`
+static mut ENVP: *const *const u8 = core::ptr::null();
+
+/// Entradas "NOME=valor", cada uma terminada em NUL
+static ENV: Mutex<Option<Vec<Vec<u8>>>> = Mutex::new(None);
+
+pub(crate) fn init(envp: *const *const u8) {
+    unsafe { ENVP = envp };
+}
+
+/// Roda `f` no ambiente, copiando o envp original se preciso
+fn with_env<R>(f: impl FnOnce(&mut Vec<Vec<u8>>) -> R) -> R {
+    let mut env = ENV.lock();
+    let vars = env.get_or_insert_with(|| {
+        let mut vars = Vec::new();
+        let mut p = unsafe { ENVP };
+        if !p.is_null() {
+            unsafe {
+                while !(*p).is_null() {
+                    let len = strlen(*p);
+                    vars.push(core::slice::from_raw_parts(*p, len + 1).to_vec());
+                    p = p.add(1);
+                }
+            }
+        }
+        vars
+    });
+    f(vars)
+}
+
+/// Posição de `name` no ambiente
+fn find(vars: &[Vec<u8>], name: &str) -> Option<usize> {
+    let name = name.as_bytes();
+    vars.iter()
+        .position(|v| v.len() > name.len() && v.starts_with(name) && v[name.len()] == b'=')
+}
+
+fn valid_name(name: &str) -> bool {
+    !name.is_empty() && !name.contains('=') && !name.contains('\0')
+}
+
+/// Valor de `name`
+pub fn getenv(name: &str) -> Option<String> {
+    with_env(|vars| {
+        let i = find(vars, name)?;
+        let value = &vars[i][name.len() + 1..vars[i].len() - 1];
+        Some(String::from_utf8_lossy(value).into_owned())
+    })
+}
+
+/// Define `name`; se já existir, só troca o valor com `overwrite`
+pub fn setenv(name: &str, value: &str, overwrite: bool) -> i32 {
+    if !valid_name(name) || value.contains('\0') {
+        set_errno(EINVAL);
+        return -1;
+    }
+    with_env(|vars| {
+        let mut entry = Vec::with_capacity(name.len() + value.len() + 2);
+        entry.extend_from_slice(name.as_bytes());
+        entry.push(b'=');
+        entry.extend_from_slice(value.as_bytes());
+        entry.push(0);
+        match find(vars, name) {
+            Some(i) if overwrite => vars[i] = entry,
+            Some(_) => {}
+            None => vars.push(entry),
+        }
+    });
+    0
+}
+
+/// Remove `name` do ambiente
+pub fn unsetenv(name: &str) -> i32 {
+    if !valid_name(name) {
+        set_errno(EINVAL);
+        return -1;
+    }
+    with_env(|vars| {
+        if let Some(i) = find(vars, name) {
+            vars.remove(i);
+        }
+    });
+    0
+}
+
+/// Esvazia o ambiente
+pub fn clearenv() -> i32 {
+    with_env(|vars| vars.clear());
+    0
+}
+
+/// Cópia do ambiente, "NOME=valor" por entrada
+pub fn vars() -> Vec<String> {
+    with_env(|vars| {
+        vars.iter()
+            .map(|v| String::from_utf8_lossy(&v[..v.len() - 1]).into_owned())
+            .collect()
+    })
+}
+
+/// envp terminado em null para `execve`. Os ponteiros valem até a próxima
+/// mudança no ambiente.
+pub fn environ() -> Vec<*const u8> {
+    with_env(|vars| {
+        let mut envp: Vec<*const u8> = vars.iter().map(|v| v.as_ptr()).collect();
+        envp.push(core::ptr::null());
+        envp
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::errno::{errno, TEST_LOCK};
+
+    // O ambiente é global e os testes rodam em paralelo: cada um usa nomes
+    // próprios
+
+    #[test]
+    fn set_get_unset() {
+        assert_eq!(getenv("STZ_TEST_A"), None);
+        assert_eq!(setenv("STZ_TEST_A", "1", true), 0);
+        assert_eq!(getenv("STZ_TEST_A").as_deref(), Some("1"));
+
+        // Sem overwrite o valor antigo fica
+        assert_eq!(setenv("STZ_TEST_A", "2", false), 0);
+        assert_eq!(getenv("STZ_TEST_A").as_deref(), Some("1"));
+        assert_eq!(setenv("STZ_TEST_A", "2", true), 0);
+        assert_eq!(getenv("STZ_TEST_A").as_deref(), Some("2"));
+
+        // Um nome que é prefixo de outro não o encontra
+        assert_eq!(setenv("STZ_TEST_AB", "x=y", true), 0);
+        assert_eq!(getenv("STZ_TEST_AB").as_deref(), Some("x=y"));
+        assert_eq!(unsetenv("STZ_TEST_A"), 0);
+        assert_eq!(getenv("STZ_TEST_A"), None);
+        assert_eq!(getenv("STZ_TEST_AB").as_deref(), Some("x=y"));
+        assert_eq!(unsetenv("STZ_TEST_AB"), 0);
+
+        // Remover o que não existe não é erro
+        assert_eq!(unsetenv("STZ_TEST_A"), 0);
+    }
+
+    #[test]
+    fn empty_value() {
+        assert_eq!(setenv("STZ_TEST_EMPTY", "", true), 0);
+        assert_eq!(getenv("STZ_TEST_EMPTY").as_deref(), Some(""));
+        assert!(vars().iter().any(|v| v == "STZ_TEST_EMPTY="));
+        assert_eq!(unsetenv("STZ_TEST_EMPTY"), 0);
+    }
+
+    #[test]
+    fn invalid_names() {
+        let _errno = TEST_LOCK.lock();
+        for name in ["", "A=B", "A\0B"] {
+            assert_eq!(setenv(name, "v", true), -1);
+            assert_eq!(errno(), EINVAL);
+            assert_eq!(unsetenv(name), -1);
+        }
+        assert_eq!(setenv("STZ_TEST_NUL", "a\0b", true), -1);
+        assert_eq!(getenv("STZ_TEST_NUL"), None);
+    }
+
+    #[test]
+    fn environ_is_null_terminated() {
+        assert_eq!(setenv("STZ_TEST_ENVIRON", "yes", true), 0);
+        let envp = environ();
+        assert!(envp.last().is_some_and(|p| p.is_null()));
+        let found = envp[..envp.len() - 1].iter().any(|&p| unsafe {
+            core::slice::from_raw_parts(p, strlen(p)) == b"STZ_TEST_ENVIRON=yes"
+        });
+        assert!(found);
+        assert_eq!(unsetenv("STZ_TEST_ENVIRON"), 0);
+    }
+}
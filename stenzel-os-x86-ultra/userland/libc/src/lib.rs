@@ -1,12 +1,46 @@
 //! Stenzel OS mini libc
 //!
 //! Biblioteca C mínima para programas userspace do Stenzel OS.
-//! Provê syscall wrappers e funções básicas.
+//! Provê syscall wrappers, malloc (e o alocador global, para usar `alloc`),
+//! stdio com buffer, errno, ambiente, diretórios e sockets.
+//!
+//! Os wrappers retornam -1 e definem `errno` em caso de erro, como em C.
+//!
+//! Os testes rodam no host, sobre as syscalls do Linux. Rode de fora de
+//! `userland/`, porque o `.cargo/config.toml` de lá força o build-std para
+//! x86_64-unknown-none (E0152 no host); `--lib` pula os doctests, que
+//! ligariam a versão no_std da lib:
+//! `cargo +nightly test --manifest-path userland/Cargo.toml -p stenzel_libc --lib --target x86_64-unknown-linux-gnu`
 
-#![no_std]
+#![cfg_attr(not(test), no_std)]
 #![allow(dead_code)]
 
-use core::arch::{asm, naked_asm};
+extern crate alloc;
+
+use core::arch::asm;
+#[cfg(not(test))]
+use core::arch::naked_asm;
+
+pub mod dirent;
+pub mod env;
+pub mod errno;
+pub mod malloc;
+pub mod socket;
+pub mod stdio;
+mod sync;
+
+pub use dirent::{closedir, dirfd, fdopendir, opendir, readdir, rewinddir, DIR};
+pub use env::{clearenv, environ, getenv, setenv, unsetenv};
+pub use errno::{errno, perror, set_errno, strerror};
+pub use malloc::{aligned_alloc, calloc, free, malloc, malloc_usable_size, posix_memalign, realloc};
+pub use stdio::{
+    clearerr, fclose, fdopen, feof, ferror, fflush, fgetc, fgets, fileno, fopen, fputc, fputs,
+    fread, fseek, ftell, fwrite, getchar, getline, putchar, puts, rewind, setvbuf, stderr, stdin,
+    stdout, ungetc, EOF, FILE,
+};
+pub use stdio::{fprintf, printf, snprintf};
+
+use errno::check;
 
 // ============================================================================
 // Syscall numbers (Linux x86_64 ABI)
@@ -45,6 +79,18 @@ pub const SYS_GETGID: u64 = 104;
 pub const SYS_GETEUID: u64 = 107;
 pub const SYS_GETEGID: u64 = 108;
 pub const SYS_GETPPID: u64 = 110;
+pub const SYS_SOCKET: u64 = 41;
+pub const SYS_CONNECT: u64 = 42;
+pub const SYS_ACCEPT: u64 = 43;
+pub const SYS_SENDTO: u64 = 44;
+pub const SYS_RECVFROM: u64 = 45;
+pub const SYS_SHUTDOWN: u64 = 48;
+pub const SYS_BIND: u64 = 49;
+pub const SYS_LISTEN: u64 = 50;
+pub const SYS_GETSOCKNAME: u64 = 51;
+pub const SYS_GETPEERNAME: u64 = 52;
+pub const SYS_SETSOCKOPT: u64 = 54;
+pub const SYS_GETSOCKOPT: u64 = 55;
 pub const SYS_ARCH_PRCTL: u64 = 158;
 pub const SYS_GETTID: u64 = 186;
 pub const SYS_GETDENTS64: u64 = 217;
@@ -176,41 +222,90 @@ pub unsafe fn syscall6(nr: u64, a1: u64, a2: u64, a3: u64, a4: u64, a5: u64, a6:
     ret
 }
 
+// ============================================================================
+// Constants
+// ============================================================================
+
+/// Flags de open
+pub const O_RDONLY: i32 = 0;
+pub const O_WRONLY: i32 = 1;
+pub const O_RDWR: i32 = 2;
+pub const O_CREAT: i32 = 0o100;
+pub const O_EXCL: i32 = 0o200;
+pub const O_TRUNC: i32 = 0o1000;
+pub const O_APPEND: i32 = 0o2000;
+pub const O_NONBLOCK: i32 = 0o4000;
+pub const O_DIRECTORY: i32 = 0o200000;
+pub const O_CLOEXEC: i32 = 0o2000000;
+
+/// whence de lseek
+pub const SEEK_SET: i32 = 0;
+pub const SEEK_CUR: i32 = 1;
+pub const SEEK_END: i32 = 2;
+
+/// mmap
+pub const PROT_NONE: i32 = 0;
+pub const PROT_READ: i32 = 1;
+pub const PROT_WRITE: i32 = 2;
+pub const PROT_EXEC: i32 = 4;
+pub const MAP_SHARED: i32 = 0x01;
+pub const MAP_PRIVATE: i32 = 0x02;
+pub const MAP_FIXED: i32 = 0x10;
+pub const MAP_ANONYMOUS: i32 = 0x20;
+pub const MAP_FAILED: *mut u8 = !0 as *mut u8;
+
+/// Maior caminho aceito, com o NUL
+pub const PATH_MAX: usize = 4096;
+
 // ============================================================================
 // POSIX-like syscall wrappers
 // ============================================================================
 
+/// Chama `f` com `path` terminado em NUL
+fn with_path(path: &str, f: impl FnOnce(u64) -> i64) -> i64 {
+    let mut buf = [0u8; PATH_MAX];
+    if path.len() >= PATH_MAX {
+        set_errno(errno::ENAMETOOLONG);
+        return -1;
+    }
+    buf[..path.len()].copy_from_slice(path.as_bytes());
+    check(f(buf.as_ptr() as u64))
+}
+
 /// write - escreve em um file descriptor
 pub fn write(fd: i32, buf: &[u8]) -> isize {
-    unsafe { syscall3(SYS_WRITE, fd as u64, buf.as_ptr() as u64, buf.len() as u64) as isize }
+    check(unsafe { syscall3(SYS_WRITE, fd as u64, buf.as_ptr() as u64, buf.len() as u64) }) as isize
 }
 
 /// read - lê de um file descriptor
 pub fn read(fd: i32, buf: &mut [u8]) -> isize {
-    unsafe { syscall3(SYS_READ, fd as u64, buf.as_mut_ptr() as u64, buf.len() as u64) as isize }
+    check(unsafe { syscall3(SYS_READ, fd as u64, buf.as_mut_ptr() as u64, buf.len() as u64) }) as isize
 }
 
 /// open - abre um arquivo
 pub fn open(path: &str, flags: i32, mode: u32) -> i32 {
-    // Precisamos de um buffer null-terminated
-    let mut buf = [0u8; 256];
-    let len = path.len().min(255);
-    buf[..len].copy_from_slice(&path.as_bytes()[..len]);
-    buf[len] = 0;
-    unsafe { syscall3(SYS_OPEN, buf.as_ptr() as u64, flags as u64, mode as u64) as i32 }
+    with_path(path, |p| unsafe { syscall3(SYS_OPEN, p, flags as u64, mode as u64) }) as i32
 }
 
 /// close - fecha um file descriptor
 pub fn close(fd: i32) -> i32 {
-    unsafe { syscall1(SYS_CLOSE, fd as u64) as i32 }
+    check(unsafe { syscall1(SYS_CLOSE, fd as u64) }) as i32
 }
 
-/// exit - termina o processo
+/// lseek - posiciona um file descriptor
+pub fn lseek(fd: i32, offset: i64, whence: i32) -> i64 {
+    check(unsafe { syscall3(SYS_LSEEK, fd as u64, offset as u64, whence as u64) })
+}
+
+/// exit - descarrega os streams de stdio e termina o processo
 pub fn exit(status: i32) -> ! {
-    unsafe {
-        syscall1(SYS_EXIT, status as u64);
-    }
-    loop {}
+    stdio::flush_all();
+    _exit(status)
+}
+
+/// _exit - termina o processo sem descarregar stdio (filho após fork)
+pub fn _exit(status: i32) -> ! {
+    exit_group(status)
 }
 
 /// exit_group - termina todas as threads
@@ -223,29 +318,19 @@ pub fn exit_group(status: i32) -> ! {
 
 /// fork - cria um processo filho
 pub fn fork() -> i32 {
-    unsafe { syscall0(SYS_FORK) as i32 }
+    check(unsafe { syscall0(SYS_FORK) }) as i32
 }
 
 /// execve - executa um programa
 pub fn execve(path: &str, argv: &[*const u8], envp: &[*const u8]) -> i32 {
-    let mut path_buf = [0u8; 256];
-    let len = path.len().min(255);
-    path_buf[..len].copy_from_slice(&path.as_bytes()[..len]);
-    path_buf[len] = 0;
-
-    unsafe {
-        syscall3(
-            SYS_EXECVE,
-            path_buf.as_ptr() as u64,
-            argv.as_ptr() as u64,
-            envp.as_ptr() as u64,
-        ) as i32
-    }
+    with_path(path, |p| unsafe {
+        syscall3(SYS_EXECVE, p, argv.as_ptr() as u64, envp.as_ptr() as u64)
+    }) as i32
 }
 
 /// wait4 - aguarda término de processo filho
 pub fn wait4(pid: i32, status: *mut i32, options: i32) -> i32 {
-    unsafe { syscall4(SYS_WAIT4, pid as u64, status as u64, options as u64, 0) as i32 }
+    check(unsafe { syscall4(SYS_WAIT4, pid as u64, status as u64, options as u64, 0) }) as i32
 }
 
 /// waitpid - wrapper para wait4
@@ -283,75 +368,73 @@ pub fn getegid() -> u32 {
     unsafe { syscall0(SYS_GETEGID) as u32 }
 }
 
-/// brk - altera o program break (heap)
+/// brk - altera o program break (heap). Retorna o break atual, que fica
+/// abaixo de `addr` se o kernel recusar.
 pub fn brk(addr: *mut u8) -> *mut u8 {
     unsafe { syscall1(SYS_BRK, addr as u64) as *mut u8 }
 }
 
+/// mmap - mapeia memória; `MAP_FAILED` com errno em caso de erro
+pub fn mmap(addr: *mut u8, len: usize, prot: i32, flags: i32, fd: i32, offset: i64) -> *mut u8 {
+    let ret = check(unsafe {
+        syscall6(SYS_MMAP, addr as u64, len as u64, prot as u64, flags as u64, fd as u64, offset as u64)
+    });
+    if ret == -1 { MAP_FAILED } else { ret as *mut u8 }
+}
+
+/// munmap - remove um mapeamento
+pub fn munmap(addr: *mut u8, len: usize) -> i32 {
+    check(unsafe { syscall2(SYS_MUNMAP, addr as u64, len as u64) }) as i32
+}
+
 /// mkdir - cria diretório
 pub fn mkdir(path: &str, mode: u32) -> i32 {
-    let mut buf = [0u8; 256];
-    let len = path.len().min(255);
-    buf[..len].copy_from_slice(&path.as_bytes()[..len]);
-    buf[len] = 0;
-    unsafe { syscall2(SYS_MKDIR, buf.as_ptr() as u64, mode as u64) as i32 }
+    with_path(path, |p| unsafe { syscall2(SYS_MKDIR, p, mode as u64) }) as i32
 }
 
 /// rmdir - remove diretório vazio
 pub fn rmdir(path: &str) -> i32 {
-    let mut buf = [0u8; 256];
-    let len = path.len().min(255);
-    buf[..len].copy_from_slice(&path.as_bytes()[..len]);
-    buf[len] = 0;
-    unsafe { syscall1(SYS_RMDIR, buf.as_ptr() as u64) as i32 }
+    with_path(path, |p| unsafe { syscall1(SYS_RMDIR, p) }) as i32
 }
 
 /// unlink - remove arquivo
 pub fn unlink(path: &str) -> i32 {
-    let mut buf = [0u8; 256];
-    let len = path.len().min(255);
-    buf[..len].copy_from_slice(&path.as_bytes()[..len]);
-    buf[len] = 0;
-    unsafe { syscall1(SYS_UNLINK, buf.as_ptr() as u64) as i32 }
+    with_path(path, |p| unsafe { syscall1(SYS_UNLINK, p) }) as i32
 }
 
 /// getcwd - obtém diretório atual
 pub fn getcwd(buf: &mut [u8]) -> i32 {
-    unsafe { syscall2(SYS_GETCWD, buf.as_mut_ptr() as u64, buf.len() as u64) as i32 }
+    check(unsafe { syscall2(SYS_GETCWD, buf.as_mut_ptr() as u64, buf.len() as u64) }) as i32
 }
 
 /// chdir - muda diretório
 pub fn chdir(path: &str) -> i32 {
-    let mut buf = [0u8; 256];
-    let len = path.len().min(255);
-    buf[..len].copy_from_slice(&path.as_bytes()[..len]);
-    buf[len] = 0;
-    unsafe { syscall1(SYS_CHDIR, buf.as_ptr() as u64) as i32 }
+    with_path(path, |p| unsafe { syscall1(SYS_CHDIR, p) }) as i32
 }
 
 /// dup - duplica file descriptor
 pub fn dup(oldfd: i32) -> i32 {
-    unsafe { syscall1(SYS_DUP, oldfd as u64) as i32 }
+    check(unsafe { syscall1(SYS_DUP, oldfd as u64) }) as i32
 }
 
 /// dup2 - duplica file descriptor para um fd específico
 pub fn dup2(oldfd: i32, newfd: i32) -> i32 {
-    unsafe { syscall2(SYS_DUP2, oldfd as u64, newfd as u64) as i32 }
+    check(unsafe { syscall2(SYS_DUP2, oldfd as u64, newfd as u64) }) as i32
 }
 
 /// pipe - cria um pipe
 pub fn pipe(pipefd: &mut [i32; 2]) -> i32 {
-    unsafe { syscall1(SYS_PIPE, pipefd.as_mut_ptr() as u64) as i32 }
+    check(unsafe { syscall1(SYS_PIPE, pipefd.as_mut_ptr() as u64) }) as i32
 }
 
 /// kill - envia sinal
 pub fn kill(pid: i32, sig: i32) -> i32 {
-    unsafe { syscall2(SYS_KILL, pid as u64, sig as u64) as i32 }
+    check(unsafe { syscall2(SYS_KILL, pid as u64, sig as u64) }) as i32
 }
 
 /// getdents64 - lê entradas de diretório
 pub fn getdents64(fd: i32, buf: &mut [u8]) -> isize {
-    unsafe { syscall3(SYS_GETDENTS64, fd as u64, buf.as_mut_ptr() as u64, buf.len() as u64) as isize }
+    check(unsafe { syscall3(SYS_GETDENTS64, fd as u64, buf.as_mut_ptr() as u64, buf.len() as u64) }) as isize
 }
 
 // ============================================================================
@@ -394,8 +477,10 @@ impl Dirent64Header {
 // Convenience functions
 // ============================================================================
 
-/// Escreve uma string em stdout
+/// Escreve uma string em stdout (sem buffer; o que `printf` deixou no
+/// buffer de stdout sai antes)
 pub fn print(s: &str) {
+    fflush(stdout());
     write(1, s.as_bytes());
 }
 
@@ -495,6 +580,7 @@ pub unsafe fn memset(dst: *mut u8, c: u8, n: usize) -> *mut u8 {
 
 /// Entry point para programas userspace.
 /// O kernel passa argc, argv, envp na stack.
+#[cfg(not(test))]
 #[unsafe(naked)]
 #[no_mangle]
 #[link_section = ".text.start"]
@@ -509,9 +595,6 @@ pub extern "C" fn _start() -> ! {
         // ...
         // NULL
 
-        // Alinha a stack em 16 bytes antes de chamar main
-        "and rsp, -16",
-
         // Carrega argc em rdi (primeiro argumento)
         "mov rdi, [rsp]",
 
@@ -524,19 +607,31 @@ pub extern "C" fn _start() -> ! {
         "shl rax, 3",
         "lea rdx, [rsi + rax]",
 
-        // Chama main(argc, argv, envp)
-        "call main",
+        // Alinha a stack em 16 bytes antes da chamada
+        "and rsp, -16",
 
-        // main retornou, chama exit(rax)
-        "mov rdi, rax",
-        "mov rax, 60",  // SYS_EXIT
-        "syscall",
+        // __libc_start_main(argc, argv, envp) chama main e exit
+        "call {start_main}",
 
         // Nunca deve chegar aqui
         "ud2",
+        start_main = sym __libc_start_main,
     )
 }
 
+#[cfg(not(test))]
+extern "C" {
+    fn main(argc: i32, argv: *const *const u8, envp: *const *const u8) -> i32;
+}
+
+/// Guarda o ambiente, roda main e termina com o seu retorno
+#[cfg(not(test))]
+extern "C" fn __libc_start_main(argc: i32, argv: *const *const u8, envp: *const *const u8) -> ! {
+    env::init(envp);
+    let status = unsafe { main(argc, argv, envp) };
+    exit(status)
+}
+
 // ============================================================================
 // pthread - POSIX Threads support
 // ============================================================================
@@ -693,16 +788,12 @@ pub mod pthread {
         start_routine: fn(*mut u8) -> *mut u8,
         arg: *mut u8,
     ) -> i32 {
-        // Allocate stack for new thread (using brk for simplicity)
+        // Allocate stack for new thread, with extra space for ThreadInfo
         let stack_size = DEFAULT_STACK_SIZE;
-        let stack_base = unsafe {
-            let current = brk(ptr::null_mut());
-            let new_brk = brk(current.add(stack_size + 64)); // Extra space for ThreadInfo
-            if new_brk == current {
-                return -1; // Out of memory
-            }
-            current
-        };
+        let stack_base = malloc(stack_size + 64);
+        if stack_base.is_null() {
+            return -1; // Out of memory
+        }
 
         // Set up ThreadInfo at the beginning of the allocated space
         let thread_info = stack_base as *mut ThreadInfo;
@@ -749,7 +840,7 @@ pub mod pthread {
             unsafe { (*info).result = ret; }
 
             // Exit thread
-            exit(0);
+            thread_exit();
         }
 
         // Parent - store thread handle
@@ -796,7 +887,15 @@ pub mod pthread {
         // Store return value if we have access to our ThreadInfo
         // For simplicity, just exit
         let _ = retval;
-        exit(0)
+        thread_exit()
+    }
+
+    /// Exit the calling thread only (exit() ends the whole process)
+    fn thread_exit() -> ! {
+        unsafe {
+            syscall1(SYS_EXIT, 0);
+        }
+        loop {}
     }
 
     /// Get the calling thread's ID
@@ -942,10 +1041,20 @@ pub use pthread::{
 // Panic handler (required for no_std)
 // ============================================================================
 
+/// Escreve direto no fd, sem passar pelos locks de stdio
+struct RawFd(i32);
+
+impl core::fmt::Write for RawFd {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        write(self.0, s.as_bytes());
+        Ok(())
+    }
+}
+
+#[cfg(not(test))]
 #[panic_handler]
-fn panic(_info: &core::panic::PanicInfo) -> ! {
-    print("PANIC: ");
-    // Não podemos imprimir a mensagem completa sem alloc
-    print("program panicked\n");
-    exit(1)
+fn panic(info: &core::panic::PanicInfo) -> ! {
+    use core::fmt::Write;
+    let _ = writeln!(RawFd(2), "PANIC: {}", info);
+    _exit(1)
 }
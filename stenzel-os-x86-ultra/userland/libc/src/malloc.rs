@@ -0,0 +1,403 @@
+//! malloc/free e o alocador global
+//!
+//! Blocos pequenos (até 4 KiB com o cabeçalho) são arredondados para uma
+//! classe de tamanho em potência de dois, cortados do heap (brk) e, depois
+//! de liberados, reaproveitados pela lista livre da sua classe. Blocos
+//! maiores são mapeados com mmap e devolvidos ao sistema no `free`.
+//!
+//! Todo bloco começa com um cabeçalho de 16 bytes, para que `free` e
+//! `realloc` funcionem sem o tamanho, como em C.
+
+use core::alloc::{GlobalAlloc, Layout};
+use core::ptr;
+
+use crate::errno::{set_errno, ENOMEM, EINVAL};
+use crate::sync::Mutex;
+use crate::{brk, mmap, munmap, MAP_ANONYMOUS, MAP_FAILED, MAP_PRIVATE, PROT_READ, PROT_WRITE};
+
+/// Cabeçalho; também o alinhamento garantido por `malloc`
+const HEADER: usize = 16;
+/// Menor e maior classe: 32 bytes e 4 KiB, cabeçalho incluído
+const MIN_SHIFT: u32 = 5;
+const MAX_SHIFT: u32 = 12;
+const NCLASSES: usize = (MAX_SHIFT - MIN_SHIFT + 1) as usize;
+/// O heap cresce pelo menos isso de cada vez
+const GROW: usize = 64 * 1024;
+const PAGE: usize = 4096;
+
+/// `tag` de um bloco mapeado; `size` é o tamanho do mapeamento
+const LARGE: usize = usize::MAX;
+/// `tag` de um bloco de `aligned_alloc`; `size` é a distância até o bloco
+/// que o contém
+const ALIGNED: usize = usize::MAX - 1;
+
+/// Cabeçalho de bloco. Blocos pequenos guardam em `tag` a classe e em
+/// `size` a capacidade.
+#[repr(C)]
+struct Header {
+    size: usize,
+    tag: usize,
+}
+
+struct Heap {
+    /// Primeiro bloco livre de cada classe (0: nenhum); o próximo fica
+    /// nos primeiros bytes de dados do bloco
+    free: [usize; NCLASSES],
+    /// Parte ainda não cortada do heap
+    top: usize,
+    end: usize,
+}
+
+static HEAP: Mutex<Heap> = Mutex::new(Heap {
+    free: [0; NCLASSES],
+    top: 0,
+    end: 0,
+});
+
+fn align_up(n: usize, align: usize) -> usize {
+    (n + align - 1) & !(align - 1)
+}
+
+fn header(p: *mut u8) -> *mut Header {
+    unsafe { p.sub(HEADER) as *mut Header }
+}
+
+impl Heap {
+    /// Bloco da classe `class`, da lista livre ou cortado do heap
+    fn take(&mut self, class: usize) -> Option<usize> {
+        let head = self.free[class];
+        if head != 0 {
+            self.free[class] = unsafe { *((head + HEADER) as *const usize) };
+            return Some(head);
+        }
+        let size = 1usize << (class as u32 + MIN_SHIFT);
+        if self.end - self.top < size {
+            self.grow(size)?;
+        }
+        let block = self.top;
+        self.top += size;
+        Some(block)
+    }
+
+    fn put(&mut self, class: usize, block: usize) {
+        unsafe { *((block + HEADER) as *mut usize) = self.free[class] };
+        self.free[class] = block;
+    }
+
+    /// Aumenta o heap em pelo menos `need` bytes: pelo brk, ou com mmap se
+    /// o kernel recusar. O resto de uma região que não continua é perdido.
+    fn grow(&mut self, need: usize) -> Option<()> {
+        let len = align_up(need.max(GROW), PAGE);
+        let current = brk(ptr::null_mut()) as usize;
+        let new = brk((current + len) as *mut u8) as usize;
+        if new >= current + len {
+            if current != self.end {
+                self.top = align_up(current, HEADER);
+            }
+            self.end = current + len;
+            return Some(());
+        }
+        let region = mmap(ptr::null_mut(), len, PROT_READ | PROT_WRITE, MAP_PRIVATE | MAP_ANONYMOUS, -1, 0);
+        if region == MAP_FAILED {
+            return None;
+        }
+        self.top = region as usize;
+        self.end = region as usize + len;
+        Some(())
+    }
+}
+
+/// Aloca `size` bytes alinhados a 16. Retorna null (errno ENOMEM) se não
+/// houver memória.
+pub fn malloc(size: usize) -> *mut u8 {
+    let Some(total) = size.max(1).checked_add(HEADER) else {
+        set_errno(ENOMEM);
+        return ptr::null_mut();
+    };
+
+    if total <= 1 << MAX_SHIFT {
+        let shift = (usize::BITS - (total - 1).leading_zeros()).max(MIN_SHIFT);
+        let class = (shift - MIN_SHIFT) as usize;
+        let Some(block) = HEAP.lock().take(class) else {
+            set_errno(ENOMEM);
+            return ptr::null_mut();
+        };
+        unsafe {
+            *(block as *mut Header) = Header { size: (1 << shift) - HEADER, tag: class };
+        }
+        return (block + HEADER) as *mut u8;
+    }
+
+    let len = align_up(total, PAGE);
+    let block = mmap(ptr::null_mut(), len, PROT_READ | PROT_WRITE, MAP_PRIVATE | MAP_ANONYMOUS, -1, 0);
+    if block == MAP_FAILED {
+        set_errno(ENOMEM);
+        return ptr::null_mut();
+    }
+    unsafe {
+        *(block as *mut Header) = Header { size: len, tag: LARGE };
+        block.add(HEADER)
+    }
+}
+
+/// Libera um bloco de `malloc`, `calloc`, `realloc` ou `aligned_alloc`
+///
+/// # Safety
+///
+/// `p` é null ou um bloco deste alocador ainda não liberado.
+pub unsafe fn free(p: *mut u8) {
+    if p.is_null() {
+        return;
+    }
+    let h = header(p);
+    let (size, tag) = unsafe { ((*h).size, (*h).tag) };
+    match tag {
+        ALIGNED => free(unsafe { p.sub(size) }),
+        LARGE => {
+            munmap(h as *mut u8, size);
+        }
+        class => HEAP.lock().put(class, h as usize),
+    }
+}
+
+/// Bytes utilizáveis a partir de `p`
+///
+/// # Safety
+///
+/// `p` é null ou um bloco deste alocador ainda não liberado.
+pub unsafe fn malloc_usable_size(p: *mut u8) -> usize {
+    if p.is_null() {
+        return 0;
+    }
+    let h = header(p);
+    let (size, tag) = unsafe { ((*h).size, (*h).tag) };
+    match tag {
+        ALIGNED => malloc_usable_size(unsafe { p.sub(size) }) - size,
+        LARGE => size - HEADER,
+        _ => size,
+    }
+}
+
+/// Aloca `n * size` bytes zerados
+pub fn calloc(n: usize, size: usize) -> *mut u8 {
+    let Some(total) = n.checked_mul(size) else {
+        set_errno(ENOMEM);
+        return ptr::null_mut();
+    };
+    let p = malloc(total);
+    if !p.is_null() {
+        unsafe { ptr::write_bytes(p, 0, total) };
+    }
+    p
+}
+
+/// Muda o tamanho de um bloco, movendo-o se não couber. Com `size` 0
+/// libera o bloco e retorna null.
+///
+/// # Safety
+///
+/// `p` é null ou um bloco deste alocador ainda não liberado; se o bloco
+/// mudar de lugar, `p` deixa de valer.
+pub unsafe fn realloc(p: *mut u8, size: usize) -> *mut u8 {
+    if p.is_null() {
+        return malloc(size);
+    }
+    if size == 0 {
+        free(p);
+        return ptr::null_mut();
+    }
+    let usable = malloc_usable_size(p);
+    if size <= usable {
+        return p;
+    }
+    let new = malloc(size);
+    if !new.is_null() {
+        unsafe { ptr::copy_nonoverlapping(p, new, usable) };
+        free(p);
+    }
+    new
+}
+
+/// Aloca `size` bytes alinhados a `align` (potência de dois)
+pub fn aligned_alloc(align: usize, size: usize) -> *mut u8 {
+    if !align.is_power_of_two() {
+        set_errno(EINVAL);
+        return ptr::null_mut();
+    }
+    if align <= HEADER {
+        return malloc(size);
+    }
+    let Some(total) = size.checked_add(align) else {
+        set_errno(ENOMEM);
+        return ptr::null_mut();
+    };
+    let raw = malloc(total);
+    if raw.is_null() {
+        return raw;
+    }
+    let aligned = align_up(raw as usize, align);
+    if aligned == raw as usize {
+        return raw;
+    }
+    // O cabeçalho cabe entre os dois: a distância é múltipla de 16
+    let p = aligned as *mut u8;
+    unsafe {
+        *header(p) = Header { size: aligned - raw as usize, tag: ALIGNED };
+    }
+    p
+}
+
+/// posix_memalign: como `aligned_alloc`, com o erro no retorno
+pub fn posix_memalign(out: &mut *mut u8, align: usize, size: usize) -> i32 {
+    if !align.is_power_of_two() || !align.is_multiple_of(core::mem::size_of::<usize>()) {
+        return EINVAL;
+    }
+    let p = aligned_alloc(align, size);
+    if p.is_null() {
+        return ENOMEM;
+    }
+    *out = p;
+    0
+}
+
+/// Alocador global dos programas userspace: `alloc::vec::Vec`,
+/// `alloc::string::String` e afins usam malloc/free
+pub struct Malloc;
+
+unsafe impl GlobalAlloc for Malloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        aligned_alloc(layout.align(), layout.size())
+    }
+
+    unsafe fn dealloc(&self, p: *mut u8, _layout: Layout) {
+        free(p)
+    }
+
+    unsafe fn realloc(&self, p: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        if layout.align() <= HEADER {
+            return realloc(p, new_size);
+        }
+        let new = aligned_alloc(layout.align(), new_size);
+        if !new.is_null() {
+            ptr::copy_nonoverlapping(p, new, layout.size().min(new_size));
+            free(p);
+        }
+        new
+    }
+}
+
+#[cfg(not(test))]
+#[global_allocator]
+static ALLOCATOR: Malloc = Malloc;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::errno::{errno, TEST_LOCK};
+
+    #[test]
+    fn size_classes() {
+        // (pedido, bytes utilizáveis): potência de dois menos o cabeçalho
+        for (size, usable) in [(0, 16), (1, 16), (16, 16), (17, 48), (48, 48), (100, 112), (4080, 4080)] {
+            let p = malloc(size);
+            assert!(!p.is_null());
+            assert_eq!(p as usize % HEADER, 0);
+            unsafe {
+                assert_eq!(malloc_usable_size(p), usable, "malloc({})", size);
+                free(p);
+            }
+        }
+        // Acima de 4 KiB: mmap, em páginas inteiras
+        let p = malloc(4081);
+        unsafe {
+            assert_eq!(malloc_usable_size(p), 2 * PAGE - HEADER);
+            free(p);
+        }
+    }
+
+    #[test]
+    fn freed_blocks_are_reused() {
+        let a = malloc(200);
+        unsafe { free(a) };
+        let b = malloc(200);
+        assert_eq!(a, b);
+        unsafe { free(b) };
+    }
+
+    #[test]
+    fn aligned_alloc_round_trip() {
+        let _errno = TEST_LOCK.lock();
+        for align in [8, 16, 32, 64, 256, 4096, 8192] {
+            for size in [1, 100, 5000] {
+                let p = aligned_alloc(align, size);
+                assert!(!p.is_null());
+                assert_eq!(p as usize % align, 0, "aligned_alloc({}, {})", align, size);
+                unsafe {
+                    assert!(malloc_usable_size(p) >= size);
+                    ptr::write_bytes(p, 0xA5, size);
+                    free(p);
+                }
+            }
+        }
+
+        assert!(aligned_alloc(24, 10).is_null());
+        assert_eq!(errno(), EINVAL);
+
+        let mut p = ptr::null_mut();
+        assert_eq!(posix_memalign(&mut p, 4, 10), EINVAL);
+        assert_eq!(posix_memalign(&mut p, 128, 10), 0);
+        assert_eq!(p as usize % 128, 0);
+        unsafe { free(p) };
+    }
+
+    #[test]
+    fn realloc_grows_and_keeps_contents() {
+        unsafe {
+            let mut p = realloc(ptr::null_mut(), 10);
+            for i in 0..10 {
+                *p.add(i) = i as u8;
+            }
+            // Na mesma classe, o bloco fica onde está
+            assert_eq!(realloc(p, 16), p);
+            for size in [100, 3000, 20000, 100000] {
+                p = realloc(p, size);
+                assert!(!p.is_null());
+                assert!(malloc_usable_size(p) >= size);
+                for i in 0..10 {
+                    assert_eq!(*p.add(i), i as u8, "realloc para {}", size);
+                }
+            }
+            assert!(realloc(p, 0).is_null());
+        }
+    }
+
+    #[test]
+    fn global_alloc_over_aligned_realloc() {
+        let layout = Layout::from_size_align(40, 64).unwrap();
+        unsafe {
+            let p = Malloc.alloc(layout);
+            assert_eq!(p as usize % 64, 0);
+            ptr::write_bytes(p, 7, 40);
+            let q = Malloc.realloc(p, layout, 1000);
+            assert_eq!(q as usize % 64, 0);
+            assert!((0..40).all(|i| *q.add(i) == 7));
+            Malloc.dealloc(q, Layout::from_size_align(1000, 64).unwrap());
+        }
+    }
+
+    #[test]
+    fn calloc_zeroes_and_checks_overflow() {
+        let _errno = TEST_LOCK.lock();
+        let p = malloc(64);
+        unsafe {
+            ptr::write_bytes(p, 0xFF, 64);
+            free(p);
+        }
+        let p = calloc(8, 8);
+        assert!((0..64).all(|i| unsafe { *p.add(i) } == 0));
+        unsafe { free(p) };
+
+        assert!(calloc(usize::MAX, 2).is_null());
+        assert_eq!(errno(), ENOMEM);
+    }
+}
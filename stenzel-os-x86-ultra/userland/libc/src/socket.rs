@@ -0,0 +1,300 @@
+//! Sockets: wrappers das syscalls e endereços IPv4 / Unix
+
+#![allow(non_camel_case_types)]
+
+use core::mem::size_of;
+
+use crate::errno::check;
+use crate::*;
+
+pub const AF_UNSPEC: i32 = 0;
+pub const AF_UNIX: i32 = 1;
+pub const AF_INET: i32 = 2;
+pub const AF_INET6: i32 = 10;
+
+pub const SOCK_STREAM: i32 = 1;
+pub const SOCK_DGRAM: i32 = 2;
+pub const SOCK_RAW: i32 = 3;
+pub const SOCK_NONBLOCK: i32 = 0o4000;
+pub const SOCK_CLOEXEC: i32 = 0o2000000;
+
+pub const IPPROTO_IP: i32 = 0;
+pub const IPPROTO_ICMP: i32 = 1;
+pub const IPPROTO_TCP: i32 = 6;
+pub const IPPROTO_UDP: i32 = 17;
+
+pub const SOL_SOCKET: i32 = 1;
+pub const SO_REUSEADDR: i32 = 2;
+pub const SO_ERROR: i32 = 4;
+pub const SO_BROADCAST: i32 = 6;
+pub const SO_SNDBUF: i32 = 7;
+pub const SO_RCVBUF: i32 = 8;
+pub const SO_KEEPALIVE: i32 = 9;
+pub const SO_RCVTIMEO: i32 = 20;
+pub const SO_SNDTIMEO: i32 = 21;
+pub const TCP_NODELAY: i32 = 1;
+
+pub const SHUT_RD: i32 = 0;
+pub const SHUT_WR: i32 = 1;
+pub const SHUT_RDWR: i32 = 2;
+
+pub const MSG_PEEK: i32 = 0x2;
+pub const MSG_DONTWAIT: i32 = 0x40;
+pub const MSG_NOSIGNAL: i32 = 0x4000;
+
+pub const INADDR_ANY: u32 = 0;
+pub const INADDR_LOOPBACK: u32 = 0x7F00_0001;
+
+pub type socklen_t = u32;
+
+/// Endereço genérico
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct sockaddr {
+    pub sa_family: u16,
+    pub sa_data: [u8; 14],
+}
+
+/// Endereço IPv4; porta e endereço em ordem de rede
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct sockaddr_in {
+    pub sin_family: u16,
+    pub sin_port: u16,
+    pub sin_addr: u32,
+    pub sin_zero: [u8; 8],
+}
+
+impl sockaddr_in {
+    /// `addr` e `port` em ordem do host
+    pub const fn new(addr: u32, port: u16) -> Self {
+        Self {
+            sin_family: AF_INET as u16,
+            sin_port: port.to_be(),
+            sin_addr: addr.to_be(),
+            sin_zero: [0; 8],
+        }
+    }
+}
+
+/// Endereço Unix (caminho terminado em NUL)
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct sockaddr_un {
+    pub sun_family: u16,
+    pub sun_path: [u8; 108],
+}
+
+impl sockaddr_un {
+    /// None se o caminho não couber
+    pub fn new(path: &str) -> Option<Self> {
+        let mut addr = Self { sun_family: AF_UNIX as u16, sun_path: [0; 108] };
+        if path.len() >= addr.sun_path.len() {
+            return None;
+        }
+        addr.sun_path[..path.len()].copy_from_slice(path.as_bytes());
+        Some(addr)
+    }
+}
+
+/// Espaço para qualquer endereço
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct sockaddr_storage {
+    pub ss_family: u16,
+    pub __ss_data: [u8; 126],
+}
+
+impl sockaddr_storage {
+    pub const fn new() -> Self {
+        Self { ss_family: 0, __ss_data: [0; 126] }
+    }
+}
+
+impl Default for sockaddr_storage {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Endereços que podem ser passados para bind/connect/sendto
+pub trait SockAddr {
+    fn socklen(&self) -> socklen_t;
+}
+
+impl SockAddr for sockaddr_in {
+    fn socklen(&self) -> socklen_t {
+        size_of::<Self>() as socklen_t
+    }
+}
+
+impl SockAddr for sockaddr_un {
+    fn socklen(&self) -> socklen_t {
+        size_of::<Self>() as socklen_t
+    }
+}
+
+impl SockAddr for sockaddr_storage {
+    fn socklen(&self) -> socklen_t {
+        size_of::<Self>() as socklen_t
+    }
+}
+
+fn addr_ptr<A: SockAddr>(addr: &A) -> u64 {
+    addr as *const A as u64
+}
+
+pub fn htons(v: u16) -> u16 {
+    v.to_be()
+}
+
+pub fn ntohs(v: u16) -> u16 {
+    u16::from_be(v)
+}
+
+pub fn htonl(v: u32) -> u32 {
+    v.to_be()
+}
+
+pub fn ntohl(v: u32) -> u32 {
+    u32::from_be(v)
+}
+
+/// "a.b.c.d" para endereço IPv4 em ordem do host
+pub fn inet_aton(s: &str) -> Option<u32> {
+    let mut addr = 0u32;
+    let mut parts = 0;
+    for part in s.split('.') {
+        if part.is_empty() || part.len() > 3 || !part.bytes().all(|c| c.is_ascii_digit()) {
+            return None;
+        }
+        let n: u32 = part.parse().ok()?;
+        if n > 255 {
+            return None;
+        }
+        addr = (addr << 8) | n;
+        parts += 1;
+    }
+    if parts == 4 { Some(addr) } else { None }
+}
+
+/// Endereço IPv4 em ordem do host para "a.b.c.d"; retorna o tamanho
+pub fn inet_ntoa(addr: u32, buf: &mut [u8; 16]) -> usize {
+    let [a, b, c, d] = addr.to_be_bytes();
+    snprintf(buf, format_args!("{}.{}.{}.{}", a, b, c, d)) as usize
+}
+
+pub fn socket(domain: i32, sock_type: i32, protocol: i32) -> i32 {
+    check(unsafe { syscall3(SYS_SOCKET, domain as u64, sock_type as u64, protocol as u64) }) as i32
+}
+
+pub fn bind<A: SockAddr>(fd: i32, addr: &A) -> i32 {
+    check(unsafe { syscall3(SYS_BIND, fd as u64, addr_ptr(addr), addr.socklen() as u64) }) as i32
+}
+
+pub fn listen(fd: i32, backlog: i32) -> i32 {
+    check(unsafe { syscall2(SYS_LISTEN, fd as u64, backlog as u64) }) as i32
+}
+
+/// Aceita uma conexão; o endereço do outro lado vai para `addr`, se dado
+pub fn accept(fd: i32, addr: Option<&mut sockaddr_storage>) -> i32 {
+    let mut len = size_of::<sockaddr_storage>() as socklen_t;
+    let (addr, len_ptr) = match addr {
+        Some(a) => (a as *mut sockaddr_storage as u64, &mut len as *mut socklen_t as u64),
+        None => (0, 0),
+    };
+    check(unsafe { syscall3(SYS_ACCEPT, fd as u64, addr, len_ptr) }) as i32
+}
+
+pub fn connect<A: SockAddr>(fd: i32, addr: &A) -> i32 {
+    check(unsafe { syscall3(SYS_CONNECT, fd as u64, addr_ptr(addr), addr.socklen() as u64) }) as i32
+}
+
+pub fn send(fd: i32, buf: &[u8], flags: i32) -> isize {
+    check(unsafe {
+        syscall6(SYS_SENDTO, fd as u64, buf.as_ptr() as u64, buf.len() as u64, flags as u64, 0, 0)
+    }) as isize
+}
+
+pub fn sendto<A: SockAddr>(fd: i32, buf: &[u8], flags: i32, addr: &A) -> isize {
+    check(unsafe {
+        syscall6(
+            SYS_SENDTO,
+            fd as u64,
+            buf.as_ptr() as u64,
+            buf.len() as u64,
+            flags as u64,
+            addr_ptr(addr),
+            addr.socklen() as u64,
+        )
+    }) as isize
+}
+
+pub fn recv(fd: i32, buf: &mut [u8], flags: i32) -> isize {
+    check(unsafe {
+        syscall6(SYS_RECVFROM, fd as u64, buf.as_mut_ptr() as u64, buf.len() as u64, flags as u64, 0, 0)
+    }) as isize
+}
+
+/// Recebe um datagrama; o remetente vai para `addr`
+pub fn recvfrom(fd: i32, buf: &mut [u8], flags: i32, addr: &mut sockaddr_storage) -> isize {
+    let mut len = size_of::<sockaddr_storage>() as socklen_t;
+    check(unsafe {
+        syscall6(
+            SYS_RECVFROM,
+            fd as u64,
+            buf.as_mut_ptr() as u64,
+            buf.len() as u64,
+            flags as u64,
+            addr as *mut sockaddr_storage as u64,
+            &mut len as *mut socklen_t as u64,
+        )
+    }) as isize
+}
+
+pub fn shutdown(fd: i32, how: i32) -> i32 {
+    check(unsafe { syscall2(SYS_SHUTDOWN, fd as u64, how as u64) }) as i32
+}
+
+/// Opção de socket com valor `i32` (SO_REUSEADDR, SO_KEEPALIVE...)
+pub fn setsockopt(fd: i32, level: i32, name: i32, value: i32) -> i32 {
+    check(unsafe {
+        syscall5(
+            SYS_SETSOCKOPT,
+            fd as u64,
+            level as u64,
+            name as u64,
+            &value as *const i32 as u64,
+            size_of::<i32>() as u64,
+        )
+    }) as i32
+}
+
+/// Lê uma opção de socket com valor `i32`; -1 com errno em caso de erro
+pub fn getsockopt(fd: i32, level: i32, name: i32, value: &mut i32) -> i32 {
+    let mut len = size_of::<i32>() as socklen_t;
+    check(unsafe {
+        syscall5(
+            SYS_GETSOCKOPT,
+            fd as u64,
+            level as u64,
+            name as u64,
+            value as *mut i32 as u64,
+            &mut len as *mut socklen_t as u64,
+        )
+    }) as i32
+}
+
+pub fn getsockname(fd: i32, addr: &mut sockaddr_storage) -> i32 {
+    let mut len = size_of::<sockaddr_storage>() as socklen_t;
+    check(unsafe {
+        syscall3(SYS_GETSOCKNAME, fd as u64, addr as *mut sockaddr_storage as u64, &mut len as *mut socklen_t as u64)
+    }) as i32
+}
+
+pub fn getpeername(fd: i32, addr: &mut sockaddr_storage) -> i32 {
+    let mut len = size_of::<sockaddr_storage>() as socklen_t;
+    check(unsafe {
+        syscall3(SYS_GETPEERNAME, fd as u64, addr as *mut sockaddr_storage as u64, &mut len as *mut socklen_t as u64)
+    }) as i32
+}
@@ -0,0 +1,587 @@
+//! stdio: streams `FILE` com buffer e saída formatada
+//!
+//! Como em C, `stdout` tem buffer de linha, `stderr` não tem buffer e os
+//! arquivos abertos com `fopen` têm buffer cheio (`BUFSIZ`). A formatação
+//! usa `core::fmt`: `printf!("{} {}\n", a, b)` no lugar dos especificadores
+//! `%d`/`%s`. Cada stream tem o seu mutex; `exit` descarrega todos.
+
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::fmt;
+use core::ptr;
+
+use crate::errno::{set_errno, EBADF, EINVAL};
+use crate::sync::Mutex;
+use crate::{close, lseek, open, read, write};
+use crate::{O_APPEND, O_CLOEXEC, O_CREAT, O_EXCL, O_RDONLY, O_RDWR, O_TRUNC, O_WRONLY};
+use crate::{SEEK_CUR, SEEK_SET};
+
+pub const EOF: i32 = -1;
+pub const BUFSIZ: usize = 4096;
+
+/// Modos de buffer de `setvbuf`
+pub const _IOFBF: i32 = 0;
+pub const _IOLBF: i32 = 1;
+pub const _IONBF: i32 = 2;
+
+/// Stream de arquivo
+pub struct FILE {
+    stream: Mutex<Stream>,
+}
+
+struct Stream {
+    fd: i32,
+    readable: bool,
+    writable: bool,
+    /// `_IOFBF`, `_IOLBF` ou `_IONBF`
+    mode: i32,
+    /// Alocado no primeiro uso. Guarda dados lidos (`rpos..rend`) ou a
+    /// escrever (`..wlen`), nunca os dois.
+    buf: Vec<u8>,
+    rpos: usize,
+    rend: usize,
+    wlen: usize,
+    /// Byte devolvido por `ungetc`
+    unget: Option<u8>,
+    eof: bool,
+    error: bool,
+}
+
+impl FILE {
+    const fn new(fd: i32, readable: bool, writable: bool, mode: i32) -> Self {
+        Self {
+            stream: Mutex::new(Stream {
+                fd,
+                readable,
+                writable,
+                mode,
+                buf: Vec::new(),
+                rpos: 0,
+                rend: 0,
+                wlen: 0,
+                unget: None,
+                eof: false,
+                error: false,
+            }),
+        }
+    }
+}
+
+static STDIN: FILE = FILE::new(0, true, false, _IOLBF);
+static STDOUT: FILE = FILE::new(1, false, true, _IOLBF);
+static STDERR: FILE = FILE::new(2, false, true, _IONBF);
+
+/// Streams de `fopen`/`fdopen` ainda abertos, para `fflush(null)` e `exit`
+static OPEN: Mutex<Vec<usize>> = Mutex::new(Vec::new());
+
+pub fn stdin() -> *mut FILE {
+    &STDIN as *const FILE as *mut FILE
+}
+
+pub fn stdout() -> *mut FILE {
+    &STDOUT as *const FILE as *mut FILE
+}
+
+pub fn stderr() -> *mut FILE {
+    &STDERR as *const FILE as *mut FILE
+}
+
+/// Roda `f` com o stream travado; `err` se `stream` for null
+fn with<R>(stream: *mut FILE, err: R, f: impl FnOnce(&mut Stream) -> R) -> R {
+    if stream.is_null() {
+        set_errno(EBADF);
+        return err;
+    }
+    let file = unsafe { &*stream };
+    let mut s = file.stream.lock();
+    f(&mut s)
+}
+
+impl Stream {
+    fn buffer(&mut self) -> &mut Vec<u8> {
+        if self.buf.len() != BUFSIZ {
+            self.buf.resize(BUFSIZ, 0);
+        }
+        &mut self.buf
+    }
+
+    fn write_all(&mut self, mut data: &[u8]) -> bool {
+        while !data.is_empty() {
+            let n = write(self.fd, data);
+            if n <= 0 {
+                self.error = true;
+                return false;
+            }
+            data = &data[n as usize..];
+        }
+        true
+    }
+
+    /// Escreve o que está no buffer
+    fn flush(&mut self) -> bool {
+        if self.wlen == 0 {
+            return true;
+        }
+        let pending = core::mem::take(&mut self.buf);
+        let ok = self.write_all(&pending[..self.wlen]);
+        self.buf = pending;
+        self.wlen = 0;
+        ok
+    }
+
+    /// Abandona o que foi lido adiante, voltando a posição do arquivo
+    fn drop_read(&mut self) {
+        let unread = self.rend - self.rpos + self.unget.is_some() as usize;
+        if unread > 0 {
+            lseek(self.fd, -(unread as i64), SEEK_CUR);
+        }
+        self.rpos = 0;
+        self.rend = 0;
+        self.unget = None;
+    }
+
+    fn write(&mut self, data: &[u8]) -> usize {
+        if !self.writable {
+            set_errno(EBADF);
+            self.error = true;
+            return 0;
+        }
+        self.drop_read();
+
+        if self.mode == _IONBF {
+            return if self.flush() && self.write_all(data) { data.len() } else { 0 };
+        }
+        if self.wlen + data.len() > BUFSIZ {
+            if !self.flush() {
+                return 0;
+            }
+            if data.len() >= BUFSIZ {
+                return if self.write_all(data) { data.len() } else { 0 };
+            }
+        }
+        let start = self.wlen;
+        self.buffer()[start..start + data.len()].copy_from_slice(data);
+        self.wlen += data.len();
+        if self.mode == _IOLBF && data.contains(&b'\n') && !self.flush() {
+            return 0;
+        }
+        data.len()
+    }
+
+    /// Lê mais dados para o buffer. Falso no fim do arquivo ou erro.
+    fn fill(&mut self) -> bool {
+        if !self.readable {
+            set_errno(EBADF);
+            self.error = true;
+            return false;
+        }
+        if !self.flush() {
+            return false;
+        }
+        // Ler de um stream com buffer de linha descarrega stdout, para
+        // que um prompt apareça antes da leitura
+        if self.mode == _IOLBF && self.fd != 1 {
+            fflush(stdout());
+        }
+        let fd = self.fd;
+        let n = read(fd, self.buffer());
+        if n < 0 {
+            self.error = true;
+            return false;
+        }
+        if n == 0 {
+            self.eof = true;
+            return false;
+        }
+        self.rpos = 0;
+        self.rend = n as usize;
+        true
+    }
+
+    fn getc(&mut self) -> Option<u8> {
+        if let Some(c) = self.unget.take() {
+            return Some(c);
+        }
+        if self.rpos == self.rend && !self.fill() {
+            return None;
+        }
+        let c = self.buf[self.rpos];
+        self.rpos += 1;
+        Some(c)
+    }
+
+    fn read(&mut self, out: &mut [u8]) -> usize {
+        let mut done = 0;
+        if let Some(c) = self.unget.take() {
+            if out.is_empty() {
+                self.unget = Some(c);
+                return 0;
+            }
+            out[0] = c;
+            done = 1;
+        }
+        while done < out.len() {
+            if self.rpos == self.rend {
+                // Leituras grandes vão direto para o destino
+                if out.len() - done >= BUFSIZ && self.readable && self.flush() {
+                    let n = read(self.fd, &mut out[done..]);
+                    if n <= 0 {
+                        if n == 0 { self.eof = true } else { self.error = true }
+                        break;
+                    }
+                    done += n as usize;
+                    continue;
+                }
+                if !self.fill() {
+                    break;
+                }
+            }
+            let n = (self.rend - self.rpos).min(out.len() - done);
+            out[done..done + n].copy_from_slice(&self.buf[self.rpos..self.rpos + n]);
+            self.rpos += n;
+            done += n;
+        }
+        done
+    }
+}
+
+/// Flags de `open` para um modo de `fopen` ("r", "w+", "ab", "re"...)
+fn mode_flags(mode: &str) -> Option<(i32, bool, bool)> {
+    let bytes = mode.as_bytes();
+    let plus = bytes.contains(&b'+');
+    let (mut flags, readable, writable) = match bytes.first()? {
+        b'r' if plus => (O_RDWR, true, true),
+        b'r' => (O_RDONLY, true, false),
+        b'w' if plus => (O_RDWR | O_CREAT | O_TRUNC, true, true),
+        b'w' => (O_WRONLY | O_CREAT | O_TRUNC, false, true),
+        b'a' if plus => (O_RDWR | O_CREAT | O_APPEND, true, true),
+        b'a' => (O_WRONLY | O_CREAT | O_APPEND, false, true),
+        _ => return None,
+    };
+    for &c in &bytes[1..] {
+        match c {
+            b'+' | b'b' => {}
+            b'x' => flags |= O_EXCL,
+            b'e' => flags |= O_CLOEXEC,
+            _ => return None,
+        }
+    }
+    Some((flags, readable, writable))
+}
+
+fn new_stream(fd: i32, readable: bool, writable: bool) -> *mut FILE {
+    let file = Box::into_raw(Box::new(FILE::new(fd, readable, writable, _IOFBF)));
+    OPEN.lock().push(file as usize);
+    file
+}
+
+/// Abre `path` num stream. Null com errno em caso de erro.
+pub fn fopen(path: &str, mode: &str) -> *mut FILE {
+    let Some((flags, readable, writable)) = mode_flags(mode) else {
+        set_errno(EINVAL);
+        return ptr::null_mut();
+    };
+    let fd = open(path, flags, 0o666);
+    if fd < 0 {
+        return ptr::null_mut();
+    }
+    new_stream(fd, readable, writable)
+}
+
+/// Stream sobre um fd já aberto
+pub fn fdopen(fd: i32, mode: &str) -> *mut FILE {
+    let Some((_, readable, writable)) = mode_flags(mode) else {
+        set_errno(EINVAL);
+        return ptr::null_mut();
+    };
+    if fd < 0 {
+        set_errno(EBADF);
+        return ptr::null_mut();
+    }
+    new_stream(fd, readable, writable)
+}
+
+/// Descarrega e fecha o stream (e o fd)
+///
+/// # Safety
+///
+/// `stream` é null ou um stream aberto; depois de fechado não pode mais
+/// ser usado.
+pub unsafe fn fclose(stream: *mut FILE) -> i32 {
+    let (flushed, fd) = with(stream, (false, -1), |s| (s.flush(), s.fd));
+    if fd < 0 {
+        return EOF;
+    }
+    let closed = close(fd) == 0;
+    let owned = {
+        let mut open = OPEN.lock();
+        let before = open.len();
+        open.retain(|&f| f != stream as usize);
+        open.len() != before
+    };
+    // stdin/stdout/stderr são estáticos
+    if owned {
+        drop(unsafe { Box::from_raw(stream) });
+    }
+    if flushed && closed { 0 } else { EOF }
+}
+
+/// Escreve o buffer do stream; com null, de todos os streams
+pub fn fflush(stream: *mut FILE) -> i32 {
+    if stream.is_null() {
+        let open: Vec<usize> = OPEN.lock().clone();
+        let mut ret = 0;
+        for f in [stdout(), stderr()].into_iter().chain(open.into_iter().map(|f| f as *mut FILE)) {
+            if fflush(f) != 0 {
+                ret = EOF;
+            }
+        }
+        return ret;
+    }
+    with(stream, EOF, |s| if s.flush() { 0 } else { EOF })
+}
+
+/// Descarrega tudo antes de o processo terminar
+pub(crate) fn flush_all() {
+    fflush(ptr::null_mut());
+}
+
+/// Muda o modo de buffer (`_IOFBF`, `_IOLBF`, `_IONBF`)
+pub fn setvbuf(stream: *mut FILE, mode: i32) -> i32 {
+    if !matches!(mode, _IOFBF | _IOLBF | _IONBF) {
+        set_errno(EINVAL);
+        return -1;
+    }
+    with(stream, -1, |s| {
+        if !s.flush() {
+            return -1;
+        }
+        s.mode = mode;
+        0
+    })
+}
+
+/// Lê até `buf.len()` bytes; retorna quantos leu
+pub fn fread(buf: &mut [u8], stream: *mut FILE) -> usize {
+    with(stream, 0, |s| s.read(buf))
+}
+
+/// Escreve `buf`; retorna quantos bytes foram aceitos
+pub fn fwrite(buf: &[u8], stream: *mut FILE) -> usize {
+    with(stream, 0, |s| s.write(buf))
+}
+
+/// Próximo byte, ou `EOF`
+pub fn fgetc(stream: *mut FILE) -> i32 {
+    with(stream, EOF, |s| s.getc().map_or(EOF, |c| c as i32))
+}
+
+pub fn getchar() -> i32 {
+    fgetc(stdin())
+}
+
+/// Devolve `c` ao stream; só um byte de cada vez
+pub fn ungetc(c: i32, stream: *mut FILE) -> i32 {
+    if c == EOF {
+        return EOF;
+    }
+    with(stream, EOF, |s| {
+        if s.unget.is_some() {
+            return EOF;
+        }
+        s.unget = Some(c as u8);
+        s.eof = false;
+        c & 0xFF
+    })
+}
+
+/// Lê uma linha (com o '\n') em `buf`, terminada em NUL. Retorna a linha
+/// lida, ou None no fim do arquivo sem nada lido.
+pub fn fgets(buf: &mut [u8], stream: *mut FILE) -> Option<&[u8]> {
+    if buf.is_empty() {
+        return None;
+    }
+    let n = with(stream, 0, |s| {
+        let mut n = 0;
+        while n + 1 < buf.len() {
+            let Some(c) = s.getc() else { break };
+            buf[n] = c;
+            n += 1;
+            if c == b'\n' {
+                break;
+            }
+        }
+        n
+    });
+    buf[n] = 0;
+    if n == 0 { None } else { Some(&buf[..n]) }
+}
+
+/// Lê uma linha inteira (com o '\n') em `line`, que é esvaziado antes.
+/// Retorna o tamanho, ou -1 no fim do arquivo.
+pub fn getline(line: &mut Vec<u8>, stream: *mut FILE) -> isize {
+    line.clear();
+    with(stream, -1, |s| {
+        while let Some(c) = s.getc() {
+            line.push(c);
+            if c == b'\n' {
+                break;
+            }
+        }
+        if line.is_empty() { -1 } else { line.len() as isize }
+    })
+}
+
+pub fn fputc(c: i32, stream: *mut FILE) -> i32 {
+    with(stream, EOF, |s| if s.write(&[c as u8]) == 1 { c & 0xFF } else { EOF })
+}
+
+pub fn putchar(c: i32) -> i32 {
+    fputc(c, stdout())
+}
+
+/// Escreve `s` sem '\n'
+pub fn fputs(s: &str, stream: *mut FILE) -> i32 {
+    with(stream, EOF, |st| if st.write(s.as_bytes()) == s.len() { 0 } else { EOF })
+}
+
+/// Escreve `s` e '\n' em stdout
+pub fn puts(s: &str) -> i32 {
+    with(stdout(), EOF, |st| {
+        if st.write(s.as_bytes()) == s.len() && st.write(b"\n") == 1 { 0 } else { EOF }
+    })
+}
+
+/// Posiciona o stream (`SEEK_SET`, `SEEK_CUR`, `SEEK_END`)
+pub fn fseek(stream: *mut FILE, offset: i64, whence: i32) -> i32 {
+    with(stream, -1, |s| {
+        if !s.flush() {
+            return -1;
+        }
+        s.drop_read();
+        if lseek(s.fd, offset, whence) < 0 {
+            return -1;
+        }
+        s.eof = false;
+        0
+    })
+}
+
+/// Posição atual, contando o que está no buffer
+pub fn ftell(stream: *mut FILE) -> i64 {
+    with(stream, -1, |s| {
+        let pos = lseek(s.fd, 0, SEEK_CUR);
+        if pos < 0 {
+            return -1;
+        }
+        let unread = (s.rend - s.rpos) as i64 + s.unget.is_some() as i64;
+        pos - unread + s.wlen as i64
+    })
+}
+
+pub fn rewind(stream: *mut FILE) {
+    fseek(stream, 0, SEEK_SET);
+    clearerr(stream);
+}
+
+pub fn feof(stream: *mut FILE) -> bool {
+    with(stream, false, |s| s.eof)
+}
+
+pub fn ferror(stream: *mut FILE) -> bool {
+    with(stream, false, |s| s.error)
+}
+
+pub fn clearerr(stream: *mut FILE) {
+    with(stream, (), |s| {
+        s.eof = false;
+        s.error = false;
+    })
+}
+
+pub fn fileno(stream: *mut FILE) -> i32 {
+    with(stream, -1, |s| s.fd)
+}
+
+/// Conta o que é escrito num stream pelo `core::fmt`
+struct StreamWriter<'a> {
+    stream: &'a mut Stream,
+    written: usize,
+}
+
+impl fmt::Write for StreamWriter<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        if self.stream.write(s.as_bytes()) != s.len() {
+            return Err(fmt::Error);
+        }
+        self.written += s.len();
+        Ok(())
+    }
+}
+
+/// Escreve `args` formatado; retorna os bytes escritos, ou -1
+pub fn fprintf(stream: *mut FILE, args: fmt::Arguments) -> i32 {
+    with(stream, -1, |s| {
+        let mut w = StreamWriter { stream: s, written: 0 };
+        match fmt::write(&mut w, args) {
+            Ok(()) => w.written as i32,
+            Err(_) => -1,
+        }
+    })
+}
+
+pub fn printf(args: fmt::Arguments) -> i32 {
+    fprintf(stdout(), args)
+}
+
+/// Formata em `buf`, cortando o que não couber e terminando em NUL.
+/// Retorna o tamanho completo, como se `buf` fosse grande o bastante.
+pub fn snprintf(buf: &mut [u8], args: fmt::Arguments) -> i32 {
+    struct Truncating<'a> {
+        buf: &'a mut [u8],
+        len: usize,
+    }
+    impl fmt::Write for Truncating<'_> {
+        fn write_str(&mut self, s: &str) -> fmt::Result {
+            let room = self.buf.len().saturating_sub(1).saturating_sub(self.len);
+            let n = room.min(s.len());
+            let start = self.len.min(self.buf.len());
+            self.buf[start..start + n].copy_from_slice(&s.as_bytes()[..n]);
+            self.len += s.len();
+            Ok(())
+        }
+    }
+
+    let mut w = Truncating { buf, len: 0 };
+    let _ = fmt::write(&mut w, args);
+    let len = w.len;
+    if !buf.is_empty() {
+        let end = len.min(buf.len() - 1);
+        buf[end] = 0;
+    }
+    len as i32
+}
+
+/// `printf!("x = {}\n", x)`: escreve em stdout
+#[macro_export]
+macro_rules! printf {
+    ($($arg:tt)*) => {
+        $crate::stdio::printf(format_args!($($arg)*))
+    };
+}
+
+/// `fprintf!(stream, "x = {}\n", x)`
+#[macro_export]
+macro_rules! fprintf {
+    ($stream:expr, $($arg:tt)*) => {
+        $crate::stdio::fprintf($stream, format_args!($($arg)*))
+    };
+}
+
+/// `snprintf!(&mut buf, "x = {}", x)`
+#[macro_export]
+macro_rules! snprintf {
+    ($buf:expr, $($arg:tt)*) => {
+        $crate::stdio::snprintf($buf, format_args!($($arg)*))
+    };
+}
@@ -0,0 +1,57 @@
+//! Mutex interno da libc (heap, ambiente, streams), sobre o futex de
+//! `pthread_mutex_t`
+
+use core::cell::UnsafeCell;
+use core::ops::{Deref, DerefMut};
+
+use crate::pthread::{pthread_mutex_lock, pthread_mutex_t, pthread_mutex_unlock};
+
+pub(crate) struct Mutex<T> {
+    lock: pthread_mutex_t,
+    data: UnsafeCell<T>,
+}
+
+unsafe impl<T: Send> Sync for Mutex<T> {}
+unsafe impl<T: Send> Send for Mutex<T> {}
+
+impl<T> Mutex<T> {
+    pub const fn new(data: T) -> Self {
+        Self {
+            lock: pthread_mutex_t::new(),
+            data: UnsafeCell::new(data),
+        }
+    }
+
+    pub fn lock(&self) -> MutexGuard<'_, T> {
+        pthread_mutex_lock(self.raw());
+        MutexGuard { mutex: self }
+    }
+
+    fn raw(&self) -> *mut pthread_mutex_t {
+        &self.lock as *const pthread_mutex_t as *mut pthread_mutex_t
+    }
+}
+
+pub(crate) struct MutexGuard<'a, T> {
+    mutex: &'a Mutex<T>,
+}
+
+impl<T> Deref for MutexGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.mutex.data.get() }
+    }
+}
+
+impl<T> DerefMut for MutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.mutex.data.get() }
+    }
+}
+
+impl<T> Drop for MutexGuard<'_, T> {
+    fn drop(&mut self) {
+        pthread_mutex_unlock(self.mutex.raw());
+    }
+}
@@ -340,12 +340,12 @@ fn execute_pipeline(line: &mut [u8]) {
             let argc = parse_args(&mut line[start..], &mut args);
 
             if argc == 0 {
-                exit(1);
+                _exit(1);
             }
 
             // Executa o comando
             exec_command(args[0], &args[..argc + 1]);
-            exit(127);
+            _exit(127);
         }
 
         pids[i] = pid;
@@ -426,7 +426,7 @@ fn exec_command(cmd: *const u8, args: &[*const u8]) {
         path_ptr = cmd;
     }
 
-    let envp: [*const u8; 1] = [core::ptr::null()];
+    let envp = environ();
 
     let path_slice = unsafe { cstr_to_slice(path_ptr) };
     if let Ok(path_str) = core::str::from_utf8(path_slice) {
@@ -675,23 +675,22 @@ fn execute_external(cmd: *const u8, args: &[*const u8]) {
             path_ptr = cmd;
         }
 
-        let envp: [*const u8; 1] = [core::ptr::null()];
+        let envp = environ();
 
         // Converte para &str
         let path_slice = unsafe { cstr_to_slice(path_ptr) };
         if let Ok(path_str) = core::str::from_utf8(path_slice) {
-            let ret = execve(path_str, args, &envp);
+            execve(path_str, args, &envp);
             // Se chegou aqui, execve falhou
-            print("sh: command not found: ");
+            print("sh: ");
             write(1, path_slice);
-            print(" (error ");
-            print_num(ret as i64);
-            println(")");
+            print(": ");
+            println(strerror(errno()));
         } else {
             println("sh: invalid command path");
         }
 
-        exit(127);
+        _exit(127);
     }
 
     // Pai - aguarda o filho